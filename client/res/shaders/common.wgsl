@@ -0,0 +1,14 @@
+// The scene uniforms, shared by every shader that draws in the world. UniformBufferData is
+// generated from the struct in renderer.rs.
+
+#include "layouts.wgsl"
+
+@group(0) @binding(0) var<uniform> uniform_buffer: UniformBufferData;
+
+// The world position at a point of the screen, 0 to 1 from the top left, with the depth the
+// scene pass wrote there. For passes after the scene that read its depth, e.g. decals.
+fn get_world_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = uniform_buffer.inverse_view_projection * ndc;
+    return world.xyz / world.w;
+}
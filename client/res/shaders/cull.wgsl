@@ -0,0 +1,43 @@
+// Compute shader, frustum culls the persistent instances of one set. The instances whose
+// bounding sphere is inside are copied to the front of the culled buffer and counted into
+// the indirect draw arguments. Matches is_sphere_in_frustum in culling.rs.
+
+#include "layouts.wgsl"
+
+struct CullUniforms {
+    planes: array<vec4<f32>, 6>, // xyz the normal pointing inside, w the distance
+};
+
+// wgpu's DrawIndexedIndirectArgs, only the instance count is written here
+struct DrawArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0) var<uniform> cull: CullUniforms;
+@group(0) @binding(1) var<storage, read> spheres: array<vec4<f32>>; // xyz the center, w the radius
+@group(0) @binding(2) var<storage, read> instances: array<StaticInstanceData>;
+@group(0) @binding(3) var<storage, read_write> culled_instances: array<StaticInstanceData>;
+@group(0) @binding(4) var<storage, read_write> draw_args: DrawArgs;
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= arrayLength(&spheres) {
+        return;
+    }
+
+    let sphere = spheres[index];
+    for (var i = 0u; i < 6u; i++) {
+        let plane = cull.planes[i];
+        if dot(plane.xyz, sphere.xyz) + plane.w < -sphere.w {
+            return;
+        }
+    }
+
+    let slot = atomicAdd(&draw_args.instance_count, 1u);
+    culled_instances[slot] = instances[index];
+}
@@ -0,0 +1,169 @@
+// Vertex shader
+
+struct VertexInput {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uvs: vec3<f32>,
+    @location(3) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = in.uvs.xy;
+    out.clip_position = vec4<f32>(in.position, 1.0);
+
+    return out;
+}
+
+// Fragment shader
+// A compact version of FXAA 3.11 (quality), running on the composited LDR image
+
+struct FxaaUniform {
+    inverse_screen_size: vec2<f32>,
+    edge_threshold: f32,
+    edge_threshold_min: f32,
+    subpixel: f32,
+};
+
+@group(0) @binding(0) var ldr_texture: texture_2d<f32>;
+@group(0) @binding(1) var ldr_sampler: sampler;
+@group(0) @binding(2) var<uniform> fxaa: FxaaUniform;
+
+const SEARCH_STEPS: i32 = 8;
+
+// The input is an sRGB view so the samples are linear, the square root is close enough
+// to perceptual luma for edge detection
+fn luma(color: vec3<f32>) -> f32 {
+    return sqrt(dot(color, vec3<f32>(0.299, 0.587, 0.114)));
+}
+
+fn sample_luma(uv: vec2<f32>) -> f32 {
+    return luma(textureSampleLevel(ldr_texture, ldr_sampler, uv, 0.0).rgb);
+}
+
+fn sample_luma_offset(uv: vec2<f32>, offset: vec2<f32>) -> f32 {
+    return sample_luma(uv + offset * fxaa.inverse_screen_size);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = in.tex_coords;
+    let texel = fxaa.inverse_screen_size;
+    let center_color = textureSampleLevel(ldr_texture, ldr_sampler, uv, 0.0);
+
+    let luma_m = luma(center_color.rgb);
+    let luma_n = sample_luma_offset(uv, vec2<f32>(0.0, -1.0));
+    let luma_s = sample_luma_offset(uv, vec2<f32>(0.0, 1.0));
+    let luma_w = sample_luma_offset(uv, vec2<f32>(-1.0, 0.0));
+    let luma_e = sample_luma_offset(uv, vec2<f32>(1.0, 0.0));
+
+    let luma_max = max(luma_m, max(max(luma_n, luma_s), max(luma_w, luma_e)));
+    let luma_min = min(luma_m, min(min(luma_n, luma_s), min(luma_w, luma_e)));
+    let contrast = luma_max - luma_min;
+
+    if (contrast < max(fxaa.edge_threshold_min, luma_max * fxaa.edge_threshold)) {
+        return center_color;
+    }
+
+    let luma_nw = sample_luma_offset(uv, vec2<f32>(-1.0, -1.0));
+    let luma_ne = sample_luma_offset(uv, vec2<f32>(1.0, -1.0));
+    let luma_sw = sample_luma_offset(uv, vec2<f32>(-1.0, 1.0));
+    let luma_se = sample_luma_offset(uv, vec2<f32>(1.0, 1.0));
+
+    // Subpixel blend factor from the low-pass of the neighbourhood
+    let luma_average = (2.0 * (luma_n + luma_s + luma_w + luma_e) + luma_nw + luma_ne + luma_sw + luma_se) / 12.0;
+    let subpixel_ratio = clamp(abs(luma_average - luma_m) / contrast, 0.0, 1.0);
+    let subpixel_smooth = smoothstep(0.0, 1.0, subpixel_ratio);
+    let subpixel_blend = subpixel_smooth * subpixel_smooth * fxaa.subpixel;
+
+    let edge_horizontal =
+        abs(luma_nw + luma_ne - 2.0 * luma_n) +
+        2.0 * abs(luma_w + luma_e - 2.0 * luma_m) +
+        abs(luma_sw + luma_se - 2.0 * luma_s);
+    let edge_vertical =
+        abs(luma_nw + luma_sw - 2.0 * luma_w) +
+        2.0 * abs(luma_n + luma_s - 2.0 * luma_m) +
+        abs(luma_ne + luma_se - 2.0 * luma_e);
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // Pick the side of the edge with the larger gradient
+    let luma_positive = select(luma_e, luma_s, is_horizontal);
+    let luma_negative = select(luma_w, luma_n, is_horizontal);
+    let gradient_positive = abs(luma_positive - luma_m);
+    let gradient_negative = abs(luma_negative - luma_m);
+
+    var step_length = select(texel.x, texel.y, is_horizontal);
+    var luma_opposite = luma_positive;
+    var gradient = gradient_positive;
+    if (gradient_negative > gradient_positive) {
+        step_length = -step_length;
+        luma_opposite = luma_negative;
+        gradient = gradient_negative;
+    }
+
+    // Walk along the edge in both directions until the contrast changes
+    var edge_uv = uv;
+    if (is_horizontal) {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+    let edge_step = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), is_horizontal);
+
+    let edge_luma = (luma_m + luma_opposite) * 0.5;
+    let gradient_threshold = gradient * 0.25;
+
+    var uv_positive = edge_uv + edge_step;
+    var uv_negative = edge_uv - edge_step;
+    var delta_positive = sample_luma(uv_positive) - edge_luma;
+    var delta_negative = sample_luma(uv_negative) - edge_luma;
+    var done_positive = abs(delta_positive) >= gradient_threshold;
+    var done_negative = abs(delta_negative) >= gradient_threshold;
+
+    for (var i = 1; i < SEARCH_STEPS; i++) {
+        if (done_positive && done_negative) {
+            break;
+        }
+        let step_scale = select(1.0, 2.0, i >= 4);
+        if (!done_positive) {
+            uv_positive += edge_step * step_scale;
+            delta_positive = sample_luma(uv_positive) - edge_luma;
+            done_positive = abs(delta_positive) >= gradient_threshold;
+        }
+        if (!done_negative) {
+            uv_negative -= edge_step * step_scale;
+            delta_negative = sample_luma(uv_negative) - edge_luma;
+            done_negative = abs(delta_negative) >= gradient_threshold;
+        }
+    }
+
+    let distance_positive = select(uv_positive.y - uv.y, uv_positive.x - uv.x, is_horizontal);
+    let distance_negative = select(uv.y - uv_negative.y, uv.x - uv_negative.x, is_horizontal);
+    let closest_is_positive = distance_positive < distance_negative;
+    let closest_distance = min(distance_positive, distance_negative);
+    let edge_length = distance_positive + distance_negative;
+
+    // Only blend when the end of the edge we are closest to moves away from our luma
+    let closest_delta = select(delta_negative, delta_positive, closest_is_positive);
+    let center_is_darker = luma_m - edge_luma < 0.0;
+    let correct_variation = (closest_delta < 0.0) != center_is_darker;
+    let edge_blend = select(0.0, 0.5 - closest_distance / edge_length, correct_variation);
+
+    let blend = max(edge_blend, subpixel_blend);
+
+    var final_uv = uv;
+    if (is_horizontal) {
+        final_uv.y += blend * step_length;
+    } else {
+        final_uv.x += blend * step_length;
+    }
+
+    return textureSampleLevel(ldr_texture, ldr_sampler, final_uv, 0.0);
+}
@@ -0,0 +1,93 @@
+// The vertices of static meshes, and of skeletal meshes with SKINNED defined. With
+// INSTANCE_VERTEX_BUFFER defined the instances are vertex attributes stepped per instance,
+// for WebGL2 which can't read storage buffers in vertex shaders.
+
+#include "common.wgsl"
+
+struct VertexInput {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uvs: vec3<f32>,
+    @location(3) color: vec4<f32>,
+#ifdef SKINNED
+    @location(4) bone_ids: vec4<i32>,
+    @location(5) bone_weights: vec4<f32>,
+#endif
+#ifdef INSTANCE_VERTEX_BUFFER
+    // StaticInstanceData, see StaticInstanceData::desc
+    @location(6) model_0: vec4<f32>,
+    @location(7) model_1: vec4<f32>,
+    @location(8) model_2: vec4<f32>,
+    @location(9) model_3: vec4<f32>,
+    @location(10) instance_color: vec4<f32>,
+    @location(11) instance_tex_coord: vec2<f32>,
+    @location(12) instance_tex_scale: vec2<f32>,
+    @location(13) data_indices: vec4<u32>,
+#endif
+};
+
+#ifdef INSTANCE_VERTEX_BUFFER
+fn get_instance(in: VertexInput) -> StaticInstanceData {
+    return StaticInstanceData(
+        mat4x4<f32>(in.model_0, in.model_1, in.model_2, in.model_3),
+        in.instance_color,
+        in.instance_tex_coord,
+        in.instance_tex_scale,
+        in.data_indices,
+    );
+}
+#else
+@group(0) @binding(1) var<storage, read> instance_buffer: array<StaticInstanceData>;
+
+fn get_instance(in: VertexInput) -> StaticInstanceData {
+    return instance_buffer[in.instance_index];
+}
+#endif
+
+#ifdef SKINNED
+// The shaders declare bone_texture or bone_buffer at the binding of their layout
+#ifdef INSTANCE_VERTEX_BUFFER
+// Four texels per matrix, a column each, see ShaderData
+fn get_bone(index: u32) -> mat4x4<f32> {
+    let width = textureDimensions(bone_texture).x;
+    let texel = vec2<u32>((index * 4u) % width, (index * 4u) / width);
+    return mat4x4<f32>(
+        textureLoad(bone_texture, texel, 0),
+        textureLoad(bone_texture, texel + vec2<u32>(1u, 0u), 0),
+        textureLoad(bone_texture, texel + vec2<u32>(2u, 0u), 0),
+        textureLoad(bone_texture, texel + vec2<u32>(3u, 0u), 0),
+    );
+}
+#else
+fn get_bone(index: u32) -> mat4x4<f32> {
+    return bone_buffer[index];
+}
+#endif
+#endif
+
+// The shadow map coordinates, xy in texture space and z the depth
+fn get_light_space_position(world_pos: vec4<f32>) -> vec3<f32> {
+    let pos_from_light = uniform_buffer.light_matrix * world_pos;
+    let ndc = pos_from_light.xyz / pos_from_light.w;
+    return vec3f(
+        ndc.xy * vec2f(0.5, -0.5) + vec2f(0.5, 0.5),
+        ndc.z
+    );
+}
+
+// Of StaticInstanceData.data_indices.y on static meshes, see instance_data.rs
+const INSTANCE_FLAG_WIND: u32 = 1u;
+
+// Leans the vertices of swaying materials with the wind and rocks them by two waves that
+// run along it. The weight is the vertex color alpha, 0 at the roots and 1 at the tips.
+fn apply_wind(world_pos: vec4<f32>, weight: f32, flags: u32) -> vec4<f32> {
+    if ((flags & INSTANCE_FLAG_WIND) == 0u) {
+        return world_pos;
+    }
+    let wind = uniform_buffer.wind;
+    let along = dot(world_pos.xz, wind.xy);
+    let sway = 0.7 * sin(wind.w * 1.7 - along * 0.004) + 0.3 * sin(wind.w * 4.3 - along * 0.011);
+    let offset = wind.xy * (wind.z * weight * (0.5 + 0.5 * sway));
+    return vec4<f32>(world_pos.x + offset.x, world_pos.y, world_pos.z + offset.y, world_pos.w);
+}
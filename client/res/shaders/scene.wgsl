@@ -1,27 +1,6 @@
 // Fragment shader
 
-struct UniformBuffer {
-    view_matrix: mat4x4<f32>,
-    projection_matrix: mat4x4<f32>,
-    camera_position: vec3<f32>,
-    light_matrix: mat4x4<f32>,
-    light_direction: vec3<f32>,
-    light_color: vec4<f32>,
-};
-
-struct Instance {
-    model_matrix: mat4x4<f32>,
-    color: vec4<f32>,
-    tex_bounds: vec4<f32>,
-}
-
-struct VertexInput {
-    @builtin(instance_index) instance_index: u32,
-    @location(0) position: vec3<f32>,
-    @location(1) normal: vec3<f32>,
-    @location(2) uvs: vec3<f32>,
-    @location(3) color: vec4<f32>,
-};
+#include "mesh.wgsl"
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
@@ -30,11 +9,9 @@ struct VertexOutput {
     @location(2) color: vec4<f32>,
     @location(3) light_space_position: vec3<f32>,
     @location(4) world_position: vec3<f32>,
+    @location(5) occlusion: f32,
 };
 
-@group(0) @binding(0) var<uniform> uniform_buffer: UniformBuffer;
-@group(0) @binding(1) var<storage, read> instance_buffer: array<Instance>;
-
 const PI: f32 = 3.14159265;
 
 fn g_schlick_ggx(n_dot_x: f32, k: f32) -> f32 {
@@ -52,6 +29,7 @@ fn stylized_ggx_pbr(
     light_color: vec3<f32>,
     ambient_top: vec3<f32>,
     ambient_bottom: vec3<f32>,
+    occlusion: f32,
     visibility: f32,
 ) -> vec3<f32> {
     // Normalize inputs
@@ -79,9 +57,7 @@ fn stylized_ggx_pbr(
     // 2. Hemispheric ambient
     // ---------------------------------------------------------------------
     let up = N.y * 0.5 + 0.5; // [-1,1] -> [0,1]
-    let ambient_dir_color = mix(ambient_bottom, ambient_top, up);
-    let ambient_strength: f32 = 0.4;
-    let ambient = ambient_dir_color * ambient_strength;
+    let ambient = mix(ambient_bottom, ambient_top, up) * occlusion;
 
     let diffuse_light = light_color * diffuse_term * visibility;
     let base_diffuse = albedo * (diffuse_light + ambient);
@@ -159,6 +135,25 @@ fn stylized_ggx_pbr(
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef UNLIT
+    // Glowing effects in the additive pass, the color and alpha are used as they are
+    let texel = textureSample(albedo_texture, albedo_sampler, in.tex_coords.xy, i32(in.tex_coords.z));
+    let unlit_color = texel.rgb * in.color.rgb;
+    return vec4<f32>(pow(max(unlit_color, vec3<f32>(0.0)), vec3<f32>(1.0 / 2.2)), texel.a * in.color.a);
+#else
+    // Debug views of the renderer, see DebugView
+#ifdef OVERDRAW
+    // Every fragment adds the same, the brightness counts how often the pixel was shaded
+    return vec4<f32>(1.0, 0.4, 0.1, 0.1);
+#endif
+#ifdef DEBUG_NORMALS
+    return vec4<f32>(normalize(in.world_normal) * 0.5 + 0.5, 1.0);
+#endif
+#ifdef DEBUG_UNLIT
+    let unlit_albedo = textureSample(albedo_texture, albedo_sampler, in.tex_coords.xy, i32(in.tex_coords.z)).rgb * in.color.rgb;
+    return vec4<f32>(pow(max(unlit_albedo, vec3<f32>(0.0)), vec3<f32>(1.0 / 2.2)), 1.0);
+#endif
+
     // PCF shadow
     var visibility = 0.0;
     let dims = vec2<f32>(textureDimensions(shadow_map).xy);
@@ -177,6 +172,7 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 
     visibility /= 9.0;
     visibility = mix(0.4, 1.0, visibility);
+    visibility = select(1.0, visibility, uniform_buffer.light_direction.w > 0.5);
 
     let albedo =  textureSample(
         albedo_texture,
@@ -188,15 +184,16 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let light_color = uniform_buffer.light_color.rgb;
 
     let N = normalize(in.world_normal);
-    let V = normalize(uniform_buffer.camera_position - in.world_position);
-    let L = normalize(-uniform_buffer.light_direction);
+    let V = normalize(uniform_buffer.camera_position.xyz - in.world_position);
+    let L = normalize(-uniform_buffer.light_direction.xyz);
 
     let roughness: f32 = 0.8;
     let metallic: f32 = 0.0;
-    let ambient_top    = vec3<f32>(0.35, 0.50, 0.80);
-    let ambient_bottom = vec3<f32>(0.30, 0.25, 0.20);
+    let ambient_intensity = uniform_buffer.ambient_top.w;
+    let ambient_top = uniform_buffer.ambient_top.rgb * ambient_intensity;
+    let ambient_bottom = uniform_buffer.ambient_bottom.rgb * ambient_intensity;
 
-    let color = stylized_ggx_pbr(
+    var color = stylized_ggx_pbr(
         N,
         V,
         L,
@@ -206,12 +203,22 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         light_color,
         ambient_top,
         ambient_bottom,
+        in.occlusion,
         visibility
     );
 
+    // Linear fog, applied in linear space before the gamma correction
+    if (uniform_buffer.fog_color.w > 0.5) {
+        let distance = length(uniform_buffer.camera_position.xyz - in.world_position);
+        let fog_range = max(uniform_buffer.fog_range.y - uniform_buffer.fog_range.x, 1e-4);
+        let fog_amount = clamp((distance - uniform_buffer.fog_range.x) / fog_range, 0.0, 1.0);
+        color = mix(color, uniform_buffer.fog_color.rgb, fog_amount);
+    }
+
     // gamma correction to sRGB
     let mapped_color = pow(max(color, vec3<f32>(0.0)), vec3<f32>(1.0 / 2.2));
 
     return vec4<f32>(mapped_color, 1.0);
+#endif
 }
 
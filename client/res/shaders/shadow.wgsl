@@ -0,0 +1,42 @@
+// Vertex shader, depth only. Skeletal meshes are drawn with SKINNED defined.
+
+#include "mesh.wgsl"
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+#ifdef SKINNED
+#ifdef INSTANCE_VERTEX_BUFFER
+@group(0) @binding(2) var bone_texture: texture_2d<f32>;
+#else
+@group(0) @binding(2) var<storage, read> bone_buffer: array<mat4x4<f32>>;
+#endif
+#endif
+
+@vertex
+fn vs_main(
+    in: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let position = vec4<f32>(in.position, 1.0);
+    let instance = get_instance(in);
+
+#ifdef SKINNED
+    var skinned_pos = vec4<f32>(0.0);
+    for (var i = 0; i < 4; i++) {
+        if (in.bone_ids[i] == -1) {
+            continue;
+        }
+
+        skinned_pos += get_bone(instance.data_indices.x + u32(in.bone_ids[i])) * position * in.bone_weights[i];
+    }
+    let world_pos = instance.model_matrix * skinned_pos;
+#else
+    // Swayed like in static.wgsl, so the shadows of vegetation stay under it
+    let world_pos = apply_wind(instance.model_matrix * position, in.color.a, instance.data_indices.y);
+#endif
+
+    out.clip_position = uniform_buffer.light_matrix * world_pos;
+    return out;
+}
@@ -1,30 +1,6 @@
 // Vertex shader
 
-struct UniformBuffer {
-    view_matrix: mat4x4<f32>,
-    projection_matrix: mat4x4<f32>,
-    camera_position: vec3<f32>,
-    light_matrix: mat4x4<f32>,
-    light_direction: vec3<f32>,
-    light_color: vec4<f32>,
-};
-
-struct Instance {
-    model_matrix: mat4x4<f32>,
-    color: vec4<f32>,
-    tex_bounds: vec4<f32>,
-    bone_offset: u32,
-}
-
-struct VertexInput {
-    @builtin(instance_index) instance_index: u32,
-    @location(0) position: vec3<f32>,
-    @location(1) normal: vec3<f32>,
-    @location(2) uvs: vec3<f32>,
-    @location(3) color: vec4<f32>,
-    @location(4) bone_ids: vec4<i32>,
-    @location(5) bone_weights: vec4<f32>,
-};
+#include "mesh.wgsl"
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
@@ -33,11 +9,14 @@ struct VertexOutput {
     @location(2) color: vec4<f32>,
     @location(3) light_space_position: vec3<f32>,
     @location(4) world_position: vec3<f32>,
+    @location(5) occlusion: f32,
 };
 
-@group(0) @binding(0) var<uniform> uniform_buffer: UniformBuffer;
-@group(0) @binding(1) var<storage, read> instance_buffer: array<Instance>;
+#ifdef INSTANCE_VERTEX_BUFFER
+@group(0) @binding(4) var bone_texture: texture_2d<f32>;
+#else
 @group(0) @binding(4) var<storage, read> bone_buffer: array<mat4x4<f32>>;
+#endif
 
 fn get_bone_debug_color(bone_id : i32) -> vec3<f32> {
     let n = u32(bone_id) * 1664525u + 1013904223u;
@@ -52,7 +31,7 @@ fn get_bone_debug_color(bone_id : i32) -> vec3<f32> {
 fn vs_main(in: VertexInput) -> VertexOutput {
 
     let position = vec4<f32>(in.position, 1.0);
-    let instance = instance_buffer[in.instance_index];
+    let instance = get_instance(in);
 
     var skinned_pos = vec4<f32>(0.0);
     for (var i = 0; i < 4; i++) {
@@ -60,7 +39,7 @@ fn vs_main(in: VertexInput) -> VertexOutput {
             continue;
         }
 
-        skinned_pos += bone_buffer[instance.bone_offset + u32(in.bone_ids[i])] * position * in.bone_weights[i];
+        skinned_pos += get_bone(instance.data_indices.x + u32(in.bone_ids[i])) * position * in.bone_weights[i];
     }
 
     let model = instance.model_matrix;
@@ -79,9 +58,13 @@ fn vs_main(in: VertexInput) -> VertexOutput {
     // }
 
     var out: VertexOutput;
-    out.tex_coords = vec3<f32>(instance.tex_bounds.xy + in.uvs.xy * instance.tex_bounds.zw, in.uvs.z);
+    // The layer of the material variant on top of the one of the mesh
+    let layer = in.uvs.z + f32(instance.data_indices.w);
+    out.tex_coords = vec3<f32>(instance.tex_coord + in.uvs.xy * instance.tex_scale, layer);
     out.clip_position = uniform_buffer.projection_matrix * view_pos;
     out.color = in.color * instance.color;
+    // The vertex alpha is the ambient occlusion baked by the mesh tool, 1 without it
+    out.occlusion = in.color.a;
     // out.color = vec4<f32>(bone_debug_color, 1.0);
 
     // World-space normal (ignoring non-uniform scale issues for now)
@@ -93,13 +76,7 @@ fn vs_main(in: VertexInput) -> VertexOutput {
     out.world_normal = normalize(model3 * in.normal);
     out.world_position = world_pos.xyz;
 
-    // light-space position (shadow map coords) – unchanged
-    let pos_from_light = uniform_buffer.light_matrix * world_pos;
-    let ndc = pos_from_light.xyz / pos_from_light.w;
-    out.light_space_position = vec3f(
-        ndc.xy * vec2f(0.5, -0.5) + vec2f(0.5, 0.5),
-        ndc.z
-    );
+    out.light_space_position = get_light_space_position(world_pos);
 
     return out;
 }
\ No newline at end of file
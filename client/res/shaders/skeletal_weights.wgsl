@@ -0,0 +1,79 @@
+// Vertex shader
+
+#include "mesh.wgsl"
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+#ifdef INSTANCE_VERTEX_BUFFER
+@group(0) @binding(4) var bone_texture: texture_2d<f32>;
+#else
+@group(0) @binding(4) var<storage, read> bone_buffer: array<mat4x4<f32>>;
+#endif
+
+// Blue at 0, green at 0.5 and red at 1
+fn get_heat_color(value: f32) -> vec3<f32> {
+    let t = clamp(value, 0.0, 1.0);
+    return vec3<f32>(
+        clamp(2.0 * t - 1.0, 0.0, 1.0),
+        1.0 - abs(2.0 * t - 1.0),
+        clamp(1.0 - 2.0 * t, 0.0, 1.0),
+    );
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let position = vec4<f32>(in.position, 1.0);
+    let instance = get_instance(in);
+    let bone_offset = instance.data_indices.x;
+    let debug_mode = instance.data_indices.y; // 1 = largest weight, 2 = weight of the selected bone
+    let debug_bone = instance.data_indices.z;
+
+    var skinned_pos = vec4<f32>(0.0);
+    var skinned_normal = vec3<f32>(0.0);
+    var largest_weight = 0.0;
+    var selected_weight = 0.0;
+    for (var i = 0; i < 4; i++) {
+        if (in.bone_ids[i] == -1) {
+            continue;
+        }
+
+        let bone = get_bone(bone_offset + u32(in.bone_ids[i]));
+        let weight = in.bone_weights[i];
+        skinned_pos += bone * position * weight;
+        skinned_normal += mat3x3<f32>(bone[0].xyz, bone[1].xyz, bone[2].xyz) * in.normal * weight;
+        largest_weight = max(largest_weight, weight);
+        if (u32(in.bone_ids[i]) == debug_bone) {
+            selected_weight += weight;
+        }
+    }
+
+    let model = instance.model_matrix;
+    let world_pos = model * skinned_pos;
+
+    var out: VertexOutput;
+    out.clip_position = uniform_buffer.projection_matrix * uniform_buffer.view_matrix * world_pos;
+    out.world_normal = mat3x3<f32>(model[0].xyz, model[1].xyz, model[2].xyz) * skinned_normal;
+
+    // Rigid vertices are red, ones split evenly between four bones are blue. With a
+    // selected bone the vertices it does not move are dark.
+    if (debug_mode == 2u) {
+        out.color = select(vec3<f32>(0.05), get_heat_color(selected_weight), selected_weight > 0.0);
+    } else {
+        out.color = get_heat_color((largest_weight - 0.25) / 0.75);
+    }
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Unlit apart from a little shape from the light, the colors have to stay readable
+    let n_dot_l = dot(normalize(in.world_normal), -normalize(uniform_buffer.light_direction.xyz));
+    let shade = 0.6 + 0.4 * max(n_dot_l, 0.0);
+    return vec4<f32>(in.color * shade, 1.0);
+}
@@ -0,0 +1,67 @@
+// Silhouettes of skeletal meshes where they are behind something else. Drawn with the depth
+// test inverted after the opaque meshes, so only the hidden pixels pass. The color is the
+// flat color of the instance, brighter towards the edges and with every other pixel left out
+// so what is in front can still be seen.
+
+// Vertex shader
+
+#include "mesh.wgsl"
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+#ifdef INSTANCE_VERTEX_BUFFER
+@group(0) @binding(4) var bone_texture: texture_2d<f32>;
+#else
+@group(0) @binding(4) var<storage, read> bone_buffer: array<mat4x4<f32>>;
+#endif
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let position = vec4<f32>(in.position, 1.0);
+    let instance = get_instance(in);
+
+    var skinned_pos = vec4<f32>(0.0);
+    var skinned_normal = vec3<f32>(0.0);
+    for (var i = 0; i < 4; i++) {
+        if (in.bone_ids[i] == -1) {
+            continue;
+        }
+
+        let bone = get_bone(instance.data_indices.x + u32(in.bone_ids[i]));
+        let weight = in.bone_weights[i];
+        skinned_pos += bone * position * weight;
+        skinned_normal += mat3x3<f32>(bone[0].xyz, bone[1].xyz, bone[2].xyz) * in.normal * weight;
+    }
+
+    let model = instance.model_matrix;
+    let world_pos = model * skinned_pos;
+
+    var out: VertexOutput;
+    out.clip_position = uniform_buffer.projection_matrix * uniform_buffer.view_matrix * world_pos;
+    out.world_normal = mat3x3<f32>(model[0].xyz, model[1].xyz, model[2].xyz) * skinned_normal;
+    out.world_position = world_pos.xyz;
+    out.color = instance.color;
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Screen door, a checkerboard of pixels
+    let pixel = vec2<u32>(in.clip_position.xy);
+    if (((pixel.x + pixel.y) & 1u) == 1u) {
+        discard;
+    }
+
+    let n = normalize(in.world_normal);
+    let v = normalize(uniform_buffer.camera_position.xyz - in.world_position);
+    let fresnel = pow(1.0 - clamp(abs(dot(n, v)), 0.0, 1.0), 2.0);
+    let strength = 0.35 + 0.65 * fresnel;
+    return vec4<f32>(in.color.rgb, in.color.a * strength);
+}
@@ -1,16 +1,8 @@
 // Vertex shader
 
-struct UniformBuffer {
-    // x = screen_w_px, y = screen_h_px, z = ui_scale, w unused
-    screen_size_and_ui_scale: vec4<f32>,
-};
-
-struct Instance {
-    position_and_scale: vec4<f32>, // xy = pos_ref_offset, zw = size_ref
-    color: vec4<f32>,
-    tex_bounds: vec4<f32>,         // xy = uv_min, zw = uv_extent
-    mode_layer_anchor_space: vec4<u32>, // x=mode, y=layer, z=anchor, w unused here
-};
+// SpriteUniformBufferData and SpriteInstanceData are generated from the structs in
+// renderer.rs and instance_data.rs
+#include "layouts.wgsl"
 
 struct VertexInput {
     @builtin(instance_index) instance_index: u32,
@@ -18,6 +10,17 @@ struct VertexInput {
     @location(1) normal: vec3<f32>,
     @location(2) uvs: vec3<f32>,
     @location(3) color: vec4<f32>,
+#ifdef INSTANCE_VERTEX_BUFFER
+    // SpriteInstanceData stepped per instance, see SpriteInstanceData::desc
+    @location(4) instance_position: vec2<f32>,
+    @location(5) instance_scale: vec2<f32>,
+    @location(6) instance_color: vec4<f32>,
+    @location(7) instance_tex_coord: vec2<f32>,
+    @location(8) instance_tex_scale: vec2<f32>,
+    @location(9) mode_layer_anchor_space: vec4<u32>,
+    @location(10) rotation: vec2<f32>,
+    @location(11) texture_layer_params_index: vec2<u32>,
+#endif
 };
 
 struct VertexOutput {
@@ -26,48 +29,116 @@ struct VertexOutput {
     @location(1) color: vec4<f32>,
     @location(2) @interpolate(flat) mode: u32,
     @location(3) @interpolate(flat) layer: u32,
+    @location(4) @interpolate(flat) params_index: u32,
+    @location(5) local: vec2<f32>, // 0..1 over the sprite, y down
+    @location(6) @interpolate(flat) size_px: vec2<f32>,
+    @location(7) @interpolate(flat) unit_px: f32, // Pixels per unit of the sprite's space
 };
 
-@group(0) @binding(0) var<uniform> uniform_buffer: UniformBuffer;
-@group(0) @binding(1) var<storage, read> instance_buffer: array<Instance>;
+@group(0) @binding(0) var<uniform> uniform_buffer: SpriteUniformBufferData;
+
+#ifdef INSTANCE_VERTEX_BUFFER
+// SpriteParams, two texels per block, see ShaderData
+@group(0) @binding(2) var params_texture: texture_2d<f32>;
+
+fn get_instance(in: VertexInput) -> SpriteInstanceData {
+    let flags = in.mode_layer_anchor_space;
+    return SpriteInstanceData(
+        in.instance_position,
+        in.instance_scale,
+        in.instance_color,
+        in.instance_tex_coord,
+        in.instance_tex_scale,
+        flags.x,
+        flags.y,
+        flags.z,
+        flags.w,
+        in.rotation,
+        in.texture_layer_params_index.x,
+        in.texture_layer_params_index.y,
+    );
+}
+
+fn get_params(index: u32) -> array<f32, 8> {
+    let width = textureDimensions(params_texture).x;
+    let texel = vec2<u32>((index * 2u) % width, (index * 2u) / width);
+    let first = textureLoad(params_texture, texel, 0);
+    let second = textureLoad(params_texture, texel + vec2<u32>(1u, 0u), 0);
+    return array<f32, 8>(
+        first.x, first.y, first.z, first.w, second.x, second.y, second.z, second.w
+    );
+}
+#else
+@group(0) @binding(1) var<storage, read> instance_buffer: array<SpriteInstanceData>;
+// SpriteParams, the first block is for the sprites without any
+@group(0) @binding(2) var<storage, read> params_buffer: array<array<f32, 8>>;
+
+fn get_instance(in: VertexInput) -> SpriteInstanceData {
+    return instance_buffer[in.instance_index];
+}
+
+fn get_params(index: u32) -> array<f32, 8> {
+    return params_buffer[index];
+}
+#endif
 
-fn anchor_origin_px(anchor: u32, screen_px: vec2<f32>) -> vec2<f32> {
+fn anchor_origin_px(anchor: u32, rect_min_px: vec2<f32>, rect_px: vec2<f32>) -> vec2<f32> {
     let ax = anchor % 3u;
     let ay = anchor / 3u;
     let ox = select(0.0, select(0.5, 1.0, ax == 2u), ax != 0u);
     let oy = select(0.0, select(0.5, 1.0, ay == 2u), ay != 0u);
-    return vec2<f32>(ox * screen_px.x, oy * screen_px.y);
+    return rect_min_px + vec2<f32>(ox * rect_px.x, oy * rect_px.y);
+}
+
+// Where the corner is from the top left of the sprite, turned around its center. Clockwise
+// on screen as y points down, the rotation is the cosine and sine of the angle.
+fn get_corner_px(local01: vec2<f32>, size_px: vec2<f32>, rotation: vec2<f32>) -> vec2<f32> {
+    let offset = (local01 - 0.5) * size_px;
+    let turned = vec2<f32>(
+        rotation.x * offset.x - rotation.y * offset.y,
+        rotation.y * offset.x + rotation.x * offset.y
+    );
+    return size_px * 0.5 + turned;
 }
 
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
-    let instance = instance_buffer[in.instance_index];
+    let instance = get_instance(in);
 
-    let screen_px = uniform_buffer.screen_size_and_ui_scale.xy;
-    let ui_scale = uniform_buffer.screen_size_and_ui_scale.z;
+    let screen_px = uniform_buffer.screen_size;
+    // Pixels per reference unit on each axis, the fit of the reference screen times the DPI scale
+    let ui_scale = uniform_buffer.ui_scale;
 
-    let mode = instance.mode_layer_anchor_space.x;
-    let layer = instance.mode_layer_anchor_space.y;
-    let anchor = instance.mode_layer_anchor_space.z;
-    let space = instance.mode_layer_anchor_space.w;
+    let mode = instance.mode;
+    let layer = instance.layer;
+    let anchor = instance.anchor;
+    let space = instance.space;
 
     var out: VertexOutput;
+    let local01 = vec2<f32>(in.position.x, 1.0 - in.position.y);
+    var size_px = screen_px;
+    var unit_px = min(screen_px.x, screen_px.y);
 
     if (space == 0) { // Reference space
-        let local01 = vec2<f32>(in.position.x, 1.0 - in.position.y);
-        let anchor_px = anchor_origin_px(anchor, screen_px);
-        let pos_px  = anchor_px + instance.position_and_scale.xy * ui_scale;
-        let size_px = instance.position_and_scale.zw * ui_scale;
-        let p_px = pos_px + local01 * size_px;
+        let safe_rect = uniform_buffer.safe_rect;
+        let anchor_px = anchor_origin_px(anchor, safe_rect.xy, safe_rect.zw);
+        var pos_px  = anchor_px + instance.position * ui_scale;
+        // Glyphs start on whole pixels so the MSDF edges land the same way in every glyph
+        if (mode == 1u) {
+            pos_px = round(pos_px);
+        }
+        size_px = instance.scale * ui_scale;
+        unit_px = min(ui_scale.x, ui_scale.y);
+        let p_px = pos_px + get_corner_px(local01, size_px, instance.rotation);
         let ndc_x = (p_px.x / screen_px.x) * 2.0 - 1.0;
         let ndc_y = 1.0 - (p_px.y / screen_px.y) * 2.0;
         out.clip_position = vec4<f32>(ndc_x, ndc_y, 0.0, 1.0);
     } else if (space == 1) { // Absolute space
-        let local01 = vec2<f32>(in.position.x, 1.0 - in.position.y);
-        let anchor_px = anchor_origin_px(anchor, screen_px);
-        let pos_px = anchor_px + instance.position_and_scale.xy;
-        let size_px = instance.position_and_scale.zw;
-        let p_px = pos_px + local01 * size_px;
+        let anchor_px = anchor_origin_px(anchor, vec2<f32>(0.0), screen_px);
+        let pos_px = anchor_px + instance.position;
+        size_px = instance.scale;
+        unit_px = 1.0;
+        let p_px = pos_px + get_corner_px(local01, size_px, instance.rotation);
         let ndc_x = (p_px.x / screen_px.x) * 2.0 - 1.0;
         let ndc_y = 1.0 - (p_px.y / screen_px.y) * 2.0;
         out.clip_position = vec4<f32>(ndc_x, ndc_y, 0.0, 1.0);
@@ -76,12 +147,16 @@ fn vs_main(in: VertexInput) -> VertexOutput {
     }
 
     out.tex_coords = vec3<f32>(
-        instance.tex_bounds.xy + in.uvs.xy * instance.tex_bounds.zw,
-        f32(layer)
+        instance.tex_coord + in.uvs.xy * instance.tex_scale,
+        f32(instance.texture_layer) // The layer of a variant material
     );
     out.color = in.color * instance.color;
     out.mode = mode;
     out.layer = layer;
+    out.params_index = instance.params_index;
+    out.local = local01;
+    out.size_px = size_px;
+    out.unit_px = unit_px;
 
     return out;
 }
@@ -92,35 +167,84 @@ fn median3(r: f32, g: f32, b: f32) -> f32 {
     return max(min(r, g), min(max(r, g), b));
 }
 
-fn screen_px_range(uv: vec2<f32>, tex_size: vec2<f32>) -> f32 {
-    let unit_range = vec2<f32>(2.0, 2.0) / tex_size;
+fn screen_px_range(uv: vec2<f32>, tex_size: vec2<f32>, distance_range: f32) -> f32 {
+    let unit_range = vec2<f32>(distance_range) / tex_size;
     let screen_tex_size = vec2<f32>(1.0, 1.0) / fwidth(uv);
     return max(0.5 * dot(unit_range, screen_tex_size), 1.0);
 }
 
-fn msdf_opacity_from_texel(texel: vec4<f32>, uv: vec2<f32>, tex_size: vec2<f32>) -> f32 {
+fn msdf_opacity_from_texel(
+    texel: vec4<f32>,
+    uv: vec2<f32>,
+    tex_size: vec2<f32>,
+    distance_range: f32
+) -> f32 {
     let sd = median3(texel.r, texel.g, texel.b);
-    let px_dist = screen_px_range(uv, tex_size) * (sd - 0.5);
+    let px_dist = screen_px_range(uv, tex_size, distance_range) * (sd - 0.5);
     return clamp(px_dist + 0.5, 0.0, 1.0);
 }
 
+// Negative inside the rounded rectangle around the origin, in the units of the position
+fn rounded_rect_distance(position: vec2<f32>, half_size: vec2<f32>, radius: f32) -> f32 {
+    let r = clamp(radius, 0.0, min(half_size.x, half_size.y));
+    let q = abs(position) - half_size + r;
+    return length(max(q, vec2<f32>(0.0))) + min(max(q.x, q.y), 0.0) - r;
+}
+
 @group(1) @binding(0) var texture: texture_2d_array<f32>;
 @group(1) @binding(1) var texture_sampler: sampler;
 
+// Set for textures made with --premultiply, whose color is already multiplied by its alpha.
+// The tint alpha is multiplied into the color as well, the pipeline blends it that way.
+override premultiplied_alpha: bool = false;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let tex_size_u = textureDimensions(texture, 0);
     let tex_size = vec2<f32>(f32(tex_size_u.x), f32(tex_size_u.y));
+    let params = get_params(in.params_index);
 
     let texel = textureSample(texture, texture_sampler, in.tex_coords.xy, i32(in.tex_coords.z));
-    let msdf_opacity = msdf_opacity_from_texel(texel, in.tex_coords.xy, tex_size);
-    let use_msdf = select(0.0, 1.0, in.mode != 0u);
-    let alpha = mix(texel.a, msdf_opacity, use_msdf);
+    // Glyphs without params are from atlases baked with the default range of 2
+    let distance_range = select(2.0, params[0], in.mode == 1u && in.params_index != 0u);
+    let msdf_opacity = msdf_opacity_from_texel(texel, in.tex_coords.xy, tex_size, distance_range);
+    let use_msdf = select(0.0, 1.0, in.mode == 1u);
+    var alpha = mix(texel.a, msdf_opacity, use_msdf);
+
+    // Radial fill, clockwise from the top as y points down
+    if (in.mode == 2u) {
+        let offset = in.local - 0.5;
+        let turn = fract(atan2(offset.x, -offset.y) / 6.28318530718 + 1.0);
+        alpha *= select(0.0, 1.0, turn < params[0]);
+    }
+
+    // Rounded rectangle and glow, measured in pixels so the edges stay as sharp at any scale
+    if (in.mode == 3u || in.mode == 4u) {
+        let position = (in.local - 0.5) * in.size_px;
+        let half_size = max(in.size_px * 0.5 - params[3] * in.unit_px, vec2<f32>(0.0));
+        let distance = rounded_rect_distance(position, half_size, params[0] * in.unit_px);
+        if (in.mode == 3u) {
+            // At least a pixel wide, which antialiases the crisp edges
+            let width = max(params[2] * in.unit_px, 1.0);
+            var coverage = 1.0 - smoothstep(-0.5 * width, 0.5 * width, distance);
+            let border_px = params[1] * in.unit_px;
+            if (border_px > 0.0) {
+                coverage *= smoothstep(-0.5 * width, 0.5 * width, distance + border_px);
+            }
+            alpha *= coverage;
+        } else {
+            let falloff_px = max(params[2] * in.unit_px, 1.0);
+            alpha *= exp(-max(distance, 0.0) / falloff_px);
+        }
+    }
 
     let sprite_rgb = texel.rgb * in.color.rgb;
     let msdf_rgb = in.color.rgb;
     let rgb = mix(sprite_rgb, msdf_rgb, use_msdf);
 
+    if (premultiplied_alpha) {
+        return vec4<f32>(rgb * in.color.a, in.color.a * alpha);
+    }
     return vec4<f32>(rgb, in.color.a * alpha);
 }
 
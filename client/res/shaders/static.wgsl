@@ -1,28 +1,6 @@
 // Vertex shader
 
-struct UniformBuffer {
-    view_matrix: mat4x4<f32>,
-    projection_matrix: mat4x4<f32>,
-    camera_position: vec3<f32>,
-    light_matrix: mat4x4<f32>,
-    light_direction: vec3<f32>,
-    light_color: vec4<f32>,
-};
-
-struct Instance {
-    model_matrix: mat4x4<f32>,
-    color: vec4<f32>,
-    tex_bounds: vec4<f32>,
-    data_indices: vec4<u32>,
-}
-
-struct VertexInput {
-    @builtin(instance_index) instance_index: u32,
-    @location(0) position: vec3<f32>,
-    @location(1) normal: vec3<f32>,
-    @location(2) uvs: vec3<f32>,
-    @location(3) color: vec4<f32>,
-};
+#include "mesh.wgsl"
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
@@ -31,29 +9,33 @@ struct VertexOutput {
     @location(2) color: vec4<f32>,
     @location(3) light_space_position: vec3<f32>,
     @location(4) world_position: vec3<f32>,
+    @location(5) occlusion: f32,
 };
 
-@group(0) @binding(0) var<uniform> uniform_buffer: UniformBuffer;
-@group(0) @binding(1) var<storage, read> instance_buffer: array<Instance>;
-
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
     let position = vec4<f32>(in.position, 1.0);
 
-    let instance = instance_buffer[in.instance_index];
+    let instance = get_instance(in);
 
     let model = instance.model_matrix;
+    let flags = instance.data_indices.y;
 
-    // World-space position
-    let world_pos = model * position;
+    // World-space position, swaying materials move with the wind
+    let world_pos = apply_wind(model * position, in.color.a, flags);
 
     // View-space position
     let view_pos = uniform_buffer.view_matrix * world_pos;
 
     var out: VertexOutput;
-    out.tex_coords = vec3<f32>(instance.tex_bounds.xy + in.uvs.xy * instance.tex_bounds.zw, in.uvs.z);
+    // The layer of the material variant on top of the one of the mesh
+    let layer = in.uvs.z + f32(instance.data_indices.w);
+    out.tex_coords = vec3<f32>(instance.tex_coord + in.uvs.xy * instance.tex_scale, layer);
     out.clip_position = uniform_buffer.projection_matrix * view_pos;
     out.color = in.color * instance.color;
+    // The vertex alpha is the ambient occlusion baked by the mesh tool, 1 without it. On
+    // swaying materials it weights the sway instead.
+    out.occlusion = select(in.color.a, 1.0, (flags & INSTANCE_FLAG_WIND) != 0u);
 
     // World-space normal (ignoring non-uniform scale issues for now)
     let model3 = mat3x3<f32>(
@@ -64,13 +46,7 @@ fn vs_main(in: VertexInput) -> VertexOutput {
     out.world_normal = normalize(model3 * in.normal);
     out.world_position = world_pos.xyz;
 
-    // light-space position (shadow map coords) – unchanged
-    let pos_from_light = uniform_buffer.light_matrix * world_pos;
-    let ndc = pos_from_light.xyz / pos_from_light.w;
-    out.light_space_position = vec3f(
-        ndc.xy * vec2f(0.5, -0.5) + vec2f(0.5, 0.5),
-        ndc.z
-    );
+    out.light_space_position = get_light_space_position(world_pos);
 
     return out;
 }
\ No newline at end of file
@@ -0,0 +1,119 @@
+// The grid on the Y=0 plane, drawn over the composited scene, see world_grid.rs
+
+#include "common.wgsl"
+
+// Vertex shader
+
+struct VertexInput {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uvs: vec3<f32>,
+    @location(3) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = in.uvs.xy;
+    out.clip_position = vec4<f32>(in.position, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+// The depth buffer of the scene, at the render scale. Bound as floats, GLSL can't load from
+// depth textures.
+#ifdef MULTISAMPLED
+@group(0) @binding(1) var depth_texture: texture_multisampled_2d<f32>;
+#else
+@group(0) @binding(1) var depth_texture: texture_2d<f32>;
+#endif
+
+// WorldGridUniformData
+struct WorldGridUniform {
+    spacing: f32,
+    major_every: f32,
+    fade_distance: f32,
+    _padding: f32,
+};
+
+@group(0) @binding(2) var<uniform> grid: WorldGridUniform;
+
+const MINOR_COLOR: vec4<f32> = vec4<f32>(0.8, 0.8, 0.8, 0.35);
+const MAJOR_COLOR: vec4<f32> = vec4<f32>(0.9, 0.9, 0.9, 0.7);
+const X_AXIS_COLOR: vec4<f32> = vec4<f32>(0.95, 0.25, 0.2, 0.9);
+const Z_AXIS_COLOR: vec4<f32> = vec4<f32>(0.2, 0.45, 0.95, 0.9);
+// In pixels
+const MINOR_WIDTH: f32 = 1.0;
+const MAJOR_WIDTH: f32 = 1.5;
+const AXIS_WIDTH: f32 = 2.5;
+
+// The first sample is enough, the grid only needs to know what is in front
+fn load_depth(uv: vec2<f32>) -> f32 {
+    let size = textureDimensions(depth_texture);
+    let texel = min(vec2<u32>(uv * vec2<f32>(size)), size - 1u);
+    return textureLoad(depth_texture, texel, 0).r;
+}
+
+// How much of the pixel the lines through the whole coordinates cover, per axis. The
+// derivative is of the coordinate over a pixel.
+fn get_line_coverage(coord: vec2<f32>, derivative: vec2<f32>, width: f32) -> vec2<f32> {
+    let distance = abs(fract(coord - 0.5) - 0.5) / derivative;
+    return 1.0 - clamp(distance - 0.5 * width + 0.5, vec2<f32>(0.0), vec2<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = in.tex_coords;
+
+    // Where the view ray of the pixel meets the plane between the near and the far plane
+    let near = get_world_position(uv, 0.0);
+    let far = get_world_position(uv, 1.0);
+    let direction = far - near;
+    let crosses = abs(direction.y) > 1e-6;
+    let t = select(-1.0, -near.y / direction.y, crosses);
+    let point = near + direction * t;
+
+    // The derivatives before anything differs between the pixels of a quad
+    let coord = point.xz / grid.spacing;
+    let derivative = max(fwidth(coord), vec2<f32>(1e-6));
+    let axis_derivative = max(fwidth(point.xz), vec2<f32>(1e-6));
+
+    let minor = get_line_coverage(coord, derivative, MINOR_WIDTH);
+    let major = get_line_coverage(
+        coord / grid.major_every,
+        derivative / grid.major_every,
+        MAJOR_WIDTH
+    );
+    let axis = 1.0 - clamp(
+        abs(point.xz) / axis_derivative - 0.5 * AXIS_WIDTH + 0.5,
+        vec2<f32>(0.0),
+        vec2<f32>(1.0)
+    );
+
+    // The minor lines give way before their cells shrink to a few pixels
+    let minor_fade = 1.0 - smoothstep(0.25, 0.5, max(derivative.x, derivative.y));
+    var color = vec4<f32>(MINOR_COLOR.rgb, MINOR_COLOR.a * max(minor.x, minor.y) * minor_fade);
+    let major_coverage = max(major.x, major.y);
+    color = mix(color, vec4<f32>(MAJOR_COLOR.rgb, MAJOR_COLOR.a * major_coverage), major_coverage);
+    // The X axis is the line along X, where z is 0
+    color = mix(color, X_AXIS_COLOR, axis.y);
+    color = mix(color, Z_AXIS_COLOR, axis.x);
+
+    let camera = uniform_buffer.camera_position.xyz;
+    let distance = length(point - camera);
+    let fade = 1.0 - smoothstep(grid.fade_distance * 0.5, grid.fade_distance, distance);
+
+    // Hidden behind the scene, with some slack for a ground that is at 0 itself
+    let surface = get_world_position(uv, load_depth(uv));
+    let slack = 0.02 + distance * 0.002;
+    let visible = crosses && t >= 0.0 && t <= 1.0 && length(surface - camera) > distance - slack;
+
+    return select(vec4<f32>(0.0), vec4<f32>(color.rgb, color.a * fade), visible);
+}
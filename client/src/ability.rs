@@ -0,0 +1,774 @@
+// Abilities on Q, W, E and R, described as data in RON files like assets/abilities/default.ron.
+// A caster runs one cast at a time through a small state machine advanced by the fixed
+// update: Idle, Casting until the cast time is up, Executing for the step the effect lands
+// in, then Idle again with the ability on cooldown. Cast times and cooldowns are counted in
+// fixed steps, so they end on the same step on every machine. The effects are carried out by
+// Game::execute_abilities, spawning projectiles needs the renderer.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+use shared::{math::*, physics::PhysicsWorld, profile_scope};
+
+use crate::{
+    combat::{CHealth, is_in_range},
+    components::{Entities, Entity, Storage, join3},
+    events::{GameEvent, GameEvents},
+    game::{CPhysicsProxy, CPlayerMovement, CTargetLocation},
+    prefab::PrefabLibrary,
+    status_effects::StatusEffectDesc,
+};
+
+pub const ABILITY_SLOTS: usize = 4; // Q, W, E and R
+
+// A duration a hair over a whole number of steps still ends on that step, float division
+// of the duration shouldn't add a step
+const STEP_TOLERANCE: f32 = 1e-3;
+
+const DEFAULT_MAX_ENERGY: f32 = 100.0;
+const DEFAULT_ENERGY_REGEN: f32 = 5.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AbilityDesc {
+    pub name: String,
+    pub cast_time: f32, // From the start of the cast to the effect, 0 lands it right away
+    pub cooldown: f32,  // Starts when the effect lands
+    #[serde(default)]
+    pub cost: f32, // Energy, paid when the effect lands
+    #[serde(default)]
+    pub range: f32, // From the caster center to the point or the target center
+    pub targeting: Targeting,
+    #[serde(default)]
+    pub locks_movement: bool,
+    #[serde(default)]
+    pub interrupted_by_movement: bool, // A move order during the cast cancels it
+    #[serde(default)]
+    pub interrupted_by_damage: bool,
+    pub effect: AbilityEffect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Targeting {
+    Point,
+    Unit,
+    Direction,
+    SelfCast,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum AbilityEffect {
+    // Flies from the caster towards the target, as far as the range for a direction
+    Projectile {
+        prefab: String,
+        speed: f32,
+        damage: f32,
+        #[serde(default)]
+        status: Option<StatusEffectDesc>, // Applied to the unit it hits
+    },
+    Damage {
+        amount: f32,
+    },
+    Heal {
+        amount: f32,
+    },
+    // On the units of other teams around a point, or on the target unit or the caster
+    Status {
+        effect: StatusEffectDesc,
+        radius: f32,
+    },
+    // Teleports the caster towards the point, see resolve_blink_destination. Points past the
+    // range are fine, the blink stops at it.
+    Blink {
+        search_radius: f32, // How far from a taken destination a free one may be
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AbilityTarget {
+    Point(Vec3),
+    Unit(Entity),
+    Direction(Vec2), // On the ground, as xz
+    SelfCast,
+}
+
+impl AbilityTarget {
+    fn matches(&self, targeting: Targeting) -> bool {
+        matches!(
+            (self, targeting),
+            (AbilityTarget::Point(_), Targeting::Point)
+                | (AbilityTarget::Unit(_), Targeting::Unit)
+                | (AbilityTarget::Direction(_), Targeting::Direction)
+                | (AbilityTarget::SelfCast, Targeting::SelfCast)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CastPhase {
+    Idle,
+    Casting {
+        slot: usize,
+        target: AbilityTarget,
+        elapsed: u32, // Steps since the cast started
+        steps: u32,   // Of the cast time, the effect lands once elapsed reaches them
+        health: f32,  // Of the caster on the last step, losing some is taking damage
+    },
+    Executing {
+        slot: usize,
+        target: AbilityTarget,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct AbilitySlot {
+    desc: AbilityDesc,
+    cooldown: u32,       // Steps until it can be cast again
+    cooldown_total: u32, // Of the running cooldown, for the HUD
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AbilityCaster {
+    slots: [Option<AbilitySlot>; ABILITY_SLOTS],
+    phase: CastPhase,
+    requested: Option<(usize, AbilityTarget)>,
+    pub energy: f32,
+    pub max_energy: f32,
+    pub energy_regen: f32, // Per second
+}
+
+impl AbilityCaster {
+    pub fn new(abilities: [Option<AbilityDesc>; ABILITY_SLOTS]) -> Self {
+        Self {
+            slots: abilities.map(|desc| {
+                desc.map(|desc| AbilitySlot {
+                    desc,
+                    cooldown: 0,
+                    cooldown_total: 0,
+                })
+            }),
+            phase: CastPhase::Idle,
+            requested: None,
+            energy: DEFAULT_MAX_ENERGY,
+            max_energy: DEFAULT_MAX_ENERGY,
+            energy_regen: DEFAULT_ENERGY_REGEN,
+        }
+    }
+
+    // Picked up by the next update, dropped there when the cast can't start
+    pub fn request_cast(&mut self, slot: usize, target: AbilityTarget) {
+        self.requested = Some((slot, target));
+    }
+
+    pub fn get_ability(&self, slot: usize) -> Option<&AbilityDesc> {
+        self.get_slot(slot).map(|slot| &slot.desc)
+    }
+
+    pub fn get_phase(&self) -> CastPhase {
+        self.phase
+    }
+
+    #[allow(dead_code)]
+    pub fn is_casting(&self) -> bool {
+        matches!(self.phase, CastPhase::Casting { .. })
+    }
+
+    pub fn is_ready(&self, slot: usize) -> bool {
+        self.get_slot(slot).is_some_and(|slot| slot.cooldown == 0)
+    }
+
+    // 1 right after the effect landed down to 0 when it can be cast again
+    pub fn get_cooldown_fraction(&self, slot: usize) -> f32 {
+        match self.get_slot(slot) {
+            Some(slot) if slot.cooldown_total > 0 => {
+                slot.cooldown as f32 / slot.cooldown_total as f32
+            }
+            _ => 0.0,
+        }
+    }
+
+    // How far the running cast is, from 0 to 1
+    pub fn get_cast_progress(&self) -> Option<(usize, f32)> {
+        let CastPhase::Casting {
+            slot,
+            elapsed,
+            steps,
+            ..
+        } = self.phase
+        else {
+            return None;
+        };
+        Some((slot, (elapsed as f32 / steps.max(1) as f32).min(1.0)))
+    }
+
+    // The steps left of the cooldown and the steps it started with, for saving
+    pub fn get_cooldown_steps(&self, slot: usize) -> (u32, u32) {
+        self.get_slot(slot)
+            .map_or((0, 0), |slot| (slot.cooldown, slot.cooldown_total))
+    }
+
+    pub fn set_cooldown_steps(&mut self, slot: usize, cooldown: u32, total: u32) {
+        if let Some(Some(slot)) = self.slots.get_mut(slot) {
+            slot.cooldown = cooldown;
+            slot.cooldown_total = total.max(cooldown);
+        }
+    }
+
+    fn get_slot(&self, slot: usize) -> Option<&AbilitySlot> {
+        self.slots.get(slot)?.as_ref()
+    }
+}
+
+// Handed to Game::execute_abilities, once per landed cast
+#[derive(Debug, Clone, PartialEq)]
+pub struct AbilityExecution {
+    pub caster: Entity,
+    pub slot: usize,
+    pub target: AbilityTarget,
+    pub desc: AbilityDesc,
+}
+
+// The fixed steps a duration takes, the first step at or after it
+pub fn get_steps(duration: f32, dt: f32) -> u32 {
+    (duration / dt - STEP_TOLERANCE).ceil().max(0.0) as u32
+}
+
+// Runs after the combat update, a locking cast keeps the movement locked over the attack's
+// unlock. Returns the casts whose effect lands this step.
+#[allow(clippy::too_many_arguments)]
+pub fn update_abilities(
+    dt: f32,
+    entities: &Entities,
+    casters: &mut Storage<AbilityCaster>,
+    healths: &Storage<CHealth>,
+    physics_proxies: &Storage<CPhysicsProxy>,
+    movements: &mut Storage<CPlayerMovement>,
+    move_targets: &mut Storage<CTargetLocation>,
+    physics_world: &PhysicsWorld,
+    events: &mut GameEvents,
+) -> Vec<AbilityExecution> {
+    profile_scope!("Abilities");
+    let mut executions = Vec::new();
+    for (caster_entity, caster, physics_proxy) in join3(entities, casters, physics_proxies) {
+        for slot in caster.slots.iter_mut().flatten() {
+            slot.cooldown = slot.cooldown.saturating_sub(1);
+        }
+        caster.energy = (caster.energy + caster.energy_regen * dt).min(caster.max_energy);
+        let requested = caster.requested.take();
+
+        let health = healths.get(caster_entity);
+        let position = physics_proxy
+            .body_id
+            .and_then(|body_id| physics_world.get_state(body_id))
+            .map(|state| state.position);
+        let is_valid = |desc: &AbilityDesc, target: &AbilityTarget| {
+            let Some(position) = position else {
+                return false;
+            };
+            match *target {
+                _ if !target.matches(desc.targeting) => false,
+                AbilityTarget::Point(_) if matches!(desc.effect, AbilityEffect::Blink { .. }) => {
+                    true
+                }
+                AbilityTarget::Point(point) => position.distance(point.xz()) <= desc.range,
+                AbilityTarget::Unit(target) => {
+                    let target_body = physics_proxies.get(target).and_then(|proxy| proxy.body_id);
+                    healths.get(target).is_some_and(|health| !health.is_dead())
+                        && physics_proxy.body_id.zip(target_body).is_some_and(
+                            |(body_id, target_body)| {
+                                is_in_range(physics_world, body_id, desc.range, target_body)
+                            },
+                        )
+                }
+                AbilityTarget::Direction(direction) => direction != Vec2::ZERO,
+                AbilityTarget::SelfCast => true,
+            }
+        };
+
+        let mut phase = match caster.phase {
+            CastPhase::Executing { .. } => CastPhase::Idle,
+            CastPhase::Casting {
+                slot,
+                target,
+                elapsed,
+                steps,
+                health,
+            } => CastPhase::Casting {
+                slot,
+                target,
+                elapsed: elapsed + 1,
+                steps,
+                health,
+            },
+            CastPhase::Idle => CastPhase::Idle,
+        };
+
+        if health.is_some_and(CHealth::is_dead) {
+            if let CastPhase::Casting { slot, .. } = phase {
+                events.push(GameEvent::AbilityInterrupted {
+                    caster: caster_entity,
+                    slot,
+                });
+            }
+            phase = CastPhase::Idle;
+        } else if phase == CastPhase::Idle
+            && let Some((slot, target)) = requested
+            && let Some(desc) = caster.get_ability(slot)
+            && caster.is_ready(slot)
+            && caster.energy >= desc.cost
+            && is_valid(desc, &target)
+        {
+            // Stop walking, a later move order is what interrupts
+            if (desc.locks_movement || desc.interrupted_by_movement)
+                && let Some(move_target) = move_targets.get_mut(caster_entity)
+            {
+                *move_target = None;
+            }
+            phase = CastPhase::Casting {
+                slot,
+                target,
+                elapsed: 0,
+                steps: get_steps(desc.cast_time, dt),
+                health: health.map_or(0.0, |health| health.current),
+            };
+            events.push(GameEvent::AbilityCastStarted {
+                caster: caster_entity,
+                slot,
+            });
+        }
+
+        if let CastPhase::Casting {
+            slot,
+            target,
+            elapsed,
+            steps,
+            health: last_health,
+        } = &mut phase
+            && let Some(desc) = caster.get_ability(*slot)
+        {
+            let current_health = health.map_or(0.0, |health| health.current);
+            let moved = move_targets
+                .get(caster_entity)
+                .is_some_and(|target| target.is_some());
+            let damaged = current_health < *last_health;
+            *last_health = current_health;
+
+            if (desc.interrupted_by_movement && moved) || (desc.interrupted_by_damage && damaged) {
+                events.push(GameEvent::AbilityInterrupted {
+                    caster: caster_entity,
+                    slot: *slot,
+                });
+                phase = CastPhase::Idle;
+            } else if *elapsed >= *steps {
+                // Checked again, the target may have died or walked away during the cast
+                let (slot, target) = (*slot, *target);
+                if caster.energy >= desc.cost && is_valid(desc, &target) {
+                    executions.push(AbilityExecution {
+                        caster: caster_entity,
+                        slot,
+                        target,
+                        desc: desc.clone(),
+                    });
+                    events.push(GameEvent::AbilityExecuted {
+                        caster: caster_entity,
+                        slot,
+                    });
+                    phase = CastPhase::Executing { slot, target };
+                } else {
+                    events.push(GameEvent::AbilityInterrupted {
+                        caster: caster_entity,
+                        slot,
+                    });
+                    phase = CastPhase::Idle;
+                }
+            }
+        }
+
+        if let CastPhase::Executing { slot, .. } = phase
+            && let Some(slot) = caster.slots[slot].as_mut()
+        {
+            caster.energy -= slot.desc.cost;
+            slot.cooldown = get_steps(slot.desc.cooldown, dt);
+            slot.cooldown_total = slot.cooldown;
+        }
+
+        let locks_movement = match phase {
+            CastPhase::Casting { slot, .. } => caster
+                .get_ability(slot)
+                .is_some_and(|desc| desc.locks_movement),
+            _ => false,
+        };
+        let was_locking = match caster.phase {
+            CastPhase::Casting { slot, .. } => caster
+                .get_ability(slot)
+                .is_some_and(|desc| desc.locks_movement),
+            _ => false,
+        };
+        if let Some(movement) = movements.get_mut(caster_entity) {
+            if locks_movement {
+                movement.locked = true;
+            } else if was_locking {
+                movement.locked = false;
+            }
+        }
+        caster.phase = phase;
+    }
+    executions
+}
+
+#[derive(Debug, Default)]
+pub struct AbilityLibrary {
+    abilities: HashMap<String, AbilityDesc>,
+}
+
+impl AbilityLibrary {
+    // Parses a file with a list of abilities, errors name the ability
+    pub fn load(bytes: &[u8]) -> anyhow::Result<Self> {
+        let options = ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME);
+        let mut deserializer = ron::Deserializer::from_bytes_with_options(bytes, options)?;
+        let descs: Vec<AbilityDesc> =
+            serde_path_to_error::deserialize(&mut deserializer).map_err(|error| {
+                let path = error.path().to_string();
+                anyhow!("{}: {}", path, deserializer.span_error(error.into_inner()))
+            })?;
+        deserializer
+            .end()
+            .map_err(|error| deserializer.span_error(error))?;
+
+        let mut abilities = HashMap::new();
+        for desc in descs {
+            check_desc(&desc)?;
+            let name = desc.name.clone();
+            if abilities.insert(name.clone(), desc).is_some() {
+                bail!("{}: there is another ability with this name", name);
+            }
+        }
+        Ok(Self { abilities })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AbilityDesc> {
+        self.abilities.get(name)
+    }
+
+    // The prefabs the abilities shoot, sorted and each once
+    pub fn get_projectile_prefabs(&self) -> Vec<&str> {
+        let mut prefabs: Vec<&str> = self
+            .abilities
+            .values()
+            .filter_map(|desc| match &desc.effect {
+                AbilityEffect::Projectile { prefab, .. } => Some(prefab.as_str()),
+                _ => None,
+            })
+            .collect();
+        prefabs.sort();
+        prefabs.dedup();
+        prefabs
+    }
+
+    // The projectiles the abilities shoot have to be prefabs
+    pub fn validate(&self, prefabs: &PrefabLibrary) -> anyhow::Result<()> {
+        let mut abilities: Vec<&AbilityDesc> = self.abilities.values().collect();
+        abilities.sort_by_key(|desc| &desc.name);
+        for desc in abilities {
+            if let AbilityEffect::Projectile { prefab, .. } = &desc.effect
+                && prefabs.get(prefab).is_none()
+            {
+                bail!("{}.effect: no prefab named \"{}\"", desc.name, prefab);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_desc(desc: &AbilityDesc) -> anyhow::Result<()> {
+    for (field, value) in [
+        ("cast_time", desc.cast_time),
+        ("cooldown", desc.cooldown),
+        ("cost", desc.cost),
+        ("range", desc.range),
+        (
+            "effect.search_radius",
+            match desc.effect {
+                AbilityEffect::Blink { search_radius } => search_radius,
+                _ => 0.0,
+            },
+        ),
+    ] {
+        if value.is_nan() || value < 0.0 {
+            bail!(
+                "{}.{}: has to be 0 or more, not {}",
+                desc.name,
+                field,
+                value
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::physics::{BodySettings, CollisionShape};
+
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn get_desc(cast_time: f32, cooldown: f32) -> AbilityDesc {
+        AbilityDesc {
+            name: "Bolt".to_string(),
+            cast_time,
+            cooldown,
+            cost: 10.0,
+            range: 500.0,
+            targeting: Targeting::Point,
+            locks_movement: true,
+            interrupted_by_movement: true,
+            interrupted_by_damage: true,
+            effect: AbilityEffect::Damage { amount: 10.0 },
+        }
+    }
+
+    // One caster with the ability on Q, stepped like Game::fixed_update
+    struct Simulation {
+        physics_world: PhysicsWorld,
+        entities: Entities,
+        casters: Storage<AbilityCaster>,
+        healths: Storage<CHealth>,
+        physics_proxies: Storage<CPhysicsProxy>,
+        movements: Storage<CPlayerMovement>,
+        move_targets: Storage<CTargetLocation>,
+        events: GameEvents,
+        caster: Entity,
+        executions: Vec<(u32, AbilityExecution)>, // With the step they landed on
+        step: u32,
+    }
+
+    impl Simulation {
+        fn new(desc: AbilityDesc) -> Self {
+            let mut physics_world = PhysicsWorld::new();
+            let mut entities = Entities::default();
+            let caster = entities.spawn();
+            let body_id = physics_world.create_rigid_body(&BodySettings {
+                position: Vec2::ZERO,
+                velocity: Vec2::ZERO,
+                layer: shared::physics::CollisionLayer::Player,
+                shape: &CollisionShape::Circle { radius: 32.0 },
+                listen_to_contact_events: false,
+            });
+            physics_world.step_simulation(0.0);
+
+            let mut physics_proxies = Storage::default();
+            physics_proxies.insert(caster, CPhysicsProxy::new(body_id, &physics_world));
+            let mut healths = Storage::default();
+            healths.insert(caster, CHealth::new(100.0));
+            let mut casters = Storage::default();
+            casters.insert(caster, AbilityCaster::new([Some(desc), None, None, None]));
+            let mut movements = Storage::default();
+            movements.insert(caster, Default::default());
+            let mut move_targets = Storage::default();
+            move_targets.insert(caster, None);
+
+            Self {
+                physics_world,
+                entities,
+                casters,
+                healths,
+                physics_proxies,
+                movements,
+                move_targets,
+                events: Default::default(),
+                caster,
+                executions: Vec::new(),
+                step: 0,
+            }
+        }
+
+        fn tick(&mut self) {
+            self.step += 1;
+            let executions = update_abilities(
+                DT,
+                &self.entities,
+                &mut self.casters,
+                &self.healths,
+                &self.physics_proxies,
+                &mut self.movements,
+                &mut self.move_targets,
+                &self.physics_world,
+                &mut self.events,
+            );
+            let step = self.step;
+            self.executions
+                .extend(executions.into_iter().map(|execution| (step, execution)));
+        }
+
+        fn cast(&mut self) {
+            self.get_caster_mut()
+                .request_cast(0, AbilityTarget::Point(Vec3::new(100.0, 0.0, 0.0)));
+        }
+
+        fn get_caster(&self) -> &AbilityCaster {
+            self.casters.get(self.caster).unwrap()
+        }
+
+        fn get_caster_mut(&mut self) -> &mut AbilityCaster {
+            self.casters.get_mut(self.caster).unwrap()
+        }
+    }
+
+    #[test]
+    fn the_effect_lands_once_at_the_end_of_the_cast() {
+        let mut sim = Simulation::new(get_desc(0.5, 2.0));
+        sim.cast();
+        sim.tick();
+        assert!(sim.get_caster().is_casting());
+        assert!(sim.movements.get(sim.caster).unwrap().locked);
+
+        for _ in 0..29 {
+            sim.tick();
+        }
+        assert!(sim.executions.is_empty());
+        sim.tick();
+        // 30 steps of 1/60 after the cast started
+        assert_eq!(sim.executions.len(), 1);
+        assert_eq!(sim.executions[0].0, 31);
+        assert!(matches!(
+            sim.get_caster().get_phase(),
+            CastPhase::Executing { slot: 0, .. }
+        ));
+        assert!(!sim.get_caster().is_ready(0));
+
+        for _ in 0..60 {
+            sim.tick();
+        }
+        assert_eq!(sim.executions.len(), 1);
+        assert_eq!(sim.get_caster().get_phase(), CastPhase::Idle);
+        assert!(!sim.movements.get(sim.caster).unwrap().locked);
+
+        let events = sim.events.drain();
+        let executed = events
+            .iter()
+            .filter(|event| matches!(event, GameEvent::AbilityExecuted { .. }))
+            .count();
+        assert_eq!(executed, 1);
+        assert_eq!(
+            events[0],
+            GameEvent::AbilityCastStarted {
+                caster: sim.caster,
+                slot: 0
+            }
+        );
+    }
+
+    #[test]
+    fn interrupted_casts_start_no_cooldown() {
+        let mut sim = Simulation::new(get_desc(0.5, 2.0));
+        sim.cast();
+        for _ in 0..10 {
+            sim.tick();
+        }
+        *sim.move_targets.get_mut(sim.caster).unwrap() = Some(Vec3::new(50.0, 0.0, 0.0));
+        sim.tick();
+
+        assert_eq!(sim.get_caster().get_phase(), CastPhase::Idle);
+        assert!(sim.get_caster().is_ready(0));
+        assert_eq!(sim.get_caster().get_cooldown_fraction(0), 0.0);
+        assert!(!sim.movements.get(sim.caster).unwrap().locked);
+        assert!(sim.events.drain().contains(&GameEvent::AbilityInterrupted {
+            caster: sim.caster,
+            slot: 0
+        }));
+
+        // Damage interrupts as well, and the next cast starts right away
+        *sim.move_targets.get_mut(sim.caster).unwrap() = None;
+        let energy = sim.get_caster().energy;
+        sim.cast();
+        sim.tick();
+        assert!(sim.get_caster().is_casting());
+        sim.healths.get_mut(sim.caster).unwrap().current -= 5.0;
+        sim.tick();
+        assert_eq!(sim.get_caster().get_phase(), CastPhase::Idle);
+        assert!(sim.get_caster().is_ready(0));
+        // Nothing was paid
+        assert!(sim.get_caster().energy >= energy);
+        for _ in 0..40 {
+            sim.tick();
+        }
+        assert!(sim.executions.is_empty());
+    }
+
+    #[test]
+    fn cooldowns_end_on_the_same_step_every_time() {
+        // Instant, so every cast lands on the step it starts
+        let mut sim = Simulation::new(get_desc(0.0, 1.0));
+        sim.get_caster_mut().energy_regen = 100.0;
+        for _ in 0..(60 * 5 + 1) {
+            sim.cast();
+            sim.tick();
+        }
+
+        // One second of 1/60 steps apart, however the float steps add up
+        let steps: Vec<u32> = sim.executions.iter().map(|(step, _)| *step).collect();
+        assert_eq!(steps, vec![1, 61, 121, 181, 241, 301]);
+
+        sim.tick();
+        assert!((sim.get_caster().get_cooldown_fraction(0) - 59.0 / 60.0).abs() < 1e-6);
+        assert_eq!(get_steps(0.1, 0.1), 1);
+        assert_eq!(get_steps(0.3, 0.1), 3);
+        assert_eq!(get_steps(0.31, 0.1), 4);
+    }
+
+    #[test]
+    fn out_of_range_and_unaffordable_casts_do_not_start() {
+        let mut sim = Simulation::new(get_desc(0.2, 1.0));
+        sim.get_caster_mut()
+            .request_cast(0, AbilityTarget::Point(Vec3::new(900.0, 0.0, 0.0)));
+        sim.tick();
+        assert_eq!(sim.get_caster().get_phase(), CastPhase::Idle);
+        // The wrong kind of target
+        sim.get_caster_mut()
+            .request_cast(0, AbilityTarget::SelfCast);
+        sim.tick();
+        assert_eq!(sim.get_caster().get_phase(), CastPhase::Idle);
+
+        sim.get_caster_mut().energy = 5.0;
+        sim.get_caster_mut().energy_regen = 0.0;
+        sim.cast();
+        sim.tick();
+        assert_eq!(sim.get_caster().get_phase(), CastPhase::Idle);
+        assert!(sim.events.drain().is_empty());
+    }
+
+    #[test]
+    fn library_loads_and_checks_the_abilities() {
+        let library =
+            AbilityLibrary::load(include_bytes!("../../assets/abilities/default.ron")).unwrap();
+        let prefabs =
+            PrefabLibrary::load(include_bytes!("../../assets/prefabs/default.ron")).unwrap();
+        library.validate(&prefabs).unwrap();
+        assert!(library.get("Bolt").is_some());
+        assert!(matches!(
+            library.get("Blink").map(|desc| &desc.effect),
+            Some(AbilityEffect::Blink { .. })
+        ));
+
+        let negative = r#"[(name: "Broken", cast_time: -1.0, cooldown: 1.0, targeting: SelfCast,
+            effect: Heal(amount: 10.0))]"#;
+        let error = format!(
+            "{:#}",
+            AbilityLibrary::load(negative.as_bytes()).unwrap_err()
+        );
+        assert!(error.contains("Broken.cast_time"), "{}", error);
+
+        let missing = r#"[(name: "Lob", cast_time: 0.0, cooldown: 1.0, targeting: Point,
+            effect: Projectile(prefab: "Rock", speed: 100.0, damage: 5.0))]"#;
+        let error = AbilityLibrary::load(missing.as_bytes())
+            .unwrap()
+            .validate(&prefabs)
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("\"Rock\""));
+    }
+}
@@ -0,0 +1,84 @@
+// Named moments of an animation clip, e.g. the frame a swing connects on. The .dat clips
+// don't carry them, the level lists them with the clip in its animation assets. Gameplay
+// plays the track on the fixed step with the time of the clip, and the pose is sampled at
+// the same time, so an event fires on the frame it was authored for.
+
+use serde::{Deserialize, Serialize};
+
+// Where an attack deals its damage, every attack clip has one
+pub const HIT_EVENT: &str = "hit";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnimationEvent {
+    pub name: String,
+    pub time: f32, // Seconds into the clip
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnimationEventTrack {
+    events: Vec<AnimationEvent>, // By time
+    duration: f32,               // Of the clip, playing it is over after
+}
+
+impl AnimationEventTrack {
+    pub fn new(mut events: Vec<AnimationEvent>, duration: f32) -> Self {
+        events.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { events, duration }
+    }
+
+    pub fn get_duration(&self) -> f32 {
+        self.duration
+    }
+
+    pub fn get_time(&self, name: &str) -> Option<f32> {
+        let event = self.events.iter().find(|event| event.name == name)?;
+        Some(event.time)
+    }
+
+    // The events the clip time passed over in a step from one time to the next. The start
+    // is in and the end is not, so consecutive steps fire every event exactly once.
+    pub fn get_passed(&self, from: f32, to: f32) -> impl Iterator<Item = &str> {
+        self.events
+            .iter()
+            .filter(move |event| from <= event.time && event.time < to)
+            .map(|event| event.name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str, time: f32) -> AnimationEvent {
+        AnimationEvent {
+            name: name.to_string(),
+            time,
+        }
+    }
+
+    #[test]
+    fn every_event_fires_once_however_the_steps_fall() {
+        let track = AnimationEventTrack::new(
+            vec![
+                event("footstep", 0.5),
+                event(HIT_EVENT, 0.2),
+                event("start", 0.0),
+            ],
+            1.0,
+        );
+        assert_eq!(track.get_time(HIT_EVENT), Some(0.2));
+        assert_eq!(track.get_time("missing"), None);
+
+        for step in [0.05, 0.1, 0.2, 0.3, 1.0 / 60.0] {
+            let mut fired = Vec::new();
+            let mut time = 0.0;
+            while time < track.get_duration() {
+                fired.extend(track.get_passed(time, time + step));
+                time += step;
+            }
+            assert_eq!(fired, ["start", HIT_EVENT, "footstep"], "{}", step);
+        }
+    }
+}
@@ -1,6 +1,6 @@
-use std::{ops::Mul, sync::Arc};
+use std::{collections::VecDeque, ops::Mul, sync::Arc};
 
-use glam::{Vec2, Vec4};
+use glam::{UVec2, Vec2, Vec3Swizzles, Vec4};
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 use winit::{
@@ -11,17 +11,70 @@ use winit::{
     window::Window,
 };
 
-use crate::renderer::{Renderer, SpriteAnchor, SpriteSpace, TextAlignment, resources::get_handle};
-use crate::{game::Game, input::InputAction};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::hot_reload::HotReloader;
+#[cfg(feature = "inspector")]
+use crate::inspector::Inspector;
+use crate::renderer::{
+    AaMode, DebugView, RenderDevice, Renderer, RendererError, Resource, SpriteAnchor, SpriteSpace,
+    TextAlignment,
+    resource_scope::ScopeHandle,
+    resources::get_handle,
+    safe_area::{FitPolicy, get_normalized_cursor_position},
+};
+use crate::{
+    camera_track::CameraTrackPlayer,
+    chunk_streamer::{ChunkStreamer, GameChunk, GameChunkHost},
+    console::{Console, ConsoleContext, ConsoleSettings},
+    crash::{ErrorBanner, FailureKind, install_panic_hook},
+    cursor::{
+        CursorGrab, CursorState, apply_cursor_grab, create_cursor_materials, submit_software_cursor,
+    },
+    debug_camera::DebugCamera,
+    diagnostics::{FrameSummary, LogRing, MAX_FRAME_SUMMARIES},
+    fetch::AssetFetcher,
+    game::Game,
+    hitch::{HitchDetector, HitchSources},
+    input::InputAction,
+    latency::{FrameHistory, LatencyStats},
+    level::Level,
+    loading::{LevelLoader, Startup, StartupLoader},
+    network::NetworkClient,
+    options::{ClientOptions, get_options},
+    prefab::PrefabLibrary,
+    profiler_overlay::ProfilerOverlay,
+    renderer::render_data::SpriteRenderJob,
+    resource_browser::ResourceBrowser,
+    selection::create_selection_materials,
+    stats::{PlayerProfile, StatsScreen, load_profile, save_profile},
+    trail::create_trail_resources,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{
+    console::get_autoexec_path,
+    diagnostics::{DiagnosticsDump, save_diagnostics},
+    save::{GameSave, get_save_path},
+    screenshot::save_screenshot,
+};
 use crate::{input::InputState, renderer::render_data::TextRenderJob};
-use shared::physics::PhysicsWorld;
+use shared::{
+    physics::PhysicsWorld,
+    profile_scope,
+    profiler::{self, FrameProfile},
+    transform::Transform,
+};
 
 pub struct PerformanceMetrics {
     pub delta_times: Vec<f32>,
     pub time_since_update: f32,
+    pub cpu_wait_total: f32, // Seconds blocked on the GPU since the last update
     pub max_ms: f32,
     pub avg_fps: u32,
     pub info: String,
+    pub stats_info: String,
+    pub latency_info: String,
+    pub history: VecDeque<FrameSummary>, // One per update, for the diagnostics
+    pub hitches: HitchDetector,
 }
 
 impl PerformanceMetrics {
@@ -31,15 +84,22 @@ impl PerformanceMetrics {
         Self {
             delta_times: Vec::new(),
             time_since_update: 0.0,
+            cpu_wait_total: 0.0,
             max_ms: 0.0,
             avg_fps: 0,
             info: String::new(),
+            stats_info: String::new(),
+            latency_info: String::new(),
+            history: VecDeque::new(),
+            hitches: HitchDetector::new(),
         }
     }
 
-    pub fn update(&mut self, dt: f32) {
+    pub fn update(&mut self, dt: f32, renderer: &Renderer, frame_history: &mut FrameHistory) {
+        let frame_stats = renderer.get_frame_stats();
         self.delta_times.push(dt);
         self.time_since_update += dt;
+        self.cpu_wait_total += renderer.get_cpu_wait();
         if self.time_since_update >= Self::UPDATE_INTERVAL {
             let frame_count = self.delta_times.len() as f32;
             self.avg_fps = (frame_count / self.time_since_update).round() as u32;
@@ -50,18 +110,84 @@ impl PerformanceMetrics {
                     self.max_ms = ms;
                 }
             }
+            let cpu_wait_ms = self.cpu_wait_total * 1000.0 / frame_count;
+            if self.history.len() == MAX_FRAME_SUMMARIES {
+                self.history.pop_front();
+            }
+            self.history.push_back(FrameSummary::new(
+                self.avg_fps,
+                self.max_ms,
+                cpu_wait_ms,
+                frame_stats,
+            ));
             self.delta_times.clear();
             self.time_since_update = 0.0;
+            self.cpu_wait_total = 0.0;
+
+            let scene_size = renderer.get_scene_size();
+            let pacing = if renderer.is_low_latency() {
+                "low latency".to_string()
+            } else {
+                format!("{} in flight", renderer.get_max_frames_in_flight())
+            };
+            let debug_view = match renderer.get_debug_view() {
+                DebugView::None => String::new(),
+                view => format!(" | View: {}", view.get_name()),
+            };
+            self.info = format!(
+                "Max: {:.2} ms | Avg FPS: {} | Scene: {}x{} ({:.0}%) | CPU wait: {:.2} ms ({}){}",
+                self.max_ms,
+                self.avg_fps,
+                scene_size.x,
+                scene_size.y,
+                renderer.get_render_scale() * 100.0,
+                cpu_wait_ms,
+                pacing,
+                debug_view
+            );
+            let lods: Vec<_> = frame_stats
+                .lod_instance_counts
+                .iter()
+                .map(|count| count.to_string())
+                .collect();
+            self.stats_info = format!(
+                "Sprites: {} ({} batches, {} glyphs) | Static: {} | Persistent: {} | Skeletal: {} | Bones: {} | LODs: {}",
+                frame_stats.sprite_instance_count,
+                frame_stats.sprite_batch_count,
+                frame_stats.text_glyph_count,
+                frame_stats.static_instance_count,
+                frame_stats.persistent_instance_count,
+                frame_stats.skeletal_instance_count,
+                frame_stats.bone_count,
+                lods.join("/"),
+            );
 
-            self.info = format!("Max: {:.2} ms | Avg FPS: {}", self.max_ms, self.avg_fps);
+            let LatencyStats {
+                input_to_update_ms: update,
+                input_to_present_ms: present,
+            } = frame_history.get_stats();
+            self.latency_info = format!(
+                "Input to update: {:.1}/{:.1}/{:.1} ms | Input to present: {:.1}/{:.1}/{:.1} ms (p50/p95/p99)",
+                update.p50, update.p95, update.p99, present.p50, present.p95, present.p99,
+            );
         }
     }
 
+    // Once the profile of the frame was collected, a hitch keeps what the frame did
+    pub fn end_frame(&mut self, profile: &FrameProfile, renderer: &Renderer, pending_loads: usize) {
+        self.hitches.end_frame(&HitchSources {
+            profile,
+            stats: renderer.get_frame_stats(),
+            gpu_passes: &renderer.get_gpu_pass_timings(),
+            pending_loads,
+        });
+    }
+
     pub fn render(&self, renderer: &mut Renderer) {
         renderer.submit(&TextRenderJob {
             font_atlas: get_handle("DebugFont"),
             font_material: get_handle("DebugFontMaterial"),
-            text: self.info.as_str(),
+            text: self.info.as_str().into(),
             position: Vec2::new(-5.0, 20.0),
             size: 20.0,
             color: Vec4::new(0.0, 1.0, 0.0, 1.0),
@@ -71,100 +197,1126 @@ impl PerformanceMetrics {
             alignment: TextAlignment::Right,
             ..Default::default()
         });
+
+        renderer.submit(&TextRenderJob {
+            font_atlas: get_handle("DebugFont"),
+            font_material: get_handle("DebugFontMaterial"),
+            text: self.stats_info.as_str().into(),
+            position: Vec2::new(-5.0, 40.0),
+            size: 16.0,
+            color: Vec4::new(0.0, 1.0, 0.0, 1.0),
+            layer: 0,
+            anchor: SpriteAnchor::TopRight,
+            space: SpriteSpace::Absolute,
+            alignment: TextAlignment::Right,
+        });
+
+        renderer.submit(&TextRenderJob {
+            font_atlas: get_handle("DebugFont"),
+            font_material: get_handle("DebugFontMaterial"),
+            text: self.latency_info.as_str().into(),
+            position: Vec2::new(-5.0, 58.0),
+            size: 16.0,
+            color: Vec4::new(0.0, 1.0, 0.0, 1.0),
+            layer: 0,
+            anchor: SpriteAnchor::TopRight,
+            space: SpriteSpace::Absolute,
+            alignment: TextAlignment::Right,
+        });
+    }
+}
+
+// The level assets are loaded over several frames before the game starts
+pub enum AppPhase {
+    // The level file and the libraries, the assets to load are listed in the level
+    Starting {
+        loader: Box<StartupLoader>,
+        server_address: Option<String>,
+    },
+    Loading {
+        loader: Box<LevelLoader>,
+        level: Box<Level>,
+        prefabs: PrefabLibrary, // Validated once the level's assets are loaded
+        server_address: Option<String>,
+    },
+    Running,
+}
+
+impl AppPhase {
+    // Leaves enough of the frame for rendering the loading screen at 60 Hz
+    const LOAD_BUDGET: f64 = 0.008;
+
+    // The loading screen fills the screen, the HUD keeps all of it in view
+    fn get_ui_fit(&self) -> FitPolicy {
+        match self {
+            AppPhase::Starting { .. } | AppPhase::Loading { .. } => FitPolicy::Cover,
+            AppPhase::Running => FitPolicy::Contain,
+        }
+    }
+
+    fn render(&self, renderer: &mut Renderer) {
+        const BAR_SIZE: Vec2 = Vec2::new(400.0, 12.0);
+
+        let (progress, status, errors) = match self {
+            AppPhase::Starting { loader, .. } => (
+                loader.get_progress(),
+                loader.get_status(),
+                loader.get_errors(),
+            ),
+            AppPhase::Loading { loader, .. } => (
+                loader.get_progress(),
+                loader.get_status(),
+                loader.get_errors(),
+            ),
+            AppPhase::Running => return,
+        };
+
+        // One batch, the fill is drawn over the background because it comes later
+        let position = Vec2::new(-0.5 * BAR_SIZE.x, 0.0);
+        let background = Vec4::new(0.15, 0.15, 0.15, 1.0);
+        let fill_size = BAR_SIZE * Vec2::new(progress, 1.0);
+        renderer.submit(&SpriteRenderJob {
+            anchor: SpriteAnchor::Center,
+            space: SpriteSpace::Absolute,
+            ..SpriteRenderJob::solid(position, BAR_SIZE, background, 0)
+        });
+        renderer.submit(&SpriteRenderJob {
+            anchor: SpriteAnchor::Center,
+            space: SpriteSpace::Absolute,
+            ..SpriteRenderJob::solid(position, fill_size, Vec4::new(0.0, 1.0, 0.0, 1.0), 0)
+        });
+
+        // The status above the bar, errors below it
+        let lines = std::iter::once((status, Vec4::ONE)).chain(
+            errors
+                .iter()
+                .map(|error| (error.clone(), Vec4::new(1.0, 0.3, 0.3, 1.0))),
+        );
+        for (index, (text, color)) in lines.enumerate() {
+            let y = if index == 0 {
+                -12.0
+            } else {
+                20.0 + 22.0 * index as f32
+            };
+            renderer.submit(&TextRenderJob {
+                font_atlas: get_handle("DebugFont"),
+                font_material: get_handle("DebugFontMaterial"),
+                text: text.into(),
+                position: Vec2::new(0.0, y),
+                size: 20.0,
+                color,
+                layer: 0,
+                anchor: SpriteAnchor::Center,
+                space: SpriteSpace::Absolute,
+                alignment: TextAlignment::Center,
+            });
+        }
     }
 }
 
 pub struct State {
     pub window: Arc<Window>,
+    pub phase: AppPhase,
     pub renderer: Renderer,
     pub physics_world: PhysicsWorld,
     pub game: Game,
     pub input_state: InputState,
     pub metrics: PerformanceMetrics,
+    pub resource_browser: ResourceBrowser,
+    pub network: Option<NetworkClient>, // Set when playing on a server
+    pub frame_history: FrameHistory,
+    pub latency_flash: bool, // Flashes a corner on the frame a left click was consumed
+    pub debug_camera: DebugCamera,
+    pub camera_track: CameraTrackPlayer,
+    pub cursor: CursorState,
+    pub error_banner: ErrorBanner,
+    pub console: Console,
+    pub console_settings: ConsoleSettings,
+    pub triggered_failure: Option<FailureKind>, // Raised once the game is running
+    pub level_scope: ScopeHandle,               // Holds the resources of the level
+    pub transparent: bool,                      // For making the surface again
+    pub pending_size: Option<UVec2>,            // Of the last resize event this frame
+    // Set when the level is split into chunks, they fetch their assets from the asset base
+    pub chunk_streamer: Option<ChunkStreamer<GameChunk>>,
+    pub chunk_debug: bool, // Their loading states in the corner
+    pub profiler_overlay: ProfilerOverlay,
+    pub profile: PlayerProfile, // The stored totals, as of the last save
+    pub stats_screen: StatsScreen,
+    pub stats_save_timer: f32,
+    pub asset_base: String,
+    #[cfg(feature = "inspector")]
+    pub inspector: Inspector,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub hot_reloader: Option<HotReloader>, // Set once the level is known
 
     pub previous_time: f64,
-    pub time_since_fixed: f32,
 }
 
+#[cfg(target_arch = "wasm32")]
 fn get_time() -> f64 {
     let window = wgpu::web_sys::window().unwrap_throw();
     let performance = window.performance().unwrap_throw();
     performance.now() * 0.001
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn get_time() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("System time is before the epoch")
+        .as_secs_f64()
+}
+
+// The browser can't block the thread, the time is spun away
+#[cfg(target_arch = "wasm32")]
+fn sleep(seconds: f64) {
+    let end = get_time() + seconds;
+    while get_time() < end {}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn sleep(seconds: f64) {
+    std::thread::sleep(std::time::Duration::from_secs_f64(seconds));
+}
+
 impl State {
     const FIXED_TIMESTEP: f32 = 1.0 / 60.0;
+    // The 3×3 chunks around the camera are loaded and kept until they are outside of 5×5
+    const CHUNK_LOAD_RADIUS: i32 = 1;
+    const CHUNK_UNLOAD_RADIUS: i32 = 2;
+    const CHUNK_BUDGET: f64 = 0.004;
+    // The stats are also saved on exit, a closed browser tab doesn't get to
+    const STATS_SAVE_INTERVAL: f32 = 60.0;
 
-    pub async fn new(window: Arc<Window>) -> anyhow::Result<Self> {
-        let mut renderer = Renderer::new(&window).await?;
-        let mut physics_world = PhysicsWorld::new();
-        let mut game = Game::new();
-        let input_state = InputState::new();
-
-        game.initialize(&mut physics_world);
+    pub async fn new(window: Arc<Window>, options: ClientOptions) -> anyhow::Result<Self> {
+        profiler::set_clock(get_time);
+        let transparent = options.is_transparent();
+        let triggered_failure = options.get_triggered_failure();
+        let mut renderer = Renderer::new(&window, transparent).await?;
+        // Everything is loaded again from these when the device is lost
+        renderer.set_keep_cpu_copy(true);
+        if !options.is_vsync() {
+            renderer.set_vsync(false);
+        }
+        if let Some(scale) = options.render_scale {
+            renderer.set_render_scale(scale);
+        }
+        if let Some(count) = options.frames_in_flight {
+            renderer.set_max_frames_in_flight(count);
+        }
+        renderer.set_low_latency(options.is_low_latency());
+        renderer.set_letterbox(options.is_letterbox());
+        renderer.set_xray_enabled(options.is_xray());
+        renderer.set_gpu_culling(options.is_gpu_culling());
+        renderer.set_scale_factor(window.scale_factor());
+        if let Some(dpi_mode) = options.get_ui_dpi_mode() {
+            renderer.set_ui_dpi_mode(dpi_mode);
+        }
 
+        // Right away, the loading screen needs it
         {
             let font_handle =
                 renderer.load_font("DebugFont", include_bytes!("../res/font/fira.dat"));
             renderer.create_font_material("DebugFontMaterial", font_handle);
-            game.load_resources(&mut renderer);
+            #[cfg(feature = "runtime-font")]
+            renderer.add_font_fallback(
+                font_handle,
+                "DebugFontFallback",
+                include_bytes!("../res/font/FiraCode-Medium.ttf"),
+            )?;
         }
+        create_cursor_materials(&mut renderer);
+        create_selection_materials(&mut renderer);
+        create_trail_resources(&mut renderer);
+
+        let asset_base = options.get_asset_base().to_string();
+        let level_scope = renderer.create_scope(options.get_level_name());
+        let loader = StartupLoader::new(AssetFetcher::new(&asset_base), options.get_level_name());
+        let phase = AppPhase::Starting {
+            loader: Box::new(loader),
+            server_address: options.connect,
+        };
+        renderer.set_ui_reference(Renderer::SPRITE_SCREEN_REFERENCE, phase.get_ui_fit());
+
+        #[cfg(feature = "inspector")]
+        let inspector = Inspector::new(&window, renderer.get_render_device());
 
         Ok(Self {
             window,
+            phase,
             renderer,
-            physics_world,
-            game,
-            input_state,
+            physics_world: PhysicsWorld::new(),
+            game: Game::new(),
+            input_state: InputState::new(),
             previous_time: get_time(),
-            time_since_fixed: 0.0,
             metrics: PerformanceMetrics::new(),
+            resource_browser: ResourceBrowser::new(),
+            network: None,
+            frame_history: FrameHistory::new(),
+            latency_flash: false,
+            debug_camera: DebugCamera::default(),
+            camera_track: CameraTrackPlayer::default(),
+            cursor: CursorState::default(),
+            error_banner: ErrorBanner::default(),
+            console: Console::new(),
+            console_settings: ConsoleSettings::default(),
+            triggered_failure,
+            level_scope,
+            chunk_streamer: None,
+            chunk_debug: false,
+            profiler_overlay: ProfilerOverlay::default(),
+            profile: load_profile(),
+            stats_screen: StatsScreen::new(),
+            stats_save_timer: 0.0,
+            asset_base,
+            transparent,
+            pending_size: None,
+            #[cfg(feature = "inspector")]
+            inspector,
+            #[cfg(not(target_arch = "wasm32"))]
+            hot_reloader: None,
         })
     }
 
+    pub fn is_loading(&self) -> bool {
+        !matches!(self.phase, AppPhase::Running)
+    }
+
+    // Requests the level's assets once the level arrived
+    fn update_starting(&mut self) {
+        let AppPhase::Starting {
+            loader,
+            server_address,
+        } = &mut self.phase
+        else {
+            return;
+        };
+        let Some(Startup {
+            level,
+            prefabs,
+            abilities,
+        }) = loader.update()
+        else {
+            return;
+        };
+
+        self.game.set_abilities(abilities);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.hot_reloader = Some(HotReloader::new(&self.asset_base, &level.assets));
+        }
+        let fetcher = AssetFetcher::new(&self.asset_base);
+        let loader = LevelLoader::new(&level, fetcher, self.level_scope);
+        self.phase = AppPhase::Loading {
+            loader: Box::new(loader),
+            level: Box::new(level),
+            prefabs,
+            server_address: server_address.take(),
+        };
+    }
+
+    // Starts the game once the last asset is loaded
+    fn update_loading(&mut self) {
+        self.update_starting();
+        let AppPhase::Loading { loader, .. } = &mut self.phase else {
+            return;
+        };
+        loader.update(&mut self.renderer, AppPhase::LOAD_BUDGET, get_time);
+        if !loader.is_done() {
+            return;
+        }
+
+        let files = loader.take_kept_files();
+        let AppPhase::Loading {
+            level,
+            prefabs,
+            server_address,
+            ..
+        } = std::mem::replace(&mut self.phase, AppPhase::Running)
+        else {
+            unreachable!();
+        };
+        self.renderer
+            .set_ui_reference(Renderer::SPRITE_SCREEN_REFERENCE, self.phase.get_ui_fit());
+        // The baked props belong to the level as well
+        self.renderer.set_current_scope(Some(self.level_scope));
+        self.game
+            .build_level(&level, &files, &mut self.renderer, &mut self.physics_world);
+        self.renderer.set_current_scope(None);
+        self.chunk_streamer = level.chunks.map(|grid| {
+            ChunkStreamer::new(
+                grid,
+                Self::CHUNK_LOAD_RADIUS,
+                Self::CHUNK_UNLOAD_RADIUS,
+                Self::CHUNK_BUDGET,
+            )
+        });
+
+        let resource_pool = self.renderer.get_resource_pool();
+        match prefabs.validate(|handle| resource_pool.get_resource(handle).map(Resource::get_kind))
+        {
+            Ok(()) => {
+                self.game.set_prefabs(prefabs);
+                self.game
+                    .prewarm_projectile_pool(&self.renderer, &mut self.physics_world);
+            }
+            Err(error) => log::error!("Prefabs refer to missing resources: {:#}", error),
+        }
+
+        if let Some(address) = server_address {
+            match NetworkClient::connect(&address, "Player", get_time()) {
+                Ok(network) => self.network = Some(network),
+                Err(e) => log::error!("Failed to connect to {}: {:#}", address, e),
+            }
+        }
+        self.run_autoexec();
+    }
+
+    // Commands for setting up the game the same way every time, e.g. for testing
+    #[cfg(not(target_arch = "wasm32"))]
+    fn run_autoexec(&mut self) {
+        let path = get_autoexec_path();
+        let Ok(script) = std::fs::read_to_string(&path) else {
+            return;
+        };
+        log::info!("Running {}", path.display());
+        let mut context = ConsoleContext {
+            game: &mut self.game,
+            renderer: &mut self.renderer,
+            physics: &mut self.physics_world,
+            settings: &mut self.console_settings,
+            camera_track: &mut self.camera_track,
+            network: self.network.as_ref(),
+            hitches: &mut self.metrics.hitches,
+        };
+        self.console
+            .run_script("autoexec.cfg", &script, &mut context);
+    }
+
+    // There is no file next to the page to read
+    #[cfg(target_arch = "wasm32")]
+    fn run_autoexec(&mut self) {}
+
+    fn run_console_line(&mut self, line: &str) {
+        let mut context = ConsoleContext {
+            game: &mut self.game,
+            renderer: &mut self.renderer,
+            physics: &mut self.physics_world,
+            settings: &mut self.console_settings,
+            camera_track: &mut self.camera_track,
+            network: self.network.as_ref(),
+            hitches: &mut self.metrics.hitches,
+        };
+        self.console.execute(line, &mut context);
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
         self.renderer.resize(width, height);
         self.game.resize(width, height);
     }
 
-    pub fn update(&mut self, dt: f32, alpha: f32) {
-        self.game.update(dt, alpha, &self.input_state);
-        self.metrics.update(dt);
+    // Dragging a window edge sends many resize events per frame, only the last one is
+    // applied before the next frame renders
+    pub fn request_resize(&mut self, width: u32, height: u32) {
+        self.pending_size = Some(UVec2::new(width, height));
+    }
+
+    fn apply_pending_resize(&mut self) {
+        if let Some(size) = self.pending_size.take() {
+            self.resize(size.x, size.y);
+        }
+    }
+
+    // Only the game runs on the scaled game dt, everything around it keeps real time
+    pub fn update(&mut self, dt: f32, game_dt: f32, alpha: f32) {
+        profile_scope!("Update");
+        self.error_banner.update(dt);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(path) = self.console_settings.diagnostics_path.take() {
+            self.dump_diagnostics(&path);
+        }
+        if let Some(ms) = self.console_settings.sleep_ms.take() {
+            profile_scope!("Sleep");
+            sleep(ms as f64 / 1000.0);
+        }
+        if self.is_loading() {
+            self.update_loading();
+            self.metrics
+                .update(dt, &self.renderer, &mut self.frame_history);
+            return;
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(hot_reloader) = &mut self.hot_reloader {
+            hot_reloader.update(dt, &mut self.renderer, &mut self.game);
+        }
+
+        #[cfg(feature = "inspector")]
+        self.inspector.update_input_capture(&mut self.input_state);
+
+        self.game
+            .update_editor(&self.input_state, &mut self.physics_world);
+        self.game.update(game_dt, dt, alpha, &self.input_state);
+        self.renderer.advance_wind(game_dt);
+        self.stats_save_timer += dt;
+        if self.stats_save_timer >= Self::STATS_SAVE_INTERVAL {
+            self.stats_save_timer = 0.0;
+            self.save_stats();
+        }
+        self.game.update_blink_aim(&self.physics_world);
+        self.update_chunks();
+        self.frame_history.on_update(
+            get_time(),
+            self.input_state.is_pressed(InputAction::LeftClick),
+        );
+
+        if let Some(network) = &mut self.network {
+            network.update(dt);
+            self.game.apply_network_state(
+                &self.renderer,
+                &mut self.physics_world,
+                network.get_entity_id(),
+                network.get_own_state(alpha),
+                &network.take_snapshots(),
+            );
+            if let Some(render_time) = network.get_render_time() {
+                self.game.update_remote_proxies(render_time, dt);
+            }
+        }
+
+        self.update_debug_camera(dt);
+
+        if self.input_state.is_pressed(InputAction::ToggleLightDebug) {
+            let enabled = !self.renderer.is_light_debug_enabled();
+            self.renderer.set_light_debug_enabled(enabled);
+        }
+
+        if self
+            .input_state
+            .is_pressed(InputAction::ToggleSafeAreaDebug)
+        {
+            let enabled = !self.renderer.is_safe_area_debug_enabled();
+            self.renderer.set_safe_area_debug_enabled(enabled);
+        }
+
+        if self.input_state.is_pressed(InputAction::CycleAntialiasing) {
+            let current = self.renderer.get_antialiasing();
+            let next = match current {
+                AaMode::Off => AaMode::Fxaa,
+                AaMode::Fxaa => AaMode::Msaa(4),
+                AaMode::Msaa(_) => AaMode::Off,
+            };
+            self.renderer.set_antialiasing(next);
+
+            // MSAA falls back to FXAA when unsupported, skip ahead so the cycle does not get stuck
+            if self.renderer.get_antialiasing() == current {
+                self.renderer.set_antialiasing(AaMode::Off);
+            }
+        }
+
+        if let Some((mesh, kind)) = self
+            .resource_browser
+            .update(&self.input_state, &mut self.renderer)
+        {
+            self.game.spawn_debug_mesh(&self.renderer, mesh, kind);
+        }
+
+        if self.input_state.is_pressed(InputAction::CycleDebugView) {
+            let current = self.renderer.get_debug_view();
+            self.renderer.set_debug_view(current.get_next());
+
+            // Wireframes fall back to no view when unsupported, skip ahead like antialiasing
+            if self.renderer.get_debug_view() == DebugView::None && current == DebugView::None {
+                self.renderer.set_debug_view(current.get_next().get_next());
+            }
+        }
+
+        if self.input_state.is_pressed(InputAction::CycleRenderScale) {
+            let next = match self.renderer.get_render_scale() {
+                scale if scale > 0.75 => 0.75,
+                scale if scale > 0.5 => 0.5,
+                scale if scale > Renderer::MIN_RENDER_SCALE => Renderer::MIN_RENDER_SCALE,
+                _ => 1.0,
+            };
+            self.renderer.set_render_scale(next);
+        }
+
+        if self.input_state.is_pressed(InputAction::ToggleChunkDebug) {
+            self.chunk_debug = !self.chunk_debug;
+        }
+
+        if self.input_state.is_pressed(InputAction::ToggleLatencyFlash) {
+            self.latency_flash = !self.latency_flash;
+        }
+
+        if self.input_state.is_pressed(InputAction::ToggleProfiler) {
+            self.profiler_overlay.toggle();
+        }
+        let screen_size = self.renderer.get_screen_size();
+        self.profiler_overlay.update(
+            self.input_state.get_mouse_position() * screen_size,
+            screen_size,
+        );
+
+        if self.input_state.is_pressed(InputAction::SaveGame)
+            && let Err(error) = self.save_game()
+        {
+            log::error!("Failed to save the game: {:#}", error);
+            self.error_banner
+                .show(format!("Failed to save the game: {}", error));
+        }
+        if self.input_state.is_pressed(InputAction::LoadGame)
+            && let Err(error) = self.load_game()
+        {
+            log::error!("Failed to load the game: {:#}", error);
+            self.error_banner
+                .show(format!("Failed to load the game: {}", error));
+        }
+
+        if self
+            .input_state
+            .is_pressed(InputAction::ToggleSoftwareCursor)
+        {
+            self.set_software_cursor(!self.cursor.software);
+        }
+        self.cursor.kind = self
+            .game
+            .get_cursor_kind(self.input_state.get_mouse_position());
+
+        self.metrics
+            .update(dt, &self.renderer, &mut self.frame_history);
+
+        #[cfg(feature = "inspector")]
+        {
+            if self.input_state.is_pressed(InputAction::ToggleInspector) {
+                self.inspector.toggle(&mut self.renderer);
+            }
+            self.inspector.update(
+                &self.window,
+                &mut self.renderer,
+                &mut self.game,
+                &self.physics_world,
+                &mut self.metrics,
+            );
+        }
+    }
+
+    // After the profile of the frame was collected, which the hitches need recorded
+    fn end_frame(&mut self) {
+        let pending_loads = match &self.phase {
+            AppPhase::Starting { loader, .. } => loader.get_pending_count(),
+            AppPhase::Loading { loader, .. } => loader.get_pending_count(),
+            AppPhase::Running => self
+                .chunk_streamer
+                .as_ref()
+                .map_or(0, ChunkStreamer::get_pending_count),
+        };
+        self.metrics.end_frame(
+            self.profiler_overlay.get_last_frame(),
+            &self.renderer,
+            pending_loads,
+        );
+        self.profiler_overlay
+            .set_recording(self.metrics.hitches.is_enabled());
+    }
+
+    // Around the point the game camera looks at, also while the debug camera flies
+    fn update_chunks(&mut self) {
+        let Some(streamer) = &mut self.chunk_streamer else {
+            return;
+        };
+        let mut host = GameChunkHost {
+            game: &mut self.game,
+            renderer: &mut self.renderer,
+            physics_world: &mut self.physics_world,
+            asset_base: &self.asset_base,
+        };
+        let position = host.game.get_camera_target().xz();
+        streamer.update(&mut host, position, get_time);
+    }
+
+    fn unload_chunks(&mut self) {
+        let Some(streamer) = &mut self.chunk_streamer else {
+            return;
+        };
+        streamer.clear(&mut GameChunkHost {
+            game: &mut self.game,
+            renderer: &mut self.renderer,
+            physics_world: &mut self.physics_world,
+            asset_base: &self.asset_base,
+        });
+    }
+
+    // The game camera keeps following underneath, the renderer only draws from the debug
+    // camera while it is active. A playing camera track goes over both.
+    fn update_debug_camera(&mut self, dt: f32) {
+        if self.input_state.is_pressed(InputAction::ToggleDebugCamera) {
+            let active = !self.debug_camera.is_active();
+            let camera = self.renderer.get_camera_transform();
+            self.debug_camera
+                .set_active(active, camera.position, camera.rotation);
+            self.cursor.mouse_look = active;
+            self.apply_cursor();
+        }
+
+        self.debug_camera.update(dt, &self.input_state);
+        if self.debug_camera.is_active() && self.input_state.is_pressed(InputAction::TeleportCamera)
+        {
+            self.game
+                .move_camera_to(self.debug_camera.get_ground_point());
+        }
+
+        let mut camera_override = self.debug_camera.is_active().then(|| Transform {
+            position: self.debug_camera.get_position(),
+            rotation: self.debug_camera.get_rotation(),
+            ..Default::default()
+        });
+        self.camera_track.update(dt);
+        let sample = self
+            .camera_track
+            .sample(|index| self.game.get_entity_position(index));
+        if let Some(sample) = sample {
+            camera_override = Some(Transform {
+                position: sample.position,
+                rotation: sample.rotation,
+                ..Default::default()
+            });
+        }
+        self.renderer.set_camera_override(camera_override);
+        self.renderer
+            .set_fov_override(sample.map(|sample| sample.fov.to_radians()));
+    }
+
+    #[allow(dead_code)]
+    pub fn set_cursor_grab(&mut self, grab: CursorGrab) {
+        self.cursor.grab = grab;
+        self.apply_cursor();
+    }
+
+    // Hides the OS cursor and the software cursor alike
+    #[allow(dead_code)]
+    pub fn set_cursor_visible(&mut self, visible: bool) {
+        self.cursor.visible = visible;
+        self.apply_cursor();
+    }
+
+    pub fn set_software_cursor(&mut self, enabled: bool) {
+        self.cursor.software = enabled;
+        self.apply_cursor();
+    }
+
+    // Alt-tab gives the cursor back to the desktop, focus takes it again
+    fn on_focus_changed(&mut self, focused: bool) {
+        self.cursor.focused = focused;
+        self.apply_cursor();
+        if !focused {
+            self.input_state.clear_mouse_history();
+        }
+    }
+
+    fn apply_cursor(&mut self) {
+        let (grab, visible) = self.cursor.get_window_state();
+        apply_cursor_grab(&self.window, grab);
+        self.window.set_cursor_visible(visible);
     }
 
     pub fn fixed_update(&mut self, dt: f32) {
-        self.game.fixed_update(dt, &mut self.physics_world);
+        if self.is_loading() {
+            return;
+        }
+
+        profile_scope!("Fixed update");
+        self.game
+            .fixed_update(dt, &self.renderer, &mut self.physics_world);
+
+        if let Some(network) = &mut self.network {
+            let now = get_time();
+            let (action_bits, cursor_world) = self.game.get_network_input();
+            network.fixed_update(action_bits, cursor_world, now);
+
+            if network.is_timed_out(now) {
+                log::error!("Lost the connection to the server");
+                self.network = None;
+            }
+        }
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        self.game.render(&mut self.renderer);
+    pub fn render(&mut self) -> Result<(), RendererError> {
+        profile_scope!("Render");
         self.window.request_redraw();
+        if self.is_loading() {
+            self.phase.render(&mut self.renderer);
+        } else {
+            self.game.render(&mut self.renderer);
+            // Like the world grid, which the renderer leaves out itself
+            if !self.console_settings.screenshot_requested {
+                self.game.render_measure_tool(&mut self.renderer);
+            }
+            if self.chunk_debug
+                && let Some(streamer) = &self.chunk_streamer
+            {
+                streamer.render_debug(&mut self.renderer);
+            }
+            self.resource_browser.render(&mut self.renderer);
+            // The editor has Tab for its gizmo
+            if self.input_state.is_down(InputAction::ShowStats) && !self.game.is_editor_enabled() {
+                let stats = self.game.get_stats();
+                let mut totals = self.profile.totals;
+                totals.add(&stats.get_unsaved());
+                self.stats_screen
+                    .render(stats.get_session(), &totals, &mut self.renderer);
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(hot_reloader) = &self.hot_reloader {
+                hot_reloader.render(&mut self.renderer);
+            }
+        }
         self.metrics.render(&mut self.renderer);
-        self.renderer.render()
+        // Nothing over a playing camera track, it is likely being recorded
+        if self.debug_camera.is_active() && !self.camera_track.is_playing() {
+            self.renderer.submit(&TextRenderJob {
+                font_atlas: get_handle("DebugFont"),
+                font_material: get_handle("DebugFontMaterial"),
+                text: self.debug_camera.get_info().into(),
+                position: Vec2::new(0.0, 20.0),
+                size: 18.0,
+                color: Vec4::new(1.0, 0.8, 0.2, 1.0),
+                layer: 0,
+                anchor: SpriteAnchor::TopCenter,
+                space: SpriteSpace::Absolute,
+                alignment: TextAlignment::Center,
+            });
+        }
+
+        // For measuring click to photon with a high-speed camera
+        if self.latency_flash && self.frame_history.is_click_frame() {
+            self.renderer.submit(&SpriteRenderJob {
+                anchor: SpriteAnchor::TopLeft,
+                space: SpriteSpace::Absolute,
+                ..SpriteRenderJob::solid(Vec2::ZERO, Vec2::splat(96.0), Vec4::ONE, 0)
+            });
+        }
+
+        self.profiler_overlay.render(&mut self.renderer);
+        self.error_banner.render(&mut self.renderer);
+        // Left out of screenshots, they are taken from the console
+        let screenshot_requested = std::mem::take(&mut self.console_settings.screenshot_requested);
+        if screenshot_requested {
+            self.renderer.request_screenshot();
+        } else {
+            self.console.render(&mut self.renderer);
+        }
+
+        // Over everything else, it is on the topmost sprite layer
+        if self.cursor.is_software_drawn() {
+            submit_software_cursor(
+                &mut self.renderer,
+                self.cursor.kind,
+                self.input_state.get_mouse_position(),
+            );
+        }
+
+        #[cfg(feature = "inspector")]
+        let overlay =
+            |device: &RenderDevice, view: &wgpu::TextureView| self.inspector.paint(device, view);
+        #[cfg(not(feature = "inspector"))]
+        let overlay = |_: &RenderDevice, _: &wgpu::TextureView| {};
+        let presented_count = self.renderer.get_presented_frame_count();
+        let result = self.renderer.render_with_overlay(overlay);
+        if self.renderer.get_presented_frame_count() != presented_count {
+            self.frame_history.on_present(get_time());
+        }
+        result?;
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(screenshot) = self.renderer.take_screenshot() {
+            match save_screenshot(&screenshot) {
+                Ok(path) => log::info!("Saved a screenshot to {}", path.display()),
+                Err(error) => {
+                    log::error!("Failed to save the screenshot: {:#}", error);
+                    self.error_banner
+                        .show(format!("Failed to save the screenshot: {:#}", error));
+                }
+            }
+        }
+
+        if !self.is_loading() {
+            match self.triggered_failure.take() {
+                Some(FailureKind::Panic) => panic!("Triggered a panic with --trigger-failure"),
+                Some(FailureKind::Surface) => return Err(wgpu::SurfaceError::Lost.into()),
+                // wgpu reports it asynchronously, it shows up after the next frame
+                Some(FailureKind::Validation) => self.renderer.trigger_validation_error(),
+                Some(FailureKind::DeviceLost) => self.renderer.trigger_device_loss(),
+                None => {}
+            }
+        }
+        Ok(())
     }
 
-    fn handle_key(&mut self, event_loop: &ActiveEventLoop, code: KeyCode, is_pressed: bool) {
+    // The quick save, only of a local game
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_game(&mut self) -> anyhow::Result<()> {
+        if self.network.is_some() {
+            anyhow::bail!("The server owns the game state");
+        }
+        let save = self.game.serialize(&self.physics_world, &self.renderer)?;
+        let path = get_save_path();
+        std::fs::write(&path, save.to_json()?)?;
+        log::info!("Saved the game to {}", path.display());
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load_game(&mut self) -> anyhow::Result<()> {
+        if self.network.is_some() {
+            anyhow::bail!("The server owns the game state");
+        }
+        let path = get_save_path();
+        let bytes = std::fs::read(&path)
+            .map_err(|error| anyhow::anyhow!("{}: {}", path.display(), error))?;
+        let save = GameSave::load(&bytes)?;
+        // Saves leave the chunks out, they stream in again around the loaded camera
+        self.unload_chunks();
+        self.game
+            .deserialize(&save, &mut self.physics_world, &self.renderer)?;
+        log::info!("Loaded the game from {}", path.display());
+        Ok(())
+    }
+
+    // For attaching to a bug report, requested from the console
+    #[cfg(not(target_arch = "wasm32"))]
+    fn dump_diagnostics(&mut self, path: &str) {
+        let dump = DiagnosticsDump::new()
+            .with_renderer(&self.renderer)
+            .with_frames(self.metrics.history.iter().copied());
+        match save_diagnostics(&dump, std::path::Path::new(path)) {
+            Ok(()) => log::info!("Wrote the diagnostics to {}", path),
+            Err(error) => {
+                log::error!("Failed to write the diagnostics: {:#}", error);
+                self.error_banner
+                    .show(format!("Failed to write the diagnostics: {:#}", error));
+            }
+        }
+    }
+
+    // Merged into the stored profile, which may have changed since it was loaded
+    fn save_stats(&mut self) {
+        match save_profile(self.game.get_stats_mut()) {
+            Ok(profile) => self.profile = profile,
+            Err(error) => log::error!("Failed to save the stats: {:#}", error),
+        }
+    }
+
+    // There is no file to write to in the browser
+    #[cfg(target_arch = "wasm32")]
+    fn save_game(&mut self) -> anyhow::Result<()> {
+        anyhow::bail!("Saving is not supported in the browser")
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn load_game(&mut self) -> anyhow::Result<()> {
+        anyhow::bail!("Loading is not supported in the browser")
+    }
+
+    // A lost surface or device is recreated, the game keeps running after anything else too
+    fn on_render_error(&mut self, error: RendererError) {
+        let size = self.window.inner_size();
+        match error {
+            RendererError::Surface(wgpu::SurfaceError::Outdated) => {
+                self.resize(size.width, size.height);
+            }
+            // E.g. the window moved to a monitor of another format or adapter
+            RendererError::Surface(wgpu::SurfaceError::Lost) => {
+                match self.renderer.reconfigure_surface(size.width, size.height) {
+                    Ok(_format_changed) => {
+                        self.game.resize(size.width, size.height);
+                        // Its pipelines draw to the surface as well
+                        #[cfg(feature = "inspector")]
+                        if _format_changed {
+                            self.inspector =
+                                Inspector::new(&self.window, self.renderer.get_render_device());
+                        }
+                    }
+                    Err(error) => {
+                        log::warn!("Failed to reconfigure the surface: {:#}", error);
+                        if let Err(error) = self.recover_device() {
+                            panic!("Failed to recreate the renderer: {:#}", error);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        if let RendererError::DeviceLost = error
+            && let Err(error) = self.recover_device()
+        {
+            panic!("Failed to recreate the renderer: {:#}", error);
+        }
+        // Outdated only means the window was resized since the frame began
+        if !matches!(error, RendererError::Surface(wgpu::SurfaceError::Outdated)) {
+            log::error!("Unable to render {}", error);
+            self.error_banner.show(error.to_string());
+        }
+    }
+
+    // Everything on the GPU is made again, the game only misses a frame or two
+    #[cfg(not(target_arch = "wasm32"))]
+    fn recover_device(&mut self) -> anyhow::Result<()> {
+        pollster::block_on(self.renderer.recreate(&self.window, self.transparent))?;
+        let size = self.window.inner_size();
+        self.resize(size.width, size.height);
+        // Its pipelines and textures were made with the lost device too
+        #[cfg(feature = "inspector")]
+        {
+            self.inspector = Inspector::new(&self.window, self.renderer.get_render_device());
+        }
+        Ok(())
+    }
+
+    // The browser restores the tab with a new page, there is nothing to block on here
+    #[cfg(target_arch = "wasm32")]
+    fn recover_device(&mut self) -> anyhow::Result<()> {
+        anyhow::bail!("Recreating the device is not supported in the browser")
+    }
+
+    // Presses are timed from here until the game sees them, see FrameHistory
+    fn on_input(&mut self, is_pressed: bool) {
+        if is_pressed && !self.is_loading() {
+            self.frame_history.on_input(get_time());
+        }
+    }
+
+    // The text is what the key typed, for the console
+    fn handle_key(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        code: KeyCode,
+        is_pressed: bool,
+        text: Option<&str>,
+    ) {
+        if code == KeyCode::Escape && is_pressed {
+            // Closes the console first
+            if self.console.is_open() {
+                self.console.set_open(false);
+                return;
+            }
+            event_loop.exit();
+        }
+        // Only quitting is possible while loading
+        if self.is_loading() {
+            return;
+        }
+
+        // While it is open the console gets the presses, releases still reach the game
+        if code == KeyCode::Backquote && is_pressed {
+            self.console.set_open(!self.console.is_open());
+            return;
+        }
+        if self.console.is_open() && is_pressed {
+            if let Some(line) = self.console.handle_key(code, text) {
+                self.run_console_line(&line);
+            }
+            return;
+        }
+
+        // While flying the movement keys belong to the debug camera. Releases reach the game
+        // too, so a key held while switching over doesn't stay down.
+        if let Some(action) = get_fly_action(code)
+            && (self.debug_camera.is_active() || !is_pressed)
+        {
+            self.input_state.set_action(action, is_pressed);
+            if is_pressed {
+                return;
+            }
+        }
+
         match code {
             KeyCode::KeyQ => self.input_state.set_action(InputAction::Q, is_pressed),
             KeyCode::KeyW => self.input_state.set_action(InputAction::W, is_pressed),
             KeyCode::KeyE => self.input_state.set_action(InputAction::E, is_pressed),
             KeyCode::KeyR => self.input_state.set_action(InputAction::R, is_pressed),
+            KeyCode::KeyS => self.input_state.set_action(InputAction::Stop, is_pressed),
+            KeyCode::ShiftLeft | KeyCode::ShiftRight => self
+                .input_state
+                .set_action(InputAction::AddToSelection, is_pressed),
             KeyCode::KeyY => self
                 .input_state
                 .set_action(InputAction::SwitchCameraMode, is_pressed),
             KeyCode::Space => self
                 .input_state
                 .set_action(InputAction::CameraFollow, is_pressed),
-            _ => {}
-        }
-
-        match (code, is_pressed) {
-            (KeyCode::Escape, true) => event_loop.exit(),
+            KeyCode::KeyL => self
+                .input_state
+                .set_action(InputAction::ToggleLightDebug, is_pressed),
+            KeyCode::F1 => self
+                .input_state
+                .set_action(InputAction::ToggleDebugCamera, is_pressed),
+            KeyCode::F2 => self
+                .input_state
+                .set_action(InputAction::CycleAntialiasing, is_pressed),
+            KeyCode::F3 => self
+                .input_state
+                .set_action(InputAction::ToggleResourceBrowser, is_pressed),
+            KeyCode::F4 => self
+                .input_state
+                .set_action(InputAction::ToggleInspector, is_pressed),
+            KeyCode::F5 => self
+                .input_state
+                .set_action(InputAction::ToggleLatencyFlash, is_pressed),
+            KeyCode::F6 => self
+                .input_state
+                .set_action(InputAction::ToggleSoftwareCursor, is_pressed),
+            KeyCode::F7 => self
+                .input_state
+                .set_action(InputAction::CycleRenderScale, is_pressed),
+            KeyCode::F8 => self
+                .input_state
+                .set_action(InputAction::SaveGame, is_pressed),
+            KeyCode::F9 => self
+                .input_state
+                .set_action(InputAction::LoadGame, is_pressed),
+            KeyCode::F10 => self
+                .input_state
+                .set_action(InputAction::ToggleSafeAreaDebug, is_pressed),
+            KeyCode::F11 => self
+                .input_state
+                .set_action(InputAction::CycleDebugView, is_pressed),
+            KeyCode::F12 => self
+                .input_state
+                .set_action(InputAction::ToggleChunkDebug, is_pressed),
+            KeyCode::KeyP => self
+                .input_state
+                .set_action(InputAction::ToggleProfiler, is_pressed),
+            KeyCode::ArrowUp => self
+                .input_state
+                .set_action(InputAction::DebugUp, is_pressed),
+            KeyCode::ArrowDown => self
+                .input_state
+                .set_action(InputAction::DebugDown, is_pressed),
+            KeyCode::Enter => self
+                .input_state
+                .set_action(InputAction::DebugSelect, is_pressed),
+            KeyCode::KeyG => self
+                .input_state
+                .set_action(InputAction::ToggleEditor, is_pressed),
+            KeyCode::Tab => {
+                self.input_state
+                    .set_action(InputAction::CycleGizmoMode, is_pressed);
+                self.input_state
+                    .set_action(InputAction::ShowStats, is_pressed);
+            }
+            KeyCode::ControlLeft | KeyCode::ControlRight => {
+                self.input_state.set_action(InputAction::Snap, is_pressed)
+            }
+            KeyCode::KeyZ => self.input_state.set_action(InputAction::Undo, is_pressed),
             _ => {}
         }
     }
 
     pub fn handle_mouse_button(&mut self, button: MouseButton, is_pressed: bool) {
+        if self.is_loading() {
+            return;
+        }
+
         match button {
             MouseButton::Left => self
                 .input_state
@@ -181,14 +1333,19 @@ pub struct App {
     #[cfg(target_arch = "wasm32")]
     proxy: Option<winit::event_loop::EventLoopProxy<State>>,
     state: Option<State>,
+    options: Option<ClientOptions>, // Taken when the window is created
 }
 
 impl App {
-    pub fn new(#[cfg(target_arch = "wasm32")] event_loop: &EventLoop<State>) -> Self {
+    pub fn new(
+        #[cfg(target_arch = "wasm32")] event_loop: &EventLoop<State>,
+        options: ClientOptions,
+    ) -> Self {
         #[cfg(target_arch = "wasm32")]
         let proxy = Some(event_loop.create_proxy());
         Self {
             state: None,
+            options: Some(options),
             #[cfg(target_arch = "wasm32")]
             proxy,
         }
@@ -197,8 +1354,20 @@ impl App {
 
 impl ApplicationHandler<State> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        #[allow(unused_mut)]
-        let mut window_attributes = Window::default_attributes();
+        let Some(options) = self.options.take() else {
+            return;
+        };
+        // Shows the desktop where nothing was rendered
+        let mut window_attributes =
+            Window::default_attributes().with_transparent(options.is_transparent());
+        if let Some((width, height)) = options.get_size() {
+            window_attributes =
+                window_attributes.with_inner_size(winit::dpi::PhysicalSize::new(width, height));
+        }
+        if options.is_fullscreen() {
+            window_attributes = window_attributes
+                .with_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
+        }
 
         #[cfg(target_arch = "wasm32")]
         {
@@ -218,7 +1387,7 @@ impl ApplicationHandler<State> for App {
 
         #[cfg(not(target_arch = "wasm32"))]
         {
-            self.state = Some(pollster::block_on(State::new(window)).unwrap());
+            self.state = Some(pollster::block_on(State::new(window, options)).unwrap());
         }
 
         #[cfg(target_arch = "wasm32")]
@@ -227,7 +1396,11 @@ impl ApplicationHandler<State> for App {
                 wasm_bindgen_futures::spawn_local(async move {
                     assert!(
                         proxy
-                            .send_event(State::new(window).await.expect("Unable to create canvas."))
+                            .send_event(
+                                State::new(window, options)
+                                    .await
+                                    .expect("Unable to create canvas.")
+                            )
                             .is_ok()
                     )
                 });
@@ -249,6 +1422,31 @@ impl ApplicationHandler<State> for App {
         self.state = Some(event);
     }
 
+    // Releases the level, anything still loaded after it was never unloaded
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        if let Some(state) = &mut self.state {
+            state.save_stats();
+            state.unload_chunks();
+            state.renderer.unload_scope(state.level_scope);
+            #[cfg(debug_assertions)]
+            state.renderer.log_unreleased_resources();
+        }
+    }
+
+    // Mouse movement for the debug camera, it keeps coming while the cursor is grabbed
+    fn device_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _device_id: DeviceId,
+        event: DeviceEvent,
+    ) {
+        if let (Some(state), DeviceEvent::MouseMotion { delta }) = (&mut self.state, event) {
+            state
+                .input_state
+                .add_mouse_delta(Vec2::new(delta.0 as f32, delta.1 as f32));
+        }
+    }
+
     #[allow(unused_mut)]
     fn window_event(
         &mut self,
@@ -261,80 +1459,270 @@ impl ApplicationHandler<State> for App {
             None => return,
         };
 
+        // The inspector sees input first, a click on one of its panels is not a move order.
+        // Releases always reach the game so nothing stays held down.
+        #[cfg(feature = "inspector")]
+        if !state.is_loading()
+            && state.inspector.on_window_event(&state.window, &event)
+            && is_press(&event)
+        {
+            return;
+        }
+
         match event {
             WindowEvent::CloseRequested => event_loop.exit(),
-            WindowEvent::Resized(size) => state.resize(size.width, size.height),
+            WindowEvent::Resized(size) => state.request_resize(size.width, size.height),
+            // E.g. moved to a monitor with other scaling. A Resized follows when the physical
+            // size changes too, the surface is sized from the inner size here in case not.
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                state.renderer.set_scale_factor(scale_factor);
+                let size = state.window.inner_size();
+                state.request_resize(size.width, size.height);
+            }
+            WindowEvent::Focused(focused) => state.on_focus_changed(focused),
             WindowEvent::RedrawRequested => {
+                let frame_scope = profiler::Scope::new("Frame");
                 let now = get_time();
                 let dt = (now - state.previous_time).clamp(0.0, 1.0 / 10.0).mul(1.0) as f32; // We clamp it to prevent instability
                 state.previous_time = now;
+                state.frame_history.begin_frame(now, dt);
+                state.input_state.set_time(now);
+                // Before the game sees the size, a restored window must not present at its
+                // old size
+                state.apply_pending_resize();
 
-                state.time_since_fixed += dt;
-                while state.time_since_fixed > State::FIXED_TIMESTEP {
-                    state.fixed_update(State::FIXED_TIMESTEP);
-                    state.time_since_fixed -= State::FIXED_TIMESTEP;
+                // Hit-stops and the time scale slow down the fixed updates too
+                let game_dt = state.game.get_time_mut().advance(dt);
+                {
+                    profile_scope!("Fixed steps");
+                    while state
+                        .game
+                        .get_time_mut()
+                        .take_fixed_step(State::FIXED_TIMESTEP)
+                    {
+                        state.fixed_update(State::FIXED_TIMESTEP);
+                    }
                 }
+                state.frame_history.on_fixed_done(get_time());
 
-                let alpha = (state.time_since_fixed / State::FIXED_TIMESTEP).clamp(0.0, 1.0);
-                state.update(dt, alpha);
+                let alpha = state.game.get_time().get_alpha(State::FIXED_TIMESTEP);
+                state.update(dt, game_dt, alpha);
 
-                match state.render() {
-                    Ok(_) => {}
-                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        let size = state.window.inner_size();
-                        state.resize(size.width, size.height);
-                    }
-                    Err(e) => {
-                        log::error!("Unable to render {}", e);
-                    }
+                if let Err(error) = state.render() {
+                    state.on_render_error(error);
                 }
+                state.renderer.wait_for_gpu();
 
+                state.frame_history.end_frame(get_time());
                 state.input_state.reset();
+                // The frame has to end before it is collected
+                drop(frame_scope);
+                state.profiler_overlay.end_frame();
+                state.end_frame();
             }
             WindowEvent::KeyboardInput {
                 event:
                     KeyEvent {
                         physical_key: PhysicalKey::Code(code),
                         state: key_state,
+                        text,
                         ..
                     },
                 ..
-            } => state.handle_key(event_loop, code, key_state.is_pressed()),
+            } => {
+                state.on_input(key_state.is_pressed());
+                state.handle_key(event_loop, code, key_state.is_pressed(), text.as_deref())
+            }
             WindowEvent::CursorMoved {
                 device_id: _device_id,
                 position,
             } => {
-                let normalized_position = Vec2::new(
-                    position.x as f32 / state.window.inner_size().width as f32,
-                    position.y as f32 / state.window.inner_size().height as f32,
+                let size = state.window.inner_size();
+                let normalized_position = get_normalized_cursor_position(
+                    Vec2::new(position.x as f32, position.y as f32),
+                    UVec2::new(size.width, size.height),
                 );
 
-                state.input_state.set_mouse_position(normalized_position);
+                state
+                    .input_state
+                    .set_mouse_position_at(normalized_position, get_time());
             }
             WindowEvent::MouseInput {
                 device_id: _device_id,
                 state: button_state,
                 button,
-            } => state.handle_mouse_button(button, button_state.is_pressed()),
+            } => {
+                state.on_input(button_state.is_pressed());
+                state.handle_mouse_button(button, button_state.is_pressed())
+            }
             _ => {}
         }
     }
 }
 
+fn get_fly_action(code: KeyCode) -> Option<InputAction> {
+    match code {
+        KeyCode::KeyW => Some(InputAction::FlyForward),
+        KeyCode::KeyS => Some(InputAction::FlyBack),
+        KeyCode::KeyA => Some(InputAction::FlyLeft),
+        KeyCode::KeyD => Some(InputAction::FlyRight),
+        KeyCode::KeyE => Some(InputAction::FlyUp),
+        KeyCode::KeyQ => Some(InputAction::FlyDown),
+        KeyCode::ShiftLeft | KeyCode::ShiftRight => Some(InputAction::FlyFast),
+        KeyCode::ControlLeft | KeyCode::ControlRight => Some(InputAction::FlySlow),
+        KeyCode::KeyT => Some(InputAction::TeleportCamera),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "inspector")]
+fn is_press(event: &WindowEvent) -> bool {
+    match event {
+        WindowEvent::KeyboardInput { event, .. } => event.state.is_pressed(),
+        WindowEvent::MouseInput { state, .. } => state.is_pressed(),
+        _ => false,
+    }
+}
+
+// client --headless <ticks>, the level is loaded and simulated without a window, e.g. on CI
+#[cfg(not(target_arch = "wasm32"))]
+fn run_headless(options: &ClientOptions, ticks: u32) -> anyhow::Result<()> {
+    use anyhow::Context;
+
+    const SIZE: (u32, u32) = (1280, 720);
+
+    let start = get_time();
+    let (width, height) = options.get_size().unwrap_or(SIZE);
+    let mut renderer = pollster::block_on(Renderer::new_headless(width, height))?;
+    let asset_base = options.get_asset_base();
+    let mut startup = StartupLoader::new(AssetFetcher::new(asset_base), options.get_level_name());
+    let Startup {
+        level,
+        prefabs,
+        abilities,
+    } = loop {
+        if let Some(error) = startup.get_errors().first() {
+            anyhow::bail!("Failed to start {}: {}", options.get_level_name(), error);
+        }
+        if let Some(startup) = startup.update() {
+            break startup;
+        }
+    };
+    let fetcher = AssetFetcher::new(asset_base);
+    let level_scope = renderer.create_scope(&level.name);
+    let mut loader = LevelLoader::new(&level, fetcher, level_scope);
+    while !loader.is_done() {
+        if let Some(error) = loader.get_errors().first() {
+            anyhow::bail!("Failed to load {}: {}", level.name, error);
+        }
+        loader.update(&mut renderer, AppPhase::LOAD_BUDGET, get_time);
+    }
+
+    let mut game = Game::new();
+    let mut physics_world = PhysicsWorld::new();
+    game.set_abilities(abilities);
+    renderer.set_current_scope(Some(level_scope));
+    game.build_level(
+        &level,
+        &loader.take_kept_files(),
+        &mut renderer,
+        &mut physics_world,
+    );
+    renderer.set_current_scope(None);
+    let resource_pool = renderer.get_resource_pool();
+    prefabs
+        .validate(|handle| resource_pool.get_resource(handle).map(Resource::get_kind))
+        .context("Prefabs refer to missing resources")?;
+    game.set_prefabs(prefabs);
+    let loaded = get_time();
+
+    for _ in 0..ticks {
+        game.fixed_update(State::FIXED_TIMESTEP, &renderer, &mut physics_world);
+        game.record_events();
+    }
+    log::info!(
+        "Simulated {} ticks of {} in {:.2}s after loading for {:.2}s, {} entities left",
+        ticks,
+        level.name,
+        get_time() - loaded,
+        loaded - start,
+        game.get_entity_count()
+    );
+    // Not saved, a run on CI isn't played by anyone
+    log::info!("Stats: {:?}", game.get_stats().get_session());
+    renderer.unload_scope(level_scope);
+    Ok(())
+}
+
+// console_log without a logger of its own, so the LogRing can wrap it
+#[cfg(target_arch = "wasm32")]
+struct WebConsoleLogger(log::Level);
+
+#[cfg(target_arch = "wasm32")]
+impl log::Log for WebConsoleLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.0
+    }
+
+    fn log(&self, record: &log::Record) {
+        console_log::log(record);
+    }
+
+    fn flush(&self) {}
+}
+
 pub fn run() -> anyhow::Result<()> {
+    // Nothing is opened with options that are wrong, a typo shouldn't start the defaults
+    let options = get_options().and_then(|options| {
+        options.validate()?;
+        Ok(options)
+    });
+    #[cfg(not(target_arch = "wasm32"))]
+    let options = options.unwrap_or_else(|error| {
+        eprintln!("error: {:#}", error);
+        std::process::exit(2);
+    });
+    #[cfg(target_arch = "wasm32")]
+    let options = options?;
+
     #[cfg(not(target_arch = "wasm32"))]
     {
-        env_logger::init();
+        // RUST_LOG is still read for the filters of single modules
+        let mut builder = env_logger::Builder::from_default_env();
+        if let Some(level) = options.get_log_level() {
+            builder.filter_level(level);
+        }
+        // The last lines are kept for the diagnostics
+        let logger = builder.build();
+        let level = logger.filter();
+        LogRing::install(Box::new(logger), level).expect("A logger was already installed");
     }
     #[cfg(target_arch = "wasm32")]
     {
-        console_log::init_with_level(log::Level::Info).unwrap_throw();
+        if let Some(level) = options
+            .get_log_level()
+            .map_or(Some(log::Level::Info), |filter| filter.to_level())
+        {
+            LogRing::install(Box::new(WebConsoleLogger(level)), level.to_level_filter())
+                .unwrap_throw();
+        }
+    }
+    install_panic_hook();
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(ticks) = options.headless {
+        return run_headless(&options, ticks);
+    }
+    #[cfg(target_arch = "wasm32")]
+    if options.headless.is_some() {
+        log::warn!("There is no headless mode in the browser");
     }
 
     let event_loop = EventLoop::with_user_event().build()?;
     let mut app = App::new(
         #[cfg(target_arch = "wasm32")]
         &event_loop,
+        options,
     );
     event_loop.run_app(&mut app)?;
 
@@ -0,0 +1,175 @@
+// Merging static props into combined meshes when a level is loaded. A prop that never moves
+// still costs an instance slot and its matrix math every frame, baked into world space the
+// props of one mesh and material are drawn as a single instance. Only the most detailed
+// level of a mesh is baked, the combined meshes have one level.
+
+use glam::Mat3;
+use shared::math::*;
+
+use crate::renderer::StaticMeshVertex;
+
+// How one prop is drawn, what its instance would apply goes into the vertices instead
+pub struct BakeInstance {
+    pub transform: Mat4,
+    pub color: Vec4,
+    pub tex_coord: Vec2,
+    pub tex_scale: Vec2,
+}
+
+#[derive(Default)]
+pub struct BakedGeometry {
+    pub vertices: Vec<StaticMeshVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl BakedGeometry {
+    // Only the vertices the indices use are copied, the other levels of the mesh are left out
+    pub fn append(
+        &mut self,
+        vertices: &[StaticMeshVertex],
+        indices: &[u32],
+        instance: &BakeInstance,
+    ) {
+        // Normals go through the inverse transpose, so non-uniform scales keep them upright
+        let normal_matrix = Mat3::from_mat4(instance.transform).inverse().transpose();
+        let mut remap = vec![u32::MAX; vertices.len()];
+        let first_index = self.indices.len();
+
+        for &index in indices {
+            let slot = &mut remap[index as usize];
+            if *slot == u32::MAX {
+                let vertex = &vertices[index as usize];
+                let uvs = Vec2::new(vertex.uvs[0], vertex.uvs[1]) * instance.tex_scale
+                    + instance.tex_coord;
+                *slot = self.vertices.len() as u32;
+                self.vertices.push(StaticMeshVertex {
+                    position: instance
+                        .transform
+                        .transform_point3(Vec3::from(vertex.position))
+                        .into(),
+                    normal: (normal_matrix * Vec3::from(vertex.normal))
+                        .normalize_or_zero()
+                        .into(),
+                    uvs: [uvs.x, uvs.y, vertex.uvs[2]],
+                    color: (Vec4::from(vertex.color) * instance.color).into(),
+                });
+            }
+            self.indices.push(*slot);
+        }
+
+        // A mirroring transform flips the winding, the triangles are turned back around
+        if instance.transform.determinant() < 0.0 {
+            for triangle in self.indices[first_index..].chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+    }
+
+    // Of the buffers it is uploaded to
+    pub fn get_size(&self) -> u64 {
+        (std::mem::size_of_val(self.vertices.as_slice())
+            + std::mem::size_of_val(self.indices.as_slice())) as u64
+    }
+}
+
+// What baking a level changed, the combined meshes come on top of the source meshes, which
+// are kept for the props that weren't baked and for reloading
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BakeStats {
+    pub instances_before: usize, // Of the props that were baked
+    pub instances_after: usize,  // One per combined mesh
+    pub batches_before: usize,   // Distinct meshes and materials
+    pub batches_after: usize,
+    pub baked_bytes: u64, // Of the combined meshes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: [f32; 3], normal: [f32; 3]) -> StaticMeshVertex {
+        StaticMeshVertex {
+            position,
+            normal,
+            uvs: [0.5, 1.0, 0.0],
+            color: [1.0, 0.5, 1.0, 1.0],
+        }
+    }
+
+    // A right triangle in the XY plane facing +Z, with a vertex no index uses
+    fn triangle() -> Vec<StaticMeshVertex> {
+        vec![
+            vertex([0.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+            vertex([1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+            vertex([9.0, 9.0, 9.0], [0.0, 0.0, 1.0]),
+            vertex([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+        ]
+    }
+
+    fn get_face_normal(geometry: &BakedGeometry, triangle: usize) -> Vec3 {
+        let [a, b, c] = [0, 1, 2].map(|corner| {
+            Vec3::from(geometry.vertices[geometry.indices[triangle * 3 + corner] as usize].position)
+        });
+        (b - a).cross(c - a).normalize()
+    }
+
+    #[test]
+    fn props_are_moved_into_world_space() {
+        let mut geometry = BakedGeometry::default();
+        geometry.append(
+            &triangle(),
+            &[0, 1, 3],
+            &BakeInstance {
+                transform: Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0)),
+                color: Vec4::new(0.5, 1.0, 1.0, 1.0),
+                tex_coord: Vec2::new(0.25, 0.0),
+                tex_scale: Vec2::splat(2.0),
+            },
+        );
+        // Skewed by a non-uniform scale, the normal stays perpendicular to the surface
+        let skew = Mat4::from_rotation_z(0.5) * Mat4::from_scale(Vec3::new(4.0, 1.0, 1.0));
+        geometry.append(
+            &triangle(),
+            &[0, 1, 3],
+            &BakeInstance {
+                transform: Mat4::from_rotation_x(1.0) * skew,
+                color: Vec4::ONE,
+                tex_coord: Vec2::ZERO,
+                tex_scale: Vec2::ONE,
+            },
+        );
+
+        assert_eq!(geometry.vertices.len(), 6);
+        assert_eq!(geometry.indices, [0, 1, 2, 3, 4, 5]);
+        let first = &geometry.vertices[1];
+        assert_eq!(first.position, [11.0, 0.0, 0.0]);
+        assert_eq!(first.uvs, [1.25, 2.0, 0.0]);
+        assert_eq!(first.color, [0.5, 0.5, 1.0, 1.0]);
+
+        for triangle in 0..2 {
+            let normal = Vec3::from(geometry.vertices[triangle * 3].normal);
+            assert!(normal.abs_diff_eq(get_face_normal(&geometry, triangle), 1e-5));
+        }
+        assert_eq!(geometry.get_size(), 6 * 52 + 6 * 4);
+    }
+
+    #[test]
+    fn mirrored_props_keep_facing_out() {
+        let mut geometry = BakedGeometry::default();
+        geometry.append(
+            &triangle(),
+            &[0, 1, 3],
+            &BakeInstance {
+                transform: Mat4::from_scale(Vec3::new(1.0, 1.0, -1.0)),
+                color: Vec4::ONE,
+                tex_coord: Vec2::ZERO,
+                tex_scale: Vec2::ONE,
+            },
+        );
+
+        // The mirrored triangle faces -Z, the winding and the normal agree on it
+        let normal = Vec3::from(geometry.vertices[0].normal);
+        assert_eq!(normal, Vec3::NEG_Z);
+        assert!(get_face_normal(&geometry, 0).abs_diff_eq(normal, 1e-6));
+    }
+}
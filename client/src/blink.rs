@@ -0,0 +1,203 @@
+// Where a blink lands. The shape of the caster is swept from its position towards the target,
+// at most the range away, and a wall on the way stops it just short of the hit. When that spot
+// is still taken, e.g. the caster was pushed into the wall, the nearest free spot within the
+// search radius is looked for, back along the way first and then in rings around it.
+
+use shared::{
+    math::*,
+    physics::{CollisionLayer, CollisionShape, LayerMask, PhysicsWorld},
+};
+
+const WALL_GAP: f32 = 1.0; // Kept between the caster and the wall it was stopped by
+const SEARCH_STEP: f32 = 8.0;
+const SEARCH_DIRECTIONS: usize = 16; // Per ring
+
+fn get_blocking_mask() -> LayerMask {
+    CollisionLayer::ALL
+        .into_iter()
+        .filter(|layer| layer.is_blocking())
+        .fold(0, |mask, layer| mask | layer.get_bit())
+}
+
+fn is_free(physics_world: &PhysicsWorld, shape: CollisionShape, position: Vec2) -> bool {
+    physics_world
+        .query_shape_filtered(position, shape, |_, layer| layer.is_blocking())
+        .is_empty()
+}
+
+// None when there is nowhere free within the search radius, the blink should fail then.
+// Uses the grid of the last step.
+pub fn resolve_blink_destination(
+    physics_world: &PhysicsWorld,
+    shape: CollisionShape,
+    start: Vec2,
+    target: Vec2,
+    range: f32,
+    search_radius: f32,
+) -> Option<Vec2> {
+    let offset = (target - start).clamp_length_max(range);
+    let direction = offset.normalize_or_zero();
+    let cast = physics_world.shape_cast(shape, start, start + offset, get_blocking_mask());
+    let destination = match cast.blocked_at {
+        Some((t, _, _)) => start + direction * (offset.length() * t - WALL_GAP).max(0.0),
+        None => start + offset,
+    };
+    if is_free(physics_world, shape, destination) {
+        return Some(destination);
+    }
+
+    let steps = (search_radius / SEARCH_STEP).floor() as u32;
+    let distances = (1..=steps).map(|step| step as f32 * SEARCH_STEP);
+    if direction != Vec2::ZERO
+        && let Some(position) = distances
+            .clone()
+            .map(|distance| destination - direction * distance)
+            .find(|&position| is_free(physics_world, shape, position))
+    {
+        return Some(position);
+    }
+    // Starting from the way back, so ties go to the side the caster came from
+    let back = if direction == Vec2::ZERO {
+        Vec2::X
+    } else {
+        -direction
+    };
+    distances
+        .flat_map(|distance| {
+            (0..SEARCH_DIRECTIONS).map(move |index| {
+                let angle = index as f32 / SEARCH_DIRECTIONS as f32 * std::f32::consts::TAU;
+                destination + Vec2::from_angle(angle).rotate(back) * distance
+            })
+        })
+        .find(|&position| is_free(physics_world, shape, position))
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::physics::BodySettings;
+
+    use super::*;
+
+    const CASTER: CollisionShape = CollisionShape::Circle { radius: 32.0 };
+    const RANGE: f32 = 600.0;
+    const SEARCH_RADIUS: f32 = 96.0;
+
+    // The walls mustn't overlap, the step would push them apart
+    fn create_world(walls: &[(Vec2, CollisionShape)], units: &[Vec2]) -> PhysicsWorld {
+        let mut physics_world = PhysicsWorld::new();
+        let unit = CollisionShape::Circle { radius: 40.0 };
+        let bodies = walls
+            .iter()
+            .map(|(position, shape)| (*position, shape, CollisionLayer::Environment))
+            .chain(
+                units
+                    .iter()
+                    .map(|position| (*position, &unit, CollisionLayer::Enemy)),
+            );
+        for (position, shape, layer) in bodies {
+            physics_world.create_rigid_body(&BodySettings {
+                position,
+                velocity: Vec2::ZERO,
+                layer,
+                shape,
+                listen_to_contact_events: false,
+            });
+        }
+        physics_world.step_simulation(0.0);
+        physics_world
+    }
+
+    fn resolve(physics_world: &PhysicsWorld, start: Vec2, target: Vec2) -> Option<Vec2> {
+        resolve_blink_destination(physics_world, CASTER, start, target, RANGE, SEARCH_RADIUS)
+    }
+
+    #[test]
+    fn open_ground_blinks_as_far_as_the_range() {
+        let physics_world = create_world(&[], &[]);
+        let destination = resolve(&physics_world, Vec2::ZERO, Vec2::new(250.0, 0.0)).unwrap();
+        assert!(destination.distance(Vec2::new(250.0, 0.0)) < 1e-3);
+        let destination = resolve(&physics_world, Vec2::ZERO, Vec2::new(0.0, -900.0)).unwrap();
+        assert!(destination.distance(Vec2::new(0.0, -RANGE)) < 1e-3);
+    }
+
+    #[test]
+    fn walls_of_every_shape_stop_the_blink_short() {
+        let walls = [
+            CollisionShape::Circle { radius: 50.0 },
+            CollisionShape::Rect {
+                half_extents: Vec2::new(50.0, 200.0),
+            },
+            CollisionShape::Obb {
+                half_extents: Vec2::new(50.0, 200.0),
+                rotation: 0.3,
+            },
+        ];
+        for wall in walls {
+            // A unit on the way isn't a wall
+            let physics_world =
+                create_world(&[(Vec2::new(300.0, 0.0), wall)], &[Vec2::new(120.0, 0.0)]);
+            // Behind the wall, then right on top of it
+            for target in [Vec2::new(500.0, 0.0), Vec2::new(300.0, 0.0)] {
+                let destination = resolve(&physics_world, Vec2::ZERO, target).unwrap();
+                assert!(is_free(&physics_world, CASTER, destination), "{:?}", wall);
+                assert_eq!(destination.y, 0.0);
+                assert!(
+                    destination.x > 180.0 && destination.x < 250.0,
+                    "{:?} {}",
+                    wall,
+                    destination
+                );
+                // Right in front of it
+                let touching = destination + Vec2::X * (WALL_GAP + 0.1);
+                assert!(!is_free(&physics_world, CASTER, touching), "{:?}", wall);
+            }
+        }
+    }
+
+    #[test]
+    fn taken_destinations_move_to_the_nearest_free_spot() {
+        // Already pushed into the wall, the sweep is stopped right away
+        let wall = CollisionShape::Rect {
+            half_extents: Vec2::new(50.0, 200.0),
+        };
+        let physics_world = create_world(&[(Vec2::new(300.0, 0.0), wall)], &[]);
+        let start = Vec2::new(230.0, 0.0);
+        let destination = resolve(&physics_world, start, Vec2::new(600.0, 0.0)).unwrap();
+        assert!(destination.distance(Vec2::new(214.0, 0.0)) < 1e-3);
+
+        // Stuck between two posts, neither way along the blink is free but around it is
+        let post = CollisionShape::Rect {
+            half_extents: Vec2::new(50.0, 40.0),
+        };
+        let physics_world = create_world(
+            &[(Vec2::new(-70.0, 0.0), post), (Vec2::new(70.0, 0.0), post)],
+            &[],
+        );
+        let destination = resolve(&physics_world, Vec2::ZERO, Vec2::new(50.0, 0.0)).unwrap();
+        assert!(is_free(&physics_world, CASTER, destination));
+        assert!(destination.y.abs() >= 72.0 && destination.y.abs() <= SEARCH_RADIUS);
+    }
+
+    #[test]
+    fn fully_enclosed_blinks_fail() {
+        // A cell narrower than the caster, with walls thicker than the search radius
+        let side = CollisionShape::Rect {
+            half_extents: Vec2::new(50.0, 120.0),
+        };
+        let end = CollisionShape::Rect {
+            half_extents: Vec2::new(19.5, 50.0),
+        };
+        let physics_world = create_world(
+            &[
+                (Vec2::new(-70.0, 0.0), side),
+                (Vec2::new(70.0, 0.0), side),
+                (Vec2::new(0.0, -70.0), end),
+                (Vec2::new(0.0, 70.0), end),
+            ],
+            &[],
+        );
+        for target in [Vec2::new(400.0, 0.0), Vec2::new(-30.0, 250.0), Vec2::ZERO] {
+            assert_eq!(resolve(&physics_world, Vec2::ZERO, target), None);
+        }
+    }
+}
@@ -0,0 +1,647 @@
+// Camera paths for trailers and bug reports, flown instead of the gameplay camera the same way
+// the debug camera is. Positions follow a Catmull-Rom spline through the keyframes with the
+// tangents taken over time, so the velocity is continuous through every keyframe even when
+// they are spaced unevenly. Rotations are nlerped and the field of view is lerped. Keyframes
+// at the same time are a cut, the camera jumps to the later one.
+//
+// Built with the camtrack console commands, e.g. "camtrack add" at a few spots with the debug
+// camera, then "camtrack play 1" and "camtrack save intro.json".
+
+use anyhow::{Context, anyhow, bail};
+use glam::EulerRot;
+use serde::{Deserialize, Serialize};
+use shared::math::*;
+
+use crate::{
+    console::{ArgSpec, ConsoleCommands, ConsoleContext},
+    level::get_euler_rotation,
+    time_controller::TimeController,
+};
+
+const KEYFRAME_SPACING: f32 = 1.0; // Seconds after the last keyframe "camtrack add" puts one
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackMode {
+    #[default]
+    Clamp, // Holds the last keyframe until stopped
+    Loop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraKeyframe {
+    pub time: f32, // Seconds
+    pub position: [f32; 3],
+    pub rotation: [f32; 3], // Euler angles in degrees (XYZ), like the props of a level
+    pub fov: f32,           // Vertical, in degrees
+    // The index of an entity to turn towards instead, while it is alive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub look_at: Option<u32>,
+}
+
+impl CameraKeyframe {
+    pub fn new(time: f32, position: Vec3, rotation: Quat, fov: f32) -> Self {
+        let (x, y, z) = rotation.to_euler(EulerRot::XYZ);
+        Self {
+            time,
+            position: position.to_array(),
+            rotation: [x, y, z].map(f32::to_degrees),
+            fov,
+            look_at: None,
+        }
+    }
+
+    fn get_position(&self) -> Vec3 {
+        Vec3::from(self.position)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraSample {
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub fov: f32, // Degrees
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraTrack {
+    #[serde(default)]
+    pub mode: PlaybackMode,
+    #[serde(default)]
+    pub drive_time_scale: bool, // The game runs at the playback speed while it plays
+    pub keyframes: Vec<CameraKeyframe>, // Sorted by time
+}
+
+impl CameraTrack {
+    pub fn load(bytes: &[u8]) -> anyhow::Result<Self> {
+        let deserializer = &mut serde_json::Deserializer::from_slice(bytes);
+        let track: CameraTrack = serde_path_to_error::deserialize(deserializer)
+            .map_err(|error| anyhow!("{}: {}", error.path(), error.inner()))?;
+        track.validate()?;
+        Ok(track)
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for (index, keyframe) in self.keyframes.iter().enumerate() {
+            let values = [keyframe.time, keyframe.fov]
+                .into_iter()
+                .chain(keyframe.position)
+                .chain(keyframe.rotation);
+            if !values.into_iter().all(f32::is_finite) {
+                bail!("keyframes[{}]: not a finite number", index);
+            }
+            if !(1.0..180.0).contains(&keyframe.fov) {
+                bail!(
+                    "keyframes[{}].fov: {} isn't a field of view",
+                    index,
+                    keyframe.fov
+                );
+            }
+        }
+        if let Some(index) = (1..self.keyframes.len())
+            .find(|&index| self.keyframes[index].time < self.keyframes[index - 1].time)
+        {
+            bail!(
+                "keyframes[{}].time: earlier than the keyframe before",
+                index
+            );
+        }
+        Ok(())
+    }
+
+    // After the keyframes at the same time, so adding one there makes a cut
+    pub fn insert(&mut self, keyframe: CameraKeyframe) {
+        let index = self
+            .keyframes
+            .partition_point(|other| other.time <= keyframe.time);
+        self.keyframes.insert(index, keyframe);
+    }
+
+    pub fn get_start(&self) -> f32 {
+        self.keyframes.first().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    pub fn get_end(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    // Clamped to the track or wrapped around it, depending on the mode
+    pub fn wrap_time(&self, time: f32) -> f32 {
+        let (start, end) = (self.get_start(), self.get_end());
+        match self.mode {
+            PlaybackMode::Loop if end > start => start + (time - start).rem_euclid(end - start),
+            _ => time.clamp(start, end),
+        }
+    }
+
+    // The velocity through the keyframe. One-sided at the ends and next to a cut, none for a
+    // keyframe with cuts on both sides.
+    fn get_tangent(&self, index: usize) -> Vec3 {
+        let keyframe = &self.keyframes[index];
+        let previous = index
+            .checked_sub(1)
+            .map(|previous| &self.keyframes[previous])
+            .filter(|previous| previous.time < keyframe.time);
+        let next = self
+            .keyframes
+            .get(index + 1)
+            .filter(|next| next.time > keyframe.time);
+        let (from, to) = match (previous, next) {
+            (Some(previous), Some(next)) => (previous, next),
+            (Some(previous), None) => (previous, keyframe),
+            (None, Some(next)) => (keyframe, next),
+            (None, None) => return Vec3::ZERO,
+        };
+        (to.get_position() - from.get_position()) / (to.time - from.time)
+    }
+
+    // None for an empty track. Looking at an entity needs its position.
+    pub fn sample(
+        &self,
+        time: f32,
+        get_target: impl Fn(u32) -> Option<Vec3>,
+    ) -> Option<CameraSample> {
+        let last = self.keyframes.len().checked_sub(1)?;
+        let time = self.wrap_time(time);
+        // The last keyframe at or before the time, the later one of a cut
+        let index = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time)
+            .saturating_sub(1);
+        let next = (index + 1).min(last);
+        let (from, to) = (&self.keyframes[index], &self.keyframes[next]);
+        let span = to.time - from.time;
+        let t = if span > 0.0 {
+            ((time - from.time) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let position = get_hermite_point(
+            from.get_position(),
+            self.get_tangent(index) * span,
+            to.get_position(),
+            self.get_tangent(next) * span,
+            t,
+        );
+        let [from_rotation, to_rotation] = [from, to].map(|keyframe| {
+            keyframe
+                .look_at
+                .and_then(&get_target)
+                .and_then(|target| get_look_rotation(target - position))
+                .unwrap_or_else(|| get_euler_rotation(keyframe.rotation))
+        });
+        Some(CameraSample {
+            position,
+            rotation: nlerp(from_rotation, to_rotation, t),
+            fov: from.fov + (to.fov - from.fov) * t,
+        })
+    }
+}
+
+// The cubic from a to b with the tangents scaled to the segment
+fn get_hermite_point(a: Vec3, a_tangent: Vec3, b: Vec3, b_tangent: Vec3, t: f32) -> Vec3 {
+    let (t2, t3) = (t * t, t * t * t);
+    a * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + a_tangent * (t3 - 2.0 * t2 + t)
+        + b * (-2.0 * t3 + 3.0 * t2)
+        + b_tangent * (t3 - t2)
+}
+
+// Along the shorter way around
+fn nlerp(a: Quat, b: Quat, t: f32) -> Quat {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    (a * (1.0 - t) + b * t).normalize()
+}
+
+// Cameras look down -z, None straight up or down
+fn get_look_rotation(direction: Vec3) -> Option<Quat> {
+    let direction = direction.try_normalize()?;
+    if direction.y.abs() > 0.999 {
+        return None;
+    }
+    Some(Quat::look_to_rh(direction, Vec3::Y).inverse())
+}
+
+struct Playback {
+    time: f32,
+    speed: f32,
+    previous_time_scale: Option<f32>, // Set back on stopping when the track drove it
+}
+
+// Plays the track on real time, so slowing the game down doesn't slow the camera
+#[derive(Default)]
+pub struct CameraTrackPlayer {
+    track: CameraTrack,
+    playback: Option<Playback>,
+}
+
+impl CameraTrackPlayer {
+    pub fn get_track(&self) -> &CameraTrack {
+        &self.track
+    }
+
+    // Editing stops the playback
+    pub fn get_track_mut(&mut self, time: &mut TimeController) -> &mut CameraTrack {
+        self.stop(time);
+        &mut self.track
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    pub fn play(&mut self, speed: f32, time: &mut TimeController) -> anyhow::Result<()> {
+        if self.track.keyframes.len() < 2 {
+            bail!("The track needs at least two keyframes");
+        }
+        if speed <= 0.0 {
+            bail!("The speed must be positive");
+        }
+        self.stop(time);
+        let previous_time_scale = self.track.drive_time_scale.then(|| {
+            let previous = time.get_base_scale();
+            time.set_base_scale(speed);
+            previous
+        });
+        self.playback = Some(Playback {
+            time: self.track.get_start(),
+            speed,
+            previous_time_scale,
+        });
+        Ok(())
+    }
+
+    pub fn stop(&mut self, time: &mut TimeController) {
+        if let Some(scale) = self
+            .playback
+            .take()
+            .and_then(|playback| playback.previous_time_scale)
+        {
+            time.set_base_scale(scale);
+        }
+    }
+
+    pub fn update(&mut self, real_dt: f32) {
+        if let Some(playback) = &mut self.playback {
+            playback.time = self
+                .track
+                .wrap_time(playback.time + real_dt * playback.speed);
+        }
+    }
+
+    // Where to draw from while playing
+    pub fn sample(&self, get_target: impl Fn(u32) -> Option<Vec3>) -> Option<CameraSample> {
+        let playback = self.playback.as_ref()?;
+        self.track.sample(playback.time, get_target)
+    }
+}
+
+fn parse_switch(value: &str) -> anyhow::Result<bool> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => bail!("Expected on or off, got {}", value),
+    }
+}
+
+pub fn register_commands(commands: &mut ConsoleCommands) {
+    // At the view drawn from, the debug camera's while it flies
+    let add = |context: &mut ConsoleContext, cut: bool| {
+        let view = context.renderer.get_view_transform();
+        let fov = context.game.get_camera_settings().fov;
+        let track = context
+            .camera_track
+            .get_track_mut(context.game.get_time_mut());
+        let time = match track.keyframes.last() {
+            Some(last) if cut => last.time,
+            Some(last) => last.time + KEYFRAME_SPACING,
+            None => 0.0,
+        };
+        track.insert(CameraKeyframe::new(time, view.position, view.rotation, fov));
+        Ok(format!(
+            "Keyframe {} at {} s",
+            track.keyframes.len() - 1,
+            time
+        ))
+    };
+    commands.register("camtrack add", &[], move |context, _| add(context, false));
+    commands.register("camtrack cut", &[], move |context, _| add(context, true));
+
+    commands.register(
+        "camtrack look",
+        &[ArgSpec::word("entity|none")],
+        |context, args| {
+            let look_at = match args.get_str(0) {
+                "none" => None,
+                value => {
+                    let index = value
+                        .parse::<u32>()
+                        .ok()
+                        .with_context(|| format!("Expected an entity or none, got {}", value))?;
+                    if context.game.get_entity_position(index).is_none() {
+                        bail!("There is no entity {}", index);
+                    }
+                    Some(index)
+                }
+            };
+            let track = context
+                .camera_track
+                .get_track_mut(context.game.get_time_mut());
+            let keyframe = track
+                .keyframes
+                .last_mut()
+                .context("There are no keyframes")?;
+            keyframe.look_at = look_at;
+            Ok(match look_at {
+                Some(index) => format!("The last keyframe looks at entity {}", index),
+                None => "The last keyframe looks where it was added".to_string(),
+            })
+        },
+    );
+
+    commands.register(
+        "camtrack play",
+        &[ArgSpec::number("speed")],
+        |context, args| {
+            let speed = args.get_f32(0);
+            context
+                .camera_track
+                .play(speed, context.game.get_time_mut())?;
+            Ok(format!("Playing the camera track at {}x", speed))
+        },
+    );
+
+    commands.register("camtrack stop", &[], |context, _| {
+        context.camera_track.stop(context.game.get_time_mut());
+        Ok("Stopped the camera track".to_string())
+    });
+
+    commands.register(
+        "camtrack mode",
+        &[ArgSpec::word("clamp|loop")],
+        |context, args| {
+            let mode = match args.get_str(0) {
+                "clamp" => PlaybackMode::Clamp,
+                "loop" => PlaybackMode::Loop,
+                value => bail!("Expected clamp or loop, got {}", value),
+            };
+            let track = context
+                .camera_track
+                .get_track_mut(context.game.get_time_mut());
+            track.mode = mode;
+            Ok(format!("Camera track mode {}", args.get_str(0)))
+        },
+    );
+
+    commands.register(
+        "camtrack timescale",
+        &[ArgSpec::word("on|off")],
+        |context, args| {
+            let enabled = parse_switch(args.get_str(0))?;
+            let track = context
+                .camera_track
+                .get_track_mut(context.game.get_time_mut());
+            track.drive_time_scale = enabled;
+            Ok(format!(
+                "The camera track {} the time scale",
+                if enabled { "drives" } else { "leaves" }
+            ))
+        },
+    );
+
+    commands.register("camtrack clear", &[], |context, _| {
+        let track = context
+            .camera_track
+            .get_track_mut(context.game.get_time_mut());
+        *track = CameraTrack::default();
+        Ok("Cleared the camera track".to_string())
+    });
+
+    commands.register(
+        "camtrack save",
+        &[ArgSpec::word("file")],
+        |context, args| {
+            if cfg!(target_arch = "wasm32") {
+                bail!("Camera tracks can't be saved in the browser");
+            }
+            let path = args.get_str(0);
+            let json = context.camera_track.get_track().to_json()?;
+            std::fs::write(path, json).with_context(|| format!("Failed to write {}", path))?;
+            Ok(format!("Saved the camera track to {}", path))
+        },
+    );
+
+    commands.register(
+        "camtrack load",
+        &[ArgSpec::word("file")],
+        |context, args| {
+            if cfg!(target_arch = "wasm32") {
+                bail!("Camera tracks can't be loaded in the browser");
+            }
+            let path = args.get_str(0);
+            let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+            let loaded =
+                CameraTrack::load(&bytes).with_context(|| format!("Failed to load {}", path))?;
+            let track = context
+                .camera_track
+                .get_track_mut(context.game.get_time_mut());
+            *track = loaded;
+            Ok(format!(
+                "Loaded {} keyframes from {}",
+                track.keyframes.len(),
+                path
+            ))
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, position: Vec3) -> CameraKeyframe {
+        CameraKeyframe::new(time, position, Quat::IDENTITY, 40.0)
+    }
+
+    fn track(points: &[(f32, Vec3)]) -> CameraTrack {
+        CameraTrack {
+            keyframes: points
+                .iter()
+                .map(|&(time, position)| keyframe(time, position))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn position_at(track: &CameraTrack, time: f32) -> Vec3 {
+        track.sample(time, |_| None).unwrap().position
+    }
+
+    #[test]
+    fn positions_match_hand_computed_points() {
+        let track = track(&[
+            (0.0, Vec3::ZERO),
+            (1.0, Vec3::new(1.0, 0.0, 0.0)),
+            (2.0, Vec3::new(3.0, 0.0, 0.0)),
+        ]);
+        // Tangents 1, (3 - 0) / 2 = 1.5 and 2 along x
+        // At 0.5: 0.5 * 0 + 0.125 * 1 + 0.5 * 1 - 0.125 * 1.5 = 0.4375
+        assert!((position_at(&track, 0.5).x - 0.4375).abs() < 1e-6);
+        // At 1.5: 0.5 * 1 + 0.125 * 1.5 + 0.5 * 3 - 0.125 * 2 = 1.9375
+        assert!((position_at(&track, 1.5).x - 1.9375).abs() < 1e-6);
+        // Through the keyframes themselves
+        for (time, x) in [(0.0, 0.0), (1.0, 1.0), (2.0, 3.0)] {
+            assert!((position_at(&track, time).x - x).abs() < 1e-6);
+        }
+
+        // Uneven spacing, tangent (4 - 0) / 3 in the middle
+        let track = self::track(&[
+            (0.0, Vec3::ZERO),
+            (1.0, Vec3::new(0.0, 2.0, 0.0)),
+            (3.0, Vec3::new(0.0, 4.0, 0.0)),
+        ]);
+        // At 2 over the second segment of 2 s, t = 0.5, tangents 4 / 3 * 2 and 1 * 2
+        // 0.5 * 2 + 0.125 * 8 / 3 + 0.5 * 4 - 0.125 * 2 = 3.08333
+        assert!((position_at(&track, 2.0).y - 3.083_333).abs() < 1e-5);
+    }
+
+    #[test]
+    fn velocity_is_continuous_through_keyframes() {
+        let track = track(&[
+            (0.0, Vec3::ZERO),
+            (0.5, Vec3::new(2.0, 1.0, 0.0)),
+            (2.0, Vec3::new(3.0, -1.0, 4.0)),
+            (2.25, Vec3::new(5.0, 0.0, 4.0)),
+        ]);
+        // One-sided differences of second order, from each segment
+        let velocity = |time: f32, h: f32| {
+            let at = |offset: f32| position_at(&track, time + offset * h);
+            (at(0.0) * -3.0 + at(1.0) * 4.0 - at(2.0)) / (2.0 * h)
+        };
+        for time in [0.5, 2.0] {
+            let (before, after) = (velocity(time, -2e-3), velocity(time, 2e-3));
+            assert!(
+                before.distance(after) < 1e-2,
+                "{} {} {}",
+                time,
+                before,
+                after
+            );
+        }
+    }
+
+    #[test]
+    fn keyframes_at_the_same_time_are_a_cut() {
+        let mut track = track(&[(0.0, Vec3::ZERO), (1.0, Vec3::new(1.0, 0.0, 0.0))]);
+        track.insert(keyframe(1.0, Vec3::new(10.0, 0.0, 0.0)));
+        track.insert(keyframe(2.0, Vec3::new(11.0, 0.0, 0.0)));
+        assert_eq!(track.keyframes[2].position, [10.0, 0.0, 0.0]);
+
+        // Straight lines on both sides, the tangents don't reach over the cut
+        assert!((position_at(&track, 0.5).x - 0.5).abs() < 1e-6);
+        assert!((position_at(&track, 1.0).x - 10.0).abs() < 1e-6);
+        assert!((position_at(&track, 1.5).x - 10.5).abs() < 1e-6);
+        assert!((position_at(&track, 1.0 - 1e-4).x - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn ends_clamp_or_loop() {
+        let mut track = track(&[
+            (1.0, Vec3::ZERO),
+            (2.0, Vec3::new(1.0, 0.0, 0.0)),
+            (3.0, Vec3::new(1.0, 1.0, 0.0)),
+        ]);
+        assert_eq!(position_at(&track, 0.0), Vec3::ZERO);
+        assert_eq!(position_at(&track, 5.0), Vec3::new(1.0, 1.0, 0.0));
+
+        track.mode = PlaybackMode::Loop;
+        assert!((track.wrap_time(3.5) - 1.5).abs() < 1e-6);
+        assert!((track.wrap_time(0.5) - 2.5).abs() < 1e-6);
+        assert!(position_at(&track, 4.0).distance(Vec3::new(1.0, 0.0, 0.0)) < 1e-5);
+
+        // A single keyframe holds still either way
+        let track = self::track(&[(1.0, Vec3::ONE)]);
+        assert_eq!(position_at(&track, 7.0), Vec3::ONE);
+        assert!(CameraTrack::default().sample(0.0, |_| None).is_none());
+    }
+
+    #[test]
+    fn rotations_and_fov_blend_between_keyframes() {
+        let quarter_turn = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        let mut track = CameraTrack::default();
+        track.insert(CameraKeyframe::new(0.0, Vec3::ZERO, Quat::IDENTITY, 30.0));
+        track.insert(CameraKeyframe::new(2.0, Vec3::ZERO, quarter_turn, 60.0));
+        let sample = track.sample(1.0, |_| None).unwrap();
+        assert!(
+            sample
+                .rotation
+                .angle_between(Quat::from_rotation_y(std::f32::consts::FRAC_PI_4))
+                < 1e-4
+        );
+        assert!((sample.fov - 45.0).abs() < 1e-5);
+
+        // Turned towards the entity instead
+        track.keyframes[1].look_at = Some(3);
+        let target = Vec3::new(0.0, 0.0, 100.0);
+        let sample = track
+            .sample(2.0, |index| (index == 3).then_some(target))
+            .unwrap();
+        assert!((sample.rotation * Vec3::NEG_Z).distance(Vec3::Z) < 1e-4);
+        // Gone, the keyframe's own rotation is used
+        let sample = track.sample(2.0, |_| None).unwrap();
+        assert!(sample.rotation.angle_between(quarter_turn) < 1e-4);
+    }
+
+    #[test]
+    fn tracks_round_trip_through_json() {
+        let mut track = track(&[(0.0, Vec3::ZERO), (1.5, Vec3::new(1.0, 2.0, 3.0))]);
+        track.mode = PlaybackMode::Loop;
+        track.keyframes[1].look_at = Some(4);
+        let json = track.to_json().unwrap();
+        assert_eq!(CameraTrack::load(json.as_bytes()).unwrap(), track);
+
+        let error = CameraTrack::load(br#"{"keyframes": [{"time": 1, "position": [0, 0, 0], "rotation": [0, 0, 0], "fov": 40}, {"time": 0, "position": [0, 0, 0], "rotation": [0, 0, 0], "fov": 40}]}"#).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "keyframes[1].time: earlier than the keyframe before"
+        );
+    }
+
+    #[test]
+    fn playback_drives_and_restores_the_time_scale() {
+        let mut time = TimeController::default();
+        let mut player = CameraTrackPlayer::default();
+        assert!(player.play(1.0, &mut time).is_err());
+
+        let track = player.get_track_mut(&mut time);
+        *track = self::track(&[(0.0, Vec3::ZERO), (1.0, Vec3::new(1.0, 0.0, 0.0))]);
+        track.drive_time_scale = true;
+        player.play(0.5, &mut time).unwrap();
+        assert_eq!(time.get_base_scale(), 0.5);
+        player.update(1.0);
+        assert!(
+            player
+                .sample(|_| None)
+                .unwrap()
+                .position
+                .distance(Vec3::new(0.5, 0.0, 0.0))
+                < 1e-5
+        );
+        player.update(10.0);
+        assert_eq!(
+            player.sample(|_| None).unwrap().position,
+            Vec3::new(1.0, 0.0, 0.0)
+        );
+
+        player.stop(&mut time);
+        assert_eq!(time.get_base_scale(), 1.0);
+        assert!(player.sample(|_| None).is_none());
+    }
+}
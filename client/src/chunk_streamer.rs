@@ -0,0 +1,589 @@
+// Streams the chunks of a large map in and out around the camera. The chunks in the square
+// neighborhood of the camera's cell are loaded, their assets arrive through the asset loader
+// and their contents are put into the world a step at a time within a budget per frame.
+// A chunk is only unloaded once the camera is further away than it was loaded at, so moving
+// back and forth over a cell border doesn't load the same chunks over and over.
+//
+// Only the props, clutter and static bodies of a chunk belong to it. Everything that moves,
+// e.g. the characters, and whatever the level itself places stays loaded the whole time.
+
+use std::collections::HashMap;
+
+use glam::IVec2;
+use shared::{math::*, physics::PhysicsWorld};
+
+use crate::{
+    fetch::AssetFetcher,
+    game::{ChunkContents, Game},
+    level::{ChunkDesc, ChunkGridDesc},
+    loading::LevelLoader,
+    renderer::{Renderer, SpriteSpace, render_data::SpriteRenderJob, resource_scope::ScopeHandle},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkState {
+    Loading,       // Its assets are on their way
+    Instantiating, // Its contents are put into the world over the next frames
+    Loaded,
+    Failed, // An asset failed to load, it stays empty until it is unloaded
+}
+
+pub enum ChunkLoad {
+    Pending,
+    Ready,
+    Failed,
+}
+
+// What streaming does to the world, the game in the client and a fake in the tests
+pub trait ChunkHost {
+    type Chunk; // Kept from the start of the load until the unload
+
+    fn begin_load(&mut self, cell: IVec2, desc: &ChunkDesc) -> Self::Chunk;
+
+    // Spends the budget on the assets of the chunk
+    fn update_load(
+        &mut self,
+        chunk: &mut Self::Chunk,
+        budget: f64,
+        get_time: &dyn Fn() -> f64,
+    ) -> ChunkLoad;
+
+    // One part of the contents, returns true once all of them are in the world
+    fn instantiate_step(&mut self, chunk: &mut Self::Chunk, desc: &ChunkDesc) -> bool;
+
+    // Removes whatever was instantiated and releases the assets, in any state
+    fn unload(&mut self, chunk: Self::Chunk);
+}
+
+// How far apart two cells are, the neighborhoods are squares
+pub fn get_cell_distance(a: IVec2, b: IVec2) -> i32 {
+    (a - b).abs().max_element()
+}
+
+// The N×N cells around the center, N = 2 * radius + 1, nearest first
+pub fn get_neighborhood(center: IVec2, radius: i32) -> Vec<IVec2> {
+    let mut cells = Vec::new();
+    for y in -radius..=radius {
+        for x in -radius..=radius {
+            cells.push(center + IVec2::new(x, y));
+        }
+    }
+    cells.sort_by_key(|cell| (*cell - center).length_squared());
+    cells
+}
+
+// A loaded chunk stays loaded until the center is further away than the unload radius
+pub fn should_unload(cell: IVec2, center: IVec2, unload_radius: i32) -> bool {
+    get_cell_distance(cell, center) > unload_radius
+}
+
+struct StreamedChunk<T> {
+    state: ChunkState,
+    host: T,
+}
+
+pub struct ChunkStreamer<T> {
+    grid: ChunkGridDesc,
+    cells: HashMap<IVec2, usize>, // Into the cells of the grid
+    load_radius: i32,
+    unload_radius: i32,
+    budget: f64, // Seconds per frame for loading and instantiating
+    chunks: HashMap<IVec2, StreamedChunk<T>>,
+    center: Option<IVec2>,
+}
+
+impl<T> ChunkStreamer<T> {
+    pub fn new(grid: ChunkGridDesc, load_radius: i32, unload_radius: i32, budget: f64) -> Self {
+        let cells = grid
+            .cells
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| (IVec2::from(chunk.cell), index))
+            .collect();
+        Self {
+            grid,
+            cells,
+            load_radius,
+            unload_radius: unload_radius.max(load_radius),
+            budget,
+            chunks: HashMap::new(),
+            center: None,
+        }
+    }
+
+    // Loads what came into range and unloads what went out of it. The budget goes to the
+    // chunks nearest to the center first, at least one step is taken every frame.
+    pub fn update<H: ChunkHost<Chunk = T>>(
+        &mut self,
+        host: &mut H,
+        position: Vec2,
+        get_time: impl Fn() -> f64,
+    ) {
+        let start = get_time();
+        let center = self.grid.get_cell(position);
+        self.center = Some(center);
+
+        let far: Vec<IVec2> = self
+            .chunks
+            .keys()
+            .copied()
+            .filter(|cell| should_unload(*cell, center, self.unload_radius))
+            .collect();
+        for cell in far {
+            if let Some(chunk) = self.chunks.remove(&cell) {
+                host.unload(chunk.host);
+            }
+        }
+
+        for cell in get_neighborhood(center, self.load_radius) {
+            if self.chunks.contains_key(&cell) {
+                continue;
+            }
+            let Some(&index) = self.cells.get(&cell) else {
+                continue; // Outside of the map
+            };
+            let chunk = host.begin_load(cell, &self.grid.cells[index]);
+            self.chunks.insert(
+                cell,
+                StreamedChunk {
+                    state: ChunkState::Loading,
+                    host: chunk,
+                },
+            );
+        }
+
+        let mut pending: Vec<IVec2> = self
+            .chunks
+            .iter()
+            .filter(|(_, chunk)| {
+                matches!(chunk.state, ChunkState::Loading | ChunkState::Instantiating)
+            })
+            .map(|(cell, _)| *cell)
+            .collect();
+        pending.sort_by_key(|cell| (*cell - center).length_squared());
+
+        let mut stepped = false;
+        for cell in pending {
+            let elapsed = get_time() - start;
+            if stepped && elapsed >= self.budget {
+                break;
+            }
+            let desc = &self.grid.cells[self.cells[&cell]];
+            let chunk = self.chunks.get_mut(&cell).unwrap();
+
+            if chunk.state == ChunkState::Loading {
+                let budget = (self.budget - elapsed).max(0.0);
+                let load = host.update_load(&mut chunk.host, budget, &get_time);
+                stepped = true;
+                match load {
+                    ChunkLoad::Pending => continue,
+                    ChunkLoad::Ready => chunk.state = ChunkState::Instantiating,
+                    ChunkLoad::Failed => {
+                        chunk.state = ChunkState::Failed;
+                        continue;
+                    }
+                }
+            }
+
+            while chunk.state == ChunkState::Instantiating {
+                if stepped && get_time() - start >= self.budget {
+                    break;
+                }
+                stepped = true;
+                if host.instantiate_step(&mut chunk.host, desc) {
+                    chunk.state = ChunkState::Loaded;
+                }
+            }
+        }
+    }
+
+    // Unloads every chunk, e.g. before the world is replaced by a save
+    pub fn clear<H: ChunkHost<Chunk = T>>(&mut self, host: &mut H) {
+        for (_, chunk) in self.chunks.drain() {
+            host.unload(chunk.host);
+        }
+        self.center = None;
+    }
+
+    pub fn get_state(&self, cell: IVec2) -> Option<ChunkState> {
+        self.chunks.get(&cell).map(|chunk| chunk.state)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_loaded_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    // Waiting for their assets or still being put into the world
+    pub fn get_pending_count(&self) -> usize {
+        self.chunks
+            .values()
+            .filter(|chunk| matches!(chunk.state, ChunkState::Loading | ChunkState::Instantiating))
+            .count()
+    }
+
+    // Every cell of the map as a square in the bottom left corner, colored by its state,
+    // with the cell of the camera outlined
+    pub fn render_debug(&self, renderer: &mut Renderer) {
+        const MAP_SIZE: f32 = 160.0; // In pixels
+        const MARGIN: f32 = 16.0;
+
+        let Some(min) = self.cells.keys().copied().reduce(IVec2::min) else {
+            return;
+        };
+        let max = self.cells.keys().copied().fold(min, IVec2::max);
+        let extent = (max - min + IVec2::ONE).as_vec2();
+        let cell_size = (MAP_SIZE / extent.max_element()).min(16.0);
+        let screen_size = renderer.get_screen_size();
+        let origin = Vec2::new(MARGIN, screen_size.y - MARGIN - extent.y * cell_size);
+        // Rows go up the screen, like the z axis on the ground seen from above
+        let get_position = |cell: IVec2| {
+            origin + Vec2::new((cell.x - min.x) as f32, (max.y - cell.y) as f32) * cell_size
+        };
+        // Same batch, the later sprites are drawn on top
+        let mut submit = |position: Vec2, size: Vec2, color: Vec4| {
+            renderer.submit(&SpriteRenderJob {
+                space: SpriteSpace::Absolute,
+                ..SpriteRenderJob::solid(position, size, color, 0)
+            });
+        };
+
+        submit(
+            origin - Vec2::splat(2.0),
+            extent * cell_size + Vec2::splat(4.0),
+            Vec4::new(0.0, 0.0, 0.0, 0.5),
+        );
+        if let Some(center) = self.center {
+            submit(
+                get_position(center) - Vec2::ONE,
+                Vec2::splat(cell_size + 2.0),
+                Vec4::ONE,
+            );
+        }
+        for &cell in self.cells.keys() {
+            let color = match self.get_state(cell) {
+                None => Vec4::new(0.25, 0.25, 0.25, 0.8),
+                Some(ChunkState::Loading) => Vec4::new(0.95, 0.8, 0.2, 1.0),
+                Some(ChunkState::Instantiating) => Vec4::new(0.95, 0.5, 0.1, 1.0),
+                Some(ChunkState::Loaded) => Vec4::new(0.2, 0.8, 0.3, 1.0),
+                Some(ChunkState::Failed) => Vec4::new(0.9, 0.15, 0.1, 1.0),
+            };
+            submit(
+                get_position(cell) + Vec2::ONE,
+                Vec2::splat(cell_size - 2.0),
+                color,
+            );
+        }
+    }
+}
+
+const PROPS_PER_STEP: usize = 32;
+
+pub struct GameChunk {
+    cell: IVec2,
+    scope: ScopeHandle, // The chunk's assets and combined meshes
+    loader: LevelLoader,
+    files: HashMap<String, Vec<u8>>, // The density maps, once the loader is done
+    contents: ChunkContents,
+    step: usize,
+}
+
+// Streams into the game, the assets are fetched from the asset base
+pub struct GameChunkHost<'a> {
+    pub game: &'a mut Game,
+    pub renderer: &'a mut Renderer,
+    pub physics_world: &'a mut PhysicsWorld,
+    pub asset_base: &'a str,
+}
+
+impl ChunkHost for GameChunkHost<'_> {
+    type Chunk = GameChunk;
+
+    fn begin_load(&mut self, cell: IVec2, desc: &ChunkDesc) -> GameChunk {
+        let scope = self.renderer.create_scope(&get_chunk_name(cell));
+        let fetcher = AssetFetcher::new(self.asset_base);
+        let mut loader = LevelLoader::from_assets(&desc.assets, fetcher, scope);
+        loader.keep_density_maps(&desc.scatter);
+        GameChunk {
+            cell,
+            scope,
+            loader,
+            files: HashMap::new(),
+            contents: Default::default(),
+            step: 0,
+        }
+    }
+
+    fn update_load(
+        &mut self,
+        chunk: &mut GameChunk,
+        budget: f64,
+        get_time: &dyn Fn() -> f64,
+    ) -> ChunkLoad {
+        chunk.loader.update(self.renderer, budget, get_time);
+        if !chunk.loader.get_errors().is_empty() {
+            ChunkLoad::Failed
+        } else if chunk.loader.is_done() {
+            chunk.files = chunk.loader.take_kept_files();
+            ChunkLoad::Ready
+        } else {
+            ChunkLoad::Pending
+        }
+    }
+
+    // The props a batch at a time, then the bodies, the baking and a clutter layer a step
+    fn instantiate_step(&mut self, chunk: &mut GameChunk, desc: &ChunkDesc) -> bool {
+        let prop_steps = desc.props.len().div_ceil(PROPS_PER_STEP);
+        let name = get_chunk_name(chunk.cell);
+        let step = chunk.step;
+        chunk.step += 1;
+
+        // The combined meshes are released with the chunk's assets
+        self.renderer.set_current_scope(Some(chunk.scope));
+        if step < prop_steps {
+            let start = step * PROPS_PER_STEP;
+            let end = (start + PROPS_PER_STEP).min(desc.props.len());
+            self.game.spawn_chunk_props(
+                chunk.cell,
+                &desc.props[start..end],
+                self.physics_world,
+                &mut chunk.contents,
+            );
+        } else if step == prop_steps {
+            self.game.spawn_chunk_bodies(
+                chunk.cell,
+                &desc.bodies,
+                self.physics_world,
+                &mut chunk.contents,
+            );
+        } else if step == prop_steps + 1 {
+            let name = format!("{}/Baked", name);
+            let stats = self
+                .game
+                .bake_chunk(self.renderer, chunk.cell, &name, &chunk.contents);
+            log::debug!(
+                "Baked {} props of {} into {} meshes",
+                stats.instances_before,
+                name,
+                stats.instances_after
+            );
+        } else if let Some(scatter) = desc.scatter.get(step - prop_steps - 2) {
+            self.game.build_chunk_scatter(
+                self.renderer,
+                &format!("{}/Scatter/{}", name, scatter.name),
+                scatter,
+                &chunk.files,
+                &mut chunk.contents,
+            );
+        }
+        self.renderer.set_current_scope(None);
+
+        chunk.step >= prop_steps + 2 + desc.scatter.len()
+    }
+
+    fn unload(&mut self, chunk: GameChunk) {
+        self.game
+            .remove_chunk(chunk.contents, self.renderer, self.physics_world);
+        self.renderer.unload_scope(chunk.scope);
+    }
+}
+
+fn get_chunk_name(cell: IVec2) -> String {
+    format!("Chunk {},{}", cell.x, cell.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, collections::HashSet, rc::Rc};
+
+    use super::*;
+
+    const CELL_SIZE: f32 = 1000.0;
+
+    fn build_grid(size: i32) -> ChunkGridDesc {
+        let mut cells = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                cells.push(ChunkDesc {
+                    cell: [x, y],
+                    ..Default::default()
+                });
+            }
+        }
+        ChunkGridDesc {
+            cell_size: CELL_SIZE,
+            origin: [0.0, 0.0],
+            cells,
+        }
+    }
+
+    #[test]
+    fn neighborhoods_are_squares_nearest_first() {
+        let grid = build_grid(1);
+        assert_eq!(grid.get_cell(Vec2::new(999.0, 0.0)), IVec2::ZERO);
+        assert_eq!(grid.get_cell(Vec2::new(-1.0, 1000.0)), IVec2::new(-1, 1));
+
+        let center = IVec2::new(4, 7);
+        let cells = get_neighborhood(center, 2);
+        assert_eq!(cells.len(), 25);
+        assert_eq!(cells[0], center);
+        assert!(
+            cells
+                .iter()
+                .all(|cell| get_cell_distance(*cell, center) <= 2)
+        );
+        assert_eq!(cells.iter().collect::<HashSet<_>>().len(), 25);
+        assert_eq!(get_cell_distance(cells[24], center), 2);
+    }
+
+    #[test]
+    fn chunks_unload_past_the_hysteresis() {
+        let center = IVec2::new(5, 5);
+        // Loaded within one cell, kept within two
+        assert!(!should_unload(IVec2::new(6, 4), center, 2));
+        assert!(!should_unload(IVec2::new(7, 3), center, 2));
+        assert!(should_unload(IVec2::new(8, 5), center, 2));
+        assert!(should_unload(IVec2::new(5, 2), center, 2));
+
+        // Moving back and forth over a border loads the far row once and keeps it
+        let mut streamer = ChunkStreamer::new(build_grid(10), 1, 2, 1.0);
+        let mut host = FakeHost::new(Rc::new(Cell::new(0.0)));
+        for x in [4.9, 5.1, 4.9, 5.1, 4.9] {
+            streamer.update(&mut host, Vec2::new(x, 5.5) * CELL_SIZE, || 0.0);
+        }
+        assert_eq!(host.loads, 12);
+        assert_eq!(host.unloads, 0);
+
+        streamer.update(&mut host, Vec2::new(7.5, 5.5) * CELL_SIZE, || 0.0);
+        assert_eq!(streamer.get_state(IVec2::new(4, 5)), None);
+        assert_eq!(
+            streamer.get_state(IVec2::new(5, 5)),
+            Some(ChunkState::Loaded)
+        );
+        assert_eq!(host.unloads, 6);
+    }
+
+    #[test]
+    fn panning_over_the_map_stays_within_the_frame_budget() {
+        const BUDGET: f64 = 0.004;
+        const THRESHOLD: f64 = 0.008; // Half a frame at 60 Hz
+        const FRAMES: usize = 600;
+
+        let clock = Rc::new(Cell::new(0.0));
+        let mut streamer = ChunkStreamer::new(build_grid(10), 1, 2, BUDGET);
+        let mut host = FakeHost::new(clock.clone());
+
+        // Diagonally from one corner of the 10×10 map to the other in ten seconds
+        let mut worst: f64 = 0.0;
+        for frame in 0..=FRAMES {
+            let position = Vec2::splat(0.5 + 9.0 * frame as f32 / FRAMES as f32) * CELL_SIZE;
+            let start = clock.get();
+            streamer.update(&mut host, position, || clock.get());
+            worst = worst.max(clock.get() - start);
+
+            assert!(host.live.len() <= 25, "{} chunks loaded", host.live.len());
+        }
+        assert!(worst <= THRESHOLD, "a frame took {} ms", worst * 1000.0);
+
+        // Once the camera rests everything around it is loaded
+        for _ in 0..120 {
+            streamer.update(&mut host, Vec2::splat(9.5) * CELL_SIZE, || clock.get());
+        }
+        for cell in get_neighborhood(IVec2::splat(9), 1) {
+            let expected = (cell.max_element() < 10).then_some(ChunkState::Loaded);
+            assert_eq!(streamer.get_state(cell), expected, "{}", cell);
+        }
+        assert!(host.unloads > 0);
+
+        streamer.clear(&mut host);
+        assert!(host.live.is_empty());
+        assert_eq!(host.loads, host.unloads);
+    }
+
+    // Assets take a few frames to arrive and a few steps to upload, every step costs time
+    struct FakeHost {
+        clock: Rc<Cell<f64>>,
+        live: HashSet<IVec2>,
+        loads: usize,
+        unloads: usize,
+    }
+
+    struct FakeChunk {
+        cell: IVec2,
+        polls: usize,
+        uploads: usize,
+        steps: usize,
+    }
+
+    const FETCH_POLLS: usize = 3;
+    const UPLOAD_STEPS: usize = 4;
+    const INSTANTIATE_STEPS: usize = 6;
+    const STEP_TIME: f64 = 0.0015;
+    const UNLOAD_TIME: f64 = 0.0002;
+
+    impl FakeHost {
+        fn new(clock: Rc<Cell<f64>>) -> Self {
+            Self {
+                clock,
+                live: HashSet::new(),
+                loads: 0,
+                unloads: 0,
+            }
+        }
+
+        fn spend(&self, time: f64) {
+            self.clock.set(self.clock.get() + time);
+        }
+    }
+
+    impl ChunkHost for FakeHost {
+        type Chunk = FakeChunk;
+
+        fn begin_load(&mut self, cell: IVec2, _desc: &ChunkDesc) -> FakeChunk {
+            assert!(self.live.insert(cell), "{} loaded twice", cell);
+            self.loads += 1;
+            FakeChunk {
+                cell,
+                polls: 0,
+                uploads: 0,
+                steps: 0,
+            }
+        }
+
+        // Like the load queue, uploads while the budget lasts and at least once
+        fn update_load(
+            &mut self,
+            chunk: &mut FakeChunk,
+            budget: f64,
+            get_time: &dyn Fn() -> f64,
+        ) -> ChunkLoad {
+            chunk.polls += 1;
+            if chunk.polls < FETCH_POLLS {
+                return ChunkLoad::Pending;
+            }
+            let start = get_time();
+            let mut uploaded = false;
+            while chunk.uploads < UPLOAD_STEPS && (!uploaded || get_time() - start < budget) {
+                self.spend(STEP_TIME);
+                chunk.uploads += 1;
+                uploaded = true;
+            }
+            match chunk.uploads {
+                UPLOAD_STEPS => ChunkLoad::Ready,
+                _ => ChunkLoad::Pending,
+            }
+        }
+
+        fn instantiate_step(&mut self, chunk: &mut FakeChunk, _desc: &ChunkDesc) -> bool {
+            self.spend(STEP_TIME);
+            chunk.steps += 1;
+            chunk.steps == INSTANTIATE_STEPS
+        }
+
+        fn unload(&mut self, chunk: FakeChunk) {
+            self.spend(UNLOAD_TIME);
+            assert!(self.live.remove(&chunk.cell));
+            self.unloads += 1;
+        }
+    }
+}
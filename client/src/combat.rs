@@ -0,0 +1,862 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use shared::{
+    physics::{BodyId, CollisionMatrix, CollisionShape, PhysicsWorld},
+    profile_scope,
+    team::Team,
+};
+
+use crate::{
+    animation_events::{AnimationEvent, AnimationEventTrack, HIT_EVENT},
+    components::{Entities, Entity, Storage, join, join3},
+    events::{GameEvent, GameEvents},
+    game::{CPhysicsProxy, CPlayerMovement, CTargetLocation},
+    status_effects::{StatusEffectDesc, StatusEffects},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CHealth {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl CHealth {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+}
+
+// An attack plays the attack clip, the time is where in it. The hit lands on the hit event
+// of the clip, the wind-up is before it and the recovery after it until the clip is over.
+// Movement is locked for the whole attack. A move order during the wind-up cancels the
+// attack, during the recovery it is kept and carried out once the recovery is over.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AttackPhase {
+    Ready,
+    WindUp { time: f32 },
+    Recovery { time: f32 },
+}
+
+impl AttackPhase {
+    pub fn get_clip_time(&self) -> Option<f32> {
+        match *self {
+            AttackPhase::Ready => None,
+            AttackPhase::WindUp { time } | AttackPhase::Recovery { time } => Some(time),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CCombat {
+    #[serde(skip)]
+    pub target: Option<Entity>, // Saved separately, entities are renumbered by saving
+    pub attack_cooldown: f32, // Until the next attack can start
+    pub damage: f32,
+    pub range: f32, // From the attacker center, reaching the target shape is enough
+    pub attack: AnimationEventTrack, // Of the attack clip
+    pub cooldown: f32, // Restarted by every hit
+    pub phase: AttackPhase,
+    attack_requested: bool,
+}
+
+impl Default for CCombat {
+    fn default() -> Self {
+        Self {
+            target: None,
+            attack_cooldown: 0.0,
+            damage: 20.0,
+            range: 150.0,
+            attack: AnimationEventTrack::new(
+                vec![AnimationEvent {
+                    name: HIT_EVENT.to_string(),
+                    time: 0.3,
+                }],
+                0.7,
+            ),
+            cooldown: 1.0,
+            phase: AttackPhase::Ready,
+            attack_requested: false,
+        }
+    }
+}
+
+impl CCombat {
+    // Picked up by the next combat update, dropped there when the attack can't start
+    pub fn request_attack(&mut self, target: Entity) {
+        self.target = Some(target);
+        self.attack_requested = true;
+    }
+
+    pub fn is_attacking(&self) -> bool {
+        self.phase != AttackPhase::Ready
+    }
+}
+
+// Flies on its body until the body touches something or its lifetime runs out, then it is
+// spent
+#[derive(Debug, Clone, PartialEq)]
+pub struct CProjectile {
+    pub source: Option<Entity>, // Never hit by its own projectile
+    pub damage: f32,
+    pub lifetime: f32, // Seconds left
+}
+
+// The status effects projectiles apply on a hit. A projectile's body carries the effect as its
+// user data, the index after the first so bodies without user data apply nothing.
+#[derive(Debug, Default)]
+pub struct ProjectileEffects {
+    descs: Vec<StatusEffectDesc>, // Only grows, the bodies of flying projectiles point in
+}
+
+impl ProjectileEffects {
+    // The user data for the body of a projectile applying the effect
+    pub fn get_user_data(&mut self, effect: Option<StatusEffectDesc>) -> u64 {
+        let Some(effect) = effect else {
+            return 0;
+        };
+        let index = match self.descs.iter().position(|desc| *desc == effect) {
+            Some(index) => index,
+            None => {
+                self.descs.push(effect);
+                self.descs.len() - 1
+            }
+        };
+        index as u64 + 1
+    }
+
+    pub fn get(&self, user_data: u64) -> Option<StatusEffectDesc> {
+        let index = usize::try_from(user_data.checked_sub(1)?).ok()?;
+        self.descs.get(index).copied()
+    }
+}
+
+// Teammates are only hurt with friendly fire, entities without a team by everyone
+pub fn can_damage(
+    teams: &Storage<Team>,
+    source: Entity,
+    target: Entity,
+    friendly_fire: bool,
+) -> bool {
+    match (teams.get(source), teams.get(target)) {
+        (Some(source), Some(target)) => friendly_fire || source != target,
+        _ => true,
+    }
+}
+
+// Projectiles pass through their own team unless friendly fire is on
+pub fn set_friendly_fire_collisions(matrix: &mut CollisionMatrix, friendly_fire: bool) {
+    for team in Team::ALL {
+        matrix.set_collides(
+            team.get_unit_layer(),
+            team.get_projectile_layer(),
+            friendly_fire,
+        );
+    }
+}
+
+// Runs after the physics step so range checks see this tick's positions, hits are pushed
+// as damage events
+#[allow(clippy::too_many_arguments)]
+pub fn update_combat(
+    dt: f32,
+    entities: &Entities,
+    combats: &mut Storage<CCombat>,
+    healths: &mut Storage<CHealth>,
+    status_effects: &Storage<StatusEffects>,
+    teams: &Storage<Team>,
+    physics_proxies: &Storage<CPhysicsProxy>,
+    movements: &mut Storage<CPlayerMovement>,
+    move_targets: &mut Storage<CTargetLocation>,
+    physics_world: &PhysicsWorld,
+    friendly_fire: bool,
+    events: &mut GameEvents,
+) {
+    profile_scope!("Combat");
+    for (attacker, combat, physics_proxy) in join3(entities, combats, physics_proxies) {
+        combat.attack_cooldown = (combat.attack_cooldown - dt).max(0.0);
+        let move_target = move_targets.get_mut(attacker);
+
+        let target_body = combat
+            .target
+            .filter(|&target| healths.get(target).is_some_and(|health| !health.is_dead()))
+            .filter(|&target| can_damage(teams, attacker, target, friendly_fire))
+            .and_then(|target| physics_proxies.get(target))
+            .and_then(|proxy| proxy.body_id);
+        let in_range = |combat: &CCombat| match (physics_proxy.body_id, target_body) {
+            (Some(body_id), Some(target_body)) => {
+                is_in_range(physics_world, body_id, combat.range, target_body)
+            }
+            _ => false,
+        };
+
+        match combat.phase {
+            AttackPhase::Ready => {
+                if combat.attack_requested && combat.attack_cooldown <= 0.0 && in_range(combat) {
+                    combat.phase = AttackPhase::WindUp { time: 0.0 };
+                    // Stop walking, a later move order cancels the wind-up
+                    if let Some(move_target) = move_target {
+                        *move_target = None;
+                    }
+                }
+                combat.attack_requested = false;
+            }
+            AttackPhase::WindUp { time } => {
+                let next_time = time + dt;
+                let hit = combat
+                    .attack
+                    .get_passed(time, next_time)
+                    .any(|name| name == HIT_EVENT);
+                if move_target.is_some_and(|target| target.is_some()) {
+                    combat.phase = AttackPhase::Ready;
+                } else if !hit {
+                    // A clip without a hit event is played out without damage
+                    combat.phase = if next_time < combat.attack.get_duration() {
+                        AttackPhase::WindUp { time: next_time }
+                    } else {
+                        AttackPhase::Ready
+                    };
+                } else {
+                    // The target may have walked away or died during the wind-up, that's a miss
+                    if let Some(target) = combat.target
+                        && in_range(combat)
+                        && let Some(health) = healths.get_mut(target)
+                        && !health.is_dead()
+                    {
+                        let multiplier = status_effects
+                            .get(target)
+                            .map_or(1.0, StatusEffects::incoming_damage_multiplier);
+                        let damage = combat.damage * multiplier;
+                        health.current = (health.current - damage).max(0.0);
+                        events.push_damage(Some(attacker), target, damage, health.is_dead());
+                    }
+
+                    combat.attack_cooldown = combat.cooldown;
+                    combat.phase = AttackPhase::Recovery { time: next_time };
+                }
+            }
+            AttackPhase::Recovery { time } => {
+                let time = time + dt;
+                combat.phase = if time < combat.attack.get_duration() {
+                    AttackPhase::Recovery { time }
+                } else {
+                    AttackPhase::Ready
+                };
+            }
+        }
+
+        if let Some(movement) = movements.get_mut(attacker) {
+            movement.locked = combat.is_attacking();
+        }
+    }
+}
+
+// Also after the physics step, a projectile hits the first body it touched in it and applies
+// the effect its body carries. Bodies without health like walls only stop it. Returns the
+// spent projectiles to despawn.
+#[allow(clippy::too_many_arguments)]
+pub fn update_projectiles(
+    entities: &Entities,
+    projectiles: &Storage<CProjectile>,
+    healths: &mut Storage<CHealth>,
+    status_effects: &mut Storage<StatusEffects>,
+    teams: &Storage<Team>,
+    physics_proxies: &Storage<CPhysicsProxy>,
+    physics_world: &PhysicsWorld,
+    projectile_effects: &ProjectileEffects,
+    friendly_fire: bool,
+    events: &mut GameEvents,
+) -> Vec<Entity> {
+    if join(entities, projectiles).next().is_none() {
+        return Vec::new();
+    }
+    let body_entities: HashMap<BodyId, Entity> = join(entities, physics_proxies)
+        .filter_map(|(entity, proxy)| Some((proxy.body_id?, entity)))
+        .collect();
+
+    let mut spent = Vec::new();
+    for (projectile_entity, projectile, proxy) in join3(entities, projectiles, physics_proxies) {
+        let Some(contacts) = proxy
+            .body_id
+            .and_then(|body_id| physics_world.get_contacts(body_id))
+        else {
+            continue;
+        };
+
+        for contact in contacts {
+            let other = body_entities.get(&contact.other).copied();
+            if other.is_some() && other == projectile.source {
+                continue;
+            }
+            let blocking = physics_world
+                .get_layer(contact.other)
+                .is_some_and(|layer| layer.is_blocking());
+
+            if let Some(target) = other
+                && can_damage(teams, projectile_entity, target, friendly_fire)
+                && let Some(health) = healths.get_mut(target)
+                && !health.is_dead()
+            {
+                let multiplier = status_effects
+                    .get(target)
+                    .map_or(1.0, StatusEffects::incoming_damage_multiplier);
+                let damage = projectile.damage * multiplier;
+                health.current = (health.current - damage).max(0.0);
+                events.push(GameEvent::ProjectileHit {
+                    projectile: projectile_entity,
+                    target,
+                });
+                events.push_damage(projectile.source, target, damage, health.is_dead());
+                // After the damage, the hit isn't changed by the effect it applies
+                if let Some(effect) = proxy
+                    .body_id
+                    .and_then(|body_id| physics_world.get_user_data(body_id))
+                    .and_then(|user_data| projectile_effects.get(user_data))
+                    && let Some(effects) = status_effects.get_mut(target)
+                {
+                    effects.apply(effect);
+                    events.push(GameEvent::EffectApplied {
+                        target,
+                        kind: effect.kind,
+                    });
+                }
+                spent.push(projectile_entity);
+                break;
+            } else if blocking {
+                spent.push(projectile_entity);
+                break;
+            }
+        }
+    }
+    spent
+}
+
+// Projectiles that flew their lifetime without hitting anything are spent too
+pub fn expire_projectiles(
+    dt: f32,
+    entities: &Entities,
+    projectiles: &mut Storage<CProjectile>,
+) -> Vec<Entity> {
+    let mut expired = Vec::new();
+    for (entity, projectile) in join(entities, projectiles) {
+        projectile.lifetime -= dt;
+        if projectile.lifetime <= 0.0 {
+            expired.push(entity);
+        }
+    }
+    expired
+}
+
+pub fn is_in_range(
+    physics_world: &PhysicsWorld,
+    body_id: BodyId,
+    range: f32,
+    target: BodyId,
+) -> bool {
+    let Some(state) = physics_world.get_state(body_id) else {
+        return false;
+    };
+
+    let shape = CollisionShape::Circle { radius: range };
+    !physics_world
+        .query_shape_filtered(state.position, shape, |id, _| id == target)
+        .is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::{math::*, physics::BodySettings};
+
+    use super::*;
+    use crate::{
+        events::GameEvent,
+        status_effects::{StackingPolicy, StatusKind},
+    };
+
+    const DT: f32 = 0.1;
+
+    // A player and a dummy enemy, stepped like Game::fixed_update without a renderer
+    struct Simulation {
+        physics_world: PhysicsWorld,
+        entities: Entities,
+        combats: Storage<CCombat>,
+        healths: Storage<CHealth>,
+        status_effects: Storage<StatusEffects>,
+        teams: Storage<Team>,
+        projectiles: Storage<CProjectile>,
+        projectile_effects: ProjectileEffects,
+        physics_proxies: Storage<CPhysicsProxy>,
+        movements: Storage<CPlayerMovement>,
+        move_targets: Storage<CTargetLocation>,
+        friendly_fire: bool,
+        player: Entity,
+        enemy: Entity,
+        events: GameEvents,
+    }
+
+    impl Simulation {
+        fn new(enemy_x: f32) -> Self {
+            let mut physics_world = PhysicsWorld::new();
+            let mut entities = Entities::default();
+            let mut physics_proxies = Storage::default();
+            let mut healths = Storage::default();
+            let mut teams = Storage::default();
+
+            let mut spawn = |x: f32, team: Team| {
+                let entity = entities.spawn();
+                let body_id = physics_world.create_rigid_body(&BodySettings {
+                    position: Vec2::new(x, 0.0),
+                    velocity: Vec2::ZERO,
+                    layer: team.get_unit_layer(),
+                    shape: &CollisionShape::Circle { radius: 32.0 },
+                    listen_to_contact_events: false,
+                });
+                physics_proxies.insert(entity, CPhysicsProxy::new(body_id, &physics_world));
+                healths.insert(entity, CHealth::new(100.0));
+                teams.insert(entity, team);
+                entity
+            };
+            let player = spawn(0.0, Team::Blue);
+            let enemy = spawn(enemy_x, Team::Red);
+
+            // Off the tick boundaries so float sums don't decide the phase changes
+            let mut combats = Storage::default();
+            combats.insert(
+                player,
+                CCombat {
+                    attack: AnimationEventTrack::new(
+                        vec![AnimationEvent {
+                            name: HIT_EVENT.to_string(),
+                            time: 0.25,
+                        }],
+                        0.55,
+                    ),
+                    cooldown: 0.95,
+                    ..Default::default()
+                },
+            );
+            let mut movements = Storage::default();
+            movements.insert(player, Default::default());
+            let mut move_targets = Storage::default();
+            move_targets.insert(player, None);
+
+            physics_world.step_simulation(0.0);
+            Self {
+                physics_world,
+                entities,
+                combats,
+                healths,
+                status_effects: Default::default(),
+                teams,
+                projectiles: Default::default(),
+                projectile_effects: Default::default(),
+                physics_proxies,
+                movements,
+                move_targets,
+                friendly_fire: false,
+                player,
+                enemy,
+                events: Default::default(),
+            }
+        }
+
+        fn tick(&mut self) {
+            self.physics_world.step_simulation(DT);
+            update_combat(
+                DT,
+                &self.entities,
+                &mut self.combats,
+                &mut self.healths,
+                &self.status_effects,
+                &self.teams,
+                &self.physics_proxies,
+                &mut self.movements,
+                &mut self.move_targets,
+                &self.physics_world,
+                self.friendly_fire,
+                &mut self.events,
+            );
+            let mut spent = update_projectiles(
+                &self.entities,
+                &self.projectiles,
+                &mut self.healths,
+                &mut self.status_effects,
+                &self.teams,
+                &self.physics_proxies,
+                &self.physics_world,
+                &self.projectile_effects,
+                self.friendly_fire,
+                &mut self.events,
+            );
+            for projectile in expire_projectiles(DT, &self.entities, &mut self.projectiles) {
+                if !spent.contains(&projectile) {
+                    spent.push(projectile);
+                }
+            }
+            for projectile in spent {
+                let body_id = self.physics_proxies.get(projectile).unwrap().body_id;
+                self.physics_world.remove_body(body_id.unwrap());
+                self.physics_proxies.remove(projectile);
+                self.projectiles.remove(projectile);
+                self.entities.despawn(projectile);
+            }
+        }
+
+        // Flying along x from the left, slow enough not to skip over a unit in a tick. Off the
+        // unit positions, a circle right on top of another has no direction to be pushed out.
+        fn fire(&mut self, source: Entity, team: Team, x: f32) -> Entity {
+            let entity = self.entities.spawn();
+            let body_id = self.physics_world.create_rigid_body(&BodySettings {
+                position: Vec2::new(x, 0.0),
+                velocity: Vec2::new(500.0, 0.0),
+                layer: team.get_projectile_layer(),
+                shape: &CollisionShape::Circle { radius: 4.0 },
+                listen_to_contact_events: true,
+            });
+            self.physics_proxies
+                .insert(entity, CPhysicsProxy::new(body_id, &self.physics_world));
+            self.teams.insert(entity, team);
+            self.projectiles.insert(
+                entity,
+                CProjectile {
+                    source: Some(source),
+                    damage: 25.0,
+                    lifetime: 10.0,
+                },
+            );
+            entity
+        }
+
+        fn set_friendly_fire(&mut self, friendly_fire: bool) {
+            let mut matrix = self.physics_world.get_collision_matrix();
+            set_friendly_fire_collisions(&mut matrix, friendly_fire);
+            self.physics_world.set_collision_matrix(matrix);
+            self.friendly_fire = friendly_fire;
+        }
+
+        fn get_player_health(&self) -> f32 {
+            self.healths.get(self.player).unwrap().current
+        }
+
+        fn attack(&mut self) {
+            let enemy = self.enemy;
+            self.combats
+                .get_mut(self.player)
+                .unwrap()
+                .request_attack(enemy);
+        }
+
+        fn order_move(&mut self) {
+            *self.move_targets.get_mut(self.player).unwrap() = Some(Vec3::new(500.0, 0.0, 0.0));
+        }
+
+        fn get_phase(&self) -> AttackPhase {
+            self.combats.get(self.player).unwrap().phase
+        }
+
+        fn get_enemy_health(&self) -> f32 {
+            self.healths.get(self.enemy).unwrap().current
+        }
+
+        fn is_locked(&self) -> bool {
+            self.movements.get(self.player).unwrap().locked
+        }
+    }
+
+    #[test]
+    fn damage_lands_on_the_hit_event() {
+        let mut sim = Simulation::new(100.0);
+        sim.attack();
+
+        sim.tick();
+        assert!(matches!(sim.get_phase(), AttackPhase::WindUp { .. }));
+        assert!(sim.is_locked());
+        sim.tick();
+        sim.tick();
+        assert_eq!(sim.get_enemy_health(), 100.0);
+
+        sim.tick();
+        assert_eq!(sim.get_enemy_health(), 80.0);
+        assert_eq!(sim.events.drain().len(), 1);
+        assert!(matches!(sim.get_phase(), AttackPhase::Recovery { .. }));
+
+        for _ in 0..3 {
+            sim.tick();
+        }
+        assert_eq!(sim.get_phase(), AttackPhase::Ready);
+        assert!(!sim.is_locked());
+    }
+
+    #[test]
+    fn clips_without_a_hit_event_never_damage() {
+        let mut sim = Simulation::new(100.0);
+        sim.combats.get_mut(sim.player).unwrap().attack =
+            AnimationEventTrack::new(Vec::new(), 0.55);
+        sim.attack();
+        for _ in 0..6 {
+            sim.tick();
+            assert!(matches!(sim.get_phase(), AttackPhase::WindUp { .. }));
+        }
+
+        sim.tick();
+        assert_eq!(sim.get_phase(), AttackPhase::Ready);
+        assert_eq!(sim.get_enemy_health(), 100.0);
+        assert!(sim.events.drain().is_empty());
+    }
+
+    #[test]
+    fn targets_out_of_range_are_not_attacked() {
+        let mut sim = Simulation::new(400.0);
+        sim.attack();
+        sim.tick();
+        assert_eq!(sim.get_phase(), AttackPhase::Ready);
+
+        // The request doesn't linger until the target comes into range
+        let enemy_body = sim.physics_proxies.get(sim.enemy).unwrap().body_id.unwrap();
+        sim.physics_world
+            .set_position(enemy_body, Vec2::new(100.0, 0.0));
+        sim.tick();
+        assert_eq!(sim.get_phase(), AttackPhase::Ready);
+    }
+
+    #[test]
+    fn moving_cancels_the_wind_up() {
+        let mut sim = Simulation::new(100.0);
+        sim.attack();
+        sim.tick();
+        sim.order_move();
+        sim.tick();
+
+        assert_eq!(sim.get_phase(), AttackPhase::Ready);
+        assert!(!sim.is_locked());
+        for _ in 0..5 {
+            sim.tick();
+        }
+        assert_eq!(sim.get_enemy_health(), 100.0);
+    }
+
+    #[test]
+    fn moving_does_not_cancel_the_recovery() {
+        let mut sim = Simulation::new(100.0);
+        sim.attack();
+        for _ in 0..4 {
+            sim.tick();
+        }
+        assert!(matches!(sim.get_phase(), AttackPhase::Recovery { .. }));
+
+        sim.order_move();
+        sim.tick();
+        assert!(matches!(sim.get_phase(), AttackPhase::Recovery { .. }));
+        assert!(sim.is_locked());
+
+        // The move order survives the recovery
+        for _ in 0..2 {
+            sim.tick();
+        }
+        assert_eq!(sim.get_phase(), AttackPhase::Ready);
+        assert!(sim.move_targets.get(sim.player).unwrap().is_some());
+    }
+
+    #[test]
+    fn shields_reduce_the_hit() {
+        let mut sim = Simulation::new(100.0);
+        let mut effects = StatusEffects::default();
+        effects.apply(StatusEffectDesc {
+            kind: StatusKind::Shield,
+            magnitude: 0.5,
+            duration: 5.0,
+            stacking: StackingPolicy::Refresh,
+        });
+        sim.status_effects.insert(sim.enemy, effects);
+
+        sim.attack();
+        for _ in 0..4 {
+            sim.tick();
+        }
+        assert_eq!(sim.get_enemy_health(), 90.0);
+    }
+
+    #[test]
+    fn a_lethal_hit_kills_once() {
+        let mut sim = Simulation::new(100.0);
+        sim.combats.get_mut(sim.player).unwrap().damage = 150.0;
+
+        // The second attack is dropped, the target is dead
+        for _ in 0..2 {
+            sim.attack();
+            for _ in 0..12 {
+                sim.tick();
+            }
+        }
+
+        let events = sim.events.drain();
+        let deaths: Vec<&GameEvent> = events
+            .iter()
+            .filter(|event| matches!(event, GameEvent::EntityDied { .. }))
+            .collect();
+        assert_eq!(
+            deaths,
+            vec![&GameEvent::EntityDied {
+                entity: sim.enemy,
+                killer: Some(sim.player),
+            }]
+        );
+        assert_eq!(events.len(), 2);
+        assert_eq!(sim.get_enemy_health(), 0.0);
+    }
+
+    #[test]
+    fn cooldown_blocks_the_next_attack() {
+        let mut sim = Simulation::new(100.0);
+        sim.attack();
+        for _ in 0..8 {
+            sim.tick();
+        }
+        assert_eq!(sim.get_phase(), AttackPhase::Ready);
+
+        // 0.5s after the hit
+        sim.attack();
+        sim.tick();
+        assert_eq!(sim.get_phase(), AttackPhase::Ready);
+
+        for _ in 0..4 {
+            sim.tick();
+        }
+        sim.attack();
+        sim.tick();
+        assert!(matches!(sim.get_phase(), AttackPhase::WindUp { .. }));
+    }
+
+    #[test]
+    fn same_team_projectiles_pass_through_and_opposite_ones_hit() {
+        // A blue projectile from behind the blue player, the red enemy further along
+        let mut sim = Simulation::new(400.0);
+        let shooter = sim.entities.spawn();
+        let projectile = sim.fire(shooter, Team::Blue, -190.0);
+        for _ in 0..8 {
+            sim.tick();
+        }
+        assert_eq!(sim.get_player_health(), 100.0);
+        assert!(sim.projectiles.get(projectile).is_some());
+
+        for _ in 0..8 {
+            sim.tick();
+        }
+        assert_eq!(sim.get_enemy_health(), 75.0);
+        assert!(sim.projectiles.get(projectile).is_none());
+        let events = sim.events.drain();
+        assert_eq!(
+            events[0],
+            GameEvent::ProjectileHit {
+                projectile,
+                target: sim.enemy,
+            }
+        );
+        assert!(matches!(
+            events[1],
+            GameEvent::DamageDealt { source: Some(source), amount: 25.0, .. } if source == shooter
+        ));
+
+        // A red one stops at the player
+        let projectile = sim.fire(sim.enemy, Team::Red, -190.0);
+        for _ in 0..8 {
+            sim.tick();
+        }
+        assert_eq!(sim.get_player_health(), 75.0);
+        assert!(sim.projectiles.get(projectile).is_none());
+    }
+
+    #[test]
+    fn friendly_fire_lets_teammates_hurt_each_other() {
+        let mut sim = Simulation::new(100.0);
+        sim.teams.insert(sim.enemy, Team::Blue);
+
+        // Neither the attack nor the projectile hurt a teammate
+        sim.attack();
+        let shooter = sim.entities.spawn();
+        sim.fire(shooter, Team::Blue, -190.0);
+        for _ in 0..6 {
+            sim.tick();
+        }
+        assert_eq!(sim.get_enemy_health(), 100.0);
+        assert_eq!(sim.get_player_health(), 100.0);
+
+        sim.set_friendly_fire(true);
+        sim.attack();
+        let projectile = sim.fire(shooter, Team::Blue, -190.0);
+        for _ in 0..6 {
+            sim.tick();
+        }
+        assert_eq!(sim.get_enemy_health(), 80.0);
+        assert_eq!(sim.get_player_health(), 75.0);
+        assert!(sim.projectiles.get(projectile).is_none());
+
+        // The shooter is never hit by its own projectile
+        let projectile = sim.fire(sim.player, Team::Blue, -20.0);
+        sim.tick();
+        assert_eq!(sim.get_player_health(), 75.0);
+        assert!(sim.projectiles.get(projectile).is_some());
+    }
+
+    #[test]
+    fn projectiles_that_hit_nothing_expire() {
+        // Past the enemy, flying away from everything
+        let mut sim = Simulation::new(400.0);
+        let shooter = sim.entities.spawn();
+        let projectile = sim.fire(shooter, Team::Blue, 1000.0);
+        sim.projectiles.get_mut(projectile).unwrap().lifetime = 3.5 * DT;
+        for _ in 0..3 {
+            sim.tick();
+        }
+        assert!(sim.projectiles.get(projectile).is_some());
+        sim.tick();
+        assert!(sim.projectiles.get(projectile).is_none());
+        assert_eq!(sim.physics_world.get_body_count(), 2);
+        assert!(sim.events.drain().is_empty());
+    }
+
+    #[test]
+    fn projectiles_apply_the_effect_their_body_carries() {
+        let mut sim = Simulation::new(100.0);
+        sim.status_effects
+            .insert(sim.enemy, StatusEffects::default());
+        let slow = StatusEffectDesc {
+            kind: StatusKind::Slow,
+            magnitude: 0.4,
+            duration: 2.0,
+            stacking: StackingPolicy::Refresh,
+        };
+        assert_eq!(sim.projectile_effects.get_user_data(None), 0);
+        let user_data = sim.projectile_effects.get_user_data(Some(slow));
+        assert_eq!(sim.projectile_effects.get_user_data(Some(slow)), user_data);
+
+        // Without user data the hit only damages
+        let shooter = sim.entities.spawn();
+        sim.fire(shooter, Team::Blue, 40.0);
+        sim.tick();
+        assert_eq!(sim.get_enemy_health(), 75.0);
+        let effects = sim.status_effects.get(sim.enemy).unwrap();
+        assert_eq!(effects.iter().count(), 0);
+
+        let projectile = sim.fire(shooter, Team::Blue, 40.0);
+        let body_id = sim.physics_proxies.get(projectile).unwrap().body_id;
+        sim.physics_world.set_user_data(body_id.unwrap(), user_data);
+        sim.tick();
+        assert_eq!(sim.get_enemy_health(), 50.0);
+        let effects = sim.status_effects.get(sim.enemy).unwrap();
+        assert_eq!(
+            effects.iter().map(|effect| effect.desc).collect::<Vec<_>>(),
+            [slow]
+        );
+        assert!(sim.events.drain().contains(&GameEvent::EffectApplied {
+            target: sim.enemy,
+            kind: StatusKind::Slow,
+        }));
+    }
+}
@@ -0,0 +1,331 @@
+// Orders buffered for the controlled entities. Game::update turns the inputs into commands
+// stamped with the fixed step they were issued on, a queue holds the one pending command,
+// and the fixed update hands it over once the caster or the movement can take it. Like the
+// cast times the buffer window is counted in fixed steps, so a command lasts as long on
+// every machine whatever the framerate.
+
+use shared::math::*;
+
+use crate::{
+    ability::{AbilityCaster, AbilityDesc, AbilityTarget, CastPhase, get_steps},
+    components::{Entities, Storage, join},
+    game::CTargetLocation,
+};
+
+// In seconds, an order given this long before the caster is free still goes through
+pub const DEFAULT_BUFFER_WINDOW: f32 = 0.25;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Move(Vec3),
+    Cast(usize, AbilityTarget), // The slot and its target
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PendingCommand {
+    command: Command,
+    issued: u32, // The fixed step
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandQueue {
+    pending: Option<PendingCommand>,
+    pub buffer_window: f32, // Simulation seconds
+}
+
+impl Default for CommandQueue {
+    fn default() -> Self {
+        Self {
+            pending: None,
+            buffer_window: DEFAULT_BUFFER_WINDOW,
+        }
+    }
+}
+
+impl CommandQueue {
+    // The newest order replaces the pending one
+    pub fn push(&mut self, command: Command, step: u32) {
+        self.pending = Some(PendingCommand {
+            command,
+            issued: step,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.pending = None;
+    }
+
+    #[allow(dead_code)]
+    pub fn get_pending(&self) -> Option<Command> {
+        self.pending.map(|pending| pending.command)
+    }
+}
+
+// Runs before update_abilities on the step. Casts wait for the running cast to end, move
+// orders only for casts a move doesn't interrupt, the others get cancelled by the move.
+pub fn update_command_queues(
+    dt: f32,
+    step: u32,
+    entities: &Entities,
+    queues: &mut Storage<CommandQueue>,
+    casters: &mut Storage<AbilityCaster>,
+    move_targets: &mut Storage<CTargetLocation>,
+) {
+    for (entity, queue) in join(entities, queues) {
+        let Some(pending) = queue.pending else {
+            continue;
+        };
+        if step.saturating_sub(pending.issued) > get_steps(queue.buffer_window, dt) {
+            log::debug!("{:?} dropped the expired {:?}", entity, pending.command);
+            queue.pending = None;
+            continue;
+        }
+
+        let caster = casters.get_mut(entity);
+        let casting: Option<&AbilityDesc> =
+            caster
+                .as_deref()
+                .and_then(|caster| match caster.get_phase() {
+                    CastPhase::Casting { slot, .. } => caster.get_ability(slot),
+                    _ => None,
+                });
+
+        match pending.command {
+            Command::Move(location) => {
+                if casting.is_some_and(|desc| !desc.interrupted_by_movement) {
+                    continue;
+                }
+                if let Some(target) = move_targets.get_mut(entity) {
+                    *target = Some(location);
+                }
+            }
+            Command::Cast(slot, target) => {
+                if casting.is_some() {
+                    continue;
+                }
+                if let Some(caster) = caster {
+                    caster.request_cast(slot, target);
+                }
+            }
+        }
+        queue.pending = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::physics::{BodySettings, CollisionLayer, CollisionShape, PhysicsWorld};
+
+    use super::*;
+    use crate::{
+        ability::{AbilityEffect, AbilityExecution, Targeting, update_abilities},
+        combat::CHealth,
+        components::Entity,
+        events::{GameEvent, GameEvents},
+        game::{CPhysicsProxy, CPlayerMovement},
+    };
+
+    const DT: f32 = 1.0 / 60.0;
+    const POINT: Vec3 = Vec3::new(100.0, 0.0, 0.0);
+
+    fn get_desc(name: &str, cast_time: f32, interrupted_by_movement: bool) -> AbilityDesc {
+        AbilityDesc {
+            name: name.to_string(),
+            cast_time,
+            cooldown: 1.0,
+            cost: 10.0,
+            range: 500.0,
+            targeting: Targeting::Point,
+            locks_movement: true,
+            interrupted_by_movement,
+            interrupted_by_damage: false,
+            effect: AbilityEffect::Damage { amount: 10.0 },
+        }
+    }
+
+    // A caster with a half second cast on Q and an instant one on W, commands are issued
+    // between the steps like Game::update does
+    struct Simulation {
+        physics_world: PhysicsWorld,
+        entities: Entities,
+        queues: Storage<CommandQueue>,
+        casters: Storage<AbilityCaster>,
+        healths: Storage<CHealth>,
+        physics_proxies: Storage<CPhysicsProxy>,
+        movements: Storage<CPlayerMovement>,
+        move_targets: Storage<CTargetLocation>,
+        events: GameEvents,
+        caster: Entity,
+        executions: Vec<(u32, AbilityExecution)>, // With the step they landed on
+        step: u32,
+    }
+
+    impl Simulation {
+        fn new(interrupted_by_movement: bool) -> Self {
+            let mut physics_world = PhysicsWorld::new();
+            let mut entities = Entities::default();
+            let caster = entities.spawn();
+            let body_id = physics_world.create_rigid_body(&BodySettings {
+                position: Vec2::ZERO,
+                velocity: Vec2::ZERO,
+                layer: CollisionLayer::Player,
+                shape: &CollisionShape::Circle { radius: 32.0 },
+                listen_to_contact_events: false,
+            });
+            physics_world.step_simulation(0.0);
+
+            let mut physics_proxies = Storage::default();
+            physics_proxies.insert(caster, CPhysicsProxy::new(body_id, &physics_world));
+            let mut healths = Storage::default();
+            healths.insert(caster, CHealth::new(100.0));
+            let mut casters = Storage::default();
+            casters.insert(
+                caster,
+                AbilityCaster::new([
+                    Some(get_desc("Bolt", 0.5, interrupted_by_movement)),
+                    Some(get_desc("Blink", 0.0, true)),
+                    None,
+                    None,
+                ]),
+            );
+            let mut queues = Storage::default();
+            queues.insert(caster, CommandQueue::default());
+            let mut movements = Storage::default();
+            movements.insert(caster, Default::default());
+            let mut move_targets = Storage::default();
+            move_targets.insert(caster, None);
+
+            Self {
+                physics_world,
+                entities,
+                queues,
+                casters,
+                healths,
+                physics_proxies,
+                movements,
+                move_targets,
+                events: Default::default(),
+                caster,
+                executions: Vec::new(),
+                step: 0,
+            }
+        }
+
+        fn issue(&mut self, command: Command) {
+            let step = self.step;
+            self.queues
+                .get_mut(self.caster)
+                .unwrap()
+                .push(command, step);
+        }
+
+        // Until the given number of steps have run
+        fn run_to(&mut self, step: u32) {
+            while self.step < step {
+                self.step += 1;
+                update_command_queues(
+                    DT,
+                    self.step,
+                    &self.entities,
+                    &mut self.queues,
+                    &mut self.casters,
+                    &mut self.move_targets,
+                );
+                let executions = update_abilities(
+                    DT,
+                    &self.entities,
+                    &mut self.casters,
+                    &self.healths,
+                    &self.physics_proxies,
+                    &mut self.movements,
+                    &mut self.move_targets,
+                    &self.physics_world,
+                    &mut self.events,
+                );
+                let step = self.step;
+                self.executions
+                    .extend(executions.into_iter().map(|execution| (step, execution)));
+            }
+        }
+
+        fn get_pending(&self) -> Option<Command> {
+            self.queues.get(self.caster).unwrap().get_pending()
+        }
+
+        fn get_landed(&self) -> Vec<(u32, usize)> {
+            self.executions
+                .iter()
+                .map(|(step, execution)| (*step, execution.slot))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn a_cast_issued_80_ms_early_runs_when_the_cast_ends() {
+        let mut sim = Simulation::new(true);
+        sim.issue(Command::Cast(0, AbilityTarget::Point(POINT)));
+        // Started on step 1, lands 30 steps later
+        sim.run_to(26);
+        sim.issue(Command::Cast(1, AbilityTarget::Point(POINT)));
+        sim.run_to(31);
+        assert_eq!(sim.get_landed(), vec![(31, 0)]);
+        assert!(sim.get_pending().is_some());
+
+        // On the step the caster is free again, the first one a new cast could start on
+        sim.run_to(40);
+        assert_eq!(sim.get_landed(), vec![(31, 0), (32, 1)]);
+        assert_eq!(sim.get_pending(), None);
+    }
+
+    #[test]
+    fn a_cast_issued_400_ms_early_is_discarded() {
+        let mut sim = Simulation::new(true);
+        sim.issue(Command::Cast(0, AbilityTarget::Point(POINT)));
+        sim.run_to(7);
+        sim.issue(Command::Cast(1, AbilityTarget::Point(POINT)));
+
+        // Kept for the 15 steps of the window, however long the frames are
+        sim.run_to(22);
+        assert!(sim.get_pending().is_some());
+        sim.run_to(23);
+        assert_eq!(sim.get_pending(), None);
+
+        sim.run_to(60);
+        assert_eq!(sim.get_landed(), vec![(31, 0)]);
+    }
+
+    #[test]
+    fn moves_during_uninterruptible_casts_queue() {
+        let location = Vec3::new(-50.0, 0.0, 20.0);
+        let mut sim = Simulation::new(false);
+        sim.issue(Command::Cast(0, AbilityTarget::Point(POINT)));
+        sim.run_to(20);
+        sim.issue(Command::Move(location));
+
+        sim.run_to(31);
+        assert_eq!(sim.get_landed(), vec![(31, 0)]);
+        assert_eq!(*sim.move_targets.get(sim.caster).unwrap(), None);
+        sim.run_to(32);
+        assert_eq!(*sim.move_targets.get(sim.caster).unwrap(), Some(location));
+        assert!(
+            !sim.events
+                .drain()
+                .iter()
+                .any(|event| matches!(event, GameEvent::AbilityInterrupted { .. }))
+        );
+
+        // A cast a move interrupts gets the order right away
+        let mut sim = Simulation::new(true);
+        sim.issue(Command::Cast(0, AbilityTarget::Point(POINT)));
+        sim.run_to(20);
+        sim.issue(Command::Move(location));
+        sim.run_to(21);
+        assert_eq!(*sim.move_targets.get(sim.caster).unwrap(), Some(location));
+        assert!(sim.events.drain().contains(&GameEvent::AbilityInterrupted {
+            caster: sim.caster,
+            slot: 0
+        }));
+        sim.run_to(40);
+        assert!(sim.executions.is_empty());
+    }
+}
@@ -0,0 +1,291 @@
+// Component storage for the game entities. Every component type lives in its own
+// storage indexed by the entity index, systems walk the storages they need side by side
+// with `join`/`join3`, skipping entities that are missing one of the components.
+
+#[derive(Copy, Clone, Hash, Debug, PartialEq, Eq)]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+impl Entity {
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    // Unique among the entities spawned so far, e.g. to key state kept outside the game
+    pub fn to_bits(self) -> u64 {
+        ((self.generation as u64) << 32) | self.index as u64
+    }
+}
+
+#[derive(Default)]
+pub struct Entities {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free_slots: Vec<u32>,
+}
+
+impl Entities {
+    pub fn spawn(&mut self) -> Entity {
+        if let Some(index) = self.free_slots.pop() {
+            let slot = index as usize;
+            self.generations[slot] = self.generations[slot].wrapping_add(1);
+            self.alive[slot] = true;
+            Entity {
+                index,
+                generation: self.generations[slot],
+            }
+        } else {
+            let index = self.generations.len() as u32;
+            self.generations.push(1);
+            self.alive.push(true);
+            Entity {
+                index,
+                generation: 1,
+            }
+        }
+    }
+
+    // Components are not touched, the owner removes them from its storages
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+
+        self.alive[entity.index()] = false;
+        self.free_slots.push(entity.index);
+        true
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.generations.get(entity.index()) == Some(&entity.generation)
+            && self.alive[entity.index()]
+    }
+
+    // The living entity in the slot, e.g. for an index typed into the console
+    pub fn get(&self, index: usize) -> Option<Entity> {
+        self.alive.get(index)?.then(|| Entity {
+            index: index as u32,
+            generation: self.generations[index],
+        })
+    }
+}
+
+pub struct Storage<T> {
+    components: Vec<Option<(u32, T)>>, // Tagged with the generation of the owning entity
+}
+
+impl<T> Default for Storage<T> {
+    fn default() -> Self {
+        Self {
+            components: Vec::new(),
+        }
+    }
+}
+
+impl<T> Storage<T> {
+    // Replaces the component the entity already had
+    pub fn insert(&mut self, entity: Entity, component: T) {
+        if self.components.len() <= entity.index() {
+            self.components.resize_with(entity.index() + 1, || None);
+        }
+        self.components[entity.index()] = Some((entity.generation, component));
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        let slot = self.components.get_mut(entity.index())?;
+        match slot {
+            Some((generation, _)) if *generation == entity.generation => {
+                slot.take().map(|(_, component)| component)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        match self.components.get(entity.index())? {
+            Some((generation, component)) if *generation == entity.generation => Some(component),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        match self.components.get_mut(entity.index())? {
+            Some((generation, component)) if *generation == entity.generation => Some(component),
+            _ => None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.components.clear();
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.components
+            .iter_mut()
+            .flatten()
+            .map(|(_, component)| component)
+    }
+}
+
+// Something that can be walked slot by slot, one slot per entity index. The slots are
+// tagged with the generation of the entity they belong to.
+pub trait Joinable {
+    type Item;
+
+    fn slots(self) -> impl Iterator<Item = Option<(u32, Self::Item)>>;
+}
+
+impl<'a, T> Joinable for &'a Storage<T> {
+    type Item = &'a T;
+
+    fn slots(self) -> impl Iterator<Item = Option<(u32, &'a T)>> {
+        self.components.iter().map(|slot| {
+            slot.as_ref()
+                .map(|(generation, component)| (*generation, component))
+        })
+    }
+}
+
+impl<'a, T> Joinable for &'a mut Storage<T> {
+    type Item = &'a mut T;
+
+    fn slots(self) -> impl Iterator<Item = Option<(u32, &'a mut T)>> {
+        self.components.iter_mut().map(|slot| {
+            slot.as_mut()
+                .map(|(generation, component)| (*generation, component))
+        })
+    }
+}
+
+// Yields the entity ids, for systems that look up further components by hand
+impl Joinable for &Entities {
+    type Item = Entity;
+
+    fn slots(self) -> impl Iterator<Item = Option<(u32, Entity)>> {
+        self.generations.iter().zip(&self.alive).enumerate().map(
+            |(index, (&generation, &alive))| {
+                alive.then_some((
+                    generation,
+                    Entity {
+                        index: index as u32,
+                        generation,
+                    },
+                ))
+            },
+        )
+    }
+}
+
+// Storages are only as long as their highest entity, so zip stopping at the shortest one
+// only drops slots that would have been skipped anyway. A component left behind by a
+// despawned entity has an older generation than the one now in its slot, and is skipped.
+pub fn join<A: Joinable, B: Joinable>(a: A, b: B) -> impl Iterator<Item = (A::Item, B::Item)> {
+    a.slots().zip(b.slots()).filter_map(|(a, b)| {
+        let ((a_generation, a), (b_generation, b)) = (a?, b?);
+        (a_generation == b_generation).then_some((a, b))
+    })
+}
+
+pub fn join3<A: Joinable, B: Joinable, C: Joinable>(
+    a: A,
+    b: B,
+    c: C,
+) -> impl Iterator<Item = (A::Item, B::Item, C::Item)> {
+    a.slots()
+        .zip(b.slots())
+        .zip(c.slots())
+        .filter_map(|((a, b), c)| {
+            let ((a_generation, a), (b_generation, b), (c_generation, c)) = (a?, b?, c?);
+            (a_generation == b_generation && b_generation == c_generation).then_some((a, b, c))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_skips_entities_missing_a_component() {
+        let mut entities = Entities::default();
+        let a = entities.spawn();
+        let b = entities.spawn();
+        let c = entities.spawn();
+
+        let mut positions = Storage::default();
+        let mut velocities = Storage::default();
+        positions.insert(a, 1);
+        positions.insert(b, 2);
+        positions.insert(c, 3);
+        velocities.insert(a, 10);
+        velocities.insert(c, 30);
+
+        for (position, velocity) in join(&mut positions, &velocities) {
+            *position += velocity;
+        }
+
+        assert_eq!(positions.get(a), Some(&11));
+        assert_eq!(positions.get(b), Some(&2));
+        assert_eq!(positions.get(c), Some(&33));
+    }
+
+    #[test]
+    fn join3_skips_missing_components_and_shorter_storages() {
+        let mut entities = Entities::default();
+        let ids: Vec<Entity> = (0..4).map(|_| entities.spawn()).collect();
+
+        let mut names = Storage::default();
+        let mut healths = Storage::default();
+        for (index, &id) in ids.iter().enumerate() {
+            names.insert(id, index);
+        }
+        healths.insert(ids[0], 100);
+        healths.insert(ids[1], 50);
+        entities.despawn(ids[1]);
+
+        let joined: Vec<(Entity, usize, i32)> = join3(&entities, &names, &healths)
+            .map(|(entity, name, health)| (entity, *name, *health))
+            .collect();
+        assert_eq!(joined, vec![(ids[0], 0, 100)]);
+    }
+
+    #[test]
+    fn stale_entities_do_not_see_new_components() {
+        let mut entities = Entities::default();
+        let mut storage = Storage::default();
+
+        let old = entities.spawn();
+        storage.insert(old, "old");
+        entities.despawn(old);
+        storage.remove(old);
+
+        let new = entities.spawn();
+        storage.insert(new, "new");
+        assert_eq!(new.index(), old.index());
+        assert_eq!(storage.get(old), None);
+        assert_eq!(storage.get(new), Some(&"new"));
+    }
+
+    #[test]
+    fn join_skips_components_of_despawned_entities() {
+        let mut entities = Entities::default();
+        let mut names = Storage::default();
+        let mut healths = Storage::default();
+
+        // The health isn't removed with the entity, the new one in the slot has none yet
+        let old = entities.spawn();
+        names.insert(old, "old");
+        healths.insert(old, 100);
+        entities.despawn(old);
+        let new = entities.spawn();
+        names.insert(new, "new");
+        assert_eq!(new.index(), old.index());
+
+        assert_eq!(join(&entities, &healths).count(), 0);
+        assert_eq!(join(&names, &healths).count(), 0);
+        assert_eq!(join3(&entities, &names, &healths).count(), 0);
+        let joined: Vec<_> = join(&entities, &names).collect();
+        assert_eq!(joined, vec![(new, &"new")]);
+    }
+}
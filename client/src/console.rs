@@ -0,0 +1,1117 @@
+// A developer console, toggled with the key below Escape. Commands are registered with the
+// arguments they take, see ConsoleCommands::register, and get them parsed to their types.
+// A name of two words is a subcommand, e.g. "camtrack add" next to "camtrack play".
+// A line can hold several commands separated by ';', '#' comments out the rest of it.
+// The same lines run from autoexec.cfg next to the executable once the level is loaded.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use anyhow::{Context, bail};
+use glam::{Vec2, Vec3, Vec4};
+use winit::keyboard::KeyCode;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::hitch::save_hitches;
+use crate::{
+    camera_track::{self, CameraTrackPlayer},
+    game::Game,
+    hitch::{HitchDetector, HitchRecord, HitchSettings},
+    network::NetworkClient,
+    prefab::PrefabOverrides,
+    renderer::{
+        Renderer, SpriteAnchor, SpriteSpace, TextAlignment, WorldGridSettings,
+        render_data::{SpriteRenderJob, TextRenderJob},
+        resources::get_handle,
+    },
+    status_effects::{StackingPolicy, StatusEffectDesc, StatusKind},
+};
+use shared::physics::PhysicsWorld;
+
+const CONSOLE_LAYER: u32 = u16::MAX as u32 - 3; // Below the error banner
+const CONSOLE_WIDTH: f32 = 1400.0;
+const PADDING: f32 = 8.0;
+const LINE_HEIGHT: f32 = 20.0;
+const TEXT_SIZE: f32 = 16.0;
+const VISIBLE_LINES: usize = 16;
+const MAX_LINES: usize = 256;
+const MAX_HISTORY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    Number,
+    Word,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ArgSpec {
+    pub name: &'static str, // Shown in the usage
+    pub kind: ArgKind,
+}
+
+impl ArgSpec {
+    pub const fn number(name: &'static str) -> Self {
+        Self {
+            name,
+            kind: ArgKind::Number,
+        }
+    }
+
+    pub const fn word(name: &'static str) -> Self {
+        Self {
+            name,
+            kind: ArgKind::Word,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgValue {
+    Number(f32),
+    Word(String),
+}
+
+// Of the kinds in the spec of the command, in its order
+#[derive(Debug, Default, PartialEq)]
+pub struct ConsoleArgs {
+    values: Vec<ArgValue>,
+}
+
+impl ConsoleArgs {
+    // The spec asked for a number there, anything else is a bug in the command
+    pub fn get_f32(&self, index: usize) -> f32 {
+        match &self.values[index] {
+            ArgValue::Number(value) => *value,
+            ArgValue::Word(_) => panic!("Argument {} is not a number", index),
+        }
+    }
+
+    pub fn get_str(&self, index: usize) -> &str {
+        match &self.values[index] {
+            ArgValue::Word(word) => word,
+            ArgValue::Number(_) => panic!("Argument {} is not a word", index),
+        }
+    }
+}
+
+// What the commands can change
+pub struct ConsoleContext<'a> {
+    pub game: &'a mut Game,
+    pub renderer: &'a mut Renderer,
+    pub physics: &'a mut PhysicsWorld,
+    pub settings: &'a mut ConsoleSettings,
+    pub camera_track: &'a mut CameraTrackPlayer,
+    pub network: Option<&'a NetworkClient>, // Set when playing on a server
+    pub hitches: &'a mut HitchDetector,
+}
+
+// Requests the app carries out after the commands ran
+#[derive(Debug, Default)]
+pub struct ConsoleSettings {
+    pub screenshot_requested: bool, // Of the next frame, without the console on it
+    pub diagnostics_path: Option<String>, // Where dumpdiag writes to
+    pub sleep_ms: Option<f32>,      // Stalls the next update, for a hitch
+}
+
+// Returns what to print, an error is printed in red
+type CommandHandler = Box<dyn Fn(&mut ConsoleContext, &ConsoleArgs) -> anyhow::Result<String>>;
+
+struct ConsoleCommand {
+    args: Vec<ArgSpec>,
+    handler: CommandHandler,
+}
+
+impl ConsoleCommand {
+    fn get_usage(&self, name: &str) -> String {
+        self.args.iter().fold(name.to_string(), |usage, arg| {
+            format!("{} <{}>", usage, arg.name)
+        })
+    }
+}
+
+// Sorted by name, completion cycles through them in that order
+#[derive(Default)]
+pub struct ConsoleCommands {
+    commands: BTreeMap<String, ConsoleCommand>,
+}
+
+impl ConsoleCommands {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Replaces a command of the same name
+    pub fn register(
+        &mut self,
+        name: &str,
+        args: &[ArgSpec],
+        handler: impl Fn(&mut ConsoleContext, &ConsoleArgs) -> anyhow::Result<String> + 'static,
+    ) {
+        self.commands.insert(
+            name.to_string(),
+            ConsoleCommand {
+                args: args.to_vec(),
+                handler: Box::new(handler),
+            },
+        );
+    }
+
+    // The words of one command, the name first. Errors about the arguments end with the
+    // usage of the command.
+    pub fn parse(&self, words: &[String]) -> Result<ConsoleArgs, String> {
+        let (name, command, words) = self.find(words)?;
+        parse_args(&command.args, words)
+            .map_err(|error| format!("{}\nUsage: {}", error, command.get_usage(&name)))
+    }
+
+    pub fn run(&self, words: &[String], context: &mut ConsoleContext) -> Result<String, String> {
+        let args = self.parse(words)?;
+        let (_, command, _) = self.find(words)?;
+        (command.handler)(context, &args).map_err(|error| format!("{:#}", error))
+    }
+
+    // A subcommand goes before a command of its first word
+    fn find<'a>(
+        &self,
+        words: &'a [String],
+    ) -> Result<(String, &ConsoleCommand, &'a [String]), String> {
+        let Some((name, rest)) = words.split_first() else {
+            return Err("Nothing to run".to_string());
+        };
+        if let Some((subcommand, rest)) = rest.split_first() {
+            let full_name = format!("{} {}", name, subcommand);
+            if let Some(command) = self.commands.get(&full_name) {
+                return Ok((full_name, command, rest));
+            }
+        }
+        if let Some(command) = self.commands.get(name) {
+            return Ok((name.clone(), command, rest));
+        }
+        let subcommands = self
+            .complete(&format!("{} ", name))
+            .iter()
+            .map(|full_name| &full_name[name.len() + 1..])
+            .collect::<Vec<_>>();
+        if subcommands.is_empty() {
+            Err(format!("Unknown command {}", name))
+        } else {
+            Err(format!("{} needs one of {}", name, subcommands.join(", ")))
+        }
+    }
+
+    pub fn complete(&self, prefix: &str) -> Vec<&str> {
+        self.commands
+            .keys()
+            .filter(|name| name.starts_with(prefix))
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+fn parse_args(specs: &[ArgSpec], words: &[String]) -> Result<ConsoleArgs, String> {
+    if words.len() != specs.len() {
+        return Err(format!(
+            "Expected {} argument{}, got {}",
+            specs.len(),
+            if specs.len() == 1 { "" } else { "s" },
+            words.len()
+        ));
+    }
+    let values = specs
+        .iter()
+        .zip(words)
+        .map(|(spec, word)| match spec.kind {
+            ArgKind::Number => word
+                .parse::<f32>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .map(ArgValue::Number)
+                .ok_or_else(|| format!("<{}> must be a number, got '{}'", spec.name, word)),
+            ArgKind::Word => Ok(ArgValue::Word(word.clone())),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(ConsoleArgs { values })
+}
+
+// The words of each command on the line. Quotes keep spaces, ';' and '#' in a word.
+pub fn split_commands(line: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut commands = Vec::new();
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("Missing the closing quote".to_string()),
+                    }
+                }
+            }
+            '#' => break,
+            ';' => {
+                words.extend(word.take());
+                if !words.is_empty() {
+                    commands.push(std::mem::take(&mut words));
+                }
+            }
+            c if c.is_whitespace() => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word.take());
+    if !words.is_empty() {
+        commands.push(words);
+    }
+    Ok(commands)
+}
+
+// Buffs by name, with the magnitudes of the abilities that give them
+fn get_buff(name: &str, duration: f32) -> Option<StatusEffectDesc> {
+    let (kind, magnitude) = match name {
+        "slow" => (StatusKind::Slow, 0.5),
+        "haste" => (StatusKind::Haste, 0.5),
+        "burn" => (StatusKind::DamageOverTime, 10.0),
+        "shield" => (StatusKind::Shield, 0.5),
+        _ => return None,
+    };
+    Some(StatusEffectDesc {
+        kind,
+        magnitude,
+        duration,
+        stacking: StackingPolicy::Refresh,
+    })
+}
+
+pub fn register_builtin_commands(commands: &mut ConsoleCommands) {
+    commands.register(
+        "spawn",
+        &[
+            ArgSpec::word("prefab"),
+            ArgSpec::number("x"),
+            ArgSpec::number("z"),
+        ],
+        |context, args| {
+            let (name, x, z) = (args.get_str(0), args.get_f32(1), args.get_f32(2));
+            let overrides = PrefabOverrides {
+                position: Vec3::new(x, 0.0, z),
+                ..Default::default()
+            };
+            context
+                .game
+                .spawn_prefab(name, &overrides, context.renderer, context.physics)?;
+            Ok(format!("Spawned {} at ({}, {})", name, x, z))
+        },
+    );
+
+    // The skins of a variant prefab side by side, e.g. `variants Brute 0 0`
+    commands.register(
+        "variants",
+        &[
+            ArgSpec::word("prefab"),
+            ArgSpec::number("x"),
+            ArgSpec::number("z"),
+        ],
+        |context, args| {
+            let (name, x, z) = (args.get_str(0), args.get_f32(1), args.get_f32(2));
+            let entities = context.game.spawn_variant_row(
+                name,
+                Vec3::new(x, 0.0, z),
+                context.renderer,
+                context.physics,
+            )?;
+            Ok(format!("Spawned {} skins of {}", entities.len(), name))
+        },
+    );
+
+    commands.register("timescale", &[ArgSpec::number("scale")], |context, args| {
+        let scale = args.get_f32(0);
+        if scale < 0.0 {
+            bail!("The time scale can't be negative");
+        }
+        context.game.get_time_mut().set_base_scale(scale);
+        Ok(format!("Time scale {}", scale))
+    });
+
+    commands.register("shadow", &[ArgSpec::word("on|off|res")], |context, args| {
+        let mut light = *context.renderer.get_directional_light();
+        match args.get_str(0) {
+            "on" | "off" => {
+                light.shadows_enabled = args.get_str(0) == "on";
+                context.renderer.set_directional_light(light);
+                Ok(format!("Shadows {}", args.get_str(0)))
+            }
+            value => {
+                let size = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|size| size.is_power_of_two())
+                    .with_context(|| {
+                        format!(
+                            "Expected on, off or a power of two resolution, got {}",
+                            value
+                        )
+                    })?;
+                context.renderer.set_shadow_map_size(size);
+                // It is clamped to what the device supports
+                let size = context.renderer.get_shadow_map_size();
+                Ok(format!("Shadow map {}x{}", size, size))
+            }
+        }
+    });
+
+    // The world grid on Y=0, turned on with the default settings
+    commands.register("grid", &[], |context, _| {
+        let grid = match context.renderer.get_world_grid() {
+            Some(_) => None,
+            None => Some(WorldGridSettings::default()),
+        };
+        context.renderer.set_world_grid(grid);
+        Ok(format!(
+            "World grid {}",
+            if grid.is_some() { "on" } else { "off" }
+        ))
+    });
+
+    // Turns the grid on if it isn't
+    commands.register(
+        "grid spacing",
+        &[ArgSpec::number("units")],
+        |context, args| {
+            let spacing = args.get_f32(0);
+            if !(0.01..=1000.0).contains(&spacing) {
+                bail!("<units> must be between 0.01 and 1000");
+            }
+            let grid = context.renderer.get_world_grid().unwrap_or_default();
+            context
+                .renderer
+                .set_world_grid(Some(WorldGridSettings { spacing, ..grid }));
+            Ok(format!("World grid every {} units", spacing))
+        },
+    );
+
+    // Silhouettes of units behind walls, the xray setting at startup
+    commands.register("xray", &[], |context, _| {
+        let enabled = !context.renderer.is_xray_enabled();
+        context.renderer.set_xray_enabled(enabled);
+        Ok(format!("X-ray {}", if enabled { "on" } else { "off" }))
+    });
+
+    // Clicks on the ground measure between them instead of selecting
+    commands.register("measure", &[], |context, _| {
+        let tool = context.game.get_measure_tool_mut();
+        tool.set_enabled(!tool.is_enabled());
+        Ok(format!(
+            "Measuring {}",
+            if tool.is_enabled() { "on" } else { "off" }
+        ))
+    });
+
+    commands.register("fov", &[ArgSpec::number("degrees")], |context, args| {
+        let fov = args.get_f32(0);
+        if !(10.0..=120.0).contains(&fov) {
+            bail!("The field of view must be between 10 and 120 degrees");
+        }
+        let mut settings = context.game.get_camera_settings();
+        settings.fov = fov;
+        context.game.set_camera_settings(settings);
+        Ok(format!("Field of view {} degrees", fov))
+    });
+
+    commands.register(
+        "teleport",
+        &[ArgSpec::number("x"), ArgSpec::number("z")],
+        |context, args| {
+            let player = context.game.get_player().context("There is no player")?;
+            let position = Vec2::new(args.get_f32(0), args.get_f32(1));
+            context.game.teleport(player, position, context.physics)?;
+            Ok(format!("Teleported to ({}, {})", position.x, position.y))
+        },
+    );
+
+    commands.register(
+        "give_buff",
+        &[ArgSpec::word("name"), ArgSpec::number("duration")],
+        |context, args| {
+            let (name, duration) = (args.get_str(0), args.get_f32(1));
+            if duration <= 0.0 {
+                bail!("The duration must be positive");
+            }
+            let effect = get_buff(name, duration).with_context(|| {
+                format!(
+                    "Unknown buff {}, there are slow, haste, burn and shield",
+                    name
+                )
+            })?;
+            let player = context.game.get_player().context("There is no player")?;
+            context.game.apply_status_effect(player, effect)?;
+            Ok(format!("Gave {} for {} s", name, duration))
+        },
+    );
+
+    commands.register(
+        "pin",
+        &[ArgSpec::number("x"), ArgSpec::number("z")],
+        |context, args| {
+            let position = Vec3::new(args.get_f32(0), 0.0, args.get_f32(1));
+            let id = context.game.pin_objective(position);
+            Ok(format!(
+                "Pinned objective {} at ({}, {})",
+                id, position.x, position.z
+            ))
+        },
+    );
+
+    commands.register("unpin", &[ArgSpec::number("id")], |context, args| {
+        let id = args.get_f32(0);
+        if id < 0.0 || id.fract() != 0.0 || !context.game.unpin_objective(id as u32) {
+            bail!("There is no objective {}", id);
+        }
+        Ok(format!("Unpinned objective {}", id))
+    });
+
+    commands.register("netstat", &[], |context, _| {
+        let Some(network) = context.network else {
+            return Ok("Not connected to a server".to_string());
+        };
+        let stats = network.get_stats();
+        let entity = network
+            .get_entity_id()
+            .map_or("joining".to_string(), |id| id.to_string());
+        let tick = network
+            .get_latest_tick()
+            .map_or("none".to_string(), |tick| tick.to_string());
+        Ok(format!(
+            "Entity {}, latest tick {}\n\
+             Sent {} datagrams, {:.1} KiB, {} resent\n\
+             Received {} datagrams, {:.1} KiB, {} snapshots",
+            entity,
+            tick,
+            stats.datagrams_sent,
+            stats.bytes_sent as f64 / 1024.0,
+            stats.resent,
+            stats.datagrams_received,
+            stats.bytes_received as f64 / 1024.0,
+            stats.snapshots
+        ))
+    });
+
+    commands.register("screenshot", &[], |context, _| {
+        if cfg!(target_arch = "wasm32") {
+            bail!("Screenshots are not supported in the browser");
+        }
+        context.settings.screenshot_requested = true;
+        Ok("Taking a screenshot".to_string())
+    });
+
+    // Along with the frame stats and settings only the app has
+    commands.register("dumpdiag", &[ArgSpec::word("file")], |context, args| {
+        if cfg!(target_arch = "wasm32") {
+            bail!("Diagnostics can't be written to files in the browser");
+        }
+        let path = args.get_str(0);
+        context.settings.diagnostics_path = Some(path.to_string());
+        Ok(format!("Writing the diagnostics to {}", path))
+    });
+
+    // An artificial hitch, the frame should show up in the hitch records
+    commands.register("sleep", &[ArgSpec::number("ms")], |context, args| {
+        let ms = args.get_f32(0);
+        if !(0.0..=10000.0).contains(&ms) {
+            bail!("<ms> must be between 0 and 10000");
+        }
+        context.settings.sleep_ms = Some(ms);
+        Ok(format!("Sleeping for {} ms", ms))
+    });
+
+    // Newest first
+    commands.register("hitches", &[], |context, _| {
+        if !context.hitches.is_enabled() {
+            return Ok("Hitch detection is disabled".to_string());
+        }
+        if context.hitches.get_count() == 0 {
+            return Ok("No hitches".to_string());
+        }
+        Ok(context
+            .hitches
+            .get_records()
+            .map(HitchRecord::get_summary)
+            .collect::<Vec<_>>()
+            .join("\n"))
+    });
+
+    commands.register("hitches clear", &[], |context, _| {
+        context.hitches.clear();
+        Ok(String::new())
+    });
+
+    // A frame is a hitch when it takes longer than this many medians
+    commands.register(
+        "hitches threshold",
+        &[ArgSpec::number("multiple")],
+        |context, args| {
+            let multiple = args.get_f32(0);
+            if multiple <= 1.0 {
+                bail!("<multiple> must be more than 1");
+            }
+            context.hitches.set_settings(HitchSettings {
+                enabled: true,
+                multiple,
+                ..context.hitches.get_settings()
+            });
+            Ok(String::new())
+        },
+    );
+
+    commands.register("hitches dump", &[ArgSpec::word("file")], |context, args| {
+        if cfg!(target_arch = "wasm32") {
+            bail!("Hitches can't be written to files in the browser");
+        }
+        let path = args.get_str(0);
+        #[cfg(not(target_arch = "wasm32"))]
+        save_hitches(context.hitches, std::path::Path::new(path))?;
+        Ok(format!(
+            "Wrote {} hitches to {}",
+            context.hitches.get_count(),
+            path
+        ))
+    });
+
+    // The batches are counted in the captured ones, capturing starts with the first check
+    commands.register("materials duplicates", &[], |context, _| {
+        if !context.renderer.is_batch_capture_enabled() {
+            context.renderer.set_batch_capture_enabled(true);
+        }
+        let duplicates = context.renderer.get_duplicate_materials();
+        if duplicates.is_empty() {
+            return Ok("No duplicate materials".to_string());
+        }
+        let lines: Vec<_> = duplicates
+            .iter()
+            .map(|duplicate| {
+                format!(
+                    "{} split {} batches",
+                    duplicate.names.join(", "),
+                    duplicate.split_batches
+                )
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    });
+
+    commands.register("materials dedupe", &[], |context, _| {
+        let count = context.renderer.dedupe_materials();
+        Ok(format!("Merged {} duplicate materials", count))
+    });
+
+    camera_track::register_commands(commands);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+    Input,
+    Output,
+    Error,
+}
+
+impl LineKind {
+    fn get_color(self) -> Vec4 {
+        match self {
+            LineKind::Input => Vec4::new(0.6, 0.6, 0.6, 1.0),
+            LineKind::Output => Vec4::ONE,
+            LineKind::Error => Vec4::new(1.0, 0.4, 0.4, 1.0),
+        }
+    }
+}
+
+struct ConsoleLine {
+    text: String,
+    kind: LineKind,
+}
+
+// Tab cycles through the commands starting with what was typed before the first Tab
+struct Completion {
+    prefix: String,
+    index: usize,
+}
+
+pub struct Console {
+    commands: ConsoleCommands,
+    open: bool,
+    input: String,
+    lines: VecDeque<ConsoleLine>,
+    scroll: usize, // Lines scrolled up from the newest
+    completion: Option<Completion>,
+    history: Vec<String>,
+    history_index: Option<usize>, // Set while stepping through the history
+}
+
+impl Console {
+    pub fn new() -> Self {
+        let mut commands = ConsoleCommands::new();
+        register_builtin_commands(&mut commands);
+        Self {
+            commands,
+            open: false,
+            input: String::new(),
+            lines: VecDeque::new(),
+            scroll: 0,
+            completion: None,
+            history: Vec::new(),
+            history_index: None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn get_commands_mut(&mut self) -> &mut ConsoleCommands {
+        &mut self.commands
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn set_open(&mut self, open: bool) {
+        self.open = open;
+    }
+
+    // The text is what the key typed, if anything. Returns the line once it is submitted.
+    pub fn handle_key(&mut self, code: KeyCode, text: Option<&str>) -> Option<String> {
+        match code {
+            KeyCode::Enter | KeyCode::NumpadEnter => {
+                self.completion = None;
+                self.history_index = None;
+                self.scroll = 0;
+                let line = std::mem::take(&mut self.input);
+                if line.trim().is_empty() {
+                    return None;
+                }
+                if self.history.last() != Some(&line) {
+                    self.history.push(line.clone());
+                    if self.history.len() > MAX_HISTORY {
+                        self.history.remove(0);
+                    }
+                }
+                return Some(line);
+            }
+            KeyCode::Tab => {
+                self.complete();
+                return None;
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::ArrowUp => self.step_history(true),
+            KeyCode::ArrowDown => self.step_history(false),
+            KeyCode::PageUp => {
+                let max_scroll = self.lines.len().saturating_sub(VISIBLE_LINES);
+                self.scroll = (self.scroll + VISIBLE_LINES / 2).min(max_scroll);
+            }
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(VISIBLE_LINES / 2),
+            _ => {
+                if let Some(text) = text {
+                    self.input.extend(text.chars().filter(|c| !c.is_control()));
+                }
+            }
+        }
+        self.completion = None;
+        None
+    }
+
+    fn complete(&mut self) {
+        // Only the name is completed, not the arguments after it
+        if self.completion.is_none()
+            && self.input.contains(' ')
+            && self.commands.complete(&self.input).is_empty()
+        {
+            return;
+        }
+        let completion = self.completion.get_or_insert_with(|| Completion {
+            prefix: self.input.clone(),
+            index: 0,
+        });
+        let names = self.commands.complete(&completion.prefix);
+        if names.is_empty() {
+            return;
+        }
+        self.input = names[completion.index % names.len()].to_string();
+        completion.index += 1;
+    }
+
+    // Up goes to older lines, down past the newest clears the input again
+    fn step_history(&mut self, older: bool) {
+        let index = match (self.history_index, older) {
+            (None, true) => self.history.len().checked_sub(1),
+            (None, false) => None,
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) => (index + 1 < self.history.len()).then_some(index + 1),
+        };
+        self.history_index = index;
+        self.input = index
+            .map(|index| self.history[index].clone())
+            .unwrap_or_default();
+    }
+
+    fn print(&mut self, text: &str, kind: LineKind) {
+        for line in text.lines() {
+            self.lines.push_back(ConsoleLine {
+                text: line.to_string(),
+                kind,
+            });
+        }
+        while self.lines.len() > MAX_LINES {
+            self.lines.pop_front();
+        }
+        self.scroll = self
+            .scroll
+            .min(self.lines.len().saturating_sub(VISIBLE_LINES));
+    }
+
+    // Echoes the line and runs its commands
+    pub fn execute(&mut self, line: &str, context: &mut ConsoleContext) {
+        self.print(&format!("] {}", line), LineKind::Input);
+        if let Err(error) = self.run_line(line, context) {
+            self.print(&error, LineKind::Error);
+        }
+    }
+
+    // Stops at the first command that fails
+    fn run_line(&mut self, line: &str, context: &mut ConsoleContext) -> Result<(), String> {
+        for words in split_commands(line)? {
+            let output = self.commands.run(&words, context)?;
+            if !output.is_empty() {
+                self.print(&output, LineKind::Output);
+            }
+        }
+        Ok(())
+    }
+
+    // Every line runs as if typed in, errors name the line they come from
+    pub fn run_script(&mut self, name: &str, script: &str, context: &mut ConsoleContext) {
+        self.print(&format!("Running {}", name), LineKind::Input);
+        for (index, line) in script.lines().enumerate() {
+            if let Err(error) = self.run_line(line, context) {
+                let error = format!("{}:{}: {}", name, index + 1, error);
+                log::warn!("{}", error);
+                self.print(&error, LineKind::Error);
+            }
+        }
+    }
+
+    pub fn render(&self, renderer: &mut Renderer) {
+        if !self.open {
+            return;
+        }
+
+        // The text is on the layer above, sprites of other materials aren't ordered
+        let height = (VISIBLE_LINES + 1) as f32 * LINE_HEIGHT + 2.0 * PADDING;
+        renderer.submit(&SpriteRenderJob {
+            anchor: SpriteAnchor::TopCenter,
+            space: SpriteSpace::Absolute,
+            ..SpriteRenderJob::solid(
+                Vec2::new(-0.5 * CONSOLE_WIDTH, 0.0),
+                Vec2::new(CONSOLE_WIDTH, height),
+                Vec4::new(0.05, 0.05, 0.08, 0.9),
+                CONSOLE_LAYER - 1,
+            )
+        });
+
+        // The newest lines end right above the input
+        let end = self.lines.len() - self.scroll;
+        let start = end.saturating_sub(VISIBLE_LINES);
+        let first_row = VISIBLE_LINES - (end - start);
+        let input = format!("] {}_", self.input);
+        let rows = self
+            .lines
+            .range(start..end)
+            .map(|line| (line.text.as_str(), line.kind.get_color()))
+            .chain(std::iter::once((input.as_str(), Vec4::ONE)));
+        for (row, (text, color)) in rows.enumerate() {
+            self.submit_text(renderer, text, first_row + row, color, TextAlignment::Left);
+        }
+        if self.scroll > 0 {
+            let text = format!("{} more below", self.scroll);
+            let color = LineKind::Input.get_color();
+            self.submit_text(renderer, &text, VISIBLE_LINES, color, TextAlignment::Right);
+        }
+    }
+
+    fn submit_text(
+        &self,
+        renderer: &mut Renderer,
+        text: &str,
+        row: usize,
+        color: Vec4,
+        alignment: TextAlignment,
+    ) {
+        let x = match alignment {
+            TextAlignment::Right => 0.5 * CONSOLE_WIDTH - PADDING,
+            _ => -0.5 * CONSOLE_WIDTH + PADDING,
+        };
+        renderer.submit(&TextRenderJob {
+            font_atlas: get_handle("DebugFont"),
+            font_material: get_handle("DebugFontMaterial"),
+            text: text.into(),
+            position: Vec2::new(x, PADDING + row as f32 * LINE_HEIGHT + 15.0),
+            size: TEXT_SIZE,
+            color,
+            layer: CONSOLE_LAYER,
+            anchor: SpriteAnchor::TopCenter,
+            space: SpriteSpace::Absolute,
+            alignment,
+        });
+    }
+}
+
+// Next to the executable like the quick save
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_autoexec_path() -> std::path::PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.to_path_buf()))
+        .unwrap_or_default()
+        .join("autoexec.cfg")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn lines_split_into_commands_and_words() {
+        let commands = split_commands(r#"spawn Orb 1 2; say "a b;c # d" # spawn Orb 3 4"#);
+        assert_eq!(
+            commands,
+            Ok(vec![
+                words("spawn Orb 1 2"),
+                vec!["say".to_string(), "a b;c # d".to_string()],
+            ])
+        );
+        assert_eq!(split_commands("  ;; # nothing"), Ok(vec![]));
+        assert_eq!(
+            split_commands(r#"say "" x"#),
+            Ok(vec![vec![
+                "say".to_string(),
+                String::new(),
+                "x".to_string()
+            ]])
+        );
+        assert!(split_commands(r#"say "open"#).is_err());
+    }
+
+    #[test]
+    fn arguments_are_parsed_to_their_kinds() {
+        let mut commands = ConsoleCommands::new();
+        register_builtin_commands(&mut commands);
+
+        let args = commands.parse(&words("spawn Orb -1.5 20")).unwrap();
+        assert_eq!(args.get_str(0), "Orb");
+        assert_eq!(args.get_f32(1), -1.5);
+        assert_eq!(args.get_f32(2), 20.0);
+        assert_eq!(
+            commands.parse(&words("netstat")),
+            Ok(ConsoleArgs::default())
+        );
+
+        // Errors come with the usage
+        let error = commands.parse(&words("spawn Orb left 20")).unwrap_err();
+        assert_eq!(
+            error,
+            "<x> must be a number, got 'left'\nUsage: spawn <prefab> <x> <z>"
+        );
+        let error = commands.parse(&words("timescale")).unwrap_err();
+        assert_eq!(
+            error,
+            "Expected 1 argument, got 0\nUsage: timescale <scale>"
+        );
+        assert!(commands.parse(&words("fov inf")).is_err());
+        assert_eq!(
+            commands.parse(&words("warp 1 2")),
+            Err("Unknown command warp".to_string())
+        );
+
+        // Subcommands take their arguments after both words
+        let args = commands.parse(&words("camtrack play 0.5")).unwrap();
+        assert_eq!(args.get_f32(0), 0.5);
+        let error = commands.parse(&words("camtrack play")).unwrap_err();
+        assert_eq!(
+            error,
+            "Expected 1 argument, got 0\nUsage: camtrack play <speed>"
+        );
+        let error = commands.parse(&words("camtrack rewind")).unwrap_err();
+        assert!(
+            error.starts_with("camtrack needs one of add, clear, cut, "),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn tab_cycles_through_matching_commands() {
+        let mut console = Console::new();
+        let type_text = |console: &mut Console, text: &str| {
+            for c in text.chars() {
+                console.handle_key(KeyCode::KeyA, Some(&c.to_string()));
+            }
+        };
+        type_text(&mut console, "s");
+
+        let mut completed = Vec::new();
+        for _ in 0..5 {
+            console.handle_key(KeyCode::Tab, None);
+            completed.push(console.input.clone());
+        }
+        assert_eq!(
+            completed,
+            ["screenshot", "shadow", "sleep", "spawn", "screenshot"]
+        );
+
+        // Typing starts over from the new input
+        console.handle_key(KeyCode::Backspace, None);
+        console.handle_key(KeyCode::Tab, None);
+        assert_eq!(console.input, "screenshot");
+
+        // Nothing to complete after the name, or for an unknown prefix
+        type_text(&mut console, " 1");
+        console.handle_key(KeyCode::Tab, None);
+        assert_eq!(console.input, "screenshot 1");
+        console.input = "zz".to_string();
+        console.handle_key(KeyCode::Tab, None);
+        assert_eq!(console.input, "zz");
+    }
+
+    #[test]
+    fn submitted_lines_go_into_the_history() {
+        let mut console = Console::new();
+        for line in ["fov 60", "fov 60", "timescale 2"] {
+            console.input = line.to_string();
+            assert_eq!(
+                console.handle_key(KeyCode::Enter, None).as_deref(),
+                Some(line)
+            );
+        }
+        assert_eq!(console.handle_key(KeyCode::Enter, None), None);
+
+        // Repeats are kept once
+        console.handle_key(KeyCode::ArrowUp, None);
+        assert_eq!(console.input, "timescale 2");
+        console.handle_key(KeyCode::ArrowUp, None);
+        console.handle_key(KeyCode::ArrowUp, None);
+        assert_eq!(console.input, "fov 60");
+        console.handle_key(KeyCode::ArrowDown, None);
+        console.handle_key(KeyCode::ArrowDown, None);
+        assert_eq!(console.input, "");
+    }
+
+    #[test]
+    fn scrolling_stays_within_the_output() {
+        let mut console = Console::new();
+        console.print(&"line\n".repeat(VISIBLE_LINES + 5), LineKind::Output);
+        console.handle_key(KeyCode::PageUp, None);
+        console.handle_key(KeyCode::PageUp, None);
+        assert_eq!(console.scroll, 5);
+        console.handle_key(KeyCode::PageDown, None);
+        console.handle_key(KeyCode::PageDown, None);
+        assert_eq!(console.scroll, 0);
+
+        console.print(&"line\n".repeat(MAX_LINES), LineKind::Output);
+        assert_eq!(console.lines.len(), MAX_LINES);
+    }
+
+    // A script as autoexec.cfg would run it after the level is loaded
+    #[cfg(feature = "test-harness")]
+    #[test]
+    fn scripts_change_the_world() {
+        use crate::prefab::PrefabLibrary;
+        use crate::renderer::test_harness::create_target;
+
+        let mut renderer = match pollster::block_on(Renderer::new_headless(64, 64)) {
+            Ok(renderer) => renderer,
+            Err(error) => {
+                log::warn!("No adapter for the console test: {}", error);
+                return;
+            }
+        };
+        let mut game = Game::new();
+        game.set_prefabs(
+            PrefabLibrary::load(include_bytes!("../../assets/prefabs/default.ron")).unwrap(),
+        );
+        let mut physics = PhysicsWorld::new();
+        let mut settings = ConsoleSettings::default();
+        let mut console = Console::new();
+        let mut hitches = HitchDetector::new();
+        let entity_count = game.get_entity_count();
+
+        let script = "# A test scene\n\
+                      spawn Orb 100 200\n\
+                      spawn Orb -50 0; timescale 0.5 # Slowed down\n\
+                      fov 60\n\
+                      shadow 1024\n\
+                      spawn Dragon 0 0\n\
+                      timescale fast\n\
+                      screenshot\n\
+                      pin 30 40\n\
+                      unpin 7\n\
+                      dumpdiag diag.json\n\
+                      sleep 120\n\
+                      hitches threshold 4\n\
+                      hitches\n";
+        let mut context = ConsoleContext {
+            game: &mut game,
+            renderer: &mut renderer,
+            physics: &mut physics,
+            settings: &mut settings,
+            camera_track: &mut CameraTrackPlayer::default(),
+            network: None,
+            hitches: &mut hitches,
+        };
+        console.run_script("autoexec.cfg", script, &mut context);
+
+        assert_eq!(game.get_entity_count(), entity_count + 2);
+        let mut positions: Vec<Vec2> = physics
+            .get_body_ids()
+            .into_iter()
+            .map(|id| physics.get_state(id).unwrap().position)
+            .collect();
+        positions.sort_by(|a, b| a.x.total_cmp(&b.x));
+        assert_eq!(positions, [Vec2::new(-50.0, 0.0), Vec2::new(100.0, 200.0)]);
+        assert_eq!(game.get_time().get_base_scale(), 0.5);
+        assert_eq!(game.get_camera_settings().fov, 60.0);
+        assert_eq!(renderer.get_shadow_map_size(), 1024);
+        assert!(settings.screenshot_requested);
+        assert_eq!(settings.diagnostics_path.as_deref(), Some("diag.json"));
+        assert_eq!(settings.sleep_ms, Some(120.0));
+        assert_eq!(hitches.get_settings().multiple, 4.0);
+        assert_eq!(console.lines.back().unwrap().text, "No hitches");
+
+        let errors: Vec<&str> = console
+            .lines
+            .iter()
+            .filter(|line| line.kind == LineKind::Error)
+            .map(|line| line.text.as_str())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "autoexec.cfg:6: No prefab named Dragon",
+                "autoexec.cfg:7: <scale> must be a number, got 'fast'",
+                "Usage: timescale <scale>",
+                "autoexec.cfg:10: There is no objective 7",
+            ]
+        );
+        assert!(game.unpin_objective(1));
+
+        // The scene still renders with the new shadow map
+        let target = create_target(&renderer, 64, 64);
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        renderer.render_to_view(&view);
+        let errors = renderer.get_render_device().take_validation_errors();
+        assert!(errors.is_empty(), "wgpu errors: {}", errors.join("\n"));
+    }
+}
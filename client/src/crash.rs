@@ -0,0 +1,226 @@
+// Making failures visible to players. Panics are written to a log file next to the
+// executable, or shown over the canvas in the browser, instead of only going to a console
+// nobody looks at. Frames the renderer could not draw are reported in a banner while the
+// game keeps running.
+
+use shared::math::*;
+
+use crate::renderer::{
+    Renderer, SpriteAnchor, SpriteSpace, TextAlignment,
+    render_data::{SpriteRenderJob, TextRenderJob},
+    resources::get_handle,
+};
+
+const BANNER_LIFETIME: f32 = 8.0;
+const BANNER_LAYER: u32 = u16::MAX as u32 - 1; // Below the software cursor
+const BANNER_SIZE: Vec2 = Vec2::new(1000.0, 36.0);
+const BANNER_TOP: f32 = 90.0;
+
+// Chains the hook that was installed before, e.g. the one printing to the console
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        previous(info);
+        report_panic(&format_panic_report(info));
+    }));
+}
+
+fn format_panic_report(info: &std::panic::PanicHookInfo) -> String {
+    let message = info.payload_as_str().unwrap_or("Box<dyn Any>");
+    let location = info
+        .location()
+        .map_or("an unknown location".to_string(), |location| {
+            location.to_string()
+        });
+    format!(
+        "Rusty Rift {} panicked at {}:\n{}\n\nBacktrace:\n{}",
+        env!("CARGO_PKG_VERSION"),
+        location,
+        message,
+        std::backtrace::Backtrace::force_capture()
+    )
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn report_panic(report: &str) {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let path = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.to_path_buf()))
+        .unwrap_or_default()
+        .join(get_crash_log_name(seconds));
+
+    match std::fs::write(&path, report) {
+        Ok(()) => eprintln!("The crash report was written to {}", path.display()),
+        Err(error) => eprintln!("Failed to write {}: {}", path.display(), error),
+    }
+}
+
+// The console already has it, the page shows it over the frozen canvas
+#[cfg(target_arch = "wasm32")]
+fn report_panic(report: &str) {
+    let Some(document) = wgpu::web_sys::window().and_then(|window| window.document()) else {
+        return;
+    };
+    let Some(body) = document.body() else {
+        return;
+    };
+    let Ok(overlay) = document.create_element("pre") else {
+        return;
+    };
+    let _ = overlay.set_attribute(
+        "style",
+        "position: fixed; inset: 0; margin: 0; padding: 24px; overflow: auto; z-index: 1000; \
+         background: rgba(20, 0, 0, 0.9); color: #ffb0b0; font: 14px monospace; \
+         white-space: pre-wrap;",
+    );
+    overlay.set_text_content(Some(&format!(
+        "The game crashed, reloading the page starts it again.\n\n{}",
+        report
+    )));
+    let _ = body.append_child(&overlay);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn get_crash_log_name(seconds: u64) -> String {
+    format!("crash-{}.log", seconds)
+}
+
+// Something that went wrong without stopping the game, shown at the top of the screen. A
+// message that keeps coming, e.g. every frame, is counted instead of repeated.
+#[derive(Default)]
+pub struct ErrorBanner {
+    message: Option<String>,
+    repeat_count: u32,
+    age: f32,
+}
+
+impl ErrorBanner {
+    pub fn show(&mut self, message: String) {
+        if self.message.as_ref() == Some(&message) {
+            self.repeat_count += 1;
+        } else {
+            self.message = Some(message);
+            self.repeat_count = 1;
+        }
+        self.age = 0.0;
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.age += dt;
+        if self.age >= BANNER_LIFETIME {
+            self.message = None;
+        }
+    }
+
+    fn get_text(&self) -> Option<String> {
+        let message = self.message.as_ref()?;
+        // Only the first line, validation errors can be long
+        let first_line = message.lines().next().unwrap_or_default();
+        Some(match self.repeat_count {
+            1 => first_line.to_string(),
+            count => format!("{} ({} times)", first_line, count),
+        })
+    }
+
+    pub fn render(&self, renderer: &mut Renderer) {
+        let Some(text) = self.get_text() else {
+            return;
+        };
+
+        // The text is on the layer above, sprites of other materials aren't ordered
+        renderer.submit(&SpriteRenderJob {
+            anchor: SpriteAnchor::TopCenter,
+            space: SpriteSpace::Absolute,
+            ..SpriteRenderJob::solid(
+                Vec2::new(-0.5 * BANNER_SIZE.x, BANNER_TOP),
+                BANNER_SIZE,
+                Vec4::new(0.45, 0.05, 0.05, 0.85),
+                BANNER_LAYER - 1,
+            )
+        });
+        renderer.submit(&TextRenderJob {
+            font_atlas: get_handle("DebugFont"),
+            font_material: get_handle("DebugFontMaterial"),
+            text: text.into(),
+            position: Vec2::new(0.0, BANNER_TOP + 24.0),
+            size: 18.0,
+            color: Vec4::new(1.0, 0.85, 0.85, 1.0),
+            layer: BANNER_LAYER,
+            anchor: SpriteAnchor::TopCenter,
+            space: SpriteSpace::Absolute,
+            alignment: TextAlignment::Center,
+        });
+    }
+}
+
+// One of each kind of failure, raised on purpose so the reporting can be checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    Panic,
+    Surface,    // A lost surface, as if the GPU was reset
+    Validation, // A buffer wgpu rejects
+    DeviceLost, // A destroyed device, as if the driver was reset
+}
+
+impl FailureKind {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "panic" => Some(Self::Panic),
+            "surface" => Some(Self::Surface),
+            "validation" => Some(Self::Validation),
+            "device" => Some(Self::DeviceLost),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_errors_are_counted_until_they_expire() {
+        let mut banner = ErrorBanner::default();
+        assert_eq!(banner.get_text(), None);
+
+        banner.show("GPU validation error: first\nmore details".to_string());
+        assert_eq!(
+            banner.get_text().as_deref(),
+            Some("GPU validation error: first")
+        );
+        banner.show("GPU validation error: first\nmore details".to_string());
+        banner.show("GPU validation error: first\nmore details".to_string());
+        assert_eq!(
+            banner.get_text().as_deref(),
+            Some("GPU validation error: first (3 times)")
+        );
+
+        // Another error replaces it, and each one keeps it up for the whole lifetime
+        banner.show("Surface error: lost".to_string());
+        banner.update(BANNER_LIFETIME - 1.0);
+        banner.show("Surface error: lost".to_string());
+        banner.update(BANNER_LIFETIME - 1.0);
+        assert_eq!(
+            banner.get_text().as_deref(),
+            Some("Surface error: lost (2 times)")
+        );
+        banner.update(1.0);
+        assert_eq!(banner.get_text(), None);
+    }
+
+    #[test]
+    fn failure_kinds_and_log_names() {
+        assert_eq!(FailureKind::parse("panic"), Some(FailureKind::Panic));
+        assert_eq!(FailureKind::parse("surface"), Some(FailureKind::Surface));
+        assert_eq!(
+            FailureKind::parse("validation"),
+            Some(FailureKind::Validation)
+        );
+        assert_eq!(FailureKind::parse("device"), Some(FailureKind::DeviceLost));
+        assert_eq!(FailureKind::parse("segfault"), None);
+        assert_eq!(get_crash_log_name(1700000000), "crash-1700000000.log");
+    }
+}
@@ -0,0 +1,266 @@
+// The mouse cursor: whether the OS cursor is grabbed and shown, and the software cursor
+// drawn as a sprite in its place. The game only picks the CursorKind, the textures are
+// generated here so there are no assets to ship.
+
+use shared::math::*;
+use winit::window::{CursorGrabMode, Window};
+
+use crate::renderer::{
+    Renderer, SpriteAnchor, SpriteSpace, render_data::SpriteRenderJob, resources::get_handle,
+};
+
+const CURSOR_SIZE: u32 = 32; // Pixels, drawn unscaled
+const CURSOR_LAYER: u32 = u16::MAX as u32; // The sprite sorting clamps layers to this
+
+#[allow(dead_code)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CursorKind {
+    #[default]
+    Default,
+    Attack,   // Over something that can be attacked
+    NoTarget, // Over something that can't be targeted
+}
+
+impl CursorKind {
+    const ALL: [CursorKind; 3] = [
+        CursorKind::Default,
+        CursorKind::Attack,
+        CursorKind::NoTarget,
+    ];
+
+    fn get_material_name(self) -> &'static str {
+        match self {
+            CursorKind::Default => "CursorDefaultMaterial",
+            CursorKind::Attack => "CursorAttackMaterial",
+            CursorKind::NoTarget => "CursorNoTargetMaterial",
+        }
+    }
+
+    // The pixel of the texture that is at the mouse position
+    fn get_hotspot(self) -> Vec2 {
+        match self {
+            CursorKind::Attack => Vec2::splat(CURSOR_SIZE as f32 * 0.5),
+            _ => Vec2::ONE,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CursorGrab {
+    #[default]
+    None,
+    Confined, // Kept inside the window, for scrolling at the edges
+    Locked,   // Kept in place, only the mouse deltas are reported
+}
+
+// What was asked for, applied to the window while it has focus. Without focus the cursor
+// is always released and shown so alt-tab gives it back to the desktop.
+#[derive(Debug)]
+pub struct CursorState {
+    pub grab: CursorGrab,
+    pub mouse_look: bool, // Locked for the debug camera, over the grab the game asked for
+    pub visible: bool,
+    pub software: bool, // Hides the OS cursor and draws the cursor as a sprite
+    pub kind: CursorKind,
+    pub focused: bool,
+}
+
+impl Default for CursorState {
+    fn default() -> Self {
+        Self {
+            grab: CursorGrab::None,
+            mouse_look: false,
+            visible: true,
+            software: false,
+            kind: CursorKind::Default,
+            focused: true,
+        }
+    }
+}
+
+impl CursorState {
+    // The grab and OS cursor visibility for the window
+    pub fn get_window_state(&self) -> (CursorGrab, bool) {
+        if !self.focused {
+            return (CursorGrab::None, true);
+        }
+        let grab = if self.mouse_look {
+            CursorGrab::Locked
+        } else {
+            self.grab
+        };
+        let visible = self.visible && !self.software && grab != CursorGrab::Locked;
+        (grab, visible)
+    }
+
+    pub fn is_software_drawn(&self) -> bool {
+        let (grab, _) = self.get_window_state();
+        self.software && self.visible && self.focused && grab != CursorGrab::Locked
+    }
+}
+
+// Confined isn't supported everywhere (macOS) and Locked isn't either (X11), so each mode
+// falls back to the closest one that works. Returns the mode that was applied.
+pub fn apply_cursor_grab(window: &Window, grab: CursorGrab) -> CursorGrab {
+    let modes: &[(CursorGrab, CursorGrabMode)] = match grab {
+        CursorGrab::None => &[(CursorGrab::None, CursorGrabMode::None)],
+        CursorGrab::Confined => &[(CursorGrab::Confined, CursorGrabMode::Confined)],
+        CursorGrab::Locked => &[
+            (CursorGrab::Locked, CursorGrabMode::Locked),
+            (CursorGrab::Confined, CursorGrabMode::Confined),
+        ],
+    };
+    for &(applied, mode) in modes {
+        match window.set_cursor_grab(mode) {
+            Ok(()) => return applied,
+            Err(e) => log::warn!("Failed to grab the cursor as {:?}: {}", mode, e),
+        }
+    }
+    if grab != CursorGrab::None {
+        let _ = window.set_cursor_grab(CursorGrabMode::None);
+    }
+    CursorGrab::None
+}
+
+// Creates the sprite materials of the software cursors
+pub fn create_cursor_materials(renderer: &mut Renderer) {
+    for kind in CursorKind::ALL {
+        let name = kind.get_material_name();
+        let texture = renderer.load_texture(name, &build_cursor_texture_bytes(kind));
+        renderer.create_sprite_material(name, texture);
+    }
+}
+
+// Mouse position in 0..1 of the window
+pub fn submit_software_cursor(renderer: &mut Renderer, kind: CursorKind, mouse_position: Vec2) {
+    let screen_size = renderer.get_screen_size();
+    renderer.submit(&SpriteRenderJob {
+        position: get_cursor_position(kind, mouse_position, screen_size),
+        size: Vec2::splat(CURSOR_SIZE as f32),
+        material: get_handle(kind.get_material_name()),
+        layer: CURSOR_LAYER,
+        anchor: SpriteAnchor::TopLeft,
+        space: SpriteSpace::Absolute,
+        ..Default::default()
+    });
+}
+
+// The top left of the sprite, in pixels
+fn get_cursor_position(kind: CursorKind, mouse_position: Vec2, screen_size: Vec2) -> Vec2 {
+    (mouse_position * screen_size - kind.get_hotspot()).round()
+}
+
+fn build_cursor_texture_bytes(kind: CursorKind) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for value in [CURSOR_SIZE, CURSOR_SIZE, 1, 4, 1, 1] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    for y in 0..CURSOR_SIZE {
+        for x in 0..CURSOR_SIZE {
+            let point = Vec2::new(x as f32, y as f32) + 0.5;
+            bytes.extend_from_slice(&get_cursor_pixel(kind, point));
+        }
+    }
+    bytes
+}
+
+// Shapes with a dark outline, so they read on light and dark ground
+fn get_cursor_pixel(kind: CursorKind, point: Vec2) -> [u8; 4] {
+    const OUTLINE: [u8; 4] = [20, 20, 20, 255];
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
+    const RED: [u8; 4] = [230, 40, 30, 255];
+
+    match kind {
+        CursorKind::Default | CursorKind::NoTarget => {
+            if kind == CursorKind::NoTarget {
+                // A slash through the lower right
+                let slash = (point - Vec2::new(22.0, 22.0)).dot(Vec2::new(1.0, 1.0).normalize());
+                let center = point.distance(Vec2::new(22.0, 22.0));
+                if slash.abs() < 1.5 && center < 8.0 {
+                    return RED;
+                }
+            }
+            let fill = match kind {
+                CursorKind::NoTarget => [150, 150, 150, 255],
+                _ => [250, 250, 250, 255],
+            };
+            match get_arrow_distance(point) {
+                distance if distance <= 0.0 => fill,
+                distance if distance <= 1.5 => OUTLINE,
+                _ => CLEAR,
+            }
+        }
+        CursorKind::Attack => {
+            // A ring around the hotspot and a dot on it
+            let distance = point.distance(Vec2::splat(CURSOR_SIZE as f32 * 0.5));
+            match distance {
+                d if (9.0..12.0).contains(&d) => RED,
+                d if (7.5..13.5).contains(&d) || d < 1.5 => OUTLINE,
+                _ => CLEAR,
+            }
+        }
+    }
+}
+
+// Negative inside the arrow, roughly the distance to its edge outside
+fn get_arrow_distance(point: Vec2) -> f32 {
+    // Tip at the top left, the left edge straight down and the right edge at 45 degrees
+    const CORNERS: [Vec2; 3] = [
+        Vec2::new(1.0, 1.0),
+        Vec2::new(1.0, 22.0),
+        Vec2::new(16.0, 16.0),
+    ];
+    let mut distance = f32::MIN;
+    for index in 0..3 {
+        let from = CORNERS[index];
+        let to = CORNERS[(index + 1) % 3];
+        let edge = (to - from).normalize();
+        let outward = Vec2::new(-edge.y, edge.x);
+        distance = distance.max((point - from).dot(outward));
+    }
+    distance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn losing_focus_releases_the_cursor() {
+        let mut cursor = CursorState {
+            grab: CursorGrab::Confined,
+            software: true,
+            ..Default::default()
+        };
+        assert_eq!(cursor.get_window_state(), (CursorGrab::Confined, false));
+        assert!(cursor.is_software_drawn());
+
+        cursor.focused = false;
+        assert_eq!(cursor.get_window_state(), (CursorGrab::None, true));
+        assert!(!cursor.is_software_drawn());
+
+        // Regaining focus restores what was asked for
+        cursor.focused = true;
+        cursor.mouse_look = true;
+        assert_eq!(cursor.get_window_state(), (CursorGrab::Locked, false));
+        assert!(!cursor.is_software_drawn());
+
+        cursor.mouse_look = false;
+        assert_eq!(cursor.get_window_state(), (CursorGrab::Confined, false));
+    }
+
+    #[test]
+    fn the_hotspot_is_at_the_mouse() {
+        let screen_size = Vec2::new(1600.0, 900.0);
+        let mouse = Vec2::new(0.5, 0.25);
+        for kind in CursorKind::ALL {
+            let position = get_cursor_position(kind, mouse, screen_size);
+            assert_eq!(position + kind.get_hotspot(), Vec2::new(800.0, 225.0));
+
+            // The hotspot is on the drawn shape
+            let hotspot = kind.get_hotspot() + 0.5;
+            assert_ne!(get_cursor_pixel(kind, hotspot)[3], 0, "{:?}", kind);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+// Deaths of skinned entities, without any physics. The pose the entity died in is frozen
+// and blended towards a lying one while the body sinks a little into the ground. The lying
+// pose tips the roots over backwards about the feet and relaxes the rest of the hierarchy:
+// every bone's rotation is damped towards the one it has relative to its parent in the bind
+// pose, more so further down the chain, so arms and heads go limp while the hips keep most
+// of the fall. The corpse lingers on the ground and fades out before it is despawned.
+
+use shared::math::*;
+
+use crate::{
+    renderer::{BoneInfo, animation::Pose},
+    tint::TintAnimator,
+};
+
+pub const SETTLE_DURATION: f32 = 0.7; // Of the blend to the lying pose and the sink
+pub const SETTLE_EASING: Easing = Easing::QuadIn; // Falls faster as it goes
+const LINGER_TIME: f32 = 2.0; // On the ground after the settle, before the fade
+const FADE_TIME: f32 = 1.0;
+const SINK_DEPTH: f32 = 5.0;
+const RELAX_ITERATIONS: usize = 3;
+const RELAX_STRENGTH: f32 = 0.35; // Of the first child bones, deeper ones relax more
+
+// Tips the up axis over onto the ground, towards the back. Both are in the space of the
+// mesh, the ground normal is its up.
+pub fn get_fall_rotation(ground_normal: Vec3, back: Vec3) -> Quat {
+    let lying = back.reject_from(ground_normal).normalize_or_zero();
+    if lying == Vec3::ZERO {
+        return Quat::IDENTITY;
+    }
+    Quat::from_rotation_arc(ground_normal.normalize(), lying)
+}
+
+// The rotation of every bone relative to its parent in the bind pose, indexed by bone id.
+// Roots get theirs relative to the mesh.
+fn get_rest_rotations(bones: &[BoneInfo]) -> Vec<Quat> {
+    let mut globals = vec![Mat4::IDENTITY; bones.len()];
+    let mut rotations = vec![Quat::IDENTITY; bones.len()];
+    // Parents come before their children
+    for bone in bones {
+        let index = bone.id as usize;
+        let global = Mat4::from_cols_array(&bone.offset_matrix).inverse();
+        let local = if bone.parent_id != -1 {
+            globals[bone.parent_id as usize].inverse() * global
+        } else {
+            global
+        };
+        globals[index] = global;
+        rotations[index] = local.to_scale_rotation_translation().1.normalize();
+    }
+    rotations
+}
+
+// One relaxation step. Children of the roots move their rotation by the strength towards
+// their rest rotation, the bones below them by more, up to all the way at the ends of
+// long chains. The roots are left where they are.
+pub fn relax_pose(bones: &[BoneInfo], pose: &mut Pose, strength: f32) {
+    let rest_rotations = get_rest_rotations(bones);
+    let mut depths = vec![0; bones.len()];
+    for bone in bones {
+        if bone.parent_id == -1 {
+            continue;
+        }
+        let index = bone.id as usize;
+        let depth = depths[bone.parent_id as usize] + 1;
+        depths[index] = depth;
+
+        let weight = 1.0 - (1.0 - strength).powi(depth);
+        let transform = &mut pose.transforms[index];
+        transform.rotation = transform
+            .rotation
+            .slerp(rest_rotations[index], weight)
+            .normalize();
+    }
+}
+
+// The pose the body ends up lying in, the fall is applied to the roots about the origin of
+// the mesh so the feet stay where they are
+pub fn get_lying_pose(bones: &[BoneInfo], pose: &Pose, fall: Quat) -> Pose {
+    let mut lying = pose.clone();
+    for bone in bones.iter().filter(|bone| bone.parent_id == -1) {
+        let transform = &mut lying.transforms[bone.id as usize];
+        transform.position = fall * transform.position;
+        transform.rotation = (fall * transform.rotation).normalize();
+    }
+    for _ in 0..RELAX_ITERATIONS {
+        relax_pose(bones, &mut lying, RELAX_STRENGTH);
+    }
+    lying
+}
+
+pub struct DeathSettle {
+    origin: Vec3,                 // Where the entity died
+    frozen: Option<(Pose, Pose)>, // The pose it died in and the lying one, on the first frame
+    progress: f32,                // Of the settle, tweened from 0 to 1
+    elapsed: f32,
+    fading: bool,
+}
+
+impl DeathSettle {
+    pub fn new(origin: Vec3) -> Self {
+        Self {
+            origin,
+            frozen: None,
+            progress: 0.0,
+            elapsed: 0.0,
+            fading: false,
+        }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.is_some()
+    }
+
+    pub fn freeze(&mut self, bones: &[BoneInfo], pose: &Pose, fall: Quat) {
+        let lying = get_lying_pose(bones, pose, fall);
+        self.frozen = Some((pose.clone(), lying));
+    }
+
+    pub fn set_progress(&mut self, progress: f32) {
+        self.progress = progress;
+    }
+
+    pub fn get_position(&self) -> Vec3 {
+        self.origin - Vec3::Y * SINK_DEPTH * self.progress
+    }
+
+    // Leaves the pose alone until it has been frozen
+    pub fn apply(&self, pose: &mut Pose) {
+        if let Some((frozen, lying)) = &self.frozen
+            && frozen.transforms.len() == pose.transforms.len()
+        {
+            Pose::blend(frozen, lying, self.progress, pose);
+        }
+    }
+
+    // True once the corpse has faded out
+    pub fn update(&mut self, dt: f32, tint: &mut TintAnimator) -> bool {
+        self.elapsed += dt;
+        let fade_start = SETTLE_DURATION + LINGER_TIME;
+        if !self.fading && self.elapsed >= fade_start {
+            tint.fade_to(0.0, FADE_TIME);
+            self.fading = true;
+        }
+        self.elapsed >= fade_start + FADE_TIME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A straight chain up the y axis, every bone bent a little off its parent
+    fn create_chain(length: usize) -> (Vec<BoneInfo>, Pose) {
+        let bones: Vec<_> = (0..length)
+            .map(|index| BoneInfo {
+                id: index as i32,
+                parent_id: index as i32 - 1,
+                offset_matrix: Mat4::from_translation(-Vec3::Y * index as f32 * 10.0)
+                    .to_cols_array(),
+            })
+            .collect();
+        let mut pose = Pose::new(length);
+        for (index, transform) in pose.transforms.iter_mut().enumerate() {
+            if index > 0 {
+                transform.position = Vec3::Y * 10.0;
+            }
+            transform.rotation = Quat::from_rotation_z(0.6);
+        }
+        (bones, pose)
+    }
+
+    fn get_bend(pose: &Pose, index: usize) -> f32 {
+        pose.transforms[index]
+            .rotation
+            .angle_between(Quat::IDENTITY)
+    }
+
+    #[test]
+    fn end_bones_converge_towards_their_parents() {
+        let (bones, mut pose) = create_chain(5);
+        let end = bones.len() - 1;
+
+        let mut last_bend = get_bend(&pose, end);
+        for _ in 0..RELAX_ITERATIONS {
+            relax_pose(&bones, &mut pose, RELAX_STRENGTH);
+            let bend = get_bend(&pose, end);
+            assert!(bend < last_bend, "{} after {}", bend, last_bend);
+            last_bend = bend;
+        }
+        assert!(last_bend < 0.01);
+        // Deeper bones relax faster and the root keeps its rotation
+        assert!(get_bend(&pose, end) < get_bend(&pose, 1));
+        assert!((get_bend(&pose, 0) - 0.6).abs() < 1e-4);
+    }
+
+    #[test]
+    fn the_lying_pose_tips_the_root_onto_its_back() {
+        let (bones, pose) = create_chain(3);
+        let fall = get_fall_rotation(Vec3::Y, -Vec3::Z);
+        let lying = get_lying_pose(&bones, &pose, fall);
+
+        let up = lying.transforms[0].rotation * (Quat::from_rotation_z(-0.6) * Vec3::Y);
+        assert!(up.distance(-Vec3::Z) < 1e-4, "{}", up);
+        // The original pose is untouched
+        assert_eq!(pose.transforms[2].rotation, Quat::from_rotation_z(0.6));
+    }
+
+    #[test]
+    fn the_corpse_sinks_and_fades_out() {
+        let mut settle = DeathSettle::new(Vec3::new(10.0, 0.0, 20.0));
+        let mut tint = TintAnimator::default();
+
+        settle.set_progress(1.0);
+        assert_eq!(settle.get_position(), Vec3::new(10.0, -SINK_DEPTH, 20.0));
+        assert!(!settle.update(SETTLE_DURATION + LINGER_TIME, &mut tint));
+        tint.update(FADE_TIME);
+        assert!(settle.update(FADE_TIME, &mut tint));
+    }
+}
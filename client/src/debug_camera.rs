@@ -0,0 +1,128 @@
+// A free-fly camera for looking around while debugging rendering (F1). It only overrides
+// what the renderer draws from, the game and its camera keep updating underneath. WASD
+// moves, Q and E go down and up, the mouse looks around while the cursor is grabbed.
+
+use glam::EulerRot;
+use shared::math::*;
+
+use crate::input::{InputAction, InputState};
+
+const SPEED: f32 = 800.0; // Units per second
+const FAST_FACTOR: f32 = 4.0; // While shift is held
+const SLOW_FACTOR: f32 = 0.25; // While ctrl is held
+const LOOK_SENSITIVITY: f32 = 0.003; // Radians per pixel of mouse movement
+const MAX_PITCH: f32 = 1.55; // Just short of straight up or down
+
+#[derive(Default)]
+pub struct DebugCamera {
+    active: bool,
+    position: Vec3,
+    yaw: f32, // Around y, zero looks down -z
+    pitch: f32,
+}
+
+impl DebugCamera {
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // Starts where the gameplay camera is, so switching over doesn't jump
+    pub fn set_active(&mut self, active: bool, position: Vec3, rotation: Quat) {
+        if active && !self.active {
+            let (yaw, pitch, _) = rotation.to_euler(EulerRot::YXZ);
+            self.position = position;
+            self.yaw = yaw;
+            self.pitch = pitch.clamp(-MAX_PITCH, MAX_PITCH);
+        }
+        self.active = active;
+    }
+
+    pub fn update(&mut self, dt: f32, input_state: &InputState) {
+        if !self.active {
+            return;
+        }
+
+        let look = input_state.get_mouse_delta() * LOOK_SENSITIVITY;
+        self.yaw = (self.yaw - look.x).rem_euclid(std::f32::consts::TAU);
+        self.pitch = (self.pitch - look.y).clamp(-MAX_PITCH, MAX_PITCH);
+
+        let axis = |positive: InputAction, negative: InputAction| {
+            input_state.is_down(positive) as i32 as f32
+                - input_state.is_down(negative) as i32 as f32
+        };
+        let local = Vec3::new(
+            axis(InputAction::FlyRight, InputAction::FlyLeft),
+            axis(InputAction::FlyUp, InputAction::FlyDown),
+            axis(InputAction::FlyBack, InputAction::FlyForward),
+        );
+
+        let mut speed = SPEED;
+        if input_state.is_down(InputAction::FlyFast) {
+            speed *= FAST_FACTOR;
+        }
+        if input_state.is_down(InputAction::FlySlow) {
+            speed *= SLOW_FACTOR;
+        }
+        // Up and down stay vertical, the rest follows where the camera looks
+        let rotation = self.get_rotation();
+        let direction = rotation * Vec3::new(local.x, 0.0, local.z) + Vec3::Y * local.y;
+        self.position += direction.normalize_or_zero() * speed * dt;
+    }
+
+    pub fn get_position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn get_rotation(&self) -> Quat {
+        Quat::from_euler(EulerRot::YXZ, self.yaw, self.pitch, 0.0)
+    }
+
+    // Where the view hits the ground, or the point below the camera when looking up
+    pub fn get_ground_point(&self) -> Vec3 {
+        let forward = self.get_rotation() * Vec3::NEG_Z;
+        if forward.y < -1e-3 && self.position.y > 0.0 {
+            return self.position + forward * (self.position.y / -forward.y);
+        }
+        self.position.with_y(0.0)
+    }
+
+    pub fn get_info(&self) -> String {
+        let position = self.position;
+        format!(
+            "Debug camera at ({:.0}, {:.0}, {:.0}), yaw {:.0}, pitch {:.0} | Shift fast, Ctrl slow, T moves the game camera here",
+            position.x,
+            position.y,
+            position.z,
+            self.yaw.to_degrees(),
+            self.pitch.to_degrees()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_from_the_gameplay_view_and_flies_where_it_looks() {
+        // Like the gameplay camera, looking down at the ground in front of it
+        let rotation = Quat::from_rotation_x(-45f32.to_radians());
+        let mut camera = DebugCamera::default();
+        camera.set_active(true, Vec3::new(0.0, 1000.0, 1000.0), rotation);
+        assert!(camera.get_rotation().angle_between(rotation) < 1e-4);
+        assert!(camera.get_ground_point().distance(Vec3::ZERO) < 1e-2);
+
+        // Turned 90 degrees to the left, forward is towards -x and still down
+        let mut input_state = InputState::new();
+        input_state.add_mouse_delta(Vec2::new(
+            -std::f32::consts::FRAC_PI_2 / LOOK_SENSITIVITY,
+            0.0,
+        ));
+        input_state.set_action(InputAction::FlyForward, true);
+        camera.update(0.5, &input_state);
+        let moved = camera.get_position() - Vec3::new(0.0, 1000.0, 1000.0);
+        let expected = Vec3::new(-1.0, -1.0, 0.0).normalize();
+        assert!(moved.normalize().distance(expected) < 1e-3);
+        assert!((moved.length() - SPEED * 0.5).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,225 @@
+// What a bug report about rendering needs: the adapter and what the device was created
+// with, the fallbacks the renderer takes on it, the settings, the frame stats of the last
+// minutes and the last lines logged. Shown on the second page of the F3 overlay, written
+// to a file by the console command dumpdiag. Parts missing when it is taken, e.g. before
+// the renderer exists, are left empty.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+};
+
+use serde::Serialize;
+
+use crate::renderer::{DeviceDiagnostics, FrameStats, Renderer};
+
+pub const MAX_LOG_LINES: usize = 200;
+pub const MAX_FRAME_SUMMARIES: usize = 120;
+
+// Filled in by LogRing, the oldest lines are dropped
+static LOG_LINES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+// Keeps a copy of the lines the logger it wraps writes
+pub struct LogRing {
+    inner: Box<dyn log::Log>,
+}
+
+impl LogRing {
+    // Instead of the wrapped logger, which filters to the level
+    pub fn install(
+        inner: Box<dyn log::Log>,
+        level: log::LevelFilter,
+    ) -> Result<(), log::SetLoggerError> {
+        log::set_boxed_logger(Box::new(Self { inner }))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+}
+
+impl log::Log for LogRing {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.inner.log(record);
+        push_log_line(format!(
+            "[{} {}] {}",
+            record.level(),
+            record.target(),
+            record.args()
+        ));
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+fn push_log_line(line: String) {
+    // Poisoned by a panic while logging, the lines are still whole
+    let mut lines = LOG_LINES.lock().unwrap_or_else(|error| error.into_inner());
+    if lines.len() == MAX_LOG_LINES {
+        lines.pop_front();
+    }
+    lines.push_back(line);
+}
+
+// Oldest first
+pub fn get_log_lines() -> Vec<String> {
+    let lines = LOG_LINES.lock().unwrap_or_else(|error| error.into_inner());
+    lines.iter().cloned().collect()
+}
+
+// Up to the count of the newest lines, oldest first, without copying them
+pub fn visit_last_log_lines(count: usize, mut visit: impl FnMut(&str)) {
+    let lines = LOG_LINES.lock().unwrap_or_else(|error| error.into_inner());
+    for line in lines.iter().skip(lines.len().saturating_sub(count)) {
+        visit(line);
+    }
+}
+
+// Of one update interval of the performance metrics
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct FrameSummary {
+    pub avg_fps: u32,
+    pub max_ms: f32,
+    pub cpu_wait_ms: f32,
+    pub static_batches: usize,
+    pub static_instances: usize,
+    pub skeletal_instances: usize,
+    pub bones: usize,
+    pub sprite_batches: usize,
+    pub sprite_instances: usize,
+    pub persistent_instances: usize,
+}
+
+impl FrameSummary {
+    pub fn new(avg_fps: u32, max_ms: f32, cpu_wait_ms: f32, stats: &FrameStats) -> Self {
+        Self {
+            avg_fps,
+            max_ms,
+            cpu_wait_ms,
+            static_batches: stats.static_batch_count,
+            static_instances: stats.static_instance_count,
+            skeletal_instances: stats.skeletal_instance_count,
+            bones: stats.bone_count,
+            sprite_batches: stats.sprite_batch_count,
+            sprite_instances: stats.sprite_instance_count,
+            persistent_instances: stats.persistent_instance_count,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DiagnosticsDump {
+    pub version: String,
+    pub device: Option<DeviceDiagnostics>, // None without a renderer
+    pub settings: BTreeMap<String, String>,
+    pub frames: Vec<FrameSummary>, // Oldest first
+    pub log: Vec<String>,
+}
+
+impl DiagnosticsDump {
+    // Only the log, the rest is added by what exists
+    pub fn new() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            log: get_log_lines(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_renderer(mut self, renderer: &Renderer) -> Self {
+        self.device = Some(renderer.get_device_diagnostics());
+        self.settings = get_render_settings(renderer);
+        self
+    }
+
+    pub fn with_frames(mut self, frames: impl IntoIterator<Item = FrameSummary>) -> Self {
+        self.frames = frames.into_iter().collect();
+        self
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+fn get_render_settings(renderer: &Renderer) -> BTreeMap<String, String> {
+    let scene_size = renderer.get_scene_size();
+    let light = renderer.get_directional_light();
+    [
+        ("antialiasing", format!("{:?}", renderer.get_antialiasing())),
+        ("debug_view", format!("{:?}", renderer.get_debug_view())),
+        ("render_scale", renderer.get_render_scale().to_string()),
+        ("scene_size", format!("{}x{}", scene_size.x, scene_size.y)),
+        ("vsync", renderer.is_vsync().to_string()),
+        ("low_latency", renderer.is_low_latency().to_string()),
+        (
+            "frames_in_flight",
+            renderer.get_max_frames_in_flight().to_string(),
+        ),
+        ("gpu_culling", renderer.is_gpu_culling().to_string()),
+        ("shadows", light.shadows_enabled.to_string()),
+        (
+            "shadow_map_size",
+            renderer.get_shadow_map_size().to_string(),
+        ),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_diagnostics(dump: &DiagnosticsDump, path: &std::path::Path) -> anyhow::Result<()> {
+    use anyhow::Context;
+
+    let json = dump.to_json()?;
+    std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_log_keeps_the_last_lines() {
+        for index in 0..MAX_LOG_LINES + 10 {
+            push_log_line(format!("line {}", index));
+        }
+        let lines = get_log_lines();
+        assert_eq!(lines.len(), MAX_LOG_LINES);
+        assert_eq!(
+            lines.last().unwrap(),
+            &format!("line {}", MAX_LOG_LINES + 9)
+        );
+    }
+
+    #[test]
+    fn dumps_without_a_renderer_serialize() {
+        let dump = DiagnosticsDump::new();
+        let json: serde_json::Value = serde_json::from_str(&dump.to_json().unwrap()).unwrap();
+        assert!(json["device"].is_null());
+        assert_eq!(json["frames"].as_array().unwrap().len(), 0);
+
+        // A device without fallbacks or limits, and some frames
+        let dump = DiagnosticsDump {
+            device: Some(DeviceDiagnostics::default()),
+            ..DiagnosticsDump::new()
+        }
+        .with_frames([FrameSummary::default(); 3]);
+        let json: serde_json::Value = serde_json::from_str(&dump.to_json().unwrap()).unwrap();
+        assert_eq!(json["device"]["fallbacks"].as_array().unwrap().len(), 0);
+        assert_eq!(json["frames"].as_array().unwrap().len(), 3);
+        assert!(
+            DeviceDiagnostics::default()
+                .get_lines()
+                .contains(&"No fallbacks".to_string())
+        );
+    }
+}
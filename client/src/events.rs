@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+
+use crate::{components::Entity, status_effects::StatusKind, tween::TweenTarget};
+
+// Pushed by the fixed update systems and drained once per rendered frame
+const MAX_EVENTS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GameEvent {
+    AbilityCastStarted {
+        caster: Entity,
+        slot: usize,
+    },
+    AbilityExecuted {
+        caster: Entity,
+        slot: usize,
+    },
+    AbilityInterrupted {
+        caster: Entity,
+        slot: usize, // Also when the target was gone by the end of the cast
+    },
+    // Landed without doing anything and refunded, e.g. a blink with nowhere free to go
+    AbilityFailed {
+        caster: Entity,
+        slot: usize,
+    },
+    DamageDealt {
+        source: Option<Entity>, // None for damage over time
+        target: Entity,
+        amount: f32,
+        fatal: bool,
+    },
+    EffectApplied {
+        target: Entity,
+        kind: StatusKind,
+    },
+    EntityDied {
+        entity: Entity,
+        killer: Option<Entity>,
+    },
+    ProjectileHit {
+        projectile: Entity,
+        target: Entity,
+    },
+    TweenFinished {
+        target: TweenTarget,
+    },
+}
+
+// A bounded queue, when nobody drains it for a while the oldest events are dropped
+#[derive(Default)]
+pub struct GameEvents {
+    queue: VecDeque<GameEvent>,
+    dropped: usize,
+}
+
+impl GameEvents {
+    pub fn push(&mut self, event: GameEvent) {
+        if self.queue.len() >= MAX_EVENTS {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+        self.queue.push_back(event);
+    }
+
+    // In the order they were pushed. Consumers get the drained slice so every one of
+    // them sees each event exactly once.
+    pub fn drain(&mut self) -> Vec<GameEvent> {
+        if self.dropped > 0 {
+            log::warn!("Dropped {} game events", self.dropped);
+            self.dropped = 0;
+        }
+        self.queue.drain(..).collect()
+    }
+
+    // Damage followed by a death when it was fatal
+    pub fn push_damage(
+        &mut self,
+        source: Option<Entity>,
+        target: Entity,
+        amount: f32,
+        fatal: bool,
+    ) {
+        self.push(GameEvent::DamageDealt {
+            source,
+            target,
+            amount,
+            fatal,
+        });
+        if fatal {
+            self.push(GameEvent::EntityDied {
+                entity: target,
+                killer: source,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Entities;
+
+    #[test]
+    fn events_are_drained_once_in_order() {
+        let mut entities = Entities::default();
+        let a = entities.spawn();
+        let b = entities.spawn();
+
+        let mut events = GameEvents::default();
+        events.push(GameEvent::EffectApplied {
+            target: b,
+            kind: StatusKind::Slow,
+        });
+        events.push_damage(Some(a), b, 30.0, true);
+
+        assert_eq!(
+            events.drain(),
+            vec![
+                GameEvent::EffectApplied {
+                    target: b,
+                    kind: StatusKind::Slow,
+                },
+                GameEvent::DamageDealt {
+                    source: Some(a),
+                    target: b,
+                    amount: 30.0,
+                    fatal: true,
+                },
+                GameEvent::EntityDied {
+                    entity: b,
+                    killer: Some(a),
+                },
+            ]
+        );
+        assert!(events.drain().is_empty());
+    }
+
+    #[test]
+    fn a_full_queue_drops_the_oldest() {
+        let mut entities = Entities::default();
+        let target = entities.spawn();
+
+        let mut events = GameEvents::default();
+        for i in 0..MAX_EVENTS + 10 {
+            events.push_damage(None, target, i as f32, false);
+        }
+
+        let drained = events.drain();
+        assert_eq!(drained.len(), MAX_EVENTS);
+        assert!(matches!(
+            drained[0],
+            GameEvent::DamageDealt { amount, .. } if amount == 10.0
+        ));
+    }
+}
@@ -0,0 +1,102 @@
+// Asset files downloaded in the background instead of being compiled into the binary. The
+// browser fetches them over HTTP, natively they are read from disk on a thread. Either way
+// the results wait in a queue until the main thread polls them, the renderer is only
+// touched there.
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+pub struct FetchedAsset {
+    pub name: String,
+    pub bytes: anyhow::Result<Vec<u8>>,
+}
+
+pub struct AssetFetcher {
+    base: String, // A URL in the browser, a directory natively
+    pending: HashSet<String>,
+    completed: Arc<Mutex<Vec<FetchedAsset>>>,
+}
+
+impl AssetFetcher {
+    pub fn new(base: &str) -> Self {
+        Self {
+            base: base.trim_end_matches('/').to_string(),
+            pending: HashSet::new(),
+            completed: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    // Requests are keyed by the resource name, asking again for a file that is still on
+    // its way does nothing and returns false
+    pub fn request(&mut self, name: &str, path: &str) -> bool {
+        if !self.pending.insert(name.to_string()) {
+            return false;
+        }
+
+        let location = format!("{}/{}", self.base, path);
+        let name = name.to_string();
+        let completed = self.completed.clone();
+        spawn_fetch(location, move |bytes| {
+            completed
+                .lock()
+                .expect("A fetch panicked")
+                .push(FetchedAsset { name, bytes });
+        });
+        true
+    }
+
+    // Everything that finished since the last poll, in the order it finished
+    pub fn poll(&mut self) -> Vec<FetchedAsset> {
+        let completed = std::mem::take(&mut *self.completed.lock().expect("A fetch panicked"));
+        for asset in &completed {
+            self.pending.remove(&asset.name);
+        }
+        completed
+    }
+
+    pub fn get_pending_count(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn_fetch(location: String, on_done: impl FnOnce(anyhow::Result<Vec<u8>>) + Send + 'static) {
+    use anyhow::Context;
+
+    std::thread::spawn(move || {
+        on_done(std::fs::read(&location).with_context(|| format!("Failed to read {}", location)))
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+fn spawn_fetch(location: String, on_done: impl FnOnce(anyhow::Result<Vec<u8>>) + 'static) {
+    wasm_bindgen_futures::spawn_local(async move {
+        on_done(fetch_bytes(&location).await);
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn fetch_bytes(url: &str) -> anyhow::Result<Vec<u8>> {
+    use anyhow::{Context, anyhow};
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+
+    let to_error = |error: wasm_bindgen::JsValue| anyhow!("Failed to fetch {}: {:?}", url, error);
+
+    let window = web_sys::window().context("No window to fetch from")?;
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str(url))
+        .await
+        .map_err(to_error)?
+        .dyn_into()
+        .map_err(to_error)?;
+    if !response.ok() {
+        anyhow::bail!("Failed to fetch {}: HTTP {}", url, response.status());
+    }
+
+    let buffer = JsFuture::from(response.array_buffer().map_err(to_error)?)
+        .await
+        .map_err(to_error)?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
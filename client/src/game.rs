@@ -1,17 +1,74 @@
-use glam::{Quat, Vec3, Vec3Swizzles};
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::bail;
+use glam::{IVec2, Quat, Vec3, Vec3Swizzles};
 use shared::{
     math::*,
+    net::{ACTION_MOVE, EntityState, SERVER_TICK_RATE, Snapshot},
     physics::{BodyId, BodySettings, BodyState, CollisionLayer, CollisionShape, PhysicsWorld},
+    profile_scope, profiler,
+    team::Team,
     transform::Transform,
 };
 
 use crate::{
+    ability::{
+        ABILITY_SLOTS, AbilityCaster, AbilityEffect, AbilityExecution, AbilityLibrary,
+        AbilityTarget, CastPhase, Targeting, update_abilities,
+    },
+    animation_events::{AnimationEvent, AnimationEventTrack, HIT_EVENT},
+    bake::{BakeInstance, BakeStats, BakedGeometry},
+    blink::resolve_blink_destination,
+    combat::{
+        CCombat, CHealth, CProjectile, ProjectileEffects, can_damage, expire_projectiles,
+        set_friendly_fire_collisions, update_combat, update_projectiles,
+    },
+    command_queue::{Command, CommandQueue, update_command_queues},
+    components::{Entities, Entity, Joinable, Storage, join, join3},
+    cursor::CursorKind,
+    death::{DeathSettle, SETTLE_DURATION, SETTLE_EASING, get_fall_rotation},
+    events::{GameEvent, GameEvents},
+    gizmo::{Gizmo, GizmoInput, GizmoMode, get_gizmo_scale},
+    hierarchy::{CParent, propagate_transforms, set_parent},
     input::{InputAction, InputState},
+    jobs,
+    kill_feed::KillFeed,
+    level::{
+        BlendSampleDesc, Level, MapBounds, PlayerDesc, PropDesc, ScatterDesc, ShapeDesc,
+        StaticBodyDesc, get_euler_rotation,
+    },
+    measure::MeasureTool,
+    offscreen_indicators::{OffscreenIndicators, get_screen_position},
+    prefab::{AiArchetype, PrefabLibrary, PrefabOverrides},
+    projectile_pool::{ProjectilePool, ProjectilePoolStats},
+    remote_proxy::CRemoteProxy,
     renderer::{
-        Renderer, ResourceHandle, SkeletalRenderJob, StaticRenderJob,
+        BlendSample, BlendSpace2D, PersistentSet, Renderer, ResourceHandle, ResourceKind,
+        SkeletalRenderJob, SpriteAnchor, SpriteSpace, StaticRenderJob, TextAlignment, TextureDesc,
         animation::{AnimationInstance, Pose},
+        render_data::{
+            RENDER_LAYER_DEFAULT, ShadowProxy, SpriteRenderJob, TextRenderJob, WeightDebugView,
+        },
         resources::get_handle,
     },
+    save::{
+        AbilityCasterSave, AnimationSave, AnimatorSave, BodySave, BodyStateSave, CameraSave,
+        CombatSave, EntitySave, GameSave, LaneFollowerSave, LayerSave, MovementSave, ParentSave,
+        PhysicsProxySave, PhysicsSave, ProjectileSave, RenderableSave, SAVE_VERSION,
+        ShadowProxySave, ShapeSave, TargetSave, TeamSave, TransformSave, WaveSpawnerSave,
+    },
+    scatter::{DensityMap, ScatterLayer},
+    selection::{SELECTION_RING_MATERIAL, SelectionGesture, SelectionSystem},
+    stats::StatsTracker,
+    status_effects::{StatusEffectDesc, StatusEffects, StatusKind},
+    time_controller::{
+        HIT_STOP_DURATION, HIT_STOP_SCALE, KILL_STOP_DURATION, KILL_STOP_SCALE, TimeController,
+    },
+    tint::TintAnimator,
+    trail::{Trail, TrailBatch, TrailRenderer},
+    tween::{TweenDesc, TweenField, TweenTarget, Tweens},
+    ui::{Tooltip, TooltipRequest, UiRect},
+    wave_spawner::{LaneFollower, WaveSpawner, get_dead_followers, update_lane_followers},
 };
 
 type CTransform = Transform;
@@ -23,6 +80,10 @@ struct CRenderable {
     pub color: Vec4,
     pub tex_coord: Vec2,
     pub tex_scale: Vec2,
+    pub casts_shadow: bool,
+    pub shadow_proxy: ShadowProxy, // Only for skeletal meshes
+    pub render_layers: u32,        // Which renders draw it, see RENDER_LAYER_DEFAULT
+    pub variant: u32,              // The skin of a variant material, clamped when drawn
 }
 
 impl Default for CRenderable {
@@ -34,30 +95,115 @@ impl Default for CRenderable {
             color: Vec4::ONE,
             tex_coord: Vec2::ZERO,
             tex_scale: Vec2::ONE,
+            casts_shadow: true,
+            shadow_proxy: ShadowProxy::None,
+            render_layers: RENDER_LAYER_DEFAULT,
+            variant: 0,
         }
     }
 }
 
+// Skeletal meshes render with a pose, without one they are static meshes
+type CPose = Pose;
+
+// A skinned entity that died, it settles onto the ground and fades out
+type CDeathSettle = DeathSettle;
+
+// A prop drawn as part of a combined mesh, its own renderable is kept for reloading
+struct CBaked {
+    #[allow(dead_code)]
+    bake: Entity, // Draws the combined mesh
+    #[allow(dead_code)]
+    renderable: CRenderable,
+}
+
+// The cell of the streamed chunk an entity belongs to, removed with the chunk
+type CStreamed = IVec2;
+
+// What a streamed chunk put into the world, taken out again when it is unloaded
 #[derive(Default)]
+pub struct ChunkContents {
+    props: Vec<Entity>, // With the bodies of the chunk
+    static_props: Vec<Entity>,
+    scatter_layers: Vec<ResourceHandle>,
+}
+
+// Debug views of the skinning of one entity, the others render normally
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SkinningDebug {
+    pub skeleton: bool, // The bones of the pose as debug lines
+    pub weights: WeightDebugView,
+}
+
+type CSkinningDebug = SkinningDebug;
+
+// Blends the locomotion clips by the velocity relative to the facing, attacks play the
+// attack clip instead
 struct CAnimator {
-    pub pose: Pose,
-    pub animation_states: [AnimationInstance; 2],
-    pub time: f32,
+    pub locomotion: BlendSpace2D,
+    pub phase: f32, // Shared by all clips
+    pub attack: Option<ResourceHandle>,
+    pub animation_states: Vec<AnimationInstance>,
 }
 
 #[derive(Default)]
-struct CPhysicsProxy {
+pub(crate) struct CPhysicsProxy {
     pub body_id: Option<BodyId>,
     pub current_state: Option<BodyState>,
     pub previous_state: Option<BodyState>,
 }
 
-#[derive(Default)]
-struct CPlayerMovement {
+impl CPhysicsProxy {
+    pub(crate) fn new(body_id: BodyId, physics_world: &PhysicsWorld) -> Self {
+        let state = physics_world.get_state(body_id);
+        Self {
+            body_id: Some(body_id),
+            current_state: state,
+            previous_state: state,
+        }
+    }
+}
+
+pub(crate) struct CPlayerMovement {
     pub velocity: Vec3,
+    pub locked: bool,          // Set by the combat system while attacking
+    pub speed_multiplier: f32, // Set from the status effects
+}
+
+impl Default for CPlayerMovement {
+    fn default() -> Self {
+        Self {
+            velocity: Vec3::ZERO,
+            locked: false,
+            speed_multiplier: 1.0,
+        }
+    }
+}
+
+pub(crate) type CTargetLocation = Option<Vec3>;
+
+type CTintAnimator = TintAnimator;
+
+type CStatusEffects = StatusEffects;
+
+// Set through Game::set_team, which keeps the physics layer in step
+type CTeam = Team;
+
+// What the bar over an entity shows, tweened after its health so hits drain it smoothly
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CHealthBar {
+    fill: f32,  // Fraction of the health
+    flash: f32, // White over the bar, when the attack is ready again
 }
 
-type CTargetLocation = Option<Vec3>;
+impl CHealthBar {
+    fn new(health: &CHealth) -> Self {
+        Self {
+            fill: health.current / health.max,
+            flash: 0.0,
+        }
+    }
+}
 
 type CCameraProjection = Mat4;
 
@@ -73,14 +219,26 @@ impl Default for CCameraMode {
     }
 }
 
-#[derive(Default)]
-struct EPlayer {
-    pub transform: CTransform,
-    pub physics_proxy: CPhysicsProxy,
-    pub renderable: CRenderable,
-    pub animator: CAnimator,
-    pub movement: CPlayerMovement,
-    pub target: CTargetLocation,
+// Orbits the followed position, editable from the inspector
+#[derive(Clone, Copy)]
+pub struct CameraSettings {
+    pub radius: f32,
+    pub angle: f32, // Degrees above the ground
+    pub fov: f32,   // Vertical, in degrees
+}
+
+const CAMERA_RADIUS: f32 = 1844.8713602850469_f32;
+const CAMERA_ANGLE: f32 = 56.0;
+const CAMERA_FOV: f32 = 40.0;
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            radius: CAMERA_RADIUS,
+            angle: CAMERA_ANGLE,
+            fov: CAMERA_FOV,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -88,18 +246,145 @@ struct ECamera {
     transform: CTransform,
     projection: CCameraProjection,
     mode: CCameraMode,
+    settings: CameraSettings,
+    catch_up: f32, // Seconds left of easing after the player instead of snapping to it
+}
+
+// While the key of a blink is held, its telegraph shows where it would land
+#[derive(Debug, Clone, Copy)]
+struct BlinkAim {
+    slot: usize,
+    cursor: Option<Vec3>,
+    destination: Option<Vec2>, // None when the blink would fail
+    radius: f32,               // Of the caster, the size of the telegraph
 }
 
 pub struct Game {
     camera: ECamera,
-    player: EPlayer,
+    screen_size: Vec2,
+    mouse_position: Vec2,       // In pixels, for the tooltips
+    level_name: Option<String>, // Of the level built last, saves only load into it
+    player: Option<Entity>,
+    character: Option<PlayerDesc>, // What other clients look like
+    animation_events: HashMap<String, Vec<AnimationEvent>>, // Of the level's animations
+    network_entities: HashMap<u32, Entity>,
+    latest_snapshot_tick: Option<u32>, // Of the newest snapshot applied
+    bounds: Option<MapBounds>,
+
+    entities: Entities,
+    transforms: Storage<CTransform>,
+    renderables: Storage<CRenderable>,
+    poses: Storage<CPose>,
+    animators: Storage<CAnimator>,
+    physics_proxies: Storage<CPhysicsProxy>,
+    movements: Storage<CPlayerMovement>,
+    targets: Storage<CTargetLocation>,
+    tints: Storage<CTintAnimator>,
+    healths: Storage<CHealth>,
+    combats: Storage<CCombat>,
+    status_effects: Storage<CStatusEffects>,
+    health_bars: Storage<CHealthBar>,
+    baked: Storage<CBaked>,
+    streamed: Storage<CStreamed>,
+    parents: Storage<CParent>,
+    skinning_debugs: Storage<CSkinningDebug>,
+    remote_proxies: Storage<CRemoteProxy>,
+    teams: Storage<CTeam>,
+    projectiles: Storage<CProjectile>,
+    ability_casters: Storage<AbilityCaster>,
+    command_queues: Storage<CommandQueue>,
+    lane_followers: Storage<LaneFollower>,
+    trails: Storage<TrailRenderer>, // On entities of their own, they outlive their owner
+    death_settles: Storage<CDeathSettle>,
+    projectile_pool: ProjectilePool,
+    projectile_effects: ProjectileEffects,
+    wave_spawners: Vec<WaveSpawner>, // Of the level built last
+
+    events: GameEvents,
+    kill_feed: KillFeed,
+    stats: StatsTracker, // Of the player, see record_events
+    offscreen_indicators: OffscreenIndicators,
+    tweens: Tweens,
+    tooltip: Tooltip,
+    bake_stats: BakeStats,               // Of the level built last
+    scatter_layers: Vec<ResourceHandle>, // Persistent instances of the level's ground clutter
+    last_attack_cooldown: f32,           // Of the player, it flashes when the attack is ready again
+    fixed_step: u32,                     // Fixed updates run so far, commands are stamped with it
+    selection: SelectionSystem,
+    time: TimeController,
+    trail: Trail, // Behind the player
+    trail_batch: TrailBatch,
+    prefabs: PrefabLibrary,
+    abilities: AbilityLibrary,
+    friendly_fire: bool,  // Off by default, see set_friendly_fire
+    editor_enabled: bool, // The gizmo on the selected entity, see update_editor
+    gizmo: Gizmo,
+    measure_tool: MeasureTool, // Its clicks go before the gizmo's and the selection's
+    blink_aim: Option<BlinkAim>,
 }
 
 impl Game {
     pub fn new() -> Self {
         Self {
             camera: Default::default(),
-            player: Default::default(),
+            screen_size: Vec2::ONE,
+            mouse_position: Vec2::ZERO,
+            level_name: None,
+            player: None,
+            character: None,
+            animation_events: Default::default(),
+            network_entities: HashMap::new(),
+            latest_snapshot_tick: None,
+            bounds: None,
+            entities: Default::default(),
+            transforms: Default::default(),
+            renderables: Default::default(),
+            poses: Default::default(),
+            animators: Default::default(),
+            physics_proxies: Default::default(),
+            movements: Default::default(),
+            targets: Default::default(),
+            tints: Default::default(),
+            healths: Default::default(),
+            combats: Default::default(),
+            status_effects: Default::default(),
+            health_bars: Default::default(),
+            baked: Default::default(),
+            streamed: Default::default(),
+            parents: Default::default(),
+            skinning_debugs: Default::default(),
+            remote_proxies: Default::default(),
+            teams: Default::default(),
+            projectiles: Default::default(),
+            ability_casters: Default::default(),
+            command_queues: Default::default(),
+            lane_followers: Default::default(),
+            trails: Default::default(),
+            death_settles: Default::default(),
+            projectile_pool: Default::default(),
+            projectile_effects: Default::default(),
+            wave_spawners: Vec::new(),
+            events: Default::default(),
+            kill_feed: Default::default(),
+            stats: Default::default(),
+            offscreen_indicators: Default::default(),
+            tweens: Default::default(),
+            tooltip: Default::default(),
+            bake_stats: Default::default(),
+            scatter_layers: Vec::new(),
+            last_attack_cooldown: 0.0,
+            fixed_step: 0,
+            selection: Default::default(),
+            time: Default::default(),
+            trail: Default::default(),
+            trail_batch: Default::default(),
+            prefabs: Default::default(),
+            abilities: Default::default(),
+            friendly_fire: false,
+            editor_enabled: false,
+            gizmo: Default::default(),
+            measure_tool: Default::default(),
+            blink_aim: None,
         }
     }
 
@@ -134,246 +419,4234 @@ impl Game {
         Some(near + dir * t)
     }
 
-    pub fn initialize(&mut self, physics_world: &mut PhysicsWorld) {
-        let player_position = Vec3::new(0.0, 0.0, 0.0);
+    // Builds the world from a level whose assets are loaded, replacing the current player
+    pub fn build_level(
+        &mut self,
+        level: &Level,
+        files: &HashMap<String, Vec<u8>>, // The kept files of the loader, see LevelLoader
+        renderer: &mut Renderer,
+        physics_world: &mut PhysicsWorld,
+    ) {
+        self.clear_entities();
+
+        let mut static_props = Vec::new();
+        for prop in &level.props {
+            let entity = self.spawn_prop(prop, physics_world);
+            if !prop.movable {
+                static_props.push(entity);
+            }
+        }
+
+        self.bake_stats = self.bake_static_geometry(renderer, &static_props, "Baked");
+        log::info!(
+            "Baked {} props into {} meshes, {} to {} batches, {} KiB of combined meshes",
+            self.bake_stats.instances_before,
+            self.bake_stats.instances_after,
+            self.bake_stats.batches_before,
+            self.bake_stats.batches_after,
+            self.bake_stats.baked_bytes / 1024
+        );
+        self.build_scatter_layers(level, files, renderer);
+
+        self.animation_events = level
+            .assets
+            .animations
+            .iter()
+            .map(|animation| (animation.name.clone(), animation.events.clone()))
+            .collect();
+
+        let player = &level.player;
+        let player_position = level
+            .get_spawn_point(&player.spawn)
+            .expect("Failed to find the player spawn point");
         let player_body_id = physics_world.create_rigid_body(&BodySettings {
             position: player_position.xz(),
             velocity: Vec2::ZERO,
-            layer: CollisionLayer::Player,
-            shape: &CollisionShape::Circle { radius: 32.0 },
+            layer: Team::Blue.get_unit_layer(),
+            shape: &get_collision_shape(player.shape),
             listen_to_contact_events: true,
         });
-        let current_state = physics_world.get_state(player_body_id);
-        self.player = EPlayer {
-            transform: CTransform {
-                position: player_position,
+
+        let entity = self.spawn_character(renderer, player, player_position);
+        self.physics_proxies
+            .insert(entity, CPhysicsProxy::new(player_body_id, physics_world));
+        self.targets.insert(entity, None);
+        self.command_queues.insert(entity, Default::default());
+        self.tints.insert(entity, Default::default());
+        let health = CHealth::new(100.0);
+        self.health_bars.insert(entity, CHealthBar::new(&health));
+        self.healths.insert(entity, health);
+        let combat = self.build_combat(renderer, player.attack_animation.as_deref());
+        self.combats.insert(entity, combat);
+        self.status_effects.insert(entity, Default::default());
+        self.teams.insert(entity, Team::Blue);
+        if let Some(caster) = self.build_caster(&player.abilities) {
+            self.ability_casters.insert(entity, caster);
+        }
+        self.player = Some(entity);
+        self.character = Some(player.clone());
+
+        self.level_name = Some(level.name.clone());
+        self.bounds = level.bounds;
+        self.wave_spawners = level
+            .wave_spawners
+            .iter()
+            .filter_map(|desc| Some(WaveSpawner::new(desc, level.get_spawn_point(&desc.spawn)?)))
+            .collect();
+
+        let environment = &level.environment;
+        renderer.set_directional_light(environment.get_directional_light());
+        renderer.set_ambient_light(environment.get_ambient_light());
+        renderer.set_fog(environment.get_fog());
+        renderer.set_wind(environment.get_wind());
+    }
+
+    fn spawn_prop(&mut self, prop: &PropDesc, physics_world: &mut PhysicsWorld) -> Entity {
+        let entity = self.entities.spawn();
+        let transform = CTransform {
+            position: Vec3::from(prop.transform.position),
+            rotation: prop.transform.get_rotation(),
+            scale: Vec3::from(prop.transform.scale),
+        };
+
+        if let Some(shape) = prop.physics {
+            self.add_static_body(entity, transform.position.xz(), shape, physics_world);
+        }
+
+        self.transforms.insert(entity, transform);
+        self.renderables.insert(
+            entity,
+            CRenderable {
+                mesh: get_handle(&prop.mesh),
+                material: get_handle(&prop.material),
+                color: Vec4::from(prop.color),
+                tex_scale: Vec2::from(prop.tex_scale),
+                casts_shadow: prop.casts_shadow,
                 ..Default::default()
             },
-            physics_proxy: CPhysicsProxy {
-                body_id: Some(player_body_id),
-                current_state: current_state,
-                previous_state: current_state,
+        );
+        entity
+    }
+
+    fn add_static_body(
+        &mut self,
+        entity: Entity,
+        position: Vec2,
+        shape: ShapeDesc,
+        physics_world: &mut PhysicsWorld,
+    ) {
+        let body_id = physics_world.create_rigid_body(&BodySettings {
+            position,
+            velocity: Vec2::ZERO,
+            layer: CollisionLayer::Environment,
+            shape: &get_collision_shape(shape),
+            listen_to_contact_events: false,
+        });
+        self.physics_proxies
+            .insert(entity, CPhysicsProxy::new(body_id, physics_world));
+    }
+
+    // The props of a streamed chunk, see chunk_streamer.rs. They are left out of saves, the
+    // chunk streams in again after loading.
+    pub fn spawn_chunk_props(
+        &mut self,
+        cell: IVec2,
+        props: &[PropDesc],
+        physics_world: &mut PhysicsWorld,
+        contents: &mut ChunkContents,
+    ) {
+        for prop in props {
+            let entity = self.spawn_prop(prop, physics_world);
+            self.streamed.insert(entity, cell);
+            contents.props.push(entity);
+            if !prop.movable {
+                contents.static_props.push(entity);
+            }
+        }
+    }
+
+    // On entities of their own, so they are removed like the props
+    pub fn spawn_chunk_bodies(
+        &mut self,
+        cell: IVec2,
+        bodies: &[StaticBodyDesc],
+        physics_world: &mut PhysicsWorld,
+        contents: &mut ChunkContents,
+    ) {
+        for body in bodies {
+            let entity = self.entities.spawn();
+            let position = Vec2::from(body.position);
+            self.add_static_body(entity, position, body.shape, physics_world);
+            self.transforms.insert(
+                entity,
+                CTransform {
+                    position: position.at_y(0.0),
+                    ..Default::default()
+                },
+            );
+            self.streamed.insert(entity, cell);
+            contents.props.push(entity);
+        }
+    }
+
+    // The names of the combined meshes start with the name, it has to be unique per chunk
+    pub fn bake_chunk(
+        &mut self,
+        renderer: &mut Renderer,
+        cell: IVec2,
+        name: &str,
+        contents: &ChunkContents,
+    ) -> BakeStats {
+        let stats = self.bake_static_geometry(renderer, &contents.static_props, name);
+        for bake in self.get_chunk_bakes(contents) {
+            self.streamed.insert(bake, cell);
+        }
+        stats
+    }
+
+    pub fn build_chunk_scatter(
+        &mut self,
+        renderer: &mut Renderer,
+        name: &str,
+        desc: &ScatterDesc,
+        files: &HashMap<String, Vec<u8>>,
+        contents: &mut ChunkContents,
+    ) {
+        match Self::build_scatter_layer(files, desc, name, renderer) {
+            Ok(handle) => contents.scatter_layers.push(handle),
+            Err(error) => log::error!("Failed to scatter {}: {:#}", name, error),
+        }
+    }
+
+    // Takes what the chunk put into the world out again, its bodies with it
+    pub fn remove_chunk(
+        &mut self,
+        contents: ChunkContents,
+        renderer: &mut Renderer,
+        physics_world: &mut PhysicsWorld,
+    ) {
+        let bakes = self.get_chunk_bakes(&contents);
+        for entity in contents.props.into_iter().chain(bakes) {
+            if !self.entities.is_alive(entity) {
+                continue;
+            }
+            if let Some(body_id) = self
+                .physics_proxies
+                .get(entity)
+                .and_then(|proxy| proxy.body_id)
+            {
+                physics_world.remove_body(body_id);
+            }
+            self.despawn(entity);
+        }
+        for handle in contents.scatter_layers {
+            renderer.remove_persistent_instances(handle);
+        }
+    }
+
+    fn get_chunk_bakes(&self, contents: &ChunkContents) -> Vec<Entity> {
+        let mut bakes: Vec<Entity> = contents
+            .static_props
+            .iter()
+            .filter_map(|entity| Some(self.baked.get(*entity)?.bake))
+            .collect();
+        bakes.sort_by_key(|entity| entity.to_bits());
+        bakes.dedup();
+        bakes
+    }
+
+    // An animated skeletal mesh that can be moved around, shared by the player and the
+    // characters of other clients
+    fn spawn_character(
+        &mut self,
+        renderer: &Renderer,
+        desc: &PlayerDesc,
+        position: Vec3,
+    ) -> Entity {
+        let mesh = get_handle(&desc.mesh);
+
+        let entity = self.entities.spawn();
+        self.transforms.insert(
+            entity,
+            CTransform {
+                position,
+                ..Default::default()
             },
-            renderable: CRenderable {
-                mesh: get_handle("Brute"),
-                material: get_handle("BruteMaterial"),
-                render_offset: Mat4::from_rotation_x(-90.0_f32.to_radians()),
+        );
+        self.renderables.insert(
+            entity,
+            CRenderable {
+                mesh,
+                material: get_handle(&desc.material),
+                render_offset: Mat4::from_quat(get_euler_rotation(desc.render_rotation)),
                 ..Default::default()
             },
-            animator: Default::default(),
-            ..Default::default()
+        );
+        self.poses.insert(entity, renderer.create_pose(mesh));
+        self.animators.insert(
+            entity,
+            CAnimator {
+                locomotion: build_locomotion(renderer, desc),
+                phase: 0.0,
+                attack: desc.attack_animation.as_deref().map(get_handle),
+                animation_states: Vec::new(),
+            },
+        );
+        self.movements.insert(entity, Default::default());
+        entity
+    }
+
+    // Attacks hit on the hit event of the attack clip, without one the default attack
+    fn build_combat(&self, renderer: &Renderer, attack_animation: Option<&str>) -> CCombat {
+        let Some(name) = attack_animation else {
+            return CCombat::default();
+        };
+        let duration = renderer.get_animation_duration(get_handle(name));
+        if duration.is_none() {
+            log::error!("Missing attack animation {}", name);
         }
+        let events = self.animation_events.get(name).cloned().unwrap_or_default();
+        let mut combat = CCombat::default();
+        combat.attack = AnimationEventTrack::new(events, duration.unwrap_or(1.0));
+        if combat.attack.get_time(HIT_EVENT).is_none() {
+            log::error!("The attack animation {} has no {} event", name, HIT_EVENT);
+        }
+        combat
     }
 
-    pub fn load_resources(&mut self, renderer: &mut Renderer) {
-        let grid_texture = renderer.load_texture(
-            "GridTexture",
-            include_bytes!("../../assets/textures/grid.dat"),
-        );
-        renderer.create_material("Grid", grid_texture);
-        renderer.load_mesh("Floor", include_bytes!("../../assets/models/floor.dat"));
-        renderer.load_mesh("Sphere", include_bytes!("../../assets/models/sphere.dat"));
+    // What the player wants this tick, sent to the server instead of moving locally
+    pub fn get_network_input(&self) -> (u32, Vec2) {
+        let target = self.player.and_then(|player| *self.targets.get(player)?);
+        match target {
+            Some(target) => (ACTION_MOVE, target.xz()),
+            None => (0, Vec2::ZERO),
+        }
+    }
 
-        let brute_texture = renderer.load_texture(
-            "BruteTexture",
-            include_bytes!(
-                "../../assets/champions/brute/textures/MaleBruteA_Body_diffuse1_ncl1_1.dat"
-            ),
-        );
-        renderer.create_material("BruteMaterial", brute_texture);
+    // Hands the snapshots to the proxies of the other entities and places our own entity
+    // where it is predicted. The first state for our own entity takes the player over from
+    // the local physics, other entities get a character spawned and are despawned once the
+    // server stops sending them.
+    pub fn apply_network_state(
+        &mut self,
+        renderer: &Renderer,
+        physics_world: &mut PhysicsWorld,
+        own_id: Option<u32>,
+        own_state: Option<EntityState>,
+        snapshots: &[Snapshot],
+    ) {
+        for snapshot in snapshots {
+            // Late snapshots only fill in the gaps, they don't spawn or despawn anything
+            let is_newest = self
+                .latest_snapshot_tick
+                .is_none_or(|tick| snapshot.tick > tick);
+            if is_newest {
+                self.latest_snapshot_tick = Some(snapshot.tick);
+            }
 
-        let mesh = renderer.load_skeletal_mesh(
-            "Brute",
-            include_bytes!("../../assets/champions/brute/Brute.dat"),
-        );
+            for state in &snapshot.entities {
+                if Some(state.id) == own_id {
+                    continue;
+                }
+                let entity = match self.network_entities.get(&state.id) {
+                    Some(&entity) => Some(entity),
+                    None if is_newest => self.spawn_remote(renderer, state.id),
+                    None => None,
+                };
+                if let Some(proxy) = entity.and_then(|entity| self.remote_proxies.get_mut(entity)) {
+                    proxy.push(snapshot.tick, state);
+                }
+            }
 
-        self.player.animator.pose = renderer.create_pose(mesh);
+            if is_newest {
+                self.despawn_missing(&snapshot.entities);
+            }
+        }
 
-        renderer.load_animation(
-            "Brute_Idle",
-            include_bytes!("../../assets/champions/brute/animations/Brute_Idle.dat"),
-        );
+        let Some(own) = own_state.filter(|own| Some(own.id) == own_id) else {
+            return;
+        };
+        let entity = if let Some(&entity) = self.network_entities.get(&own.id) {
+            entity
+        } else if let Some(player) = self.player {
+            if let Some(proxy) = self.physics_proxies.remove(player)
+                && let Some(body_id) = proxy.body_id
+            {
+                physics_world.remove_body(body_id);
+            }
+            self.network_entities.insert(own.id, player);
+            player
+        } else {
+            return;
+        };
 
-        renderer.load_animation(
-            "Brute_Run",
-            include_bytes!("../../assets/champions/brute/animations/Brute_Run.dat"),
-        );
+        if let Some(transform) = self.transforms.get_mut(entity) {
+            transform.position = own.position.at_y(transform.position.y);
+            transform.rotation = Quat::from_rotation_y(own.facing);
+        }
+        if let Some(movement) = self.movements.get_mut(entity) {
+            movement.velocity = own.velocity.at_y(0.0);
+        }
+    }
 
-        let font_handle = renderer.load_font(
-            "DefaultFont",
-            include_bytes!("../../assets/ui/fonts/poppins_font.dat"),
+    // Moves the other entities to their smoothed states, see CRemoteProxy
+    pub fn update_remote_proxies(&mut self, render_time: f32, dt: f32) {
+        update_remote_proxies(
+            render_time,
+            dt,
+            &mut self.remote_proxies,
+            &mut self.transforms,
+            &mut self.movements,
         );
-        renderer.create_font_material("DefaultFontMaterial", font_handle);
     }
 
-    pub fn update(&mut self, dt: f32, alpha: f32, input_state: &InputState) {
-        // Player
-        {
-            let transform = &mut self.player.transform;
-            let physics_proxy = &mut self.player.physics_proxy;
-            let target = &mut self.player.target;
+    fn spawn_remote(&mut self, renderer: &Renderer, id: u32) -> Option<Entity> {
+        let desc = self.character.clone()?;
+        let entity = self.spawn_character(renderer, &desc, Vec3::ZERO);
+        self.remote_proxies
+            .insert(entity, CRemoteProxy::new(SERVER_TICK_RATE));
+        self.network_entities.insert(id, entity);
+        Some(entity)
+    }
 
-            if let Some(state) = physics_proxy.current_state
-                && let Some(prev_state) = physics_proxy.previous_state
-            {
-                transform.position = prev_state.position.lerp(state.position, alpha).at_y(0.0);
-            }
+    // Our own entity stays around even if a snapshot misses it
+    fn despawn_missing(&mut self, states: &[EntityState]) {
+        let gone: Vec<(u32, Entity)> = self
+            .network_entities
+            .iter()
+            .filter(|&(&id, &entity)| {
+                Some(entity) != self.player && !states.iter().any(|state| state.id == id)
+            })
+            .map(|(&id, &entity)| (id, entity))
+            .collect();
+        for (id, entity) in gone {
+            self.network_entities.remove(&id);
+            self.despawn(entity);
+        }
+    }
+
+    // Validated against the loaded resources, see PrefabLibrary::validate
+    pub fn set_prefabs(&mut self, prefabs: PrefabLibrary) {
+        self.prefabs = prefabs;
+    }
 
-            if let Some(mouse_world_position) = Self::get_world_position_from_screen(
-                self.camera.projection * self.camera.transform.to_matrix().inverse(),
-                input_state.get_mouse_position(),
-                0.0,
-            ) {
-                if input_state.is_pressed(InputAction::RightClick) {
-                    *target = Some(mouse_world_position);
+    // Parks projectiles of every prefab the abilities shoot, so the first fights don't create
+    // them. Needs the prefabs, once the level is built.
+    pub fn prewarm_projectile_pool(
+        &mut self,
+        renderer: &Renderer,
+        physics_world: &mut PhysicsWorld,
+    ) {
+        let prefabs: Vec<String> = self
+            .abilities
+            .get_projectile_prefabs()
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        for prefab in prefabs {
+            for _ in 0..PROJECTILE_POOL_PREWARM {
+                match self.spawn_prefab(&prefab, &Default::default(), renderer, physics_world) {
+                    Ok(entity) => {
+                        self.projectile_pool.add(&prefab, entity);
+                        self.retire_projectile(entity, physics_world);
+                    }
+                    Err(error) => {
+                        log::error!("Failed to prewarm {}: {:#}", prefab, error);
+                        break;
+                    }
                 }
             }
+        }
+    }
 
-            let movement = &mut self.player.movement;
-            let animator = &mut self.player.animator;
+    #[allow(dead_code)]
+    pub fn get_projectile_pool_stats(&self) -> ProjectilePoolStats {
+        self.projectile_pool.get_stats()
+    }
 
-            const SPEED: f32 = 300.0;
-            const MIN_TARGET_DISTANCE: f32 = 10.0;
-            let mut input_velocity = Vec3::ZERO;
-            if let Some(target_location) = *target {
-                let to_target = target_location - transform.position;
-                let distance_sqrd = to_target.length_squared();
-                if distance_sqrd > MIN_TARGET_DISTANCE * MIN_TARGET_DISTANCE {
-                    input_velocity = SPEED * to_target.normalize_or_zero();
-                } else {
-                    *target = None;
-                }
+    // Before the level is built, the player gets the abilities the level names
+    pub fn set_abilities(&mut self, abilities: AbilityLibrary) {
+        self.abilities = abilities;
+    }
+
+    // Unknown abilities leave their key empty
+    fn build_caster(&self, names: &[Option<String>]) -> Option<AbilityCaster> {
+        if names.is_empty() {
+            return None;
+        }
+        let abilities = std::array::from_fn(|slot| {
+            let name = names.get(slot)?.as_ref()?;
+            let desc = self.abilities.get(name).cloned();
+            if desc.is_none() {
+                log::error!("No ability named {}", name);
             }
+            desc
+        });
+        Some(AbilityCaster::new(abilities))
+    }
 
-            movement.velocity = movement
-                .velocity
-                .lerp(input_velocity, (15.0 * dt).clamp(0.0, 1.0));
+    // Spawns the entity a prefab describes, with the components of its sections. The
+    // overrides win over the values of the prefab and its parents.
+    #[allow(dead_code)]
+    pub fn spawn_prefab(
+        &mut self,
+        name: &str,
+        overrides: &PrefabOverrides,
+        renderer: &Renderer,
+        physics_world: &mut PhysicsWorld,
+    ) -> anyhow::Result<Entity> {
+        let Some(prefab) = self.prefabs.get(name).cloned() else {
+            bail!("No prefab named {}", name);
+        };
 
-            let blend = movement.velocity.length() / SPEED;
-            animator.time += dt;
-            animator.animation_states = [
-                AnimationInstance {
-                    animation: get_handle("Brute_Idle"),
-                    blend_weight: 1.0 - blend,
-                    time: animator.time,
-                    looping: true,
+        let entity = self.entities.spawn();
+        let transform = prefab.get_transform(overrides);
+        if let Some(renderable) = &prefab.renderable {
+            let mesh = get_handle(&renderable.mesh);
+            self.renderables.insert(
+                entity,
+                CRenderable {
+                    mesh,
+                    material: get_handle(&renderable.material),
+                    render_offset: Mat4::from_quat(get_euler_rotation(renderable.render_rotation)),
+                    color: prefab.get_color(overrides),
+                    casts_shadow: renderable.casts_shadow,
+                    variant: overrides.variant,
+                    ..Default::default()
                 },
-                AnimationInstance {
-                    animation: get_handle("Brute_Run"),
-                    blend_weight: blend,
-                    time: animator.time,
-                    looping: true,
-                },
-            ];
+            );
+            if renderer
+                .get_resource_pool()
+                .get_skeletal_mesh(mesh)
+                .is_some()
+            {
+                self.poses.insert(entity, renderer.create_pose(mesh));
+            }
+        }
 
-            transform.rotation = transform.rotation.slerp(
-                Quat::from_rotation_y(movement.velocity.x.atan2(movement.velocity.z)),
-                20.0 * dt,
+        if let Some(animator) = &prefab.animator {
+            self.animators.insert(
+                entity,
+                CAnimator {
+                    locomotion: build_blend_space(
+                        renderer,
+                        &animator.idle_animation,
+                        &animator.run_animation,
+                        &animator.locomotion,
+                    ),
+                    phase: 0.0,
+                    attack: animator.attack_animation.as_deref().map(get_handle),
+                    animation_states: Vec::new(),
+                },
             );
+            // The blend space is driven by the velocity
+            self.movements.insert(entity, Default::default());
         }
 
-        // Camera
-        {
-            const CAMERA_RADIUS: f32 = 1844.8713602850469_f32;
-            const CAMERA_ANGLE: f32 = f32::to_radians(56.0);
+        if let Some(physics) = &prefab.physics {
+            let mut layer = physics.layer.get_layer();
+            if let Some(team) = overrides.team {
+                layer = team.get_layer_like(layer);
+            }
+            let body_id = physics_world.create_rigid_body(&BodySettings {
+                position: transform.position.xz(),
+                velocity: Vec2::ZERO,
+                layer,
+                shape: &get_collision_shape(physics.shape),
+                listen_to_contact_events: physics.sensor,
+            });
+            self.physics_proxies
+                .insert(entity, CPhysicsProxy::new(body_id, physics_world));
+            if let Some(team) = Team::from_layer(layer) {
+                self.teams.insert(entity, team);
+            }
+        } else if let Some(team) = overrides.team {
+            self.teams.insert(entity, team);
+        }
 
-            if input_state.is_pressed(InputAction::SwitchCameraMode) {
-                self.camera.mode = match self.camera.mode {
-                    CCameraMode::Follow => CCameraMode::Detached,
-                    CCameraMode::Detached => CCameraMode::Follow,
-                }
+        if let Some(max) = prefab.health {
+            let health = CHealth::new(max);
+            self.health_bars.insert(entity, CHealthBar::new(&health));
+            self.healths.insert(entity, health);
+        }
+
+        if let Some(ai) = prefab.ai {
+            self.targets.insert(entity, None);
+            self.command_queues.insert(entity, Default::default());
+            self.movements.insert(entity, Default::default());
+            if ai == AiArchetype::Fighter {
+                let attack_animation = prefab
+                    .animator
+                    .as_ref()
+                    .and_then(|animator| animator.attack_animation.as_deref());
+                let combat = self.build_combat(renderer, attack_animation);
+                self.combats.insert(entity, combat);
+                self.status_effects.insert(entity, Default::default());
+                self.tints.insert(entity, Default::default());
             }
+        }
 
-            let transform = &mut self.camera.transform;
+        self.transforms.insert(entity, transform);
+        Ok(entity)
+    }
 
-            if self.camera.mode == CCameraMode::Follow
-                || input_state.is_down(InputAction::CameraFollow)
-            {
-                let camera_target =
-                    glam::vec3(0.0, 120.0, 0.0) + self.player.transform.position.xz().at_y(0.0);
+    // One of each skin of a variant prefab in a row along x. They share the material so
+    // they are drawn in one batch.
+    pub fn spawn_variant_row(
+        &mut self,
+        name: &str,
+        position: Vec3,
+        renderer: &Renderer,
+        physics_world: &mut PhysicsWorld,
+    ) -> anyhow::Result<Vec<Entity>> {
+        (0..VARIANT_ROW_COUNT)
+            .map(|variant| {
+                let overrides = PrefabOverrides {
+                    position: position + Vec3::X * VARIANT_ROW_SPACING * variant as f32,
+                    variant,
+                    ..Default::default()
+                };
+                self.spawn_prefab(name, &overrides, renderer, physics_world)
+            })
+            .collect()
+    }
 
-                transform.position = camera_target
-                    + Vec3 {
-                        x: 0.0,
-                        y: CAMERA_ANGLE.sin(),
-                        z: CAMERA_ANGLE.cos(),
-                    } * CAMERA_RADIUS;
+    // A prefab with a body flying from the source towards the target, on the source's team.
+    // The status effect is applied to the unit it hits.
+    #[allow(dead_code, clippy::too_many_arguments)]
+    pub fn spawn_projectile(
+        &mut self,
+        source: Entity,
+        prefab: &str,
+        target: Vec3,
+        speed: f32,
+        damage: f32,
+        status: Option<StatusEffectDesc>,
+        renderer: &Renderer,
+        physics_world: &mut PhysicsWorld,
+    ) -> anyhow::Result<Entity> {
+        let Some(mut position) = self
+            .transforms
+            .get(source)
+            .map(|transform| transform.position)
+        else {
+            bail!("The source of the projectile has no transform");
+        };
+        // The body and not the interpolated transform, so the fixed update stays deterministic
+        if let Some(state) = self
+            .physics_proxies
+            .get(source)
+            .and_then(|proxy| proxy.body_id)
+            .and_then(|body_id| physics_world.get_state(body_id))
+        {
+            position = state.position.extend(position.y).xzy();
+        }
+        let direction = (target - position).xz().normalize_or_zero();
+        let overrides = PrefabOverrides {
+            position,
+            rotation: Quat::from_rotation_y(f32::atan2(direction.x, direction.y)),
+            team: self.teams.get(source).copied(),
+            ..Default::default()
+        };
+        let entity = match self.take_pooled_projectile(prefab, &overrides, physics_world) {
+            Some(entity) => entity,
+            None => {
+                let entity = self.spawn_prefab(prefab, &overrides, renderer, physics_world)?;
+                if self.physics_proxies.get(entity).is_some() {
+                    self.projectile_pool.add(prefab, entity);
+                }
+                entity
             }
+        };
 
-            transform.rotation = Quat::from_rotation_x(-CAMERA_ANGLE);
-        }
+        let Some(body_id) = self
+            .physics_proxies
+            .get(entity)
+            .and_then(|proxy| proxy.body_id)
+        else {
+            self.despawn(entity);
+            bail!("The projectile prefab {} has no physics", prefab);
+        };
+        physics_world.set_velocity(body_id, direction * speed);
+        // Also over what a pooled projectile carried before
+        let user_data = self.projectile_effects.get_user_data(status);
+        physics_world.set_user_data(body_id, user_data);
+        self.projectiles.insert(
+            entity,
+            CProjectile {
+                source: Some(source),
+                damage,
+                lifetime: PROJECTILE_LIFETIME,
+            },
+        );
+        let trail = self.entities.spawn();
+        self.trails.insert(trail, TrailRenderer::new(entity));
+        Ok(entity)
     }
 
-    pub fn fixed_update(&mut self, dt: f32, physics_world: &mut PhysicsWorld) {
-        if let Some(body_id) = self.player.physics_proxy.body_id {
-            physics_world.set_velocity(body_id, self.player.movement.velocity.xz());
+    // A parked projectile of the prefab, placed and colored like spawn_prefab would. The
+    // renderable and the body are the ones it was created with.
+    fn take_pooled_projectile(
+        &mut self,
+        prefab: &str,
+        overrides: &PrefabOverrides,
+        physics_world: &mut PhysicsWorld,
+    ) -> Option<Entity> {
+        let desc = self.prefabs.get(prefab)?;
+        let transform = desc.get_transform(overrides);
+        let color = desc.get_color(overrides);
+        let mut layer = desc.physics.as_ref()?.layer.get_layer();
+        if let Some(team) = overrides.team {
+            layer = team.get_layer_like(layer);
         }
 
-        physics_world.step_simulation(dt);
+        let entity = self.projectile_pool.take(prefab)?;
+        let body_id = self.physics_proxies.get(entity)?.body_id?;
+        physics_world.set_position(body_id, transform.position.xz());
+        physics_world.set_layer(body_id, layer);
+        physics_world.set_enabled(body_id, true);
+        // Not interpolated from where it was parked
+        self.physics_proxies
+            .insert(entity, CPhysicsProxy::new(body_id, physics_world));
+        if let Some(renderable) = self.renderables.get_mut(entity) {
+            renderable.color = color;
+            renderable.variant = overrides.variant;
+        }
+        if let Some(team) = Team::from_layer(layer) {
+            self.teams.insert(entity, team);
+        } else {
+            self.teams.remove(entity);
+        }
+        self.transforms.insert(entity, transform);
+        Some(entity)
+    }
+
+    // Spent projectiles of the pool are parked: their body is disabled and without a transform
+    // they aren't drawn. The others are despawned.
+    fn retire_projectile(&mut self, projectile: Entity, physics_world: &mut PhysicsWorld) {
+        let body_id = self
+            .physics_proxies
+            .get(projectile)
+            .and_then(|proxy| proxy.body_id);
+        if !self.projectile_pool.park(projectile) {
+            if let Some(body_id) = body_id {
+                physics_world.remove_body(body_id);
+            }
+            self.despawn(projectile);
+            return;
+        }
 
-        if let Some(body_id) = self.player.physics_proxy.body_id {
-            self.player.physics_proxy.previous_state = self.player.physics_proxy.current_state;
-            self.player.physics_proxy.current_state = physics_world.get_state(body_id);
+        if let Some(body_id) = body_id {
+            physics_world.set_velocity(body_id, Vec2::ZERO);
+            physics_world.set_enabled(body_id, false);
+        }
+        self.transforms.remove(projectile);
+        self.projectiles.remove(projectile);
+        self.tweens.stop_entity(projectile);
+        // Its trail fades like after a despawn, not following it when it is fired again
+        for trail in self.trails.iter_mut() {
+            if trail.owner == Some(projectile) {
+                trail.owner = None;
+            }
         }
     }
 
-    pub fn render(&mut self, renderer: &mut Renderer) {
-        renderer.submit(&StaticRenderJob {
-            transform: Mat4::from_scale_rotation_translation(
-                Vec3 {
-                    x: 0.4,
-                    y: 0.4,
-                    z: 0.4,
-                },
-                Quat::IDENTITY,
-                Vec3 {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-            ),
-            material: get_handle("Grid"),
-            mesh: get_handle("Floor"),
-            color: Vec4::new(0.651, 0.541, 0.392, 1.0),
-            tex_scale: Vec2::ONE * 10.0,
-            ..Default::default()
-        });
+    #[allow(dead_code)]
+    pub fn get_team(&self, entity: Entity) -> Option<Team> {
+        self.teams.get(entity).copied()
+    }
 
-        // Player
+    // Mind control and the like. The body moves to the layer of the new team right away and
+    // the colors follow from the team, so nothing sees the entity half switched.
+    #[allow(dead_code)]
+    pub fn set_team(&mut self, entity: Entity, team: Team, physics_world: &mut PhysicsWorld) {
+        if !self.entities.is_alive(entity) {
+            return;
+        }
+        if let Some(body_id) = self
+            .physics_proxies
+            .get(entity)
+            .and_then(|proxy| proxy.body_id)
+            && let Some(layer) = physics_world.get_layer(body_id)
+            && !layer.is_blocking()
         {
-            let transform = &self.player.transform;
-            let renderable = &self.player.renderable;
-            let animator = &mut self.player.animator;
+            physics_world.set_layer(body_id, team.get_layer_like(layer));
+        }
+        self.teams.insert(entity, team);
 
-            renderer.accumulate_pose(&animator.animation_states, &mut animator.pose);
+        // Its attack was aimed at someone who may be a teammate now
+        if let Some(combat) = self.combats.get_mut(entity) {
+            combat.target = None;
+        }
+    }
 
-            renderer.submit(&SkeletalRenderJob {
-                transform: transform.to_matrix() * renderable.render_offset,
-                material: renderable.material,
-                mesh: renderable.mesh,
-                tex_coord: renderable.tex_coord,
-                tex_scale: renderable.tex_scale,
-                color: renderable.color,
-                pose: Some(&animator.pose),
+    #[allow(dead_code)]
+    pub fn is_friendly_fire(&self) -> bool {
+        self.friendly_fire
+    }
+
+    // Lets attacks and projectiles hurt teammates, projectiles start colliding with them
+    #[allow(dead_code)]
+    pub fn set_friendly_fire(&mut self, friendly_fire: bool, physics_world: &mut PhysicsWorld) {
+        let mut matrix = physics_world.get_collision_matrix();
+        set_friendly_fire_collisions(&mut matrix, friendly_fire);
+        physics_world.set_collision_matrix(matrix);
+        self.friendly_fire = friendly_fire;
+    }
+
+    // Places the mesh at the origin with the grid material, skeletal meshes keep their bind pose
+    pub fn spawn_debug_mesh(
+        &mut self,
+        renderer: &Renderer,
+        mesh: ResourceHandle,
+        kind: ResourceKind,
+    ) {
+        let entity = self.entities.spawn();
+        self.transforms.insert(entity, Default::default());
+        self.renderables.insert(
+            entity,
+            CRenderable {
+                mesh,
+                material: get_handle("Grid"),
+                ..Default::default()
+            },
+        );
+        if kind == ResourceKind::SkeletalMesh {
+            self.poses.insert(entity, renderer.create_pose(mesh));
+        }
+    }
+
+    // The bone count may have changed, the poses start over from the bind pose
+    pub fn on_skeletal_mesh_reloaded(&mut self, renderer: &Renderer, mesh: ResourceHandle) {
+        let entities: Vec<Entity> = join(&self.entities, &self.renderables)
+            .filter(|(_, renderable)| renderable.mesh == mesh)
+            .map(|(entity, _)| entity)
+            .collect();
+        for entity in entities {
+            if let Some(pose) = self.poses.get_mut(entity) {
+                *pose = renderer.create_pose(mesh);
+            }
+        }
+    }
+
+    // The dt is gameplay time, scaled by the time controller. The real dt drives what keeps
+    // going during a hit-stop.
+    // The editor's gizmo on the first selected entity. Before update, a click on a handle
+    // doesn't change the selection. Tab switches between moving and rotating, Ctrl snaps,
+    // Ctrl+Z puts back what the last drag moved and Ctrl+Shift+Z moves it again.
+    pub fn update_editor(&mut self, input_state: &InputState, physics_world: &mut PhysicsWorld) {
+        if input_state.is_pressed(InputAction::ToggleEditor) {
+            self.editor_enabled = !self.editor_enabled;
+        }
+        let view_projection = self.camera.projection * self.camera.transform.to_matrix().inverse();
+        let ray = Ray::from_screen(view_projection, input_state.get_mouse_position());
+        self.measure_tool
+            .update(&ray, input_state.is_pressed(InputAction::LeftClick));
+        if !self.editor_enabled || self.measure_tool.is_enabled() {
+            // Ends a drag that was going on
+            self.gizmo.update(None, &ray, 1.0, GizmoInput::default());
+            return;
+        }
+
+        if input_state.is_pressed(InputAction::CycleGizmoMode) {
+            self.gizmo.set_mode(match self.gizmo.get_mode() {
+                GizmoMode::Translate => GizmoMode::Rotate,
+                GizmoMode::Rotate => GizmoMode::Translate,
             });
         }
+        let snap = input_state.is_down(InputAction::Snap);
+        if snap && input_state.is_pressed(InputAction::Undo) {
+            if input_state.is_down(InputAction::AddToSelection) {
+                if let Some(edit) = self.gizmo.redo() {
+                    self.set_edited_transform(edit.entity, edit.after, physics_world);
+                }
+            } else if let Some(edit) = self.gizmo.undo() {
+                self.set_edited_transform(edit.entity, edit.before, physics_world);
+            }
+        }
 
-        // Camera
+        let selected = self
+            .selection
+            .get_selected()
+            .first()
+            .and_then(|entity| Some((*entity, *self.transforms.get(*entity)?)));
+        let scale = selected.map_or(1.0, |(_, transform)| {
+            self.get_gizmo_scale(transform.position)
+        });
+        let input = GizmoInput {
+            pressed: input_state.is_pressed(InputAction::LeftClick),
+            down: input_state.is_down(InputAction::LeftClick),
+            snap,
+        };
+        if let Some(transform) = self.gizmo.update(selected, &ray, scale, input)
+            && let Some((entity, _)) = selected
         {
-            renderer.set_camera_projection(self.camera.projection);
-            renderer.set_camera_position_and_orientation(
-                self.camera.transform.position,
-                self.camera.transform.rotation,
-            );
+            self.set_edited_transform(entity, transform, physics_world);
         }
     }
 
-    pub fn resize(&mut self, width: u32, height: u32) {
-        self.camera.projection = Mat4::perspective_rh(
-            f32::to_radians(40.0),
-            width as f32 / height as f32,
-            1.0,
-            3000.0,
-        );
+    pub fn is_editor_enabled(&self) -> bool {
+        self.editor_enabled
+    }
+
+    pub fn get_measure_tool_mut(&mut self) -> &mut MeasureTool {
+        &mut self.measure_tool
+    }
+
+    // Apart from render, it is left out of screenshots
+    pub fn render_measure_tool(&self, renderer: &mut Renderer) {
+        let view_projection = self.camera.projection * self.camera.transform.to_matrix().inverse();
+        self.measure_tool
+            .render(renderer, view_projection, self.screen_size);
+    }
+
+    fn get_gizmo_scale(&self, position: Vec3) -> f32 {
+        get_gizmo_scale(
+            &self.camera.transform,
+            self.camera.settings.fov.to_radians(),
+            self.screen_size.y,
+            position,
+        )
+    }
+
+    // Its body is moved along, right away and not interpolated there
+    fn set_edited_transform(
+        &mut self,
+        entity: Entity,
+        transform: Transform,
+        physics_world: &mut PhysicsWorld,
+    ) {
+        if !self.entities.is_alive(entity) {
+            return;
+        }
+        if let Some(proxy) = self.physics_proxies.get_mut(entity)
+            && let Some(body_id) = proxy.body_id
+        {
+            physics_world.set_position(body_id, transform.position.xz());
+            proxy.current_state = physics_world.get_state(body_id);
+            proxy.previous_state = proxy.current_state;
+        }
+        self.transforms.insert(entity, transform);
+    }
+
+    pub fn update(&mut self, dt: f32, real_dt: f32, alpha: f32, input_state: &InputState) {
+        interpolate_transforms(alpha, &mut self.transforms, &self.physics_proxies);
+        let input_scope = profiler::Scope::new("Input");
+        self.mouse_position = input_state.get_mouse_position() * self.screen_size;
+
+        self.selection.retain(|entity| {
+            !self
+                .healths
+                .get(*entity)
+                .is_some_and(|health| health.is_dead())
+        });
+        if !self.gizmo.is_capturing_mouse()
+            && !self.measure_tool.is_enabled()
+            && let Some(gesture) = self.selection.update(input_state, self.screen_size)
+        {
+            let additive = input_state.is_down(InputAction::AddToSelection);
+            let picked = self.get_selectable_in(gesture);
+            self.selection.select(picked, additive);
+        }
+
+        let mouse_world_position = Self::get_world_position_from_screen(
+            self.camera.projection * self.camera.transform.to_matrix().inverse(),
+            input_state.get_mouse_position(),
+            0.0,
+        );
+
+        // Orders go to the selected units, or to the player when nothing is selected
+        let units: Vec<Entity> = match self.selection.get_selected() {
+            [] => self.player.into_iter().collect(),
+            selected => selected.to_vec(),
+        };
+        if input_state.is_pressed(InputAction::RightClick)
+            && let Some(mouse_world_position) = mouse_world_position
+        {
+            // Units that can fight attack what is clicked, the others walk there
+            let target = self.pick_entity(input_state.get_mouse_position(), |entity| {
+                units.iter().any(|&unit| self.can_attack(unit, entity))
+            });
+            for &unit in &units {
+                if let Some(target) = target
+                    && self.can_attack(unit, target)
+                    && let Some(combat) = self.combats.get_mut(unit)
+                {
+                    combat.request_attack(target);
+                } else if let Some(queue) = self.command_queues.get_mut(unit) {
+                    queue.push(Command::Move(mouse_world_position), self.fixed_step);
+                }
+            }
+        }
+        // Drops what is buffered and stands still, a running cast goes on
+        if input_state.is_pressed(InputAction::Stop) {
+            for &unit in &units {
+                if let Some(queue) = self.command_queues.get_mut(unit) {
+                    queue.clear();
+                }
+                if let Some(target) = self.targets.get_mut(unit) {
+                    *target = None;
+                }
+            }
+            self.blink_aim = None;
+        }
+
+        // Q, W, E and R cast the player's abilities, E attacks when it has no ability on it
+        let keys = [
+            InputAction::Q,
+            InputAction::W,
+            InputAction::E,
+            InputAction::R,
+        ];
+        if let Some(player) = self.player {
+            for (slot, key) in keys.into_iter().enumerate() {
+                if !input_state.is_pressed(key) {
+                    continue;
+                }
+                let ability = self
+                    .ability_casters
+                    .get(player)
+                    .and_then(|caster| caster.get_ability(slot))
+                    .map(|desc| {
+                        let blink = matches!(desc.effect, AbilityEffect::Blink { .. });
+                        (desc.targeting, blink)
+                    });
+                match ability {
+                    Some((_, true)) => {
+                        self.blink_aim = Some(BlinkAim {
+                            slot,
+                            cursor: mouse_world_position,
+                            destination: None,
+                            radius: 0.0,
+                        });
+                    }
+                    Some((targeting, false)) => {
+                        if let Some(target) =
+                            self.get_ability_target(player, targeting, mouse_world_position)
+                            && let Some(queue) = self.command_queues.get_mut(player)
+                        {
+                            queue.push(Command::Cast(slot, target), self.fixed_step);
+                        }
+                    }
+                    None if slot == 2 => {
+                        let mouse_position = input_state.get_mouse_position();
+                        if let Some(target) = self
+                            .pick_entity(mouse_position, |entity| self.can_attack(player, entity))
+                            && let Some(combat) = self.combats.get_mut(player)
+                        {
+                            combat.request_attack(target);
+                        }
+                    }
+                    None => {}
+                }
+            }
+
+            // A blink is aimed while its key is held and cast once it is let go
+            if let Some(aim) = &mut self.blink_aim {
+                aim.cursor = mouse_world_position.or(aim.cursor);
+                if !input_state.is_down(keys[aim.slot]) {
+                    if let Some(cursor) = aim.cursor
+                        && let Some(queue) = self.command_queues.get_mut(player)
+                    {
+                        queue.push(
+                            Command::Cast(aim.slot, AbilityTarget::Point(cursor)),
+                            self.fixed_step,
+                        );
+                    }
+                    self.blink_aim = None;
+                }
+            }
+        } else {
+            self.blink_aim = None;
+        }
+        drop(input_scope);
+
+        update_movement(dt, &self.transforms, &mut self.targets, &mut self.movements);
+        advance_animations(
+            dt,
+            &self.entities,
+            &mut self.animators,
+            &self.movements,
+            &self.transforms,
+            &self.combats,
+        );
+        face_movement(dt, &mut self.transforms, &self.movements);
+        update_tints(dt, &mut self.tints);
+
+        let player_position = self
+            .player
+            .and_then(|player| self.transforms.get(player))
+            .map(|transform| transform.position);
+        self.trail.update(dt, player_position);
+        self.update_trails(dt);
+
+        // Everything the fixed updates since the last frame produced
+        for event in self.events.drain() {
+            self.stats.record_event(&event, self.player);
+            match event {
+                GameEvent::DamageDealt {
+                    source,
+                    target,
+                    amount,
+                    fatal,
+                } => {
+                    log::debug!(
+                        "{:?} hit {:?} for {} damage{}",
+                        source,
+                        target,
+                        amount,
+                        if fatal { ", killing it" } else { "" }
+                    );
+
+                    if let Some(health) = self.healths.get(target)
+                        && let Some(bar) = self.health_bars.get(target)
+                    {
+                        self.tweens.start(
+                            TweenTarget::Entity(target, TweenField::HealthBarFill),
+                            TweenDesc {
+                                delay: HEALTH_BAR_DRAIN_DELAY,
+                                ..TweenDesc::new(
+                                    bar.fill,
+                                    health.current / health.max,
+                                    HEALTH_BAR_DRAIN_TIME,
+                                    Easing::QuadOut,
+                                )
+                            },
+                        );
+                    }
+
+                    // Arrows towards what hits the player, they only show while it is
+                    // out of view
+                    if self.player == Some(target)
+                        && let Some(source) = source
+                        && source != target
+                        && let Some(transform) = self.transforms.get(source)
+                    {
+                        self.offscreen_indicators
+                            .push_damage(source, transform.position);
+                    }
+
+                    // Only the hits the player is part of are worth a pause
+                    if self
+                        .player
+                        .is_some_and(|player| source == Some(player) || target == player)
+                    {
+                        if fatal {
+                            self.time.hit_stop(KILL_STOP_DURATION, KILL_STOP_SCALE);
+                        } else {
+                            self.time.hit_stop(HIT_STOP_DURATION, HIT_STOP_SCALE);
+                        }
+                    }
+                }
+                GameEvent::EntityDied { entity, killer } => {
+                    let text = match killer {
+                        Some(killer) => {
+                            format!("{} killed {}", self.get_name(killer), self.get_name(entity))
+                        }
+                        None => format!("{} died", self.get_name(entity)),
+                    };
+                    self.kill_feed.push(text);
+                    self.tweens.start(
+                        TweenTarget::KillFeedSlide,
+                        TweenDesc::new(0.0, 1.0, KILL_FEED_SLIDE_TIME, Easing::CubicOut),
+                    );
+                }
+                // There are no sounds, the caster flashes instead
+                GameEvent::AbilityFailed { caster, slot } => {
+                    log::debug!("{:?} failed the ability on slot {}", caster, slot);
+                    if let Some(tint) = self.tints.get_mut(caster) {
+                        tint.flash(FAILED_CAST_FLASH_COLOR, FAILED_CAST_FLASH_TIME);
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.kill_feed.update(real_dt);
+        let transforms = &self.transforms;
+        self.offscreen_indicators.update(real_dt, |entity| {
+            transforms.get(entity).map(|transform| transform.position)
+        });
+
+        if let Some(player) = self.player
+            && let Some(combat) = self.combats.get(player)
+        {
+            if self.last_attack_cooldown > 0.0 && combat.attack_cooldown <= 0.0 {
+                // Up and back down
+                self.tweens.start(
+                    TweenTarget::Entity(player, TweenField::HealthBarFlash),
+                    TweenDesc {
+                        repeat: 1,
+                        yoyo: true,
+                        ..TweenDesc::new(0.0, 1.0, COOLDOWN_FLASH_TIME * 0.5, Easing::SineOut)
+                    },
+                );
+            }
+            self.last_attack_cooldown = combat.attack_cooldown;
+        }
+
+        self.tooltip.update(real_dt, &mut self.tweens);
+
+        // On real time like the kill feed, the bars keep draining during a hit-stop
+        let health_bars = &mut self.health_bars;
+        let death_settles = &mut self.death_settles;
+        let kill_feed = &mut self.kill_feed;
+        let tooltip = &mut self.tooltip;
+        self.tweens
+            .advance(real_dt, &mut self.events, |target, value| match target {
+                TweenTarget::Entity(entity, field) => match field {
+                    TweenField::HealthBarFill | TweenField::HealthBarFlash => {
+                        if let Some(bar) = health_bars.get_mut(entity) {
+                            if field == TweenField::HealthBarFill {
+                                bar.fill = value;
+                            } else {
+                                bar.flash = value;
+                            }
+                        }
+                    }
+                    TweenField::DeathSettle => {
+                        if let Some(settle) = death_settles.get_mut(entity) {
+                            settle.set_progress(value);
+                        }
+                    }
+                },
+                TweenTarget::KillFeedSlide => kill_feed.set_slide(value),
+                TweenTarget::TooltipFade => tooltip.set_alpha(value),
+            });
+        self.update_death_settles(real_dt);
+
+        // Camera
+        {
+            let radius = self.camera.settings.radius;
+            let angle = self.camera.settings.angle.to_radians();
+
+            if input_state.is_pressed(InputAction::SwitchCameraMode) {
+                self.camera.mode = match self.camera.mode {
+                    CCameraMode::Follow => CCameraMode::Detached,
+                    CCameraMode::Detached => CCameraMode::Follow,
+                }
+            }
+
+            let player_transform = self.player.and_then(|player| self.transforms.get(player));
+            let transform = &mut self.camera.transform;
+
+            if let Some(player_transform) = player_transform
+                && (self.camera.mode == CCameraMode::Follow
+                    || input_state.is_down(InputAction::CameraFollow))
+            {
+                let mut target_position = player_transform.position.xz();
+                if let Some(bounds) = &self.bounds {
+                    target_position = bounds.clamp(target_position);
+                }
+                let camera_target = glam::vec3(0.0, 120.0, 0.0) + target_position.at_y(0.0);
+
+                let follow_position = camera_target
+                    + Vec3 {
+                        x: 0.0,
+                        y: angle.sin(),
+                        z: angle.cos(),
+                    } * radius;
+                if self.camera.catch_up > 0.0 {
+                    self.camera.catch_up -= real_dt;
+                    let t = 1.0 - (-CAMERA_CATCH_UP_RATE * real_dt).exp();
+                    transform.position = transform.position.lerp(follow_position, t);
+                } else {
+                    transform.position = follow_position;
+                }
+            }
+
+            transform.rotation = Quat::from_rotation_x(-angle);
+        }
+    }
+
+    // The renderer is only read, abilities spawn projectiles from prefabs
+    pub fn fixed_update(&mut self, dt: f32, renderer: &Renderer, physics_world: &mut PhysicsWorld) {
+        push_velocities(&self.physics_proxies, &self.movements, physics_world);
+        physics_world.step_simulation(dt);
+        pull_body_states(&mut self.physics_proxies, physics_world);
+        let player_position = self
+            .player
+            .and_then(|player| self.physics_proxies.get(player))
+            .and_then(|proxy| proxy.current_state)
+            .map(|state| state.position);
+        self.stats.record_step(dt, player_position);
+
+        update_status_effects(
+            dt,
+            &self.entities,
+            &mut self.status_effects,
+            &mut self.healths,
+            &mut self.movements,
+            &mut self.events,
+        );
+
+        self.update_wave_spawners(dt, renderer, physics_world);
+        update_lane_followers(
+            &self.entities,
+            &mut self.lane_followers,
+            &mut self.combats,
+            &self.healths,
+            &self.teams,
+            &self.physics_proxies,
+            &mut self.targets,
+            physics_world,
+        );
+
+        update_combat(
+            dt,
+            &self.entities,
+            &mut self.combats,
+            &mut self.healths,
+            &self.status_effects,
+            &self.teams,
+            &self.physics_proxies,
+            &mut self.movements,
+            &mut self.targets,
+            physics_world,
+            self.friendly_fire,
+            &mut self.events,
+        );
+
+        self.fixed_step += 1;
+        update_command_queues(
+            dt,
+            self.fixed_step,
+            &self.entities,
+            &mut self.command_queues,
+            &mut self.ability_casters,
+            &mut self.targets,
+        );
+        let executions = update_abilities(
+            dt,
+            &self.entities,
+            &mut self.ability_casters,
+            &self.healths,
+            &self.physics_proxies,
+            &mut self.movements,
+            &mut self.targets,
+            physics_world,
+            &mut self.events,
+        );
+        self.execute_abilities(executions, renderer, physics_world);
+
+        let mut spent = update_projectiles(
+            &self.entities,
+            &self.projectiles,
+            &mut self.healths,
+            &mut self.status_effects,
+            &self.teams,
+            &self.physics_proxies,
+            physics_world,
+            &self.projectile_effects,
+            self.friendly_fire,
+            &mut self.events,
+        );
+        for projectile in expire_projectiles(dt, &self.entities, &mut self.projectiles) {
+            if !spent.contains(&projectile) {
+                spent.push(projectile);
+            }
+        }
+        for projectile in spent {
+            self.retire_projectile(projectile, physics_world);
+        }
+
+        for entity in get_dead_followers(&self.entities, &self.lane_followers, &self.healths) {
+            if let Some(body_id) = self
+                .physics_proxies
+                .get(entity)
+                .and_then(|proxy| proxy.body_id)
+            {
+                physics_world.remove_body(body_id);
+            }
+            if self.poses.get(entity).is_some() {
+                self.start_death_settle(entity);
+            } else {
+                self.despawn(entity);
+            }
+        }
+    }
+
+    // The body is left without anything that moves or animates it, its pose is frozen on
+    // the next frame
+    fn start_death_settle(&mut self, entity: Entity) {
+        self.physics_proxies.remove(entity);
+        self.animators.remove(entity);
+        self.movements.remove(entity);
+        self.targets.remove(entity);
+        self.combats.remove(entity);
+        self.status_effects.remove(entity);
+        self.health_bars.remove(entity);
+        self.ability_casters.remove(entity);
+        self.command_queues.remove(entity);
+        self.lane_followers.remove(entity);
+        self.tweens.stop_entity(entity);
+
+        let origin = self
+            .transforms
+            .get(entity)
+            .map_or(Vec3::ZERO, |transform| transform.position);
+        self.death_settles.insert(entity, DeathSettle::new(origin));
+        if self.tints.get(entity).is_none() {
+            self.tints.insert(entity, TintAnimator::default());
+        }
+        self.tweens.start(
+            TweenTarget::Entity(entity, TweenField::DeathSettle),
+            TweenDesc::new(0.0, 1.0, SETTLE_DURATION, SETTLE_EASING),
+        );
+    }
+
+    // Corpses sink with their settle and are despawned once they have faded out
+    fn update_death_settles(&mut self, dt: f32) {
+        let mut finished = Vec::new();
+        for (entity, settle, tint) in
+            join3(&self.entities, &mut self.death_settles, &mut self.tints)
+        {
+            if let Some(transform) = self.transforms.get_mut(entity) {
+                transform.position = settle.get_position();
+            }
+            if settle.update(dt, tint) {
+                finished.push(entity);
+            }
+        }
+        for entity in finished {
+            self.despawn(entity);
+        }
+    }
+
+    // The waves due on this step join the lanes. Only a local game has them, the server
+    // owns the units of a networked one.
+    fn update_wave_spawners(
+        &mut self,
+        dt: f32,
+        renderer: &Renderer,
+        physics_world: &mut PhysicsWorld,
+    ) {
+        if !self.network_entities.is_empty() {
+            return;
+        }
+        let mut positions = Vec::new();
+        for index in 0..self.wave_spawners.len() {
+            let spawner = &mut self.wave_spawners[index];
+            spawner.tick(dt, &mut positions);
+            if positions.is_empty() {
+                continue;
+            }
+            let name = spawner.name.clone();
+            let prefab = spawner.prefab.clone();
+            let lane = spawner.get_lane();
+            let overrides = PrefabOverrides {
+                rotation: spawner.get_rotation(),
+                team: Some(spawner.team),
+                ..Default::default()
+            };
+            for &position in &positions {
+                let overrides = PrefabOverrides {
+                    position,
+                    ..overrides
+                };
+                match self.spawn_prefab(&prefab, &overrides, renderer, physics_world) {
+                    Ok(entity) => self
+                        .lane_followers
+                        .insert(entity, LaneFollower::new(lane.clone())),
+                    Err(error) => log::error!("{} failed to spawn a wave: {:#}", name, error),
+                }
+            }
+        }
+    }
+
+    // Trails follow their owners and are despawned once they have faded after them
+    fn update_trails(&mut self, dt: f32) {
+        let mut finished = Vec::new();
+        for (entity, trail) in join(&self.entities, &mut self.trails) {
+            let position = trail
+                .owner
+                .and_then(|owner| self.transforms.get(owner))
+                .map(|transform| transform.position);
+            trail.update(dt, position);
+            if trail.is_finished() {
+                finished.push(entity);
+            }
+        }
+        for entity in finished {
+            self.despawn(entity);
+        }
+    }
+
+    // The effects of the casts that landed this step
+    fn execute_abilities(
+        &mut self,
+        executions: Vec<AbilityExecution>,
+        renderer: &Renderer,
+        physics_world: &mut PhysicsWorld,
+    ) {
+        for execution in executions {
+            let caster = execution.caster;
+            let desc = &execution.desc;
+            let Some(position) = self
+                .physics_proxies
+                .get(caster)
+                .and_then(|proxy| proxy.body_id)
+                .and_then(|body_id| physics_world.get_state(body_id))
+                .map(|state| state.position)
+            else {
+                continue;
+            };
+            let target_entity = match execution.target {
+                AbilityTarget::Unit(target) => Some(target),
+                AbilityTarget::SelfCast => Some(caster),
+                AbilityTarget::Point(_) | AbilityTarget::Direction(_) => None,
+            };
+
+            match &desc.effect {
+                AbilityEffect::Projectile {
+                    prefab,
+                    speed,
+                    damage,
+                    status,
+                } => {
+                    let target = match execution.target {
+                        AbilityTarget::Point(point) => Some(point),
+                        AbilityTarget::Direction(direction) => {
+                            Some((position + direction * desc.range).extend(0.0).xzy())
+                        }
+                        AbilityTarget::Unit(_) | AbilityTarget::SelfCast => target_entity
+                            .and_then(|target| self.transforms.get(target))
+                            .map(|transform| transform.position),
+                    };
+                    if let Some(target) = target
+                        && let Err(error) = self.spawn_projectile(
+                            caster,
+                            prefab,
+                            target,
+                            *speed,
+                            *damage,
+                            *status,
+                            renderer,
+                            physics_world,
+                        )
+                    {
+                        log::error!("{}: {:#}", desc.name, error);
+                    }
+                }
+                AbilityEffect::Damage { amount } => {
+                    if let Some(target) = target_entity
+                        && can_damage(&self.teams, caster, target, self.friendly_fire)
+                        && let Some(health) = self.healths.get_mut(target)
+                        && !health.is_dead()
+                    {
+                        let multiplier = self
+                            .status_effects
+                            .get(target)
+                            .map_or(1.0, StatusEffects::incoming_damage_multiplier);
+                        let damage = amount * multiplier;
+                        health.current = (health.current - damage).max(0.0);
+                        self.events
+                            .push_damage(Some(caster), target, damage, health.is_dead());
+                    }
+                }
+                AbilityEffect::Heal { amount } => {
+                    if let Some(target) = target_entity
+                        && let Some(health) = self.healths.get_mut(target)
+                        && !health.is_dead()
+                    {
+                        health.current = (health.current + amount).min(health.max);
+                    }
+                }
+                AbilityEffect::Status { effect, radius } => {
+                    let targets: Vec<Entity> = match execution.target {
+                        AbilityTarget::Point(point) => {
+                            let team = self.teams.get(caster).copied();
+                            let bodies = physics_world.query_shape_of_teams(
+                                point.xz(),
+                                CollisionShape::Circle { radius: *radius },
+                                |other| self.friendly_fire || Some(other) != team,
+                            );
+                            join(&self.entities, &self.physics_proxies)
+                                .filter(|(entity, proxy)| {
+                                    *entity != caster
+                                        && proxy.body_id.is_some_and(|id| bodies.contains(&id))
+                                })
+                                .map(|(entity, _)| entity)
+                                .collect()
+                        }
+                        _ => target_entity.into_iter().collect(),
+                    };
+                    for target in targets {
+                        if let Some(effects) = self.status_effects.get_mut(target) {
+                            effects.apply(*effect);
+                            self.events.push(GameEvent::EffectApplied {
+                                target,
+                                kind: effect.kind,
+                            });
+                        }
+                    }
+                }
+                AbilityEffect::Blink { search_radius } => {
+                    let body_id = self
+                        .physics_proxies
+                        .get(caster)
+                        .and_then(|proxy| proxy.body_id);
+                    let destination = match execution.target {
+                        AbilityTarget::Point(point) => body_id
+                            .and_then(|body_id| physics_world.get_shape(body_id))
+                            .and_then(|shape| {
+                                resolve_blink_destination(
+                                    physics_world,
+                                    shape,
+                                    position,
+                                    point.xz(),
+                                    desc.range,
+                                    *search_radius,
+                                )
+                            }),
+                        _ => None,
+                    };
+
+                    if let Some(destination) = destination
+                        && let Some(transform) = self.transforms.get(caster).copied()
+                    {
+                        let position = destination.at_y(transform.position.y);
+                        self.set_edited_transform(
+                            caster,
+                            Transform {
+                                position,
+                                ..transform
+                            },
+                            physics_world,
+                        );
+                        if self.player == Some(caster) {
+                            self.camera.catch_up = BLINK_CAMERA_CATCH_UP_TIME;
+                        }
+                    } else {
+                        // Nothing was done, so nothing is paid
+                        if let Some(caster) = self.ability_casters.get_mut(caster) {
+                            caster.set_cooldown_steps(execution.slot, 0, 0);
+                            caster.energy = (caster.energy + desc.cost).min(caster.max_energy);
+                        }
+                        self.events.push(GameEvent::AbilityFailed {
+                            caster,
+                            slot: execution.slot,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    // The destination the telegraph of an aimed blink shows, resolved like the cast will be
+    pub fn update_blink_aim(&mut self, physics_world: &PhysicsWorld) {
+        let Some(aim) = &mut self.blink_aim else {
+            return;
+        };
+        let player = self.player;
+        let blink = || {
+            let player = player?;
+            let desc = self.ability_casters.get(player)?.get_ability(aim.slot)?;
+            let AbilityEffect::Blink { search_radius } = desc.effect else {
+                return None;
+            };
+            let body_id = self.physics_proxies.get(player)?.body_id?;
+            let shape = physics_world.get_shape(body_id)?;
+            let position = physics_world.get_state(body_id)?.position;
+            Some((desc.range, search_radius, shape, position))
+        };
+        let Some((range, search_radius, shape, position)) = blink() else {
+            aim.destination = None;
+            return;
+        };
+
+        let (min, max) = shape.get_local_abb();
+        aim.radius = (max - min).max_element() * 0.5;
+        aim.destination = aim.cursor.and_then(|cursor| {
+            resolve_blink_destination(
+                physics_world,
+                shape,
+                position,
+                cursor.xz(),
+                range,
+                search_radius,
+            )
+        });
+    }
+
+    pub fn render(&mut self, renderer: &mut Renderer) {
+        accumulate_poses(renderer, &self.animators, &mut self.poses);
+        settle_death_poses(
+            renderer,
+            &self.entities,
+            &self.renderables,
+            &mut self.death_settles,
+            &mut self.poses,
+        );
+        // After the poses, children can be attached to bones
+        propagate_transforms(
+            &self.entities,
+            &mut self.parents,
+            &mut self.transforms,
+            |parent, bone| {
+                let renderable = self.renderables.get(parent)?;
+                let pose = self.poses.get(parent)?;
+                let bone = renderer.get_bone_model_matrix(renderable.mesh, pose, bone)?;
+                Some(renderable.render_offset * bone)
+            },
+        );
+        let player_team = self
+            .player
+            .and_then(|player| self.teams.get(player).copied());
+        submit_renderables(
+            renderer,
+            &self.entities,
+            &self.transforms,
+            &self.renderables,
+            &self.poses,
+            &self.tints,
+            &self.teams,
+            &self.skinning_debugs,
+            player_team,
+            self.selection.get_selected(),
+        );
+        draw_skeletons(
+            renderer,
+            &self.entities,
+            &self.transforms,
+            &self.renderables,
+            &self.poses,
+            &self.skinning_debugs,
+        );
+        self.trail.render(renderer);
+        self.trail_batch.build(
+            join(&self.entities, &self.trails).map(|(_, trail)| trail),
+            self.camera.transform.position,
+        );
+        self.trail_batch.render(renderer);
+        submit_selection_rings(
+            renderer,
+            &self.transforms,
+            &self.teams,
+            self.selection.get_selected(),
+        );
+        if self.editor_enabled
+            && let Some(transform) = self
+                .selection
+                .get_selected()
+                .first()
+                .and_then(|entity| self.transforms.get(*entity))
+        {
+            let position = transform.position;
+            self.gizmo
+                .render(renderer, position, self.get_gizmo_scale(position));
+        }
+        let view_projection = self.camera.projection * self.camera.transform.to_matrix().inverse();
+        submit_health_bars(
+            renderer,
+            view_projection,
+            self.screen_size,
+            &self.entities,
+            &self.transforms,
+            &self.health_bars,
+            &self.teams,
+        );
+        submit_status_icons(
+            renderer,
+            view_projection,
+            self.screen_size,
+            &self.transforms,
+            &self.status_effects,
+            self.mouse_position,
+            &mut self.tooltip,
+        );
+        submit_telegraphs(renderer, &self.entities, &self.ability_casters);
+        if let Some(aim) = &self.blink_aim {
+            submit_blink_telegraph(renderer, aim);
+        }
+        if let Some(caster) = self
+            .player
+            .and_then(|player| self.ability_casters.get(player))
+        {
+            submit_ability_bar(renderer, self.screen_size, caster);
+        }
+        self.kill_feed.render(renderer);
+        self.offscreen_indicators
+            .render(renderer, view_projection, self.screen_size);
+        self.selection.render(renderer);
+
+        // Camera
+        {
+            renderer.set_camera_projection(self.camera.projection);
+            renderer.set_camera_position_and_orientation(
+                self.camera.transform.position,
+                self.camera.transform.rotation,
+            );
+        }
+
+        // Last, once everything had the chance to request it
+        self.tooltip.render(renderer);
+    }
+
+    // The window size, picking works in it whatever the render scale of the scene
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.screen_size = Vec2::new(width as f32, height as f32);
+        self.update_projection();
+    }
+
+    fn update_projection(&mut self) {
+        self.camera.projection = Mat4::perspective_rh(
+            self.camera.settings.fov.to_radians(),
+            self.screen_size.x / self.screen_size.y,
+            1.0,
+            3000.0,
+        );
+    }
+
+    pub fn get_camera_settings(&self) -> CameraSettings {
+        self.camera.settings
+    }
+
+    pub fn set_camera_settings(&mut self, settings: CameraSettings) {
+        self.camera.settings = settings;
+        self.update_projection();
+    }
+
+    // Attack over a living entity other than the player, no target over a dead one
+    pub fn get_cursor_kind(&self, mouse_position: Vec2) -> CursorKind {
+        let hovered = self
+            .pick_entity(mouse_position, |entity| Some(entity) != self.player)
+            .and_then(|entity| self.healths.get(entity));
+        match hovered {
+            Some(health) if health.is_dead() => CursorKind::NoTarget,
+            Some(_) => CursorKind::Attack,
+            None => CursorKind::Default,
+        }
+    }
+
+    // The entity with health closest to where the mouse points on the ground, if it is
+    // close enough and passes the filter
+    pub fn pick_entity(
+        &self,
+        mouse_position: Vec2,
+        filter: impl Fn(Entity) -> bool,
+    ) -> Option<Entity> {
+        const PICK_RADIUS: f32 = 80.0;
+
+        let point = Self::get_world_position_from_screen(
+            self.camera.projection * self.camera.transform.to_matrix().inverse(),
+            mouse_position,
+            0.0,
+        )?;
+        join3(&self.entities, &self.transforms, &self.healths)
+            .filter(|(entity, _, _)| filter(*entity))
+            .map(|(entity, transform, _)| (transform.position.xz().distance(point.xz()), entity))
+            .filter(|(distance, _)| *distance < PICK_RADIUS)
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, entity)| entity)
+    }
+
+    // Our own living units, the ones that take move orders. The server moves the entities
+    // of other clients, they have no target location.
+    fn is_selectable(&self, entity: Entity) -> bool {
+        self.targets.get(entity).is_some()
+            && self.remote_proxies.get(entity).is_none()
+            && !self
+                .healths
+                .get(entity)
+                .is_some_and(|health| health.is_dead())
+    }
+
+    // From the cursor on the ground, units are the closest one
+    fn get_ability_target(
+        &self,
+        caster: Entity,
+        targeting: Targeting,
+        cursor: Option<Vec3>,
+    ) -> Option<AbilityTarget> {
+        match targeting {
+            Targeting::Point => cursor.map(AbilityTarget::Point),
+            Targeting::Unit => self.get_closest_target(caster).map(AbilityTarget::Unit),
+            Targeting::Direction => {
+                let position = self.transforms.get(caster)?.position;
+                let direction = (cursor? - position).xz().normalize_or_zero();
+                Some(AbilityTarget::Direction(direction))
+            }
+            Targeting::SelfCast => Some(AbilityTarget::SelfCast),
+        }
+    }
+
+    // The unit under a click, or the units whose positions are inside a drag on screen
+    fn get_selectable_in(&self, gesture: SelectionGesture) -> Vec<Entity> {
+        match gesture {
+            SelectionGesture::Click(position) => self
+                .pick_entity(position / self.screen_size, |entity| {
+                    self.is_selectable(entity)
+                })
+                .into_iter()
+                .collect(),
+            SelectionGesture::Drag { min, max } => {
+                let view_projection =
+                    self.camera.projection * self.camera.transform.to_matrix().inverse();
+                join(&self.entities, &self.transforms)
+                    .filter(|(entity, _)| self.is_selectable(*entity))
+                    .filter(|(_, transform)| {
+                        get_screen_position(view_projection, transform.position, self.screen_size)
+                            .is_some_and(|point| point.cmpge(min).all() && point.cmple(max).all())
+                    })
+                    .map(|(entity, _)| entity)
+                    .collect()
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn get_selection(&self) -> &[Entity] {
+        self.selection.get_selected()
+    }
+
+    pub fn get_time(&self) -> &TimeController {
+        &self.time
+    }
+
+    pub fn get_time_mut(&mut self) -> &mut TimeController {
+        &mut self.time
+    }
+
+    // Detaches the camera and centers it on the point, like following something there
+    pub fn move_camera_to(&mut self, target: Vec3) {
+        let angle = self.camera.settings.angle.to_radians();
+        let offset = Vec3::new(0.0, angle.sin(), angle.cos()) * self.camera.settings.radius;
+        self.camera.mode = CCameraMode::Detached;
+        self.camera.transform.position = target.with_y(120.0) + offset;
+    }
+
+    // The point on the ground the camera looks at, the inverse of move_camera_to
+    pub fn get_camera_target(&self) -> Vec3 {
+        let angle = self.camera.settings.angle.to_radians();
+        let offset = Vec3::new(0.0, angle.sin(), angle.cos()) * self.camera.settings.radius;
+        (self.camera.transform.position - offset).with_y(0.0)
+    }
+
+    pub fn get_player(&self) -> Option<Entity> {
+        self.player
+    }
+
+    // An arrow at the edge of the screen points at it while it is out of view, the id
+    // unpins it again
+    pub fn pin_objective(&mut self, position: Vec3) -> u32 {
+        self.offscreen_indicators.pin_objective(position)
+    }
+
+    pub fn unpin_objective(&mut self, id: u32) -> bool {
+        self.offscreen_indicators.unpin_objective(id)
+    }
+
+    pub fn get_position(&self, entity: Entity) -> Option<Vec3> {
+        self.transforms
+            .get(entity)
+            .map(|transform| transform.position)
+    }
+
+    // By the index shown in names like "Entity 12"
+    pub fn get_entity_position(&self, index: u32) -> Option<Vec3> {
+        self.get_position(self.entities.get(index as usize)?)
+    }
+
+    // Moves the entity and its body on the ground, without sweeping through what is in
+    // between. It forgets where it was walking to.
+    pub fn teleport(
+        &mut self,
+        entity: Entity,
+        position: Vec2,
+        physics_world: &mut PhysicsWorld,
+    ) -> anyhow::Result<()> {
+        let Some(transform) = self.transforms.get(entity) else {
+            bail!("{} has no position", self.get_name(entity));
+        };
+        let transform = Transform {
+            position: position.extend(transform.position.y).xzy(),
+            ..*transform
+        };
+        self.set_edited_transform(entity, transform, physics_world);
+        if let Some(target) = self.targets.get_mut(entity) {
+            *target = None;
+        }
+        Ok(())
+    }
+
+    // Like a status ability hitting the entity
+    pub fn apply_status_effect(
+        &mut self,
+        entity: Entity,
+        effect: StatusEffectDesc,
+    ) -> anyhow::Result<()> {
+        let Some(effects) = self.status_effects.get_mut(entity) else {
+            bail!("{} can't have status effects", self.get_name(entity));
+        };
+        effects.apply(effect);
+        self.events.push(GameEvent::EffectApplied {
+            target: entity,
+            kind: effect.kind,
+        });
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn get_camera_position(&self) -> Vec3 {
+        self.camera.transform.position
+    }
+
+    #[allow(dead_code)]
+    pub fn get_entity_count(&self) -> usize {
+        join(&self.entities, &self.transforms).count()
+    }
+
+    pub fn get_stats(&self) -> &StatsTracker {
+        &self.stats
+    }
+
+    pub fn get_stats_mut(&mut self) -> &mut StatsTracker {
+        &mut self.stats
+    }
+
+    // Without frames nothing else drains the events, e.g. when simulating headless. Only the
+    // stats see them.
+    #[allow(dead_code)]
+    pub fn record_events(&mut self) {
+        for event in self.events.drain() {
+            self.stats.record_event(&event, self.player);
+        }
+    }
+
+    // Keeps the child where it is in the world, None detaches it
+    #[allow(dead_code)]
+    pub fn set_parent(&mut self, child: Entity, parent: Option<Entity>) -> bool {
+        set_parent(&mut self.parents, &self.transforms, child, parent)
+    }
+
+    // The child follows the bone with the given offset, e.g. a weapon in a hand
+    #[allow(dead_code)]
+    pub fn attach_to_bone(&mut self, child: Entity, parent: Entity, bone: usize, local: Transform) {
+        self.parents.insert(
+            child,
+            CParent {
+                parent,
+                bone: Some(bone),
+                local,
+            },
+        );
+    }
+
+    // Entities with a pose and their bone count
+    #[allow(dead_code)]
+    pub fn get_skinned_entities(&self) -> Vec<(Entity, usize)> {
+        join(&self.entities, &self.poses)
+            .map(|(entity, pose)| (entity, pose.transforms.len()))
+            .collect()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_skinning_debug(&self, entity: Entity) -> SkinningDebug {
+        self.skinning_debugs
+            .get(entity)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    #[allow(dead_code)]
+    pub fn set_skinning_debug(&mut self, entity: Entity, debug: SkinningDebug) {
+        if debug == SkinningDebug::default() {
+            self.skinning_debugs.remove(entity);
+        } else {
+            self.skinning_debugs.insert(entity, debug);
+        }
+    }
+
+    pub fn get_name(&self, entity: Entity) -> String {
+        if Some(entity) == self.player {
+            "You".to_string()
+        } else {
+            format!("Entity {}", entity.index())
+        }
+    }
+
+    // Attacks go to the entity under the cursor, the combat system checks the range
+    fn can_attack(&self, attacker: Entity, target: Entity) -> bool {
+        target != attacker
+            && self
+                .healths
+                .get(target)
+                .is_some_and(|health| !health.is_dead())
+            && can_damage(&self.teams, attacker, target, self.friendly_fire)
+    }
+
+    // The living entity with health closest to the caster, the ability checks the range
+    fn get_closest_target(&self, attacker: Entity) -> Option<Entity> {
+        let position = self.transforms.get(attacker)?.position;
+        join3(&self.entities, &self.transforms, &self.healths)
+            .filter(|(entity, _, health)| *entity != attacker && !health.is_dead())
+            .min_by(|(_, a, _), (_, b, _)| {
+                let a = a.position.distance_squared(position);
+                let b = b.position.distance_squared(position);
+                a.total_cmp(&b)
+            })
+            .map(|(entity, _, _)| entity)
+    }
+
+    // Groups the props by how they are drawn and replaces every group by one entity drawing
+    // a combined mesh. The props keep their bodies, their renderables are kept in CBaked.
+    // A group of one prop gains nothing and is left alone.
+    fn bake_static_geometry(
+        &mut self,
+        renderer: &mut Renderer,
+        props: &[Entity],
+        name_prefix: &str,
+    ) -> BakeStats {
+        // Ordered, so the combined meshes come out the same on every load
+        let mut groups: BTreeMap<(ResourceHandle, ResourceHandle, bool, u32), Vec<Entity>> =
+            BTreeMap::new();
+        for &entity in props {
+            let is_static = self.poses.get(entity).is_none()
+                && self.animators.get(entity).is_none()
+                && self.movements.get(entity).is_none()
+                && self.parents.get(entity).is_none();
+            let (Some(renderable), Some(_)) =
+                (self.renderables.get(entity), self.transforms.get(entity))
+            else {
+                continue;
+            };
+            // Skeletal and dynamic meshes have no geometry to bake
+            if !is_static || renderer.get_static_mesh_geometry(renderable.mesh).is_none() {
+                continue;
+            }
+            let key = (
+                renderable.mesh,
+                renderable.material,
+                renderable.casts_shadow,
+                renderable.render_layers,
+            );
+            groups.entry(key).or_default().push(entity);
+        }
+        groups.retain(|_, members| members.len() > 1);
+
+        let mut stats = BakeStats::default();
+        let mut batches = Vec::new();
+        for ((mesh, material, casts_shadow, render_layers), members) in groups {
+            let Some((vertices, indices)) = renderer.get_static_mesh_geometry(mesh) else {
+                continue;
+            };
+            let mut geometry = BakedGeometry::default();
+            for &entity in &members {
+                let transform = &self.transforms.get(entity).unwrap();
+                let renderable = self.renderables.get(entity).unwrap();
+                geometry.append(
+                    vertices,
+                    indices,
+                    &BakeInstance {
+                        transform: transform.to_matrix() * renderable.render_offset,
+                        color: renderable.color,
+                        tex_coord: renderable.tex_coord,
+                        tex_scale: renderable.tex_scale,
+                    },
+                );
+            }
+
+            let get_name = |handle| {
+                let pool = renderer.get_resource_pool();
+                pool.get_name(handle)
+                    .map_or_else(|| format!("{:016x}", handle), str::to_string)
+            };
+            let name = format!(
+                "{}/{}/{}/{}/{}",
+                name_prefix,
+                get_name(mesh),
+                get_name(material),
+                if casts_shadow { "shadow" } else { "no shadow" },
+                render_layers
+            );
+            let baked_mesh =
+                renderer.create_static_mesh(&name, &geometry.vertices, &geometry.indices);
+
+            let bake = self.entities.spawn();
+            self.transforms.insert(bake, Default::default());
+            self.renderables.insert(
+                bake,
+                CRenderable {
+                    mesh: baked_mesh,
+                    material,
+                    casts_shadow,
+                    render_layers,
+                    ..Default::default()
+                },
+            );
+            for &entity in &members {
+                if let Some(renderable) = self.renderables.remove(entity) {
+                    self.baked.insert(entity, CBaked { bake, renderable });
+                }
+            }
+
+            if !batches.contains(&(mesh, material)) {
+                batches.push((mesh, material));
+            }
+            stats.instances_before += members.len();
+            stats.instances_after += 1;
+            stats.batches_after += 1;
+            stats.baked_bytes += geometry.get_size();
+        }
+        stats.batches_before = batches.len();
+        stats
+    }
+
+    // Replaces the clutter of the level built before
+    fn build_scatter_layers(
+        &mut self,
+        level: &Level,
+        files: &HashMap<String, Vec<u8>>,
+        renderer: &mut Renderer,
+    ) {
+        for handle in self.scatter_layers.drain(..) {
+            renderer.remove_persistent_instances(handle);
+        }
+
+        for desc in &level.scatter {
+            let name = format!("Scatter/{}", desc.name);
+            match Self::build_scatter_layer(files, desc, &name, renderer) {
+                Ok(handle) => self.scatter_layers.push(handle),
+                Err(error) => log::error!("Failed to scatter {}: {:#}", desc.name, error),
+            }
+        }
+    }
+
+    fn build_scatter_layer(
+        files: &HashMap<String, Vec<u8>>,
+        desc: &ScatterDesc,
+        name: &str,
+        renderer: &mut Renderer,
+    ) -> anyhow::Result<ResourceHandle> {
+        let mesh = get_handle(&desc.mesh);
+        let Some((mesh_min, mesh_max)) = renderer.get_static_mesh_bounds(mesh) else {
+            bail!("{} is not a static mesh with geometry", desc.mesh);
+        };
+
+        let density_map = match &desc.density_map {
+            Some(map) => {
+                let Some(bytes) = files.get(map) else {
+                    bail!("No texture file for the density map {}", map);
+                };
+                Some(DensityMap::from_texture(&TextureDesc::load(bytes)?)?)
+            }
+            None => None,
+        };
+
+        let layer = ScatterLayer {
+            min: Vec2::from(desc.min),
+            max: Vec2::from(desc.max),
+            density: desc.density,
+            density_map,
+            seed: desc.seed,
+            scale: desc.scale[0]..desc.scale[1],
+            yaw: desc.yaw[0]..desc.yaw[1],
+            color: Vec4::from(desc.color),
+            chunk_size: desc.chunk_size,
+        };
+        // The maps are flat, the ground is at zero everywhere
+        let scatter = layer.generate(mesh_min, mesh_max, |_| 0.0);
+        log::info!(
+            "Scattered {} instances of {} in {} chunks",
+            scatter.instances.len(),
+            desc.name,
+            scatter.chunks.len()
+        );
+
+        Ok(renderer.create_persistent_instances(
+            name,
+            PersistentSet {
+                mesh,
+                material: get_handle(&desc.material),
+                casts_shadow: desc.casts_shadow,
+                render_layers: RENDER_LAYER_DEFAULT,
+                chunks: scatter.chunks,
+            },
+            &scatter.instances,
+        ))
+    }
+
+    // Logged when the level is built, the inspector shows them as well
+    #[cfg(feature = "inspector")]
+    pub fn get_bake_stats(&self) -> &BakeStats {
+        &self.bake_stats
+    }
+
+    // Resources are saved by name, see GameSave
+    pub fn serialize(
+        &self,
+        physics_world: &PhysicsWorld,
+        renderer: &Renderer,
+    ) -> anyhow::Result<GameSave> {
+        let resource_pool = renderer.get_resource_pool();
+        self.save(physics_world, |handle| {
+            resource_pool.get_name(handle).map(str::to_string)
+        })
+    }
+
+    // Replaces every entity and physics body with the saved ones. The level the save was
+    // made in has to be loaded, its resources are looked up by name.
+    pub fn deserialize(
+        &mut self,
+        save: &GameSave,
+        physics_world: &mut PhysicsWorld,
+        renderer: &Renderer,
+    ) -> anyhow::Result<()> {
+        let resource_pool = renderer.get_resource_pool();
+        self.restore(
+            save,
+            physics_world,
+            |name| {
+                let handle = get_handle(name);
+                if resource_pool.get_resource(handle).is_none() {
+                    bail!("Unknown resource \"{}\"", name);
+                }
+                Ok(handle)
+            },
+            |mesh| renderer.create_pose(mesh),
+            |desc| build_locomotion(renderer, desc),
+        )
+    }
+
+    fn save(
+        &self,
+        physics_world: &PhysicsWorld,
+        get_name: impl Fn(ResourceHandle) -> Option<String>,
+    ) -> anyhow::Result<GameSave> {
+        let Some(level) = &self.level_name else {
+            bail!("There is no level to save");
+        };
+        if !self.network_entities.is_empty() {
+            bail!("The server owns the game state, it can't be saved");
+        }
+        let name = |handle: ResourceHandle| {
+            get_name(handle).ok_or_else(|| anyhow::anyhow!("Resource {:016x} has no name", handle))
+        };
+
+        // The bodies of the entities first, in the order of the entities, so the same state
+        // always numbers them the same
+        // Trails are only for show and are left out, like the parked projectiles of the pool
+        // and their disabled bodies. Streamed chunks load again and are left out with their
+        // bodies.
+        let entities: Vec<Entity> = (&self.entities)
+            .slots()
+            .flatten()
+            .map(|(_, entity)| entity)
+            .filter(|entity| self.trails.get(*entity).is_none())
+            .filter(|entity| !self.projectile_pool.is_parked(*entity))
+            .filter(|entity| self.streamed.get(*entity).is_none())
+            .collect();
+        let streamed_body_ids: Vec<BodyId> = join(&self.entities, &self.streamed)
+            .filter_map(|(entity, _)| self.physics_proxies.get(entity)?.body_id)
+            .collect();
+        let entity_indices: HashMap<Entity, usize> = entities
+            .iter()
+            .enumerate()
+            .map(|(index, entity)| (*entity, index))
+            .collect();
+        let mut body_ids: Vec<BodyId> = entities
+            .iter()
+            .filter_map(|entity| self.physics_proxies.get(*entity)?.body_id)
+            .filter(|body_id| physics_world.get_state(*body_id).is_some())
+            .collect();
+        for body_id in physics_world.get_body_ids() {
+            if physics_world.is_enabled(body_id)
+                && !body_ids.contains(&body_id)
+                && !streamed_body_ids.contains(&body_id)
+            {
+                body_ids.push(body_id);
+            }
+        }
+        let body_indices: HashMap<BodyId, usize> = body_ids
+            .iter()
+            .enumerate()
+            .map(|(index, body_id)| (*body_id, index))
+            .collect();
+
+        let bodies = body_ids
+            .iter()
+            .filter_map(|&body_id| {
+                let state = physics_world.get_state(body_id)?;
+                Some(BodySave {
+                    position: state.position.into(),
+                    velocity: state.velocity.into(),
+                    layer: save_layer(physics_world.get_layer(body_id)?),
+                    shape: save_shape(physics_world.get_shape(body_id)?),
+                    listen_to_contact_events: physics_world.is_listening_to_contact_events(body_id),
+                })
+            })
+            .collect();
+
+        let mut entity_saves = Vec::with_capacity(entities.len());
+        for &entity in &entities {
+            let renderable = match self.renderables.get(entity) {
+                Some(renderable) => Some(RenderableSave {
+                    mesh: name(renderable.mesh)?,
+                    material: name(renderable.material)?,
+                    render_offset: renderable.render_offset.to_cols_array(),
+                    color: renderable.color.into(),
+                    tex_coord: renderable.tex_coord.into(),
+                    tex_scale: renderable.tex_scale.into(),
+                    casts_shadow: renderable.casts_shadow,
+                    shadow_proxy: match renderable.shadow_proxy {
+                        ShadowProxy::None => ShadowProxySave::None,
+                        ShadowProxy::StaticMesh(mesh) => {
+                            ShadowProxySave::StaticMesh { mesh: name(mesh)? }
+                        }
+                        ShadowProxy::Capsule { radius, height } => {
+                            ShadowProxySave::Capsule { radius, height }
+                        }
+                    },
+                    render_layers: renderable.render_layers,
+                    variant: renderable.variant,
+                }),
+                None => None,
+            };
+            let animator = match self.animators.get(entity) {
+                Some(animator) => Some(AnimatorSave {
+                    phase: animator.phase,
+                    animations: animator
+                        .animation_states
+                        .iter()
+                        .map(|state| {
+                            Ok(AnimationSave {
+                                animation: name(state.animation)?,
+                                time: state.time,
+                                looping: state.looping,
+                                blend_weight: state.blend_weight,
+                            })
+                        })
+                        .collect::<anyhow::Result<_>>()?,
+                }),
+                None => None,
+            };
+
+            let lane_follower = match self.lane_followers.get(entity) {
+                Some(follower) => Some(LaneFollowerSave {
+                    lane: self
+                        .wave_spawners
+                        .iter()
+                        .position(|spawner| follower.is_on_lane_of(spawner))
+                        .ok_or_else(|| anyhow::anyhow!("A unit walks a lane no spawner has"))?,
+                    waypoint: follower.get_waypoint(),
+                    aggro_range: follower.aggro_range,
+                    target: self
+                        .combats
+                        .get(entity)
+                        .and_then(|combat| combat.target)
+                        .and_then(|target| entity_indices.get(&target).copied()),
+                }),
+                None => None,
+            };
+
+            entity_saves.push(EntitySave {
+                transform: self.transforms.get(entity).map(save_transform),
+                renderable,
+                posed: self.poses.get(entity).is_some(),
+                animator,
+                physics: self
+                    .physics_proxies
+                    .get(entity)
+                    .map(|proxy| PhysicsProxySave {
+                        body: proxy
+                            .body_id
+                            .and_then(|body_id| body_indices.get(&body_id).copied()),
+                        current_state: proxy.current_state.map(save_body_state),
+                        previous_state: proxy.previous_state.map(save_body_state),
+                    }),
+                movement: self.movements.get(entity).map(|movement| MovementSave {
+                    velocity: movement.velocity.into(),
+                    locked: movement.locked,
+                    speed_multiplier: movement.speed_multiplier,
+                }),
+                target: self.targets.get(entity).map(|target| TargetSave {
+                    location: target.map(|location| location.to_array()),
+                }),
+                tinted: self.tints.get(entity).is_some(),
+                health: self.healths.get(entity).cloned(),
+                combat: self.combats.get(entity).map(|combat| {
+                    let target = combat
+                        .target
+                        .and_then(|target| entity_indices.get(&target).copied());
+                    let mut combat = combat.clone();
+                    combat.target = None;
+                    CombatSave { target, combat }
+                }),
+                status_effects: self.status_effects.get(entity).cloned(),
+                parent: self.parents.get(entity).and_then(|parent| {
+                    Some(ParentSave {
+                        parent: *entity_indices.get(&parent.parent)?,
+                        bone: parent.bone,
+                        local: save_transform(&parent.local),
+                    })
+                }),
+                team: self.teams.get(entity).copied().map(save_team),
+                projectile: self
+                    .projectiles
+                    .get(entity)
+                    .map(|projectile| ProjectileSave {
+                        source: projectile
+                            .source
+                            .and_then(|source| entity_indices.get(&source).copied()),
+                        damage: projectile.damage,
+                        lifetime: Some(projectile.lifetime),
+                        status: self
+                            .physics_proxies
+                            .get(entity)
+                            .and_then(|proxy| proxy.body_id)
+                            .and_then(|body_id| physics_world.get_user_data(body_id))
+                            .and_then(|user_data| self.projectile_effects.get(user_data)),
+                    }),
+                abilities: self
+                    .ability_casters
+                    .get(entity)
+                    .map(|caster| AbilityCasterSave {
+                        abilities: (0..ABILITY_SLOTS)
+                            .map(|slot| caster.get_ability(slot).map(|desc| desc.name.clone()))
+                            .collect(),
+                        cooldowns: (0..ABILITY_SLOTS)
+                            .map(|slot| caster.get_cooldown_steps(slot).into())
+                            .collect(),
+                        energy: caster.energy,
+                        max_energy: caster.max_energy,
+                        energy_regen: caster.energy_regen,
+                    }),
+                lane_follower,
+            });
+        }
+
+        Ok(GameSave {
+            version: SAVE_VERSION,
+            level: level.clone(),
+            camera: CameraSave {
+                transform: save_transform(&self.camera.transform),
+                detached: self.camera.mode == CCameraMode::Detached,
+                radius: self.camera.settings.radius,
+                angle: self.camera.settings.angle,
+                fov: self.camera.settings.fov,
+            },
+            physics: PhysicsSave {
+                cell_size: physics_world.get_cell_size(),
+                iterations: physics_world.get_iterations(),
+                quantized: physics_world.is_quantized(),
+                bodies,
+            },
+            entities: entity_saves,
+            player: self
+                .player
+                .and_then(|player| entity_indices.get(&player).copied()),
+            wave_spawners: self
+                .wave_spawners
+                .iter()
+                .map(|spawner| WaveSpawnerSave {
+                    steps_to_wave: spawner.get_steps_to_wave(),
+                    rng: spawner.get_rng().clone(),
+                })
+                .collect(),
+        })
+    }
+
+    // Everything is checked before the current state is dropped, a broken save changes nothing
+    fn restore(
+        &mut self,
+        save: &GameSave,
+        physics_world: &mut PhysicsWorld,
+        resolve: impl Fn(&str) -> anyhow::Result<ResourceHandle>,
+        create_pose: impl Fn(ResourceHandle) -> Pose,
+        build_locomotion: impl Fn(&PlayerDesc) -> BlendSpace2D,
+    ) -> anyhow::Result<()> {
+        if self.level_name.as_ref() != Some(&save.level) {
+            bail!(
+                "The save is of level \"{}\", it can't be loaded into another level",
+                save.level
+            );
+        }
+        if !self.network_entities.is_empty() {
+            bail!("The server owns the game state, a save can't be loaded");
+        }
+
+        let entity_count = save.entities.len();
+        let body_count = save.physics.bodies.len();
+        let check_index = |kind: &str, index: usize, count: usize| {
+            if index >= count {
+                bail!("There is no {} {} in the save", kind, index);
+            }
+            Ok(())
+        };
+        if let Some(player) = save.player {
+            check_index("entity", player, entity_count)?;
+        }
+        // Saves from before the spawners were saved keep the running clocks
+        let spawner_count = self.wave_spawners.len();
+        if !save.wave_spawners.is_empty() && save.wave_spawners.len() != spawner_count {
+            bail!(
+                "The save has {} wave spawners, the level {}",
+                save.wave_spawners.len(),
+                spawner_count
+            );
+        }
+
+        let mut renderables = Vec::with_capacity(entity_count);
+        let mut animations = Vec::with_capacity(entity_count);
+        for saved in &save.entities {
+            let renderable = match &saved.renderable {
+                Some(renderable) => Some(CRenderable {
+                    mesh: resolve(&renderable.mesh)?,
+                    material: resolve(&renderable.material)?,
+                    render_offset: Mat4::from_cols_array(&renderable.render_offset),
+                    color: Vec4::from(renderable.color),
+                    tex_coord: Vec2::from(renderable.tex_coord),
+                    tex_scale: Vec2::from(renderable.tex_scale),
+                    casts_shadow: renderable.casts_shadow,
+                    shadow_proxy: match &renderable.shadow_proxy {
+                        ShadowProxySave::None => ShadowProxy::None,
+                        ShadowProxySave::StaticMesh { mesh } => {
+                            ShadowProxy::StaticMesh(resolve(mesh)?)
+                        }
+                        ShadowProxySave::Capsule { radius, height } => ShadowProxy::Capsule {
+                            radius: *radius,
+                            height: *height,
+                        },
+                    },
+                    render_layers: renderable.render_layers,
+                    variant: renderable.variant,
+                }),
+                None => None,
+            };
+            if saved.posed && renderable.is_none() {
+                bail!("A posed entity has no mesh to pose");
+            }
+            renderables.push(renderable);
+
+            let mut animation_states = Vec::new();
+            if let Some(animator) = &saved.animator {
+                if self.character.is_none() {
+                    bail!("The level has no character to animate the saved entities with");
+                }
+                for animation in &animator.animations {
+                    animation_states.push(AnimationInstance {
+                        animation: resolve(&animation.animation)?,
+                        time: animation.time,
+                        looping: animation.looping,
+                        blend_weight: animation.blend_weight,
+                    });
+                }
+            }
+            animations.push(animation_states);
+
+            if let Some(body) = saved.physics.and_then(|proxy| proxy.body) {
+                check_index("body", body, body_count)?;
+            }
+            if let Some(target) = saved.combat.as_ref().and_then(|combat| combat.target) {
+                check_index("entity", target, entity_count)?;
+            }
+            if let Some(parent) = &saved.parent {
+                check_index("entity", parent.parent, entity_count)?;
+            }
+            if let Some(source) = saved.projectile.and_then(|projectile| projectile.source) {
+                check_index("entity", source, entity_count)?;
+            }
+            if let Some(follower) = &saved.lane_follower {
+                check_index("wave spawner", follower.lane, spawner_count)?;
+                if let Some(target) = follower.target {
+                    check_index("entity", target, entity_count)?;
+                }
+            }
+            if let Some(caster) = &saved.abilities {
+                if caster.abilities.len() > ABILITY_SLOTS {
+                    bail!("A caster has more than {} abilities", ABILITY_SLOTS);
+                }
+                for name in caster.abilities.iter().flatten() {
+                    if self.abilities.get(name).is_none() {
+                        bail!("There is no ability named {}", name);
+                    }
+                }
+            }
+        }
+
+        // Every body is replaced, their slots are reused so loading again doesn't grow the pool
+        for body_id in physics_world.get_body_ids() {
+            physics_world.remove_body(body_id);
+        }
+        physics_world.set_iterations(save.physics.iterations);
+        physics_world.set_quantized(save.physics.quantized);
+        let body_ids: Vec<BodyId> = save
+            .physics
+            .bodies
+            .iter()
+            .map(|body| {
+                physics_world.create_rigid_body(&BodySettings {
+                    position: Vec2::from(body.position),
+                    velocity: Vec2::from(body.velocity),
+                    layer: load_layer(body.layer),
+                    shape: &load_shape(body.shape),
+                    listen_to_contact_events: body.listen_to_contact_events,
+                })
+            })
+            .collect();
+        // Also builds the grid, queries work before the next step
+        physics_world.set_cell_size(save.physics.cell_size);
+
+        self.clear_entities();
+        self.events.drain();
+        self.trail = Default::default();
+        let entities: Vec<Entity> = (0..entity_count).map(|_| self.entities.spawn()).collect();
+        for (((&entity, saved), renderable), animation_states) in entities
+            .iter()
+            .zip(&save.entities)
+            .zip(renderables)
+            .zip(animations)
+        {
+            if let Some(transform) = &saved.transform {
+                self.transforms.insert(entity, load_transform(transform));
+            }
+            if let Some(renderable) = renderable {
+                if saved.posed {
+                    self.poses.insert(entity, create_pose(renderable.mesh));
+                }
+                self.renderables.insert(entity, renderable);
+            }
+            if let Some(animator) = &saved.animator
+                && let Some(character) = &self.character
+            {
+                self.animators.insert(
+                    entity,
+                    CAnimator {
+                        locomotion: build_locomotion(character),
+                        phase: animator.phase,
+                        attack: character.attack_animation.as_deref().map(get_handle),
+                        animation_states,
+                    },
+                );
+            }
+            if let Some(proxy) = &saved.physics {
+                self.physics_proxies.insert(
+                    entity,
+                    CPhysicsProxy {
+                        body_id: proxy.body.map(|body| body_ids[body]),
+                        current_state: proxy.current_state.map(load_body_state),
+                        previous_state: proxy.previous_state.map(load_body_state),
+                    },
+                );
+            }
+            if let Some(movement) = &saved.movement {
+                self.movements.insert(
+                    entity,
+                    CPlayerMovement {
+                        velocity: Vec3::from(movement.velocity),
+                        locked: movement.locked,
+                        speed_multiplier: movement.speed_multiplier,
+                    },
+                );
+            }
+            if let Some(target) = &saved.target {
+                self.targets.insert(entity, target.location.map(Vec3::from));
+                self.command_queues.insert(entity, Default::default());
+            }
+            if saved.tinted {
+                self.tints.insert(entity, Default::default());
+            }
+            if let Some(health) = &saved.health {
+                self.health_bars.insert(entity, CHealthBar::new(health));
+                self.healths.insert(entity, health.clone());
+            }
+            if let Some(combat) = &saved.combat {
+                let mut component = combat.combat.clone();
+                component.target = combat.target.map(|target| entities[target]);
+                self.combats.insert(entity, component);
+            }
+            if let Some(status_effects) = &saved.status_effects {
+                self.status_effects.insert(entity, status_effects.clone());
+            }
+            if let Some(parent) = &saved.parent {
+                self.parents.insert(
+                    entity,
+                    CParent {
+                        parent: entities[parent.parent],
+                        bone: parent.bone,
+                        local: load_transform(&parent.local),
+                    },
+                );
+            }
+            if let Some(team) = saved.team {
+                self.teams.insert(entity, load_team(team));
+            }
+            if let Some(projectile) = &saved.projectile {
+                self.projectiles.insert(
+                    entity,
+                    CProjectile {
+                        source: projectile.source.map(|source| entities[source]),
+                        damage: projectile.damage,
+                        lifetime: projectile.lifetime.unwrap_or(PROJECTILE_LIFETIME),
+                    },
+                );
+                if let Some(body_id) = self
+                    .physics_proxies
+                    .get(entity)
+                    .and_then(|proxy| proxy.body_id)
+                {
+                    let user_data = self.projectile_effects.get_user_data(projectile.status);
+                    physics_world.set_user_data(body_id, user_data);
+                }
+            }
+            if let Some(saved_caster) = &saved.abilities {
+                let mut caster = AbilityCaster::new(std::array::from_fn(|slot| {
+                    let name = saved_caster.abilities.get(slot)?.as_ref()?;
+                    self.abilities.get(name).cloned()
+                }));
+                for (slot, [cooldown, total]) in saved_caster.cooldowns.iter().enumerate() {
+                    caster.set_cooldown_steps(slot, *cooldown, *total);
+                }
+                caster.energy = saved_caster.energy;
+                caster.max_energy = saved_caster.max_energy;
+                caster.energy_regen = saved_caster.energy_regen;
+                self.ability_casters.insert(entity, caster);
+            }
+            if let Some(saved_follower) = &saved.lane_follower {
+                let mut follower =
+                    LaneFollower::new(self.wave_spawners[saved_follower.lane].get_lane());
+                follower.set_waypoint(saved_follower.waypoint);
+                follower.aggro_range = saved_follower.aggro_range;
+                if let Some(combat) = self.combats.get_mut(entity) {
+                    combat.target = saved_follower.target.map(|target| entities[target]);
+                }
+                self.lane_followers.insert(entity, follower);
+            }
+        }
+        self.player = save.player.map(|player| entities[player]);
+        for (spawner, saved) in self.wave_spawners.iter_mut().zip(&save.wave_spawners) {
+            spawner.set_clock(saved.steps_to_wave, saved.rng.clone());
+        }
+
+        let camera = &save.camera;
+        self.camera.transform = load_transform(&camera.transform);
+        self.camera.mode = if camera.detached {
+            CCameraMode::Detached
+        } else {
+            CCameraMode::Follow
+        };
+        self.camera.settings = CameraSettings {
+            radius: camera.radius,
+            angle: camera.angle,
+            fov: camera.fov,
+        };
+        self.update_projection();
+
+        Ok(())
+    }
+
+    fn despawn(&mut self, entity: Entity) {
+        self.entities.despawn(entity);
+        self.transforms.remove(entity);
+        self.renderables.remove(entity);
+        self.poses.remove(entity);
+        self.animators.remove(entity);
+        self.physics_proxies.remove(entity);
+        self.movements.remove(entity);
+        self.targets.remove(entity);
+        self.tints.remove(entity);
+        self.healths.remove(entity);
+        self.combats.remove(entity);
+        self.status_effects.remove(entity);
+        self.health_bars.remove(entity);
+        self.baked.remove(entity);
+        self.streamed.remove(entity);
+        self.tweens.stop_entity(entity);
+        self.parents.remove(entity);
+        self.skinning_debugs.remove(entity);
+        self.remote_proxies.remove(entity);
+        self.teams.remove(entity);
+        self.projectiles.remove(entity);
+        self.ability_casters.remove(entity);
+        self.command_queues.remove(entity);
+        self.lane_followers.remove(entity);
+        self.trails.remove(entity);
+        self.death_settles.remove(entity);
+        self.projectile_pool.remove(entity);
+        self.selection.retain(|selected| *selected != entity);
+    }
+
+    fn clear_entities(&mut self) {
+        self.player = None;
+        self.network_entities.clear();
+        self.latest_snapshot_tick = None;
+        self.entities = Default::default();
+        self.transforms.clear();
+        self.renderables.clear();
+        self.poses.clear();
+        self.animators.clear();
+        self.physics_proxies.clear();
+        self.movements.clear();
+        self.targets.clear();
+        self.tints.clear();
+        self.blink_aim = None;
+        self.healths.clear();
+        self.combats.clear();
+        self.status_effects.clear();
+        self.health_bars.clear();
+        self.baked.clear();
+        self.streamed.clear();
+        self.tweens.clear();
+        self.last_attack_cooldown = 0.0;
+        self.parents.clear();
+        self.skinning_debugs.clear();
+        self.remote_proxies.clear();
+        self.teams.clear();
+        self.projectiles.clear();
+        self.ability_casters.clear();
+        self.command_queues.clear();
+        self.lane_followers.clear();
+        self.trails.clear();
+        self.death_settles.clear();
+        self.projectile_pool.clear();
+        self.selection.clear();
+        self.gizmo.clear();
+    }
+}
+
+const MOVEMENT_SPEED: f32 = 300.0;
+
+const PROJECTILE_LIFETIME: f32 = 3.0; // Seconds until a projectile that hit nothing is spent
+const PROJECTILE_POOL_PREWARM: usize = 32; // Per prefab, a few casts' worth
+
+const VARIANT_ROW_COUNT: u32 = 5; // Skins of BruteSkins in the default level
+const VARIANT_ROW_SPACING: f32 = 100.0;
+
+const HEALTH_BAR_DRAIN_DELAY: f32 = 0.15; // The lost chunk stays visible for a moment
+const HEALTH_BAR_DRAIN_TIME: f32 = 0.4;
+const COOLDOWN_FLASH_TIME: f32 = 0.3;
+const KILL_FEED_SLIDE_TIME: f32 = 0.25;
+const FAILED_CAST_FLASH_COLOR: Vec4 = Vec4::new(1.0, 0.1, 0.1, 0.8);
+const FAILED_CAST_FLASH_TIME: f32 = 0.3;
+
+const BLINK_CAMERA_CATCH_UP_TIME: f32 = 0.2;
+const CAMERA_CATCH_UP_RATE: f32 = 25.0; // Per second, of the distance left
+
+// Above the heads of characters, the status icons go below the health bar
+const HEAD_HEIGHT: f32 = 260.0;
+
+fn save_transform(transform: &Transform) -> TransformSave {
+    TransformSave {
+        position: transform.position.into(),
+        rotation: transform.rotation.into(),
+        scale: transform.scale.into(),
+    }
+}
+
+fn load_transform(transform: &TransformSave) -> Transform {
+    Transform {
+        position: Vec3::from(transform.position),
+        rotation: Quat::from_array(transform.rotation),
+        scale: Vec3::from(transform.scale),
+    }
+}
+
+fn save_body_state(state: BodyState) -> BodyStateSave {
+    BodyStateSave {
+        position: state.position.into(),
+        velocity: state.velocity.into(),
+    }
+}
+
+fn load_body_state(state: BodyStateSave) -> BodyState {
+    BodyState {
+        position: Vec2::from(state.position),
+        velocity: Vec2::from(state.velocity),
+    }
+}
+
+fn save_layer(layer: CollisionLayer) -> LayerSave {
+    match layer {
+        CollisionLayer::Environment => LayerSave::Environment,
+        CollisionLayer::Player => LayerSave::Player,
+        CollisionLayer::PlayerProjectile => LayerSave::PlayerProjectile,
+        CollisionLayer::Enemy => LayerSave::Enemy,
+        CollisionLayer::EnemyProjectile => LayerSave::EnemyProjectile,
+        CollisionLayer::Neutral => LayerSave::Neutral,
+        CollisionLayer::NeutralProjectile => LayerSave::NeutralProjectile,
+    }
+}
+
+fn load_layer(layer: LayerSave) -> CollisionLayer {
+    match layer {
+        LayerSave::Environment => CollisionLayer::Environment,
+        LayerSave::Player => CollisionLayer::Player,
+        LayerSave::PlayerProjectile => CollisionLayer::PlayerProjectile,
+        LayerSave::Enemy => CollisionLayer::Enemy,
+        LayerSave::EnemyProjectile => CollisionLayer::EnemyProjectile,
+        LayerSave::Neutral => CollisionLayer::Neutral,
+        LayerSave::NeutralProjectile => CollisionLayer::NeutralProjectile,
+    }
+}
+
+fn save_team(team: Team) -> TeamSave {
+    match team {
+        Team::Blue => TeamSave::Blue,
+        Team::Red => TeamSave::Red,
+        Team::Neutral => TeamSave::Neutral,
+    }
+}
+
+fn load_team(team: TeamSave) -> Team {
+    match team {
+        TeamSave::Blue => Team::Blue,
+        TeamSave::Red => Team::Red,
+        TeamSave::Neutral => Team::Neutral,
+    }
+}
+
+// Multiplies the color of the team's units, and colors the rings under them
+fn get_team_color(team: Team) -> Vec4 {
+    match team {
+        Team::Blue => Vec4::new(0.55, 0.75, 1.0, 1.0),
+        Team::Red => Vec4::new(1.0, 0.6, 0.55, 1.0),
+        Team::Neutral => Vec4::new(1.0, 0.95, 0.7, 1.0),
+    }
+}
+
+fn save_shape(shape: CollisionShape) -> ShapeSave {
+    match shape {
+        CollisionShape::Circle { radius } => ShapeSave::Circle { radius },
+        CollisionShape::Rect { half_extents } => ShapeSave::Rect {
+            half_extents: half_extents.into(),
+        },
+        CollisionShape::Obb {
+            half_extents,
+            rotation,
+        } => ShapeSave::Obb {
+            half_extents: half_extents.into(),
+            rotation,
+        },
+    }
+}
+
+fn load_shape(shape: ShapeSave) -> CollisionShape {
+    match shape {
+        ShapeSave::Circle { radius } => CollisionShape::Circle { radius },
+        ShapeSave::Rect { half_extents } => CollisionShape::Rect {
+            half_extents: Vec2::from(half_extents),
+        },
+        ShapeSave::Obb {
+            half_extents,
+            rotation,
+        } => CollisionShape::Obb {
+            half_extents: Vec2::from(half_extents),
+            rotation,
+        },
+    }
+}
+
+// Places bodies between the last two physics states
+fn interpolate_transforms(
+    alpha: f32,
+    transforms: &mut Storage<CTransform>,
+    physics_proxies: &Storage<CPhysicsProxy>,
+) {
+    for (transform, physics_proxy) in join(transforms, physics_proxies) {
+        if let Some(state) = physics_proxy.current_state
+            && let Some(prev_state) = physics_proxy.previous_state
+        {
+            transform.position = prev_state
+                .position
+                .lerp(state.position, alpha)
+                .at_y(transform.position.y);
+        }
+    }
+}
+
+// Accelerates towards the target location, which is dropped once reached
+pub(crate) fn update_movement(
+    dt: f32,
+    transforms: &Storage<CTransform>,
+    targets: &mut Storage<CTargetLocation>,
+    movements: &mut Storage<CPlayerMovement>,
+) {
+    const MIN_TARGET_DISTANCE: f32 = 10.0;
+
+    for (transform, target, movement) in join3(transforms, targets, movements) {
+        // Move orders wait for the attack to finish
+        if movement.locked {
+            movement.velocity = Vec3::ZERO;
+            continue;
+        }
+
+        let mut input_velocity = Vec3::ZERO;
+        if let Some(target_location) = *target {
+            let to_target = target_location - transform.position;
+            let distance_sqrd = to_target.length_squared();
+            if distance_sqrd > MIN_TARGET_DISTANCE * MIN_TARGET_DISTANCE {
+                input_velocity =
+                    MOVEMENT_SPEED * movement.speed_multiplier * to_target.normalize_or_zero();
+            } else {
+                *target = None;
+            }
+        }
+
+        movement.velocity = movement
+            .velocity
+            .lerp(input_velocity, (15.0 * dt).clamp(0.0, 1.0));
+    }
+}
+
+fn update_remote_proxies(
+    render_time: f32,
+    dt: f32,
+    remote_proxies: &mut Storage<CRemoteProxy>,
+    transforms: &mut Storage<CTransform>,
+    movements: &mut Storage<CPlayerMovement>,
+) {
+    for (proxy, transform, movement) in join3(remote_proxies, transforms, movements) {
+        let Some(remote) = proxy.sample(render_time, dt) else {
+            continue;
+        };
+        transform.position = remote.position.at_y(transform.position.y);
+        transform.rotation = Quat::from_rotation_y(remote.facing);
+        // The animations blend by the velocity the entity is seen moving with
+        movement.velocity = remote.velocity.at_y(0.0);
+    }
+}
+
+fn build_locomotion(renderer: &Renderer, desc: &PlayerDesc) -> BlendSpace2D {
+    build_blend_space(
+        renderer,
+        &desc.idle_animation,
+        &desc.run_animation,
+        &desc.locomotion,
+    )
+}
+
+// Idle at rest, the described clips or the run animation in every direction when moving
+fn build_blend_space(
+    renderer: &Renderer,
+    idle_animation: &str,
+    run: &str,
+    locomotion: &[BlendSampleDesc],
+) -> BlendSpace2D {
+    let clips: Vec<(&str, Vec2)> = if locomotion.is_empty() {
+        vec![
+            (idle_animation, Vec2::ZERO),
+            (run, Vec2::new(MOVEMENT_SPEED, 0.0)),
+            (run, Vec2::new(-MOVEMENT_SPEED, 0.0)),
+            (run, Vec2::new(0.0, MOVEMENT_SPEED)),
+            (run, Vec2::new(0.0, -MOVEMENT_SPEED)),
+        ]
+    } else {
+        locomotion
+            .iter()
+            .map(|sample| (sample.animation.as_str(), Vec2::from(sample.parameter)))
+            .collect()
+    };
+
+    BlendSpace2D::new(
+        clips
+            .into_iter()
+            .map(|(name, parameter)| {
+                let animation = get_handle(name);
+                let duration = renderer.get_animation_duration(animation);
+                if duration.is_none() {
+                    log::error!("Missing locomotion animation {}", name);
+                }
+                BlendSample {
+                    parameter,
+                    animation,
+                    duration: duration.unwrap_or(1.0),
+                }
+            })
+            .collect(),
+    )
+}
+
+fn advance_animations(
+    dt: f32,
+    entities: &Entities,
+    animators: &mut Storage<CAnimator>,
+    movements: &Storage<CPlayerMovement>,
+    transforms: &Storage<CTransform>,
+    combats: &Storage<CCombat>,
+) {
+    profile_scope!("Animation");
+    for (entity, animator, movement) in join3(entities, animators, movements) {
+        // At the time the combat system is at in the clip, so the hit lands on its frame
+        let attack_time = combats
+            .get(entity)
+            .and_then(|combat| combat.phase.get_clip_time());
+        if let (Some(animation), Some(time)) = (animator.attack, attack_time) {
+            animator.animation_states.clear();
+            animator.animation_states.push(AnimationInstance {
+                animation,
+                time,
+                looping: false,
+                blend_weight: 1.0,
+            });
+            continue;
+        }
+
+        let Some(transform) = transforms.get(entity) else {
+            continue;
+        };
+        // (forward, strafe), the characters face +Z
+        let parameter = Vec2::new(
+            movement.velocity.dot(transform.rotation * Vec3::Z),
+            movement.velocity.dot(transform.rotation * Vec3::X),
+        );
+        animator.locomotion.sample(
+            parameter,
+            dt,
+            &mut animator.phase,
+            &mut animator.animation_states,
+        );
+    }
+}
+
+fn face_movement(
+    dt: f32,
+    transforms: &mut Storage<CTransform>,
+    movements: &Storage<CPlayerMovement>,
+) {
+    for (transform, movement) in join(transforms, movements) {
+        // Standing still keeps the last facing
+        if movement.velocity == Vec3::ZERO {
+            continue;
+        }
+
+        transform.rotation = transform.rotation.slerp(
+            Quat::from_rotation_y(movement.velocity.x.atan2(movement.velocity.z)),
+            20.0 * dt,
+        );
+    }
+}
+
+// Ticks the effects, applies damage over time and hands the speed modifier to movement. The
+// effects tick on the workers, the damage is applied after in the order of the entities.
+fn update_status_effects(
+    dt: f32,
+    entities: &Entities,
+    status_effects: &mut Storage<CStatusEffects>,
+    healths: &mut Storage<CHealth>,
+    movements: &mut Storage<CPlayerMovement>,
+    events: &mut GameEvents,
+) {
+    let mut ticked: Vec<_> = join(entities, &mut *status_effects)
+        .filter(|(entity, _)| healths.get(*entity).is_some())
+        .collect();
+    let damages = jobs::map_mut(&mut ticked, |(entity, effects)| {
+        (
+            *entity,
+            effects.update(dt) * effects.incoming_damage_multiplier(),
+        )
+    });
+
+    for (entity, damage) in damages {
+        let Some(health) = healths.get_mut(entity) else {
+            continue;
+        };
+        if damage > 0.0 && !health.is_dead() {
+            health.current = (health.current - damage).max(0.0);
+            events.push_damage(None, entity, damage, health.is_dead());
+        }
+    }
+
+    for (effects, movement) in join(&*status_effects, movements) {
+        movement.speed_multiplier = effects.movement_speed_multiplier();
+    }
+}
+
+fn update_tints(dt: f32, tints: &mut Storage<CTintAnimator>) {
+    for tint in tints.iter_mut() {
+        tint.update(dt);
+    }
+}
+
+fn push_velocities(
+    physics_proxies: &Storage<CPhysicsProxy>,
+    movements: &Storage<CPlayerMovement>,
+    physics_world: &mut PhysicsWorld,
+) {
+    for (physics_proxy, movement) in join(physics_proxies, movements) {
+        if let Some(body_id) = physics_proxy.body_id {
+            physics_world.set_velocity(body_id, movement.velocity.xz());
+        }
+    }
+}
+
+fn pull_body_states(physics_proxies: &mut Storage<CPhysicsProxy>, physics_world: &PhysicsWorld) {
+    for physics_proxy in physics_proxies.iter_mut() {
+        if let Some(body_id) = physics_proxy.body_id {
+            physics_proxy.previous_state = physics_proxy.current_state;
+            physics_proxy.current_state = physics_world.get_state(body_id);
+        }
+    }
+}
+
+fn accumulate_poses(
+    renderer: &Renderer,
+    animators: &Storage<CAnimator>,
+    poses: &mut Storage<CPose>,
+) {
+    profile_scope!("Poses");
+    for (animator, pose) in join(animators, poses) {
+        renderer.accumulate_pose(&animator.animation_states, pose);
+    }
+}
+
+// The animators of the dead are gone, so their poses still hold the last frame they were
+// animated on. That is frozen on the first frame and blended towards the lying pose after.
+fn settle_death_poses(
+    renderer: &Renderer,
+    entities: &Entities,
+    renderables: &Storage<CRenderable>,
+    settles: &mut Storage<CDeathSettle>,
+    poses: &mut Storage<CPose>,
+) {
+    for (entity, settle, pose) in join3(entities, settles, poses) {
+        if !settle.is_frozen()
+            && let Some(renderable) = renderables.get(entity)
+            && let Some(bones) = renderer.get_skeletal_mesh_bones(renderable.mesh)
+        {
+            // Characters face +Z, they fall backwards onto the ground
+            let to_mesh = Quat::from_mat4(&renderable.render_offset).inverse();
+            let fall = get_fall_rotation(to_mesh * Vec3::Y, to_mesh * Vec3::NEG_Z);
+            settle.freeze(bones, pose, fall);
+        }
+        settle.apply(pose);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn submit_renderables(
+    renderer: &mut Renderer,
+    entities: &Entities,
+    transforms: &Storage<CTransform>,
+    renderables: &Storage<CRenderable>,
+    poses: &Storage<CPose>,
+    tints: &Storage<CTintAnimator>,
+    teams: &Storage<CTeam>,
+    skinning_debugs: &Storage<CSkinningDebug>,
+    player_team: Option<Team>,
+    selected: &[Entity],
+) {
+    for (entity, transform, renderable) in join3(entities, transforms, renderables) {
+        let transform = transform.to_matrix() * renderable.render_offset;
+        let color = match teams.get(entity) {
+            Some(team) => renderable.color * get_team_color(*team),
+            None => renderable.color,
+        };
+        let color = match tints.get(entity) {
+            Some(tint) => tint.apply(color),
+            None => color,
+        };
+
+        // Units of the player's team and the selected ones stay visible behind walls
+        let team = teams.get(entity).copied();
+        let xray = (team.is_some() && team == player_team) || selected.contains(&entity);
+
+        match poses.get(entity) {
+            Some(pose) => renderer.submit(&SkeletalRenderJob {
+                transform,
+                material: renderable.material,
+                mesh: renderable.mesh,
+                tex_coord: renderable.tex_coord,
+                tex_scale: renderable.tex_scale,
+                color,
+                pose: Some(pose),
+                casts_shadow: renderable.casts_shadow,
+                shadow_proxy: renderable.shadow_proxy,
+                weight_debug: skinning_debugs
+                    .get(entity)
+                    .map_or(WeightDebugView::None, |debug| debug.weights),
+                xray,
+                xray_color: team.map_or(Vec4::ONE, get_team_color),
+                render_layers: renderable.render_layers,
+                variant: renderable.variant,
+                instance_id: Some(entity.to_bits()),
+            }),
+            None => renderer.submit(&StaticRenderJob {
+                transform,
+                material: renderable.material,
+                mesh: renderable.mesh,
+                tex_coord: renderable.tex_coord,
+                tex_scale: renderable.tex_scale,
+                color,
+                casts_shadow: renderable.casts_shadow,
+                render_layers: renderable.render_layers,
+                variant: renderable.variant,
+                instance_id: Some(entity.to_bits()),
+            }),
+        }
+    }
+}
+
+// The bones of the entities with the skeleton debug view on, where they are drawn
+fn draw_skeletons(
+    renderer: &mut Renderer,
+    entities: &Entities,
+    transforms: &Storage<CTransform>,
+    renderables: &Storage<CRenderable>,
+    poses: &Storage<CPose>,
+    skinning_debugs: &Storage<CSkinningDebug>,
+) {
+    for (entity, debug, pose) in join3(entities, skinning_debugs, poses) {
+        if !debug.skeleton {
+            continue;
+        }
+        let (Some(transform), Some(renderable)) = (transforms.get(entity), renderables.get(entity))
+        else {
+            continue;
+        };
+        let transform = transform.to_matrix() * renderable.render_offset;
+        renderer.draw_debug_skeleton(renderable.mesh, pose, transform);
+    }
+}
+
+// A ring on the ground under every selected unit
+fn submit_selection_rings(
+    renderer: &mut Renderer,
+    transforms: &Storage<CTransform>,
+    teams: &Storage<CTeam>,
+    selected: &[Entity],
+) {
+    const RING_RADIUS: f32 = 60.0;
+    const RING_HEIGHT: f32 = 2.0; // Above the ground so it doesn't flicker
+
+    for &entity in selected {
+        let Some(transform) = transforms.get(entity) else {
+            continue;
+        };
+        let color = match teams.get(entity) {
+            Some(team) => get_team_color(*team),
+            None => Vec4::new(0.3, 0.9, 0.4, 1.0),
+        };
+        renderer.submit(&StaticRenderJob {
+            transform: Mat4::from_scale_rotation_translation(
+                Vec3::new(RING_RADIUS, 1.0, RING_RADIUS),
+                Quat::IDENTITY,
+                transform.position.with_y(RING_HEIGHT),
+            ),
+            material: get_handle(SELECTION_RING_MATERIAL),
+            mesh: Renderer::RING_MESH,
+            color,
+            casts_shadow: false,
+            ..Default::default()
+        });
+    }
+}
+
+// A ring where an area ability will land, growing more opaque over the cast
+fn submit_telegraphs(
+    renderer: &mut Renderer,
+    entities: &Entities,
+    casters: &Storage<AbilityCaster>,
+) {
+    const TELEGRAPH_HEIGHT: f32 = 3.0; // Above the selection rings
+
+    for (_, caster) in join(entities, casters) {
+        let (
+            CastPhase::Casting {
+                slot,
+                target: AbilityTarget::Point(point),
+                ..
+            },
+            Some((_, progress)),
+        ) = (caster.get_phase(), caster.get_cast_progress())
+        else {
+            continue;
+        };
+        let Some(AbilityEffect::Status { radius, .. }) =
+            caster.get_ability(slot).map(|desc| &desc.effect)
+        else {
+            continue;
+        };
+        renderer.submit(&StaticRenderJob {
+            transform: Mat4::from_scale_rotation_translation(
+                Vec3::new(*radius, 1.0, *radius),
+                Quat::IDENTITY,
+                point.with_y(TELEGRAPH_HEIGHT),
+            ),
+            material: get_handle(SELECTION_RING_MATERIAL),
+            mesh: Renderer::RING_MESH,
+            color: Vec4::new(1.0, 0.5, 0.2, 0.3 + 0.7 * progress),
+            casts_shadow: false,
+            ..Default::default()
+        });
+    }
+}
+
+// Where an aimed blink lands, or red at the cursor when it would fail
+fn submit_blink_telegraph(renderer: &mut Renderer, aim: &BlinkAim) {
+    const TELEGRAPH_HEIGHT: f32 = 3.0;
+
+    let (position, color) = match (aim.destination, aim.cursor) {
+        (Some(destination), _) => (destination.at_y(0.0), Vec4::new(0.3, 0.7, 1.0, 0.8)),
+        (None, Some(cursor)) => (cursor, Vec4::new(1.0, 0.2, 0.2, 0.8)),
+        (None, None) => return,
+    };
+    renderer.submit(&StaticRenderJob {
+        transform: Mat4::from_scale_rotation_translation(
+            Vec3::new(aim.radius, 1.0, aim.radius),
+            Quat::IDENTITY,
+            position.with_y(TELEGRAPH_HEIGHT),
+        ),
+        material: get_handle(SELECTION_RING_MATERIAL),
+        mesh: Renderer::RING_MESH,
+        color,
+        casts_shadow: false,
+        ..Default::default()
+    });
+}
+
+// The keys at the bottom of the screen. The cooldown darkens a key from the top, the bars
+// above the keys are the running cast and the energy.
+fn submit_ability_bar(renderer: &mut Renderer, screen_size: Vec2, caster: &AbilityCaster) {
+    const KEY_SIZE: f32 = 48.0;
+    const KEY_SPACING: f32 = 8.0;
+    const BOTTOM_MARGIN: f32 = 24.0;
+    const BAR_HEIGHT: f32 = 6.0;
+    const KEY_NAMES: [&str; ABILITY_SLOTS] = ["Q", "W", "E", "R"];
+
+    let width = ABILITY_SLOTS as f32 * (KEY_SIZE + KEY_SPACING) - KEY_SPACING;
+    let origin = Vec2::new(
+        (screen_size.x - width) * 0.5,
+        screen_size.y - BOTTOM_MARGIN - KEY_SIZE,
+    );
+    let cast = caster.get_cast_progress();
+    let mut submit = |job: SpriteRenderJob| {
+        renderer.submit(&SpriteRenderJob {
+            space: SpriteSpace::Absolute,
+            ..job
+        });
+    };
+
+    for slot in 0..ABILITY_SLOTS {
+        let position = origin + Vec2::X * slot as f32 * (KEY_SIZE + KEY_SPACING);
+        let color = match caster.get_ability(slot) {
+            None => Vec4::new(0.1, 0.1, 0.1, 0.4),
+            Some(_) if cast.is_some_and(|(casting, _)| casting == slot) => {
+                Vec4::new(0.95, 0.8, 0.3, 1.0)
+            }
+            Some(desc) if caster.energy < desc.cost => Vec4::new(0.2, 0.25, 0.45, 0.9),
+            Some(_) => Vec4::new(0.35, 0.35, 0.4, 0.9),
+        };
+        submit(SpriteRenderJob::solid(
+            position,
+            Vec2::splat(KEY_SIZE),
+            color,
+            0,
+        ));
+        // Swept away clockwise as the cooldown runs out
+        let cooldown = caster.get_cooldown_fraction(slot);
+        if cooldown > 0.0 {
+            submit(
+                SpriteRenderJob::solid(
+                    position,
+                    Vec2::splat(KEY_SIZE),
+                    Vec4::new(0.0, 0.0, 0.0, 0.6),
+                    0,
+                )
+                .radial_fill(cooldown),
+            );
+        }
+    }
+
+    let energy = (caster.energy / caster.max_energy.max(1.0)).clamp(0.0, 1.0);
+    let energy_position = origin - Vec2::new(0.0, BAR_HEIGHT + 4.0);
+    submit(SpriteRenderJob::solid(
+        energy_position,
+        Vec2::new(width, BAR_HEIGHT),
+        Vec4::new(0.0, 0.0, 0.0, 0.6),
+        0,
+    ));
+    submit(SpriteRenderJob::solid(
+        energy_position,
+        Vec2::new(width * energy, BAR_HEIGHT),
+        Vec4::new(0.25, 0.55, 1.0, 1.0),
+        0,
+    ));
+    if let Some((_, progress)) = cast {
+        submit(SpriteRenderJob::solid(
+            energy_position - Vec2::new(0.0, BAR_HEIGHT + 2.0),
+            Vec2::new(width * progress, BAR_HEIGHT),
+            Vec4::new(0.95, 0.8, 0.3, 1.0),
+            0,
+        ));
+    }
+
+    for (slot, name) in KEY_NAMES.iter().enumerate() {
+        let position = origin + Vec2::new(slot as f32 * (KEY_SIZE + KEY_SPACING) + 4.0, 2.0);
+        renderer.submit(&TextRenderJob {
+            font_atlas: get_handle("DebugFont"),
+            font_material: get_handle("DebugFontMaterial"),
+            text: (*name).into(),
+            position,
+            size: 16.0,
+            color: Vec4::new(1.0, 1.0, 1.0, 0.9),
+            layer: 1,
+            anchor: SpriteAnchor::TopLeft,
+            space: SpriteSpace::Absolute,
+            alignment: TextAlignment::Left,
+        });
+    }
+}
+
+fn submit_health_bars(
+    renderer: &mut Renderer,
+    view_projection: Mat4,
+    screen_size: Vec2,
+    entities: &Entities,
+    transforms: &Storage<CTransform>,
+    health_bars: &Storage<CHealthBar>,
+    teams: &Storage<CTeam>,
+) {
+    const BAR_SIZE: Vec2 = Vec2::new(80.0, 8.0);
+    const ABOVE_ICONS: f32 = 28.0;
+
+    for (entity, transform, bar) in join3(entities, transforms, health_bars) {
+        let head = transform.position + Vec3::Y * HEAD_HEIGHT;
+        let Some(screen_position) = get_screen_position(view_projection, head, screen_size) else {
+            continue;
+        };
+
+        // Same batch, the later sprites are drawn on top
+        let position = screen_position - Vec2::new(BAR_SIZE.x * 0.5, ABOVE_ICONS + BAR_SIZE.y);
+        let fill = bar.fill.clamp(0.0, 1.0);
+        let fill_color = match teams.get(entity) {
+            Some(Team::Blue) => Vec4::new(0.2, 0.45, 0.95, 1.0),
+            Some(Team::Red) | None => Vec4::new(0.85, 0.15, 0.1, 1.0),
+            Some(Team::Neutral) => Vec4::new(0.9, 0.75, 0.2, 1.0),
+        };
+        for (size, color) in [
+            (BAR_SIZE, Vec4::new(0.0, 0.0, 0.0, 0.6)),
+            (Vec2::new(BAR_SIZE.x * fill, BAR_SIZE.y), fill_color),
+            (BAR_SIZE, Vec4::new(1.0, 1.0, 1.0, 0.8 * bar.flash)),
+        ] {
+            renderer.submit(&SpriteRenderJob {
+                space: SpriteSpace::Absolute,
+                ..SpriteRenderJob::solid(position, size, color, 0)
+            });
+        }
+    }
+}
+
+// A row of icons above the head, a dark pie sweeps over each one as its effect runs out
+fn submit_status_icons(
+    renderer: &mut Renderer,
+    view_projection: Mat4,
+    screen_size: Vec2,
+    transforms: &Storage<CTransform>,
+    status_effects: &Storage<CStatusEffects>,
+    mouse_position: Vec2,
+    tooltip: &mut Tooltip,
+) {
+    const ICON_SIZE: f32 = 20.0;
+    const ICON_SPACING: f32 = 4.0;
+
+    for (transform, effects) in join(transforms, status_effects) {
+        let count = effects.iter().count();
+        if count == 0 {
+            continue;
+        }
+
+        let head = transform.position + Vec3::Y * HEAD_HEIGHT;
+        let Some(screen_position) = get_screen_position(view_projection, head, screen_size) else {
+            continue;
+        };
+
+        let row_width = count as f32 * (ICON_SIZE + ICON_SPACING) - ICON_SPACING;
+        let mut position = screen_position - Vec2::new(row_width * 0.5, ICON_SIZE);
+        for effect in effects.iter() {
+            let color = match effect.desc.kind {
+                StatusKind::Slow => Vec4::new(0.3, 0.5, 1.0, 1.0),
+                StatusKind::Haste => Vec4::new(1.0, 0.85, 0.2, 1.0),
+                StatusKind::DamageOverTime => Vec4::new(0.4, 0.9, 0.2, 1.0),
+                StatusKind::Shield => Vec4::new(0.9, 0.9, 0.95, 1.0),
+            };
+            renderer.submit(&SpriteRenderJob {
+                space: SpriteSpace::Absolute,
+                ..SpriteRenderJob::solid(position, Vec2::splat(ICON_SIZE), color, 0)
+            });
+
+            // Same batch as the icons, so submitting it later draws it on top
+            let elapsed = 1.0 - effect.get_remaining_fraction();
+            renderer.submit(
+                &SpriteRenderJob {
+                    space: SpriteSpace::Absolute,
+                    ..SpriteRenderJob::solid(
+                        position,
+                        Vec2::splat(ICON_SIZE),
+                        Vec4::new(0.0, 0.0, 0.0, 0.6),
+                        0,
+                    )
+                }
+                .radial_fill(elapsed),
+            );
+
+            let rect = UiRect {
+                position,
+                size: Vec2::splat(ICON_SIZE),
+            };
+            if rect.contains(mouse_position) {
+                let magnitude = effect.desc.magnitude;
+                let (title, effect_text) = match effect.desc.kind {
+                    StatusKind::Slow => ("Slow", format!("{:.0}% slower", magnitude * 100.0)),
+                    StatusKind::Haste => ("Haste", format!("{:.0}% faster", magnitude * 100.0)),
+                    StatusKind::DamageOverTime => (
+                        "Damage over time",
+                        format!("{:.0} damage per second", magnitude),
+                    ),
+                    StatusKind::Shield => (
+                        "Shield",
+                        format!("Blocks {:.0}% of damage", magnitude * 100.0),
+                    ),
+                };
+                let body = format!("{}\n{:.1}s left", effect_text, effect.remaining);
+                tooltip.request(&TooltipRequest {
+                    anchor_rect: rect,
+                    title,
+                    body: &body,
+                });
+            }
+
+            position.x += ICON_SIZE + ICON_SPACING;
+        }
+    }
+}
+
+fn get_collision_shape(shape: ShapeDesc) -> CollisionShape {
+    match shape {
+        ShapeDesc::Circle { radius } => CollisionShape::Circle { radius },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        combat::AttackPhase,
+        level::{TeamDesc, WaveSpawnerDesc},
+        status_effects::{StackingPolicy, StatusEffectDesc},
+    };
+
+    const DEFAULT_LEVEL: &[u8] = include_bytes!("../../assets/levels/default.json");
+    const BONE_COUNT: usize = 4;
+
+    fn save(game: &Game, physics_world: &PhysicsWorld, names: &[&str]) -> GameSave {
+        game.save(physics_world, |handle| {
+            names
+                .iter()
+                .find(|name| get_handle(name) == handle)
+                .map(|name| name.to_string())
+        })
+        .unwrap()
+    }
+
+    fn restore(
+        game: &mut Game,
+        save: &GameSave,
+        physics_world: &mut PhysicsWorld,
+        names: &[&str],
+    ) -> anyhow::Result<()> {
+        game.restore(
+            save,
+            physics_world,
+            |name| match names.contains(&name) {
+                true => Ok(get_handle(name)),
+                false => bail!("Unknown resource \"{}\"", name),
+            },
+            |_| Pose::new(BONE_COUNT),
+            |_| BlendSpace2D::new(Vec::new()),
+        )
+    }
+
+    // A wall, the player in the middle of an attack on a slowed enemy, and a sword in its hand
+    fn build_game(level: &Level, physics_world: &mut PhysicsWorld) -> Game {
+        let mut game = Game::new();
+        game.level_name = Some(level.name.clone());
+        game.character = Some(level.player.clone());
+
+        let wall = game.entities.spawn();
+        let wall_body = physics_world.create_rigid_body(&BodySettings {
+            position: Vec2::new(300.0, 0.0),
+            velocity: Vec2::ZERO,
+            layer: CollisionLayer::Environment,
+            shape: &CollisionShape::Rect {
+                half_extents: Vec2::new(20.0, 200.0),
+            },
+            listen_to_contact_events: false,
+        });
+        game.physics_proxies
+            .insert(wall, CPhysicsProxy::new(wall_body, physics_world));
+        game.transforms.insert(wall, Default::default());
+        game.renderables.insert(
+            wall,
+            CRenderable {
+                mesh: get_handle("Sphere"),
+                material: get_handle("Grid"),
+                shadow_proxy: ShadowProxy::StaticMesh(get_handle("Sphere")),
+                ..Default::default()
+            },
+        );
+
+        let mut spawn_character = |position: Vec2, layer: CollisionLayer| {
+            let entity = game.entities.spawn();
+            let body_id = physics_world.create_rigid_body(&BodySettings {
+                position,
+                velocity: Vec2::new(3.0, -1.5),
+                layer,
+                shape: &CollisionShape::Circle { radius: 32.0 },
+                listen_to_contact_events: true,
+            });
+            game.physics_proxies
+                .insert(entity, CPhysicsProxy::new(body_id, physics_world));
+            game.transforms.insert(
+                entity,
+                CTransform {
+                    position: position.extend(0.0).xzy(),
+                    rotation: Quat::from_rotation_y(0.7),
+                    ..Default::default()
+                },
+            );
+            game.renderables.insert(
+                entity,
+                CRenderable {
+                    mesh: get_handle(&level.player.mesh),
+                    material: get_handle(&level.player.material),
+                    ..Default::default()
+                },
+            );
+            game.poses.insert(entity, Pose::new(BONE_COUNT));
+            game.healths.insert(entity, CHealth::new(100.0));
+            game.status_effects.insert(entity, Default::default());
+            entity
+        };
+        let player = spawn_character(Vec2::ZERO, CollisionLayer::Player);
+        let enemy = spawn_character(Vec2::new(100.0, 50.0), CollisionLayer::Enemy);
+
+        game.animators.insert(
+            player,
+            CAnimator {
+                locomotion: BlendSpace2D::new(Vec::new()),
+                phase: 0.25,
+                attack: None,
+                animation_states: vec![AnimationInstance {
+                    animation: get_handle("Brute_Idle"),
+                    time: 0.4,
+                    looping: false,
+                    blend_weight: 0.5,
+                }],
+            },
+        );
+        game.movements.insert(
+            player,
+            CPlayerMovement {
+                velocity: Vec3::new(1.0, 0.0, 2.0),
+                ..Default::default()
+            },
+        );
+        game.targets
+            .insert(player, Some(Vec3::new(80.0, 0.0, 40.0)));
+        game.tints.insert(player, Default::default());
+        game.teams.insert(player, Team::Blue);
+        game.teams.insert(enemy, Team::Red);
+        game.set_abilities(
+            AbilityLibrary::load(include_bytes!("../../assets/abilities/default.ron")).unwrap(),
+        );
+        let caster = game
+            .build_caster(&[Some("Bolt".into()), None, Some("Mend".into())])
+            .unwrap();
+        game.ability_casters.insert(player, caster);
+        game.ability_casters
+            .get_mut(player)
+            .unwrap()
+            .set_cooldown_steps(2, 30, 120);
+        let mut combat = CCombat::default();
+        combat.request_attack(enemy);
+        combat.phase = AttackPhase::WindUp { time: 0.1 };
+        game.combats.insert(player, combat);
+        game.healths.get_mut(enemy).unwrap().current = 35.0;
+        game.status_effects
+            .get_mut(enemy)
+            .unwrap()
+            .apply(StatusEffectDesc {
+                kind: StatusKind::Slow,
+                magnitude: 0.3,
+                duration: 2.0,
+                stacking: StackingPolicy::Refresh,
+            });
+        game.player = Some(player);
+
+        let sword = game.entities.spawn();
+        game.transforms.insert(sword, Default::default());
+        game.parents.insert(
+            sword,
+            CParent {
+                parent: player,
+                bone: Some(2),
+                local: Transform {
+                    position: Vec3::new(0.0, 10.0, 0.0),
+                    ..Default::default()
+                },
+            },
+        );
+
+        // A body nobody points at, e.g. a removed entity's that is still falling through
+        physics_world.create_rigid_body(&BodySettings {
+            position: Vec2::new(-50.0, 0.0),
+            velocity: Vec2::ZERO,
+            layer: CollisionLayer::EnemyProjectile,
+            shape: &CollisionShape::Circle { radius: 4.0 },
+            listen_to_contact_events: false,
+        });
+        physics_world.set_iterations(6);
+        game
+    }
+
+    // A spawner some steps into its wave clock, the enemy walks its lane and stopped to fight
+    // the player
+    fn add_running_wave(game: &mut Game) {
+        let desc = WaveSpawnerDesc {
+            name: "RedMid".to_string(),
+            spawn: String::new(),
+            lane: vec![[0.0, 0.0, 0.0], [-600.0, 0.0, 0.0]],
+            interval: 10.0,
+            count: 3,
+            team: TeamDesc::Red,
+            prefab: "Minion".to_string(),
+            seed: 7,
+        };
+        let mut spawner = WaveSpawner::new(&desc, Vec3::new(600.0, 0.0, 0.0));
+        let mut positions = Vec::new();
+        for _ in 0..5 {
+            spawner.tick(1.0 / 60.0, &mut positions);
+        }
+
+        let player = game.player.unwrap();
+        let enemy = game.combats.get(player).unwrap().target.unwrap();
+        let mut follower = LaneFollower::new(spawner.get_lane());
+        follower.set_waypoint(1);
+        game.lane_followers.insert(enemy, follower);
+        let mut combat = CCombat::default();
+        combat.target = Some(player);
+        game.combats.insert(enemy, combat);
+        game.wave_spawners.push(spawner);
+    }
+
+    #[test]
+    fn saves_load_back_into_the_same_state() {
+        let level = Level::load(DEFAULT_LEVEL).unwrap();
+        let names = [
+            "Sphere",
+            "Grid",
+            "Brute_Idle",
+            level.player.mesh.as_str(),
+            level.player.material.as_str(),
+        ];
+        let mut physics_world = PhysicsWorld::new();
+        let mut game = build_game(&level, &mut physics_world);
+        add_running_wave(&mut game);
+        let first = save(&game, &physics_world, &names);
+        assert_eq!(first.entities.len(), 4);
+        assert_eq!(first.physics.bodies.len(), 4);
+
+        // Through the file, into a game whose bodies are all gone
+        let loaded = GameSave::load(first.to_json().unwrap().as_bytes()).unwrap();
+        assert_eq!(loaded, first);
+        for _ in 0..3 {
+            restore(&mut game, &loaded, &mut physics_world, &names).unwrap();
+            let again = save(&game, &physics_world, &names);
+            assert_eq!(again.to_json().unwrap(), first.to_json().unwrap());
+        }
+        // The removed bodies' slots were reused
+        assert_eq!(physics_world.get_body_ids().len(), 4);
+        assert!(
+            physics_world
+                .get_body_ids()
+                .iter()
+                .all(|body_id| body_id.index() < 4)
+        );
+
+        let player = game.player.unwrap();
+        let combat = game.combats.get(player).unwrap();
+        let enemy = combat.target.unwrap();
+        assert_eq!(game.healths.get(enemy).unwrap().current, 35.0);
+        assert!(
+            game.status_effects
+                .get(enemy)
+                .unwrap()
+                .movement_speed_multiplier()
+                < 1.0
+        );
+        let caster = game.ability_casters.get(player).unwrap();
+        assert_eq!(caster.get_cooldown_steps(2), (30, 120));
+        assert!(caster.get_ability(1).is_none());
+
+        // The wave walks on from where it was and the next one comes on time
+        let spawner = &game.wave_spawners[0];
+        assert_eq!(
+            spawner.get_steps_to_wave(),
+            first.wave_spawners[0].steps_to_wave
+        );
+        assert!(spawner.get_steps_to_wave() > 0);
+        let follower = game.lane_followers.get(enemy).unwrap();
+        assert!(follower.is_on_lane_of(spawner));
+        assert_eq!(follower.get_waypoint(), 1);
+        assert_eq!(game.combats.get(enemy).unwrap().target, Some(player));
+        game.healths.get_mut(enemy).unwrap().current = 0.0;
+        assert_eq!(
+            get_dead_followers(&game.entities, &game.lane_followers, &game.healths),
+            [enemy]
+        );
+    }
+
+    #[test]
+    fn attacks_play_the_attack_clip_then_the_locomotion_again() {
+        let level = Level::load(DEFAULT_LEVEL).unwrap();
+        let mut physics_world = PhysicsWorld::new();
+        let mut game = build_game(&level, &mut physics_world);
+        let player = game.player.unwrap();
+        let animator = game.animators.get_mut(player).unwrap();
+        animator.attack = Some(get_handle("Brute_Swing"));
+        animator.locomotion = BlendSpace2D::new(vec![BlendSample {
+            parameter: Vec2::ZERO,
+            animation: get_handle("Brute_Idle"),
+            duration: 1.0,
+        }]);
+        let advance = |game: &mut Game| {
+            advance_animations(
+                0.1,
+                &game.entities,
+                &mut game.animators,
+                &game.movements,
+                &game.transforms,
+                &game.combats,
+            );
+            let states = &game.animators.get(player).unwrap().animation_states;
+            assert_eq!(states.len(), 1);
+            (states[0].animation, states[0].time, states[0].looping)
+        };
+
+        // The swing is where the combat system is in it
+        assert_eq!(advance(&mut game), (get_handle("Brute_Swing"), 0.1, false));
+
+        game.combats.get_mut(player).unwrap().phase = AttackPhase::Ready;
+        let (animation, _, looping) = advance(&mut game);
+        assert_eq!(animation, get_handle("Brute_Idle"));
+        assert!(looping);
+    }
+
+    #[test]
+    fn the_dead_settle_without_touching_the_living() {
+        let level = Level::load(DEFAULT_LEVEL).unwrap();
+        let mut physics_world = PhysicsWorld::new();
+        let mut game = build_game(&level, &mut physics_world);
+        let player = game.player.unwrap();
+        let enemy = game.combats.get(player).unwrap().target.unwrap();
+        // On the same animation as the player
+        game.animators.insert(
+            enemy,
+            CAnimator {
+                locomotion: BlendSpace2D::new(Vec::new()),
+                phase: 0.0,
+                attack: None,
+                animation_states: vec![AnimationInstance {
+                    animation: get_handle("Brute_Idle"),
+                    time: 0.9,
+                    looping: false,
+                    blend_weight: 1.0,
+                }],
+            },
+        );
+        game.healths.get_mut(enemy).unwrap().current = 0.0;
+
+        game.start_death_settle(enemy);
+        assert!(game.animators.get(enemy).is_none());
+        assert!(game.physics_proxies.get(enemy).is_none());
+        assert!(game.death_settles.get(enemy).is_some());
+        assert_eq!(
+            game.animators.get(player).unwrap().animation_states[0].time,
+            0.4
+        );
+        assert!(game.movements.get(player).is_some());
+        assert!(game.combats.get(player).is_some());
+
+        // Settled, lingering and faded out
+        for _ in 0..300 {
+            game.update_death_settles(1.0 / 60.0);
+        }
+        assert!(!game.entities.is_alive(enemy));
+        assert!(game.death_settles.get(enemy).is_none());
+        assert!(game.entities.is_alive(player));
+    }
+
+    #[test]
+    fn broken_saves_change_nothing() {
+        let level = Level::load(DEFAULT_LEVEL).unwrap();
+        let names = [
+            "Sphere",
+            "Grid",
+            "Brute_Idle",
+            level.player.mesh.as_str(),
+            level.player.material.as_str(),
+        ];
+        let mut physics_world = PhysicsWorld::new();
+        let mut game = build_game(&level, &mut physics_world);
+        add_running_wave(&mut game);
+        let before = save(&game, &physics_world, &names);
+
+        let mut other_level = before.clone();
+        other_level.level = "Arena".to_string();
+        let mut missing_resource = before.clone();
+        missing_resource.entities[0]
+            .renderable
+            .as_mut()
+            .unwrap()
+            .material = "Missing".to_string();
+        let mut missing_body = before.clone();
+        missing_body.entities[1].physics.as_mut().unwrap().body = Some(9);
+        let mut missing_parent = before.clone();
+        missing_parent.entities[3].parent.as_mut().unwrap().parent = 9;
+        let mut missing_ability = before.clone();
+        missing_ability.entities[1]
+            .abilities
+            .as_mut()
+            .unwrap()
+            .abilities[0] = Some("Missing".to_string());
+        let mut missing_lane = before.clone();
+        missing_lane.entities[2]
+            .lane_follower
+            .as_mut()
+            .unwrap()
+            .lane = 9;
+        let mut other_spawners = before.clone();
+        other_spawners.wave_spawners.clear();
+        other_spawners.wave_spawners.extend([
+            before.wave_spawners[0].clone(),
+            before.wave_spawners[0].clone(),
+        ]);
+
+        for broken in [
+            other_level,
+            missing_resource,
+            missing_body,
+            missing_parent,
+            missing_ability,
+            missing_lane,
+            other_spawners,
+        ] {
+            assert!(restore(&mut game, &broken, &mut physics_world, &names).is_err());
+            assert_eq!(save(&game, &physics_world, &names), before);
+        }
+    }
+
+    // The demo row of skins goes through the prefab, the renderable and the render job
+    #[cfg(feature = "test-harness")]
+    #[test]
+    fn a_row_of_skins_is_one_batch() {
+        use crate::renderer::test_harness::{
+            build_box, build_mesh_bytes, build_texture_array_bytes, create_target,
+        };
+
+        let mut renderer = match pollster::block_on(Renderer::new_headless(64, 64)) {
+            Ok(renderer) => renderer,
+            Err(error) => {
+                log::warn!("No adapter for the variant test: {}", error);
+                return;
+            }
+        };
+        let skins = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        let texture = renderer.load_texture("Skins", &build_texture_array_bytes(4, &skins));
+        renderer.create_variant_material("SkinsMaterial", texture, 3);
+        let column = build_box(Vec3::new(-20.0, 0.0, -20.0), Vec3::new(20.0, 80.0, 20.0));
+        renderer.load_mesh("Column", &build_mesh_bytes(&column));
+
+        let mut physics_world = PhysicsWorld::new();
+        let mut game = Game::new();
+        let prefabs =
+            r#"[(name: "Column", renderable: (mesh: "Column", material: "SkinsMaterial"))]"#;
+        game.set_prefabs(PrefabLibrary::load(prefabs.as_bytes()).unwrap());
+        let entities = game
+            .spawn_variant_row("Column", Vec3::ZERO, &renderer, &mut physics_world)
+            .unwrap();
+        let variants: Vec<u32> = entities
+            .iter()
+            .map(|entity| game.renderables.get(*entity).unwrap().variant)
+            .collect();
+        assert_eq!(variants, [0, 1, 2, 3, 4]);
+
+        game.render(&mut renderer);
+        let target = create_target(&renderer, 64, 64);
+        renderer.render_to_view(&target.create_view(&Default::default()));
+        let errors = renderer.get_render_device().take_validation_errors();
+        assert!(errors.is_empty(), "wgpu errors: {}", errors.join("\n"));
+        let stats = renderer.get_frame_stats();
+        assert_eq!(stats.static_batch_count, 1);
+        assert_eq!(stats.static_instance_count, 5);
+    }
+
+    // The player walks up to the enemy, bolts it and finishes it with an attack while the
+    // enemy hits back once, without a window or frames
+    #[cfg(feature = "test-harness")]
+    #[test]
+    fn a_scripted_bout_adds_up_in_the_stats() {
+        let renderer = match pollster::block_on(Renderer::new_headless(64, 64)) {
+            Ok(renderer) => renderer,
+            Err(error) => {
+                log::warn!("No adapter for the stats test: {}", error);
+                return;
+            }
+        };
+        let level = Level::load(DEFAULT_LEVEL).unwrap();
+        let mut physics_world = PhysicsWorld::new();
+        let mut game = build_game(&level, &mut physics_world);
+        game.set_prefabs(
+            PrefabLibrary::load(include_bytes!("../../assets/prefabs/default.ron")).unwrap(),
+        );
+        let player = game.player.unwrap();
+        let enemy = game.combats.get(player).unwrap().target.unwrap();
+        *game.combats.get_mut(player).unwrap() = CCombat::default();
+        *game.targets.get_mut(player).unwrap() = None;
+        game.healths.get_mut(enemy).unwrap().current = 45.0;
+        let body = game.physics_proxies.get(enemy).unwrap().body_id.unwrap();
+        physics_world.set_velocity(body, Vec2::ZERO);
+        let mut enemy_combat = CCombat::default();
+        enemy_combat.damage = 15.0;
+        enemy_combat.cooldown = 10.0;
+        game.combats.insert(enemy, enemy_combat);
+
+        let step = |game: &mut Game, physics_world: &mut PhysicsWorld| {
+            game.fixed_update(1.0 / 60.0, &renderer, physics_world);
+            game.record_events();
+        };
+
+        // Where the player starts, then half a second walking away at 120 units per second
+        game.movements.get_mut(player).unwrap().velocity = Vec3::ZERO;
+        step(&mut game, &mut physics_world);
+        game.movements.get_mut(player).unwrap().velocity = Vec3::new(0.0, 0.0, -120.0);
+        for _ in 0..30 {
+            step(&mut game, &mut physics_world);
+        }
+        game.movements.get_mut(player).unwrap().velocity = Vec3::ZERO;
+
+        // The bolt leaves the enemy with 15, the attack takes it
+        let direction = (Vec2::new(100.0, 50.0) - Vec2::new(0.0, -60.0)).normalize();
+        game.ability_casters
+            .get_mut(player)
+            .unwrap()
+            .request_cast(0, AbilityTarget::Direction(direction));
+        game.combats.get_mut(enemy).unwrap().request_attack(player);
+        for _ in 0..90 {
+            step(&mut game, &mut physics_world);
+        }
+        game.combats.get_mut(player).unwrap().request_attack(enemy);
+        for _ in 0..60 {
+            step(&mut game, &mut physics_world);
+        }
+
+        assert!(game.healths.get(enemy).unwrap().is_dead());
+        let stats = game.get_stats().get_session();
+        assert_eq!(stats.damage_dealt, 50.0);
+        assert_eq!(stats.damage_taken, 15.0);
+        assert_eq!(stats.kills, 1);
+        assert_eq!(stats.abilities_cast, 1);
+        assert!((stats.distance - 60.0).abs() < 0.5, "{}", stats.distance);
+        assert!((stats.time - 181.0 / 60.0).abs() < 1e-3, "{}", stats.time);
     }
 }
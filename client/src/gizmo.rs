@@ -0,0 +1,601 @@
+// Handles for moving and rotating the selected entity in the editor. Arrows along the axes
+// and squares between them move it, rings around the axes rotate it. The handles are scaled
+// with the distance to the camera so they keep their size on screen, and are drawn over the
+// scene. The game hands in the picking ray every frame and writes the transforms the drags
+// produce back into the entity, the finished drags are kept for undoing.
+
+use shared::{
+    math::{ray::get_circle_points, ray::get_signed_angle, *},
+    transform::Transform,
+};
+
+use crate::{components::Entity, renderer::Renderer};
+
+const GIZMO_SIZE: f32 = 100.0; // Pixels, the length of the arrows and the radius of the rings
+const PICK_RADIUS: f32 = 0.08; // Around the handles, of the gizmo size
+const PLANE_HANDLE_MIN: f32 = 0.2; // The squares between the arrows, of the gizmo size
+const PLANE_HANDLE_MAX: f32 = 0.4;
+const RING_SEGMENT_COUNT: u32 = 48;
+const SNAP_STEP: f32 = 50.0; // World units
+const SNAP_ANGLE: f32 = std::f32::consts::PI / 12.0;
+const MAX_UNDO_COUNT: usize = 100;
+
+const HIGHLIGHT_COLOR: Vec4 = Vec4::new(1.0, 0.9, 0.2, 1.0);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoMode {
+    #[default]
+    Translate,
+    Rotate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl GizmoAxis {
+    pub const ALL: [GizmoAxis; 3] = [GizmoAxis::X, GizmoAxis::Y, GizmoAxis::Z];
+
+    pub fn get_direction(self) -> Vec3 {
+        match self {
+            GizmoAxis::X => Vec3::X,
+            GizmoAxis::Y => Vec3::Y,
+            GizmoAxis::Z => Vec3::Z,
+        }
+    }
+
+    fn get_color(self) -> Vec4 {
+        match self {
+            GizmoAxis::X => Vec4::new(0.9, 0.2, 0.2, 1.0),
+            GizmoAxis::Y => Vec4::new(0.3, 0.9, 0.3, 1.0),
+            GizmoAxis::Z => Vec4::new(0.2, 0.4, 1.0, 1.0),
+        }
+    }
+
+    // The two others, spanning the plane this one is the normal of
+    fn get_plane_directions(self) -> (Vec3, Vec3) {
+        match self {
+            GizmoAxis::X => (Vec3::Y, Vec3::Z),
+            GizmoAxis::Y => (Vec3::Z, Vec3::X),
+            GizmoAxis::Z => (Vec3::X, Vec3::Y),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoHandle {
+    Axis(GizmoAxis),  // Moves along it
+    Plane(GizmoAxis), // Moves in the plane it is the normal of
+    Ring(GizmoAxis),  // Rotates around it
+}
+
+// A finished drag, the entity can be put back where it was and moved again after
+#[derive(Debug, Clone, Copy)]
+pub struct TransformEdit {
+    pub entity: Entity,
+    pub before: Transform,
+    pub after: Transform,
+}
+
+struct GizmoDrag {
+    entity: Entity,
+    handle: GizmoHandle,
+    start: Transform,
+    grab: Vec3, // Where the ray met the handle's line or plane when the drag began
+    current: Transform,
+}
+
+// What the mouse does this frame
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GizmoInput {
+    pub pressed: bool, // The left button went down
+    pub down: bool,
+    pub snap: bool,
+}
+
+#[derive(Default)]
+pub struct Gizmo {
+    mode: GizmoMode,
+    hovered: Option<GizmoHandle>,
+    drag: Option<GizmoDrag>,
+    captured: bool, // The mouse was used by the gizmo this frame
+    undo_stack: Vec<TransformEdit>,
+    redo_stack: Vec<TransformEdit>, // What was undone, a new drag drops it
+}
+
+// World units per gizmo size at the position, so the handles keep their size on screen. The
+// depth along the view and not the distance, like the perspective divide.
+pub fn get_gizmo_scale(camera: &Transform, fov_y: f32, screen_height: f32, position: Vec3) -> f32 {
+    let forward = camera.rotation * Vec3::NEG_Z;
+    let depth = (position - camera.position).dot(forward).max(1.0);
+    let pixel_size = 2.0 * depth * (fov_y * 0.5).tan() / screen_height.max(1.0);
+    GIZMO_SIZE * pixel_size
+}
+
+impl Gizmo {
+    pub fn get_mode(&self) -> GizmoMode {
+        self.mode
+    }
+
+    // Not in the middle of a drag
+    pub fn set_mode(&mut self, mode: GizmoMode) {
+        if self.drag.is_none() {
+            self.mode = mode;
+            self.hovered = None;
+        }
+    }
+
+    // Clicks used by the gizmo don't select anything
+    pub fn is_capturing_mouse(&self) -> bool {
+        self.captured
+    }
+
+    // The handle under the ray, the closest one when several are
+    pub fn pick(&self, ray: &Ray, position: Vec3, scale: f32) -> Option<GizmoHandle> {
+        let pick_radius = PICK_RADIUS * scale;
+        let mut hits: Vec<(f32, GizmoHandle)> = Vec::new();
+        match self.mode {
+            GizmoMode::Translate => {
+                for axis in GizmoAxis::ALL {
+                    let end = position + axis.get_direction() * scale;
+                    let proximity = ray.get_proximity_to_segment(position, end);
+                    if proximity.distance < pick_radius {
+                        hits.push((proximity.t, GizmoHandle::Axis(axis)));
+                    }
+
+                    let (u, v) = axis.get_plane_directions();
+                    if let Some(t) = ray.intersect_plane(position, axis.get_direction()) {
+                        let offset = (ray.get_point(t) - position) / scale;
+                        let range = PLANE_HANDLE_MIN..=PLANE_HANDLE_MAX;
+                        if range.contains(&offset.dot(u)) && range.contains(&offset.dot(v)) {
+                            hits.push((t, GizmoHandle::Plane(axis)));
+                        }
+                    }
+                }
+            }
+            GizmoMode::Rotate => {
+                for axis in GizmoAxis::ALL {
+                    let proximity = ray.get_proximity_to_circle(
+                        position,
+                        axis.get_direction(),
+                        scale,
+                        RING_SEGMENT_COUNT,
+                    );
+                    if proximity.distance < pick_radius {
+                        hits.push((proximity.t, GizmoHandle::Ring(axis)));
+                    }
+                }
+            }
+        }
+        hits.into_iter()
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, handle)| handle)
+    }
+
+    // Hovers and drags the handles of the entity's gizmo, returns the entity's transform
+    // while it is being dragged. A drag of another entity is ended first.
+    pub fn update(
+        &mut self,
+        entity: Option<(Entity, Transform)>,
+        ray: &Ray,
+        scale: f32,
+        input: GizmoInput,
+    ) -> Option<Transform> {
+        self.captured = self.drag.is_some();
+        if self
+            .drag
+            .as_ref()
+            .is_some_and(|drag| Some(drag.entity) != entity.map(|(entity, _)| entity))
+        {
+            self.end_drag();
+        }
+        let Some((entity, transform)) = entity else {
+            self.hovered = None;
+            return None;
+        };
+
+        if let Some(drag) = &mut self.drag {
+            if !input.down {
+                self.end_drag();
+                return None;
+            }
+            if let Some(dragged) = get_dragged_transform(drag, ray, input.snap) {
+                drag.current = dragged;
+            }
+            return Some(drag.current);
+        }
+
+        self.hovered = self.pick(ray, transform.position, scale);
+        if input.pressed
+            && let Some(handle) = self.hovered
+            && let Some(grab) = get_grab_point(handle, ray, transform.position)
+        {
+            self.drag = Some(GizmoDrag {
+                entity,
+                handle,
+                start: transform,
+                grab,
+                current: transform,
+            });
+            self.captured = true;
+        }
+        None
+    }
+
+    // The drag stops where it is, it can be undone
+    fn end_drag(&mut self) {
+        let Some(drag) = self.drag.take() else {
+            return;
+        };
+        if drag.current.position == drag.start.position
+            && drag.current.rotation == drag.start.rotation
+        {
+            return;
+        }
+        if self.undo_stack.len() == MAX_UNDO_COUNT {
+            self.undo_stack.remove(0);
+        }
+        self.undo_stack.push(TransformEdit {
+            entity: drag.entity,
+            before: drag.start,
+            after: drag.current,
+        });
+        self.redo_stack.clear();
+    }
+
+    // The last finished drag, for putting the entity back. Not while dragging.
+    pub fn undo(&mut self) -> Option<TransformEdit> {
+        if self.drag.is_some() {
+            return None;
+        }
+        let edit = self.undo_stack.pop()?;
+        self.redo_stack.push(edit);
+        Some(edit)
+    }
+
+    // The last undone drag, for moving the entity where the drag left it. Not while dragging.
+    pub fn redo(&mut self) -> Option<TransformEdit> {
+        if self.drag.is_some() {
+            return None;
+        }
+        let edit = self.redo_stack.pop()?;
+        self.undo_stack.push(edit);
+        Some(edit)
+    }
+
+    // The entities are gone, the mode stays
+    pub fn clear(&mut self) {
+        self.hovered = None;
+        self.drag = None;
+        self.captured = false;
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+    }
+
+    #[allow(dead_code)]
+    pub fn get_undo_count(&self) -> usize {
+        self.undo_stack.len()
+    }
+
+    // The handles of the mode, the hovered or dragged one highlighted
+    pub fn render(&self, renderer: &mut Renderer, position: Vec3, scale: f32) {
+        let active = self.drag.as_ref().map(|drag| drag.handle).or(self.hovered);
+        let get_color = |handle: GizmoHandle, axis: GizmoAxis| match active == Some(handle) {
+            true => HIGHLIGHT_COLOR,
+            false => axis.get_color(),
+        };
+
+        match self.mode {
+            GizmoMode::Translate => {
+                for axis in GizmoAxis::ALL {
+                    let direction = axis.get_direction();
+                    let color = get_color(GizmoHandle::Axis(axis), axis);
+                    let tip = position + direction * scale;
+                    renderer.draw_gizmo_line(position, tip, color);
+                    // An arrow head of four lines back from the tip
+                    let (u, v) = axis.get_plane_directions();
+                    let base = tip - direction * scale * 0.15;
+                    for side in [u, -u, v, -v] {
+                        renderer.draw_gizmo_line(tip, base + side * scale * 0.05, color);
+                    }
+
+                    let color = get_color(GizmoHandle::Plane(axis), axis);
+                    let corners = [
+                        (PLANE_HANDLE_MIN, PLANE_HANDLE_MIN),
+                        (PLANE_HANDLE_MAX, PLANE_HANDLE_MIN),
+                        (PLANE_HANDLE_MAX, PLANE_HANDLE_MAX),
+                        (PLANE_HANDLE_MIN, PLANE_HANDLE_MAX),
+                    ]
+                    .map(|(a, b)| position + (u * a + v * b) * scale);
+                    for index in 0..corners.len() {
+                        let next = corners[(index + 1) % corners.len()];
+                        renderer.draw_gizmo_line(corners[index], next, color);
+                    }
+                }
+            }
+            GizmoMode::Rotate => {
+                for axis in GizmoAxis::ALL {
+                    let color = get_color(GizmoHandle::Ring(axis), axis);
+                    let points = get_circle_points(
+                        position,
+                        axis.get_direction(),
+                        scale,
+                        RING_SEGMENT_COUNT,
+                    );
+                    for segment in points.windows(2) {
+                        renderer.draw_gizmo_line(segment[0], segment[1], color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Where the drag of the handle starts, on the axis for arrows and in the plane otherwise.
+// None when the ray runs along it and there is no sensible point.
+fn get_grab_point(handle: GizmoHandle, ray: &Ray, position: Vec3) -> Option<Vec3> {
+    match handle {
+        GizmoHandle::Axis(axis) => {
+            let direction = axis.get_direction();
+            let s = ray.get_closest_on_line(position, direction)?;
+            Some(position + direction * s)
+        }
+        GizmoHandle::Plane(axis) | GizmoHandle::Ring(axis) => {
+            let t = ray.intersect_plane(position, axis.get_direction())?;
+            Some(ray.get_point(t))
+        }
+    }
+}
+
+// The transform the drag has moved the entity to, snapped to the grid or the angle steps.
+// None when the ray can't be followed, the entity stays where the drag last left it.
+fn get_dragged_transform(drag: &GizmoDrag, ray: &Ray, snap: bool) -> Option<Transform> {
+    let start = drag.start;
+    let grab = get_grab_point(drag.handle, ray, start.position)?;
+    let mut transform = start;
+    match drag.handle {
+        GizmoHandle::Axis(axis) | GizmoHandle::Plane(axis) => {
+            let mut position = start.position + (grab - drag.grab);
+            if snap {
+                // Only what the handle moves, the entity stays where it is on the other axes
+                let moved = match drag.handle {
+                    GizmoHandle::Axis(_) => axis.get_direction(),
+                    _ => Vec3::ONE - axis.get_direction(),
+                };
+                let snapped = (position / SNAP_STEP).round() * SNAP_STEP;
+                position = Vec3::select(moved.cmpgt(Vec3::ZERO), snapped, position);
+            }
+            transform.position = position;
+        }
+        GizmoHandle::Ring(axis) => {
+            let direction = axis.get_direction();
+            let mut angle =
+                get_signed_angle(drag.grab - start.position, grab - start.position, direction);
+            if snap {
+                angle = (angle / SNAP_ANGLE).round() * SNAP_ANGLE;
+            }
+            transform.rotation =
+                (Quat::from_axis_angle(direction, angle) * start.rotation).normalize();
+        }
+    }
+    Some(transform)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Entities;
+
+    // Looking down on the origin from above, like the game camera but straight down
+    fn get_ray_from_above(point: Vec3) -> Ray {
+        Ray::new(point + Vec3::new(0.0, 1000.0, 0.0), Vec3::NEG_Y)
+    }
+
+    // Pressed on the first ray, held along the path and released on the last one
+    fn drag(
+        gizmo: &mut Gizmo,
+        entity: Entity,
+        transform: &mut Transform,
+        path: &[Ray],
+        snap: bool,
+    ) {
+        for (index, ray) in path.iter().enumerate() {
+            let input = GizmoInput {
+                pressed: index == 0,
+                down: true,
+                snap,
+            };
+            if let Some(dragged) = gizmo.update(Some((entity, *transform)), ray, 100.0, input) {
+                *transform = dragged;
+            }
+        }
+        let release = GizmoInput {
+            snap,
+            ..Default::default()
+        };
+        gizmo.update(
+            Some((entity, *transform)),
+            path.last().unwrap(),
+            100.0,
+            release,
+        );
+        // Released, holding the button again without a press moves nothing
+        let held = GizmoInput {
+            down: true,
+            ..Default::default()
+        };
+        assert!(
+            gizmo
+                .update(Some((entity, *transform)), &path[0], 100.0, held)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn handles_keep_their_size_on_screen() {
+        let camera = Transform::default(); // Looking down -z
+        let fov = 60f32.to_radians();
+        let near = get_gizmo_scale(&camera, fov, 1000.0, Vec3::new(0.0, 0.0, -500.0));
+        let far = get_gizmo_scale(&camera, fov, 1000.0, Vec3::new(300.0, 0.0, -1000.0));
+        assert!((far / near - 2.0).abs() < 1e-4);
+        // 100 pixels of a 1000 pixel screen, where the view is 2 tan(30) deep wide
+        let expected = 0.1 * 2.0 * 500.0 * (fov * 0.5).tan();
+        assert!((near - expected).abs() < 1e-3);
+        // A larger window fits more pixels in the same view
+        let larger = get_gizmo_scale(&camera, fov, 2000.0, Vec3::new(0.0, 0.0, -500.0));
+        assert!((larger * 2.0 - near).abs() < 1e-3);
+    }
+
+    #[test]
+    fn the_closest_handle_under_the_ray_is_picked() {
+        let mut gizmo = Gizmo::default();
+        let position = Vec3::new(10.0, 0.0, 20.0);
+        let ray = get_ray_from_above(position + Vec3::new(60.0, 0.0, 2.0));
+        assert_eq!(
+            gizmo.pick(&ray, position, 100.0),
+            Some(GizmoHandle::Axis(GizmoAxis::X))
+        );
+        // Between x and z, on the square in the ground plane
+        let ray = get_ray_from_above(position + Vec3::new(30.0, 0.0, 30.0));
+        assert_eq!(
+            gizmo.pick(&ray, position, 100.0),
+            Some(GizmoHandle::Plane(GizmoAxis::Y))
+        );
+        let ray = get_ray_from_above(position + Vec3::new(-60.0, 0.0, -60.0));
+        assert_eq!(gizmo.pick(&ray, position, 100.0), None);
+
+        // From above the y ring is a circle around the entity, the others are lines through it
+        gizmo.set_mode(GizmoMode::Rotate);
+        let ray = get_ray_from_above(position + Vec3::new(0.0, 0.0, -100.0));
+        let handle = gizmo.pick(&ray, position, 100.0);
+        assert!(matches!(
+            handle,
+            Some(GizmoHandle::Ring(GizmoAxis::Y | GizmoAxis::X))
+        ));
+        let ray = get_ray_from_above(position + Vec3::new(70.7, 0.0, 70.7));
+        assert_eq!(
+            gizmo.pick(&ray, position, 100.0),
+            Some(GizmoHandle::Ring(GizmoAxis::Y))
+        );
+    }
+
+    #[test]
+    fn dragging_an_arrow_moves_along_its_axis_only() {
+        let mut entities = Entities::default();
+        let entity = entities.spawn();
+        let mut gizmo = Gizmo::default();
+        let mut transform = Transform::default();
+        let path = [
+            get_ray_from_above(Vec3::new(50.0, 0.0, 0.0)),
+            get_ray_from_above(Vec3::new(120.0, 0.0, 40.0)),
+            get_ray_from_above(Vec3::new(173.0, 0.0, 80.0)),
+        ];
+        drag(&mut gizmo, entity, &mut transform, &path, false);
+        assert!(
+            transform
+                .position
+                .abs_diff_eq(Vec3::new(123.0, 0.0, 0.0), 1e-3)
+        );
+
+        // Snapped, the x goes to the grid and the rest stays off it
+        transform.position.z = 7.0;
+        let path = [
+            get_ray_from_above(Vec3::new(173.0, 0.0, 7.0)),
+            get_ray_from_above(Vec3::new(236.0, 0.0, 7.0)),
+        ];
+        drag(&mut gizmo, entity, &mut transform, &path, true);
+        assert!(
+            transform
+                .position
+                .abs_diff_eq(Vec3::new(200.0, 0.0, 7.0), 1e-3)
+        );
+    }
+
+    #[test]
+    fn dragging_a_plane_moves_in_it_and_rings_rotate() {
+        let mut entities = Entities::default();
+        let entity = entities.spawn();
+        let mut gizmo = Gizmo::default();
+        let mut transform = Transform::default();
+        let path = [
+            get_ray_from_above(Vec3::new(30.0, 0.0, 30.0)),
+            get_ray_from_above(Vec3::new(80.0, 0.0, -20.0)),
+        ];
+        drag(&mut gizmo, entity, &mut transform, &path, false);
+        assert!(
+            transform
+                .position
+                .abs_diff_eq(Vec3::new(50.0, 0.0, -50.0), 1e-3)
+        );
+
+        // Close to a quarter turn around y on the ring, snapped to it
+        gizmo.set_mode(GizmoMode::Rotate);
+        let center = transform.position;
+        let path = [
+            get_ray_from_above(center + Vec3::new(70.7, 0.0, 70.7)),
+            get_ray_from_above(center + Vec3::new(75.0, 0.0, -65.0)),
+        ];
+        drag(&mut gizmo, entity, &mut transform, &path, true);
+        let (axis, angle) = transform.rotation.to_axis_angle();
+        assert!(axis.abs_diff_eq(Vec3::Y, 1e-4));
+        assert!((angle - std::f32::consts::FRAC_PI_2).abs() < 1e-4);
+        assert_eq!(transform.position, center);
+    }
+
+    #[test]
+    fn finished_drags_are_undone_last_first() {
+        let mut entities = Entities::default();
+        let entity = entities.spawn();
+        let mut gizmo = Gizmo::default();
+        let mut transform = Transform::default();
+        for x in [100.0, 200.0] {
+            let start = transform.position.x + 50.0;
+            let path = [
+                get_ray_from_above(Vec3::new(start, 0.0, 0.0)),
+                get_ray_from_above(Vec3::new(start + 100.0, 0.0, 0.0)),
+            ];
+            drag(&mut gizmo, entity, &mut transform, &path, false);
+            assert!((transform.position.x - x).abs() < 1e-3);
+        }
+        // A click on a handle without moving is no edit
+        let click = [get_ray_from_above(Vec3::new(250.0, 0.0, 0.0)); 2];
+        drag(&mut gizmo, entity, &mut transform, &click, false);
+        assert_eq!(gizmo.get_undo_count(), 2);
+
+        let edit = gizmo.undo().unwrap();
+        assert_eq!(edit.entity, entity);
+        assert!((edit.before.position.x - 100.0).abs() < 1e-3);
+        assert!((edit.after.position.x - 200.0).abs() < 1e-3);
+        assert_eq!(gizmo.undo().unwrap().before.position, Vec3::ZERO);
+        assert!(gizmo.undo().is_none());
+    }
+
+    #[test]
+    fn undone_drags_are_redone_until_the_next_drag() {
+        let mut entities = Entities::default();
+        let entity = entities.spawn();
+        let mut gizmo = Gizmo::default();
+        let mut transform = Transform::default();
+        let path = [
+            get_ray_from_above(Vec3::new(50.0, 0.0, 0.0)),
+            get_ray_from_above(Vec3::new(150.0, 0.0, 0.0)),
+        ];
+        drag(&mut gizmo, entity, &mut transform, &path, false);
+        assert!(gizmo.redo().is_none());
+
+        transform = gizmo.undo().unwrap().before;
+        assert_eq!(transform.position, Vec3::ZERO);
+        transform = gizmo.redo().unwrap().after;
+        assert!((transform.position.x - 100.0).abs() < 1e-3);
+        assert!(gizmo.redo().is_none());
+
+        // Redone, it can be undone again, and a new drag leaves nothing to redo
+        transform = gizmo.undo().unwrap().before;
+        drag(&mut gizmo, entity, &mut transform, &path, false);
+        assert!(gizmo.redo().is_none());
+        assert_eq!(gizmo.get_undo_count(), 1);
+    }
+}
@@ -0,0 +1,260 @@
+// Parent/child transforms. A child's transform is computed from its parent every frame,
+// its own CTransform then holds the result in world space like for any other entity.
+
+use shared::{math::*, transform::Transform};
+
+use crate::components::{Entities, Entity, Storage, join};
+
+#[derive(Debug, Clone, Copy)]
+pub struct CParent {
+    pub parent: Entity,
+    pub bone: Option<usize>, // Follows a bone of the parent's pose instead of its origin
+    pub local: Transform,    // Relative to the parent or the bone
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    Pending,
+    InProgress,
+    Done,
+}
+
+// Attaches the child keeping its world transform, or detaches it with None. Fails when the
+// child is an ancestor of the new parent.
+pub fn set_parent(
+    parents: &mut Storage<CParent>,
+    transforms: &Storage<Transform>,
+    child: Entity,
+    parent: Option<Entity>,
+) -> bool {
+    let Some(parent) = parent else {
+        parents.remove(child);
+        return true;
+    };
+
+    let mut ancestor = Some(parent);
+    while let Some(entity) = ancestor {
+        if entity == child {
+            log::error!("Can't parent {:?} to its descendant {:?}", child, parent);
+            return false;
+        }
+        ancestor = parents.get(entity).map(|link| link.parent);
+    }
+
+    let (Some(child_transform), Some(parent_transform)) =
+        (transforms.get(child), transforms.get(parent))
+    else {
+        return false;
+    };
+    let local = parent_transform.to_matrix().inverse() * child_transform.to_matrix();
+    parents.insert(
+        child,
+        CParent {
+            parent,
+            bone: None,
+            local: Transform::from_matrix(&local),
+        },
+    );
+    true
+}
+
+// Writes the world transform of every child, parents before their children. Children of
+// despawned parents become roots where they are, and so does the child that closes a
+// parenting cycle. get_socket returns a bone of the parent relative to the parent.
+pub fn propagate_transforms(
+    entities: &Entities,
+    parents: &mut Storage<CParent>,
+    transforms: &mut Storage<Transform>,
+    get_socket: impl Fn(Entity, usize) -> Option<Mat4>,
+) {
+    let children: Vec<Entity> = join(entities, &*parents)
+        .map(|(entity, _)| entity)
+        .collect();
+
+    let mut propagation = Propagation {
+        entities,
+        parents,
+        transforms,
+        get_socket,
+        visits: Vec::new(),
+        detached: Vec::new(),
+    };
+    for child in children {
+        propagation.resolve(child);
+    }
+
+    let detached = propagation.detached;
+    for child in detached {
+        parents.remove(child);
+    }
+}
+
+struct Propagation<'a, F> {
+    entities: &'a Entities,
+    parents: &'a Storage<CParent>,
+    transforms: &'a mut Storage<Transform>,
+    get_socket: F,
+    visits: Vec<Visit>, // By entity index
+    detached: Vec<Entity>,
+}
+
+impl<F: Fn(Entity, usize) -> Option<Mat4>> Propagation<'_, F> {
+    // The world matrix of the entity, None when it has no transform
+    fn resolve(&mut self, entity: Entity) -> Option<Mat4> {
+        let index = entity.index();
+        if self.visits.len() <= index {
+            self.visits.resize(index + 1, Visit::Pending);
+        }
+
+        let world = self.transforms.get(entity)?.to_matrix();
+        let link = match self.parents.get(entity) {
+            Some(link) if self.visits[index] == Visit::Pending => *link,
+            _ => return Some(world), // A root or already resolved this frame
+        };
+        self.visits[index] = Visit::InProgress;
+
+        let parent_index = link.parent.index();
+        let parent_world = if !self.entities.is_alive(link.parent) {
+            None
+        } else if self.visits.get(parent_index) == Some(&Visit::InProgress) {
+            log::error!("Parenting cycle, detached {:?}", entity);
+            None
+        } else {
+            self.resolve(link.parent)
+        };
+        self.visits[index] = Visit::Done;
+
+        let Some(parent_world) = parent_world else {
+            self.detached.push(entity);
+            return Some(world);
+        };
+
+        let socket = link
+            .bone
+            .and_then(|bone| (self.get_socket)(link.parent, bone))
+            .unwrap_or(Mat4::IDENTITY);
+        let world = parent_world * socket * link.local.to_matrix();
+        if let Some(transform) = self.transforms.get_mut(entity) {
+            *transform = Transform::from_matrix(&world);
+        }
+        Some(world)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f32) -> Transform {
+        Transform {
+            position: Vec3::new(x, 0.0, 0.0),
+            ..Default::default()
+        }
+    }
+
+    fn get_x(transforms: &Storage<Transform>, entity: Entity) -> f32 {
+        transforms.get(entity).unwrap().position.x
+    }
+
+    fn no_sockets(_: Entity, _: usize) -> Option<Mat4> {
+        None
+    }
+
+    #[test]
+    fn children_follow_their_parents_in_any_order() {
+        let mut entities = Entities::default();
+        let grandchild = entities.spawn();
+        let child = entities.spawn();
+        let root = entities.spawn();
+
+        let mut transforms = Storage::default();
+        let mut parents = Storage::default();
+        for entity in [grandchild, child, root] {
+            transforms.insert(entity, Transform::default());
+        }
+        parents.insert(
+            grandchild,
+            CParent {
+                parent: child,
+                bone: None,
+                local: at(1.0),
+            },
+        );
+        parents.insert(
+            child,
+            CParent {
+                parent: root,
+                bone: Some(3),
+                local: at(10.0),
+            },
+        );
+
+        transforms.insert(root, at(100.0));
+        let socket = |parent: Entity, bone: usize| {
+            (parent == root && bone == 3).then(|| Mat4::from_translation(Vec3::Y * 50.0))
+        };
+        propagate_transforms(&entities, &mut parents, &mut transforms, socket);
+
+        assert_eq!(get_x(&transforms, child), 110.0);
+        assert_eq!(get_x(&transforms, grandchild), 111.0);
+        assert_eq!(transforms.get(grandchild).unwrap().position.y, 50.0);
+    }
+
+    #[test]
+    fn reparenting_and_despawning_keep_the_world_transform() {
+        let mut entities = Entities::default();
+        let a = entities.spawn();
+        let b = entities.spawn();
+        let child = entities.spawn();
+
+        let mut transforms = Storage::default();
+        let mut parents = Storage::default();
+        transforms.insert(a, at(10.0));
+        transforms.insert(b, at(-20.0));
+        transforms.insert(child, at(15.0));
+
+        assert!(set_parent(&mut parents, &transforms, child, Some(a)));
+        propagate_transforms(&entities, &mut parents, &mut transforms, no_sockets);
+        assert_eq!(get_x(&transforms, child), 15.0);
+
+        assert!(set_parent(&mut parents, &transforms, child, Some(b)));
+        transforms.insert(b, at(-10.0));
+        propagate_transforms(&entities, &mut parents, &mut transforms, no_sockets);
+        assert_eq!(get_x(&transforms, child), 25.0);
+
+        entities.despawn(b);
+        transforms.remove(b);
+        propagate_transforms(&entities, &mut parents, &mut transforms, no_sockets);
+        assert_eq!(get_x(&transforms, child), 25.0);
+        assert!(parents.get(child).is_none());
+    }
+
+    #[test]
+    fn cycles_are_refused_and_broken() {
+        let mut entities = Entities::default();
+        let a = entities.spawn();
+        let b = entities.spawn();
+
+        let mut transforms = Storage::default();
+        let mut parents = Storage::default();
+        transforms.insert(a, at(1.0));
+        transforms.insert(b, at(2.0));
+
+        assert!(set_parent(&mut parents, &transforms, b, Some(a)));
+        assert!(!set_parent(&mut parents, &transforms, a, Some(b)));
+        assert!(!set_parent(&mut parents, &transforms, a, Some(a)));
+
+        // Only possible by writing the links directly
+        parents.insert(
+            a,
+            CParent {
+                parent: b,
+                bone: None,
+                local: at(0.0),
+            },
+        );
+        propagate_transforms(&entities, &mut parents, &mut transforms, no_sockets);
+        let remaining = [a, b].iter().filter(|&&e| parents.get(e).is_some()).count();
+        assert_eq!(remaining, 1);
+    }
+}
@@ -0,0 +1,538 @@
+// Catches the frames that take much longer than the ones around them, which the averages
+// of the FPS counter hide. A frame is a hitch when it takes longer than a multiple of the
+// median of the frames before it. What the frame did is kept in a record: its profiled
+// scopes, the draw counts, the GPU times of the passes, the loads under way and the last
+// lines logged. The records are allocated up front and the oldest is written over, looking
+// at a frame allocates nothing. Shown in the inspector, listed and dumped by the console.
+
+use serde::Serialize;
+use shared::profiler::FrameProfile;
+
+use crate::{
+    diagnostics::visit_last_log_lines,
+    renderer::{FrameStats, PassTiming, gpu_timer::MAX_TIMED_PASSES},
+};
+
+pub const MEDIAN_WINDOW: usize = 120; // Frames the median is taken of
+pub const WARM_UP_FRAMES: usize = 30; // Before the median is trusted, e.g. after loading
+pub const MAX_HITCHES: usize = 16;
+pub const MAX_HITCH_SCOPES: usize = 256; // Deeper profiles are cut off
+pub const MAX_HITCH_LOG_LINES: usize = 16;
+const MAX_LOG_LINE_LENGTH: usize = 160; // In bytes, longer lines are cut off
+
+// The median of the last frames, in a ring that is sorted into a copy when asked
+pub struct RollingMedian {
+    values: [f32; MEDIAN_WINDOW],
+    scratch: [f32; MEDIAN_WINDOW],
+    next: usize,
+    count: usize, // Ever pushed, the window holds the last ones
+}
+
+impl RollingMedian {
+    pub fn new() -> Self {
+        Self {
+            values: [0.0; MEDIAN_WINDOW],
+            scratch: [0.0; MEDIAN_WINDOW],
+            next: 0,
+            count: 0,
+        }
+    }
+
+    pub fn push(&mut self, value: f32) {
+        self.values[self.next] = value;
+        self.next = (self.next + 1) % MEDIAN_WINDOW;
+        self.count += 1;
+    }
+
+    // The upper one of an even count, None before anything was pushed
+    pub fn get_median(&mut self) -> Option<f32> {
+        let len = self.count.min(MEDIAN_WINDOW);
+        if len == 0 {
+            return None;
+        }
+        let scratch = &mut self.scratch[..len];
+        scratch.copy_from_slice(&self.values[..len]);
+        let (_, median, _) = scratch.select_nth_unstable_by(len / 2, f32::total_cmp);
+        Some(*median)
+    }
+
+    pub fn get_count(&self) -> usize {
+        self.count
+    }
+
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.count = 0;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitchSettings {
+    pub enabled: bool,
+    pub multiple: f32, // Of the median
+    pub min_ms: f32,   // Shorter frames are never hitches, e.g. at high frame rates
+}
+
+impl Default for HitchSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            multiple: 3.0,
+            min_ms: 20.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct HitchScope {
+    pub name: &'static str,
+    pub thread: u32,
+    pub depth: u32,
+    pub parent: Option<usize>, // Index of the enclosing scope
+    pub start_ms: f32,         // From the start of the frame
+    pub ms: f32,
+}
+
+// What the app knows about the frame as it ends
+pub struct HitchSources<'a> {
+    pub profile: &'a FrameProfile,
+    pub stats: &'a FrameStats,
+    pub gpu_passes: &'a [PassTiming], // Of a frame a few before, the GPU lags behind
+    pub pending_loads: usize,         // Chunks and files still loading
+}
+
+pub struct HitchRecord {
+    pub frame: u64, // Counted by the detector
+    pub frame_ms: f32,
+    pub median_ms: f32, // Of the frames before
+    pub stats: FrameStats,
+    pub scopes: Vec<HitchScope>,
+    pub gpu_passes: Vec<PassTiming>,
+    pub pending_loads: usize,
+    log: Vec<String>, // Every slot is allocated, the first log_count are in use
+    log_count: usize,
+}
+
+impl HitchRecord {
+    fn new() -> Self {
+        Self {
+            frame: 0,
+            frame_ms: 0.0,
+            median_ms: 0.0,
+            stats: FrameStats::default(),
+            scopes: Vec::with_capacity(MAX_HITCH_SCOPES),
+            gpu_passes: Vec::with_capacity(MAX_TIMED_PASSES),
+            pending_loads: 0,
+            log: (0..MAX_HITCH_LOG_LINES)
+                .map(|_| String::with_capacity(MAX_LOG_LINE_LENGTH))
+                .collect(),
+            log_count: 0,
+        }
+    }
+
+    // Copies into what was allocated, the scopes and lines past it are left out
+    fn capture(&mut self, frame: u64, frame_ms: f32, median_ms: f32, sources: &HitchSources) {
+        self.frame = frame;
+        self.frame_ms = frame_ms;
+        self.median_ms = median_ms;
+        self.stats = *sources.stats;
+        self.pending_loads = sources.pending_loads;
+
+        let profile = sources.profile;
+        self.scopes.clear();
+        self.scopes.extend(
+            profile
+                .nodes
+                .iter()
+                .take(MAX_HITCH_SCOPES)
+                .map(|node| HitchScope {
+                    name: node.name,
+                    thread: node.thread,
+                    depth: node.depth,
+                    // Parents come before their children, the cut never leaves one out
+                    parent: node.parent,
+                    start_ms: ((node.start - profile.start) * 1000.0) as f32,
+                    ms: (node.get_duration() * 1000.0) as f32,
+                }),
+        );
+        self.gpu_passes.clear();
+        self.gpu_passes
+            .extend(sources.gpu_passes.iter().take(MAX_TIMED_PASSES));
+
+        self.log_count = 0;
+        visit_last_log_lines(MAX_HITCH_LOG_LINES, |line| {
+            let slot = &mut self.log[self.log_count];
+            slot.clear();
+            slot.push_str(get_prefix(line, MAX_LOG_LINE_LENGTH));
+            self.log_count += 1;
+        });
+    }
+
+    // Oldest first
+    pub fn get_log_lines(&self) -> &[String] {
+        &self.log[..self.log_count]
+    }
+
+    // The scope that took the most time of its own, without its children
+    pub fn get_slowest_scope(&self) -> Option<(&HitchScope, f32)> {
+        let mut self_ms: Vec<f32> = self.scopes.iter().map(|scope| scope.ms).collect();
+        for scope in &self.scopes {
+            if let Some(parent) = scope.parent {
+                self_ms[parent] -= scope.ms;
+            }
+        }
+        self.scopes
+            .iter()
+            .zip(self_ms)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    pub fn get_summary(&self) -> String {
+        let slowest = match self.get_slowest_scope() {
+            Some((scope, ms)) => format!(", most in {} ({:.1} ms)", scope.name, ms),
+            None => String::new(),
+        };
+        format!(
+            "Frame {}: {:.1} ms, {:.1}x the median of {:.1} ms, {} loads{}",
+            self.frame,
+            self.frame_ms,
+            self.frame_ms / self.median_ms.max(f32::EPSILON),
+            self.median_ms,
+            self.pending_loads,
+            slowest
+        )
+    }
+
+    fn get_dump(&self) -> HitchDump<'_> {
+        HitchDump {
+            frame: self.frame,
+            frame_ms: self.frame_ms,
+            median_ms: self.median_ms,
+            stats: &self.stats,
+            pending_loads: self.pending_loads,
+            scopes: &self.scopes,
+            gpu_passes: &self.gpu_passes,
+            log: self.get_log_lines(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HitchDump<'a> {
+    frame: u64,
+    frame_ms: f32,
+    median_ms: f32,
+    stats: &'a FrameStats,
+    pending_loads: usize,
+    scopes: &'a [HitchScope],
+    gpu_passes: &'a [PassTiming],
+    log: &'a [String],
+}
+
+// At most max bytes, cut at a character
+fn get_prefix(text: &str, max: usize) -> &str {
+    let mut end = text.len().min(max);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+pub struct HitchDetector {
+    settings: HitchSettings,
+    median: RollingMedian,
+    records: Vec<HitchRecord>,
+    next: usize,  // The record written next
+    count: usize, // Of the records in use
+    frame: u64,
+    last_profile_end: f64, // A profile that didn't change since is of no new frame
+}
+
+impl HitchDetector {
+    pub fn new() -> Self {
+        Self {
+            settings: HitchSettings::default(),
+            median: RollingMedian::new(),
+            records: (0..MAX_HITCHES).map(|_| HitchRecord::new()).collect(),
+            next: 0,
+            count: 0,
+            frame: 0,
+            last_profile_end: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn get_settings(&self) -> HitchSettings {
+        self.settings
+    }
+
+    // The median starts over when enabled, the frames it was of are long gone
+    pub fn set_settings(&mut self, settings: HitchSettings) {
+        if settings.enabled && !self.settings.enabled {
+            self.median.clear();
+        }
+        self.settings = settings;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.settings.enabled
+    }
+
+    // A frame longer than this is a hitch, None while warming up
+    pub fn get_threshold(&mut self) -> Option<f32> {
+        if self.median.get_count() < WARM_UP_FRAMES {
+            return None;
+        }
+        let median = self.median.get_median()?;
+        Some((median * self.settings.multiple).max(self.settings.min_ms))
+    }
+
+    // Once per frame, after its profile was collected. Returns whether it was a hitch.
+    pub fn end_frame(&mut self, sources: &HitchSources) -> bool {
+        let profile = sources.profile;
+        if !self.settings.enabled || profile.end <= self.last_profile_end {
+            return false;
+        }
+        self.last_profile_end = profile.end;
+        self.frame += 1;
+
+        let frame_ms = ((profile.end - profile.start) * 1000.0) as f32;
+        let threshold = self.get_threshold();
+        let median_ms = self.median.get_median().unwrap_or_default();
+        self.median.push(frame_ms);
+        if !threshold.is_some_and(|threshold| frame_ms > threshold) {
+            return false;
+        }
+
+        self.records[self.next].capture(self.frame, frame_ms, median_ms, sources);
+        self.next = (self.next + 1) % MAX_HITCHES;
+        self.count = (self.count + 1).min(MAX_HITCHES);
+        log::warn!("Hitch: {}", self.get_latest().unwrap().get_summary());
+        true
+    }
+
+    // Newest first
+    pub fn get_records(&self) -> impl Iterator<Item = &HitchRecord> {
+        (1..=self.count).map(|age| &self.records[(self.next + MAX_HITCHES - age) % MAX_HITCHES])
+    }
+
+    pub fn get_latest(&self) -> Option<&HitchRecord> {
+        self.get_records().next()
+    }
+
+    pub fn get_count(&self) -> usize {
+        self.count
+    }
+
+    // The records stay allocated
+    pub fn clear(&mut self) {
+        self.count = 0;
+        self.next = 0;
+    }
+
+    // Newest first
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        let dumps: Vec<HitchDump> = self.get_records().map(HitchRecord::get_dump).collect();
+        Ok(serde_json::to_string_pretty(&dumps)?)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_hitches(detector: &HitchDetector, path: &std::path::Path) -> anyhow::Result<()> {
+    use anyhow::Context;
+
+    let json = detector.to_json()?;
+    std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::profiler::ProfileNode;
+
+    use super::*;
+
+    // Frames of the length one after another, each with a scope of its own and one inside
+    struct FrameClock {
+        time: f64,
+    }
+
+    impl FrameClock {
+        fn next(&mut self, ms: f64) -> FrameProfile {
+            let start = self.time;
+            self.time += ms / 1000.0;
+            let node = |name, depth, parent, end| ProfileNode {
+                name,
+                thread: 0,
+                depth,
+                parent,
+                start,
+                end,
+            };
+            FrameProfile {
+                start,
+                end: self.time,
+                nodes: vec![
+                    node("Frame", 0, None, self.time),
+                    node("Sleep", 1, Some(0), self.time - 0.001),
+                ],
+            }
+        }
+    }
+
+    fn end_frame(detector: &mut HitchDetector, profile: &FrameProfile) -> bool {
+        detector.end_frame(&HitchSources {
+            profile,
+            stats: &FrameStats::default(),
+            gpu_passes: &[],
+            pending_loads: 0,
+        })
+    }
+
+    #[test]
+    fn the_median_is_of_the_last_frames() {
+        let mut median = RollingMedian::new();
+        assert_eq!(median.get_median(), None);
+        for value in [5.0, 1.0, 3.0] {
+            median.push(value);
+        }
+        assert_eq!(median.get_median(), Some(3.0));
+        // Outliers don't move it
+        median.push(1000.0);
+        median.push(2.0);
+        assert_eq!(median.get_median(), Some(3.0));
+
+        // Once the window is full the old frames fall out of it
+        for _ in 0..MEDIAN_WINDOW {
+            median.push(10.0);
+        }
+        assert_eq!(median.get_median(), Some(10.0));
+        median.clear();
+        assert_eq!(median.get_median(), None);
+    }
+
+    #[test]
+    fn no_hitches_are_found_while_warming_up() {
+        let mut detector = HitchDetector::new();
+        let mut clock = FrameClock { time: 1.0 };
+        // Loading frames are long and uneven
+        for index in 0..WARM_UP_FRAMES {
+            let ms = if index % 5 == 0 { 200.0 } else { 16.0 };
+            assert_eq!(detector.get_threshold(), None);
+            assert!(!end_frame(&mut detector, &clock.next(ms)));
+        }
+        assert_eq!(detector.get_count(), 0);
+        assert_eq!(detector.get_threshold(), Some(48.0));
+    }
+
+    #[test]
+    fn frames_over_the_threshold_are_hitches_in_steady_state() {
+        let mut detector = HitchDetector::new();
+        let mut clock = FrameClock { time: 1.0 };
+        for _ in 0..WARM_UP_FRAMES * 2 {
+            assert!(!end_frame(&mut detector, &clock.next(16.0)));
+        }
+        assert!(!end_frame(&mut detector, &clock.next(47.0)));
+        assert!(end_frame(&mut detector, &clock.next(49.0)));
+        // The same profile again is no new frame
+        let profile = clock.next(120.0);
+        assert!(end_frame(&mut detector, &profile));
+        assert!(!end_frame(&mut detector, &profile));
+
+        // At high frame rates the minimum keeps short frames from counting
+        let mut detector = HitchDetector::new();
+        for _ in 0..WARM_UP_FRAMES * 2 {
+            end_frame(&mut detector, &clock.next(4.0));
+        }
+        assert_eq!(detector.get_threshold(), Some(20.0));
+        assert!(!end_frame(&mut detector, &clock.next(15.0)));
+        assert!(end_frame(&mut detector, &clock.next(21.0)));
+
+        // Nothing is looked at while disabled, and the median starts over after
+        detector.set_settings(HitchSettings {
+            enabled: false,
+            ..detector.get_settings()
+        });
+        assert!(!end_frame(&mut detector, &clock.next(500.0)));
+        detector.set_settings(HitchSettings::default());
+        assert_eq!(detector.get_threshold(), None);
+    }
+
+    #[test]
+    fn an_artificial_hitch_is_captured_whole() {
+        let mut detector = HitchDetector::new();
+        let mut clock = FrameClock { time: 1.0 };
+        for _ in 0..WARM_UP_FRAMES {
+            end_frame(&mut detector, &clock.next(16.0));
+        }
+
+        // What `sleep 120` does to a frame
+        let stats = FrameStats {
+            static_instance_count: 12,
+            sprite_batch_count: 3,
+            ..Default::default()
+        };
+        let gpu_passes = [PassTiming {
+            label: "Scene Pass",
+            ms: 1.5,
+        }];
+        let profile = clock.next(136.0);
+        assert!(detector.end_frame(&HitchSources {
+            profile: &profile,
+            stats: &stats,
+            gpu_passes: &gpu_passes,
+            pending_loads: 2,
+        }));
+
+        let record = detector.get_latest().unwrap();
+        assert_eq!(record.frame, WARM_UP_FRAMES as u64 + 1);
+        assert!((record.frame_ms - 136.0).abs() < 1e-3);
+        assert!((record.median_ms - 16.0).abs() < 1e-3);
+        assert_eq!(record.stats, stats);
+        assert_eq!(record.gpu_passes, gpu_passes);
+        assert_eq!(record.pending_loads, 2);
+        assert_eq!(record.scopes.len(), 2);
+        assert_eq!(record.scopes[1].parent, Some(0));
+        let (slowest, ms) = record.get_slowest_scope().unwrap();
+        assert_eq!(slowest.name, "Sleep");
+        assert!((ms - 135.0).abs() < 1e-2);
+
+        let json: serde_json::Value = serde_json::from_str(&detector.to_json().unwrap()).unwrap();
+        assert_eq!(json[0]["scopes"][1]["name"], "Sleep");
+        assert_eq!(json[0]["gpu_passes"][0]["label"], "Scene Pass");
+        assert_eq!(json[0]["stats"]["static_instance_count"], 12);
+        assert!(json[0]["log"].is_array());
+    }
+
+    #[test]
+    fn the_oldest_records_are_written_over() {
+        let mut detector = HitchDetector::new();
+        let mut clock = FrameClock { time: 1.0 };
+        for _ in 0..WARM_UP_FRAMES {
+            end_frame(&mut detector, &clock.next(16.0));
+        }
+        for index in 0..MAX_HITCHES + 3 {
+            end_frame(&mut detector, &clock.next(100.0 + index as f64));
+            // Keeps the median down
+            for _ in 0..4 {
+                end_frame(&mut detector, &clock.next(16.0));
+            }
+        }
+        assert_eq!(detector.get_count(), MAX_HITCHES);
+        let frame_ms: Vec<f32> = detector
+            .get_records()
+            .map(|record| record.frame_ms.round())
+            .collect();
+        assert_eq!(frame_ms.first(), Some(&(100.0 + MAX_HITCHES as f32 + 2.0)));
+        assert_eq!(frame_ms.last(), Some(&103.0));
+
+        detector.clear();
+        assert_eq!(detector.get_records().count(), 0);
+    }
+
+    #[test]
+    fn log_lines_are_cut_at_a_character() {
+        assert_eq!(get_prefix("abc", 10), "abc");
+        assert_eq!(get_prefix("abcdef", 3), "abc");
+        // 'é' takes two bytes
+        assert_eq!(get_prefix("aé", 2), "a");
+    }
+}
@@ -0,0 +1,287 @@
+// Reloads converted asset files while the game runs, only natively where the assets are
+// read from a directory (--assets or the repository's). A thread polls the modification
+// times of the .dat files and sends the changed ones over a channel, the main thread
+// reloads them between frames.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, Sender, TryRecvError, channel},
+    time::{Duration, SystemTime},
+};
+
+use shared::math::*;
+
+use crate::{
+    game::Game,
+    level::LevelAssets,
+    renderer::{
+        Renderer, ResourceKind, SpriteAnchor, SpriteSpace, TextAlignment,
+        render_data::TextRenderJob, resources::get_handle,
+    },
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const TOAST_LIFETIME: f32 = 3.0;
+
+// Paths relative to the watched directory with / separators, like the level asset paths
+pub struct AssetWatcher {
+    changes: Receiver<String>,
+}
+
+impl AssetWatcher {
+    pub fn new(directory: &str) -> Self {
+        let (sender, changes) = channel();
+        let directory = PathBuf::from(directory);
+        std::thread::spawn(move || watch(&directory, sender));
+        Self { changes }
+    }
+
+    // Every file that changed since the last poll, once
+    pub fn poll(&self) -> Vec<String> {
+        let mut changed = Vec::new();
+        loop {
+            match self.changes.try_recv() {
+                Ok(path) if !changed.contains(&path) => changed.push(path),
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    log::error!("The asset watcher stopped");
+                    break;
+                }
+            }
+        }
+        changed
+    }
+}
+
+// Runs until the watcher is dropped. Files that exist when it starts are not reported.
+fn watch(directory: &Path, sender: Sender<String>) {
+    let mut modified = HashMap::new();
+    scan(directory, &mut modified);
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        for path in scan(directory, &mut modified) {
+            if sender.send(path).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Updates the modification times, returns the files that are new or were written since
+fn scan(directory: &Path, modified: &mut HashMap<PathBuf, SystemTime>) -> Vec<String> {
+    let mut files = Vec::new();
+    collect_files(directory, &mut files);
+
+    let mut changed = Vec::new();
+    for (path, time) in files {
+        if modified.insert(path.clone(), time) == Some(time) {
+            continue;
+        }
+        if let Ok(relative) = path.strip_prefix(directory) {
+            let components: Vec<_> = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect();
+            changed.push(components.join("/"));
+        }
+    }
+    changed
+}
+
+fn collect_files(directory: &Path, files: &mut Vec<(PathBuf, SystemTime)>) {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            collect_files(&path, files);
+        } else if path.extension().is_some_and(|extension| extension == "dat")
+            && let Ok(time) = metadata.modified()
+        {
+            files.push((path, time));
+        }
+    }
+}
+
+struct Toast {
+    text: String,
+    color: Vec4,
+    age: f32,
+}
+
+// Watches the asset directory and swaps the changed files into the renderer
+pub struct HotReloader {
+    directory: String,
+    watcher: AssetWatcher,
+    manifest: HashMap<String, (String, ResourceKind)>, // Name and kind by path
+    toasts: VecDeque<Toast>,
+}
+
+impl HotReloader {
+    pub fn new(directory: &str, assets: &LevelAssets) -> Self {
+        log::info!("Watching {} for changed assets", directory);
+        Self {
+            directory: directory.trim_end_matches('/').to_string(),
+            watcher: AssetWatcher::new(directory),
+            manifest: get_manifest(assets),
+            toasts: VecDeque::new(),
+        }
+    }
+
+    // Between frames, nothing of the old resources is in use anymore
+    pub fn update(&mut self, dt: f32, renderer: &mut Renderer, game: &mut Game) {
+        for toast in self.toasts.iter_mut() {
+            toast.age += dt;
+        }
+        self.toasts.retain(|toast| toast.age < TOAST_LIFETIME);
+
+        for path in self.watcher.poll() {
+            let Some((name, kind)) = self.resolve(&path, renderer) else {
+                log::warn!("Changed asset {} is not loaded, skipped", path);
+                continue;
+            };
+
+            let location = format!("{}/{}", self.directory, path);
+            let result = std::fs::read(&location)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| renderer.reload_resource(&name, kind, &bytes));
+            match result {
+                Ok(handle) => {
+                    if kind == ResourceKind::SkeletalMesh {
+                        game.on_skeletal_mesh_reloaded(renderer, handle);
+                    }
+                    log::info!("Reloaded {} {} from {}", kind.get_name(), name, path);
+                    self.push_toast(format!("Reloaded {}", name), Vec4::new(0.6, 1.0, 0.6, 1.0));
+                }
+                Err(e) => {
+                    log::error!("Failed to reload {}: {:#}", path, e);
+                    self.push_toast(
+                        format!("Failed to reload {}", name),
+                        Vec4::new(1.0, 0.3, 0.3, 1.0),
+                    );
+                }
+            }
+        }
+    }
+
+    // By the level manifest, otherwise a loaded resource named like the file
+    fn resolve(&self, path: &str, renderer: &Renderer) -> Option<(String, ResourceKind)> {
+        if let Some(entry) = self.manifest.get(path) {
+            return Some(entry.clone());
+        }
+
+        let name = Path::new(path).file_stem()?.to_str()?;
+        let kind = renderer
+            .get_resource_pool()
+            .get_resource(get_handle(name))?
+            .get_kind();
+        Some((name.to_string(), kind))
+    }
+
+    fn push_toast(&mut self, text: String, color: Vec4) {
+        self.toasts.push_back(Toast {
+            text,
+            color,
+            age: 0.0,
+        });
+    }
+
+    // Bottom left, the newest at the bottom
+    pub fn render(&self, renderer: &mut Renderer) {
+        const LINE_HEIGHT: f32 = 22.0;
+
+        for (index, toast) in self.toasts.iter().rev().enumerate() {
+            let alpha = (TOAST_LIFETIME - toast.age).clamp(0.0, 1.0);
+            renderer.submit(&TextRenderJob {
+                font_atlas: get_handle("DebugFont"),
+                font_material: get_handle("DebugFontMaterial"),
+                text: toast.text.as_str().into(),
+                position: Vec2::new(10.0, -10.0 - index as f32 * LINE_HEIGHT),
+                size: 18.0,
+                color: toast.color.with_w(alpha),
+                layer: 0,
+                anchor: SpriteAnchor::BottomLeft,
+                space: SpriteSpace::Absolute,
+                alignment: TextAlignment::Left,
+            });
+        }
+    }
+}
+
+fn get_manifest(assets: &LevelAssets) -> HashMap<String, (String, ResourceKind)> {
+    let groups = [
+        (&assets.textures, ResourceKind::Texture),
+        (&assets.meshes, ResourceKind::StaticMesh),
+        (&assets.skeletal_meshes, ResourceKind::SkeletalMesh),
+        (&assets.animations, ResourceKind::Animation),
+    ];
+
+    let mut manifest = HashMap::new();
+    for (descs, kind) in groups {
+        for desc in descs {
+            manifest.insert(desc.path.clone(), (desc.name.clone(), kind));
+        }
+    }
+    for font in &assets.fonts {
+        manifest.insert(font.path.clone(), (font.name.clone(), ResourceKind::Font));
+    }
+    manifest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_reports_new_and_written_dat_files() {
+        let directory = std::env::temp_dir().join(format!("hot_reload_{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("models")).unwrap();
+        std::fs::write(directory.join("grid.dat"), [1]).unwrap();
+        std::fs::write(directory.join("notes.txt"), [1]).unwrap();
+
+        let mut modified = HashMap::new();
+        assert_eq!(scan(&directory, &mut modified), ["grid.dat"]);
+        assert!(scan(&directory, &mut modified).is_empty());
+
+        // Written again a second later, as seen by the file system
+        let file = std::fs::File::options()
+            .write(true)
+            .open(directory.join("grid.dat"))
+            .unwrap();
+        let time = modified[&directory.join("grid.dat")] + Duration::from_secs(1);
+        file.set_modified(time).unwrap();
+        std::fs::write(directory.join("models/cube.dat"), [2]).unwrap();
+
+        let mut changed = scan(&directory, &mut modified);
+        changed.sort();
+        assert_eq!(changed, ["grid.dat", "models/cube.dat"]);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn manifest_maps_paths_to_resources() {
+        let level = crate::level::Level::load(include_bytes!("../../assets/levels/default.json"))
+            .expect("Failed to load the default level");
+        let manifest = get_manifest(&level.assets);
+
+        assert_eq!(
+            manifest.get("textures/grid.dat"),
+            Some(&("GridTexture".to_string(), ResourceKind::Texture))
+        );
+        assert_eq!(
+            manifest.get("champions/brute/Brute.dat"),
+            Some(&("Brute".to_string(), ResourceKind::SkeletalMesh))
+        );
+        assert_eq!(
+            manifest.get("ui/fonts/poppins_font.dat"),
+            Some(&("DefaultFont".to_string(), ResourceKind::Font))
+        );
+    }
+}
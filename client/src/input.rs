@@ -9,22 +9,135 @@ pub enum InputAction {
     W,
     E,
     R,
+    Stop, // S, drops the buffered orders
 
     SwitchCameraMode,
     CameraFollow,
+    ToggleLightDebug,
+    CycleAntialiasing,
+    ToggleResourceBrowser,
+    DebugUp,
+    DebugDown,
+    DebugSelect,
+    ToggleInspector,
+    ToggleLatencyFlash,
+    ToggleDebugCamera,
+    FlyForward,
+    FlyBack,
+    FlyLeft,
+    FlyRight,
+    FlyUp,
+    FlyDown,
+    FlyFast,
+    FlySlow,
+    TeleportCamera, // Moves the gameplay camera to where the debug camera looks
+    ToggleSoftwareCursor,
+    AddToSelection, // Shift, selecting keeps what is already selected
+    CycleRenderScale,
+    SaveGame,
+    LoadGame,
+    ToggleSafeAreaDebug,
+    ToggleEditor,
+    CycleGizmoMode, // Between moving and rotating the selected entity
+    Snap,           // Ctrl, gizmo drags snap to the grid and to angle steps
+    Undo,           // With Snap, Ctrl+Z. With Shift too it redoes.
+    CycleDebugView,
+    ToggleChunkDebug, // The loading state of the streamed chunks
+    ToggleProfiler,   // The scopes of the last frame as bars
+    ShowStats,        // Held, the session and all time stats of the player
 }
 
 impl InputAction {
-    pub fn get_value(self) -> u32 {
-        1 << (self as u32)
+    // Bits of a u64, so there can be 64 actions
+    pub fn get_value(self) -> u64 {
+        1 << (self as u64)
+    }
+}
+
+// How far back the mouse path is kept, in seconds
+pub const MOUSE_HISTORY_DURATION: f64 = 0.2;
+// Fixed, so a fast mouse doesn't grow it. Positions closer together than the interval
+// replace the newest one, which keeps the whole duration in the buffer.
+const MOUSE_HISTORY_SIZE: usize = 64;
+const MOUSE_SAMPLE_INTERVAL: f64 = MOUSE_HISTORY_DURATION / (MOUSE_HISTORY_SIZE - 8) as f64;
+// Flicks are judged on the path of this last part of the history
+const FLICK_DURATION: f64 = 0.1;
+
+#[derive(Clone, Copy, Default)]
+struct MouseSample {
+    time: f64,
+    position: Vec2,
+}
+
+// The recent positions of the cursor, oldest first, for aiming with a smoothed cursor
+struct MouseHistory {
+    samples: [MouseSample; MOUSE_HISTORY_SIZE],
+    start: usize,
+    len: usize,
+}
+
+impl MouseHistory {
+    fn new() -> Self {
+        Self {
+            samples: [MouseSample::default(); MOUSE_HISTORY_SIZE],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, sample: MouseSample) {
+        if let Some(last) = self.iter().last()
+            && sample.time - last.time < MOUSE_SAMPLE_INTERVAL
+        {
+            let index = (self.start + self.len - 1) % MOUSE_HISTORY_SIZE;
+            self.samples[index].position = sample.position;
+            return;
+        }
+
+        if self.len == MOUSE_HISTORY_SIZE {
+            self.start = (self.start + 1) % MOUSE_HISTORY_SIZE;
+            self.len -= 1;
+        }
+        self.samples[(self.start + self.len) % MOUSE_HISTORY_SIZE] = sample;
+        self.len += 1;
+    }
+
+    // The oldest ones, except the last one from before the duration, which still tells
+    // where the cursor was when it began
+    fn forget_before(&mut self, time: f64) {
+        while self.len > 1 && self.samples[(self.start + 1) % MOUSE_HISTORY_SIZE].time <= time {
+            self.start = (self.start + 1) % MOUSE_HISTORY_SIZE;
+            self.len -= 1;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = MouseSample> + '_ {
+        (0..self.len).map(|index| self.samples[(self.start + index) % MOUSE_HISTORY_SIZE])
+    }
+
+    // Where the cursor was at the time, None before the history
+    fn get_position_at(&self, time: f64) -> Option<Vec2> {
+        self.iter()
+            .rev()
+            .find(|sample| sample.time <= time)
+            .map(|sample| sample.position)
     }
 }
 
 pub struct InputState {
-    state: u32,
-    pressed_events: u32,
-    released_events: u32,
+    state: u64,
+    pressed_events: u64,
+    released_events: u64,
     mouse_position: Vec2,
+    mouse_delta: Vec2, // Raw mouse movement since the last reset, in pixels
+    ui_captured: bool, // A debug UI is under the cursor, clicks are not meant for the game
+    time: f64,         // Of the current frame, in seconds
+    mouse_history: MouseHistory,
 }
 
 impl InputState {
@@ -34,6 +147,10 @@ impl InputState {
             pressed_events: 0,
             released_events: 0,
             mouse_position: Vec2::ZERO,
+            mouse_delta: Vec2::ZERO,
+            ui_captured: false,
+            time: 0.0,
+            mouse_history: MouseHistory::new(),
         }
     }
 
@@ -57,10 +174,11 @@ impl InputState {
     pub fn reset(&mut self) {
         self.pressed_events = 0;
         self.released_events = 0;
+        self.mouse_delta = Vec2::ZERO;
     }
 
     pub fn is_pressed(&self, action: InputAction) -> bool {
-        (self.pressed_events & action.get_value()) != 0
+        (self.pressed_events & self.get_mask(action)) != 0
     }
 
     #[allow(dead_code)]
@@ -69,14 +187,266 @@ impl InputState {
     }
 
     pub fn is_down(&self, action: InputAction) -> bool {
-        (self.state & action.get_value()) != 0
+        (self.state & self.get_mask(action)) != 0
+    }
+
+    #[allow(dead_code)]
+    pub fn set_ui_captured(&mut self, captured: bool) {
+        self.ui_captured = captured;
     }
 
+    // Mouse buttons read as up while a debug UI has the pointer
+    fn get_mask(&self, action: InputAction) -> u64 {
+        match action {
+            InputAction::LeftClick | InputAction::RightClick if self.ui_captured => 0,
+            _ => action.get_value(),
+        }
+    }
+
+    // Before the frame reads the mouse, the helpers below look back from it
+    pub fn set_time(&mut self, time: f64) {
+        self.time = time;
+        self.mouse_history
+            .forget_before(time - MOUSE_HISTORY_DURATION);
+    }
+
+    // At the time of the frame, see set_mouse_position_at for the time of the event
+    #[allow(dead_code)]
     pub fn set_mouse_position(&mut self, position: Vec2) {
+        self.set_mouse_position_at(position, self.time);
+    }
+
+    pub fn set_mouse_position_at(&mut self, position: Vec2, time: f64) {
         self.mouse_position = position;
+        self.mouse_history.push(MouseSample { time, position });
+    }
+
+    // E.g. when the window loses focus, the path from before isn't one movement with what
+    // comes after
+    pub fn clear_mouse_history(&mut self) {
+        self.mouse_history.clear();
+    }
+
+    // The average over the last seconds, weighted by how long the cursor was at each
+    // position. Steadies aiming against jitter, at the cost of some lag.
+    #[allow(dead_code)]
+    pub fn get_smoothed_mouse_position(&self, window: f32) -> Vec2 {
+        let start = self.time - window.max(0.0) as f64;
+        let mut sum = Vec2::ZERO;
+        let mut duration = 0.0;
+        let mut samples = self.mouse_history.iter().peekable();
+        while let Some(sample) = samples.next() {
+            let end = samples.peek().map_or(self.time, |next| next.time);
+            let held = end.min(self.time) - sample.time.max(start);
+            if held > 0.0 {
+                sum += sample.position * held as f32;
+                duration += held;
+            }
+        }
+
+        if duration > 0.0 {
+            sum / duration as f32
+        } else {
+            self.mouse_position
+        }
+    }
+
+    // Per second, over the part of the history a flick is judged on. Zero once the cursor
+    // stopped for that long.
+    #[allow(dead_code)]
+    pub fn get_mouse_velocity(&self) -> Vec2 {
+        let start = self.time - FLICK_DURATION;
+        match self.mouse_history.get_position_at(start) {
+            Some(position) => (self.mouse_position - position) / FLICK_DURATION as f32,
+            None => {
+                // Not that much history yet, from the first position on
+                let Some(first) = self.mouse_history.iter().next() else {
+                    return Vec2::ZERO;
+                };
+                let elapsed = self.time - first.time;
+                if elapsed <= 0.0 {
+                    return Vec2::ZERO;
+                }
+                (self.mouse_position - first.position) / elapsed as f32
+            }
+        }
+    }
+
+    // Whether the cursor just moved fast and straight, e.g. to aim a dash. Every step of the
+    // path has to stay within the tolerance, in radians, of the direction of the whole flick.
+    #[allow(dead_code)]
+    pub fn was_flick(&self, direction_tolerance: f32, min_speed: f32) -> bool {
+        let velocity = self.get_mouse_velocity();
+        if velocity.length() < min_speed {
+            return false;
+        }
+
+        let direction = velocity.normalize();
+        let min_cos = direction_tolerance.cos();
+        let start = self.time - FLICK_DURATION;
+        let mut previous = self.mouse_history.get_position_at(start);
+        for sample in self
+            .mouse_history
+            .iter()
+            .filter(|sample| sample.time > start)
+        {
+            if let Some(previous) = previous {
+                let step = sample.position - previous;
+                if step != Vec2::ZERO && step.normalize().dot(direction) < min_cos {
+                    return false;
+                }
+            }
+            previous = Some(sample.position);
+        }
+        true
     }
 
     pub fn get_mouse_position(&self) -> Vec2 {
         self.mouse_position
     }
+
+    // Keeps coming while the cursor is grabbed and can't move
+    pub fn add_mouse_delta(&mut self, delta: Vec2) {
+        self.mouse_delta += delta;
+    }
+
+    pub fn get_mouse_delta(&self) -> Vec2 {
+        self.mouse_delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clicks_on_a_debug_ui_do_not_reach_the_game() {
+        let mut input = InputState::new();
+        input.set_ui_captured(true);
+        input.set_action(InputAction::RightClick, true);
+        input.set_action(InputAction::E, true);
+        assert!(!input.is_pressed(InputAction::RightClick));
+        assert!(!input.is_down(InputAction::RightClick));
+        assert!(input.is_pressed(InputAction::E));
+
+        input.reset();
+        input.set_ui_captured(false);
+        input.set_action(InputAction::RightClick, false);
+        input.set_action(InputAction::RightClick, true);
+        assert!(input.is_pressed(InputAction::RightClick));
+    }
+
+    // Feeds positions at a fixed rate from the start, at the times they happen
+    fn feed(input: &mut InputState, start: f64, path: impl Iterator<Item = Vec2>) {
+        for (index, position) in path.enumerate() {
+            let time = start + index as f64 / 120.0;
+            input.set_time(time);
+            input.set_mouse_position_at(position, time);
+        }
+    }
+
+    #[test]
+    fn straight_drag_lags_behind_at_its_speed() {
+        let mut input = InputState::new();
+        // 0.5 screens per second to the right, for a second
+        feed(
+            &mut input,
+            10.0,
+            (0..=120).map(|index| Vec2::new(index as f32 / 240.0, 0.5)),
+        );
+
+        let velocity = input.get_mouse_velocity();
+        assert!(
+            (velocity - Vec2::new(0.5, 0.0)).length() < 1e-3,
+            "{}",
+            velocity
+        );
+        // Held positions over the last 0.1 seconds average to about half of it back
+        let smoothed = input.get_smoothed_mouse_position(0.1);
+        assert!((smoothed.x - (0.5 - 0.025)).abs() < 0.005, "{}", smoothed);
+        assert!((smoothed.y - 0.5).abs() < 1e-5);
+        assert_eq!(
+            input.get_smoothed_mouse_position(0.0),
+            input.get_mouse_position()
+        );
+
+        // Slow and straight isn't a flick
+        assert!(!input.was_flick(0.3, 2.0));
+        assert!(input.was_flick(0.3, 0.4));
+
+        // Once it stops, nothing is moving any more
+        input.set_time(11.5);
+        assert_eq!(input.get_mouse_velocity(), Vec2::ZERO);
+        assert_eq!(input.get_smoothed_mouse_position(0.1), Vec2::new(0.5, 0.5));
+    }
+
+    #[test]
+    fn jittery_hover_smooths_to_its_center() {
+        let mut input = InputState::new();
+        let center = Vec2::new(0.3, 0.6);
+        feed(
+            &mut input,
+            0.0,
+            (0..60).map(|index| {
+                let side = if index % 2 == 0 { 1.0 } else { -1.0 };
+                center + Vec2::new(0.01, -0.005) * side
+            }),
+        );
+
+        let smoothed = input.get_smoothed_mouse_position(MOUSE_HISTORY_DURATION as f32);
+        assert!((smoothed - center).length() < 0.002, "{}", smoothed);
+        assert!(input.get_mouse_velocity().length() < 0.3);
+        assert!(!input.was_flick(0.5, 1.0));
+    }
+
+    #[test]
+    fn fast_flick_is_detected_until_focus_is_lost() {
+        let mut input = InputState::new();
+        // Resting, then 4 screens per second diagonally for a tenth of a second
+        feed(&mut input, 0.0, (0..30).map(|_| Vec2::splat(0.2)));
+        let direction = Vec2::new(1.0, 1.0).normalize();
+        feed(
+            &mut input,
+            30.0 / 120.0,
+            (1..=12).map(|index| Vec2::splat(0.2) + direction * index as f32 * 4.0 / 120.0),
+        );
+
+        let velocity = input.get_mouse_velocity();
+        assert!(velocity.normalize().dot(direction) > 0.99, "{}", velocity);
+        assert!(velocity.length() > 3.0, "{}", velocity);
+        assert!(input.was_flick(0.3, 3.0));
+        assert!(!input.was_flick(0.3, 10.0));
+
+        // A hook at the end breaks the straight line
+        input.set_mouse_position_at(
+            input.get_mouse_position() + Vec2::new(0.2, -0.3),
+            input.time,
+        );
+        assert!(!input.was_flick(0.3, 1.0));
+
+        input.clear_mouse_history();
+        assert_eq!(input.get_mouse_velocity(), Vec2::ZERO);
+        assert!(!input.was_flick(0.3, 1.0));
+        assert_eq!(
+            input.get_smoothed_mouse_position(0.1),
+            input.get_mouse_position()
+        );
+    }
+
+    #[test]
+    fn history_keeps_its_duration_in_a_fixed_buffer() {
+        let mut input = InputState::new();
+        // A 1000 Hz mouse for two seconds
+        for index in 0..2000 {
+            let time = index as f64 / 1000.0;
+            input.set_time(time);
+            input.set_mouse_position_at(Vec2::new(time as f32, 0.0), time);
+        }
+
+        let samples: Vec<_> = input.mouse_history.iter().collect();
+        assert!(samples.len() <= MOUSE_HISTORY_SIZE);
+        assert!(input.time - samples[0].time >= MOUSE_HISTORY_DURATION);
+        assert!(input.time - samples[1].time < MOUSE_HISTORY_DURATION);
+        assert!(samples.windows(2).all(|pair| pair[0].time < pair[1].time));
+    }
 }
@@ -0,0 +1,777 @@
+// An egui debug inspector over the game (F4), only built with the inspector feature.
+// Panels edit the camera and lighting live, list what the renderer holds, find materials
+// that split batches, show the hitch records and switch the skinning debug views of one
+// entity.
+
+use shared::{math::*, physics::PhysicsWorld};
+use winit::{event::WindowEvent, window::Window};
+
+use crate::{
+    app::PerformanceMetrics,
+    components::Entity,
+    game::Game,
+    hitch::HitchDetector,
+    input::InputState,
+    renderer::{
+        DebugView, Fog, RenderDevice, Renderer, batch_diagnostics::DuplicateMaterials,
+        device::MAX_FRAMES_IN_FLIGHT, render_data::WeightDebugView,
+    },
+    resource_browser::format_size,
+};
+
+// What the last update produced, painted by the next render
+struct InspectorFrame {
+    primitives: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+    pixels_per_point: f32,
+}
+
+pub struct Inspector {
+    context: egui::Context,
+    winit_state: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    visible: bool,
+    frame: Option<InspectorFrame>,
+    skinning_entity: Option<Entity>, // Shown in the skinning panel
+    duplicate_materials: Option<Vec<DuplicateMaterials>>, // Of the last check in the batch panel
+}
+
+impl Inspector {
+    pub fn new(window: &Window, render_device: &RenderDevice) -> Self {
+        let context = egui::Context::default();
+        let winit_state = egui_winit::State::new(
+            context.clone(),
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            None,
+        );
+        let renderer = egui_wgpu::Renderer::new(
+            &render_device.device,
+            render_device.get_surface_format(),
+            egui_wgpu::RendererOptions::default(),
+        );
+
+        Self {
+            context,
+            winit_state,
+            renderer,
+            visible: false,
+            frame: None,
+            skinning_entity: None,
+            duplicate_materials: None,
+        }
+    }
+
+    pub fn toggle(&mut self, renderer: &mut Renderer) {
+        self.visible = !self.visible;
+        renderer.set_batch_capture_enabled(self.visible);
+    }
+
+    // True when egui used the event and the game should not see it
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        if !self.visible {
+            return false;
+        }
+        self.winit_state.on_window_event(window, event).consumed
+    }
+
+    pub fn update_input_capture(&self, input_state: &mut InputState) {
+        let captured = self.visible
+            && (self.context.is_pointer_over_area() || self.context.wants_pointer_input());
+        input_state.set_ui_captured(captured);
+    }
+
+    pub fn update(
+        &mut self,
+        window: &Window,
+        renderer: &mut Renderer,
+        game: &mut Game,
+        physics_world: &PhysicsWorld,
+        metrics: &mut PerformanceMetrics,
+    ) {
+        if !self.visible {
+            self.frame = None;
+            return;
+        }
+
+        let input = self.winit_state.take_egui_input(window);
+        let output = self.context.run(input, |context| {
+            show_frame_panel(context, renderer, game, physics_world, metrics);
+            show_camera_panel(context, game);
+            show_time_panel(context, game);
+            show_lighting_panel(context, renderer);
+            show_render_scale_panel(context, renderer);
+            show_frame_pacing_panel(context, renderer);
+            show_culling_panel(context, renderer);
+            show_overlay_panel(context, renderer, game);
+            show_resource_panel(context, renderer);
+            show_batch_panel(context, renderer, &mut self.duplicate_materials);
+            show_hitch_panel(context, &mut metrics.hitches);
+            show_skinning_panel(context, game, &mut self.skinning_entity);
+        });
+        self.winit_state
+            .handle_platform_output(window, output.platform_output);
+
+        self.frame = Some(InspectorFrame {
+            primitives: self
+                .context
+                .tessellate(output.shapes, output.pixels_per_point),
+            textures_delta: output.textures_delta,
+            pixels_per_point: output.pixels_per_point,
+        });
+    }
+
+    // Draws over the finished frame, see Renderer::render_with_overlay
+    pub fn paint(&mut self, render_device: &RenderDevice, view: &wgpu::TextureView) {
+        let Some(frame) = self.frame.take() else {
+            return;
+        };
+        let device = &render_device.device;
+        let queue = &render_device.queue;
+
+        for (id, delta) in &frame.textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+
+        let screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [render_device.config.width, render_device.config.height],
+            pixels_per_point: frame.pixels_per_point,
+        };
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Inspector Encoder"),
+        });
+        let mut commands =
+            self.renderer
+                .update_buffers(device, queue, &mut encoder, &frame.primitives, &screen);
+        {
+            let mut render_pass = encoder
+                .begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Inspector Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        depth_slice: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                })
+                .forget_lifetime();
+            self.renderer
+                .render(&mut render_pass, &frame.primitives, &screen);
+        }
+        commands.push(encoder.finish());
+        queue.submit(commands);
+
+        for id in &frame.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
+
+fn show_frame_panel(
+    context: &egui::Context,
+    renderer: &Renderer,
+    game: &Game,
+    physics_world: &PhysicsWorld,
+    metrics: &PerformanceMetrics,
+) {
+    egui::Window::new("Frame").show(context, |ui| {
+        let stats = renderer.get_frame_stats();
+        let physics = physics_world.last_step_stats();
+        let bake = game.get_bake_stats();
+        egui::Grid::new("frame_stats").show(ui, |ui| {
+            let rows = [
+                ("FPS", metrics.avg_fps.to_string()),
+                ("Worst frame", format!("{:.2} ms", metrics.max_ms)),
+                ("Entities", game.get_entity_count().to_string()),
+                ("Physics bodies", physics_world.get_body_count().to_string()),
+                (
+                    "Physics step",
+                    format!(
+                        "{} pairs, {} contacts, {} cells",
+                        physics.pair_count, physics.contact_count, physics.grid_cell_count
+                    ),
+                ),
+                (
+                    "Solver",
+                    format!(
+                        "{}/{} iterations, {:.2} left",
+                        physics.iteration_count,
+                        physics_world.get_iterations(),
+                        physics.max_penetration_after_solve
+                    ),
+                ),
+                (
+                    "Static",
+                    format!(
+                        "{} batches, {} instances",
+                        stats.static_batch_count, stats.static_instance_count
+                    ),
+                ),
+                (
+                    "Persistent",
+                    format!(
+                        "{} batches, {} instances",
+                        stats.persistent_batch_count, stats.persistent_instance_count
+                    ),
+                ),
+                (
+                    "Skeletal",
+                    format!(
+                        "{} batches, {} instances, {} bones",
+                        stats.skeletal_batch_count, stats.skeletal_instance_count, stats.bone_count
+                    ),
+                ),
+                (
+                    "Sprite",
+                    format!(
+                        "{} batches, {} instances",
+                        stats.sprite_batch_count, stats.sprite_instance_count
+                    ),
+                ),
+                ("Glyphs", stats.text_glyph_count.to_string()),
+                ("Debug lines", stats.debug_line_count.to_string()),
+                (
+                    "Instances per LOD",
+                    format!("{:?}", stats.lod_instance_counts),
+                ),
+                (
+                    "Baked props",
+                    format!(
+                        "{} into {} instances, {} KiB",
+                        bake.instances_before,
+                        bake.instances_after,
+                        bake.baked_bytes / 1024
+                    ),
+                ),
+            ];
+            for (name, value) in rows {
+                ui.label(name);
+                ui.label(value);
+                ui.end_row();
+            }
+        });
+
+        // Timestamp queries are an optional feature, the adapter might not have them
+        ui.separator();
+        if !renderer.get_render_device().supports_timestamps() {
+            ui.label("GPU timings unavailable");
+            return;
+        }
+        let timings = renderer.get_gpu_pass_timings();
+        egui::Grid::new("gpu_timings").show(ui, |ui| {
+            for timing in timings.iter() {
+                ui.label(timing.label);
+                ui.label(format!("{:.2} ms", timing.ms));
+                ui.end_row();
+            }
+            ui.label("GPU total");
+            let total: f32 = timings.iter().map(|timing| timing.ms).sum();
+            ui.label(format!("{:.2} ms", total));
+            ui.end_row();
+        });
+    });
+}
+
+fn show_camera_panel(context: &egui::Context, game: &mut Game) {
+    egui::Window::new("Camera").show(context, |ui| {
+        let mut settings = game.get_camera_settings();
+        let mut changed = false;
+        changed |= ui
+            .add(egui::Slider::new(&mut settings.radius, 100.0..=3000.0).text("Radius"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut settings.angle, 10.0..=89.0).text("Angle"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut settings.fov, 10.0..=90.0).text("Field of view"))
+            .changed();
+        if changed {
+            game.set_camera_settings(settings);
+        }
+
+        let position = game.get_camera_position();
+        ui.label(format!(
+            "Position {:.0} {:.0} {:.0}",
+            position.x, position.y, position.z
+        ));
+    });
+}
+
+fn show_time_panel(context: &egui::Context, game: &mut Game) {
+    egui::Window::new("Time").show(context, |ui| {
+        let time = game.get_time_mut();
+        let mut scale = time.get_base_scale();
+        if ui
+            .add(egui::Slider::new(&mut scale, 0.0..=2.0).text("Time scale"))
+            .changed()
+        {
+            time.set_base_scale(scale);
+        }
+        ui.label(format!("Current scale {:.2}", time.get_scale()));
+    });
+}
+
+fn show_lighting_panel(context: &egui::Context, renderer: &mut Renderer) {
+    egui::Window::new("Lighting").show(context, |ui| {
+        ui.heading("Directional");
+        let mut light = *renderer.get_directional_light();
+        let mut changed = edit_vec3(ui, "Direction", &mut light.direction);
+        changed |= edit_color(ui, "Color", &mut light.color);
+        changed |= ui
+            .add(egui::Slider::new(&mut light.intensity, 0.0..=5.0).text("Intensity"))
+            .changed();
+        changed |= ui.checkbox(&mut light.shadows_enabled, "Shadows").changed();
+        if changed {
+            renderer.set_directional_light(light);
+        }
+
+        ui.heading("Ambient");
+        let mut ambient = *renderer.get_ambient_light();
+        let mut changed = edit_color(ui, "Top", &mut ambient.top_color);
+        changed |= edit_color(ui, "Bottom", &mut ambient.bottom_color);
+        changed |= ui
+            .add(egui::Slider::new(&mut ambient.intensity, 0.0..=2.0).text("Intensity"))
+            .changed();
+        if changed {
+            renderer.set_ambient_light(ambient);
+        }
+
+        ui.heading("Fog");
+        let mut fog = renderer.get_fog().copied();
+        let mut enabled = fog.is_some();
+        let mut changed = ui.checkbox(&mut enabled, "Enabled").changed();
+        if enabled {
+            let fog = fog.get_or_insert(Fog {
+                color: Vec3::splat(0.5),
+                start: 1000.0,
+                end: 3000.0,
+            });
+            changed |= edit_color(ui, "Color", &mut fog.color);
+            changed |= ui
+                .add(egui::Slider::new(&mut fog.start, 0.0..=5000.0).text("Start"))
+                .changed();
+            changed |= ui
+                .add(egui::Slider::new(&mut fog.end, 0.0..=5000.0).text("End"))
+                .changed();
+        }
+        if changed {
+            renderer.set_fog(fog.filter(|_| enabled));
+        }
+
+        ui.heading("Wind");
+        let mut wind = *renderer.get_wind();
+        let mut angle = wind.direction.y.atan2(wind.direction.x).to_degrees();
+        let mut changed = ui
+            .add(egui::Slider::new(&mut angle, -180.0..=180.0).text("Direction"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut wind.strength, 0.0..=20.0).text("Strength"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut wind.gust_strength, 0.0..=2.0).text("Gusts"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut wind.gust_period, 0.5..=20.0).text("Gust period"))
+            .changed();
+        if changed {
+            wind.direction = Vec2::from_angle(angle.to_radians());
+            renderer.set_wind(wind);
+        }
+
+        ui.heading("Silhouettes");
+        let mut xray = renderer.is_xray_enabled();
+        if ui.checkbox(&mut xray, "Units behind walls").changed() {
+            renderer.set_xray_enabled(xray);
+        }
+
+        ui.heading("Debug view");
+        let mut view = renderer.get_debug_view();
+        egui::ComboBox::from_label("View")
+            .selected_text(view.get_name())
+            .show_ui(ui, |ui| {
+                for option in DebugView::ALL {
+                    ui.selectable_value(&mut view, option, option.get_name());
+                }
+            });
+        if view != renderer.get_debug_view() {
+            renderer.set_debug_view(view);
+        }
+    });
+}
+
+fn show_render_scale_panel(context: &egui::Context, renderer: &mut Renderer) {
+    egui::Window::new("Render Scale").show(context, |ui| {
+        let mut scale = renderer.get_render_scale();
+        if ui
+            .add(egui::Slider::new(&mut scale, Renderer::MIN_RENDER_SCALE..=1.0).text("Scale"))
+            .changed()
+        {
+            renderer.set_render_scale(scale);
+        }
+        let size = renderer.get_scene_size();
+        ui.label(format!("Scene {}x{}, UI at full size", size.x, size.y));
+    });
+}
+
+fn show_frame_pacing_panel(context: &egui::Context, renderer: &mut Renderer) {
+    egui::Window::new("Frame Pacing").show(context, |ui| {
+        let mut count = renderer.get_max_frames_in_flight();
+        if ui
+            .add(egui::Slider::new(&mut count, 1..=MAX_FRAMES_IN_FLIGHT).text("Frames in flight"))
+            .changed()
+        {
+            renderer.set_max_frames_in_flight(count);
+        }
+        let mut low_latency = renderer.is_low_latency();
+        if ui.checkbox(&mut low_latency, "Low latency").changed() {
+            renderer.set_low_latency(low_latency);
+        }
+        ui.label(format!(
+            "CPU wait {:.2} ms",
+            renderer.get_cpu_wait() * 1000.0
+        ));
+    });
+}
+
+fn show_culling_panel(context: &egui::Context, renderer: &mut Renderer) {
+    egui::Window::new("Culling")
+        .default_open(false)
+        .show(context, |ui| {
+            if !renderer.supports_gpu_culling() {
+                ui.label("No compute shaders, culling on the CPU");
+                return;
+            }
+            let mut gpu_culling = renderer.is_gpu_culling();
+            if ui.checkbox(&mut gpu_culling, "GPU culling").changed() {
+                renderer.set_gpu_culling(gpu_culling);
+            }
+            let mut validation = renderer.is_gpu_culling_validation();
+            if ui
+                .checkbox(&mut validation, "Validate against the CPU")
+                .changed()
+            {
+                renderer.set_gpu_culling_validation(validation);
+            }
+            for count in renderer.get_gpu_cull_counts() {
+                let status = if count.gpu == count.cpu {
+                    ""
+                } else {
+                    ", mismatch"
+                };
+                ui.label(format!(
+                    "{:#x}: GPU {}, CPU {}{}",
+                    count.instances, count.gpu, count.cpu, status
+                ));
+            }
+        });
+}
+
+// The world grid and the measure tool, neither is in screenshots
+fn show_overlay_panel(context: &egui::Context, renderer: &mut Renderer, game: &mut Game) {
+    egui::Window::new("Overlays")
+        .default_open(false)
+        .show(context, |ui| {
+            let grid = renderer.get_world_grid();
+            let mut enabled = grid.is_some();
+            if ui.checkbox(&mut enabled, "World grid").changed() {
+                renderer.set_world_grid(enabled.then(|| grid.unwrap_or_default()));
+            }
+            if let Some(mut grid) = grid {
+                let mut changed = false;
+                changed |= ui
+                    .add(
+                        egui::Slider::new(&mut grid.spacing, 0.1..=10.0)
+                            .logarithmic(true)
+                            .text("Spacing"),
+                    )
+                    .changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut grid.major_every, 1..=20).text("Major every"))
+                    .changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut grid.fade_distance, 10.0..=500.0).text("Fade"))
+                    .changed();
+                if changed {
+                    renderer.set_world_grid(Some(grid));
+                }
+            }
+
+            let tool = game.get_measure_tool_mut();
+            let mut measuring = tool.is_enabled();
+            if ui.checkbox(&mut measuring, "Measure").changed() {
+                tool.set_enabled(measuring);
+            }
+            if measuring {
+                match tool.get_length() {
+                    Some(length) => ui.label(format!("Length {:.2}", length)),
+                    None => ui.label("Click two points on the ground"),
+                };
+                if ui.button("Clear").clicked() {
+                    tool.clear();
+                }
+            }
+        });
+}
+
+fn show_resource_panel(context: &egui::Context, renderer: &Renderer) {
+    egui::Window::new("Resources")
+        .default_open(false)
+        .show(context, |ui| {
+            let resource_pool = renderer.get_resource_pool();
+            let mut resources: Vec<_> = resource_pool
+                .iter()
+                .map(|(handle, kind)| {
+                    let name = match resource_pool.get_name(handle) {
+                        Some(name) => name.to_string(),
+                        None => format!("{:016x}", handle),
+                    };
+                    let size = resource_pool
+                        .get_resource(handle)
+                        .map_or(0, |resource| resource.get_gpu_size());
+                    (kind, name, size)
+                })
+                .collect();
+            resources.sort();
+
+            let total_size = resources.iter().map(|(_, _, size)| size).sum();
+            ui.label(format!(
+                "{} resources, {}",
+                resources.len(),
+                format_size(total_size)
+            ));
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("resources").striped(true).show(ui, |ui| {
+                    for (kind, name, size) in &resources {
+                        ui.label(name);
+                        ui.label(kind.get_name());
+                        ui.label(format_size(*size));
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+}
+
+fn show_batch_panel(
+    context: &egui::Context,
+    renderer: &mut Renderer,
+    duplicate_materials: &mut Option<Vec<DuplicateMaterials>>,
+) {
+    egui::Window::new("Batches")
+        .default_open(false)
+        .show(context, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Find duplicate materials").clicked() {
+                    *duplicate_materials = Some(renderer.get_duplicate_materials());
+                }
+                if ui.button("Merge duplicates").clicked() {
+                    let count = renderer.dedupe_materials();
+                    log::info!("Merged {} duplicate materials.", count);
+                    *duplicate_materials = Some(renderer.get_duplicate_materials());
+                }
+            });
+            if let Some(duplicates) = duplicate_materials {
+                if duplicates.is_empty() {
+                    ui.label("No duplicate materials");
+                }
+                for duplicate in duplicates.iter() {
+                    ui.label(format!(
+                        "{} split {} batches",
+                        duplicate.names.join(", "),
+                        duplicate.split_batches
+                    ));
+                }
+                ui.separator();
+            }
+
+            let resource_pool = renderer.get_resource_pool();
+            let get_name = |handle| resource_pool.get_name(handle).unwrap_or("?");
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("batches").striped(true).show(ui, |ui| {
+                    for info in renderer.get_captured_batches() {
+                        let batch = &info.batch;
+                        ui.label(info.pass);
+                        ui.label(get_name(batch.material_instance));
+                        ui.label(get_name(batch.mesh));
+                        ui.label(format!("{} instances", batch.instance_range.len()));
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+}
+
+// Newest first, each with the scopes of the frame indented by depth
+fn show_hitch_panel(context: &egui::Context, hitches: &mut HitchDetector) {
+    egui::Window::new("Hitches")
+        .default_open(false)
+        .show(context, |ui| {
+            let mut settings = hitches.get_settings();
+            let mut changed = ui.checkbox(&mut settings.enabled, "Detect").changed();
+            changed |= ui
+                .add(egui::Slider::new(&mut settings.multiple, 1.5..=10.0).text("Times the median"))
+                .changed();
+            changed |= ui
+                .add(egui::Slider::new(&mut settings.min_ms, 0.0..=100.0).text("At least (ms)"))
+                .changed();
+            if changed {
+                hitches.set_settings(settings);
+            }
+            if ui.button("Clear").clicked() {
+                hitches.clear();
+            }
+            if hitches.get_count() == 0 {
+                ui.label("No hitches");
+                return;
+            }
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for record in hitches.get_records() {
+                    egui::CollapsingHeader::new(record.get_summary())
+                        .id_salt(record.frame)
+                        .show(ui, |ui| {
+                            let stats = &record.stats;
+                            ui.label(format!(
+                                "Static {} | Persistent {} | Skeletal {} | Sprites {} in {} batches",
+                                stats.static_instance_count,
+                                stats.persistent_instance_count,
+                                stats.skeletal_instance_count,
+                                stats.sprite_instance_count,
+                                stats.sprite_batch_count
+                            ));
+                            ui.label(format!("{} loads under way", record.pending_loads));
+                            ui.separator();
+                            for scope in &record.scopes {
+                                ui.monospace(format!(
+                                    "{}{} {:.2} ms at {:.2} ms",
+                                    "  ".repeat(scope.depth as usize),
+                                    scope.name,
+                                    scope.ms,
+                                    scope.start_ms
+                                ));
+                            }
+                            ui.separator();
+                            if record.gpu_passes.is_empty() {
+                                ui.label("No GPU pass timings");
+                            }
+                            for pass in &record.gpu_passes {
+                                ui.label(format!("{} {:.2} ms", pass.label, pass.ms));
+                            }
+                            ui.separator();
+                            for line in record.get_log_lines() {
+                                ui.monospace(line);
+                            }
+                        });
+                }
+            });
+        });
+}
+
+fn show_skinning_panel(context: &egui::Context, game: &mut Game, selected: &mut Option<Entity>) {
+    egui::Window::new("Skinning")
+        .default_open(false)
+        .show(context, |ui| {
+            let entities = game.get_skinned_entities();
+            if !entities
+                .iter()
+                .any(|(entity, _)| Some(*entity) == *selected)
+            {
+                *selected = entities.first().map(|(entity, _)| *entity);
+            }
+            let Some(entity) = *selected else {
+                ui.label("No skinned entities");
+                return;
+            };
+
+            egui::ComboBox::from_label("Entity")
+                .selected_text(game.get_name(entity))
+                .show_ui(ui, |ui| {
+                    for (other, _) in &entities {
+                        ui.selectable_value(selected, Some(*other), game.get_name(*other));
+                    }
+                });
+            let Some(entity) = *selected else {
+                return;
+            };
+            let bone_count = entities
+                .iter()
+                .find(|(other, _)| *other == entity)
+                .map_or(0, |(_, bone_count)| *bone_count);
+
+            let mut debug = game.get_skinning_debug(entity);
+            let mut changed = ui.checkbox(&mut debug.skeleton, "Skeleton").changed();
+
+            ui.heading("Weights");
+            let mut selected_bone = match debug.weights {
+                WeightDebugView::Bone(bone) => bone,
+                _ => 0,
+            };
+            ui.horizontal(|ui| {
+                changed |= ui
+                    .radio_value(&mut debug.weights, WeightDebugView::None, "Off")
+                    .changed();
+                changed |= ui
+                    .radio_value(
+                        &mut debug.weights,
+                        WeightDebugView::LargestWeight,
+                        "Largest",
+                    )
+                    .changed();
+                if ui
+                    .radio(matches!(debug.weights, WeightDebugView::Bone(_)), "Bone")
+                    .clicked()
+                {
+                    debug.weights = WeightDebugView::Bone(selected_bone);
+                    changed = true;
+                }
+            });
+            if let WeightDebugView::Bone(_) = debug.weights {
+                let last_bone = bone_count.saturating_sub(1) as u32;
+                if ui
+                    .add(egui::Slider::new(&mut selected_bone, 0..=last_bone).text("Bone"))
+                    .changed()
+                {
+                    debug.weights = WeightDebugView::Bone(selected_bone);
+                    changed = true;
+                }
+            }
+
+            if changed {
+                game.set_skinning_debug(entity, debug);
+            }
+        });
+}
+
+fn edit_color(ui: &mut egui::Ui, label: &str, color: &mut Vec3) -> bool {
+    ui.horizontal(|ui| {
+        let mut rgb = color.to_array();
+        let changed = ui.color_edit_button_rgb(&mut rgb).changed();
+        ui.label(label);
+        *color = Vec3::from_array(rgb);
+        changed
+    })
+    .inner
+}
+
+fn edit_vec3(ui: &mut egui::Ui, label: &str, value: &mut Vec3) -> bool {
+    ui.horizontal(|ui| {
+        let mut changed = false;
+        for component in [&mut value.x, &mut value.y, &mut value.z] {
+            changed |= ui
+                .add(egui::DragValue::new(component).speed(0.01))
+                .changed();
+        }
+        ui.label(label);
+        changed
+    })
+    .inner
+}
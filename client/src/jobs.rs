@@ -0,0 +1,223 @@
+// Fork-join jobs for the fixed update. A system hands its work out in a scope and the scope
+// returns once every job has finished, so jobs can borrow the components of the system. The
+// helpers split the components into disjoint chunks, a job only ever sees its own, so two jobs
+// can't touch the same component. Results come back in the order of the items, the system
+// applies them in that order and the simulation ends up the same as on one thread.
+//
+// At most get_worker_count jobs run at once, the calling thread runs one of them. The threads
+// are std scoped threads started per scope, which keeps borrowing sound without a pool of
+// our own. On wasm there are no threads, the jobs run one after the other as they are spawned.
+
+use std::marker::PhantomData;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+
+// More rarely pays off for the small systems of a tick
+const MAX_WORKER_COUNT: usize = 8;
+// Fewer items than this per job aren't worth a thread
+const MIN_ITEMS_PER_JOB: usize = 64;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_worker_count() -> usize {
+    static WORKER_COUNT: OnceLock<usize> = OnceLock::new();
+    *WORKER_COUNT.get_or_init(|| {
+        std::thread::available_parallelism()
+            .map_or(1, |count| count.get())
+            .min(MAX_WORKER_COUNT)
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn get_worker_count() -> usize {
+    1
+}
+
+#[derive(Clone, Copy)]
+pub struct Scope<'scope, 'env: 'scope> {
+    #[cfg(not(target_arch = "wasm32"))]
+    inner: &'scope std::thread::Scope<'scope, 'env>,
+    _scope: PhantomData<&'scope mut &'env ()>,
+}
+
+impl<'scope> Scope<'scope, '_> {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn spawn<F: FnOnce() + Send + 'scope>(&self, job: F) {
+        self.inner.spawn(job);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn spawn<F: FnOnce() + Send + 'scope>(&self, job: F) {
+        job();
+    }
+}
+
+// Returns once all the jobs spawned in it have finished, a panic in one of them is raised
+// again here
+#[cfg(not(target_arch = "wasm32"))]
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(Scope<'scope, 'env>) -> T,
+{
+    std::thread::scope(|inner| {
+        f(Scope {
+            inner,
+            _scope: PhantomData,
+        })
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(Scope<'scope, 'env>) -> T,
+{
+    f(Scope {
+        _scope: PhantomData,
+    })
+}
+
+// How many items each job gets, all of them when one job is enough
+fn get_chunk_size(item_count: usize) -> usize {
+    let job_count = (item_count / MIN_ITEMS_PER_JOB).clamp(1, get_worker_count());
+    item_count.div_ceil(job_count).max(1)
+}
+
+// Maps the items on the workers, the results are in the order of the items
+pub fn map_mut<T, R, F>(items: &mut [T], f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(&mut T) -> R + Sync,
+{
+    let chunk_size = get_chunk_size(items.len());
+    if chunk_size >= items.len() {
+        return items.iter_mut().map(f).collect();
+    }
+
+    let mut results: Vec<Vec<R>> = items.chunks(chunk_size).map(|_| Vec::new()).collect();
+    let f = &f;
+    scope(|s| {
+        // The first chunk is left for this thread
+        let mut jobs = items.chunks_mut(chunk_size).zip(results.iter_mut());
+        let first = jobs.next();
+        for (chunk, chunk_results) in jobs {
+            s.spawn(move || chunk_results.extend(chunk.iter_mut().map(f)));
+        }
+        if let Some((chunk, chunk_results)) = first {
+            chunk_results.extend(chunk.iter_mut().map(f));
+        }
+    });
+    results.into_iter().flatten().collect()
+}
+
+// Like map_mut for systems that only read
+#[allow(dead_code)]
+pub fn map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let mut items: Vec<&T> = items.iter().collect();
+    map_mut(&mut items, |item| f(item))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use shared::math::*;
+
+    #[test]
+    fn results_are_in_the_order_of_the_items() {
+        let mut items: Vec<u32> = (0..1000).collect();
+        let doubled = map_mut(&mut items, |item| {
+            *item += 1;
+            *item * 2
+        });
+        assert_eq!(doubled, (1..=1000).map(|item| item * 2).collect::<Vec<_>>());
+        // Every item was given to exactly one job
+        assert_eq!(items, (1..=1000).collect::<Vec<_>>());
+
+        // Too few to split
+        assert_eq!(map(&[1, 2, 3], |item| item * 10), vec![10, 20, 30]);
+        assert!(map(&[] as &[u32], |item| *item).is_empty());
+    }
+
+    #[test]
+    fn jobs_borrow_from_the_caller_and_finish_in_the_scope() {
+        let mut halves = [vec![0u32; 100], vec![0u32; 100]];
+        let offset = 5;
+        let [first, second] = &mut halves;
+        scope(|s| {
+            s.spawn(|| first.iter_mut().for_each(|value| *value = offset));
+            s.spawn(|| second.iter_mut().for_each(|value| *value = offset * 2));
+        });
+        assert!(halves[0].iter().all(|value| *value == 5));
+        assert!(halves[1].iter().all(|value| *value == 10));
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_in_jobs_reach_the_caller() {
+        let mut items = vec![0u32; 1000];
+        map_mut(&mut items, |item| {
+            if *item == 0 {
+                panic!("Job failed");
+            }
+        });
+    }
+
+    // The closest other entity for each of 1000, like AI picking targets
+    fn get_closest_targets(positions: &[Vec3], parallel: bool) -> Vec<Option<usize>> {
+        let closest = |(index, position): &(usize, Vec3)| {
+            positions
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| other != index)
+                .min_by(|(_, a), (_, b)| {
+                    a.distance_squared(*position)
+                        .total_cmp(&b.distance_squared(*position))
+                })
+                .map(|(other, _)| other)
+        };
+        let items: Vec<(usize, Vec3)> = positions.iter().copied().enumerate().collect();
+        if parallel {
+            map(&items, closest)
+        } else {
+            items.iter().map(closest).collect()
+        }
+    }
+
+    // cargo test -p client --release jobs -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn target_selection_of_1000_entities_scales_with_the_workers() {
+        let positions: Vec<Vec3> = (0..1000)
+            .map(|index| {
+                let t = index as f32;
+                Vec3::new((t * 0.37).sin() * 500.0, 0.0, (t * 0.71).cos() * 500.0)
+            })
+            .collect();
+
+        const TICKS: u32 = 60;
+        let time = |parallel: bool| {
+            let start = std::time::Instant::now();
+            let mut targets = Vec::new();
+            for _ in 0..TICKS {
+                targets = get_closest_targets(&positions, parallel);
+            }
+            (start.elapsed() / TICKS, targets)
+        };
+        let (sequential, expected) = time(false);
+        let (parallel, targets) = time(true);
+        println!(
+            "{} workers, {:?} per tick on one thread and {:?} on the workers, {:.1}x",
+            get_worker_count(),
+            sequential,
+            parallel,
+            sequential.as_secs_f64() / parallel.as_secs_f64()
+        );
+        assert_eq!(targets, expected);
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+
+use shared::math::*;
+
+use crate::renderer::{
+    Renderer, SpriteAnchor, SpriteSpace, TextAlignment, render_data::TextRenderJob,
+    resources::get_handle,
+};
+
+const MAX_LINES: usize = 5;
+const LINE_LIFETIME: f32 = 6.0;
+const FADE_TIME: f32 = 1.0; // At the end of the lifetime
+const SLIDE_DISTANCE: f32 = 120.0;
+
+struct KillFeedLine {
+    text: String,
+    age: f32,
+}
+
+// The last few deaths as text lines in the top right corner, below the debug metrics.
+// The newest line is at the top, it slides in from the right as the slide goes from 0 to
+// 1 and pushes the older lines down.
+#[derive(Default)]
+pub struct KillFeed {
+    lines: VecDeque<KillFeedLine>,
+    slide: f32, // Set by the game's tweens
+}
+
+impl KillFeed {
+    pub fn push(&mut self, text: String) {
+        if self.lines.len() >= MAX_LINES {
+            self.lines.pop_back();
+        }
+        self.lines.push_front(KillFeedLine { text, age: 0.0 });
+        self.slide = 0.0;
+    }
+
+    pub fn set_slide(&mut self, slide: f32) {
+        self.slide = slide;
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for line in self.lines.iter_mut() {
+            line.age += dt;
+        }
+        self.lines.retain(|line| line.age < LINE_LIFETIME);
+    }
+
+    pub fn render(&self, renderer: &mut Renderer) {
+        const TOP: f32 = 70.0;
+        const LINE_HEIGHT: f32 = 22.0;
+
+        for (index, line) in self.lines.iter().enumerate() {
+            let mut alpha = ((LINE_LIFETIME - line.age) / FADE_TIME).clamp(0.0, 1.0);
+            let mut x = -10.0;
+            if index == 0 {
+                alpha *= self.slide.clamp(0.0, 1.0);
+                x += (1.0 - self.slide) * SLIDE_DISTANCE;
+            }
+            let row = (index as f32 - 1.0 + self.slide).max(0.0);
+            renderer.submit(&TextRenderJob {
+                font_atlas: get_handle("DebugFont"),
+                font_material: get_handle("DebugFontMaterial"),
+                text: line.text.as_str().into(),
+                position: Vec2::new(x, TOP + row * LINE_HEIGHT),
+                size: 18.0,
+                color: Vec4::new(1.0, 0.9, 0.85, alpha),
+                layer: 0,
+                anchor: SpriteAnchor::TopRight,
+                space: SpriteSpace::Absolute,
+                alignment: TextAlignment::Right,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_newest_lines_until_they_expire() {
+        let mut feed = KillFeed::default();
+        for i in 0..7 {
+            feed.push(format!("Kill {}", i));
+            feed.update(0.5);
+        }
+
+        let texts: Vec<&str> = feed.lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, ["Kill 6", "Kill 5", "Kill 4", "Kill 3", "Kill 2"]);
+
+        // Kill 2 was pushed 2.5s before Kill 6
+        feed.update(LINE_LIFETIME - 2.5);
+        assert_eq!(feed.lines.len(), 4);
+        feed.update(2.5);
+        assert!(feed.lines.is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+// Timing of the last frames for diagnosing input latency. An input is timed from the window
+// event to the update that consumes it and to the present of the frame that shows it. The
+// history is allocated once, recording a frame never allocates.
+
+pub const FRAME_HISTORY_LENGTH: usize = 600;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FrameRecord {
+    pub dt_ms: f32,
+    pub fixed_ms: f32,                   // All fixed updates of the frame
+    pub update_ms: f32,                  // The variable update
+    pub render_ms: f32,                  // Submitting and presenting
+    pub input_to_update_ms: Option<f32>, // Only for frames that consumed an input
+    pub input_to_present_ms: Option<f32>,
+    pub click_consumed: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Percentiles {
+    pub p50: f32,
+    pub p95: f32,
+    pub p99: f32,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LatencyStats {
+    pub input_to_update_ms: Percentiles,
+    pub input_to_present_ms: Percentiles,
+}
+
+// The frame being recorded, times in seconds
+#[derive(Default)]
+struct CurrentFrame {
+    start: f64,
+    fixed_end: f64,
+    update_end: f64,
+    present: Option<f64>,
+    consumed_input: Option<f64>,
+    record: FrameRecord,
+}
+
+pub struct FrameHistory {
+    records: Vec<FrameRecord>, // A ring, next is the oldest once it is full
+    next: usize,
+    pending_input: Option<f64>, // The oldest input no update has consumed yet
+    current: CurrentFrame,
+    scratch: Vec<f32>, // For sorting the percentiles
+}
+
+impl FrameHistory {
+    pub fn new() -> Self {
+        Self {
+            records: Vec::with_capacity(FRAME_HISTORY_LENGTH),
+            next: 0,
+            pending_input: None,
+            current: CurrentFrame::default(),
+            scratch: Vec::with_capacity(FRAME_HISTORY_LENGTH),
+        }
+    }
+
+    // Inputs arriving before the same update are measured from the first of them
+    pub fn on_input(&mut self, time: f64) {
+        self.pending_input.get_or_insert(time);
+    }
+
+    pub fn begin_frame(&mut self, time: f64, dt: f32) {
+        self.current = CurrentFrame {
+            start: time,
+            fixed_end: time,
+            update_end: time,
+            record: FrameRecord {
+                dt_ms: dt * 1000.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+    }
+
+    pub fn on_fixed_done(&mut self, time: f64) {
+        self.current.fixed_end = time;
+    }
+
+    // After Game::update, everything that arrived until now has been seen by the game
+    pub fn on_update(&mut self, time: f64, click_consumed: bool) {
+        self.current.update_end = time;
+        self.current.consumed_input = self.pending_input.take();
+        self.current.record.click_consumed = click_consumed;
+    }
+
+    pub fn on_present(&mut self, time: f64) {
+        self.current.present = Some(time);
+    }
+
+    pub fn end_frame(&mut self, time: f64) {
+        let current = &mut self.current;
+        let to_ms = |from: f64, to: f64| ((to - from) * 1000.0) as f32;
+
+        let record = &mut current.record;
+        record.fixed_ms = to_ms(current.start, current.fixed_end);
+        record.update_ms = to_ms(current.fixed_end, current.update_end);
+        record.render_ms = to_ms(current.update_end, time);
+        if let Some(input) = current.consumed_input {
+            record.input_to_update_ms = Some(to_ms(input, current.update_end));
+            record.input_to_present_ms = current.present.map(|present| to_ms(input, present));
+        }
+
+        if self.records.len() < FRAME_HISTORY_LENGTH {
+            self.records.push(*record);
+        } else {
+            self.records[self.next] = *record;
+        }
+        self.next = (self.next + 1) % FRAME_HISTORY_LENGTH;
+    }
+
+    // True from the update that consumed a left click until the frame ends
+    pub fn is_click_frame(&self) -> bool {
+        self.current.record.click_consumed
+    }
+
+    // Oldest first
+    #[allow(dead_code)]
+    pub fn iter(&self) -> impl Iterator<Item = &FrameRecord> {
+        let (newer, older) = self.records.split_at(self.next % self.records.len().max(1));
+        older.iter().chain(newer)
+    }
+
+    pub fn get_stats(&mut self) -> LatencyStats {
+        LatencyStats {
+            input_to_update_ms: self.get_percentiles(|record| record.input_to_update_ms),
+            input_to_present_ms: self.get_percentiles(|record| record.input_to_present_ms),
+        }
+    }
+
+    fn get_percentiles(&mut self, get: impl Fn(&FrameRecord) -> Option<f32>) -> Percentiles {
+        self.scratch.clear();
+        self.scratch.extend(self.records.iter().filter_map(get));
+        self.scratch.sort_by(f32::total_cmp);
+
+        let scratch = &self.scratch;
+        let at = |fraction: f32| {
+            let index = ((scratch.len() as f32 - 1.0) * fraction).round() as usize;
+            scratch.get(index).copied().unwrap_or(0.0)
+        };
+        Percentiles {
+            p50: at(0.5),
+            p95: at(0.95),
+            p99: at(0.99),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A frame at 10 ms steps, update at 2 ms and present at 8 ms into it
+    fn record_frame(history: &mut FrameHistory, index: usize, click: bool) {
+        let start = index as f64 * 0.01;
+        history.begin_frame(start, 0.01);
+        history.on_fixed_done(start + 0.001);
+        history.on_update(start + 0.002, click);
+        history.on_present(start + 0.008);
+        history.end_frame(start + 0.009);
+    }
+
+    #[test]
+    fn inputs_are_timed_to_the_update_and_present() {
+        let mut history = FrameHistory::new();
+        record_frame(&mut history, 0, false);
+
+        // Two inputs between the updates of frame 0 and 1, timed from the first
+        history.on_input(0.005);
+        history.on_input(0.009);
+        record_frame(&mut history, 1, true);
+        assert!(history.is_click_frame());
+        record_frame(&mut history, 2, false);
+
+        let records: Vec<_> = history.iter().collect();
+        assert_eq!(records.len(), 3);
+        assert!(records[0].input_to_update_ms.is_none());
+        let latency = records[1].input_to_update_ms.unwrap();
+        assert!((latency - 7.0).abs() < 1e-3);
+        let latency = records[1].input_to_present_ms.unwrap();
+        assert!((latency - 13.0).abs() < 1e-3);
+        assert!((records[1].render_ms - 7.0).abs() < 1e-3);
+        assert!(records[2].input_to_update_ms.is_none());
+    }
+
+    #[test]
+    fn keeps_the_last_frames_without_growing() {
+        let mut history = FrameHistory::new();
+        for index in 0..FRAME_HISTORY_LENGTH + 50 {
+            // Every frame consumes an input, the first 50 waited 100 ms longer
+            let waited = if index < 50 { 0.1 } else { 0.0 };
+            history.on_input(index as f64 * 0.01 - waited);
+            record_frame(&mut history, index, false);
+        }
+        assert_eq!(history.records.capacity(), FRAME_HISTORY_LENGTH);
+        assert_eq!(history.iter().count(), FRAME_HISTORY_LENGTH);
+
+        // The slow first 50 frames are gone, all remaining inputs took 2 ms
+        let stats = history.get_stats();
+        assert!((stats.input_to_update_ms.p99 - 2.0).abs() < 1e-3);
+        assert!((stats.input_to_present_ms.p50 - 8.0).abs() < 1e-3);
+        assert_eq!(history.scratch.capacity(), FRAME_HISTORY_LENGTH);
+    }
+}
@@ -0,0 +1,957 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, bail};
+use glam::{EulerRot, IVec2};
+use serde::{Deserialize, Serialize};
+use shared::{math::*, team::Team};
+
+use crate::{
+    animation_events::{AnimationEvent, HIT_EVENT},
+    renderer::{AmbientLight, DirectionalLight, Fog, Wind},
+    scatter::MAX_SCATTER_CANDIDATES,
+};
+
+// Everything needed to build a scene: the assets to load, the props placed in the world,
+// the player and the environment. Vectors are stored as arrays and rotations as euler
+// angles in degrees (XYZ) so the files stay easy to write by hand.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Level {
+    pub name: String,
+    #[serde(default)]
+    pub assets: LevelAssets,
+    #[serde(default)]
+    pub props: Vec<PropDesc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scatter: Vec<ScatterDesc>,
+    pub player: PlayerDesc,
+    #[serde(default)]
+    pub spawn_points: Vec<SpawnPoint>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wave_spawners: Vec<WaveSpawnerDesc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<MapBounds>,
+    #[serde(default)]
+    pub environment: EnvironmentDesc,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<ChunkGridDesc>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LevelAssets {
+    #[serde(default)]
+    pub textures: Vec<AssetDesc>,
+    #[serde(default)]
+    pub materials: Vec<MaterialDesc>,
+    #[serde(default)]
+    pub meshes: Vec<AssetDesc>,
+    #[serde(default)]
+    pub skeletal_meshes: Vec<AssetDesc>,
+    #[serde(default)]
+    pub animations: Vec<AssetDesc>,
+    #[serde(default)]
+    pub fonts: Vec<FontDesc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AssetDesc {
+    pub name: String,
+    pub path: String, // Relative to the assets folder
+    // Named moments of an animation, e.g. where an attack clip hits
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<AnimationEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialDesc {
+    pub name: String,
+    pub texture: String,
+    // Sways in the wind, weighted by the vertex color alpha, e.g. for vegetation
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub wind: bool,
+    // One skin per tint of the texture, picked by the variant of the renderable
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<[f32; 3]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FontDesc {
+    pub name: String,
+    pub path: String,
+    pub material: String, // Name of the font material created for it
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformDesc {
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default = "get_one3")]
+    pub scale: [f32; 3],
+}
+
+impl Default for TransformDesc {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: get_one3(),
+        }
+    }
+}
+
+impl TransformDesc {
+    pub fn get_rotation(&self) -> Quat {
+        get_euler_rotation(self.rotation)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ShapeDesc {
+    Circle { radius: f32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PropDesc {
+    pub name: String,
+    pub mesh: String,
+    pub material: String,
+    #[serde(default)]
+    pub transform: TransformDesc,
+    #[serde(default = "get_one4")]
+    pub color: [f32; 4],
+    #[serde(default = "get_one2")]
+    pub tex_scale: [f32; 2],
+    #[serde(default = "get_true")]
+    pub casts_shadow: bool,
+    // Static body on the environment layer, placed at the prop position
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub physics: Option<ShapeDesc>,
+    // Left out of the static baking, for props that may move later
+    #[serde(default)]
+    pub movable: bool,
+}
+
+// Ground clutter, one mesh scattered over a rect of the ground when the level is built
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScatterDesc {
+    pub name: String,
+    pub mesh: String,
+    pub material: String,
+    pub min: [f32; 2], // xz
+    pub max: [f32; 2],
+    pub density: f32, // Instances per square unit
+    // Texture stretched over the rect, its first channel scales the density
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub density_map: Option<String>,
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default = "get_one2")]
+    pub scale: [f32; 2], // Range of the uniform scale
+    #[serde(default = "get_full_turn")]
+    pub yaw: [f32; 2], // Range in degrees
+    #[serde(default = "get_one4")]
+    pub color: [f32; 4],
+    #[serde(default)]
+    pub casts_shadow: bool,
+    #[serde(default = "get_scatter_chunk_size")]
+    pub chunk_size: f32,
+}
+
+// Props, clutter and bodies of a large map that are loaded around the camera instead of with
+// the level, see chunk_streamer.rs. The map is split into square cells on the ground, cell
+// [0, 0] starts at the origin. What crosses cells belongs in the level itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChunkGridDesc {
+    pub cell_size: f32,
+    #[serde(default)]
+    pub origin: [f32; 2], // xz
+    #[serde(default)]
+    pub cells: Vec<ChunkDesc>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChunkDesc {
+    pub cell: [i32; 2],
+    // Loaded with the chunk and released with it, the level's assets can be used as well
+    #[serde(default)]
+    pub assets: LevelAssets,
+    #[serde(default)]
+    pub props: Vec<PropDesc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scatter: Vec<ScatterDesc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bodies: Vec<StaticBodyDesc>,
+}
+
+// An invisible static body on the environment layer, e.g. a cliff edge
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StaticBodyDesc {
+    pub position: [f32; 2], // xz
+    pub shape: ShapeDesc,
+}
+
+impl ChunkGridDesc {
+    pub fn get_cell(&self, position: Vec2) -> IVec2 {
+        ((position - Vec2::from(self.origin)) / self.cell_size)
+            .floor()
+            .as_ivec2()
+    }
+
+    pub fn get_cell_min(&self, cell: IVec2) -> Vec2 {
+        Vec2::from(self.origin) + cell.as_vec2() * self.cell_size
+    }
+
+    fn contains(&self, cell: IVec2, position: Vec2) -> bool {
+        let min = self.get_cell_min(cell);
+        let max = min + Vec2::splat(self.cell_size);
+        position.cmpge(min).all() && position.cmple(max).all()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlayerDesc {
+    pub mesh: String, // Skeletal mesh
+    pub material: String,
+    pub idle_animation: String,
+    pub run_animation: String,
+    pub spawn: String, // Name of the spawn point
+    pub shape: ShapeDesc,
+    #[serde(default)]
+    pub render_rotation: [f32; 3], // Corrects the orientation the mesh was exported with
+    // Clips by (forward speed, strafe speed), the run animation in every direction otherwise
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub locomotion: Vec<BlendSampleDesc>,
+    // Names of abilities on Q, W, E and R, null leaves a key empty. E attacks without one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub abilities: Vec<Option<String>>,
+    // Played by attacks, they hit on its hit event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attack_animation: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlendSampleDesc {
+    pub animation: String,
+    pub parameter: [f32; 2],
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpawnPoint {
+    pub name: String,
+    pub position: [f32; 3],
+}
+
+// Sends a wave of units down a lane every interval, see wave_spawner.rs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WaveSpawnerDesc {
+    pub name: String,
+    pub spawn: String,       // Name of the spawn point the waves form up at
+    pub lane: Vec<[f32; 3]>, // The waypoints walked in order, the last one is where they stop
+    pub interval: f32,       // Seconds between the waves, the first one spawns right away
+    pub count: u32,          // Units in a wave
+    pub team: TeamDesc,
+    #[serde(default = "get_minion_prefab")]
+    pub prefab: String,
+    #[serde(default)]
+    pub seed: u64, // Of the jitter in the formation
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TeamDesc {
+    Blue,
+    Red,
+    Neutral,
+}
+
+impl TeamDesc {
+    pub fn get_team(self) -> Team {
+        match self {
+            TeamDesc::Blue => Team::Blue,
+            TeamDesc::Red => Team::Red,
+            TeamDesc::Neutral => Team::Neutral,
+        }
+    }
+}
+
+// Area on the ground plane the camera target is kept in, as xz
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MapBounds {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl MapBounds {
+    pub fn clamp(&self, position: Vec2) -> Vec2 {
+        position.clamp(Vec2::from(self.min), Vec2::from(self.max))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SunDesc {
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub shadows: bool,
+    pub shadow_depth_extension: f32,
+}
+
+impl Default for SunDesc {
+    fn default() -> Self {
+        let light = DirectionalLight::default();
+        Self {
+            direction: light.direction.to_array(),
+            color: light.color.to_array(),
+            intensity: light.intensity,
+            shadows: light.shadows_enabled,
+            shadow_depth_extension: light.shadow_depth_extension,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AmbientDesc {
+    pub top_color: [f32; 3],
+    pub bottom_color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl Default for AmbientDesc {
+    fn default() -> Self {
+        let ambient = AmbientLight::default();
+        Self {
+            top_color: ambient.top_color.to_array(),
+            bottom_color: ambient.bottom_color.to_array(),
+            intensity: ambient.intensity,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FogDesc {
+    pub color: [f32; 3],
+    pub start: f32,
+    pub end: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WindDesc {
+    pub direction: [f32; 2], // xz
+    pub strength: f32,
+    pub gust_strength: f32,
+    pub gust_period: f32,
+}
+
+impl Default for WindDesc {
+    fn default() -> Self {
+        let wind = Wind::default();
+        Self {
+            direction: wind.direction.to_array(),
+            strength: wind.strength,
+            gust_strength: wind.gust_strength,
+            gust_period: wind.gust_period,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnvironmentDesc {
+    #[serde(default)]
+    pub sun: SunDesc,
+    #[serde(default)]
+    pub ambient: AmbientDesc,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fog: Option<FogDesc>,
+    #[serde(default)]
+    pub wind: WindDesc,
+}
+
+impl EnvironmentDesc {
+    pub fn get_directional_light(&self) -> DirectionalLight {
+        DirectionalLight {
+            direction: Vec3::from(self.sun.direction),
+            color: Vec3::from(self.sun.color),
+            intensity: self.sun.intensity,
+            shadows_enabled: self.sun.shadows,
+            shadow_depth_extension: self.sun.shadow_depth_extension,
+        }
+    }
+
+    pub fn get_ambient_light(&self) -> AmbientLight {
+        AmbientLight {
+            top_color: Vec3::from(self.ambient.top_color),
+            bottom_color: Vec3::from(self.ambient.bottom_color),
+            intensity: self.ambient.intensity,
+        }
+    }
+
+    pub fn get_fog(&self) -> Option<Fog> {
+        self.fog.as_ref().map(|fog| Fog {
+            color: Vec3::from(fog.color),
+            start: fog.start,
+            end: fog.end,
+        })
+    }
+
+    pub fn get_wind(&self) -> Wind {
+        Wind {
+            direction: Vec2::from(self.wind.direction),
+            strength: self.wind.strength,
+            gust_strength: self.wind.gust_strength,
+            gust_period: self.wind.gust_period,
+        }
+    }
+}
+
+impl Level {
+    // Parses and validates a level file, errors name the offending entry, e.g.
+    // "props[2].transform.scale: invalid length 2, expected an array of length 3"
+    pub fn load(bytes: &[u8]) -> anyhow::Result<Self> {
+        let deserializer = &mut serde_json::Deserializer::from_slice(bytes);
+        let level: Level = serde_path_to_error::deserialize(deserializer)
+            .map_err(|error| anyhow!("{}: {}", error.path(), error.inner()))?;
+        level.validate()?;
+        Ok(level)
+    }
+
+    #[allow(dead_code)]
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn get_spawn_point(&self, name: &str) -> Option<Vec3> {
+        self.spawn_points
+            .iter()
+            .find(|spawn_point| spawn_point.name == name)
+            .map(|spawn_point| Vec3::from(spawn_point.position))
+    }
+
+    // Checks that every name a level refers to is declared and every asset path exists
+    fn validate(&self) -> anyhow::Result<()> {
+        let assets = &self.assets;
+        check_paths("assets.textures", &assets.textures)?;
+        check_paths("assets.meshes", &assets.meshes)?;
+        check_paths("assets.skeletal_meshes", &assets.skeletal_meshes)?;
+        check_animations("assets.animations", &assets.animations)?;
+        for (index, font) in assets.fonts.iter().enumerate() {
+            check_path(&format!("assets.fonts[{}].path", index), &font.path)?;
+        }
+
+        let textures = get_names(&assets.textures);
+        let meshes = get_names(&assets.meshes);
+        let skeletal_meshes = get_names(&assets.skeletal_meshes);
+        let animations = get_names(&assets.animations);
+        let materials: HashSet<&str> = assets.materials.iter().map(|m| m.name.as_str()).collect();
+        let spawn_points: HashSet<&str> =
+            self.spawn_points.iter().map(|s| s.name.as_str()).collect();
+
+        for (index, material) in assets.materials.iter().enumerate() {
+            let path = format!("assets.materials[{}].texture", index);
+            check_name(&path, "texture", &material.texture, &textures)?;
+            if material.wind && !material.variants.is_empty() {
+                bail!(
+                    "assets.materials[{}]: a swaying material has no variants",
+                    index
+                );
+            }
+        }
+
+        for (index, prop) in self.props.iter().enumerate() {
+            check_name(
+                &format!("props[{}].mesh", index),
+                "mesh",
+                &prop.mesh,
+                &meshes,
+            )?;
+            let path = format!("props[{}].material", index);
+            check_name(&path, "material", &prop.material, &materials)?;
+        }
+
+        for (index, scatter) in self.scatter.iter().enumerate() {
+            scatter.validate(
+                &format!("scatter[{}]", index),
+                &meshes,
+                &materials,
+                &textures,
+            )?;
+        }
+
+        let player = &self.player;
+        let path = "player.mesh";
+        check_name(path, "skeletal mesh", &player.mesh, &skeletal_meshes)?;
+        check_name("player.material", "material", &player.material, &materials)?;
+        let path = "player.idle_animation";
+        check_name(path, "animation", &player.idle_animation, &animations)?;
+        let path = "player.run_animation";
+        check_name(path, "animation", &player.run_animation, &animations)?;
+        check_name("player.spawn", "spawn point", &player.spawn, &spawn_points)?;
+        if let Some(attack_animation) = &player.attack_animation {
+            let path = "player.attack_animation";
+            check_name(path, "animation", attack_animation, &animations)?;
+            let animation = assets
+                .animations
+                .iter()
+                .find(|a| &a.name == attack_animation);
+            if !animation.is_some_and(|a| a.events.iter().any(|e| e.name == HIT_EVENT)) {
+                bail!(
+                    "{}: \"{}\" has no \"{}\" event",
+                    path,
+                    attack_animation,
+                    HIT_EVENT
+                );
+            }
+        }
+
+        for (index, spawner) in self.wave_spawners.iter().enumerate() {
+            let path = format!("wave_spawners[{}]", index);
+            check_name(
+                &format!("{}.spawn", path),
+                "spawn point",
+                &spawner.spawn,
+                &spawn_points,
+            )?;
+            if spawner.lane.is_empty() {
+                bail!("{}.lane: needs at least one waypoint", path);
+            }
+            if spawner.interval <= 0.0 {
+                bail!("{}.interval: {} is not above 0", path, spawner.interval);
+            }
+        }
+
+        if let Some(bounds) = &self.bounds
+            && (bounds.min[0] > bounds.max[0] || bounds.min[1] > bounds.max[1])
+        {
+            bail!("bounds: min {:?} is above max {:?}", bounds.min, bounds.max);
+        }
+
+        if let Some(fog) = &self.environment.fog
+            && fog.start >= fog.end
+        {
+            bail!(
+                "environment.fog: start {} is not below end {}",
+                fog.start,
+                fog.end
+            );
+        }
+
+        if let Some(chunks) = &self.chunks {
+            chunks.validate(assets)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ChunkGridDesc {
+    // Names resolve to the chunk's own assets or the level's
+    fn validate(&self, level_assets: &LevelAssets) -> anyhow::Result<()> {
+        if self.cell_size <= 0.0 {
+            bail!("chunks: cell_size {} is not positive", self.cell_size);
+        }
+
+        let mut cells = HashSet::new();
+        for (index, chunk) in self.cells.iter().enumerate() {
+            let path = format!("chunks.cells[{}]", index);
+            let cell = IVec2::from(chunk.cell);
+            if !cells.insert(cell) {
+                bail!("{}: the cell {:?} is listed twice", path, chunk.cell);
+            }
+
+            let assets = &chunk.assets;
+            check_paths(&format!("{}.assets.textures", path), &assets.textures)?;
+            check_paths(&format!("{}.assets.meshes", path), &assets.meshes)?;
+            if !assets.skeletal_meshes.is_empty()
+                || !assets.animations.is_empty()
+                || !assets.fonts.is_empty()
+            {
+                bail!("{}.assets: only textures, materials and meshes", path);
+            }
+
+            let mut textures = get_names(&level_assets.textures);
+            textures.extend(get_names(&assets.textures));
+            let mut meshes = get_names(&level_assets.meshes);
+            meshes.extend(get_names(&assets.meshes));
+            let materials: HashSet<&str> = level_assets
+                .materials
+                .iter()
+                .chain(&assets.materials)
+                .map(|m| m.name.as_str())
+                .collect();
+
+            for (material_index, material) in assets.materials.iter().enumerate() {
+                let material_path =
+                    format!("{}.assets.materials[{}].texture", path, material_index);
+                check_name(&material_path, "texture", &material.texture, &textures)?;
+            }
+
+            for (prop_index, prop) in chunk.props.iter().enumerate() {
+                let prop_path = format!("{}.props[{}]", path, prop_index);
+                check_name(&format!("{}.mesh", prop_path), "mesh", &prop.mesh, &meshes)?;
+                let material_path = format!("{}.material", prop_path);
+                check_name(&material_path, "material", &prop.material, &materials)?;
+                let position = Vec3::from(prop.transform.position).xz();
+                if !self.contains(cell, position) {
+                    bail!("{}: {} is outside of the cell", prop_path, position);
+                }
+            }
+
+            for (scatter_index, scatter) in chunk.scatter.iter().enumerate() {
+                let scatter_path = format!("{}.scatter[{}]", path, scatter_index);
+                scatter.validate(&scatter_path, &meshes, &materials, &textures)?;
+                if !self.contains(cell, Vec2::from(scatter.min))
+                    || !self.contains(cell, Vec2::from(scatter.max))
+                {
+                    bail!("{}: the rect is outside of the cell", scatter_path);
+                }
+            }
+
+            for (body_index, body) in chunk.bodies.iter().enumerate() {
+                let position = Vec2::from(body.position);
+                if !self.contains(cell, position) {
+                    bail!(
+                        "{}.bodies[{}]: {} is outside of the cell",
+                        path,
+                        body_index,
+                        position
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ScatterDesc {
+    fn validate(
+        &self,
+        path: &str,
+        meshes: &HashSet<&str>,
+        materials: &HashSet<&str>,
+        textures: &HashSet<&str>,
+    ) -> anyhow::Result<()> {
+        check_name(&format!("{}.mesh", path), "mesh", &self.mesh, meshes)?;
+        let material_path = format!("{}.material", path);
+        check_name(&material_path, "material", &self.material, materials)?;
+        if let Some(density_map) = &self.density_map {
+            let map_path = format!("{}.density_map", path);
+            check_name(&map_path, "texture", density_map, textures)?;
+        }
+
+        if self.min[0] >= self.max[0] || self.min[1] >= self.max[1] {
+            bail!(
+                "{}: min {:?} is not below max {:?}",
+                path,
+                self.min,
+                self.max
+            );
+        }
+        let area = (self.max[0] - self.min[0]) * (self.max[1] - self.min[1]);
+        if !(self.density >= 0.0 && area * self.density <= MAX_SCATTER_CANDIDATES) {
+            bail!(
+                "{}: density {} is negative or scatters more than {} instances",
+                path,
+                self.density,
+                MAX_SCATTER_CANDIDATES
+            );
+        }
+        if !(self.scale[0] > 0.0 && self.scale[0] <= self.scale[1]) {
+            bail!("{}: scale {:?} is not a positive range", path, self.scale);
+        }
+        if self.chunk_size <= 0.0 {
+            bail!("{}: chunk_size {} is not positive", path, self.chunk_size);
+        }
+        Ok(())
+    }
+}
+
+fn get_names(assets: &[AssetDesc]) -> HashSet<&str> {
+    assets.iter().map(|asset| asset.name.as_str()).collect()
+}
+
+fn check_paths(path: &str, assets: &[AssetDesc]) -> anyhow::Result<()> {
+    for (index, asset) in assets.iter().enumerate() {
+        check_path(&format!("{}[{}].path", path, index), &asset.path)?;
+        if !asset.events.is_empty() {
+            bail!("{}[{}].events: only animations have events", path, index);
+        }
+    }
+    Ok(())
+}
+
+fn check_animations(path: &str, animations: &[AssetDesc]) -> anyhow::Result<()> {
+    for (index, animation) in animations.iter().enumerate() {
+        check_path(&format!("{}[{}].path", path, index), &animation.path)?;
+        for (event_index, event) in animation.events.iter().enumerate() {
+            let path = format!("{}[{}].events[{}]", path, index, event_index);
+            if event.name.is_empty() {
+                bail!("{}.name: is empty", path);
+            }
+            if event.time < 0.0 || event.time.is_nan() {
+                bail!("{}.time: {} is below 0", path, event.time);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Whether the file is there is only known once it is fetched, the path has to stay in the
+// assets folder though
+fn check_path(path: &str, asset_path: &str) -> anyhow::Result<()> {
+    let outside = asset_path.starts_with('/') || asset_path.split('/').any(|part| part == "..");
+    if asset_path.is_empty() || asset_path.contains('\\') || outside {
+        bail!(
+            "{}: \"{}\" is not a path in the assets folder",
+            path,
+            asset_path
+        );
+    }
+    Ok(())
+}
+
+fn check_name(path: &str, kind: &str, name: &str, names: &HashSet<&str>) -> anyhow::Result<()> {
+    if !names.contains(name) {
+        bail!("{}: unknown {} \"{}\"", path, kind, name);
+    }
+    Ok(())
+}
+
+pub fn get_euler_rotation(degrees: [f32; 3]) -> Quat {
+    let [x, y, z] = degrees.map(f32::to_radians);
+    Quat::from_euler(EulerRot::XYZ, x, y, z)
+}
+
+fn get_one2() -> [f32; 2] {
+    [1.0; 2]
+}
+
+fn get_one3() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn get_one4() -> [f32; 4] {
+    [1.0; 4]
+}
+
+fn get_true() -> bool {
+    true
+}
+
+fn get_full_turn() -> [f32; 2] {
+    [0.0, 360.0]
+}
+
+fn get_scatter_chunk_size() -> f32 {
+    256.0
+}
+
+fn get_minion_prefab() -> String {
+    "Minion".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT_LEVEL: &[u8] = include_bytes!("../../assets/levels/default.json");
+
+    #[test]
+    fn default_level_loads() {
+        let level = Level::load(DEFAULT_LEVEL).unwrap();
+        assert_eq!(level.get_spawn_point(&level.player.spawn), Some(Vec3::ZERO));
+
+        // The renderer normalizes the direction, the file doesn't have to
+        let mut light = level.environment.get_directional_light();
+        light.direction = light.direction.normalize();
+        let default_light = DirectionalLight::default();
+        assert!(light.direction.abs_diff_eq(default_light.direction, 1e-6));
+        assert_eq!(light.intensity, default_light.intensity);
+        assert_eq!(
+            light.shadow_depth_extension,
+            default_light.shadow_depth_extension
+        );
+        assert_eq!(
+            level.environment.get_ambient_light(),
+            AmbientLight::default()
+        );
+    }
+
+    #[test]
+    fn built_level_round_trips() {
+        let mut level = Level::load(DEFAULT_LEVEL).unwrap();
+        level.props.push(PropDesc {
+            name: "Pillar".to_string(),
+            mesh: "Sphere".to_string(),
+            material: "Grid".to_string(),
+            transform: TransformDesc {
+                position: [300.0, 0.0, -150.0],
+                rotation: [0.0, 45.0, 0.0],
+                scale: [2.0, 4.0, 2.0],
+            },
+            color: [0.5, 0.5, 0.5, 1.0],
+            tex_scale: [2.0, 2.0],
+            casts_shadow: true,
+            physics: Some(ShapeDesc::Circle { radius: 64.0 }),
+            movable: true,
+        });
+        level.scatter.push(ScatterDesc {
+            name: "Pebbles".to_string(),
+            mesh: "Sphere".to_string(),
+            material: "Grid".to_string(),
+            min: [-1000.0, -1000.0],
+            max: [1000.0, 500.0],
+            density: 0.001,
+            density_map: Some("GridTexture".to_string()),
+            seed: 3,
+            scale: [0.1, 0.3],
+            yaw: [0.0, 360.0],
+            color: [0.4, 0.4, 0.4, 1.0],
+            casts_shadow: false,
+            chunk_size: 250.0,
+        });
+        level.wave_spawners.push(WaveSpawnerDesc {
+            name: "BlueMid".to_string(),
+            spawn: "PlayerSpawn".to_string(),
+            lane: vec![[500.0, 0.0, 0.0], [1500.0, 0.0, 200.0]],
+            interval: 30.0,
+            count: 4,
+            team: TeamDesc::Blue,
+            prefab: "Minion".to_string(),
+            seed: 11,
+        });
+        level.environment.fog = Some(FogDesc {
+            color: [0.6, 0.7, 0.8],
+            start: 1500.0,
+            end: 4000.0,
+        });
+        let mut rock = level.props[0].clone();
+        rock.transform.position = [2500.0, 0.0, -500.0];
+        level.chunks = Some(ChunkGridDesc {
+            cell_size: 1000.0,
+            origin: [0.0, -1000.0],
+            cells: vec![ChunkDesc {
+                cell: [2, 0],
+                props: vec![rock],
+                bodies: vec![StaticBodyDesc {
+                    position: [2900.0, -100.0],
+                    shape: ShapeDesc::Circle { radius: 32.0 },
+                }],
+                ..Default::default()
+            }],
+        });
+        let chunks = level.chunks.as_ref().unwrap();
+        assert_eq!(chunks.get_cell(Vec2::new(2500.0, -500.0)), IVec2::new(2, 0));
+
+        let json = level.to_json().unwrap();
+        assert_eq!(Level::load(json.as_bytes()).unwrap(), level);
+    }
+
+    #[test]
+    fn errors_name_the_offending_entry() {
+        let mut level: serde_json::Value = serde_json::from_slice(DEFAULT_LEVEL).unwrap();
+        level["props"][0]["transform"]["scale"] = serde_json::json!([1.0, 2.0]);
+        let error = Level::load(level.to_string().as_bytes()).unwrap_err();
+        assert!(
+            error.to_string().starts_with("props[0].transform.scale:"),
+            "{}",
+            error
+        );
+
+        let mut level: serde_json::Value = serde_json::from_slice(DEFAULT_LEVEL).unwrap();
+        level["props"][0]["material"] = serde_json::json!("Missing");
+        let error = Level::load(level.to_string().as_bytes()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "props[0].material: unknown material \"Missing\""
+        );
+
+        let mut level: serde_json::Value = serde_json::from_slice(DEFAULT_LEVEL).unwrap();
+        level["assets"]["textures"][0]["path"] = serde_json::json!("../secrets.dat");
+        let error = Level::load(level.to_string().as_bytes()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "assets.textures[0].path: \"../secrets.dat\" is not a path in the assets folder"
+        );
+
+        let mut level: serde_json::Value = serde_json::from_slice(DEFAULT_LEVEL).unwrap();
+        level["assets"]["animations"][2]["events"][0]["time"] = serde_json::json!(-0.1);
+        let error = Level::load(level.to_string().as_bytes()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "assets.animations[2].events[0].time: -0.1 is below 0"
+        );
+
+        let mut level: serde_json::Value = serde_json::from_slice(DEFAULT_LEVEL).unwrap();
+        level["player"]["attack_animation"] = serde_json::json!("Brute_Run");
+        let error = Level::load(level.to_string().as_bytes()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "player.attack_animation: \"Brute_Run\" has no \"hit\" event"
+        );
+
+        let mut level: serde_json::Value = serde_json::from_slice(DEFAULT_LEVEL).unwrap();
+        level["scatter"] = serde_json::json!([{
+            "name": "Grass",
+            "mesh": "Sphere",
+            "material": "Grid",
+            "min": [0.0, 0.0],
+            "max": [100.0, 100.0],
+            "density": 1000.0
+        }]);
+        let error = Level::load(level.to_string().as_bytes()).unwrap_err();
+        assert!(
+            error.to_string().starts_with("scatter[0]: density"),
+            "{}",
+            error
+        );
+
+        let mut level: serde_json::Value = serde_json::from_slice(DEFAULT_LEVEL).unwrap();
+        level["wave_spawners"] = serde_json::json!([{
+            "name": "RedMid",
+            "spawn": "PlayerSpawn",
+            "lane": [[0.0, 0.0, 0.0]],
+            "interval": 0.0,
+            "count": 3,
+            "team": "Red"
+        }]);
+        let error = Level::load(level.to_string().as_bytes()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "wave_spawners[0].interval: 0 is not above 0"
+        );
+
+        let mut level: serde_json::Value = serde_json::from_slice(DEFAULT_LEVEL).unwrap();
+        let mut prop = level["props"][0].clone();
+        prop["transform"]["position"] = serde_json::json!([1500.0, 0.0, 0.0]);
+        level["chunks"] = serde_json::json!({
+            "cell_size": 1000.0,
+            "cells": [{ "cell": [0, 0], "props": [prop] }]
+        });
+        let error = Level::load(level.to_string().as_bytes()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "chunks.cells[0].props[0]: [1500, 0] is outside of the cell"
+        );
+    }
+}
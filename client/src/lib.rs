@@ -1,4 +1,65 @@
+// In the browser the game starts from here. Natively the game is the binary and the library
+// only carries the modules for the tests, so what it leaves unused is checked in the binary.
+#![cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+
+mod ability;
+mod animation_events;
 mod app;
+mod bake;
+mod blink;
+mod camera_track;
+mod chunk_streamer;
+mod combat;
+mod command_queue;
+mod components;
+mod console;
+mod crash;
+mod cursor;
+mod death;
+mod debug_camera;
+mod diagnostics;
+mod events;
+pub mod fetch;
 mod game;
+mod gizmo;
+mod hierarchy;
+mod hitch;
+#[cfg(not(target_arch = "wasm32"))]
+mod hot_reload;
 mod input;
+#[cfg(feature = "inspector")]
+mod inspector;
+mod jobs;
+mod kill_feed;
+mod latency;
+mod level;
+mod loading;
+mod measure;
+mod network;
+mod offscreen_indicators;
+mod options;
+mod prediction;
+mod prefab;
+mod profiler_overlay;
+mod projectile_pool;
+mod remote_proxy;
+#[cfg(not(feature = "test-harness"))]
 mod renderer;
+#[cfg(feature = "test-harness")]
+pub mod renderer;
+mod resource_browser;
+mod save;
+mod scatter;
+mod screenshot;
+mod selection;
+mod stats;
+mod status_effects;
+mod time_controller;
+mod tint;
+mod trail;
+mod tween;
+#[cfg(not(feature = "test-harness"))]
+mod ui;
+#[cfg(feature = "test-harness")]
+pub mod ui;
+mod wave_spawner;
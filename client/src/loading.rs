@@ -0,0 +1,519 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use anyhow::Context;
+
+use crate::{
+    ability::AbilityLibrary,
+    fetch::AssetFetcher,
+    level::{AssetDesc, Level, LevelAssets, MaterialDesc, ScatterDesc},
+    prefab::PrefabLibrary,
+    renderer::{
+        Renderer, resource_scope::ScopeHandle, resources::get_handle, texture::get_tinted_layers,
+    },
+};
+use shared::math::*;
+
+struct LoadTask<C> {
+    name: String,
+    step: Box<dyn FnMut(&mut C) -> bool>, // Returns true when the task is finished
+}
+
+// Work that would block a frame for too long, spread over several frames. Tasks run in
+// the order they were pushed, as many per frame as fit into the time budget.
+pub struct LoadQueue<C> {
+    tasks: VecDeque<LoadTask<C>>,
+    task_count: usize,
+}
+
+impl<C> LoadQueue<C> {
+    pub fn new() -> Self {
+        Self {
+            tasks: VecDeque::new(),
+            task_count: 0,
+        }
+    }
+
+    pub fn push(&mut self, name: &str, task: impl FnOnce(&mut C) + 'static) {
+        let mut task = Some(task);
+        self.push_chunked(name, move |context| {
+            if let Some(task) = task.take() {
+                task(context);
+            }
+            true
+        });
+    }
+
+    // The step is called once per frame at most, until it returns true
+    pub fn push_chunked(&mut self, name: &str, step: impl FnMut(&mut C) -> bool + 'static) {
+        self.tasks.push_back(LoadTask {
+            name: name.to_string(),
+            step: Box::new(step),
+        });
+        self.task_count += 1;
+    }
+
+    // Always makes progress, even when a single step takes longer than the budget
+    pub fn process(&mut self, context: &mut C, budget: f64, get_time: impl Fn() -> f64) {
+        let start = get_time();
+        let mut stepped = 0;
+        while let Some(task) = self.tasks.front_mut() {
+            if stepped > 0 && get_time() - start >= budget {
+                break;
+            }
+
+            stepped += 1;
+            if !(task.step)(context) {
+                break; // The next step of a chunked task waits for the next frame
+            }
+            self.tasks.pop_front();
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    // The fraction of finished tasks, a chunked task counts as one
+    #[allow(dead_code)]
+    pub fn get_progress(&self) -> f32 {
+        if self.task_count == 0 {
+            return 1.0;
+        }
+        self.get_finished_count() as f32 / self.task_count as f32
+    }
+
+    pub fn get_finished_count(&self) -> usize {
+        self.task_count - self.tasks.len()
+    }
+
+    pub fn get_current_name(&self) -> Option<&str> {
+        self.tasks.front().map(|task| task.name.as_str())
+    }
+}
+
+// What a level needs before its assets can be requested: the level file that lists them,
+// and the prefabs and abilities every level uses, checked against each other
+pub struct Startup {
+    pub level: Level,
+    pub prefabs: PrefabLibrary,
+    pub abilities: AbilityLibrary,
+}
+
+// Fetches and parses the files of the Startup, the same way for every platform
+pub struct StartupLoader {
+    fetcher: AssetFetcher,
+    level: Option<Level>,
+    prefabs: Option<PrefabLibrary>,
+    abilities: Option<AbilityLibrary>,
+    errors: Vec<String>,
+}
+
+impl StartupLoader {
+    const FILE_COUNT: usize = 3;
+
+    pub fn new(mut fetcher: AssetFetcher, level_name: &str) -> Self {
+        fetcher.request("Level", &format!("levels/{}.json", level_name));
+        fetcher.request("Prefabs", "prefabs/default.ron");
+        fetcher.request("Abilities", "abilities/default.ron");
+        Self {
+            fetcher,
+            level: None,
+            prefabs: None,
+            abilities: None,
+            errors: Vec::new(),
+        }
+    }
+
+    // Some once every file arrived and made sense
+    pub fn update(&mut self) -> Option<Startup> {
+        for fetched in self.fetcher.poll() {
+            let name = fetched.name;
+            let parsed = fetched.bytes.and_then(|bytes| match name.as_str() {
+                "Level" => Level::load(&bytes).map(|level| self.level = Some(level)),
+                "Prefabs" => PrefabLibrary::load(&bytes).map(|p| self.prefabs = Some(p)),
+                "Abilities" => AbilityLibrary::load(&bytes).map(|a| self.abilities = Some(a)),
+                _ => unreachable!("Only the startup files are requested"),
+            });
+            if let Err(e) = parsed.with_context(|| format!("Failed to load the {}", name)) {
+                log::error!("{:#}", e);
+                self.errors.push(format!("{:#}", e));
+            }
+        }
+
+        let (Some(_), Some(prefabs), Some(abilities)) =
+            (&self.level, &self.prefabs, &self.abilities)
+        else {
+            return None;
+        };
+        if let Err(e) = abilities.validate(prefabs) {
+            let error = format!("Abilities refer to missing prefabs: {:#}", e);
+            log::error!("{}", error);
+            self.errors.push(error);
+            self.abilities = None;
+            return None;
+        }
+        Some(Startup {
+            level: self.level.take()?,
+            prefabs: self.prefabs.take()?,
+            abilities: self.abilities.take()?,
+        })
+    }
+
+    pub fn get_progress(&self) -> f32 {
+        let pending = self.fetcher.get_pending_count();
+        (Self::FILE_COUNT - pending) as f32 / Self::FILE_COUNT as f32
+    }
+
+    pub fn get_status(&self) -> String {
+        format!("Downloading {} files", self.fetcher.get_pending_count())
+    }
+
+    pub fn get_errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    pub fn get_pending_count(&self) -> usize {
+        self.fetcher.get_pending_count()
+    }
+}
+
+enum FileKind {
+    Texture,
+    Mesh,
+    SkeletalMesh,
+    Animation,
+    Font { material: String },
+}
+
+// A level asset that comes from a file
+struct AssetFile {
+    name: String,
+    path: String,
+    kind: FileKind,
+}
+
+// Loads the assets of a level, the files are queued as they arrive. Materials wait for
+// every file since they need their textures. Everything is registered to the scope of the
+// level.
+pub struct LevelLoader {
+    queue: LoadQueue<Renderer>,
+    scope: ScopeHandle,
+    fetcher: AssetFetcher,
+    files: Vec<AssetFile>, // Requested and not arrived yet
+    materials: Vec<MaterialDesc>,
+    kept_names: HashSet<String>, // Textures the game reads as well, e.g. density maps
+    kept_files: HashMap<String, Vec<u8>>,
+    fetch_count: usize,
+    task_count: usize,
+    errors: Vec<String>,
+}
+
+impl LevelLoader {
+    pub fn new(level: &Level, fetcher: AssetFetcher, scope: ScopeHandle) -> Self {
+        let mut loader = Self::from_assets(&level.assets, fetcher, scope);
+        loader.keep_density_maps(&level.scatter);
+        loader
+    }
+
+    // Also loads the assets of a chunk, see chunk_streamer.rs
+    pub fn from_assets(assets: &LevelAssets, fetcher: AssetFetcher, scope: ScopeHandle) -> Self {
+        let files = get_asset_files(assets);
+        let mut loader = Self {
+            queue: LoadQueue::new(),
+            scope,
+            fetcher,
+            files: Vec::new(),
+            materials: assets.materials.clone(),
+            kept_names: HashSet::new(),
+            kept_files: HashMap::new(),
+            fetch_count: 0,
+            task_count: files.len() + assets.materials.len() + get_variant_count(assets),
+            errors: Vec::new(),
+        };
+
+        for file in files {
+            if loader.fetcher.request(&file.name, &file.path) {
+                loader.files.push(file);
+            }
+        }
+        loader.fetch_count = loader.files.len();
+        loader
+    }
+
+    // Picks up the fetched files and spends the budget on the queued work
+    pub fn update(&mut self, renderer: &mut Renderer, budget: f64, get_time: impl Fn() -> f64) {
+        for fetched in self.fetcher.poll() {
+            let Some(index) = self.files.iter().position(|f| f.name == fetched.name) else {
+                continue;
+            };
+            let file = self.files.swap_remove(index);
+            match fetched.bytes {
+                Ok(bytes) => self.queue_file(file, bytes),
+                Err(e) => self.fail(format!("{:#}", e)),
+            }
+        }
+
+        // Without their textures the materials could not be created
+        if self.files.is_empty() && self.errors.is_empty() {
+            for MaterialDesc {
+                name,
+                texture,
+                wind,
+                variants,
+            } in self.materials.drain(..)
+            {
+                let texture = get_handle(&texture);
+                self.queue.push(&name.clone(), move |renderer| {
+                    if !variants.is_empty() {
+                        let texture = get_handle(&get_variants_name(&name));
+                        renderer.create_variant_material(&name, texture, variants.len() as u32);
+                    } else if wind {
+                        renderer.create_swaying_material(&name, texture);
+                    } else {
+                        renderer.create_material(&name, texture);
+                    }
+                });
+            }
+        }
+
+        renderer.set_current_scope(Some(self.scope));
+        self.queue.process(renderer, budget, get_time);
+        renderer.set_current_scope(None);
+    }
+
+    // Textures of variant materials also get a tinted copy with a layer per variant
+    fn queue_file(&mut self, file: AssetFile, bytes: Vec<u8>) {
+        if self.kept_names.contains(&file.name) {
+            self.kept_files.insert(file.name.clone(), bytes.clone());
+        }
+        let variant_materials = self.materials.iter().filter(|material| {
+            matches!(file.kind, FileKind::Texture)
+                && material.texture == file.name
+                && !material.variants.is_empty()
+        });
+        let mut tinted_textures = Vec::new();
+        for material in variant_materials {
+            let tints: Vec<Vec3> = material.variants.iter().copied().map(Vec3::from).collect();
+            match get_tinted_layers(&bytes, &tints) {
+                Ok(tinted) => tinted_textures.push((get_variants_name(&material.name), tinted)),
+                Err(e) => {
+                    let error = format!("{}: {:#}", material.name, e);
+                    self.fail(error);
+                    return;
+                }
+            }
+        }
+        for (name, tinted) in tinted_textures {
+            self.queue.push(&name.clone(), move |renderer| {
+                renderer.load_texture(&name, &tinted);
+            });
+        }
+        queue_file(&mut self.queue, file, bytes);
+    }
+
+    fn fail(&mut self, error: String) {
+        log::error!("{}", error);
+        self.errors.push(error);
+    }
+
+    // Never done after an error, the level can't be played with assets missing
+    pub fn is_done(&self) -> bool {
+        self.errors.is_empty()
+            && self.files.is_empty()
+            && self.materials.is_empty()
+            && self.queue.is_done()
+    }
+
+    // Downloads and renderer work count the same
+    pub fn get_progress(&self) -> f32 {
+        let total = self.fetch_count + self.task_count;
+        if total == 0 {
+            return 1.0;
+        }
+        let fetched = self.fetch_count - self.files.len();
+        (fetched + self.queue.get_finished_count()) as f32 / total as f32
+    }
+
+    pub fn get_status(&self) -> String {
+        let downloading = self.fetcher.get_pending_count();
+        match self.queue.get_current_name() {
+            Some(name) => format!("Loading {}", name),
+            None if downloading > 0 => format!("Downloading {} files", downloading),
+            None => "Loading".to_string(),
+        }
+    }
+
+    pub fn get_errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    // The files still being downloaded
+    pub fn get_pending_count(&self) -> usize {
+        self.files.len()
+    }
+
+    // The scatter reads its density maps from the files, see take_kept_files
+    pub fn keep_density_maps(&mut self, scatter: &[ScatterDesc]) {
+        let names = scatter.iter().filter_map(|desc| desc.density_map.clone());
+        self.kept_names.extend(names);
+    }
+
+    // The files of the textures the game reads itself, by their name
+    pub fn take_kept_files(&mut self) -> HashMap<String, Vec<u8>> {
+        std::mem::take(&mut self.kept_files)
+    }
+}
+
+fn get_asset_files(assets: &LevelAssets) -> Vec<AssetFile> {
+    let files = |descs: &[AssetDesc], kind: fn() -> FileKind| {
+        descs
+            .iter()
+            .map(|desc| AssetFile {
+                name: desc.name.clone(),
+                path: desc.path.clone(),
+                kind: kind(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut all = files(&assets.textures, || FileKind::Texture);
+    all.extend(files(&assets.meshes, || FileKind::Mesh));
+    all.extend(files(&assets.skeletal_meshes, || FileKind::SkeletalMesh));
+    all.extend(files(&assets.animations, || FileKind::Animation));
+    all.extend(assets.fonts.iter().map(|font| AssetFile {
+        name: font.name.clone(),
+        path: font.path.clone(),
+        kind: FileKind::Font {
+            material: font.material.clone(),
+        },
+    }));
+    all
+}
+
+fn get_variant_count(assets: &LevelAssets) -> usize {
+    let materials = assets.materials.iter();
+    materials.filter(|m| !m.variants.is_empty()).count()
+}
+
+// The texture array the variants of a material are drawn with
+fn get_variants_name(material: &str) -> String {
+    format!("{}Variants", material)
+}
+
+// One task per file, textures upload a mip per frame
+fn queue_file(queue: &mut LoadQueue<Renderer>, file: AssetFile, bytes: Vec<u8>) {
+    let AssetFile { name, kind, .. } = file;
+    match kind {
+        FileKind::Texture => {
+            let mut upload = None;
+            queue.push_chunked(&name.clone(), move |renderer: &mut Renderer| {
+                let upload =
+                    upload.get_or_insert_with(|| renderer.begin_texture_upload(&name, &bytes));
+                renderer.upload_texture_mip(upload)
+            });
+        }
+        FileKind::Mesh => queue.push(&name.clone(), move |renderer| {
+            renderer.load_mesh(&name, &bytes);
+        }),
+        FileKind::SkeletalMesh => queue.push(&name.clone(), move |renderer| {
+            renderer.load_skeletal_mesh(&name, &bytes);
+        }),
+        FileKind::Animation => queue.push(&name.clone(), move |renderer| {
+            renderer.load_animation(&name, &bytes);
+        }),
+        FileKind::Font { material } => queue.push(&name.clone(), move |renderer| {
+            let font_handle = renderer.load_font(&name, &bytes);
+            renderer.create_font_material(&material, font_handle);
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    // Every step takes 3 ms of fake time
+    fn step_clock(clock: &Cell<f64>) {
+        clock.set(clock.get() + 0.003);
+    }
+
+    #[test]
+    fn stays_within_the_budget() {
+        let clock = Rc::new(Cell::new(0.0));
+        let mut queue = LoadQueue::<Vec<usize>>::new();
+        for i in 0..10 {
+            let clock = clock.clone();
+            queue.push(&format!("Task {}", i), move |loaded| {
+                step_clock(&clock);
+                loaded.push(i);
+            });
+        }
+
+        let mut loaded = Vec::new();
+        let mut frames = 0;
+        while !queue.is_done() {
+            queue.process(&mut loaded, 0.008, || clock.get());
+            frames += 1;
+        }
+
+        // Three tasks per frame, the third one starts at 6 ms and ends past the budget
+        assert_eq!(frames, 4);
+        assert_eq!(loaded, (0..10).collect::<Vec<_>>());
+        assert_eq!(queue.get_progress(), 1.0);
+    }
+
+    #[test]
+    fn chunked_tasks_take_one_step_per_frame() {
+        let mut queue = LoadQueue::<Vec<&str>>::new();
+        let mut remaining = 3;
+        queue.push_chunked("Texture", move |loaded| {
+            loaded.push("mip");
+            remaining -= 1;
+            remaining == 0
+        });
+        queue.push("Mesh", |loaded| loaded.push("mesh"));
+
+        let mut loaded = Vec::new();
+        queue.process(&mut loaded, 1.0, || 0.0);
+        assert_eq!(queue.get_current_name(), Some("Texture"));
+        assert_eq!(queue.get_progress(), 0.0);
+
+        queue.process(&mut loaded, 1.0, || 0.0);
+        queue.process(&mut loaded, 1.0, || 0.0);
+        assert_eq!(loaded, ["mip", "mip", "mip", "mesh"]);
+        assert!(queue.is_done());
+    }
+
+    // Natively the files are read on threads, so this waits for them
+    fn start(level_name: &str) -> (StartupLoader, Option<Startup>) {
+        let base = crate::options::ClientOptions::DEFAULT_ASSET_BASE;
+        let mut loader = StartupLoader::new(AssetFetcher::new(base), level_name);
+        for _ in 0..10_000 {
+            if let Some(startup) = loader.update() {
+                return (loader, Some(startup));
+            }
+            if loader.get_pending_count() == 0 && !loader.get_errors().is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        (loader, None)
+    }
+
+    #[test]
+    fn startup_fetches_the_level_and_the_libraries() {
+        let (loader, startup) = start("default");
+        let startup = startup.expect("The default level didn't start");
+        assert_eq!(startup.level.name, "Default");
+        assert!(startup.prefabs.get("Brute").is_some());
+        assert_eq!(loader.get_progress(), 1.0);
+        assert!(loader.get_errors().is_empty());
+
+        let (loader, startup) = start("missing");
+        assert!(startup.is_none());
+        assert_eq!(loader.get_errors().len(), 1);
+        assert!(loader.get_errors()[0].contains("levels/missing.json"));
+    }
+}
@@ -1,5 +1,60 @@
+mod ability;
+mod animation_events;
 mod app;
+mod bake;
+mod blink;
+mod camera_track;
+mod chunk_streamer;
+mod combat;
+mod command_queue;
+mod components;
+mod console;
+mod crash;
+mod cursor;
+mod death;
+mod debug_camera;
+mod diagnostics;
+mod events;
+mod fetch;
+mod game;
+mod gizmo;
+mod hierarchy;
+mod hitch;
+#[cfg(not(target_arch = "wasm32"))]
+mod hot_reload;
+mod input;
+#[cfg(feature = "inspector")]
+mod inspector;
+mod jobs;
+mod kill_feed;
+mod latency;
+mod level;
+mod loading;
+mod measure;
+mod network;
+mod offscreen_indicators;
+mod options;
+mod prediction;
+mod prefab;
+mod profiler_overlay;
+mod projectile_pool;
+mod remote_proxy;
+// The test harness is for the integration tests, which reach it through the library
+#[cfg_attr(feature = "test-harness", allow(dead_code))]
 mod renderer;
+mod resource_browser;
+mod save;
+mod scatter;
+mod screenshot;
+mod selection;
+mod stats;
+mod status_effects;
+mod time_controller;
+mod tint;
+mod trail;
+mod tween;
+mod ui;
+mod wave_spawner;
 
 use app::run;
 
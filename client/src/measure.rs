@@ -0,0 +1,173 @@
+// Distances on the ground for placing props and tuning ability ranges. While the tool is on,
+// two clicks put the ends of a segment where the picking ray meets the Y=0 plane the world
+// grid is drawn on, a third click starts a new one. The segment is drawn over the scene with
+// its length next to its middle, until the tool is turned off.
+
+use shared::math::{ray::Ray, *};
+
+use crate::{
+    offscreen_indicators::get_screen_position,
+    renderer::{
+        Renderer, SpriteAnchor, SpriteSpace, TextAlignment, render_data::TextRenderJob,
+        resources::get_handle,
+    },
+};
+
+const LINE_COLOR: Vec4 = Vec4::new(1.0, 0.85, 0.2, 1.0);
+const PREVIEW_COLOR: Vec4 = Vec4::new(1.0, 0.85, 0.2, 0.5); // To the cursor, before the second click
+const MARKER_SIZE: f32 = 0.2; // World units, the crosses on the ends
+const TEXT_SIZE: f32 = 18.0;
+const TEXT_OFFSET: f32 = 12.0; // Pixels above the middle of the segment
+
+#[derive(Debug, Default)]
+pub struct MeasureTool {
+    enabled: bool,
+    start: Option<Vec3>,
+    end: Option<Vec3>,
+    hovered: Option<Vec3>, // Under the cursor
+}
+
+impl MeasureTool {
+    // Turning it off forgets the segment
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.clear();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn clear(&mut self) {
+        self.start = None;
+        self.end = None;
+        self.hovered = None;
+    }
+
+    pub fn update(&mut self, ray: &Ray, clicked: bool) {
+        if !self.enabled {
+            return;
+        }
+        self.hovered = get_ground_point(ray);
+        if !clicked {
+            return;
+        }
+        let Some(point) = self.hovered else {
+            return;
+        };
+        if self.start.is_none() || self.end.is_some() {
+            self.start = Some(point);
+            self.end = None;
+        } else {
+            self.end = Some(point);
+        }
+    }
+
+    // The finished segment, or the one to the cursor after the first click
+    pub fn get_segment(&self) -> Option<(Vec3, Vec3)> {
+        Some((self.start?, self.end.or(self.hovered)?))
+    }
+
+    // Of the segment to the cursor as well
+    pub fn get_length(&self) -> Option<f32> {
+        let (start, end) = self.get_segment()?;
+        Some(start.distance(end))
+    }
+
+    // Both ends were clicked
+    pub fn is_finished(&self) -> bool {
+        self.start.is_some() && self.end.is_some()
+    }
+
+    pub fn render(&self, renderer: &mut Renderer, view_projection: Mat4, screen_size: Vec2) {
+        if !self.enabled {
+            return;
+        }
+        for point in [self.start, self.end.or(self.hovered)]
+            .into_iter()
+            .flatten()
+        {
+            for direction in [Vec3::X, Vec3::Z] {
+                let offset = direction * MARKER_SIZE * 0.5;
+                renderer.draw_gizmo_line(point - offset, point + offset, LINE_COLOR);
+            }
+        }
+        let (Some((start, end)), Some(length)) = (self.get_segment(), self.get_length()) else {
+            return;
+        };
+        let color = match self.is_finished() {
+            true => LINE_COLOR,
+            false => PREVIEW_COLOR,
+        };
+        renderer.draw_gizmo_line(start, end, color);
+
+        let middle = (start + end) * 0.5;
+        let Some(position) = get_screen_position(view_projection, middle, screen_size) else {
+            return;
+        };
+        renderer.submit(&TextRenderJob {
+            text: format!("{:.2}", length).into(),
+            font_atlas: get_handle("DebugFont"),
+            font_material: get_handle("DebugFontMaterial"),
+            position: position - Vec2::Y * TEXT_OFFSET,
+            size: TEXT_SIZE,
+            color,
+            layer: 0,
+            anchor: SpriteAnchor::TopLeft,
+            space: SpriteSpace::Absolute,
+            alignment: TextAlignment::Center,
+        });
+    }
+}
+
+// Where the ray meets the Y=0 plane, None when it points away from it
+fn get_ground_point(ray: &Ray) -> Option<Vec3> {
+    let t = ray.intersect_plane(Vec3::ZERO, Vec3::Y)?;
+    Some(ray.get_point(t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_ray_from_above(x: f32, z: f32) -> Ray {
+        Ray::new(Vec3::new(x, 10.0, z), Vec3::NEG_Y)
+    }
+
+    #[test]
+    fn two_clicks_measure_the_distance_on_the_ground() {
+        let mut tool = MeasureTool::default();
+        tool.update(&get_ray_from_above(1.0, 1.0), true);
+        assert_eq!(
+            tool.get_segment(),
+            None,
+            "Clicks are ignored while it is off"
+        );
+
+        tool.set_enabled(true);
+        tool.update(&get_ray_from_above(1.0, 1.0), true);
+        tool.update(&get_ray_from_above(4.0, 5.0), false);
+        assert_eq!(
+            tool.get_segment(),
+            Some((Vec3::new(1.0, 0.0, 1.0), Vec3::new(4.0, 0.0, 5.0)))
+        );
+        assert_eq!(tool.get_length(), Some(5.0));
+        assert!(!tool.is_finished(), "Only follows the cursor so far");
+
+        tool.update(&get_ray_from_above(4.0, 5.0), true);
+        assert!(tool.is_finished());
+        assert_eq!(tool.get_length(), Some(5.0));
+
+        // A third click starts over, a ray into the sky places nothing
+        tool.update(&get_ray_from_above(-2.0, 0.0), true);
+        assert!(!tool.is_finished());
+        tool.update(&Ray::new(Vec3::Y, Vec3::Y), true);
+        assert!(!tool.is_finished());
+        assert_eq!(tool.get_length(), None);
+
+        tool.set_enabled(false);
+        assert_eq!(tool.get_segment(), None);
+    }
+}
@@ -0,0 +1,368 @@
+#[cfg(target_arch = "wasm32")]
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use shared::{
+    math::Vec2,
+    movement::MoveInput,
+    net::{
+        Connection, EntityState, Message, QuantizationBounds, SERVER_TICK_RATE, SNAPSHOT_INTERVAL,
+        Snapshot,
+    },
+    physics::BodyState,
+};
+
+use crate::prediction::Predictor;
+
+// Renders one snapshot interval plus some slack in the past, so a late snapshot doesn't
+// leave us without one to blend towards
+const INTERPOLATION_DELAY: f32 = SNAPSHOT_INTERVAL as f32 / SERVER_TICK_RATE * 1.5;
+// A clock further off than this is reset instead of slowly pulled back
+const MAX_CLOCK_DRIFT: f32 = 0.25;
+
+// Counted since connecting, for the netstat console command
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NetStats {
+    pub datagrams_sent: u64,
+    pub bytes_sent: u64,
+    pub datagrams_received: u64,
+    pub bytes_received: u64,
+    pub resent: u64, // Reliable datagrams sent again for lack of an ack
+    pub snapshots: u64,
+}
+
+// The connection to an authoritative server. Inputs go out every fixed tick, the entity
+// states come back as snapshots that the game smooths per entity for rendering, see
+// CRemoteProxy. Our own entity is predicted instead, starting from the first snapshot that
+// has it. Natively the datagrams go over UDP, in the browser over a WebSocket.
+pub struct NetworkClient {
+    socket: Socket,
+    connection: Connection,
+    bounds: QuantizationBounds,
+    snapshots: Vec<Snapshot>, // Received since the game last took them
+    latest_tick: Option<u32>,
+    own_state: Option<EntityState>, // The newest server state of our own entity
+    entity_id: Option<u32>,
+    input_tick: u32,
+    server_time: Option<f32>, // Our estimate of the server clock, in seconds
+    predictor: Option<Predictor>,
+    stats: NetStats,
+}
+
+impl NetworkClient {
+    pub fn connect(address: &str, name: &str, now: f64) -> anyhow::Result<Self> {
+        let socket = Socket::connect(address)?;
+        let mut client = Self {
+            socket,
+            connection: Connection::new(now),
+            bounds: QuantizationBounds::default(),
+            snapshots: Vec::new(),
+            latest_tick: None,
+            own_state: None,
+            entity_id: None,
+            input_tick: 0,
+            server_time: None,
+            predictor: None,
+            stats: NetStats::default(),
+        };
+        client.send(
+            &Message::Join {
+                name: name.to_string(),
+            },
+            now,
+        );
+        log::info!("Connecting to {}", address);
+
+        Ok(client)
+    }
+
+    // The entity the server spawned for us, known once the server accepted the join
+    pub fn get_entity_id(&self) -> Option<u32> {
+        self.entity_id
+    }
+
+    pub fn get_stats(&self) -> NetStats {
+        self.stats
+    }
+
+    // The newest server tick we have a snapshot of
+    pub fn get_latest_tick(&self) -> Option<u32> {
+        self.latest_tick
+    }
+
+    pub fn is_timed_out(&self, now: f64) -> bool {
+        self.connection.is_timed_out(now)
+    }
+
+    pub fn fixed_update(&mut self, action_bits: u32, cursor_world: Vec2, now: f64) {
+        self.receive(now);
+
+        if self.entity_id.is_some() {
+            self.input_tick = self.input_tick.wrapping_add(1);
+            if let Some(predictor) = &mut self.predictor {
+                let input = MoveInput {
+                    action_bits,
+                    cursor_world,
+                };
+                predictor.predict(self.input_tick, input);
+            }
+            self.send(
+                &Message::ClientInput {
+                    tick: self.input_tick,
+                    action_bits,
+                    cursor_world,
+                },
+                now,
+            );
+        }
+
+        for datagram in self.connection.get_resends(now) {
+            self.stats.resent += 1;
+            self.send_datagram(&datagram);
+        }
+    }
+
+    // Advances the server clock estimate and smooths corrections, called once per frame
+    pub fn update(&mut self, dt: f32) {
+        if let Some(server_time) = &mut self.server_time {
+            *server_time += dt;
+        }
+        if let Some(predictor) = &mut self.predictor {
+            predictor.update(dt);
+        }
+    }
+
+    // Every snapshot that arrived since the last call, oldest first
+    pub fn take_snapshots(&mut self) -> Vec<Snapshot> {
+        std::mem::take(&mut self.snapshots)
+    }
+
+    // Where on the server tick clock the other entities are rendered, a bit in the past
+    pub fn get_render_time(&self) -> Option<f32> {
+        self.server_time
+            .map(|server_time| server_time - INTERPOLATION_DELAY)
+    }
+
+    // Our predicted entity, alpha places it between the last two fixed ticks
+    pub fn get_own_state(&self, alpha: f32) -> Option<EntityState> {
+        let predictor = self.predictor.as_ref()?;
+        let mut own = self.own_state?;
+        let state = predictor.get_state();
+        own.position = predictor.get_visual_position(alpha);
+        own.velocity = state.velocity;
+        if state.velocity != Vec2::ZERO {
+            own.facing = state.velocity.x.atan2(state.velocity.y);
+        }
+        Some(own)
+    }
+
+    fn receive(&mut self, now: f64) {
+        while let Some(datagram) = self.socket.recv() {
+            self.stats.datagrams_received += 1;
+            self.stats.bytes_received += datagram.len() as u64;
+            let received = self.connection.receive(&datagram, now);
+            if let Some(reply) = received.reply {
+                self.send_datagram(&reply);
+            }
+            let Some(payload) = received.payload else {
+                continue;
+            };
+
+            match Message::decode(&payload, &self.bounds) {
+                Ok(Message::Accepted { entity_id }) => {
+                    log::info!("Joined as entity {}", entity_id);
+                    self.entity_id = Some(entity_id);
+                }
+                Ok(Message::ServerSnapshot {
+                    tick,
+                    last_input_tick,
+                    entities,
+                }) => {
+                    // Reordered snapshots would rewind the prediction to an older state,
+                    // the other entities can still blend with them
+                    if self.latest_tick.is_none_or(|latest| tick > latest) {
+                        self.latest_tick = Some(tick);
+                        self.sync_clock(tick);
+                        self.reconcile(last_input_tick, &entities);
+                    }
+                    self.stats.snapshots += 1;
+                    self.snapshots.push(Snapshot { tick, entities });
+                }
+                Ok(message) => log::debug!("Ignored {:?}", message),
+                Err(e) => log::debug!("Dropped a message: {}", e),
+            }
+        }
+    }
+
+    fn reconcile(&mut self, last_input_tick: u32, entities: &[EntityState]) {
+        let Some(own) = entities
+            .iter()
+            .find(|entity| Some(entity.id) == self.entity_id)
+        else {
+            return;
+        };
+        self.own_state = Some(*own);
+
+        let server_state = BodyState {
+            position: own.position,
+            velocity: own.velocity,
+        };
+        match &mut self.predictor {
+            Some(predictor) => predictor.reconcile(last_input_tick, server_state),
+            None => self.predictor = Some(Predictor::new(server_state, 1.0 / SERVER_TICK_RATE)),
+        }
+    }
+
+    // Nudges the clock towards the newest snapshot, so jitter doesn't make movement stutter
+    fn sync_clock(&mut self, tick: u32) {
+        let tick_time = tick as f32 / SERVER_TICK_RATE;
+        self.server_time = Some(match self.server_time {
+            Some(server_time) if (tick_time - server_time).abs() < MAX_CLOCK_DRIFT => {
+                server_time + (tick_time - server_time) * 0.1
+            }
+            _ => tick_time,
+        });
+    }
+
+    fn send(&mut self, message: &Message, now: f64) {
+        let payload = message.encode(&self.bounds);
+        let datagram = self.connection.send(&payload, message.is_reliable(), now);
+        self.send_datagram(&datagram);
+    }
+
+    fn send_datagram(&mut self, datagram: &[u8]) {
+        self.stats.datagrams_sent += 1;
+        self.stats.bytes_sent += datagram.len() as u64;
+        self.socket.send(datagram);
+    }
+}
+
+impl Drop for NetworkClient {
+    // Best effort, the server times us out otherwise
+    fn drop(&mut self) {
+        if self.entity_id.is_some() {
+            let payload = Message::Leave.encode(&self.bounds);
+            let datagram = self.connection.send(&payload, true, 0.0);
+            self.send_datagram(&datagram);
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_DATAGRAM_SIZE: usize = 1200;
+
+#[cfg(not(target_arch = "wasm32"))]
+struct Socket(std::net::UdpSocket);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Socket {
+    fn connect(address: &str) -> anyhow::Result<Self> {
+        use std::net::{ToSocketAddrs, UdpSocket};
+
+        use anyhow::Context;
+
+        let address = address
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve {}", address))?
+            .next()
+            .with_context(|| format!("No address for {}", address))?;
+        let socket = UdpSocket::bind(if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        socket.connect(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self(socket))
+    }
+
+    // The next datagram that arrived, None once there are no more
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        use std::io::ErrorKind;
+
+        let mut buffer = [0u8; MAX_DATAGRAM_SIZE];
+        match self.0.recv(&mut buffer) {
+            Ok(length) => Some(buffer[..length].to_vec()),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => None,
+            // The server isn't up (yet), we keep resending the join until the timeout
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => None,
+            Err(e) => {
+                log::warn!("Failed to receive: {}", e);
+                None
+            }
+        }
+    }
+
+    // Losing a datagram is normal for UDP, failing to send one is treated the same way
+    fn send(&self, datagram: &[u8]) {
+        if let Err(e) = self.0.send(datagram) {
+            log::debug!("Failed to send: {}", e);
+        }
+    }
+}
+
+// Browsers can't send UDP, each binary message carries one datagram instead. The server
+// listens for WebSockets on its own port, see server/src/websocket.rs.
+#[cfg(target_arch = "wasm32")]
+struct Socket {
+    websocket: web_sys::WebSocket,
+    received: Rc<RefCell<VecDeque<Vec<u8>>>>,
+    _on_message: wasm_bindgen::closure::Closure<dyn FnMut(web_sys::MessageEvent)>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Socket {
+    // host:port is a ws:// URL, wss:// has to be spelled out
+    fn connect(address: &str) -> anyhow::Result<Self> {
+        use wasm_bindgen::{JsCast, closure::Closure};
+
+        let url = if address.contains("://") {
+            address.to_string()
+        } else {
+            format!("ws://{}", address)
+        };
+        let websocket = web_sys::WebSocket::new(&url)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {:?}", url, e))?;
+        websocket.set_binary_type(web_sys::BinaryType::Arraybuffer);
+
+        let received = Rc::new(RefCell::new(VecDeque::new()));
+        let queue = Rc::clone(&received);
+        let on_message = Closure::<dyn FnMut(_)>::new(move |event: web_sys::MessageEvent| {
+            if let Ok(buffer) = event.data().dyn_into::<js_sys::ArrayBuffer>() {
+                let datagram = js_sys::Uint8Array::new(&buffer).to_vec();
+                queue.borrow_mut().push_back(datagram);
+            }
+        });
+        websocket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        Ok(Self {
+            websocket,
+            received,
+            _on_message: on_message,
+        })
+    }
+
+    // The next message that arrived, None once there are no more
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        self.received.borrow_mut().pop_front()
+    }
+
+    // Until the socket is open the datagrams are dropped, the join is resent like after a
+    // lost datagram
+    fn send(&self, datagram: &[u8]) {
+        if self.websocket.ready_state() != web_sys::WebSocket::OPEN {
+            return;
+        }
+        if let Err(e) = self.websocket.send_with_u8_array(datagram) {
+            log::debug!("Failed to send: {:?}", e);
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for Socket {
+    // After the leave, the browser sends what is queued before it closes
+    fn drop(&mut self) {
+        self.websocket.set_onmessage(None);
+        let _ = self.websocket.close();
+    }
+}
@@ -0,0 +1,330 @@
+// Arrows at the edge of the screen pointing towards things out of view. A hit on the player
+// from out of view shows one towards the attacker for a moment, pinned objectives show one
+// for as long as they are pinned.
+
+use shared::math::*;
+
+use crate::components::Entity;
+use crate::renderer::{Renderer, SpriteSpace, render_data::SpriteRenderJob};
+
+const DAMAGE_LIFETIME: f32 = 2.0;
+const FADE_TIME: f32 = 0.75; // At the end of the lifetime
+const EDGE_INSET: f32 = 48.0; // From the edges of the screen to the tips of the arrows
+const ARM_SIZE: Vec2 = Vec2::new(18.0, 5.0);
+
+// In pixels from the top left, None behind the camera
+pub fn get_screen_position(
+    view_projection: Mat4,
+    position: Vec3,
+    screen_size: Vec2,
+) -> Option<Vec2> {
+    let clip = view_projection * position.extend(1.0);
+    if clip.w <= 0.0 {
+        return None;
+    }
+    let ndc = clip.xy() / clip.w;
+    Some(Vec2::new(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5) * screen_size)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EdgeArrow {
+    pub position: Vec2, // In pixels, on the rectangle inset from the edges
+    pub rotation: f32,  // Radians clockwise on screen, 0 points right
+}
+
+// None when the position is on screen. The arrow is where the line from the middle of the
+// screen towards the position crosses the inset rectangle. Behind the camera dividing by w
+// mirrors the position through the middle, so the direction is taken from the clip position
+// as it is, which is still on the side the position is on.
+pub fn get_edge_arrow(
+    view_projection: Mat4,
+    position: Vec3,
+    screen_size: Vec2,
+    inset: f32,
+) -> Option<EdgeArrow> {
+    let clip = view_projection * position.extend(1.0);
+    let direction = if clip.w > 0.0 {
+        let ndc = clip.xy() / clip.w;
+        if ndc.abs().max_element() <= 1.0 {
+            return None;
+        }
+        ndc
+    } else {
+        clip.xy()
+    };
+
+    // In pixels, y points down on screen
+    let half_size = screen_size * 0.5;
+    let mut direction = Vec2::new(direction.x, -direction.y) * half_size;
+    if direction.length_squared() < 1e-12 {
+        // Right behind the camera, any side is as good
+        direction = Vec2::Y;
+    }
+    let half_extent = (half_size - Vec2::splat(inset)).max(Vec2::ZERO);
+    // Dividing by a zero component gives infinity, the other one decides then
+    let scale = (half_extent / direction.abs()).min_element();
+    Some(EdgeArrow {
+        position: half_size + direction * scale,
+        rotation: direction.y.atan2(direction.x),
+    })
+}
+
+struct DamageIndicator {
+    source: Entity,
+    position: Vec3, // Where the source is, or was last
+    age: f32,
+}
+
+#[derive(Default)]
+pub struct OffscreenIndicators {
+    damage: Vec<DamageIndicator>,
+    objectives: Vec<(u32, Vec3)>,
+    next_objective: u32,
+}
+
+impl OffscreenIndicators {
+    // Another hit from the same source starts its arrow over
+    pub fn push_damage(&mut self, source: Entity, position: Vec3) {
+        match self
+            .damage
+            .iter_mut()
+            .find(|indicator| indicator.source == source)
+        {
+            Some(indicator) => {
+                indicator.position = position;
+                indicator.age = 0.0;
+            }
+            None => self.damage.push(DamageIndicator {
+                source,
+                position,
+                age: 0.0,
+            }),
+        }
+    }
+
+    // The id unpins it again
+    pub fn pin_objective(&mut self, position: Vec3) -> u32 {
+        self.next_objective += 1;
+        self.objectives.push((self.next_objective, position));
+        self.next_objective
+    }
+
+    pub fn unpin_objective(&mut self, id: u32) -> bool {
+        let count = self.objectives.len();
+        self.objectives.retain(|(objective, _)| *objective != id);
+        self.objectives.len() < count
+    }
+
+    // The arrows follow their sources while they are around, the ones of sources that are
+    // gone keep pointing at where they were last
+    pub fn update(&mut self, dt: f32, get_position: impl Fn(Entity) -> Option<Vec3>) {
+        for indicator in self.damage.iter_mut() {
+            indicator.age += dt;
+            if let Some(position) = get_position(indicator.source) {
+                indicator.position = position;
+            }
+        }
+        self.damage
+            .retain(|indicator| indicator.age < DAMAGE_LIFETIME);
+    }
+
+    pub fn render(&self, renderer: &mut Renderer, view_projection: Mat4, screen_size: Vec2) {
+        let objectives = self
+            .objectives
+            .iter()
+            .map(|(_, position)| (*position, Vec4::new(1.0, 0.85, 0.2, 0.9)));
+        let damage = self.damage.iter().map(|indicator| {
+            let alpha = ((DAMAGE_LIFETIME - indicator.age) / FADE_TIME).clamp(0.0, 1.0);
+            (indicator.position, Vec4::new(0.95, 0.15, 0.1, 0.9 * alpha))
+        });
+        for (position, color) in objectives.chain(damage) {
+            if let Some(arrow) = get_edge_arrow(view_projection, position, screen_size, EDGE_INSET)
+            {
+                submit_arrow(renderer, arrow, color);
+            }
+        }
+    }
+}
+
+// A chevron of two arms going back from the tip
+fn submit_arrow(renderer: &mut Renderer, arrow: EdgeArrow, color: Vec4) {
+    for side in [-1.0, 1.0] {
+        let rotation = arrow.rotation + side * std::f32::consts::FRAC_PI_4 * 3.0;
+        let center = arrow.position + Vec2::from_angle(rotation) * ARM_SIZE.x * 0.5;
+        renderer.submit(&SpriteRenderJob {
+            space: SpriteSpace::Absolute,
+            rotation,
+            ..SpriteRenderJob::solid(center - ARM_SIZE * 0.5, ARM_SIZE, color, 0)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Entities;
+
+    const SCREEN_SIZE: Vec2 = Vec2::new(1600.0, 900.0);
+    const INSET: f32 = 50.0;
+
+    fn get_view_projection(eye: Vec3, target: Vec3) -> Mat4 {
+        let projection = Mat4::perspective_rh(
+            60f32.to_radians(),
+            SCREEN_SIZE.x / SCREEN_SIZE.y,
+            0.1,
+            1000.0,
+        );
+        projection * Mat4::look_at_rh(eye, target, Vec3::Y)
+    }
+
+    fn get_arrow(view_projection: Mat4, position: Vec3) -> EdgeArrow {
+        get_edge_arrow(view_projection, position, SCREEN_SIZE, INSET).unwrap()
+    }
+
+    fn assert_angle(angle: f32, expected: f32) {
+        let difference = (angle - expected + std::f32::consts::PI)
+            .rem_euclid(std::f32::consts::TAU)
+            - std::f32::consts::PI;
+        assert!(difference.abs() < 1e-3, "{} is not {}", angle, expected);
+    }
+
+    // On the inset rectangle, at least one side touching it and none past it
+    fn assert_on_inset_rect(arrow: EdgeArrow) {
+        let min = Vec2::splat(INSET);
+        let max = SCREEN_SIZE - INSET;
+        let position = arrow.position;
+        assert!(
+            position.cmpge(min - 1e-2).all() && position.cmple(max + 1e-2).all(),
+            "{:?}",
+            arrow
+        );
+        let distance = (position - min).abs().min((max - position).abs());
+        assert!(distance.min_element() < 1e-2, "{:?}", arrow);
+    }
+
+    #[test]
+    fn positions_on_screen_get_no_arrow() {
+        let view_projection = get_view_projection(Vec3::ZERO, Vec3::NEG_Z);
+        for position in [
+            Vec3::new(0.0, 0.0, -10.0),
+            Vec3::new(5.0, -2.0, -20.0),
+            Vec3::new(-0.5, 0.5, -1.0),
+        ] {
+            assert_eq!(
+                get_edge_arrow(view_projection, position, SCREEN_SIZE, INSET),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn arrows_point_along_the_screen_towards_positions_in_front() {
+        let view_projection = get_view_projection(Vec3::ZERO, Vec3::NEG_Z);
+
+        // Far to the right, level with the camera
+        let arrow = get_arrow(view_projection, Vec3::new(100.0, 0.0, -10.0));
+        assert!((arrow.position - Vec2::new(SCREEN_SIZE.x - INSET, 450.0)).length() < 1e-2);
+        assert_angle(arrow.rotation, 0.0);
+
+        // Above and to the left, clockwise from pointing right with y down
+        let arrow = get_arrow(view_projection, Vec3::new(-100.0, 100.0, -10.0));
+        assert!(arrow.position.x < 800.0 && arrow.position.y < 450.0);
+        assert!(arrow.rotation < -std::f32::consts::FRAC_PI_2);
+        assert_on_inset_rect(arrow);
+
+        // The arrow is on the line from the middle of the screen through the position
+        let position = Vec3::new(30.0, -40.0, -20.0);
+        let arrow = get_arrow(view_projection, position);
+        let projected = get_screen_position(view_projection, position, SCREEN_SIZE).unwrap();
+        let towards = (projected - SCREEN_SIZE * 0.5).normalize();
+        let along = (arrow.position - SCREEN_SIZE * 0.5).normalize();
+        assert!(towards.abs_diff_eq(along, 1e-4), "{} {}", towards, along);
+        assert_angle(arrow.rotation, towards.y.atan2(towards.x));
+        assert_on_inset_rect(arrow);
+    }
+
+    #[test]
+    fn positions_behind_the_camera_keep_their_side() {
+        let view_projection = get_view_projection(Vec3::ZERO, Vec3::NEG_Z);
+
+        // Dividing by the negative w would put these on the left and at the bottom
+        let arrow = get_arrow(view_projection, Vec3::new(5.0, 0.0, 10.0));
+        assert_angle(arrow.rotation, 0.0);
+        assert!((arrow.position.x - (SCREEN_SIZE.x - INSET)).abs() < 1e-2);
+        let arrow = get_arrow(view_projection, Vec3::new(0.0, 5.0, 10.0));
+        assert_angle(arrow.rotation, -std::f32::consts::FRAC_PI_2);
+        assert!((arrow.position - Vec2::new(800.0, INSET)).length() < 1e-2);
+
+        // Right behind it there is no side, down is picked
+        let arrow = get_arrow(view_projection, Vec3::new(0.0, 0.0, 10.0));
+        assert_angle(arrow.rotation, std::f32::consts::FRAC_PI_2);
+        assert!((arrow.position - Vec2::new(800.0, SCREEN_SIZE.y - INSET)).length() < 1e-2);
+
+        // Closer than the near plane is out of view as well
+        let arrow = get_arrow(view_projection, Vec3::new(-1.0, 0.0, -0.05));
+        assert_angle(arrow.rotation, std::f32::consts::PI);
+    }
+
+    #[test]
+    fn arrows_follow_the_camera_orientation() {
+        // Looking down -X the right of the screen is -Z
+        let view_projection = get_view_projection(Vec3::ZERO, Vec3::NEG_X);
+        assert_angle(
+            get_arrow(view_projection, Vec3::new(-1.0, 0.0, -50.0)).rotation,
+            0.0,
+        );
+        assert_angle(
+            get_arrow(view_projection, Vec3::new(3.0, 0.0, 50.0)).rotation,
+            std::f32::consts::PI,
+        );
+
+        // The game's camera, above and behind looking down at the player. What is behind the
+        // player and out of view below the screen is at the bottom, even once it is behind
+        // the camera as well.
+        let eye = Vec3::new(0.0, 12.0, 10.0);
+        let view_projection = get_view_projection(eye, Vec3::ZERO);
+        for position in [Vec3::new(0.0, 0.0, 9.0), Vec3::new(0.0, 0.0, 30.0)] {
+            let arrow = get_arrow(view_projection, position);
+            assert_angle(arrow.rotation, std::f32::consts::FRAC_PI_2);
+            assert_on_inset_rect(arrow);
+        }
+        // Behind the camera and to the side, on the side and below the middle
+        let arrow = get_arrow(view_projection, Vec3::new(-40.0, 0.0, 30.0));
+        assert!(
+            arrow.position.x < 800.0 && arrow.position.y > 450.0,
+            "{:?}",
+            arrow
+        );
+        assert_on_inset_rect(arrow);
+        // Far ahead above the top of the screen
+        let arrow = get_arrow(view_projection, Vec3::new(0.0, 0.0, -300.0));
+        assert_angle(arrow.rotation, -std::f32::consts::FRAC_PI_2);
+    }
+
+    #[test]
+    fn damage_arrows_fade_and_objectives_stay() {
+        let mut entities = Entities::default();
+        let (first, second) = (entities.spawn(), entities.spawn());
+        let mut indicators = OffscreenIndicators::default();
+        indicators.push_damage(first, Vec3::X);
+        indicators.push_damage(second, Vec3::Y);
+        let objective = indicators.pin_objective(Vec3::Z);
+
+        // The first source moves and hits again, the second one is gone
+        indicators.update(DAMAGE_LIFETIME * 0.75, |entity| {
+            (entity == first).then_some(Vec3::splat(5.0))
+        });
+        indicators.push_damage(first, Vec3::splat(6.0));
+        indicators.update(DAMAGE_LIFETIME * 0.5, |_| None);
+        assert_eq!(indicators.damage.len(), 1);
+        assert_eq!(indicators.damage[0].source, first);
+        assert_eq!(indicators.damage[0].position, Vec3::splat(6.0));
+
+        indicators.update(DAMAGE_LIFETIME, |_| None);
+        assert!(indicators.damage.is_empty());
+        assert_eq!(indicators.objectives, [(objective, Vec3::Z)]);
+        assert!(indicators.unpin_objective(objective));
+        assert!(!indicators.unpin_objective(objective));
+        assert!(indicators.objectives.is_empty());
+    }
+}
@@ -0,0 +1,644 @@
+// Options the client is started with. Natively they come from the command line, parsed by
+// clap like the tools, and from settings.ron next to the executable, in the browser from the
+// query of the page URL and from the data attributes of the canvas. The command line overrides the settings file, which
+// overrides the defaults, and the same way the query overrides the canvas attributes.
+//
+//   client --width 1280 --height 720 --level arena --connect 127.0.0.1:7777
+//   client --headless 600 --log-level debug
+//   index.html?level=arena&render-scale=0.5&no-vsync&connect=127.0.0.1:7778
+//
+// settings.ron holds the same options with underscores, e.g. (render_scale: 0.5, vsync: false).
+
+use anyhow::{Context, anyhow, bail};
+use serde::Deserialize;
+
+use crate::{crash::FailureKind, renderer::safe_area::UiDpiMode};
+
+// The names in the query and the canvas attributes, the same as on the command line. Flags
+// have no value, "no-vsync" is the same as "no-vsync=true".
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+const FLAGS: [&str; 7] = [
+    "fullscreen",
+    "no-vsync",
+    "low-latency",
+    "transparent",
+    "letterbox",
+    "xray",
+    "gpu-culling",
+];
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+const VALUES: [&str; 13] = [
+    "width",
+    "height",
+    "level",
+    "connect",
+    "headless",
+    "render-scale",
+    "log-level",
+    "asset-dir",
+    "ui-scale",
+    "frames-in-flight",
+    "trigger-failure",
+    // The names these had before
+    "server",
+    "assets",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientOptions {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fullscreen: Option<bool>,
+    pub level: Option<String>, // levels/<name>.json in the asset directory
+    pub connect: Option<String>,
+    pub headless: Option<u32>, // Fixed steps simulated without a window before exiting
+    pub render_scale: Option<f32>,
+    pub vsync: Option<bool>,
+    pub log_level: Option<String>,
+    pub asset_dir: Option<String>,
+    pub ui_scale: Option<String>, // "os" or a scale
+    pub frames_in_flight: Option<u32>,
+    pub low_latency: Option<bool>,
+    pub transparent: Option<bool>,
+    pub letterbox: Option<bool>,
+    pub xray: Option<bool>, // Silhouettes of units behind walls, on by default
+    pub gpu_culling: Option<bool>,
+    pub trigger_failure: Option<String>,
+}
+
+// Flags take an optional "=false", so the command line can turn off what the settings file
+// turns on
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(clap::Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(long)]
+    width: Option<u32>,
+    #[arg(long)]
+    height: Option<u32>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true",
+          value_parser = clap::builder::BoolishValueParser::new())]
+    fullscreen: Option<bool>,
+    /// levels/<name>.json in the asset directory
+    #[arg(long)]
+    level: Option<String>,
+    /// The address of a server to play on
+    #[arg(long, alias = "server")]
+    connect: Option<String>,
+    /// Fixed steps to simulate without a window before exiting
+    #[arg(long, value_name = "TICKS")]
+    headless: Option<u32>,
+    /// Of the scene, from 0.25 to 1
+    #[arg(long)]
+    render_scale: Option<f32>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true",
+          value_parser = clap::builder::BoolishValueParser::new())]
+    no_vsync: Option<bool>,
+    /// off, error, warn, info, debug or trace
+    #[arg(long)]
+    log_level: Option<String>,
+    #[arg(long, alias = "assets")]
+    asset_dir: Option<String>,
+    /// os or a scale
+    #[arg(long)]
+    ui_scale: Option<String>,
+    /// From 1 to 3
+    #[arg(long)]
+    frames_in_flight: Option<u32>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true",
+          value_parser = clap::builder::BoolishValueParser::new())]
+    low_latency: Option<bool>,
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true",
+          value_parser = clap::builder::BoolishValueParser::new())]
+    transparent: Option<bool>,
+    /// Bars beside the reference screen instead of more of the world on other aspect ratios
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true",
+          value_parser = clap::builder::BoolishValueParser::new())]
+    letterbox: Option<bool>,
+    /// Silhouettes of units behind walls, on unless turned off with --xray=false
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true",
+          value_parser = clap::builder::BoolishValueParser::new())]
+    xray: Option<bool>,
+    /// Culls the level's instances one by one on the GPU, where the device can
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true",
+          value_parser = clap::builder::BoolishValueParser::new())]
+    gpu_culling: Option<bool>,
+    /// panic, surface, validation or device, to test the crash handling
+    #[arg(long)]
+    trigger_failure: Option<String>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<Args> for ClientOptions {
+    fn from(args: Args) -> Self {
+        Self {
+            width: args.width,
+            height: args.height,
+            fullscreen: args.fullscreen,
+            level: args.level,
+            connect: args.connect,
+            headless: args.headless,
+            render_scale: args.render_scale,
+            vsync: args.no_vsync.map(|no_vsync| !no_vsync),
+            log_level: args.log_level,
+            asset_dir: args.asset_dir,
+            ui_scale: args.ui_scale,
+            frames_in_flight: args.frames_in_flight,
+            low_latency: args.low_latency,
+            transparent: args.transparent,
+            letterbox: args.letterbox,
+            xray: args.xray,
+            gpu_culling: args.gpu_culling,
+            trigger_failure: args.trigger_failure,
+        }
+    }
+}
+
+impl ClientOptions {
+    pub const DEFAULT_LEVEL: &str = "default";
+    // Where the assets are fetched from without --asset-dir, next to the page in the browser
+    // and the repository's folder natively
+    #[cfg(target_arch = "wasm32")]
+    pub const DEFAULT_ASSET_BASE: &str = "assets";
+    #[cfg(not(target_arch = "wasm32"))]
+    pub const DEFAULT_ASSET_BASE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../assets");
+
+    // "level=arena&no-vsync", with or without the leading "?". Only the browser has one.
+    #[allow(dead_code)]
+    pub fn parse_query(query: &str) -> anyhow::Result<Self> {
+        let mut options = Self::default();
+        let pairs = query
+            .trim_start_matches('?')
+            .split('&')
+            .filter(|pair| !pair.is_empty());
+        for pair in pairs {
+            let (name, value) = match pair.split_once('=') {
+                Some((name, value)) => (name, Some(decode_query_component(value)?)),
+                None => (pair, None),
+            };
+            options.set(name, value.as_deref())?;
+        }
+        Ok(options)
+    }
+
+    pub fn load_settings(bytes: &[u8]) -> anyhow::Result<Self> {
+        let options = ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME);
+        let mut deserializer = ron::Deserializer::from_bytes_with_options(bytes, options)?;
+        let settings: Self =
+            serde_path_to_error::deserialize(&mut deserializer).map_err(|error| {
+                let path = error.path().to_string();
+                anyhow!("{}: {}", path, deserializer.span_error(error.into_inner()))
+            })?;
+        deserializer
+            .end()
+            .map_err(|error| deserializer.span_error(error))?;
+        Ok(settings)
+    }
+
+    // For the query and the canvas attributes. A value is None when the option wasn't given,
+    // flags may be given as true or false.
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    pub fn set(&mut self, name: &str, value: Option<&str>) -> anyhow::Result<()> {
+        if FLAGS.contains(&name) {
+            let enabled = match value {
+                None | Some("") | Some("true") | Some("1") => true,
+                Some("false") | Some("0") => false,
+                Some(value) => bail!("--{} is a flag, got '{}'", name, value),
+            };
+            match name {
+                "fullscreen" => self.fullscreen = Some(enabled),
+                "no-vsync" => self.vsync = Some(!enabled),
+                "low-latency" => self.low_latency = Some(enabled),
+                "transparent" => self.transparent = Some(enabled),
+                "letterbox" => self.letterbox = Some(enabled),
+                "xray" => self.xray = Some(enabled),
+                "gpu-culling" => self.gpu_culling = Some(enabled),
+                _ => unreachable!(),
+            }
+            return Ok(());
+        }
+
+        if !VALUES.contains(&name) {
+            bail!("Unknown option --{}", name);
+        }
+        let value = value
+            .filter(|value| !value.is_empty())
+            .with_context(|| format!("--{} needs a value", name))?;
+        match name {
+            "width" => self.width = Some(parse_number(name, value)?),
+            "height" => self.height = Some(parse_number(name, value)?),
+            "level" => self.level = Some(value.to_string()),
+            "connect" | "server" => self.connect = Some(value.to_string()),
+            "headless" => self.headless = Some(parse_number(name, value)?),
+            "render-scale" => self.render_scale = Some(parse_number(name, value)?),
+            "log-level" => self.log_level = Some(value.to_string()),
+            "asset-dir" | "assets" => self.asset_dir = Some(value.to_string()),
+            "ui-scale" => self.ui_scale = Some(value.to_string()),
+            "frames-in-flight" => self.frames_in_flight = Some(parse_number(name, value)?),
+            "trigger-failure" => self.trigger_failure = Some(value.to_string()),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    // Each option given here wins over the one in the fallback
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            width: self.width.or(fallback.width),
+            height: self.height.or(fallback.height),
+            fullscreen: self.fullscreen.or(fallback.fullscreen),
+            level: self.level.or(fallback.level),
+            connect: self.connect.or(fallback.connect),
+            headless: self.headless.or(fallback.headless),
+            render_scale: self.render_scale.or(fallback.render_scale),
+            vsync: self.vsync.or(fallback.vsync),
+            log_level: self.log_level.or(fallback.log_level),
+            asset_dir: self.asset_dir.or(fallback.asset_dir),
+            ui_scale: self.ui_scale.or(fallback.ui_scale),
+            frames_in_flight: self.frames_in_flight.or(fallback.frames_in_flight),
+            low_latency: self.low_latency.or(fallback.low_latency),
+            transparent: self.transparent.or(fallback.transparent),
+            letterbox: self.letterbox.or(fallback.letterbox),
+            xray: self.xray.or(fallback.xray),
+            gpu_culling: self.gpu_culling.or(fallback.gpu_culling),
+            trigger_failure: self.trigger_failure.or(fallback.trigger_failure),
+        }
+    }
+
+    // Checked before the window is opened. The files are only looked at natively.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match (self.width, self.height) {
+            (Some(0), _) | (_, Some(0)) => bail!("--width and --height must be above 0"),
+            (Some(_), None) | (None, Some(_)) => bail!("--width and --height go together"),
+            _ => {}
+        }
+        if let Some(level) = &self.level
+            && (level.is_empty()
+                || !level
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+        {
+            bail!("'{}' isn't a level name", level);
+        }
+        if self.headless == Some(0) {
+            bail!("--headless needs at least one tick");
+        }
+        if let Some(scale) = self.render_scale
+            && !(0.25..=1.0).contains(&scale)
+        {
+            bail!("--render-scale must be between 0.25 and 1, got {}", scale);
+        }
+        if let Some(count) = self.frames_in_flight
+            && !(1..=3).contains(&count)
+        {
+            bail!("--frames-in-flight must be between 1 and 3, got {}", count);
+        }
+        if let Some(level) = &self.log_level
+            && level.parse::<log::LevelFilter>().is_err()
+        {
+            bail!(
+                "--log-level must be off, error, warn, info, debug or trace, got '{}'",
+                level
+            );
+        }
+        if self.ui_scale.is_some() && self.get_ui_dpi_mode().is_none() {
+            bail!("--ui-scale must be os or a scale");
+        }
+        if let Some(name) = &self.trigger_failure
+            && FailureKind::parse(name).is_none()
+        {
+            bail!("--trigger-failure must be panic, surface, validation or device");
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            use std::path::Path;
+
+            if let Some(directory) = &self.asset_dir
+                && !Path::new(directory).is_dir()
+            {
+                bail!("The asset directory {} doesn't exist", directory);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_size(&self) -> Option<(u32, u32)> {
+        Some((self.width?, self.height?))
+    }
+
+    pub fn get_asset_base(&self) -> &str {
+        self.asset_dir
+            .as_deref()
+            .unwrap_or(Self::DEFAULT_ASSET_BASE)
+    }
+
+    pub fn get_level_name(&self) -> &str {
+        self.level.as_deref().unwrap_or(Self::DEFAULT_LEVEL)
+    }
+
+    pub fn get_log_level(&self) -> Option<log::LevelFilter> {
+        self.log_level.as_deref()?.parse().ok()
+    }
+
+    pub fn get_ui_dpi_mode(&self) -> Option<UiDpiMode> {
+        match self.ui_scale.as_deref()? {
+            "os" => Some(UiDpiMode::FollowOs),
+            value => value.parse().ok().map(UiDpiMode::Fixed),
+        }
+    }
+
+    pub fn get_triggered_failure(&self) -> Option<FailureKind> {
+        FailureKind::parse(self.trigger_failure.as_deref()?)
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.fullscreen.unwrap_or(false)
+    }
+
+    pub fn is_vsync(&self) -> bool {
+        self.vsync.unwrap_or(true)
+    }
+
+    pub fn is_low_latency(&self) -> bool {
+        self.low_latency.unwrap_or(false)
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.transparent.unwrap_or(false)
+    }
+
+    pub fn is_letterbox(&self) -> bool {
+        self.letterbox.unwrap_or(false)
+    }
+
+    pub fn is_xray(&self) -> bool {
+        self.xray.unwrap_or(true)
+    }
+
+    pub fn is_gpu_culling(&self) -> bool {
+        self.gpu_culling.unwrap_or(false)
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> anyhow::Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("--{} expects a number, got '{}'", name, value))
+}
+
+// "+" and "%XX" escapes
+#[allow(dead_code)]
+fn decode_query_component(value: &str) -> anyhow::Result<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let digits = [input.next(), input.next()];
+                let hex = digits
+                    .iter()
+                    .flatten()
+                    .map(|&digit| char::from(digit))
+                    .collect::<String>();
+                let decoded = u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .with_context(|| format!("Invalid escape in '{}'", value))?;
+                bytes.push(decoded);
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).with_context(|| format!("'{}' isn't UTF-8", value))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_settings_path() -> std::path::PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.to_path_buf()))
+        .unwrap_or_default()
+        .join("settings.ron")
+}
+
+// The command line over settings.ron, which may be missing. clap prints the usage errors and
+// --help itself and exits.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_options() -> anyhow::Result<ClientOptions> {
+    use clap::Parser;
+
+    let options = ClientOptions::from(Args::parse());
+    let path = get_settings_path();
+    let settings = match std::fs::read(&path) {
+        Ok(bytes) => ClientOptions::load_settings(&bytes)
+            .with_context(|| format!("Failed to load {}", path.display()))?,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => ClientOptions::default(),
+        Err(error) => {
+            return Err(error).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+    Ok(options.or(settings))
+}
+
+// The page query over <canvas id="canvas" data-render-scale="0.5" data-low-latency>
+#[cfg(target_arch = "wasm32")]
+pub fn get_options() -> anyhow::Result<ClientOptions> {
+    let window = wgpu::web_sys::window().context("No window")?;
+    let query = window
+        .location()
+        .search()
+        .map_err(|_| anyhow!("Failed to read the page URL"))?;
+    let options = ClientOptions::parse_query(&query)?;
+
+    let mut attributes = ClientOptions::default();
+    let canvas = window
+        .document()
+        .and_then(|document| document.get_element_by_id("canvas"));
+    if let Some(canvas) = canvas {
+        for name in FLAGS.into_iter().chain(VALUES) {
+            if let Some(value) = canvas.get_attribute(&format!("data-{}", name)) {
+                attributes.set(name, Some(&value))?;
+            }
+        }
+    }
+    Ok(options.or(attributes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Without the name of the executable
+    fn parse(args: &[&str]) -> Result<ClientOptions, clap::Error> {
+        use clap::Parser;
+
+        let args = std::iter::once(&"client").chain(args);
+        Ok(Args::try_parse_from(args)?.into())
+    }
+
+    #[test]
+    fn arguments_are_parsed() {
+        let options = parse(&[
+            "--width",
+            "1280",
+            "--height=720",
+            "--fullscreen",
+            "--level",
+            "arena",
+            "--server",
+            "127.0.0.1:7777",
+            "--headless",
+            "600",
+            "--render-scale",
+            "0.5",
+            "--no-vsync",
+            "--letterbox=false",
+            "--xray=false",
+            "--gpu-culling",
+            "--log-level=debug",
+            "--assets",
+            "res",
+        ])
+        .unwrap();
+        assert_eq!(options.get_size(), Some((1280, 720)));
+        assert!(options.is_fullscreen());
+        assert_eq!(options.get_level_name(), "arena");
+        assert_eq!(options.connect.as_deref(), Some("127.0.0.1:7777"));
+        assert_eq!(options.headless, Some(600));
+        assert_eq!(options.render_scale, Some(0.5));
+        assert!(!options.is_vsync());
+        assert_eq!(options.get_log_level(), Some(log::LevelFilter::Debug));
+        assert_eq!(options.asset_dir.as_deref(), Some("res"));
+        assert!(!options.is_transparent());
+        assert_eq!(options.letterbox, Some(false));
+        assert!(!options.is_xray());
+        assert!(options.is_gpu_culling());
+
+        let defaults = parse(&[]).unwrap();
+        assert_eq!(defaults, ClientOptions::default());
+        assert_eq!(defaults.get_level_name(), ClientOptions::DEFAULT_LEVEL);
+        assert!(defaults.is_vsync());
+        assert!(defaults.is_xray());
+        assert!(!defaults.is_gpu_culling());
+        assert_eq!(defaults.validate().ok(), Some(()));
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        use clap::error::ErrorKind;
+
+        let errors = [
+            (&["--widht", "1280"][..], ErrorKind::UnknownArgument),
+            (&["--level"], ErrorKind::InvalidValue),
+            (&["--width", "wide"], ErrorKind::ValueValidation),
+            (&["--no-vsync=maybe"], ErrorKind::ValueValidation),
+            (&["arena"], ErrorKind::UnknownArgument),
+            (&["--replay", "a.replay"], ErrorKind::UnknownArgument),
+        ];
+        for (args, kind) in errors {
+            let error = parse(args).unwrap_err();
+            assert_eq!(error.kind(), kind, "{}", error);
+        }
+        // A flag doesn't take the next argument as its value
+        assert!(parse(&["--fullscreen", "--level", "arena"]).is_ok());
+        let error = parse(&["--level="]).unwrap().validate().unwrap_err();
+        assert_eq!(error.to_string(), "'' isn't a level name");
+    }
+
+    #[test]
+    fn invalid_options_are_caught_before_starting() {
+        let errors = [
+            (&["--width", "1280"][..], "--width and --height go together"),
+            (
+                &["--render-scale", "2"],
+                "--render-scale must be between 0.25 and 1, got 2",
+            ),
+            (
+                &["--frames-in-flight", "4"],
+                "--frames-in-flight must be between 1 and 3, got 4",
+            ),
+            (&["--headless", "0"], "--headless needs at least one tick"),
+            (
+                &["--level", "../secrets"],
+                "'../secrets' isn't a level name",
+            ),
+            (
+                &["--log-level", "loud"],
+                "--log-level must be off, error, warn, info, debug or trace, got 'loud'",
+            ),
+            (&["--ui-scale", "big"], "--ui-scale must be os or a scale"),
+        ];
+        for (args, message) in errors {
+            let error = parse(args).unwrap().validate().unwrap_err();
+            assert_eq!(error.to_string(), message);
+        }
+        let error = parse(&["--asset-dir", "missing"])
+            .unwrap()
+            .validate()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The asset directory missing doesn't exist"
+        );
+    }
+
+    #[test]
+    fn queries_use_the_same_names() {
+        let options = ClientOptions::parse_query(
+            "?level=arena&render-scale=0.5&no-vsync&letterbox&connect=ws%3A%2F%2Fhost+1",
+        )
+        .unwrap();
+        assert_eq!(options.get_level_name(), "arena");
+        assert_eq!(options.render_scale, Some(0.5));
+        assert!(!options.is_vsync());
+        assert!(options.is_letterbox());
+        assert_eq!(options.connect.as_deref(), Some("ws://host 1"));
+        assert_eq!(
+            ClientOptions::parse_query("").unwrap(),
+            ClientOptions::default()
+        );
+        assert!(ClientOptions::parse_query("level=%4").is_err());
+        assert!(ClientOptions::parse_query("no-vsync=0").unwrap().is_vsync());
+    }
+
+    #[test]
+    fn command_line_overrides_settings_over_defaults() {
+        let settings = ClientOptions::load_settings(
+            br#"(width: 1920, height: 1080, render_scale: 0.75, vsync: false, level: "arena")"#,
+        )
+        .unwrap();
+        let options = parse(&["--render-scale", "0.5", "--level", "duel"])
+            .unwrap()
+            .or(settings);
+        assert_eq!(options.render_scale, Some(0.5));
+        assert_eq!(options.get_level_name(), "duel");
+        assert_eq!(options.get_size(), Some((1920, 1080)));
+        assert!(!options.is_vsync());
+        // Neither sets it
+        assert!(!options.is_fullscreen());
+
+        // A flag turned off on the command line still wins
+        let options = parse(&["--no-vsync=false"])
+            .unwrap()
+            .or(ClientOptions::load_settings(b"(vsync: false, xray: false)").unwrap());
+        assert!(options.is_vsync());
+        assert!(!options.is_xray());
+    }
+
+    #[test]
+    fn settings_files_are_checked() {
+        assert_eq!(
+            ClientOptions::load_settings(b"()").unwrap(),
+            ClientOptions::default()
+        );
+        let error = ClientOptions::load_settings(b"(render_scael: 0.5)").unwrap_err();
+        assert!(error.to_string().contains("render_scael"), "{}", error);
+        let error = ClientOptions::load_settings(b"(width: \"wide\")").unwrap_err();
+        assert!(error.to_string().starts_with("width:"), "{}", error);
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::VecDeque;
+
+use shared::{
+    math::Vec2,
+    movement::{MoveInput, simulate_body},
+    physics::BodyState,
+};
+
+const MAX_HISTORY: usize = 120; // Two seconds of unacknowledged input at 60 Hz
+// Server states are quantized, differences below this are noise and not worth a replay
+const MATCH_TOLERANCE: f32 = 0.5;
+// Smaller corrections are applied right away, larger ones are smoothed out and anything
+// beyond the limit is a teleport that snaps
+const CORRECTION_THRESHOLD: f32 = 2.0;
+const MAX_SMOOTHED_CORRECTION: f32 = 200.0;
+const CORRECTION_TIME: f32 = 0.1;
+
+struct PredictedTick {
+    tick: u32,
+    input: MoveInput,
+    state: BodyState, // After applying the input
+}
+
+// Moves the local player right away instead of waiting a round trip for the server. Every
+// input is kept until the server acknowledges it, an acknowledged server state that
+// disagrees with our prediction replaces it and the newer inputs are applied on top again.
+pub struct Predictor {
+    dt: f32,
+    state: BodyState,
+    previous_state: BodyState,
+    history: VecDeque<PredictedTick>,
+    correction: Vec2, // Visual offset from the predicted position, shrinks to zero
+    correction_remaining: f32,
+}
+
+impl Predictor {
+    pub fn new(state: BodyState, dt: f32) -> Self {
+        Self {
+            dt,
+            state,
+            previous_state: state,
+            history: VecDeque::with_capacity(MAX_HISTORY),
+            correction: Vec2::ZERO,
+            correction_remaining: 0.0,
+        }
+    }
+
+    pub fn predict(&mut self, tick: u32, input: MoveInput) {
+        self.previous_state = self.state;
+        self.state = simulate_body(self.state, &input, self.dt);
+
+        if self.history.len() >= MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(PredictedTick {
+            tick,
+            input,
+            state: self.state,
+        });
+    }
+
+    // The server state is the one after applying the input of the acknowledged tick
+    pub fn reconcile(&mut self, acked_tick: u32, server_state: BodyState) {
+        let predicted = self
+            .history
+            .iter()
+            .find(|entry| entry.tick == acked_tick)
+            .map(|entry| entry.state);
+        while let Some(entry) = self.history.front()
+            && !is_after(entry.tick, acked_tick)
+        {
+            self.history.pop_front();
+        }
+
+        if predicted.is_some_and(|state| matches(state, server_state)) {
+            return;
+        }
+
+        let mut state = server_state;
+        for entry in self.history.iter_mut() {
+            state = simulate_body(state, &entry.input, self.dt);
+            entry.state = state;
+        }
+
+        let error = self.state.position - state.position;
+        self.previous_state.position -= error;
+        self.state = state;
+
+        let distance = error.length();
+        if distance > MAX_SMOOTHED_CORRECTION {
+            self.previous_state = state;
+            self.correction = Vec2::ZERO;
+            self.correction_remaining = 0.0;
+        } else if distance > CORRECTION_THRESHOLD {
+            self.correction += error;
+            self.correction_remaining = CORRECTION_TIME;
+        }
+    }
+
+    // Called once per frame, shrinks the visual correction linearly
+    pub fn update(&mut self, dt: f32) {
+        if self.correction_remaining <= dt {
+            self.correction = Vec2::ZERO;
+            self.correction_remaining = 0.0;
+            return;
+        }
+
+        self.correction *= 1.0 - dt / self.correction_remaining;
+        self.correction_remaining -= dt;
+    }
+
+    pub fn get_state(&self) -> BodyState {
+        self.state
+    }
+
+    // Between the last two predicted ticks like the local physics, plus the correction
+    pub fn get_visual_position(&self, alpha: f32) -> Vec2 {
+        self.previous_state
+            .position
+            .lerp(self.state.position, alpha)
+            + self.correction
+    }
+}
+
+fn matches(a: BodyState, b: BodyState) -> bool {
+    a.position.distance(b.position) <= MATCH_TOLERANCE
+        && a.velocity.distance(b.velocity) <= MATCH_TOLERANCE
+}
+
+// Tick comparison that survives the wrap around
+pub(crate) fn is_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::{movement::MOVEMENT_SPEED, net::ACTION_MOVE};
+
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+    const LATENCY: u32 = 6; // Ticks each way
+    const SNAPSHOT_INTERVAL: u32 = 3;
+    const PUSH_TICK: u32 = 60;
+    const PUSH: Vec2 = Vec2::new(40.0, 0.0);
+
+    // Delivers after a fixed number of ticks
+    struct Link<T> {
+        in_flight: VecDeque<(u32, T)>,
+    }
+
+    impl<T> Link<T> {
+        fn new() -> Self {
+            Self {
+                in_flight: VecDeque::new(),
+            }
+        }
+
+        fn send(&mut self, now: u32, value: T) {
+            self.in_flight.push_back((now + LATENCY, value));
+        }
+
+        fn receive(&mut self, now: u32) -> Vec<T> {
+            let mut delivered = Vec::new();
+            while self.in_flight.front().is_some_and(|(at, _)| *at <= now) {
+                delivered.push(self.in_flight.pop_front().unwrap().1);
+            }
+            delivered
+        }
+    }
+
+    // Steps like the real server, one queued input per tick and the last one repeated when
+    // none arrived, plus a push from something the client doesn't know about
+    struct FakeServer {
+        state: BodyState,
+        queued: VecDeque<(u32, MoveInput)>,
+        input: MoveInput,
+        last_input_tick: u32,
+    }
+
+    impl FakeServer {
+        fn step(&mut self, tick: u32) {
+            if let Some((input_tick, input)) = self.queued.pop_front() {
+                self.input = input;
+                self.last_input_tick = input_tick;
+            }
+            self.state = simulate_body(self.state, &self.input, DT);
+            if tick == PUSH_TICK {
+                self.state.position += PUSH;
+            }
+        }
+    }
+
+    fn get_input(tick: u32) -> MoveInput {
+        if tick <= 90 {
+            MoveInput {
+                action_bits: ACTION_MOVE,
+                cursor_world: Vec2::new(400.0, 200.0),
+            }
+        } else {
+            MoveInput::default()
+        }
+    }
+
+    #[test]
+    fn converges_to_the_server_despite_latency_and_loss() {
+        let start = BodyState {
+            position: Vec2::ZERO,
+            velocity: Vec2::ZERO,
+        };
+        let mut predictor = Predictor::new(start, DT);
+        let mut server = FakeServer {
+            state: start,
+            queued: VecDeque::new(),
+            input: MoveInput::default(),
+            last_input_tick: 0,
+        };
+        let mut inputs = Link::new();
+        let mut snapshots = Link::new();
+
+        let mut max_visual_step: f32 = 0.0;
+        let mut corrected = false;
+        let mut last_visual = predictor.get_visual_position(1.0);
+        for tick in 1..=300 {
+            // Client
+            for (acked_tick, state) in snapshots.receive(tick) {
+                predictor.reconcile(acked_tick, state);
+            }
+            let input = get_input(tick);
+            predictor.predict(tick, input);
+            predictor.update(DT);
+            if tick % 7 != 0 {
+                inputs.send(tick, (tick, input));
+            }
+
+            let visual = predictor.get_visual_position(1.0);
+            max_visual_step = max_visual_step.max(visual.distance(last_visual));
+            corrected |= predictor.correction != Vec2::ZERO;
+            last_visual = visual;
+
+            // Server
+            server.queued.extend(inputs.receive(tick));
+            server.step(tick);
+            if tick % SNAPSHOT_INTERVAL == 0 && tick % (5 * SNAPSHOT_INTERVAL) != 0 {
+                snapshots.send(tick, (server.last_input_tick, server.state));
+            }
+        }
+
+        // The push was smoothed out over several frames instead of a 40 unit jump
+        assert!(corrected);
+        let walking_step = MOVEMENT_SPEED * DT;
+        assert!(max_visual_step < walking_step + PUSH.length() * 0.5);
+
+        // At rest the prediction agrees with the server and the correction is gone
+        let state = predictor.get_state();
+        assert!(state.position.distance(server.state.position) < 1e-3);
+        assert!(
+            predictor
+                .get_visual_position(1.0)
+                .distance(server.state.position)
+                < 1e-3
+        );
+    }
+
+    #[test]
+    fn matching_server_states_leave_the_prediction_alone() {
+        let start = BodyState {
+            position: Vec2::ZERO,
+            velocity: Vec2::ZERO,
+        };
+        let mut predictor = Predictor::new(start, DT);
+        for tick in 1..=10 {
+            predictor.predict(tick, get_input(tick));
+        }
+        let predicted = predictor.get_state();
+
+        // The server agrees up to quantization
+        let mut server_state = start;
+        for tick in 1..=4 {
+            server_state = simulate_body(server_state, &get_input(tick), DT);
+        }
+        server_state.position += Vec2::splat(0.05);
+        predictor.reconcile(4, server_state);
+
+        assert_eq!(predictor.get_state(), predicted);
+        assert_eq!(predictor.history.len(), 6);
+    }
+
+    #[test]
+    fn teleports_snap() {
+        let start = BodyState {
+            position: Vec2::ZERO,
+            velocity: Vec2::ZERO,
+        };
+        let mut predictor = Predictor::new(start, DT);
+        predictor.predict(1, MoveInput::default());
+
+        let teleported = BodyState {
+            position: Vec2::new(1000.0, 0.0),
+            velocity: Vec2::ZERO,
+        };
+        predictor.reconcile(1, teleported);
+        assert_eq!(predictor.get_visual_position(0.0), teleported.position);
+        assert_eq!(predictor.get_visual_position(1.0), teleported.position);
+    }
+}
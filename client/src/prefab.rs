@@ -0,0 +1,578 @@
+// Entities described as data instead of assembled in code. A prefab names the components an
+// entity spawns with and their values, loaded from RON files like assets/prefabs/default.ron.
+// A prefab can inherit from a parent, every field it leaves out comes from the parent, down
+// to the single fields of a component. The spawn overrides win over both, see
+// Game::spawn_prefab.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+use shared::{math::*, physics::CollisionLayer, team::Team, transform::Transform};
+
+use crate::{
+    level::{BlendSampleDesc, ShapeDesc},
+    renderer::{ResourceHandle, ResourceKind, resources::get_handle},
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrefabDesc {
+    pub name: String,
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub renderable: Option<RenderableDesc>,
+    #[serde(default)]
+    pub physics: Option<PhysicsDesc>,
+    #[serde(default)]
+    pub animator: Option<AnimatorDesc>,
+    #[serde(default)]
+    pub health: Option<f32>, // The max health, it spawns with all of it
+    #[serde(default)]
+    pub ai: Option<AiArchetype>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderableDesc {
+    #[serde(default)]
+    pub mesh: Option<String>, // Static or skeletal, skeletal meshes get a pose
+    #[serde(default)]
+    pub material: Option<String>,
+    #[serde(default)]
+    pub color: Option<[f32; 4]>,
+    #[serde(default)]
+    pub scale: Option<[f32; 3]>,
+    #[serde(default)]
+    pub render_rotation: Option<[f32; 3]>, // Euler degrees, like PlayerDesc
+    #[serde(default)]
+    pub casts_shadow: Option<bool>,
+}
+
+// A body at the entity position, the shape is in world units and isn't scaled
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PhysicsDesc {
+    #[serde(default)]
+    pub shape: Option<ShapeDesc>,
+    #[serde(default)]
+    pub layer: Option<LayerDesc>,
+    #[serde(default)]
+    pub sensor: Option<bool>, // Reports its contacts, e.g. a projectile hitting something
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LayerDesc {
+    Environment,
+    Player,
+    PlayerProjectile,
+    Enemy,
+    EnemyProjectile,
+    Neutral,
+    NeutralProjectile,
+}
+
+impl LayerDesc {
+    pub fn get_layer(self) -> CollisionLayer {
+        match self {
+            LayerDesc::Environment => CollisionLayer::Environment,
+            LayerDesc::Player => CollisionLayer::Player,
+            LayerDesc::PlayerProjectile => CollisionLayer::PlayerProjectile,
+            LayerDesc::Enemy => CollisionLayer::Enemy,
+            LayerDesc::EnemyProjectile => CollisionLayer::EnemyProjectile,
+            LayerDesc::Neutral => CollisionLayer::Neutral,
+            LayerDesc::NeutralProjectile => CollisionLayer::NeutralProjectile,
+        }
+    }
+}
+
+// The locomotion blend space of a skeletal mesh, like the player's
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnimatorDesc {
+    #[serde(default)]
+    pub idle_animation: Option<String>,
+    #[serde(default)]
+    pub run_animation: Option<String>,
+    #[serde(default)]
+    pub locomotion: Option<Vec<BlendSampleDesc>>,
+    #[serde(default)]
+    pub attack_animation: Option<String>, // Played by fighters, they hit on its hit event
+}
+
+// What drives the entity, with the components those systems need
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AiArchetype {
+    Unit,    // Walks to its target location, e.g. when selected and ordered
+    Fighter, // A unit that also attacks and takes status effects
+}
+
+// A prefab with its parents folded in and every required field present
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prefab {
+    pub name: String,
+    pub renderable: Option<PrefabRenderable>,
+    pub physics: Option<PrefabPhysics>,
+    pub animator: Option<PrefabAnimator>,
+    pub health: Option<f32>,
+    pub ai: Option<AiArchetype>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefabRenderable {
+    pub mesh: String,
+    pub material: String,
+    pub color: Vec4,
+    pub scale: Vec3,
+    pub render_rotation: [f32; 3],
+    pub casts_shadow: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefabPhysics {
+    pub shape: ShapeDesc,
+    pub layer: LayerDesc,
+    pub sensor: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefabAnimator {
+    pub idle_animation: String,
+    pub run_animation: String,
+    pub locomotion: Vec<BlendSampleDesc>,
+    pub attack_animation: Option<String>,
+}
+
+// Per spawn, e.g. where a wave spawns its enemies and in which team color
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PrefabOverrides {
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub scale: Option<Vec3>, // Replaces the scale of the prefab
+    pub tint: Option<Vec4>,  // Replaces the color of the prefab
+    pub team: Option<Team>,  // Moves the body to the layer of the team, and colors it
+    pub variant: u32,        // The skin, for prefabs with a variant material
+}
+
+impl Prefab {
+    pub fn get_transform(&self, overrides: &PrefabOverrides) -> Transform {
+        let scale = self
+            .renderable
+            .as_ref()
+            .map_or(Vec3::ONE, |renderable| renderable.scale);
+        Transform {
+            position: overrides.position,
+            rotation: overrides.rotation,
+            scale: overrides.scale.unwrap_or(scale),
+        }
+    }
+
+    pub fn get_color(&self, overrides: &PrefabOverrides) -> Vec4 {
+        let color = self
+            .renderable
+            .as_ref()
+            .map_or(Vec4::ONE, |renderable| renderable.color);
+        overrides.tint.unwrap_or(color)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PrefabLibrary {
+    prefabs: HashMap<String, Prefab>,
+}
+
+impl PrefabLibrary {
+    // Parses a file with a list of prefabs and resolves their parents, which have to be in
+    // the same file. Errors name the prefab, e.g. "Enemy: parent \"Characte\" doesn't exist".
+    pub fn load(bytes: &[u8]) -> anyhow::Result<Self> {
+        let options = ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME);
+        let mut deserializer = ron::Deserializer::from_bytes_with_options(bytes, options)?;
+        let descs: Vec<PrefabDesc> =
+            serde_path_to_error::deserialize(&mut deserializer).map_err(|error| {
+                let path = error.path().to_string();
+                anyhow!("{}: {}", path, deserializer.span_error(error.into_inner()))
+            })?;
+        deserializer
+            .end()
+            .map_err(|error| deserializer.span_error(error))?;
+
+        Self::from_descs(&descs)
+    }
+
+    fn from_descs(descs: &[PrefabDesc]) -> anyhow::Result<Self> {
+        let mut by_name: HashMap<&str, &PrefabDesc> = HashMap::new();
+        for desc in descs {
+            if by_name.insert(desc.name.as_str(), desc).is_some() {
+                bail!("{}: there is another prefab with this name", desc.name);
+            }
+        }
+
+        let mut prefabs = HashMap::new();
+        for desc in descs {
+            let resolved = resolve(desc, &by_name)?;
+            prefabs.insert(desc.name.clone(), finish(resolved)?);
+        }
+        Ok(Self { prefabs })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Prefab> {
+        self.prefabs.get(name)
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.prefabs.len()
+    }
+
+    // Checks every resource the prefabs refer to is loaded and of the right kind, once the
+    // level's assets are in
+    pub fn validate(
+        &self,
+        get_kind: impl Fn(ResourceHandle) -> Option<ResourceKind>,
+    ) -> anyhow::Result<()> {
+        let check =
+            |path: String, name: &str, kinds: &[ResourceKind]| match get_kind(get_handle(name)) {
+                Some(kind) if kinds.contains(&kind) => Ok(()),
+                Some(kind) => bail!(
+                    "{}: \"{}\" is a {}, not a {}",
+                    path,
+                    name,
+                    kind.get_name(),
+                    kinds[0].get_name()
+                ),
+                None => bail!("{}: no {} named \"{}\"", path, kinds[0].get_name(), name),
+            };
+
+        let mut prefabs: Vec<&Prefab> = self.prefabs.values().collect();
+        prefabs.sort_by_key(|prefab| &prefab.name);
+        for prefab in prefabs {
+            let name = &prefab.name;
+            if let Some(renderable) = &prefab.renderable {
+                let mesh_kinds: &[ResourceKind] = if prefab.animator.is_some() {
+                    &[ResourceKind::SkeletalMesh]
+                } else {
+                    &[
+                        ResourceKind::StaticMesh,
+                        ResourceKind::SkeletalMesh,
+                        ResourceKind::DynamicMesh,
+                    ]
+                };
+                check(
+                    format!("{}.renderable.mesh", name),
+                    &renderable.mesh,
+                    mesh_kinds,
+                )?;
+                check(
+                    format!("{}.renderable.material", name),
+                    &renderable.material,
+                    &[ResourceKind::MaterialInstance],
+                )?;
+            }
+
+            if let Some(animator) = &prefab.animator {
+                let animations = [&animator.idle_animation, &animator.run_animation]
+                    .into_iter()
+                    .chain(animator.locomotion.iter().map(|sample| &sample.animation))
+                    .chain(&animator.attack_animation);
+                for animation in animations {
+                    check(
+                        format!("{}.animator", name),
+                        animation,
+                        &[ResourceKind::Animation],
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Folds the parents in, from the root down
+fn resolve(desc: &PrefabDesc, by_name: &HashMap<&str, &PrefabDesc>) -> anyhow::Result<PrefabDesc> {
+    let mut chain = vec![desc];
+    let mut visited = HashSet::from([desc.name.as_str()]);
+    while let Some(parent) = &chain.last().unwrap().parent {
+        let Some(parent_desc) = by_name.get(parent.as_str()) else {
+            bail!("{}: parent \"{}\" doesn't exist", desc.name, parent);
+        };
+        if !visited.insert(parent.as_str()) {
+            let names: Vec<&str> = chain.iter().map(|desc| desc.name.as_str()).collect();
+            bail!(
+                "{}: inheritance cycle {} -> {}",
+                desc.name,
+                names.join(" -> "),
+                parent
+            );
+        }
+        chain.push(parent_desc);
+    }
+
+    let mut resolved = PrefabDesc::default();
+    for desc in chain.into_iter().rev() {
+        resolved = PrefabDesc {
+            name: desc.name.clone(),
+            parent: None,
+            renderable: inherit_component(
+                &desc.renderable,
+                resolved.renderable,
+                |child, parent| RenderableDesc {
+                    mesh: inherit(&child.mesh, parent.mesh),
+                    material: inherit(&child.material, parent.material),
+                    color: inherit(&child.color, parent.color),
+                    scale: inherit(&child.scale, parent.scale),
+                    render_rotation: inherit(&child.render_rotation, parent.render_rotation),
+                    casts_shadow: inherit(&child.casts_shadow, parent.casts_shadow),
+                },
+            ),
+            physics: inherit_component(&desc.physics, resolved.physics, |child, parent| {
+                PhysicsDesc {
+                    shape: inherit(&child.shape, parent.shape),
+                    layer: inherit(&child.layer, parent.layer),
+                    sensor: inherit(&child.sensor, parent.sensor),
+                }
+            }),
+            animator: inherit_component(&desc.animator, resolved.animator, |child, parent| {
+                AnimatorDesc {
+                    idle_animation: inherit(&child.idle_animation, parent.idle_animation),
+                    run_animation: inherit(&child.run_animation, parent.run_animation),
+                    locomotion: inherit(&child.locomotion, parent.locomotion),
+                    attack_animation: inherit(&child.attack_animation, parent.attack_animation),
+                }
+            }),
+            health: inherit(&desc.health, resolved.health),
+            ai: inherit(&desc.ai, resolved.ai),
+        };
+    }
+    Ok(resolved)
+}
+
+fn inherit<T: Clone>(child: &Option<T>, parent: Option<T>) -> Option<T> {
+    child.clone().or(parent)
+}
+
+fn inherit_component<T: Clone>(
+    child: &Option<T>,
+    parent: Option<T>,
+    merge: impl Fn(&T, T) -> T,
+) -> Option<T> {
+    match (child, parent) {
+        (Some(child), Some(parent)) => Some(merge(child, parent)),
+        (child, parent) => inherit(child, parent),
+    }
+}
+
+fn finish(desc: PrefabDesc) -> anyhow::Result<Prefab> {
+    let name = desc.name;
+    let require = |value: Option<String>, path: &str| {
+        value.ok_or_else(|| anyhow!("{}.{}: missing, and no parent has it", name, path))
+    };
+
+    let renderable = match desc.renderable {
+        Some(renderable) => Some(PrefabRenderable {
+            mesh: require(renderable.mesh, "renderable.mesh")?,
+            material: require(renderable.material, "renderable.material")?,
+            color: Vec4::from(renderable.color.unwrap_or([1.0; 4])),
+            scale: Vec3::from(renderable.scale.unwrap_or([1.0; 3])),
+            render_rotation: renderable.render_rotation.unwrap_or_default(),
+            casts_shadow: renderable.casts_shadow.unwrap_or(true),
+        }),
+        None => None,
+    };
+
+    let physics = match desc.physics {
+        Some(physics) => {
+            let (Some(shape), Some(layer)) = (physics.shape, physics.layer) else {
+                bail!("{}.physics: needs a shape and a layer", name);
+            };
+            Some(PrefabPhysics {
+                shape,
+                layer,
+                sensor: physics.sensor.unwrap_or(false),
+            })
+        }
+        None => None,
+    };
+
+    let animator = match desc.animator {
+        Some(animator) => {
+            if renderable.is_none() {
+                bail!("{}.animator: needs a renderable with a skeletal mesh", name);
+            }
+            Some(PrefabAnimator {
+                idle_animation: require(animator.idle_animation, "animator.idle_animation")?,
+                run_animation: require(animator.run_animation, "animator.run_animation")?,
+                locomotion: animator.locomotion.unwrap_or_default(),
+                attack_animation: animator.attack_animation,
+            })
+        }
+        None => None,
+    };
+
+    if let Some(health) = desc.health
+        && (health <= 0.0 || health.is_nan())
+    {
+        bail!("{}.health: {} is not positive", name, health);
+    }
+
+    Ok(Prefab {
+        name,
+        renderable,
+        physics,
+        animator,
+        health: desc.health,
+        ai: desc.ai,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT_PREFABS: &[u8] = include_bytes!("../../assets/prefabs/default.ron");
+
+    const FAMILY: &str = r#"[
+        (
+            name: "Character",
+            renderable: (mesh: "Brute", material: "BruteMaterial", render_rotation: (-90.0, 0.0, 0.0)),
+            physics: (shape: (type: "circle", radius: 32.0), layer: Player),
+            health: 100.0,
+        ),
+        (
+            name: "Enemy",
+            parent: "Character",
+            renderable: (color: (1.0, 0.2, 0.2, 1.0)),
+            physics: (layer: Enemy),
+            ai: Fighter,
+        ),
+        (
+            name: "Boss",
+            parent: "Enemy",
+            renderable: (scale: (2.0, 2.0, 2.0), casts_shadow: false),
+            health: 500.0,
+        ),
+    ]"#;
+
+    #[test]
+    fn children_inherit_the_fields_they_leave_out() {
+        let library = PrefabLibrary::load(FAMILY.as_bytes()).unwrap();
+        assert_eq!(library.len(), 3);
+
+        let boss = library.get("Boss").unwrap();
+        let renderable = boss.renderable.as_ref().unwrap();
+        // From the grandparent, the parent and the boss itself
+        assert_eq!(renderable.mesh, "Brute");
+        assert_eq!(renderable.render_rotation, [-90.0, 0.0, 0.0]);
+        assert_eq!(renderable.color, Vec4::new(1.0, 0.2, 0.2, 1.0));
+        assert_eq!(renderable.scale, Vec3::splat(2.0));
+        assert!(!renderable.casts_shadow);
+        let physics = boss.physics.as_ref().unwrap();
+        assert_eq!(physics.shape, ShapeDesc::Circle { radius: 32.0 });
+        assert_eq!(physics.layer, LayerDesc::Enemy);
+        assert!(!physics.sensor);
+        assert_eq!(boss.health, Some(500.0));
+        assert_eq!(boss.ai, Some(AiArchetype::Fighter));
+
+        // Parents are left as they are
+        let character = library.get("Character").unwrap();
+        assert_eq!(character.renderable.as_ref().unwrap().color, Vec4::ONE);
+        assert_eq!(character.physics.as_ref().unwrap().layer, LayerDesc::Player);
+        assert_eq!(character.ai, None);
+        assert_eq!(library.get("Enemy").unwrap().health, Some(100.0));
+
+        assert!(PrefabLibrary::load(DEFAULT_PREFABS).is_ok());
+    }
+
+    #[test]
+    fn overrides_win_over_the_prefab() {
+        let library = PrefabLibrary::load(FAMILY.as_bytes()).unwrap();
+        let boss = library.get("Boss").unwrap();
+
+        let defaults = PrefabOverrides::default();
+        assert_eq!(boss.get_transform(&defaults).scale, Vec3::splat(2.0));
+        assert_eq!(boss.get_color(&defaults), Vec4::new(1.0, 0.2, 0.2, 1.0));
+
+        let overrides = PrefabOverrides {
+            position: Vec3::new(10.0, 0.0, -5.0),
+            rotation: Quat::from_rotation_y(1.0),
+            scale: Some(Vec3::splat(0.5)),
+            tint: Some(Vec4::new(0.2, 0.4, 1.0, 1.0)),
+            team: None,
+            variant: 0,
+        };
+        let transform = boss.get_transform(&overrides);
+        assert_eq!(transform.position, overrides.position);
+        assert_eq!(transform.rotation, overrides.rotation);
+        assert_eq!(transform.scale, Vec3::splat(0.5));
+        assert_eq!(boss.get_color(&overrides), overrides.tint.unwrap());
+
+        // Without a renderable there is nothing to inherit a scale or color from
+        let library = PrefabLibrary::load(b"[(name: \"Marker\")]").unwrap();
+        let marker = library.get("Marker").unwrap();
+        assert_eq!(marker.get_transform(&defaults).scale, Vec3::ONE);
+        assert_eq!(marker.get_color(&defaults), Vec4::ONE);
+    }
+
+    #[test]
+    fn broken_prefabs_name_the_problem() {
+        let error =
+            |source: &str| format!("{:#}", PrefabLibrary::load(source.as_bytes()).unwrap_err());
+
+        let missing = error(r#"[(name: "Enemy", parent: "Characte")]"#);
+        assert!(
+            missing.contains("Enemy: parent \"Characte\" doesn't exist"),
+            "{}",
+            missing
+        );
+        let cycle = error(r#"[(name: "A", parent: "B"), (name: "B", parent: "A")]"#);
+        assert!(cycle.contains("inheritance cycle A -> B -> A"), "{}", cycle);
+        let duplicate = error(r#"[(name: "A"), (name: "A")]"#);
+        assert!(
+            duplicate.contains("A: there is another prefab"),
+            "{}",
+            duplicate
+        );
+        let no_mesh = error(r#"[(name: "A", renderable: (material: "Grid"))]"#);
+        assert!(
+            no_mesh.contains("A.renderable.mesh: missing"),
+            "{}",
+            no_mesh
+        );
+        let no_layer = error(r#"[(name: "A", physics: (shape: (type: "circle", radius: 1.0)))]"#);
+        assert!(
+            no_layer.contains("A.physics: needs a shape and a layer"),
+            "{}",
+            no_layer
+        );
+        let unknown = error(r#"[(name: "A", health: 10.0, armor: 5.0)]"#);
+        assert!(unknown.contains("[0]"), "{}", unknown);
+        assert!(unknown.contains("armor"), "{}", unknown);
+
+        // Names that don't resolve to loaded resources of the right kind
+        let library = PrefabLibrary::load(FAMILY.as_bytes()).unwrap();
+        let kinds = HashMap::from([
+            (get_handle("Brute"), ResourceKind::SkeletalMesh),
+            (get_handle("BruteMaterial"), ResourceKind::MaterialInstance),
+        ]);
+        assert!(
+            library
+                .validate(|handle| kinds.get(&handle).copied())
+                .is_ok()
+        );
+
+        let wrong_kind = HashMap::from([
+            (get_handle("Brute"), ResourceKind::SkeletalMesh),
+            (get_handle("BruteMaterial"), ResourceKind::Texture),
+        ]);
+        let error = library
+            .validate(|handle| wrong_kind.get(&handle).copied())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Boss.renderable.material"), "{}", error);
+        assert!(error.contains("is a"), "{}", error);
+        let error = library.validate(|_| None).unwrap_err().to_string();
+        assert!(error.contains("no "), "{}", error);
+        assert!(error.contains("\"Brute\""), "{}", error);
+    }
+}
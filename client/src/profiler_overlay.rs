@@ -0,0 +1,300 @@
+// The profiled scopes of the last frame as nested bars across the top of the screen, a row
+// per depth and a band of rows per thread. The frame budget spans the width, so a bar is as
+// long as its share of the budget and a frame over budget runs off the right edge. The bar
+// under the mouse shows its exact times.
+
+use shared::{
+    math::*,
+    profiler::{self, FrameProfile, ProfileCollector},
+};
+
+use crate::renderer::{
+    Renderer, SpriteAnchor, SpriteSpace, TextAlignment,
+    render_data::{SpriteRenderJob, TextRenderJob},
+    resources::get_handle,
+};
+
+const FRAME_BUDGET: f64 = 1.0 / 60.0;
+const PROFILER_LAYER: u32 = u16::MAX as u32 - 6; // Below the console, the text one above
+const MARGIN: f32 = 16.0;
+const TOP: f32 = 80.0; // Below the debug metrics
+const ROW_HEIGHT: f32 = 18.0;
+const BAND_GAP: f32 = 6.0; // Between the threads
+const TEXT_SIZE: f32 = 14.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Bar {
+    node: usize,
+    position: Vec2, // From the top left of the first band
+    size: Vec2,
+}
+
+// The bands of the threads in the order they first show up, `width` pixels for the budget
+fn layout_bars(frame: &FrameProfile, width: f32, bars: &mut Vec<Bar>) -> f32 {
+    let mut bands: Vec<(u32, u32)> = Vec::new(); // The thread and its rows
+    for node in &frame.nodes {
+        match bands.iter_mut().find(|(thread, _)| *thread == node.thread) {
+            Some((_, rows)) => *rows = (*rows).max(node.depth + 1),
+            None => bands.push((node.thread, node.depth + 1)),
+        }
+    }
+    let mut band_tops = Vec::with_capacity(bands.len());
+    let mut height = 0.0;
+    for (_, rows) in &bands {
+        band_tops.push(height);
+        height += *rows as f32 * ROW_HEIGHT + BAND_GAP;
+    }
+
+    let scale = width as f64 / FRAME_BUDGET;
+    bars.clear();
+    for (index, node) in frame.nodes.iter().enumerate() {
+        let band = bands
+            .iter()
+            .position(|(thread, _)| *thread == node.thread)
+            .unwrap_or_default();
+        let x = ((node.start - frame.start) * scale) as f32;
+        bars.push(Bar {
+            node: index,
+            position: Vec2::new(x, band_tops[band] + node.depth as f32 * ROW_HEIGHT),
+            // Even the shortest scopes stay visible
+            size: Vec2::new(
+                ((node.get_duration() * scale) as f32).max(1.0),
+                ROW_HEIGHT - 2.0,
+            ),
+        });
+    }
+    (height - BAND_GAP).max(0.0)
+}
+
+fn get_bar_at(bars: &[Bar], point: Vec2) -> Option<usize> {
+    bars.iter()
+        .find(|bar| {
+            let offset = point - bar.position;
+            offset.cmpge(Vec2::ZERO).all() && offset.cmplt(bar.size).all()
+        })
+        .map(|bar| bar.node)
+}
+
+// Stable per name, so a system keeps its color from frame to frame
+fn get_color(name: &str) -> Vec4 {
+    let hash = name.bytes().fold(2166136261u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(16777619)
+    });
+    let hue = (hash % 360) as f32 / 60.0;
+    let channel = |offset: f32| (((hue + offset) % 6.0 - 3.0).abs() - 1.0).clamp(0.0, 1.0);
+    (Vec3::new(channel(0.0), channel(4.0), channel(2.0)) * 0.6 + 0.2).extend(0.9)
+}
+
+#[derive(Default)]
+pub struct ProfilerOverlay {
+    collector: ProfileCollector,
+    visible: bool,
+    recording: bool, // Kept on without the overlay, e.g. for the hitch records
+    bars: Vec<Bar>,
+    width: f32,
+    height: f32,
+    hovered: Option<usize>,
+}
+
+impl ProfilerOverlay {
+    // Recording only runs while it shows, unless it was asked for
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+        self.bars.clear();
+        self.hovered = None;
+        self.update_enabled();
+    }
+
+    pub fn set_recording(&mut self, recording: bool) {
+        if recording != self.recording {
+            self.recording = recording;
+            self.update_enabled();
+        }
+    }
+
+    fn update_enabled(&mut self) {
+        let enabled = self.visible || self.recording;
+        if enabled && !profiler::is_enabled() {
+            self.collector.reset();
+        }
+        profiler::set_enabled(enabled);
+    }
+
+    // After the outermost scope of the frame ended
+    pub fn end_frame(&mut self) {
+        if profiler::is_enabled() {
+            self.collector.end_frame();
+        }
+    }
+
+    // Of the last frame that ended while recording
+    pub fn get_last_frame(&self) -> &FrameProfile {
+        self.collector.get_last_frame()
+    }
+
+    // The mouse position in pixels
+    pub fn update(&mut self, mouse_position: Vec2, screen_size: Vec2) {
+        if !self.visible {
+            return;
+        }
+        self.width = (screen_size.x - MARGIN * 2.0).max(1.0);
+        self.height = layout_bars(self.collector.get_last_frame(), self.width, &mut self.bars);
+        self.hovered = get_bar_at(&self.bars, mouse_position - Vec2::new(MARGIN, TOP));
+    }
+
+    pub fn render(&self, renderer: &mut Renderer) {
+        if !self.visible {
+            return;
+        }
+        let frame = self.collector.get_last_frame();
+        let origin = Vec2::new(MARGIN, TOP);
+        let submit_text = |renderer: &mut Renderer, text: String, position: Vec2| {
+            renderer.submit(&TextRenderJob {
+                font_atlas: get_handle("DebugFont"),
+                font_material: get_handle("DebugFontMaterial"),
+                text: text.into(),
+                position,
+                size: TEXT_SIZE,
+                color: Vec4::ONE,
+                layer: PROFILER_LAYER + 1,
+                anchor: SpriteAnchor::TopLeft,
+                space: SpriteSpace::Absolute,
+                alignment: TextAlignment::Left,
+            });
+        };
+
+        let frame_ms = (frame.end - frame.start) * 1000.0;
+        submit_text(
+            renderer,
+            format!(
+                "Frame: {:.2} ms of {:.2} ms, {} scopes",
+                frame_ms,
+                FRAME_BUDGET * 1000.0,
+                frame.nodes.len()
+            ),
+            origin - Vec2::new(0.0, TEXT_SIZE + 2.0),
+        );
+
+        // Same batch, the bars are drawn over the background
+        renderer.submit(&SpriteRenderJob {
+            space: SpriteSpace::Absolute,
+            ..SpriteRenderJob::solid(
+                origin - Vec2::splat(2.0),
+                Vec2::new(self.width, self.height) + Vec2::splat(4.0),
+                Vec4::new(0.0, 0.0, 0.0, 0.6),
+                PROFILER_LAYER,
+            )
+        });
+        let font = get_handle("DebugFont");
+        for bar in &self.bars {
+            let Some(node) = frame.nodes.get(bar.node) else {
+                continue;
+            };
+            // Cut at the right edge
+            let visible_width = bar.size.x.min(self.width - bar.position.x);
+            if visible_width <= 0.0 {
+                continue;
+            }
+            let mut color = get_color(node.name);
+            if self.hovered == Some(bar.node) {
+                color = (color.truncate() + 0.3).min(Vec3::ONE).extend(1.0);
+            }
+            renderer.submit(&SpriteRenderJob {
+                space: SpriteSpace::Absolute,
+                ..SpriteRenderJob::solid(
+                    origin + bar.position,
+                    Vec2::new(visible_width, bar.size.y),
+                    color,
+                    PROFILER_LAYER,
+                )
+            });
+            if renderer.get_text_width(font, node.name, TEXT_SIZE) + 4.0 <= visible_width {
+                submit_text(
+                    renderer,
+                    node.name.to_string(),
+                    origin + bar.position + Vec2::new(2.0, 1.0),
+                );
+            }
+        }
+
+        // The exact times of the hovered scope, self is without its children
+        if let Some(index) = self.hovered
+            && let Some(node) = frame.nodes.get(index)
+        {
+            let children: f64 = frame
+                .nodes
+                .iter()
+                .filter(|child| child.parent == Some(index))
+                .map(|child| child.get_duration())
+                .sum();
+            let duration_ms = node.get_duration() * 1000.0;
+            submit_text(
+                renderer,
+                format!(
+                    "{}: {:.3} ms ({:.1}% of the budget), self {:.3} ms, from {:.3} ms",
+                    node.name,
+                    duration_ms,
+                    duration_ms / (FRAME_BUDGET * 1000.0) * 100.0,
+                    (node.get_duration() - children) * 1000.0,
+                    (node.start - frame.start) * 1000.0
+                ),
+                origin + Vec2::new(0.0, self.height + 6.0),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::profiler::ProfileNode;
+
+    use super::*;
+
+    fn node(name: &'static str, thread: u32, depth: u32, start: f64, end: f64) -> ProfileNode {
+        ProfileNode {
+            name,
+            thread,
+            depth,
+            parent: None,
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn bars_are_scaled_to_the_budget_and_found_under_the_mouse() {
+        let frame = FrameProfile {
+            start: 1.0,
+            end: 1.0 + FRAME_BUDGET,
+            nodes: vec![
+                node("Frame", 0, 0, 1.0, 1.0 + FRAME_BUDGET),
+                node(
+                    "Update",
+                    0,
+                    1,
+                    1.0 + FRAME_BUDGET * 0.25,
+                    1.0 + FRAME_BUDGET * 0.5,
+                ),
+                node("Job", 3, 0, 1.0, 1.0 + FRAME_BUDGET * 0.1),
+            ],
+        };
+        let mut bars = Vec::new();
+        let height = layout_bars(&frame, 400.0, &mut bars);
+        assert_eq!(height, ROW_HEIGHT * 3.0 + BAND_GAP);
+        assert!((bars[0].size.x - 400.0).abs() < 1e-3);
+        assert!((bars[1].position.x - 100.0).abs() < 1e-3);
+        assert!((bars[1].size.x - 100.0).abs() < 1e-3);
+        assert_eq!(bars[1].position.y, ROW_HEIGHT);
+        // The second thread gets a band of its own below the first
+        assert_eq!(bars[2].position.y, ROW_HEIGHT * 2.0 + BAND_GAP);
+
+        assert_eq!(get_bar_at(&bars, Vec2::new(50.0, 5.0)), Some(0));
+        assert_eq!(
+            get_bar_at(&bars, Vec2::new(150.0, ROW_HEIGHT + 5.0)),
+            Some(1)
+        );
+        assert_eq!(get_bar_at(&bars, Vec2::new(50.0, ROW_HEIGHT + 5.0)), None);
+        assert_eq!(get_bar_at(&bars, Vec2::new(20.0, height - 5.0)), Some(2));
+        assert_eq!(get_bar_at(&bars, Vec2::new(-1.0, 5.0)), None);
+    }
+}
@@ -0,0 +1,292 @@
+// A teamfight fires and spends hundreds of projectiles a second. Instead of a new entity and
+// body for each, spent projectiles are parked with their body disabled and fired again, per
+// prefab. The pool only keeps track of the entities, the game parks and restores their
+// components. Projectiles that weren't made for the pool, e.g. loaded from a save, are
+// despawned as before.
+
+use std::collections::HashMap;
+
+use crate::components::Entity;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ProjectilePoolStats {
+    pub created: usize, // Entities made for the pool, flying or parked
+    pub in_use: usize,
+    pub peak_in_use: usize,
+    pub reused: u64, // Fired from the pool instead of being created
+}
+
+impl ProjectilePoolStats {
+    // Of the created entities, how many are flying
+    #[allow(dead_code)]
+    pub fn get_utilization(&self) -> f32 {
+        if self.created == 0 {
+            return 0.0;
+        }
+        self.in_use as f32 / self.created as f32
+    }
+}
+
+struct PooledProjectile {
+    prefab: String,
+    parked: bool,
+}
+
+#[derive(Default)]
+pub struct ProjectilePool {
+    projectiles: HashMap<Entity, PooledProjectile>,
+    parked: HashMap<String, Vec<Entity>>, // Per prefab
+    stats: ProjectilePoolStats,
+}
+
+impl ProjectilePool {
+    // A projectile made because none of the prefab was parked, it is in use
+    pub fn add(&mut self, prefab: &str, entity: Entity) {
+        let previous = self.projectiles.insert(
+            entity,
+            PooledProjectile {
+                prefab: prefab.to_string(),
+                parked: false,
+            },
+        );
+        debug_assert!(previous.is_none(), "The projectile is already pooled");
+        self.stats.created += 1;
+        self.set_in_use(self.stats.in_use + 1);
+    }
+
+    pub fn take(&mut self, prefab: &str) -> Option<Entity> {
+        let entity = self.parked.get_mut(prefab)?.pop()?;
+        if let Some(projectile) = self.projectiles.get_mut(&entity) {
+            projectile.parked = false;
+        }
+        self.stats.reused += 1;
+        self.set_in_use(self.stats.in_use + 1);
+        Some(entity)
+    }
+
+    // False for projectiles that aren't pooled or are already parked
+    pub fn park(&mut self, entity: Entity) -> bool {
+        let Some(projectile) = self.projectiles.get_mut(&entity) else {
+            return false;
+        };
+        if projectile.parked {
+            return false;
+        }
+        projectile.parked = true;
+        self.parked
+            .entry(projectile.prefab.clone())
+            .or_default()
+            .push(entity);
+        self.set_in_use(self.stats.in_use - 1);
+        true
+    }
+
+    // Parked projectiles aren't part of the game, e.g. saves leave them out
+    pub fn is_parked(&self, entity: Entity) -> bool {
+        self.projectiles
+            .get(&entity)
+            .is_some_and(|projectile| projectile.parked)
+    }
+
+    // The projectile was despawned, e.g. with its level
+    pub fn remove(&mut self, entity: Entity) {
+        let Some(projectile) = self.projectiles.remove(&entity) else {
+            return;
+        };
+        if projectile.parked {
+            if let Some(parked) = self.parked.get_mut(&projectile.prefab) {
+                parked.retain(|other| *other != entity);
+            }
+        } else {
+            self.stats.in_use -= 1;
+        }
+        self.stats.created -= 1;
+    }
+
+    // When the entities are cleared, the statistics are kept
+    pub fn clear(&mut self) {
+        self.projectiles.clear();
+        self.parked.clear();
+        self.stats.created = 0;
+        self.stats.in_use = 0;
+    }
+
+    pub fn get_stats(&self) -> ProjectilePoolStats {
+        self.stats
+    }
+
+    fn set_in_use(&mut self, in_use: usize) {
+        self.stats.in_use = in_use;
+        self.stats.peak_in_use = self.stats.peak_in_use.max(in_use);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use shared::{
+        math::*,
+        physics::{BodyId, BodySettings, CollisionLayer, CollisionShape, PhysicsWorld},
+    };
+
+    use super::*;
+    use crate::components::Entities;
+
+    #[test]
+    fn parked_projectiles_are_fired_again_per_prefab() {
+        let mut entities = Entities::default();
+        let mut pool = ProjectilePool::default();
+        let bolt = entities.spawn();
+        let arrow = entities.spawn();
+        pool.add("Bolt", bolt);
+        pool.add("Arrow", arrow);
+        assert_eq!(pool.take("Bolt"), None);
+
+        assert!(pool.park(bolt));
+        assert!(pool.is_parked(bolt));
+        assert!(!pool.park(bolt));
+        // Never pooled
+        assert!(!pool.park(entities.spawn()));
+        assert_eq!(pool.take("Arrow"), None);
+        assert_eq!(pool.take("Bolt"), Some(bolt));
+        assert!(!pool.is_parked(bolt));
+
+        assert_eq!(
+            pool.get_stats(),
+            ProjectilePoolStats {
+                created: 2,
+                in_use: 2,
+                peak_in_use: 2,
+                reused: 1,
+            }
+        );
+        assert_eq!(pool.get_stats().get_utilization(), 1.0);
+        pool.park(arrow);
+        assert_eq!(pool.get_stats().get_utilization(), 0.5);
+
+        pool.remove(arrow);
+        pool.remove(bolt);
+        assert_eq!(pool.take("Arrow"), None);
+        assert_eq!(pool.get_stats().in_use, 0);
+        pool.add("Bolt", bolt);
+        pool.clear();
+        assert_eq!(pool.take("Bolt"), None);
+        assert_eq!(pool.get_stats().created, 0);
+    }
+
+    // 500 projectiles a second for 30 seconds, fired through the pool and parked again like
+    // the game does, at a ring of enemies holding their ground. They hit one or fly past until
+    // they expire.
+    // cargo test -p client --release projectile_pool -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn firing_500_projectiles_a_second_neither_grows_the_pool_nor_slows_the_ticks() {
+        const DT: f32 = 1.0 / 60.0;
+        const TICKS_PER_SECOND: u32 = 60;
+        const SECONDS: u32 = 30;
+        const WARM_UP_SECONDS: u32 = 5;
+        const FIRE_RATE: u32 = 500;
+        const LIFETIME_TICKS: u32 = 60;
+        const SPEED: f32 = 600.0;
+
+        let mut entities = Entities::default();
+        let mut pool = ProjectilePool::default();
+        let mut world = PhysicsWorld::new();
+        let enemies: Vec<(BodyId, Vec2)> = (0..24)
+            .map(|index| {
+                let angle = index as f32 / 24.0 * std::f32::consts::TAU;
+                let position = Vec2::from_angle(angle) * 400.0;
+                let body_id = world.create_rigid_body(&BodySettings {
+                    position,
+                    velocity: Vec2::ZERO,
+                    layer: CollisionLayer::Enemy,
+                    shape: &CollisionShape::Circle { radius: 30.0 },
+                    listen_to_contact_events: false,
+                });
+                (body_id, position)
+            })
+            .collect();
+
+        let mut bodies: HashMap<Entity, BodyId> = HashMap::new();
+        let mut flying: Vec<(Entity, u32)> = Vec::new();
+        let mut fired = 0u32;
+        let mut created_after_warm_up = 0;
+        let mut seconds: Vec<Duration> = Vec::new();
+        for second in 0..SECONDS {
+            if second == WARM_UP_SECONDS {
+                created_after_warm_up = pool.get_stats().created;
+            }
+            let start = Instant::now();
+            for tick in 0..TICKS_PER_SECOND {
+                let to_fire =
+                    (tick + 1) * FIRE_RATE / TICKS_PER_SECOND - tick * FIRE_RATE / TICKS_PER_SECOND;
+                for _ in 0..to_fire {
+                    let entity = pool.take("Bolt").unwrap_or_else(|| {
+                        let entity = entities.spawn();
+                        let body_id = world.create_rigid_body(&BodySettings {
+                            position: Vec2::ZERO,
+                            velocity: Vec2::ZERO,
+                            layer: CollisionLayer::PlayerProjectile,
+                            shape: &CollisionShape::Circle { radius: 5.0 },
+                            listen_to_contact_events: true,
+                        });
+                        bodies.insert(entity, body_id);
+                        pool.add("Bolt", entity);
+                        entity
+                    });
+                    let body_id = bodies[&entity];
+                    let direction = Vec2::from_angle(fired as f32 * 2.39996);
+                    world.set_position(body_id, Vec2::ZERO);
+                    world.set_velocity(body_id, direction * SPEED);
+                    world.set_enabled(body_id, true);
+                    flying.push((entity, LIFETIME_TICKS));
+                    fired += 1;
+                }
+
+                for (body_id, position) in &enemies {
+                    world.set_position(*body_id, *position);
+                }
+                world.step_simulation(DT);
+                flying.retain_mut(|(entity, lifetime)| {
+                    *lifetime -= 1;
+                    let body_id = bodies[entity];
+                    // Projectiles touching each other fly on, like in the game
+                    let hit = world.get_contacts(body_id).is_some_and(|contacts| {
+                        contacts.iter().any(|contact| {
+                            world.get_layer(contact.other) == Some(CollisionLayer::Enemy)
+                        })
+                    });
+                    if !hit && *lifetime > 0 {
+                        return true;
+                    }
+                    world.set_velocity(body_id, Vec2::ZERO);
+                    world.set_enabled(body_id, false);
+                    assert!(pool.park(*entity));
+                    false
+                });
+            }
+            seconds.push(start.elapsed());
+        }
+
+        let stats = pool.get_stats();
+        let after_warm_up = &seconds[WARM_UP_SECONDS as usize..];
+        let fastest = *after_warm_up.iter().min().unwrap();
+        let slowest = *after_warm_up.iter().max().unwrap();
+        println!(
+            "{} fired, {:?}, ticks of a second took {:?} to {:?}",
+            fired, stats, fastest, slowest
+        );
+        assert_eq!(fired, FIRE_RATE * SECONDS);
+        assert_eq!(stats.created, created_after_warm_up);
+        assert_eq!(stats.reused, (fired as usize - stats.created) as u64);
+        assert!(stats.peak_in_use <= stats.created);
+        assert_eq!(world.get_body_count(), 24 + stats.created);
+        assert!(
+            slowest <= fastest * 2,
+            "The ticks slowed down from {:?} to {:?} a second",
+            fastest,
+            slowest
+        );
+    }
+}
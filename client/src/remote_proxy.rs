@@ -0,0 +1,295 @@
+// Smooths the entities the server moves and we don't predict. Snapshots arrive every few
+// ticks and not evenly, rendering the newest one would make the entities jump between
+// them. The proxy keeps the last few states and is sampled a bit in the past, where there
+// are usually two states to blend between. When the states run out it keeps moving along
+// the last velocity for a while, and a late state disagreeing with that is blended in.
+
+use std::{
+    collections::VecDeque,
+    f32::consts::{PI, TAU},
+};
+
+use shared::{math::Vec2, net::EntityState};
+
+use crate::prediction::is_after;
+
+const MAX_SAMPLES: usize = 8;
+const MAX_EXTRAPOLATION: f32 = 0.25; // Seconds past the newest state
+// Smaller jumps are shown as they are, larger ones are smoothed out and anything beyond
+// the teleport distance snaps
+const JUMP_TOLERANCE: f32 = 1.0;
+const CORRECTION_TIME: f32 = 0.15;
+const TELEPORT_DISTANCE: f32 = 200.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RemoteSample {
+    tick: u32,
+    position: Vec2,
+    velocity: Vec2,
+    facing: f32,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RemoteTransform {
+    pub position: Vec2,
+    pub velocity: Vec2, // How the rendered position moves, for the animations
+    pub facing: f32,
+}
+
+pub(crate) struct CRemoteProxy {
+    tick_rate: f32,
+    samples: VecDeque<RemoteSample>, // Oldest first
+    target: Option<RemoteTransform>, // The last sample before smoothing
+    correction: Vec2,                // Added to the target, shrinks to zero
+    correction_remaining: f32,
+    snap_count: u32,
+}
+
+impl CRemoteProxy {
+    pub fn new(tick_rate: f32) -> Self {
+        Self {
+            tick_rate,
+            samples: VecDeque::with_capacity(MAX_SAMPLES + 1),
+            target: None,
+            correction: Vec2::ZERO,
+            correction_remaining: 0.0,
+            snap_count: 0,
+        }
+    }
+
+    // Late states are sorted in, duplicates and states older than the buffer are dropped
+    pub fn push(&mut self, tick: u32, state: &EntityState) {
+        let index = self
+            .samples
+            .partition_point(|sample| is_after(tick, sample.tick));
+        if self.samples.get(index).is_some_and(|s| s.tick == tick)
+            || (index == 0 && self.samples.len() == MAX_SAMPLES)
+        {
+            return;
+        }
+
+        self.samples.insert(
+            index,
+            RemoteSample {
+                tick,
+                position: state.position,
+                velocity: state.velocity,
+                facing: state.facing,
+            },
+        );
+        if self.samples.len() > MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+
+    // Time is in seconds on the server tick clock, dt is the time since the last sample
+    pub fn sample(&mut self, render_time: f32, dt: f32) -> Option<RemoteTransform> {
+        let target = self.get_target(render_time)?;
+        self.update_correction(dt);
+
+        if let Some(previous) = self.target {
+            let shown = previous.position + self.correction;
+            let distance = target.position.distance(shown);
+            if distance > TELEPORT_DISTANCE {
+                self.snap_count += 1;
+                log::info!(
+                    "Remote entity snapped {:.0} units, {} snaps so far",
+                    distance,
+                    self.snap_count
+                );
+                self.correction = Vec2::ZERO;
+                self.correction_remaining = 0.0;
+            } else {
+                // Moving along the last velocity is expected, anything else is a jump
+                let jump = target.position - (previous.position + previous.velocity * dt);
+                if jump.length() > JUMP_TOLERANCE {
+                    self.correction -= jump;
+                    self.correction_remaining = CORRECTION_TIME;
+                }
+            }
+        }
+
+        self.target = Some(target);
+        Some(RemoteTransform {
+            position: target.position + self.correction,
+            ..target
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn get_snap_count(&self) -> u32 {
+        self.snap_count
+    }
+
+    fn update_correction(&mut self, dt: f32) {
+        if self.correction_remaining <= dt {
+            self.correction = Vec2::ZERO;
+            self.correction_remaining = 0.0;
+            return;
+        }
+
+        self.correction *= 1.0 - dt / self.correction_remaining;
+        self.correction_remaining -= dt;
+    }
+
+    fn get_time(&self, sample: &RemoteSample) -> f32 {
+        sample.tick as f32 / self.tick_rate
+    }
+
+    // Blended between the states around the time, or extrapolated past the newest one
+    fn get_target(&self, time: f32) -> Option<RemoteTransform> {
+        let newest = self.samples.back()?;
+        let next = self
+            .samples
+            .iter()
+            .position(|sample| self.get_time(sample) > time);
+
+        match next {
+            Some(0) => {
+                let oldest = &self.samples[0];
+                Some(RemoteTransform {
+                    position: oldest.position,
+                    velocity: oldest.velocity,
+                    facing: oldest.facing,
+                })
+            }
+            Some(index) => {
+                let from = &self.samples[index - 1];
+                let to = &self.samples[index];
+                let duration = self.get_time(to) - self.get_time(from);
+                let t = (time - self.get_time(from)) / duration;
+
+                // Shortest way around so facing doesn't spin when crossing zero
+                let facing_delta = (to.facing - from.facing + PI).rem_euclid(TAU) - PI;
+                Some(RemoteTransform {
+                    position: from.position.lerp(to.position, t),
+                    velocity: (to.position - from.position) / duration,
+                    facing: (from.facing + facing_delta * t).rem_euclid(TAU),
+                })
+            }
+            None => {
+                let elapsed = time - self.get_time(newest);
+                let velocity = if elapsed < MAX_EXTRAPOLATION {
+                    newest.velocity
+                } else {
+                    Vec2::ZERO // Standing where the extrapolation ended
+                };
+                Some(RemoteTransform {
+                    position: newest.position + newest.velocity * elapsed.min(MAX_EXTRAPOLATION),
+                    velocity,
+                    facing: newest.facing,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK_RATE: f32 = 60.0;
+    const SNAPSHOT_INTERVAL: u32 = 3;
+    const FRAME_RATE: f32 = 144.0;
+    const DELAY: f32 = 0.075;
+    const SPEED: f32 = 300.0;
+
+    // Runs around a circle, so neither blending nor extrapolating is exact
+    fn get_state(tick: u32) -> EntityState {
+        let angle = tick as f32 / TICK_RATE * SPEED / 200.0;
+        EntityState {
+            id: 1,
+            position: Vec2::new(angle.cos(), angle.sin()) * 200.0,
+            velocity: Vec2::new(-angle.sin(), angle.cos()) * SPEED,
+            facing: angle,
+            anim_state: 0,
+        }
+    }
+
+    // Renders two seconds with the snapshots arriving at the given times, returns the
+    // rendered positions
+    fn play(arrivals: &[(u32, f32)]) -> (Vec<Vec2>, CRemoteProxy) {
+        let mut proxy = CRemoteProxy::new(TICK_RATE);
+        let mut positions = Vec::new();
+        let dt = 1.0 / FRAME_RATE;
+        for frame in 0..(2.0 * FRAME_RATE) as u32 {
+            let now = frame as f32 * dt;
+            for &(tick, _) in arrivals
+                .iter()
+                .filter(|(_, at)| *at > now - dt && *at <= now)
+            {
+                proxy.push(tick, &get_state(tick));
+            }
+            if let Some(transform) = proxy.sample(now - DELAY, dt) {
+                positions.push(transform.position);
+            }
+        }
+        (positions, proxy)
+    }
+
+    // Snapshots sent every interval and received 30 ms later
+    fn get_arrivals() -> Vec<(u32, f32)> {
+        (0..120)
+            .step_by(SNAPSHOT_INTERVAL as usize)
+            .map(|tick| (tick, tick as f32 / TICK_RATE + 0.03))
+            .collect()
+    }
+
+    fn assert_continuous(positions: &[Vec2]) {
+        // Twice the speed leaves room for blending in a correction
+        let max_step = 2.0 * SPEED / FRAME_RATE;
+        for pair in positions.windows(2) {
+            let step = pair[0].distance(pair[1]);
+            assert!(step < max_step, "jumped {} from {}", step, pair[0]);
+        }
+    }
+
+    #[test]
+    fn steady_snapshots_follow_the_path() {
+        let (positions, proxy) = play(&get_arrivals());
+        assert_continuous(&positions);
+        assert_eq!(proxy.get_snap_count(), 0);
+
+        // The last frame renders the state DELAY seconds ago
+        let time = (2.0 * FRAME_RATE - 1.0) / FRAME_RATE - DELAY;
+        let angle = time * SPEED / 200.0;
+        let expected = Vec2::new(angle.cos(), angle.sin()) * 200.0;
+        assert!(positions.last().unwrap().distance(expected) < 2.0);
+    }
+
+    #[test]
+    fn dropped_late_and_bursty_snapshots_stay_continuous() {
+        let mut arrivals = get_arrivals();
+        // Four snapshots in a row lost, the buffer runs dry and extrapolates
+        arrivals.retain(|(tick, _)| !(30..42).contains(tick));
+        for (tick, at) in arrivals.iter_mut() {
+            // One late enough to arrive after the next one
+            if *tick == 60 {
+                *at += 0.08;
+            }
+            // A stall and then everything at once
+            if (75..90).contains(tick) {
+                *at = 90.0 / TICK_RATE;
+            }
+        }
+
+        let (positions, proxy) = play(&arrivals);
+        assert_continuous(&positions);
+        assert_eq!(proxy.get_snap_count(), 0);
+    }
+
+    #[test]
+    fn teleports_snap() {
+        let mut proxy = CRemoteProxy::new(TICK_RATE);
+        let mut state = get_state(0);
+        state.velocity = Vec2::ZERO;
+        proxy.push(0, &state);
+        proxy.sample(0.0, 0.01);
+
+        state.position += Vec2::new(1000.0, 0.0);
+        proxy.push(3, &state);
+        let transform = proxy.sample(1.0, 0.01).unwrap();
+        assert_eq!(transform.position, state.position);
+        assert_eq!(proxy.get_snap_count(), 1);
+    }
+}
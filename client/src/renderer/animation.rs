@@ -1,6 +1,6 @@
-use shared::math::*;
+use shared::{animation::AnimationClip, math::*};
 
-use crate::renderer::{RenderDevice, ResourceHandle, SkeletalMesh};
+use crate::renderer::{BoneInfo, RenderDevice, ResourceHandle, SkeletalMesh};
 
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Zeroable, Debug)]
@@ -25,7 +25,7 @@ impl Default for LocalBoneTransform {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Pose {
     pub transforms: Vec<LocalBoneTransform>,
 }
@@ -84,6 +84,59 @@ impl SkeletalMesh {
             .to_data();
         }
     }
+
+    // The bone in the space of the whole mesh, without the bind pose offset
+    pub fn get_bone_model_matrix(&self, pose: &Pose, bone_index: usize) -> Option<Mat4> {
+        let mut matrix = pose.transforms.get(bone_index)?.to_matrix();
+        let mut parent_id = self.bones.get(bone_index)?.parent_id;
+        while parent_id != -1 {
+            let parent_index = parent_id as usize;
+            matrix = pose.get_matrix(parent_index) * matrix;
+            parent_id = self.bones[parent_index].parent_id;
+        }
+        Some(matrix)
+    }
+
+    // See get_skeleton_lines
+    pub fn get_skeleton_lines(&self, pose: &Pose) -> Vec<(usize, Vec3, Vec3)> {
+        get_skeleton_lines(&self.bones, pose)
+    }
+}
+
+// A line from the joint of every bone to the joint of its parent in the space of the whole
+// mesh, with the index of the bone. Built from the local transforms of the pose and the
+// hierarchy alone, a wrong parent shows up even when the skinning matrices hide it.
+pub fn get_skeleton_lines(bones: &[BoneInfo], pose: &Pose) -> Vec<(usize, Vec3, Vec3)> {
+    // Parents come before their children
+    let mut joints = vec![Mat4::IDENTITY; pose.transforms.len()];
+    let mut lines = Vec::new();
+    for bone in bones {
+        let index = bone.id as usize;
+        let Some(local) = pose.transforms.get(index) else {
+            continue;
+        };
+        let parent = usize::try_from(bone.parent_id).ok();
+        let parent_joint = parent.map_or(Mat4::IDENTITY, |parent| joints[parent]);
+        joints[index] = parent_joint * local.to_matrix();
+
+        if parent.is_some() {
+            lines.push((
+                index,
+                parent_joint.w_axis.truncate(),
+                joints[index].w_axis.truncate(),
+            ));
+        }
+    }
+    lines
+}
+
+// A stable color per bone, the same hash as get_bone_debug_color in skeletal.wgsl
+pub fn get_bone_debug_color(bone_index: usize) -> Vec4 {
+    let n = (bone_index as u32)
+        .wrapping_mul(1664525)
+        .wrapping_add(1013904223);
+    let channel = |shift: u32| ((n >> shift) & 255) as f32 / 255.0;
+    Vec4::new(channel(0), channel(8), channel(16), 1.0)
 }
 
 pub struct Animation {
@@ -102,7 +155,6 @@ impl Animation {
         self.frames.len() / self.get_frame_count()
     }
 
-    #[allow(dead_code)]
     pub fn get_duration(&self) -> f32 {
         self.times.last().cloned().unwrap_or(0.0)
     }
@@ -218,56 +270,21 @@ pub struct AnimationLoadDesc {
 }
 
 impl AnimationLoadDesc {
-    // Might need to look over this and just do simple copies instead, but this will do for now
+    // Either variant of the .dat format, compressed clips are decompressed here
     pub fn load(bytes: &[u8]) -> anyhow::Result<AnimationLoadDesc> {
-        let mut read_index: usize = 0;
-        let mut tmp = [0u8; 4];
-
-        tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
-        let num_bones = u32::from_le_bytes(tmp) as usize;
-        read_index += 4;
-        tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
-        let num_frames = u32::from_le_bytes(tmp) as usize;
-        read_index += 4;
-
-        let num_total_frames = num_frames * num_bones;
-        let mut frames: Vec<LocalBoneTransform> = Vec::new();
-        frames.resize(num_total_frames, Default::default());
-
-        for i in 0..num_total_frames {
-            tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
-            frames[i].position.x = f32::from_le_bytes(tmp);
-            read_index += 4;
-            tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
-            frames[i].position.y = f32::from_le_bytes(tmp);
-            read_index += 4;
-            tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
-            frames[i].position.z = f32::from_le_bytes(tmp);
-            read_index += 4;
-
-            tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
-            frames[i].rotation.w = f32::from_le_bytes(tmp);
-            read_index += 4;
-            tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
-            frames[i].rotation.x = f32::from_le_bytes(tmp);
-            read_index += 4;
-            tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
-            frames[i].rotation.y = f32::from_le_bytes(tmp);
-            read_index += 4;
-            tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
-            frames[i].rotation.z = f32::from_le_bytes(tmp);
-            read_index += 4;
-        }
-
-        let mut times: Vec<f32> = Vec::new();
-        times.resize(num_frames, 0.0);
-        for i in 0..num_frames {
-            tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
-            times[i] = f32::from_le_bytes(tmp);
-            read_index += 4;
-        }
-
-        Ok(AnimationLoadDesc { frames, times })
+        let clip = AnimationClip::decode(bytes)?;
+        let frames = clip
+            .frames
+            .iter()
+            .map(|key| LocalBoneTransform {
+                position: key.position,
+                rotation: key.rotation,
+            })
+            .collect();
+        Ok(AnimationLoadDesc {
+            frames,
+            times: clip.times,
+        })
     }
 }
 
@@ -284,3 +301,62 @@ impl RenderDevice {
         Ok(Animation { frames, times })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bone(id: i32, parent_id: i32) -> BoneInfo {
+        BoneInfo {
+            id,
+            parent_id,
+            offset_matrix: Mat4::IDENTITY.to_cols_array(),
+        }
+    }
+
+    #[test]
+    fn compressed_animations_load_into_the_same_frames() {
+        let clip = shared::animation::AnimationClip {
+            bone_count: 2,
+            frames: (0..8)
+                .map(|index| shared::animation::BoneKey {
+                    position: Vec3::new(index as f32 * 0.1, 1.0, 0.0),
+                    rotation: Quat::from_rotation_y(index as f32 * 0.2),
+                })
+                .collect(),
+            times: vec![0.0, 0.1, 0.2, 0.3],
+        };
+        let plain = AnimationLoadDesc::load(&clip.encode()).unwrap();
+        let compressed = AnimationLoadDesc::load(&clip.encode_compressed()).unwrap();
+        assert_eq!(compressed.times, plain.times);
+        assert_eq!(compressed.frames.len(), 8);
+        for (a, b) in plain.frames.iter().zip(&compressed.frames) {
+            assert!(a.position.distance(b.position) < 1e-4);
+            assert!(a.rotation.angle_between(b.rotation).to_degrees() < 0.1);
+        }
+        assert!(AnimationLoadDesc::load(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn skeleton_lines_follow_the_hierarchy() {
+        // A root with a spine going up and an arm off the spine, rotated forward at the spine
+        let bones = [bone(0, -1), bone(1, 0), bone(2, 1)];
+        let mut pose = Pose::new(3);
+        pose.transforms[1].position = Vec3::new(0.0, 1.0, 0.0);
+        pose.transforms[1].rotation = Quat::from_rotation_x(std::f32::consts::FRAC_PI_2);
+        pose.transforms[2].position = Vec3::new(0.0, 1.0, 0.0);
+
+        let lines = get_skeleton_lines(&bones, &pose);
+        assert_eq!(lines.len(), 2);
+
+        let (index, start, end) = lines[0];
+        assert_eq!(index, 1);
+        assert!(start.distance(Vec3::ZERO) < 1e-5);
+        assert!(end.distance(Vec3::new(0.0, 1.0, 0.0)) < 1e-5);
+
+        let (index, start, end) = lines[1];
+        assert_eq!(index, 2);
+        assert!(start.distance(Vec3::new(0.0, 1.0, 0.0)) < 1e-5);
+        assert!(end.distance(Vec3::new(0.0, 1.0, 1.0)) < 1e-5);
+    }
+}
@@ -0,0 +1,100 @@
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum AaMode {
+    #[default]
+    Off,
+    Fxaa,
+    Msaa(u32), // Sample count of the scene targets
+}
+
+impl AaMode {
+    // MSAA on the Rgba16Float scene target is not available everywhere (WebGL2-class
+    // adapters), so unsupported sample counts fall back to FXAA.
+    pub fn resolve(self, supported_sample_counts: &[u32]) -> AaMode {
+        match self {
+            AaMode::Msaa(samples) if samples <= 1 => AaMode::Off,
+            AaMode::Msaa(samples) if !supported_sample_counts.contains(&samples) => {
+                log::warn!(
+                    "{}x MSAA is not supported on this adapter (supported: {:?}), using FXAA instead.",
+                    samples,
+                    supported_sample_counts
+                );
+                AaMode::Fxaa
+            }
+            mode => mode,
+        }
+    }
+
+    pub fn get_sample_count(self) -> u32 {
+        match self {
+            AaMode::Msaa(samples) => samples,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FxaaSettings {
+    pub edge_threshold: f32, // Minimum local contrast relative to the brightest sample
+    pub edge_threshold_min: f32, // Absolute contrast below which dark areas are skipped
+    pub subpixel: f32,       // Amount of subpixel aliasing removal, 0 is off and 1 is soft
+}
+
+impl Default for FxaaSettings {
+    fn default() -> Self {
+        Self {
+            edge_threshold: 0.125,
+            edge_threshold_min: 0.0312,
+            subpixel: 0.75,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FxaaUniformData {
+    pub inverse_screen_size: [f32; 2],
+    pub edge_threshold: f32,
+    pub edge_threshold_min: f32,
+    pub subpixel: f32,
+    pub _padding: [f32; 3],
+}
+
+impl FxaaUniformData {
+    pub fn new(settings: &FxaaSettings, width: u32, height: u32) -> Self {
+        Self {
+            inverse_screen_size: [1.0 / width.max(1) as f32, 1.0 / height.max(1) as f32],
+            edge_threshold: settings.edge_threshold.clamp(0.063, 0.333),
+            edge_threshold_min: settings.edge_threshold_min.clamp(0.0, 0.1),
+            subpixel: settings.subpixel.clamp(0.0, 1.0),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_msaa_falls_back_to_fxaa() {
+        let supported = [1, 4];
+        assert_eq!(AaMode::Msaa(4).resolve(&supported), AaMode::Msaa(4));
+        assert_eq!(AaMode::Msaa(8).resolve(&supported), AaMode::Fxaa);
+        assert_eq!(AaMode::Msaa(1).resolve(&supported), AaMode::Off);
+        assert_eq!(AaMode::Fxaa.resolve(&[1]), AaMode::Fxaa);
+    }
+
+    #[test]
+    fn fxaa_uniform_clamps_settings() {
+        let settings = FxaaSettings {
+            edge_threshold: 1.0,
+            edge_threshold_min: -1.0,
+            subpixel: 2.0,
+        };
+        let data = FxaaUniformData::new(&settings, 1920, 1080);
+        assert_eq!(data.edge_threshold, 0.333);
+        assert_eq!(data.edge_threshold_min, 0.0);
+        assert_eq!(data.subpixel, 1.0);
+        assert_eq!(data.inverse_screen_size, [1.0 / 1920.0, 1.0 / 1080.0]);
+    }
+}
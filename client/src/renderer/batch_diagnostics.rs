@@ -0,0 +1,193 @@
+// Materials that bind the same texture with the same pipeline and sampler draw the same,
+// but each of them still gets batches of its own, e.g. a grid texture made into a material
+// by every prop that uses it. These find them and how many batches they cost.
+
+use std::collections::HashMap;
+
+use crate::renderer::{ResourceHandle, ResourceKind, ResourcePool, renderer::BatchInfo};
+
+// What the bind group of a material was made from, recorded when it is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MaterialFingerprint {
+    pub pipeline: &'static str,  // e.g. "scene" or "premultiplied sprite"
+    pub texture: ResourceHandle, // The texture, or the font of the atlas
+    pub sampler: &'static str,
+    pub variant_count: u32, // 0 unless it is a variant material
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateMaterials {
+    pub fingerprint: MaterialFingerprint,
+    pub materials: Vec<ResourceHandle>, // By name, deduplicating keeps the first
+    pub names: Vec<String>,
+    pub split_batches: usize, // Batches of the frame that one material would have saved
+}
+
+// Materials already remapped to another one are left out, see Renderer::dedupe_materials.
+// The split batches are counted in the batches of the last frame, none without them.
+pub fn find_duplicate_materials(
+    resource_pool: &ResourcePool,
+    batches: &[BatchInfo],
+) -> Vec<DuplicateMaterials> {
+    let materials = resource_pool
+        .iter()
+        .filter(|(handle, kind)| {
+            *kind == ResourceKind::MaterialInstance
+                && resource_pool.get_canonical_material(*handle) == *handle
+        })
+        .filter_map(|(handle, _)| {
+            let fingerprint = resource_pool.get_material_instance(handle)?.fingerprint?;
+            let name = match resource_pool.get_name(handle) {
+                Some(name) => name.to_string(),
+                None => format!("{:016x}", handle),
+            };
+            Some((handle, name, fingerprint))
+        });
+    group_duplicates(materials, batches)
+}
+
+fn group_duplicates(
+    materials: impl Iterator<Item = (ResourceHandle, String, MaterialFingerprint)>,
+    batches: &[BatchInfo],
+) -> Vec<DuplicateMaterials> {
+    let mut groups: HashMap<MaterialFingerprint, Vec<(String, ResourceHandle)>> = HashMap::new();
+    for (handle, name, fingerprint) in materials {
+        groups.entry(fingerprint).or_default().push((name, handle));
+    }
+
+    let mut duplicates: Vec<DuplicateMaterials> = groups
+        .into_iter()
+        .filter(|(_, materials)| materials.len() > 1)
+        .map(|(fingerprint, mut materials)| {
+            materials.sort();
+            let handles: Vec<_> = materials.iter().map(|(_, handle)| *handle).collect();
+            DuplicateMaterials {
+                fingerprint,
+                split_batches: count_split_batches(&handles, batches),
+                materials: handles,
+                names: materials.into_iter().map(|(name, _)| name).collect(),
+            }
+        })
+        .collect();
+
+    // The costliest first
+    duplicates.sort_by(|a, b| {
+        b.split_batches
+            .cmp(&a.split_batches)
+            .then_with(|| a.names.cmp(&b.names))
+    });
+    duplicates
+}
+
+// Batches of the materials that only differ in the material, beyond the first of them
+fn count_split_batches(materials: &[ResourceHandle], batches: &[BatchInfo]) -> usize {
+    let mut slots: HashMap<_, usize> = HashMap::new();
+    for info in batches {
+        let batch = &info.batch;
+        if !materials.contains(&batch.material_instance) {
+            continue;
+        }
+        // Sprites of different layers never share a batch, see BatchKey::sort_key
+        let layer = if info.pass == "Sprite" {
+            batch.sort_key >> 48
+        } else {
+            0
+        };
+        let slot = (
+            info.pass,
+            batch.mesh,
+            batch.lod,
+            layer,
+            batch.casts_shadow,
+            batch.shadow_only,
+        );
+        *slots.entry(slot).or_default() += 1;
+    }
+    slots.values().map(|count| count - 1).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{
+        RenderData, StaticRenderJob,
+        render_data::{ALL_RENDER_LAYERS, SpriteRenderJob},
+    };
+
+    fn fingerprint(texture: ResourceHandle) -> MaterialFingerprint {
+        MaterialFingerprint {
+            pipeline: "scene",
+            texture,
+            sampler: "default",
+            variant_count: 0,
+        }
+    }
+
+    #[test]
+    fn identical_materials_are_reported_with_their_split_batches() {
+        let mut resource_pool = ResourcePool::new();
+        let mut render_data = RenderData::new();
+        let (grid_a, grid_b, rock) = (1, 2, 3);
+
+        let submit = |resource_pool: &ResourcePool, render_data: &mut RenderData| {
+            for material in [grid_a, grid_b, rock] {
+                render_data.submit(
+                    &StaticRenderJob {
+                        material,
+                        mesh: 10,
+                        ..Default::default()
+                    },
+                    resource_pool,
+                );
+            }
+            // Another layer would be a batch of its own anyway
+            for (material, layer) in [(grid_a, 0), (grid_b, 0), (grid_b, 1)] {
+                render_data.submit(
+                    &SpriteRenderJob {
+                        material,
+                        layer,
+                        ..Default::default()
+                    },
+                    resource_pool,
+                );
+            }
+            let (draw_data, _) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+            let mut batches = Vec::new();
+            draw_data.list_batches(&mut batches);
+            batches
+        };
+        let materials = || {
+            [
+                (grid_b, "Materials/GridB".to_string(), fingerprint(100)),
+                (grid_a, "Materials/GridA".to_string(), fingerprint(100)),
+                (rock, "Materials/Rock".to_string(), fingerprint(101)),
+            ]
+            .into_iter()
+        };
+
+        let batches = submit(&resource_pool, &mut render_data);
+        let duplicates = group_duplicates(materials(), &batches);
+        assert_eq!(
+            duplicates,
+            vec![DuplicateMaterials {
+                fingerprint: fingerprint(100),
+                materials: vec![grid_a, grid_b],
+                names: vec!["Materials/GridA".to_string(), "Materials/GridB".to_string()],
+                // The scene and shadow batches and the sprite batch of layer 0
+                split_batches: 3,
+            }]
+        );
+
+        // Remapped, they are drawn together
+        resource_pool.set_material_alias(grid_b, grid_a);
+        let batches = submit(&resource_pool, &mut render_data);
+        assert_eq!(count_split_batches(&[grid_a, grid_b], &batches), 0);
+        assert_eq!(
+            batches
+                .iter()
+                .filter(|info| info.batch.material_instance == grid_b)
+                .count(),
+            0
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use shared::math::*;
+
+use crate::renderer::{ResourceHandle, animation::AnimationInstance};
+
+const EPSILON: f32 = 1e-5;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BlendSample {
+    pub parameter: Vec2,
+    pub animation: ResourceHandle,
+    pub duration: f32, // Of the animation, every clip plays at the same normalized phase
+}
+
+// Blends clips by a 2D parameter, e.g. locomotion by forward and strafe speed. The samples
+// are triangulated once, a parameter inside a triangle blends its three clips by the
+// barycentric weights and one outside of the samples is clamped to the closest edge.
+pub struct BlendSpace2D {
+    samples: Vec<BlendSample>,
+    triangles: Vec<[usize; 3]>,
+    edges: Vec<[usize; 2]>, // The outline the parameter is clamped to
+}
+
+impl BlendSpace2D {
+    pub fn new(samples: Vec<BlendSample>) -> Self {
+        let points: Vec<Vec2> = samples.iter().map(|sample| sample.parameter).collect();
+        let triangles = triangulate(&points);
+        let edges = if triangles.is_empty() {
+            get_line_edges(&points)
+        } else {
+            get_outline_edges(&triangles)
+        };
+
+        Self {
+            samples,
+            triangles,
+            edges,
+        }
+    }
+
+    // At most three clips, their weights sum up to one
+    #[allow(dead_code)]
+    pub fn evaluate(&self, parameter: Vec2) -> impl Iterator<Item = (ResourceHandle, f32)> + '_ {
+        self.get_weights(parameter)
+            .into_iter()
+            .flatten()
+            .map(|(index, weight)| (self.samples[index].animation, weight))
+    }
+
+    // Advances the shared phase by the blended duration and writes the instances to pose
+    // with, so clips of different lengths keep their steps in sync
+    pub fn sample(
+        &self,
+        parameter: Vec2,
+        dt: f32,
+        phase: &mut f32,
+        instances: &mut Vec<AnimationInstance>,
+    ) {
+        let weights = self.get_weights(parameter);
+        let duration: f32 = weights
+            .iter()
+            .flatten()
+            .map(|&(index, weight)| self.samples[index].duration * weight)
+            .sum();
+        if duration > 0.0 {
+            *phase = (*phase + dt / duration).fract();
+        }
+
+        instances.clear();
+        instances.extend(weights.iter().flatten().map(|&(index, weight)| {
+            let sample = &self.samples[index];
+            AnimationInstance {
+                animation: sample.animation,
+                time: *phase * sample.duration,
+                looping: true,
+                blend_weight: weight,
+            }
+        }));
+    }
+
+    fn get_weights(&self, parameter: Vec2) -> [Option<(usize, f32)>; 3] {
+        if self.samples.len() == 1 {
+            return [Some((0, 1.0)), None, None];
+        }
+
+        for &[a, b, c] in &self.triangles {
+            let [u, v, w] = get_barycentric(
+                parameter,
+                self.samples[a].parameter,
+                self.samples[b].parameter,
+                self.samples[c].parameter,
+            );
+            if u.min(v).min(w) >= -EPSILON {
+                let [u, v, w] = [u.max(0.0), v.max(0.0), w.max(0.0)];
+                let sum = u + v + w;
+                return [(a, u / sum), (b, v / sum), (c, w / sum)]
+                    .map(|entry| Some(entry).filter(|(_, weight)| *weight > EPSILON));
+            }
+        }
+
+        // Outside, clamped to the closest point of the outline
+        let closest = self
+            .edges
+            .iter()
+            .map(|&[a, b]| {
+                let start = self.samples[a].parameter;
+                let end = self.samples[b].parameter;
+                let t = get_segment_parameter(parameter, start, end);
+                (a, b, t, parameter.distance_squared(start.lerp(end, t)))
+            })
+            .min_by(|x, y| x.3.total_cmp(&y.3));
+        match closest {
+            Some((a, b, t, _)) => [Some((a, 1.0 - t)), Some((b, t)), None]
+                .map(|entry| entry.filter(|(_, weight)| *weight > EPSILON)),
+            None => [None; 3],
+        }
+    }
+}
+
+fn get_barycentric(p: Vec2, a: Vec2, b: Vec2, c: Vec2) -> [f32; 3] {
+    let area = (b - a).perp_dot(c - a);
+    let u = (b - p).perp_dot(c - p) / area;
+    let v = (c - p).perp_dot(a - p) / area;
+    [u, v, 1.0 - u - v]
+}
+
+fn get_segment_parameter(p: Vec2, start: Vec2, end: Vec2) -> f32 {
+    let direction = end - start;
+    let length_squared = direction.length_squared();
+    if length_squared <= EPSILON {
+        return 0.0;
+    }
+    ((p - start).dot(direction) / length_squared).clamp(0.0, 1.0)
+}
+
+// Delaunay triangulation by Bowyer-Watson, counter-clockwise triangles. Empty when all
+// points are on a line, duplicated points are left out.
+fn triangulate(points: &[Vec2]) -> Vec<[usize; 3]> {
+    if points.len() < 3 {
+        return Vec::new();
+    }
+
+    // Scaled to a unit box so the tolerances work for any parameter range
+    let min = points.iter().fold(Vec2::MAX, |min, &p| min.min(p));
+    let max = points.iter().fold(Vec2::MIN, |max, &p| max.max(p));
+    let center = (min + max) * 0.5;
+    let size = (max - min).max_element().max(EPSILON);
+    let mut vertices: Vec<Vec2> = points.iter().map(|&p| (p - center) / size).collect();
+
+    // A triangle around every point, removed again at the end
+    let count = points.len();
+    vertices.extend([
+        Vec2::new(-20.0, -10.0),
+        Vec2::new(20.0, -10.0),
+        Vec2::new(0.0, 20.0),
+    ]);
+    let mut triangles = vec![[count, count + 1, count + 2]];
+
+    for index in 0..count {
+        let point = vertices[index];
+        if vertices[..index]
+            .iter()
+            .any(|p| p.distance(point) <= EPSILON)
+        {
+            continue;
+        }
+
+        let (bad, good): (Vec<_>, Vec<_>) = triangles
+            .into_iter()
+            .partition(|&triangle| is_in_circumcircle(&vertices, triangle, point));
+
+        // The edges around the hole the bad triangles leave
+        let edges: Vec<[usize; 2]> = bad
+            .iter()
+            .flat_map(|&[a, b, c]| [[a, b], [b, c], [c, a]])
+            .collect();
+        let boundary = edges.iter().filter(|&&[a, b]| {
+            edges
+                .iter()
+                .filter(|&&[c, d]| (a == c && b == d) || (a == d && b == c))
+                .count()
+                == 1
+        });
+
+        triangles = good;
+        triangles.extend(boundary.map(|&[a, b]| [a, b, index]));
+    }
+
+    triangles.retain(|triangle| {
+        let [a, b, c] = triangle.map(|index| vertices[index]);
+        triangle.iter().all(|&index| index < count) && (b - a).perp_dot(c - a).abs() > EPSILON
+    });
+    triangles
+}
+
+fn is_in_circumcircle(vertices: &[Vec2], [a, b, c]: [usize; 3], point: Vec2) -> bool {
+    let [a, b, c] = [vertices[a], vertices[b], vertices[c]].map(|v| v - point);
+    let determinant = a.length_squared() * b.perp_dot(c) - b.length_squared() * a.perp_dot(c)
+        + c.length_squared() * a.perp_dot(b);
+    // Positive for counter-clockwise triangles
+    determinant > EPSILON
+}
+
+// Edges used by a single triangle
+fn get_outline_edges(triangles: &[[usize; 3]]) -> Vec<[usize; 2]> {
+    let edges: Vec<[usize; 2]> = triangles
+        .iter()
+        .flat_map(|&[a, b, c]| [[a, b], [b, c], [c, a]])
+        .collect();
+    edges
+        .iter()
+        .filter(|&&[a, b]| !edges.contains(&[b, a]))
+        .copied()
+        .collect()
+}
+
+// Neighbours along the line the points lie on
+fn get_line_edges(points: &[Vec2]) -> Vec<[usize; 2]> {
+    let Some(&first) = points.first() else {
+        return Vec::new();
+    };
+    let far = points
+        .iter()
+        .copied()
+        .max_by(|a, b| {
+            a.distance_squared(first)
+                .total_cmp(&b.distance_squared(first))
+        })
+        .unwrap_or(first);
+    let direction = far - first;
+
+    let mut order: Vec<usize> = (0..points.len()).collect();
+    order.sort_by(|&a, &b| {
+        let a = (points[a] - first).dot(direction);
+        let b = (points[b] - first).dot(direction);
+        a.total_cmp(&b)
+    });
+    order.windows(2).map(|pair| [pair[0], pair[1]]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Idle in the middle, a clip per direction around it
+    fn get_locomotion() -> BlendSpace2D {
+        let parameters = [
+            Vec2::ZERO,
+            Vec2::new(1.0, 0.0),
+            Vec2::new(0.0, 1.0),
+            Vec2::new(-1.0, 0.0),
+            Vec2::new(0.0, -1.0),
+        ];
+        BlendSpace2D::new(
+            parameters
+                .iter()
+                .enumerate()
+                .map(|(index, &parameter)| BlendSample {
+                    parameter,
+                    animation: index as ResourceHandle,
+                    duration: 1.0 + index as f32,
+                })
+                .collect(),
+        )
+    }
+
+    // The weights sum up to one and reproduce the parameter they were evaluated for
+    fn blend(space: &BlendSpace2D, parameter: Vec2) -> (usize, Vec2) {
+        let weights: Vec<_> = space.evaluate(parameter).collect();
+        let sum: f32 = weights.iter().map(|(_, weight)| weight).sum();
+        assert!((sum - 1.0).abs() < 1e-4);
+        let blended = weights
+            .iter()
+            .fold(Vec2::ZERO, |blended, &(handle, weight)| {
+                blended + space.samples[handle as usize].parameter * weight
+            });
+        (weights.len(), blended)
+    }
+
+    #[test]
+    fn blends_three_clips_inside_the_hull() {
+        let space = get_locomotion();
+        assert_eq!(space.triangles.len(), 4);
+
+        let parameter = Vec2::new(0.3, 0.2);
+        let (count, blended) = blend(&space, parameter);
+        assert_eq!(count, 3);
+        assert!(blended.distance(parameter) < 1e-4);
+
+        let (count, _) = blend(&space, Vec2::new(0.0, 1.0));
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn blends_two_clips_on_an_edge() {
+        let space = get_locomotion();
+        for parameter in [Vec2::new(0.5, 0.5), Vec2::new(-0.4, 0.0)] {
+            let (count, blended) = blend(&space, parameter);
+            assert_eq!(count, 2);
+            assert!(blended.distance(parameter) < 1e-4);
+        }
+    }
+
+    #[test]
+    fn clamps_to_the_hull_outside() {
+        let space = get_locomotion();
+        let (count, blended) = blend(&space, Vec2::new(3.0, 0.0));
+        assert_eq!(count, 1);
+        assert!(blended.distance(Vec2::new(1.0, 0.0)) < 1e-4);
+
+        let (count, blended) = blend(&space, Vec2::new(2.0, 2.0));
+        assert_eq!(count, 2);
+        assert!(blended.distance(Vec2::new(0.5, 0.5)) < 1e-4);
+    }
+
+    #[test]
+    fn samples_on_a_line_blend_like_a_1d_space() {
+        let space = BlendSpace2D::new(
+            [0.0, 2.0, 1.0]
+                .iter()
+                .enumerate()
+                .map(|(index, &x)| BlendSample {
+                    parameter: Vec2::new(x, 0.0),
+                    animation: index as ResourceHandle,
+                    duration: 1.0,
+                })
+                .collect(),
+        );
+        assert!(space.triangles.is_empty());
+
+        let (count, blended) = blend(&space, Vec2::new(1.5, 1.0));
+        assert_eq!(count, 2);
+        assert!(blended.distance(Vec2::new(1.5, 0.0)) < 1e-4);
+    }
+
+    #[test]
+    fn clips_share_the_phase() {
+        let space = get_locomotion();
+        let mut phase = 0.0;
+        let mut instances = Vec::new();
+
+        // Halfway between idle (1s) and the forward clip (2s), a cycle takes 1.5s
+        space.sample(Vec2::new(0.5, 0.0), 0.75, &mut phase, &mut instances);
+        assert!((phase - 0.5).abs() < 1e-4);
+        assert_eq!(instances.len(), 2);
+        for instance in &instances {
+            let duration = 1.0 + instance.animation as f32;
+            assert!((instance.time - 0.5 * duration).abs() < 1e-4);
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use wgpu::util::DrawIndexedIndirectArgs;
+
 use crate::renderer::RenderDevice;
 
 pub struct BufferDesc {
@@ -24,4 +26,26 @@ impl RenderDevice {
     pub fn write_buffer(&self, buffer: &Buffer, data: &[u8], offset: usize) {
         self.queue.write_buffer(&buffer.buffer, offset as u64, data);
     }
+
+    // Arguments for draw_indexed_indirect, one draw per slot. Compute shaders may write
+    // them and they can be copied out to check what was drawn.
+    pub fn create_indirect_buffer(&self, count: usize) -> Buffer {
+        self.create_buffer(&BufferDesc {
+            size: count.max(1) * std::mem::size_of::<DrawIndexedIndirectArgs>(),
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        })
+    }
+
+    pub fn write_indirect_args(
+        &self,
+        buffer: &Buffer,
+        slot: usize,
+        args: &DrawIndexedIndirectArgs,
+    ) {
+        let offset = slot * std::mem::size_of::<DrawIndexedIndirectArgs>();
+        self.write_buffer(buffer, args.as_bytes(), offset);
+    }
 }
@@ -0,0 +1,258 @@
+// A champion's assets in one file, written by the bundle command of the tools. A table of
+// contents comes first, then the entries, each one the file its converter would write:
+//
+// u32 entry count, then per entry
+//     u32 name length, the name as UTF-8
+//     u32 kind, see BundleEntry::get_kind_code
+//     u32 flags, ZSTD_FLAG when the entry is compressed
+//     u64 offset from the start of the file, u64 length as stored
+// the entry data
+
+use std::{borrow::Cow, collections::BTreeMap, io::Read};
+
+use anyhow::{Context, bail};
+
+use crate::renderer::{ResourceHandle, ResourceKind};
+
+pub const ZSTD_FLAG: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BundleEntry {
+    pub name: String,
+    pub kind: ResourceKind,
+    pub compressed: bool,
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl BundleEntry {
+    // Only the kinds the converters write
+    pub fn get_kind_code(kind: ResourceKind) -> Option<u32> {
+        match kind {
+            ResourceKind::StaticMesh => Some(0),
+            ResourceKind::SkeletalMesh => Some(1),
+            ResourceKind::Texture => Some(2),
+            ResourceKind::Animation => Some(3),
+            _ => None,
+        }
+    }
+
+    fn get_kind(code: u32) -> Option<ResourceKind> {
+        [
+            ResourceKind::StaticMesh,
+            ResourceKind::SkeletalMesh,
+            ResourceKind::Texture,
+            ResourceKind::Animation,
+        ]
+        .into_iter()
+        .find(|kind| Self::get_kind_code(*kind) == Some(code))
+    }
+
+    // The file the converter wrote, decompressed if needed
+    pub fn get_bytes<'a>(&self, bundle: &'a [u8]) -> anyhow::Result<Cow<'a, [u8]>> {
+        let range = usize::try_from(self.offset)
+            .ok()
+            .zip(usize::try_from(self.length).ok())
+            .and_then(|(offset, length)| Some(offset..offset.checked_add(length)?));
+        let Some(stored) = range.and_then(|range| bundle.get(range)) else {
+            bail!(
+                "{} bytes at {} are past the end of the bundle ({} bytes)",
+                self.length,
+                self.offset,
+                bundle.len()
+            );
+        };
+        if !self.compressed {
+            return Ok(Cow::Borrowed(stored));
+        }
+
+        let mut decoder = ruzstd::decoding::StreamingDecoder::new(stored)
+            .map_err(|error| anyhow::anyhow!("Invalid zstd frame: {}", error))?;
+        let mut bytes = Vec::new();
+        decoder
+            .read_to_end(&mut bytes)
+            .context("Failed to decompress")?;
+        Ok(Cow::Owned(bytes))
+    }
+}
+
+// In the order of the table of contents
+pub fn read_bundle_entries(bytes: &[u8]) -> anyhow::Result<Vec<BundleEntry>> {
+    let mut reader = BundleReader { bytes, offset: 0 };
+    let entry_count = reader
+        .read_u32()
+        .context("Failed to read the entry count")?;
+
+    let mut entries = Vec::new();
+    for index in 0..entry_count {
+        let entry = reader
+            .read_entry()
+            .with_context(|| format!("Failed to read entry {} of the table", index))?;
+        if entries
+            .iter()
+            .any(|other: &BundleEntry| other.name == entry.name)
+        {
+            bail!("The bundle has two entries named {}", entry.name);
+        }
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+struct BundleReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl BundleReader<'_> {
+    fn read<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        let Some(bytes) = self.bytes.get(self.offset..self.offset + N) else {
+            bail!("The bundle ends at {} bytes", self.bytes.len());
+        };
+        self.offset += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn read_u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.read()?))
+    }
+
+    fn read_u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.read()?))
+    }
+
+    fn read_entry(&mut self) -> anyhow::Result<BundleEntry> {
+        let name_length = self.read_u32()? as usize;
+        let Some(name) = self.bytes.get(self.offset..self.offset + name_length) else {
+            bail!("The bundle ends at {} bytes", self.bytes.len());
+        };
+        let name = String::from_utf8(name.to_vec()).context("The name is not UTF-8")?;
+        self.offset += name_length;
+
+        let kind_code = self.read_u32()?;
+        let Some(kind) = BundleEntry::get_kind(kind_code) else {
+            bail!("{} has the unknown kind {}", name, kind_code);
+        };
+        let flags = self.read_u32()?;
+
+        Ok(BundleEntry {
+            name,
+            kind,
+            compressed: flags & ZSTD_FLAG != 0,
+            offset: self.read_u64()?,
+            length: self.read_u64()?,
+        })
+    }
+}
+
+// What Renderer::load_bundle registered, by entry name. The resources themselves are named
+// "{prefix}/{entry name}".
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BundleHandles {
+    pub handles: BTreeMap<String, ResourceHandle>,
+}
+
+impl BundleHandles {
+    #[allow(dead_code)]
+    pub fn get(&self, name: &str) -> Option<ResourceHandle> {
+        self.handles.get(name).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Same layout as the tool writes, see tools/src/bundle.rs
+    fn write_bundle(entries: &[(&str, ResourceKind, &[u8], bool)]) -> Vec<u8> {
+        let stored: Vec<Vec<u8>> = entries
+            .iter()
+            .map(|(_, _, bytes, compressed)| {
+                if *compressed {
+                    let level = ruzstd::encoding::CompressionLevel::Fastest;
+                    ruzstd::encoding::compress_to_vec(*bytes, level)
+                } else {
+                    bytes.to_vec()
+                }
+            })
+            .collect();
+
+        let table_length: usize = entries
+            .iter()
+            .map(|(name, _, _, _)| 4 + name.len() + 4 + 4 + 8 + 8)
+            .sum();
+        let mut offset = (4 + table_length) as u64;
+
+        let mut bundle = (entries.len() as u32).to_le_bytes().to_vec();
+        for ((name, kind, _, compressed), data) in entries.iter().zip(&stored) {
+            bundle.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            bundle.extend_from_slice(name.as_bytes());
+            let kind_code = BundleEntry::get_kind_code(*kind).unwrap();
+            bundle.extend_from_slice(&kind_code.to_le_bytes());
+            let flags = if *compressed { ZSTD_FLAG } else { 0 };
+            bundle.extend_from_slice(&flags.to_le_bytes());
+            bundle.extend_from_slice(&offset.to_le_bytes());
+            bundle.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            offset += data.len() as u64;
+        }
+        for data in &stored {
+            bundle.extend_from_slice(data);
+        }
+        bundle
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let texture: Vec<u8> = (0..200).map(|i| (i % 7) as u8).collect();
+        let bundle = write_bundle(&[
+            ("Brute", ResourceKind::SkeletalMesh, &[1, 2, 3, 4], false),
+            ("Body", ResourceKind::Texture, &texture, true),
+            ("Idle", ResourceKind::Animation, &[], false),
+        ]);
+
+        let entries = read_bundle_entries(&bundle).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["Brute", "Body", "Idle"]);
+        assert_eq!(entries[0].kind, ResourceKind::SkeletalMesh);
+        assert_eq!(entries[1].kind, ResourceKind::Texture);
+        assert!(entries[1].compressed);
+        assert!(entries[1].length < texture.len() as u64);
+
+        assert_eq!(&*entries[0].get_bytes(&bundle).unwrap(), &[1, 2, 3, 4]);
+        assert_eq!(&*entries[1].get_bytes(&bundle).unwrap(), texture.as_slice());
+        assert!(entries[2].get_bytes(&bundle).unwrap().is_empty());
+    }
+
+    #[test]
+    fn broken_bundles_name_the_entry() {
+        let bundle = write_bundle(&[
+            ("Brute", ResourceKind::SkeletalMesh, &[1, 2, 3, 4], false),
+            ("Body", ResourceKind::Texture, &[5, 6, 7, 8], false),
+        ]);
+
+        // Cut into the data of the last entry
+        let entries = read_bundle_entries(&bundle[..bundle.len() - 2]).unwrap();
+        assert!(entries[0].get_bytes(&bundle[..bundle.len() - 2]).is_ok());
+        let error = entries[1].get_bytes(&bundle[..bundle.len() - 2]);
+        assert!(error.unwrap_err().to_string().contains("past the end"));
+
+        // Cut into the table of contents, the first entry takes up 33 bytes after the count
+        let error = read_bundle_entries(&bundle[..50]).unwrap_err();
+        assert!(format!("{:#}", error).contains("entry 1"));
+
+        let duplicate = write_bundle(&[
+            ("Body", ResourceKind::Texture, &[], false),
+            ("Body", ResourceKind::Texture, &[], false),
+        ]);
+        let error = read_bundle_entries(&duplicate).unwrap_err();
+        assert!(error.to_string().contains("two entries named Body"));
+
+        // Not a zstd frame anymore
+        let mut corrupt = write_bundle(&[("Body", ResourceKind::Texture, &[1; 64], true)]);
+        let entries = read_bundle_entries(&corrupt).unwrap();
+        let data_start = entries[0].offset as usize;
+        corrupt[data_start..data_start + 4].fill(0);
+        assert!(entries[0].get_bytes(&corrupt).is_err());
+    }
+}
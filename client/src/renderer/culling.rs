@@ -0,0 +1,160 @@
+// Frustum culling of the persistent instances on the GPU. Every instance gets a bounding
+// sphere when the instances are created. Each frame a compute pass tests the spheres against
+// the camera frustum, copies the instances inside to the front of a second buffer and counts
+// them into the indirect arguments the scene pass draws them with, see res/shaders/cull.wgsl.
+// The functions here are the same tests on the CPU, the reference the GPU counts are
+// validated against.
+
+use shared::math::*;
+
+pub const CULL_WORKGROUP_SIZE: u32 = 64; // Matches cull.wgsl
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CullUniformData {
+    pub planes: [Vec4Data; 6],
+}
+
+// Of the persistent instances of one frame, when the GPU counts are validated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GpuCullCount {
+    pub instances: u64, // The handle of the persistent instances
+    pub gpu: u32,
+    pub cpu: u32,
+}
+
+// xyz is the normal pointing inside, w the distance. Normalized, so the distance of a point
+// to a plane is in world units.
+pub fn get_frustum_planes(view_projection: Mat4) -> [Vec4; 6] {
+    let rows = [0, 1, 2, 3].map(|index| view_projection.row(index));
+    // Clip space depth goes from 0 to 1, so the near plane is the depth row alone
+    [
+        rows[3] + rows[0],
+        rows[3] - rows[0],
+        rows[3] + rows[1],
+        rows[3] - rows[1],
+        rows[2],
+        rows[3] - rows[2],
+    ]
+    .map(|plane| plane / plane.truncate().length())
+}
+
+// The sphere is xyz the center and w the radius
+pub fn is_sphere_in_frustum(planes: &[Vec4; 6], sphere: Vec4) -> bool {
+    planes
+        .iter()
+        .all(|plane| plane.truncate().dot(sphere.truncate()) + plane.w >= -sphere.w)
+}
+
+// Conservative, a box next to a corner of the frustum can pass without being inside
+pub fn is_box_in_frustum(planes: &[Vec4; 6], min: Vec3, max: Vec3) -> bool {
+    planes.iter().all(|plane| {
+        // The corner furthest along the plane normal decides
+        let normal = plane.truncate();
+        let corner = Vec3::select(normal.cmpge(Vec3::ZERO), max, min);
+        normal.dot(corner) + plane.w >= 0.0
+    })
+}
+
+// Around the box of the mesh, placed with the instance transform. Scaled by the largest
+// axis, so it still holds the mesh under non-uniform scales.
+pub fn get_bounding_sphere(transform: Mat4, min: Vec3, max: Vec3) -> Vec4 {
+    let center = transform.transform_point3((min + max) * 0.5);
+    let scale = [transform.x_axis, transform.y_axis, transform.z_axis]
+        .map(|axis| axis.truncate().length())
+        .into_iter()
+        .fold(0.0, f32::max);
+    center.extend((max - min).length() * 0.5 * scale)
+}
+
+pub fn count_visible_spheres(planes: &[Vec4; 6], spheres: &[Vec4]) -> u32 {
+    spheres
+        .iter()
+        .filter(|sphere| is_sphere_in_frustum(planes, **sphere))
+        .count() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spheres_are_culled_by_every_plane() {
+        // Looking down -z from the origin
+        let camera = Mat4::perspective_rh(f32::to_radians(90.0), 1.0, 1.0, 100.0);
+        let planes = get_frustum_planes(camera);
+        for plane in &planes {
+            assert!((plane.truncate().length() - 1.0).abs() < 1e-5);
+        }
+
+        let inside = Vec4::new(0.0, 0.0, -50.0, 1.0);
+        assert!(is_sphere_in_frustum(&planes, inside));
+        // Behind the camera, past the far plane, and beside the frustum
+        assert!(!is_sphere_in_frustum(
+            &planes,
+            Vec4::new(0.0, 0.0, 5.0, 1.0)
+        ));
+        assert!(!is_sphere_in_frustum(
+            &planes,
+            Vec4::new(0.0, 0.0, -110.0, 5.0)
+        ));
+        assert!(!is_sphere_in_frustum(
+            &planes,
+            Vec4::new(60.0, 0.0, -50.0, 5.0)
+        ));
+        assert!(!is_sphere_in_frustum(
+            &planes,
+            Vec4::new(0.0, -60.0, -50.0, 5.0)
+        ));
+        // Its center is outside, but the radius reaches in
+        assert!(is_sphere_in_frustum(
+            &planes,
+            Vec4::new(0.0, 0.0, -103.0, 5.0)
+        ));
+        assert!(is_sphere_in_frustum(
+            &planes,
+            Vec4::new(53.0, 0.0, -50.0, 5.0)
+        ));
+
+        let spheres = [inside, Vec4::new(0.0, 0.0, 5.0, 1.0), inside];
+        assert_eq!(count_visible_spheres(&planes, &spheres), 2);
+
+        assert!(is_box_in_frustum(
+            &planes,
+            Vec3::splat(-1.0),
+            Vec3::new(1.0, 1.0, -2.0)
+        ));
+        assert!(!is_box_in_frustum(
+            &planes,
+            Vec3::new(-1.0, -1.0, 2.0),
+            Vec3::splat(3.0)
+        ));
+        // Between the camera and the near plane
+        let near_min = Vec3::new(-0.5, -0.5, -0.9);
+        assert!(!is_box_in_frustum(
+            &planes,
+            near_min,
+            Vec3::new(0.5, 0.5, -0.5)
+        ));
+    }
+
+    #[test]
+    fn bounding_spheres_hold_the_transformed_box() {
+        let (min, max) = (Vec3::new(-1.0, 0.0, -2.0), Vec3::new(1.0, 4.0, 2.0));
+        let transform = Mat4::from_scale_rotation_translation(
+            Vec3::new(1.0, 3.0, 2.0),
+            Quat::from_rotation_y(0.7),
+            Vec3::new(10.0, 0.0, -5.0),
+        );
+        let sphere = get_bounding_sphere(transform, min, max);
+        for corner in 0..8 {
+            let local = Vec3::select(
+                glam::BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0),
+                max,
+                min,
+            );
+            let world = transform.transform_point3(local);
+            assert!(world.distance(sphere.truncate()) <= sphere.w + 1e-4);
+        }
+    }
+}
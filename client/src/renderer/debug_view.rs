@@ -0,0 +1,93 @@
+use crate::renderer::PassTarget;
+
+// What the scene pass draws instead of the lit materials, for finding problems with meshes
+// and fill rate. Only the pipelines of the scene batches change, the materials and their
+// instances stay as they are.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DebugView {
+    #[default]
+    None,
+    Wireframe, // The triangle edges, lit like the scene
+    Overdraw,  // Brighter the more often a pixel was shaded, hidden or not
+    Unlit,     // The texture and color of the materials
+    Normals,   // The world normals, 0.5 + 0.5 * normal as the color
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 5] = [
+        DebugView::None,
+        DebugView::Wireframe,
+        DebugView::Overdraw,
+        DebugView::Unlit,
+        DebugView::Normals,
+    ];
+
+    // Wireframes need the POLYGON_MODE_LINE feature, which WebGPU doesn't have. Without it
+    // the scene is drawn as it is.
+    pub fn resolve(self, supports_wireframe: bool) -> DebugView {
+        match self {
+            DebugView::Wireframe if !supports_wireframe => {
+                log::warn!("Wireframes are not supported on this adapter.");
+                DebugView::None
+            }
+            view => view,
+        }
+    }
+
+    pub fn get_next(self) -> DebugView {
+        let index = Self::ALL.iter().position(|view| *view == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn get_name(self) -> &'static str {
+        match self {
+            DebugView::None => "None",
+            DebugView::Wireframe => "Wireframe",
+            DebugView::Overdraw => "Overdraw",
+            DebugView::Unlit => "Unlit",
+            DebugView::Normals => "Normals",
+        }
+    }
+
+    // Of the scene fragment shader
+    pub fn get_defines(self) -> &'static [&'static str] {
+        match self {
+            DebugView::None | DebugView::Wireframe => &[],
+            DebugView::Overdraw => &["OVERDRAW"],
+            DebugView::Unlit => &["DEBUG_UNLIT"],
+            DebugView::Normals => &["DEBUG_NORMALS"],
+        }
+    }
+
+    pub fn get_pass_target(self) -> PassTarget {
+        match self {
+            DebugView::Wireframe => PassTarget::Wireframe,
+            DebugView::Overdraw => PassTarget::Overdraw,
+            DebugView::None | DebugView::Unlit | DebugView::Normals => PassTarget::Scene,
+        }
+    }
+
+    // Also drawn instead of the additive pipeline, which is unlit already
+    pub fn replaces_additive(self) -> bool {
+        matches!(self, DebugView::Wireframe | DebugView::Overdraw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn views_cycle_and_fall_back_without_wireframes() {
+        let mut view = DebugView::None;
+        for expected in DebugView::ALL.iter().skip(1) {
+            view = view.get_next();
+            assert_eq!(view, *expected);
+        }
+        assert_eq!(view.get_next(), DebugView::None);
+
+        assert_eq!(DebugView::Wireframe.resolve(true), DebugView::Wireframe);
+        assert_eq!(DebugView::Wireframe.resolve(false), DebugView::None);
+        assert_eq!(DebugView::Normals.resolve(false), DebugView::Normals);
+    }
+}
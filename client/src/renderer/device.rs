@@ -1,25 +1,115 @@
+use serde::Serialize;
+use shared::math::UVec2;
 use wgpu::ExperimentalFeatures;
 use winit::window::Window;
 
-use std::sync::Arc;
+use crate::renderer::shader::{ShaderKey, get_shader_source, preprocess_shader};
+#[cfg(debug_assertions)]
+use crate::renderer::shader_layout::{VertexInput, get_vertex_layout_diff, scan_vertex_inputs};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+pub const MAX_FRAMES_IN_FLIGHT: u32 = 3;
+
+// How the vertex shaders get the instances. WebGL2 can't read storage buffers in them, the
+// instances are vertex attributes stepped per instance there and the bones and sprite
+// params are read from textures, see ShaderData.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstancePath {
+    StorageBuffers,
+    VertexBuffers,
+}
+
+// Defined for every shader on InstancePath::VertexBuffers
+pub const INSTANCE_VERTEX_BUFFER_DEFINE: &str = "INSTANCE_VERTEX_BUFFER";
+
+// The adapter, what the device was created with and what the renderer does without, for
+// bug reports. The limits are the ones the renderer relies on.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeviceDiagnostics {
+    pub adapter: String,
+    pub vendor: String, // PCI ids where the backend has them
+    pub device: String,
+    pub device_type: String,
+    pub backend: String,
+    pub driver: String,
+    pub driver_info: String,
+    pub surface_format: String,
+    pub present_mode: String,
+    pub alpha_mode: String,
+    pub features: Vec<String>,
+    pub limits: Vec<(String, u64)>,
+    pub fallbacks: Vec<String>,
+}
+
+impl DeviceDiagnostics {
+    // One per line on the overlay, the limits are left out
+    pub fn get_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("Adapter: {} ({})", self.adapter, self.device_type),
+            format!("Vendor: {} | Device: {}", self.vendor, self.device),
+            format!(
+                "Backend: {} | Driver: {} {}",
+                self.backend, self.driver, self.driver_info
+            ),
+            format!(
+                "Surface: {} | Present mode: {} | Alpha mode: {}",
+                self.surface_format, self.present_mode, self.alpha_mode
+            ),
+            format!("Features: {}", self.features.join(", ")),
+        ];
+        if self.fallbacks.is_empty() {
+            lines.push("No fallbacks".to_string());
+        }
+        lines.extend(
+            self.fallbacks
+                .iter()
+                .map(|fallback| format!("Fallback: {}", fallback)),
+        );
+        lines
+    }
+}
 
 pub struct RenderDevice {
-    pub surface: wgpu::Surface<'static>,
+    pub surface: Option<wgpu::Surface<'static>>, // None when rendering offscreen
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
     pub is_surface_configured: bool,
+    adapter: wgpu::Adapter, // The surface capabilities are asked again when it is lost
+    transparent: bool,
+    vsync: bool, // Off presents as soon as a frame is done, see choose_present_mode
+    pub scene_sample_counts: Vec<u32>, // MSAA sample counts usable for the scene targets
+    supports_compute: bool, // Compute shaders and indirect draws, for the GPU culling
+    supports_wireframe: bool, // POLYGON_MODE_LINE, for DebugView::Wireframe
+    instance_path: InstancePath,
+    validation_errors: Arc<Mutex<Vec<String>>>, // Instead of the default handler panicking
+    lost: Arc<AtomicBool>, // Set by wgpu when the driver resets or the device is destroyed
+    max_frames_in_flight: u32, // How far the CPU may run ahead of the GPU
+    in_flight: VecDeque<(u64, wgpu::SubmissionIndex)>, // Frames the GPU may still run, oldest first
+    submitted_frame_count: u64,
+    completed_frame_count: Arc<AtomicU64>, // Counted up by the queue as the frames finish
+    shaders: Mutex<HashMap<ShaderKey, wgpu::ShaderModule>>, // By file and defines
+    // The label and vertex inputs of the vertex shaders, for the pipelines to check against
+    #[cfg(debug_assertions)]
+    vertex_inputs: Mutex<HashMap<wgpu::ShaderModule, (String, Vec<VertexInput>)>>,
 }
 
 impl RenderDevice {
-    pub async fn new(window: &Arc<Window>) -> anyhow::Result<Self> {
-        let size = window.inner_size();
-
+    // A transparent window also needs to be created with transparency by winit, the
+    // surface falls back to opaque when the platform can't composite it
+    pub async fn new(window: &Arc<Window>, transparent: bool) -> anyhow::Result<Self> {
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
             #[cfg(not(target_arch = "wasm32"))]
             backends: wgpu::Backends::PRIMARY,
             #[cfg(target_arch = "wasm32")]
-            backends: wgpu::Backends::BROWSER_WEBGPU,
+            backends: wgpu::Backends::BROWSER_WEBGPU | wgpu::Backends::GL,
             ..Default::default()
         });
 
@@ -36,10 +126,10 @@ impl RenderDevice {
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
                 label: None,
-                required_features: wgpu::Features::empty(),
+                required_features: Self::get_optional_features(&adapter),
                 experimental_features: ExperimentalFeatures::disabled(),
                 required_limits: if cfg!(target_arch = "wasm32") {
-                    wgpu::Limits::downlevel_defaults()
+                    Self::get_web_limits(&adapter)
                 } else {
                     wgpu::Limits::defaults()
                 },
@@ -48,33 +138,740 @@ impl RenderDevice {
             })
             .await?;
 
-        let surface_capabilities = surface.get_capabilities(&adapter);
-        let surface_format = surface_capabilities
-            .formats
-            .iter()
-            .find(|f| f.is_srgb())
-            .copied()
-            .unwrap_or(surface_capabilities.formats[0]);
-
-        log::log!(log::Level::Info, "Surface format: {:?}", surface_format);
-
-        let surface_config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_capabilities.present_modes[0],
-            alpha_mode: surface_capabilities.alpha_modes[0],
-            view_formats: vec![],
-            desired_maximum_frame_latency: 2,
-        };
+        let size = window.inner_size();
+        let surface_config = choose_surface_config(
+            &surface.get_capabilities(&adapter),
+            size.width,
+            size.height,
+            transparent,
+            true,
+        );
+        log::info!(
+            "Surface format: {:?} | Present mode: {:?} | Alpha mode: {:?}",
+            surface_config.format,
+            surface_config.present_mode,
+            surface_config.alpha_mode
+        );
 
         Ok(Self {
-            surface,
+            surface: Some(surface),
+            scene_sample_counts: Self::get_scene_sample_counts(&adapter),
+            supports_compute: Self::get_compute_support(&adapter, &device),
+            supports_wireframe: device
+                .features()
+                .contains(wgpu::Features::POLYGON_MODE_LINE),
+            instance_path: Self::get_instance_path_support(&adapter, &device),
+            validation_errors: Self::capture_errors(&device),
+            lost: Self::watch_device_loss(&device),
+            max_frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            in_flight: VecDeque::new(),
+            submitted_frame_count: 0,
+            completed_frame_count: Arc::new(AtomicU64::new(0)),
+            shaders: Default::default(),
+            #[cfg(debug_assertions)]
+            vertex_inputs: Default::default(),
             device,
             queue,
             config: surface_config,
             is_surface_configured: false,
+            adapter,
+            transparent,
+            vsync: true,
+        })
+    }
+
+    // Renders into textures only, used by the golden-image tests and client --headless. Any
+    // adapter will do, including software ones, so it also runs on machines without a GPU.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn new_headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await?;
+
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: None,
+                required_features: Self::get_optional_features(&adapter),
+                experimental_features: ExperimentalFeatures::disabled(),
+                required_limits: wgpu::Limits::downlevel_defaults()
+                    .using_resolution(adapter.limits()),
+                memory_hints: Default::default(),
+                trace: wgpu::Trace::Off,
+            })
+            .await?;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: DEFAULT_FRAMES_IN_FLIGHT,
+        };
+
+        Ok(Self {
+            surface: None,
+            scene_sample_counts: Self::get_scene_sample_counts(&adapter),
+            supports_compute: Self::get_compute_support(&adapter, &device),
+            supports_wireframe: device
+                .features()
+                .contains(wgpu::Features::POLYGON_MODE_LINE),
+            instance_path: Self::get_instance_path_support(&adapter, &device),
+            validation_errors: Self::capture_errors(&device),
+            lost: Self::watch_device_loss(&device),
+            max_frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            in_flight: VecDeque::new(),
+            submitted_frame_count: 0,
+            completed_frame_count: Arc::new(AtomicU64::new(0)),
+            shaders: Default::default(),
+            #[cfg(debug_assertions)]
+            vertex_inputs: Default::default(),
+            device,
+            queue,
+            config,
+            is_surface_configured: true,
+            adapter,
+            transparent: false,
+            vsync: true,
         })
     }
+
+    // WebGL2 and some older GPUs have neither
+    fn get_compute_support(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        let required = wgpu::DownlevelFlags::COMPUTE_SHADERS
+            | wgpu::DownlevelFlags::INDIRECT_EXECUTION
+            | wgpu::DownlevelFlags::VERTEX_STORAGE;
+        let limits = device.limits();
+        adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(required)
+            && limits.max_compute_workgroups_per_dimension > 0
+            && limits.max_storage_buffers_per_shader_stage >= 4
+    }
+
+    // The cull pass writes the instances for the storage buffer path only
+    pub fn supports_compute(&self) -> bool {
+        self.supports_compute && self.instance_path == InstancePath::StorageBuffers
+    }
+
+    // WebGL2 gets the limits it can meet, browsers with WebGPU the usual ones
+    fn get_web_limits(adapter: &wgpu::Adapter) -> wgpu::Limits {
+        let flags = adapter.get_downlevel_capabilities().flags;
+        if flags.contains(wgpu::DownlevelFlags::VERTEX_STORAGE) {
+            wgpu::Limits::downlevel_defaults()
+        } else {
+            wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits())
+        }
+    }
+
+    fn get_instance_path_support(adapter: &wgpu::Adapter, device: &wgpu::Device) -> InstancePath {
+        choose_instance_path(adapter.get_downlevel_capabilities().flags, &device.limits())
+    }
+
+    pub fn get_instance_path(&self) -> InstancePath {
+        self.instance_path
+    }
+
+    // Takes the WebGL2 path on any device, so the golden-image tests cover it on native.
+    // Only before the renderer makes its shaders and pipelines.
+    #[cfg(feature = "test-harness")]
+    pub fn force_instance_vertex_buffers(&mut self) {
+        self.instance_path = InstancePath::VertexBuffers;
+    }
+
+    // The layout of the instances for a pipeline, None where the shaders read them from
+    // storage buffers
+    pub fn get_instance_layout(
+        &self,
+        layout: wgpu::VertexBufferLayout<'static>,
+    ) -> Option<wgpu::VertexBufferLayout<'static>> {
+        (self.instance_path == InstancePath::VertexBuffers).then_some(layout)
+    }
+
+    pub fn get_adapter_info(&self) -> wgpu::AdapterInfo {
+        self.adapter.get_info()
+    }
+
+    pub fn get_diagnostics(&self) -> DeviceDiagnostics {
+        let info = self.get_adapter_info();
+        let limits = self.device.limits();
+        let features = self
+            .device
+            .features()
+            .iter_names()
+            .map(|(name, _)| name.to_string())
+            .collect();
+
+        let mut fallbacks = Vec::new();
+        if self.instance_path == InstancePath::VertexBuffers {
+            fallbacks.push(
+                "No storage buffers in vertex shaders, instances are read from vertex buffers"
+                    .to_string(),
+            );
+        }
+        if !self.supports_compute() {
+            fallbacks.push("No compute shaders, culling on the CPU".to_string());
+        }
+        if !self.scene_sample_counts.iter().any(|count| *count > 1) {
+            fallbacks.push("MSAA is unsupported, antialiasing falls back to FXAA".to_string());
+        }
+        if !self.supports_wireframe {
+            fallbacks.push("No wireframe debug view".to_string());
+        }
+        if !self.supports_timestamps() {
+            fallbacks.push("No timestamp queries, the passes have no GPU times".to_string());
+        }
+        if !self.is_surface_srgb() {
+            fallbacks.push("The surface is not sRGB, colors look too dark".to_string());
+        }
+        if self.transparent && self.get_clear_alpha() == 1.0 {
+            fallbacks.push("The surface can't be transparent, the window is opaque".to_string());
+        }
+
+        DeviceDiagnostics {
+            adapter: info.name,
+            vendor: format!("{:#06x}", info.vendor),
+            device: format!("{:#06x}", info.device),
+            device_type: format!("{:?}", info.device_type),
+            backend: format!("{:?}", info.backend),
+            driver: info.driver,
+            driver_info: info.driver_info,
+            surface_format: format!("{:?}", self.config.format),
+            present_mode: format!("{:?}", self.config.present_mode),
+            alpha_mode: format!("{:?}", self.config.alpha_mode),
+            features,
+            limits: [
+                (
+                    "max_texture_dimension_2d",
+                    limits.max_texture_dimension_2d as u64,
+                ),
+                ("max_bind_groups", limits.max_bind_groups as u64),
+                (
+                    "max_storage_buffers_per_shader_stage",
+                    limits.max_storage_buffers_per_shader_stage as u64,
+                ),
+                (
+                    "max_storage_buffer_binding_size",
+                    limits.max_storage_buffer_binding_size as u64,
+                ),
+                (
+                    "max_uniform_buffer_binding_size",
+                    limits.max_uniform_buffer_binding_size as u64,
+                ),
+                ("max_vertex_buffers", limits.max_vertex_buffers as u64),
+                ("max_vertex_attributes", limits.max_vertex_attributes as u64),
+                (
+                    "max_compute_workgroups_per_dimension",
+                    limits.max_compute_workgroups_per_dimension as u64,
+                ),
+                ("max_buffer_size", limits.max_buffer_size),
+            ]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+            fallbacks,
+        }
+    }
+
+    // Requested when the adapter has them, the renderer works without
+    fn get_optional_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+        adapter.features()
+            & (wgpu::Features::POLYGON_MODE_LINE
+                | wgpu::Features::TEXTURE_FORMAT_16BIT_NORM
+                | wgpu::Features::TIMESTAMP_QUERY)
+    }
+
+    pub fn supports_wireframe(&self) -> bool {
+        self.supports_wireframe
+    }
+
+    // TIMESTAMP_QUERY, for the GPU times of the passes
+    pub fn supports_timestamps(&self) -> bool {
+        self.device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+    }
+
+    // Errors nobody caught with an error scope, e.g. validation, are kept until taken
+    fn capture_errors(device: &wgpu::Device) -> Arc<Mutex<Vec<String>>> {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let handler_errors = errors.clone();
+        device.on_uncaptured_error(Arc::new(move |error: wgpu::Error| {
+            log::error!("wgpu error: {}", error);
+            if let Ok(mut errors) = handler_errors.lock() {
+                errors.push(error.to_string());
+            }
+        }));
+        errors
+    }
+
+    // A shader of res/shaders, preprocessed with the defines. Each combination is compiled
+    // once and shared by the pipelines made with it.
+    pub fn create_shader(&self, name: &str, defines: &[&str]) -> wgpu::ShaderModule {
+        let mut defines = defines.to_vec();
+        if self.instance_path == InstancePath::VertexBuffers {
+            defines.push(INSTANCE_VERTEX_BUFFER_DEFINE);
+        }
+        let defines = defines.as_slice();
+        let key = ShaderKey::new(name, defines);
+        let mut shaders = self.shaders.lock().unwrap();
+        if let Some(module) = shaders.get(&key) {
+            return module.clone();
+        }
+
+        // The shaders are built in, so a broken one is a bug of the build
+        let shader = preprocess_shader(name, defines, get_shader_source)
+            .unwrap_or_else(|error| panic!("Failed to preprocess {}: {:#}", name, error));
+        let label = key.get_label();
+        let module = self
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(&label),
+                source: wgpu::ShaderSource::Wgsl(shader.source.as_str().into()),
+            });
+
+        // The lines of wgpu's messages are of the pasted source, these are of the files
+        #[cfg(not(target_arch = "wasm32"))]
+        for message in pollster::block_on(module.get_compilation_info()).messages {
+            let location = message
+                .location
+                .and_then(|location| shader.get_location(location.line_number));
+            if let Some((file, line)) = location {
+                log::error!("{} at {}:{}: {}", label, file, line, message.message);
+            }
+        }
+
+        #[cfg(debug_assertions)]
+        if let Some(inputs) = scan_vertex_inputs(&shader.source) {
+            let mut vertex_inputs = self.vertex_inputs.lock().unwrap();
+            vertex_inputs.insert(module.clone(), (label, inputs));
+        }
+
+        shaders.insert(key, module.clone());
+        module
+    }
+
+    // Panics when the vertex shader reads something the layout doesn't have, before it turns
+    // into garbled geometry. Only debug builds keep the inputs.
+    #[cfg(debug_assertions)]
+    pub fn validate_vertex_layout(
+        &self,
+        shader: &wgpu::ShaderModule,
+        layouts: &[wgpu::VertexBufferLayout],
+    ) {
+        let vertex_inputs = self.vertex_inputs.lock().unwrap();
+        let Some((label, inputs)) = vertex_inputs.get(shader) else {
+            return;
+        };
+        if let Some(diff) = get_vertex_layout_diff(inputs, layouts) {
+            panic!("The vertex layout doesn't match {}:\n{}", label, diff);
+        }
+    }
+
+    // Nothing made with a lost device works anymore, the renderer has to be recreated
+    fn watch_device_loss(device: &wgpu::Device) -> Arc<AtomicBool> {
+        let lost = Arc::new(AtomicBool::new(false));
+        let callback_lost = lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            log::error!("Lost the GPU device ({:?}): {}", reason, message);
+            callback_lost.store(true, Ordering::Relaxed);
+        });
+        lost
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    pub fn take_validation_errors(&self) -> Vec<String> {
+        self.validation_errors
+            .lock()
+            .map(|mut errors| std::mem::take(&mut *errors))
+            .unwrap_or_default()
+    }
+
+    pub fn get_max_frames_in_flight(&self) -> u32 {
+        self.max_frames_in_flight
+    }
+
+    // The swapchain is asked for the same depth, so presenting doesn't queue up more
+    pub fn set_max_frames_in_flight(&mut self, count: u32) {
+        self.max_frames_in_flight = count.clamp(1, MAX_FRAMES_IN_FLIGHT);
+        self.config.desired_maximum_frame_latency = self.max_frames_in_flight;
+        if self.is_surface_configured
+            && let Some(surface) = &self.surface
+        {
+            surface.configure(&self.device, &self.config);
+        }
+    }
+
+    pub fn is_vsync(&self) -> bool {
+        self.vsync
+    }
+
+    // Takes effect right away when the surface is configured, otherwise with the next resize
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.vsync = vsync;
+        let Some(surface) = &self.surface else {
+            return;
+        };
+        let capabilities = surface.get_capabilities(&self.adapter);
+        self.config.present_mode = choose_present_mode(&capabilities.present_modes, vsync);
+        if self.is_surface_configured {
+            surface.configure(&self.device, &self.config);
+        }
+    }
+
+    // Chooses the format and modes again, they can change when the window moves to a
+    // monitor on another output or adapter. Returns whether the format changed, the
+    // pipelines that draw to the surface have to be made again then. The surface is
+    // configured by the next resize.
+    pub fn refresh_surface_config(&mut self) -> anyhow::Result<bool> {
+        let Some(surface) = &self.surface else {
+            return Ok(false);
+        };
+        let capabilities = surface.get_capabilities(&self.adapter);
+        if capabilities.formats.is_empty() {
+            anyhow::bail!("The adapter can't present to the surface anymore");
+        }
+
+        let mut config = choose_surface_config(
+            &capabilities,
+            self.config.width,
+            self.config.height,
+            self.transparent,
+            self.vsync,
+        );
+        config.desired_maximum_frame_latency = self.max_frames_in_flight;
+        let format_changed = config.format != self.config.format;
+        if format_changed {
+            log::info!(
+                "Surface format changed from {:?} to {:?}",
+                self.config.format,
+                config.format
+            );
+        }
+        self.config = config;
+        self.is_surface_configured = false;
+        Ok(format_changed)
+    }
+
+    // Fences all the work submitted for the frame, overlays included. An empty submission
+    // finishes after everything submitted before it.
+    pub fn end_frame(&mut self) {
+        let index = self.queue.submit([]);
+        self.submitted_frame_count += 1;
+        let frame = self.submitted_frame_count;
+        let completed_frame_count = self.completed_frame_count.clone();
+        self.queue.on_submitted_work_done(move || {
+            completed_frame_count.fetch_max(frame, Ordering::Relaxed);
+        });
+        self.in_flight.push_back((frame, index));
+    }
+
+    // Blocks until no more than the count of frames is left on the GPU, returns the
+    // seconds it waited
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn wait_for_frames(&mut self, count: u32) -> f32 {
+        shared::profile_scope!("Wait for frames");
+        self.drop_completed_frames();
+        let start = std::time::Instant::now();
+        while self.in_flight.len() > count as usize {
+            let Some((_, index)) = self.in_flight.pop_front() else {
+                break;
+            };
+            // A lost device never finishes, it is reported as a timeout
+            if let Err(error) = self.device.poll(wgpu::PollType::Wait {
+                submission_index: Some(index),
+                timeout: Some(std::time::Duration::from_secs(1)),
+            }) {
+                log::warn!("Failed to wait for a frame: {}", error);
+            }
+        }
+        start.elapsed().as_secs_f32()
+    }
+
+    // The browser finishes the frames from its own event loop, it can't be blocked
+    #[cfg(target_arch = "wasm32")]
+    pub fn wait_for_frames(&mut self, _count: u32) -> f32 {
+        self.drop_completed_frames();
+        0.0
+    }
+
+    // The frames are counted by end_frame
+    pub fn get_submitted_frame_count(&self) -> u64 {
+        self.submitted_frame_count
+    }
+
+    pub fn get_completed_frame_count(&self) -> u64 {
+        self.completed_frame_count.load(Ordering::Relaxed)
+    }
+
+    fn drop_completed_frames(&mut self) {
+        let completed = self.completed_frame_count.load(Ordering::Relaxed);
+        self.in_flight.retain(|(frame, _)| *frame > completed);
+    }
+
+    pub fn get_surface_format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    // In pixels, at least one in each direction
+    pub fn get_window_size(&self) -> UVec2 {
+        UVec2::new(self.config.width.max(1), self.config.height.max(1))
+    }
+
+    // Whether writes to the surface are encoded to sRGB by the hardware, shaders output
+    // linear colors either way
+    pub fn is_surface_srgb(&self) -> bool {
+        self.config.format.is_srgb()
+    }
+
+    // Pixels read back from the surface come in blue, green, red order
+    pub fn is_surface_bgra(&self) -> bool {
+        matches!(
+            self.config.format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        )
+    }
+
+    // The clear color alpha, zero when the window shows what is behind it
+    pub fn get_clear_alpha(&self) -> f64 {
+        match self.config.alpha_mode {
+            wgpu::CompositeAlphaMode::Opaque | wgpu::CompositeAlphaMode::Auto => 1.0,
+            _ => 0.0,
+        }
+    }
+
+    fn get_scene_sample_counts(adapter: &wgpu::Adapter) -> Vec<u32> {
+        let color_sample_counts = adapter
+            .get_texture_format_features(wgpu::TextureFormat::Rgba16Float)
+            .flags
+            .supported_sample_counts();
+        let depth_sample_counts = adapter
+            .get_texture_format_features(wgpu::TextureFormat::Depth32Float)
+            .flags
+            .supported_sample_counts();
+
+        color_sample_counts
+            .into_iter()
+            .filter(|count| depth_sample_counts.contains(count))
+            .collect()
+    }
+}
+
+const DEFAULT_FRAMES_IN_FLIGHT: u32 = 2;
+
+// Storage buffers in the vertex stage, and two of them for the sprite shader
+fn choose_instance_path(flags: wgpu::DownlevelFlags, limits: &wgpu::Limits) -> InstancePath {
+    if flags.contains(wgpu::DownlevelFlags::VERTEX_STORAGE)
+        && limits.max_storage_buffers_per_shader_stage >= 2
+    {
+        InstancePath::StorageBuffers
+    } else {
+        InstancePath::VertexBuffers
+    }
+}
+
+const PREFERRED_FORMATS: [wgpu::TextureFormat; 2] = [
+    wgpu::TextureFormat::Bgra8UnormSrgb,
+    wgpu::TextureFormat::Rgba8UnormSrgb,
+];
+
+// The composite pass writes linear colors, so an 8 bit sRGB format is preferred. Which of
+// Bgra and Rgba is available depends on the platform, neither is better.
+fn choose_surface_config(
+    capabilities: &wgpu::SurfaceCapabilities,
+    width: u32,
+    height: u32,
+    transparent: bool,
+    vsync: bool,
+) -> wgpu::SurfaceConfiguration {
+    let format = capabilities
+        .formats
+        .iter()
+        .find(|format| PREFERRED_FORMATS.contains(format))
+        .or_else(|| capabilities.formats.iter().find(|format| format.is_srgb()))
+        .copied()
+        .unwrap_or_else(|| {
+            log::warn!(
+                "No sRGB surface format in {:?}, colors will look too dark",
+                capabilities.formats
+            );
+            capabilities.formats[0]
+        });
+
+    let present_mode = choose_present_mode(&capabilities.present_modes, vsync);
+
+    // Premultiplied is the most common mode that composites, and cleared pixels are
+    // transparent black in any mode
+    let alpha_mode = if transparent {
+        [
+            wgpu::CompositeAlphaMode::PreMultiplied,
+            wgpu::CompositeAlphaMode::Inherit,
+        ]
+        .into_iter()
+        .find(|mode| capabilities.alpha_modes.contains(mode))
+        .unwrap_or_else(|| {
+            log::warn!(
+                "Transparent windows aren't supported with {:?}",
+                capabilities.alpha_modes
+            );
+            capabilities.alpha_modes[0]
+        })
+    } else if capabilities
+        .alpha_modes
+        .contains(&wgpu::CompositeAlphaMode::Opaque)
+    {
+        wgpu::CompositeAlphaMode::Opaque
+    } else {
+        capabilities.alpha_modes[0]
+    };
+
+    // Copying the frame back is only needed for screenshots, not every surface allows it
+    let usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+        | (capabilities.usages & wgpu::TextureUsages::COPY_SRC);
+
+    wgpu::SurfaceConfiguration {
+        usage,
+        format,
+        width,
+        height,
+        present_mode,
+        alpha_mode,
+        view_formats: vec![],
+        desired_maximum_frame_latency: DEFAULT_FRAMES_IN_FLIGHT,
+    }
+}
+
+// Fifo is the only mode every platform supports. Without vsync mailbox replaces the queued
+// frame instead of tearing, immediate is the fallback.
+fn choose_present_mode(modes: &[wgpu::PresentMode], vsync: bool) -> wgpu::PresentMode {
+    let preferred: &[wgpu::PresentMode] = if vsync {
+        &[wgpu::PresentMode::Fifo]
+    } else {
+        &[
+            wgpu::PresentMode::Mailbox,
+            wgpu::PresentMode::Immediate,
+            wgpu::PresentMode::Fifo,
+        ]
+    };
+    preferred
+        .iter()
+        .find(|mode| modes.contains(mode))
+        .copied()
+        .unwrap_or(modes[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_capabilities(
+        formats: &[wgpu::TextureFormat],
+        alpha_modes: &[wgpu::CompositeAlphaMode],
+    ) -> wgpu::SurfaceCapabilities {
+        wgpu::SurfaceCapabilities {
+            formats: formats.to_vec(),
+            present_modes: vec![wgpu::PresentMode::Mailbox, wgpu::PresentMode::Fifo],
+            alpha_modes: alpha_modes.to_vec(),
+            usages: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        }
+    }
+
+    #[test]
+    fn prefers_8_bit_srgb() {
+        let capabilities = get_capabilities(
+            &[
+                wgpu::TextureFormat::Rgba16Float,
+                wgpu::TextureFormat::Bgra8Unorm,
+                wgpu::TextureFormat::Rgb10a2Unorm,
+                wgpu::TextureFormat::Rgba8UnormSrgb,
+            ],
+            &[wgpu::CompositeAlphaMode::Auto],
+        );
+        let config = choose_surface_config(&capabilities, 800, 600, false, true);
+        assert_eq!(config.format, wgpu::TextureFormat::Rgba8UnormSrgb);
+        assert_eq!(config.present_mode, wgpu::PresentMode::Fifo);
+        let config = choose_surface_config(&capabilities, 800, 600, false, false);
+        assert_eq!(config.present_mode, wgpu::PresentMode::Mailbox);
+        assert_eq!(
+            choose_present_mode(&[wgpu::PresentMode::Fifo], false),
+            wgpu::PresentMode::Fifo
+        );
+
+        // Falls back to the first format when nothing is sRGB
+        let capabilities = get_capabilities(
+            &[
+                wgpu::TextureFormat::Bgra8Unorm,
+                wgpu::TextureFormat::Rgba8Unorm,
+            ],
+            &[wgpu::CompositeAlphaMode::Auto],
+        );
+        let config = choose_surface_config(&capabilities, 800, 600, false, true);
+        assert_eq!(config.format, wgpu::TextureFormat::Bgra8Unorm);
+        assert_eq!(config.usage, wgpu::TextureUsages::RENDER_ATTACHMENT);
+    }
+
+    #[test]
+    fn webgl2_takes_the_vertex_buffer_path() {
+        assert_eq!(
+            choose_instance_path(wgpu::DownlevelFlags::all(), &wgpu::Limits::defaults()),
+            InstancePath::StorageBuffers
+        );
+        assert_eq!(
+            choose_instance_path(
+                wgpu::DownlevelFlags::all(),
+                &wgpu::Limits::downlevel_defaults()
+            ),
+            InstancePath::StorageBuffers
+        );
+        assert_eq!(
+            choose_instance_path(
+                wgpu::DownlevelFlags::all() - wgpu::DownlevelFlags::VERTEX_STORAGE,
+                &wgpu::Limits::downlevel_webgl2_defaults()
+            ),
+            InstancePath::VertexBuffers
+        );
+        // Some GLES drivers have the flag but no storage buffers at all
+        assert_eq!(
+            choose_instance_path(
+                wgpu::DownlevelFlags::all(),
+                &wgpu::Limits::downlevel_webgl2_defaults()
+            ),
+            InstancePath::VertexBuffers
+        );
+    }
+
+    #[test]
+    fn transparency_needs_a_compositing_alpha_mode() {
+        let formats = [wgpu::TextureFormat::Bgra8UnormSrgb];
+        let capabilities = get_capabilities(
+            &formats,
+            &[
+                wgpu::CompositeAlphaMode::Opaque,
+                wgpu::CompositeAlphaMode::PreMultiplied,
+            ],
+        );
+        let config = choose_surface_config(&capabilities, 800, 600, true, true);
+        assert_eq!(config.alpha_mode, wgpu::CompositeAlphaMode::PreMultiplied);
+        let config = choose_surface_config(&capabilities, 800, 600, false, true);
+        assert_eq!(config.alpha_mode, wgpu::CompositeAlphaMode::Opaque);
+
+        let capabilities = get_capabilities(&formats, &[wgpu::CompositeAlphaMode::Opaque]);
+        let config = choose_surface_config(&capabilities, 800, 600, true, true);
+        assert_eq!(config.alpha_mode, wgpu::CompositeAlphaMode::Opaque);
+    }
 }
@@ -2,6 +2,8 @@ use std::collections::HashMap;
 
 use shared::math::*;
 
+#[cfg(feature = "runtime-font")]
+use crate::renderer::runtime_font::RuntimeFont;
 use crate::renderer::{RenderDevice, Texture, TextureDesc};
 
 pub struct FontDesc {
@@ -115,9 +117,15 @@ pub struct Glyph {
     pub uv: Option<Bounds>,
 }
 
+// The font files don't store it, the atlases are baked with this range
+pub const DEFAULT_DISTANCE_RANGE: f32 = 2.0;
+
 pub struct Font {
     pub glyphs: HashMap<u32, Glyph>,
     pub atlas: Texture,
+    pub distance_range: f32, // Of the MSDF atlas, in its pixels
+    #[cfg(feature = "runtime-font")]
+    pub runtime: Option<Box<RuntimeFont>>, // Rasterizes the glyphs missing from the atlas
 }
 
 impl Font {
@@ -126,9 +134,27 @@ impl Font {
     }
 
     pub fn get_glyphs(&self, text: &str) -> impl Iterator<Item = Option<&Glyph>> {
-        text.as_bytes()
-            .iter()
-            .map(|u| self.glyphs.get(&(*u as u32)))
+        text.chars().map(|c| self.glyphs.get(&(c as u32)))
+    }
+
+    // In ems, from the atlas or otherwise the runtime glyphs
+    pub fn get_advance(&self, unicode: u32) -> Option<f32> {
+        if let Some(glyph) = self.glyphs.get(&unicode) {
+            return Some(glyph.advance);
+        }
+        #[cfg(feature = "runtime-font")]
+        if let Some(runtime) = &self.runtime {
+            return runtime.get_advance(unicode);
+        }
+        None
+    }
+
+    // In the units of the size, characters without a glyph take no space
+    pub fn get_text_width(&self, text: &str, size: f32) -> f32 {
+        text.chars()
+            .filter_map(|c| self.get_advance(c as u32))
+            .map(|advance| advance * size)
+            .sum()
     }
 }
 
@@ -141,6 +167,9 @@ impl RenderDevice {
         Ok(Font {
             glyphs: desc.glyphs,
             atlas,
+            distance_range: DEFAULT_DISTANCE_RANGE,
+            #[cfg(feature = "runtime-font")]
+            runtime: None,
         })
     }
 }
@@ -0,0 +1,551 @@
+// The passes of a frame, described by the textures they read and write. The graph checks
+// that no pass reads a texture it writes, orders the passes so textures are written
+// before they are read and begins the render passes from the descriptions. Textures are
+// either imported, like the scene texture or the surface, or transient and taken from a
+// pool that keeps them between frames, e.g. the mips of a bloom.
+//
+// A post pass only needs its texture and a description of what it reads and writes:
+//
+//   graph.create_transient(OUTLINE_MASK, TransientDesc { size, format });
+//   graph.add_pass(
+//       PassDesc::new("Outline Mask Pass").color(OUTLINE_MASK, Some(wgpu::Color::BLACK)),
+//       |render_pass, _| renderer.draw_outline_mask(render_pass),
+//   );
+//   graph.add_pass(
+//       PassDesc::new("Outline Pass").read(OUTLINE_MASK).color(SURFACE, None),
+//       |render_pass, textures| renderer.draw_outline(render_pass, textures.get_view(OUTLINE_MASK)),
+//   );
+
+use std::collections::HashMap;
+
+use glam::UVec2;
+
+use crate::renderer::{RenderDevice, Texture, TextureDesc, gpu_timer::GpuTimer};
+
+pub type TextureKey = &'static str;
+
+pub struct ColorAttachment {
+    pub key: TextureKey,
+    pub resolve: Option<TextureKey>, // The single sampled texture a multisampled one ends in
+    pub clear: Option<wgpu::Color>,  // None keeps what was there
+}
+
+pub struct DepthAttachment {
+    pub key: TextureKey,
+    pub clear: Option<f32>,
+}
+
+pub struct PassDesc {
+    pub label: &'static str,
+    pub reads: Vec<TextureKey>, // Sampled in the pass
+    pub colors: Vec<ColorAttachment>,
+    pub depth: Option<DepthAttachment>,
+}
+
+impl PassDesc {
+    pub fn new(label: &'static str) -> Self {
+        Self {
+            label,
+            reads: Vec::new(),
+            colors: Vec::new(),
+            depth: None,
+        }
+    }
+
+    pub fn read(mut self, key: TextureKey) -> Self {
+        self.reads.push(key);
+        self
+    }
+
+    pub fn color(self, key: TextureKey, clear: Option<wgpu::Color>) -> Self {
+        self.resolved_color(key, None, clear)
+    }
+
+    pub fn resolved_color(
+        mut self,
+        key: TextureKey,
+        resolve: Option<TextureKey>,
+        clear: Option<wgpu::Color>,
+    ) -> Self {
+        self.colors.push(ColorAttachment {
+            key,
+            resolve,
+            clear,
+        });
+        self
+    }
+
+    pub fn depth(mut self, key: TextureKey, clear: Option<f32>) -> Self {
+        self.depth = Some(DepthAttachment { key, clear });
+        self
+    }
+
+    fn get_writes(&self) -> impl Iterator<Item = TextureKey> + '_ {
+        self.colors
+            .iter()
+            .flat_map(|color| std::iter::once(color.key).chain(color.resolve))
+            .chain(self.depth.as_ref().map(|depth| depth.key))
+    }
+
+    fn get_textures(&self) -> impl Iterator<Item = TextureKey> + '_ {
+        self.reads.iter().copied().chain(self.get_writes())
+    }
+}
+
+// Transient textures are only shared between frames when these match
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TransientDesc {
+    pub size: UVec2,
+    pub format: wgpu::TextureFormat,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameGraphError {
+    UnknownTexture(&'static str, TextureKey), // Neither imported nor transient
+    ReadWhileWritten(&'static str, TextureKey),
+    NeverWritten(&'static str, TextureKey), // A transient texture nothing draws into
+    NoAttachments(&'static str),
+    Cycle(Vec<&'static str>), // The passes that wait on each other
+}
+
+impl std::fmt::Display for FrameGraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameGraphError::UnknownTexture(pass, texture) => {
+                write!(f, "{} uses the unknown texture {}", pass, texture)
+            }
+            FrameGraphError::ReadWhileWritten(pass, texture) => {
+                write!(f, "{} reads {} while writing it", pass, texture)
+            }
+            FrameGraphError::NeverWritten(pass, texture) => {
+                write!(f, "{} reads {} which no pass writes", pass, texture)
+            }
+            FrameGraphError::NoAttachments(pass) => write!(f, "{} writes nothing", pass),
+            FrameGraphError::Cycle(passes) => {
+                write!(f, "The passes {} wait on each other", passes.join(", "))
+            }
+        }
+    }
+}
+
+// The views of the textures while the passes run
+pub struct FrameTextures<'a> {
+    views: HashMap<TextureKey, &'a wgpu::TextureView>,
+}
+
+impl FrameTextures<'_> {
+    #[allow(dead_code)]
+    pub fn get_view(&self, key: TextureKey) -> Option<&wgpu::TextureView> {
+        self.views.get(key).copied()
+    }
+}
+
+type PassFn<'a> = Box<dyn FnOnce(&mut wgpu::RenderPass, &FrameTextures) + 'a>;
+
+enum GraphTexture<'a> {
+    Imported(&'a wgpu::TextureView),
+    Transient(TransientDesc),
+}
+
+#[derive(Default)]
+pub struct FrameGraph<'a> {
+    textures: HashMap<TextureKey, GraphTexture<'a>>,
+    passes: Vec<(PassDesc, PassFn<'a>)>,
+}
+
+impl<'a> FrameGraph<'a> {
+    pub fn new() -> Self {
+        Self {
+            textures: HashMap::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    pub fn import(&mut self, key: TextureKey, view: &'a wgpu::TextureView) {
+        self.textures.insert(key, GraphTexture::Imported(view));
+    }
+
+    // Taken from the pool for the frame, its contents don't last past it
+    #[allow(dead_code)]
+    pub fn create_transient(&mut self, key: TextureKey, desc: TransientDesc) {
+        self.textures.insert(key, GraphTexture::Transient(desc));
+    }
+
+    // Passes writing the same texture run in the order they are added
+    pub fn add_pass(
+        &mut self,
+        desc: PassDesc,
+        execute: impl FnOnce(&mut wgpu::RenderPass, &FrameTextures) + 'a,
+    ) {
+        self.passes.push((desc, Box::new(execute)));
+    }
+
+    // The order to run the passes in. A pass reading a texture runs after every pass
+    // writing it, otherwise they keep the order they were added in.
+    fn compile(&self) -> Result<Vec<usize>, FrameGraphError> {
+        let mut writers: HashMap<TextureKey, Vec<usize>> = HashMap::new();
+        for (index, (desc, _)) in self.passes.iter().enumerate() {
+            if desc.colors.is_empty() && desc.depth.is_none() {
+                return Err(FrameGraphError::NoAttachments(desc.label));
+            }
+            if let Some(key) = desc
+                .get_textures()
+                .find(|key| !self.textures.contains_key(key))
+            {
+                return Err(FrameGraphError::UnknownTexture(desc.label, key));
+            }
+            if let Some(key) = desc.get_writes().find(|key| desc.reads.contains(key)) {
+                return Err(FrameGraphError::ReadWhileWritten(desc.label, key));
+            }
+            for key in desc.get_writes() {
+                writers.entry(key).or_default().push(index);
+            }
+        }
+
+        let mut dependencies: Vec<Vec<usize>> = vec![Vec::new(); self.passes.len()];
+        for (index, (desc, _)) in self.passes.iter().enumerate() {
+            for key in &desc.reads {
+                match writers.get(key) {
+                    Some(writers) => dependencies[index].extend(writers),
+                    None if matches!(self.textures[key], GraphTexture::Transient(_)) => {
+                        return Err(FrameGraphError::NeverWritten(desc.label, key));
+                    }
+                    None => {}
+                }
+            }
+            for key in desc.get_writes() {
+                let earlier = writers[key].iter().take_while(|writer| **writer < index);
+                dependencies[index].extend(earlier);
+            }
+        }
+
+        // The first pass that is ready each time, which keeps the order they were added in
+        let mut order = Vec::with_capacity(self.passes.len());
+        let mut done = vec![false; self.passes.len()];
+        while order.len() < self.passes.len() {
+            let Some(next) = (0..self.passes.len()).find(|index| {
+                !done[*index]
+                    && dependencies[*index]
+                        .iter()
+                        .all(|dependency| done[*dependency])
+            }) else {
+                let waiting = (0..self.passes.len())
+                    .filter(|index| !done[*index])
+                    .map(|index| self.passes[index].0.label)
+                    .collect();
+                return Err(FrameGraphError::Cycle(waiting));
+            };
+            done[next] = true;
+            order.push(next);
+        }
+        Ok(order)
+    }
+
+    // Records the passes into the encoder, the transient textures go back to the pool after.
+    // The passes are timed on the GPU with a timer.
+    pub fn execute(
+        self,
+        render_device: &RenderDevice,
+        pool: &mut TransientPool,
+        encoder: &mut wgpu::CommandEncoder,
+        mut timer: Option<&mut GpuTimer>,
+    ) -> Result<(), FrameGraphError> {
+        let order = self.compile()?;
+
+        let transients: Vec<(TextureKey, TransientDesc, Texture)> = self
+            .textures
+            .iter()
+            .filter_map(|(key, texture)| match texture {
+                GraphTexture::Transient(desc) => {
+                    Some((*key, *desc, pool.acquire(render_device, *desc)))
+                }
+                GraphTexture::Imported(_) => None,
+            })
+            .collect();
+        let views = self
+            .textures
+            .iter()
+            .filter_map(|(key, texture)| match texture {
+                GraphTexture::Imported(view) => Some((*key, *view)),
+                GraphTexture::Transient(_) => None,
+            })
+            .chain(
+                transients
+                    .iter()
+                    .map(|(key, _, texture)| (*key, &texture.view)),
+            )
+            .collect();
+        let textures = FrameTextures { views };
+
+        let mut passes: Vec<Option<(PassDesc, PassFn)>> =
+            self.passes.into_iter().map(Some).collect();
+        for index in order {
+            let (desc, execute) = passes[index].take().unwrap();
+            let color_attachments: Vec<_> = desc
+                .colors
+                .iter()
+                .map(|color| {
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: textures.views[color.key],
+                        resolve_target: color.resolve.map(|key| textures.views[key]),
+                        depth_slice: None,
+                        ops: wgpu::Operations {
+                            load: color.clear.map_or(wgpu::LoadOp::Load, wgpu::LoadOp::Clear),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })
+                })
+                .collect();
+            let depth_stencil_attachment =
+                desc.depth
+                    .as_ref()
+                    .map(|depth| wgpu::RenderPassDepthStencilAttachment {
+                        view: textures.views[depth.key],
+                        depth_ops: Some(wgpu::Operations {
+                            load: depth.clear.map_or(wgpu::LoadOp::Load, wgpu::LoadOp::Clear),
+                            store: wgpu::StoreOp::Store,
+                        }),
+                        stencil_ops: None,
+                    });
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(desc.label),
+                color_attachments: &color_attachments,
+                depth_stencil_attachment,
+                timestamp_writes: timer
+                    .as_deref_mut()
+                    .and_then(|timer| timer.get_timestamp_writes(desc.label)),
+                occlusion_query_set: None,
+            });
+            execute(&mut render_pass, &textures);
+        }
+        if let Some(timer) = timer {
+            timer.resolve(encoder);
+        }
+
+        drop(textures);
+        for (_, desc, texture) in transients {
+            pool.release(desc, texture);
+        }
+        pool.end_frame();
+        Ok(())
+    }
+}
+
+struct PooledTexture {
+    desc: TransientDesc,
+    texture: Texture,
+    released_frame: u64,
+}
+
+// Transient textures between the frames that use them. The ones no frame asked for in a
+// few frames are dropped, e.g. the old sizes after a resize.
+#[derive(Default)]
+pub struct TransientPool {
+    free: Vec<PooledTexture>,
+    frame: u64,
+}
+
+impl TransientPool {
+    const MAX_UNUSED_FRAMES: u64 = 3;
+
+    fn acquire(&mut self, render_device: &RenderDevice, desc: TransientDesc) -> Texture {
+        if let Some(index) = self.free.iter().position(|pooled| pooled.desc == desc) {
+            return self.free.swap_remove(index).texture;
+        }
+        render_device.create_texture(&TextureDesc {
+            width: desc.size.x.max(1),
+            height: desc.size.y.max(1),
+            layer_count: 1,
+            format: Some(desc.format),
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            ..Default::default()
+        })
+    }
+
+    fn release(&mut self, desc: TransientDesc, texture: Texture) {
+        self.free.push(PooledTexture {
+            desc,
+            texture,
+            released_frame: self.frame,
+        });
+    }
+
+    fn end_frame(&mut self) {
+        self.frame += 1;
+        let frame = self.frame;
+        self.free
+            .retain(|pooled| frame - pooled.released_frame <= Self::MAX_UNUSED_FRAMES);
+    }
+
+    #[allow(dead_code)]
+    pub fn get_texture_count(&self) -> usize {
+        self.free.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: UVec2 = UVec2::new(64, 64);
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+    fn create_graph(keys: &[TextureKey]) -> FrameGraph<'static> {
+        let mut graph = FrameGraph::new();
+        for key in keys {
+            graph.create_transient(
+                key,
+                TransientDesc {
+                    size: SIZE,
+                    format: FORMAT,
+                },
+            );
+        }
+        graph
+    }
+
+    fn get_labels(graph: &FrameGraph) -> Result<Vec<&'static str>, FrameGraphError> {
+        let order = graph.compile()?;
+        Ok(order
+            .into_iter()
+            .map(|index| graph.passes[index].0.label)
+            .collect())
+    }
+
+    #[test]
+    fn passes_run_after_what_they_read() {
+        let mut graph = create_graph(&["Scene", "Bloom", "Out"]);
+        // Added in the wrong order, the composite reads the bloom and the scene
+        graph.add_pass(
+            PassDesc::new("Composite")
+                .read("Scene")
+                .read("Bloom")
+                .color("Out", None),
+            |_, _| {},
+        );
+        graph.add_pass(
+            PassDesc::new("Bloom").read("Scene").color("Bloom", None),
+            |_, _| {},
+        );
+        graph.add_pass(PassDesc::new("Scene").color("Scene", None), |_, _| {});
+        graph.add_pass(PassDesc::new("Sprites").color("Out", None), |_, _| {});
+        assert_eq!(
+            get_labels(&graph),
+            Ok(vec!["Scene", "Bloom", "Composite", "Sprites"])
+        );
+    }
+
+    #[test]
+    fn hazards_are_rejected() {
+        let mut graph = create_graph(&["Scene"]);
+        graph.add_pass(
+            PassDesc::new("Feedback").read("Scene").color("Scene", None),
+            |_, _| {},
+        );
+        assert_eq!(
+            get_labels(&graph),
+            Err(FrameGraphError::ReadWhileWritten("Feedback", "Scene"))
+        );
+
+        let mut graph = create_graph(&["Mask"]);
+        graph.add_pass(
+            PassDesc::new("Outline").read("Mask").color("Out", None),
+            |_, _| {},
+        );
+        assert_eq!(
+            get_labels(&graph),
+            Err(FrameGraphError::UnknownTexture("Outline", "Out"))
+        );
+
+        let mut graph = create_graph(&["Mask", "Out"]);
+        graph.add_pass(
+            PassDesc::new("Outline").read("Mask").color("Out", None),
+            |_, _| {},
+        );
+        assert_eq!(
+            get_labels(&graph),
+            Err(FrameGraphError::NeverWritten("Outline", "Mask"))
+        );
+
+        let mut graph = create_graph(&["A", "B"]);
+        graph.add_pass(PassDesc::new("First").read("B").color("A", None), |_, _| {});
+        graph.add_pass(
+            PassDesc::new("Second").read("A").color("B", None),
+            |_, _| {},
+        );
+        graph.add_pass(PassDesc::new("Empty"), |_, _| {});
+        assert_eq!(
+            get_labels(&graph),
+            Err(FrameGraphError::NoAttachments("Empty"))
+        );
+        graph.passes.pop();
+        assert_eq!(
+            get_labels(&graph),
+            Err(FrameGraphError::Cycle(vec!["First", "Second"]))
+        );
+    }
+
+    #[cfg(feature = "test-harness")]
+    #[test]
+    fn transient_textures_are_reused_between_frames() {
+        let render_device = match pollster::block_on(RenderDevice::new_headless(64, 64)) {
+            Ok(render_device) => render_device,
+            Err(error) => {
+                log::warn!("No adapter for the frame graph test: {}", error);
+                return;
+            }
+        };
+        let mut pool = TransientPool::default();
+        let run_frame = |pool: &mut TransientPool, size: UVec2| {
+            let mut graph = FrameGraph::new();
+            graph.create_transient(
+                "Mask",
+                TransientDesc {
+                    size,
+                    format: FORMAT,
+                },
+            );
+            graph.create_transient(
+                "Out",
+                TransientDesc {
+                    size,
+                    format: FORMAT,
+                },
+            );
+            graph.add_pass(
+                PassDesc::new("Mask").color("Mask", Some(wgpu::Color::WHITE)),
+                |_, _| {},
+            );
+            graph.add_pass(
+                PassDesc::new("Out")
+                    .read("Mask")
+                    .color("Out", Some(wgpu::Color::BLACK)),
+                |_, textures| assert!(textures.get_view("Mask").is_some()),
+            );
+            let mut encoder = render_device
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            graph
+                .execute(&render_device, pool, &mut encoder, None)
+                .unwrap();
+            render_device
+                .queue
+                .submit(std::iter::once(encoder.finish()));
+        };
+
+        for _ in 0..3 {
+            run_frame(&mut pool, SIZE);
+            assert_eq!(pool.get_texture_count(), 2);
+        }
+        // The old size is dropped once it went unused for a few frames
+        for _ in 0..=TransientPool::MAX_UNUSED_FRAMES {
+            run_frame(&mut pool, SIZE * 2);
+        }
+        assert_eq!(pool.get_texture_count(), 2);
+        assert!(pool.free.iter().all(|pooled| pooled.desc.size == SIZE * 2));
+        let errors = render_device.take_validation_errors();
+        assert!(errors.is_empty(), "wgpu errors: {}", errors.join("\n"));
+    }
+}
@@ -0,0 +1,213 @@
+// Timestamps at the start and end of the frame graph's render passes. They are copied to a
+// buffer of their own per frame and read once the GPU got to it, a few frames later, so the
+// CPU never waits on them. Needs TIMESTAMP_QUERY, without it the passes have no times.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use serde::Serialize;
+
+use crate::renderer::{RenderDevice, device::MAX_FRAMES_IN_FLIGHT};
+
+// More passes in a frame go untimed
+pub const MAX_TIMED_PASSES: usize = 8;
+const QUERY_COUNT: u32 = MAX_TIMED_PASSES as u32 * 2;
+const QUERY_SIZE: u64 = std::mem::size_of::<u64>() as u64;
+// One per frame the GPU may still be on, and the one being read
+const READBACK_COUNT: usize = MAX_FRAMES_IN_FLIGHT as usize + 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct PassTiming {
+    pub label: &'static str,
+    pub ms: f32,
+}
+
+struct Readback {
+    buffer: wgpu::Buffer,
+    labels: Vec<&'static str>, // Of the timed passes, in the order of their queries
+    mapped: Arc<AtomicBool>,   // Set by wgpu once it can be read
+    in_use: bool,              // From being written until it was read
+}
+
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readbacks: Vec<Readback>,
+    current: Option<usize>, // The readback of the frame being recorded, None when all are in use
+    period: f32,            // Nanoseconds per tick
+    timings: Vec<PassTiming>, // Of the last frame that was read
+}
+
+impl GpuTimer {
+    // None without timestamp queries
+    pub fn new(render_device: &RenderDevice) -> Option<Self> {
+        if !render_device.supports_timestamps() {
+            return None;
+        }
+        let device = &render_device.device;
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Pass Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: QUERY_COUNT,
+        });
+        let size = QUERY_COUNT as u64 * QUERY_SIZE;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readbacks = (0..READBACK_COUNT)
+            .map(|_| Readback {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Timestamp Readback Buffer"),
+                    size,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                labels: Vec::with_capacity(MAX_TIMED_PASSES),
+                mapped: Arc::new(AtomicBool::new(false)),
+                in_use: false,
+            })
+            .collect();
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readbacks,
+            current: None,
+            period: render_device.queue.get_timestamp_period(),
+            timings: Vec::with_capacity(MAX_TIMED_PASSES),
+        })
+    }
+
+    // Reads the frames the GPU finished and picks a readback for the next one
+    pub fn begin_frame(&mut self, render_device: &RenderDevice) {
+        let _ = render_device.device.poll(wgpu::PollType::Poll);
+        for readback in &mut self.readbacks {
+            if !readback.mapped.swap(false, Ordering::Acquire) {
+                continue;
+            }
+            {
+                let data = readback.buffer.slice(..).get_mapped_range();
+                let ticks: &[u64] = bytemuck::cast_slice(&data);
+                self.timings.clear();
+                for (index, label) in readback.labels.iter().enumerate() {
+                    let elapsed = ticks[index * 2 + 1].saturating_sub(ticks[index * 2]);
+                    self.timings.push(PassTiming {
+                        label,
+                        ms: elapsed as f32 * self.period / 1_000_000.0,
+                    });
+                }
+            }
+            readback.buffer.unmap();
+            readback.in_use = false;
+        }
+
+        self.current = self.readbacks.iter().position(|readback| !readback.in_use);
+        if let Some(index) = self.current {
+            self.readbacks[index].labels.clear();
+        }
+    }
+
+    // For the pass about to begin, None when the frame isn't timed or has no queries left
+    pub fn get_timestamp_writes(
+        &mut self,
+        label: &'static str,
+    ) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        let readback = &mut self.readbacks[self.current?];
+        if readback.labels.len() == MAX_TIMED_PASSES {
+            return None;
+        }
+        let index = readback.labels.len() as u32 * 2;
+        readback.labels.push(label);
+        Some(wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(index),
+            end_of_pass_write_index: Some(index + 1),
+        })
+    }
+
+    // After the last pass, copies the timestamps out of the query set
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(readback) = self.current.map(|index| &mut self.readbacks[index]) else {
+            return;
+        };
+        let query_count = readback.labels.len() as u32 * 2;
+        if query_count == 0 {
+            return;
+        }
+        encoder.resolve_query_set(&self.query_set, 0..query_count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &readback.buffer,
+            0,
+            query_count as u64 * QUERY_SIZE,
+        );
+        readback.in_use = true;
+    }
+
+    // After the frame was submitted, the buffer is mapped once the GPU is done with it
+    pub fn end_frame(&mut self) {
+        let Some(readback) = self.current.take().map(|index| &self.readbacks[index]) else {
+            return;
+        };
+        if !readback.in_use {
+            return;
+        }
+        let mapped = readback.mapped.clone();
+        readback
+            .buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                mapped.store(result.is_ok(), Ordering::Release);
+            });
+    }
+
+    // Of the last frame the GPU finished, in the order the passes ran
+    pub fn get_timings(&self) -> &[PassTiming] {
+        &self.timings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Frames rendered offscreen get the times of their passes a frame later
+    #[cfg(feature = "test-harness")]
+    #[test]
+    fn the_passes_of_a_frame_are_timed() {
+        use crate::renderer::{Renderer, test_harness::create_target};
+
+        let mut renderer = match pollster::block_on(Renderer::new_headless(64, 64)) {
+            Ok(renderer) => renderer,
+            Err(error) => {
+                log::warn!("No adapter for the timer test: {}", error);
+                return;
+            }
+        };
+        if !renderer.get_render_device().supports_timestamps() {
+            assert!(renderer.get_gpu_pass_timings().is_empty());
+            return;
+        }
+        let target = create_target(&renderer, 64, 64);
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        for _ in 0..3 {
+            renderer.render_to_view(&view);
+            let _ = renderer
+                .get_render_device()
+                .device
+                .poll(wgpu::PollType::wait_indefinitely());
+        }
+
+        let timings = renderer.get_gpu_pass_timings();
+        let labels: Vec<&str> = timings.iter().map(|timing| timing.label).collect();
+        assert_eq!(
+            labels,
+            ["Shadow Pass", "Scene Pass", "Composite Pass", "Sprite Pass"]
+        );
+        assert!(timings.iter().all(|timing| timing.ms >= 0.0));
+    }
+}
@@ -1,15 +1,25 @@
 use shared::math::*;
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct StaticInstanceData {
-    pub(crate) model_matrix: Mat4Data,
-    pub(crate) color: Vec4Data,
-    pub(crate) tex_coord: Vec2Data,
-    pub(crate) tex_scale: Vec2Data,
-    pub(crate) data_indices: [u32; 4],
+use crate::renderer::shader_layout::wgsl_struct;
+
+wgsl_struct! {
+    #[repr(C)]
+    #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct StaticInstanceData {
+        pub(crate) model_matrix: Mat4Data => "mat4x4<f32>",
+        pub(crate) color: Vec4Data => "vec4<f32>",
+        pub(crate) tex_coord: Vec2Data => "vec2<f32>",
+        pub(crate) tex_scale: Vec2Data => "vec2<f32>",
+        // x the bone offset of skeletal meshes, y and z the bone weight debug mode and bone,
+        // w the texture layer of the material variant. The y of static meshes holds the
+        // INSTANCE_FLAG bits instead.
+        pub(crate) data_indices: [u32; 4] => "vec4<u32>",
+    }
 }
 
+// Of the static mesh instances, in data_indices.y. Matches mesh.wgsl.
+pub const INSTANCE_FLAG_WIND: u32 = 1; // The material sways in the wind
+
 impl Default for StaticInstanceData {
     fn default() -> Self {
         Self {
@@ -22,18 +32,74 @@ impl Default for StaticInstanceData {
     }
 }
 
-#[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct SpriteInstanceData {
-    pub(crate) position: Vec2Data,
-    pub(crate) scale: Vec2Data,
-    pub(crate) color: Vec4Data,
-    pub(crate) tex_coord: Vec2Data,
-    pub(crate) tex_scale: Vec2Data,
-    pub(crate) mode: u32,
-    pub(crate) layer: u32,
-    pub(crate) anchor: u32,
-    pub(crate) space: u32,
+impl StaticInstanceData {
+    // After the attributes of the skeletal vertices, see mesh.wgsl
+    const ATTRIBS: [wgpu::VertexAttribute; 8] = wgpu::vertex_attr_array![
+        6 => Float32x4, 7 => Float32x4, 8 => Float32x4, 9 => Float32x4,
+        10 => Float32x4, 11 => Float32x2, 12 => Float32x2, 13 => Uint32x4
+    ];
+
+    // Only where the vertex shaders can't read the instances from storage, see InstancePath
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<StaticInstanceData>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+
+    // Over the whole texture
+    pub fn new(transform: Mat4, color: Vec4) -> Self {
+        Self {
+            model_matrix: transform.to_data(),
+            color: color.to_data(),
+            ..Default::default()
+        }
+    }
+}
+
+wgsl_struct! {
+    #[repr(C)]
+    #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct SpriteInstanceData {
+        pub(crate) position: Vec2Data => "vec2<f32>",
+        pub(crate) scale: Vec2Data => "vec2<f32>",
+        pub(crate) color: Vec4Data => "vec4<f32>",
+        pub(crate) tex_coord: Vec2Data => "vec2<f32>",
+        pub(crate) tex_scale: Vec2Data => "vec2<f32>",
+        pub(crate) mode: u32 => "u32",
+        pub(crate) layer: u32 => "u32",
+        pub(crate) anchor: u32 => "u32",
+        pub(crate) space: u32 => "u32",
+        pub(crate) rotation: Vec2Data => "vec2<f32>", // The cosine and sine of the angle
+        pub(crate) texture_layer: u32 => "u32",
+        pub(crate) params_index: u32 => "u32", // Into the sprite params, 0 has none
+    }
+}
+
+// Per-sprite values of the effect its mode draws, read by sprite.wgsl:
+//   Msdf:       distance range of the atlas in pixels
+//   RadialFill: filled fraction, clockwise from the top
+//   Rounded:    corner radius, border width (0 fills), softness of the edge, inset of the
+//               shape from the quad, in the units of the sprite's space
+//   Glow:       corner radius, unused, falloff, inset, like Rounded
+pub type SpriteParams = [f32; 8];
+
+impl SpriteInstanceData {
+    // After the attributes of the quad, see sprite.wgsl
+    const ATTRIBS: [wgpu::VertexAttribute; 8] = wgpu::vertex_attr_array![
+        4 => Float32x2, 5 => Float32x2, 6 => Float32x4, 7 => Float32x2,
+        8 => Float32x2, 9 => Uint32x4, 10 => Float32x2, 11 => Uint32x2
+    ];
+
+    // Like StaticInstanceData::desc
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<SpriteInstanceData>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
 }
 
 impl Default for SpriteInstanceData {
@@ -45,9 +111,12 @@ impl Default for SpriteInstanceData {
             tex_coord: Vec2::ZERO.to_array(),
             tex_scale: Vec2::ONE.to_array(),
             mode: 0,
-            layer: 0, // This is not used in the shader, only to sort sprites
+            layer: 0, // Only to sort sprites, the texture layer is picked by texture_layer
             anchor: 0,
             space: 0,
+            rotation: [1.0, 0.0],
+            texture_layer: 0,
+            params_index: 0,
         }
     }
 }
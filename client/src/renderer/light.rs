@@ -0,0 +1,116 @@
+use shared::math::*;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DirectionalLight {
+    pub direction: Vec3, // Kept normalized by the renderer
+    pub color: Vec3,
+    pub intensity: f32,
+    pub shadows_enabled: bool,
+    pub shadow_depth_extension: f32, // Extra depth towards the light for casters outside the view
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::new(0.0, -1.0, -1.0).normalize(),
+            color: Vec3::ONE,
+            intensity: 1.0,
+            shadows_enabled: true,
+            shadow_depth_extension: 200.0,
+        }
+    }
+}
+
+impl DirectionalLight {
+    pub fn get_radiance(&self) -> Vec3 {
+        self.color * self.intensity
+    }
+}
+
+// Hemispheric ambient, blended from bottom to top by the up component of the normal
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AmbientLight {
+    pub top_color: Vec3,
+    pub bottom_color: Vec3,
+    pub intensity: f32,
+}
+
+impl Default for AmbientLight {
+    fn default() -> Self {
+        Self {
+            top_color: Vec3::new(0.35, 0.50, 0.80),
+            bottom_color: Vec3::new(0.30, 0.25, 0.20),
+            intensity: 0.4,
+        }
+    }
+}
+
+// Linear fog by distance to the camera, fully fogged at end
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Fog {
+    pub color: Vec3,
+    pub start: f32,
+    pub end: f32,
+}
+
+// Sways the vertices of the materials made to, see Renderer::create_swaying_material. The
+// gusts scale the strength up and down over their period, on top of the sway itself.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Wind {
+    pub direction: Vec2,    // On the ground, xz
+    pub strength: f32,      // How far the tips move, in world units
+    pub gust_strength: f32, // Added to the strength in the strongest gusts, as a fraction of it
+    pub gust_period: f32,   // Seconds
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Self {
+            direction: Vec2::new(1.0, 0.5).normalize(),
+            strength: 4.0,
+            gust_strength: 0.75,
+            gust_period: 6.0,
+        }
+    }
+}
+
+impl Wind {
+    // Two waves of unrelated periods, so the gusts don't repeat noticeably
+    pub fn get_strength(&self, time: f32) -> f32 {
+        let phase = time * std::f32::consts::TAU / self.gust_period.max(0.1);
+        let gust = 0.6 * phase.sin() + 0.4 * (phase * 2.3 + 1.7).sin();
+        self.strength * (1.0 + self.gust_strength * (0.5 + 0.5 * gust))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gusts_stay_between_calm_and_the_gust_strength() {
+        let wind = Wind::default();
+        let strengths: Vec<f32> = (0..600)
+            .map(|step| wind.get_strength(step as f32 * 0.1))
+            .collect();
+        let (min, max) = strengths
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), s| {
+                (min.min(*s), max.max(*s))
+            });
+        assert!(min >= wind.strength - 1e-4, "{}", min);
+        assert!(
+            max <= wind.strength * (1.0 + wind.gust_strength) + 1e-4,
+            "{}",
+            max
+        );
+        // It does gust
+        assert!(max - min > 0.5 * wind.strength * wind.gust_strength);
+
+        let calm = Wind {
+            gust_strength: 0.0,
+            ..wind
+        };
+        assert_eq!(calm.get_strength(3.0), calm.strength);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::renderer::RenderDevice;
+use crate::renderer::{RenderDevice, ResourceHandle, batch_diagnostics::MaterialFingerprint};
 
 pub struct MaterialPipelineDesc<'a> {
     pub vertex_shader: &'a wgpu::ShaderModule,
@@ -6,13 +6,33 @@ pub struct MaterialPipelineDesc<'a> {
     pub bind_group_layouts: &'a [&'a wgpu::BindGroupLayout],
     pub layout_entries: &'a [wgpu::BindGroupLayoutEntry],
     pub vertex_layout: &'a wgpu::VertexBufferLayout<'static>,
+    // The second vertex buffer, from RenderDevice::get_instance_layout
+    pub instance_layout: Option<wgpu::VertexBufferLayout<'static>>,
     pub push_contant_ranges: &'a [wgpu::PushConstantRange],
     pub pass_target: PassTarget,
+    pub topology: wgpu::PrimitiveTopology,
+    pub sample_count: u32,
+    // Blends composite targets for colors already multiplied by their alpha, the shader's
+    // premultiplied_alpha override is set so it can output them that way
+    pub premultiplied_alpha: bool,
 }
 
 pub enum PassTarget {
     Scene,
     Composite,
+    // The scene target, adding to what is there without writing depth, so the order of the
+    // draws doesn't matter. Drawn after everything opaque, both sides of the triangles.
+    Additive,
+    // The scene target, blended over it where the depth test fails for the opaque pass, e.g.
+    // silhouettes of units behind walls. Writes no depth.
+    Occluded,
+    // The scene target, blended over everything whatever the depth, e.g. editor gizmos
+    Overlay,
+    // Like Scene with only the edges of the triangles, needs POLYGON_MODE_LINE
+    Wireframe,
+    // The scene target, adding to what is there whatever the depth and writing none, so
+    // every fragment shaded counts. See DebugView::Overdraw.
+    Overdraw,
 }
 
 impl Default for PassTarget {
@@ -25,6 +45,10 @@ pub struct MaterialPipeline {
     pub _pipeline_layout: wgpu::PipelineLayout,
     pub pipeline: wgpu::RenderPipeline,
     pub bindgroup_layout: Option<wgpu::BindGroupLayout>,
+    pub target_format: wgpu::TextureFormat,
+    pub premultiplied_alpha: bool,
+    pub additive: bool,
+    pub instance_stride: Option<wgpu::BufferAddress>, // Set when the instances are a vertex buffer
 }
 
 impl MaterialPipeline {}
@@ -61,12 +85,61 @@ impl RenderDevice {
                 write_mask: wgpu::ColorWrites::ALL,
             })];
 
+        // The alpha scales the color added, the alpha of the target is left alone
+        const ADDITIVE_COLOR_TARGETS: [Option<wgpu::ColorTargetState>; 1] =
+            [Some(wgpu::ColorTargetState {
+                format: wgpu::TextureFormat::Rgba16Float,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Zero,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })];
+
+        // Blended with the alpha, the alpha of the target is left alone like above
+        const OCCLUDED_COLOR_TARGETS: [Option<wgpu::ColorTargetState>; 1] =
+            [Some(wgpu::ColorTargetState {
+                format: wgpu::TextureFormat::Rgba16Float,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Zero,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })];
+
         let composite_color_targets = [Some(wgpu::ColorTargetState {
             format: self.config.format,
-            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+            blend: Some(if desc.premultiplied_alpha {
+                wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING
+            } else {
+                wgpu::BlendState::ALPHA_BLENDING
+            }),
             write_mask: wgpu::ColorWrites::ALL,
         })];
 
+        // Only shaders that declare the override may get it
+        let fragment_constants: &[(&str, f64)] = if desc.premultiplied_alpha {
+            &[("premultiplied_alpha", 1.0)]
+        } else {
+            &[]
+        };
+
         let default_depth_stencil = wgpu::DepthStencilState {
             format: wgpu::TextureFormat::Depth32Float,
             depth_write_enabled: true,
@@ -75,6 +148,13 @@ impl RenderDevice {
             bias: wgpu::DepthBiasState::default(),
         };
 
+        let vertex_layouts: Vec<_> = std::iter::once(desc.vertex_layout.clone())
+            .chain(desc.instance_layout.clone())
+            .collect();
+
+        #[cfg(debug_assertions)]
+        self.validate_vertex_layout(desc.vertex_shader, &vertex_layouts);
+
         let pipeline = self
             .device
             .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -84,38 +164,63 @@ impl RenderDevice {
                     module: desc.vertex_shader,
                     entry_point: Some("vs_main"),
                     compilation_options: wgpu::PipelineCompilationOptions::default(),
-                    buffers: &[desc.vertex_layout.clone()],
+                    buffers: &vertex_layouts,
                 },
                 fragment: match desc.fragment_shader {
                     Some(fragment_shader) => Some(wgpu::FragmentState {
                         module: fragment_shader,
                         entry_point: Some("fs_main"),
-                        compilation_options: wgpu::PipelineCompilationOptions::default(),
+                        compilation_options: wgpu::PipelineCompilationOptions {
+                            constants: fragment_constants,
+                            ..Default::default()
+                        },
                         targets: match desc.pass_target {
-                            PassTarget::Scene => &SCENE_COLOR_TARGETS,
+                            PassTarget::Scene | PassTarget::Wireframe => &SCENE_COLOR_TARGETS,
                             PassTarget::Composite => &composite_color_targets,
+                            PassTarget::Additive | PassTarget::Overdraw => &ADDITIVE_COLOR_TARGETS,
+                            PassTarget::Occluded | PassTarget::Overlay => &OCCLUDED_COLOR_TARGETS,
                         },
                     }),
                     None => None,
                 },
                 primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    topology: desc.topology,
                     strip_index_format: None,
                     front_face: wgpu::FrontFace::Ccw,
                     cull_mode: match desc.pass_target {
-                        PassTarget::Scene => Some(wgpu::Face::Back),
-                        PassTarget::Composite => None,
+                        PassTarget::Scene
+                        | PassTarget::Occluded
+                        | PassTarget::Wireframe
+                        | PassTarget::Overdraw => Some(wgpu::Face::Back),
+                        PassTarget::Composite | PassTarget::Additive | PassTarget::Overlay => None,
                     },
                     unclipped_depth: false,
-                    polygon_mode: wgpu::PolygonMode::Fill,
+                    polygon_mode: match desc.pass_target {
+                        PassTarget::Wireframe => wgpu::PolygonMode::Line,
+                        _ => wgpu::PolygonMode::Fill,
+                    },
                     conservative: false,
                 },
                 depth_stencil: match desc.pass_target {
-                    PassTarget::Scene => Some(default_depth_stencil),
+                    PassTarget::Scene | PassTarget::Wireframe => Some(default_depth_stencil),
                     PassTarget::Composite => None,
+                    PassTarget::Additive => Some(wgpu::DepthStencilState {
+                        depth_write_enabled: false,
+                        ..default_depth_stencil
+                    }),
+                    PassTarget::Occluded => Some(wgpu::DepthStencilState {
+                        depth_write_enabled: false,
+                        depth_compare: wgpu::CompareFunction::Greater,
+                        ..default_depth_stencil
+                    }),
+                    PassTarget::Overlay | PassTarget::Overdraw => Some(wgpu::DepthStencilState {
+                        depth_write_enabled: false,
+                        depth_compare: wgpu::CompareFunction::Always,
+                        ..default_depth_stencil
+                    }),
                 },
                 multisample: wgpu::MultisampleState {
-                    count: 1,
+                    count: desc.sample_count,
                     mask: !0,
                     alpha_to_coverage_enabled: false,
                 },
@@ -127,6 +232,61 @@ impl RenderDevice {
             _pipeline_layout: pipeline_layout,
             pipeline,
             bindgroup_layout: extra_bind_group_layout,
+            target_format: match desc.pass_target {
+                PassTarget::Scene
+                | PassTarget::Additive
+                | PassTarget::Occluded
+                | PassTarget::Overlay
+                | PassTarget::Wireframe
+                | PassTarget::Overdraw => wgpu::TextureFormat::Rgba16Float,
+                PassTarget::Composite => self.config.format,
+            },
+            premultiplied_alpha: desc.premultiplied_alpha,
+            additive: matches!(desc.pass_target, PassTarget::Additive),
+            instance_stride: desc
+                .instance_layout
+                .as_ref()
+                .map(|layout| layout.array_stride),
+        }
+    }
+}
+
+// Compute shaders have their entry point at cs_main
+pub struct ComputePipelineDesc<'a> {
+    pub shader: &'a wgpu::ShaderModule,
+    pub bind_group_layouts: &'a [&'a wgpu::BindGroupLayout],
+}
+
+pub struct ComputePipeline {
+    pub _pipeline_layout: wgpu::PipelineLayout,
+    pub pipeline: wgpu::ComputePipeline,
+}
+
+impl RenderDevice {
+    // Check supports_compute first, adapters like WebGL2 have no compute shaders
+    pub fn create_compute_pipeline(&self, desc: &ComputePipelineDesc) -> ComputePipeline {
+        let pipeline_layout = self
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: desc.bind_group_layouts,
+                push_constant_ranges: &[],
+            });
+
+        let pipeline = self
+            .device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                module: desc.shader,
+                entry_point: Some("cs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            });
+
+        ComputePipeline {
+            _pipeline_layout: pipeline_layout,
+            pipeline,
         }
     }
 }
@@ -137,6 +297,35 @@ pub struct MaterialInstanceDesc<'a> {
 
 pub struct MaterialInstance {
     pub bind_group: wgpu::BindGroup,
+    pub premultiplied_alpha: bool, // Of its pipeline, see Renderer::render_batches
+    pub additive: bool,            // Its batches go to the additive pass
+    pub variant_set: Option<MaterialVariantSet>,
+    pub swaying: bool, // Its instances sway in the wind, see Renderer::create_swaying_material
+    // What its bind group was made from, None for the built-in materials. Set by the
+    // renderer, see batch_diagnostics.
+    pub fingerprint: Option<MaterialFingerprint>,
+}
+
+// The variants of a material are the layers of its array texture, e.g. skins and team
+// colors, picked per instance so the entities still share their batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaterialVariantSet {
+    pub texture: ResourceHandle,
+    pub variant_count: u32,
+}
+
+impl MaterialVariantSet {
+    // The texture layer of the variant, out of range ones fall back to the last variant
+    pub fn get_layer(&self, variant: u32) -> u32 {
+        if variant >= self.variant_count {
+            log::warn!(
+                "Variant {} is out of range, the material has {} variants.",
+                variant,
+                self.variant_count
+            );
+        }
+        variant.min(self.variant_count.saturating_sub(1))
+    }
 }
 
 impl RenderDevice {
@@ -153,6 +342,11 @@ impl RenderDevice {
 
         MaterialInstance {
             bind_group: bindgroup,
+            premultiplied_alpha: pipeline.premultiplied_alpha,
+            additive: pipeline.additive,
+            variant_set: None,
+            swaying: false,
+            fingerprint: None,
         }
     }
 }
@@ -1,5 +1,17 @@
+use std::ops::Range;
+
 use crate::renderer::{Buffer, BufferDesc, RenderDevice};
 
+// The most detail levels a mesh can have, the frame stats count the instances per level
+pub const MAX_LOD_COUNT: usize = 4;
+
+// Set in the mesh count of a file with a LOD table after it
+const LOD_TABLE_FLAG: u32 = 1 << 31;
+
+// How far past a switch distance an instance has to be before its level changes, as a
+// fraction of the distance, so instances standing on the boundary don't keep popping
+const LOD_HYSTERESIS: f32 = 0.1;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct StaticMeshVertex {
@@ -45,6 +57,26 @@ impl SkeletalMeshVertex {
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugLineVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl DebugLineVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DebugLineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct BoneInfo {
@@ -53,11 +85,19 @@ pub struct BoneInfo {
     pub offset_matrix: [f32; 16],
 }
 
+// One detail level, its triangles share the vertex buffer with the other levels
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshLod {
+    pub index_range: Range<u32>,
+    pub switch_distance: f32, // From the camera, the first level starts at 0
+}
+
 #[derive(Default)]
 pub struct MeshLoadDesc {
     pub vertex_data: Vec<u8>,
     pub indices: Vec<u32>,
     pub _bones: Vec<BoneInfo>,
+    pub lods: Vec<MeshLod>, // Empty for a single level with all of the indices
 }
 
 impl MeshLoadDesc {
@@ -71,7 +111,51 @@ impl MeshLoadDesc {
         let mesh_count = u32::from_le_bytes(tmp);
         read_index += 4;
 
-        for _ in 0..mesh_count {
+        // The meshes of each level follow each other, most detailed first
+        let mut lod_mesh_counts = Vec::new();
+        if mesh_count & LOD_TABLE_FLAG != 0 {
+            tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
+            let lod_count = u32::from_le_bytes(tmp) as usize;
+            read_index += 4;
+            anyhow::ensure!(
+                (1..=MAX_LOD_COUNT).contains(&lod_count),
+                "Meshes can have 1 to {} LODs, not {}",
+                MAX_LOD_COUNT,
+                lod_count
+            );
+
+            for _ in 0..lod_count {
+                tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
+                let lod_mesh_count = u32::from_le_bytes(tmp);
+                tmp.copy_from_slice(&bytes[read_index + 4..read_index + 8]);
+                let switch_distance = f32::from_le_bytes(tmp);
+                read_index += 8;
+                anyhow::ensure!(lod_mesh_count > 0, "A LOD without meshes");
+                lod_mesh_counts.push((lod_mesh_count, switch_distance));
+            }
+        }
+        let mesh_count = mesh_count & !LOD_TABLE_FLAG;
+        anyhow::ensure!(
+            lod_mesh_counts.is_empty()
+                || lod_mesh_counts.iter().map(|(count, _)| count).sum::<u32>() == mesh_count,
+            "The LOD table doesn't add up to the {} meshes",
+            mesh_count
+        );
+
+        for mesh_index in 0..mesh_count {
+            // A level starts with its first mesh
+            let mut first_mesh = 0;
+            for (lod_mesh_count, switch_distance) in &lod_mesh_counts {
+                if mesh_index == first_mesh {
+                    let start = desc.indices.len() as u32;
+                    desc.lods.push(MeshLod {
+                        index_range: start..start,
+                        switch_distance: *switch_distance,
+                    });
+                }
+                first_mesh += lod_mesh_count;
+            }
+
             // Vertex data read
             {
                 tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
@@ -110,6 +194,10 @@ impl MeshLoadDesc {
 
                 read_index += index_data_size;
             }
+
+            if let Some(lod) = desc.lods.last_mut() {
+                lod.index_range.end = desc.indices.len() as u32;
+            }
         }
 
         // If there are more bytes to read, there is a bone buffer
@@ -125,26 +213,61 @@ impl MeshLoadDesc {
 
         Ok(desc)
     }
+
+    fn get_lods(&self) -> Vec<MeshLod> {
+        if self.lods.is_empty() {
+            return vec![MeshLod {
+                index_range: 0..self.indices.len() as u32,
+                switch_distance: 0.0,
+            }];
+        }
+        self.lods.clone()
+    }
+}
+
+// The level for an instance at the distance from the camera. With the level it had the
+// last frame it only changes once the distance is past the hysteresis band.
+pub fn select_lod(lods: &[MeshLod], distance: f32, previous: Option<usize>) -> usize {
+    let Some(mut lod) = previous else {
+        return lods
+            .iter()
+            .skip(1)
+            .take_while(|lod| distance >= lod.switch_distance)
+            .count();
+    };
+
+    lod = lod.min(lods.len().saturating_sub(1));
+    while lod + 1 < lods.len() && distance >= lods[lod + 1].switch_distance * (1.0 + LOD_HYSTERESIS)
+    {
+        lod += 1;
+    }
+    while lod > 0 && distance < lods[lod].switch_distance * (1.0 - LOD_HYSTERESIS) {
+        lod -= 1;
+    }
+    lod
 }
 
 pub struct MeshDrawInfo<'a> {
     pub vertex_slice: wgpu::BufferSlice<'a>,
     pub index_slice: wgpu::BufferSlice<'a>,
-    pub index_count: u32,
+    pub index_range: Range<u32>, // Of the level that was asked for
 }
 
 pub struct StaticMesh {
     pub vertex_buffer: Buffer,
     pub index_buffer: Buffer,
-    pub index_count: u32,
+    pub lods: Vec<MeshLod>, // At least one
+    // Kept on the CPU so static props can be baked into combined meshes, see bake
+    pub vertices: Vec<StaticMeshVertex>,
+    pub indices: Vec<u32>,
 }
 
 impl StaticMesh {
-    pub fn get_draw_info(&self) -> MeshDrawInfo<'_> {
+    pub fn get_draw_info(&self, lod: usize) -> MeshDrawInfo<'_> {
         MeshDrawInfo {
             vertex_slice: self.vertex_buffer.buffer.slice(..),
             index_slice: self.index_buffer.buffer.slice(..),
-            index_count: self.index_count,
+            index_range: get_lod_index_range(&self.lods, lod),
         }
     }
 }
@@ -152,18 +275,80 @@ impl StaticMesh {
 pub struct SkeletalMesh {
     pub vertex_buffer: Buffer,
     pub index_buffer: Buffer,
-    pub index_count: u32,
     pub bones: Vec<BoneInfo>,
+    pub lods: Vec<MeshLod>, // At least one, all of them use the same bones
 }
 
 impl SkeletalMesh {
+    pub fn get_draw_info(&self, lod: usize) -> MeshDrawInfo<'_> {
+        MeshDrawInfo {
+            vertex_slice: self.vertex_buffer.buffer.slice(..),
+            index_slice: self.index_buffer.buffer.slice(..),
+            index_range: get_lod_index_range(&self.lods, lod),
+        }
+    }
+}
+
+// Geometry the CPU rewrites as often as every frame, e.g. trails. The buffers are bigger
+// than needed and only grow, the index count says how much of them is valid.
+pub struct DynamicMesh {
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Buffer,
+    pub vertex_capacity: usize,
+    pub index_capacity: usize,
+    pub index_count: u32,
+}
+
+impl DynamicMesh {
     pub fn get_draw_info(&self) -> MeshDrawInfo<'_> {
         MeshDrawInfo {
             vertex_slice: self.vertex_buffer.buffer.slice(..),
             index_slice: self.index_buffer.buffer.slice(..),
-            index_count: self.index_count,
+            index_range: 0..self.index_count,
         }
     }
+
+    // Replaces the geometry, new buffers are made when it doesn't fit. The indices may only
+    // refer to the vertices passed along with them.
+    pub fn update(
+        &mut self,
+        render_device: &RenderDevice,
+        vertices: &[StaticMeshVertex],
+        indices: &[u32],
+    ) {
+        if vertices.len() > self.vertex_capacity {
+            self.vertex_capacity = get_grown_capacity(self.vertex_capacity, vertices.len());
+            self.vertex_buffer = render_device.create_dynamic_vertex_buffer(self.vertex_capacity);
+        }
+        if indices.len() > self.index_capacity {
+            self.index_capacity = get_grown_capacity(self.index_capacity, indices.len());
+            self.index_buffer = render_device.create_dynamic_index_buffer(self.index_capacity);
+        }
+
+        if !vertices.is_empty() {
+            render_device.write_buffer(&self.vertex_buffer, bytemuck::cast_slice(vertices), 0);
+        }
+        if !indices.is_empty() {
+            render_device.write_buffer(&self.index_buffer, bytemuck::cast_slice(indices), 0);
+        }
+        self.index_count = indices.len() as u32;
+    }
+}
+
+// At least doubles, so geometry growing a little every frame doesn't reallocate every frame
+pub fn get_grown_capacity(capacity: usize, needed: usize) -> usize {
+    if needed <= capacity {
+        capacity
+    } else {
+        needed.max(capacity * 2)
+    }
+}
+
+// Levels the mesh doesn't have fall back to its least detailed one
+fn get_lod_index_range(lods: &[MeshLod], lod: usize) -> Range<u32> {
+    lods.get(lod)
+        .or(lods.last())
+        .map_or(0..0, |lod| lod.index_range.clone())
 }
 
 impl RenderDevice {
@@ -207,7 +392,9 @@ impl RenderDevice {
         Ok(StaticMesh {
             vertex_buffer,
             index_buffer,
-            index_count: desc.indices.len() as u32,
+            lods: desc.get_lods(),
+            vertices: bytemuck::pod_collect_to_vec(&desc.vertex_data),
+            indices: desc.indices.clone(),
         })
     }
 
@@ -216,8 +403,236 @@ impl RenderDevice {
         Ok(SkeletalMesh {
             vertex_buffer,
             index_buffer,
-            index_count: desc.indices.len() as u32,
             bones: desc._bones.clone(),
+            lods: desc.get_lods(),
+        })
+    }
+
+    // Room for the vertices and three indices per vertex, which is about what strips need
+    pub fn create_dynamic_mesh(&self, initial_capacity: usize) -> DynamicMesh {
+        let vertex_capacity = initial_capacity.max(1); // Empty buffers can't be bound
+        let index_capacity = vertex_capacity * 3;
+        DynamicMesh {
+            vertex_buffer: self.create_dynamic_vertex_buffer(vertex_capacity),
+            index_buffer: self.create_dynamic_index_buffer(index_capacity),
+            vertex_capacity,
+            index_capacity,
+            index_count: 0,
+        }
+    }
+
+    fn create_dynamic_vertex_buffer(&self, capacity: usize) -> Buffer {
+        self.create_buffer(&BufferDesc {
+            size: capacity * std::mem::size_of::<StaticMeshVertex>(),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         })
     }
+
+    fn create_dynamic_index_buffer(&self, capacity: usize) -> Buffer {
+        self.create_buffer(&BufferDesc {
+            size: capacity * std::mem::size_of::<u32>(),
+            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+}
+
+// A capsule of radius 0.5 standing on the origin and 2 units high, e.g. as a shadow proxy.
+// Two hemispheres of the given rings with the cylinder between their equators.
+pub fn get_capsule_geometry(segments: u32, rings: u32) -> (Vec<StaticMeshVertex>, Vec<u32>) {
+    use std::f32::consts::{FRAC_PI_2, TAU};
+
+    let mut vertices = Vec::new();
+    for (center, first_angle) in [(1.5, 0.0), (0.5, FRAC_PI_2)] {
+        for ring in 0..=rings {
+            let polar = first_angle + ring as f32 / rings as f32 * FRAC_PI_2;
+            for segment in 0..=segments {
+                let azimuth = segment as f32 / segments as f32 * TAU;
+                let normal = [
+                    polar.sin() * azimuth.cos(),
+                    polar.cos(),
+                    -polar.sin() * azimuth.sin(),
+                ];
+                vertices.push(StaticMeshVertex {
+                    position: [0.5 * normal[0], center + 0.5 * normal[1], 0.5 * normal[2]],
+                    normal,
+                    uvs: [
+                        segment as f32 / segments as f32,
+                        ring as f32 / rings as f32,
+                        0.0,
+                    ],
+                    color: [1.0, 1.0, 1.0, 1.0],
+                });
+            }
+        }
+    }
+
+    // Counter-clockwise from the outside
+    let row = segments + 1;
+    let row_count = 2 * (rings + 1);
+    let mut indices = Vec::new();
+    for ring in 0..row_count - 1 {
+        for segment in 0..segments {
+            let a = ring * row + segment;
+            let b = a + row;
+            indices.extend([a, b, a + 1, a + 1, b, b + 1]);
+        }
+    }
+
+    (vertices, indices)
+}
+
+// A flat ring on the ground around the origin, facing up, with an outer radius of 1, e.g.
+// to mark selected units
+pub fn get_ring_geometry(segments: u32, inner_radius: f32) -> (Vec<StaticMeshVertex>, Vec<u32>) {
+    use std::f32::consts::TAU;
+
+    let mut vertices = Vec::new();
+    for segment in 0..=segments {
+        let angle = segment as f32 / segments as f32 * TAU;
+        for (radius, v) in [(inner_radius, 0.0), (1.0, 1.0)] {
+            vertices.push(StaticMeshVertex {
+                position: [radius * angle.cos(), 0.0, -radius * angle.sin()],
+                normal: [0.0, 1.0, 0.0],
+                uvs: [segment as f32 / segments as f32, v, 0.0],
+                color: [1.0, 1.0, 1.0, 1.0],
+            });
+        }
+    }
+
+    // Counter-clockwise seen from above
+    let mut indices = Vec::new();
+    for segment in 0..segments {
+        let a = segment * 2;
+        indices.extend([a, a + 1, a + 2, a + 2, a + 1, a + 3]);
+    }
+
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capsule_faces_point_outwards() {
+        let (vertices, indices) = get_capsule_geometry(12, 4);
+        let position = |index: u32| {
+            let [x, y, z] = vertices[index as usize].position;
+            shared::math::Vec3::new(x, y, z)
+        };
+
+        for vertex in &vertices {
+            let [x, y, z] = vertex.position;
+            assert!((0.0..=2.0).contains(&y));
+            assert!((x * x + z * z).sqrt() <= 0.5 + 1e-5);
+        }
+
+        for triangle in indices.chunks(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(position);
+            let normal = (b - a).cross(c - a);
+            if normal.length() < 1e-6 {
+                continue; // At the poles
+            }
+            let center = (a + b + c) / 3.0;
+            let axis = shared::math::Vec3::new(0.0, center.y.clamp(0.5, 1.5), 0.0);
+            assert!(normal.dot(center - axis) > 0.0);
+        }
+    }
+
+    // A file with one triangle per mesh, the LOD table lists how many meshes each level has
+    fn write_mesh_file(lods: &[(u32, f32)], mesh_count: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if lods.is_empty() {
+            bytes.extend_from_slice(&mesh_count.to_le_bytes());
+        } else {
+            bytes.extend_from_slice(&(mesh_count | LOD_TABLE_FLAG).to_le_bytes());
+            bytes.extend_from_slice(&(lods.len() as u32).to_le_bytes());
+            for (count, distance) in lods {
+                bytes.extend_from_slice(&count.to_le_bytes());
+                bytes.extend_from_slice(&distance.to_le_bytes());
+            }
+        }
+        for mesh_index in 0..mesh_count {
+            bytes.extend_from_slice(&3u32.to_le_bytes());
+            bytes.extend(std::iter::repeat_n(0, 3 * size_of::<StaticMeshVertex>()));
+            bytes.extend_from_slice(&3u32.to_le_bytes());
+            for index in 0..3 {
+                bytes.extend_from_slice(&(mesh_index * 3 + index).to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn lod_tables_split_the_indices() {
+        let vertex_size = size_of::<StaticMeshVertex>();
+        let desc = MeshLoadDesc::load(&write_mesh_file(&[], 2), vertex_size).unwrap();
+        assert!(desc.lods.is_empty());
+        assert_eq!(desc.get_lods()[0].index_range, 0..6);
+
+        let lods = [(2, 0.0), (1, 1500.0), (1, 3000.0)];
+        let desc = MeshLoadDesc::load(&write_mesh_file(&lods, 4), vertex_size).unwrap();
+        let ranges: Vec<_> = desc
+            .lods
+            .iter()
+            .map(|lod| lod.index_range.clone())
+            .collect();
+        assert_eq!(ranges, vec![0..6, 6..9, 9..12]);
+        assert_eq!(desc.lods[2].switch_distance, 3000.0);
+        assert_eq!(get_lod_index_range(&desc.lods, 5), 9..12);
+
+        assert!(
+            MeshLoadDesc::load(&write_mesh_file(&[(2, 0.0), (1, 1500.0)], 4), vertex_size).is_err()
+        );
+    }
+
+    #[test]
+    fn lods_switch_past_the_hysteresis_band() {
+        let lod = |switch_distance| MeshLod {
+            index_range: 0..0,
+            switch_distance,
+        };
+        let lods = [lod(0.0), lod(1000.0), lod(2000.0)];
+        assert_eq!(select_lod(&lods, 500.0, None), 0);
+        assert_eq!(select_lod(&lods, 1000.0, None), 1);
+        assert_eq!(select_lod(&lods, 5000.0, None), 2);
+
+        // Walking out past the first boundary and back
+        assert_eq!(select_lod(&lods, 1050.0, Some(0)), 0);
+        assert_eq!(select_lod(&lods, 1150.0, Some(0)), 1);
+        assert_eq!(select_lod(&lods, 950.0, Some(1)), 1);
+        assert_eq!(select_lod(&lods, 850.0, Some(1)), 0);
+
+        // Jumps skip the levels in between
+        assert_eq!(select_lod(&lods, 3000.0, Some(0)), 2);
+        assert_eq!(select_lod(&lods, 100.0, Some(2)), 0);
+        assert_eq!(select_lod(&lods[..1], 3000.0, Some(2)), 0);
+    }
+
+    #[test]
+    fn dynamic_capacity_at_least_doubles() {
+        assert_eq!(get_grown_capacity(64, 10), 64);
+        assert_eq!(get_grown_capacity(64, 64), 64);
+        assert_eq!(get_grown_capacity(64, 65), 128);
+        assert_eq!(get_grown_capacity(64, 1000), 1000);
+        assert_eq!(get_grown_capacity(1, 2), 2);
+    }
+
+    #[test]
+    fn ring_faces_up() {
+        let (vertices, indices) = get_ring_geometry(16, 0.8);
+        let position = |index: u32| {
+            let [x, y, z] = vertices[index as usize].position;
+            shared::math::Vec3::new(x, y, z)
+        };
+
+        for vertex in &vertices {
+            let radius = shared::math::Vec3::from(vertex.position).length();
+            assert!((radius - 0.8).abs() < 1e-5 || (radius - 1.0).abs() < 1e-5);
+        }
+        for triangle in indices.chunks(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(position);
+            assert!((b - a).cross(c - a).y > 0.0);
+        }
+    }
 }
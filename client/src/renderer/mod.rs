@@ -1,28 +1,59 @@
 pub mod material;
 pub use material::{
-    MaterialInstance, MaterialInstanceDesc, MaterialPipeline, MaterialPipelineDesc, PassTarget,
+    MaterialInstance, MaterialInstanceDesc, MaterialPipeline, MaterialPipelineDesc,
+    MaterialVariantSet, PassTarget,
 };
 pub mod renderer;
-pub use renderer::{DrawData, Renderer};
+pub use renderer::{DrawData, Renderer, RendererError, Screenshot};
+pub mod bundle;
+pub use bundle::BundleHandles;
 pub mod buffer;
+pub mod culling;
 pub use buffer::{Buffer, BufferDesc};
 pub mod texture;
-pub use texture::{Texture, TextureDesc};
+pub use texture::{Texture, TextureDesc, TextureUpload};
 pub mod mesh;
 pub use mesh::{
-    MeshDrawInfo, MeshLoadDesc, SkeletalMesh, SkeletalMeshVertex, StaticMesh, StaticMeshVertex,
+    BoneInfo, DebugLineVertex, MeshDrawInfo, MeshLoadDesc, SkeletalMesh, SkeletalMeshVertex,
+    StaticMesh, StaticMeshVertex,
 };
 pub mod animation;
+pub mod batch_diagnostics;
+pub mod blend_space;
+pub use blend_space::{BlendSample, BlendSpace2D};
+pub mod antialiasing;
 pub use animation::Animation;
+pub use antialiasing::{AaMode, FxaaSettings};
+pub mod debug_view;
+pub use debug_view::DebugView;
 pub mod device;
+pub mod frame_graph;
+pub mod gpu_timer;
+pub use gpu_timer::PassTiming;
+pub mod light;
+pub use light::{AmbientLight, DirectionalLight, Fog, Wind};
 pub mod font;
-pub use device::RenderDevice;
+pub use device::{DeviceDiagnostics, InstancePath, RenderDevice};
 pub use font::{Font, Glyph};
 pub mod instance_data;
-pub use instance_data::{SpriteInstanceData, StaticInstanceData};
+pub use instance_data::{SpriteInstanceData, SpriteParams, StaticInstanceData};
+pub mod resource_scope;
 pub mod resources;
-pub use resources::{Resource, ResourceHandle, ResourcePool};
+pub mod safe_area;
+pub mod shader;
+pub mod shader_data;
+pub mod shader_layout;
+pub use resources::{Resource, ResourceHandle, ResourceKind, ResourcePool};
+#[cfg(feature = "runtime-font")]
+pub mod runtime_font;
+pub mod sprite_atlas;
+#[cfg(feature = "test-harness")]
+pub mod test_harness;
+pub use sprite_atlas::{PixelRect, SpriteRegion};
 pub mod render_data;
 pub use render_data::{
-    RenderData, SkeletalRenderJob, SpriteAnchor, SpriteSpace, StaticRenderJob, TextAlignment,
+    DebugLineRenderJob, FrameStats, GizmoLineRenderJob, PersistentChunk, PersistentSet, RenderData,
+    SkeletalRenderJob, SpriteAnchor, SpriteSpace, StaticRenderJob, TextAlignment,
 };
+pub mod world_grid;
+pub use world_grid::WorldGridSettings;
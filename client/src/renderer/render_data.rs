@@ -1,26 +1,117 @@
-use std::{collections::HashMap, ops::Range};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+};
 
-use shared::math::*;
+use serde::Serialize;
+use shared::{math::*, profile_scope};
 
 use crate::renderer::{
-    DrawData, Renderer, ResourceHandle, ResourcePool, SpriteInstanceData, StaticInstanceData,
-    animation::Pose, renderer::RenderBatch,
+    DebugLineVertex, DrawData, Renderer, ResourceHandle, ResourcePool, SpriteInstanceData,
+    SpriteParams, SpriteRegion, StaticInstanceData,
+    animation::Pose,
+    culling::{get_frustum_planes, is_box_in_frustum},
+    font::Bounds,
+    instance_data::INSTANCE_FLAG_WIND,
+    mesh::{MAX_LOD_COUNT, select_lod},
+    renderer::{PersistentBatch, RenderBatch},
 };
 
+// The texture layer of a material variant, see Renderer::create_variant_material. Out of
+// range variants are clamped, materials without variants only have the first one.
+fn get_variant_layer(resource_pool: &ResourcePool, material: ResourceHandle, variant: u32) -> u32 {
+    if variant == 0 {
+        return 0;
+    }
+    match resource_pool
+        .get_material_instance(material)
+        .and_then(|material| material.variant_set)
+    {
+        Some(variant_set) => variant_set.get_layer(variant),
+        None => {
+            log::warn!("Variant {} of a material without variants.", variant);
+            0
+        }
+    }
+}
+
+// The INSTANCE_FLAG bits static meshes of the material are drawn with
+pub(crate) fn get_instance_flags(resource_pool: &ResourcePool, material: ResourceHandle) -> u32 {
+    match resource_pool.get_material_instance(material) {
+        Some(material) if material.swaying => INSTANCE_FLAG_WIND,
+        _ => 0,
+    }
+}
+
 pub trait SubmitJob {
     fn submit(&self, render_data: &mut RenderData, resource_pool: &ResourcePool);
 }
 
+// Bits of the render layers of a job, a render only draws the jobs on its layer mask
+pub const RENDER_LAYER_DEFAULT: u32 = 1 << 0;
+#[allow(dead_code)]
+pub const RENDER_LAYER_MINIMAP: u32 = 1 << 1; // Markers only the minimap draws
+pub const ALL_RENDER_LAYERS: u32 = u32::MAX;
+
+// Jobs nothing was submitted to for this many frames are dropped, with their allocations
+const JOB_LIFETIME_FRAMES: u64 = 300;
+
+// The first block of the sprite params, for the sprites without any
+const NO_SPRITE_PARAMS: SpriteParams = [0.0; 8];
+
 #[derive(Default)]
 struct InstancedRenderJob<T> {
     instances: Vec<T>,
+    last_used_frame: u64,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Default)]
 struct BatchKey {
     material: ResourceHandle,
     mesh: ResourceHandle,
-    layer: u32,
+    lod: u8,    // Detail level of the mesh, the levels draw different index ranges
+    layer: u32, // Sprite layer, or the depth bucket for transparent batches
+    casts_shadow: bool,
+    shadow_only: bool,  // A shadow proxy, not drawn in the scene
+    weight_debug: bool, // Drawn with the bone weight debug pipeline
+    xray: bool,         // A silhouette, drawn where the mesh is hidden
+    render_layers: u32,
+}
+
+#[allow(dead_code)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum BatchCategory {
+    Opaque,
+    Sprite,
+    Transparent,
+}
+
+impl BatchKey {
+    // Sort key layout, from the most significant bits:
+    //   Opaque:      material (32 bits) | mesh (32 bits), groups state changes together
+    //   Sprite:      layer (16 bits) | material (48 bits), lower layers are drawn first
+    //   Transparent: inverted depth bucket (16 bits) | material (48 bits), drawn back to front
+    // Handles are truncated to fit, ties are broken on the full handles when sorting.
+    fn sort_key(&self, category: BatchCategory) -> u64 {
+        const LOW_32: u64 = 0xFFFF_FFFF;
+        const LOW_48: u64 = 0xFFFF_FFFF_FFFF;
+        let layer = self.layer.min(u16::MAX as u32) as u64;
+
+        match category {
+            BatchCategory::Opaque => ((self.material & LOW_32) << 32) | (self.mesh & LOW_32),
+            BatchCategory::Sprite => (layer << 48) | (self.material & LOW_48),
+            BatchCategory::Transparent => {
+                ((u16::MAX as u64 - layer) << 48) | (self.material & LOW_48)
+            }
+        }
+    }
+}
+
+// Maps a view space depth to the bucket stored in the layer of transparent batch keys
+#[allow(dead_code)]
+pub fn get_depth_bucket(view_depth: f32, max_depth: f32) -> u32 {
+    ((view_depth / max_depth).clamp(0.0, 1.0) * u16::MAX as f32) as u32
 }
 
 pub struct StaticRenderJob {
@@ -30,6 +121,12 @@ pub struct StaticRenderJob {
     pub color: Vec4,
     pub tex_coord: Vec2,
     pub tex_scale: Vec2,
+    pub casts_shadow: bool,
+    pub render_layers: u32,
+    pub variant: u32, // The layer of a variant material
+    // Stable between frames, e.g. the entity, so its detail level only changes once it is
+    // past the hysteresis band. Without one the switch distances are used as they are.
+    pub instance_id: Option<u64>,
 }
 
 impl Default for StaticRenderJob {
@@ -41,26 +138,84 @@ impl Default for StaticRenderJob {
             color: Vec4::ONE,
             tex_coord: Vec2::ZERO,
             tex_scale: Vec2::ONE,
+            casts_shadow: true,
+            render_layers: RENDER_LAYER_DEFAULT,
+            variant: 0,
+            instance_id: None,
         }
     }
 }
 
 impl SubmitJob for StaticRenderJob {
-    fn submit(&self, render_data: &mut RenderData, _resource_pool: &ResourcePool) {
+    fn submit(&self, render_data: &mut RenderData, resource_pool: &ResourcePool) {
         let key = BatchKey {
             mesh: self.mesh,
-            material: self.material,
+            lod: render_data.select_lod(self.mesh, self.transform, self.instance_id, resource_pool),
+            material: resource_pool.get_canonical_material(self.material),
             layer: 0,
+            casts_shadow: self.casts_shadow,
+            shadow_only: false,
+            weight_debug: false,
+            xray: false,
+            render_layers: self.render_layers,
         };
 
+        let texture_layer = get_variant_layer(resource_pool, self.material, self.variant);
+        let flags = get_instance_flags(resource_pool, self.material);
         let instanced_job = render_data.static_jobs.entry(key).or_default();
-        instanced_job.instances.push(StaticInstanceData {
+        instanced_job
+            .instances
+            .push(self.get_instance_data(texture_layer, flags));
+    }
+}
+
+impl StaticRenderJob {
+    fn get_instance_data(&self, texture_layer: u32, flags: u32) -> StaticInstanceData {
+        StaticInstanceData {
             model_matrix: self.transform.to_data(),
             color: self.color.to_data(),
             tex_coord: self.tex_coord.to_data(),
             tex_scale: self.tex_scale.to_data(),
-            ..Default::default()
-        });
+            data_indices: [0, flags, 0, texture_layer],
+        }
+    }
+}
+
+// What casts the shadow of a skeletal mesh. Skinning runs once per pass, a static proxy
+// saves the skinning of the shadow pass where the detail is barely visible.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ShadowProxy {
+    #[default]
+    None, // The skinned mesh itself
+    StaticMesh(ResourceHandle), // In the space of the skeletal mesh, e.g. a low-poly version
+    // In world units, standing on the job's origin
+    Capsule {
+        radius: f32,
+        height: f32,
+    },
+}
+
+// Colors a skeletal mesh by its bone weights instead of its material, for finding bad
+// weights. The instance data tells the debug shader which view to draw.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WeightDebugView {
+    #[default]
+    None,
+    #[cfg_attr(not(feature = "inspector"), allow(dead_code))]
+    LargestWeight, // Red where a single bone moves the vertex, blue for an even split
+    #[cfg_attr(not(feature = "inspector"), allow(dead_code))]
+    Bone(u32), // The weight of one bone, dark where it has no influence
+}
+
+impl WeightDebugView {
+    // Mode and bone in the instance data indices
+    fn get_data(self) -> [u32; 2] {
+        match self {
+            WeightDebugView::None => [0, 0],
+            WeightDebugView::LargestWeight => [1, 0],
+            WeightDebugView::Bone(bone) => [2, bone],
+        }
     }
 }
 
@@ -73,6 +228,16 @@ pub struct SkeletalRenderJob<'a> {
     pub tex_coord: Vec2,
     pub tex_scale: Vec2,
     pub pose: Option<&'a Pose>,
+    pub casts_shadow: bool,
+    pub shadow_proxy: ShadowProxy,
+    pub weight_debug: WeightDebugView,
+    // Also drawn as a silhouette of the x-ray color where something is in front of it, e.g.
+    // units of the player's team behind walls
+    pub xray: bool,
+    pub xray_color: Vec4,
+    pub render_layers: u32,
+    pub variant: u32,
+    pub instance_id: Option<u64>, // See StaticRenderJob
 }
 
 impl Default for SkeletalRenderJob<'_> {
@@ -85,16 +250,75 @@ impl Default for SkeletalRenderJob<'_> {
             tex_coord: Vec2::ZERO,
             tex_scale: Vec2::ONE,
             pose: None,
+            casts_shadow: true,
+            shadow_proxy: ShadowProxy::None,
+            weight_debug: WeightDebugView::None,
+            xray: false,
+            xray_color: Vec4::ONE,
+            render_layers: RENDER_LAYER_DEFAULT,
+            variant: 0,
+            instance_id: None,
         }
     }
 }
 
+impl SkeletalRenderJob<'_> {
+    // The static instance drawn in the shadow pass instead of the skinned mesh
+    fn get_shadow_proxy(&self) -> Option<(ResourceHandle, Mat4)> {
+        match self.shadow_proxy {
+            ShadowProxy::None => None,
+            ShadowProxy::StaticMesh(mesh) => Some((mesh, self.transform)),
+            ShadowProxy::Capsule { radius, height } => {
+                // The mesh is 1 unit wide and 2 units high
+                let scale = Vec3::new(2.0 * radius, 0.5 * height, 2.0 * radius);
+                let origin = self.transform.w_axis.truncate();
+                Some((
+                    Renderer::CAPSULE_MESH,
+                    Mat4::from_scale_rotation_translation(scale, Quat::IDENTITY, origin),
+                ))
+            }
+        }
+    }
+
+    // Returns false when the skinned mesh casts the shadow itself
+    fn submit_shadow_proxy(&self, render_data: &mut RenderData) -> bool {
+        if !self.casts_shadow || !render_data.is_beyond_shadow_proxy_distance(self.transform) {
+            return false;
+        }
+        let Some((mesh, transform)) = self.get_shadow_proxy() else {
+            return false;
+        };
+
+        render_data.push_shadow_proxy(&StaticRenderJob {
+            transform,
+            material: self.material,
+            mesh,
+            render_layers: self.render_layers,
+            ..Default::default()
+        });
+        true
+    }
+}
+
 impl SubmitJob for SkeletalRenderJob<'_> {
     fn submit(&self, render_data: &mut RenderData, _resource_pool: &ResourcePool) {
+        let has_proxy = self.submit_shadow_proxy(render_data);
+
         let key = BatchKey {
             mesh: self.mesh,
-            material: self.material,
+            lod: render_data.select_lod(
+                self.mesh,
+                self.transform,
+                self.instance_id,
+                _resource_pool,
+            ),
+            material: _resource_pool.get_canonical_material(self.material),
             layer: 0,
+            casts_shadow: self.casts_shadow && !has_proxy,
+            shadow_only: false,
+            weight_debug: self.weight_debug != WeightDebugView::None,
+            xray: false,
+            render_layers: self.render_layers,
         };
 
         let pose = self.pose.expect("Pose was None");
@@ -116,22 +340,48 @@ impl SubmitJob for SkeletalRenderJob<'_> {
             &mut render_data.bones[bone_index..bone_index + bone_count],
         );
 
+        let [debug_mode, debug_bone] = self.weight_debug.get_data();
+        let texture_layer = get_variant_layer(_resource_pool, self.material, self.variant);
         let instanced_job = render_data.skeletal_jobs.entry(key).or_default();
         instanced_job.instances.push(StaticInstanceData {
             model_matrix: self.transform.to_data(),
             color: self.color.to_data(),
             tex_coord: self.tex_coord.to_data(),
             tex_scale: self.tex_scale.to_data(),
-            data_indices: [bone_index as u32, 0, 0, 0],
+            data_indices: [bone_index as u32, debug_mode, debug_bone, texture_layer],
         });
+
+        // The same bones again, in a batch of its own that casts no shadow
+        if self.xray {
+            let xray_key = BatchKey {
+                casts_shadow: false,
+                weight_debug: false,
+                xray: true,
+                ..key
+            };
+            let instanced_job = render_data.skeletal_jobs.entry(xray_key).or_default();
+            instanced_job.instances.push(StaticInstanceData {
+                model_matrix: self.transform.to_data(),
+                color: self.xray_color.to_data(),
+                tex_coord: self.tex_coord.to_data(),
+                tex_scale: self.tex_scale.to_data(),
+                data_indices: [bone_index as u32, 0, 0, 0],
+            });
+        }
     }
 }
 
+// Of the falloff a glow reaches out, where exp(-5) is below a step of an 8-bit channel
+const GLOW_EXTENT: f32 = 5.0;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub enum SpriteRenderMode {
     Normal = 0,
     Msdf = 1,
+    RadialFill = 2,
+    Rounded = 3,
+    Glow = 4,
 }
 
 #[allow(dead_code)]
@@ -158,6 +408,11 @@ pub enum SpriteSpace {
     Normalized = 2,
 }
 
+// Batching rules for UI code: sprites are batched by layer and material, and a batch draws
+// its sprites in the order they were submitted. Between materials of one layer the order
+// is up to the sorting, so anything that has to go on top of another material needs a
+// higher layer. Solid quads all share the white material, so each layer has at most one
+// batch of them, however they are interleaved with textured sprites.
 #[derive(Debug)]
 #[allow(dead_code)]
 pub struct SpriteRenderJob {
@@ -171,6 +426,9 @@ pub struct SpriteRenderJob {
     pub mode: SpriteRenderMode,
     pub anchor: SpriteAnchor,
     pub space: SpriteSpace,
+    pub rotation: f32, // Radians clockwise on screen, around the center of the sprite
+    pub variant: u32,  // The layer of a variant material
+    pub params: Option<SpriteParams>, // For the mode, see SpriteParams
 }
 
 impl Default for SpriteRenderJob {
@@ -178,7 +436,7 @@ impl Default for SpriteRenderJob {
         Self {
             position: Vec2::ZERO,
             size: Vec2::ONE,
-            material: 0,
+            material: Renderer::WHITE_SPRITE_MATERIAL,
             color: Vec4::ONE,
             tex_coord: Vec2::ZERO,
             tex_scale: Vec2::ONE,
@@ -186,18 +444,113 @@ impl Default for SpriteRenderJob {
             mode: SpriteRenderMode::Normal,
             anchor: SpriteAnchor::TopLeft,
             space: SpriteSpace::Reference,
+            rotation: 0.0,
+            variant: 0,
+            params: None,
+        }
+    }
+}
+
+impl SpriteRenderJob {
+    // A plain colored quad, the rest can be set with struct update syntax
+    pub fn solid(position: Vec2, size: Vec2, color: Vec4, layer: u32) -> Self {
+        Self {
+            position,
+            size,
+            material: Renderer::WHITE_SPRITE_MATERIAL,
+            color,
+            layer,
+            ..Default::default()
+        }
+    }
+
+    // Fills in the atlas material and uvs, the rest can be set with struct update syntax
+    #[allow(dead_code)]
+    pub fn from_region(region: &SpriteRegion, position: Vec2, size: Vec2) -> Self {
+        Self {
+            position,
+            size,
+            material: region.material,
+            tex_coord: region.tex_coord,
+            tex_scale: region.tex_scale,
+            ..Default::default()
+        }
+    }
+
+    // Only the filled fraction of the sprite is drawn, clockwise from the top, e.g. the
+    // sweep of a cooldown
+    pub fn radial_fill(self, amount: f32) -> Self {
+        let mut params = NO_SPRITE_PARAMS;
+        params[0] = amount.clamp(0.0, 1.0);
+        Self {
+            mode: SpriteRenderMode::RadialFill,
+            params: Some(params),
+            ..self
+        }
+    }
+
+    // A rounded rectangle, only the border of it when the width is above 0. Like the other
+    // sizes they are in the units of the sprite's space.
+    pub fn rounded(self, radius: f32, border: f32) -> Self {
+        let mut params = NO_SPRITE_PARAMS;
+        params[0] = radius;
+        params[1] = border;
+        Self {
+            mode: SpriteRenderMode::Rounded,
+            params: Some(params),
+            ..self
+        }
+    }
+
+    // The rounded rectangle blurred over the softness, e.g. a drop shadow offset below a
+    // panel. The quad grows so the blur outside of the shape isn't cut off.
+    pub fn shadow(self, radius: f32, softness: f32) -> Self {
+        let mut params = NO_SPRITE_PARAMS;
+        params[0] = radius;
+        params[2] = softness;
+        params[3] = softness;
+        Self {
+            position: self.position - softness,
+            size: self.size + 2.0 * softness,
+            mode: SpriteRenderMode::Rounded,
+            params: Some(params),
+            ..self
+        }
+    }
+
+    // Light around the rounded rectangle that falls off exponentially away from its edge,
+    // the quad grows to where it is no longer visible
+    pub fn glow(self, radius: f32, falloff: f32) -> Self {
+        let extent = falloff * GLOW_EXTENT;
+        let mut params = NO_SPRITE_PARAMS;
+        params[0] = radius;
+        params[2] = falloff;
+        params[3] = extent;
+        Self {
+            position: self.position - extent,
+            size: self.size + 2.0 * extent,
+            mode: SpriteRenderMode::Glow,
+            params: Some(params),
+            ..self
         }
     }
 }
 
 impl SubmitJob for SpriteRenderJob {
-    fn submit(&self, render_data: &mut RenderData, _resource_pool: &ResourcePool) {
+    fn submit(&self, render_data: &mut RenderData, resource_pool: &ResourcePool) {
         let key = BatchKey {
             mesh: Renderer::QUAD_MESH,
-            material: self.material,
+            lod: 0,
+            material: resource_pool.get_canonical_material(self.material),
             layer: self.layer,
+            casts_shadow: false,
+            shadow_only: false,
+            weight_debug: false,
+            xray: false,
+            render_layers: ALL_RENDER_LAYERS,
         };
 
+        let params_index = render_data.push_sprite_params(self.params);
         let instanced_job = render_data.sprite_jobs.entry(key).or_default();
         instanced_job.instances.push(SpriteInstanceData {
             position: self.position.to_data(),
@@ -209,7 +562,9 @@ impl SubmitJob for SpriteRenderJob {
             layer: self.layer,
             anchor: self.anchor as u32,
             space: self.space as u32,
-            ..Default::default()
+            rotation: Vec2::from_angle(self.rotation).to_array(),
+            texture_layer: get_variant_layer(resource_pool, self.material, self.variant),
+            params_index,
         });
     }
 }
@@ -225,7 +580,7 @@ pub enum TextAlignment {
 #[derive(Debug)]
 #[allow(dead_code)]
 pub struct TextRenderJob<'a> {
-    pub text: &'a str,
+    pub text: Cow<'a, str>, // Borrowed, or a String formatted for this frame
     pub font_atlas: ResourceHandle,
     pub font_material: ResourceHandle,
     pub position: Vec2,
@@ -240,7 +595,7 @@ pub struct TextRenderJob<'a> {
 impl Default for TextRenderJob<'_> {
     fn default() -> Self {
         Self {
-            text: "",
+            text: Cow::Borrowed(""),
             font_atlas: 0,
             font_material: 0,
             position: Vec2::ZERO,
@@ -258,8 +613,14 @@ impl SubmitJob for TextRenderJob<'_> {
     fn submit(&self, render_data: &mut RenderData, resource_pool: &ResourcePool) {
         let key = BatchKey {
             mesh: Renderer::QUAD_MESH,
-            material: self.font_material,
+            lod: 0,
+            material: resource_pool.get_canonical_material(self.font_material),
             layer: self.layer,
+            casts_shadow: false,
+            shadow_only: false,
+            weight_debug: false,
+            xray: false,
+            render_layers: ALL_RENDER_LAYERS,
         };
 
         let font = resource_pool
@@ -267,67 +628,220 @@ impl SubmitJob for TextRenderJob<'_> {
             .expect("Failed to get font atlas");
 
         let mut render_position = self.position;
+        let text_width = || font.get_text_width(&self.text, self.size);
         match self.alignment {
             TextAlignment::Left => {}
-            TextAlignment::Center => {
-                let text_width: f32 = self
-                    .text
-                    .chars()
-                    .filter_map(|c| font.get_glyph(&(c as u32)))
-                    .map(|g| g.advance * self.size)
-                    .sum();
-                render_position.x -= text_width * 0.5;
-            }
-            TextAlignment::Right => {
-                let text_width: f32 = self
-                    .text
-                    .chars()
-                    .filter_map(|c| font.get_glyph(&(c as u32)))
-                    .map(|g| g.advance * self.size)
-                    .sum();
-                render_position.x -= text_width;
-            }
+            TextAlignment::Center => render_position.x -= text_width() * 0.5,
+            TextAlignment::Right => render_position.x -= text_width(),
         }
 
-        let glyphs = font.get_glyphs(self.text);
+        // Glyphs from the runtime atlas use its material, so they go into a batch of their own
+        #[cfg(feature = "runtime-font")]
+        let mut runtime_instances = Vec::new();
+        #[cfg(feature = "runtime-font")]
+        let frame = render_data.get_submit_frame();
+
+        // One block for all of the glyphs from the atlas
+        let mut msdf_params = NO_SPRITE_PARAMS;
+        msdf_params[0] = font.distance_range;
+        let params_index = render_data.push_sprite_params(Some(msdf_params));
+
         let instanced_job = render_data.sprite_jobs.entry(key).or_default();
-        for glyph in glyphs {
-            match glyph {
-                Some(glyph) => {
-                    match (&glyph.uv, &glyph.plane) {
-                        (Some(uv), Some(plane)) => {
-                            let position = render_position + plane.offset * self.size;
-                            let size = plane.size * self.size;
-
-                            instanced_job.instances.push(SpriteInstanceData {
-                                position: position.to_data(),
-                                scale: size.to_data(),
-                                color: self.color.to_data(),
-                                tex_coord: uv.offset.to_data(),
-                                tex_scale: uv.size.to_data(),
-                                mode: SpriteRenderMode::Msdf as u32,
-                                layer: self.layer,
-                                space: self.space as u32,
-                                anchor: self.anchor as u32,
-                            });
-                        }
-                        _ => {}
-                    }
-                    render_position.x += glyph.advance * self.size;
+        let mut glyph_count = 0;
+        for c in self.text.chars() {
+            if let Some(glyph) = font.get_glyph(&(c as u32)) {
+                if let (Some(uv), Some(plane)) = (&glyph.uv, &glyph.plane) {
+                    instanced_job.instances.push(self.get_glyph_instance(
+                        render_position,
+                        plane,
+                        uv.offset,
+                        uv.size,
+                        SpriteRenderMode::Msdf,
+                        params_index,
+                    ));
+                    glyph_count += 1;
+                }
+                render_position.x += glyph.advance * self.size;
+                continue;
+            }
+
+            #[cfg(feature = "runtime-font")]
+            if let Some(runtime) = &font.runtime {
+                let Some(glyph) = runtime.get_glyph(c as u32, frame) else {
+                    // Rasterized before the frame is built, drawn from the next one
+                    render_data.missing_glyphs.push((self.font_atlas, c as u32));
+                    continue;
+                };
+                if let (Some(rect), Some(plane)) = (glyph.rect, &glyph.plane) {
+                    let region = SpriteRegion::from_pixel_rect(
+                        runtime.material,
+                        rect,
+                        runtime.get_atlas_size(),
+                    );
+                    runtime_instances.push(self.get_glyph_instance(
+                        render_position,
+                        plane,
+                        region.tex_coord,
+                        region.tex_scale,
+                        SpriteRenderMode::Normal,
+                        0,
+                    ));
                 }
-                _ => {}
+                render_position.x += glyph.advance * self.size;
             }
         }
+
+        #[cfg(feature = "runtime-font")]
+        if let Some(runtime) = &font.runtime
+            && !runtime_instances.is_empty()
+        {
+            let key = BatchKey {
+                material: resource_pool.get_canonical_material(runtime.material),
+                ..key
+            };
+            glyph_count += runtime_instances.len();
+            let instanced_job = render_data.sprite_jobs.entry(key).or_default();
+            instanced_job.instances.append(&mut runtime_instances);
+        }
+
+        render_data.text_glyph_count += glyph_count;
+    }
+}
+
+impl TextRenderJob<'_> {
+    // The pen position is on the baseline, the plane is in ems from it
+    fn get_glyph_instance(
+        &self,
+        pen_position: Vec2,
+        plane: &Bounds,
+        tex_coord: Vec2,
+        tex_scale: Vec2,
+        mode: SpriteRenderMode,
+        params_index: u32,
+    ) -> SpriteInstanceData {
+        let position = pen_position + plane.offset * self.size;
+        let size = plane.size * self.size;
+        SpriteInstanceData {
+            position: position.to_data(),
+            scale: size.to_data(),
+            color: self.color.to_data(),
+            tex_coord: tex_coord.to_data(),
+            tex_scale: tex_scale.to_data(),
+            mode: mode as u32,
+            layer: self.layer,
+            space: self.space as u32,
+            anchor: self.anchor as u32,
+            params_index,
+            ..Default::default()
+        }
+    }
+}
+
+// Lines are drawn in the scene pass, mostly for visualizing things while developing
+pub struct DebugLineRenderJob {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub color: Vec4,
+}
+
+impl SubmitJob for DebugLineRenderJob {
+    fn submit(&self, render_data: &mut RenderData, _resource_pool: &ResourcePool) {
+        let color = self.color.to_array();
+        render_data.debug_lines.push(DebugLineVertex {
+            position: self.start.to_array(),
+            color,
+        });
+        render_data.debug_lines.push(DebugLineVertex {
+            position: self.end.to_array(),
+            color,
+        });
+    }
+}
+
+// Lines drawn over the whole scene, whatever is in front of them, e.g. the handles of the
+// editor gizmo
+pub struct GizmoLineRenderJob {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub color: Vec4,
+}
+
+impl SubmitJob for GizmoLineRenderJob {
+    fn submit(&self, render_data: &mut RenderData, _resource_pool: &ResourcePool) {
+        let color = self.color.to_array();
+        render_data.gizmo_lines.push(DebugLineVertex {
+            position: self.start.to_array(),
+            color,
+        });
+        render_data.gizmo_lines.push(DebugLineVertex {
+            position: self.end.to_array(),
+            color,
+        });
     }
 }
 
 type JobMap<T> = HashMap<BatchKey, InstancedRenderJob<T>>;
 
+// Instances that stay in a buffer of their own instead of being submitted every frame, e.g.
+// ground clutter. They are grouped into chunks that are culled as a whole.
+#[derive(Clone, Debug)]
+pub struct PersistentSet {
+    pub mesh: ResourceHandle,
+    pub material: ResourceHandle,
+    pub casts_shadow: bool,
+    pub render_layers: u32,
+    pub chunks: Vec<PersistentChunk>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PersistentChunk {
+    pub instance_range: Range<u32>,
+    pub min: Vec3, // World space bounds of the instances
+    pub max: Vec3,
+}
+
+// The detail level an instance was drawn with, dropped like the jobs when it goes unused
+struct LodState {
+    lod: usize,
+    last_used_frame: u64,
+}
+
+// Per-frame counters, these are only lengths so they are cheap to gather
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct FrameStats {
+    pub static_batch_count: usize,
+    pub static_instance_count: usize,
+    pub skeletal_batch_count: usize,
+    pub skeletal_instance_count: usize,
+    pub bone_count: usize,
+    pub sprite_batch_count: usize,
+    pub sprite_instance_count: usize,
+    pub text_glyph_count: usize,
+    pub debug_line_count: usize,
+    pub lod_instance_counts: [usize; MAX_LOD_COUNT], // Static and skeletal, per detail level
+    pub persistent_batch_count: usize,
+    pub persistent_instance_count: usize, // In the chunks that passed culling
+}
+
 pub struct RenderData {
     static_jobs: JobMap<StaticInstanceData>,
     skeletal_jobs: JobMap<StaticInstanceData>,
     bones: Vec<Mat4Data>,
     sprite_jobs: JobMap<SpriteInstanceData>,
+    // By the index in the instances, which stays the same however the batches are sorted
+    sprite_params: Vec<SpriteParams>,
+    text_glyph_count: usize,
+    lod_instance_counts: [usize; MAX_LOD_COUNT],
+    lod_states: HashMap<(u64, ResourceHandle), LodState>, // By instance id and mesh
+    debug_lines: Vec<DebugLineVertex>,
+    gizmo_lines: Vec<DebugLineVertex>,
+    camera_position: Vec3,
+    shadow_proxy_distance: Option<f32>, // Full skinning closer to the camera than this
+    frame: u64,                         // Counts the built draw data
+    persistent_sets: BTreeMap<ResourceHandle, PersistentSet>, // By their instance buffer
+    cull_planes: Option<([Vec4; 6], [Vec4; 6])>, // Camera and light, for the persistent chunks
+    #[cfg(feature = "runtime-font")]
+    missing_glyphs: Vec<(ResourceHandle, u32)>, // Font and unicode, for the renderer to rasterize
 }
 
 impl RenderData {
@@ -337,18 +851,136 @@ impl RenderData {
             skeletal_jobs: HashMap::new(),
             bones: Vec::new(),
             sprite_jobs: HashMap::new(),
+            sprite_params: vec![NO_SPRITE_PARAMS],
+            text_glyph_count: 0,
+            lod_instance_counts: [0; MAX_LOD_COUNT],
+            lod_states: HashMap::new(),
+            debug_lines: Vec::new(),
+            gizmo_lines: Vec::new(),
+            camera_position: Vec3::ZERO,
+            shadow_proxy_distance: None,
+            frame: 0,
+            persistent_sets: BTreeMap::new(),
+            cull_planes: None,
+            #[cfg(feature = "runtime-font")]
+            missing_glyphs: Vec::new(),
         }
     }
 
+    // The frame the jobs are being submitted for
+    pub fn get_submit_frame(&self) -> u64 {
+        self.frame + 1
+    }
+
+    // Glyphs texts asked the runtime atlas for since the last call, may repeat
+    #[cfg(feature = "runtime-font")]
+    pub fn take_missing_glyphs(&mut self) -> Vec<(ResourceHandle, u32)> {
+        std::mem::take(&mut self.missing_glyphs)
+    }
+
+    // The index for the params_index of the instance, 0 without params
+    fn push_sprite_params(&mut self, params: Option<SpriteParams>) -> u32 {
+        let Some(params) = params else {
+            return 0;
+        };
+        self.sprite_params.push(params);
+        (self.sprite_params.len() - 1) as u32
+    }
+
+    pub fn set_camera_position(&mut self, position: Vec3) {
+        self.camera_position = position;
+    }
+
+    // Persistent chunks outside of the camera are left out of the scene pass and the ones
+    // outside of the light out of the shadow pass. Nothing is culled until this is set.
+    pub fn set_cull_view_projections(&mut self, camera: Mat4, light: Mat4) {
+        self.cull_planes = Some((get_frustum_planes(camera), get_frustum_planes(light)));
+    }
+
+    // Drawn every frame from the buffer the handle stands for, until it is removed
+    pub fn add_persistent_set(&mut self, instances: ResourceHandle, set: PersistentSet) {
+        self.persistent_sets.insert(instances, set);
+    }
+
+    pub fn remove_persistent_set(&mut self, instances: ResourceHandle) {
+        self.persistent_sets.remove(&instances);
+    }
+
+    // Without a distance the shadow proxies are always used
+    pub fn set_shadow_proxy_distance(&mut self, distance: Option<f32>) {
+        self.shadow_proxy_distance = distance;
+    }
+
+    fn is_beyond_shadow_proxy_distance(&self, transform: Mat4) -> bool {
+        self.shadow_proxy_distance.is_none_or(|distance| {
+            let position = transform.w_axis.truncate();
+            position.distance_squared(self.camera_position) > distance * distance
+        })
+    }
+
+    // Picked from the distance of the camera to the origin of the instance
+    fn select_lod(
+        &mut self,
+        mesh: ResourceHandle,
+        transform: Mat4,
+        instance_id: Option<u64>,
+        resource_pool: &ResourcePool,
+    ) -> u8 {
+        let lods = resource_pool.get_mesh_lods(mesh).unwrap_or_default();
+        let distance = transform.w_axis.truncate().distance(self.camera_position);
+        let frame = self.get_submit_frame();
+
+        let lod = match instance_id {
+            Some(id) => {
+                let state = self.lod_states.entry((id, mesh)).or_insert(LodState {
+                    lod: select_lod(lods, distance, None),
+                    last_used_frame: frame,
+                });
+                state.lod = select_lod(lods, distance, Some(state.lod));
+                state.last_used_frame = frame;
+                state.lod
+            }
+            None => select_lod(lods, distance, None),
+        };
+        self.lod_instance_counts[lod.min(MAX_LOD_COUNT - 1)] += 1;
+        lod as u8
+    }
+
+    fn push_shadow_proxy(&mut self, job: &StaticRenderJob) {
+        let key = BatchKey {
+            mesh: job.mesh,
+            lod: 0,
+            material: job.material,
+            layer: 0,
+            casts_shadow: true,
+            shadow_only: true,
+            weight_debug: false,
+            xray: false,
+            render_layers: job.render_layers,
+        };
+        self.static_jobs
+            .entry(key)
+            .or_default()
+            .instances
+            .push(job.get_instance_data(0, 0));
+    }
+
     pub fn submit<T: SubmitJob>(&mut self, job: &T, resource_pool: &ResourcePool) {
         job.submit(self, resource_pool);
     }
 
     // NOTE: The instance data from the jobs is moved into the draw data when built, so
     // the jobs stay allocated and the instance vectors are not reallocated every frame.
-    // They can however be explicitly reset with the reset method.
+    // Jobs that go unused for JOB_LIFETIME_FRAMES are trimmed, e.g. the materials of a
+    // previous level, and all of them can be dropped with the reset method.
 
-    fn build_batches<T>(jobs: &mut JobMap<T>) -> (Vec<RenderBatch>, Vec<T>) {
+    // Jobs outside of the layer mask are dropped for this frame
+    fn build_batches<T>(
+        jobs: &mut JobMap<T>,
+        category: BatchCategory,
+        layer_mask: u32,
+        frame: u64,
+    ) -> (Vec<RenderBatch>, Vec<T>) {
         let batch_count = jobs.len();
         let instance_count = jobs.iter().map(|(_, job)| job.instances.len()).sum();
 
@@ -356,6 +988,16 @@ impl RenderData {
         let mut instances: Vec<T> = Vec::with_capacity(instance_count);
 
         for (key, job) in jobs.iter_mut() {
+            // Jobs stay allocated between frames, so skip the ones nothing was submitted to
+            if job.instances.is_empty() {
+                continue;
+            }
+            job.last_used_frame = frame;
+            if key.render_layers & layer_mask == 0 {
+                job.instances.clear();
+                continue;
+            }
+
             let start = instances.len() as u32;
 
             // Instances are moved
@@ -366,36 +1008,807 @@ impl RenderData {
             batches.push(RenderBatch {
                 material_instance: key.material,
                 mesh: key.mesh,
-                layer: key.layer,
+                lod: key.lod,
+                sort_key: key.sort_key(category),
+                casts_shadow: key.casts_shadow,
+                shadow_only: key.shadow_only,
+                weight_debug: key.weight_debug,
+                xray: key.xray,
                 instance_range: Range { start, end },
             });
         }
 
-        batches.sort_by_key(|b| (b.material_instance, b.mesh, b.layer));
+        jobs.retain(|_, job| frame - job.last_used_frame < JOB_LIFETIME_FRAMES);
+
+        // The renderer draws the batches in this order
+        batches.sort_by_key(|b| (b.sort_key, b.material_instance, b.mesh, b.lod));
         (batches, instances)
     }
 
-    pub fn build_draw_data(&mut self) -> DrawData {
-        let (static_batches, static_instances) = Self::build_batches(&mut self.static_jobs);
-        let (skeletal_batches, skeletal_instances) = Self::build_batches(&mut self.skeletal_jobs);
-        let (sprite_batches, sprite_instances) = Self::build_batches(&mut self.sprite_jobs);
+    // Shadow batches reuse the instance ranges of the scene batches, so the instance data
+    // is only uploaded once. Sets that need different instances will need their own jobs.
+    fn get_shadow_batches(batches: &[RenderBatch]) -> Vec<RenderBatch> {
+        batches
+            .iter()
+            .filter(|batch| batch.casts_shadow)
+            .cloned()
+            .collect()
+    }
+
+    // One batch per run of neighbouring chunks that pass, they draw in a single call
+    fn cull_persistent_set(set: &PersistentSet, planes: Option<&[Vec4; 6]>) -> Vec<RenderBatch> {
+        let mut ranges: Vec<Range<u32>> = Vec::new();
+        for chunk in &set.chunks {
+            if planes.is_some_and(|planes| !is_box_in_frustum(planes, chunk.min, chunk.max)) {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(last) if last.end == chunk.instance_range.start => {
+                    last.end = chunk.instance_range.end
+                }
+                _ => ranges.push(chunk.instance_range.clone()),
+            }
+        }
+
+        ranges
+            .into_iter()
+            .map(|instance_range| RenderBatch {
+                material_instance: set.material,
+                mesh: set.mesh,
+                lod: 0,
+                sort_key: 0,
+                casts_shadow: set.casts_shadow,
+                shadow_only: false,
+                weight_debug: false,
+                xray: false,
+                instance_range,
+            })
+            .collect()
+    }
+
+    // Scene and shadow batches
+    fn build_persistent_batches(
+        &self,
+        layer_mask: u32,
+    ) -> (Vec<PersistentBatch>, Vec<PersistentBatch>) {
+        let (camera, light) = self
+            .cull_planes
+            .as_ref()
+            .map(|(camera, light)| (camera, light))
+            .unzip();
+        let mut scene_batches = Vec::new();
+        let mut shadow_batches = Vec::new();
+        for (&instances, set) in &self.persistent_sets {
+            if set.render_layers & layer_mask == 0 {
+                continue;
+            }
+
+            let batches = Self::cull_persistent_set(set, camera);
+            if !batches.is_empty() {
+                scene_batches.push(PersistentBatch { instances, batches });
+            }
+            if set.casts_shadow {
+                let batches = Self::cull_persistent_set(set, light);
+                if !batches.is_empty() {
+                    shadow_batches.push(PersistentBatch { instances, batches });
+                }
+            }
+        }
+
+        (scene_batches, shadow_batches)
+    }
+
+    // For the render target the layer mask belongs to, e.g. the main camera
+    pub fn build_draw_data(&mut self, layer_mask: u32) -> (DrawData, FrameStats) {
+        profile_scope!("Build draw data");
+        self.frame += 1;
+        let frame = self.frame;
+        let (mut static_batches, static_instances) = Self::build_batches(
+            &mut self.static_jobs,
+            BatchCategory::Opaque,
+            layer_mask,
+            frame,
+        );
+        let (mut skeletal_batches, skeletal_instances) = Self::build_batches(
+            &mut self.skeletal_jobs,
+            BatchCategory::Opaque,
+            layer_mask,
+            frame,
+        );
+        let (sprite_batches, sprite_instances) = Self::build_batches(
+            &mut self.sprite_jobs,
+            BatchCategory::Sprite,
+            layer_mask,
+            frame,
+        );
+
+        let shadow_static_batches = Self::get_shadow_batches(&static_batches);
+        let shadow_skeletal_batches = Self::get_shadow_batches(&skeletal_batches);
+        static_batches.retain(|batch| !batch.shadow_only);
+        let weight_debug_batches: Vec<_> = skeletal_batches
+            .extract_if(.., |batch| batch.weight_debug)
+            .collect();
+        let xray_batches: Vec<_> = skeletal_batches
+            .extract_if(.., |batch| batch.xray)
+            .collect();
+
+        let (persistent_batches, shadow_persistent_batches) =
+            self.build_persistent_batches(layer_mask);
 
         let bones = self.bones.clone();
         self.bones.clear();
+        let sprite_params = std::mem::replace(&mut self.sprite_params, vec![NO_SPRITE_PARAMS]);
 
-        DrawData {
+        let debug_line_vertices = self.debug_lines.clone();
+        self.debug_lines.clear();
+        let gizmo_line_vertices = self.gizmo_lines.clone();
+        self.gizmo_lines.clear();
+
+        let stats = FrameStats {
+            static_batch_count: static_batches.len(),
+            static_instance_count: static_instances.len(),
+            skeletal_batch_count: skeletal_batches.len()
+                + weight_debug_batches.len()
+                + xray_batches.len(),
+            skeletal_instance_count: skeletal_instances.len(),
+            bone_count: bones.len(),
+            sprite_batch_count: sprite_batches.len(),
+            sprite_instance_count: sprite_instances.len(),
+            text_glyph_count: self.text_glyph_count,
+            debug_line_count: debug_line_vertices.len() / 2,
+            lod_instance_counts: self.lod_instance_counts,
+            persistent_batch_count: persistent_batches
+                .iter()
+                .map(|persistent| persistent.batches.len())
+                .sum(),
+            persistent_instance_count: persistent_batches
+                .iter()
+                .flat_map(|persistent| &persistent.batches)
+                .map(|batch| batch.instance_range.len())
+                .sum(),
+        };
+        self.text_glyph_count = 0;
+        self.lod_instance_counts = [0; MAX_LOD_COUNT];
+        self.lod_states
+            .retain(|_, state| frame - state.last_used_frame < JOB_LIFETIME_FRAMES);
+
+        let draw_data = DrawData {
             static_batches,
             static_instances,
             skeletal_batches,
             skeletal_instances,
+            weight_debug_batches,
+            xray_batches,
             bones,
+            additive_batches: Vec::new(), // Sorted out by the renderer, which knows the materials
+            shadow_static_batches,
+            shadow_skeletal_batches,
+            persistent_batches,
+            shadow_persistent_batches,
             sprite_batches,
             sprite_instances,
-        }
+            sprite_params,
+            debug_line_vertices,
+            gizmo_line_vertices,
+        };
+
+        (draw_data, stats)
     }
 
-    #[allow(dead_code)]
+    // Drops every job and whatever was submitted since the last draw data was built, the
+    // persistent sets stay
     pub fn reset(&mut self) {
         self.static_jobs.clear();
+        self.skeletal_jobs.clear();
+        self.sprite_jobs.clear();
+        self.sprite_params.truncate(1);
+        self.bones.clear();
+        self.debug_lines.clear();
+        self.gizmo_lines.clear();
+        self.text_glyph_count = 0;
+        self.lod_instance_counts = [0; MAX_LOD_COUNT];
+        self.lod_states.clear();
+        #[cfg(feature = "runtime-font")]
+        self.missing_glyphs.clear();
+    }
+
+    #[allow(dead_code)]
+    pub fn get_job_count(&self) -> usize {
+        self.static_jobs.len() + self.skeletal_jobs.len() + self.sprite_jobs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_stats_count_submitted_jobs() {
+        let resource_pool = ResourcePool::new();
+        let mut render_data = RenderData::new();
+
+        for material in [1, 1, 1, 2] {
+            render_data.submit(
+                &StaticRenderJob {
+                    material,
+                    mesh: 10,
+                    ..Default::default()
+                },
+                &resource_pool,
+            );
+        }
+
+        for layer in [0, 0, 0, 1, 1] {
+            render_data.submit(
+                &SpriteRenderJob {
+                    material: 3,
+                    layer,
+                    ..Default::default()
+                },
+                &resource_pool,
+            );
+        }
+
+        // Skinned meshes and fonts need a device, so this is what their submits leave behind:
+        // two units of three bones and a text of seven glyphs
+        let job = render_data
+            .skeletal_jobs
+            .entry(BatchKey {
+                material: 4,
+                mesh: 20,
+                render_layers: ALL_RENDER_LAYERS,
+                ..Default::default()
+            })
+            .or_default();
+        job.instances.resize(2, bytemuck::Zeroable::zeroed());
+        render_data.bones.resize(6, Mat4::IDENTITY.to_data());
+        render_data.text_glyph_count += 7;
+
+        let (draw_data, stats) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert_eq!(
+            stats,
+            FrameStats {
+                static_batch_count: 2,
+                static_instance_count: 4,
+                skeletal_batch_count: 1,
+                skeletal_instance_count: 2,
+                bone_count: 6,
+                sprite_batch_count: 2,
+                sprite_instance_count: 5,
+                text_glyph_count: 7,
+                lod_instance_counts: [4, 0, 0, 0], // The mesh isn't loaded, so it has one level
+                ..Default::default()
+            }
+        );
+        assert_eq!(draw_data.bones.len(), stats.bone_count);
+        assert_eq!(
+            draw_data.static_instances.len(),
+            stats.static_instance_count
+        );
+        assert_eq!(
+            draw_data.sprite_instances.len(),
+            stats.sprite_instance_count
+        );
+    }
+
+    #[test]
+    fn frame_stats_reset_between_frames() {
+        let resource_pool = ResourcePool::new();
+        let mut render_data = RenderData::new();
+
+        render_data.submit(&SpriteRenderJob::default(), &resource_pool);
+        let (_, stats) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert_eq!(stats.sprite_instance_count, 1);
+
+        let (_, stats) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert_eq!(stats.sprite_batch_count, 0);
+        assert_eq!(stats.sprite_instance_count, 0);
+        assert_eq!(stats.text_glyph_count, 0);
+    }
+
+    #[test]
+    fn unused_jobs_are_trimmed() {
+        let resource_pool = ResourcePool::new();
+        let mut render_data = RenderData::new();
+        let persistent = |render_data: &mut RenderData| {
+            for _ in 0..100 {
+                render_data.submit(
+                    &StaticRenderJob {
+                        material: 1,
+                        mesh: 10,
+                        ..Default::default()
+                    },
+                    &resource_pool,
+                );
+            }
+        };
+
+        // A new material every frame, like a scene that keeps changing
+        let mut persistent_instances = None;
+        for frame in 0..1000 {
+            persistent(&mut render_data);
+            render_data.submit(
+                &SpriteRenderJob {
+                    material: 1000 + frame,
+                    ..Default::default()
+                },
+                &resource_pool,
+            );
+            render_data.build_draw_data(ALL_RENDER_LAYERS);
+
+            // The persistent job keeps the allocation of its first frame
+            let job = &render_data.static_jobs.values().next().unwrap().instances;
+            let instances = (job.as_ptr(), job.capacity());
+            assert_eq!(*persistent_instances.get_or_insert(instances), instances);
+        }
+        assert_eq!(
+            render_data.get_job_count(),
+            1 + JOB_LIFETIME_FRAMES as usize
+        );
+
+        // Everything but the persistent job expires once the changing sprites stop
+        for _ in 0..JOB_LIFETIME_FRAMES {
+            persistent(&mut render_data);
+            render_data.build_draw_data(ALL_RENDER_LAYERS);
+        }
+        assert_eq!(render_data.get_job_count(), 1);
+
+        render_data.submit(&SpriteRenderJob::default(), &resource_pool);
+        render_data.reset();
+        assert_eq!(render_data.get_job_count(), 0);
+        let (_, stats) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert_eq!(stats, FrameStats::default());
+    }
+
+    fn batch_order(batches: &[RenderBatch]) -> Vec<(ResourceHandle, ResourceHandle)> {
+        batches
+            .iter()
+            .map(|b| (b.material_instance, b.mesh))
+            .collect()
+    }
+
+    #[test]
+    fn opaque_batches_sort_by_material_then_mesh() {
+        let resource_pool = ResourcePool::new();
+        let mut render_data = RenderData::new();
+
+        for (material, mesh) in [(2, 1), (1, 2), (2, 0), (1, 1)] {
+            render_data.submit(
+                &StaticRenderJob {
+                    material,
+                    mesh,
+                    ..Default::default()
+                },
+                &resource_pool,
+            );
+        }
+
+        let (draw_data, _) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert_eq!(
+            batch_order(&draw_data.static_batches),
+            vec![(1, 1), (1, 2), (2, 0), (2, 1)]
+        );
+    }
+
+    #[test]
+    fn sprite_batches_sort_by_layer_then_material() {
+        let resource_pool = ResourcePool::new();
+        let mut render_data = RenderData::new();
+
+        for (material, layer) in [(1, 2), (3, 0), (2, 1), (1, 0)] {
+            render_data.submit(
+                &SpriteRenderJob {
+                    material,
+                    layer,
+                    ..Default::default()
+                },
+                &resource_pool,
+            );
+        }
+
+        let (draw_data, _) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        let order: Vec<ResourceHandle> = draw_data
+            .sprite_batches
+            .iter()
+            .map(|b| b.material_instance)
+            .collect();
+        assert_eq!(order, vec![1, 3, 2, 1]);
+    }
+
+    #[test]
+    fn solid_quads_share_one_batch_per_layer() {
+        let resource_pool = ResourcePool::new();
+        let mut render_data = RenderData::new();
+
+        for i in 0..50 {
+            let position = Vec2::splat(i as f32);
+            render_data.submit(
+                &SpriteRenderJob::solid(position, Vec2::ONE, Vec4::ONE, 2),
+                &resource_pool,
+            );
+            render_data.submit(
+                &SpriteRenderJob {
+                    position,
+                    material: 7,
+                    layer: 2,
+                    ..Default::default()
+                },
+                &resource_pool,
+            );
+        }
+
+        let (draw_data, _) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert_eq!(draw_data.sprite_batches.len(), 2);
+        let solid = draw_data
+            .sprite_batches
+            .iter()
+            .find(|batch| batch.material_instance == Renderer::WHITE_SPRITE_MATERIAL)
+            .unwrap();
+        assert_eq!(solid.instance_range.len(), 50);
+
+        // Still in submission order within the batch
+        let first = &draw_data.sprite_instances[solid.instance_range.start as usize];
+        let last = &draw_data.sprite_instances[solid.instance_range.end as usize - 1];
+        assert_eq!(first.position, [0.0, 0.0]);
+        assert_eq!(last.position, [49.0, 49.0]);
+    }
+
+    #[test]
+    fn transparent_batches_sort_back_to_front() {
+        let mut jobs: JobMap<u32> = HashMap::new();
+        for (material, depth) in [(1, 100.0), (2, 900.0), (3, 500.0)] {
+            let key = BatchKey {
+                material,
+                mesh: 0,
+                lod: 0,
+                layer: get_depth_bucket(depth, 1000.0),
+                casts_shadow: false,
+                shadow_only: false,
+                weight_debug: false,
+                xray: false,
+                render_layers: ALL_RENDER_LAYERS,
+            };
+            jobs.entry(key).or_default().instances.push(0);
+        }
+
+        let (batches, _) =
+            RenderData::build_batches(&mut jobs, BatchCategory::Transparent, ALL_RENDER_LAYERS, 1);
+        assert_eq!(batch_order(&batches), vec![(2, 0), (3, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn lods_of_a_mesh_are_batched_apart() {
+        let mut jobs: JobMap<u32> = HashMap::new();
+        for lod in [1, 0, 1, 2] {
+            let key = BatchKey {
+                material: 1,
+                mesh: 10,
+                lod,
+                casts_shadow: true,
+                render_layers: ALL_RENDER_LAYERS,
+                ..Default::default()
+            };
+            jobs.entry(key).or_default().instances.push(lod as u32);
+        }
+
+        let (batches, instances) =
+            RenderData::build_batches(&mut jobs, BatchCategory::Opaque, ALL_RENDER_LAYERS, 1);
+        let lods: Vec<_> = batches.iter().map(|batch| batch.lod).collect();
+        assert_eq!(lods, vec![0, 1, 2]);
+        // Each batch only has the instances of its level
+        for batch in &batches {
+            for index in batch.instance_range.clone() {
+                assert_eq!(instances[index as usize], batch.lod as u32);
+            }
+        }
+    }
+
+    #[test]
+    fn shadow_batches_skip_non_casters() {
+        let resource_pool = ResourcePool::new();
+        let mut render_data = RenderData::new();
+
+        for (material, casts_shadow) in [(1, true), (2, false), (1, false), (3, true), (1, true)] {
+            render_data.submit(
+                &StaticRenderJob {
+                    material,
+                    mesh: 10,
+                    casts_shadow,
+                    ..Default::default()
+                },
+                &resource_pool,
+            );
+        }
+
+        let (draw_data, _) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert_eq!(draw_data.static_batches.len(), 4);
+        assert_eq!(
+            batch_order(&draw_data.shadow_static_batches),
+            vec![(1, 10), (3, 10)]
+        );
+
+        // Both sets index into the same instances, and every shadow range is also a scene range
+        let instance_count = draw_data.static_instances.len() as u32;
+        for batch in &draw_data.shadow_static_batches {
+            assert!(batch.instance_range.end <= instance_count);
+            assert!(
+                draw_data
+                    .static_batches
+                    .iter()
+                    .any(|b| b.instance_range == batch.instance_range)
+            );
+        }
+
+        let shadow_instances: u32 = draw_data
+            .shadow_static_batches
+            .iter()
+            .map(|b| b.instance_range.len() as u32)
+            .sum();
+        assert_eq!(shadow_instances, 3);
+        assert!(draw_data.shadow_skeletal_batches.is_empty());
+    }
+
+    #[test]
+    fn layer_masks_partition_the_instances() {
+        const GIZMO: u32 = 1 << 2;
+
+        let resource_pool = ResourcePool::new();
+        let mut render_data = RenderData::new();
+        let submit_frame = |render_data: &mut RenderData| {
+            for (mesh, render_layers) in [
+                (1, RENDER_LAYER_DEFAULT),
+                (2, RENDER_LAYER_MINIMAP),
+                (1, RENDER_LAYER_DEFAULT),
+                (3, RENDER_LAYER_DEFAULT | RENDER_LAYER_MINIMAP),
+                (4, GIZMO),
+            ] {
+                render_data.submit(
+                    &StaticRenderJob {
+                        material: 1,
+                        mesh,
+                        render_layers,
+                        ..Default::default()
+                    },
+                    &resource_pool,
+                );
+            }
+        };
+
+        let expected = [
+            (
+                ALL_RENDER_LAYERS & !RENDER_LAYER_MINIMAP,
+                vec![(1, 2), (3, 1), (4, 1)],
+            ),
+            (RENDER_LAYER_MINIMAP, vec![(2, 1), (3, 1)]),
+            (0, vec![]),
+        ];
+        for (layer_mask, meshes) in expected {
+            submit_frame(&mut render_data);
+            let (draw_data, stats) = render_data.build_draw_data(layer_mask);
+
+            let batches: Vec<_> = draw_data
+                .static_batches
+                .iter()
+                .map(|b| (b.mesh, b.instance_range.len()))
+                .collect();
+            assert_eq!(batches, meshes);
+            assert!(
+                draw_data
+                    .static_batches
+                    .iter()
+                    .all(|b| !b.instance_range.is_empty())
+            );
+
+            // Nothing left behind for the next frame
+            let count: usize = meshes.iter().map(|(_, count)| count).sum();
+            assert_eq!(stats.static_instance_count, count);
+        }
+    }
+
+    #[test]
+    fn shadow_proxies_only_draw_in_the_shadow_pass() {
+        let resource_pool = ResourcePool::new();
+        let mut render_data = RenderData::new();
+        render_data.set_camera_position(Vec3::ZERO);
+        render_data.set_shadow_proxy_distance(Some(100.0));
+
+        let job_at = |x: f32, shadow_proxy| SkeletalRenderJob {
+            transform: Mat4::from_translation(Vec3::new(x, 0.0, 0.0)),
+            material: 1,
+            mesh: 10,
+            shadow_proxy,
+            ..Default::default()
+        };
+        let capsule = ShadowProxy::Capsule {
+            radius: 30.0,
+            height: 180.0,
+        };
+
+        // Close to the camera or without a proxy the skinned mesh casts the shadow
+        assert!(!job_at(50.0, capsule).submit_shadow_proxy(&mut render_data));
+        assert!(!job_at(500.0, ShadowProxy::None).submit_shadow_proxy(&mut render_data));
+        assert!(job_at(500.0, capsule).submit_shadow_proxy(&mut render_data));
+        assert!(job_at(600.0, ShadowProxy::StaticMesh(20)).submit_shadow_proxy(&mut render_data));
+        render_data.submit(
+            &StaticRenderJob {
+                material: 1,
+                mesh: 30,
+                ..Default::default()
+            },
+            &resource_pool,
+        );
+
+        let (draw_data, _) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert_eq!(batch_order(&draw_data.static_batches), vec![(1, 30)]);
+        assert_eq!(
+            batch_order(&draw_data.shadow_static_batches),
+            vec![(1, 20), (1, 30), (1, Renderer::CAPSULE_MESH)]
+        );
+        assert!(
+            draw_data
+                .shadow_static_batches
+                .iter()
+                .all(|b| !b.instance_range.is_empty())
+        );
+
+        // The capsule stands on the job's origin, scaled to the proxy size
+        let capsule_batch = draw_data
+            .shadow_static_batches
+            .iter()
+            .find(|b| b.mesh == Renderer::CAPSULE_MESH)
+            .unwrap();
+        let instance = &draw_data.static_instances[capsule_batch.instance_range.start as usize];
+        let matrix = Mat4::from_cols_array(&instance.model_matrix);
+        let top = matrix.transform_point3(Vec3::new(0.5, 2.0, 0.0));
+        assert!(top.distance(Vec3::new(530.0, 180.0, 0.0)) < 1e-3);
+    }
+
+    #[test]
+    fn xray_batches_are_drawn_apart_and_cast_no_shadow() {
+        let mut render_data = RenderData::new();
+        for (material, xray) in [(1, false), (1, true), (2, false)] {
+            let key = BatchKey {
+                material,
+                mesh: 10,
+                casts_shadow: !xray,
+                xray,
+                render_layers: ALL_RENDER_LAYERS,
+                ..Default::default()
+            };
+            let job = render_data.skeletal_jobs.entry(key).or_default();
+            job.instances.push(bytemuck::Zeroable::zeroed());
+        }
+
+        let (draw_data, stats) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert_eq!(
+            batch_order(&draw_data.skeletal_batches),
+            vec![(1, 10), (2, 10)]
+        );
+        assert_eq!(batch_order(&draw_data.xray_batches), vec![(1, 10)]);
+        assert!(draw_data.xray_batches.iter().all(|b| b.xray));
+        assert_eq!(
+            batch_order(&draw_data.shadow_skeletal_batches),
+            vec![(1, 10), (2, 10)]
+        );
+        assert_eq!(stats.skeletal_batch_count, 3);
+    }
+
+    #[test]
+    fn persistent_chunks_are_culled_as_a_whole() {
+        // Looking down -z from the origin, the chunks are a row of boxes along x at z -100
+        let camera = Mat4::perspective_rh(f32::to_radians(60.0), 1.0, 1.0, 1000.0);
+        let chunk_at = |index: u32, x: f32| PersistentChunk {
+            instance_range: index * 10..index * 10 + 10,
+            min: Vec3::new(x - 5.0, -5.0, -105.0),
+            max: Vec3::new(x + 5.0, 5.0, -95.0),
+        };
+
+        let mut render_data = RenderData::new();
+        render_data.add_persistent_set(
+            100,
+            PersistentSet {
+                mesh: 10,
+                material: 1,
+                casts_shadow: true,
+                render_layers: RENDER_LAYER_DEFAULT,
+                chunks: vec![
+                    chunk_at(0, -300.0),
+                    chunk_at(1, -20.0),
+                    chunk_at(2, 0.0),
+                    chunk_at(3, 300.0),
+                    chunk_at(4, 20.0),
+                ],
+            },
+        );
+
+        // Without view projections nothing is culled
+        let (draw_data, stats) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert_eq!(draw_data.persistent_batches[0].batches.len(), 1);
+        assert_eq!(stats.persistent_instance_count, 50);
+
+        // The light sees everything, neighbouring chunks draw together
+        render_data.set_cull_view_projections(
+            camera,
+            Mat4::orthographic_rh(-500.0, 500.0, -500.0, 500.0, -500.0, 500.0),
+        );
+        let (draw_data, stats) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        let ranges = |batches: &[PersistentBatch]| -> Vec<Range<u32>> {
+            batches
+                .iter()
+                .flat_map(|persistent| &persistent.batches)
+                .map(|batch| batch.instance_range.clone())
+                .collect()
+        };
+        assert_eq!(ranges(&draw_data.persistent_batches), vec![10..30, 40..50]);
+        assert_eq!(ranges(&draw_data.shadow_persistent_batches), vec![0..50]);
+        assert_eq!(draw_data.persistent_batches[0].instances, 100);
+        assert_eq!(stats.persistent_batch_count, 2);
+        assert_eq!(stats.persistent_instance_count, 30);
+
+        // Sets stay until they are removed, other layers don't draw them
+        let (draw_data, _) = render_data.build_draw_data(RENDER_LAYER_MINIMAP);
+        assert!(draw_data.persistent_batches.is_empty());
+        render_data.reset();
+        let (draw_data, _) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert_eq!(draw_data.persistent_batches.len(), 1);
+        render_data.remove_persistent_set(100);
+        let (draw_data, _) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert!(draw_data.persistent_batches.is_empty());
+        assert!(draw_data.shadow_persistent_batches.is_empty());
+    }
+
+    #[test]
+    fn sprite_params_follow_their_instances_when_batches_are_sorted() {
+        let resource_pool = ResourcePool::new();
+        let mut render_data = RenderData::new();
+
+        // Higher layers first, so sorting moves the batches around. Every other sprite has
+        // params, their first value the same as the x of the sprite.
+        for i in 0..12u64 {
+            let mut params = None;
+            if i.is_multiple_of(2) {
+                params = Some([i as f32, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+            }
+            render_data.submit(
+                &SpriteRenderJob {
+                    position: Vec2::new(i as f32, 0.0),
+                    material: 1 + i % 3,
+                    layer: 3 - i as u32 % 4,
+                    params,
+                    ..Default::default()
+                },
+                &resource_pool,
+            );
+        }
+
+        let (draw_data, _) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert_eq!(draw_data.sprite_params.len(), 7);
+        assert_eq!(draw_data.sprite_params[0], NO_SPRITE_PARAMS);
+        let mut drawn = 0;
+        let mut last_layer = 0;
+        for batch in &draw_data.sprite_batches {
+            let range = batch.instance_range.start as usize..batch.instance_range.end as usize;
+            for instance in &draw_data.sprite_instances[range] {
+                assert!(instance.layer >= last_layer);
+                last_layer = instance.layer;
+
+                let x = instance.position[0];
+                if (x as u32).is_multiple_of(2) {
+                    let params = draw_data.sprite_params[instance.params_index as usize];
+                    assert_eq!(params[0], x);
+                } else {
+                    assert_eq!(instance.params_index, 0);
+                }
+                drawn += 1;
+            }
+        }
+        assert_eq!(drawn, 12);
+
+        // Every frame starts over
+        render_data.submit(&SpriteRenderJob::default().radial_fill(0.5), &resource_pool);
+        let (draw_data, _) = render_data.build_draw_data(ALL_RENDER_LAYERS);
+        assert_eq!(draw_data.sprite_params.len(), 2);
+        assert_eq!(draw_data.sprite_instances[0].params_index, 1);
+        assert_eq!(draw_data.sprite_params[1][0], 0.5);
     }
 }
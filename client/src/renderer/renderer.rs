@@ -1,46 +1,215 @@
-use shared::{math::*, transform::Transform};
+use anyhow::Context;
+use shared::{math::*, profile_scope, transform::Transform};
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
 use std::ops::Range;
 use std::sync::Arc;
 use wgpu::BufferUsages;
+use wgpu::util::DrawIndexedIndirectArgs;
 use winit::window::Window;
 
+#[cfg(feature = "runtime-font")]
+use crate::renderer::runtime_font::{AtlasChange, RuntimeFont};
 use crate::renderer::{
-    Buffer, BufferDesc, Glyph, MaterialInstanceDesc, MaterialPipeline, MaterialPipelineDesc,
-    MeshLoadDesc, PassTarget, RenderData, RenderDevice, Resource, ResourceHandle, ResourcePool,
-    SkeletalMeshVertex, SpriteInstanceData, StaticInstanceData, StaticMesh, StaticMeshVertex,
-    Texture, TextureDesc,
-    animation::{AnimationInstance, Pose},
-    render_data::SubmitJob,
-    resources::get_handle,
+    AaMode, AmbientLight, BoneInfo, Buffer, BufferDesc, BundleHandles, DebugLineRenderJob,
+    DebugLineVertex, DebugView, DeviceDiagnostics, DirectionalLight, Fog, FrameStats, FxaaSettings,
+    GizmoLineRenderJob, Glyph, InstancePath, MaterialInstance, MaterialInstanceDesc,
+    MaterialPipeline, MaterialPipelineDesc, MaterialVariantSet, MeshLoadDesc, PassTarget,
+    PixelRect, RenderData, RenderDevice, Resource, ResourceHandle, ResourceKind, ResourcePool,
+    SkeletalMeshVertex, SpriteInstanceData, SpriteParams, SpriteRegion, StaticInstanceData,
+    StaticMesh, StaticMeshVertex, Texture, TextureDesc, TextureUpload, Wind,
+    animation::{AnimationInstance, Pose, get_bone_debug_color},
+    antialiasing::FxaaUniformData,
+    batch_diagnostics::{DuplicateMaterials, MaterialFingerprint, find_duplicate_materials},
+    bundle,
+    culling::{
+        CULL_WORKGROUP_SIZE, CullUniformData, GpuCullCount, count_visible_spheres,
+        get_bounding_sphere, get_frustum_planes,
+    },
+    frame_graph::{FrameGraph, PassDesc, TextureKey, TransientPool},
+    gpu_timer::{GpuTimer, PassTiming},
+    material::{ComputePipeline, ComputePipelineDesc},
+    mesh::{get_capsule_geometry, get_grown_capacity, get_ring_geometry},
+    render_data::{
+        ALL_RENDER_LAYERS, PersistentSet, RENDER_LAYER_MINIMAP, SpriteRenderJob, SpriteSpace,
+        SubmitJob, get_instance_flags,
+    },
+    resource_scope::{ResourceScopes, ScopeHandle},
+    resources::{ResourceSource, get_handle},
+    safe_area::{FitPolicy, Insets, UiDpiMode, UiViewport},
+    shader_data::ShaderData,
+    shader_layout::wgsl_struct,
+    sprite_atlas::AtlasRegionsDesc,
+    world_grid::{WorldGridSettings, WorldGridUniformData},
 };
 
-#[repr(C)]
-#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct UniformBufferData {
-    view_matrix: Mat4Data,
-    projection_matrix: Mat4Data,
-    camera_position: Vec4Data,
-
-    light_matrix: Mat4Data,
-    light_direction: Vec4Data,
-    light_color: Vec4Data,
+// The textures of the frame graph in draw_frame
+const SHADOW_MAP: TextureKey = "ShadowMap";
+const SCENE_DEPTH: TextureKey = "SceneDepth";
+const SCENE_MSAA: TextureKey = "SceneMsaa";
+const SCENE: TextureKey = "Scene";
+const LDR: TextureKey = "Ldr";
+const SURFACE: TextureKey = "Surface";
+
+wgsl_struct! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    pub(crate) struct UniformBufferData {
+        view_matrix: Mat4Data => "mat4x4<f32>",
+        projection_matrix: Mat4Data => "mat4x4<f32>",
+        inverse_view_projection: Mat4Data => "mat4x4<f32>", // Rebuilds world positions from the depth
+        camera_position: Vec4Data => "vec4<f32>",
+
+        light_matrix: Mat4Data => "mat4x4<f32>",
+        light_direction: Vec4Data => "vec4<f32>", // w is 1.0 when shadows are enabled
+        light_color: Vec4Data => "vec4<f32>",
+
+        ambient_top: Vec4Data => "vec4<f32>", // w is the intensity
+        ambient_bottom: Vec4Data => "vec4<f32>",
+        fog_color: Vec4Data => "vec4<f32>", // w is 1.0 when fog is enabled
+        fog_range: Vec4Data => "vec4<f32>", // x the start and y the end
+        wind: Vec4Data => "vec4<f32>", // xy the direction on the ground, z the strength with the gusts and w the time
+    }
 }
 
-#[repr(C)]
-#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct SpriteUniformBufferData {
-    pub screen_size: Vec2Data,
-    pub ui_scale: f32,
-    _padding: f32,
+wgsl_struct! {
+    #[repr(C)]
+    #[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+    pub(crate) struct SpriteUniformBufferData {
+        pub screen_size: Vec2Data => "vec2<f32>",
+        pub ui_scale: Vec2Data => "vec2<f32>", // Per axis, the fit times the DPI scale
+        pub safe_rect: Vec4Data => "vec4<f32>", // In pixels, xy the top left and zw the size
+    }
 }
 
+#[derive(Clone, Debug)]
 pub struct RenderBatch {
     pub material_instance: ResourceHandle,
     pub mesh: ResourceHandle,
-    pub layer: u32,
+    pub lod: u8,       // Detail level of the mesh
+    pub sort_key: u64, // See BatchKey::sort_key for the layout per category
+    pub casts_shadow: bool,
+    pub shadow_only: bool,  // Only drawn in the shadow pass
+    pub weight_debug: bool, // Drawn with the bone weight debug pipeline
+    pub xray: bool,         // Drawn with the x-ray pipeline where the mesh is hidden
     pub instance_range: Range<u32>,
 }
 
+// Batches of instances in a persistent buffer, drawn with its bind group instead of the
+// per-frame instance buffer
+#[derive(Clone, Debug)]
+pub struct PersistentBatch {
+    pub instances: ResourceHandle,
+    pub batches: Vec<RenderBatch>,
+}
+
+// A frame copied back from the GPU, see Renderer::request_screenshot
+pub struct Screenshot {
+    pub size: UVec2,
+    pub pixels: Vec<u8>, // RGBA, the top row first
+}
+
+// Static instances uploaded once, see Renderer::create_persistent_instances
+struct PersistentInstances {
+    instances: Vec<StaticInstanceData>, // Uploaded again when the device is recreated
+    spheres: Vec<Vec4>, // Bounds of the instances, empty when the mesh had no geometry
+    buffer: Buffer,
+    scene_bind_group: wgpu::BindGroup,
+    shadow_bind_group: wgpu::BindGroup,
+    gpu_culled: Option<GpuCulledInstances>, // When the device can cull them
+}
+
+// What the cull pass reads and writes for one set of persistent instances, see culling.rs
+struct GpuCulledInstances {
+    _sphere_buffer: Buffer,
+    _culled_buffer: Buffer,
+    draw_args: Buffer,
+    cull_bind_group: wgpu::BindGroup,
+    scene_bind_group: wgpu::BindGroup, // With the culled instances in place of all of them
+}
+
+struct CullPipeline {
+    bind_group_layout: wgpu::BindGroupLayout,
+    compute_pipeline: ComputePipeline,
+    uniform_buffer: Buffer,
+}
+
+// The instance counts the cull pass wrote, copied out to compare them with the CPU
+struct CullReadback {
+    buffer: wgpu::Buffer,
+    counts: Vec<GpuCullCount>, // Have the CPU counts, the GPU ones are read into them
+}
+
+// The texture or font a material was created from
+#[derive(Clone, Copy)]
+enum MaterialSource {
+    Scene(ResourceHandle),
+    Additive(ResourceHandle),
+    Sprite(ResourceHandle),
+    Font(ResourceHandle),
+    // A scene material of an array texture, the render jobs pick the layer
+    Variant(MaterialVariantSet),
+    Swaying(ResourceHandle), // A scene material moved by the wind
+}
+
+impl MaterialSource {
+    fn get_pipeline_name(self, premultiplied_alpha: bool) -> &'static str {
+        match self {
+            Self::Scene(_) | Self::Variant(_) => "scene",
+            // The same pipeline, but its instances can't be drawn with the other materials
+            Self::Swaying(_) => "swaying scene",
+            Self::Additive(_) => "additive",
+            Self::Sprite(_) | Self::Font(_) if premultiplied_alpha => "premultiplied sprite",
+            Self::Sprite(_) | Self::Font(_) => "sprite",
+        }
+    }
+
+    fn get_dependency(self) -> ResourceHandle {
+        match self {
+            Self::Scene(handle)
+            | Self::Additive(handle)
+            | Self::Sprite(handle)
+            | Self::Font(handle)
+            | Self::Swaying(handle) => handle,
+            Self::Variant(variant_set) => variant_set.texture,
+        }
+    }
+}
+
+// Why a frame could not be rendered. The renderer keeps working after the first two, the
+// frame is just lost. After a lost device it has to be recreated, see Renderer::recreate.
+#[derive(Debug)]
+pub enum RendererError {
+    Surface(wgpu::SurfaceError),
+    Validation(String), // wgpu errors nobody caught, the frame may be incomplete
+    DeviceLost,
+}
+
+impl std::fmt::Display for RendererError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Surface(error) => write!(f, "Surface error: {}", error),
+            Self::Validation(error) => write!(f, "GPU validation error: {}", error),
+            Self::DeviceLost => write!(f, "GPU device lost"),
+        }
+    }
+}
+
+impl std::error::Error for RendererError {}
+
+impl From<wgpu::SurfaceError> for RendererError {
+    fn from(error: wgpu::SurfaceError) -> Self {
+        Self::Surface(error)
+    }
+}
+
+// A batch of the last frame as seen by debug tools, with the pass it was drawn in
+#[derive(Clone, Debug)]
+pub struct BatchInfo {
+    pub pass: &'static str,
+    pub batch: RenderBatch,
+}
+
 // Generated before each draw
 pub struct DrawData {
     pub static_batches: Vec<RenderBatch>,
@@ -48,10 +217,59 @@ pub struct DrawData {
 
     pub skeletal_batches: Vec<RenderBatch>,
     pub skeletal_instances: Vec<StaticInstanceData>,
+    pub weight_debug_batches: Vec<RenderBatch>, // Skeletal, instead of their scene batches
+    pub xray_batches: Vec<RenderBatch>,         // Skeletal, besides their scene batches
     pub bones: Vec<Mat4Data>,
 
+    // Static batches of additive materials, taken out of the static batches
+    pub additive_batches: Vec<RenderBatch>,
+
+    // Index into the same instance buffers as the scene batches above
+    pub shadow_static_batches: Vec<RenderBatch>,
+    pub shadow_skeletal_batches: Vec<RenderBatch>,
+
+    // Static, in the buffers of their persistent instances
+    pub persistent_batches: Vec<PersistentBatch>,
+    pub shadow_persistent_batches: Vec<PersistentBatch>,
+
     pub sprite_batches: Vec<RenderBatch>,
     pub sprite_instances: Vec<SpriteInstanceData>,
+    pub sprite_params: Vec<SpriteParams>, // By the params_index of the sprite instances
+
+    pub debug_line_vertices: Vec<DebugLineVertex>,
+    pub gizmo_line_vertices: Vec<DebugLineVertex>, // Drawn last, over everything
+}
+
+impl DrawData {
+    // Replaces the list with every batch in the order the passes draw them
+    pub(crate) fn list_batches(&self, list: &mut Vec<BatchInfo>) {
+        let get_persistent = |persistent: &[PersistentBatch]| -> Vec<RenderBatch> {
+            persistent
+                .iter()
+                .flat_map(|persistent| persistent.batches.iter().cloned())
+                .collect()
+        };
+        let passes = [
+            ("Shadow static", self.shadow_static_batches.clone()),
+            ("Shadow skeletal", self.shadow_skeletal_batches.clone()),
+            (
+                "Shadow persistent",
+                get_persistent(&self.shadow_persistent_batches),
+            ),
+            ("Static", self.static_batches.clone()),
+            ("Persistent", get_persistent(&self.persistent_batches)),
+            ("Skeletal", self.skeletal_batches.clone()),
+            ("Weight debug", self.weight_debug_batches.clone()),
+            ("X-ray", self.xray_batches.clone()),
+            ("Additive", self.additive_batches.clone()),
+            ("Sprite", self.sprite_batches.clone()),
+        ];
+
+        list.clear();
+        for (pass, batches) in passes {
+            list.extend(batches.into_iter().map(|batch| BatchInfo { pass, batch }));
+        }
+    }
 }
 
 // A short-term abstraction
@@ -65,6 +283,31 @@ pub struct BindCollection {
     bind_group_layout: wgpu::BindGroupLayout,
 }
 
+// What the per-frame buffers hold, less on the WebGL2 path where the bones are a texture
+#[derive(Debug, Clone, Copy)]
+struct InstanceCaps {
+    static_instances: usize, // And as many skeletal ones
+    bones: usize,
+    sprite_instances: usize,
+}
+
+impl InstanceCaps {
+    fn new(path: InstancePath) -> Self {
+        match path {
+            InstancePath::StorageBuffers => Self {
+                static_instances: 512,
+                bones: 512 * 64,
+                sprite_instances: 2046,
+            },
+            InstancePath::VertexBuffers => Self {
+                static_instances: 256,
+                bones: 256 * 64,
+                sprite_instances: 1024,
+            },
+        }
+    }
+}
+
 pub struct BindEntry<'a> {
     binding: u32,
     visibility: wgpu::ShaderStages,
@@ -111,6 +354,28 @@ impl RenderDevice {
             bind_group_layout,
         }
     }
+
+    // The same layout with other resources, e.g. a buffer that grew, so the pipelines made
+    // with the layout stay usable
+    pub fn rebind_collection<'a>(
+        &self,
+        collection: &mut BindCollection,
+        entries: Vec<BindEntry<'a>>,
+    ) {
+        let group_entries: Vec<wgpu::BindGroupEntry> = entries
+            .into_iter()
+            .map(|e| wgpu::BindGroupEntry {
+                binding: e.binding,
+                resource: e.resource,
+            })
+            .collect();
+
+        collection.bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &collection.bind_group_layout,
+            entries: &group_entries,
+        });
+    }
 }
 
 pub struct Renderer {
@@ -121,19 +386,31 @@ pub struct Renderer {
 
     _depth_sampler: wgpu::Sampler,
     default_sampler: wgpu::Sampler,
+    screen_sampler: wgpu::Sampler, // Clamped, for reading render targets across the screen
 
     shadow_map: Texture,
+    shadow_map_size: u32, // Width and height, it is square
     depth_buffer: Texture,
     scene_texture: Texture,
+    scene_msaa_texture: Option<Texture>,
+    ldr_texture: Texture,
+    transient_pool: RefCell<TransientPool>, // For the frame graph, see draw_frame
+    gpu_timer: RefCell<Option<GpuTimer>>,   // None without timestamp queries
 
     static_shadow_bind_collection: BindCollection,
     skeletal_shadow_bind_collection: BindCollection,
     shadow_material_pipeline: MaterialGroup,
     sprite_material_pipeline: MaterialPipeline,
+    premultiplied_sprite_material_pipeline: MaterialPipeline,
 
     static_scene_bind_collection: BindCollection,
     skeletal_scene_bind_collection: BindCollection,
     scene_material_pipeline: MaterialGroup,
+    debug_view: DebugView,
+    debug_view_material_pipeline: Option<MaterialGroup>, // Drawn instead of the scene pipelines
+    weight_debug_material_pipeline: MaterialPipeline,
+    xray_material_pipeline: MaterialPipeline,
+    additive_material_pipeline: MaterialPipeline,
     sprite_bind_collection: BindCollection,
 
     composite_bind_collection: BindCollection,
@@ -141,29 +418,91 @@ pub struct Renderer {
 
     camera_projection_matrix: Mat4,
     camera_transform: Transform,
+    camera_override: Option<Transform>, // Drawn from instead of the camera the game sets
+    fov_override: Option<f32>,          // Vertical, in radians, replaces the one of the projection
     uniform_data: UniformBufferData,
     sprite_uniform_data: SpriteUniformBufferData,
 
     uniform_buffer: Buffer,
     static_instance_buffer: Buffer,
     skeletal_instance_buffer: Buffer,
-    bone_buffer: Buffer,
+    bone_buffer: ShaderData,
     sprite_uniform_buffer: Buffer,
     sprite_instance_buffer: Buffer,
+    sprite_params_buffer: ShaderData,
+    sprite_params_capacity: usize, // Blocks, grows with the params of a frame
+    instance_caps: InstanceCaps,
+
+    debug_line_buffer: Buffer,
+    debug_line_bind_collection: BindCollection,
+    debug_line_material_pipeline: MaterialPipeline,
+    gizmo_line_buffer: Buffer,
+    gizmo_material_pipeline: MaterialPipeline, // The debug line shader, over everything
+
+    fxaa_uniform_buffer: Buffer,
+    fxaa_bind_collection: BindCollection,
+    fxaa_material_pipeline: MaterialPipeline,
+    aa_mode: AaMode,
+    fxaa_settings: FxaaSettings,
+    world_grid_uniform_buffer: Buffer,
+    world_grid_bind_collection: BindCollection,
+    world_grid_material_pipeline: MaterialPipeline,
+    world_grid: Option<WorldGridSettings>, // None hides it
+    render_scale: f32, // Of the scene targets to the window, the UI is always drawn at full size
+
+    directional_light: DirectionalLight,
+    ambient_light: AmbientLight,
+    fog: Option<Fog>,
+    wind: Wind,
+    wind_time: f32, // Advanced with the game, the sway stops while it is paused
+    light_debug_enabled: bool,
+    ui_viewport: UiViewport,
+    ui_layout_changed: bool, // Since the game last asked
+    safe_area_debug_enabled: bool,
+    xray_enabled: bool,
+    letterbox: bool,
 
     render_data: RenderData,
+    sprite_atlas_sizes: HashMap<ResourceHandle, UVec2>,
+    material_sources: HashMap<ResourceHandle, MaterialSource>, // By material
+    resource_scopes: ResourceScopes,
+    released_resources: Vec<(u64, Resource)>, // With the last frame that may still use them
+    persistent_instances: HashMap<ResourceHandle, PersistentInstances>,
+    cull_pipeline: Option<CullPipeline>, // None when the device can't cull on the GPU
+    gpu_culling: bool,                   // Of the persistent instances, when the device can
+    gpu_culling_validation: bool,        // Reads the GPU counts back every frame
+    cull_planes: [Vec4; 6],              // Of the camera
+    gpu_cull_counts: Vec<GpuCullCount>,  // Of the last validated frame
+    frame_stats: FrameStats,
+    captured_batches: Option<Vec<BatchInfo>>, // Only kept while a debug tool asks for them
+    budget_warnings: u32, // Bit per budget, set once its warning has been logged
+    presented_frame_count: u64,
+    suspended: bool, // The window has no area, e.g. minimized, nothing is rendered
+    layer_mask: u32, // Render layers the main camera draws
+    low_latency: bool,
+    pending_cpu_wait: f32, // Waited in wait_for_gpu, counted with the next frame
+    cpu_wait: f32,         // Seconds the last frame blocked on the GPU
+    screenshot_requested: bool,
+    screenshot: Option<Screenshot>, // Of the last frame that was requested, until taken
 }
 
 impl Renderer {
-    const SHADOW_MAP_WIDTH: u32 = 2048;
-    const SHADOW_MAP_HEIGHT: u32 = 2048;
+    pub const DEFAULT_SHADOW_MAP_SIZE: u32 = 2048;
+    pub const MIN_SHADOW_MAP_SIZE: u32 = 256;
+
+    const SPRITE_PARAMS_COUNT: usize = 256; // To start with
+    const DEBUG_LINE_COUNT: usize = 4096;
+    const GIZMO_LINE_COUNT: usize = 1024;
 
-    const STATIC_INSTANCE_COUNT: usize = 512;
-    const BONE_COUNT: usize = Self::STATIC_INSTANCE_COUNT * 64;
-    const SRPITE_INSTANCE_COUNT: usize = 2046;
+    const BUDGET_WARNING_THRESHOLD: f32 = 0.8;
 
+    pub const MIN_RENDER_SCALE: f32 = 0.25;
+
+    // Until the game sets another one with set_ui_reference
     pub const SPRITE_SCREEN_REFERENCE: Vec2 = Vec2::new(1920.0, 1080.0);
     pub const QUAD_MESH: ResourceHandle = get_handle("quad");
+    pub const CAPSULE_MESH: ResourceHandle = get_handle("capsule");
+    pub const RING_MESH: ResourceHandle = get_handle("ring");
     pub const WHITE_SPRITE_MATERIAL: ResourceHandle = get_handle("white_sprite_material");
 
     fn create_default_resources(
@@ -206,22 +545,63 @@ impl Renderer {
         );
     }
 
-    fn create_depth_buffer(render_device: &RenderDevice) -> Texture {
+    fn create_depth_buffer(
+        render_device: &RenderDevice,
+        size: UVec2,
+        sample_count: u32,
+    ) -> Texture {
         render_device.create_texture(&TextureDesc {
-            width: render_device.config.width.max(1),
-            height: render_device.config.height.max(1),
+            width: size.x,
+            height: size.y,
             layer_count: 1,
+            sample_count,
             format: Some(wgpu::TextureFormat::Depth32Float),
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_dimension: wgpu::TextureViewDimension::D2,
             ..Default::default()
         })
     }
 
-    fn create_scene_texture(render_device: &RenderDevice) -> Texture {
+    // The multisampled scene target, it is resolved into the scene texture at the end of the pass
+    fn create_scene_msaa_texture(
+        render_device: &RenderDevice,
+        size: UVec2,
+        sample_count: u32,
+    ) -> Option<Texture> {
+        if sample_count <= 1 {
+            return None;
+        }
+
+        Some(render_device.create_texture(&TextureDesc {
+            width: size.x,
+            height: size.y,
+            layer_count: 1,
+            sample_count,
+            format: Some(wgpu::TextureFormat::Rgba16Float),
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            ..Default::default()
+        }))
+    }
+
+    // The composited image before UI, only rendered to when FXAA is enabled
+    fn create_ldr_texture(render_device: &RenderDevice) -> Texture {
         render_device.create_texture(&TextureDesc {
             width: render_device.config.width.max(1),
             height: render_device.config.height.max(1),
             layer_count: 1,
+            format: Some(render_device.config.format),
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            ..Default::default()
+        })
+    }
+
+    fn create_scene_texture(render_device: &RenderDevice, size: UVec2) -> Texture {
+        render_device.create_texture(&TextureDesc {
+            width: size.x,
+            height: size.y,
+            layer_count: 1,
             format: Some(wgpu::TextureFormat::Rgba16Float),
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             view_dimension: wgpu::TextureViewDimension::D2,
@@ -229,7 +609,9 @@ impl Renderer {
         })
     }
 
-    fn create_samplers(render_device: &RenderDevice) -> (wgpu::Sampler, wgpu::Sampler) {
+    fn create_samplers(
+        render_device: &RenderDevice,
+    ) -> (wgpu::Sampler, wgpu::Sampler, wgpu::Sampler) {
         let default_sampler = render_device
             .device
             .create_sampler(&wgpu::SamplerDescriptor {
@@ -257,13 +639,25 @@ impl Renderer {
                 ..Default::default()
             });
 
-        (default_sampler, depth_sampler)
+        // Bilinear, so a scene rendered smaller is upscaled smoothly
+        let screen_sampler = render_device
+            .device
+            .create_sampler(&wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            });
+
+        (default_sampler, depth_sampler, screen_sampler)
     }
 
-    fn create_shadow_map(render_device: &RenderDevice) -> Texture {
+    fn create_shadow_map(render_device: &RenderDevice, size: u32) -> Texture {
         render_device.create_texture(&TextureDesc {
-            width: Self::SHADOW_MAP_WIDTH,
-            height: Self::SHADOW_MAP_HEIGHT,
+            width: size,
+            height: size,
             layer_count: 1,
             format: Some(wgpu::TextureFormat::Depth32Float),
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
@@ -273,23 +667,27 @@ impl Renderer {
         })
     }
 
-    fn create_meshes(render_device: &RenderDevice) -> (StaticMesh, StaticMesh) {
+    fn create_meshes(
+        render_device: &RenderDevice,
+    ) -> (StaticMesh, StaticMesh, StaticMesh, StaticMesh) {
+        // The screen is the 0 to 1 part of the UVs, targets read with a clamped sampler
+        // depend on it
         let screen_vertices: [StaticMeshVertex; 3] = [
             StaticMeshVertex {
                 position: [-1.0, -1.0, 0.0],
-                uvs: [0.0, 2.0, 0.0],
+                uvs: [0.0, 1.0, 0.0],
                 color: [1.0, 1.0, 1.0, 1.0],
                 normal: [0.0, 0.0, 1.0],
             },
             StaticMeshVertex {
                 position: [3.0, -1.0, 0.0],
-                uvs: [2.0, 2.0, 0.0],
+                uvs: [2.0, 1.0, 0.0],
                 color: [1.0, 1.0, 1.0, 1.0],
                 normal: [0.0, 0.0, 1.0],
             },
             StaticMeshVertex {
                 position: [-1.0, 3.0, 0.0],
-                uvs: [0.0, 0.0, 0.0],
+                uvs: [0.0, -1.0, 0.0],
                 color: [1.0, 1.0, 1.0, 1.0],
                 normal: [0.0, 0.0, 1.0],
             },
@@ -342,30 +740,44 @@ impl Renderer {
             })
             .expect("Could not create quad mesh");
 
-        (screen_mesh, quad_mesh)
+        let (capsule_vertices, capsule_indices) = get_capsule_geometry(16, 4);
+        let capsule_mesh = render_device
+            .create_mesh(&MeshLoadDesc {
+                vertex_data: bytemuck::cast_slice(capsule_vertices.as_slice()).to_vec(),
+                indices: capsule_indices,
+                ..Default::default()
+            })
+            .expect("Could not create capsule mesh");
+
+        let (ring_vertices, ring_indices) = get_ring_geometry(32, 0.85);
+        let ring_mesh = render_device
+            .create_mesh(&MeshLoadDesc {
+                vertex_data: bytemuck::cast_slice(ring_vertices.as_slice()).to_vec(),
+                indices: ring_indices,
+                ..Default::default()
+            })
+            .expect("Could not create ring mesh");
+
+        (screen_mesh, quad_mesh, capsule_mesh, ring_mesh)
     }
 
-    fn create_storage_buffers(render_device: &RenderDevice) -> (Buffer, Buffer, Buffer, Buffer) {
-        let size = Self::STATIC_INSTANCE_COUNT * std::mem::size_of::<StaticInstanceData>();
+    fn create_storage_buffers(
+        render_device: &RenderDevice,
+        caps: InstanceCaps,
+    ) -> (Buffer, Buffer, ShaderData, Buffer) {
+        let size = caps.static_instances * std::mem::size_of::<StaticInstanceData>();
+        let usage = Self::get_instance_usage(render_device);
 
-        let static_instance_buffer = render_device.create_buffer(&BufferDesc {
-            size,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
-        });
+        let static_instance_buffer = render_device.create_buffer(&BufferDesc { size, usage });
 
-        let skeletal_instance_buffer = render_device.create_buffer(&BufferDesc {
-            size,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
-        });
+        let skeletal_instance_buffer = render_device.create_buffer(&BufferDesc { size, usage });
 
-        let bone_buffer = render_device.create_buffer(&BufferDesc {
-            size: Self::BONE_COUNT * std::mem::size_of::<Mat4Data>(),
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
-        });
+        let bone_buffer =
+            render_device.create_shader_data(caps.bones * std::mem::size_of::<Mat4Data>());
 
         let sprite_instance_buffer = render_device.create_buffer(&BufferDesc {
-            size: Self::SRPITE_INSTANCE_COUNT * std::mem::size_of::<SpriteInstanceData>(),
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            size: caps.sprite_instances * std::mem::size_of::<SpriteInstanceData>(),
+            usage,
         });
 
         (
@@ -376,7 +788,46 @@ impl Renderer {
         )
     }
 
-    fn create_uniform_buffers(render_device: &RenderDevice) -> (Buffer, Buffer) {
+    // The instances are a vertex buffer where the vertex shaders can't read storage buffers
+    fn get_instance_usage(render_device: &RenderDevice) -> BufferUsages {
+        match render_device.get_instance_path() {
+            InstancePath::StorageBuffers => BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            InstancePath::VertexBuffers => BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        }
+    }
+
+    // None where render_batches sets the instances as a vertex buffer instead
+    fn get_instance_bind_entry<'a>(
+        render_device: &RenderDevice,
+        visibility: wgpu::ShaderStages,
+        instance_buffer: &'a Buffer,
+    ) -> Option<BindEntry<'a>> {
+        (render_device.get_instance_path() == InstancePath::StorageBuffers).then(|| BindEntry {
+            binding: 1,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            resource: instance_buffer.buffer.as_entire_binding(),
+        })
+    }
+
+    // Like get_instance_bind_entry, for bind groups of the layouts made with it
+    fn get_instance_group_entry<'a>(
+        &self,
+        instance_buffer: &'a Buffer,
+    ) -> Option<wgpu::BindGroupEntry<'a>> {
+        (self.render_device.get_instance_path() == InstancePath::StorageBuffers).then(|| {
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: instance_buffer.buffer.as_entire_binding(),
+            }
+        })
+    }
+
+    fn create_uniform_buffers(render_device: &RenderDevice) -> (Buffer, Buffer, Buffer) {
         (
             render_device.create_buffer(&BufferDesc {
                 size: std::mem::size_of::<UniformBufferData>(),
@@ -386,6 +837,10 @@ impl Renderer {
                 size: std::mem::size_of::<SpriteUniformBufferData>(),
                 usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
             }),
+            render_device.create_buffer(&BufferDesc {
+                size: std::mem::size_of::<FxaaUniformData>(),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            }),
         )
     }
 
@@ -396,105 +851,298 @@ impl Renderer {
         depth_sampler: &wgpu::Sampler,
         static_instance_buffer: &Buffer,
         skeletal_instance_buffer: &Buffer,
-        bone_buffer: &Buffer,
+        bone_buffer: &ShaderData,
     ) -> (
         BindCollection,
         BindCollection,
         BindCollection,
         BindCollection,
     ) {
-        let static_scene = render_device.create_bind_collection(vec![
-            BindEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                resource: uniform_buffer.buffer.as_entire_binding(),
+        let uniform_entry = || BindEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
             },
-            BindEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+            resource: uniform_buffer.buffer.as_entire_binding(),
+        };
+        let shadow_entries = || {
+            [
+                BindEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    resource: wgpu::BindingResource::TextureView(&shadow_map.view),
+                },
+                BindEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    resource: wgpu::BindingResource::Sampler(depth_sampler),
                 },
-                resource: static_instance_buffer.buffer.as_entire_binding(),
+            ]
+        };
+        let bone_entry = |binding| BindEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: bone_buffer.get_binding_type(),
+            resource: bone_buffer.get_resource(),
+        };
+        let instance_entry = |instance_buffer| {
+            Self::get_instance_bind_entry(
+                render_device,
+                wgpu::ShaderStages::VERTEX,
+                instance_buffer,
+            )
+        };
+
+        let static_scene = render_device.create_bind_collection(
+            std::iter::once(uniform_entry())
+                .chain(instance_entry(static_instance_buffer))
+                .chain(shadow_entries())
+                .collect(),
+        );
+
+        let skeletal_scene = render_device.create_bind_collection(
+            std::iter::once(uniform_entry())
+                .chain(instance_entry(skeletal_instance_buffer))
+                .chain(shadow_entries())
+                .chain([bone_entry(4)])
+                .collect(),
+        );
+
+        let static_shadow = render_device.create_bind_collection(
+            std::iter::once(uniform_entry())
+                .chain(instance_entry(static_instance_buffer))
+                .collect(),
+        );
+
+        let skeletal_shadow = render_device.create_bind_collection(
+            std::iter::once(uniform_entry())
+                .chain(instance_entry(skeletal_instance_buffer))
+                .chain([bone_entry(2)])
+                .collect(),
+        );
+
+        return (static_scene, skeletal_scene, static_shadow, skeletal_shadow);
+    }
+
+    fn create_sprite_params_buffer(render_device: &RenderDevice, capacity: usize) -> ShaderData {
+        render_device.create_shader_data(capacity * std::mem::size_of::<SpriteParams>())
+    }
+
+    fn get_sprite_bind_entries<'a>(
+        render_device: &RenderDevice,
+        uniform_buffer: &'a Buffer,
+        instance_buffer: &'a Buffer,
+        params_buffer: &'a ShaderData,
+    ) -> Vec<BindEntry<'a>> {
+        let uniform_entry = BindEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            resource: uniform_buffer.buffer.as_entire_binding(),
+        };
+        let params_entry = BindEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: params_buffer.get_binding_type(),
+            resource: params_buffer.get_resource(),
+        };
+        std::iter::once(uniform_entry)
+            .chain(Self::get_instance_bind_entry(
+                render_device,
+                wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                instance_buffer,
+            ))
+            .chain([params_entry])
+            .collect()
+    }
+
+    fn create_sprite_pipeline(
+        render_device: &RenderDevice,
+        uniform_buffer: &Buffer,
+        instance_buffer: &Buffer,
+        params_buffer: &ShaderData,
+    ) -> (BindCollection, MaterialPipeline, MaterialPipeline) {
+        let bind_collection = render_device.create_bind_collection(Self::get_sprite_bind_entries(
+            render_device,
+            uniform_buffer,
+            instance_buffer,
+            params_buffer,
+        ));
+
+        let sprite_shader = render_device.create_shader("sprite.wgsl", &[]);
+
+        // Textures made with --premultiply get the second one, see Texture::premultiplied_alpha
+        let create_pipeline = |premultiplied_alpha| {
+            render_device.create_material_pipeline(&MaterialPipelineDesc {
+                vertex_shader: &sprite_shader,
+                fragment_shader: Some(&sprite_shader),
+                bind_group_layouts: &[&bind_collection.bind_group_layout],
+                layout_entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                vertex_layout: &StaticMeshVertex::desc(),
+                instance_layout: render_device.get_instance_layout(SpriteInstanceData::desc()),
+                push_contant_ranges: &[],
+                pass_target: PassTarget::Composite,
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                sample_count: 1,
+                premultiplied_alpha,
+            })
+        };
+
+        let (material_pipeline, premultiplied_material_pipeline) =
+            (create_pipeline(false), create_pipeline(true));
+
+        (
+            bind_collection,
+            material_pipeline,
+            premultiplied_material_pipeline,
+        )
+    }
+
+    fn create_debug_line_resources(
+        render_device: &RenderDevice,
+        uniform_buffer: &Buffer,
+    ) -> (Buffer, BindCollection) {
+        let line_buffer = render_device.create_buffer(&BufferDesc {
+            size: Self::DEBUG_LINE_COUNT * 2 * std::mem::size_of::<DebugLineVertex>(),
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        });
+
+        let bind_collection = render_device.create_bind_collection(vec![BindEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
             },
+            resource: uniform_buffer.buffer.as_entire_binding(),
+        }]);
+
+        (line_buffer, bind_collection)
+    }
+
+    fn create_debug_line_pipeline(
+        render_device: &RenderDevice,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        pass_target: PassTarget,
+        sample_count: u32,
+    ) -> MaterialPipeline {
+        let debug_line_shader = render_device.create_shader("debug_line.wgsl", &[]);
+
+        render_device.create_material_pipeline(&MaterialPipelineDesc {
+            vertex_shader: &debug_line_shader,
+            fragment_shader: Some(&debug_line_shader),
+            bind_group_layouts: &[bind_group_layout],
+            layout_entries: &[],
+            vertex_layout: &DebugLineVertex::desc(),
+            instance_layout: None,
+            push_contant_ranges: &[],
+            pass_target,
+            topology: wgpu::PrimitiveTopology::LineList,
+            sample_count,
+            premultiplied_alpha: false,
+        })
+    }
+
+    fn create_composite_pipeline(
+        render_device: &RenderDevice,
+        scene_texture: &Texture,
+        sampler: &wgpu::Sampler,
+    ) -> (BindCollection, MaterialPipeline) {
+        let bind_collection = render_device.create_bind_collection(vec![
             BindEntry {
-                binding: 2,
+                binding: 0,
                 visibility: wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Texture {
                     multisampled: false,
                     view_dimension: wgpu::TextureViewDimension::D2,
-                    sample_type: wgpu::TextureSampleType::Depth,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
                 },
-                resource: wgpu::BindingResource::TextureView(&shadow_map.view),
+                resource: wgpu::BindingResource::TextureView(&scene_texture.view),
             },
             BindEntry {
-                binding: 3,
+                binding: 1,
                 visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
-                resource: wgpu::BindingResource::Sampler(depth_sampler),
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                resource: wgpu::BindingResource::Sampler(sampler),
             },
         ]);
 
-        let skeletal_scene = render_device.create_bind_collection(vec![
+        let composite_shader = render_device.create_shader("composite.wgsl", &[]);
+
+        let material_pipeline = render_device.create_material_pipeline(&MaterialPipelineDesc {
+            vertex_shader: &composite_shader,
+            fragment_shader: Some(&composite_shader),
+            bind_group_layouts: &[&bind_collection.bind_group_layout],
+            layout_entries: &[],
+            vertex_layout: &StaticMeshVertex::desc(),
+            instance_layout: None,
+            push_contant_ranges: &[],
+            pass_target: PassTarget::Composite,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            sample_count: 1,
+            premultiplied_alpha: false,
+        });
+
+        (bind_collection, material_pipeline)
+    }
+
+    fn create_fxaa_pipeline(
+        render_device: &RenderDevice,
+        ldr_texture: &Texture,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &Buffer,
+    ) -> (BindCollection, MaterialPipeline) {
+        let bind_collection = render_device.create_bind_collection(vec![
             BindEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
                 },
-                resource: uniform_buffer.buffer.as_entire_binding(),
+                resource: wgpu::BindingResource::TextureView(&ldr_texture.view),
             },
             BindEntry {
                 binding: 1,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                resource: skeletal_instance_buffer.buffer.as_entire_binding(),
-            },
-            BindEntry {
-                binding: 2,
                 visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Texture {
-                    multisampled: false,
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    sample_type: wgpu::TextureSampleType::Depth,
-                },
-                resource: wgpu::BindingResource::TextureView(&shadow_map.view),
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                resource: wgpu::BindingResource::Sampler(sampler),
             },
             BindEntry {
-                binding: 3,
+                binding: 2,
                 visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
-                resource: wgpu::BindingResource::Sampler(depth_sampler),
-            },
-            BindEntry {
-                binding: 4,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                resource: bone_buffer.buffer.as_entire_binding(),
-            },
-        ]);
-
-        let static_shadow = render_device.create_bind_collection(vec![
-            BindEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -502,63 +1150,40 @@ impl Renderer {
                 },
                 resource: uniform_buffer.buffer.as_entire_binding(),
             },
-            BindEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                resource: static_instance_buffer.buffer.as_entire_binding(),
-            },
         ]);
 
-        let skeletal_shadow = render_device.create_bind_collection(vec![
-            BindEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                resource: uniform_buffer.buffer.as_entire_binding(),
-            },
-            BindEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                resource: skeletal_instance_buffer.buffer.as_entire_binding(),
-            },
-            BindEntry {
-                binding: 2,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                resource: bone_buffer.buffer.as_entire_binding(),
-            },
-        ]);
+        let fxaa_shader = render_device.create_shader("fxaa.wgsl", &[]);
 
-        return (static_scene, skeletal_scene, static_shadow, skeletal_shadow);
+        let material_pipeline = render_device.create_material_pipeline(&MaterialPipelineDesc {
+            vertex_shader: &fxaa_shader,
+            fragment_shader: Some(&fxaa_shader),
+            bind_group_layouts: &[&bind_collection.bind_group_layout],
+            layout_entries: &[],
+            vertex_layout: &StaticMeshVertex::desc(),
+            instance_layout: None,
+            push_contant_ranges: &[],
+            pass_target: PassTarget::Composite,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            sample_count: 1,
+            premultiplied_alpha: false,
+        });
+
+        return (bind_collection, material_pipeline);
     }
 
-    fn create_sprite_pipeline(
+    // Reads the depth buffer, so it is made again with it
+    fn create_world_grid_pipeline(
         render_device: &RenderDevice,
         uniform_buffer: &Buffer,
-        instance_buffer: &Buffer,
+        world_grid_uniform_buffer: &Buffer,
+        depth_buffer: &Texture,
+        sample_count: u32,
     ) -> (BindCollection, MaterialPipeline) {
+        let multisampled = sample_count > 1;
         let bind_collection = render_device.create_bind_collection(vec![
             BindEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                visibility: wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -568,101 +1193,44 @@ impl Renderer {
             },
             BindEntry {
                 binding: 1,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                resource: instance_buffer.buffer.as_entire_binding(),
-            },
-        ]);
-
-        let sprite_shader =
-            render_device
-                .device
-                .create_shader_module(wgpu::ShaderModuleDescriptor {
-                    label: Some("SpriteShader"),
-                    source: wgpu::ShaderSource::Wgsl(
-                        include_str!("../../res/shaders/sprite.wgsl").into(),
-                    ),
-                });
-
-        let material_pipeline = render_device.create_material_pipeline(&MaterialPipelineDesc {
-            vertex_shader: &sprite_shader,
-            fragment_shader: Some(&sprite_shader),
-            bind_group_layouts: &[&bind_collection.bind_group_layout],
-            layout_entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2Array,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
-            vertex_layout: &StaticMeshVertex::desc(),
-            push_contant_ranges: &[],
-            pass_target: PassTarget::Composite,
-        });
-
-        return (bind_collection, material_pipeline);
-    }
-
-    fn create_composite_pipeline(
-        render_device: &RenderDevice,
-        scene_texture: &Texture,
-        sampler: &wgpu::Sampler,
-    ) -> (BindCollection, MaterialPipeline) {
-        let bind_collection = render_device.create_bind_collection(vec![
-            BindEntry {
-                binding: 0,
                 visibility: wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Texture {
-                    multisampled: false,
+                    multisampled,
                     view_dimension: wgpu::TextureViewDimension::D2,
-                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
                 },
-                resource: wgpu::BindingResource::TextureView(&scene_texture.view),
+                resource: wgpu::BindingResource::TextureView(&depth_buffer.view),
             },
             BindEntry {
-                binding: 1,
+                binding: 2,
                 visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                resource: wgpu::BindingResource::Sampler(sampler),
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                resource: world_grid_uniform_buffer.buffer.as_entire_binding(),
             },
         ]);
 
-        let composite_shader =
-            render_device
-                .device
-                .create_shader_module(wgpu::ShaderModuleDescriptor {
-                    label: Some("CompositeShader"),
-                    source: wgpu::ShaderSource::Wgsl(
-                        include_str!("../../res/shaders/composite.wgsl").into(),
-                    ),
-                });
+        let defines: &[&str] = if multisampled { &["MULTISAMPLED"] } else { &[] };
+        let world_grid_shader = render_device.create_shader("world_grid.wgsl", defines);
 
         let material_pipeline = render_device.create_material_pipeline(&MaterialPipelineDesc {
-            vertex_shader: &composite_shader,
-            fragment_shader: Some(&composite_shader),
+            vertex_shader: &world_grid_shader,
+            fragment_shader: Some(&world_grid_shader),
             bind_group_layouts: &[&bind_collection.bind_group_layout],
             layout_entries: &[],
             vertex_layout: &StaticMeshVertex::desc(),
+            instance_layout: None,
             push_contant_ranges: &[],
             pass_target: PassTarget::Composite,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            sample_count: 1,
+            premultiplied_alpha: false,
         });
 
-        return (bind_collection, material_pipeline);
+        (bind_collection, material_pipeline)
     }
 
     fn create_shadow_material_pipelines(
@@ -670,25 +1238,9 @@ impl Renderer {
         static_bind_group_layout: &wgpu::BindGroupLayout,
         skeletal_bind_group_layout: &wgpu::BindGroupLayout,
     ) -> MaterialGroup {
-        let static_shadow_shader =
-            render_device
-                .device
-                .create_shader_module(wgpu::ShaderModuleDescriptor {
-                    label: Some("StaticShadowShader"),
-                    source: wgpu::ShaderSource::Wgsl(
-                        include_str!("../../res/shaders/static_shadow.wgsl").into(),
-                    ),
-                });
+        let static_shadow_shader = render_device.create_shader("shadow.wgsl", &[]);
 
-        let skeletal_shadow_shader =
-            render_device
-                .device
-                .create_shader_module(wgpu::ShaderModuleDescriptor {
-                    label: Some("SkeletalShadowShader"),
-                    source: wgpu::ShaderSource::Wgsl(
-                        include_str!("../../res/shaders/skeletal_shadow.wgsl").into(),
-                    ),
-                });
+        let skeletal_shadow_shader = render_device.create_shader("shadow.wgsl", &["SKINNED"]);
 
         MaterialGroup {
             static_material_pipeline: render_device.create_material_pipeline(
@@ -698,8 +1250,12 @@ impl Renderer {
                     bind_group_layouts: &[static_bind_group_layout],
                     layout_entries: &[],
                     vertex_layout: &StaticMeshVertex::desc(),
+                    instance_layout: render_device.get_instance_layout(StaticInstanceData::desc()),
                     push_contant_ranges: &[],
                     pass_target: PassTarget::Scene,
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    sample_count: 1,
+                    premultiplied_alpha: false,
                 },
             ),
             skeletal_material_pipeline: render_device.create_material_pipeline(
@@ -709,8 +1265,12 @@ impl Renderer {
                     bind_group_layouts: &[skeletal_bind_group_layout],
                     layout_entries: &[],
                     vertex_layout: &SkeletalMeshVertex::desc(),
+                    instance_layout: render_device.get_instance_layout(StaticInstanceData::desc()),
                     push_contant_ranges: &[],
                     pass_target: PassTarget::Scene,
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    sample_count: 1,
+                    premultiplied_alpha: false,
                 },
             ),
         }
@@ -720,98 +1280,266 @@ impl Renderer {
         render_device: &RenderDevice,
         static_bind_group_layout: &wgpu::BindGroupLayout,
         skeletal_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+        view: DebugView,
     ) -> MaterialGroup {
-        let static_vertex_shader =
-            render_device
-                .device
-                .create_shader_module(wgpu::ShaderModuleDescriptor {
-                    label: Some("StaticVertexShader"),
-                    source: wgpu::ShaderSource::Wgsl(
-                        include_str!("../../res/shaders/static.wgsl").into(),
-                    ),
-                });
+        let static_vertex_shader = render_device.create_shader("static.wgsl", &[]);
 
-        let skeletal_vertex_shader =
-            render_device
-                .device
-                .create_shader_module(wgpu::ShaderModuleDescriptor {
-                    label: Some("SkeletalVertexShader"),
-                    source: wgpu::ShaderSource::Wgsl(
-                        include_str!("../../res/shaders/skeletal.wgsl").into(),
-                    ),
-                });
+        let skeletal_vertex_shader = render_device.create_shader("skeletal.wgsl", &["SKINNED"]);
 
-        let fragment_shader =
-            render_device
-                .device
-                .create_shader_module(wgpu::ShaderModuleDescriptor {
-                    label: Some("SceneFragmentShader"),
-                    source: wgpu::ShaderSource::Wgsl(
-                        include_str!("../../res/shaders/scene.wgsl").into(),
-                    ),
-                });
+        let fragment_shader = render_device.create_shader("scene.wgsl", view.get_defines());
 
-        let material_layout_entries = [
-            wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Texture {
-                    multisampled: false,
-                    view_dimension: wgpu::TextureViewDimension::D2Array,
-                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                },
-                count: None,
-            },
-            wgpu::BindGroupLayoutEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                count: None,
-            },
-        ];
+        let material_layout_entries = Self::get_scene_material_layout_entries();
 
         MaterialGroup {
             static_material_pipeline: render_device.create_material_pipeline(
                 &MaterialPipelineDesc {
                     bind_group_layouts: &[static_bind_group_layout],
                     push_contant_ranges: &[],
-                    pass_target: PassTarget::Scene,
+                    pass_target: view.get_pass_target(),
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    sample_count,
+                    premultiplied_alpha: false,
                     vertex_shader: &static_vertex_shader,
                     fragment_shader: Some(&fragment_shader),
                     layout_entries: &material_layout_entries,
                     vertex_layout: &StaticMeshVertex::desc(),
+                    instance_layout: render_device.get_instance_layout(StaticInstanceData::desc()),
                 },
             ),
             skeletal_material_pipeline: render_device.create_material_pipeline(
                 &MaterialPipelineDesc {
                     bind_group_layouts: &[skeletal_bind_group_layout],
                     push_contant_ranges: &[],
-                    pass_target: PassTarget::Scene,
+                    pass_target: view.get_pass_target(),
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    sample_count,
+                    premultiplied_alpha: false,
                     vertex_shader: &skeletal_vertex_shader,
                     fragment_shader: Some(&fragment_shader),
                     layout_entries: &material_layout_entries,
                     vertex_layout: &SkeletalMeshVertex::desc(),
+                    instance_layout: render_device.get_instance_layout(StaticInstanceData::desc()),
                 },
             ),
         }
     }
 
-    pub async fn new(window: &Arc<Window>) -> anyhow::Result<Renderer> {
-        let render_device = RenderDevice::new(window).await?;
+    fn get_scene_material_layout_entries() -> [wgpu::BindGroupLayoutEntry; 2] {
+        [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+    }
+
+    // Skeletal meshes colored by their bone weights, takes the material bind group of the
+    // scene pipelines without sampling it so the batches draw the same way
+    fn create_weight_debug_pipeline(
+        render_device: &RenderDevice,
+        skeletal_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> MaterialPipeline {
+        let weight_debug_shader =
+            render_device.create_shader("skeletal_weights.wgsl", &["SKINNED"]);
+
+        render_device.create_material_pipeline(&MaterialPipelineDesc {
+            vertex_shader: &weight_debug_shader,
+            fragment_shader: Some(&weight_debug_shader),
+            bind_group_layouts: &[skeletal_bind_group_layout],
+            layout_entries: &Self::get_scene_material_layout_entries(),
+            vertex_layout: &SkeletalMeshVertex::desc(),
+            instance_layout: render_device.get_instance_layout(StaticInstanceData::desc()),
+            push_contant_ranges: &[],
+            pass_target: PassTarget::Scene,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            sample_count,
+            premultiplied_alpha: false,
+        })
+    }
+
+    // Silhouettes of skeletal meshes where they are hidden, takes the material bind group of
+    // the scene pipelines without sampling it like the weight debug pipeline
+    fn create_xray_pipeline(
+        render_device: &RenderDevice,
+        skeletal_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> MaterialPipeline {
+        let xray_shader = render_device.create_shader("skeletal_xray.wgsl", &["SKINNED"]);
+
+        render_device.create_material_pipeline(&MaterialPipelineDesc {
+            vertex_shader: &xray_shader,
+            fragment_shader: Some(&xray_shader),
+            bind_group_layouts: &[skeletal_bind_group_layout],
+            layout_entries: &Self::get_scene_material_layout_entries(),
+            vertex_layout: &SkeletalMeshVertex::desc(),
+            instance_layout: render_device.get_instance_layout(StaticInstanceData::desc()),
+            push_contant_ranges: &[],
+            pass_target: PassTarget::Occluded,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            sample_count,
+            premultiplied_alpha: false,
+        })
+    }
+
+    // Static meshes unlit in the additive pass, e.g. trails
+    fn create_additive_pipeline(
+        render_device: &RenderDevice,
+        static_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> MaterialPipeline {
+        let vertex_shader = render_device.create_shader("static.wgsl", &[]);
+        let fragment_shader = render_device.create_shader("scene.wgsl", &["UNLIT"]);
+
+        render_device.create_material_pipeline(&MaterialPipelineDesc {
+            vertex_shader: &vertex_shader,
+            fragment_shader: Some(&fragment_shader),
+            bind_group_layouts: &[static_bind_group_layout],
+            layout_entries: &Self::get_scene_material_layout_entries(),
+            vertex_layout: &StaticMeshVertex::desc(),
+            instance_layout: render_device.get_instance_layout(StaticInstanceData::desc()),
+            push_contant_ranges: &[],
+            pass_target: PassTarget::Additive,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            sample_count,
+            premultiplied_alpha: false,
+        })
+    }
+
+    fn create_cull_pipeline(render_device: &RenderDevice) -> CullPipeline {
+        let storage = |read_only| wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout =
+            render_device
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("Cull Bind Group Layout"),
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            ..storage(true)
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            ..storage(true)
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 3,
+                            ..storage(false)
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 4,
+                            ..storage(false)
+                        },
+                    ],
+                });
+
+        let cull_shader = render_device.create_shader("cull.wgsl", &[]);
+        let compute_pipeline = render_device.create_compute_pipeline(&ComputePipelineDesc {
+            shader: &cull_shader,
+            bind_group_layouts: &[&bind_group_layout],
+        });
+        let uniform_buffer = render_device.create_buffer(&BufferDesc {
+            size: std::mem::size_of::<CullUniformData>(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        CullPipeline {
+            bind_group_layout,
+            compute_pipeline,
+            uniform_buffer,
+        }
+    }
+
+    pub async fn new(window: &Arc<Window>, transparent: bool) -> anyhow::Result<Renderer> {
+        let render_device = RenderDevice::new(window, transparent).await?;
+        Ok(Self::from_device(render_device))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn new_headless(width: u32, height: u32) -> anyhow::Result<Renderer> {
+        let render_device = RenderDevice::new_headless(width, height).await?;
+        let mut renderer = Self::from_device(render_device);
+        renderer.resize(width, height);
+        Ok(renderer)
+    }
+
+    // Draws the way WebGL2 does whatever the adapter supports, see InstancePath
+    #[cfg(feature = "test-harness")]
+    pub async fn new_headless_fallback(width: u32, height: u32) -> anyhow::Result<Renderer> {
+        let mut render_device = RenderDevice::new_headless(width, height).await?;
+        render_device.force_instance_vertex_buffers();
+        let mut renderer = Self::from_device(render_device);
+        renderer.resize(width, height);
+        Ok(renderer)
+    }
+
+    fn from_device(render_device: RenderDevice) -> Renderer {
+        let instance_path = render_device.get_instance_path();
+        log::info!(
+            "Adapter: {:?} | Instances: {:?}",
+            render_device.get_adapter_info(),
+            instance_path
+        );
+        for fallback in render_device.get_diagnostics().fallbacks {
+            log::info!("Fallback: {}", fallback);
+        }
+        let gpu_timer = GpuTimer::new(&render_device);
+
         let mut resource_pool = ResourcePool::new();
 
-        let (screen_mesh, quad_mesh) = Self::create_meshes(&render_device);
+        let (screen_mesh, quad_mesh, capsule_mesh, ring_mesh) = Self::create_meshes(&render_device);
         resource_pool.add_resource(Self::QUAD_MESH, Resource::StaticMesh(quad_mesh));
+        resource_pool.add_resource(Self::CAPSULE_MESH, Resource::StaticMesh(capsule_mesh));
+        resource_pool.add_resource(Self::RING_MESH, Resource::StaticMesh(ring_mesh));
 
-        let (default_sampler, depth_sampler) = Self::create_samplers(&render_device);
+        let (default_sampler, depth_sampler, screen_sampler) =
+            Self::create_samplers(&render_device);
 
-        let shadow_map = Self::create_shadow_map(&render_device);
-        let depth_buffer = Renderer::create_depth_buffer(&render_device);
-        let scene_texture = Renderer::create_scene_texture(&render_device);
+        let shadow_map = Self::create_shadow_map(&render_device, Self::DEFAULT_SHADOW_MAP_SIZE);
+        let scene_size = get_scaled_size(render_device.get_window_size(), 1.0);
+        let depth_buffer = Renderer::create_depth_buffer(&render_device, scene_size, 1);
+        let scene_texture = Renderer::create_scene_texture(&render_device, scene_size);
+        let ldr_texture = Renderer::create_ldr_texture(&render_device);
 
+        let instance_caps = InstanceCaps::new(instance_path);
         let (static_instance_buffer, skeletal_instance_buffer, bone_buffer, sprite_instance_buffer) =
-            Self::create_storage_buffers(&render_device);
-        let (uniform_buffer, sprite_uniform_buffer) = Self::create_uniform_buffers(&render_device);
+            Self::create_storage_buffers(&render_device, instance_caps);
+        let (uniform_buffer, sprite_uniform_buffer, fxaa_uniform_buffer) =
+            Self::create_uniform_buffers(&render_device);
+        let sprite_params_buffer =
+            Self::create_sprite_params_buffer(&render_device, Self::SPRITE_PARAMS_COUNT);
 
         let (
             static_scene_bind_collection,
@@ -828,14 +1556,58 @@ impl Renderer {
             &bone_buffer,
         );
 
-        let (sprite_bind_collection, sprite_material_pipeline) = Self::create_sprite_pipeline(
+        let (
+            sprite_bind_collection,
+            sprite_material_pipeline,
+            premultiplied_sprite_material_pipeline,
+        ) = Self::create_sprite_pipeline(
             &render_device,
             &sprite_uniform_buffer,
             &sprite_instance_buffer,
+            &sprite_params_buffer,
         );
 
         let (composite_bind_collection, composite_material_pipeline) =
-            Self::create_composite_pipeline(&render_device, &scene_texture, &default_sampler);
+            Self::create_composite_pipeline(&render_device, &scene_texture, &screen_sampler);
+
+        let (fxaa_bind_collection, fxaa_material_pipeline) = Self::create_fxaa_pipeline(
+            &render_device,
+            &ldr_texture,
+            &default_sampler,
+            &fxaa_uniform_buffer,
+        );
+
+        let world_grid_uniform_buffer = render_device.create_buffer(&BufferDesc {
+            size: std::mem::size_of::<WorldGridUniformData>(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let (world_grid_bind_collection, world_grid_material_pipeline) =
+            Self::create_world_grid_pipeline(
+                &render_device,
+                &uniform_buffer,
+                &world_grid_uniform_buffer,
+                &depth_buffer,
+                1,
+            );
+
+        let (debug_line_buffer, debug_line_bind_collection) =
+            Self::create_debug_line_resources(&render_device, &uniform_buffer);
+        let debug_line_material_pipeline = Self::create_debug_line_pipeline(
+            &render_device,
+            &debug_line_bind_collection.bind_group_layout,
+            PassTarget::Scene,
+            1,
+        );
+        let gizmo_line_buffer = render_device.create_buffer(&BufferDesc {
+            size: Self::GIZMO_LINE_COUNT * 2 * std::mem::size_of::<DebugLineVertex>(),
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        });
+        let gizmo_material_pipeline = Self::create_debug_line_pipeline(
+            &render_device,
+            &debug_line_bind_collection.bind_group_layout,
+            PassTarget::Overlay,
+            1,
+        );
 
         let shadow_material_pipeline = Self::create_shadow_material_pipelines(
             &render_device,
@@ -846,8 +1618,29 @@ impl Renderer {
             &render_device,
             &static_scene_bind_collection.bind_group_layout,
             &skeletal_scene_bind_collection.bind_group_layout,
+            1,
+            DebugView::None,
+        );
+        let weight_debug_material_pipeline = Self::create_weight_debug_pipeline(
+            &render_device,
+            &skeletal_scene_bind_collection.bind_group_layout,
+            1,
+        );
+        let xray_material_pipeline = Self::create_xray_pipeline(
+            &render_device,
+            &skeletal_scene_bind_collection.bind_group_layout,
+            1,
+        );
+        let additive_material_pipeline = Self::create_additive_pipeline(
+            &render_device,
+            &static_scene_bind_collection.bind_group_layout,
+            1,
         );
 
+        let cull_pipeline = render_device
+            .supports_compute()
+            .then(|| Self::create_cull_pipeline(&render_device));
+
         Self::create_default_resources(
             &render_device,
             &sprite_material_pipeline,
@@ -855,20 +1648,25 @@ impl Renderer {
             &mut resource_pool,
         );
 
-        Ok(Renderer {
+        Renderer {
             render_device,
             resource_pool,
             screen_mesh,
             default_sampler,
             _depth_sampler: depth_sampler,
+            screen_sampler,
             shadow_map,
+            shadow_map_size: Self::DEFAULT_SHADOW_MAP_SIZE,
             depth_buffer,
             scene_texture,
+            scene_msaa_texture: None,
+            ldr_texture,
             static_shadow_bind_collection,
             skeletal_shadow_bind_collection,
             sprite_bind_collection,
             shadow_material_pipeline,
             sprite_material_pipeline,
+            premultiplied_sprite_material_pipeline,
             camera_transform: Transform {
                 position: Vec3 {
                     x: 0.0,
@@ -878,17 +1676,48 @@ impl Renderer {
                 rotation: Quat::from_rotation_x(f32::to_radians(-30.0)),
                 ..Default::default()
             },
+            camera_override: None,
+            fov_override: None,
             camera_projection_matrix: Mat4::IDENTITY,
             render_data: RenderData::new(),
+            sprite_atlas_sizes: HashMap::new(),
+            material_sources: HashMap::new(),
+            resource_scopes: ResourceScopes::new(),
+            released_resources: Vec::new(),
+            persistent_instances: HashMap::new(),
+            cull_pipeline,
+            gpu_culling: false,
+            gpu_culling_validation: false,
+            cull_planes: [Vec4::ZERO; 6],
+            gpu_cull_counts: Vec::new(),
+            frame_stats: Default::default(),
+            captured_batches: None,
+            budget_warnings: 0,
+            presented_frame_count: 0,
+            suspended: false,
+            transient_pool: RefCell::new(TransientPool::default()),
+            gpu_timer: RefCell::new(gpu_timer),
+            layer_mask: ALL_RENDER_LAYERS & !RENDER_LAYER_MINIMAP,
+            low_latency: false,
+            pending_cpu_wait: 0.0,
+            cpu_wait: 0.0,
+            screenshot_requested: false,
+            screenshot: None,
             uniform_buffer,
             sprite_uniform_buffer,
             uniform_data: UniformBufferData {
                 view_matrix: Mat4::IDENTITY.to_data(),
                 projection_matrix: Mat4::IDENTITY.to_data(),
+                inverse_view_projection: Mat4::IDENTITY.to_data(),
                 camera_position: [0.0, 0.0, 0.0, 0.0],
                 light_matrix: Mat4::IDENTITY.to_data(),
-                light_direction: [0.0, -1.0, -1.0, 0.0],
-                light_color: [1.0, 1.0, 1.0, 1.0],
+                light_direction: [0.0, 0.0, 0.0, 0.0],
+                light_color: [0.0, 0.0, 0.0, 0.0],
+                ambient_top: [0.0, 0.0, 0.0, 0.0],
+                ambient_bottom: [0.0, 0.0, 0.0, 0.0],
+                fog_color: [0.0, 0.0, 0.0, 0.0],
+                fog_range: [0.0, 0.0, 0.0, 0.0],
+                wind: [0.0, 0.0, 0.0, 0.0],
             },
             sprite_uniform_data: Default::default(),
             composite_bind_collection,
@@ -897,74 +1726,1039 @@ impl Renderer {
             skeletal_instance_buffer,
             bone_buffer,
             sprite_instance_buffer,
+            sprite_params_buffer,
+            sprite_params_capacity: Self::SPRITE_PARAMS_COUNT,
+            instance_caps,
+            debug_line_buffer,
+            debug_line_bind_collection,
+            debug_line_material_pipeline,
+            gizmo_line_buffer,
+            gizmo_material_pipeline,
+            fxaa_uniform_buffer,
+            fxaa_bind_collection,
+            fxaa_material_pipeline,
+            aa_mode: AaMode::Off,
+            render_scale: 1.0,
+            fxaa_settings: Default::default(),
+            world_grid_uniform_buffer,
+            world_grid_bind_collection,
+            world_grid_material_pipeline,
+            world_grid: None,
+            directional_light: Default::default(),
+            ambient_light: Default::default(),
+            fog: None,
+            wind: Default::default(),
+            wind_time: 0.0,
+            light_debug_enabled: false,
+            ui_viewport: UiViewport::new(Vec2::ZERO),
+            ui_layout_changed: false,
+            safe_area_debug_enabled: false,
+            xray_enabled: true,
+            letterbox: false,
             scene_material_pipeline,
+            debug_view: DebugView::None,
+            debug_view_material_pipeline: None,
+            weight_debug_material_pipeline,
+            xray_material_pipeline,
+            additive_material_pipeline,
             static_scene_bind_collection,
             skeletal_scene_bind_collection,
-        })
+        }
     }
 
+    // A surface can't have zero pixels, so a window without area suspends the rendering
+    // until it is resized to one again. The targets are kept at the last size meanwhile.
     pub fn resize(&mut self, width: u32, height: u32) {
-        if width > 0 && height > 0 {
-            let render_device = &mut self.render_device;
+        if width == 0 || height == 0 {
+            if !self.suspended {
+                log::info!("Rendering suspended, the window has no area");
+            }
+            self.suspended = true;
+            return;
+        }
+        self.suspended = false;
 
-            render_device.config.width = width;
-            render_device.config.height = height;
-            render_device
-                .surface
-                .configure(&render_device.device, &render_device.config);
-            render_device.is_surface_configured = true;
-
-            self.sprite_uniform_data.screen_size = [width as f32, height as f32];
-            self.sprite_uniform_data.ui_scale = f32::min(
-                width as f32 / Self::SPRITE_SCREEN_REFERENCE.x,
-                height as f32 / Self::SPRITE_SCREEN_REFERENCE.y,
-            );
+        self.ui_viewport.screen_size = Vec2::new(width as f32, height as f32);
+        self.update_sprite_uniform();
 
-            self.depth_buffer = Renderer::create_depth_buffer(&render_device);
-            self.scene_texture = Renderer::create_scene_texture(&render_device);
-            let (composite_bind_collection, composite_material_pipeline) =
-                Self::create_composite_pipeline(
-                    &render_device,
-                    &self.scene_texture,
-                    &self.default_sampler,
-                );
-            self.composite_bind_collection = composite_bind_collection;
-            self.composite_material_pipeline = composite_material_pipeline;
+        let render_device = &mut self.render_device;
+
+        render_device.config.width = width;
+        render_device.config.height = height;
+        if let Some(surface) = &render_device.surface {
+            surface.configure(&render_device.device, &render_device.config);
+        }
+        render_device.is_surface_configured = true;
+
+        self.ldr_texture = Renderer::create_ldr_texture(render_device);
+        let (fxaa_bind_collection, fxaa_material_pipeline) = Self::create_fxaa_pipeline(
+            render_device,
+            &self.ldr_texture,
+            &self.default_sampler,
+            &self.fxaa_uniform_buffer,
+        );
+        self.fxaa_bind_collection = fxaa_bind_collection;
+        self.fxaa_material_pipeline = fxaa_material_pipeline;
+        self.upload_fxaa_uniform();
+
+        self.recreate_scene_targets();
+    }
+
+    #[cfg(feature = "test-harness")]
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    // After the surface was lost. Its capabilities are checked again as the window may be
+    // on another monitor now, the pipelines drawing to it are made again when its format
+    // changed. Returns whether it did, fails when the adapter can't present to it anymore.
+    pub fn reconfigure_surface(&mut self, width: u32, height: u32) -> anyhow::Result<bool> {
+        let format_changed = self.render_device.refresh_surface_config()?;
+        if format_changed {
+            self.recreate_surface_pipelines();
+        }
+        self.resize(width, height);
+        Ok(format_changed)
+    }
+
+    // The sprites are drawn to the surface, so their pipelines and materials are made for
+    // its format. The composite and FXAA pipelines are made by resize.
+    fn recreate_surface_pipelines(&mut self) {
+        let (sprite_bind_collection, sprite_material_pipeline, premultiplied_pipeline) =
+            Self::create_sprite_pipeline(
+                &self.render_device,
+                &self.sprite_uniform_buffer,
+                &self.sprite_instance_buffer,
+                &self.sprite_params_buffer,
+            );
+        self.sprite_bind_collection = sprite_bind_collection;
+        self.sprite_material_pipeline = sprite_material_pipeline;
+        self.premultiplied_sprite_material_pipeline = premultiplied_pipeline;
+
+        Self::create_default_resources(
+            &self.render_device,
+            &self.sprite_material_pipeline,
+            &self.default_sampler,
+            &mut self.resource_pool,
+        );
+        let sprite_materials: Vec<_> = self
+            .material_sources
+            .iter()
+            .filter(|(_, source)| {
+                matches!(source, MaterialSource::Sprite(_) | MaterialSource::Font(_))
+            })
+            .map(|(&material, &source)| (material, source))
+            .collect();
+        for (material, source) in sprite_materials {
+            if let Some(material_instance) = self.build_material_instance(source) {
+                self.resource_pool
+                    .add_resource(material, Resource::MaterialInstance(material_instance));
+            }
+        }
+    }
+
+    // Stands in for a window moved to a monitor of another format
+    #[cfg(feature = "test-harness")]
+    pub fn set_surface_format(&mut self, format: wgpu::TextureFormat) {
+        self.render_device.config.format = format;
+        self.recreate_surface_pipelines();
+        let size = self.render_device.get_window_size();
+        self.resize(size.x, size.y);
+    }
+
+    // The targets the scene passes draw to, at the render scale of the window size. The
+    // composite pass reads the scene texture and the grid the depth, so they get new bind
+    // groups too.
+    fn recreate_scene_targets(&mut self) {
+        let render_device = &self.render_device;
+        let size = get_scaled_size(render_device.get_window_size(), self.render_scale);
+        let sample_count = self.aa_mode.get_sample_count();
+        self.depth_buffer = Renderer::create_depth_buffer(render_device, size, sample_count);
+        self.scene_texture = Renderer::create_scene_texture(render_device, size);
+        self.scene_msaa_texture =
+            Renderer::create_scene_msaa_texture(render_device, size, sample_count);
+
+        let (composite_bind_collection, composite_material_pipeline) =
+            Self::create_composite_pipeline(
+                render_device,
+                &self.scene_texture,
+                &self.screen_sampler,
+            );
+        self.composite_bind_collection = composite_bind_collection;
+        self.composite_material_pipeline = composite_material_pipeline;
+        debug_assert_eq!(
+            self.composite_material_pipeline.target_format,
+            render_device.get_surface_format()
+        );
+        self.recreate_world_grid_pipeline();
+    }
+
+    // Renders the 3D scene smaller than the window for weak GPUs, it is upscaled before the
+    // UI is drawn on top at full resolution. Clamped to MIN_RENDER_SCALE..=1.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        let scale = scale.clamp(Self::MIN_RENDER_SCALE, 1.0);
+        if scale == self.render_scale {
+            return;
+        }
+
+        self.render_scale = scale;
+        self.recreate_scene_targets();
+        let size = self.get_scene_size();
+        log::info!("Render scale set to {} ({}x{})", scale, size.x, size.y);
+    }
+
+    pub fn get_render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    // Of the scene targets in pixels, the window size at a render scale of 1
+    pub fn get_scene_size(&self) -> UVec2 {
+        UVec2::new(
+            self.scene_texture._texture.width(),
+            self.scene_texture._texture.height(),
+        )
+    }
+
+    // Clamped to 1..=MAX_FRAMES_IN_FLIGHT, fewer frames lower the latency but leave the GPU
+    // idle while the CPU builds the next one
+    pub fn set_max_frames_in_flight(&mut self, count: u32) {
+        self.render_device.set_max_frames_in_flight(count);
+        log::info!(
+            "Max frames in flight set to {}",
+            self.render_device.get_max_frames_in_flight()
+        );
+    }
+
+    pub fn get_max_frames_in_flight(&self) -> u32 {
+        self.render_device.get_max_frames_in_flight()
+    }
+
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.render_device.set_vsync(vsync);
+        log::info!(
+            "Present mode set to {:?}",
+            self.render_device.config.present_mode
+        );
+    }
+
+    pub fn is_vsync(&self) -> bool {
+        self.render_device.is_vsync()
+    }
+
+    // Waits for the GPU to finish each frame before the next one is built, see wait_for_gpu
+    pub fn set_low_latency(&mut self, enabled: bool) {
+        self.low_latency = enabled;
+    }
+
+    pub fn is_low_latency(&self) -> bool {
+        self.low_latency
+    }
+
+    // Only blocks in low latency mode. Called after rendering, so the input that arrives
+    // while waiting still makes it into the next frame.
+    pub fn wait_for_gpu(&mut self) {
+        if self.low_latency {
+            self.pending_cpu_wait += self.render_device.wait_for_frames(0);
+        }
+    }
+
+    // In seconds, how long the last rendered frame waited for the GPU to catch up
+    pub fn get_cpu_wait(&self) -> f32 {
+        self.cpu_wait
+    }
+
+    // Of a frame a few frames back, empty without timestamp queries
+    pub fn get_gpu_pass_timings(&self) -> Ref<'_, [PassTiming]> {
+        Ref::map(self.gpu_timer.borrow(), |timer| {
+            timer.as_ref().map_or(&[][..], GpuTimer::get_timings)
+        })
+    }
+
+    // Unsupported MSAA sample counts fall back to FXAA, see AaMode::resolve
+    pub fn set_antialiasing(&mut self, mode: AaMode) {
+        let mode = mode.resolve(&self.render_device.scene_sample_counts);
+        if mode == self.aa_mode {
+            return;
         }
+
+        let sample_count = mode.get_sample_count();
+        if sample_count != self.aa_mode.get_sample_count() {
+            let render_device = &self.render_device;
+            let size = self.get_scene_size();
+            self.depth_buffer = Renderer::create_depth_buffer(render_device, size, sample_count);
+            self.scene_msaa_texture =
+                Renderer::create_scene_msaa_texture(render_device, size, sample_count);
+            self.scene_material_pipeline = Self::create_scene_material_pipelines(
+                render_device,
+                &self.static_scene_bind_collection.bind_group_layout,
+                &self.skeletal_scene_bind_collection.bind_group_layout,
+                sample_count,
+                DebugView::None,
+            );
+            self.debug_view_material_pipeline = self.create_debug_view_pipelines(sample_count);
+            self.weight_debug_material_pipeline = Self::create_weight_debug_pipeline(
+                render_device,
+                &self.skeletal_scene_bind_collection.bind_group_layout,
+                sample_count,
+            );
+            self.xray_material_pipeline = Self::create_xray_pipeline(
+                render_device,
+                &self.skeletal_scene_bind_collection.bind_group_layout,
+                sample_count,
+            );
+            self.additive_material_pipeline = Self::create_additive_pipeline(
+                render_device,
+                &self.static_scene_bind_collection.bind_group_layout,
+                sample_count,
+            );
+            self.debug_line_material_pipeline = Self::create_debug_line_pipeline(
+                render_device,
+                &self.debug_line_bind_collection.bind_group_layout,
+                PassTarget::Scene,
+                sample_count,
+            );
+            self.gizmo_material_pipeline = Self::create_debug_line_pipeline(
+                render_device,
+                &self.debug_line_bind_collection.bind_group_layout,
+                PassTarget::Overlay,
+                sample_count,
+            );
+        }
+
+        log::info!("Antialiasing set to {:?}", mode);
+        self.aa_mode = mode;
+        self.recreate_world_grid_pipeline();
+    }
+
+    pub fn get_antialiasing(&self) -> AaMode {
+        self.aa_mode
+    }
+
+    // Wireframes fall back to the normal view without POLYGON_MODE_LINE, see
+    // DebugView::resolve. The materials are left alone, switching back draws as before.
+    pub fn set_debug_view(&mut self, view: DebugView) {
+        let view = view.resolve(self.render_device.supports_wireframe());
+        if view == self.debug_view {
+            return;
+        }
+
+        log::info!("Debug view set to {:?}", view);
+        self.debug_view = view;
+        self.debug_view_material_pipeline =
+            self.create_debug_view_pipelines(self.aa_mode.get_sample_count());
+    }
+
+    pub fn get_debug_view(&self) -> DebugView {
+        self.debug_view
+    }
+
+    fn create_debug_view_pipelines(&self, sample_count: u32) -> Option<MaterialGroup> {
+        (self.debug_view != DebugView::None).then(|| {
+            Self::create_scene_material_pipelines(
+                &self.render_device,
+                &self.static_scene_bind_collection.bind_group_layout,
+                &self.skeletal_scene_bind_collection.bind_group_layout,
+                sample_count,
+                self.debug_view,
+            )
+        })
+    }
+
+    // The pipelines the scene batches are drawn with in the current debug view
+    fn get_scene_pipelines(&self) -> &MaterialGroup {
+        self.debug_view_material_pipeline
+            .as_ref()
+            .unwrap_or(&self.scene_material_pipeline)
+    }
+
+    #[allow(dead_code)]
+    pub fn set_fxaa_settings(&mut self, settings: FxaaSettings) {
+        self.fxaa_settings = settings;
+        self.upload_fxaa_uniform();
+    }
+
+    #[allow(dead_code)]
+    pub fn get_fxaa_settings(&self) -> &FxaaSettings {
+        &self.fxaa_settings
+    }
+
+    // None hides it, see world_grid.rs. Left out of screenshots.
+    pub fn set_world_grid(&mut self, settings: Option<WorldGridSettings>) {
+        self.world_grid = settings;
+        if let Some(settings) = &settings {
+            let data = WorldGridUniformData::new(settings);
+            self.render_device.write_buffer(
+                &self.world_grid_uniform_buffer,
+                bytemuck::bytes_of(&data),
+                0,
+            );
+        }
+    }
+
+    pub fn get_world_grid(&self) -> Option<WorldGridSettings> {
+        self.world_grid
+    }
+
+    fn recreate_world_grid_pipeline(&mut self) {
+        let (bind_collection, material_pipeline) = Self::create_world_grid_pipeline(
+            &self.render_device,
+            &self.uniform_buffer,
+            &self.world_grid_uniform_buffer,
+            &self.depth_buffer,
+            self.aa_mode.get_sample_count(),
+        );
+        self.world_grid_bind_collection = bind_collection;
+        self.world_grid_material_pipeline = material_pipeline;
+    }
+
+    fn upload_fxaa_uniform(&self) {
+        let config = &self.render_device.config;
+        let data = FxaaUniformData::new(&self.fxaa_settings, config.width, config.height);
+        self.render_device
+            .write_buffer(&self.fxaa_uniform_buffer, bytemuck::bytes_of(&data), 0);
+    }
+
+    // The app draws with its overlay, this is for the tests
+    #[cfg(feature = "test-harness")]
+    pub fn render(&mut self) -> Result<(), RendererError> {
+        self.render_with_overlay(|_, _| {})
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        if !self.render_device.is_surface_configured {
+    // The overlay draws on top of the finished frame with its own encoder and pipelines,
+    // e.g. a debug UI
+    pub fn render_with_overlay(
+        &mut self,
+        overlay: impl FnOnce(&RenderDevice, &wgpu::TextureView),
+    ) -> Result<(), RendererError> {
+        // Nothing can be drawn with it anymore
+        if self.render_device.is_lost() {
+            return Err(RendererError::DeviceLost);
+        }
+        // What was submitted for the frame is dropped, it would pile up until the next one
+        if self.suspended || !self.render_device.is_surface_configured {
+            self.render_data.reset();
+            return Ok(());
+        }
+
+        // Caps the frames queued on the GPU, the swapchain alone lets some drivers queue more
+        let max_frames_in_flight = self.render_device.get_max_frames_in_flight();
+        self.cpu_wait = std::mem::take(&mut self.pending_cpu_wait)
+            + self.render_device.wait_for_frames(max_frames_in_flight - 1);
+        self.drop_released_resources();
+        if let Some(timer) = self.gpu_timer.get_mut() {
+            timer.begin_frame(&self.render_device);
+        }
+
+        let draw_data = self.prepare_frame();
+
+        let Some(surface) = &self.render_device.surface else {
             return Ok(());
+        };
+        let output = surface.get_current_texture()?;
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let cull_readback = self.draw_frame(&draw_data, &view);
+        // Without the overlay, it is a debug UI
+        if std::mem::take(&mut self.screenshot_requested) {
+            self.screenshot = self.read_screenshot(&output.texture);
+        }
+        overlay(&self.render_device, &view);
+        self.render_device.end_frame();
+        {
+            profile_scope!("Present");
+            output.present();
+        }
+        self.presented_frame_count += 1;
+        if let Some(readback) = cull_readback {
+            self.read_gpu_cull_counts(readback);
+        }
+
+        // Losing the device mid-frame also makes everything after it invalid
+        if self.render_device.is_lost() {
+            return Err(RendererError::DeviceLost);
+        }
+        // Also the ones from between frames, e.g. loading
+        let errors = self.render_device.take_validation_errors();
+        if !errors.is_empty() {
+            return Err(RendererError::Validation(errors.join("\n")));
+        }
+
+        Ok(())
+    }
+
+    // The next presented frame is copied back, see take_screenshot
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    pub fn take_screenshot(&mut self) -> Option<Screenshot> {
+        self.screenshot.take()
+    }
+
+    fn read_screenshot(&self, texture: &wgpu::Texture) -> Option<Screenshot> {
+        if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            log::warn!("The surface can't be copied from, no screenshot was taken");
+            return None;
+        }
+
+        let mut pixels = self.read_texture(texture);
+        if self.render_device.is_surface_bgra() {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Some(Screenshot {
+            size: UVec2::new(texture.width(), texture.height()),
+            pixels,
+        })
+    }
+
+    // Waits for the GPU and copies a texture with 4 bytes per pixel back, the top row first
+    pub fn read_texture(&self, texture: &wgpu::Texture) -> Vec<u8> {
+        let render_device = &self.render_device;
+        let (width, height) = (texture.width(), texture.height());
+
+        // Rows in buffer copies have to be aligned
+        let unpadded_bytes_per_row = width * 4;
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let bytes_per_row = unpadded_bytes_per_row.div_ceil(alignment) * alignment;
+
+        let readback_buffer = render_device.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (bytes_per_row * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder =
+            render_device
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Readback Encoder"),
+                });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &readback_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        render_device
+            .queue
+            .submit(std::iter::once(encoder.finish()));
+
+        let slice = readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| {
+            result.expect("Failed to map the readback buffer")
+        });
+        render_device
+            .device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .expect("Failed to wait for the readback");
+
+        let mapped = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        for row in mapped.chunks(bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
         }
+        drop(mapped);
+        readback_buffer.unmap();
+        pixels
+    }
+
+    // Makes wgpu report a validation error, to check it reaches the player
+    pub fn trigger_validation_error(&self) {
+        // Creating it is already invalid, the buffer itself is never used
+        let _ = self
+            .render_device
+            .device
+            .create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Invalid Buffer"),
+                size: 4,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::MAP_WRITE,
+                mapped_at_creation: false,
+            });
+    }
+
+    // Loses the device like a driver reset would, to check the recovery
+    pub fn trigger_device_loss(&self) {
+        self.render_device.device.destroy();
+        // wgpu reports the loss once the queue is empty
+        let _ = self
+            .render_device
+            .device
+            .poll(wgpu::PollType::wait_indefinitely());
+    }
+
+    #[allow(dead_code)]
+    pub fn is_device_lost(&self) -> bool {
+        self.render_device.is_lost()
+    }
+
+    // Keeps the files of the resources loaded from now on, so recreate can load them again.
+    // Without it only meshes made on the CPU, animations and sprite regions survive.
+    pub fn set_keep_cpu_copy(&mut self, enabled: bool) {
+        self.resource_pool.set_keep_cpu_copy(enabled);
+    }
+
+    // After the device was lost, makes a new one for the window and everything on it again.
+    // The resources keep their handles, see recreate_resources. Dynamic meshes are empty
+    // until their next update.
+    pub async fn recreate(
+        &mut self,
+        window: &Arc<Window>,
+        transparent: bool,
+    ) -> anyhow::Result<()> {
+        // A window can only have one surface at a time
+        self.render_device.surface = None;
+        let render_device = RenderDevice::new(window, transparent).await?;
+        self.recreate_from(render_device);
+        Ok(())
+    }
+
+    #[cfg(feature = "test-harness")]
+    pub async fn recreate_headless(&mut self) -> anyhow::Result<()> {
+        let size = self.render_device.get_window_size();
+        let mut render_device = RenderDevice::new_headless(size.x, size.y).await?;
+        if self.render_device.get_instance_path() == InstancePath::VertexBuffers {
+            render_device.force_instance_vertex_buffers();
+        }
+        self.recreate_from(render_device);
+        Ok(())
+    }
 
+    fn recreate_from(&mut self, render_device: RenderDevice) {
+        let size = self.render_device.get_window_size();
+        let old = std::mem::replace(self, Self::from_device(render_device));
+
+        // The settings and whatever the game submitted carry over
+        self.render_device
+            .set_max_frames_in_flight(old.render_device.get_max_frames_in_flight());
+        self.render_device.set_vsync(old.render_device.is_vsync());
+        self.camera_projection_matrix = old.camera_projection_matrix;
+        self.camera_transform = old.camera_transform;
+        self.camera_override = old.camera_override;
+        self.fov_override = old.fov_override;
+        self.directional_light = old.directional_light;
+        self.ambient_light = old.ambient_light;
+        self.fog = old.fog;
+        // Before the persistent instances, their bind groups sample the shadow map
+        self.set_shadow_map_size(old.shadow_map_size);
+        self.light_debug_enabled = old.light_debug_enabled;
+        self.ui_viewport = old.ui_viewport;
+        self.safe_area_debug_enabled = old.safe_area_debug_enabled;
+        self.xray_enabled = old.xray_enabled;
+        self.letterbox = old.letterbox;
+        self.set_debug_view(old.debug_view);
+        self.layer_mask = old.layer_mask;
+        self.low_latency = old.low_latency;
+        self.render_data = old.render_data;
+        self.sprite_atlas_sizes = old.sprite_atlas_sizes;
+        self.material_sources = old.material_sources;
+        self.resource_scopes = old.resource_scopes;
+        for (handle, persistent) in old.persistent_instances {
+            let persistent =
+                self.create_persistent_buffer(persistent.instances, persistent.spheres);
+            self.persistent_instances.insert(handle, persistent);
+        }
+        self.gpu_culling = old.gpu_culling;
+        self.gpu_culling_validation = old.gpu_culling_validation;
+        self.captured_batches = old.captured_batches;
+        self.budget_warnings = old.budget_warnings;
+        self.presented_frame_count = old.presented_frame_count;
+        self.fxaa_settings = old.fxaa_settings;
+        self.set_world_grid(old.world_grid);
+        self.render_scale = old.render_scale;
+        self.resize(size.x, size.y);
+        self.set_antialiasing(old.aa_mode);
+
+        let lost = self.recreate_resources(old.resource_pool);
+        if lost.is_empty() {
+            log::info!("Recreated the renderer with all of its resources");
+        } else {
+            log::warn!(
+                "Recreated the renderer, {} resources were lost since their files weren't kept: {}",
+                lost.len(),
+                lost.join(", ")
+            );
+        }
+    }
+
+    // The built-in resources are made by from_device already. Static meshes are made again
+    // from their CPU copy, loaded files from the sources the pool kept and materials from
+    // what they were made from. Returns the names of the ones that can't be made again.
+    fn recreate_resources(&mut self, mut old_pool: ResourcePool) -> Vec<String> {
+        self.resource_pool
+            .set_keep_cpu_copy(old_pool.is_keeping_cpu_copy());
+
+        let mut lost = Vec::new();
+        let handles: Vec<_> = old_pool.iter().collect();
+        for (handle, kind) in handles {
+            let is_built_in = self.resource_pool.get_resource(handle).is_some();
+            let is_material = self.material_sources.contains_key(&handle);
+            if is_built_in || (kind == ResourceKind::MaterialInstance && is_material) {
+                continue;
+            }
+
+            let name = old_pool.get_name(handle).map(str::to_string);
+            let source = old_pool.get_source(handle).cloned();
+            let Some(resource) = old_pool.remove_resource(handle) else {
+                continue;
+            };
+            match self.recreate_resource(resource, source.as_ref()) {
+                Some(resource) => {
+                    self.add_recreated_resource(handle, name.as_deref(), resource);
+                    if let Some(source) = source {
+                        self.resource_pool
+                            .keep_source(handle, source.kind, &source.bytes);
+                    }
+                }
+                None => lost.push((handle, name)),
+            }
+        }
+
+        // The atlases of rasterized glyphs have no file, they are made from the runtime font
+        #[cfg(feature = "runtime-font")]
+        for (handle, _) in self.resource_pool.iter().collect::<Vec<_>>() {
+            let Some(runtime) = self
+                .resource_pool
+                .get_font(handle)
+                .and_then(|font| font.runtime.as_ref())
+            else {
+                continue;
+            };
+            let texture = runtime.texture;
+            let atlas = self.render_device.create_texture(&runtime.get_atlas_desc());
+            let name = lost
+                .iter()
+                .position(|(lost, _)| *lost == texture)
+                .and_then(|index| lost.remove(index).1);
+            self.add_recreated_resource(texture, name.as_deref(), Resource::Texture(atlas));
+        }
+
+        let materials: Vec<_> = self
+            .material_sources
+            .iter()
+            .map(|(&material, &source)| (material, source))
+            .collect();
+        for (material, source) in materials {
+            let name = old_pool.get_name(material);
+            match self.build_material_instance(source) {
+                Some(material_instance) => self.add_recreated_resource(
+                    material,
+                    name,
+                    Resource::MaterialInstance(material_instance),
+                ),
+                None => lost.push((material, name.map(str::to_string))),
+            }
+        }
+
+        lost.into_iter()
+            .map(|(handle, name)| name.unwrap_or_else(|| format!("{:#x}", handle)))
+            .collect()
+    }
+
+    fn recreate_resource(
+        &self,
+        resource: Resource,
+        source: Option<&ResourceSource>,
+    ) -> Option<Resource> {
+        match resource {
+            Resource::StaticMesh(mesh) => self
+                .render_device
+                .create_mesh(&MeshLoadDesc {
+                    vertex_data: bytemuck::cast_slice(&mesh.vertices).to_vec(),
+                    indices: mesh.indices,
+                    lods: mesh.lods,
+                    ..Default::default()
+                })
+                .ok()
+                .map(Resource::StaticMesh),
+            Resource::DynamicMesh(mesh) => Some(Resource::DynamicMesh(
+                self.render_device.create_dynamic_mesh(mesh.vertex_capacity),
+            )),
+            Resource::Animation(_) | Resource::SpriteRegion(_) => Some(resource),
+            Resource::SkeletalMesh(_) | Resource::Texture(_) => self.load_source(source),
+            #[cfg_attr(not(feature = "runtime-font"), allow(unused_variables))]
+            Resource::Font(old_font) => {
+                #[allow(unused_mut)]
+                let mut font = self.load_source(source)?;
+                // The rasterized glyphs don't come from the file, so they are kept
+                #[cfg(feature = "runtime-font")]
+                if let Resource::Font(font) = &mut font {
+                    font.runtime = old_font.runtime;
+                }
+                Some(font)
+            }
+            Resource::MaterialPipeline(_) | Resource::MaterialInstance(_) => None,
+        }
+    }
+
+    fn load_source(&self, source: Option<&ResourceSource>) -> Option<Resource> {
+        let source = source?;
+        self.load_resource(source.kind, &source.bytes)
+            .inspect_err(|error| {
+                log::error!(
+                    "Failed to load a {} again: {:#}",
+                    source.kind.get_name(),
+                    error
+                )
+            })
+            .ok()
+    }
+
+    // Under its name again when it had one, handles of named resources are their hash
+    fn add_recreated_resource(
+        &mut self,
+        handle: ResourceHandle,
+        name: Option<&str>,
+        resource: Resource,
+    ) {
+        match name {
+            Some(name) => {
+                self.resource_pool.add_named_resource(name, resource);
+            }
+            None => self.resource_pool.add_resource(handle, resource),
+        }
+    }
+
+    // Renders the frame into any view with the surface format, instead of the swapchain
+    #[cfg(feature = "test-harness")]
+    pub fn render_to_view(&mut self, target: &wgpu::TextureView) {
+        if let Some(timer) = self.gpu_timer.get_mut() {
+            timer.begin_frame(&self.render_device);
+        }
+        let draw_data = self.prepare_frame();
+        if let Some(readback) = self.draw_frame(&draw_data, target) {
+            self.read_gpu_cull_counts(readback);
+        }
+    }
+
+    pub fn get_device_diagnostics(&self) -> DeviceDiagnostics {
+        self.render_device.get_diagnostics()
+    }
+
+    #[cfg(any(feature = "test-harness", feature = "inspector"))]
+    pub fn get_render_device(&self) -> &RenderDevice {
+        &self.render_device
+    }
+
+    fn prepare_frame(&mut self) -> DrawData {
         self.upload_uniform_buffer();
 
-        let draw_data = self.render_data.build_draw_data();
+        if self.light_debug_enabled {
+            self.draw_light_debug();
+        }
+        if self.safe_area_debug_enabled {
+            self.draw_safe_area_debug();
+        }
+
+        #[cfg(feature = "runtime-font")]
+        self.rasterize_missing_glyphs();
+
+        let (mut draw_data, frame_stats) = self.render_data.build_draw_data(self.layer_mask);
+        let resource_pool = &self.resource_pool;
+        draw_data.additive_batches = draw_data
+            .static_batches
+            .extract_if(.., |batch| {
+                resource_pool
+                    .get_material_instance(batch.material_instance)
+                    .is_some_and(|material| material.additive)
+            })
+            .collect();
+        if !self.xray_enabled {
+            draw_data.xray_batches.clear();
+        }
+        self.check_budgets(&frame_stats);
+        self.frame_stats = frame_stats;
+        if let Some(captured) = &mut self.captured_batches {
+            draw_data.list_batches(captured);
+        }
 
         self.upload_draw_data(&draw_data);
 
-        self.draw_frame(&draw_data)
+        draw_data
+    }
+
+    fn check_budgets(&mut self, stats: &FrameStats) {
+        let budgets = [
+            (
+                "static instance",
+                stats.static_instance_count,
+                self.instance_caps.static_instances,
+            ),
+            (
+                "skeletal instance",
+                stats.skeletal_instance_count,
+                self.instance_caps.static_instances,
+            ),
+            ("bone", stats.bone_count, self.instance_caps.bones),
+            (
+                "sprite instance",
+                stats.sprite_instance_count,
+                self.instance_caps.sprite_instances,
+            ),
+        ];
+
+        for (i, (name, count, capacity)) in budgets.into_iter().enumerate() {
+            let bit = 1 << i;
+            if self.budget_warnings & bit == 0
+                && count as f32 >= capacity as f32 * Self::BUDGET_WARNING_THRESHOLD
+            {
+                log::warn!(
+                    "The {} count is at {}/{} of the buffer capacity, consider raising it.",
+                    name,
+                    count,
+                    capacity
+                );
+                self.budget_warnings |= bit;
+            }
+        }
+    }
+
+    pub fn get_frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    // Jobs on none of these render layers are not drawn by the main camera
+    #[allow(dead_code)]
+    pub fn set_layer_mask(&mut self, layer_mask: u32) {
+        self.layer_mask = layer_mask;
+    }
+
+    #[allow(dead_code)]
+    pub fn get_layer_mask(&self) -> u32 {
+        self.layer_mask
+    }
+
+    // In pixels
+    pub fn get_screen_size(&self) -> Vec2 {
+        Vec2::from(self.sprite_uniform_data.screen_size)
+    }
+
+    // Frames handed to the swapchain, skipped frames are not counted
+    pub fn get_presented_frame_count(&self) -> u64 {
+        self.presented_frame_count
+    }
+
+    #[allow(dead_code)]
+    pub fn set_batch_capture_enabled(&mut self, enabled: bool) {
+        self.captured_batches = enabled.then(Vec::new);
+    }
+
+    pub fn is_batch_capture_enabled(&self) -> bool {
+        self.captured_batches.is_some()
+    }
+
+    // The batches of the last frame, empty unless capturing is enabled
+    #[allow(dead_code)]
+    pub fn get_captured_batches(&self) -> &[BatchInfo] {
+        self.captured_batches.as_deref().unwrap_or_default()
+    }
+
+    // Materials that could be one, the batches they split are counted in the captured
+    // batches, see set_batch_capture_enabled
+    pub fn get_duplicate_materials(&self) -> Vec<DuplicateMaterials> {
+        find_duplicate_materials(&self.resource_pool, self.get_captured_batches())
+    }
+
+    // Draws the duplicates with the first material of each, returns how many were remapped
+    pub fn dedupe_materials(&mut self) -> usize {
+        let mut count = 0;
+        for duplicates in self.get_duplicate_materials() {
+            let (canonical, rest) = duplicates
+                .materials
+                .split_first()
+                .expect("A duplicate has at least two materials");
+            for &duplicate in rest {
+                self.resource_pool.set_material_alias(duplicate, *canonical);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn get_resource_pool(&self) -> &ResourcePool {
+        &self.resource_pool
+    }
+
+    pub fn get_texture_size(&self, handle: ResourceHandle) -> Option<UVec2> {
+        self.resource_pool
+            .get_texture(handle)
+            .map(|texture| UVec2::new(texture._texture.width(), texture._texture.height()))
+    }
+
+    // How wide a text job with the font and size would be, 0.0 when the font isn't loaded
+    pub fn get_text_width(&self, font_atlas: ResourceHandle, text: &str, size: f32) -> f32 {
+        self.resource_pool
+            .get_font(font_atlas)
+            .map_or(0.0, |font| font.get_text_width(text, size))
     }
 
     fn upload_uniform_buffer(&mut self) {
-        self.uniform_data.projection_matrix = self.camera_projection_matrix.to_data();
+        let projection = self.get_camera_projection();
+        self.uniform_data.projection_matrix = projection.to_data();
 
         self.camera_transform.rotation *= Quat::from_rotation_y(f32::to_radians(0.1));
-        let view_matrix = self.camera_transform.to_matrix().inverse();
+        let camera = self.camera_override.unwrap_or(self.camera_transform);
+        let view_matrix = camera.to_matrix().inverse();
         self.uniform_data.view_matrix = view_matrix.to_data();
-        self.uniform_data.camera_position = [
-            self.camera_transform.position.x,
-            self.camera_transform.position.y,
-            self.camera_transform.position.z,
-            0.0,
-        ];
-        self.uniform_data.light_matrix = Self::compute_directional_light_vp(
+        self.uniform_data.inverse_view_projection = (projection * view_matrix).inverse().to_data();
+        self.uniform_data.camera_position = camera.position.extend(0.0).to_array();
+
+        let light = &self.directional_light;
+        let radiance = light.get_radiance();
+        self.uniform_data.light_direction = light
+            .direction
+            .extend(if light.shadows_enabled { 1.0 } else { 0.0 })
+            .to_array();
+        self.uniform_data.light_color = radiance.extend(1.0).to_array();
+        let light_matrix = Self::compute_directional_light_vp(
             view_matrix,
-            self.camera_projection_matrix,
-            Vec3::from_slice(&self.uniform_data.light_direction),
-        )
-        .to_data();
+            projection,
+            light.direction,
+            light.shadow_depth_extension,
+            self.shadow_map_size,
+        );
+        self.uniform_data.light_matrix = light_matrix.to_data();
+        let camera_view_projection = projection * view_matrix;
+        self.render_data
+            .set_cull_view_projections(camera_view_projection, light_matrix);
+        self.cull_planes = get_frustum_planes(camera_view_projection);
+
+        let ambient = &self.ambient_light;
+        self.uniform_data.ambient_top = ambient.top_color.extend(ambient.intensity).to_array();
+        self.uniform_data.ambient_bottom = ambient.bottom_color.extend(1.0).to_array();
+        match self.fog {
+            Some(fog) => {
+                self.uniform_data.fog_color = fog.color.extend(1.0).to_array();
+                self.uniform_data.fog_range = [fog.start, fog.end, 0.0, 0.0];
+            }
+            None => self.uniform_data.fog_color = [0.0; 4],
+        }
+        let wind = &self.wind;
+        self.uniform_data.wind = [
+            wind.direction.x,
+            wind.direction.y,
+            wind.get_strength(self.wind_time),
+            self.wind_time,
+        ];
 
         self.render_device.write_buffer(
             &self.uniform_buffer,
@@ -992,10 +2786,9 @@ impl Renderer {
             0,
         );
 
-        self.render_device.write_buffer(
+        self.render_device.write_shader_data(
             &self.bone_buffer,
             bytemuck::cast_slice(draw_data.bones.as_slice()),
-            0,
         );
 
         self.render_device.write_buffer(
@@ -1003,14 +2796,55 @@ impl Renderer {
             bytemuck::cast_slice(draw_data.sprite_instances.as_slice()),
             0,
         );
-    }
 
-    fn draw_frame(&self, draw_data: &DrawData) -> Result<(), wgpu::SurfaceError> {
-        let output = self.render_device.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        // Grows like the dynamic meshes, the bind group is made again for the new buffer
+        if draw_data.sprite_params.len() > self.sprite_params_capacity {
+            self.sprite_params_capacity =
+                get_grown_capacity(self.sprite_params_capacity, draw_data.sprite_params.len());
+            self.sprite_params_buffer =
+                Self::create_sprite_params_buffer(&self.render_device, self.sprite_params_capacity);
+            self.render_device.rebind_collection(
+                &mut self.sprite_bind_collection,
+                Self::get_sprite_bind_entries(
+                    &self.render_device,
+                    &self.sprite_uniform_buffer,
+                    &self.sprite_instance_buffer,
+                    &self.sprite_params_buffer,
+                ),
+            );
+        }
+        self.render_device.write_shader_data(
+            &self.sprite_params_buffer,
+            bytemuck::cast_slice(draw_data.sprite_params.as_slice()),
+        );
 
+        let line_vertex_count = draw_data
+            .debug_line_vertices
+            .len()
+            .min(Self::DEBUG_LINE_COUNT * 2);
+        self.render_device.write_buffer(
+            &self.debug_line_buffer,
+            bytemuck::cast_slice(&draw_data.debug_line_vertices[..line_vertex_count]),
+            0,
+        );
+
+        let gizmo_vertex_count = draw_data
+            .gizmo_line_vertices
+            .len()
+            .min(Self::GIZMO_LINE_COUNT * 2);
+        self.render_device.write_buffer(
+            &self.gizmo_line_buffer,
+            bytemuck::cast_slice(&draw_data.gizmo_line_vertices[..gizmo_vertex_count]),
+            0,
+        );
+    }
+
+    fn draw_frame(&self, draw_data: &DrawData, view: &wgpu::TextureView) -> Option<CullReadback> {
+        profile_scope!("Draw frame");
+        let clear_color = wgpu::Color {
+            a: self.render_device.get_clear_alpha(),
+            ..wgpu::Color::BLACK
+        };
         let mut encoder =
             self.render_device
                 .device
@@ -1018,130 +2852,441 @@ impl Renderer {
                     label: Some("Render Encoder"),
                 });
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Shadow Pass"),
-                color_attachments: &[],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.shadow_map.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
+        let (gpu_culled, cull_readback) = self.cull_on_gpu(&mut encoder, draw_data);
 
-            self.render_batches(
-                &mut render_pass,
-                &self.shadow_material_pipeline.static_material_pipeline,
-                &[&self.static_shadow_bind_collection.bind_group],
-                &draw_data.static_batches,
+        let mut graph = FrameGraph::new();
+        graph.import(SHADOW_MAP, &self.shadow_map.view);
+        graph.import(SCENE_DEPTH, &self.depth_buffer.view);
+        graph.import(SCENE, &self.scene_texture.view);
+        if let Some(msaa_texture) = &self.scene_msaa_texture {
+            graph.import(SCENE_MSAA, &msaa_texture.view);
+        }
+        graph.import(LDR, &self.ldr_texture.view);
+        graph.import(SURFACE, view);
+
+        // The pass still runs to clear the map, the shader ignores it when shadows are off
+        graph.add_pass(
+            PassDesc::new("Shadow Pass").depth(SHADOW_MAP, Some(1.0)),
+            |render_pass, _| {
+                if self.directional_light.shadows_enabled {
+                    self.draw_shadows(render_pass, draw_data);
+                }
+            },
+        );
+
+        let scene_pass = match self.scene_msaa_texture {
+            Some(_) => PassDesc::new("Scene Pass").resolved_color(
+                SCENE_MSAA,
+                Some(SCENE),
+                Some(clear_color),
+            ),
+            None => PassDesc::new("Scene Pass").color(SCENE, Some(clear_color)),
+        };
+        graph.add_pass(
+            scene_pass.read(SHADOW_MAP).depth(SCENE_DEPTH, Some(1.0)),
+            |render_pass, _| self.draw_scene(render_pass, draw_data, &gpu_culled),
+        );
+
+        // FXAA needs the composited image as input, so it is drawn to the LDR texture first
+        let fxaa_enabled = self.aa_mode == AaMode::Fxaa;
+        if fxaa_enabled {
+            graph.add_pass(
+                PassDesc::new("Tonemap Pass")
+                    .read(SCENE)
+                    .color(LDR, Some(clear_color)),
+                |render_pass, _| {
+                    self.draw_fullscreen(
+                        render_pass,
+                        &self.composite_material_pipeline,
+                        &self.composite_bind_collection,
+                    )
+                },
             );
+        }
 
-            self.render_batches(
-                &mut render_pass,
-                &self.shadow_material_pipeline.skeletal_material_pipeline,
-                &[&self.skeletal_shadow_bind_collection.bind_group],
-                &draw_data.skeletal_batches,
+        let (composite_input, composite_pipeline, composite_bind_collection) = if fxaa_enabled {
+            (
+                LDR,
+                &self.fxaa_material_pipeline,
+                &self.fxaa_bind_collection,
+            )
+        } else {
+            (
+                SCENE,
+                &self.composite_material_pipeline,
+                &self.composite_bind_collection,
+            )
+        };
+        // The clear of the surface is what fills the bars
+        let letterbox = self
+            .letterbox
+            .then(|| self.ui_viewport.get_letterbox())
+            .flatten();
+        let cut_to_letterbox = |render_pass: &mut wgpu::RenderPass| {
+            if let Some((position, size)) = letterbox {
+                render_pass.set_scissor_rect(position.x, position.y, size.x, size.y);
+            }
+        };
+        graph.add_pass(
+            PassDesc::new("Composite Pass")
+                .read(composite_input)
+                .color(SURFACE, Some(clear_color)),
+            |render_pass, _| {
+                cut_to_letterbox(render_pass);
+                self.draw_fullscreen(render_pass, composite_pipeline, composite_bind_collection)
+            },
+        );
+
+        // Over the scene and under the UI, not in screenshots
+        if self.world_grid.is_some() && !self.screenshot_requested {
+            graph.add_pass(
+                PassDesc::new("World Grid Pass")
+                    .read(SCENE_DEPTH)
+                    .color(SURFACE, None),
+                |render_pass, _| {
+                    cut_to_letterbox(render_pass);
+                    self.draw_fullscreen(
+                        render_pass,
+                        &self.world_grid_material_pipeline,
+                        &self.world_grid_bind_collection,
+                    )
+                },
             );
         }
 
+        graph.add_pass(
+            PassDesc::new("Sprite Pass").color(SURFACE, None),
+            |render_pass, _| {
+                self.render_batches(
+                    render_pass,
+                    &self.sprite_material_pipeline,
+                    &[&self.sprite_bind_collection.bind_group],
+                    &self.sprite_instance_buffer,
+                    &draw_data.sprite_batches,
+                )
+            },
+        );
+
+        let mut gpu_timer = self.gpu_timer.borrow_mut();
+        if let Err(error) = graph.execute(
+            &self.render_device,
+            &mut self.transient_pool.borrow_mut(),
+            &mut encoder,
+            gpu_timer.as_mut(),
+        ) {
+            log::error!("Unable to draw the frame: {}", error);
+        }
+
         {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Scene Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &self.scene_texture.view,
-                    resolve_target: None,
-                    depth_slice: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_buffer.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
+            profile_scope!("GPU submit");
+            self.render_device
+                .queue
+                .submit(std::iter::once(encoder.finish()));
+        }
+        if let Some(timer) = gpu_timer.as_mut() {
+            timer.end_frame();
+        }
 
-            self.render_batches(
-                &mut render_pass,
-                &self.scene_material_pipeline.static_material_pipeline,
-                &[&self.static_scene_bind_collection.bind_group],
-                &draw_data.static_batches,
-            );
+        cull_readback
+    }
 
-            self.render_batches(
-                &mut render_pass,
-                &self.scene_material_pipeline.skeletal_material_pipeline,
-                &[&self.skeletal_scene_bind_collection.bind_group],
-                &draw_data.skeletal_batches,
-            );
+    fn draw_shadows(&self, render_pass: &mut wgpu::RenderPass, draw_data: &DrawData) {
+        self.render_batches(
+            render_pass,
+            &self.shadow_material_pipeline.static_material_pipeline,
+            &[&self.static_shadow_bind_collection.bind_group],
+            &self.static_instance_buffer,
+            &draw_data.shadow_static_batches,
+        );
+
+        for persistent in &draw_data.shadow_persistent_batches {
+            if let Some(instances) = self.persistent_instances.get(&persistent.instances) {
+                self.render_batches(
+                    render_pass,
+                    &self.shadow_material_pipeline.static_material_pipeline,
+                    &[&instances.shadow_bind_group],
+                    &instances.buffer,
+                    &persistent.batches,
+                );
+            }
         }
 
+        self.render_batches(
+            render_pass,
+            &self.shadow_material_pipeline.skeletal_material_pipeline,
+            &[&self.skeletal_shadow_bind_collection.bind_group],
+            &self.skeletal_instance_buffer,
+            &draw_data.shadow_skeletal_batches,
+        );
+    }
+
+    fn draw_scene(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        draw_data: &DrawData,
+        gpu_culled: &[ResourceHandle],
+    ) {
+        let scene_pipelines = self.get_scene_pipelines();
+        self.render_batches(
+            render_pass,
+            &scene_pipelines.static_material_pipeline,
+            &[&self.static_scene_bind_collection.bind_group],
+            &self.static_instance_buffer,
+            &draw_data.static_batches,
+        );
+
+        for persistent in &draw_data.persistent_batches {
+            if gpu_culled.contains(&persistent.instances) {
+                self.draw_gpu_culled(render_pass, persistent);
+            } else if let Some(instances) = self.persistent_instances.get(&persistent.instances) {
+                self.render_batches(
+                    render_pass,
+                    &scene_pipelines.static_material_pipeline,
+                    &[&instances.scene_bind_group],
+                    &instances.buffer,
+                    &persistent.batches,
+                );
+            }
+        }
+
+        self.render_batches(
+            render_pass,
+            &scene_pipelines.skeletal_material_pipeline,
+            &[&self.skeletal_scene_bind_collection.bind_group],
+            &self.skeletal_instance_buffer,
+            &draw_data.skeletal_batches,
+        );
+
+        self.render_batches(
+            render_pass,
+            &self.weight_debug_material_pipeline,
+            &[&self.skeletal_scene_bind_collection.bind_group],
+            &self.skeletal_instance_buffer,
+            &draw_data.weight_debug_batches,
+        );
+
+        // Only where the opaque meshes above are in front, before the blended passes
+        self.render_batches(
+            render_pass,
+            &self.xray_material_pipeline,
+            &[&self.skeletal_scene_bind_collection.bind_group],
+            &self.skeletal_instance_buffer,
+            &draw_data.xray_batches,
+        );
+
+        // After everything that writes depth
+        self.render_batches(
+            render_pass,
+            if self.debug_view.replaces_additive() {
+                &scene_pipelines.static_material_pipeline
+            } else {
+                &self.additive_material_pipeline
+            },
+            &[&self.static_scene_bind_collection.bind_group],
+            &self.static_instance_buffer,
+            &draw_data.additive_batches,
+        );
+
+        let line_vertex_count = draw_data
+            .debug_line_vertices
+            .len()
+            .min(Self::DEBUG_LINE_COUNT * 2) as u32;
+        if line_vertex_count > 0 {
+            render_pass.set_pipeline(&self.debug_line_material_pipeline.pipeline);
+            render_pass.set_bind_group(0, &self.debug_line_bind_collection.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.debug_line_buffer.buffer.slice(..));
+            render_pass.draw(0..line_vertex_count, 0..1);
+        }
+
+        // Last, nothing in the scene hides them
+        let gizmo_vertex_count = draw_data
+            .gizmo_line_vertices
+            .len()
+            .min(Self::GIZMO_LINE_COUNT * 2) as u32;
+        if gizmo_vertex_count > 0 {
+            render_pass.set_pipeline(&self.gizmo_material_pipeline.pipeline);
+            render_pass.set_bind_group(0, &self.debug_line_bind_collection.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.gizmo_line_buffer.buffer.slice(..));
+            render_pass.draw(0..gizmo_vertex_count, 0..1);
+        }
+    }
+
+    // Culls the persistent instances the chunks left in against the camera, instance by
+    // instance. Returns the ones that were culled, with their counts copied out when they
+    // are validated.
+    fn cull_on_gpu(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        draw_data: &DrawData,
+    ) -> (Vec<ResourceHandle>, Option<CullReadback>) {
+        let Some(cull) = self.cull_pipeline.as_ref().filter(|_| self.gpu_culling) else {
+            return (Vec::new(), None);
+        };
+        let uniform_data = CullUniformData {
+            planes: self.cull_planes.map(|plane| plane.to_array()),
+        };
+        self.render_device
+            .write_buffer(&cull.uniform_buffer, bytemuck::bytes_of(&uniform_data), 0);
+
+        let mut culled = Vec::new();
+        let mut counts = Vec::new();
         {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Composite Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    depth_slice: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Cull Pass"),
                 timestamp_writes: None,
-                occlusion_query_set: None,
             });
-
-            // Full screen quad draw
-            {
-                render_pass.set_pipeline(&self.composite_material_pipeline.pipeline);
-                render_pass.set_bind_group(0, &self.composite_bind_collection.bind_group, &[]);
-                let draw_info = self.screen_mesh.get_draw_info();
-                render_pass.set_vertex_buffer(0, draw_info.vertex_slice);
-                render_pass.set_index_buffer(draw_info.index_slice, wgpu::IndexFormat::Uint32);
-                render_pass.draw_indexed(0..draw_info.index_count, 0, 0..1);
+            compute_pass.set_pipeline(&cull.compute_pipeline.pipeline);
+
+            for persistent in &draw_data.persistent_batches {
+                let Some(instances) = self.persistent_instances.get(&persistent.instances) else {
+                    continue;
+                };
+                let Some(gpu_culled) = &instances.gpu_culled else {
+                    continue;
+                };
+                let Some(draw_info) = persistent
+                    .batches
+                    .first()
+                    .and_then(|batch| self.resource_pool.get_mesh_draw_info(batch.mesh, 0))
+                else {
+                    continue;
+                };
+
+                // The shader counts the instances up from zero
+                self.render_device.write_indirect_args(
+                    &gpu_culled.draw_args,
+                    0,
+                    &DrawIndexedIndirectArgs {
+                        index_count: draw_info.index_range.len() as u32,
+                        instance_count: 0,
+                        first_index: draw_info.index_range.start,
+                        base_vertex: 0,
+                        first_instance: 0,
+                    },
+                );
+                compute_pass.set_bind_group(0, &gpu_culled.cull_bind_group, &[]);
+                let workgroup_count = instances
+                    .spheres
+                    .len()
+                    .div_ceil(CULL_WORKGROUP_SIZE as usize);
+                compute_pass.dispatch_workgroups(workgroup_count as u32, 1, 1);
+
+                culled.push(persistent.instances);
+                if self.gpu_culling_validation {
+                    counts.push(GpuCullCount {
+                        instances: persistent.instances,
+                        gpu: 0,
+                        cpu: count_visible_spheres(&self.cull_planes, &instances.spheres),
+                    });
+                }
             }
+        }
 
-            // Sprite Rendering
-            {
-                self.render_batches(
-                    &mut render_pass,
-                    &self.sprite_material_pipeline,
-                    &[&self.sprite_bind_collection.bind_group],
-                    &draw_data.sprite_batches,
+        if counts.is_empty() {
+            return (culled, None);
+        }
+        let count_size = std::mem::size_of::<u32>() as u64;
+        let buffer = self
+            .render_device
+            .device
+            .create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Cull Readback Buffer"),
+                size: counts.len() as u64 * count_size,
+                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+        for (slot, count) in counts.iter().enumerate() {
+            let draw_args = &self.persistent_instances[&count.instances]
+                .gpu_culled
+                .as_ref()
+                .unwrap()
+                .draw_args;
+            // The instance count comes after the index count
+            encoder.copy_buffer_to_buffer(
+                &draw_args.buffer,
+                count_size,
+                &buffer,
+                slot as u64 * count_size,
+                count_size,
+            );
+        }
+
+        (culled, Some(CullReadback { buffer, counts }))
+    }
+
+    // Blocks until the frame is done, only while validating
+    fn read_gpu_cull_counts(&mut self, readback: CullReadback) {
+        let CullReadback { buffer, mut counts } = readback;
+        let slice = buffer.slice(..);
+        let mapped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let on_mapped = mapped.clone();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            on_mapped.store(result.is_ok(), std::sync::atomic::Ordering::Release);
+        });
+        // On the web the buffer is only mapped once the browser gets to it, that frame is skipped
+        let _ = self
+            .render_device
+            .device
+            .poll(wgpu::PollType::wait_indefinitely());
+        if !mapped.load(std::sync::atomic::Ordering::Acquire) {
+            return;
+        }
+
+        let gpu_counts: Vec<u32> = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        buffer.unmap();
+        for (count, gpu) in counts.iter_mut().zip(gpu_counts) {
+            count.gpu = gpu;
+            if count.gpu != count.cpu {
+                log::warn!(
+                    "GPU culling kept {} of the persistent instances {:#x}, the CPU {}",
+                    count.gpu,
+                    count.instances,
+                    count.cpu
                 );
             }
         }
+        self.gpu_cull_counts = counts;
+    }
 
-        self.render_device
-            .queue
-            .submit(std::iter::once(encoder.finish()));
-        output.present();
+    // The instances the cull pass kept, drawn with the arguments it wrote
+    fn draw_gpu_culled(&self, render_pass: &mut wgpu::RenderPass, persistent: &PersistentBatch) {
+        let (Some(batch), Some(gpu_culled)) = (
+            persistent.batches.first(),
+            self.persistent_instances
+                .get(&persistent.instances)
+                .and_then(|instances| instances.gpu_culled.as_ref()),
+        ) else {
+            return;
+        };
+        let material_instance = self
+            .resource_pool
+            .get_material_instance(batch.material_instance)
+            .unwrap();
+        let draw_info = self
+            .resource_pool
+            .get_mesh_draw_info(batch.mesh, 0)
+            .unwrap();
+
+        render_pass.set_pipeline(&self.get_scene_pipelines().static_material_pipeline.pipeline);
+        render_pass.set_bind_group(0, &gpu_culled.scene_bind_group, &[]);
+        render_pass.set_bind_group(1, &material_instance.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, draw_info.vertex_slice);
+        render_pass.set_index_buffer(draw_info.index_slice, wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed_indirect(&gpu_culled.draw_args.buffer, 0);
+    }
 
-        Ok(())
+    fn draw_fullscreen(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        material_pipeline: &MaterialPipeline,
+        bind_collection: &BindCollection,
+    ) {
+        render_pass.set_pipeline(&material_pipeline.pipeline);
+        render_pass.set_bind_group(0, &bind_collection.bind_group, &[]);
+        let draw_info = self.screen_mesh.get_draw_info(0);
+        render_pass.set_vertex_buffer(0, draw_info.vertex_slice);
+        render_pass.set_index_buffer(draw_info.index_slice, wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(draw_info.index_range, 0, 0..1);
     }
 
     fn render_batches(
@@ -1149,14 +3294,17 @@ impl Renderer {
         render_pass: &mut wgpu::RenderPass,
         material_pipeline: &MaterialPipeline,
         bind_groups: &[&wgpu::BindGroup],
+        instances: &Buffer,
         batches: &[RenderBatch],
     ) {
         render_pass.set_pipeline(&material_pipeline.pipeline);
 
         let mut current_material_instance: Option<ResourceHandle> = None;
-        let mut current_mesh: Option<ResourceHandle> = None;
-        let mut index_count: u32 = 0;
+        let mut current_premultiplied_alpha = material_pipeline.premultiplied_alpha;
+        let mut current_mesh: Option<(ResourceHandle, u8)> = None;
+        let mut index_range = 0..0;
 
+        // Batches are already sorted by their sort key when the draw data is built
         for batch in batches {
             let material_changed = match current_material_instance {
                 Some(handle) => handle != batch.material_instance,
@@ -1169,6 +3317,17 @@ impl Renderer {
                     .get_material_instance(batch.material_instance)
                     .unwrap();
 
+                // Only sprite materials of premultiplied textures blend differently, they
+                // switch to the matching sprite pipeline in between the others
+                if material_instance.premultiplied_alpha != current_premultiplied_alpha {
+                    render_pass.set_pipeline(if material_instance.premultiplied_alpha {
+                        &self.premultiplied_sprite_material_pipeline.pipeline
+                    } else {
+                        &material_pipeline.pipeline
+                    });
+                    current_premultiplied_alpha = material_instance.premultiplied_alpha;
+                }
+
                 let mut bind_group_index: u32 = 0;
                 for bind_group in bind_groups {
                     render_pass.set_bind_group(bind_group_index, *bind_group, &[]);
@@ -1180,28 +3339,89 @@ impl Renderer {
                 current_material_instance = Some(batch.material_instance);
             }
 
-            let mesh_changed = match current_mesh {
-                Some(handle) => handle != batch.mesh,
-                None => true,
-            };
+            // The levels of a mesh share its buffers, only the index range differs
+            let mesh_changed = current_mesh.is_none_or(|(handle, _)| handle != batch.mesh);
+            let lod_changed = current_mesh != Some((batch.mesh, batch.lod));
 
-            if mesh_changed {
-                let mesh_draw_info = self.resource_pool.get_mesh_draw_info(batch.mesh).unwrap();
-                render_pass.set_vertex_buffer(0, mesh_draw_info.vertex_slice);
-                render_pass.set_index_buffer(mesh_draw_info.index_slice, wgpu::IndexFormat::Uint32);
+            if lod_changed {
+                let mesh_draw_info = self
+                    .resource_pool
+                    .get_mesh_draw_info(batch.mesh, batch.lod as usize)
+                    .unwrap();
+                if mesh_changed {
+                    render_pass.set_vertex_buffer(0, mesh_draw_info.vertex_slice);
+                    render_pass
+                        .set_index_buffer(mesh_draw_info.index_slice, wgpu::IndexFormat::Uint32);
+                }
 
-                current_mesh = Some(batch.mesh);
-                index_count = mesh_draw_info.index_count;
+                current_mesh = Some((batch.mesh, batch.lod));
+                index_range = mesh_draw_info.index_range;
             }
 
-            // We can clone the range, it is very small so it is fine
-            render_pass.draw_indexed(0..index_count, 0, batch.instance_range.clone());
+            // We can clone the ranges, they are very small so it is fine
+            let Some(stride) = material_pipeline.instance_stride else {
+                render_pass.draw_indexed(index_range.clone(), 0, batch.instance_range.clone());
+                continue;
+            };
+
+            // WebGL2 can't start at another instance than the first, the instances of the
+            // batch are bound from where they start instead
+            if batch.instance_range.is_empty() {
+                continue;
+            }
+            let start = batch.instance_range.start as wgpu::BufferAddress * stride;
+            let end = batch.instance_range.end as wgpu::BufferAddress * stride;
+            render_pass.set_vertex_buffer(1, instances.buffer.slice(start..end));
+            render_pass.draw_indexed(index_range.clone(), 0, 0..batch.instance_range.len() as u32);
         }
     }
 
     pub fn set_camera_position_and_orientation(&mut self, position: Vec3, orientation: Quat) {
         self.camera_transform.position = position;
         self.camera_transform.rotation = orientation;
+        if self.camera_override.is_none() {
+            self.render_data.set_camera_position(position);
+        }
+    }
+
+    // The camera the game set, even while it is overridden
+    pub fn get_camera_transform(&self) -> Transform {
+        self.camera_transform
+    }
+
+    // Draws from the transform instead of the game camera until it is set back to None
+    pub fn set_camera_override(&mut self, transform: Option<Transform>) {
+        self.camera_override = transform;
+        let camera = transform.unwrap_or(self.camera_transform);
+        self.render_data.set_camera_position(camera.position);
+    }
+
+    // What is drawn from, the override while there is one
+    pub fn get_view_transform(&self) -> Transform {
+        self.camera_override.unwrap_or(self.camera_transform)
+    }
+
+    // Vertical, in radians. The aspect ratio and the depth range of the game's projection stay.
+    pub fn set_fov_override(&mut self, fov: Option<f32>) {
+        self.fov_override = fov;
+    }
+
+    fn get_camera_projection(&self) -> Mat4 {
+        let mut projection = self.camera_projection_matrix;
+        if let Some(fov) = self.fov_override {
+            // Both scale with 1 / tan(fov / 2) in a perspective projection
+            let scale = (1.0 / (fov * 0.5).tan()) / projection.y_axis.y;
+            projection.x_axis.x *= scale;
+            projection.y_axis.y *= scale;
+        }
+        projection
+    }
+
+    // Skeletal meshes with a shadow proxy are skinned in the shadow pass only while closer to
+    // the camera than this, None uses the proxies at any distance
+    #[allow(dead_code)]
+    pub fn set_shadow_proxy_distance(&mut self, distance: Option<f32>) {
+        self.render_data.set_shadow_proxy_distance(distance);
     }
 
     pub fn set_camera_projection(&mut self, projection: Mat4) {
@@ -1209,22 +3429,316 @@ impl Renderer {
     }
 
     #[allow(dead_code)]
+    pub fn get_directional_light(&self) -> &DirectionalLight {
+        &self.directional_light
+    }
+
+    #[allow(dead_code)]
+    pub fn set_directional_light(&mut self, light: DirectionalLight) {
+        self.directional_light = light;
+        self.set_lighting_direction(light.direction);
+    }
+
+    #[allow(dead_code)]
+    pub fn get_shadow_map_size(&self) -> u32 {
+        self.shadow_map_size
+    }
+
+    // Sharper shadows for more memory, clamped to what the device supports. The scene bind
+    // groups sample the shadow map, they are made again on the same layouts.
+    pub fn set_shadow_map_size(&mut self, size: u32) {
+        let max_size = self.render_device.device.limits().max_texture_dimension_2d;
+        let size = size.clamp(Self::MIN_SHADOW_MAP_SIZE, max_size);
+        if size == self.shadow_map_size {
+            return;
+        }
+
+        self.shadow_map = Self::create_shadow_map(&self.render_device, size);
+        self.shadow_map_size = size;
+        let (static_scene, skeletal_scene) = self.create_scene_bind_groups();
+        self.static_scene_bind_collection.bind_group = static_scene;
+        self.skeletal_scene_bind_collection.bind_group = skeletal_scene;
+        let persistent_bind_groups: Vec<_> = self
+            .persistent_instances
+            .iter()
+            .map(|(handle, persistent)| {
+                (
+                    *handle,
+                    self.create_persistent_scene_bind_group(&persistent.buffer),
+                )
+            })
+            .collect();
+        for (handle, bind_group) in persistent_bind_groups {
+            if let Some(persistent) = self.persistent_instances.get_mut(&handle) {
+                persistent.scene_bind_group = bind_group;
+            }
+        }
+    }
+
+    fn create_scene_bind_groups(&self) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let device = &self.render_device.device;
+        let shadow_entries = [
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&self.shadow_map.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&self._depth_sampler),
+            },
+        ];
+
+        let uniform_entry = wgpu::BindGroupEntry {
+            binding: 0,
+            resource: self.uniform_buffer.buffer.as_entire_binding(),
+        };
+
+        let static_entries: Vec<_> = std::iter::once(uniform_entry.clone())
+            .chain(self.get_instance_group_entry(&self.static_instance_buffer))
+            .chain(shadow_entries.clone())
+            .collect();
+        let static_scene = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.static_scene_bind_collection.bind_group_layout,
+            entries: &static_entries,
+        });
+        let skeletal_entries: Vec<_> = std::iter::once(uniform_entry)
+            .chain(self.get_instance_group_entry(&self.skeletal_instance_buffer))
+            .chain(shadow_entries)
+            .chain([wgpu::BindGroupEntry {
+                binding: 4,
+                resource: self.bone_buffer.get_resource(),
+            }])
+            .collect();
+        let skeletal_scene = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.skeletal_scene_bind_collection.bind_group_layout,
+            entries: &skeletal_entries,
+        });
+        (static_scene, skeletal_scene)
+    }
+
     pub fn set_lighting_color(&mut self, color: Vec3) {
-        self.uniform_data.light_color = [color.x, color.y, color.z, 1.0];
+        self.directional_light.color = color;
     }
 
     #[allow(dead_code)]
     pub fn set_lighting_direction(&mut self, direction: Vec3) {
-        self.uniform_data.light_direction = [direction.x, direction.y, direction.z, 0.0];
+        // The shadow fit and the shader both expect a unit vector
+        self.directional_light.direction = direction.try_normalize().unwrap_or(Vec3::NEG_Y);
     }
 
-    pub fn compute_directional_light_vp(
-        camera_view: Mat4,
-        camera_proj: Mat4,
-        light_dir: Vec3,
-    ) -> Mat4 {
-        let inv_view_proj = (camera_proj * camera_view).inverse();
+    #[allow(dead_code)]
+    pub fn get_ambient_light(&self) -> &AmbientLight {
+        &self.ambient_light
+    }
+
+    pub fn set_ambient_light(&mut self, ambient_light: AmbientLight) {
+        self.ambient_light = ambient_light;
+    }
+
+    #[allow(dead_code)]
+    pub fn get_fog(&self) -> Option<&Fog> {
+        self.fog.as_ref()
+    }
+
+    pub fn set_fog(&mut self, fog: Option<Fog>) {
+        self.fog = fog;
+    }
+
+    #[allow(dead_code)]
+    pub fn get_wind(&self) -> &Wind {
+        &self.wind
+    }
+
+    pub fn set_wind(&mut self, wind: Wind) {
+        self.wind = Wind {
+            direction: wind.direction.try_normalize().unwrap_or(Vec2::X),
+            ..wind
+        };
+    }
+
+    pub fn advance_wind(&mut self, dt: f32) {
+        self.wind_time += dt;
+    }
+
+    pub fn set_light_debug_enabled(&mut self, enabled: bool) {
+        self.light_debug_enabled = enabled;
+    }
+
+    pub fn is_light_debug_enabled(&self) -> bool {
+        self.light_debug_enabled
+    }
+
+    // In reference units, inside the screen edges and the inset of the aspect limit
+    #[allow(dead_code)]
+    pub fn set_ui_safe_area(&mut self, margins: Insets) {
+        self.ui_viewport.margins = margins;
+        self.update_sprite_uniform();
+    }
+
+    // Screens wider than this get their sides inset, infinity turns it off
+    #[allow(dead_code)]
+    pub fn set_ui_max_aspect(&mut self, max_aspect: f32) {
+        self.ui_viewport.max_aspect = max_aspect;
+        self.update_sprite_uniform();
+    }
+
+    // Of the window, from winit. The surface is sized in physical pixels either way, this
+    // only scales the UI when it follows the OS.
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.ui_viewport.scale_factor = scale_factor as f32;
+        self.update_sprite_uniform();
+    }
+
+    #[allow(dead_code)]
+    pub fn set_ui_dpi_mode(&mut self, dpi_mode: UiDpiMode) {
+        self.ui_viewport.dpi_mode = dpi_mode;
+        self.update_sprite_uniform();
+    }
+
+    // The size of the screen reference space sprites are laid out for and how it is fit
+    // into the screen, e.g. a menu covering the screen or an editor with a bigger canvas
+    pub fn set_ui_reference(&mut self, reference: Vec2, fit: FitPolicy) {
+        self.ui_viewport.reference = reference.max(Vec2::ONE);
+        self.ui_viewport.fit = fit;
+        self.update_sprite_uniform();
+    }
+
+    // What reference space sprites are placed in, for hit testing them
+    pub fn get_ui_viewport(&self) -> UiViewport {
+        self.ui_viewport
+    }
+
+    // Whether reference space moved on the screen since the last call, from a resize or
+    // any of the UI settings. Retained UI that caches pixel rects lays itself out again.
+    #[allow(dead_code)]
+    pub fn take_ui_layout_changed(&mut self) -> bool {
+        std::mem::take(&mut self.ui_layout_changed)
+    }
+
+    fn update_sprite_uniform(&mut self) {
+        let (position, size) = self.ui_viewport.get_safe_rect();
+        let data = SpriteUniformBufferData {
+            screen_size: self.ui_viewport.screen_size.to_array(),
+            ui_scale: self.ui_viewport.get_ui_scale().to_array(),
+            safe_rect: [position.x, position.y, size.x, size.y],
+        };
+        self.ui_layout_changed |=
+            bytemuck::bytes_of(&data) != bytemuck::bytes_of(&self.sprite_uniform_data);
+        self.sprite_uniform_data = data;
+    }
+
+    pub fn set_safe_area_debug_enabled(&mut self, enabled: bool) {
+        self.safe_area_debug_enabled = enabled;
+    }
+
+    pub fn is_safe_area_debug_enabled(&self) -> bool {
+        self.safe_area_debug_enabled
+    }
+
+    // Cuts the scene to the reference screen while the UI is fit with Contain, so every
+    // aspect ratio sees the same part of the world. The bars keep the clear color.
+    pub fn set_letterbox(&mut self, enabled: bool) {
+        self.letterbox = enabled;
+    }
+
+    // Silhouettes of the skeletal jobs marked x-ray, on by default
+    pub fn set_xray_enabled(&mut self, enabled: bool) {
+        self.xray_enabled = enabled;
+    }
+
+    pub fn is_xray_enabled(&self) -> bool {
+        self.xray_enabled
+    }
+
+    // Outlines the safe rect in magenta, on top of the UI
+    fn draw_safe_area_debug(&mut self) {
+        const THICKNESS: f32 = 2.0;
+        const LAYER: u32 = u16::MAX as u32 - 2; // Below the tooltip, see ui.rs
+
+        let (position, size) = self.ui_viewport.get_safe_rect();
+        let edges = [
+            (position, Vec2::new(size.x, THICKNESS)),
+            (
+                position + Vec2::new(0.0, size.y - THICKNESS),
+                Vec2::new(size.x, THICKNESS),
+            ),
+            (position, Vec2::new(THICKNESS, size.y)),
+            (
+                position + Vec2::new(size.x - THICKNESS, 0.0),
+                Vec2::new(THICKNESS, size.y),
+            ),
+        ];
+        for (position, size) in edges {
+            self.submit(&SpriteRenderJob {
+                space: SpriteSpace::Absolute,
+                ..SpriteRenderJob::solid(position, size, Vec4::new(1.0, 0.0, 1.0, 0.8), LAYER)
+            });
+        }
+    }
+
+    // Draws the shadow volume in yellow and the camera frustum it is fitted to in cyan
+    fn draw_light_debug(&mut self) {
+        let camera_view_proj = Mat4::from_cols_array(&self.uniform_data.projection_matrix)
+            * Mat4::from_cols_array(&self.uniform_data.view_matrix);
+        let light_view_proj = Mat4::from_cols_array(&self.uniform_data.light_matrix);
+
+        self.draw_debug_frustum(light_view_proj, Vec4::new(1.0, 1.0, 0.0, 1.0));
+        self.draw_debug_frustum(camera_view_proj, Vec4::new(0.0, 1.0, 1.0, 1.0));
+    }
+
+    pub fn draw_debug_line(&mut self, start: Vec3, end: Vec3, color: Vec4) {
+        self.submit(&DebugLineRenderJob { start, end, color });
+    }
+
+    // Over the scene, for handles that have to stay visible behind walls
+    pub fn draw_gizmo_line(&mut self, start: Vec3, end: Vec3, color: Vec4) {
+        self.submit(&GizmoLineRenderJob { start, end, color });
+    }
+
+    // The bones of the pose as lines between the joints, colored by bone index
+    pub fn draw_debug_skeleton(&mut self, mesh: ResourceHandle, pose: &Pose, transform: Mat4) {
+        let Some(mesh) = self.resource_pool.get_skeletal_mesh(mesh) else {
+            return;
+        };
+        for (bone_index, start, end) in mesh.get_skeleton_lines(pose) {
+            self.render_data.submit(
+                &DebugLineRenderJob {
+                    start: transform.transform_point3(start),
+                    end: transform.transform_point3(end),
+                    color: get_bone_debug_color(bone_index),
+                },
+                &self.resource_pool,
+            );
+        }
+    }
+
+    pub fn draw_debug_frustum(&mut self, view_proj: Mat4, color: Vec4) {
+        let corners = Self::get_frustum_corners(view_proj.inverse());
+
+        const EDGES: [(usize, usize); 12] = [
+            (0, 1),
+            (1, 3),
+            (3, 2),
+            (2, 0),
+            (4, 5),
+            (5, 7),
+            (7, 6),
+            (6, 4),
+            (0, 4),
+            (1, 5),
+            (2, 6),
+            (3, 7),
+        ];
+
+        for (a, b) in EDGES {
+            self.draw_debug_line(corners[a], corners[b], color);
+        }
+    }
 
+    // Near plane corners first, then far plane, both in the order (-x,-y), (x,-y), (-x,y), (x,y)
+    fn get_frustum_corners(inv_view_proj: Mat4) -> [Vec3; 8] {
         let clip_space_corners = [
             Vec4::new(-1.0, -1.0, 0.0, 1.0),
             Vec4::new(1.0, -1.0, 0.0, 1.0),
@@ -1236,88 +3750,384 @@ impl Renderer {
             Vec4::new(1.0, 1.0, 1.0, 1.0),
         ];
 
-        let mut frustum_corners_world = [Vec3::ZERO; 8];
+        let mut corners = [Vec3::ZERO; 8];
         for (i, c) in clip_space_corners.iter().enumerate() {
             let world = inv_view_proj * *c;
-            frustum_corners_world[i] = (world / world.w).truncate();
+            corners[i] = (world / world.w).truncate();
         }
 
+        corners
+    }
+
+    // Fits an orthographic shadow volume around the bounding sphere of the camera frustum.
+    // The sphere only depends on the frustum shape, so its size stays constant while the camera
+    // moves, and its center is snapped to whole texels so the shadow edges do not shimmer.
+    pub fn compute_directional_light_vp(
+        camera_view: Mat4,
+        camera_proj: Mat4,
+        light_dir: Vec3,
+        depth_extension: f32,
+        shadow_map_size: u32,
+    ) -> Mat4 {
+        let frustum_corners_world =
+            Self::get_frustum_corners((camera_proj * camera_view).inverse());
+
         let mut center = Vec3::ZERO;
         for c in &frustum_corners_world {
             center += *c;
         }
         center /= frustum_corners_world.len() as f32;
 
-        let light_dir_norm = light_dir.normalize();
-        let light_forward = -light_dir_norm;
+        let mut radius: f32 = 0.0;
+        for c in &frustum_corners_world {
+            radius = radius.max((*c - center).length());
+        }
+        // Rounding up hides the float noise from the inverse projection
+        radius = (radius * 16.0).ceil() / 16.0;
+
+        let light_view = Self::get_light_rotation(light_dir);
+
+        let world_units_per_texel = radius * 2.0 / shadow_map_size as f32;
+        let center_ls = (light_view * center.extend(1.0)).truncate();
+        let center_ls = (center_ls / world_units_per_texel).floor() * world_units_per_texel;
+
+        let left = center_ls.x - radius;
+        let right = center_ls.x + radius;
+        let bottom = center_ls.y - radius;
+        let top = center_ls.y + radius;
 
-        let world_up = if light_forward.abs().dot(Vec3::Y).abs() > 0.9 {
+        // The view looks down -z, the extension pulls the near plane towards the light so
+        // casters outside of the camera frustum still end up in the map
+        let near_z = -center_ls.z - radius - depth_extension;
+        let far_z = -center_ls.z + radius;
+
+        let light_proj = Mat4::orthographic_rh(left, right, bottom, top, near_z, far_z);
+
+        light_proj * light_view
+    }
+
+    // Light view without translation, this keeps the light space axes fixed for texel snapping
+    fn get_light_rotation(light_dir: Vec3) -> Mat4 {
+        let light_forward = light_dir.normalize();
+
+        let world_up = if light_forward.dot(Vec3::Y).abs() > 0.9 {
             Vec3::Z
         } else {
             Vec3::Y
         };
 
-        let light_right = world_up.cross(light_forward).normalize();
-        let light_up = light_forward.cross(light_right).normalize();
+        Mat4::look_to_rh(Vec3::ZERO, light_forward, world_up)
+    }
 
-        let mut radius: f32 = 0.0;
-        for c in &frustum_corners_world {
-            radius = radius.max((*c - center).length());
+    pub fn load_mesh(&mut self, name: &str, bytes: &[u8]) -> ResourceHandle {
+        if let Some(handle) = self.find_loaded(name) {
+            return handle;
+        }
+        let mesh = self
+            .render_device
+            .load_mesh(bytes)
+            .expect("Failed to load mesh");
+
+        let handle = self
+            .resource_pool
+            .add_named_resource(name, Resource::StaticMesh(mesh));
+        self.add_to_current_scope(handle);
+        handle
+    }
+
+    pub fn load_skeletal_mesh(&mut self, name: &str, bytes: &[u8]) -> ResourceHandle {
+        if let Some(handle) = self.find_loaded(name) {
+            return handle;
         }
-        let light_pos = center + light_forward * radius * 2.0;
+        let mesh = self
+            .render_device
+            .load_skeletal_mesh(bytes)
+            .expect("Failed to load mesh");
 
-        let light_view = Mat4::look_at_rh(light_pos, center, light_up);
+        let handle = self
+            .resource_pool
+            .add_named_resource(name, Resource::SkeletalMesh(mesh));
+        self.resource_pool
+            .keep_source(handle, ResourceKind::SkeletalMesh, bytes);
+        self.add_to_current_scope(handle);
+        handle
+    }
 
-        let mut min_ls = Vec3::splat(f32::INFINITY);
-        let mut max_ls = Vec3::splat(f32::NEG_INFINITY);
+    // Geometry made on the CPU, e.g. baked props. Replaces a mesh of the same name.
+    pub fn create_static_mesh(
+        &mut self,
+        name: &str,
+        vertices: &[StaticMeshVertex],
+        indices: &[u32],
+    ) -> ResourceHandle {
+        let mesh = self
+            .render_device
+            .create_mesh(&MeshLoadDesc {
+                vertex_data: bytemuck::cast_slice(vertices).to_vec(),
+                indices: indices.to_vec(),
+                ..Default::default()
+            })
+            .expect("Failed to create mesh");
+        let handle = self
+            .resource_pool
+            .add_named_resource(name, Resource::StaticMesh(mesh));
+        self.add_to_current_scope(handle);
+        handle
+    }
 
-        for c in &frustum_corners_world {
-            let v = light_view * c.extend(1.0);
-            let v3 = v.truncate();
+    // Static instances that are uploaded once and drawn every frame without being submitted,
+    // e.g. ground clutter. The set groups them into chunks that are culled as a whole.
+    // Replaces the instances of the same name.
+    pub fn create_persistent_instances(
+        &mut self,
+        name: &str,
+        set: PersistentSet,
+        instances: &[StaticInstanceData],
+    ) -> ResourceHandle {
+        let handle = get_handle(name);
+        let flags = get_instance_flags(&self.resource_pool, set.material);
+        let instances: Vec<_> = instances
+            .iter()
+            .map(|instance| StaticInstanceData {
+                data_indices: [
+                    instance.data_indices[0],
+                    instance.data_indices[1] | flags,
+                    instance.data_indices[2],
+                    instance.data_indices[3],
+                ],
+                ..*instance
+            })
+            .collect();
+        let spheres = match self.get_static_mesh_bounds(set.mesh) {
+            Some((min, max)) => instances
+                .iter()
+                .map(|instance| {
+                    get_bounding_sphere(Mat4::from_cols_array(&instance.model_matrix), min, max)
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        let persistent = self.create_persistent_buffer(instances, spheres);
+        self.persistent_instances.insert(handle, persistent);
+        self.render_data.add_persistent_set(handle, set);
+        handle
+    }
+
+    pub fn remove_persistent_instances(&mut self, handle: ResourceHandle) {
+        self.persistent_instances.remove(&handle);
+        self.render_data.remove_persistent_set(handle);
+    }
+
+    // Bound like the per-frame static instance buffer, in its place
+    fn create_persistent_buffer(
+        &self,
+        instances: Vec<StaticInstanceData>,
+        spheres: Vec<Vec4>,
+    ) -> PersistentInstances {
+        let device = &self.render_device.device;
+        let instances_size = instances.len().max(1) * std::mem::size_of::<StaticInstanceData>();
+        let buffer = self.render_device.create_buffer(&BufferDesc {
+            size: instances_size,
+            usage: Self::get_instance_usage(&self.render_device),
+        });
+        self.render_device
+            .write_buffer(&buffer, bytemuck::cast_slice(&instances), 0);
+
+        let scene_bind_group = self.create_persistent_scene_bind_group(&buffer);
+        let shadow_entries: Vec<_> = std::iter::once(wgpu::BindGroupEntry {
+            binding: 0,
+            resource: self.uniform_buffer.buffer.as_entire_binding(),
+        })
+        .chain(self.get_instance_group_entry(&buffer))
+        .collect();
+        let shadow_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Persistent Shadow Bind Group"),
+            layout: &self.static_shadow_bind_collection.bind_group_layout,
+            entries: &shadow_entries,
+        });
+
+        let gpu_culled = match &self.cull_pipeline {
+            Some(cull) if !spheres.is_empty() => {
+                let sphere_buffer = self.render_device.create_buffer(&BufferDesc {
+                    size: spheres.len() * std::mem::size_of::<Vec4Data>(),
+                    usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                });
+                let sphere_data: Vec<Vec4Data> =
+                    spheres.iter().map(|sphere| sphere.to_array()).collect();
+                self.render_device.write_buffer(
+                    &sphere_buffer,
+                    bytemuck::cast_slice(&sphere_data),
+                    0,
+                );
+                let culled_buffer = self.render_device.create_buffer(&BufferDesc {
+                    size: instances_size,
+                    usage: BufferUsages::STORAGE,
+                });
+                let draw_args = self.render_device.create_indirect_buffer(1);
+
+                let cull_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Cull Bind Group"),
+                    layout: &cull.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: cull.uniform_buffer.buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: sphere_buffer.buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: buffer.buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: culled_buffer.buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: draw_args.buffer.as_entire_binding(),
+                        },
+                    ],
+                });
+
+                Some(GpuCulledInstances {
+                    scene_bind_group: self.create_persistent_scene_bind_group(&culled_buffer),
+                    _sphere_buffer: sphere_buffer,
+                    _culled_buffer: culled_buffer,
+                    draw_args,
+                    cull_bind_group,
+                })
+            }
+            _ => None,
+        };
 
-            min_ls = min_ls.min(v3);
-            max_ls = max_ls.max(v3);
+        PersistentInstances {
+            instances,
+            spheres,
+            buffer,
+            scene_bind_group,
+            shadow_bind_group,
+            gpu_culled,
         }
+    }
 
-        let left = min_ls.x;
-        let right = max_ls.x;
-        let bottom = min_ls.y;
-        let top = max_ls.y;
+    fn create_persistent_scene_bind_group(&self, instances: &Buffer) -> wgpu::BindGroup {
+        let entries: Vec<_> = std::iter::once(wgpu::BindGroupEntry {
+            binding: 0,
+            resource: self.uniform_buffer.buffer.as_entire_binding(),
+        })
+        .chain(self.get_instance_group_entry(instances))
+        .chain([
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&self.shadow_map.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&self._depth_sampler),
+            },
+        ])
+        .collect();
+        self.render_device
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Persistent Scene Bind Group"),
+                layout: &self.static_scene_bind_collection.bind_group_layout,
+                entries: &entries,
+            })
+    }
 
-        let near_z = -max_ls.z - 10.0;
-        let far_z = -min_ls.z + 10.0;
+    // Whether persistent instances can be culled one by one on the GPU, not on WebGL2
+    pub fn supports_gpu_culling(&self) -> bool {
+        self.cull_pipeline.is_some()
+    }
 
-        let light_proj = Mat4::orthographic_rh(left, right, bottom, top, near_z.max(0.1), far_z);
+    // Culls the persistent instances on the GPU as well, after the chunks were culled on the
+    // CPU. Stays off when the device can't.
+    pub fn set_gpu_culling(&mut self, enabled: bool) {
+        if enabled && !self.supports_gpu_culling() {
+            log::warn!("GPU culling is not supported by this device, culling on the CPU");
+        }
+        self.gpu_culling = enabled && self.supports_gpu_culling();
+    }
 
-        light_proj * light_view
+    pub fn is_gpu_culling(&self) -> bool {
+        self.gpu_culling
     }
 
-    pub fn load_mesh(&mut self, name: &'static str, bytes: &[u8]) -> ResourceHandle {
-        let handle = get_handle(name);
-        let mesh = self
-            .render_device
-            .load_mesh(bytes)
-            .expect("Failed to load mesh");
+    // Reads back what the GPU kept every frame and compares it with the CPU, which waits
+    // for the frame to finish
+    #[cfg(any(feature = "test-harness", feature = "inspector"))]
+    pub fn set_gpu_culling_validation(&mut self, enabled: bool) {
+        self.gpu_culling_validation = enabled;
+        if !enabled {
+            self.gpu_cull_counts.clear();
+        }
+    }
 
-        self.resource_pool
-            .add_resource(handle, Resource::StaticMesh(mesh));
+    #[cfg(any(feature = "test-harness", feature = "inspector"))]
+    pub fn is_gpu_culling_validation(&self) -> bool {
+        self.gpu_culling_validation
+    }
 
-        handle
+    // Of the last frame culled on the GPU while validating, empty otherwise
+    #[cfg(any(feature = "test-harness", feature = "inspector"))]
+    pub fn get_gpu_cull_counts(&self) -> &[GpuCullCount] {
+        &self.gpu_cull_counts
     }
 
-    pub fn load_skeletal_mesh(&mut self, name: &'static str, bytes: &[u8]) -> ResourceHandle {
-        let handle = get_handle(name);
-        let mesh = self
-            .render_device
-            .load_skeletal_mesh(bytes)
-            .expect("Failed to load mesh");
+    // Of the most detailed level, None for other resources
+    pub fn get_static_mesh_bounds(&self, handle: ResourceHandle) -> Option<(Vec3, Vec3)> {
+        let (vertices, indices) = self.get_static_mesh_geometry(handle)?;
+        if indices.is_empty() {
+            return None;
+        }
+        Some(indices.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), index| {
+                let position = Vec3::from_array(vertices[*index as usize].position);
+                (min.min(position), max.max(position))
+            },
+        ))
+    }
 
-        self.resource_pool
-            .add_resource(handle, Resource::SkeletalMesh(mesh));
+    // The vertices and the indices of the most detailed level, None for other resources
+    pub fn get_static_mesh_geometry(
+        &self,
+        handle: ResourceHandle,
+    ) -> Option<(&[StaticMeshVertex], &[u32])> {
+        let mesh = self.resource_pool.get_mesh(handle)?;
+        let range = mesh.lods.first()?.index_range.clone();
+        Some((
+            &mesh.vertices,
+            &mesh.indices[range.start as usize..range.end as usize],
+        ))
+    }
 
+    // Drawn like a static mesh, with the geometry of the last update
+    pub fn create_dynamic_mesh(&mut self, name: &str, initial_capacity: usize) -> ResourceHandle {
+        let mesh = self.render_device.create_dynamic_mesh(initial_capacity);
+        let handle = self
+            .resource_pool
+            .add_named_resource(name, Resource::DynamicMesh(mesh));
+        self.add_to_current_scope(handle);
         handle
     }
 
+    // Does nothing when the handle is not a dynamic mesh
+    pub fn update_dynamic_mesh(
+        &mut self,
+        handle: ResourceHandle,
+        vertices: &[StaticMeshVertex],
+        indices: &[u32],
+    ) {
+        if let Some(mesh) = self.resource_pool.get_dynamic_mesh_mut(handle) {
+            mesh.update(&self.render_device, vertices, indices);
+        }
+    }
+
     pub fn create_pose(&self, mesh: ResourceHandle) -> Pose {
         let mesh = self
             .resource_pool
@@ -1326,97 +4136,235 @@ impl Renderer {
         Pose::new(mesh.bones.len())
     }
 
-    pub fn load_animation(&mut self, name: &'static str, bytes: &[u8]) -> ResourceHandle {
-        let handle = get_handle(name);
+    pub fn get_skeletal_mesh_bones(&self, mesh: ResourceHandle) -> Option<&[BoneInfo]> {
+        self.resource_pool
+            .get_skeletal_mesh(mesh)
+            .map(|mesh| mesh.bones.as_slice())
+    }
+
+    pub fn load_animation(&mut self, name: &str, bytes: &[u8]) -> ResourceHandle {
+        if let Some(handle) = self.find_loaded(name) {
+            return handle;
+        }
         let animation = self
             .render_device
             .load_animation(bytes)
             .expect("Failed to load animation");
 
-        self.resource_pool
-            .add_resource(handle, Resource::Animation(animation));
-
+        let handle = self
+            .resource_pool
+            .add_named_resource(name, Resource::Animation(animation));
+        self.add_to_current_scope(handle);
         handle
     }
 
-    pub fn load_texture(&mut self, name: &'static str, bytes: &[u8]) -> ResourceHandle {
-        let handle = get_handle(name);
+    pub fn load_texture(&mut self, name: &str, bytes: &[u8]) -> ResourceHandle {
+        if let Some(handle) = self.find_loaded(name) {
+            return handle;
+        }
         let texture = self
             .render_device
             .load_texture(bytes)
             .expect("Failed to load texture");
 
+        let handle = self
+            .resource_pool
+            .add_named_resource(name, Resource::Texture(texture));
         self.resource_pool
-            .add_resource(handle, Resource::Texture(texture));
-
+            .keep_source(handle, ResourceKind::Texture, bytes);
+        self.add_to_current_scope(handle);
         handle
     }
 
-    pub fn load_font(&mut self, name: &'static str, bytes: &[u8]) -> ResourceHandle {
-        let handle = get_handle(name);
+    // Like load_texture, but the pixels are left to upload_texture_mip
+    pub fn begin_texture_upload(&mut self, name: &str, bytes: &[u8]) -> TextureUpload {
+        if let Some(handle) = self.find_loaded(name) {
+            return TextureUpload::finished(handle);
+        }
+        let (texture, upload) = self
+            .render_device
+            .begin_texture_upload(get_handle(name), bytes)
+            .expect("Failed to load texture");
+
+        let handle = self
+            .resource_pool
+            .add_named_resource(name, Resource::Texture(texture));
+        self.resource_pool
+            .keep_source(handle, ResourceKind::Texture, bytes);
+        self.add_to_current_scope(handle);
+        upload
+    }
+
+    // Uploads the next mip, returns true once all of them are uploaded
+    pub fn upload_texture_mip(&mut self, upload: &mut TextureUpload) -> bool {
+        if let Some(Resource::Texture(texture)) = self.resource_pool.get_resource(upload.handle) {
+            self.render_device.upload_texture_mip(texture, upload);
+        }
+        upload.is_done()
+    }
+
+    pub fn load_font(&mut self, name: &str, bytes: &[u8]) -> ResourceHandle {
+        if let Some(handle) = self.find_loaded(name) {
+            return handle;
+        }
         let font = self
             .render_device
             .load_font(bytes)
             .expect("Failed to load font");
 
+        let handle = self
+            .resource_pool
+            .add_named_resource(name, Resource::Font(font));
         self.resource_pool
-            .add_resource(handle, Resource::Font(font));
-
+            .keep_source(handle, ResourceKind::Font, bytes);
+        self.add_to_current_scope(handle);
         handle
     }
 
     pub fn create_material(
         &mut self,
-        name: &'static str,
+        name: &str,
         texture_handle: ResourceHandle,
     ) -> ResourceHandle {
-        let handle = get_handle(name);
-        let texture = self
-            .resource_pool
-            .get_texture(texture_handle)
-            .expect("Failed to get texture");
+        self.create_material_from(name, MaterialSource::Scene(texture_handle))
+    }
 
-        let material_instance = self.render_device.create_material_instance(
-            &self.scene_material_pipeline.static_material_pipeline, // Need to be looked over later
-            &MaterialInstanceDesc {
-                entires: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&self.default_sampler),
-                    },
-                ],
-            },
+    // Unlit and added to the scene, see PassTarget::Additive. The alpha of the texture and
+    // of the instance color scale what is added.
+    pub fn create_additive_material(
+        &mut self,
+        name: &str,
+        texture_handle: ResourceHandle,
+    ) -> ResourceHandle {
+        self.create_material_from(name, MaterialSource::Additive(texture_handle))
+    }
+
+    // One material for all layers of the array texture, the render jobs pick theirs with
+    // their variant. Entities of different variants are still drawn in one batch.
+    pub fn create_variant_material(
+        &mut self,
+        name: &str,
+        texture_array_handle: ResourceHandle,
+        variant_count: u32,
+    ) -> ResourceHandle {
+        let layer_count = self
+            .resource_pool
+            .get_texture(texture_array_handle)
+            .expect("Failed to get texture")
+            ._texture
+            .depth_or_array_layers();
+        assert!(
+            variant_count > 0 && variant_count <= layer_count,
+            "{} needs {} variants, its texture has {} layers",
+            name,
+            variant_count,
+            layer_count
         );
 
-        self.resource_pool
-            .add_resource(handle, Resource::MaterialInstance(material_instance));
+        self.create_material_from(
+            name,
+            MaterialSource::Variant(MaterialVariantSet {
+                texture: texture_array_handle,
+                variant_count,
+            }),
+        )
+    }
 
-        handle
+    // A scene material whose instances sway in the wind, see Wind. The vertex color alpha
+    // weights the sway, see the gradient-y vertex colors of the mesh tool, and is not used
+    // as the ambient occlusion.
+    pub fn create_swaying_material(
+        &mut self,
+        name: &str,
+        texture_handle: ResourceHandle,
+    ) -> ResourceHandle {
+        self.create_material_from(name, MaterialSource::Swaying(texture_handle))
     }
 
     #[allow(dead_code)]
     pub fn create_sprite_material(
         &mut self,
-        name: &'static str,
+        name: &str,
         texture_handle: ResourceHandle,
     ) -> ResourceHandle {
-        let handle = get_handle(name);
-        let texture = self
-            .resource_pool
-            .get_texture(texture_handle)
+        self.create_material_from(name, MaterialSource::Sprite(texture_handle))
+    }
+
+    // Remembers the source, the material is rebuilt when it is reloaded
+    fn create_material_from(&mut self, name: &str, source: MaterialSource) -> ResourceHandle {
+        if let Some(handle) = self.find_loaded(name) {
+            return handle;
+        }
+        let material_instance = self
+            .build_material_instance(source)
             .expect("Failed to get texture");
+        #[cfg(debug_assertions)]
+        self.warn_duplicate_material(name, &material_instance);
+        let handle = self
+            .resource_pool
+            .add_named_resource(name, Resource::MaterialInstance(material_instance));
+        self.material_sources.insert(handle, source);
+        self.add_to_current_scope(handle);
+        handle
+    }
+
+    // Another material of the same texture, pipeline and sampler splits the batches that
+    // could have been one, see batch_diagnostics
+    #[cfg(debug_assertions)]
+    fn warn_duplicate_material(&self, name: &str, material_instance: &MaterialInstance) {
+        let Some(fingerprint) = material_instance.fingerprint else {
+            return;
+        };
+        let duplicate = self.resource_pool.iter().find(|&(handle, kind)| {
+            kind == ResourceKind::MaterialInstance
+                && self
+                    .resource_pool
+                    .get_material_instance(handle)
+                    .is_some_and(|other| other.fingerprint == Some(fingerprint))
+        });
+        if let Some((handle, _)) = duplicate {
+            log::warn!(
+                "Material {} binds the same texture as {}, their batches are drawn apart.",
+                name,
+                self.resource_pool.get_name(handle).unwrap_or("?")
+            );
+        }
+    }
+
+    fn build_material_instance(&self, source: MaterialSource) -> Option<MaterialInstance> {
+        let (pipeline, view) = match source {
+            MaterialSource::Scene(texture)
+            | MaterialSource::Swaying(texture)
+            | MaterialSource::Variant(MaterialVariantSet { texture, .. }) => (
+                &self.scene_material_pipeline.static_material_pipeline, // Need to be looked over later
+                &self.resource_pool.get_texture(texture)?.view,
+            ),
+            MaterialSource::Additive(texture) => (
+                &self.additive_material_pipeline,
+                &self.resource_pool.get_texture(texture)?.view,
+            ),
+            MaterialSource::Sprite(texture) => {
+                let texture = self.resource_pool.get_texture(texture)?;
+                let pipeline = if texture.premultiplied_alpha {
+                    &self.premultiplied_sprite_material_pipeline
+                } else {
+                    &self.sprite_material_pipeline
+                };
+                (pipeline, &texture.view)
+            }
+            MaterialSource::Font(font) => (
+                &self.sprite_material_pipeline,
+                &self.resource_pool.get_font(font)?.atlas.view,
+            ),
+        };
 
-        let material_instance = self.render_device.create_material_instance(
-            &self.sprite_material_pipeline, // Need to be looked over later
+        let mut material_instance = self.render_device.create_material_instance(
+            pipeline,
             &MaterialInstanceDesc {
                 entires: &[
                     wgpu::BindGroupEntry {
                         binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                        resource: wgpu::BindingResource::TextureView(view),
                     },
                     wgpu::BindGroupEntry {
                         binding: 1,
@@ -1425,44 +4373,328 @@ impl Renderer {
                 ],
             },
         );
+        if let MaterialSource::Variant(variant_set) = source {
+            material_instance.variant_set = Some(variant_set);
+        }
+        material_instance.swaying = matches!(source, MaterialSource::Swaying(_));
+        material_instance.fingerprint = Some(MaterialFingerprint {
+            pipeline: source.get_pipeline_name(pipeline.premultiplied_alpha),
+            texture: source.get_dependency(),
+            sampler: "default",
+            variant_count: material_instance
+                .variant_set
+                .map_or(0, |variant_set| variant_set.variant_count),
+        });
+        Some(material_instance)
+    }
 
-        self.resource_pool
-            .add_resource(handle, Resource::MaterialInstance(material_instance));
+    // The atlas handle is also the material used by all of its regions
+    #[allow(dead_code)]
+    pub fn create_sprite_atlas(
+        &mut self,
+        name: &str,
+        texture_handle: ResourceHandle,
+    ) -> ResourceHandle {
+        let texture = self
+            .resource_pool
+            .get_texture(texture_handle)
+            .expect("Failed to get texture");
+        let size = UVec2::new(texture._texture.width(), texture._texture.height());
+
+        let handle = self.create_sprite_material(name, texture_handle);
+        self.sprite_atlas_sizes.insert(handle, size);
 
         handle
     }
 
+    #[allow(dead_code)]
+    pub fn atlas_region(
+        &mut self,
+        atlas: ResourceHandle,
+        name: &str,
+        pixel_rect: PixelRect,
+    ) -> SpriteRegion {
+        let atlas_size = *self
+            .sprite_atlas_sizes
+            .get(&atlas)
+            .expect("Failed to get sprite atlas");
+
+        let region = SpriteRegion::from_pixel_rect(atlas, pixel_rect, atlas_size);
+        let handle = self
+            .resource_pool
+            .add_named_resource(name, Resource::SpriteRegion(region));
+        self.add_to_current_scope(handle);
+
+        region
+    }
+
+    // Registers all regions from the json written by the texture tool, returns the region count
+    #[allow(dead_code)]
+    pub fn load_atlas_regions(&mut self, atlas: ResourceHandle, bytes: &[u8]) -> usize {
+        let desc = AtlasRegionsDesc::load(bytes).expect("Failed to load atlas regions");
+        if self.sprite_atlas_sizes.get(&atlas) != Some(&UVec2::new(desc.width, desc.height)) {
+            log::warn!("The atlas regions were generated for a different atlas size.");
+        }
+
+        for (name, pixel_rect) in desc.regions.iter() {
+            self.atlas_region(atlas, name, *pixel_rect);
+        }
+
+        desc.regions.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_sprite_region(&self, handle: ResourceHandle) -> Option<SpriteRegion> {
+        self.resource_pool.get_sprite_region(handle).copied()
+    }
+
     pub fn create_font_material(
         &mut self,
-        name: &'static str,
+        name: &str,
         font_handle: ResourceHandle,
     ) -> ResourceHandle {
+        self.create_material_from(name, MaterialSource::Font(font_handle))
+    }
+
+    // Replaces a loaded file while the game runs, called between frames. Materials made from
+    // the replaced texture or font get new bind groups, poses of a skeletal mesh are left
+    // to the caller.
+    pub fn reload_resource(
+        &mut self,
+        name: &str,
+        kind: ResourceKind,
+        bytes: &[u8],
+    ) -> anyhow::Result<ResourceHandle> {
+        #[allow(unused_mut)]
+        let mut resource = self.load_resource(kind, bytes)?;
+        // The rasterized glyphs don't come from the file, so they are kept
+        #[cfg(feature = "runtime-font")]
+        if let Resource::Font(font) = &mut resource {
+            font.runtime = self
+                .resource_pool
+                .get_font_mut(get_handle(name))
+                .and_then(|old| old.runtime.take());
+        }
+        let handle = self.resource_pool.add_named_resource(name, resource);
+        self.resource_pool.keep_source(handle, kind, bytes);
+        self.rebuild_dependent_materials(handle);
+
+        Ok(handle)
+    }
+
+    fn load_resource(&self, kind: ResourceKind, bytes: &[u8]) -> anyhow::Result<Resource> {
+        Ok(match kind {
+            ResourceKind::StaticMesh => Resource::StaticMesh(self.render_device.load_mesh(bytes)?),
+            ResourceKind::SkeletalMesh => {
+                Resource::SkeletalMesh(self.render_device.load_skeletal_mesh(bytes)?)
+            }
+            ResourceKind::Animation => {
+                Resource::Animation(self.render_device.load_animation(bytes)?)
+            }
+            ResourceKind::Texture => Resource::Texture(self.render_device.load_texture(bytes)?),
+            ResourceKind::Font => Resource::Font(self.render_device.load_font(bytes)?),
+            _ => anyhow::bail!("{} resources can't be loaded from a file", kind.get_name()),
+        })
+    }
+
+    // Registers every entry of a bundle written by the bundle command of the tools as
+    // "{prefix}/{entry name}". When an entry fails to load, the entries registered before it
+    // are unloaded again and the error names the entry.
+    #[allow(dead_code)]
+    pub fn load_bundle(&mut self, prefix: &str, bytes: &[u8]) -> anyhow::Result<BundleHandles> {
+        let entries = bundle::read_bundle_entries(bytes)
+            .with_context(|| format!("Failed to read the bundle {}", prefix))?;
+
+        let mut handles = BundleHandles::default();
+        for entry in &entries {
+            let name = format!("{}/{}", prefix, entry.name);
+            let loaded = entry.get_bytes(bytes).and_then(|entry_bytes| {
+                let resource = self.load_resource(entry.kind, &entry_bytes)?;
+                Ok((resource, entry_bytes))
+            });
+            match loaded {
+                Ok((resource, entry_bytes)) => {
+                    let handle = self.resource_pool.add_named_resource(&name, resource);
+                    self.resource_pool
+                        .keep_source(handle, entry.kind, &entry_bytes);
+                    handles.handles.insert(entry.name.clone(), handle);
+                }
+                Err(error) => {
+                    for handle in handles.handles.values() {
+                        self.resource_pool.remove_resource(*handle);
+                    }
+                    return Err(error.context(format!(
+                        "Failed to load the {} {} of the bundle {}",
+                        entry.kind.get_name(),
+                        entry.name,
+                        prefix
+                    )));
+                }
+            }
+        }
+
+        // Only once all of them loaded, the failed ones were never registered
+        for handle in handles.handles.values() {
+            self.add_to_current_scope(*handle);
+        }
+
+        log::info!("Loaded {} entries of the bundle {}", entries.len(), prefix);
+        Ok(handles)
+    }
+
+    // Resource scopes, see resource_scope.rs. The resources loaded while a scope is current
+    // are registered to it, until another one or None is made current.
+    pub fn create_scope(&mut self, name: &str) -> ScopeHandle {
+        self.resource_scopes.create_scope(name)
+    }
+
+    pub fn set_current_scope(&mut self, scope: Option<ScopeHandle>) {
+        self.resource_scopes.set_current(scope);
+    }
+
+    // Removes the resources only the scope held on to, returns how many. The frames already
+    // submitted may still draw with them, so they are dropped once the GPU is done with those.
+    pub fn unload_scope(&mut self, scope: ScopeHandle) -> usize {
+        let released = self.resource_scopes.unload_scope(scope);
+        let frame = self.render_device.get_submitted_frame_count();
+        for handle in &released {
+            self.material_sources.remove(handle);
+            self.sprite_atlas_sizes.remove(handle);
+            if let Some(resource) = self.resource_pool.remove_resource(*handle) {
+                self.released_resources.push((frame, resource));
+            }
+        }
+        log::info!("Unloaded a scope, released {} resources", released.len());
+        released.len()
+    }
+
+    fn drop_released_resources(&mut self) {
+        let completed = self.render_device.get_completed_frame_count();
+        self.released_resources
+            .retain(|(frame, _)| *frame > completed);
+    }
+
+    // The scopes that are still loaded and what they hold, called at shutdown in debug
+    // builds once everything should have been unloaded
+    pub fn log_unreleased_resources(&self) {
+        for (scope, handles) in self.resource_scopes.get_loaded_scopes() {
+            let names: Vec<_> = handles
+                .iter()
+                .map(|handle| match self.resource_pool.get_name(*handle) {
+                    Some(name) => name.to_string(),
+                    None => format!("{:#x}", handle),
+                })
+                .collect();
+            log::warn!(
+                "The scope {} was never unloaded, it holds {}",
+                scope,
+                names.join(", ")
+            );
+        }
+    }
+
+    // With what it was made from, when a scope is current
+    fn add_to_current_scope(&mut self, handle: ResourceHandle) {
+        let Some(scope) = self.resource_scopes.get_current() else {
+            return;
+        };
+        let dependency = match self.resource_pool.get_resource(handle) {
+            Some(Resource::SpriteRegion(region)) => Some(region.material),
+            _ => self
+                .material_sources
+                .get(&handle)
+                .map(|source| source.get_dependency()),
+        };
+        self.resource_scopes
+            .register(scope, handle, dependency.as_slice());
+    }
+
+    // While a scope is current, a name that is already loaded is used as it is instead of
+    // being loaded again. It is registered to the scope too, unless it was loaded outside of
+    // the scopes and lives on anyway.
+    fn find_loaded(&mut self, name: &str) -> Option<ResourceHandle> {
+        self.resource_scopes.get_current()?;
         let handle = get_handle(name);
-        let font = self
-            .resource_pool
-            .get_font(font_handle)
-            .expect("Failed to get font");
+        self.resource_pool.get_resource(handle)?;
+        if self.resource_scopes.is_registered(handle) {
+            self.add_to_current_scope(handle);
+        }
+        Some(handle)
+    }
 
-        let material_instance = self.render_device.create_material_instance(
-            &self.sprite_material_pipeline, // Need to be looked over later
-            &MaterialInstanceDesc {
-                entires: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&font.atlas.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&self.default_sampler),
-                    },
-                ],
-            },
-        );
+    // After the texture or font was replaced
+    fn rebuild_dependent_materials(&mut self, handle: ResourceHandle) {
+        let dependents: Vec<_> = self
+            .material_sources
+            .iter()
+            .filter(|(_, source)| source.get_dependency() == handle)
+            .map(|(&material, &source)| (material, source))
+            .collect();
+        for (material, source) in dependents {
+            if let Some(material_instance) = self.build_material_instance(source) {
+                self.resource_pool
+                    .add_resource(material, Resource::MaterialInstance(material_instance));
+            }
+        }
+    }
 
-        self.resource_pool
-            .add_resource(handle, Resource::MaterialInstance(material_instance));
+    // Glyphs missing from the baked atlas of the font are rasterized from the TTF into an
+    // atlas texture of the given name, see runtime_font.rs
+    #[cfg(feature = "runtime-font")]
+    pub fn add_font_fallback(
+        &mut self,
+        font_handle: ResourceHandle,
+        name: &str,
+        ttf: &[u8],
+    ) -> anyhow::Result<()> {
+        if self.resource_pool.get_font(font_handle).is_none() {
+            anyhow::bail!("No font to add the fallback {} to", name);
+        }
+        let material_name = format!("{}Material", name);
+        let runtime = RuntimeFont::new(ttf.to_vec(), get_handle(name), get_handle(&material_name))?;
 
-        handle
+        let atlas = self.render_device.create_texture(&runtime.get_atlas_desc());
+        let texture = self
+            .resource_pool
+            .add_named_resource(name, Resource::Texture(atlas));
+        self.create_sprite_material(&material_name, texture);
+
+        if let Some(font) = self.resource_pool.get_font_mut(font_handle) {
+            font.runtime = Some(Box::new(runtime));
+        }
+        Ok(())
+    }
+
+    // Uploads the glyphs the texts of this frame were missing, before the frame is built
+    #[cfg(feature = "runtime-font")]
+    fn rasterize_missing_glyphs(&mut self) {
+        let frame = self.render_data.get_submit_frame();
+        for (font_handle, unicode) in self.render_data.take_missing_glyphs() {
+            let Some(runtime) = self
+                .resource_pool
+                .get_font_mut(font_handle)
+                .and_then(|font| font.runtime.as_mut())
+            else {
+                continue;
+            };
+            let texture_handle = runtime.texture;
+            match runtime.add_glyph(unicode, frame) {
+                Some(AtlasChange::Region(rect)) => {
+                    let pixels = runtime.get_region_pixels(rect);
+                    if let Some(texture) = self.resource_pool.get_texture(texture_handle) {
+                        self.render_device
+                            .write_texture_region(texture, rect, 4, &pixels);
+                    }
+                }
+                Some(AtlasChange::Resized) => {
+                    let atlas = self.render_device.create_texture(&runtime.get_atlas_desc());
+                    self.resource_pool
+                        .add_resource(texture_handle, Resource::Texture(atlas));
+                    self.rebuild_dependent_materials(texture_handle);
+                }
+                None => {}
+            }
+        }
     }
 
     #[allow(dead_code)]
@@ -1514,6 +4746,24 @@ impl Renderer {
         }
     }
 
+    pub fn get_animation_duration(&self, animation: ResourceHandle) -> Option<f32> {
+        self.resource_pool
+            .get_animation(animation)
+            .map(|animation| animation.get_duration())
+    }
+
+    // Where a bone of a posed skeletal mesh is, relative to the mesh origin
+    pub fn get_bone_model_matrix(
+        &self,
+        mesh: ResourceHandle,
+        pose: &Pose,
+        bone_index: usize,
+    ) -> Option<Mat4> {
+        self.resource_pool
+            .get_skeletal_mesh(mesh)?
+            .get_bone_model_matrix(pose, bone_index)
+    }
+
     #[allow(dead_code)]
     pub fn get_font_glyphs(
         &self,
@@ -1532,3 +4782,94 @@ impl Renderer {
         self.render_data.submit(job, &self.resource_pool);
     }
 }
+
+// Rounded to whole pixels, never empty
+fn get_scaled_size(window_size: UVec2, scale: f32) -> UVec2 {
+    (window_size.as_vec2() * scale)
+        .round()
+        .as_uvec2()
+        .max(UVec2::ONE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera_view_proj(camera_position: Vec3) -> (Mat4, Mat4) {
+        let camera = Transform {
+            position: camera_position,
+            rotation: Quat::from_rotation_x(f32::to_radians(-30.0)),
+            ..Default::default()
+        };
+        let proj = Mat4::perspective_rh(f32::to_radians(60.0), 16.0 / 9.0, 1.0, 1000.0);
+
+        (camera.to_matrix().inverse(), proj)
+    }
+
+    fn light_vp_at(camera_position: Vec3, light_dir: Vec3) -> Mat4 {
+        let (view, proj) = camera_view_proj(camera_position);
+        Renderer::compute_directional_light_vp(
+            view,
+            proj,
+            light_dir,
+            10.0,
+            Renderer::DEFAULT_SHADOW_MAP_SIZE,
+        )
+    }
+
+    #[test]
+    fn sub_texel_camera_moves_keep_the_light_matrix() {
+        let light_dir = Vec3::new(0.3, -1.0, -0.6).normalize();
+        let light_rotation = Renderer::get_light_rotation(light_dir);
+        let base = Vec3::new(10.0, 400.0, 25.0);
+
+        let vp = light_vp_at(base, light_dir);
+        let world_units_per_texel =
+            2.0 / (vp.row(0).truncate().length() * Renderer::DEFAULT_SHADOW_MAP_SIZE as f32);
+
+        // Move the camera so the frustum center sits in the middle of a texel, then nudge it
+        let (view, proj) = camera_view_proj(base);
+        let corners = Renderer::get_frustum_corners((proj * view).inverse());
+        let center_ls = light_rotation.transform_point3(corners.iter().sum::<Vec3>() / 8.0);
+        let target_ls = ((center_ls / world_units_per_texel).floor() + 0.5) * world_units_per_texel;
+        let centered = base
+            + light_rotation
+                .inverse()
+                .transform_vector3(target_ls - center_ls);
+
+        let nudge = Vec3::new(0.2, -0.1, 0.25) * world_units_per_texel;
+        assert_eq!(
+            light_vp_at(centered, light_dir),
+            light_vp_at(centered + nudge, light_dir)
+        );
+
+        let far_move = Vec3::X * world_units_per_texel * 3.0;
+        assert_ne!(
+            light_vp_at(centered, light_dir),
+            light_vp_at(centered + far_move, light_dir)
+        );
+    }
+
+    #[test]
+    fn scene_size_follows_the_render_scale() {
+        let window_size = UVec2::new(1920, 1080);
+        assert_eq!(get_scaled_size(window_size, 1.0), window_size);
+        assert_eq!(get_scaled_size(window_size, 0.5), UVec2::new(960, 540));
+        assert_eq!(get_scaled_size(window_size, 0.3), UVec2::new(576, 324));
+        assert_eq!(get_scaled_size(UVec2::new(2, 1), 0.25), UVec2::ONE);
+    }
+
+    #[test]
+    fn shadow_volume_contains_the_camera_frustum() {
+        let light_dir = Vec3::new(0.0, -1.0, -1.0).normalize();
+        let camera_position = Vec3::new(-40.0, 400.0, 120.0);
+        let vp = light_vp_at(camera_position, light_dir);
+
+        let (view, proj) = camera_view_proj(camera_position);
+        for corner in Renderer::get_frustum_corners((proj * view).inverse()) {
+            let clip = vp.project_point3(corner);
+            assert!(clip.x.abs() <= 1.0 && clip.y.abs() <= 1.0);
+            assert!((0.0..=1.0).contains(&clip.z));
+        }
+    }
+}
@@ -0,0 +1,221 @@
+// Who uses which resources, so the resources of a level can be dropped once it is left. A
+// scope is made for a level or a bundle, and the resources loaded while it is the current
+// scope are registered to it. Loading a name another scope already has registers the same
+// resource again instead of loading it twice. Materials and sprite regions also hold on to
+// what they were made from.
+//
+// The count of a resource is the scopes it is registered to plus the registered resources
+// that depend on it. Unloading a scope releases the resources whose count reaches zero, and
+// with them what only they held on to. Resources loaded outside of any scope, e.g. the debug
+// font, are never counted and never released.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::renderer::ResourceHandle;
+
+pub type ScopeHandle = u32;
+
+struct Scope {
+    name: String,
+    resources: Vec<ResourceHandle>, // In the order they were registered
+}
+
+#[derive(Default)]
+pub struct ResourceScopes {
+    scopes: HashMap<ScopeHandle, Scope>,
+    next_scope: ScopeHandle,
+    current: Option<ScopeHandle>,
+    counts: HashMap<ResourceHandle, u32>, // Only the registered resources
+    dependencies: HashMap<ResourceHandle, Vec<ResourceHandle>>,
+}
+
+impl ResourceScopes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn create_scope(&mut self, name: &str) -> ScopeHandle {
+        let scope = self.next_scope;
+        self.next_scope += 1;
+        self.scopes.insert(
+            scope,
+            Scope {
+                name: name.to_string(),
+                resources: Vec::new(),
+            },
+        );
+        scope
+    }
+
+    // Where the resources loaded from now on are registered, None for nowhere
+    pub fn set_current(&mut self, scope: Option<ScopeHandle>) {
+        self.current = scope.filter(|scope| self.scopes.contains_key(scope));
+    }
+
+    pub fn get_current(&self) -> Option<ScopeHandle> {
+        self.current
+    }
+
+    pub fn is_registered(&self, handle: ResourceHandle) -> bool {
+        self.counts.contains_key(&handle)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_count(&self, handle: ResourceHandle) -> u32 {
+        self.counts.get(&handle).copied().unwrap_or(0)
+    }
+
+    // Once per scope, registering it again changes nothing. The dependencies are only
+    // counted the first time the resource is registered anywhere, and only the ones that are
+    // registered themselves.
+    pub fn register(
+        &mut self,
+        scope: ScopeHandle,
+        handle: ResourceHandle,
+        dependencies: &[ResourceHandle],
+    ) {
+        let Some(entry) = self.scopes.get_mut(&scope) else {
+            return;
+        };
+        if entry.resources.contains(&handle) {
+            return;
+        }
+        entry.resources.push(handle);
+
+        if !self.counts.contains_key(&handle) {
+            let dependencies: Vec<_> = dependencies
+                .iter()
+                .copied()
+                .filter(|dependency| *dependency != handle && self.is_registered(*dependency))
+                .collect();
+            for dependency in &dependencies {
+                *self.counts.entry(*dependency).or_default() += 1;
+            }
+            self.dependencies.insert(handle, dependencies);
+        }
+        *self.counts.entry(handle).or_default() += 1;
+    }
+
+    // Returns the resources nothing holds on to anymore, a resource before the ones it
+    // depends on
+    pub fn unload_scope(&mut self, scope: ScopeHandle) -> Vec<ResourceHandle> {
+        let Some(entry) = self.scopes.remove(&scope) else {
+            return Vec::new();
+        };
+        if self.current == Some(scope) {
+            self.current = None;
+        }
+
+        let mut released = Vec::new();
+        let mut pending = entry.resources;
+        pending.reverse();
+        while let Some(handle) = pending.pop() {
+            let Some(count) = self.counts.get_mut(&handle) else {
+                continue;
+            };
+            *count -= 1;
+            if *count > 0 {
+                continue;
+            }
+            self.counts.remove(&handle);
+            released.push(handle);
+            if let Some(dependencies) = self.dependencies.remove(&handle) {
+                pending.extend(dependencies.into_iter().rev());
+            }
+        }
+        released
+    }
+
+    // The scopes that were never unloaded with what they still hold, e.g. for leak checks
+    pub fn get_loaded_scopes(&self) -> Vec<(&str, &[ResourceHandle])> {
+        let mut scopes: Vec<_> = self.scopes.iter().collect();
+        scopes.sort_by_key(|(scope, _)| **scope);
+        scopes
+            .into_iter()
+            .map(|(_, entry)| (entry.name.as_str(), entry.resources.as_slice()))
+            .collect()
+    }
+
+    // The registered resources, e.g. to check nothing is left once every scope is unloaded
+    #[allow(dead_code)]
+    pub fn get_registered(&self) -> HashSet<ResourceHandle> {
+        self.counts.keys().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXTURE: ResourceHandle = 1;
+    const MATERIAL: ResourceHandle = 2;
+    const MESH: ResourceHandle = 3;
+    const OTHER_MATERIAL: ResourceHandle = 4;
+
+    #[test]
+    fn shared_resources_are_released_with_the_last_scope() {
+        let mut scopes = ResourceScopes::new();
+        let first = scopes.create_scope("Arena");
+        let second = scopes.create_scope("Forest");
+
+        // Both levels load the same material of the same texture
+        for scope in [first, second] {
+            scopes.register(scope, TEXTURE, &[]);
+            scopes.register(scope, MATERIAL, &[TEXTURE]);
+            scopes.register(scope, MATERIAL, &[TEXTURE]);
+        }
+        scopes.register(first, MESH, &[]);
+        assert_eq!(scopes.get_count(MATERIAL), 2);
+        // Two scopes and the material
+        assert_eq!(scopes.get_count(TEXTURE), 3);
+
+        assert_eq!(scopes.unload_scope(first), vec![MESH]);
+        assert_eq!(scopes.get_count(MATERIAL), 1);
+        assert_eq!(scopes.get_count(TEXTURE), 2);
+
+        assert_eq!(scopes.unload_scope(second), vec![MATERIAL, TEXTURE]);
+        assert!(scopes.get_registered().is_empty());
+        assert!(scopes.unload_scope(second).is_empty());
+    }
+
+    #[test]
+    fn dependencies_outlive_their_scope_while_a_material_holds_them() {
+        // Each scope has its own material, the texture is only registered to the first
+        let mut scopes = ResourceScopes::new();
+        let first = scopes.create_scope("Arena");
+        let second = scopes.create_scope("Forest");
+        scopes.register(first, TEXTURE, &[]);
+        scopes.register(first, MATERIAL, &[TEXTURE]);
+        scopes.register(second, OTHER_MATERIAL, &[TEXTURE]);
+        assert_eq!(scopes.get_count(TEXTURE), 3);
+
+        assert_eq!(scopes.unload_scope(first), vec![MATERIAL]);
+        assert!(scopes.is_registered(TEXTURE));
+        assert_eq!(
+            scopes.get_loaded_scopes(),
+            vec![("Forest", &[OTHER_MATERIAL][..])]
+        );
+
+        assert_eq!(scopes.unload_scope(second), vec![OTHER_MATERIAL, TEXTURE]);
+        assert!(scopes.get_loaded_scopes().is_empty());
+    }
+
+    #[test]
+    fn resources_outside_of_scopes_are_never_released() {
+        let mut scopes = ResourceScopes::new();
+        let scope = scopes.create_scope("Arena");
+        // The texture was loaded before any scope, e.g. a built-in one
+        scopes.register(scope, MATERIAL, &[TEXTURE]);
+        assert!(!scopes.is_registered(TEXTURE));
+        assert_eq!(scopes.unload_scope(scope), vec![MATERIAL]);
+
+        // Unloading the current scope leaves none current
+        let scope = scopes.create_scope("Forest");
+        scopes.set_current(Some(scope));
+        assert_eq!(scopes.get_current(), Some(scope));
+        scopes.unload_scope(scope);
+        assert_eq!(scopes.get_current(), None);
+        scopes.set_current(Some(scope));
+        assert_eq!(scopes.get_current(), None);
+    }
+}
@@ -1,19 +1,102 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use crate::renderer::{
-    Animation, Font, MaterialInstance, MaterialPipeline, MeshDrawInfo, SkeletalMesh, StaticMesh,
-    Texture,
+    Animation, Font, MaterialInstance, MaterialPipeline, MeshDrawInfo, SkeletalMesh, SpriteRegion,
+    StaticMesh, Texture,
+    mesh::{DynamicMesh, MeshLod},
 };
 
 #[allow(dead_code)]
 pub enum Resource {
     StaticMesh(StaticMesh),
     SkeletalMesh(SkeletalMesh),
+    DynamicMesh(DynamicMesh),
     Animation(Animation),
     Texture(Texture),
     MaterialPipeline(MaterialPipeline),
     MaterialInstance(MaterialInstance),
     Font(Font),
+    SpriteRegion(SpriteRegion),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ResourceKind {
+    StaticMesh,
+    SkeletalMesh,
+    DynamicMesh,
+    Animation,
+    Texture,
+    MaterialPipeline,
+    MaterialInstance,
+    Font,
+    SpriteRegion,
+}
+
+impl ResourceKind {
+    pub fn get_name(self) -> &'static str {
+        match self {
+            Self::StaticMesh => "Static Mesh",
+            Self::SkeletalMesh => "Skeletal Mesh",
+            Self::DynamicMesh => "Dynamic Mesh",
+            Self::Animation => "Animation",
+            Self::Texture => "Texture",
+            Self::MaterialPipeline => "Pipeline",
+            Self::MaterialInstance => "Material",
+            Self::Font => "Font",
+            Self::SpriteRegion => "Sprite Region",
+        }
+    }
+}
+
+impl Resource {
+    pub fn get_kind(&self) -> ResourceKind {
+        match self {
+            Self::StaticMesh(_) => ResourceKind::StaticMesh,
+            Self::SkeletalMesh(_) => ResourceKind::SkeletalMesh,
+            Self::DynamicMesh(_) => ResourceKind::DynamicMesh,
+            Self::Animation(_) => ResourceKind::Animation,
+            Self::Texture(_) => ResourceKind::Texture,
+            Self::MaterialPipeline(_) => ResourceKind::MaterialPipeline,
+            Self::MaterialInstance(_) => ResourceKind::MaterialInstance,
+            Self::Font(_) => ResourceKind::Font,
+            Self::SpriteRegion(_) => ResourceKind::SpriteRegion,
+        }
+    }
+
+    // Approximate, only buffers and textures are counted. Bind groups and pipelines
+    // have no size we can query.
+    pub fn get_gpu_size(&self) -> u64 {
+        match self {
+            Self::StaticMesh(mesh) => {
+                mesh.vertex_buffer.buffer.size() + mesh.index_buffer.buffer.size()
+            }
+            Self::SkeletalMesh(mesh) => {
+                mesh.vertex_buffer.buffer.size() + mesh.index_buffer.buffer.size()
+            }
+            Self::DynamicMesh(mesh) => {
+                mesh.vertex_buffer.buffer.size() + mesh.index_buffer.buffer.size()
+            }
+            Self::Texture(texture) => get_texture_size(&texture._texture),
+            Self::Font(font) => get_texture_size(&font.atlas._texture),
+            _ => 0,
+        }
+    }
+}
+
+fn get_texture_size(texture: &wgpu::Texture) -> u64 {
+    let (block_width, block_height) = texture.format().block_dimensions();
+    let block_size = texture.format().block_copy_size(None).unwrap_or(0) as u64;
+
+    let mut size = 0;
+    for mip_level in 0..texture.mip_level_count() {
+        let width = (texture.width() >> mip_level).max(1).div_ceil(block_width) as u64;
+        let height = (texture.height() >> mip_level)
+            .max(1)
+            .div_ceil(block_height) as u64;
+        size += width * height * block_size;
+    }
+
+    size * texture.depth_or_array_layers() as u64 * texture.sample_count() as u64
 }
 
 pub type ResourceHandle = u64;
@@ -35,19 +118,93 @@ pub const fn get_handle(s: &str) -> ResourceHandle {
     hash
 }
 
+// The file a resource was loaded from, kept to load it again when the device is lost
+#[derive(Clone)]
+pub struct ResourceSource {
+    pub kind: ResourceKind,
+    pub bytes: Arc<[u8]>,
+}
+
 pub struct ResourcePool {
     resources: HashMap<ResourceHandle, Resource>,
+    names: HashMap<ResourceHandle, String>, // Only for debugging, handles are hashes
+    keep_cpu_copy: bool,                    // Off by default, the files are kept in memory
+    sources: HashMap<ResourceHandle, ResourceSource>,
+    material_aliases: HashMap<ResourceHandle, ResourceHandle>, // Duplicate to canonical
 }
 
 impl ResourcePool {
     pub fn new() -> Self {
         Self {
             resources: HashMap::new(),
+            names: HashMap::new(),
+            keep_cpu_copy: false,
+            sources: HashMap::new(),
+            material_aliases: HashMap::new(),
         }
     }
 
+    // A new resource at the handle has no source until keep_source is called for it
     pub fn add_resource(&mut self, handle: ResourceHandle, resource: Resource) {
         self.resources.insert(handle, resource);
+        self.sources.remove(&handle);
+    }
+
+    // Same as add_resource with the handle of the name, but remembers the name
+    pub fn add_named_resource(&mut self, name: &str, resource: Resource) -> ResourceHandle {
+        let handle = get_handle(name);
+        self.add_resource(handle, resource);
+        self.names.insert(handle, name.to_string());
+        handle
+    }
+
+    pub fn remove_resource(&mut self, handle: ResourceHandle) -> Option<Resource> {
+        self.names.remove(&handle);
+        self.sources.remove(&handle);
+        self.material_aliases
+            .retain(|duplicate, canonical| *duplicate != handle && *canonical != handle);
+        self.resources.remove(&handle)
+    }
+
+    // Whether the files of loaded resources are kept, see keep_source. Turning it off
+    // drops the ones kept so far.
+    pub fn set_keep_cpu_copy(&mut self, enabled: bool) {
+        self.keep_cpu_copy = enabled;
+        if !enabled {
+            self.sources.clear();
+        }
+    }
+
+    pub fn is_keeping_cpu_copy(&self) -> bool {
+        self.keep_cpu_copy
+    }
+
+    // Only kept with the policy on and for kinds that live on the GPU. Static meshes and
+    // animations keep their data on the CPU anyway.
+    pub fn keep_source(&mut self, handle: ResourceHandle, kind: ResourceKind, bytes: &[u8]) {
+        let needs_source = matches!(
+            kind,
+            ResourceKind::SkeletalMesh | ResourceKind::Texture | ResourceKind::Font
+        );
+        if self.keep_cpu_copy && needs_source {
+            let bytes = Arc::from(bytes);
+            self.sources.insert(handle, ResourceSource { kind, bytes });
+        }
+    }
+
+    pub fn get_source(&self, handle: ResourceHandle) -> Option<&ResourceSource> {
+        self.sources.get(&handle)
+    }
+
+    pub fn get_name(&self, handle: ResourceHandle) -> Option<&str> {
+        self.names.get(&handle).map(String::as_str)
+    }
+
+    // In no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (ResourceHandle, ResourceKind)> + '_ {
+        self.resources
+            .iter()
+            .map(|(handle, resource)| (*handle, resource.get_kind()))
     }
 
     pub fn get_resource(&self, handle: ResourceHandle) -> Option<&Resource> {
@@ -65,6 +222,22 @@ impl ResourcePool {
         }
     }
 
+    // Jobs of the duplicate are batched with the canonical material from now on, the
+    // duplicate stays in the pool. See batch_diagnostics.
+    pub fn set_material_alias(&mut self, duplicate: ResourceHandle, canonical: ResourceHandle) {
+        if duplicate != canonical {
+            self.material_aliases.insert(duplicate, canonical);
+        }
+    }
+
+    // The material the batches of the handle are drawn with
+    pub fn get_canonical_material(&self, handle: ResourceHandle) -> ResourceHandle {
+        self.material_aliases
+            .get(&handle)
+            .copied()
+            .unwrap_or(handle)
+    }
+
     pub fn get_material_instance(&self, handle: ResourceHandle) -> Option<&MaterialInstance> {
         match self.get_resource(handle) {
             Some(resource) => match resource {
@@ -75,7 +248,6 @@ impl ResourcePool {
         }
     }
 
-    #[allow(dead_code)]
     pub fn get_mesh(&self, handle: ResourceHandle) -> Option<&StaticMesh> {
         match self.get_resource(handle) {
             Some(resource) => match resource {
@@ -96,17 +268,37 @@ impl ResourcePool {
         }
     }
 
-    pub fn get_mesh_draw_info(&'_ self, handle: ResourceHandle) -> Option<MeshDrawInfo<'_>> {
+    pub fn get_dynamic_mesh_mut(&mut self, handle: ResourceHandle) -> Option<&mut DynamicMesh> {
+        match self.resources.get_mut(&handle) {
+            Some(Resource::DynamicMesh(mesh)) => Some(mesh),
+            _ => None,
+        }
+    }
+
+    pub fn get_mesh_draw_info(
+        &'_ self,
+        handle: ResourceHandle,
+        lod: usize,
+    ) -> Option<MeshDrawInfo<'_>> {
         match self.get_resource(handle) {
             Some(resource) => match resource {
-                Resource::StaticMesh(mesh) => Some(mesh.get_draw_info()),
-                Resource::SkeletalMesh(mesh) => Some(mesh.get_draw_info()),
+                Resource::StaticMesh(mesh) => Some(mesh.get_draw_info(lod)),
+                Resource::SkeletalMesh(mesh) => Some(mesh.get_draw_info(lod)),
+                Resource::DynamicMesh(mesh) => Some(mesh.get_draw_info()),
                 _ => None,
             },
             _ => None,
         }
     }
 
+    pub fn get_mesh_lods(&self, handle: ResourceHandle) -> Option<&[MeshLod]> {
+        match self.get_resource(handle) {
+            Some(Resource::StaticMesh(mesh)) => Some(&mesh.lods),
+            Some(Resource::SkeletalMesh(mesh)) => Some(&mesh.lods),
+            _ => None,
+        }
+    }
+
     #[allow(dead_code)]
     pub fn get_animation(&self, handle: ResourceHandle) -> Option<&Animation> {
         match self.get_resource(handle) {
@@ -129,6 +321,21 @@ impl ResourcePool {
         }
     }
 
+    #[allow(dead_code)]
+    pub fn get_font_mut(&mut self, handle: ResourceHandle) -> Option<&mut Font> {
+        match self.resources.get_mut(&handle) {
+            Some(Resource::Font(font)) => Some(font),
+            _ => None,
+        }
+    }
+
+    pub fn get_sprite_region(&self, handle: ResourceHandle) -> Option<&SpriteRegion> {
+        match self.get_resource(handle) {
+            Some(Resource::SpriteRegion(region)) => Some(region),
+            _ => None,
+        }
+    }
+
     pub fn get_texture(&self, handle: ResourceHandle) -> Option<&Texture> {
         match self.get_resource(handle) {
             Some(resource) => match resource {
@@ -139,3 +346,72 @@ impl ResourcePool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_region(material: ResourceHandle) -> Resource {
+        Resource::SpriteRegion(SpriteRegion {
+            material,
+            tex_coord: Default::default(),
+            tex_scale: Default::default(),
+        })
+    }
+
+    #[test]
+    fn named_resources_can_be_listed() {
+        let mut pool = ResourcePool::new();
+        let handle = pool.add_named_resource("Icons/Sword", create_region(1));
+        pool.add_resource(get_handle("Unnamed"), create_region(2));
+
+        assert_eq!(handle, get_handle("Icons/Sword"));
+        assert_eq!(pool.get_name(handle), Some("Icons/Sword"));
+        assert_eq!(pool.get_name(get_handle("Unnamed")), None);
+
+        let mut listed: Vec<_> = pool.iter().collect();
+        listed.sort();
+        let mut expected = vec![
+            (handle, ResourceKind::SpriteRegion),
+            (get_handle("Unnamed"), ResourceKind::SpriteRegion),
+        ];
+        expected.sort();
+        assert_eq!(listed, expected);
+        assert_eq!(pool.get_resource(handle).unwrap().get_gpu_size(), 0);
+
+        assert!(pool.remove_resource(handle).is_some());
+        assert!(pool.get_resource(handle).is_none());
+        assert_eq!(pool.get_name(handle), None);
+    }
+
+    #[test]
+    fn sources_are_only_kept_with_the_policy() {
+        let mut pool = ResourcePool::new();
+        let handle = pool.add_named_resource("Textures/Grass", create_region(1));
+        pool.keep_source(handle, ResourceKind::Texture, &[1, 2, 3]);
+        assert!(pool.get_source(handle).is_none());
+
+        pool.set_keep_cpu_copy(true);
+        pool.keep_source(handle, ResourceKind::Texture, &[1, 2, 3]);
+        let source = pool.get_source(handle).unwrap();
+        assert_eq!(source.kind, ResourceKind::Texture);
+        assert_eq!(&*source.bytes, &[1, 2, 3]);
+
+        // Kept on the CPU anyway
+        let animation = get_handle("Animations/Idle");
+        pool.keep_source(animation, ResourceKind::Animation, &[4]);
+        assert!(pool.get_source(animation).is_none());
+
+        // Replaced by something that didn't come from the file
+        pool.add_resource(handle, create_region(2));
+        assert!(pool.get_source(handle).is_none());
+
+        pool.keep_source(handle, ResourceKind::Texture, &[5]);
+        assert!(pool.remove_resource(handle).is_some());
+        assert!(pool.get_source(handle).is_none());
+
+        pool.keep_source(handle, ResourceKind::Texture, &[5]);
+        pool.set_keep_cpu_copy(false);
+        assert!(pool.get_source(handle).is_none());
+    }
+}
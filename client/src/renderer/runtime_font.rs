@@ -0,0 +1,355 @@
+// Glyphs the baked MSDF atlas of a font doesn't have, e.g. non-Latin player names, are
+// rasterized from a TTF the first time a text needs them. They go into a second atlas that
+// is drawn as plain alpha sprites, so one string can mix both kinds: the baked glyphs are
+// batched with the font material and the rasterized ones with the material of this atlas.
+// Missing glyphs are collected while texts are submitted and rasterized before the frame
+// is built, so they show up one frame late.
+
+use std::{cell::Cell, collections::HashMap};
+
+use ab_glyph::{Font as _, FontVec, PxScale, ScaleFont};
+use shared::math::*;
+
+use crate::renderer::{PixelRect, ResourceHandle, TextureDesc, font::Bounds};
+
+const EM_SIZE: f32 = 32.0; // Pixels per em the glyphs are rasterized at
+const GLYPH_PADDING: u32 = 1; // Right and below each glyph, so filtering doesn't bleed
+const SHELF_ROUNDING: u32 = 8; // Shelf heights are rounded up so similar glyphs share them
+const ATLAS_WIDTH: u32 = 512;
+const INITIAL_ATLAS_HEIGHT: u32 = 128;
+const MAX_ATLAS_HEIGHT: u32 = 1024;
+
+// Rows of rects. A rect goes into the lowest shelf it fits in, or a new one below the others.
+pub struct ShelfPacker {
+    width: u32,
+    height: u32,
+    shelves: Vec<Shelf>,
+}
+
+struct Shelf {
+    y: u32,
+    height: u32,
+    used_width: u32,
+}
+
+impl ShelfPacker {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            shelves: Vec::new(),
+        }
+    }
+
+    pub fn allocate(&mut self, size: UVec2) -> Option<PixelRect> {
+        let width = self.width;
+        let shelf = self
+            .shelves
+            .iter_mut()
+            .filter(|shelf| shelf.height >= size.y && width - shelf.used_width >= size.x)
+            .min_by_key(|shelf| shelf.height);
+        if let Some(shelf) = shelf {
+            let x = shelf.used_width;
+            shelf.used_width += size.x;
+            return Some(PixelRect {
+                x,
+                y: shelf.y,
+                width: size.x,
+                height: size.y,
+            });
+        }
+
+        let y = self
+            .shelves
+            .last()
+            .map_or(0, |shelf| shelf.y + shelf.height);
+        let height = size.y.next_multiple_of(SHELF_ROUNDING);
+        if size.x > width || y + height > self.height {
+            return None;
+        }
+        self.shelves.push(Shelf {
+            y,
+            height,
+            used_width: size.x,
+        });
+        Some(PixelRect {
+            x: 0,
+            y,
+            width: size.x,
+            height: size.y,
+        })
+    }
+
+    // New shelves can go below the old ones, the allocated rects stay where they are
+    pub fn grow(&mut self, height: u32) {
+        self.height = self.height.max(height);
+    }
+}
+
+pub struct RuntimeGlyph {
+    pub advance: f32,            // In ems, like the baked glyphs
+    pub plane: Option<Bounds>,   // In ems from the pen position, None for blanks
+    pub rect: Option<PixelRect>, // In the atlas
+    last_used: Cell<u64>,        // Frame, for evicting when the atlas is full
+}
+
+// What has to be uploaded after adding a glyph
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AtlasChange {
+    Region(PixelRect),
+    Resized, // The texture has to be created again
+}
+
+pub struct RuntimeFont {
+    font: FontVec,
+    scale: PxScale,
+    glyphs: HashMap<u32, RuntimeGlyph>,
+    packer: ShelfPacker,
+    size: UVec2,
+    max_height: u32,
+    pixels: Vec<u8>, // RGBA, white with the coverage in alpha
+    pub texture: ResourceHandle,
+    pub material: ResourceHandle,
+}
+
+impl RuntimeFont {
+    pub fn new(
+        ttf: Vec<u8>,
+        texture: ResourceHandle,
+        material: ResourceHandle,
+    ) -> anyhow::Result<Self> {
+        let font = FontVec::try_from_vec(ttf)?;
+        let units_per_em = font
+            .units_per_em()
+            .ok_or_else(|| anyhow::anyhow!("The font has no units per em"))?;
+        // PxScale is the height from the descender to the ascender, not the em
+        let scale = PxScale::from(EM_SIZE * font.height_unscaled() / units_per_em);
+
+        let size = UVec2::new(ATLAS_WIDTH, INITIAL_ATLAS_HEIGHT);
+        Ok(Self {
+            font,
+            scale,
+            glyphs: HashMap::new(),
+            packer: ShelfPacker::new(size.x, size.y),
+            size,
+            max_height: MAX_ATLAS_HEIGHT,
+            pixels: vec![0; (size.x * size.y * 4) as usize],
+            texture,
+            material,
+        })
+    }
+
+    // Marks the glyph as used in the frame
+    pub fn get_glyph(&self, unicode: u32, frame: u64) -> Option<&RuntimeGlyph> {
+        let glyph = self.glyphs.get(&unicode)?;
+        glyph.last_used.set(frame);
+        Some(glyph)
+    }
+
+    // Doesn't mark the glyph as used, for measuring text
+    pub fn get_advance(&self, unicode: u32) -> Option<f32> {
+        self.glyphs.get(&unicode).map(|glyph| glyph.advance)
+    }
+
+    pub fn get_atlas_size(&self) -> UVec2 {
+        self.size
+    }
+
+    pub fn get_glyph_count(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    // The whole atlas, for creating the texture
+    pub fn get_atlas_desc(&self) -> TextureDesc {
+        TextureDesc {
+            width: self.size.x,
+            height: self.size.y,
+            channel_count: 4,
+            format: Some(wgpu::TextureFormat::Rgba8Unorm),
+            pixels: self.pixels.clone(),
+            ..Default::default()
+        }
+    }
+
+    // Tightly packed rows of the rect
+    pub fn get_region_pixels(&self, rect: PixelRect) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity((rect.width * rect.height * 4) as usize);
+        for y in rect.y..rect.y + rect.height {
+            let start = ((y * self.size.x + rect.x) * 4) as usize;
+            pixels.extend_from_slice(&self.pixels[start..start + rect.width as usize * 4]);
+        }
+        pixels
+    }
+
+    // Rasterizes the glyph unless it is there already. Characters the font doesn't have get
+    // its missing glyph box. None when nothing has to be uploaded.
+    pub fn add_glyph(&mut self, unicode: u32, frame: u64) -> Option<AtlasChange> {
+        if self.glyphs.contains_key(&unicode) {
+            return None;
+        }
+        let id = self.font.glyph_id(char::from_u32(unicode)?);
+        let advance = self.font.as_scaled(self.scale).h_advance(id) / EM_SIZE;
+
+        let Some(outlined) = self.font.outline_glyph(id.with_scale(self.scale)) else {
+            // A space or another blank, only the advance is needed
+            self.insert(unicode, advance, None, None, frame);
+            return None;
+        };
+        let bounds = outlined.px_bounds();
+        let size = UVec2::new(bounds.width() as u32, bounds.height() as u32);
+        let Some((slot, change)) = self.allocate(size + GLYPH_PADDING, frame) else {
+            log::warn!(
+                "The runtime glyph atlas is full, can't add {:?}",
+                char::from_u32(unicode)
+            );
+            return None;
+        };
+
+        let width = self.size.x;
+        outlined.draw(|x, y, coverage| {
+            let index = (((slot.y + y) * width + slot.x + x) * 4) as usize;
+            let alpha = (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+            self.pixels[index..index + 4].copy_from_slice(&[255, 255, 255, alpha]);
+        });
+
+        let plane = Bounds {
+            offset: Vec2::new(bounds.min.x, bounds.min.y) / EM_SIZE,
+            size: size.as_vec2() / EM_SIZE,
+        };
+        let rect = PixelRect {
+            width: size.x,
+            height: size.y,
+            ..slot
+        };
+        self.insert(unicode, advance, Some(plane), Some(rect), frame);
+        Some(change)
+    }
+
+    fn insert(
+        &mut self,
+        unicode: u32,
+        advance: f32,
+        plane: Option<Bounds>,
+        rect: Option<PixelRect>,
+        frame: u64,
+    ) {
+        self.glyphs.insert(
+            unicode,
+            RuntimeGlyph {
+                advance,
+                plane,
+                rect,
+                last_used: Cell::new(frame),
+            },
+        );
+    }
+
+    // Grows the atlas when the packer is out of room, once it can't grow any more the glyph
+    // least recently used before this frame gives up its place if it is large enough
+    fn allocate(&mut self, size: UVec2, frame: u64) -> Option<(PixelRect, AtlasChange)> {
+        if let Some(slot) = self.packer.allocate(size) {
+            return Some((slot, AtlasChange::Region(slot)));
+        }
+
+        while self.size.y < self.max_height {
+            self.size.y = (self.size.y * 2).min(self.max_height);
+            self.pixels
+                .resize((self.size.x * self.size.y * 4) as usize, 0);
+            self.packer.grow(self.size.y);
+            if let Some(slot) = self.packer.allocate(size) {
+                return Some((slot, AtlasChange::Resized));
+            }
+        }
+
+        let get_slot = |rect: PixelRect| PixelRect {
+            width: rect.width + GLYPH_PADDING,
+            height: rect.height + GLYPH_PADDING,
+            ..rect
+        };
+        let (&unicode, _) = self
+            .glyphs
+            .iter()
+            .filter(|(_, glyph)| glyph.last_used.get() < frame)
+            .filter(|(_, glyph)| {
+                glyph
+                    .rect
+                    .map(get_slot)
+                    .is_some_and(|slot| slot.width >= size.x && slot.height >= size.y)
+            })
+            .min_by_key(|(_, glyph)| glyph.last_used.get())?;
+        let slot = get_slot(self.glyphs.remove(&unicode)?.rect?);
+        for y in slot.y..slot.y + slot.height {
+            let start = ((y * self.size.x + slot.x) * 4) as usize;
+            self.pixels[start..start + slot.width as usize * 4].fill(0);
+        }
+        Some((slot, AtlasChange::Region(slot)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(a: &PixelRect, b: &PixelRect) -> bool {
+        a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+    }
+
+    #[test]
+    fn shelves_pack_without_overlapping() {
+        let mut packer = ShelfPacker::new(64, 32);
+        let mut rects = Vec::new();
+        for (width, height) in [(20, 14), (30, 10), (10, 16), (40, 12), (20, 9)] {
+            rects.push(packer.allocate(UVec2::new(width, height)).unwrap());
+        }
+        for (index, a) in rects.iter().enumerate() {
+            assert!(a.x + a.width <= 64 && a.y + a.height <= 32);
+            for b in &rects[index + 1..] {
+                assert!(!overlaps(a, b), "{:?} and {:?}", a, b);
+            }
+        }
+        // The short one went next to the first row instead of opening a shelf
+        assert_eq!(rects[4].y, 16);
+
+        // No room for another shelf until the packer grows
+        assert_eq!(packer.allocate(UVec2::new(64, 8)), None);
+        packer.grow(48);
+        assert_eq!(packer.allocate(UVec2::new(64, 8)).unwrap().y, 32);
+    }
+
+    #[test]
+    fn full_atlases_grow_and_then_evict_the_oldest_glyphs() {
+        let ttf = include_bytes!("../../res/font/FiraCode-Medium.ttf").to_vec();
+        let mut font = RuntimeFont::new(ttf, 1, 2).unwrap();
+        font.max_height = INITIAL_ATLAS_HEIGHT * 2;
+
+        let letter = 'Ж' as u32;
+        assert!(matches!(
+            font.add_glyph(letter, 1),
+            Some(AtlasChange::Region(_))
+        ));
+        let glyph = font.get_glyph(letter, 1).unwrap();
+        let plane = glyph.plane.as_ref().unwrap();
+        // Above the baseline and about as wide as the advance of a monospace font
+        assert!(plane.offset.y < -0.5 && plane.offset.y + plane.size.y <= 0.05);
+        assert!(glyph.advance > 0.4 && glyph.advance < 0.8);
+        assert_eq!(font.add_glyph(letter, 1), None);
+
+        // More Cyrillic and Greek letters than fit even after growing once
+        let mut changes = Vec::new();
+        for unicode in (0x0400..0x0530).chain(0x0391..0x03c9) {
+            if unicode != letter {
+                changes.push(font.add_glyph(unicode, 2));
+            }
+        }
+        assert_eq!(font.get_atlas_size().y, INITIAL_ATLAS_HEIGHT * 2);
+        assert!(changes.contains(&Some(AtlasChange::Resized)));
+        // The letter of the last frame made room, then everything left was used this frame
+        assert!(font.get_glyph(letter, 2).is_none());
+        assert_eq!(changes.last(), Some(&None));
+
+        // The next frame the glyphs of this one can be evicted
+        let count = font.get_glyph_count();
+        assert!(font.add_glyph('x' as u32, 3).is_some());
+        assert_eq!(font.get_glyph_count(), count);
+    }
+}
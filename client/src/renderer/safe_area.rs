@@ -0,0 +1,458 @@
+// The part of the screen reference space sprites are anchored to. Its edges are the margins
+// in from the screen edges, and on screens wider than the aspect limit the sides are moved
+// in further until the rect is no wider than the limit, so a HUD in the corners stays in
+// view on ultrawide monitors. Absolute sprites are anchored to the whole screen.
+// sprite.wgsl places the sprites in the rect the Renderer uploads from here, the UI hit
+// tests with the same rect.
+//
+// Everything is in physical pixels, the surface and the cursor too. The sprites fit the
+// reference screen into the screen by the fit policy and are then scaled by the DPI scale,
+// by default the scale factor of the window so the UI is as much bigger as the OS asks for.
+// Contain leaves bars beside the reference screen, with letterboxing on the Renderer cuts
+// the scene to the reference screen and the bars keep the clear color.
+
+use shared::math::*;
+
+use crate::renderer::{Renderer, SpriteAnchor};
+
+// Wider screens get the automatic inset
+pub const DEFAULT_MAX_UI_ASPECT: f32 = 16.0 / 9.0;
+// Of the DPI scale, past these the UI no longer fits or can't be read
+const MIN_UI_DPI_SCALE: f32 = 0.5;
+const MAX_UI_DPI_SCALE: f32 = 3.0;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UiDpiMode {
+    FollowOs, // The scale factor of the window, 1.5 at 150% scaling
+    Fixed(f32),
+}
+
+impl UiDpiMode {
+    pub fn get_scale(self, scale_factor: f32) -> f32 {
+        let scale = match self {
+            UiDpiMode::FollowOs => scale_factor,
+            UiDpiMode::Fixed(scale) => scale,
+        };
+        if scale.is_finite() {
+            scale.clamp(MIN_UI_DPI_SCALE, MAX_UI_DPI_SCALE)
+        } else {
+            1.0
+        }
+    }
+}
+
+// How the reference screen is fit into the screen
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitPolicy {
+    Contain, // All of the reference screen is in view, for the HUD
+    Cover,   // The reference screen fills the screen and its edges may be cut off
+    Stretch, // Each axis on its own, the aspect ratio is not kept
+}
+
+// The cursor from the window to the 0 to 1 of InputState, both in physical pixels. A
+// minimized window has no size, the cursor is then at the top left.
+pub fn get_normalized_cursor_position(position: Vec2, window_size: UVec2) -> Vec2 {
+    if window_size.x == 0 || window_size.y == 0 {
+        return Vec2::ZERO;
+    }
+    position / window_size.as_vec2()
+}
+
+// Per edge. The margins are in reference units, see UiViewport::reference.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Insets {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+#[allow(dead_code)]
+impl Insets {
+    pub const ZERO: Insets = Insets::uniform(0.0);
+
+    pub const fn uniform(inset: f32) -> Self {
+        Self {
+            left: inset,
+            top: inset,
+            right: inset,
+            bottom: inset,
+        }
+    }
+
+    fn get_min(&self) -> Vec2 {
+        Vec2::new(self.left, self.top)
+    }
+
+    fn get_max(&self) -> Vec2 {
+        Vec2::new(self.right, self.bottom)
+    }
+}
+
+// Where the anchor is on a rect, from (0, 0) at the top left to (1, 1) at the bottom right.
+// The same as anchor_origin_px in sprite.wgsl.
+pub fn get_anchor_factor(anchor: SpriteAnchor) -> Vec2 {
+    let index = anchor as u32;
+    Vec2::new((index % 3) as f32, (index / 3) as f32) * 0.5
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UiViewport {
+    pub screen_size: Vec2, // Pixels
+    pub reference: Vec2,   // The size of the screen the UI is laid out for
+    pub fit: FitPolicy,
+    pub margins: Insets,
+    pub max_aspect: f32,
+    pub dpi_mode: UiDpiMode,
+    pub scale_factor: f32, // Of the window
+}
+
+impl UiViewport {
+    pub fn new(screen_size: Vec2) -> Self {
+        Self {
+            screen_size,
+            reference: Renderer::SPRITE_SCREEN_REFERENCE,
+            fit: FitPolicy::Contain,
+            margins: Insets::ZERO,
+            max_aspect: DEFAULT_MAX_UI_ASPECT,
+            dpi_mode: UiDpiMode::FollowOs,
+            scale_factor: 1.0,
+        }
+    }
+
+    // Pixels per reference unit on each axis, the same on both unless stretched
+    pub fn get_fit_scale(&self) -> Vec2 {
+        let scale = self.screen_size / self.reference;
+        match self.fit {
+            FitPolicy::Contain => Vec2::splat(scale.min_element()),
+            FitPolicy::Cover => Vec2::splat(scale.max_element()),
+            FitPolicy::Stretch => scale,
+        }
+    }
+
+    pub fn get_dpi_scale(&self) -> f32 {
+        self.dpi_mode.get_scale(self.scale_factor)
+    }
+
+    // Pixels per reference unit the sprites are drawn with
+    pub fn get_ui_scale(&self) -> Vec2 {
+        self.get_fit_scale() * self.get_dpi_scale()
+    }
+
+    // The margins and the aspect inset together, in pixels. The margins are kept from the
+    // screen edges, they only follow the fit.
+    pub fn get_insets(&self) -> Insets {
+        let scale = self.get_fit_scale();
+        let max_width = self.screen_size.y * self.max_aspect;
+        let side = ((self.screen_size.x - max_width) * 0.5).max(0.0);
+        Insets {
+            left: side + self.margins.left * scale.x,
+            top: self.margins.top * scale.y,
+            right: side + self.margins.right * scale.x,
+            bottom: self.margins.bottom * scale.y,
+        }
+    }
+
+    // The top left and the size in pixels, never negative
+    pub fn get_safe_rect(&self) -> (Vec2, Vec2) {
+        let insets = self.get_insets();
+        let position = insets.get_min().min(self.screen_size);
+        let size = (self.screen_size - insets.get_min() - insets.get_max()).max(Vec2::ZERO);
+        (position, size)
+    }
+
+    // The pixel a reference space sprite anchored there is at with a position of zero
+    pub fn get_anchor_point(&self, anchor: SpriteAnchor) -> Vec2 {
+        let (position, size) = self.get_safe_rect();
+        position + size * get_anchor_factor(anchor)
+    }
+
+    // A point in the reference space of the anchor to pixels, like the shader does
+    pub fn get_pixel(&self, anchor: SpriteAnchor, point: Vec2) -> Vec2 {
+        self.get_anchor_point(anchor) + point * self.get_ui_scale()
+    }
+
+    // Where the reference screen is on the screen, centered. Only Contain leaves bars beside
+    // it, the other policies fill the screen.
+    pub fn get_content_rect(&self) -> (Vec2, Vec2) {
+        let size = (self.reference * self.get_fit_scale()).min(self.screen_size);
+        ((self.screen_size - size) * 0.5, size)
+    }
+
+    // The scissor rect of the scene in whole pixels, None without bars to clear
+    pub fn get_letterbox(&self) -> Option<(UVec2, UVec2)> {
+        let (position, size) = self.get_content_rect();
+        let screen_size = self.screen_size.as_uvec2();
+        let position = position.round().as_uvec2();
+        let size = size
+            .round()
+            .as_uvec2()
+            .min(screen_size - position.min(screen_size));
+        (size != screen_size && size.cmpgt(UVec2::ZERO).all()).then_some((position, size))
+    }
+
+    #[allow(dead_code)]
+    pub fn get_reference_point(&self, anchor: SpriteAnchor, pixel: Vec2) -> Vec2 {
+        (pixel - self.get_anchor_point(anchor)) / self.get_ui_scale()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDE: Vec2 = Vec2::new(2560.0, 1080.0); // 21:9
+    const SQUARE: Vec2 = Vec2::new(1440.0, 1080.0); // 4:3
+
+    #[test]
+    fn wide_screens_keep_the_corners_within_the_aspect_limit() {
+        let viewport = UiViewport::new(WIDE);
+        assert_eq!(viewport.get_ui_scale(), Vec2::ONE);
+        // 1920 wide in the middle
+        assert_eq!(
+            viewport.get_safe_rect(),
+            (Vec2::new(320.0, 0.0), Vec2::new(1920.0, 1080.0))
+        );
+        assert_eq!(
+            viewport.get_pixel(SpriteAnchor::TopLeft, Vec2::new(10.0, 20.0)),
+            Vec2::new(330.0, 20.0)
+        );
+        assert_eq!(
+            viewport.get_pixel(SpriteAnchor::BottomRight, Vec2::new(-10.0, -20.0)),
+            Vec2::new(2230.0, 1060.0)
+        );
+        assert_eq!(
+            viewport.get_anchor_point(SpriteAnchor::Center),
+            Vec2::new(1280.0, 540.0)
+        );
+
+        // Without the limit it is the whole screen
+        let viewport = UiViewport {
+            max_aspect: f32::INFINITY,
+            ..viewport
+        };
+        assert_eq!(
+            viewport.get_pixel(SpriteAnchor::BottomRight, Vec2::new(-10.0, -20.0)),
+            Vec2::new(2550.0, 1060.0)
+        );
+    }
+
+    #[test]
+    fn margins_are_scaled_with_the_ui() {
+        // Narrower than the limit, the whole screen
+        assert_eq!(
+            UiViewport::new(SQUARE).get_safe_rect(),
+            (Vec2::ZERO, SQUARE)
+        );
+
+        // Half of 4:3, the width decides the scale
+        let viewport = UiViewport {
+            margins: Insets {
+                left: 40.0,
+                top: 20.0,
+                right: 60.0,
+                bottom: 0.0,
+            },
+            ..UiViewport::new(SQUARE / 2.0)
+        };
+        assert_eq!(viewport.get_ui_scale(), Vec2::splat(0.375));
+        assert_eq!(
+            viewport.get_safe_rect(),
+            (Vec2::new(15.0, 7.5), Vec2::new(720.0 - 37.5, 540.0 - 7.5))
+        );
+        assert_eq!(
+            viewport.get_pixel(SpriteAnchor::TopRight, Vec2::new(-8.0, 0.0)),
+            Vec2::new(694.5, 7.5)
+        );
+        let point = Vec2::new(-30.0, 12.0);
+        assert_eq!(
+            viewport.get_reference_point(
+                SpriteAnchor::Center,
+                viewport.get_pixel(SpriteAnchor::Center, point)
+            ),
+            point
+        );
+
+        // Margins wider than the screen leave an empty rect
+        let viewport = UiViewport {
+            margins: Insets::uniform(2000.0),
+            ..UiViewport::new(SQUARE)
+        };
+        assert_eq!(viewport.get_safe_rect().1, Vec2::ZERO);
+    }
+
+    #[test]
+    fn cursor_and_sprites_agree_at_every_scale_factor() {
+        // The scale factor and the logical window size, winit rounds the physical size
+        let matrix = [
+            (1.0, Vec2::new(1920.0, 1080.0)),
+            (1.25, Vec2::new(1536.0, 864.0)),
+            (1.5, Vec2::new(1280.0, 720.0)),
+            (1.75, Vec2::new(1100.0, 700.0)),
+            (2.0, Vec2::new(1920.0, 1080.0)),
+        ];
+        for (scale_factor, logical_size) in matrix {
+            let physical_size = (logical_size * scale_factor).round().as_uvec2();
+            let viewport = UiViewport {
+                scale_factor,
+                ..UiViewport::new(physical_size.as_vec2())
+            };
+            assert_eq!(viewport.get_dpi_scale(), scale_factor);
+            assert_eq!(
+                viewport.get_ui_scale(),
+                viewport.get_fit_scale() * scale_factor
+            );
+
+            // The cursor normalizes to the same point whatever the scaling
+            let logical_cursor = logical_size * Vec2::new(0.25, 0.75);
+            let normalized =
+                get_normalized_cursor_position(logical_cursor * scale_factor, physical_size);
+            assert!(normalized.abs_diff_eq(Vec2::new(0.25, 0.75), 1e-3));
+
+            // A button 100 by 50 at (40, 30) from the top left, the cursor on its center hits
+            // its center
+            let position = viewport.get_pixel(SpriteAnchor::TopLeft, Vec2::new(40.0, 30.0));
+            let size = Vec2::new(100.0, 50.0) * viewport.get_ui_scale();
+            let cursor = get_normalized_cursor_position(position + size * 0.5, physical_size);
+            let pixel = cursor * viewport.screen_size;
+            assert!(
+                viewport
+                    .get_reference_point(SpriteAnchor::TopLeft, pixel)
+                    .abs_diff_eq(Vec2::new(90.0, 55.0), 1e-3),
+                "{} at {}",
+                scale_factor,
+                logical_size
+            );
+
+            // Text of size 20 is 20 reference units tall in physical pixels
+            let glyph_pixels = 20.0 * viewport.get_ui_scale();
+            assert_eq!(
+                glyph_pixels,
+                Vec2::splat(
+                    20.0 * scale_factor
+                        * (physical_size.y as f32 / 1080.0).min(physical_size.x as f32 / 1920.0)
+                )
+            );
+
+            // A fixed scale ignores the OS
+            let fixed = UiViewport {
+                dpi_mode: UiDpiMode::Fixed(1.0),
+                ..viewport
+            };
+            assert_eq!(fixed.get_ui_scale(), fixed.get_fit_scale());
+            // The margins only follow the fit
+            let fixed_margins = UiViewport {
+                margins: Insets::uniform(10.0),
+                ..fixed
+            };
+            let margins = UiViewport {
+                margins: Insets::uniform(10.0),
+                ..viewport
+            };
+            assert_eq!(margins.get_safe_rect(), fixed_margins.get_safe_rect());
+        }
+    }
+
+    #[test]
+    fn fit_policies_scale_the_reference_screen() {
+        // 21:9 against a 16:9 reference, the height fits and the width is too wide
+        let viewport = UiViewport {
+            max_aspect: f32::INFINITY,
+            ..UiViewport::new(WIDE)
+        };
+        assert_eq!(viewport.get_fit_scale(), Vec2::ONE);
+        let cover = UiViewport {
+            fit: FitPolicy::Cover,
+            ..viewport
+        };
+        assert_eq!(cover.get_fit_scale(), Vec2::splat(2560.0 / 1920.0));
+        let stretch = UiViewport {
+            fit: FitPolicy::Stretch,
+            ..viewport
+        };
+        assert_eq!(stretch.get_fit_scale(), Vec2::new(2560.0 / 1920.0, 1.0));
+        // The far corner of the reference screen is the far corner of the screen
+        assert_eq!(
+            stretch.get_pixel(SpriteAnchor::TopLeft, Renderer::SPRITE_SCREEN_REFERENCE),
+            WIDE
+        );
+
+        // Margins follow each axis of a stretch
+        let margins = UiViewport {
+            margins: Insets::uniform(30.0),
+            ..stretch
+        };
+        assert_eq!(margins.get_safe_rect().0, Vec2::new(40.0, 30.0));
+    }
+
+    #[test]
+    fn contain_leaves_bars_to_clear() {
+        // Bars on the sides of a wide screen, above and below a square one
+        let wide = UiViewport::new(WIDE);
+        assert_eq!(
+            wide.get_content_rect(),
+            (Vec2::new(320.0, 0.0), Vec2::new(1920.0, 1080.0))
+        );
+        assert_eq!(
+            wide.get_letterbox(),
+            Some((UVec2::new(320, 0), UVec2::new(1920, 1080)))
+        );
+        assert_eq!(
+            UiViewport::new(SQUARE).get_letterbox(),
+            Some((UVec2::new(0, 135), UVec2::new(1440, 810)))
+        );
+
+        // Nothing to clear when the aspect ratios match or the reference fills the screen
+        assert_eq!(
+            UiViewport::new(Vec2::new(1280.0, 720.0)).get_letterbox(),
+            None
+        );
+        for fit in [FitPolicy::Cover, FitPolicy::Stretch] {
+            let viewport = UiViewport { fit, ..wide };
+            assert_eq!(viewport.get_content_rect(), (Vec2::ZERO, WIDE));
+            assert_eq!(viewport.get_letterbox(), None);
+        }
+        // Nor on a minimized window
+        assert_eq!(UiViewport::new(Vec2::ZERO).get_letterbox(), None);
+    }
+
+    #[test]
+    fn a_new_reference_moves_anchored_points() {
+        // 4:3, the width of the reference decides
+        let viewport = UiViewport::new(SQUARE);
+        let point = Vec2::new(-100.0, -50.0);
+        assert_eq!(
+            viewport.get_pixel(SpriteAnchor::BottomRight, point),
+            Vec2::new(1365.0, 1042.5)
+        );
+
+        // Half as big a reference in the same session, now the width is twice the reference
+        // and the height 1.5 times
+        let viewport = UiViewport {
+            reference: Vec2::new(960.0, 540.0),
+            ..viewport
+        };
+        assert_eq!(viewport.get_ui_scale(), Vec2::splat(1.5));
+        let pixel = viewport.get_pixel(SpriteAnchor::BottomRight, point);
+        assert_eq!(pixel, Vec2::new(1290.0, 1005.0));
+        assert_eq!(
+            viewport.get_reference_point(SpriteAnchor::BottomRight, pixel),
+            point
+        );
+    }
+
+    #[test]
+    fn dpi_scales_are_kept_in_range() {
+        assert_eq!(UiDpiMode::Fixed(10.0).get_scale(1.0), 3.0);
+        assert_eq!(UiDpiMode::Fixed(0.0).get_scale(1.0), 0.5);
+        assert_eq!(UiDpiMode::FollowOs.get_scale(f32::NAN), 1.0);
+        assert_eq!(UiDpiMode::FollowOs.get_scale(1.5), 1.5);
+        // Minimized
+        assert_eq!(
+            get_normalized_cursor_position(Vec2::new(10.0, 10.0), UVec2::ZERO),
+            Vec2::ZERO
+        );
+    }
+}
@@ -0,0 +1,349 @@
+// A small preprocessor for the WGSL shaders in res/shaders, which WGSL has none of:
+//
+//     #include "common.wgsl"   pastes the file in, once per shader, from res/shaders
+//     #ifdef FEATURE           keeps the lines up to #else or #endif when FEATURE is defined
+//     #ifndef FEATURE          the opposite
+//     #else, #endif
+//
+// The directives have to be on lines of their own. The result keeps where every line came
+// from, so errors wgpu reports in the pasted source can be traced back to the files.
+
+use std::collections::HashSet;
+
+use anyhow::{Context, bail};
+
+use crate::renderer::shader_layout::{LAYOUTS_FILE, get_layouts_source};
+
+// The shaders are built into the binary, so they are there on the web as well
+const SHADER_SOURCES: &[(&str, &str)] = &[
+    ("common.wgsl", include_str!("../../res/shaders/common.wgsl")),
+    (
+        "composite.wgsl",
+        include_str!("../../res/shaders/composite.wgsl"),
+    ),
+    ("cull.wgsl", include_str!("../../res/shaders/cull.wgsl")),
+    (
+        "debug_line.wgsl",
+        include_str!("../../res/shaders/debug_line.wgsl"),
+    ),
+    ("fxaa.wgsl", include_str!("../../res/shaders/fxaa.wgsl")),
+    ("mesh.wgsl", include_str!("../../res/shaders/mesh.wgsl")),
+    ("scene.wgsl", include_str!("../../res/shaders/scene.wgsl")),
+    ("shadow.wgsl", include_str!("../../res/shaders/shadow.wgsl")),
+    (
+        "skeletal.wgsl",
+        include_str!("../../res/shaders/skeletal.wgsl"),
+    ),
+    (
+        "skeletal_weights.wgsl",
+        include_str!("../../res/shaders/skeletal_weights.wgsl"),
+    ),
+    (
+        "skeletal_xray.wgsl",
+        include_str!("../../res/shaders/skeletal_xray.wgsl"),
+    ),
+    ("sprite.wgsl", include_str!("../../res/shaders/sprite.wgsl")),
+    ("static.wgsl", include_str!("../../res/shaders/static.wgsl")),
+    (
+        "world_grid.wgsl",
+        include_str!("../../res/shaders/world_grid.wgsl"),
+    ),
+];
+
+// Along with layouts.wgsl, which is generated from the Rust structs
+pub fn get_shader_source(name: &str) -> Option<&'static str> {
+    if name == LAYOUTS_FILE {
+        return Some(get_layouts_source());
+    }
+    SHADER_SOURCES
+        .iter()
+        .find(|(file, _)| *file == name)
+        .map(|(_, source)| *source)
+}
+
+// A shader with its defines, the defines are sorted so their order doesn't matter
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShaderKey {
+    pub name: String,
+    pub defines: Vec<String>,
+}
+
+impl ShaderKey {
+    pub fn new(name: &str, defines: &[&str]) -> Self {
+        let mut defines: Vec<String> = defines.iter().map(|define| define.to_string()).collect();
+        defines.sort();
+        defines.dedup();
+        Self {
+            name: name.to_string(),
+            defines,
+        }
+    }
+
+    // E.g. "shadow.wgsl SKINNED"
+    pub fn get_label(&self) -> String {
+        std::iter::once(self.name.as_str())
+            .chain(self.defines.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[derive(Debug)]
+pub struct PreprocessedShader {
+    pub source: String,
+    pub lines: Vec<(String, u32)>, // The file and line of every line of the source
+}
+
+impl PreprocessedShader {
+    // Both 1-based like the line numbers of wgpu's messages
+    pub fn get_location(&self, line: u32) -> Option<(&str, u32)> {
+        let (file, line) = self.lines.get((line as usize).checked_sub(1)?)?;
+        Some((file.as_str(), *line))
+    }
+}
+
+pub fn preprocess_shader<'a>(
+    name: &str,
+    defines: &[&str],
+    get_source: impl Fn(&str) -> Option<&'a str>,
+) -> anyhow::Result<PreprocessedShader> {
+    let mut preprocessor = Preprocessor {
+        defines,
+        get_source: &get_source,
+        stack: Vec::new(),
+        included: HashSet::new(),
+        shader: PreprocessedShader {
+            source: String::new(),
+            lines: Vec::new(),
+        },
+    };
+    preprocessor.add_file(name)?;
+    Ok(preprocessor.shader)
+}
+
+struct Preprocessor<'a, 'b> {
+    defines: &'b [&'b str],
+    get_source: &'b dyn Fn(&str) -> Option<&'a str>,
+    stack: Vec<String>,        // The files being included, the shader itself first
+    included: HashSet<String>, // Including one of them again does nothing
+    shader: PreprocessedShader,
+}
+
+impl Preprocessor<'_, '_> {
+    fn add_file(&mut self, name: &str) -> anyhow::Result<()> {
+        if self.stack.iter().any(|file| file == name) {
+            bail!("Include cycle: {} -> {}", self.stack.join(" -> "), name);
+        }
+        if !self.included.insert(name.to_string()) {
+            return Ok(());
+        }
+        let Some(source) = (self.get_source)(name) else {
+            bail!("No shader named {}", name);
+        };
+
+        self.stack.push(name.to_string());
+        // Whether the lines are kept, one per open #ifdef or #ifndef
+        let mut conditions: Vec<bool> = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index as u32 + 1;
+            let location = || format!("{}:{}", name, line_number);
+            let active = conditions.iter().all(|active| *active);
+
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    self.shader.source.push_str(line);
+                    self.shader.source.push('\n');
+                    self.shader.lines.push((name.to_string(), line_number));
+                }
+                continue;
+            };
+
+            let (keyword, argument) = directive
+                .split_once(char::is_whitespace)
+                .map_or((directive, ""), |(keyword, argument)| {
+                    (keyword, argument.trim())
+                });
+            match keyword {
+                "include" => {
+                    let Some(file) = argument
+                        .strip_prefix('"')
+                        .and_then(|argument| argument.strip_suffix('"'))
+                    else {
+                        bail!("{}: Expected a quoted file name", location());
+                    };
+                    if active {
+                        self.add_file(file)
+                            .with_context(|| format!("Included from {}", location()))?;
+                    }
+                }
+                "ifdef" | "ifndef" => {
+                    if argument.is_empty() {
+                        bail!("{}: #{} needs a define", location(), keyword);
+                    }
+                    let defined = self.defines.contains(&argument);
+                    conditions.push(defined == (keyword == "ifdef"));
+                }
+                "else" => {
+                    let Some(condition) = conditions.last_mut() else {
+                        bail!("{}: #else without #ifdef", location());
+                    };
+                    *condition = !*condition;
+                }
+                "endif" => {
+                    if conditions.pop().is_none() {
+                        bail!("{}: #endif without #ifdef", location());
+                    }
+                }
+                _ => bail!("{}: Unknown directive #{}", location(), keyword),
+            }
+        }
+        if !conditions.is_empty() {
+            bail!("{}: #ifdef without #endif", name);
+        }
+        self.stack.pop();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocess(
+        name: &str,
+        defines: &[&str],
+        files: &[(&str, &'static str)],
+    ) -> anyhow::Result<PreprocessedShader> {
+        preprocess_shader(name, defines, |file| {
+            files
+                .iter()
+                .find(|(other, _)| *other == file)
+                .map(|(_, source)| *source)
+        })
+    }
+
+    #[test]
+    fn includes_are_pasted_once_and_keep_their_lines() {
+        let files = [
+            (
+                "main.wgsl",
+                "#include \"a.wgsl\"\n#include \"b.wgsl\"\nmain",
+            ),
+            ("a.wgsl", "#include \"common.wgsl\"\na"),
+            ("b.wgsl", "  #include \"common.wgsl\"\nb"),
+            ("common.wgsl", "common 1\ncommon 2"),
+        ];
+        assert_eq!(
+            preprocess("main.wgsl", &[], &files).unwrap().source,
+            "common 1\ncommon 2\na\nb\nmain\n"
+        );
+
+        let shader = preprocess("main.wgsl", &[], &files).unwrap();
+        assert_eq!(shader.get_location(2), Some(("common.wgsl", 2)));
+        assert_eq!(shader.get_location(4), Some(("b.wgsl", 2)));
+        assert_eq!(shader.get_location(5), Some(("main.wgsl", 3)));
+        assert_eq!(shader.get_location(0), None);
+        assert_eq!(shader.get_location(6), None);
+    }
+
+    #[test]
+    fn defines_pick_the_branches() {
+        let files = [(
+            "main.wgsl",
+            "#ifdef SKINNED\nskinned\n#ifndef SHADOW\nlit\n#endif\n#else\nstatic\n#endif\nend",
+        )];
+        assert_eq!(
+            preprocess("main.wgsl", &[], &files).unwrap().source,
+            "static\nend\n"
+        );
+        assert_eq!(
+            preprocess("main.wgsl", &["SKINNED"], &files)
+                .unwrap()
+                .source,
+            "skinned\nlit\nend\n"
+        );
+        assert_eq!(
+            preprocess("main.wgsl", &["SHADOW", "SKINNED"], &files)
+                .unwrap()
+                .source,
+            "skinned\nend\n"
+        );
+    }
+
+    #[test]
+    fn broken_shaders_name_the_file_and_line() {
+        let error = |files: &[(&str, &'static str)]| {
+            let error = preprocess("main.wgsl", &[], files).unwrap_err();
+            format!("{:#}", error)
+        };
+
+        let cycle = error(&[
+            ("main.wgsl", "#include \"a.wgsl\""),
+            ("a.wgsl", "\n#include \"main.wgsl\""),
+        ]);
+        assert!(cycle.contains("a.wgsl:2"), "{}", cycle);
+        assert!(
+            cycle.contains("main.wgsl -> a.wgsl -> main.wgsl"),
+            "{}",
+            cycle
+        );
+
+        let missing = error(&[("main.wgsl", "x\n#include \"missing.wgsl\"")]);
+        assert!(missing.contains("main.wgsl:2"), "{}", missing);
+        assert!(
+            missing.contains("No shader named missing.wgsl"),
+            "{}",
+            missing
+        );
+
+        let unclosed = error(&[("main.wgsl", "#ifdef A\nx")]);
+        assert!(unclosed.contains("#ifdef without #endif"), "{}", unclosed);
+        let unopened = error(&[("main.wgsl", "x\n#endif")]);
+        assert!(unopened.contains("main.wgsl:2"), "{}", unopened);
+        let unknown = error(&[("main.wgsl", "#define A")]);
+        assert!(unknown.contains("Unknown directive #define"), "{}", unknown);
+    }
+
+    #[test]
+    fn built_in_shaders_preprocess() {
+        for (name, _) in SHADER_SOURCES {
+            for defines in [&[][..], &["SKINNED"]] {
+                let shader = preprocess_shader(name, defines, get_shader_source);
+                assert!(shader.is_ok(), "{}: {:#}", name, shader.unwrap_err());
+            }
+        }
+
+        assert_eq!(
+            ShaderKey::new("shadow.wgsl", &["SKINNED", "A", "SKINNED"]),
+            ShaderKey::new("shadow.wgsl", &["A", "SKINNED"]),
+        );
+        assert_ne!(
+            ShaderKey::new("shadow.wgsl", &["SKINNED"]),
+            ShaderKey::new("shadow.wgsl", &[]),
+        );
+    }
+
+    #[test]
+    fn shadows_sway_with_the_static_meshes() {
+        // The shadow pass leans the same vertices by the same uniform, or the shadows of
+        // the grass would stay put while it moves
+        for name in ["static.wgsl", "shadow.wgsl"] {
+            let shader = preprocess_shader(name, &[], get_shader_source).unwrap();
+            assert!(shader.source.contains("fn apply_wind("), "{}", name);
+            assert!(
+                shader.source.contains("apply_wind(instance.model_matrix")
+                    || shader.source.contains("apply_wind(model"),
+                "{} does not sway",
+                name
+            );
+            assert!(
+                shader
+                    .source
+                    .contains("@group(0) @binding(0) var<uniform> uniform_buffer"),
+                "{}",
+                name
+            );
+        }
+    }
+}
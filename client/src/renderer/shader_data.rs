@@ -0,0 +1,141 @@
+// Arrays the shaders index, e.g. the bones. A storage buffer where the device has them in
+// every stage, otherwise a texture of vec4<f32> texels the shaders read with textureLoad,
+// see InstancePath.
+
+use crate::renderer::{Buffer, BufferDesc, InstancePath, RenderDevice};
+
+// Texels per row, a multiple of 4 so a matrix is never split across rows. WebGL2 textures
+// can be 2048 wide at least.
+pub const DATA_TEXTURE_WIDTH: u32 = 1024;
+const TEXEL_SIZE: usize = 16;
+
+pub enum ShaderData {
+    Buffer(Buffer),
+    Texture {
+        texture: wgpu::Texture,
+        view: wgpu::TextureView,
+    },
+}
+
+impl ShaderData {
+    pub fn get_binding_type(&self) -> wgpu::BindingType {
+        match self {
+            Self::Buffer(_) => wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            Self::Texture { .. } => wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+        }
+    }
+
+    pub fn get_resource(&self) -> wgpu::BindingResource<'_> {
+        match self {
+            Self::Buffer(buffer) => buffer.buffer.as_entire_binding(),
+            Self::Texture { view, .. } => wgpu::BindingResource::TextureView(view),
+        }
+    }
+}
+
+// The rows of texels the size in bytes takes
+fn get_data_texture_height(size: usize) -> u32 {
+    let texel_count = size.div_ceil(TEXEL_SIZE) as u32;
+    texel_count.div_ceil(DATA_TEXTURE_WIDTH).max(1)
+}
+
+impl RenderDevice {
+    pub fn create_shader_data(&self, size: usize) -> ShaderData {
+        if self.get_instance_path() == InstancePath::StorageBuffers {
+            return ShaderData::Buffer(self.create_buffer(&BufferDesc {
+                size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            }));
+        }
+
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shader Data"),
+            size: wgpu::Extent3d {
+                width: DATA_TEXTURE_WIDTH,
+                height: get_data_texture_height(size),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        ShaderData::Texture { texture, view }
+    }
+
+    // From the start, the bytes are whole texels
+    pub fn write_shader_data(&self, data: &ShaderData, bytes: &[u8]) {
+        let texture = match data {
+            ShaderData::Buffer(buffer) => {
+                self.write_buffer(buffer, bytes, 0);
+                return;
+            }
+            ShaderData::Texture { texture, .. } => texture,
+        };
+
+        // The full rows, then what is left for the last one
+        let row_size = DATA_TEXTURE_WIDTH as usize * TEXEL_SIZE;
+        let row_count = bytes.len() / row_size;
+        let rest = &bytes[row_count * row_size..];
+        let copies = [
+            (
+                0,
+                DATA_TEXTURE_WIDTH,
+                row_count as u32,
+                &bytes[..row_count * row_size],
+            ),
+            (row_count as u32, (rest.len() / TEXEL_SIZE) as u32, 1, rest),
+        ];
+        for (row, width, height, bytes) in copies {
+            if width == 0 || height == 0 {
+                continue;
+            }
+            self.queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: row, z: 0 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytes,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width * TEXEL_SIZE as u32),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_textures_have_whole_rows_for_the_size() {
+        let row_size = DATA_TEXTURE_WIDTH as usize * TEXEL_SIZE;
+        assert_eq!(get_data_texture_height(0), 1);
+        assert_eq!(get_data_texture_height(64), 1);
+        assert_eq!(get_data_texture_height(row_size), 1);
+        assert_eq!(get_data_texture_height(row_size + 64), 2);
+        // The bones of the WebGL2 path, four texels each
+        assert_eq!(get_data_texture_height(256 * 64 * 64), 64);
+    }
+}
@@ -0,0 +1,613 @@
+// Keeps the shaders and the Rust structs they read from drifting apart.
+//
+// The structs of the instance and uniform buffers are declared with wgsl_struct!, which
+// gives every field its WGSL type. Their WGSL is generated from the Rust fields and included
+// by the shaders from layouts.wgsl, generating it fails when the WGSL rules would put a field
+// somewhere else than the Rust struct has it.
+//
+// The vertex inputs have to be written out in the shaders, in debug builds they are scanned
+// from the source and compared with the vertex buffer layout of every pipeline made with it.
+
+use std::sync::LazyLock;
+
+use anyhow::bail;
+
+use crate::renderer::renderer::{SpriteUniformBufferData, UniformBufferData};
+use crate::renderer::{SpriteInstanceData, StaticInstanceData};
+
+pub const LAYOUTS_FILE: &str = "layouts.wgsl";
+
+// A Rust struct with the WGSL type of every field:
+//
+//   wgsl_struct! {
+//       #[repr(C)]
+//       pub struct LightData {
+//           pub direction: Vec4Data => "vec4<f32>",
+//           pub intensity: f32 => "f32",
+//       }
+//   }
+macro_rules! wgsl_struct {
+    (
+        $(#[$attribute:meta])*
+        $visibility:vis struct $name:ident {
+            $($field_visibility:vis $field:ident: $field_type:ty => $wgsl_type:literal,)*
+        }
+    ) => {
+        $(#[$attribute])*
+        $visibility struct $name {
+            $($field_visibility $field: $field_type,)*
+        }
+
+        impl $crate::renderer::shader_layout::WgslStruct for $name {
+            const WGSL_NAME: &'static str = stringify!($name);
+
+            fn get_wgsl_fields() -> Vec<$crate::renderer::shader_layout::WgslField> {
+                vec![$($crate::renderer::shader_layout::WgslField {
+                    name: stringify!($field),
+                    wgsl_type: $wgsl_type,
+                    offset: std::mem::offset_of!($name, $field),
+                    size: std::mem::size_of::<$field_type>(),
+                },)*]
+            }
+        }
+    };
+}
+pub(crate) use wgsl_struct;
+
+pub struct WgslField {
+    pub name: &'static str,
+    pub wgsl_type: &'static str,
+    pub offset: usize, // Where the Rust struct has it
+    pub size: usize,
+}
+
+pub trait WgslStruct: Sized {
+    const WGSL_NAME: &'static str;
+
+    fn get_wgsl_fields() -> Vec<WgslField>;
+}
+
+// The size and alignment in buffers, only the types the structs use
+fn get_wgsl_size_align(wgsl_type: &str) -> Option<(usize, usize)> {
+    match wgsl_type {
+        "f32" | "u32" | "i32" => Some((4, 4)),
+        "vec2<f32>" | "vec2<u32>" | "vec2<i32>" => Some((8, 8)),
+        "vec3<f32>" | "vec3<u32>" | "vec3<i32>" => Some((12, 16)),
+        "vec4<f32>" | "vec4<u32>" | "vec4<i32>" => Some((16, 16)),
+        "mat4x4<f32>" => Some((64, 16)),
+        _ => None,
+    }
+}
+
+pub fn generate_wgsl_struct<T: WgslStruct>() -> anyhow::Result<String> {
+    let mut source = format!("struct {} {{\n", T::WGSL_NAME);
+    let mut offset = 0usize;
+    let mut struct_align = 1;
+    for field in T::get_wgsl_fields() {
+        let Some((size, align)) = get_wgsl_size_align(field.wgsl_type) else {
+            bail!(
+                "{}.{} has the unknown type {}",
+                T::WGSL_NAME,
+                field.name,
+                field.wgsl_type
+            );
+        };
+        offset = offset.next_multiple_of(align);
+        struct_align = struct_align.max(align);
+        if offset != field.offset || size != field.size {
+            bail!(
+                "{}.{} is {} bytes at {} in Rust but {} bytes at {} as {} in WGSL",
+                T::WGSL_NAME,
+                field.name,
+                field.size,
+                field.offset,
+                size,
+                offset,
+                field.wgsl_type
+            );
+        }
+        source.push_str(&format!("    {}: {},\n", field.name, field.wgsl_type));
+        offset += size;
+    }
+
+    // Also the stride of arrays of them
+    let size = offset.next_multiple_of(struct_align);
+    if size != std::mem::size_of::<T>() {
+        bail!(
+            "{} is {} bytes in Rust but {} bytes in WGSL",
+            T::WGSL_NAME,
+            std::mem::size_of::<T>(),
+            size
+        );
+    }
+    source.push_str("};\n");
+    Ok(source)
+}
+
+static LAYOUTS_SOURCE: LazyLock<String> = LazyLock::new(|| {
+    let structs = [
+        generate_wgsl_struct::<UniformBufferData>(),
+        generate_wgsl_struct::<SpriteUniformBufferData>(),
+        generate_wgsl_struct::<StaticInstanceData>(),
+        generate_wgsl_struct::<SpriteInstanceData>(),
+    ];
+    let mut source = String::from("// Generated from the Rust structs, see shader_layout.rs\n");
+    for generated in structs {
+        // The structs are built in, so a mismatch is a bug of the build
+        let generated = generated.unwrap_or_else(|error| panic!("{:#}", error));
+        source.push('\n');
+        source.push_str(&generated);
+    }
+    source
+});
+
+pub fn get_layouts_source() -> &'static str {
+    &LAYOUTS_SOURCE
+}
+
+// An input of the vertex shader the vertex buffer fills
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexInput {
+    pub location: u32,
+    pub name: String,
+    pub wgsl_type: String,
+}
+
+// The @location inputs of the @vertex entry point, taken directly or in the structs it takes.
+// Only reads as much WGSL as the declarations need, the source has to be preprocessed. None
+// when there is no vertex entry point.
+pub fn scan_vertex_inputs(source: &str) -> Option<Vec<VertexInput>> {
+    let source: String = source
+        .lines()
+        .map(|line| line.split("//").next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let entry_point = &source[source.find("@vertex")?..];
+    // The attributes of the parameters have parentheses of their own
+    let parameters = &entry_point[entry_point.find('(')? + 1..];
+    let parameters = &parameters[..find_closing(parameters)?];
+
+    let mut inputs = Vec::new();
+    for parameter in split_top_level(parameters) {
+        let Some(declaration) = parse_declaration(parameter) else {
+            continue;
+        };
+        if let Some(location) = declaration.location {
+            inputs.push(VertexInput {
+                location,
+                name: declaration.name,
+                wgsl_type: declaration.wgsl_type,
+            });
+        } else if let Some(fields) = find_struct_body(&source, &declaration.wgsl_type) {
+            inputs.extend(split_top_level(fields).filter_map(|field| {
+                let declaration = parse_declaration(field)?;
+                Some(VertexInput {
+                    location: declaration.location?,
+                    name: declaration.name,
+                    wgsl_type: declaration.wgsl_type,
+                })
+            }));
+        }
+    }
+    inputs.sort_by_key(|input| input.location);
+    Some(inputs)
+}
+
+// Where the parenthesis closing the one before the text is
+fn find_closing(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, character) in text.char_indices() {
+        match character {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(index),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+// By the commas that aren't in parentheses or template arguments
+fn split_top_level(text: &str) -> impl Iterator<Item = &str> {
+    let mut depth = 0;
+    let mut start = 0;
+    let mut parts = Vec::new();
+    for (index, character) in text.char_indices() {
+        match character {
+            '(' | '<' => depth += 1,
+            ')' | '>' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&text[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+}
+
+fn find_struct_body<'a>(source: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = source;
+    while let Some(index) = rest.find("struct") {
+        let after = &rest[index + "struct".len()..];
+        let (declared, body) = after.split_once('{')?;
+        if declared.trim() == name {
+            return Some(body.split_once('}')?.0);
+        }
+        rest = after;
+    }
+    None
+}
+
+struct Declaration {
+    location: Option<u32>,
+    name: String,
+    wgsl_type: String,
+}
+
+// E.g. "@location(2) @interpolate(flat) layer: u32"
+fn parse_declaration(text: &str) -> Option<Declaration> {
+    let mut location = None;
+    let mut rest = text.trim();
+    while let Some(attribute) = rest.strip_prefix('@') {
+        let name_end = attribute
+            .find(|character: char| !character.is_alphanumeric() && character != '_')
+            .unwrap_or(attribute.len());
+        let (name, after) = attribute.split_at(name_end);
+        rest = after.trim_start();
+        if let Some(arguments) = rest.strip_prefix('(') {
+            let end = find_closing(arguments)?;
+            if name == "location" {
+                location = Some(arguments[..end].trim().parse().ok()?);
+            }
+            rest = arguments[end + 1..].trim_start();
+        }
+    }
+    let (name, wgsl_type) = rest.split_once(':')?;
+    Some(Declaration {
+        location,
+        name: name.trim().to_string(),
+        wgsl_type: wgsl_type.split_whitespace().collect(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarKind {
+    Float,
+    Uint,
+    Sint,
+}
+
+// What the shader sees of a vertex format, the normalized ones are read as floats
+fn get_format_shape(format: wgpu::VertexFormat) -> (ScalarKind, u32) {
+    use wgpu::VertexFormat as Format;
+    match format {
+        Format::Uint8 | Format::Uint16 | Format::Uint32 => (ScalarKind::Uint, 1),
+        Format::Uint8x2 | Format::Uint16x2 | Format::Uint32x2 => (ScalarKind::Uint, 2),
+        Format::Uint32x3 => (ScalarKind::Uint, 3),
+        Format::Uint8x4 | Format::Uint16x4 | Format::Uint32x4 => (ScalarKind::Uint, 4),
+        Format::Sint8 | Format::Sint16 | Format::Sint32 => (ScalarKind::Sint, 1),
+        Format::Sint8x2 | Format::Sint16x2 | Format::Sint32x2 => (ScalarKind::Sint, 2),
+        Format::Sint32x3 => (ScalarKind::Sint, 3),
+        Format::Sint8x4 | Format::Sint16x4 | Format::Sint32x4 => (ScalarKind::Sint, 4),
+        Format::Unorm8
+        | Format::Snorm8
+        | Format::Unorm16
+        | Format::Snorm16
+        | Format::Float16
+        | Format::Float32
+        | Format::Float64 => (ScalarKind::Float, 1),
+        Format::Unorm8x2
+        | Format::Snorm8x2
+        | Format::Unorm16x2
+        | Format::Snorm16x2
+        | Format::Float16x2
+        | Format::Float32x2
+        | Format::Float64x2 => (ScalarKind::Float, 2),
+        Format::Float32x3 | Format::Float64x3 => (ScalarKind::Float, 3),
+        Format::Unorm8x4
+        | Format::Snorm8x4
+        | Format::Unorm16x4
+        | Format::Snorm16x4
+        | Format::Float16x4
+        | Format::Float32x4
+        | Format::Float64x4
+        | Format::Unorm10_10_10_2
+        | Format::Unorm8x4Bgra => (ScalarKind::Float, 4),
+    }
+}
+
+// Both "vec3<f32>" and "vec3f"
+fn get_wgsl_shape(wgsl_type: &str) -> Option<(ScalarKind, u32)> {
+    let (count, scalar) = match wgsl_type.strip_prefix("vec") {
+        Some(vector) => {
+            let (count, scalar) = vector.split_at_checked(1)?;
+            (count.parse().ok()?, scalar)
+        }
+        None => (1, wgsl_type),
+    };
+    let kind = match scalar {
+        "f32" | "<f32>" | "f" => ScalarKind::Float,
+        "u32" | "<u32>" | "u" => ScalarKind::Uint,
+        "i32" | "<i32>" | "i" => ScalarKind::Sint,
+        _ => return None,
+    };
+    Some((kind, count))
+}
+
+// A table of the inputs and the attributes of all the buffers, the lines that don't match
+// marked with a !. None when they match. Attributes the shader doesn't read are fine.
+pub fn get_vertex_layout_diff(
+    inputs: &[VertexInput],
+    layouts: &[wgpu::VertexBufferLayout],
+) -> Option<String> {
+    let attributes: Vec<&wgpu::VertexAttribute> = layouts
+        .iter()
+        .flat_map(|layout| layout.attributes.iter())
+        .collect();
+    let mut locations: Vec<u32> = inputs
+        .iter()
+        .map(|input| input.location)
+        .chain(attributes.iter().map(|attribute| attribute.shader_location))
+        .collect();
+    locations.sort();
+    locations.dedup();
+
+    let mut diff = format!("  {:<10}{:<32}{}\n", "location", "shader", "buffer");
+    let mut matches = true;
+    for location in locations {
+        let input = inputs.iter().find(|input| input.location == location);
+        let attribute = attributes
+            .iter()
+            .find(|attribute| attribute.shader_location == location);
+        let line_matches = match (input, attribute) {
+            (Some(input), Some(attribute)) => {
+                get_wgsl_shape(&input.wgsl_type) == Some(get_format_shape(attribute.format))
+            }
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        matches &= line_matches;
+        let shader = input.map_or("-".to_string(), |input| {
+            format!("{}: {}", input.name, input.wgsl_type)
+        });
+        let buffer = attribute.map_or("-".to_string(), |attribute| {
+            format!("{:?}", attribute.format)
+        });
+        diff.push_str(&format!(
+            "{} {:<10}{:<32}{}\n",
+            if line_matches { ' ' } else { '!' },
+            location,
+            shader,
+            buffer
+        ));
+    }
+    (!matches).then_some(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::shader::{get_shader_source, preprocess_shader};
+    use crate::renderer::{
+        DebugLineVertex, SkeletalMeshVertex, SpriteInstanceData, StaticInstanceData,
+        StaticMeshVertex,
+    };
+    use shared::math::{Vec2Data, Vec4Data};
+
+    fn scan(name: &str, defines: &[&str]) -> Vec<VertexInput> {
+        let shader = preprocess_shader(name, defines, get_shader_source).unwrap();
+        scan_vertex_inputs(&shader.source).unwrap()
+    }
+
+    #[test]
+    fn vertex_inputs_are_scanned_from_the_entry_point() {
+        let source = "
+            struct VertexInput {
+                @builtin(instance_index) instance_index: u32,
+                @location(1) normal: vec3<f32>, // After the position
+                @location(0) position: vec3f,
+            };
+
+            @vertex
+            fn vs_main(in: VertexInput, @location(2) @interpolate(flat) id: vec2<u32>) -> Out {
+        ";
+        let inputs = scan_vertex_inputs(source).unwrap();
+        let inputs: Vec<_> = inputs
+            .iter()
+            .map(|input| {
+                (
+                    input.location,
+                    input.name.as_str(),
+                    input.wgsl_type.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            inputs,
+            [
+                (0, "position", "vec3f"),
+                (1, "normal", "vec3<f32>"),
+                (2, "id", "vec2<u32>")
+            ]
+        );
+        assert_eq!(scan_vertex_inputs("@compute fn cs_main() {}"), None);
+    }
+
+    #[test]
+    fn built_in_shaders_match_their_vertex_layouts() {
+        let static_instanced = vec![StaticMeshVertex::desc(), StaticInstanceData::desc()];
+        let skeletal_instanced = vec![SkeletalMeshVertex::desc(), StaticInstanceData::desc()];
+        let cases = [
+            ("static.wgsl", &[][..], vec![StaticMeshVertex::desc()]),
+            ("shadow.wgsl", &[], vec![StaticMeshVertex::desc()]),
+            ("sprite.wgsl", &[], vec![StaticMeshVertex::desc()]),
+            ("composite.wgsl", &[], vec![StaticMeshVertex::desc()]),
+            ("fxaa.wgsl", &[], vec![StaticMeshVertex::desc()]),
+            ("world_grid.wgsl", &[], vec![StaticMeshVertex::desc()]),
+            (
+                "world_grid.wgsl",
+                &["MULTISAMPLED"],
+                vec![StaticMeshVertex::desc()],
+            ),
+            (
+                "skeletal.wgsl",
+                &["SKINNED"],
+                vec![SkeletalMeshVertex::desc()],
+            ),
+            (
+                "shadow.wgsl",
+                &["SKINNED"],
+                vec![SkeletalMeshVertex::desc()],
+            ),
+            (
+                "skeletal_xray.wgsl",
+                &["SKINNED"],
+                vec![SkeletalMeshVertex::desc()],
+            ),
+            ("debug_line.wgsl", &[], vec![DebugLineVertex::desc()]),
+            // The WebGL2 path, the instances in a second buffer
+            (
+                "static.wgsl",
+                &["INSTANCE_VERTEX_BUFFER"],
+                static_instanced.clone(),
+            ),
+            ("shadow.wgsl", &["INSTANCE_VERTEX_BUFFER"], static_instanced),
+            (
+                "skeletal.wgsl",
+                &["SKINNED", "INSTANCE_VERTEX_BUFFER"],
+                skeletal_instanced.clone(),
+            ),
+            (
+                "skeletal_weights.wgsl",
+                &["SKINNED", "INSTANCE_VERTEX_BUFFER"],
+                skeletal_instanced.clone(),
+            ),
+            (
+                "skeletal_xray.wgsl",
+                &["SKINNED", "INSTANCE_VERTEX_BUFFER"],
+                skeletal_instanced.clone(),
+            ),
+            (
+                "shadow.wgsl",
+                &["SKINNED", "INSTANCE_VERTEX_BUFFER"],
+                skeletal_instanced,
+            ),
+            (
+                "sprite.wgsl",
+                &["INSTANCE_VERTEX_BUFFER"],
+                vec![StaticMeshVertex::desc(), SpriteInstanceData::desc()],
+            ),
+        ];
+        for (name, defines, layouts) in cases {
+            let inputs = scan(name, defines);
+            assert!(!inputs.is_empty(), "{}", name);
+            if let Some(diff) = get_vertex_layout_diff(&inputs, &layouts) {
+                panic!("{} {:?}:\n{}", name, defines, diff);
+            }
+        }
+    }
+
+    #[test]
+    fn desynced_vertex_layouts_are_caught() {
+        let inputs = scan("skeletal.wgsl", &["SKINNED"]);
+
+        // The bone ids read as floats, and the weights left out
+        let attributes = wgpu::vertex_attr_array![
+            0 => Float32x3, 1 => Float32x3, 2 => Float32x3, 3 => Float32x4, 4 => Float32x4
+        ];
+        let layout = wgpu::VertexBufferLayout {
+            array_stride: 80,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &attributes,
+        };
+        let diff = get_vertex_layout_diff(&inputs, &[layout]).unwrap();
+        assert!(diff.contains("! 4         bone_ids: vec4<i32>"), "{}", diff);
+        assert!(diff.contains("! 5         bone_weights"), "{}", diff);
+        assert!(diff.contains("  3         color: vec4<f32>"), "{}", diff);
+
+        // A tangent squeezed in before the color moves the color along
+        let attributes = wgpu::vertex_attr_array![
+            0 => Float32x3, 1 => Float32x3, 2 => Float32x3, 3 => Float32x3, 4 => Float32x4
+        ];
+        let layout = wgpu::VertexBufferLayout {
+            attributes: &attributes,
+            ..StaticMeshVertex::desc()
+        };
+        let diff = get_vertex_layout_diff(&scan("static.wgsl", &[]), &[layout]).unwrap();
+        assert!(diff.contains("! 3         color: vec4<f32>"), "{}", diff);
+        assert!(diff.contains("Float32x3"), "{}", diff);
+    }
+
+    wgsl_struct! {
+        #[repr(C)]
+        #[allow(dead_code)]
+        struct PaddedData {
+            position: Vec2Data => "vec2<f32>",
+            flags: u32 => "u32",
+            color: Vec4Data => "vec4<f32>",
+        }
+    }
+
+    wgsl_struct! {
+        #[repr(C)]
+        #[allow(dead_code)]
+        struct MistypedData {
+            color: Vec4Data => "vec4<f32>",
+            uv: Vec2Data => "vec3<f32>",
+        }
+    }
+
+    wgsl_struct! {
+        #[repr(C)]
+        #[allow(dead_code)]
+        struct UnpaddedData {
+            color: Vec4Data => "vec4<f32>",
+            flags: u32 => "u32",
+        }
+    }
+
+    #[test]
+    fn drifting_fields_fail_the_generation() {
+        // WGSL aligns the vec4 to 16 bytes, the Rust struct has it right after the flags
+        let error = generate_wgsl_struct::<PaddedData>()
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "PaddedData.color is 16 bytes at 12 in Rust but 16 bytes at 16 as vec4<f32> in WGSL"
+        );
+        let error = generate_wgsl_struct::<MistypedData>()
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("MistypedData.uv is 8 bytes"), "{}", error);
+        // Arrays of it would have a stride of 32 in WGSL
+        let error = generate_wgsl_struct::<UnpaddedData>()
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "UnpaddedData is 20 bytes in Rust but 32 bytes in WGSL"
+        );
+    }
+
+    #[test]
+    fn layouts_are_generated_from_the_rust_structs() {
+        let source = get_layouts_source();
+        assert!(
+            source.contains(
+                "struct StaticInstanceData {\n    model_matrix: mat4x4<f32>,\n    color: vec4<f32>,\n"
+            ),
+            "{}",
+            source
+        );
+        assert!(source.contains("struct SpriteInstanceData {"));
+        assert!(source.contains("struct UniformBufferData {"));
+        assert!(source.contains("struct SpriteUniformBufferData {"));
+    }
+}
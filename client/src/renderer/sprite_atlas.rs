@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use shared::math::*;
+
+use crate::renderer::ResourceHandle;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+pub struct PixelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// A named part of an atlas, sprites using it all share the atlas material and end up in one batch
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SpriteRegion {
+    pub material: ResourceHandle,
+    pub tex_coord: Vec2,
+    pub tex_scale: Vec2,
+}
+
+impl SpriteRegion {
+    pub fn from_pixel_rect(material: ResourceHandle, rect: PixelRect, atlas_size: UVec2) -> Self {
+        let atlas_size = atlas_size.as_vec2();
+        Self {
+            material,
+            tex_coord: Vec2::new(rect.x as f32, rect.y as f32) / atlas_size,
+            tex_scale: Vec2::new(rect.width as f32, rect.height as f32) / atlas_size,
+        }
+    }
+}
+
+// Matches the json written by the texture tool with the --atlas-regions flag
+#[derive(Debug, Deserialize)]
+pub struct AtlasRegionsDesc {
+    pub width: u32,
+    pub height: u32,
+    pub regions: HashMap<String, PixelRect>,
+}
+
+impl AtlasRegionsDesc {
+    pub fn load(bytes: &[u8]) -> anyhow::Result<AtlasRegionsDesc> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_rect_maps_to_atlas_uvs() {
+        let region = SpriteRegion::from_pixel_rect(
+            7,
+            PixelRect {
+                x: 64,
+                y: 128,
+                width: 32,
+                height: 64,
+            },
+            UVec2::new(256, 512),
+        );
+
+        assert_eq!(region.material, 7);
+        assert_eq!(region.tex_coord, Vec2::new(0.25, 0.25));
+        assert_eq!(region.tex_scale, Vec2::new(0.125, 0.125));
+    }
+
+    #[test]
+    fn regions_load_from_tool_json() {
+        let json = br#"{
+            "width": 128,
+            "height": 64,
+            "regions": {
+                "health_bar": { "x": 0, "y": 0, "width": 100, "height": 10 },
+                "icon": { "x": 101, "y": 0, "width": 16, "height": 16 }
+            }
+        }"#;
+
+        let desc = AtlasRegionsDesc::load(json).unwrap();
+        assert_eq!(desc.width, 128);
+        assert_eq!(desc.height, 64);
+        assert_eq!(desc.regions.len(), 2);
+        assert_eq!(
+            desc.regions["icon"],
+            PixelRect {
+                x: 101,
+                y: 0,
+                width: 16,
+                height: 16
+            }
+        );
+    }
+}
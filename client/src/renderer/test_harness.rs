@@ -0,0 +1,446 @@
+// Offscreen rendering and golden-image comparison for the renderer, see tests/golden.rs.
+// The meshes are built here instead of being converted by the tools, so the tests do not
+// depend on the source art. The font is the checked-in debug font.
+
+use std::path::Path;
+
+use image::{Rgba, RgbaImage};
+use shared::math::*;
+
+use crate::renderer::{
+    BoneInfo, Renderer, ResourceHandle, SkeletalMeshVertex, StaticMeshVertex, animation::Pose,
+    texture::PREMULTIPLIED_ALPHA_FLAG,
+};
+
+pub struct TestAssets {
+    pub cube_mesh: ResourceHandle,
+    pub floor_mesh: ResourceHandle,
+    pub skinned_mesh: ResourceHandle,
+    pub checker_material: ResourceHandle,
+    pub checker_sprite_material: ResourceHandle,
+    pub font: ResourceHandle,
+    pub font_material: ResourceHandle,
+}
+
+pub struct RenderHarness {
+    pub renderer: Renderer,
+    pub assets: TestAssets,
+    target: wgpu::Texture,
+    target_view: wgpu::TextureView,
+}
+
+impl RenderHarness {
+    // Returns None when there is no adapter at all, the caller should skip the tests then
+    pub fn new(width: u32, height: u32) -> Option<Self> {
+        Self::from_renderer(Renderer::new_headless(width, height), width, height)
+    }
+
+    // Draws the way WebGL2 does, so the fallback is tested on any adapter
+    pub fn new_fallback(width: u32, height: u32) -> Option<Self> {
+        Self::from_renderer(
+            Renderer::new_headless_fallback(width, height),
+            width,
+            height,
+        )
+    }
+
+    fn from_renderer(
+        renderer: impl Future<Output = anyhow::Result<Renderer>>,
+        width: u32,
+        height: u32,
+    ) -> Option<Self> {
+        let mut renderer = match pollster::block_on(renderer) {
+            Ok(renderer) => renderer,
+            Err(error) => {
+                log::warn!("No adapter for the render harness: {}", error);
+                return None;
+            }
+        };
+
+        let assets = load_test_assets(&mut renderer);
+
+        let target = create_target(&renderer, width, height);
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Some(Self {
+            renderer,
+            assets,
+            target,
+            target_view,
+        })
+    }
+
+    pub fn create_pose(&self) -> Pose {
+        self.renderer.create_pose(self.assets.skinned_mesh)
+    }
+
+    // Renders everything submitted since the last capture and reads it back
+    pub fn capture(&mut self) -> RgbaImage {
+        self.renderer.render_to_view(&self.target_view);
+
+        let errors = self.renderer.get_render_device().take_validation_errors();
+        assert!(errors.is_empty(), "wgpu errors: {}", errors.join("\n"));
+
+        read_target(&self.renderer, &self.target)
+    }
+}
+
+// A texture in the surface format to render into with Renderer::render_to_view
+pub fn create_target(renderer: &Renderer, width: u32, height: u32) -> wgpu::Texture {
+    let render_device = renderer.get_render_device();
+    render_device
+        .device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some("Harness Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: render_device.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        })
+}
+
+// Waits for the GPU and copies the target back
+pub fn read_target(renderer: &Renderer, target: &wgpu::Texture) -> RgbaImage {
+    let pixels = renderer.read_texture(target);
+    RgbaImage::from_raw(target.width(), target.height(), pixels).expect("Readback size mismatch")
+}
+
+pub struct GoldenTolerance {
+    pub pixel_threshold: f32, // Perceptual distance in [0, 1] above which a pixel differs
+    pub max_mismatch_ratio: f32, // Fraction of differing pixels that is still accepted
+}
+
+impl Default for GoldenTolerance {
+    fn default() -> Self {
+        Self {
+            pixel_threshold: 0.1,
+            max_mismatch_ratio: 0.002,
+        }
+    }
+}
+
+// Compares against golden_dir/name.png, or overwrites it when blessing. On a mismatch
+// the actual image and a diff are written to failure_dir.
+pub fn check_golden(
+    name: &str,
+    image: &RgbaImage,
+    golden_dir: &Path,
+    failure_dir: &Path,
+    tolerance: &GoldenTolerance,
+    bless: bool,
+) -> Result<(), String> {
+    let golden_path = golden_dir.join(format!("{}.png", name));
+
+    if bless {
+        std::fs::create_dir_all(golden_dir).map_err(|e| e.to_string())?;
+        image.save(&golden_path).map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    let golden = image::open(&golden_path)
+        .map_err(|e| {
+            format!(
+                "Could not open {}: {}, run with --bless to create it",
+                golden_path.display(),
+                e
+            )
+        })?
+        .to_rgba8();
+
+    if golden.dimensions() != image.dimensions() {
+        return Err(format!(
+            "{} is {:?} but the render is {:?}",
+            golden_path.display(),
+            golden.dimensions(),
+            image.dimensions()
+        ));
+    }
+
+    let mut diff = RgbaImage::new(image.width(), image.height());
+    let mut mismatch_count = 0;
+    for (x, y, actual) in image.enumerate_pixels() {
+        let expected = golden.get_pixel(x, y);
+        if get_perceptual_distance(expected, actual) > tolerance.pixel_threshold {
+            mismatch_count += 1;
+            diff.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+        } else {
+            let gray = (expected.0[0] as u32 + expected.0[1] as u32 + expected.0[2] as u32) / 12;
+            diff.put_pixel(x, y, Rgba([gray as u8, gray as u8, gray as u8, 255]));
+        }
+    }
+
+    let mismatch_ratio = mismatch_count as f32 / (image.width() * image.height()) as f32;
+    if mismatch_ratio <= tolerance.max_mismatch_ratio {
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(failure_dir).map_err(|e| e.to_string())?;
+    let actual_path = failure_dir.join(format!("{}.actual.png", name));
+    let diff_path = failure_dir.join(format!("{}.diff.png", name));
+    image.save(&actual_path).map_err(|e| e.to_string())?;
+    diff.save(&diff_path).map_err(|e| e.to_string())?;
+
+    Err(format!(
+        "{} pixels ({:.3}%) differ from {}, see {}",
+        mismatch_count,
+        mismatch_ratio * 100.0,
+        golden_path.display(),
+        diff_path.display()
+    ))
+}
+
+// YIQ based color distance (as used by pixelmatch), normalized so 1.0 is black vs white
+pub fn get_perceptual_distance(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    let to_yiq = |p: &Rgba<u8>| {
+        let [r, g, b, _] = p.0.map(|c| c as f32 / 255.0);
+        Vec3::new(
+            0.2988953 * r + 0.5866225 * g + 0.1144822 * b,
+            0.595978 * r - 0.2741761 * g - 0.3218019 * b,
+            0.2114702 * r - 0.5226171 * g + 0.3111469 * b,
+        )
+    };
+
+    let delta = to_yiq(a) - to_yiq(b);
+    let distance =
+        0.5053 * delta.x * delta.x + 0.299 * delta.y * delta.y + 0.1957 * delta.z * delta.z;
+    distance / 0.5053
+}
+
+fn load_test_assets(renderer: &mut Renderer) -> TestAssets {
+    let cube_mesh = renderer.load_mesh(
+        "TestCube",
+        &build_mesh_bytes(&build_box(Vec3::splat(-0.5), Vec3::splat(0.5))),
+    );
+    let floor_mesh = renderer.load_mesh(
+        "TestFloor",
+        &build_mesh_bytes(&build_box(
+            Vec3::new(-1.0, -0.01, -1.0),
+            Vec3::new(1.0, 0.0, 1.0),
+        )),
+    );
+
+    // A two bone column, the upper half bends around the joint at y = 1
+    let lower = build_box(Vec3::new(-0.2, 0.0, -0.2), Vec3::new(0.2, 1.0, 0.2));
+    let upper = build_box(Vec3::new(-0.2, 1.0, -0.2), Vec3::new(0.2, 2.0, 0.2));
+    let skinned: Vec<(StaticMeshVertex, i32)> = lower
+        .into_iter()
+        .map(|v| (v, 0))
+        .chain(upper.into_iter().map(|v| (v, 1)))
+        .collect();
+    let bones = [
+        BoneInfo {
+            id: 0,
+            parent_id: -1,
+            offset_matrix: Mat4::IDENTITY.to_cols_array(),
+        },
+        BoneInfo {
+            id: 1,
+            parent_id: 0,
+            offset_matrix: Mat4::from_translation(Vec3::new(0.0, -1.0, 0.0)).to_cols_array(),
+        },
+    ];
+    let skinned_mesh =
+        renderer.load_skeletal_mesh("TestSkinned", &build_skinned_mesh_bytes(&skinned, &bones));
+
+    let checker_texture = renderer.load_texture(
+        "TestChecker",
+        &add_texture_layer(&build_checker_texture_bytes(8)),
+    );
+    let checker_material = renderer.create_material("TestCheckerMaterial", checker_texture);
+    let checker_sprite_material =
+        renderer.create_sprite_material("TestCheckerSpriteMaterial", checker_texture);
+
+    let font_bytes: &[u8] = include_bytes!("../../res/font/fira.dat");
+    let atlas_offset = get_font_atlas_offset(font_bytes);
+    let mut padded_font_bytes = font_bytes[..atlas_offset].to_vec();
+    padded_font_bytes.extend(add_texture_layer(&font_bytes[atlas_offset..]));
+    let font = renderer.load_font("TestFont", &padded_font_bytes);
+    let font_material = renderer.create_font_material("TestFontMaterial", font);
+
+    TestAssets {
+        cube_mesh,
+        floor_mesh,
+        skinned_mesh,
+        checker_material,
+        checker_sprite_material,
+        font,
+        font_material,
+    }
+}
+
+// Four vertices per face so every face gets a flat normal, the indices are generated per quad
+pub fn build_box(min: Vec3, max: Vec3) -> Vec<StaticMeshVertex> {
+    let faces = [
+        (Vec3::X, Vec3::Y),
+        (Vec3::NEG_X, Vec3::Y),
+        (Vec3::Y, Vec3::Z),
+        (Vec3::NEG_Y, Vec3::Z),
+        (Vec3::Z, Vec3::Y),
+        (Vec3::NEG_Z, Vec3::Y),
+    ];
+
+    let center = (min + max) * 0.5;
+    let half = (max - min) * 0.5;
+
+    let mut vertices = Vec::with_capacity(24);
+    for (normal, bitangent) in faces {
+        // This makes the corners below counter clockwise when seen from outside
+        let tangent = normal.cross(bitangent);
+        let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+        for (u, v) in corners {
+            let position = center + (normal + tangent * -u + bitangent * v) * half;
+            vertices.push(StaticMeshVertex {
+                position: position.to_array(),
+                normal: normal.to_array(),
+                uvs: [(u + 1.0) * 0.5, (1.0 - v) * 0.5, 0.0],
+                color: [1.0; 4],
+            });
+        }
+    }
+
+    vertices
+}
+
+pub fn get_quad_indices(vertex_count: usize) -> Vec<u32> {
+    (0..vertex_count as u32 / 4)
+        .flat_map(|quad| [0, 1, 2, 0, 2, 3].map(|i| quad * 4 + i))
+        .collect()
+}
+
+// Same layout as the mesh tool output, see MeshLoadDesc::load
+pub fn build_mesh_bytes(vertices: &[StaticMeshVertex]) -> Vec<u8> {
+    let indices = get_quad_indices(vertices.len());
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.extend_from_slice(&(vertices.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(bytemuck::cast_slice(vertices));
+    bytes.extend_from_slice(&(indices.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(bytemuck::cast_slice(&indices));
+    bytes
+}
+
+pub fn build_skinned_mesh_bytes(
+    vertices: &[(StaticMeshVertex, i32)],
+    bones: &[BoneInfo],
+) -> Vec<u8> {
+    let skinned_vertices: Vec<SkeletalMeshVertex> = vertices
+        .iter()
+        .map(|(v, bone)| SkeletalMeshVertex {
+            position: v.position,
+            normal: v.normal,
+            uvs: v.uvs,
+            color: v.color,
+            bone_ids: [*bone, -1, -1, -1],
+            bone_weights: [1.0, 0.0, 0.0, 0.0],
+        })
+        .collect();
+    let indices = get_quad_indices(vertices.len());
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.extend_from_slice(&(skinned_vertices.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(bytemuck::cast_slice(&skinned_vertices));
+    bytes.extend_from_slice(&(indices.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(bytemuck::cast_slice(&indices));
+    bytes.extend_from_slice(&(bones.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(bytemuck::cast_slice(bones));
+    bytes
+}
+
+// Same layout as the texture tool output, see TextureDesc::load
+pub fn build_checker_texture_bytes(size: u32) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for value in [size, size, 1, 4, 1, 1] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    for y in 0..size {
+        for x in 0..size {
+            let value = if (x + y) % 2 == 0 { 230 } else { 90 };
+            bytes.extend_from_slice(&[value, value, value, 255]);
+        }
+    }
+
+    bytes
+}
+
+// A solid color per layer, e.g. the skins of a variant material
+pub fn build_texture_array_bytes(size: u32, colors: &[[u8; 4]]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for value in [size, size, colors.len() as u32, 4, 1, 1] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    for color in colors {
+        for _ in 0..size * size {
+            bytes.extend_from_slice(color);
+        }
+    }
+
+    bytes
+}
+
+// The GL backend creates textures with a single layer as plain 2D textures, which then
+// can't be sampled through the texture_2d_array bindings. The software adapters the tests
+// usually run on are GL, so every texture gets a copy of its first layer appended.
+pub fn add_texture_layer(bytes: &[u8]) -> Vec<u8> {
+    let read_u32 =
+        |index: usize| u32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap());
+    let [
+        width,
+        height,
+        layer_count,
+        flagged_channel_count,
+        bytes_per_channel,
+        mip_level_count,
+    ] = [0, 1, 2, 3, 4, 5].map(read_u32);
+    let channel_count = flagged_channel_count & !PREMULTIPLIED_ALPHA_FLAG;
+    assert_eq!(
+        layer_count, 1,
+        "Only single layer textures need the extra layer"
+    );
+
+    let mut padded = bytes[..24].to_vec();
+    padded[8..12].copy_from_slice(&2u32.to_le_bytes());
+
+    let mut read_index = 24;
+    for mip_index in 0..mip_level_count {
+        let mip_size =
+            ((width >> mip_index) * (height >> mip_index) * channel_count * bytes_per_channel)
+                as usize;
+        let mip_pixels = &bytes[read_index..read_index + mip_size];
+        padded.extend_from_slice(mip_pixels);
+        padded.extend_from_slice(mip_pixels);
+        read_index += mip_size;
+    }
+
+    padded
+}
+
+// Skips the glyph table, see FontDesc::load
+fn get_font_atlas_offset(bytes: &[u8]) -> usize {
+    let glyph_count = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+
+    let mut read_index = 4;
+    for _ in 0..glyph_count {
+        // Unicode and advance, then the bounds flag
+        read_index += 8;
+        let has_bounds = bytes[read_index] != 0;
+        read_index += 1;
+
+        if has_bounds {
+            // Plane and uv bounds
+            read_index += 32;
+        }
+    }
+
+    read_index
+}
@@ -1,6 +1,16 @@
+use anyhow::bail;
+use shared::math::*;
 use wgpu::TextureUsages;
 
-use crate::renderer::RenderDevice;
+use crate::renderer::{PixelRect, RenderDevice, ResourceHandle};
+
+// Set in the channel count by the texture tool when the color is multiplied by the alpha
+pub const PREMULTIPLIED_ALPHA_FLAG: u32 = 1 << 31;
+// Set in the channel count by the texture tool for data like heightmaps, the texels are
+// integers read as 0 to 1 and never sRGB
+pub const LINEAR_DATA_FLAG: u32 = 1 << 30;
+// Width, height, layer count, channel count, bytes per channel and mip level count
+const HEADER_SIZE: usize = 24;
 
 pub struct TextureDesc {
     pub width: u32,
@@ -9,11 +19,14 @@ pub struct TextureDesc {
     pub channel_count: u32,
     pub bytes_per_channel: u32,
     pub mip_level_count: u32,
+    pub sample_count: u32,
     pub format: Option<wgpu::TextureFormat>,
     pub pixels: Vec<u8>, // If empty, othing will be uploaded
     pub usage: wgpu::TextureUsages,
     pub view_dimension: wgpu::TextureViewDimension,
     pub aspect: wgpu::TextureAspect,
+    pub premultiplied_alpha: bool, // Sprite materials of it blend with premultiplied alpha
+    pub linear_data: bool,         // 2 byte channels are unorm instead of half floats
 }
 
 impl Default for TextureDesc {
@@ -25,11 +38,14 @@ impl Default for TextureDesc {
             channel_count: 1,
             bytes_per_channel: 1,
             mip_level_count: 1,
+            sample_count: 1,
             pixels: vec![],
             format: None,
             usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
             view_dimension: wgpu::TextureViewDimension::D2Array,
             aspect: wgpu::TextureAspect::All,
+            premultiplied_alpha: false,
+            linear_data: false,
         }
     }
 }
@@ -54,7 +70,10 @@ impl TextureDesc {
         read_index += 4;
 
         tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
-        desc.channel_count = u32::from_le_bytes(tmp);
+        let channel_count = u32::from_le_bytes(tmp);
+        desc.channel_count = channel_count & !(PREMULTIPLIED_ALPHA_FLAG | LINEAR_DATA_FLAG);
+        desc.premultiplied_alpha = channel_count & PREMULTIPLIED_ALPHA_FLAG != 0;
+        desc.linear_data = channel_count & LINEAR_DATA_FLAG != 0;
         read_index += 4;
 
         tmp.copy_from_slice(&bytes[read_index..read_index + 4]);
@@ -73,7 +92,7 @@ impl TextureDesc {
 
     pub fn wgpu_format(&self) -> Result<wgpu::TextureFormat, String> {
         match self.bytes_per_channel {
-            // We only support u8, f16 and f32 for now
+            // We only support u8, f16 or u16 and f32 for now
 
             // u8
             1 => match self.channel_count {
@@ -87,6 +106,17 @@ impl TextureDesc {
                 )),
             },
 
+            // u16, integer data would be garbled as f16
+            2 if self.linear_data => match self.channel_count {
+                1 => Ok(wgpu::TextureFormat::R16Unorm),
+                2 => Ok(wgpu::TextureFormat::Rg16Unorm),
+                4 => Ok(wgpu::TextureFormat::Rgba16Unorm),
+                _ => Err(format!(
+                    "Unknown channel count for {}: {}",
+                    self.bytes_per_channel, self.channel_count
+                )),
+            },
+
             // f16
             2 => match self.channel_count {
                 1 => Ok(wgpu::TextureFormat::R16Float),
@@ -117,18 +147,173 @@ impl TextureDesc {
     }
 }
 
+// A texture file with one layer per tint, each a copy of the first layer with its color
+// channels scaled. Only for 8 bit color textures, the alpha is kept.
+pub fn get_tinted_layers(bytes: &[u8], tints: &[Vec3]) -> anyhow::Result<Vec<u8>> {
+    let desc = TextureDesc::load(bytes)?;
+    if desc.bytes_per_channel != 1 || desc.channel_count < 3 || desc.linear_data {
+        bail!(
+            "Only 8 bit color textures can be tinted, not {} channels of {} bytes",
+            desc.channel_count,
+            desc.bytes_per_channel
+        );
+    }
+
+    let mut tinted = bytes[..HEADER_SIZE].to_vec();
+    tinted[8..12].copy_from_slice(&(tints.len() as u32).to_le_bytes());
+    let texel_size = desc.channel_count as usize;
+    let mut read_offset = 0;
+    for mip_index in 0..desc.mip_level_count {
+        let layer_size =
+            texel_size * (desc.width >> mip_index) as usize * (desc.height >> mip_index) as usize;
+        let Some(layer) = desc.pixels.get(read_offset..read_offset + layer_size) else {
+            bail!("The texture ends in mip {}", mip_index);
+        };
+        for tint in tints {
+            tinted.extend(layer.chunks_exact(texel_size).flat_map(|texel| {
+                let mut texel = texel.to_vec();
+                for (channel, scale) in texel.iter_mut().zip(tint.to_array()) {
+                    *channel = (*channel as f32 * scale).round().clamp(0.0, 255.0) as u8;
+                }
+                texel
+            }));
+        }
+        read_offset += layer_size * desc.layer_count as usize;
+    }
+    Ok(tinted)
+}
+
 pub struct Texture {
     pub _texture: wgpu::Texture,
     pub view: wgpu::TextureView,
+    pub premultiplied_alpha: bool,
+}
+
+// A texture that was created without its pixels, they are uploaded one mip at a time
+pub struct TextureUpload {
+    pub handle: ResourceHandle,
+    desc: TextureDesc,
+    next_mip: u32,
+    read_offset: usize,
+}
+
+impl TextureUpload {
+    // For a texture that is already there, nothing is uploaded
+    pub fn finished(handle: ResourceHandle) -> Self {
+        Self {
+            handle,
+            desc: TextureDesc::default(),
+            next_mip: 0,
+            read_offset: 0,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.desc.pixels.is_empty() || self.next_mip >= self.desc.mip_level_count
+    }
 }
 
 impl RenderDevice {
     pub fn load_texture(&self, bytes: &[u8]) -> anyhow::Result<Texture> {
-        let desc = TextureDesc::load(bytes)?;
+        let desc = self.load_texture_desc(bytes)?;
         Ok(self.create_texture(&desc))
     }
 
+    // Adapters without the 16 bit unorm formats, e.g. browsers, get data textures as half
+    // floats of the same values, 11 bits of precision instead of 16
+    fn load_texture_desc(&self, bytes: &[u8]) -> anyhow::Result<TextureDesc> {
+        let mut desc = TextureDesc::load(bytes)?;
+        if desc.linear_data
+            && desc.bytes_per_channel == 2
+            && !self
+                .device
+                .features()
+                .contains(wgpu::Features::TEXTURE_FORMAT_16BIT_NORM)
+        {
+            for value in desc.pixels.chunks_exact_mut(2) {
+                let unorm = u16::from_le_bytes([value[0], value[1]]);
+                value.copy_from_slice(&get_half_bits(unorm as f32 / 65535.0).to_le_bytes());
+            }
+            desc.linear_data = false;
+        }
+        Ok(desc)
+    }
+
     pub fn create_texture(&self, desc: &TextureDesc) -> Texture {
+        let texture = self.create_empty_texture(desc);
+
+        if !desc.pixels.is_empty() {
+            let mut read_offset: usize = 0;
+            for mip_index in 0..desc.mip_level_count {
+                read_offset = self.write_texture_mip(&texture, desc, mip_index, read_offset);
+            }
+        }
+
+        texture
+    }
+
+    // Starts an upload of which each upload_texture_mip call does one mip, largest first
+    pub fn begin_texture_upload(
+        &self,
+        handle: ResourceHandle,
+        bytes: &[u8],
+    ) -> anyhow::Result<(Texture, TextureUpload)> {
+        let desc = self.load_texture_desc(bytes)?;
+        let texture = self.create_empty_texture(&desc);
+        let upload = TextureUpload {
+            handle,
+            desc,
+            next_mip: 0,
+            read_offset: 0,
+        };
+        Ok((texture, upload))
+    }
+
+    pub fn upload_texture_mip(&self, texture: &Texture, upload: &mut TextureUpload) {
+        if upload.is_done() {
+            return;
+        }
+
+        upload.read_offset =
+            self.write_texture_mip(texture, &upload.desc, upload.next_mip, upload.read_offset);
+        upload.next_mip += 1;
+    }
+
+    // Replaces a rect of the first mip and layer, the pixels are rows of the rect's width
+    #[allow(dead_code)]
+    pub fn write_texture_region(
+        &self,
+        texture: &Texture,
+        rect: PixelRect,
+        bytes_per_pixel: u32,
+        pixels: &[u8],
+    ) {
+        self.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture._texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: rect.x,
+                    y: rect.y,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            pixels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(rect.width * bytes_per_pixel),
+                rows_per_image: Some(rect.height),
+            },
+            wgpu::Extent3d {
+                width: rect.width,
+                height: rect.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    fn create_empty_texture(&self, desc: &TextureDesc) -> Texture {
         let format = desc
             .format
             .unwrap_or(desc.wgpu_format().expect("Unknown format"));
@@ -141,54 +326,13 @@ impl RenderDevice {
                 depth_or_array_layers: desc.layer_count,
             },
             mip_level_count: desc.mip_level_count,
-            sample_count: 1,
+            sample_count: desc.sample_count,
             dimension: wgpu::TextureDimension::D2,
             format,
             usage: desc.usage,
             view_formats: &[],
         });
 
-        if desc.pixels.len() > 0 {
-            let mut read_offset: usize = 0;
-            for mip_index in 0..desc.mip_level_count {
-                let mip_width = desc.width >> mip_index;
-                let mip_height = desc.height >> mip_index;
-
-                assert_ne!(mip_width, 0);
-                assert_ne!(mip_height, 0);
-
-                let bytes_per_row = desc.bytes_per_channel * desc.channel_count * mip_width;
-
-                let upload_size: usize = (bytes_per_row * mip_height * desc.layer_count) as usize;
-                let read_end = read_offset + upload_size;
-                let mip_pixels = &desc.pixels[read_offset..read_end];
-
-                self.queue.write_texture(
-                    wgpu::TexelCopyTextureInfo {
-                        texture: &texture,
-                        mip_level: mip_index,
-                        origin: wgpu::Origin3d::ZERO,
-                        aspect: wgpu::TextureAspect::All,
-                    },
-                    // The actual pixel data
-                    mip_pixels,
-                    // The layout of the texture
-                    wgpu::TexelCopyBufferLayout {
-                        offset: 0,
-                        bytes_per_row: Some(bytes_per_row),
-                        rows_per_image: Some(mip_height),
-                    },
-                    wgpu::Extent3d {
-                        width: mip_width,
-                        height: mip_height,
-                        depth_or_array_layers: desc.layer_count,
-                    },
-                );
-
-                read_offset += upload_size;
-            }
-        }
-
         let view = texture.create_view(&wgpu::TextureViewDescriptor {
             label: None,
             dimension: Some(desc.view_dimension),
@@ -204,6 +348,175 @@ impl RenderDevice {
         Texture {
             _texture: texture,
             view,
+            premultiplied_alpha: desc.premultiplied_alpha,
+        }
+    }
+
+    // The mips are stored one after the other, returns where the next one starts
+    fn write_texture_mip(
+        &self,
+        texture: &Texture,
+        desc: &TextureDesc,
+        mip_index: u32,
+        read_offset: usize,
+    ) -> usize {
+        let mip_width = desc.width >> mip_index;
+        let mip_height = desc.height >> mip_index;
+
+        assert_ne!(mip_width, 0);
+        assert_ne!(mip_height, 0);
+
+        let bytes_per_row = desc.bytes_per_channel * desc.channel_count * mip_width;
+
+        let upload_size: usize = (bytes_per_row * mip_height * desc.layer_count) as usize;
+        let read_end = read_offset + upload_size;
+        let mip_pixels = &desc.pixels[read_offset..read_end];
+
+        self.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture._texture,
+                mip_level: mip_index,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            // The actual pixel data
+            mip_pixels,
+            // The layout of the texture
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: Some(mip_height),
+            },
+            wgpu::Extent3d {
+                width: mip_width,
+                height: mip_height,
+                depth_or_array_layers: desc.layer_count,
+            },
+        );
+
+        read_end
+    }
+}
+
+// Rounded to the nearest half float, too large values become infinity
+fn get_half_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent >= 31 {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal, the implicit bit is shifted in
+        if exponent < -10 {
+            return sign;
         }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let rounding = (mantissa >> (shift - 1)) & 1;
+        return sign | ((mantissa >> shift) + rounding) as u16;
+    }
+    // A carry out of the mantissa moves on to the next exponent, which is still right
+    let half = sign | ((exponent as u16) << 10) | (mantissa >> 13) as u16;
+    half + ((mantissa >> 12) & 1) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_texture_file(channel_count: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for value in [2, 2, 1, channel_count, 1, 1] {
+            bytes.extend_from_slice(&u32::to_le_bytes(value));
+        }
+        bytes.extend_from_slice(&[128; 16]);
+        bytes
+    }
+
+    // Like `tools texture --data`, one 16 bit channel and no mips
+    fn write_data_texture_file(texels: &[u16]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for value in [texels.len() as u32, 1, 1, 1 | LINEAR_DATA_FLAG, 2, 1] {
+            bytes.extend_from_slice(&u32::to_le_bytes(value));
+        }
+        for texel in texels {
+            bytes.extend_from_slice(&texel.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn premultiplied_flag_is_taken_out_of_the_channel_count() {
+        let desc = TextureDesc::load(&write_texture_file(4 | PREMULTIPLIED_ALPHA_FLAG)).unwrap();
+        assert_eq!(desc.channel_count, 4);
+        assert!(desc.premultiplied_alpha);
+        assert_eq!(desc.wgpu_format(), Ok(wgpu::TextureFormat::Rgba8Unorm));
+        assert_eq!(desc.pixels.len(), 16);
+
+        let desc = TextureDesc::load(&write_texture_file(4)).unwrap();
+        assert!(!desc.premultiplied_alpha);
+    }
+
+    #[test]
+    fn data_textures_are_unorm_and_keep_their_texels() {
+        let texels = [0, 1, 255, 256, 1000, 32767, 32768, 65534, 65535];
+        let desc = TextureDesc::load(&write_data_texture_file(&texels)).unwrap();
+        assert_eq!(desc.channel_count, 1);
+        assert!(desc.linear_data);
+        assert!(!desc.premultiplied_alpha);
+        assert_eq!(desc.wgpu_format(), Ok(wgpu::TextureFormat::R16Unorm));
+        let loaded: Vec<u16> = desc
+            .pixels
+            .chunks_exact(2)
+            .map(|value| u16::from_le_bytes([value[0], value[1]]))
+            .collect();
+        assert_eq!(loaded, texels);
+
+        // Without the flag 2 byte channels stay half floats
+        let mut bytes = write_data_texture_file(&texels);
+        bytes[12..16].copy_from_slice(&1u32.to_le_bytes());
+        let desc = TextureDesc::load(&bytes).unwrap();
+        assert_eq!(desc.wgpu_format(), Ok(wgpu::TextureFormat::R16Float));
+    }
+
+    #[test]
+    fn tinted_layers_scale_the_color_of_every_mip() {
+        // 2x2 and 1x1 mips of two layers, the second layer is left out of the tints
+        let mut bytes = Vec::new();
+        for value in [2, 2, 2, 4, 1, 2] {
+            bytes.extend_from_slice(&u32::to_le_bytes(value));
+        }
+        bytes.extend_from_slice(&[200; 16]);
+        bytes.extend_from_slice(&[10; 16]);
+        bytes.extend_from_slice(&[100; 4]);
+        bytes.extend_from_slice(&[10; 4]);
+
+        let tints = [Vec3::ONE, Vec3::new(0.5, 2.0, 0.0)];
+        let desc = TextureDesc::load(&get_tinted_layers(&bytes, &tints).unwrap()).unwrap();
+        assert_eq!((desc.width, desc.height, desc.layer_count), (2, 2, 2));
+        assert_eq!(desc.mip_level_count, 2);
+        let mip_0 = [[200; 4].repeat(4), [100, 255, 0, 200].repeat(4)].concat();
+        let mip_1 = [[100; 4], [50, 200, 0, 100]].concat();
+        assert_eq!(desc.pixels, [mip_0, mip_1].concat());
+
+        assert!(get_tinted_layers(&write_data_texture_file(&[1, 2]), &tints).is_err());
+        assert!(get_tinted_layers(&bytes[..40], &tints).is_err());
+    }
+
+    #[test]
+    fn half_floats_round_to_the_nearest() {
+        assert_eq!(get_half_bits(0.0), 0);
+        assert_eq!(get_half_bits(1.0), 0x3c00);
+        assert_eq!(get_half_bits(0.5), 0x3800);
+        assert_eq!(get_half_bits(-2.0), 0xc000);
+        assert_eq!(get_half_bits(65504.0), 0x7bff);
+        assert_eq!(get_half_bits(1e6), 0x7c00);
+        // The smallest subnormal, three quarters of it round up to it
+        assert_eq!(get_half_bits(2.0f32.powi(-24)), 1);
+        assert_eq!(get_half_bits(2.0f32.powi(-25) * 1.5), 1);
+        // Rounds up into the next exponent
+        assert_eq!(get_half_bits(1.0 - 2.0f32.powi(-12)), 0x3c00);
     }
 }
@@ -0,0 +1,118 @@
+// A grid on the Y=0 plane for placing props and tuning ranges, drawn over the composited
+// scene by a fullscreen pass. The pass finds the plane along the view ray of each pixel and
+// hides it where the scene is in front, with the world position of the scene rebuilt from
+// the depth buffer. get_world_position in common.wgsl does that for any pass that reads the
+// depth, e.g. decals.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WorldGridSettings {
+    pub spacing: f32,       // Between the minor lines, in world units
+    pub major_every: u32,   // Minor cells between the major lines
+    pub fade_distance: f32, // From the camera to where the grid is gone, it starts fading at half
+}
+
+impl Default for WorldGridSettings {
+    fn default() -> Self {
+        Self {
+            spacing: 1.0,
+            major_every: 10,
+            fade_distance: 60.0,
+        }
+    }
+}
+
+// Read by world_grid.wgsl
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct WorldGridUniformData {
+    pub spacing: f32,
+    pub major_every: f32,
+    pub fade_distance: f32,
+    pub _padding: f32,
+}
+
+impl WorldGridUniformData {
+    pub fn new(settings: &WorldGridSettings) -> Self {
+        Self {
+            spacing: settings.spacing.max(0.01),
+            major_every: settings.major_every.max(1) as f32,
+            fade_distance: settings.fade_distance.max(1.0),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::math::*;
+
+    use super::*;
+
+    // Line for line the shader's, which has no tests of its own
+    fn get_world_position(inverse_view_projection: Mat4, uv: Vec2, depth: f32) -> Vec3 {
+        let ndc = Vec4::new(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+        let world = inverse_view_projection * ndc;
+        world.truncate() / world.w
+    }
+
+    #[test]
+    fn world_positions_are_rebuilt_from_the_depth() {
+        let projection = Mat4::perspective_rh(f32::to_radians(60.0), 16.0 / 9.0, 0.1, 100.0);
+        let view = Mat4::look_at_rh(Vec3::new(4.0, 8.0, 6.0), Vec3::ZERO, Vec3::Y);
+        let view_projection = projection * view;
+        let inverse = view_projection.inverse();
+
+        for position in [
+            Vec3::ZERO,
+            Vec3::new(3.0, 0.0, -2.0),
+            Vec3::new(-1.5, 2.0, 1.0),
+        ] {
+            let clip = view_projection * position.extend(1.0);
+            let ndc = clip.truncate() / clip.w;
+            let uv = Vec2::new(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+            let rebuilt = get_world_position(inverse, uv, ndc.z);
+            assert!(
+                rebuilt.distance(position) < 1e-3,
+                "{} rebuilt as {}",
+                position,
+                rebuilt
+            );
+        }
+    }
+
+    #[test]
+    fn grid_settings_are_kept_drawable() {
+        let data = WorldGridUniformData::new(&WorldGridSettings {
+            spacing: 0.0,
+            major_every: 0,
+            fade_distance: -5.0,
+        });
+        assert_eq!(data.spacing, 0.01);
+        assert_eq!(data.major_every, 1.0);
+        assert_eq!(data.fade_distance, 1.0);
+    }
+
+    // With both kinds of depth buffer the pass reads
+    #[cfg(feature = "test-harness")]
+    #[test]
+    fn the_grid_draws_over_single_and_multisampled_depth() {
+        use crate::renderer::{AaMode, Renderer, test_harness::create_target};
+
+        let mut renderer = match pollster::block_on(Renderer::new_headless(64, 64)) {
+            Ok(renderer) => renderer,
+            Err(error) => {
+                log::warn!("No adapter for the world grid test: {}", error);
+                return;
+            }
+        };
+        let target = create_target(&renderer, 64, 64);
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        renderer.set_world_grid(Some(WorldGridSettings::default()));
+        for mode in [AaMode::Off, AaMode::Msaa(4)] {
+            renderer.set_antialiasing(mode);
+            renderer.render_to_view(&view);
+        }
+        let errors = renderer.get_render_device().take_validation_errors();
+        assert!(errors.is_empty(), "wgpu errors: {}", errors.join("\n"));
+    }
+}
@@ -0,0 +1,285 @@
+use shared::math::*;
+
+use crate::{
+    input::{InputAction, InputState},
+    renderer::{
+        Renderer, ResourceHandle, ResourceKind, SpriteAnchor, SpriteSpace, TextAlignment,
+        render_data::{SpriteRenderJob, TextRenderJob},
+        resources::get_handle,
+    },
+};
+
+struct ResourceEntry {
+    handle: ResourceHandle,
+    kind: ResourceKind,
+    text: String,
+}
+
+// F3 pages through them and back to hidden
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Page {
+    Hidden,
+    Resources,
+    Diagnostics, // Of the device, for bug reports
+}
+
+impl Page {
+    fn get_next(self) -> Self {
+        match self {
+            Page::Hidden => Page::Resources,
+            Page::Resources => Page::Diagnostics,
+            Page::Diagnostics => Page::Hidden,
+        }
+    }
+}
+
+// Debug overlay listing everything in the resource pool. Textures are previewed as a
+// fullscreen sprite, meshes are handed back to the caller to spawn. The second page shows
+// the adapter and the fallbacks taken on it.
+pub struct ResourceBrowser {
+    page: Page,
+    entries: Vec<ResourceEntry>,
+    diagnostics: Vec<String>,
+    selected: usize,
+    scroll: usize,
+    title: String,
+    preview: Option<Vec2>, // Size of the previewed texture in reference space
+}
+
+impl ResourceBrowser {
+    const VISIBLE_ROWS: usize = 24;
+    const ROW_HEIGHT: f32 = 18.0;
+    const PREVIEW_MATERIAL: &str = "DebugPreviewMaterial";
+
+    pub fn new() -> Self {
+        Self {
+            page: Page::Hidden,
+            entries: Vec::new(),
+            diagnostics: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            title: String::new(),
+            preview: None,
+        }
+    }
+
+    // Returns a mesh when one was selected
+    pub fn update(
+        &mut self,
+        input_state: &InputState,
+        renderer: &mut Renderer,
+    ) -> Option<(ResourceHandle, ResourceKind)> {
+        if input_state.is_pressed(InputAction::ToggleResourceBrowser) {
+            self.page = self.page.get_next();
+            self.preview = None;
+            match self.page {
+                Page::Resources => self.refresh(renderer),
+                Page::Diagnostics => {
+                    self.diagnostics = renderer.get_device_diagnostics().get_lines();
+                }
+                Page::Hidden => {}
+            }
+        }
+
+        if self.page != Page::Resources || self.entries.is_empty() {
+            return None;
+        }
+
+        if input_state.is_pressed(InputAction::DebugUp) {
+            self.selected = self.selected.saturating_sub(1);
+        }
+        if input_state.is_pressed(InputAction::DebugDown) {
+            self.selected = (self.selected + 1).min(self.entries.len() - 1);
+        }
+        self.scroll = get_scroll(self.selected, self.scroll, Self::VISIBLE_ROWS);
+
+        if !input_state.is_pressed(InputAction::DebugSelect) {
+            return None;
+        }
+
+        let entry = &self.entries[self.selected];
+        match entry.kind {
+            ResourceKind::Texture => {
+                renderer.create_sprite_material(Self::PREVIEW_MATERIAL, entry.handle);
+                let size = renderer
+                    .get_texture_size(entry.handle)
+                    .expect("Failed to get texture")
+                    .as_vec2();
+
+                // Fit into the reference screen, keeping the aspect ratio
+                let reference = renderer.get_ui_viewport().reference;
+                let scale = (reference.x / size.x).min(reference.y / size.y);
+                self.preview = Some(size * scale);
+                None
+            }
+            ResourceKind::StaticMesh | ResourceKind::SkeletalMesh => {
+                self.preview = None;
+                Some((entry.handle, entry.kind))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn render(&self, renderer: &mut Renderer) {
+        if self.page == Page::Hidden {
+            return;
+        }
+
+        if let Some(size) = self.preview {
+            renderer.submit(&SpriteRenderJob {
+                position: -size * 0.5,
+                size,
+                material: get_handle(Self::PREVIEW_MATERIAL),
+                anchor: SpriteAnchor::Center,
+                ..Default::default()
+            });
+        }
+
+        // The list goes on a higher layer so it stays readable over the preview
+        let submit_row = |renderer: &mut Renderer, row: usize, text: &str, color: Vec4| {
+            renderer.submit(&TextRenderJob {
+                font_atlas: get_handle("DebugFont"),
+                font_material: get_handle("DebugFontMaterial"),
+                text: text.into(),
+                position: Vec2::new(10.0, 20.0 + row as f32 * Self::ROW_HEIGHT),
+                size: 16.0,
+                color,
+                layer: 1,
+                anchor: SpriteAnchor::TopLeft,
+                space: SpriteSpace::Absolute,
+                alignment: TextAlignment::Left,
+            });
+        };
+
+        if self.page == Page::Diagnostics {
+            submit_row(
+                renderer,
+                0,
+                "Diagnostics | dumpdiag <file> in the console writes them out",
+                Vec4::new(1.0, 1.0, 1.0, 1.0),
+            );
+            for (index, line) in self.diagnostics.iter().enumerate() {
+                submit_row(renderer, index + 1, line, Vec4::new(0.0, 1.0, 0.0, 1.0));
+            }
+            return;
+        }
+
+        submit_row(renderer, 0, &self.title, Vec4::new(1.0, 1.0, 1.0, 1.0));
+
+        let visible_end = (self.scroll + Self::VISIBLE_ROWS).min(self.entries.len());
+        for index in self.scroll..visible_end {
+            let color = if index == self.selected {
+                Vec4::new(1.0, 1.0, 0.0, 1.0)
+            } else {
+                Vec4::new(0.0, 1.0, 0.0, 1.0)
+            };
+            submit_row(
+                renderer,
+                index - self.scroll + 1,
+                &self.entries[index].text,
+                color,
+            );
+        }
+    }
+
+    // The pool only changes on loads, so the list is built when the browser opens
+    fn refresh(&mut self, renderer: &Renderer) {
+        let resource_pool = renderer.get_resource_pool();
+
+        let mut total_size = 0;
+        self.entries = resource_pool
+            .iter()
+            .map(|(handle, kind)| {
+                let size = resource_pool
+                    .get_resource(handle)
+                    .map_or(0, |resource| resource.get_gpu_size());
+                total_size += size;
+
+                let name = match resource_pool.get_name(handle) {
+                    Some(name) => name.to_string(),
+                    None => format!("{:016x}", handle),
+                };
+
+                ResourceEntry {
+                    handle,
+                    kind,
+                    text: format!(
+                        "{:<32} {:<14} {:>10}",
+                        name,
+                        kind.get_name(),
+                        format_size(size)
+                    ),
+                }
+            })
+            .collect();
+        self.entries
+            .sort_by(|a, b| a.kind.cmp(&b.kind).then_with(|| a.text.cmp(&b.text)));
+
+        self.selected = self.selected.min(self.entries.len().saturating_sub(1));
+        self.title = format!(
+            "Resources: {} ({}) | Up/Down, Enter to preview or spawn",
+            self.entries.len(),
+            format_size(total_size)
+        );
+    }
+}
+
+// Keeps the selected row inside the visible window, moving it as little as possible
+fn get_scroll(selected: usize, scroll: usize, visible_rows: usize) -> usize {
+    if selected < scroll {
+        selected
+    } else if selected >= scroll + visible_rows {
+        selected + 1 - visible_rows
+    } else {
+        scroll
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scroll_follows_the_selection() {
+        assert_eq!(get_scroll(3, 0, 10), 0);
+        assert_eq!(get_scroll(10, 0, 10), 1);
+        assert_eq!(get_scroll(25, 1, 10), 16);
+        assert_eq!(get_scroll(4, 16, 10), 4);
+    }
+
+    #[test]
+    fn pages_cycle_back_to_hidden() {
+        let mut page = Page::Hidden;
+        let mut pages = Vec::new();
+        for _ in 0..3 {
+            page = page.get_next();
+            pages.push(page);
+        }
+        assert_eq!(pages, [Page::Resources, Page::Diagnostics, Page::Hidden]);
+    }
+
+    #[test]
+    fn sizes_use_binary_units() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(64 * 1024 * 1024), "64.0 MiB");
+    }
+}
@@ -0,0 +1,346 @@
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+use shared::rng::Rng;
+
+use crate::{
+    combat::{CCombat, CHealth},
+    status_effects::{StatusEffectDesc, StatusEffects},
+};
+
+// Bumped whenever a change breaks reading older saves
+pub const SAVE_VERSION: u32 = 2;
+
+// The full game state, written by Game::serialize and read back by Game::deserialize.
+// Entities and physics bodies refer to each other by their position in the lists, so the
+// same state always saves the same way. Resources are saved by name, vectors as arrays
+// like in levels.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GameSave {
+    pub version: u32,
+    pub level: String, // Only loaded into the same level
+    pub camera: CameraSave,
+    pub physics: PhysicsSave,
+    pub entities: Vec<EntitySave>,
+    pub player: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wave_spawners: Vec<WaveSpawnerSave>, // In the order of the level's
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraSave {
+    pub transform: TransformSave,
+    pub detached: bool,
+    pub radius: f32,
+    pub angle: f32,
+    pub fov: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PhysicsSave {
+    pub cell_size: f32,
+    pub iterations: u32,
+    pub quantized: bool,
+    pub bodies: Vec<BodySave>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BodySave {
+    pub position: [f32; 2],
+    pub velocity: [f32; 2],
+    pub layer: LayerSave,
+    pub shape: ShapeSave,
+    pub listen_to_contact_events: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerSave {
+    Environment,
+    Player,
+    PlayerProjectile,
+    Enemy,
+    EnemyProjectile,
+    Neutral,
+    NeutralProjectile,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TeamSave {
+    Blue,
+    Red,
+    Neutral,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ShapeSave {
+    Circle {
+        radius: f32,
+    },
+    Rect {
+        half_extents: [f32; 2],
+    },
+    Obb {
+        half_extents: [f32; 2],
+        rotation: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformSave {
+    pub position: [f32; 3],
+    pub rotation: [f32; 4], // Quaternion, xyzw
+    pub scale: [f32; 3],
+}
+
+// Every component is optional, like in the storages
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntitySave {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<TransformSave>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renderable: Option<RenderableSave>,
+    #[serde(default)]
+    pub posed: bool, // Poses start from the bind pose, the animator moves them again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animator: Option<AnimatorSave>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub physics: Option<PhysicsProxySave>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub movement: Option<MovementSave>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<TargetSave>,
+    #[serde(default)]
+    pub tinted: bool, // Running tints are only visual, they are not kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<CHealth>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub combat: Option<CombatSave>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_effects: Option<StatusEffects>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<ParentSave>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<TeamSave>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub projectile: Option<ProjectileSave>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub abilities: Option<AbilityCasterSave>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lane_follower: Option<LaneFollowerSave>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderableSave {
+    pub mesh: String,
+    pub material: String,
+    pub render_offset: [f32; 16], // Column major
+    pub color: [f32; 4],
+    pub tex_coord: [f32; 2],
+    pub tex_scale: [f32; 2],
+    pub casts_shadow: bool,
+    pub shadow_proxy: ShadowProxySave,
+    pub render_layers: u32,
+    #[serde(default)]
+    pub variant: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ShadowProxySave {
+    None,
+    StaticMesh { mesh: String },
+    Capsule { radius: f32, height: f32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnimatorSave {
+    pub phase: f32,
+    pub animations: Vec<AnimationSave>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnimationSave {
+    pub animation: String,
+    pub time: f32,
+    pub looping: bool,
+    pub blend_weight: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PhysicsProxySave {
+    pub body: Option<usize>, // Into the saved bodies
+    pub current_state: Option<BodyStateSave>,
+    pub previous_state: Option<BodyStateSave>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BodyStateSave {
+    pub position: [f32; 2],
+    pub velocity: [f32; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MovementSave {
+    pub velocity: [f32; 3],
+    pub locked: bool,
+    pub speed_multiplier: f32,
+}
+
+// A target location component, with or without a location to move to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetSave {
+    pub location: Option<[f32; 3]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CombatSave {
+    pub target: Option<usize>, // Into the saved entities, the one in the combat is not saved
+    pub combat: CCombat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParentSave {
+    pub parent: usize, // Into the saved entities
+    pub bone: Option<usize>,
+    pub local: TransformSave,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectileSave {
+    pub source: Option<usize>, // Into the saved entities
+    pub damage: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lifetime: Option<f32>, // Saves from before lifetimes get the full one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<StatusEffectDesc>, // Applied to the unit it hits
+}
+
+// A running cast is not kept, the caster loads idle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AbilityCasterSave {
+    pub abilities: Vec<Option<String>>, // By name, on Q, W, E and R
+    pub cooldowns: Vec<[u32; 2]>,       // Fixed steps left and the steps it started with
+    pub energy: f32,
+    pub max_energy: f32,
+    pub energy_regen: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LaneFollowerSave {
+    pub lane: usize, // Into the saved wave spawners, the one whose lane it walks
+    pub waypoint: usize,
+    pub aggro_range: f32,
+    pub target: Option<usize>, // Into the saved entities, the enemy it stopped to fight
+}
+
+// The clock of the next wave and the formation jitter, the rest comes from the level
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WaveSpawnerSave {
+    pub steps_to_wave: u32,
+    pub rng: Rng,
+}
+
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+impl GameSave {
+    pub fn load(bytes: &[u8]) -> anyhow::Result<Self> {
+        // Checked first, a save of another version likely fails to parse with a worse error
+        let header: SaveHeader = serde_json::from_slice(bytes)?;
+        if header.version != SAVE_VERSION {
+            bail!(
+                "The save is version {}, only version {} can be loaded",
+                header.version,
+                SAVE_VERSION
+            );
+        }
+
+        let deserializer = &mut serde_json::Deserializer::from_slice(bytes);
+        serde_path_to_error::deserialize(deserializer)
+            .map_err(|error| anyhow!("{}: {}", error.path(), error.inner()))
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+// Next to the executable, like the crash logs
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_save_path() -> std::path::PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.to_path_buf()))
+        .unwrap_or_default()
+        .join("quicksave.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn other_versions_are_refused() {
+        let save = GameSave {
+            version: SAVE_VERSION,
+            level: "Default".to_string(),
+            camera: CameraSave {
+                transform: TransformSave {
+                    position: [0.0, 1.0, 2.0],
+                    rotation: [0.0, 0.0, 0.0, 1.0],
+                    scale: [1.0; 3],
+                },
+                detached: false,
+                radius: 10.0,
+                angle: 45.0,
+                fov: 40.0,
+            },
+            physics: PhysicsSave {
+                cell_size: 160.0,
+                iterations: 4,
+                quantized: false,
+                bodies: Vec::new(),
+            },
+            entities: vec![EntitySave::default()],
+            player: None,
+            wave_spawners: Vec::new(),
+        };
+        let json = save.to_json().unwrap();
+        assert_eq!(GameSave::load(json.as_bytes()).unwrap(), save);
+
+        let json = json.replace(
+            &format!("\"version\": {}", SAVE_VERSION),
+            "\"version\": 999",
+        );
+        assert_eq!(
+            GameSave::load(json.as_bytes()).unwrap_err().to_string(),
+            format!(
+                "The save is version 999, only version {} can be loaded",
+                SAVE_VERSION
+            )
+        );
+    }
+}
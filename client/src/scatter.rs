@@ -0,0 +1,353 @@
+// Ground clutter like grass tufts, rocks and flowers. A layer scatters one mesh over a rect
+// of the ground when the level is built. The positions come from a seeded generator, so
+// every load and every client get the same clutter. The instances are uploaded once as
+// persistent instances and grouped into square chunks, which the renderer culls as a whole
+// instead of every instance on its own.
+
+use std::ops::Range;
+
+use anyhow::bail;
+use shared::{
+    math::*,
+    rng::{Rng, stream},
+};
+
+use crate::renderer::{PersistentChunk, StaticInstanceData, TextureDesc};
+
+// More would take too long to generate on load, levels asking for more are rejected
+pub const MAX_SCATTER_CANDIDATES: f32 = 1_000_000.0;
+
+// Scales the density over the rect, from the first channel of a texture, 8 bit or a 16 bit
+// data texture. The texture is stretched over the rect with its top row at the min z.
+pub struct DensityMap {
+    width: u32,
+    height: u32,
+    values: Vec<f32>, // 0 to 1, row by row
+}
+
+impl DensityMap {
+    pub fn from_texture(desc: &TextureDesc) -> anyhow::Result<Self> {
+        let bytes = desc.bytes_per_channel as usize;
+        if bytes != 1 && !(bytes == 2 && desc.linear_data) {
+            bail!(
+                "Density maps need one byte per channel or 16 bit data, the texture has {} bytes",
+                desc.bytes_per_channel
+            );
+        }
+
+        // The top level of the first layer comes first
+        let stride = desc.channel_count.max(1) as usize * bytes;
+        let texel_count = desc.width as usize * desc.height as usize;
+        let Some(pixels) = desc.pixels.get(..texel_count * stride) else {
+            bail!(
+                "The texture has {} bytes of pixels, too few for {}x{}",
+                desc.pixels.len(),
+                desc.width,
+                desc.height
+            );
+        };
+        if texel_count == 0 {
+            bail!("The texture is empty");
+        }
+
+        Ok(Self {
+            width: desc.width,
+            height: desc.height,
+            values: pixels
+                .chunks_exact(stride)
+                .map(|texel| match texel[..bytes] {
+                    [value] => value as f32 / 255.0,
+                    [low, high, ..] => u16::from_le_bytes([low, high]) as f32 / 65535.0,
+                    [] => unreachable!(),
+                })
+                .collect(),
+        })
+    }
+
+    // The nearest texel, uv from 0 to 1 over the rect
+    pub fn sample(&self, uv: Vec2) -> f32 {
+        let size = Vec2::new(self.width as f32, self.height as f32);
+        let texel = (uv * size).clamp(Vec2::ZERO, size - 1.0).as_uvec2();
+        self.values[(texel.y * self.width + texel.x) as usize]
+    }
+}
+
+pub struct ScatterLayer {
+    pub min: Vec2, // The rect on the ground, as xz
+    pub max: Vec2,
+    pub density: f32, // Instances per square unit, where the density map is white
+    pub density_map: Option<DensityMap>,
+    pub seed: u64,
+    pub scale: Range<f32>, // Uniform scale of the mesh
+    pub yaw: Range<f32>,   // Degrees around the up axis
+    pub color: Vec4,
+    pub chunk_size: f32, // Side of the square chunks
+}
+
+pub struct ScatterInstances {
+    pub instances: Vec<StaticInstanceData>, // Ordered by chunk
+    pub chunks: Vec<PersistentChunk>,
+}
+
+impl ScatterLayer {
+    // Candidates are spread evenly over the rect and kept with the density of the map at
+    // their position. Every candidate draws the same numbers, kept or not, so painting the
+    // map only adds or removes clutter where it changed.
+    pub fn get_candidate_count(&self) -> u32 {
+        let size = self.max - self.min;
+        (size.x * size.y * self.density).round().max(0.0) as u32
+    }
+
+    // The mesh bounds are in its own space, the height of the ground is looked up per xz
+    pub fn generate(
+        &self,
+        mesh_min: Vec3,
+        mesh_max: Vec3,
+        get_height: impl Fn(Vec2) -> f32,
+    ) -> ScatterInstances {
+        let mut rng = Rng::with_stream(self.seed, stream::SCATTER);
+        let size = self.max - self.min;
+        let columns = ((size.x / self.chunk_size).ceil() as u32).max(1);
+        let rows = ((size.y / self.chunk_size).ceil() as u32).max(1);
+
+        // By chunk index, the order within a chunk is the order they were generated in
+        let mut scattered: Vec<(u32, Vec3, f32, f32)> = Vec::new();
+        for _ in 0..self.get_candidate_count() {
+            let uv = Vec2::new(rng.gen_f32(), rng.gen_f32());
+            let scale = lerp(&self.scale, rng.gen_f32());
+            let yaw = lerp(&self.yaw, rng.gen_f32());
+            let keep = rng.gen_f32();
+            let density = self.density_map.as_ref().map_or(1.0, |map| map.sample(uv));
+            if keep >= density {
+                continue;
+            }
+
+            let ground = self.min + uv * size;
+            let cell = ((ground - self.min) / self.chunk_size).as_uvec2();
+            let chunk = cell.y.min(rows - 1) * columns + cell.x.min(columns - 1);
+            let position = Vec3::new(ground.x, get_height(ground), ground.y);
+            scattered.push((chunk, position, scale, yaw));
+        }
+        scattered.sort_by_key(|(chunk, ..)| *chunk);
+
+        // Spun around the up axis the mesh stays within the circle through its furthest corner
+        let radius = [mesh_min.x.abs(), mesh_max.x.abs()]
+            .into_iter()
+            .flat_map(|x| [mesh_min.z.abs(), mesh_max.z.abs()].map(|z| Vec2::new(x, z).length()))
+            .fold(0.0, f32::max);
+
+        let mut instances = Vec::with_capacity(scattered.len());
+        let mut chunks: Vec<PersistentChunk> = Vec::new();
+        let mut current_chunk = None;
+        for (chunk, position, scale, yaw) in scattered {
+            let min = position + Vec3::new(-radius, mesh_min.y, -radius) * scale;
+            let max = position + Vec3::new(radius, mesh_max.y, radius) * scale;
+            let index = instances.len() as u32;
+            match chunks.last_mut() {
+                Some(last) if current_chunk == Some(chunk) => {
+                    last.instance_range.end = index + 1;
+                    last.min = last.min.min(min);
+                    last.max = last.max.max(max);
+                }
+                _ => chunks.push(PersistentChunk {
+                    instance_range: index..index + 1,
+                    min,
+                    max,
+                }),
+            }
+            current_chunk = Some(chunk);
+
+            let transform = Mat4::from_scale_rotation_translation(
+                Vec3::splat(scale),
+                Quat::from_rotation_y(yaw.to_radians()),
+                position,
+            );
+            instances.push(StaticInstanceData::new(transform, self.color));
+        }
+
+        ScatterInstances { instances, chunks }
+    }
+}
+
+fn lerp(range: &Range<f32>, t: f32) -> f32 {
+    range.start + (range.end - range.start) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_layer(density_map: Option<DensityMap>) -> ScatterLayer {
+        ScatterLayer {
+            min: Vec2::new(-500.0, -300.0),
+            max: Vec2::new(500.0, 300.0),
+            density: 0.005,
+            density_map,
+            seed: 7,
+            scale: 0.5..2.0,
+            yaw: 0.0..360.0,
+            color: Vec4::ONE,
+            chunk_size: 128.0,
+        }
+    }
+
+    fn get_positions(scatter: &ScatterInstances) -> Vec<Vec3> {
+        scatter
+            .instances
+            .iter()
+            .map(|instance| {
+                Mat4::from_cols_array(&instance.model_matrix)
+                    .w_axis
+                    .truncate()
+            })
+            .collect()
+    }
+
+    const MESH_MIN: Vec3 = Vec3::new(-2.0, 0.0, -1.0);
+    const MESH_MAX: Vec3 = Vec3::new(2.0, 5.0, 1.0);
+
+    #[test]
+    fn same_seed_scatters_the_same() {
+        let layer = get_layer(None);
+        let first = layer.generate(MESH_MIN, MESH_MAX, |_| 0.0);
+        let second = layer.generate(MESH_MIN, MESH_MAX, |_| 0.0);
+        assert_eq!(first.chunks, second.chunks);
+        assert_eq!(
+            bytemuck::cast_slice::<_, u8>(&first.instances),
+            bytemuck::cast_slice::<_, u8>(&second.instances)
+        );
+
+        let other = ScatterLayer {
+            seed: 8,
+            ..get_layer(None)
+        };
+        let other = other.generate(MESH_MIN, MESH_MAX, |_| 0.0);
+        assert_ne!(get_positions(&first), get_positions(&other));
+    }
+
+    #[test]
+    fn instances_stay_in_the_rect_and_their_chunks() {
+        let layer = get_layer(None);
+        let scatter = layer.generate(MESH_MIN, MESH_MAX, |ground| ground.x * 0.01);
+
+        // Constant density keeps every candidate, 1000 x 600 units at 0.005 per unit
+        assert_eq!(scatter.instances.len(), 3000);
+        // 8 x 5 chunks of 128 units, all of them get some
+        assert_eq!(scatter.chunks.len(), 40);
+
+        let positions = get_positions(&scatter);
+        for position in &positions {
+            assert!(position.xz().cmpge(layer.min).all() && position.xz().cmple(layer.max).all());
+            assert!((position.y - position.x * 0.01).abs() < 1e-3);
+        }
+
+        let mut next = 0;
+        for chunk in &scatter.chunks {
+            assert_eq!(chunk.instance_range.start, next);
+            next = chunk.instance_range.end;
+
+            let first = positions[chunk.instance_range.start as usize];
+            let cell = ((first.xz() - layer.min) / layer.chunk_size).floor();
+            for index in chunk.instance_range.clone() {
+                let instance = &scatter.instances[index as usize];
+                let transform = Mat4::from_cols_array(&instance.model_matrix);
+                let (scale, _, position) = transform.to_scale_rotation_translation();
+                assert!(scale.x >= 0.5 - 1e-4 && scale.x <= 2.0 + 1e-4);
+                assert_eq!(
+                    ((position.xz() - layer.min) / layer.chunk_size).floor(),
+                    cell
+                );
+
+                // Every corner of the placed mesh is inside the chunk bounds
+                for corner in 0..8 {
+                    let local = Vec3::select(
+                        glam::BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0),
+                        MESH_MAX,
+                        MESH_MIN,
+                    );
+                    let world = transform.transform_point3(local);
+                    assert!(
+                        world.cmpge(chunk.min - 1e-3).all(),
+                        "{} {}",
+                        world,
+                        chunk.min
+                    );
+                    assert!(
+                        world.cmple(chunk.max + 1e-3).all(),
+                        "{} {}",
+                        world,
+                        chunk.max
+                    );
+                }
+            }
+        }
+        assert_eq!(next as usize, scatter.instances.len());
+    }
+
+    #[test]
+    fn density_map_leaves_black_areas_empty() {
+        // Black on the left half, white on the right
+        let desc = TextureDesc {
+            width: 4,
+            height: 2,
+            channel_count: 4,
+            pixels: [[0, 0, 0, 255], [0, 0, 0, 255], [255; 4], [255; 4]]
+                .repeat(2)
+                .concat(),
+            ..Default::default()
+        };
+        let map = DensityMap::from_texture(&desc).unwrap();
+        assert_eq!(map.sample(Vec2::new(0.2, 0.9)), 0.0);
+        assert_eq!(map.sample(Vec2::new(0.8, 0.1)), 1.0);
+        assert_eq!(map.sample(Vec2::new(1.0, 1.0)), 1.0);
+
+        let layer = get_layer(Some(map));
+        let scatter = layer.generate(MESH_MIN, MESH_MAX, |_| 0.0);
+        let positions = get_positions(&scatter);
+        assert!(positions.iter().all(|position| position.x >= 0.0));
+        // Half of the 3000 candidates, give or take the randomness
+        assert!(
+            (1350..1650).contains(&positions.len()),
+            "{}",
+            positions.len()
+        );
+
+        // The white half scatters exactly like the layer without a map
+        let unmapped = get_layer(None).generate(MESH_MIN, MESH_MAX, |_| 0.0);
+        let unmapped: Vec<_> = get_positions(&unmapped)
+            .into_iter()
+            .filter(|position| position.x >= 0.0)
+            .collect();
+        assert_eq!(positions.len(), unmapped.len());
+
+        let short = TextureDesc {
+            width: 4,
+            height: 4,
+            pixels: vec![0; 8],
+            ..Default::default()
+        };
+        assert!(DensityMap::from_texture(&short).is_err());
+
+        // A 16 bit data texture keeps the steps between the 8 bit values
+        let data = TextureDesc {
+            width: 3,
+            height: 1,
+            bytes_per_channel: 2,
+            linear_data: true,
+            pixels: [0u16, 1, 65535]
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+            ..Default::default()
+        };
+        let map = DensityMap::from_texture(&data).unwrap();
+        assert_eq!(map.sample(Vec2::new(0.1, 0.5)), 0.0);
+        assert_eq!(map.sample(Vec2::new(0.5, 0.5)), 1.0 / 65535.0);
+        assert_eq!(map.sample(Vec2::new(0.9, 0.5)), 1.0);
+        let half_floats = TextureDesc {
+            linear_data: false,
+            ..data
+        };
+        assert!(DensityMap::from_texture(&half_floats).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+// Frames from Renderer::take_screenshot written as uncompressed BMP files, which need no
+// image encoder and open everywhere
+
+use crate::renderer::Screenshot;
+
+const FILE_HEADER_SIZE: u32 = 14;
+const INFO_HEADER_SIZE: u32 = 108; // BITMAPV4HEADER, it can describe the alpha channel
+
+// 32 bits per pixel in BGRA. The rows are stored top row first, with a negative height.
+pub fn encode_bmp(screenshot: &Screenshot) -> Vec<u8> {
+    let pixel_bytes = screenshot.size.x * screenshot.size.y * 4;
+    let offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+    let mut bytes = Vec::with_capacity((offset + pixel_bytes) as usize);
+
+    bytes.extend_from_slice(b"BM");
+    bytes.extend_from_slice(&(offset + pixel_bytes).to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes()); // Reserved
+    bytes.extend_from_slice(&offset.to_le_bytes());
+
+    bytes.extend_from_slice(&INFO_HEADER_SIZE.to_le_bytes());
+    bytes.extend_from_slice(&(screenshot.size.x as i32).to_le_bytes());
+    bytes.extend_from_slice(&(-(screenshot.size.y as i32)).to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // Planes
+    bytes.extend_from_slice(&32u16.to_le_bytes());
+    bytes.extend_from_slice(&3u32.to_le_bytes()); // BI_BITFIELDS, the masks follow
+    bytes.extend_from_slice(&pixel_bytes.to_le_bytes());
+    bytes.extend_from_slice(&2835i32.to_le_bytes()); // 72 DPI
+    bytes.extend_from_slice(&2835i32.to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes()); // Palette size
+    bytes.extend_from_slice(&0u32.to_le_bytes()); // Important colors
+    for mask in [0x00ff0000u32, 0x0000ff00, 0x000000ff, 0xff000000] {
+        bytes.extend_from_slice(&mask.to_le_bytes());
+    }
+    bytes.extend_from_slice(b"BGRs"); // LCS_sRGB, stored reversed
+    bytes.resize(offset as usize, 0); // Endpoints and gamma, unused for sRGB
+
+    for pixel in screenshot.pixels.chunks_exact(4) {
+        bytes.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+    }
+    bytes
+}
+
+// Next to the executable like the quick save, named by the time so they don't overwrite
+// each other
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_screenshot(screenshot: &Screenshot) -> anyhow::Result<std::path::PathBuf> {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let path = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.to_path_buf()))
+        .unwrap_or_default()
+        .join(format!("screenshot-{}.bmp", seconds));
+    std::fs::write(&path, encode_bmp(screenshot))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::UVec2;
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn bitmaps_are_top_down_bgra() {
+        // A red and a green pixel over a blue and a transparent one
+        let screenshot = Screenshot {
+            size: UVec2::new(2, 2),
+            pixels: vec![
+                255, 0, 0, 255, 0, 255, 0, 255, //
+                0, 0, 255, 255, 0, 0, 0, 0,
+            ],
+        };
+        let bytes = encode_bmp(&screenshot);
+
+        assert_eq!(&bytes[0..2], b"BM");
+        assert_eq!(read_u32(&bytes, 2) as usize, bytes.len());
+        let offset = read_u32(&bytes, 10) as usize;
+        assert_eq!(offset, 122);
+        assert_eq!(read_u32(&bytes, 18), 2);
+        assert_eq!(read_u32(&bytes, 22) as i32, -2);
+        assert_eq!(
+            &bytes[offset..],
+            [
+                0, 0, 255, 255, 0, 255, 0, 255, //
+                255, 0, 0, 255, 0, 0, 0, 0,
+            ]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+// Selecting our own units with the left mouse button. A click selects the unit under the
+// cursor, a drag selects every unit inside the rectangle. Shift adds to the selection,
+// otherwise it is replaced, so a click on the ground clears it. The game decides which
+// entities are under the cursor or in the rectangle, this only tracks the gesture and the
+// selected entities.
+
+use shared::math::*;
+
+use crate::{
+    components::Entity,
+    input::{InputAction, InputState},
+    renderer::{Renderer, SpriteSpace, render_data::SpriteRenderJob},
+};
+
+pub const SELECTION_RING_MATERIAL: &str = "SelectionRingMaterial";
+
+const DRAG_THRESHOLD: f32 = 6.0; // Pixels, shorter drags are clicks
+const DRAG_LAYER: u32 = u16::MAX as u32 - 1; // Just below the software cursor
+const DRAG_BORDER: f32 = 1.0;
+const DRAG_FILL_COLOR: Vec4 = Vec4::new(0.3, 0.9, 0.4, 0.15);
+const DRAG_BORDER_COLOR: Vec4 = Vec4::new(0.3, 0.9, 0.4, 0.8);
+
+// A finished gesture, in pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectionGesture {
+    Click(Vec2),
+    Drag { min: Vec2, max: Vec2 },
+}
+
+#[derive(Default)]
+pub struct SelectionSystem {
+    drag_start: Option<Vec2>, // Pixels, while the left button is held
+    drag_end: Vec2,
+    selected: Vec<Entity>,
+}
+
+impl SelectionSystem {
+    // Returns the gesture once the left button is released
+    pub fn update(
+        &mut self,
+        input_state: &InputState,
+        screen_size: Vec2,
+    ) -> Option<SelectionGesture> {
+        let mouse_position = input_state.get_mouse_position() * screen_size;
+        if input_state.is_pressed(InputAction::LeftClick) {
+            self.drag_start = Some(mouse_position);
+        }
+        self.drag_end = mouse_position;
+
+        let start = self.drag_start?;
+        if input_state.is_down(InputAction::LeftClick) {
+            return None;
+        }
+        self.drag_start = None;
+        if start.distance(mouse_position) < DRAG_THRESHOLD {
+            Some(SelectionGesture::Click(start))
+        } else {
+            Some(SelectionGesture::Drag {
+                min: start.min(mouse_position),
+                max: start.max(mouse_position),
+            })
+        }
+    }
+
+    // Replaces the selection unless it is additive, an empty one clears it
+    pub fn select(&mut self, entities: impl IntoIterator<Item = Entity>, additive: bool) {
+        if !additive {
+            self.selected.clear();
+        }
+        for entity in entities {
+            if !self.selected.contains(&entity) {
+                self.selected.push(entity);
+            }
+        }
+    }
+
+    pub fn get_selected(&self) -> &[Entity] {
+        &self.selected
+    }
+
+    pub fn retain(&mut self, f: impl FnMut(&Entity) -> bool) {
+        self.selected.retain(f);
+    }
+
+    pub fn clear(&mut self) {
+        self.drag_start = None;
+        self.selected.clear();
+    }
+
+    // The rectangle being dragged, in pixels
+    pub fn get_drag_rect(&self) -> Option<(Vec2, Vec2)> {
+        let start = self.drag_start?;
+        if start.distance(self.drag_end) < DRAG_THRESHOLD {
+            return None;
+        }
+        Some((start.min(self.drag_end), start.max(self.drag_end)))
+    }
+
+    // A translucent rectangle with a border while dragging
+    pub fn render(&self, renderer: &mut Renderer) {
+        let Some((min, max)) = self.get_drag_rect() else {
+            return;
+        };
+        let size = max - min;
+        let border = DRAG_BORDER;
+        let quads = [
+            (min, size, DRAG_FILL_COLOR),
+            (min, Vec2::new(size.x, border), DRAG_BORDER_COLOR),
+            (
+                Vec2::new(min.x, max.y - border),
+                Vec2::new(size.x, border),
+                DRAG_BORDER_COLOR,
+            ),
+            (
+                min + Vec2::new(0.0, border),
+                Vec2::new(border, size.y - 2.0 * border),
+                DRAG_BORDER_COLOR,
+            ),
+            (
+                Vec2::new(max.x - border, min.y + border),
+                Vec2::new(border, size.y - 2.0 * border),
+                DRAG_BORDER_COLOR,
+            ),
+        ];
+        // Same batch, so the border submitted after the fill is drawn on top of it
+        for (position, size, color) in quads {
+            renderer.submit(&SpriteRenderJob {
+                space: SpriteSpace::Absolute,
+                ..SpriteRenderJob::solid(position, size, color, DRAG_LAYER)
+            });
+        }
+    }
+}
+
+// The material the selection rings are drawn with, white so the job color shows
+pub fn create_selection_materials(renderer: &mut Renderer) {
+    let mut bytes = Vec::new();
+    for value in [1u32, 1, 1, 4, 1, 1] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&[255; 4]);
+    let texture = renderer.load_texture(SELECTION_RING_MATERIAL, &bytes);
+    renderer.create_material(SELECTION_RING_MATERIAL, texture);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Entities;
+
+    const SCREEN_SIZE: Vec2 = Vec2::new(1000.0, 500.0);
+
+    // Presses the left button at one point and releases it at the other
+    fn drag(selection: &mut SelectionSystem, from: Vec2, to: Vec2) -> Option<SelectionGesture> {
+        let mut input_state = InputState::new();
+        input_state.set_mouse_position(from / SCREEN_SIZE);
+        input_state.set_action(InputAction::LeftClick, true);
+        assert_eq!(selection.update(&input_state, SCREEN_SIZE), None);
+
+        input_state.reset();
+        input_state.set_mouse_position(to / SCREEN_SIZE);
+        assert_eq!(selection.update(&input_state, SCREEN_SIZE), None);
+        input_state.set_action(InputAction::LeftClick, false);
+        selection.update(&input_state, SCREEN_SIZE)
+    }
+
+    #[test]
+    fn short_drags_are_clicks() {
+        let mut selection = SelectionSystem::default();
+        let start = Vec2::new(100.0, 100.0);
+        assert_eq!(
+            drag(&mut selection, start, start + Vec2::new(3.0, -2.0)),
+            Some(SelectionGesture::Click(start))
+        );
+
+        // Dragged up and to the left, the rectangle still goes from min to max
+        assert_eq!(
+            drag(&mut selection, start, Vec2::new(40.0, 60.0)),
+            Some(SelectionGesture::Drag {
+                min: Vec2::new(40.0, 60.0),
+                max: start,
+            })
+        );
+        assert_eq!(selection.get_drag_rect(), None);
+    }
+
+    #[test]
+    fn only_additive_selections_keep_the_selection() {
+        let mut entities = Entities::default();
+        let a = entities.spawn();
+        let b = entities.spawn();
+
+        let mut selection = SelectionSystem::default();
+        selection.select([a], false);
+        selection.select([b, a], true);
+        assert_eq!(selection.get_selected(), &[a, b]);
+
+        // Clicking nothing with shift keeps it, without clears it
+        selection.select([], true);
+        assert_eq!(selection.get_selected().len(), 2);
+        selection.select([], false);
+        assert!(selection.get_selected().is_empty());
+    }
+}
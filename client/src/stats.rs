@@ -0,0 +1,666 @@
+// What the player did, in this session and over all the sessions before it. The game feeds
+// the StatsTracker from the events it drains and from the player's body after the physics
+// step, so counting costs nothing per frame. The totals live in the player profile, natively
+// profile.json in the config directory and in the browser an entry of the local storage.
+// Saving reads the stored profile again and adds what happened since the last save, so two
+// clients running at once don't undo each other. Tab shows both as a table.
+
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+use shared::math::*;
+
+use crate::{
+    components::Entity,
+    events::GameEvent,
+    renderer::{Renderer, SpriteAnchor, TextAlignment, resources::get_handle},
+    ui::{UiContent, UiNode, UiRenderer},
+};
+
+// Bumped whenever a change breaks reading older profiles
+pub const PROFILE_VERSION: u32 = 1;
+
+// Blinks and respawns jump further than this in one step, they aren't walked
+const MAX_STEP_DISTANCE: f32 = 100.0;
+
+const STATS_LAYER: u32 = u16::MAX as u32 - 10; // Below the profiler
+const TITLE_SIZE: f32 = 24.0;
+const ROW_SIZE: f32 = 18.0;
+const ROW_HEIGHT: f32 = 28.0;
+const NAME_WIDTH: f32 = 180.0;
+const VALUE_WIDTH: f32 = 130.0;
+const PADDING: f32 = 20.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Stats {
+    pub damage_dealt: f32,
+    pub damage_taken: f32,
+    pub kills: u32,
+    pub abilities_cast: u32,
+    pub distance: f32, // Walked on the ground
+    pub time: f32,     // Seconds of fixed steps, slowed down time counts less
+}
+
+impl Stats {
+    pub fn add(&mut self, other: &Stats) {
+        self.damage_dealt += other.damage_dealt;
+        self.damage_taken += other.damage_taken;
+        self.kills += other.kills;
+        self.abilities_cast += other.abilities_cast;
+        self.distance += other.distance;
+        self.time += other.time;
+    }
+
+    // What was added since the earlier stats were taken
+    pub fn get_since(&self, earlier: &Stats) -> Stats {
+        Stats {
+            damage_dealt: self.damage_dealt - earlier.damage_dealt,
+            damage_taken: self.damage_taken - earlier.damage_taken,
+            kills: self.kills - earlier.kills,
+            abilities_cast: self.abilities_cast - earlier.abilities_cast,
+            distance: self.distance - earlier.distance,
+            time: self.time - earlier.time,
+        }
+    }
+
+    // The rows of the stats screen, a name and the value as text
+    pub fn get_rows(&self) -> [(&'static str, String); 6] {
+        [
+            ("Damage dealt", format!("{:.0}", self.damage_dealt)),
+            ("Damage taken", format!("{:.0}", self.damage_taken)),
+            ("Kills", self.kills.to_string()),
+            ("Abilities cast", self.abilities_cast.to_string()),
+            ("Distance", format!("{:.0}", self.distance)),
+            ("Time played", format_duration(self.time)),
+        ]
+    }
+}
+
+// 1:02:05, or 2:05 under an hour
+fn format_duration(seconds: f32) -> String {
+    let seconds = seconds.max(0.0) as u32;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+// The stats of this session, only about the player
+#[derive(Debug, Default)]
+pub struct StatsTracker {
+    session: Stats,
+    saved: Stats,     // The part of the session that is in the stored profile
+    saved_once: bool, // The session is counted in the profile
+    last_position: Option<Vec2>,
+}
+
+impl StatsTracker {
+    pub fn get_session(&self) -> &Stats {
+        &self.session
+    }
+
+    // What the stored profile is still missing
+    pub fn get_unsaved(&self) -> Stats {
+        self.session.get_since(&self.saved)
+    }
+
+    pub fn record_event(&mut self, event: &GameEvent, player: Option<Entity>) {
+        let Some(player) = player else {
+            return;
+        };
+        match *event {
+            GameEvent::DamageDealt {
+                source,
+                target,
+                amount,
+                ..
+            } => {
+                if target == player {
+                    self.session.damage_taken += amount;
+                } else if source == Some(player) {
+                    self.session.damage_dealt += amount;
+                }
+            }
+            GameEvent::EntityDied { entity, killer }
+                if killer == Some(player) && entity != player =>
+            {
+                self.session.kills += 1;
+            }
+            // Interrupted and failed casts don't count
+            GameEvent::AbilityExecuted { caster, .. } if caster == player => {
+                self.session.abilities_cast += 1;
+            }
+            _ => {}
+        }
+    }
+
+    // Once per fixed step, with where the player's body is after it. None while there is no
+    // player, the next position starts over from where it is.
+    pub fn record_step(&mut self, dt: f32, position: Option<Vec2>) {
+        self.session.time += dt;
+        if let (Some(last), Some(position)) = (self.last_position, position) {
+            let distance = last.distance(position);
+            if distance <= MAX_STEP_DISTANCE {
+                self.session.distance += distance;
+            }
+        }
+        self.last_position = position;
+    }
+
+    // After the merged profile was stored
+    pub fn mark_saved(&mut self) {
+        self.saved = self.session;
+        self.saved_once = true;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlayerProfile {
+    pub version: u32,
+    pub sessions: u32,
+    pub totals: Stats,
+}
+
+impl Default for PlayerProfile {
+    fn default() -> Self {
+        Self {
+            version: PROFILE_VERSION,
+            sessions: 0,
+            totals: Stats::default(),
+        }
+    }
+}
+
+impl PlayerProfile {
+    // Other versions are refused, errors name the offending field
+    pub fn load(bytes: &[u8]) -> anyhow::Result<Self> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+        let version: Version = serde_json::from_slice(bytes)?;
+        if version.version != PROFILE_VERSION {
+            bail!(
+                "The profile is of version {}, this client reads version {}",
+                version.version,
+                PROFILE_VERSION
+            );
+        }
+
+        let deserializer = &mut serde_json::Deserializer::from_slice(bytes);
+        serde_path_to_error::deserialize(deserializer)
+            .map_err(|error| anyhow!("{}: {}", error.path(), error.inner()))
+    }
+
+    // A broken profile starts over, the next save replaces it
+    pub fn load_or_reset(bytes: &[u8]) -> Self {
+        Self::load(bytes).unwrap_or_else(|error| {
+            log::warn!(
+                "Resetting the player profile, it can't be read: {:#}",
+                error
+            );
+            Self::default()
+        })
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    // Adds what the session did since it was last saved, the first save also counts the
+    // session itself
+    pub fn merge(&mut self, tracker: &StatsTracker) {
+        if !tracker.saved_once {
+            self.sessions += 1;
+        }
+        self.totals.add(&tracker.get_unsaved());
+    }
+}
+
+// What is stored, a missing profile is a new one
+pub fn load_profile() -> PlayerProfile {
+    match read_profile() {
+        Ok(Some(bytes)) => PlayerProfile::load_or_reset(&bytes),
+        Ok(None) => PlayerProfile::default(),
+        Err(error) => {
+            log::warn!("Failed to read the player profile: {:#}", error);
+            PlayerProfile::default()
+        }
+    }
+}
+
+// Merged into what is stored now, returns the stored profile
+pub fn save_profile(tracker: &mut StatsTracker) -> anyhow::Result<PlayerProfile> {
+    let mut profile = load_profile();
+    profile.merge(tracker);
+    write_profile(&profile.to_json()?)?;
+    tracker.mark_saved();
+    Ok(profile)
+}
+
+// In the config directory of the platform, next to the executable when there is none
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_profile_path() -> std::path::PathBuf {
+    use std::{env, path::PathBuf};
+
+    let home = || env::var_os("HOME").map(PathBuf::from);
+    let config_dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| Some(home()?.join(".config")))
+    };
+    match config_dir {
+        Some(config_dir) => config_dir.join("rusty-rift"),
+        None => env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.parent()?.to_path_buf()))
+            .unwrap_or_default(),
+    }
+    .join("profile.json")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_profile() -> anyhow::Result<Option<Vec<u8>>> {
+    let path = get_profile_path();
+    match std::fs::read(&path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(anyhow!("{}: {}", path.display(), error)),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_profile(json: &str) -> anyhow::Result<()> {
+    let path = get_profile_path();
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)
+            .map_err(|error| anyhow!("{}: {}", directory.display(), error))?;
+    }
+    std::fs::write(&path, json).map_err(|error| anyhow!("{}: {}", path.display(), error))
+}
+
+#[cfg(target_arch = "wasm32")]
+const PROFILE_KEY: &str = "rusty-rift.profile";
+
+#[cfg(target_arch = "wasm32")]
+fn get_local_storage() -> anyhow::Result<web_sys::Storage> {
+    web_sys::window()
+        .ok_or_else(|| anyhow!("No window"))?
+        .local_storage()
+        .map_err(|error| anyhow!("{:?}", error))?
+        .ok_or_else(|| anyhow!("There is no local storage"))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_profile() -> anyhow::Result<Option<Vec<u8>>> {
+    let item = get_local_storage()?
+        .get_item(PROFILE_KEY)
+        .map_err(|error| anyhow!("{:?}", error))?;
+    Ok(item.map(String::into_bytes))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_profile(json: &str) -> anyhow::Result<()> {
+    get_local_storage()?
+        .set_item(PROFILE_KEY, json)
+        .map_err(|error| anyhow!("{:?}", error))
+}
+
+// The table shown while Tab is held. The labels are built once, their texts are set as
+// the table is drawn.
+pub struct StatsScreen {
+    root: UiNode,
+    ui_renderer: UiRenderer,
+}
+
+impl StatsScreen {
+    pub fn new() -> Self {
+        Self {
+            root: build_stats_table(),
+            ui_renderer: UiRenderer {
+                font_atlas: get_handle("DebugFont"),
+                font_material: get_handle("DebugFontMaterial"),
+                layer: STATS_LAYER,
+            },
+        }
+    }
+
+    pub fn render(&mut self, session: &Stats, totals: &Stats, renderer: &mut Renderer) {
+        self.set_values(session, totals);
+        self.ui_renderer.submit(&self.root, renderer);
+    }
+
+    fn set_values(&mut self, session: &Stats, totals: &Stats) {
+        for ((name, session), (_, total)) in session.get_rows().iter().zip(totals.get_rows()) {
+            if let Some(label) = self.root.find_mut(&format!("{}.session", name)) {
+                label.set_text(session);
+            }
+            if let Some(label) = self.root.find_mut(&format!("{}.total", name)) {
+                label.set_text(&total);
+            }
+        }
+    }
+}
+
+// A title over a header and a row per stat, a column of names and one of values for the
+// session and for all of them
+fn build_stats_table() -> UiNode {
+    let row_count = Stats::default().get_rows().len() + 1;
+    let width = NAME_WIDTH + 2.0 * VALUE_WIDTH;
+    let height = TITLE_SIZE + 12.0 + row_count as f32 * ROW_HEIGHT;
+
+    let label = |text: &str, size: f32, width: f32, alignment: TextAlignment, color: Vec4| UiNode {
+        content: UiContent::Label {
+            text: text.to_string(),
+            size,
+            color,
+            alignment,
+        },
+        ..UiNode::label(text, size, width)
+    };
+    let row = |index: usize, cells: [UiNode; 3]| {
+        let mut x = 0.0;
+        let children = cells
+            .into_iter()
+            .map(|cell| {
+                let offset = Vec2::new(x, 0.0);
+                x += cell.size.x;
+                UiNode { offset, ..cell }
+            })
+            .collect();
+        UiNode {
+            offset: Vec2::new(0.0, TITLE_SIZE + 12.0 + index as f32 * ROW_HEIGHT),
+            children,
+            ..UiNode::group(Vec2::new(width, ROW_HEIGHT))
+        }
+    };
+
+    let header_color = Vec4::new(1.0, 0.9, 0.6, 1.0);
+    let mut children = vec![
+        label("Stats", TITLE_SIZE, width, TextAlignment::Left, Vec4::ONE),
+        row(
+            0,
+            [
+                label("", ROW_SIZE, NAME_WIDTH, TextAlignment::Left, header_color),
+                label(
+                    "This session",
+                    ROW_SIZE,
+                    VALUE_WIDTH,
+                    TextAlignment::Right,
+                    header_color,
+                ),
+                label(
+                    "All time",
+                    ROW_SIZE,
+                    VALUE_WIDTH,
+                    TextAlignment::Right,
+                    header_color,
+                ),
+            ],
+        ),
+    ];
+    for (index, (name, _)) in Stats::default().get_rows().into_iter().enumerate() {
+        let value = |column: &str| UiNode {
+            name: format!("{}.{}", name, column),
+            ..label("0", ROW_SIZE, VALUE_WIDTH, TextAlignment::Right, Vec4::ONE)
+        };
+        children.push(row(
+            index + 1,
+            [
+                label(
+                    name,
+                    ROW_SIZE,
+                    NAME_WIDTH,
+                    TextAlignment::Left,
+                    Vec4::splat(0.85),
+                ),
+                value("session"),
+                value("total"),
+            ],
+        ));
+    }
+
+    UiNode {
+        anchor: SpriteAnchor::Center,
+        padding: Vec2::splat(PADDING),
+        children,
+        ..UiNode::panel(
+            Vec2::new(width, height) + 2.0 * PADDING,
+            Vec4::new(0.05, 0.06, 0.08, 0.85),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{components::Entities, ui::visit_layout};
+
+    #[test]
+    fn only_events_about_the_player_count() {
+        let mut entities = Entities::default();
+        let player = entities.spawn();
+        let enemy = entities.spawn();
+        let other = entities.spawn();
+
+        let mut tracker = StatsTracker::default();
+        let events = [
+            GameEvent::DamageDealt {
+                source: Some(player),
+                target: enemy,
+                amount: 20.0,
+                fatal: false,
+            },
+            GameEvent::DamageDealt {
+                source: Some(player),
+                target: enemy,
+                amount: 30.0,
+                fatal: true,
+            },
+            GameEvent::EntityDied {
+                entity: enemy,
+                killer: Some(player),
+            },
+            // Damage over time has no source
+            GameEvent::DamageDealt {
+                source: None,
+                target: player,
+                amount: 5.0,
+                fatal: false,
+            },
+            GameEvent::DamageDealt {
+                source: Some(enemy),
+                target: player,
+                amount: 15.0,
+                fatal: false,
+            },
+            GameEvent::DamageDealt {
+                source: Some(enemy),
+                target: other,
+                amount: 50.0,
+                fatal: true,
+            },
+            GameEvent::EntityDied {
+                entity: other,
+                killer: Some(enemy),
+            },
+            GameEvent::AbilityCastStarted {
+                caster: player,
+                slot: 0,
+            },
+            GameEvent::AbilityExecuted {
+                caster: player,
+                slot: 0,
+            },
+            GameEvent::AbilityInterrupted {
+                caster: player,
+                slot: 1,
+            },
+            GameEvent::AbilityExecuted {
+                caster: enemy,
+                slot: 0,
+            },
+        ];
+        for event in &events {
+            tracker.record_event(event, Some(player));
+        }
+        // Without a player nothing counts
+        for event in &events {
+            tracker.record_event(event, None);
+        }
+
+        assert_eq!(
+            *tracker.get_session(),
+            Stats {
+                damage_dealt: 50.0,
+                damage_taken: 20.0,
+                kills: 1,
+                abilities_cast: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn walking_counts_and_jumps_do_not() {
+        let mut tracker = StatsTracker::default();
+        tracker.record_step(0.5, Some(Vec2::ZERO));
+        tracker.record_step(0.5, Some(Vec2::new(3.0, 4.0)));
+        // A blink, then walking on from where it landed
+        tracker.record_step(0.5, Some(Vec2::new(500.0, 4.0)));
+        tracker.record_step(0.5, Some(Vec2::new(510.0, 4.0)));
+        // Dead, and back somewhere else
+        tracker.record_step(0.5, None);
+        tracker.record_step(0.5, Some(Vec2::new(-20.0, 0.0)));
+
+        assert_eq!(tracker.get_session().distance, 15.0);
+        assert_eq!(tracker.get_session().time, 3.0);
+    }
+
+    #[test]
+    fn saving_again_only_adds_what_is_new() {
+        let mut tracker = StatsTracker::default();
+        tracker.session.kills = 2;
+        tracker.session.time = 60.0;
+
+        // Another session saved 5 kills before this one
+        let mut stored = PlayerProfile {
+            sessions: 1,
+            totals: Stats {
+                kills: 5,
+                time: 100.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        stored.merge(&tracker);
+        tracker.mark_saved();
+        assert_eq!(stored.sessions, 2);
+        assert_eq!(stored.totals.kills, 7);
+        assert_eq!(tracker.get_unsaved(), Stats::default());
+
+        // Saving twice without anything new changes nothing
+        let mut again = stored.clone();
+        again.merge(&tracker);
+        assert_eq!(again, stored);
+
+        tracker.session.kills += 1;
+        tracker.session.time += 30.0;
+        stored.merge(&tracker);
+        tracker.mark_saved();
+        assert_eq!(stored.sessions, 2);
+        assert_eq!(stored.totals.kills, 8);
+        assert_eq!(stored.totals.time, 190.0);
+
+        // Through the file
+        let loaded = PlayerProfile::load(stored.to_json().unwrap().as_bytes()).unwrap();
+        assert_eq!(loaded, stored);
+    }
+
+    #[test]
+    fn broken_profiles_start_over() {
+        let newer = format!(
+            "{{ \"version\": {}, \"sessions\": 3, \"totals\": {{}} }}",
+            PROFILE_VERSION + 1
+        );
+        let unknown = format!(
+            "{{ \"version\": {}, \"sessions\": 3, \"totals\": {{ \"gold\": 4 }} }}",
+            PROFILE_VERSION
+        );
+        for bytes in [
+            &b"{ \"version\": 1, \"sess"[..],
+            &[0xff, 0x00, 0x12][..],
+            newer.as_bytes(),
+            unknown.as_bytes(),
+        ] {
+            assert_eq!(
+                PlayerProfile::load_or_reset(bytes),
+                PlayerProfile::default()
+            );
+        }
+
+        let error = PlayerProfile::load(unknown.as_bytes()).unwrap_err();
+        assert!(format!("{:#}", error).contains("totals"), "{:#}", error);
+
+        // Stats missing from an older profile of the same version are 0
+        let fewer = format!(
+            "{{ \"version\": {}, \"sessions\": 3, \"totals\": {{ \"kills\": 4 }} }}",
+            PROFILE_VERSION
+        );
+        let profile = PlayerProfile::load(fewer.as_bytes()).unwrap();
+        assert_eq!(profile.totals.kills, 4);
+        assert_eq!(profile.totals.time, 0.0);
+    }
+
+    #[test]
+    fn the_table_has_a_row_per_stat_inside_the_panel() {
+        let mut screen = StatsScreen::new();
+        let session = Stats {
+            kills: 3,
+            time: 125.0,
+            ..Default::default()
+        };
+        let totals = Stats {
+            kills: 40,
+            time: 3725.0,
+            ..Default::default()
+        };
+        screen.set_values(&session, &totals);
+
+        let mut panel = None;
+        let mut values = Vec::new();
+        visit_layout(
+            &screen.root,
+            Vec2::new(1280.0, 720.0),
+            &mut |node, rect, _| {
+                if panel.is_none() {
+                    panel = Some(*rect);
+                }
+                assert!(
+                    rect.position.cmpge(panel.unwrap().position).all()
+                        && rect.get_end().cmple(panel.unwrap().get_end()).all(),
+                    "{} sticks out of the panel",
+                    node.name
+                );
+                if let UiContent::Label { text, .. } = &node.content
+                    && !node.name.is_empty()
+                {
+                    values.push((node.name.clone(), text.clone()));
+                }
+            },
+        );
+
+        assert_eq!(values.len(), 2 * Stats::default().get_rows().len());
+        assert!(values.contains(&("Kills.session".to_string(), "3".to_string())));
+        assert!(values.contains(&("Kills.total".to_string(), "40".to_string())));
+        assert!(values.contains(&("Time played.session".to_string(), "2:05".to_string())));
+        assert!(values.contains(&("Time played.total".to_string(), "1:02:05".to_string())));
+    }
+}
@@ -0,0 +1,233 @@
+// Timed buffs and debuffs on an entity. Effects of the same kind from different sources
+// live side by side, the aggregate modifiers pick the strongest one per kind so that two
+// slows don't multiply into a near standstill.
+
+use serde::{Deserialize, Serialize};
+
+const MAX_STATUS_EFFECTS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StatusKind {
+    Slow,           // Magnitude is the fraction of speed removed
+    Haste,          // Magnitude is the fraction of speed added
+    DamageOverTime, // Magnitude is the damage per second
+    Shield,         // Magnitude is the fraction of incoming damage blocked
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StackingPolicy {
+    Refresh,                   // Restarts the duration
+    Stack { max_stacks: u32 }, // Adds a stack and restarts the duration
+    Ignore,                    // Keeps the running effect untouched
+}
+
+// Reapplying an equal descriptor follows its stacking policy, any other descriptor is
+// added as a separate effect
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StatusEffectDesc {
+    pub kind: StatusKind,
+    pub magnitude: f32,
+    pub duration: f32,
+    pub stacking: StackingPolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ActiveEffect {
+    pub desc: StatusEffectDesc,
+    pub remaining: f32,
+    pub stacks: u32,
+}
+
+impl ActiveEffect {
+    fn get_magnitude(&self) -> f32 {
+        self.desc.magnitude * self.stacks as f32
+    }
+
+    // 1.0 right after being applied, 0.0 when it runs out
+    pub fn get_remaining_fraction(&self) -> f32 {
+        if self.desc.duration <= 0.0 {
+            return 0.0;
+        }
+        (self.remaining / self.desc.duration).clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusEffects {
+    effects: [Option<ActiveEffect>; MAX_STATUS_EFFECTS],
+}
+
+impl StatusEffects {
+    pub fn apply(&mut self, desc: StatusEffectDesc) {
+        if let Some(effect) = self.effects.iter_mut().flatten().find(|e| e.desc == desc) {
+            match desc.stacking {
+                StackingPolicy::Refresh => effect.remaining = desc.duration,
+                StackingPolicy::Stack { max_stacks } => {
+                    effect.stacks = (effect.stacks + 1).min(max_stacks.max(1));
+                    effect.remaining = desc.duration;
+                }
+                StackingPolicy::Ignore => {}
+            }
+            return;
+        }
+
+        let effect = ActiveEffect {
+            desc,
+            remaining: desc.duration,
+            stacks: 1,
+        };
+
+        // Takes a free slot, or replaces the effect closest to running out
+        let slot = match self.effects.iter().position(Option::is_none) {
+            Some(free_index) => free_index,
+            None => self
+                .effects
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| {
+                    let a = a.as_ref().map_or(0.0, |e| e.remaining);
+                    let b = b.as_ref().map_or(0.0, |e| e.remaining);
+                    a.total_cmp(&b)
+                })
+                .map(|(index, _)| index)
+                .unwrap_or(0),
+        };
+        self.effects[slot] = Some(effect);
+    }
+
+    // Returns the damage over time dealt during the tick, before the damage multiplier
+    pub fn update(&mut self, dt: f32) -> f32 {
+        let mut damage = 0.0;
+        for slot in self.effects.iter_mut() {
+            let Some(effect) = slot else {
+                continue;
+            };
+
+            // The last tick only deals damage for the time that was left
+            let active_time = dt.min(effect.remaining);
+            if effect.desc.kind == StatusKind::DamageOverTime {
+                damage += effect.get_magnitude() * active_time;
+            }
+
+            effect.remaining -= dt;
+            if effect.remaining <= 0.0 {
+                *slot = None;
+            }
+        }
+
+        damage
+    }
+
+    pub fn movement_speed_multiplier(&self) -> f32 {
+        let slow = self.get_strongest(StatusKind::Slow).clamp(0.0, 1.0);
+        let haste = self.get_strongest(StatusKind::Haste).max(0.0);
+        (1.0 - slow) * (1.0 + haste)
+    }
+
+    pub fn incoming_damage_multiplier(&self) -> f32 {
+        1.0 - self.get_strongest(StatusKind::Shield).clamp(0.0, 1.0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ActiveEffect> {
+        self.effects.iter().flatten()
+    }
+
+    fn get_strongest(&self, kind: StatusKind) -> f32 {
+        self.iter()
+            .filter(|effect| effect.desc.kind == kind)
+            .map(ActiveEffect::get_magnitude)
+            .fold(0.0, f32::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_desc(kind: StatusKind, magnitude: f32, stacking: StackingPolicy) -> StatusEffectDesc {
+        StatusEffectDesc {
+            kind,
+            magnitude,
+            duration: 2.0,
+            stacking,
+        }
+    }
+
+    #[test]
+    fn effects_expire_after_their_duration() {
+        let mut effects = StatusEffects::default();
+        effects.apply(get_desc(StatusKind::Slow, 0.5, StackingPolicy::Refresh));
+        assert_eq!(effects.movement_speed_multiplier(), 0.5);
+
+        effects.update(1.5);
+        assert_eq!(effects.movement_speed_multiplier(), 0.5);
+        effects.update(0.5);
+        assert_eq!(effects.movement_speed_multiplier(), 1.0);
+        assert_eq!(effects.iter().count(), 0);
+    }
+
+    #[test]
+    fn reapplying_mid_duration_follows_the_stacking_policy() {
+        let refresh = get_desc(StatusKind::Shield, 0.25, StackingPolicy::Refresh);
+        let stack = get_desc(
+            StatusKind::Slow,
+            0.2,
+            StackingPolicy::Stack { max_stacks: 2 },
+        );
+        let ignore = get_desc(StatusKind::Haste, 0.5, StackingPolicy::Ignore);
+
+        let mut effects = StatusEffects::default();
+        effects.apply(refresh);
+        effects.apply(stack);
+        effects.apply(ignore);
+        effects.update(1.5);
+
+        effects.apply(refresh);
+        effects.apply(stack);
+        effects.apply(stack); // Capped at two stacks
+        effects.apply(ignore);
+        assert_eq!(effects.iter().count(), 3);
+        assert_eq!(effects.incoming_damage_multiplier(), 0.75);
+        assert!((effects.movement_speed_multiplier() - 0.6 * 1.5).abs() < 1e-6);
+
+        // The refreshed and stacked effects got their full duration back, haste runs out
+        effects.update(1.0);
+        assert_eq!(effects.incoming_damage_multiplier(), 0.75);
+        assert!((effects.movement_speed_multiplier() - 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn simultaneous_slows_pick_the_strongest() {
+        let mut effects = StatusEffects::default();
+        effects.apply(get_desc(StatusKind::Slow, 0.3, StackingPolicy::Refresh));
+        effects.apply(StatusEffectDesc {
+            duration: 1.0,
+            ..get_desc(StatusKind::Slow, 0.6, StackingPolicy::Refresh)
+        });
+        effects.apply(get_desc(StatusKind::Slow, 0.1, StackingPolicy::Refresh));
+        assert!((effects.movement_speed_multiplier() - 0.4).abs() < 1e-6);
+
+        // The next strongest takes over once the strongest expires
+        effects.update(1.0);
+        assert!((effects.movement_speed_multiplier() - 0.7).abs() < 1e-6);
+    }
+
+    #[test]
+    fn damage_over_time_stops_at_expiry() {
+        let mut effects = StatusEffects::default();
+        effects.apply(get_desc(
+            StatusKind::DamageOverTime,
+            10.0,
+            StackingPolicy::Stack { max_stacks: 3 },
+        ));
+        effects.apply(get_desc(
+            StatusKind::DamageOverTime,
+            10.0,
+            StackingPolicy::Stack { max_stacks: 3 },
+        ));
+
+        assert_eq!(effects.update(0.5), 10.0);
+        assert_eq!(effects.update(2.0), 30.0); // Only 1.5s were left
+        assert_eq!(effects.update(1.0), 0.0);
+    }
+}
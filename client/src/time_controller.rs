@@ -0,0 +1,152 @@
+// How fast gameplay time runs compared to real time, for hit-stops and slow motion. Only
+// the game and its fixed updates get the scaled time, the UI, the overlays and the debug
+// camera keep running on real time so menus stay responsive during a slowdown.
+
+// The hit-stops the game asks for on its own
+pub const HIT_STOP_DURATION: f32 = 0.05;
+pub const HIT_STOP_SCALE: f32 = 0.2;
+pub const KILL_STOP_DURATION: f32 = 0.1;
+pub const KILL_STOP_SCALE: f32 = 0.05;
+
+struct TimeDilation {
+    scale: f32,
+    remaining: f32, // Real seconds
+}
+
+pub struct TimeController {
+    base_scale: f32, // 1 for normal speed, 0 pauses
+    dilations: Vec<TimeDilation>,
+    accumulator: f32, // Scaled time not simulated by fixed updates yet
+}
+
+impl Default for TimeController {
+    fn default() -> Self {
+        Self {
+            base_scale: 1.0,
+            dilations: Vec::new(),
+            accumulator: 0.0,
+        }
+    }
+}
+
+impl TimeController {
+    pub fn get_base_scale(&self) -> f32 {
+        self.base_scale
+    }
+
+    pub fn set_base_scale(&mut self, scale: f32) {
+        self.base_scale = scale.max(0.0);
+    }
+
+    // Slows time down to the scale for the real duration. Overlapping dilations don't
+    // multiply, the slowest one wins until it runs out.
+    pub fn hit_stop(&mut self, duration: f32, scale: f32) {
+        if duration > 0.0 {
+            self.dilations.push(TimeDilation {
+                scale: scale.clamp(0.0, 1.0),
+                remaining: duration,
+            });
+        }
+    }
+
+    pub fn get_scale(&self) -> f32 {
+        let dilation = self
+            .dilations
+            .iter()
+            .map(|dilation| dilation.scale)
+            .fold(1.0, f32::min);
+        self.base_scale * dilation
+    }
+
+    // Runs the dilations for the real dt and returns the gameplay dt. A dilation ending
+    // partway through the frame only slows down its part of the frame.
+    pub fn advance(&mut self, real_dt: f32) -> f32 {
+        let mut scaled_dt = 0.0;
+        let mut left = real_dt;
+        while left > 0.0 {
+            let step = self
+                .dilations
+                .iter()
+                .map(|dilation| dilation.remaining)
+                .fold(left, f32::min);
+            scaled_dt += step * self.get_scale();
+            for dilation in self.dilations.iter_mut() {
+                dilation.remaining -= step;
+            }
+            self.dilations.retain(|dilation| dilation.remaining > 0.0);
+            left -= step;
+        }
+
+        // Paused time never builds up, so resuming doesn't run a burst of fixed updates
+        self.accumulator += scaled_dt;
+        scaled_dt
+    }
+
+    // True while there is enough scaled time for another fixed update of the step
+    pub fn take_fixed_step(&mut self, step: f32) -> bool {
+        if self.accumulator < step {
+            return false;
+        }
+        self.accumulator -= step;
+        true
+    }
+
+    // How far the time is between the last fixed update and the next one
+    pub fn get_alpha(&self, step: f32) -> f32 {
+        (self.accumulator / step).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP: f32 = 1.0 / 60.0;
+
+    // Runs frames of real time, returns the fixed updates they ran
+    fn run_frames(time: &mut TimeController, frame_count: u32, real_dt: f32) -> u32 {
+        let mut step_count = 0;
+        for _ in 0..frame_count {
+            time.advance(real_dt);
+            while time.take_fixed_step(STEP) {
+                step_count += 1;
+            }
+        }
+        step_count
+    }
+
+    #[test]
+    fn fixed_updates_follow_the_scale() {
+        let mut time = TimeController::default();
+        assert_eq!(run_frames(&mut time, 60, 1.0 / 30.0), 120);
+
+        time.set_base_scale(0.5);
+        assert_eq!(run_frames(&mut time, 120, STEP), 60);
+
+        // Pausing for a long time doesn't make up for it once resumed
+        time.set_base_scale(0.0);
+        assert_eq!(run_frames(&mut time, 600, STEP), 0);
+        time.set_base_scale(1.0);
+        let alpha = time.get_alpha(STEP);
+        assert!(run_frames(&mut time, 1, STEP) <= 1);
+        assert_eq!(time.get_alpha(STEP), alpha);
+    }
+
+    #[test]
+    fn the_strongest_dilation_wins_for_its_real_duration() {
+        let mut time = TimeController::default();
+        time.hit_stop(0.1, 0.5);
+        time.hit_stop(0.05, 0.1);
+        assert_eq!(time.get_scale(), 0.1);
+
+        // 0.05 s at a tenth, then 0.05 s at half and 0.1 s at normal speed
+        let scaled = time.advance(0.2);
+        assert!((scaled - (0.005 + 0.025 + 0.1)).abs() < 1e-6);
+        assert_eq!(time.get_scale(), 1.0);
+
+        // The base scale applies on top
+        time.set_base_scale(0.5);
+        time.hit_stop(1.0, 0.5);
+        assert_eq!(time.get_scale(), 0.25);
+    }
+}
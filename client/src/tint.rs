@@ -0,0 +1,307 @@
+use shared::math::*;
+
+// Color effects layered on top of a renderable's base color. Fades and pulses are
+// multiplicative, flashes are additive and applied after them, so a white flash still
+// shows up on a faded or tinted entity:
+//   color = base * multiplier + vec4(additive, 0)
+// Effects live in fixed slots and free them when they complete.
+
+const MAX_EFFECTS: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+enum TintEffect {
+    Flash {
+        color: Vec4, // The alpha scales the flash strength
+        duration: f32,
+        elapsed: f32,
+    },
+    Fade {
+        from: f32,
+        to: f32,
+        duration: f32,
+        elapsed: f32,
+    },
+    Pulse {
+        color: Vec3,
+        frequency: f32,
+        elapsed: f32,
+    },
+}
+
+impl TintEffect {
+    fn get_remaining(&self) -> f32 {
+        match *self {
+            Self::Flash {
+                duration, elapsed, ..
+            }
+            | Self::Fade {
+                duration, elapsed, ..
+            } => duration - elapsed,
+            Self::Pulse { .. } => f32::INFINITY,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TintAnimator {
+    effects: [Option<TintEffect>; MAX_EFFECTS],
+    alpha: f32, // Where the last completed fade ended
+    multiplier: Vec4,
+    additive: Vec3,
+}
+
+impl Default for TintAnimator {
+    fn default() -> Self {
+        Self {
+            effects: [None; MAX_EFFECTS],
+            alpha: 1.0,
+            multiplier: Vec4::ONE,
+            additive: Vec3::ZERO,
+        }
+    }
+}
+
+impl TintAnimator {
+    // Fades the flash color out linearly over the duration
+    pub fn flash(&mut self, color: Vec4, duration: f32) {
+        self.add_effect(TintEffect::Flash {
+            color,
+            duration,
+            elapsed: 0.0,
+        });
+    }
+
+    // Starts from the current alpha, so it can interrupt a running fade without a pop.
+    // The alpha stays at the target once the fade is done.
+    #[allow(dead_code)]
+    pub fn fade_to(&mut self, alpha: f32, duration: f32) {
+        let from = self.get_current_alpha();
+        for slot in self.effects.iter_mut() {
+            if let Some(TintEffect::Fade { .. }) = slot {
+                *slot = None;
+            }
+        }
+
+        self.add_effect(TintEffect::Fade {
+            from,
+            to: alpha,
+            duration,
+            elapsed: 0.0,
+        });
+    }
+
+    // Oscillates between the base color and the base color tinted by color until cleared
+    #[allow(dead_code)]
+    pub fn pulse(&mut self, color: Vec3, frequency: f32) {
+        self.add_effect(TintEffect::Pulse {
+            color,
+            frequency,
+            elapsed: 0.0,
+        });
+    }
+
+    #[allow(dead_code)]
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        let mut multiplier = Vec4::ONE;
+        let mut additive = Vec3::ZERO;
+        let mut alpha = self.alpha;
+
+        for slot in self.effects.iter_mut() {
+            let Some(effect) = slot else {
+                continue;
+            };
+
+            match effect {
+                TintEffect::Flash {
+                    color,
+                    duration,
+                    elapsed,
+                } => {
+                    *elapsed += dt;
+                    if *elapsed >= *duration {
+                        *slot = None;
+                        continue;
+                    }
+
+                    let strength = 1.0 - *elapsed / *duration;
+                    additive += color.truncate() * color.w * strength;
+                }
+                TintEffect::Fade {
+                    from,
+                    to,
+                    duration,
+                    elapsed,
+                } => {
+                    *elapsed += dt;
+                    if *elapsed >= *duration {
+                        self.alpha = *to;
+                        alpha = *to;
+                        *slot = None;
+                        continue;
+                    }
+
+                    alpha = *from + (*to - *from) * (*elapsed / *duration);
+                }
+                TintEffect::Pulse {
+                    color,
+                    frequency,
+                    elapsed,
+                } => {
+                    *elapsed += dt;
+
+                    // Starts at the base color, fully tinted half a period in
+                    let weight = 0.5 - 0.5 * (std::f32::consts::TAU * *frequency * *elapsed).cos();
+                    multiplier *= Vec3::ONE.lerp(*color, weight).extend(1.0);
+                }
+            }
+        }
+
+        multiplier.w *= alpha;
+        self.multiplier = multiplier;
+        self.additive = additive;
+    }
+
+    pub fn apply(&self, base_color: Vec4) -> Vec4 {
+        base_color * self.multiplier + self.additive.extend(0.0)
+    }
+
+    #[allow(dead_code)]
+    pub fn is_animating(&self) -> bool {
+        self.effects.iter().any(Option::is_some)
+    }
+
+    fn get_current_alpha(&self) -> f32 {
+        for effect in self.effects.iter().flatten() {
+            if let TintEffect::Fade {
+                from,
+                to,
+                duration,
+                elapsed,
+            } = *effect
+            {
+                return from + (to - from) * (elapsed / duration).min(1.0);
+            }
+        }
+
+        self.alpha
+    }
+
+    // Takes a free slot, or replaces the effect closest to finishing when all are taken
+    fn add_effect(&mut self, effect: TintEffect) {
+        let slot = match self.effects.iter().position(Option::is_none) {
+            Some(free_index) => free_index,
+            None => self
+                .effects
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| {
+                    let a = a.as_ref().map_or(0.0, TintEffect::get_remaining);
+                    let b = b.as_ref().map_or(0.0, TintEffect::get_remaining);
+                    a.total_cmp(&b)
+                })
+                .map(|(index, _)| index)
+                .unwrap_or(0),
+        };
+
+        self.effects[slot] = Some(effect);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec4, b: Vec4) {
+        assert!(a.abs_diff_eq(b, 1e-4), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn flash_decays_linearly_and_frees_its_slot() {
+        let mut tint = TintAnimator::default();
+        tint.flash(Vec4::ONE, 1.0);
+
+        tint.update(0.25);
+        assert_close(
+            tint.apply(Vec4::ZERO.with_w(1.0)),
+            Vec4::new(0.75, 0.75, 0.75, 1.0),
+        );
+
+        tint.update(0.5);
+        assert_close(
+            tint.apply(Vec4::ZERO.with_w(1.0)),
+            Vec4::new(0.25, 0.25, 0.25, 1.0),
+        );
+
+        tint.update(0.5);
+        assert!(!tint.is_animating());
+        assert_close(tint.apply(Vec4::splat(0.5)), Vec4::splat(0.5));
+    }
+
+    #[test]
+    fn fade_holds_its_target_alpha() {
+        let mut tint = TintAnimator::default();
+        tint.fade_to(0.0, 0.5);
+
+        tint.update(0.125);
+        assert_close(tint.apply(Vec4::ONE), Vec4::new(1.0, 1.0, 1.0, 0.75));
+
+        tint.update(0.5);
+        assert!(!tint.is_animating());
+        assert_close(tint.apply(Vec4::ONE), Vec4::new(1.0, 1.0, 1.0, 0.0));
+
+        // A new fade starts where the last one ended
+        tint.fade_to(1.0, 1.0);
+        tint.update(0.5);
+        assert_close(tint.apply(Vec4::ONE), Vec4::new(1.0, 1.0, 1.0, 0.5));
+    }
+
+    #[test]
+    fn pulse_follows_a_cosine() {
+        let mut tint = TintAnimator::default();
+        tint.pulse(Vec3::new(1.0, 0.0, 0.0), 1.0);
+
+        tint.update(0.0);
+        assert_close(tint.apply(Vec4::ONE), Vec4::ONE);
+
+        tint.update(0.25);
+        assert_close(tint.apply(Vec4::ONE), Vec4::new(1.0, 0.5, 0.5, 1.0));
+
+        tint.update(0.25);
+        assert_close(tint.apply(Vec4::ONE), Vec4::new(1.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn flash_is_added_after_the_multiplicative_effects() {
+        let mut tint = TintAnimator::default();
+        tint.pulse(Vec3::ZERO, 0.5);
+        tint.fade_to(0.5, 2.0);
+        tint.flash(Vec4::new(1.0, 1.0, 1.0, 0.5), 2.0);
+
+        // Half a pulse period, so the pulse is fully black
+        tint.update(1.0);
+        let color = tint.apply(Vec4::new(0.8, 0.6, 0.4, 1.0));
+        assert_close(color, Vec4::new(0.25, 0.25, 0.25, 0.75));
+    }
+
+    #[test]
+    fn full_slots_replace_the_effect_closest_to_finishing() {
+        let mut tint = TintAnimator::default();
+        tint.pulse(Vec3::ZERO, 1.0);
+        tint.flash(Vec4::ONE, 3.0);
+        tint.flash(Vec4::ONE, 1.0);
+        tint.flash(Vec4::ONE, 2.0);
+        tint.flash(Vec4::new(1.0, 0.0, 0.0, 1.0), 4.0);
+
+        let remaining: Vec<f32> = tint
+            .effects
+            .iter()
+            .flatten()
+            .map(TintEffect::get_remaining)
+            .collect();
+        assert_eq!(remaining, vec![f32::INFINITY, 3.0, 4.0, 2.0]);
+    }
+}
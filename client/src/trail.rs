@@ -0,0 +1,515 @@
+use std::collections::VecDeque;
+
+use shared::math::*;
+
+use crate::{
+    components::Entity,
+    renderer::{Renderer, StaticMeshVertex, StaticRenderJob, resources::get_handle},
+};
+
+pub const TRAIL_MESH: &str = "TrailMesh";
+pub const TRAIL_MATERIAL: &str = "TrailMaterial";
+pub const PROJECTILE_TRAIL_MESH: &str = "ProjectileTrailMesh";
+pub const PROJECTILE_TRAIL_MATERIAL: &str = "ProjectileTrailMaterial";
+
+const POINT_SPACING: f32 = 12.0; // How far the entity moves before the next point
+const POINT_LIFETIME: f32 = 0.5;
+const MAX_POINTS: usize = 64;
+const TRAIL_WIDTH: f32 = 36.0; // At the head, it narrows down to nothing at the tail
+const TRAIL_HEIGHT: f32 = 1.5; // Below the selection rings
+const HEAD_COLOR: Vec4 = Vec4::new(0.5, 0.85, 1.0, 1.0);
+const TAIL_COLOR: Vec4 = Vec4::new(0.1, 0.25, 0.5, 1.0);
+
+struct TrailPoint {
+    position: Vec3,
+    age: f32,
+}
+
+// A ribbon on the ground behind a moving entity. The points are dropped as it moves and
+// age out, the geometry is rebuilt into a dynamic mesh every frame.
+#[derive(Default)]
+pub struct Trail {
+    points: VecDeque<TrailPoint>, // Oldest first
+    head: Option<Vec3>,           // Where the entity is now
+}
+
+impl Trail {
+    pub fn update(&mut self, dt: f32, position: Option<Vec3>) {
+        for point in self.points.iter_mut() {
+            point.age += dt;
+        }
+        self.points.retain(|point| point.age < POINT_LIFETIME);
+
+        self.head = position;
+        let Some(position) = position else {
+            self.points.clear();
+            return;
+        };
+        if self
+            .points
+            .back()
+            .is_none_or(|point| point.position.distance(position) >= POINT_SPACING)
+        {
+            if self.points.len() >= MAX_POINTS {
+                self.points.pop_front();
+            }
+            self.points.push_back(TrailPoint { position, age: 0.0 });
+        }
+    }
+
+    // Two vertices across the trail for every point, in world space, facing up
+    pub fn get_geometry(&self) -> (Vec<StaticMeshVertex>, Vec<u32>) {
+        let mut points: Vec<(Vec3, f32)> = self
+            .points
+            .iter()
+            .map(|point| (point.position, point.age))
+            .collect();
+        if let Some(head) = self.head
+            && points.last().is_none_or(|(position, _)| *position != head)
+        {
+            points.push((head, 0.0));
+        }
+        if points.len() < 2 {
+            return (Vec::new(), Vec::new());
+        }
+
+        let mut vertices = Vec::with_capacity(points.len() * 2);
+        let mut side = Vec3::ZERO;
+        for (index, (position, age)) in points.iter().enumerate() {
+            let previous = points[index.saturating_sub(1)].0;
+            let next = points[(index + 1).min(points.len() - 1)].0;
+            let direction = (next - previous).with_y(0.0);
+            // Keeps the last side when the points are on top of each other
+            if direction.length_squared() > 1e-6 {
+                side = Vec3::new(-direction.z, 0.0, direction.x).normalize();
+            }
+
+            let life = 1.0 - age / POINT_LIFETIME;
+            let half_width = TRAIL_WIDTH * 0.5 * life;
+            let color = TAIL_COLOR.lerp(HEAD_COLOR, life);
+            let v = index as f32 / (points.len() - 1) as f32;
+            for (offset, u) in [(-half_width, 0.0), (half_width, 1.0)] {
+                vertices.push(StaticMeshVertex {
+                    position: (position.with_y(TRAIL_HEIGHT) + side * offset).into(),
+                    normal: [0.0, 1.0, 0.0],
+                    uvs: [u, v, 0.0],
+                    color: color.into(),
+                });
+            }
+        }
+
+        // Counter-clockwise seen from above
+        let mut indices = Vec::with_capacity((points.len() - 1) * 6);
+        for segment in 0..points.len() as u32 - 1 {
+            let a = segment * 2;
+            indices.extend([a, a + 1, a + 2, a + 2, a + 1, a + 3]);
+        }
+
+        (vertices, indices)
+    }
+
+    pub fn render(&self, renderer: &mut Renderer) {
+        let (vertices, indices) = self.get_geometry();
+        let mesh = get_handle(TRAIL_MESH);
+        renderer.update_dynamic_mesh(mesh, &vertices, &indices);
+        if indices.is_empty() {
+            return;
+        }
+
+        renderer.submit(&StaticRenderJob {
+            material: get_handle(TRAIL_MATERIAL),
+            mesh,
+            casts_shadow: false,
+            ..Default::default()
+        });
+    }
+}
+
+// A ribbon following a projectile through the air, turned to the camera and added to the
+// scene. The samples are kept in a fixed ring, and every trail is written into one shared
+// dynamic mesh, so hundreds of them cost one draw and no allocations once warmed up.
+// A trail is an entity of its own: when its owner is gone it stops growing and lingers
+// until the last sample has faded.
+const RIBBON_CAPACITY: usize = 24;
+const RIBBON_SPACING: f32 = 10.0; // How far the owner moves before the next sample
+const RIBBON_LIFETIME: f32 = 0.3;
+const RIBBON_WIDTH: f32 = 14.0; // At the head, it narrows down to nothing at the tail
+// Farther in one update is a teleport, the strip is broken instead of stretched across
+const RIBBON_TELEPORT_DISTANCE: f32 = 300.0;
+const RIBBON_COLOR: Vec4 = Vec4::new(1.0, 0.75, 0.35, 0.9);
+
+#[derive(Clone, Copy, Default)]
+struct RibbonSample {
+    position: Vec3,
+    age: f32,
+    starts_strip: bool, // Not joined to the sample before it
+}
+
+pub struct TrailRenderer {
+    pub owner: Option<Entity>, // None once the owner is gone
+    samples: [RibbonSample; RIBBON_CAPACITY],
+    first: usize, // Of the oldest sample in the ring
+    count: usize,
+    head: Option<Vec3>, // Where the owner is now
+    pub width: f32,
+    pub color: Vec4,
+}
+
+impl TrailRenderer {
+    pub fn new(owner: Entity) -> Self {
+        Self {
+            owner: Some(owner),
+            samples: [RibbonSample::default(); RIBBON_CAPACITY],
+            first: 0,
+            count: 0,
+            head: None,
+            width: RIBBON_WIDTH,
+            color: RIBBON_COLOR,
+        }
+    }
+
+    // None when the owner is gone, the samples keep aging out
+    pub fn update(&mut self, dt: f32, position: Option<Vec3>) {
+        for index in 0..self.count {
+            self.get_sample_mut(index).age += dt;
+        }
+        while self.count > 0 && self.get_sample(0).age >= RIBBON_LIFETIME {
+            self.first = (self.first + 1) % RIBBON_CAPACITY;
+            self.count -= 1;
+        }
+
+        let Some(position) = position else {
+            self.owner = None;
+            self.head = None;
+            return;
+        };
+        let teleported = self
+            .head
+            .is_some_and(|head| head.distance(position) > RIBBON_TELEPORT_DISTANCE);
+        self.head = Some(position);
+
+        let last = self
+            .count
+            .checked_sub(1)
+            .map(|index| self.get_sample(index));
+        if teleported || last.is_none_or(|last| last.position.distance(position) >= RIBBON_SPACING)
+        {
+            if self.count == RIBBON_CAPACITY {
+                self.first = (self.first + 1) % RIBBON_CAPACITY;
+                self.count -= 1;
+            }
+            let index = (self.first + self.count) % RIBBON_CAPACITY;
+            self.samples[index] = RibbonSample {
+                position,
+                age: 0.0,
+                starts_strip: teleported,
+            };
+            self.count += 1;
+        }
+    }
+
+    // Owner gone and faded out, the trail entity can go
+    pub fn is_finished(&self) -> bool {
+        self.owner.is_none() && self.count == 0
+    }
+
+    fn get_sample(&self, index: usize) -> RibbonSample {
+        self.samples[(self.first + index) % RIBBON_CAPACITY]
+    }
+
+    fn get_sample_mut(&mut self, index: usize) -> &mut RibbonSample {
+        &mut self.samples[(self.first + index) % RIBBON_CAPACITY]
+    }
+
+    // The samples oldest first, then the owner when it moved on from the last one
+    fn get_point(&self, index: usize) -> RibbonSample {
+        if index < self.count {
+            return self.get_sample(index);
+        }
+        RibbonSample {
+            position: self.head.unwrap_or_default(),
+            age: 0.0,
+            starts_strip: false,
+        }
+    }
+
+    fn get_point_count(&self) -> usize {
+        let head = self
+            .head
+            .filter(|head| self.count > 0 && self.get_sample(self.count - 1).position != *head);
+        self.count + head.is_some() as usize
+    }
+
+    // Appends two vertices across the ribbon for every point, in world space and facing the
+    // camera. u goes across, v is the age from the head (0) to the end of the lifetime (1).
+    // Segments of no length keep the side of their neighbours, a ribbon without any length
+    // adds nothing.
+    pub fn build_ribbon(
+        &self,
+        camera_position: Vec3,
+        vertices: &mut Vec<StaticMeshVertex>,
+        indices: &mut Vec<u32>,
+    ) {
+        let count = self.get_point_count();
+        if count < 2 {
+            return;
+        }
+
+        // The side of the first segment that has one, for the points before it
+        let get_side = |index: usize| -> Option<Vec3> {
+            let previous = self.get_point(index.saturating_sub(1));
+            let next = self.get_point((index + 1).min(count - 1));
+            let position = self.get_point(index).position;
+            let direction = next.position - previous.position;
+            let side = direction.cross(camera_position - position);
+            (side.length_squared() > 1e-6).then(|| side.normalize())
+        };
+        let Some(mut side) = (0..count).find_map(get_side) else {
+            return;
+        };
+
+        let base = vertices.len() as u32;
+        for index in 0..count {
+            let point = self.get_point(index);
+            if let Some(point_side) = get_side(index) {
+                side = point_side;
+            }
+
+            let life = (1.0 - point.age / RIBBON_LIFETIME).clamp(0.0, 1.0);
+            let half_width = self.width * 0.5 * life;
+            let color = self.color.with_w(self.color.w * life);
+            let v = 1.0 - life;
+            for (offset, u) in [(-half_width, 0.0), (half_width, 1.0)] {
+                vertices.push(StaticMeshVertex {
+                    position: (point.position + side * offset).into(),
+                    normal: (camera_position - point.position)
+                        .normalize_or_zero()
+                        .into(),
+                    uvs: [u, v, 0.0],
+                    color: color.into(),
+                });
+            }
+
+            // Joins the point to the one before it, unless the strip was broken there
+            if index > 0 && !point.starts_strip {
+                let a = base + (index as u32 - 1) * 2;
+                indices.extend([a, a + 1, a + 2, a + 2, a + 1, a + 3]);
+            }
+        }
+    }
+}
+
+// Reused every frame, so the vectors only grow to the most trails seen at once
+#[derive(Default)]
+pub struct TrailBatch {
+    vertices: Vec<StaticMeshVertex>,
+    indices: Vec<u32>,
+}
+
+impl TrailBatch {
+    pub fn build<'a>(
+        &mut self,
+        trails: impl Iterator<Item = &'a TrailRenderer>,
+        camera_position: Vec3,
+    ) {
+        self.vertices.clear();
+        self.indices.clear();
+        for trail in trails {
+            trail.build_ribbon(camera_position, &mut self.vertices, &mut self.indices);
+        }
+    }
+
+    pub fn render(&self, renderer: &mut Renderer) {
+        let mesh = get_handle(PROJECTILE_TRAIL_MESH);
+        renderer.update_dynamic_mesh(mesh, &self.vertices, &self.indices);
+        if self.indices.is_empty() {
+            return;
+        }
+
+        renderer.submit(&StaticRenderJob {
+            material: get_handle(PROJECTILE_TRAIL_MATERIAL),
+            mesh,
+            casts_shadow: false,
+            ..Default::default()
+        });
+    }
+}
+
+pub fn create_trail_resources(renderer: &mut Renderer) {
+    renderer.create_dynamic_mesh(TRAIL_MESH, MAX_POINTS * 2);
+    renderer.create_dynamic_mesh(PROJECTILE_TRAIL_MESH, 64 * RIBBON_CAPACITY * 2);
+
+    let mut bytes = Vec::new();
+    for value in [1u32, 1, 1, 4, 1, 1] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&[255; 4]);
+    let texture = renderer.load_texture(TRAIL_MATERIAL, &bytes);
+    renderer.create_material(TRAIL_MATERIAL, texture);
+    renderer.create_additive_material(PROJECTILE_TRAIL_MATERIAL, texture);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trail_faces_up_and_narrows_to_the_tail() {
+        let mut trail = Trail::default();
+        for step in 0..10 {
+            trail.update(
+                0.02,
+                Some(Vec3::new(step as f32 * 20.0, 0.0, step as f32 * 5.0)),
+            );
+        }
+        let (vertices, indices) = trail.get_geometry();
+        assert_eq!(vertices.len(), 20);
+        assert_eq!(indices.len(), 9 * 6);
+
+        let position = |index: u32| Vec3::from(vertices[index as usize].position);
+        for triangle in indices.chunks(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(position);
+            assert!((b - a).cross(c - a).y > 0.0);
+        }
+
+        let width = |point: u32| position(point * 2).distance(position(point * 2 + 1));
+        assert!(width(0) < width(5));
+        assert!((width(9) - TRAIL_WIDTH).abs() < 1e-3);
+    }
+
+    fn get_owner() -> Entity {
+        crate::components::Entities::default().spawn()
+    }
+
+    fn get_ribbon(trail: &TrailRenderer, camera: Vec3) -> (Vec<StaticMeshVertex>, Vec<u32>) {
+        let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+        trail.build_ribbon(camera, &mut vertices, &mut indices);
+        (vertices, indices)
+    }
+
+    #[test]
+    fn ribbons_face_the_camera_and_taper_to_the_tail() {
+        let mut trail = TrailRenderer::new(get_owner());
+        for step in 0..10 {
+            trail.update(0.02, Some(Vec3::new(step as f32 * 20.0, 50.0, 0.0)));
+        }
+        // From straight above, the ribbon lies across z
+        let camera = Vec3::new(90.0, 1000.0, 0.0);
+        let (vertices, indices) = get_ribbon(&trail, camera);
+        assert_eq!(vertices.len(), 20);
+        assert_eq!(indices.len(), 9 * 6);
+
+        let position = |index: usize| Vec3::from(vertices[index].position);
+        for point in 0..10 {
+            let across = position(point * 2 + 1) - position(point * 2);
+            assert!(across.x.abs() < 1e-3 && across.y.abs() < 1e-3);
+            assert_eq!(vertices[point * 2].uvs[0], 0.0);
+            assert_eq!(vertices[point * 2 + 1].uvs[0], 1.0);
+        }
+
+        let width = |point: usize| position(point * 2).distance(position(point * 2 + 1));
+        assert!((width(9) - RIBBON_WIDTH).abs() < 1e-3);
+        assert!(width(0) < width(5) && width(5) < width(9));
+        let alpha = |point: usize| vertices[point * 2].color[3];
+        assert!(alpha(0) < alpha(9));
+        assert_eq!(vertices[18].uvs[1], 0.0);
+        assert!((vertices[0].uvs[1] - 0.18 / RIBBON_LIFETIME).abs() < 1e-4);
+    }
+
+    #[test]
+    fn teleports_break_the_strip_and_zero_length_segments_keep_their_side() {
+        let mut trail = TrailRenderer::new(get_owner());
+        let camera = Vec3::new(0.0, 1000.0, 0.0);
+        for x in [0.0, 20.0, 40.0, 1000.0, 1020.0] {
+            trail.update(0.01, Some(Vec3::new(x, 0.0, 0.0)));
+        }
+        let (vertices, indices) = get_ribbon(&trail, camera);
+        assert_eq!(vertices.len(), 10);
+        // Nothing between the third and the fourth point
+        assert_eq!(indices.len(), 3 * 6);
+        assert!(indices.chunks(6).all(|quad| quad[0] != 4));
+
+        // Points on top of each other take the side of the segment after them
+        let mut trail = TrailRenderer::new(get_owner());
+        for position in [Vec3::ZERO, Vec3::ZERO, Vec3::ZERO, Vec3::X * 30.0] {
+            trail.samples[trail.count] = RibbonSample {
+                position,
+                ..Default::default()
+            };
+            trail.count += 1;
+        }
+        let (vertices, indices) = get_ribbon(&trail, camera);
+        assert_eq!(indices.len(), 3 * 6);
+        for point in 0..4 {
+            let across = Vec3::from(vertices[point * 2 + 1].position)
+                - Vec3::from(vertices[point * 2].position);
+            assert!((across.length() - RIBBON_WIDTH).abs() < 1e-3);
+            assert!(across.x.abs() < 1e-3);
+        }
+
+        // A ribbon with no length at all adds nothing
+        trail.samples[3].position = Vec3::ZERO;
+        let (vertices, indices) = get_ribbon(&trail, camera);
+        assert!(vertices.is_empty() && indices.is_empty());
+    }
+
+    #[test]
+    fn trails_outlive_their_owner_until_faded() {
+        let mut trail = TrailRenderer::new(get_owner());
+        trail.update(0.05, Some(Vec3::ZERO));
+        trail.update(0.05, Some(Vec3::X * 50.0));
+        trail.update(0.05, None);
+        assert!(trail.owner.is_none());
+        assert!(!trail.is_finished());
+        assert_eq!(get_ribbon(&trail, Vec3::Y * 1000.0).1.len(), 6);
+
+        trail.update(RIBBON_LIFETIME, None);
+        assert!(trail.is_finished());
+    }
+
+    #[test]
+    fn hundreds_of_trails_stop_allocating_once_warmed_up() {
+        const TRAIL_COUNT: usize = 300;
+        let owner = get_owner();
+        let mut trails: Vec<TrailRenderer> = (0..TRAIL_COUNT)
+            .map(|_| TrailRenderer::new(owner))
+            .collect();
+        let mut batch = TrailBatch::default();
+        let camera = Vec3::new(0.0, 1000.0, 500.0);
+
+        let mut capacities = (0, 0);
+        for frame in 0..240 {
+            for (index, trail) in trails.iter_mut().enumerate() {
+                let angle = frame as f32 * 0.05 + index as f32;
+                let position = Vec3::new(angle.cos(), 0.1, angle.sin()) * (100.0 + index as f32);
+                trail.update(1.0 / 60.0, Some(position));
+            }
+            batch.build(trails.iter(), camera);
+            assert!(batch.vertices.len() <= TRAIL_COUNT * (RIBBON_CAPACITY + 1) * 2);
+            if frame == 120 {
+                capacities = (batch.vertices.capacity(), batch.indices.capacity());
+            }
+        }
+        assert!(!batch.indices.is_empty());
+        assert_eq!(
+            (batch.vertices.capacity(), batch.indices.capacity()),
+            capacities
+        );
+    }
+
+    #[test]
+    fn standing_still_lets_the_trail_run_out() {
+        let mut trail = Trail::default();
+        trail.update(0.1, Some(Vec3::ZERO));
+        trail.update(0.1, Some(Vec3::X * 50.0));
+        assert_eq!(trail.get_geometry().1.len(), 6);
+
+        for _ in 0..10 {
+            trail.update(0.1, Some(Vec3::X * 50.0));
+        }
+        assert!(trail.get_geometry().0.is_empty());
+
+        trail.update(0.1, None);
+        assert!(trail.points.is_empty());
+    }
+}
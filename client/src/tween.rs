@@ -0,0 +1,227 @@
+// Values eased over time for presentation, e.g. a health bar draining after a hit. The
+// tweens only compute the values, the game writes them to what they target. Starting a
+// tween on a target that already has one replaces it, so a bar hit twice in a row follows
+// the latest value instead of two tweens fighting over it.
+
+use shared::math::Easing;
+
+use crate::{
+    components::Entity,
+    events::{GameEvent, GameEvents},
+};
+
+const MAX_TWEENS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TweenField {
+    HealthBarFill,
+    HealthBarFlash,
+    DeathSettle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TweenTarget {
+    Entity(Entity, TweenField),
+    KillFeedSlide, // Of the newest line
+    TooltipFade,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TweenDesc {
+    pub from: f32,
+    pub to: f32,
+    pub duration: f32,
+    pub delay: f32, // Before the first run, the value isn't touched until it is over
+    pub easing: Easing,
+    pub repeat: u32, // Runs after the first one
+    pub yoyo: bool,  // Every other run goes back from the end to the start
+}
+
+// The constructor fills in the values, the rest can be set with struct update syntax
+impl TweenDesc {
+    pub fn new(from: f32, to: f32, duration: f32, easing: Easing) -> Self {
+        Self {
+            from,
+            to,
+            duration,
+            delay: 0.0,
+            easing,
+            repeat: 0,
+            yoyo: false,
+        }
+    }
+
+    // The value the tween ends on, after all its runs
+    fn get_end(&self) -> f32 {
+        if self.yoyo && self.repeat % 2 == 1 {
+            self.from
+        } else {
+            self.to
+        }
+    }
+}
+
+struct Tween {
+    target: TweenTarget,
+    desc: TweenDesc,
+    elapsed: f32, // Including the delay
+}
+
+impl Tween {
+    // None during the delay
+    fn get_value(&self) -> Option<f32> {
+        let time = self.elapsed - self.desc.delay;
+        if time < 0.0 {
+            return None;
+        }
+        if self.desc.duration <= 0.0 {
+            return Some(self.desc.get_end());
+        }
+
+        let run = ((time / self.desc.duration) as u32).min(self.desc.repeat);
+        let progress = (time - run as f32 * self.desc.duration) / self.desc.duration;
+        let (from, to) = if self.desc.yoyo && run % 2 == 1 {
+            (self.desc.to, self.desc.from)
+        } else {
+            (self.desc.from, self.desc.to)
+        };
+        let t = self.desc.easing.apply(progress);
+        Some(from + (to - from) * t)
+    }
+
+    fn is_finished(&self) -> bool {
+        let total = self.desc.delay + self.desc.duration * (self.desc.repeat + 1) as f32;
+        self.elapsed >= total
+    }
+}
+
+#[derive(Default)]
+pub struct Tweens {
+    tweens: Vec<Tween>, // Oldest first
+}
+
+impl Tweens {
+    // When too many are running the oldest one is dropped where it is, without finishing
+    pub fn start(&mut self, target: TweenTarget, desc: TweenDesc) {
+        self.stop(target);
+        if self.tweens.len() >= MAX_TWEENS {
+            self.tweens.remove(0);
+        }
+        self.tweens.push(Tween {
+            target,
+            desc,
+            elapsed: 0.0,
+        });
+    }
+
+    // Leaves the target at the value it has now
+    pub fn stop(&mut self, target: TweenTarget) {
+        self.tweens.retain(|tween| tween.target != target);
+    }
+
+    // The tweens of despawned entities
+    pub fn stop_entity(&mut self, entity: Entity) {
+        self.tweens.retain(
+            |tween| !matches!(tween.target, TweenTarget::Entity(tweened, _) if tweened == entity),
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.tweens.clear();
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.tweens.len()
+    }
+
+    // Hands every running tween's value to apply. Finished tweens get their end value and a
+    // TweenFinished event, then they are removed.
+    pub fn advance(
+        &mut self,
+        dt: f32,
+        events: &mut GameEvents,
+        mut apply: impl FnMut(TweenTarget, f32),
+    ) {
+        for tween in self.tweens.iter_mut() {
+            tween.elapsed += dt;
+            if tween.is_finished() {
+                apply(tween.target, tween.desc.get_end());
+                events.push(GameEvent::TweenFinished {
+                    target: tween.target,
+                });
+            } else if let Some(value) = tween.get_value() {
+                apply(tween.target, value);
+            }
+        }
+        self.tweens.retain(|tween| !tween.is_finished());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The values applied by one advance
+    fn advance(tweens: &mut Tweens, dt: f32, events: &mut GameEvents) -> Vec<(TweenTarget, f32)> {
+        let mut values = Vec::new();
+        tweens.advance(dt, events, |target, value| values.push((target, value)));
+        values
+    }
+
+    #[test]
+    fn delayed_yoyo_runs_end_where_they_started() {
+        let mut tweens = Tweens::default();
+        let mut events = GameEvents::default();
+        let target = TweenTarget::KillFeedSlide;
+        tweens.start(
+            target,
+            TweenDesc {
+                delay: 0.5,
+                repeat: 1,
+                yoyo: true,
+                ..TweenDesc::new(0.0, 10.0, 1.0, Easing::Linear)
+            },
+        );
+
+        assert!(advance(&mut tweens, 0.25, &mut events).is_empty());
+        assert_eq!(advance(&mut tweens, 0.5, &mut events), [(target, 2.5)]);
+        // On the way back
+        assert_eq!(advance(&mut tweens, 1.0, &mut events), [(target, 7.5)]);
+        assert!(events.drain().is_empty());
+
+        assert_eq!(advance(&mut tweens, 1.0, &mut events), [(target, 0.0)]);
+        assert_eq!(events.drain(), [GameEvent::TweenFinished { target }]);
+        assert_eq!(tweens.len(), 0);
+    }
+
+    #[test]
+    fn restarting_a_target_replaces_it_and_the_oldest_is_evicted() {
+        let mut entities = crate::components::Entities::default();
+        let mut tweens = Tweens::default();
+        let mut events = GameEvents::default();
+
+        let entity = entities.spawn();
+        let fill = TweenTarget::Entity(entity, TweenField::HealthBarFill);
+        tweens.start(fill, TweenDesc::new(1.0, 0.8, 1.0, Easing::Linear));
+        tweens.start(fill, TweenDesc::new(0.9, 0.5, 1.0, Easing::Linear));
+        assert_eq!(tweens.len(), 1);
+        assert_eq!(advance(&mut tweens, 0.5, &mut events), [(fill, 0.7)]);
+
+        let others: Vec<Entity> = (0..MAX_TWEENS).map(|_| entities.spawn()).collect();
+        for &other in &others {
+            tweens.start(
+                TweenTarget::Entity(other, TweenField::HealthBarFlash),
+                TweenDesc::new(0.0, 1.0, 1.0, Easing::Linear),
+            );
+        }
+        assert_eq!(tweens.len(), MAX_TWEENS);
+        let values = advance(&mut tweens, 0.1, &mut events);
+        assert!(values.iter().all(|(target, _)| *target != fill));
+
+        // Despawning drops the entity's tweens without finishing them
+        tweens.stop_entity(others[0]);
+        assert_eq!(tweens.len(), MAX_TWEENS - 1);
+        assert!(events.drain().is_empty());
+    }
+}
@@ -0,0 +1,838 @@
+// A retained UI tree. The game builds the nodes once and changes them when something
+// happens, the UiRenderer walks the tree every frame and submits the sprite and text jobs.
+// Nodes are laid out in reference space, relative to an anchor point of their parent, and
+// drawn with SpriteSpace::Reference and the anchor of the root node. A tree anchored to a
+// screen corner stays in that corner whatever the aspect ratio.
+
+use shared::math::*;
+
+use crate::{
+    renderer::{
+        Renderer, ResourceHandle, SpriteAnchor, SpriteRegion, SpriteSpace, TextAlignment,
+        render_data::{SpriteRenderJob, TextRenderJob},
+        resources::get_handle,
+        safe_area::{UiViewport, get_anchor_factor},
+    },
+    tween::{TweenDesc, TweenTarget, Tweens},
+};
+
+// The highest sprite layer the batch keys hold is kept for the tooltip text, its panel
+// goes one below. Nothing else should draw on them.
+pub const TOOLTIP_LAYER: u32 = u16::MAX as u32 - 1;
+const TOOLTIP_DELAY: f32 = 0.3; // Of hovering before the tooltip fades in
+const TOOLTIP_FADE_TIME: f32 = 0.15;
+const TOOLTIP_GAP: f32 = 6.0; // Between the tooltip and what it is about
+const TOOLTIP_PADDING: f32 = 8.0;
+const TOOLTIP_TITLE_SIZE: f32 = 18.0;
+const TOOLTIP_BODY_SIZE: f32 = 15.0;
+const TOOLTIP_LINE_SPACING: f32 = 1.25;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct UiRect {
+    pub position: Vec2, // Top left
+    pub size: Vec2,
+}
+
+#[allow(dead_code)]
+impl UiRect {
+    pub fn get_point(&self, anchor: SpriteAnchor) -> Vec2 {
+        self.position + self.size * get_anchor_factor(anchor)
+    }
+
+    pub fn contains(&self, point: Vec2) -> bool {
+        point.cmpge(self.position).all() && point.cmplt(self.get_end()).all()
+    }
+
+    pub fn get_end(&self) -> Vec2 {
+        self.position + self.size
+    }
+
+    fn shrink(&self, padding: Vec2) -> UiRect {
+        UiRect {
+            position: self.position + padding,
+            size: (self.size - 2.0 * padding).max(Vec2::ZERO),
+        }
+    }
+
+    // The shadow, the fill and the border of a panel over the rect, in the order they are
+    // drawn. They share the white material, so in one layer they are drawn in that order.
+    // The space and anchor can be set on each with struct update syntax.
+    pub fn get_panel_jobs(&self, style: &PanelStyle, layer: u32) -> Vec<SpriteRenderJob> {
+        let mut jobs = Vec::with_capacity(3);
+        if style.shadow_color.w > 0.0 {
+            jobs.push(
+                SpriteRenderJob::solid(
+                    self.position + style.shadow_offset,
+                    self.size,
+                    style.shadow_color,
+                    layer,
+                )
+                .shadow(style.corner_radius, style.shadow_softness),
+            );
+        }
+        jobs.push(
+            SpriteRenderJob::solid(self.position, self.size, style.color, layer)
+                .rounded(style.corner_radius, 0.0),
+        );
+        if style.border_width > 0.0 {
+            jobs.push(
+                SpriteRenderJob::solid(self.position, self.size, style.border_color, layer)
+                    .rounded(style.corner_radius, style.border_width),
+            );
+        }
+        jobs
+    }
+
+    // Light around the rect, drawn below a panel over it
+    pub fn get_glow_job(
+        &self,
+        radius: f32,
+        falloff: f32,
+        color: Vec4,
+        layer: u32,
+    ) -> SpriteRenderJob {
+        SpriteRenderJob::solid(self.position, self.size, color, layer).glow(radius, falloff)
+    }
+}
+
+// How a panel drawn by UiRect::get_panel_jobs looks, sizes are in the units of its space.
+// No shadow is drawn when its color is transparent, no border when its width is 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PanelStyle {
+    pub color: Vec4,
+    pub corner_radius: f32,
+    pub border_width: f32,
+    pub border_color: Vec4,
+    pub shadow_color: Vec4,
+    pub shadow_offset: Vec2,
+    pub shadow_softness: f32,
+}
+
+impl Default for PanelStyle {
+    fn default() -> Self {
+        Self {
+            color: Vec4::new(0.05, 0.06, 0.08, 0.9),
+            corner_radius: 6.0,
+            border_width: 0.0,
+            border_color: Vec4::ONE,
+            shadow_color: Vec4::new(0.0, 0.0, 0.0, 0.5),
+            shadow_offset: Vec2::new(0.0, 3.0),
+            shadow_softness: 8.0,
+        }
+    }
+}
+
+// The screen in the space of sprites drawn with the anchor, the anchor point is the origin.
+// The size is the reference of the viewport.
+pub fn get_screen_rect(anchor: SpriteAnchor, reference: Vec2) -> UiRect {
+    UiRect {
+        position: -get_anchor_factor(anchor) * reference,
+        size: reference,
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub enum UiContent {
+    Group, // Only places its children
+    Panel {
+        color: Vec4,
+    },
+    // Rounded, with a shadow and a border, see PanelStyle
+    StyledPanel {
+        style: PanelStyle,
+    },
+    Image {
+        material: ResourceHandle,
+        tex_coord: Vec2,
+        tex_scale: Vec2,
+        color: Vec4,
+    },
+    // The baseline is on the bottom edge of the rect
+    Label {
+        text: String,
+        size: f32,
+        color: Vec4,
+        alignment: TextAlignment,
+    },
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct UiNode {
+    pub name: String, // For finding the node, may be empty
+    pub content: UiContent,
+    pub anchor: SpriteAnchor, // The point of the parent and of the node that meet
+    pub offset: Vec2,         // From the anchor point of the parent
+    pub size: Vec2,
+    pub padding: Vec2, // Between the edges and the children
+    pub visible: bool, // Hides the children too
+    pub children: Vec<UiNode>,
+}
+
+impl Default for UiNode {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            content: UiContent::Group,
+            anchor: SpriteAnchor::TopLeft,
+            offset: Vec2::ZERO,
+            size: Vec2::ZERO,
+            padding: Vec2::ZERO,
+            visible: true,
+            children: Vec::new(),
+        }
+    }
+}
+
+// The constructors fill in the content, the rest can be set with struct update syntax
+#[allow(dead_code)]
+impl UiNode {
+    pub fn group(size: Vec2) -> Self {
+        Self {
+            size,
+            ..Default::default()
+        }
+    }
+
+    pub fn panel(size: Vec2, color: Vec4) -> Self {
+        Self {
+            content: UiContent::Panel { color },
+            size,
+            ..Default::default()
+        }
+    }
+
+    pub fn styled_panel(size: Vec2, style: PanelStyle) -> Self {
+        Self {
+            content: UiContent::StyledPanel { style },
+            size,
+            ..Default::default()
+        }
+    }
+
+    pub fn image(region: &SpriteRegion, size: Vec2) -> Self {
+        Self {
+            content: UiContent::Image {
+                material: region.material,
+                tex_coord: region.tex_coord,
+                tex_scale: region.tex_scale,
+                color: Vec4::ONE,
+            },
+            size,
+            ..Default::default()
+        }
+    }
+
+    // As high as the text, the width is only used for the alignment
+    pub fn label(text: &str, size: f32, width: f32) -> Self {
+        Self {
+            content: UiContent::Label {
+                text: text.to_string(),
+                size,
+                color: Vec4::ONE,
+                alignment: TextAlignment::Left,
+            },
+            size: Vec2::new(width, size),
+            ..Default::default()
+        }
+    }
+
+    pub fn get_rect(&self, parent: &UiRect) -> UiRect {
+        let factor = get_anchor_factor(self.anchor);
+        let point = parent.position + parent.size * factor + self.offset;
+        UiRect {
+            position: point - self.size * factor,
+            size: self.size,
+        }
+    }
+
+    // Depth first, the node itself before its children
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut UiNode> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children
+            .iter_mut()
+            .find_map(|child| child.find_mut(name))
+    }
+
+    // Changes the text of a label, other nodes are left alone
+    pub fn set_text(&mut self, value: &str) {
+        if let UiContent::Label { text, .. } = &mut self.content
+            && text != value
+        {
+            text.clear();
+            text.push_str(value);
+        }
+    }
+}
+
+// Calls the visitor with every visible node, its rect and its depth below the root. The
+// rects are relative to the anchor point of the root on the screen.
+pub fn visit_layout<'a>(
+    root: &'a UiNode,
+    reference: Vec2,
+    visitor: &mut impl FnMut(&'a UiNode, &UiRect, u32),
+) {
+    visit_node(root, &get_screen_rect(root.anchor, reference), 0, visitor);
+}
+
+fn visit_node<'a>(
+    node: &'a UiNode,
+    parent: &UiRect,
+    depth: u32,
+    visitor: &mut impl FnMut(&'a UiNode, &UiRect, u32),
+) {
+    if !node.visible {
+        return;
+    }
+
+    let rect = node.get_rect(parent);
+    visitor(node, &rect, depth);
+
+    let inner = rect.shrink(node.padding);
+    for child in &node.children {
+        visit_node(child, &inner, depth + 1, visitor);
+    }
+}
+
+// A rect laid out relative to the anchor point of its root to pixels, where the sprites of
+// the node end up on the screen
+pub fn get_pixel_rect(rect: &UiRect, anchor: SpriteAnchor, viewport: &UiViewport) -> UiRect {
+    UiRect {
+        position: viewport.get_pixel(anchor, rect.position),
+        size: rect.size * viewport.get_ui_scale(),
+    }
+}
+
+// The deepest named node under a pixel, e.g. the button the mouse is over. Unnamed nodes
+// are only layout and never hit.
+#[allow(dead_code)]
+pub fn hit_test<'a>(root: &'a UiNode, viewport: &UiViewport, pixel: Vec2) -> Option<&'a str> {
+    let mut hit = None;
+    visit_layout(root, viewport.reference, &mut |node, rect, _| {
+        if !node.name.is_empty() && get_pixel_rect(rect, root.anchor, viewport).contains(pixel) {
+            hit = Some(node.name.as_str());
+        }
+    });
+    hit
+}
+
+// Children go one sprite layer above their parent, so they are drawn on top of it whatever
+// their material is
+#[allow(dead_code)]
+pub struct UiRenderer {
+    pub font_atlas: ResourceHandle,
+    pub font_material: ResourceHandle,
+    pub layer: u32, // Of the root nodes
+}
+
+#[allow(dead_code)]
+impl UiRenderer {
+    pub fn submit(&self, root: &UiNode, renderer: &mut Renderer) {
+        let anchor = root.anchor;
+        let space = SpriteSpace::Reference;
+        let reference = renderer.get_ui_viewport().reference;
+        visit_layout(root, reference, &mut |node, rect, depth| {
+            let layer = self.layer + depth;
+            match &node.content {
+                UiContent::Group => {}
+                UiContent::Panel { color } => renderer.submit(&SpriteRenderJob {
+                    anchor,
+                    space,
+                    ..SpriteRenderJob::solid(rect.position, rect.size, *color, layer)
+                }),
+                UiContent::StyledPanel { style } => {
+                    for job in rect.get_panel_jobs(style, layer) {
+                        renderer.submit(&SpriteRenderJob {
+                            anchor,
+                            space,
+                            ..job
+                        });
+                    }
+                }
+                UiContent::Image {
+                    material,
+                    tex_coord,
+                    tex_scale,
+                    color,
+                } => renderer.submit(&SpriteRenderJob {
+                    position: rect.position,
+                    size: rect.size,
+                    material: *material,
+                    color: *color,
+                    tex_coord: *tex_coord,
+                    tex_scale: *tex_scale,
+                    layer,
+                    anchor,
+                    space,
+                    ..Default::default()
+                }),
+                UiContent::Label {
+                    text,
+                    size,
+                    color,
+                    alignment,
+                } => {
+                    let x = match alignment {
+                        TextAlignment::Left => 0.0,
+                        TextAlignment::Center => 0.5,
+                        TextAlignment::Right => 1.0,
+                    };
+                    renderer.submit(&TextRenderJob {
+                        text: text.as_str().into(),
+                        font_atlas: self.font_atlas,
+                        font_material: self.font_material,
+                        position: rect.position + rect.size * Vec2::new(x, 1.0),
+                        size: *size,
+                        color: *color,
+                        layer,
+                        alignment: *alignment,
+                        anchor,
+                        space,
+                    })
+                }
+            }
+        });
+    }
+}
+
+// What the UI asks the tooltip to show, the anchor rect is what the mouse is over in
+// pixels. Made again every frame the mouse stays there.
+#[derive(Debug, Clone, Copy)]
+pub struct TooltipRequest<'a> {
+    pub anchor_rect: UiRect,
+    pub title: &'a str,
+    pub body: &'a str, // Lines are split on newlines
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct TooltipContent {
+    anchor_rect: UiRect,
+    title: String,
+    body: String,
+}
+
+// Drawn on top of everything else, after the rest of the frame is submitted. It fades in
+// once the same title has been requested for TOOLTIP_DELAY, and fades out from where it
+// was when the requests stop.
+#[derive(Default)]
+pub struct Tooltip {
+    request: Option<TooltipContent>, // Of this frame
+    hovered: Option<TooltipContent>, // Requested last frame
+    shown: Option<TooltipContent>,   // Kept while fading out
+    hover_time: f32,
+    fading_in: bool,
+    alpha: f32, // Set by the game's tweens
+}
+
+impl Tooltip {
+    // Only the first request of a frame is kept
+    pub fn request(&mut self, request: &TooltipRequest) {
+        if self.request.is_some() {
+            return;
+        }
+        self.request = Some(TooltipContent {
+            anchor_rect: request.anchor_rect,
+            title: request.title.to_string(),
+            body: request.body.to_string(),
+        });
+    }
+
+    pub fn set_alpha(&mut self, alpha: f32) {
+        self.alpha = alpha;
+    }
+
+    // Starts the fades, on real time like the other UI
+    pub fn update(&mut self, dt: f32, tweens: &mut Tweens) {
+        let Some(hovered) = &self.hovered else {
+            self.hover_time = 0.0;
+            if self.fading_in {
+                self.fading_in = false;
+                self.start_fade(0.0, tweens);
+            }
+            return;
+        };
+
+        // Moving to something else waits for the delay again, unless it is already showing
+        let same = self
+            .shown
+            .as_ref()
+            .is_some_and(|shown| shown.title == hovered.title);
+        if !same && !self.fading_in {
+            self.hover_time = 0.0;
+        }
+        self.shown = Some(hovered.clone());
+
+        self.hover_time += dt;
+        if !self.fading_in && self.hover_time >= TOOLTIP_DELAY {
+            self.fading_in = true;
+            self.start_fade(1.0, tweens);
+        }
+    }
+
+    fn start_fade(&self, to: f32, tweens: &mut Tweens) {
+        tweens.start(
+            TweenTarget::TooltipFade,
+            TweenDesc::new(self.alpha, to, TOOLTIP_FADE_TIME, Easing::QuadOut),
+        );
+    }
+
+    // Called last in the frame, lays the tooltip out and submits it in pixels. It stays in
+    // the safe area like the rest of the UI.
+    pub fn render(&mut self, renderer: &mut Renderer) {
+        self.hovered = self.request.take();
+        // The rect follows what it is about while the fade is running
+        if let (Some(hovered), Some(shown)) = (&self.hovered, &mut self.shown)
+            && hovered.title == shown.title
+        {
+            *shown = hovered.clone();
+        }
+
+        let Some(shown) = &self.shown else {
+            return;
+        };
+        if self.alpha <= 0.0 {
+            return;
+        }
+
+        let font_atlas = get_handle("DebugFont");
+        let font_material = get_handle("DebugFontMaterial");
+        let lines: Vec<&str> = shown.body.lines().collect();
+        let width = lines
+            .iter()
+            .map(|line| renderer.get_text_width(font_atlas, line, TOOLTIP_BODY_SIZE))
+            .fold(
+                renderer.get_text_width(font_atlas, &shown.title, TOOLTIP_TITLE_SIZE),
+                f32::max,
+            );
+        let size = get_tooltip_size(width, lines.len());
+        let (safe_position, safe_size) = renderer.get_ui_viewport().get_safe_rect();
+        let screen = UiRect {
+            position: safe_position,
+            size: safe_size,
+        };
+        let rect = place_tooltip(&shown.anchor_rect, size, &screen);
+
+        let space = SpriteSpace::Absolute;
+        let style = PanelStyle {
+            color: Vec4::new(0.05, 0.06, 0.08, 0.9 * self.alpha),
+            shadow_color: Vec4::new(0.0, 0.0, 0.0, 0.5 * self.alpha),
+            ..Default::default()
+        };
+        for job in rect.get_panel_jobs(&style, TOOLTIP_LAYER) {
+            renderer.submit(&SpriteRenderJob { space, ..job });
+        }
+
+        let alpha = self.alpha;
+        let mut submit_text = |text: &str, size: f32, baseline: f32, color: Vec3| {
+            renderer.submit(&TextRenderJob {
+                text: text.into(),
+                font_atlas,
+                font_material,
+                position: Vec2::new(rect.position.x + TOOLTIP_PADDING, baseline),
+                size,
+                color: color.extend(alpha),
+                layer: TOOLTIP_LAYER + 1,
+                space,
+                ..Default::default()
+            })
+        };
+        let mut baseline = rect.position.y + TOOLTIP_PADDING + TOOLTIP_TITLE_SIZE;
+        submit_text(
+            &shown.title,
+            TOOLTIP_TITLE_SIZE,
+            baseline,
+            Vec3::new(1.0, 0.9, 0.6),
+        );
+        for line in lines {
+            baseline += TOOLTIP_BODY_SIZE * TOOLTIP_LINE_SPACING;
+            submit_text(line, TOOLTIP_BODY_SIZE, baseline, Vec3::splat(0.9));
+        }
+    }
+}
+
+// The widest line is measured in pixels
+fn get_tooltip_size(text_width: f32, body_line_count: usize) -> Vec2 {
+    let body_height = body_line_count as f32 * TOOLTIP_BODY_SIZE * TOOLTIP_LINE_SPACING;
+    Vec2::new(text_width, TOOLTIP_TITLE_SIZE + body_height) + 2.0 * TOOLTIP_PADDING
+}
+
+// Below the anchor rect and starting at its left edge. Near the right edge of the screen
+// it ends at the right edge of the anchor rect instead, near the bottom it goes above.
+// Whatever still sticks out is pushed back onto the screen.
+pub fn place_tooltip(anchor_rect: &UiRect, size: Vec2, screen: &UiRect) -> UiRect {
+    let anchor_end = anchor_rect.get_end();
+    let screen_end = screen.get_end();
+
+    let mut position = Vec2::new(anchor_rect.position.x, anchor_end.y + TOOLTIP_GAP);
+    if position.x + size.x > screen_end.x {
+        position.x = anchor_end.x - size.x;
+    }
+    if position.y + size.y > screen_end.y {
+        position.y = anchor_rect.position.y - TOOLTIP_GAP - size.y;
+    }
+
+    UiRect {
+        position: position.min(screen_end - size).max(screen.position),
+        size,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::safe_area::Insets;
+
+    fn get_rects(root: &UiNode) -> Vec<(String, UiRect, u32)> {
+        let mut rects = Vec::new();
+        visit_layout(
+            root,
+            Renderer::SPRITE_SCREEN_REFERENCE,
+            &mut |node, rect, depth| rects.push((node.name.clone(), *rect, depth)),
+        );
+        rects
+    }
+
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> UiRect {
+        UiRect {
+            position: Vec2::new(x, y),
+            size: Vec2::new(width, height),
+        }
+    }
+
+    #[test]
+    fn nested_anchors_resolve_from_the_parent() {
+        // A panel in the bottom right corner with a padded title bar and a centered icon
+        let root = UiNode {
+            name: "panel".to_string(),
+            anchor: SpriteAnchor::BottomRight,
+            offset: Vec2::new(-20.0, -10.0),
+            padding: Vec2::splat(5.0),
+            children: vec![
+                UiNode {
+                    name: "title".to_string(),
+                    anchor: SpriteAnchor::TopCenter,
+                    children: vec![UiNode {
+                        name: "close".to_string(),
+                        anchor: SpriteAnchor::CenterRight,
+                        offset: Vec2::new(-2.0, 0.0),
+                        ..UiNode::group(Vec2::splat(10.0))
+                    }],
+                    ..UiNode::group(Vec2::new(100.0, 20.0))
+                },
+                UiNode {
+                    name: "icon".to_string(),
+                    anchor: SpriteAnchor::Center,
+                    ..UiNode::group(Vec2::splat(40.0))
+                },
+            ],
+            ..UiNode::panel(Vec2::new(200.0, 100.0), Vec4::ONE)
+        };
+
+        // Relative to the bottom right corner of the screen, where the sprites are anchored
+        let rects = get_rects(&root);
+        assert_eq!(
+            rects[0],
+            ("panel".into(), rect(-220.0, -110.0, 200.0, 100.0), 0)
+        );
+        assert_eq!(
+            rects[1],
+            ("title".into(), rect(-170.0, -105.0, 100.0, 20.0), 1)
+        );
+        assert_eq!(
+            rects[2],
+            ("close".into(), rect(-82.0, -100.0, 10.0, 10.0), 2)
+        );
+        assert_eq!(
+            rects[3],
+            ("icon".into(), rect(-140.0, -80.0, 40.0, 40.0), 1)
+        );
+    }
+
+    #[test]
+    fn roots_are_placed_like_anchored_sprites() {
+        // A sprite anchored to the center with its size at -size/2 is centered on screen
+        let root = UiNode {
+            anchor: SpriteAnchor::Center,
+            ..UiNode::group(Vec2::new(400.0, 12.0))
+        };
+        assert_eq!(get_rects(&root)[0].1, rect(-200.0, -6.0, 400.0, 12.0));
+
+        // The top left anchor is the top left of the reference screen
+        let root = UiNode {
+            offset: Vec2::new(10.0, 20.0),
+            ..UiNode::group(Vec2::splat(50.0))
+        };
+        assert_eq!(get_rects(&root)[0].1, rect(10.0, 20.0, 50.0, 50.0));
+    }
+
+    #[test]
+    fn hidden_nodes_and_their_children_are_skipped() {
+        let mut root = UiNode {
+            children: vec![UiNode {
+                name: "health".to_string(),
+                children: vec![UiNode {
+                    name: "value".to_string(),
+                    ..UiNode::label("100", 20.0, 50.0)
+                }],
+                ..UiNode::group(Vec2::splat(50.0))
+            }],
+            ..UiNode::group(Vec2::splat(100.0))
+        };
+
+        root.find_mut("value").unwrap().set_text("75");
+        let Some(UiNode {
+            content: UiContent::Label { text, .. },
+            ..
+        }) = root.find_mut("value")
+        else {
+            panic!("The label is missing");
+        };
+        assert_eq!(text, "75");
+        assert_eq!(get_rects(&root).len(), 3);
+
+        root.find_mut("health").unwrap().visible = false;
+        assert_eq!(get_rects(&root).len(), 1);
+    }
+
+    #[test]
+    fn tooltips_flip_away_from_the_screen_corners() {
+        let screen = rect(0.0, 0.0, 1920.0, 1080.0);
+        let size = Vec2::new(200.0, 60.0);
+        let place = |x, y| place_tooltip(&rect(x, y, 20.0, 20.0), size, &screen).position;
+
+        // Top left, below the anchor and starting at its left edge
+        assert_eq!(place(10.0, 10.0), Vec2::new(10.0, 36.0));
+        // Top right, ending at the right edge of the anchor
+        assert_eq!(place(1890.0, 10.0), Vec2::new(1710.0, 36.0));
+        // Bottom left, above the anchor
+        assert_eq!(place(10.0, 1050.0), Vec2::new(10.0, 984.0));
+        // Bottom right, both
+        assert_eq!(place(1890.0, 1050.0), Vec2::new(1710.0, 984.0));
+
+        // Too big to flip, it is pushed onto the screen
+        let size = Vec2::new(200.0, 1000.0);
+        let placed = place_tooltip(&rect(10.0, 500.0, 20.0, 20.0), size, &screen);
+        assert_eq!(placed.position, Vec2::new(10.0, 0.0));
+    }
+
+    #[test]
+    fn hit_tests_follow_the_safe_area() {
+        // A button in the top right corner
+        let root = UiNode {
+            anchor: SpriteAnchor::TopRight,
+            offset: Vec2::new(-10.0, 10.0),
+            children: vec![UiNode {
+                name: "close".to_string(),
+                anchor: SpriteAnchor::Center,
+                ..UiNode::group(Vec2::splat(20.0))
+            }],
+            ..UiNode::group(Vec2::splat(40.0))
+        };
+        let center = Vec2::new(-30.0, 30.0); // Of the button, from the corner
+
+        // 21:9, the corner is at the edge of the 16:9 middle
+        let wide = UiViewport::new(Vec2::new(2560.0, 1080.0));
+        let button = Vec2::new(2240.0, 0.0) + center;
+        assert_eq!(hit_test(&root, &wide, button), Some("close"));
+        assert_eq!(hit_test(&root, &wide, Vec2::new(2530.0, 30.0)), None);
+        let margins = Insets {
+            right: 100.0,
+            ..Insets::uniform(0.0)
+        };
+        let inset = UiViewport { margins, ..wide };
+        assert_eq!(hit_test(&root, &inset, button), None);
+        assert_eq!(
+            hit_test(&root, &inset, button - Vec2::new(100.0, 0.0)),
+            Some("close")
+        );
+
+        // 4:3 at half the reference width, everything is half as big
+        let square = UiViewport::new(Vec2::new(960.0, 720.0));
+        let button = Vec2::new(960.0, 0.0) + center * 0.5;
+        assert_eq!(hit_test(&root, &square, button), Some("close"));
+        let inset = UiViewport { margins, ..square };
+        assert_eq!(hit_test(&root, &inset, button), None);
+        assert_eq!(
+            hit_test(&root, &inset, button - Vec2::new(50.0, 0.0)),
+            Some("close")
+        );
+        assert_eq!(
+            get_pixel_rect(&rect(-40.0, 20.0, 20.0, 20.0), root.anchor, &inset),
+            rect(890.0, 10.0, 10.0, 10.0)
+        );
+
+        // Tooltips flip at the same edge, it would fit before the edge of the screen
+        let (position, size) = inset.get_safe_rect();
+        let screen = UiRect { position, size };
+        let placed = place_tooltip(&rect(895.0, 10.0, 5.0, 5.0), Vec2::new(40.0, 20.0), &screen);
+        assert_eq!(placed.get_end().x, 900.0);
+    }
+
+    #[test]
+    fn tooltips_wait_for_the_hover_delay() {
+        let mut tooltip = Tooltip::default();
+        let mut tweens = Tweens::default();
+        let request = TooltipRequest {
+            anchor_rect: rect(0.0, 0.0, 20.0, 20.0),
+            title: "Slow",
+            body: "",
+        };
+        let frame = |tooltip: &mut Tooltip, hovering: bool, tweens: &mut Tweens| {
+            if hovering {
+                tooltip.request(&request);
+            }
+            tooltip.hovered = tooltip.request.take(); // What render does
+            tooltip.update(0.2, tweens);
+        };
+
+        frame(&mut tooltip, true, &mut tweens);
+        assert_eq!(tweens.len(), 0);
+        frame(&mut tooltip, true, &mut tweens);
+        assert_eq!(tweens.len(), 1);
+        assert!(tooltip.fading_in);
+
+        frame(&mut tooltip, false, &mut tweens);
+        assert!(!tooltip.fading_in);
+        assert!(tooltip.shown.is_some(), "Kept for the fade out");
+    }
+
+    #[test]
+    fn panels_draw_the_shadow_below_the_fill_and_the_border() {
+        use crate::renderer::render_data::SpriteRenderMode;
+
+        let style = PanelStyle {
+            border_width: 2.0,
+            ..Default::default()
+        };
+        let jobs = rect(100.0, 50.0, 200.0, 80.0).get_panel_jobs(&style, 4);
+        assert_eq!(jobs.len(), 3);
+        assert!(jobs.iter().all(|job| job.layer == 4));
+        assert!(
+            jobs.iter()
+                .all(|job| matches!(job.mode, SpriteRenderMode::Rounded))
+        );
+
+        // The shadow is offset and grown by its softness so the blur isn't cut off
+        let softness = style.shadow_softness;
+        assert_eq!(
+            jobs[0].position,
+            Vec2::new(100.0, 50.0) + style.shadow_offset - softness
+        );
+        assert_eq!(jobs[0].size, Vec2::new(200.0, 80.0) + 2.0 * softness);
+        assert_eq!(jobs[1].params.unwrap()[1], 0.0);
+        assert_eq!(jobs[2].params.unwrap()[1], 2.0);
+        assert_eq!(jobs[2].color, style.border_color);
+
+        let plain = PanelStyle {
+            shadow_color: Vec4::ZERO,
+            ..Default::default()
+        };
+        assert_eq!(
+            rect(0.0, 0.0, 10.0, 10.0).get_panel_jobs(&plain, 0).len(),
+            1
+        );
+    }
+}
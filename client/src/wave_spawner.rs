@@ -0,0 +1,469 @@
+// Level objects sending waves of units down a lane, like the minions of a MOBA. Every
+// interval a spawner forms up a wave around its spawn point, each unit walks the lane from
+// waypoint to waypoint, stops to fight the enemies that come within its aggro range and
+// walks on once none are left. Both run in the fixed update and the formation jitter comes
+// from a seeded generator, so the same level plays out the same way on every machine.
+
+use std::{collections::HashMap, sync::Arc};
+
+use shared::{
+    math::*,
+    physics::{BodyId, CollisionShape, PhysicsWorld},
+    rng::{Rng, stream},
+    team::Team,
+};
+
+use crate::{
+    ability::get_steps,
+    combat::{CCombat, CHealth, is_in_range},
+    components::{Entities, Entity, Storage, join, join3},
+    game::{CPhysicsProxy, CTargetLocation},
+    level::WaveSpawnerDesc,
+};
+
+pub const WAYPOINT_TOLERANCE: f32 = 40.0; // A waypoint this close counts as reached
+pub const AGGRO_RANGE: f32 = 400.0;
+const FORMATION_COLUMNS: u32 = 3;
+const FORMATION_SPACING: f32 = 80.0;
+const FORMATION_JITTER: f32 = 16.0; // At most, along both axes
+
+pub struct WaveSpawner {
+    pub name: String,
+    pub prefab: String,
+    pub team: Team,
+    position: Vec3,
+    lane: Arc<[Vec3]>, // Shared with the units it spawned
+    interval: f32,     // Seconds
+    count: u32,
+    rng: Rng,
+    steps_to_wave: u32, // The next wave spawns when it is 0
+}
+
+impl WaveSpawner {
+    pub fn new(desc: &WaveSpawnerDesc, position: Vec3) -> Self {
+        Self {
+            name: desc.name.clone(),
+            prefab: desc.prefab.clone(),
+            team: desc.team.get_team(),
+            position,
+            lane: desc.lane.iter().copied().map(Vec3::from).collect(),
+            interval: desc.interval,
+            count: desc.count,
+            rng: Rng::with_stream(desc.seed, stream::SPAWN),
+            steps_to_wave: 0,
+        }
+    }
+
+    // Once per fixed step, fills in the positions of the wave spawning on it. Rows of
+    // FORMATION_COLUMNS behind the spawn point, facing the first waypoint.
+    pub fn tick(&mut self, dt: f32, positions: &mut Vec<Vec3>) {
+        positions.clear();
+        if self.steps_to_wave > 0 {
+            self.steps_to_wave -= 1;
+            return;
+        }
+        self.steps_to_wave = get_steps(self.interval, dt).max(1) - 1;
+
+        let forward = self.get_forward();
+        let side = Vec3::new(-forward.z, 0.0, forward.x);
+        for index in 0..self.count {
+            let row = (index / FORMATION_COLUMNS) as f32;
+            let column = (index % FORMATION_COLUMNS) as f32 - (FORMATION_COLUMNS - 1) as f32 * 0.5;
+            let jitter = Vec3::new(
+                (self.rng.gen_f32() * 2.0 - 1.0) * FORMATION_JITTER,
+                0.0,
+                (self.rng.gen_f32() * 2.0 - 1.0) * FORMATION_JITTER,
+            );
+            positions
+                .push(self.position + (side * column - forward * row) * FORMATION_SPACING + jitter);
+        }
+    }
+
+    // On the ground, towards the first waypoint
+    fn get_forward(&self) -> Vec3 {
+        let to_lane = self
+            .lane
+            .first()
+            .map_or(Vec3::ZERO, |first| *first - self.position);
+        to_lane.with_y(0.0).normalize_or(Vec3::X)
+    }
+
+    pub fn get_rotation(&self) -> Quat {
+        let forward = self.get_forward();
+        Quat::from_rotation_y(f32::atan2(forward.x, forward.z))
+    }
+
+    pub fn get_lane(&self) -> Arc<[Vec3]> {
+        self.lane.clone()
+    }
+
+    // Fixed steps until the next wave
+    pub fn get_steps_to_wave(&self) -> u32 {
+        self.steps_to_wave
+    }
+
+    pub fn get_rng(&self) -> &Rng {
+        &self.rng
+    }
+
+    // Picks up a saved game where it left off
+    pub fn set_clock(&mut self, steps_to_wave: u32, rng: Rng) {
+        self.steps_to_wave = steps_to_wave;
+        self.rng = rng;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LaneFollower {
+    lane: Arc<[Vec3]>,
+    waypoint: usize, // The next one to walk to, the length of the lane once it is walked
+    pub aggro_range: f32,
+}
+
+impl LaneFollower {
+    pub fn new(lane: Arc<[Vec3]>) -> Self {
+        Self {
+            lane,
+            waypoint: 0,
+            aggro_range: AGGRO_RANGE,
+        }
+    }
+
+    pub fn get_waypoint(&self) -> usize {
+        self.waypoint
+    }
+
+    // Past the end it has walked the whole lane
+    pub fn set_waypoint(&mut self, waypoint: usize) {
+        self.waypoint = waypoint.min(self.lane.len());
+    }
+
+    // The lanes are shared, not compared by their waypoints
+    pub fn is_on_lane_of(&self, spawner: &WaveSpawner) -> bool {
+        Arc::ptr_eq(&self.lane, &spawner.lane)
+    }
+}
+
+// Runs before update_combat on the step. The closest living enemy within aggro range
+// becomes the target, the current one is kept while it stays within it. Out of attack
+// range the unit walks up to its target, in range it stands and attacks, without enemies
+// it walks the lane. Units without combat only walk the lane.
+#[allow(clippy::too_many_arguments)]
+pub fn update_lane_followers(
+    entities: &Entities,
+    followers: &mut Storage<LaneFollower>,
+    combats: &mut Storage<CCombat>,
+    healths: &Storage<CHealth>,
+    teams: &Storage<Team>,
+    physics_proxies: &Storage<CPhysicsProxy>,
+    move_targets: &mut Storage<CTargetLocation>,
+    physics_world: &PhysicsWorld,
+) {
+    let body_entities: HashMap<BodyId, Entity> = join(entities, physics_proxies)
+        .filter_map(|(entity, proxy)| Some((proxy.body_id?, entity)))
+        .collect();
+    let is_alive = |entity: Entity| healths.get(entity).is_some_and(|health| !health.is_dead());
+
+    for (entity, follower, proxy) in join3(entities, followers, physics_proxies) {
+        let Some(body_id) = proxy.body_id else {
+            continue;
+        };
+        let Some(position) = physics_world.get_state(body_id).map(|state| state.position) else {
+            continue;
+        };
+
+        let team = teams.get(entity).copied();
+        let shape = CollisionShape::Circle {
+            radius: follower.aggro_range,
+        };
+        let enemies: Vec<(Entity, BodyId, Vec2)> = physics_world
+            .query_shape_of_teams(position, shape, |other| team != Some(other))
+            .into_iter()
+            .filter_map(|body| {
+                let enemy = *body_entities.get(&body)?;
+                Some((enemy, body, physics_world.get_state(body)?.position))
+            })
+            .filter(|&(enemy, _, _)| enemy != entity && is_alive(enemy))
+            .collect();
+
+        let mut combat = combats.get_mut(entity);
+        let current = combat.as_ref().and_then(|combat| combat.target);
+        let target = enemies
+            .iter()
+            .find(|(enemy, _, _)| Some(*enemy) == current)
+            .or_else(|| {
+                enemies.iter().min_by(|(_, _, a), (_, _, b)| {
+                    let a = a.distance_squared(position);
+                    let b = b.distance_squared(position);
+                    a.total_cmp(&b)
+                })
+            });
+        let move_target = move_targets.get_mut(entity);
+
+        if let Some(&(target, target_body, target_position)) = target
+            && let Some(combat) = combat.as_mut()
+        {
+            if is_in_range(physics_world, body_id, combat.range, target_body) {
+                // A move target would cancel the wind-up
+                if let Some(move_target) = move_target {
+                    *move_target = None;
+                }
+                combat.request_attack(target);
+            } else {
+                combat.target = Some(target);
+                if !combat.is_attacking()
+                    && let Some(move_target) = move_target
+                {
+                    *move_target = Some(Vec3::new(target_position.x, 0.0, target_position.y));
+                }
+            }
+            continue;
+        }
+
+        if let Some(combat) = combat {
+            combat.target = None;
+        }
+        while let Some(waypoint) = follower.lane.get(follower.waypoint)
+            && waypoint.xz().distance(position) <= WAYPOINT_TOLERANCE
+        {
+            follower.waypoint += 1;
+        }
+        if let Some(move_target) = move_target {
+            *move_target = follower.lane.get(follower.waypoint).copied();
+        }
+    }
+}
+
+// Killed units leave the world, their deaths are in the events already
+pub fn get_dead_followers(
+    entities: &Entities,
+    followers: &Storage<LaneFollower>,
+    healths: &Storage<CHealth>,
+) -> Vec<Entity> {
+    join(entities, followers)
+        .map(|(entity, _)| entity)
+        .filter(|&entity| healths.get(entity).is_some_and(CHealth::is_dead))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::{physics::BodySettings, transform::Transform};
+
+    use super::*;
+    use crate::{
+        combat::update_combat,
+        events::{GameEvent, GameEvents},
+        game::{CPlayerMovement, update_movement},
+        level::TeamDesc,
+        prefab::PrefabLibrary,
+    };
+
+    const DT: f32 = 1.0 / 60.0;
+    const LANE_END: f32 = 1500.0;
+    const WAVE_SIZE: u32 = 3;
+
+    // A blue and a red spawner at the two ends of one lane, stepped like Game::fixed_update
+    // with the movement of every frame in between, without a renderer
+    struct Simulation {
+        physics_world: PhysicsWorld,
+        entities: Entities,
+        spawners: Vec<WaveSpawner>,
+        followers: Storage<LaneFollower>,
+        combats: Storage<CCombat>,
+        healths: Storage<CHealth>,
+        teams: Storage<Team>,
+        physics_proxies: Storage<CPhysicsProxy>,
+        transforms: Storage<Transform>,
+        movements: Storage<CPlayerMovement>,
+        move_targets: Storage<CTargetLocation>,
+        events: GameEvents,
+        health: f32,                // Of the Minion prefab
+        deaths: Vec<(u32, Entity)>, // With the step they died on
+        step: u32,
+    }
+
+    impl Simulation {
+        fn new() -> Self {
+            let prefabs =
+                PrefabLibrary::load(include_bytes!("../../assets/prefabs/default.ron")).unwrap();
+            let health = prefabs.get("Minion").unwrap().health.unwrap();
+
+            let mut spawners = Vec::new();
+            for (team, x, seed) in [(TeamDesc::Blue, -LANE_END, 1), (TeamDesc::Red, LANE_END, 2)] {
+                let desc = WaveSpawnerDesc {
+                    name: format!("{:?}Mid", team),
+                    spawn: String::new(),
+                    lane: vec![[0.0, 0.0, 0.0], [-x, 0.0, 0.0]],
+                    interval: 10.0,
+                    count: WAVE_SIZE,
+                    team,
+                    prefab: "Minion".to_string(),
+                    seed,
+                };
+                spawners.push(WaveSpawner::new(&desc, Vec3::new(x, 0.0, 0.0)));
+            }
+
+            Self {
+                physics_world: PhysicsWorld::new(),
+                entities: Default::default(),
+                spawners,
+                followers: Default::default(),
+                combats: Default::default(),
+                healths: Default::default(),
+                teams: Default::default(),
+                physics_proxies: Default::default(),
+                transforms: Default::default(),
+                movements: Default::default(),
+                move_targets: Default::default(),
+                events: Default::default(),
+                health,
+                deaths: Vec::new(),
+                step: 0,
+            }
+        }
+
+        // What Game::spawn_prefab gives a Fighter
+        fn spawn(&mut self, position: Vec3, team: Team, lane: Arc<[Vec3]>) {
+            let entity = self.entities.spawn();
+            let body_id = self.physics_world.create_rigid_body(&BodySettings {
+                position: position.xz(),
+                velocity: Vec2::ZERO,
+                layer: team.get_unit_layer(),
+                shape: &CollisionShape::Circle { radius: 24.0 },
+                listen_to_contact_events: false,
+            });
+            self.physics_proxies
+                .insert(entity, CPhysicsProxy::new(body_id, &self.physics_world));
+            self.transforms.insert(
+                entity,
+                Transform {
+                    position,
+                    ..Default::default()
+                },
+            );
+            self.healths.insert(entity, CHealth::new(self.health));
+            self.combats.insert(entity, Default::default());
+            self.teams.insert(entity, team);
+            self.movements.insert(entity, Default::default());
+            self.move_targets.insert(entity, None);
+            self.followers.insert(entity, LaneFollower::new(lane));
+        }
+
+        fn tick(&mut self) {
+            self.step += 1;
+            update_movement(
+                DT,
+                &self.transforms,
+                &mut self.move_targets,
+                &mut self.movements,
+            );
+            for (proxy, movement) in join(&self.physics_proxies, &self.movements) {
+                if let Some(body_id) = proxy.body_id {
+                    self.physics_world
+                        .set_velocity(body_id, movement.velocity.xz());
+                }
+            }
+            self.physics_world.step_simulation(DT);
+            for (transform, proxy) in join(&mut self.transforms, &self.physics_proxies) {
+                if let Some(state) = proxy
+                    .body_id
+                    .and_then(|id| self.physics_world.get_state(id))
+                {
+                    transform.position = state.position.extend(0.0).xzy();
+                }
+            }
+
+            let mut positions = Vec::new();
+            for index in 0..self.spawners.len() {
+                self.spawners[index].tick(DT, &mut positions);
+                let team = self.spawners[index].team;
+                let lane = self.spawners[index].get_lane();
+                for &position in &positions {
+                    self.spawn(position, team, lane.clone());
+                }
+            }
+
+            update_lane_followers(
+                &self.entities,
+                &mut self.followers,
+                &mut self.combats,
+                &self.healths,
+                &self.teams,
+                &self.physics_proxies,
+                &mut self.move_targets,
+                &self.physics_world,
+            );
+            update_combat(
+                DT,
+                &self.entities,
+                &mut self.combats,
+                &mut self.healths,
+                &Default::default(),
+                &self.teams,
+                &self.physics_proxies,
+                &mut self.movements,
+                &mut self.move_targets,
+                &self.physics_world,
+                false,
+                &mut self.events,
+            );
+
+            for entity in get_dead_followers(&self.entities, &self.followers, &self.healths) {
+                if let Some(body_id) = self.physics_proxies.get(entity).and_then(|p| p.body_id) {
+                    self.physics_world.remove_body(body_id);
+                }
+                self.entities.despawn(entity);
+                self.deaths.push((self.step, entity));
+            }
+        }
+
+        fn get_alive_count(&self) -> usize {
+            join(&self.entities, &self.followers).count()
+        }
+    }
+
+    #[test]
+    fn opposing_waves_meet_mid_lane_and_resolve() {
+        let mut sim = Simulation::new();
+        let mut first_hit = None;
+        let mut most_alive = 0;
+        for _ in 0..60 * 60 {
+            sim.tick();
+            most_alive = most_alive.max(sim.get_alive_count());
+            for event in sim.events.drain() {
+                if let GameEvent::DamageDealt {
+                    source: Some(source),
+                    target,
+                    ..
+                } = event
+                    && first_hit.is_none()
+                {
+                    let x = |entity| sim.transforms.get(entity).unwrap().position.x;
+                    first_hit = Some((sim.step, x(source), x(target)));
+                }
+            }
+        }
+
+        // Both waves walk the same distance, they fight around the middle of the lane
+        let (step, source_x, target_x) = first_hit.unwrap();
+        assert!(step < 60 * 10, "The first hit landed on step {}", step);
+        assert!(source_x.abs() < 300.0 && target_x.abs() < 300.0);
+
+        // Six waves a side, the fights are over before the next waves arrive
+        assert!(
+            most_alive <= (WAVE_SIZE * 4) as usize,
+            "{} alive",
+            most_alive
+        );
+        assert!(sim.deaths.len() >= (WAVE_SIZE * 2 * 5) as usize);
+        assert!(sim.get_alive_count() <= (WAVE_SIZE * 4) as usize);
+
+        // The same seeds play out the same way
+        let mut again = Simulation::new();
+        for _ in 0..60 * 60 {
+            again.tick();
+        }
+        assert_eq!(again.deaths, sim.deaths);
+    }
+}
@@ -0,0 +1,142 @@
+// Loses the device of a headless renderer and recreates it, run with
+//   cargo test -p client --features test-harness --test device_loss
+
+use client::renderer::{
+    PersistentChunk, PersistentSet, Renderer, RendererError, ResourceKind, StaticInstanceData,
+    StaticRenderJob,
+    test_harness::{
+        add_texture_layer, build_box, build_checker_texture_bytes, build_mesh_bytes, create_target,
+        get_quad_indices, read_target,
+    },
+};
+use image::RgbaImage;
+use shared::{math::*, transform::Transform};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 64;
+
+struct Scene {
+    material: u64,
+    loaded_mesh: u64,
+    created_mesh: u64,
+}
+
+fn render(renderer: &mut Renderer, scene: &Scene) -> RgbaImage {
+    for (mesh, x) in [(scene.loaded_mesh, -0.8), (scene.created_mesh, 0.8)] {
+        renderer.submit(&StaticRenderJob {
+            transform: Mat4::from_translation(Vec3::new(x, 0.0, 0.0)),
+            material: scene.material,
+            mesh,
+            color: Vec4::ONE,
+            ..Default::default()
+        });
+    }
+
+    let target = create_target(renderer, WIDTH, HEIGHT);
+    renderer.render_to_view(&target.create_view(&Default::default()));
+    let image = read_target(renderer, &target);
+
+    let errors = renderer.get_render_device().take_validation_errors();
+    assert!(errors.is_empty(), "wgpu errors: {}", errors.join("\n"));
+    image
+}
+
+fn get_resources(renderer: &Renderer) -> Vec<(u64, ResourceKind)> {
+    let mut resources: Vec<_> = renderer.get_resource_pool().iter().collect();
+    resources.sort();
+    resources
+}
+
+// With 1, 2 and 3 frames in flight, the setting is kept by the new device
+#[test]
+fn resources_survive_a_lost_device() {
+    for frames_in_flight in 1..=3 {
+        let Ok(renderer) = pollster::block_on(Renderer::new_headless(WIDTH, HEIGHT)) else {
+            println!("No graphics adapter available, skipping the device loss test");
+            return;
+        };
+        lose_the_device(renderer, frames_in_flight);
+    }
+}
+
+fn lose_the_device(mut renderer: Renderer, frames_in_flight: u32) {
+    renderer.set_max_frames_in_flight(frames_in_flight);
+    renderer.set_keep_cpu_copy(true);
+
+    let texture = renderer.load_texture(
+        "Checker",
+        &add_texture_layer(&build_checker_texture_bytes(8)),
+    );
+    let box_vertices = build_box(Vec3::splat(-0.5), Vec3::splat(0.5));
+    let box_indices = get_quad_indices(box_vertices.len());
+    let scene = Scene {
+        material: renderer.create_material("CheckerMaterial", texture),
+        loaded_mesh: renderer.load_mesh("LoadedBox", &build_mesh_bytes(&box_vertices)),
+        created_mesh: renderer.create_static_mesh("CreatedBox", &box_vertices, &box_indices),
+    };
+
+    // Drawn from its own buffer, which has to be uploaded again
+    let position = Vec3::new(0.0, 1.0, 0.0);
+    renderer.create_persistent_instances(
+        "Persistent",
+        PersistentSet {
+            mesh: scene.created_mesh,
+            material: scene.material,
+            casts_shadow: true,
+            render_layers: u32::MAX,
+            chunks: vec![PersistentChunk {
+                instance_range: 0..1,
+                min: position - 0.25,
+                max: position + 0.25,
+            }],
+        },
+        &[StaticInstanceData::new(
+            Mat4::from_scale_rotation_translation(Vec3::splat(0.5), Quat::IDENTITY, position),
+            Vec4::ONE,
+        )],
+    );
+
+    renderer.set_camera_projection(Mat4::perspective_rh(
+        f32::to_radians(60.0),
+        WIDTH as f32 / HEIGHT as f32,
+        0.5,
+        20.0,
+    ));
+    let eye = Vec3::new(0.0, 2.0, 4.0);
+    let view = Mat4::look_at_rh(eye, Vec3::ZERO, Vec3::Y);
+    renderer.set_camera_override(Some(Transform {
+        position: eye,
+        rotation: Quat::from_mat4(&view.inverse()),
+        ..Default::default()
+    }));
+
+    let before = render(&mut renderer, &scene);
+    let resources = get_resources(&renderer);
+
+    renderer.trigger_device_loss();
+    assert!(renderer.is_device_lost());
+    assert!(matches!(renderer.render(), Err(RendererError::DeviceLost)));
+
+    pollster::block_on(renderer.recreate_headless()).expect("Failed to recreate the renderer");
+    assert!(!renderer.is_device_lost());
+    assert_eq!(renderer.get_max_frames_in_flight(), frames_in_flight);
+    assert_eq!(get_resources(&renderer), resources);
+    assert_eq!(renderer.get_texture_size(texture), Some(UVec2::new(8, 8)));
+    let (vertices, indices) = renderer
+        .get_static_mesh_geometry(scene.created_mesh)
+        .unwrap();
+    assert_eq!(
+        bytemuck::cast_slice::<_, u8>(vertices),
+        bytemuck::cast_slice::<_, u8>(&box_vertices)
+    );
+    assert_eq!(indices, box_indices.as_slice());
+    assert_eq!(
+        renderer.get_resource_pool().get_name(scene.material),
+        Some("CheckerMaterial")
+    );
+
+    // The same frame as before, drawn with the new device
+    let after = render(&mut renderer, &scene);
+    assert!(before.pixels().any(|pixel| pixel != before.get_pixel(0, 0)));
+    assert_eq!(before, after);
+}
@@ -0,0 +1,49 @@
+// The native side of the asset fetcher against files on disk
+
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use client::fetch::{AssetFetcher, FetchedAsset};
+
+fn wait_for_all(fetcher: &mut AssetFetcher) -> Vec<FetchedAsset> {
+    let start = Instant::now();
+    let mut fetched = Vec::new();
+    while fetcher.get_pending_count() > 0 {
+        assert!(start.elapsed() < Duration::from_secs(10), "Fetching timed out");
+        fetched.extend(fetcher.poll());
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    fetched
+}
+
+#[test]
+fn reads_files_and_reports_missing_ones() {
+    let directory = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("fetch_assets");
+    std::fs::create_dir_all(directory.join("models")).unwrap();
+    std::fs::write(directory.join("models/cube.dat"), [1, 2, 3]).unwrap();
+    std::fs::write(directory.join("grid.dat"), vec![7; 4096]).unwrap();
+
+    let mut fetcher = AssetFetcher::new(directory.to_str().unwrap());
+    assert!(fetcher.request("Cube", "models/cube.dat"));
+    assert!(fetcher.request("Grid", "grid.dat"));
+    assert!(fetcher.request("Missing", "missing.dat"));
+    // Still on its way, the second request is coalesced into the first
+    assert!(!fetcher.request("Cube", "models/cube.dat"));
+    assert_eq!(fetcher.get_pending_count(), 3);
+
+    let mut fetched = wait_for_all(&mut fetcher);
+    fetched.sort_by(|a, b| a.name.cmp(&b.name));
+    let names: Vec<&str> = fetched.iter().map(|asset| asset.name.as_str()).collect();
+    assert_eq!(names, ["Cube", "Grid", "Missing"]);
+
+    assert_eq!(fetched[0].bytes.as_ref().unwrap(), &[1, 2, 3]);
+    assert_eq!(fetched[1].bytes.as_ref().unwrap().len(), 4096);
+    let error = fetched[2].bytes.as_ref().unwrap_err();
+    assert!(format!("{:#}", error).contains("missing.dat"));
+
+    // Done, so asking again reads the file again
+    assert!(fetcher.request("Cube", "models/cube.dat"));
+    assert_eq!(wait_for_all(&mut fetcher).len(), 1);
+}
@@ -0,0 +1,443 @@
+// Golden-image tests for the renderer, run with
+//   cargo test -p client --features test-harness --test golden
+// Pass `-- --bless` to regenerate the images in tests/goldens after an intended change,
+// any other argument filters the scenes by name.
+
+use std::path::{Path, PathBuf};
+
+use client::renderer::{
+    AaMode, DebugView, DirectionalLight, SpriteSpace, StaticMeshVertex, StaticRenderJob,
+    TextAlignment,
+    render_data::{SkeletalRenderJob, SpriteRenderJob, TextRenderJob},
+    test_harness::{GoldenTolerance, RenderHarness, check_golden},
+};
+use client::ui::{PanelStyle, UiRect};
+use image::imageops;
+use shared::math::*;
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+
+struct Scene {
+    name: &'static str,
+    setup: fn(&mut RenderHarness),
+    golden: Option<&'static str>, // Another scene's image it has to match, not blessed
+}
+
+const SCENES: &[Scene] = &[
+    Scene {
+        name: "scene_pass",
+        setup: scene_pass,
+        golden: None,
+    },
+    Scene {
+        name: "shadows_off",
+        setup: shadows_off,
+        golden: None,
+    },
+    Scene {
+        name: "sprites_text",
+        setup: sprites_text,
+        golden: None,
+    },
+    Scene {
+        name: "fxaa_edges",
+        setup: fxaa_edges,
+        golden: None,
+    },
+    Scene {
+        name: "dynamic_mesh",
+        setup: dynamic_mesh,
+        golden: None,
+    },
+    Scene {
+        name: "render_scale",
+        setup: render_scale,
+        golden: None,
+    },
+    Scene {
+        name: "ui_panels",
+        setup: ui_panels,
+        golden: None,
+    },
+    Scene {
+        name: "debug_views_restored",
+        setup: debug_views_restored,
+        golden: Some("scene_pass"),
+    },
+];
+
+// Drawn again at twice the size and scaled down, they have to match their golden. The
+// UI is laid out in reference units, so only the resolution changes.
+const SCALED_SCENES: &[&str] = &["ui_panels"];
+const SCALE: u32 = 2;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let bless = args.iter().any(|arg| arg == "--bless");
+    let filters: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+
+    let Some(harness) = RenderHarness::new(WIDTH, HEIGHT) else {
+        println!("No graphics adapter available, skipping the golden-image tests");
+        return;
+    };
+
+    let golden_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/goldens");
+    let failure_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden-failures");
+    let tolerance = GoldenTolerance::default();
+
+    // The WebGL2 path has to draw the same images, it is never blessed
+    let mut failures = Vec::new();
+    let runs = [
+        ("", Some(harness)),
+        (" (fallback)", RenderHarness::new_fallback(WIDTH, HEIGHT)),
+    ];
+    for (suffix, harness) in runs {
+        let Some(mut harness) = harness else {
+            continue;
+        };
+        for scene in SCENES {
+            if !filters.is_empty() && !filters.iter().any(|f| scene.name.contains(f.as_str())) {
+                continue;
+            }
+
+            reset(&mut harness);
+            (scene.setup)(&mut harness);
+            let image = harness.capture();
+
+            let name = format!("{}{}", scene.name, suffix);
+            match check_golden(
+                scene.golden.unwrap_or(scene.name),
+                &image,
+                &golden_dir,
+                &failure_dir,
+                &tolerance,
+                bless && scene.golden.is_none() && suffix.is_empty(),
+            ) {
+                Ok(()) => println!("golden {} ... ok", name),
+                Err(error) => {
+                    println!("golden {} ... FAILED\n    {}", name, error);
+                    failures.push(name);
+                }
+            }
+        }
+    }
+
+    if let Some(mut harness) = RenderHarness::new(WIDTH * SCALE, HEIGHT * SCALE) {
+        for scene in SCENES {
+            if !SCALED_SCENES.contains(&scene.name)
+                || (!filters.is_empty() && !filters.iter().any(|f| scene.name.contains(f.as_str())))
+            {
+                continue;
+            }
+
+            reset(&mut harness);
+            (scene.setup)(&mut harness);
+            let image = imageops::resize(
+                &harness.capture(),
+                WIDTH,
+                HEIGHT,
+                imageops::FilterType::Triangle,
+            );
+
+            let name = format!("{} ({}x)", scene.name, SCALE);
+            match check_golden(
+                scene.name,
+                &image,
+                &golden_dir,
+                &failure_dir,
+                &tolerance,
+                false,
+            ) {
+                Ok(()) => println!("golden {} ... ok", name),
+                Err(error) => {
+                    println!("golden {} ... FAILED\n    {}", name, error);
+                    failures.push(name);
+                }
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("{} golden-image failures: {:?}", failures.len(), failures);
+        std::process::exit(1);
+    }
+}
+
+// Every scene starts from the same light, antialiasing and camera. The shadow map depth
+// range follows the camera range and the extension, both are kept short so the fixed
+// depth bias stays below the size of the props.
+fn reset(harness: &mut RenderHarness) {
+    let renderer = &mut harness.renderer;
+    renderer.set_directional_light(DirectionalLight {
+        direction: Vec3::new(-0.4, -1.0, -0.3).normalize(),
+        shadow_depth_extension: 5.0,
+        ..Default::default()
+    });
+    renderer.set_antialiasing(AaMode::Off);
+    renderer.set_render_scale(1.0);
+    renderer.set_camera_projection(Mat4::perspective_rh(
+        f32::to_radians(40.0),
+        WIDTH as f32 / HEIGHT as f32,
+        0.5,
+        15.0,
+    ));
+    look_at(harness, Vec3::new(3.0, 3.5, 5.0), Vec3::new(0.0, 0.5, 0.0));
+}
+
+fn look_at(harness: &mut RenderHarness, eye: Vec3, target: Vec3) {
+    let view = Mat4::look_at_rh(eye, target, Vec3::Y);
+    let orientation = Quat::from_mat4(&view.inverse());
+    harness
+        .renderer
+        .set_camera_position_and_orientation(eye, orientation);
+}
+
+fn submit_props(harness: &mut RenderHarness) {
+    let assets = &harness.assets;
+
+    harness.renderer.submit(&StaticRenderJob {
+        transform: Mat4::from_scale(Vec3::new(3.0, 1.0, 3.0)),
+        material: assets.checker_material,
+        mesh: assets.floor_mesh,
+        color: Vec4::new(0.651, 0.541, 0.392, 1.0),
+        tex_scale: Vec2::ONE * 4.0,
+        casts_shadow: false,
+        ..Default::default()
+    });
+
+    harness.renderer.submit(&StaticRenderJob {
+        transform: Mat4::from_rotation_translation(
+            Quat::from_rotation_y(0.6),
+            Vec3::new(-1.0, 0.5, 0.5),
+        ),
+        material: assets.checker_material,
+        mesh: assets.cube_mesh,
+        color: Vec4::new(0.3, 0.5, 0.9, 1.0),
+        ..Default::default()
+    });
+
+    // The upper bone is bent so skinning shows up in the image
+    let mut pose = harness.create_pose();
+    pose.transforms[1].position = Vec3::new(0.0, 1.0, 0.0);
+    pose.transforms[1].rotation = Quat::from_rotation_z(0.7);
+
+    let assets = &harness.assets;
+    harness.renderer.submit(&SkeletalRenderJob {
+        transform: Mat4::from_translation(Vec3::new(1.0, 0.0, -0.5)),
+        material: assets.checker_material,
+        mesh: assets.skinned_mesh,
+        color: Vec4::new(0.9, 0.4, 0.3, 1.0),
+        pose: Some(&pose),
+        ..Default::default()
+    });
+}
+
+fn scene_pass(harness: &mut RenderHarness) {
+    submit_props(harness);
+}
+
+fn shadows_off(harness: &mut RenderHarness) {
+    let mut light = *harness.renderer.get_directional_light();
+    light.shadows_enabled = false;
+    harness.renderer.set_directional_light(light);
+
+    submit_props(harness);
+}
+
+fn sprites_text(harness: &mut RenderHarness) {
+    let assets = &harness.assets;
+
+    // Overlapping layers, the higher layer has to end up on top
+    harness.renderer.submit(&SpriteRenderJob {
+        position: Vec2::new(200.0, 200.0),
+        size: Vec2::new(900.0, 500.0),
+        material: assets.checker_sprite_material,
+        color: Vec4::new(0.2, 0.6, 0.3, 1.0),
+        tex_scale: Vec2::ONE * 4.0,
+        layer: 0,
+        ..Default::default()
+    });
+    harness.renderer.submit(&SpriteRenderJob {
+        position: Vec2::new(120.0, 60.0),
+        size: Vec2::new(80.0, 40.0),
+        material: assets.checker_sprite_material,
+        color: Vec4::new(0.9, 0.8, 0.2, 0.8),
+        layer: 1,
+        space: SpriteSpace::Absolute,
+        ..Default::default()
+    });
+
+    harness.renderer.submit(&TextRenderJob {
+        text: "Rusty Rift".into(),
+        font_atlas: assets.font,
+        font_material: assets.font_material,
+        position: Vec2::new(960.0, 1300.0),
+        size: 160.0,
+        color: Vec4::ONE,
+        layer: 1,
+        alignment: TextAlignment::Center,
+        ..Default::default()
+    });
+}
+
+// Rounded panels with shadows and borders and a glow, over the checker so the soft edges
+// blend with something
+fn ui_panels(harness: &mut RenderHarness) {
+    let assets = &harness.assets;
+    harness.renderer.submit(&SpriteRenderJob {
+        position: Vec2::ZERO,
+        size: Vec2::new(1920.0, 1080.0),
+        material: assets.checker_sprite_material,
+        color: Vec4::new(0.3, 0.4, 0.5, 1.0),
+        tex_scale: Vec2::ONE * 4.0,
+        layer: 0,
+        ..Default::default()
+    });
+
+    let panel = UiRect {
+        position: Vec2::new(200.0, 150.0),
+        size: Vec2::new(700.0, 400.0),
+    };
+    let style = PanelStyle {
+        color: Vec4::new(0.1, 0.12, 0.16, 0.95),
+        corner_radius: 48.0,
+        border_width: 12.0,
+        border_color: Vec4::new(0.9, 0.7, 0.3, 1.0),
+        shadow_color: Vec4::new(0.0, 0.0, 0.0, 0.8),
+        shadow_offset: Vec2::new(32.0, 48.0),
+        shadow_softness: 60.0,
+    };
+    for job in panel.get_panel_jobs(&style, 1) {
+        harness.renderer.submit(&job);
+    }
+
+    let button = UiRect {
+        position: Vec2::new(1100.0, 600.0),
+        size: Vec2::new(500.0, 200.0),
+    };
+    harness
+        .renderer
+        .submit(&button.get_glow_job(100.0, 40.0, Vec4::new(0.3, 0.8, 1.0, 0.8), 1));
+    let style = PanelStyle {
+        color: Vec4::new(0.2, 0.5, 0.8, 1.0),
+        corner_radius: 100.0,
+        shadow_color: Vec4::ZERO,
+        ..Default::default()
+    };
+    for job in button.get_panel_jobs(&style, 1) {
+        harness.renderer.submit(&job);
+    }
+}
+
+fn fxaa_edges(harness: &mut RenderHarness) {
+    harness.renderer.set_antialiasing(AaMode::Fxaa);
+    look_at(harness, Vec3::new(0.0, 2.0, 3.0), Vec3::ZERO);
+
+    // Thin, slightly rotated bars give long near-horizontal edges for FXAA to smooth
+    let assets = &harness.assets;
+    for i in 0..5 {
+        harness.renderer.submit(&StaticRenderJob {
+            transform: Mat4::from_scale_rotation_translation(
+                Vec3::new(2.5, 0.05, 0.1),
+                Quat::from_rotation_y(0.08),
+                Vec3::new(0.0, 0.0, -1.0 + i as f32 * 0.5),
+            ),
+            material: assets.checker_material,
+            mesh: assets.cube_mesh,
+            color: Vec4::ONE,
+            casts_shadow: false,
+            ..Default::default()
+        });
+    }
+}
+
+// The scene is upscaled from half the size, the text on top stays sharp
+fn render_scale(harness: &mut RenderHarness) {
+    harness.renderer.set_render_scale(0.5);
+    submit_props(harness);
+
+    let assets = &harness.assets;
+    harness.renderer.submit(&TextRenderJob {
+        text: "Rusty Rift".into(),
+        font_atlas: assets.font,
+        font_material: assets.font_material,
+        position: Vec2::new(960.0, 1800.0),
+        size: 160.0,
+        color: Vec4::ONE,
+        alignment: TextAlignment::Center,
+        ..Default::default()
+    });
+}
+
+// Every debug view is drawn once, switching back has to give the scene pass again
+fn debug_views_restored(harness: &mut RenderHarness) {
+    let resource_count = harness.renderer.get_resource_pool().iter().count();
+    for view in DebugView::ALL {
+        harness.renderer.set_debug_view(view);
+        submit_props(harness);
+        harness.capture();
+    }
+    harness.renderer.set_debug_view(DebugView::None);
+    assert_eq!(harness.renderer.get_debug_view(), DebugView::None);
+    assert_eq!(
+        harness.renderer.get_resource_pool().iter().count(),
+        resource_count
+    );
+    // The camera turns a little with every frame, back to where scene_pass saw it from
+    reset(harness);
+    submit_props(harness);
+}
+
+// A strip of quads along x on the floor, counter-clockwise seen from above
+fn get_strip_geometry(quad_count: u32, width: f32) -> (Vec<StaticMeshVertex>, Vec<u32>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for point in 0..=quad_count {
+        let x = -1.5 + point as f32 * 3.0 / quad_count as f32;
+        for z in [-width * 0.5, width * 0.5] {
+            vertices.push(StaticMeshVertex {
+                position: [x, 0.01, z],
+                normal: [0.0, 1.0, 0.0],
+                uvs: [0.0, 0.0, 0.0],
+                color: [1.0, 1.0, 1.0, 1.0],
+            });
+        }
+    }
+    for quad in 0..quad_count {
+        let a = quad * 2;
+        indices.extend([a, a + 1, a + 2, a + 2, a + 1, a + 3]);
+    }
+    (vertices, indices)
+}
+
+fn dynamic_mesh(harness: &mut RenderHarness) {
+    // Outgrows the buffers, then is rewritten with less. The wide strip left in the buffers
+    // must not show, only the narrow one that was written last.
+    let renderer = &mut harness.renderer;
+    let mesh = renderer.create_dynamic_mesh("TestDynamicMesh", 4);
+    let (vertices, indices) = get_strip_geometry(1, 0.5);
+    renderer.update_dynamic_mesh(mesh, &vertices, &indices);
+    let (vertices, indices) = get_strip_geometry(24, 2.0);
+    renderer.update_dynamic_mesh(mesh, &vertices, &indices);
+    let (vertices, indices) = get_strip_geometry(6, 0.4);
+    renderer.update_dynamic_mesh(mesh, &vertices, &indices);
+
+    let assets = &harness.assets;
+    harness.renderer.submit(&StaticRenderJob {
+        transform: Mat4::from_scale(Vec3::new(3.0, 1.0, 3.0)),
+        material: assets.checker_material,
+        mesh: assets.floor_mesh,
+        color: Vec4::new(0.651, 0.541, 0.392, 1.0),
+        tex_scale: Vec2::ONE * 4.0,
+        casts_shadow: false,
+        ..Default::default()
+    });
+    harness.renderer.submit(&StaticRenderJob {
+        material: assets.checker_material,
+        mesh,
+        color: Vec4::new(0.3, 0.8, 1.0, 1.0),
+        casts_shadow: false,
+        ..Default::default()
+    });
+}
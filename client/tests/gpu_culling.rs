@@ -0,0 +1,110 @@
+// Culls persistent instances on the GPU of a headless renderer and checks it against the
+// CPU, run with
+//   cargo test -p client --features test-harness --test gpu_culling
+
+use client::renderer::{
+    PersistentChunk, PersistentSet, Renderer, StaticInstanceData,
+    test_harness::{
+        add_texture_layer, build_box, build_checker_texture_bytes, create_target, get_quad_indices,
+        read_target,
+    },
+};
+use image::RgbaImage;
+use shared::{math::*, transform::Transform};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 64;
+
+fn render(renderer: &mut Renderer) -> RgbaImage {
+    let target = create_target(renderer, WIDTH, HEIGHT);
+    renderer.render_to_view(&target.create_view(&Default::default()));
+    let image = read_target(renderer, &target);
+
+    let errors = renderer.get_render_device().take_validation_errors();
+    assert!(errors.is_empty(), "wgpu errors: {}", errors.join("\n"));
+    image
+}
+
+#[test]
+fn gpu_culling_matches_the_cpu() {
+    let Ok(mut renderer) = pollster::block_on(Renderer::new_headless(WIDTH, HEIGHT)) else {
+        println!("No graphics adapter available, skipping the GPU culling test");
+        return;
+    };
+    if !renderer.supports_gpu_culling() {
+        println!("No compute shaders, skipping the GPU culling test");
+        return;
+    }
+
+    let texture = renderer.load_texture(
+        "Checker",
+        &add_texture_layer(&build_checker_texture_bytes(8)),
+    );
+    let material = renderer.create_material("CheckerMaterial", texture);
+    let box_vertices = build_box(Vec3::splat(-0.5), Vec3::splat(0.5));
+    let box_indices = get_quad_indices(box_vertices.len());
+    let mesh = renderer.create_static_mesh("Box", &box_vertices, &box_indices);
+
+    // A grid around the camera, one chunk so the CPU keeps all of them
+    let mut instances = Vec::new();
+    for x in -10..=10 {
+        for z in -10..=10 {
+            let position = Vec3::new(x as f32 * 2.0, 0.0, z as f32 * 2.0);
+            instances.push(StaticInstanceData::new(
+                Mat4::from_scale_rotation_translation(Vec3::splat(0.5), Quat::IDENTITY, position),
+                Vec4::ONE,
+            ));
+        }
+    }
+    let handle = renderer.create_persistent_instances(
+        "Grid",
+        PersistentSet {
+            mesh,
+            material,
+            casts_shadow: true,
+            render_layers: u32::MAX,
+            chunks: vec![PersistentChunk {
+                instance_range: 0..instances.len() as u32,
+                min: Vec3::new(-21.0, -1.0, -21.0),
+                max: Vec3::new(21.0, 1.0, 21.0),
+            }],
+        },
+        &instances,
+    );
+
+    renderer.set_camera_projection(Mat4::perspective_rh(
+        f32::to_radians(60.0),
+        WIDTH as f32 / HEIGHT as f32,
+        0.5,
+        30.0,
+    ));
+    let eye = Vec3::new(0.0, 3.0, 0.0);
+    let view = Mat4::look_at_rh(eye, Vec3::new(0.0, 0.0, -10.0), Vec3::Y);
+    renderer.set_camera_override(Some(Transform {
+        position: eye,
+        rotation: Quat::from_mat4(&view.inverse()),
+        ..Default::default()
+    }));
+
+    let cpu_culled = render(&mut renderer);
+    assert!(renderer.get_gpu_cull_counts().is_empty());
+
+    renderer.set_gpu_culling(true);
+    renderer.set_gpu_culling_validation(true);
+    assert!(renderer.is_gpu_culling());
+    let gpu_culled = render(&mut renderer);
+
+    let counts = renderer.get_gpu_cull_counts();
+    assert_eq!(counts.len(), 1);
+    assert_eq!(counts[0].instances, handle);
+    assert_eq!(counts[0].gpu, counts[0].cpu);
+    // The camera looks at one side of the grid
+    assert!(counts[0].cpu > 0 && (counts[0].cpu as usize) < instances.len() / 2);
+
+    assert!(
+        cpu_culled
+            .pixels()
+            .any(|pixel| pixel != cpu_culled.get_pixel(0, 0))
+    );
+    assert_eq!(cpu_culled, gpu_culled);
+}
@@ -0,0 +1,104 @@
+// Five Brutes of one variant material in a headless renderer, each drawn with the layer
+// of its variant and all of them in one batch, run with
+//   cargo test -p client --features test-harness --test material_variants
+
+use client::renderer::{
+    BoneInfo, Renderer, StaticMeshVertex,
+    render_data::SkeletalRenderJob,
+    test_harness::{
+        build_box, build_skinned_mesh_bytes, build_texture_array_bytes, create_target, read_target,
+    },
+};
+use shared::math::*;
+
+const WIDTH: u32 = 128;
+const HEIGHT: u32 = 64;
+
+const SKINS: [[u8; 4]; 3] = [[220, 40, 40, 255], [40, 220, 40, 255], [40, 40, 220, 255]];
+
+#[test]
+fn variants_pick_their_layer_in_one_batch() {
+    let Ok(renderer) = pollster::block_on(Renderer::new_headless(WIDTH, HEIGHT)) else {
+        println!("No graphics adapter available, skipping the material variant test");
+        return;
+    };
+    check_variants(renderer);
+}
+
+// The instances in a vertex buffer and the bones in a texture, as on WebGL2
+#[test]
+fn variants_pick_their_layer_with_instance_vertex_buffers() {
+    let Ok(renderer) = pollster::block_on(Renderer::new_headless_fallback(WIDTH, HEIGHT)) else {
+        println!("No graphics adapter available, skipping the material variant test");
+        return;
+    };
+    check_variants(renderer);
+}
+
+fn check_variants(mut renderer: Renderer) {
+    let texture = renderer.load_texture("BruteSkins", &build_texture_array_bytes(4, &SKINS));
+    let material = renderer.create_variant_material("BruteSkinsMaterial", texture, 3);
+    // A one bone column standing in for the Brute
+    let vertices: Vec<(StaticMeshVertex, i32)> =
+        build_box(Vec3::new(-0.5, 0.0, -0.5), Vec3::new(0.5, 2.0, 0.5))
+            .into_iter()
+            .map(|v| (v, 0))
+            .collect();
+    let bones = [BoneInfo {
+        id: 0,
+        parent_id: -1,
+        offset_matrix: Mat4::IDENTITY.to_cols_array(),
+    }];
+    let mesh = renderer.load_skeletal_mesh("Brute", &build_skinned_mesh_bytes(&vertices, &bones));
+    let pose = renderer.create_pose(mesh);
+
+    let projection = Mat4::perspective_rh(
+        f32::to_radians(60.0),
+        WIDTH as f32 / HEIGHT as f32,
+        0.5,
+        30.0,
+    );
+    let eye = Vec3::new(0.0, 1.0, 8.0);
+    let view = Mat4::look_at_rh(eye, Vec3::new(0.0, 1.0, 0.0), Vec3::Y);
+    renderer.set_camera_projection(projection);
+    renderer.set_camera_position_and_orientation(eye, Quat::from_mat4(&view.inverse()));
+
+    // The last one is out of range and clamped to the last skin
+    let variants = [0, 1, 2, 1, 7];
+    let positions = [-4.0, -2.0, 0.0, 2.0, 4.0].map(|x| Vec3::new(x, 0.0, 0.0));
+    for (variant, position) in variants.into_iter().zip(positions) {
+        renderer.submit(&SkeletalRenderJob {
+            transform: Mat4::from_translation(position),
+            material,
+            mesh,
+            pose: Some(&pose),
+            variant,
+            ..Default::default()
+        });
+    }
+
+    let target = create_target(&renderer, WIDTH, HEIGHT);
+    renderer.render_to_view(&target.create_view(&Default::default()));
+    let image = read_target(&renderer, &target);
+    let errors = renderer.get_render_device().take_validation_errors();
+    assert!(errors.is_empty(), "wgpu errors: {}", errors.join("\n"));
+
+    let stats = renderer.get_frame_stats();
+    assert_eq!(stats.skeletal_batch_count, 1);
+    assert_eq!(stats.skeletal_instance_count, 5);
+
+    // The middle of the front face shows the color of the skin
+    for (layer, position) in [0, 1, 2, 1, 2].into_iter().zip(positions) {
+        let clip = projection * view * (position + Vec3::new(0.0, 1.0, 0.5)).extend(1.0);
+        let ndc = clip.truncate() / clip.w;
+        let x = ((ndc.x * 0.5 + 0.5) * WIDTH as f32) as u32;
+        let y = ((0.5 - ndc.y * 0.5) * HEIGHT as f32) as u32;
+        let pixel = image.get_pixel(x, y);
+        let brightest = (0..3).max_by_key(|&channel| pixel[channel]).unwrap();
+        assert_eq!(
+            brightest, layer,
+            "The Brute at {} has the color {:?}",
+            position, pixel
+        );
+    }
+}
@@ -0,0 +1,156 @@
+// Resizes a headless renderer through sizes including zero, the way minimizing and
+// dragging between monitors does, and checks the frame after them, run with
+//   cargo test -p client --features test-harness --test surface_resize
+
+use client::renderer::{
+    Renderer, ResourceHandle, SpriteAnchor, SpriteSpace,
+    render_data::SpriteRenderJob,
+    safe_area::FitPolicy,
+    test_harness::{build_texture_array_bytes, create_target, read_target},
+};
+use image::RgbaImage;
+use shared::math::*;
+
+const SIZES: [(u32, u32); 8] = [
+    (0, 0),
+    (300, 200),
+    (0, 200),
+    (1, 1),
+    (640, 0),
+    (0, 0),
+    (640, 360),
+    (17, 911),
+];
+
+const RED: Vec4 = Vec4::new(1.0, 0.0, 0.0, 1.0);
+
+// White, with two layers for the GL backend, see add_texture_layer
+fn create_white_material(renderer: &mut Renderer) -> ResourceHandle {
+    let texture = renderer.load_texture("White", &build_texture_array_bytes(1, &[[255; 4]; 2]));
+    renderer.create_sprite_material("WhiteMaterial", texture)
+}
+
+// A red sprite over the whole window
+fn submit_background(renderer: &mut Renderer, material: ResourceHandle, size: UVec2) {
+    renderer.submit(&SpriteRenderJob {
+        size: size.as_vec2(),
+        material,
+        color: RED,
+        space: SpriteSpace::Absolute,
+        ..Default::default()
+    });
+}
+
+fn render(renderer: &mut Renderer, size: UVec2) -> RgbaImage {
+    let target = create_target(renderer, size.x, size.y);
+    renderer.render_to_view(&target.create_view(&Default::default()));
+    let image = read_target(renderer, &target);
+
+    let errors = renderer.get_render_device().take_validation_errors();
+    assert!(errors.is_empty(), "wgpu errors: {}", errors.join("\n"));
+    image
+}
+
+// With 1, 2 and 3 frames in flight, each waits for a different number of frames
+#[test]
+fn rapid_resizes_end_at_the_final_size() {
+    for frames_in_flight in 1..=3 {
+        let Ok(renderer) = pollster::block_on(Renderer::new_headless(64, 64)) else {
+            println!("No graphics adapter available, skipping the surface resize test");
+            return;
+        };
+        resize_rapidly(renderer, frames_in_flight);
+    }
+}
+
+fn resize_rapidly(mut renderer: Renderer, frames_in_flight: u32) {
+    renderer.set_max_frames_in_flight(frames_in_flight);
+    assert_eq!(renderer.get_max_frames_in_flight(), frames_in_flight);
+    let material = create_white_material(&mut renderer);
+
+    // Several events per frame, only some frames render in between
+    for (index, (width, height)) in SIZES.into_iter().cycle().take(40).enumerate() {
+        renderer.resize(width, height);
+        assert_eq!(renderer.is_suspended(), width == 0 || height == 0);
+        if index % 3 != 0 {
+            continue;
+        }
+
+        let size = UVec2::new(width, height);
+        submit_background(&mut renderer, material, size.max(UVec2::ONE));
+        assert!(renderer.render().is_ok());
+        if !renderer.is_suspended() {
+            render(&mut renderer, size);
+        }
+    }
+
+    // Minimized and restored at another size
+    renderer.resize(0, 0);
+    submit_background(&mut renderer, material, UVec2::ONE);
+    assert!(renderer.render().is_ok());
+    renderer.resize(96, 48);
+    assert!(!renderer.is_suspended());
+    assert_eq!(renderer.get_scene_size(), UVec2::new(96, 48));
+
+    // Nothing submitted while suspended is left over for this frame
+    submit_background(&mut renderer, material, UVec2::new(96, 48));
+    let image = render(&mut renderer, UVec2::new(96, 48));
+    assert_eq!(renderer.get_frame_stats().sprite_instance_count, 1);
+    assert_eq!(image.dimensions(), (96, 48));
+    for (x, y) in [(0, 0), (95, 0), (0, 47), (95, 47)] {
+        assert_eq!(image.get_pixel(x, y).0, [255, 0, 0, 255], "at {}, {}", x, y);
+    }
+}
+
+#[test]
+fn a_new_surface_format_gets_new_pipelines() {
+    let Ok(mut renderer) = pollster::block_on(Renderer::new_headless(64, 64)) else {
+        println!("No graphics adapter available, skipping the surface format test");
+        return;
+    };
+    let material = create_white_material(&mut renderer);
+
+    let size = UVec2::new(64, 64);
+    renderer.set_surface_format(wgpu::TextureFormat::Bgra8UnormSrgb);
+    submit_background(&mut renderer, material, size);
+    let image = render(&mut renderer, size);
+    // Read back in the order of the format
+    assert_eq!(image.get_pixel(32, 32).0, [0, 0, 255, 255]);
+}
+
+#[test]
+fn anchored_sprites_follow_a_new_ui_reference() {
+    let Ok(mut renderer) = pollster::block_on(Renderer::new_headless(64, 64)) else {
+        println!("No graphics adapter available, skipping the UI reference test");
+        return;
+    };
+    let material = create_white_material(&mut renderer);
+    let size = UVec2::new(640, 360);
+    renderer.resize(size.x, size.y);
+    assert!(renderer.take_ui_layout_changed());
+    assert!(!renderer.take_ui_layout_changed());
+
+    // 20 units from the bottom right corner, the same reference space as the screen first
+    let render_corner = |renderer: &mut Renderer| {
+        renderer.submit(&SpriteRenderJob {
+            anchor: SpriteAnchor::BottomRight,
+            material,
+            ..SpriteRenderJob::solid(Vec2::splat(-40.0), Vec2::splat(20.0), RED, 0)
+        });
+        render(renderer, size)
+    };
+    renderer.set_ui_reference(size.as_vec2(), FitPolicy::Contain);
+    let image = render_corner(&mut renderer);
+    assert_eq!(image.get_pixel(610, 330).0, [255, 0, 0, 255]);
+
+    // Half the reference, every unit is two pixels
+    renderer.set_ui_reference(size.as_vec2() * 0.5, FitPolicy::Contain);
+    assert!(renderer.take_ui_layout_changed());
+    let image = render_corner(&mut renderer);
+    assert_eq!(image.get_pixel(580, 300).0, [255, 0, 0, 255]);
+    assert_ne!(image.get_pixel(610, 330).0, [255, 0, 0, 255]);
+
+    // Setting the same reference again moves nothing
+    renderer.set_ui_reference(size.as_vec2() * 0.5, FitPolicy::Contain);
+    assert!(!renderer.take_ui_layout_changed());
+}
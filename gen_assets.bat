@@ -3,4 +3,5 @@ cargo run -p tools -- mesh .\assets\champions\brute\Brute.FBX -s .\assets\champi
 cargo run -p tools -- texture .\assets\champions\brute\textures\MaleBruteA_Body_diffuse1_ncl1_1.PNG -o .\assets\champions\brute\textures\MaleBruteA_Body_diffuse1_ncl1_1.dat
 cargo run -p tools -- animation .\assets\champions\brute\animations\Brute_Idle.FBX -s .\assets\champions\brute\brute.json -o .\assets\champions\brute\animations\Brute_Idle.dat
 cargo run -p tools -- animation .\assets\champions\brute\animations\Brute_Run.FBX -s .\assets\champions\brute\brute.json -o .\assets\champions\brute\animations\Brute_Run.dat
-cargo run -p tools -- animation .\assets\champions\brute\animations\Rumba.FBX -s .\assets\champions\brute\brute.json -o .\assets\champions\brute\animations\Rumba.dat
\ No newline at end of file
+cargo run -p tools -- animation .\assets\champions\brute\animations\Rumba.FBX -s .\assets\champions\brute\brute.json -o .\assets\champions\brute\animations\Rumba.dat
+cargo run -p tools -- bundle .\assets\champions\brute\bundle.json -o .\assets\champions\brute\brute.bundle
\ No newline at end of file
@@ -0,0 +1,4 @@
+mod server;
+pub use server::Server;
+mod simulation;
+pub mod websocket;
@@ -1,3 +1,31 @@
-fn main() {
-    println!("Hello, world!");
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use server::Server;
+
+const DEFAULT_ADDRESS: &str = "0.0.0.0:7777";
+const DEFAULT_WEBSOCKET_ADDRESS: &str = "0.0.0.0:7778";
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    // server [address] [websocket address]
+    let mut args = std::env::args().skip(1);
+    let address = args.next().unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+    let websocket_address = args
+        .next()
+        .unwrap_or_else(|| DEFAULT_WEBSOCKET_ADDRESS.to_string());
+    let mut server = Server::bind(&address)?;
+    log::info!("Listening on {}", server.local_addr()?);
+    let websocket_address = server.listen_websocket(&websocket_address)?;
+    log::info!("Listening for WebSockets on {}", websocket_address);
+
+    let start = Instant::now();
+    loop {
+        server.update(start.elapsed().as_secs_f64())?;
+        // Well below the tick duration, the server catches up on the ticks it slept through
+        thread::sleep(Duration::from_millis(2));
+    }
 }
@@ -0,0 +1,366 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+};
+
+use anyhow::Context;
+use shared::{
+    movement::MoveInput,
+    net::{Connection, Message, QuantizationBounds, SERVER_TICK_RATE, SNAPSHOT_INTERVAL},
+};
+
+use crate::{
+    simulation::Simulation,
+    websocket::{WebSocketListener, WebSocketPeer},
+};
+
+const MAX_CLIENTS: usize = 16;
+const MAX_DATAGRAM_SIZE: usize = 1200;
+const MAX_CATCH_UP_TICKS: u32 = 10; // Beyond that the server just falls behind
+// Inputs a client may send ahead of the tick rate before they get dropped
+const MAX_INPUT_BURST: f32 = 10.0;
+
+// Where a client talks from, browsers can only use WebSockets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Peer {
+    Udp(SocketAddr),
+    WebSocket(SocketAddr),
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Udp(address) => write!(f, "{}", address),
+            Peer::WebSocket(address) => write!(f, "{} (WebSocket)", address),
+        }
+    }
+}
+
+// The sockets of both kinds of peers
+struct Transport {
+    socket: UdpSocket,
+    listener: Option<WebSocketListener>,
+    websockets: HashMap<SocketAddr, WebSocketPeer>,
+}
+
+impl Transport {
+    // Lost datagrams are normal for UDP, failing to send is treated the same way
+    fn send(&mut self, peer: Peer, datagram: &[u8]) {
+        match peer {
+            Peer::Udp(address) => {
+                if let Err(e) = self.socket.send_to(datagram, address) {
+                    log::debug!("Failed to send to {}: {}", address, e);
+                }
+            }
+            Peer::WebSocket(address) => {
+                if let Some(websocket) = self.websockets.get_mut(&address) {
+                    websocket.send(datagram);
+                }
+            }
+        }
+    }
+}
+
+struct Client {
+    connection: Connection,
+    entity_id: u32,
+    last_input_tick: Option<u32>,
+    input_allowance: f32, // Refilled by one input per server tick
+}
+
+impl Client {
+    // Stale, duplicate and flooded inputs are dropped
+    fn accept_input(&mut self, tick: u32) -> bool {
+        if self
+            .last_input_tick
+            .is_some_and(|last| tick.wrapping_sub(last) as i32 <= 0)
+            || self.input_allowance < 1.0
+        {
+            return false;
+        }
+
+        self.last_input_tick = Some(tick);
+        self.input_allowance -= 1.0;
+        true
+    }
+}
+
+// Runs the simulation at a fixed rate and talks to the clients over one UDP socket, and
+// optionally WebSockets for the browser. Time is passed in by the caller so tests can run
+// faster than real time.
+pub struct Server {
+    transport: Transport,
+    bounds: QuantizationBounds,
+    clients: HashMap<Peer, Client>,
+    simulation: Simulation,
+    previous_time: Option<f64>,
+    time_since_tick: f64,
+}
+
+impl Server {
+    pub fn bind(address: impl ToSocketAddrs) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(address).context("Failed to bind the server socket")?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            transport: Transport {
+                socket,
+                listener: None,
+                websockets: HashMap::new(),
+            },
+            bounds: QuantizationBounds::default(),
+            clients: HashMap::new(),
+            simulation: Simulation::new(),
+            previous_time: None,
+            time_since_tick: 0.0,
+        })
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.transport.socket.local_addr()?)
+    }
+
+    // Also accepts clients over WebSockets, returns the address it listens on
+    pub fn listen_websocket(&mut self, address: impl ToSocketAddrs) -> anyhow::Result<SocketAddr> {
+        let listener = WebSocketListener::bind(address)?;
+        let address = listener.local_addr()?;
+        self.transport.listener = Some(listener);
+        Ok(address)
+    }
+
+    pub fn get_client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn update(&mut self, now: f64) -> anyhow::Result<()> {
+        self.receive(now)?;
+
+        let tick_duration = 1.0 / SERVER_TICK_RATE as f64;
+        self.time_since_tick += now - self.previous_time.unwrap_or(now);
+        self.previous_time = Some(now);
+
+        let mut ticks = 0;
+        while self.time_since_tick >= tick_duration {
+            self.time_since_tick -= tick_duration;
+            ticks += 1;
+            if ticks > MAX_CATCH_UP_TICKS {
+                self.time_since_tick = 0.0;
+                break;
+            }
+
+            for client in self.clients.values_mut() {
+                client.input_allowance = (client.input_allowance + 1.0).min(MAX_INPUT_BURST);
+            }
+            self.simulation.step(tick_duration as f32);
+            if self.simulation.get_tick().is_multiple_of(SNAPSHOT_INTERVAL) {
+                self.broadcast_snapshot(now);
+            }
+        }
+
+        self.drop_timed_out_clients(now);
+        for (peer, client) in self.clients.iter_mut() {
+            for datagram in client.connection.get_resends(now) {
+                self.transport.send(*peer, &datagram);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn receive(&mut self, now: f64) -> anyhow::Result<()> {
+        let mut buffer = [0u8; MAX_DATAGRAM_SIZE];
+        loop {
+            match self.transport.socket.recv_from(&mut buffer) {
+                Ok((length, address)) => {
+                    self.handle_datagram(Peer::Udp(address), &buffer[..length], now)
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // Windows reports an earlier send to a closed port on the next receive
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e).context("Failed to receive"),
+            }
+        }
+
+        if let Some(listener) = &mut self.transport.listener {
+            for websocket in listener.accept() {
+                self.transport
+                    .websockets
+                    .insert(websocket.get_address(), websocket);
+            }
+        }
+        let mut received = Vec::new();
+        for (address, websocket) in self.transport.websockets.iter_mut() {
+            for datagram in websocket.receive() {
+                received.push((*address, datagram));
+            }
+        }
+        for (address, datagram) in received {
+            self.handle_datagram(Peer::WebSocket(address), &datagram, now);
+        }
+
+        // A closed WebSocket is a client that left, it can't come back on the same socket
+        let closed: Vec<SocketAddr> = self
+            .transport
+            .websockets
+            .iter()
+            .filter(|(_, websocket)| websocket.is_closed())
+            .map(|(address, _)| *address)
+            .collect();
+        for address in closed {
+            self.transport.websockets.remove(&address);
+            if self.clients.contains_key(&Peer::WebSocket(address)) {
+                log::info!("{} closed its WebSocket", address);
+                self.remove_client(Peer::WebSocket(address));
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_datagram(&mut self, peer: Peer, datagram: &[u8], now: f64) {
+        // Unknown peers only get state once they sent a Join
+        let mut new_connection = None;
+        let connection = match self.clients.get_mut(&peer) {
+            Some(client) => &mut client.connection,
+            None => new_connection.insert(Connection::new(now)),
+        };
+
+        let received = connection.receive(datagram, now);
+        if let Some(reply) = &received.reply {
+            self.transport.send(peer, reply);
+        }
+        let Some(payload) = received.payload else {
+            return;
+        };
+        let message = match Message::decode(&payload, &self.bounds) {
+            Ok(message) => message,
+            Err(e) => {
+                log::debug!("Dropped a message from {}: {}", peer, e);
+                return;
+            }
+        };
+
+        match (message, new_connection) {
+            (Message::Join { name }, Some(connection)) => {
+                self.accept_client(peer, connection, &name, now)
+            }
+            (Message::Leave, None) => {
+                log::info!("{} left", peer);
+                self.remove_client(peer);
+            }
+            (
+                Message::ClientInput {
+                    tick,
+                    action_bits,
+                    cursor_world,
+                },
+                None,
+            ) => {
+                if let Some(client) = self.clients.get_mut(&peer)
+                    && client.accept_input(tick)
+                {
+                    let input = MoveInput {
+                        action_bits,
+                        cursor_world,
+                    };
+                    self.simulation.push_input(client.entity_id, tick, input);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn accept_client(&mut self, peer: Peer, connection: Connection, name: &str, now: f64) {
+        if self.clients.len() >= MAX_CLIENTS {
+            log::warn!("Rejected {} ({}), the server is full", name, peer);
+            return;
+        }
+
+        let entity_id = self.simulation.spawn_player();
+        let mut client = Client {
+            connection,
+            entity_id,
+            last_input_tick: None,
+            input_allowance: MAX_INPUT_BURST,
+        };
+
+        let accepted = Message::Accepted { entity_id }.encode(&self.bounds);
+        let datagram = client.connection.send(&accepted, true, now);
+        self.transport.send(peer, &datagram);
+
+        log::info!("{} joined from {} as entity {}", name, peer, entity_id);
+        self.clients.insert(peer, client);
+    }
+
+    fn remove_client(&mut self, peer: Peer) {
+        if let Some(client) = self.clients.remove(&peer) {
+            self.simulation.despawn_player(client.entity_id);
+        }
+    }
+
+    fn drop_timed_out_clients(&mut self, now: f64) {
+        let timed_out: Vec<Peer> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.connection.is_timed_out(now))
+            .map(|(peer, _)| *peer)
+            .collect();
+
+        for peer in timed_out {
+            log::info!("{} timed out", peer);
+            self.remove_client(peer);
+        }
+    }
+
+    fn broadcast_snapshot(&mut self, now: f64) {
+        let tick = self.simulation.get_tick();
+        let entities = self.simulation.get_entity_states();
+
+        // Encoded per client, each one gets its own input acknowledged
+        for (peer, client) in self.clients.iter_mut() {
+            let snapshot = Message::ServerSnapshot {
+                tick,
+                last_input_tick: self.simulation.get_last_input_tick(client.entity_id),
+                entities: entities.clone(),
+            }
+            .encode(&self.bounds);
+            let datagram = client.connection.send(&snapshot, false, now);
+            self.transport.send(*peer, &datagram);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_client() -> Client {
+        Client {
+            connection: Connection::new(0.0),
+            entity_id: 1,
+            last_input_tick: None,
+            input_allowance: MAX_INPUT_BURST,
+        }
+    }
+
+    #[test]
+    fn flooded_inputs_are_dropped() {
+        let mut client = get_client();
+        let accepted = (1..=100).filter(|&tick| client.accept_input(tick)).count();
+        assert_eq!(accepted, MAX_INPUT_BURST as usize);
+    }
+
+    #[test]
+    fn stale_inputs_are_dropped() {
+        let mut client = get_client();
+        assert!(client.accept_input(5));
+        assert!(!client.accept_input(5));
+        assert!(!client.accept_input(3));
+        assert!(client.accept_input(6));
+
+        // Tick numbers wrap around
+        client.last_input_tick = Some(u32::MAX);
+        assert!(client.accept_input(0));
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::VecDeque;
+
+use shared::{
+    math::*,
+    movement::{MoveInput, simulate_body},
+    net::EntityState,
+    physics::{BodyId, BodySettings, CollisionLayer, CollisionShape, PhysicsWorld},
+};
+
+const PLAYER_RADIUS: f32 = 32.0;
+const SPAWN_RADIUS: f32 = 200.0;
+// Inputs arriving in a burst wait for their tick, a longer backlog only adds latency
+const MAX_QUEUED_INPUTS: usize = 4;
+
+pub const ANIM_IDLE: u8 = 0;
+pub const ANIM_RUN: u8 = 1;
+
+struct Player {
+    entity_id: u32,
+    body_id: BodyId,
+    queued_inputs: VecDeque<(u32, MoveInput)>,
+    input: MoveInput, // Repeated on ticks without a new input
+    last_input_tick: u32,
+    facing: f32,
+}
+
+// The headless part of the game, only players walking around for now
+pub struct Simulation {
+    physics_world: PhysicsWorld,
+    players: Vec<Player>,
+    next_entity_id: u32,
+    tick: u32,
+}
+
+impl Simulation {
+    pub fn new() -> Self {
+        Self {
+            physics_world: PhysicsWorld::new(),
+            players: Vec::new(),
+            next_entity_id: 1,
+            tick: 0,
+        }
+    }
+
+    pub fn get_tick(&self) -> u32 {
+        self.tick
+    }
+
+    pub fn spawn_player(&mut self) -> u32 {
+        let entity_id = self.next_entity_id;
+        self.next_entity_id += 1;
+
+        // Spread around the origin so new players don't spawn inside each other
+        let angle = entity_id as f32 * 2.4;
+        let position = Vec2::new(angle.cos(), angle.sin()) * SPAWN_RADIUS;
+        let body_id = self.physics_world.create_rigid_body(&BodySettings {
+            position,
+            velocity: Vec2::ZERO,
+            layer: CollisionLayer::Player,
+            shape: &CollisionShape::Circle {
+                radius: PLAYER_RADIUS,
+            },
+            listen_to_contact_events: false,
+        });
+
+        self.players.push(Player {
+            entity_id,
+            body_id,
+            queued_inputs: VecDeque::new(),
+            input: MoveInput::default(),
+            last_input_tick: 0,
+            facing: 0.0,
+        });
+        entity_id
+    }
+
+    pub fn despawn_player(&mut self, entity_id: u32) {
+        if let Some(index) = self.players.iter().position(|p| p.entity_id == entity_id) {
+            let player = self.players.swap_remove(index);
+            self.physics_world.remove_body(player.body_id);
+        }
+    }
+
+    // One queued input is applied per tick, in the order they were sent
+    pub fn push_input(&mut self, entity_id: u32, tick: u32, input: MoveInput) {
+        if let Some(player) = self.players.iter_mut().find(|p| p.entity_id == entity_id) {
+            if player.queued_inputs.len() >= MAX_QUEUED_INPUTS {
+                player.queued_inputs.pop_front();
+            }
+            player.queued_inputs.push_back((tick, input));
+        }
+    }
+
+    // The client input tick the player state is at, 0 before any input
+    pub fn get_last_input_tick(&self, entity_id: u32) -> u32 {
+        self.players
+            .iter()
+            .find(|p| p.entity_id == entity_id)
+            .map_or(0, |player| player.last_input_tick)
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        for player in self.players.iter_mut() {
+            let Some(state) = self.physics_world.get_state(player.body_id) else {
+                continue;
+            };
+
+            if let Some((tick, input)) = player.queued_inputs.pop_front() {
+                player.input = input;
+                player.last_input_tick = tick;
+            }
+
+            // The physics step moves the body by this velocity, so without collisions it
+            // ends up where the client predicted it
+            let velocity = simulate_body(state, &player.input, dt).velocity;
+            if velocity != Vec2::ZERO {
+                // Around the up axis, matching the client's rotation from the xz velocity
+                player.facing = velocity.x.atan2(velocity.y);
+            }
+            self.physics_world.set_velocity(player.body_id, velocity);
+        }
+
+        self.physics_world.step_simulation(dt);
+        self.tick = self.tick.wrapping_add(1);
+    }
+
+    pub fn get_entity_states(&self) -> Vec<EntityState> {
+        self.players
+            .iter()
+            .filter_map(|player| {
+                let state = self.physics_world.get_state(player.body_id)?;
+                let moving = state.velocity.length_squared() > 1.0;
+                Some(EntityState {
+                    id: player.entity_id,
+                    position: state.position,
+                    velocity: state.velocity,
+                    facing: player.facing,
+                    anim_state: if moving { ANIM_RUN } else { ANIM_IDLE },
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::{
+        movement::MIN_TARGET_DISTANCE,
+        net::ACTION_MOVE,
+    };
+
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    #[test]
+    fn players_walk_to_the_cursor_and_stop() {
+        let mut simulation = Simulation::new();
+        let id = simulation.spawn_player();
+        let target = Vec2::new(600.0, -300.0);
+        simulation.push_input(
+            id,
+            1,
+            MoveInput {
+                action_bits: ACTION_MOVE,
+                cursor_world: target,
+            },
+        );
+
+        for _ in 0..300 {
+            simulation.step(DT);
+        }
+
+        let state = simulation.get_entity_states()[0];
+        assert!(state.position.distance(target) <= MIN_TARGET_DISTANCE + 1.0);
+        assert_eq!(state.anim_state, ANIM_IDLE);
+        assert_eq!(simulation.get_tick(), 300);
+        assert_eq!(simulation.get_last_input_tick(id), 1);
+    }
+
+    #[test]
+    fn queued_inputs_are_applied_one_per_tick() {
+        let mut simulation = Simulation::new();
+        let id = simulation.spawn_player();
+        for tick in 1..=6 {
+            simulation.push_input(id, tick, MoveInput::default());
+        }
+
+        // The two oldest were dropped to keep the backlog short
+        simulation.step(DT);
+        assert_eq!(simulation.get_last_input_tick(id), 3);
+        simulation.step(DT);
+        assert_eq!(simulation.get_last_input_tick(id), 4);
+        for _ in 0..4 {
+            simulation.step(DT);
+        }
+        assert_eq!(simulation.get_last_input_tick(id), 6);
+    }
+
+    #[test]
+    fn despawned_players_leave_the_snapshot() {
+        let mut simulation = Simulation::new();
+        let a = simulation.spawn_player();
+        let b = simulation.spawn_player();
+        simulation.despawn_player(a);
+        simulation.step(DT);
+
+        let ids: Vec<u32> = simulation
+            .get_entity_states()
+            .iter()
+            .map(|s| s.id)
+            .collect();
+        assert_eq!(ids, vec![b]);
+    }
+}
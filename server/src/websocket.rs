@@ -0,0 +1,424 @@
+// WebSockets for the browser clients, which can't send UDP. Only what the game needs: the
+// opening handshake and unfragmented binary messages, see RFC 6455. Every message carries
+// one datagram of the same reliability layer as UDP, so both kinds of clients are handled
+// alike once their datagrams arrived.
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use anyhow::Context;
+
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_HANDSHAKE_SIZE: usize = 4096;
+const MAX_MESSAGE_SIZE: usize = u16::MAX as usize; // Datagrams are far smaller
+
+pub const OPCODE_BINARY: u8 = 0x2;
+pub const OPCODE_CLOSE: u8 = 0x8;
+pub const OPCODE_PING: u8 = 0x9;
+pub const OPCODE_PONG: u8 = 0xa;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub opcode: u8,
+    pub payload: Vec<u8>, // Unmasked
+}
+
+// A connection that sent its HTTP upgrade request only partly so far
+struct Handshake {
+    stream: TcpStream,
+    address: SocketAddr,
+    request: Vec<u8>,
+}
+
+pub struct WebSocketListener {
+    listener: TcpListener,
+    handshakes: Vec<Handshake>,
+}
+
+impl WebSocketListener {
+    pub fn bind(address: impl ToSocketAddrs) -> anyhow::Result<Self> {
+        let listener =
+            TcpListener::bind(address).context("Failed to bind the WebSocket listener")?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            handshakes: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    // The peers that finished their handshake since the last call
+    pub fn accept(&mut self) -> Vec<WebSocketPeer> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    // Datagrams are small and shouldn't wait for each other
+                    let configured = stream
+                        .set_nonblocking(true)
+                        .and_then(|_| stream.set_nodelay(true));
+                    if let Err(e) = configured {
+                        log::debug!("Dropped {}: {}", address, e);
+                        continue;
+                    }
+                    self.handshakes.push(Handshake {
+                        stream,
+                        address,
+                        request: Vec::new(),
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::debug!("Failed to accept: {}", e);
+                    break;
+                }
+            }
+        }
+
+        let mut accepted = Vec::new();
+        let mut index = 0;
+        while index < self.handshakes.len() {
+            match self.handshakes[index].advance() {
+                Ok(false) => index += 1,
+                Ok(true) => {
+                    let handshake = self.handshakes.swap_remove(index);
+                    accepted.push(WebSocketPeer::new(handshake.stream, handshake.address));
+                }
+                Err(e) => {
+                    let handshake = self.handshakes.swap_remove(index);
+                    log::debug!("Handshake with {} failed: {:#}", handshake.address, e);
+                }
+            }
+        }
+        accepted
+    }
+}
+
+impl Handshake {
+    // Reads what arrived, true once the request is complete and answered
+    fn advance(&mut self) -> anyhow::Result<bool> {
+        let mut buffer = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => anyhow::bail!("Closed during the handshake"),
+                Ok(length) => self.request.extend_from_slice(&buffer[..length]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if self.request.len() > MAX_HANDSHAKE_SIZE {
+            anyhow::bail!("The upgrade request is too long");
+        }
+        if !self.request.ends_with(b"\r\n\r\n") {
+            return Ok(false);
+        }
+
+        let request = std::str::from_utf8(&self.request).context("The request isn't text")?;
+        let key = request
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("Sec-WebSocket-Key"))
+            .map(|(_, value)| value.trim())
+            .context("No Sec-WebSocket-Key in the request")?;
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            get_accept_key(key)
+        );
+        // A few bytes that fit into any socket buffer
+        self.stream.write_all(response.as_bytes())?;
+        Ok(true)
+    }
+}
+
+// One browser client, its messages are the datagrams
+pub struct WebSocketPeer {
+    stream: TcpStream,
+    address: SocketAddr,
+    received: Vec<u8>, // Of frames that didn't arrive in full yet
+    unsent: Vec<u8>,   // What the socket didn't take yet, sent before anything new
+    closed: bool,
+}
+
+impl WebSocketPeer {
+    fn new(stream: TcpStream, address: SocketAddr) -> Self {
+        Self {
+            stream,
+            address,
+            received: Vec::new(),
+            unsent: Vec::new(),
+            closed: false,
+        }
+    }
+
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    // Closed by the client, or dropped for breaking the protocol
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    // The binary messages that arrived in full since the last call
+    pub fn receive(&mut self) -> Vec<Vec<u8>> {
+        let mut buffer = [0u8; 4096];
+        while !self.closed {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => self.closed = true,
+                Ok(length) => self.received.extend_from_slice(&buffer[..length]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::debug!("Failed to receive from {}: {}", self.address, e);
+                    self.closed = true;
+                }
+            }
+        }
+
+        let mut messages = Vec::new();
+        let mut read = 0;
+        while !self.closed {
+            match decode_frame(&self.received[read..]) {
+                Ok(Some((frame, length))) => {
+                    read += length;
+                    match frame.opcode {
+                        OPCODE_BINARY => messages.push(frame.payload),
+                        OPCODE_PING => self.send_frame(OPCODE_PONG, &frame.payload),
+                        OPCODE_CLOSE => {
+                            self.send_frame(OPCODE_CLOSE, &[]);
+                            self.closed = true;
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log::debug!("Dropped {}: {}", self.address, e);
+                    self.closed = true;
+                }
+            }
+        }
+        self.received.drain(..read);
+        messages
+    }
+
+    pub fn send(&mut self, datagram: &[u8]) {
+        self.send_frame(OPCODE_BINARY, datagram);
+    }
+
+    fn send_frame(&mut self, opcode: u8, payload: &[u8]) {
+        if self.closed {
+            return;
+        }
+        self.unsent.extend(encode_frame(opcode, payload, None));
+        while !self.unsent.is_empty() {
+            match self.stream.write(&self.unsent) {
+                Ok(length) => {
+                    self.unsent.drain(..length);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::debug!("Failed to send to {}: {}", self.address, e);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Servers send unmasked frames, clients masked ones
+pub fn encode_frame(opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        length @ 0..=125 => frame.push(mask_bit | length as u8),
+        length @ 126..=0xffff => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+        }
+        None => frame.extend_from_slice(payload),
+    }
+    frame
+}
+
+// The frame at the start of the bytes and its length, None until all of it arrived
+pub fn decode_frame(bytes: &[u8]) -> Result<Option<(Frame, usize)>, String> {
+    let [first, second, ..] = *bytes else {
+        return Ok(None);
+    };
+    if first & 0x80 == 0 {
+        return Err("Fragmented messages aren't supported".to_string());
+    }
+    let opcode = first & 0x0f;
+    let masked = second & 0x80 != 0;
+
+    let mut read = 2;
+    let length = match second & 0x7f {
+        126 => {
+            let Some(length) = bytes.get(2..4) else {
+                return Ok(None);
+            };
+            read += 2;
+            u16::from_be_bytes([length[0], length[1]]) as usize
+        }
+        127 => {
+            let Some(length) = bytes.get(2..10) else {
+                return Ok(None);
+            };
+            read += 8;
+            u64::from_be_bytes(length.try_into().unwrap()) as usize
+        }
+        length => length as usize,
+    };
+    if length > MAX_MESSAGE_SIZE {
+        return Err(format!("A message of {} bytes is too long", length));
+    }
+
+    let mask = if masked {
+        let Some(mask) = bytes.get(read..read + 4) else {
+            return Ok(None);
+        };
+        read += 4;
+        Some([mask[0], mask[1], mask[2], mask[3]])
+    } else {
+        None
+    };
+    let Some(payload) = bytes.get(read..read + length) else {
+        return Ok(None);
+    };
+    let payload = match mask {
+        Some(mask) => payload
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(b, m)| b ^ m)
+            .collect(),
+        None => payload.to_vec(),
+    };
+    Ok(Some((Frame { opcode, payload }, read + length)))
+}
+
+// What the server answers the key of the client with
+pub fn get_accept_key(key: &str) -> String {
+    encode_base64(&get_sha1(format!("{}{}", key, HANDSHAKE_GUID).as_bytes()))
+}
+
+// Only for the handshake, it proves nothing about security
+fn get_sha1(bytes: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(bytes.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..20 => ((b & c) | (!b & d), 0x5a827999),
+                20..40 => (b ^ c ^ d, 0x6ed9eba1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(added);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let value = chunk.iter().enumerate().fold(0u32, |value, (i, byte)| {
+            value | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(value >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_key_matches_the_rfc() {
+        assert_eq!(
+            get_accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(encode_base64(b"ab"), "YWI=");
+        assert_eq!(encode_base64(b"a"), "YQ==");
+    }
+
+    #[test]
+    fn frames_round_trip_in_pieces() {
+        let long = vec![7u8; 300];
+        let mut bytes = encode_frame(OPCODE_BINARY, b"hello", Some([1, 2, 3, 4]));
+        bytes.extend(encode_frame(OPCODE_BINARY, &long, None));
+
+        // Nothing until the whole frame is there
+        assert_eq!(decode_frame(&bytes[..6]), Ok(None));
+        let (frame, length) = decode_frame(&bytes).unwrap().unwrap();
+        assert_eq!(frame.payload, b"hello");
+        assert_eq!(length, 2 + 4 + 5);
+        let (frame, length) = decode_frame(&bytes[length..]).unwrap().unwrap();
+        assert_eq!(frame.opcode, OPCODE_BINARY);
+        assert_eq!(frame.payload, long);
+        assert_eq!(length, 4 + 300);
+
+        let fragment = [OPCODE_BINARY, 0];
+        assert!(decode_frame(&fragment).is_err());
+    }
+}
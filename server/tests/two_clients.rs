@@ -0,0 +1,181 @@
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    thread,
+    time::Duration,
+};
+
+use server::Server;
+use shared::{
+    math::Vec2,
+    net::{
+        ACTION_MOVE, CONNECTION_TIMEOUT, Connection, EntityState, Message, QuantizationBounds,
+        SERVER_TICK_RATE,
+    },
+};
+
+const DT: f64 = 1.0 / SERVER_TICK_RATE as f64;
+
+// Talks to the server over a real localhost socket, only the clock is simulated
+struct TestClient {
+    socket: UdpSocket,
+    connection: Connection,
+    entity_id: Option<u32>,
+    latest: Option<(u32, Vec<EntityState>)>,
+    tick: u32,
+}
+
+impl TestClient {
+    fn connect(server: SocketAddr, name: &str) -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(server).unwrap();
+        socket.set_nonblocking(true).unwrap();
+
+        let mut client = Self {
+            socket,
+            connection: Connection::new(0.0),
+            entity_id: None,
+            latest: None,
+            tick: 0,
+        };
+        client.send(
+            &Message::Join {
+                name: name.to_string(),
+            },
+            0.0,
+        );
+        client
+    }
+
+    fn send(&mut self, message: &Message, now: f64) {
+        let payload = message.encode(&QuantizationBounds::default());
+        let datagram = self.connection.send(&payload, message.is_reliable(), now);
+        self.socket.send(&datagram).unwrap();
+    }
+
+    fn send_input(&mut self, cursor_world: Vec2, now: f64) {
+        self.tick += 1;
+        self.send(
+            &Message::ClientInput {
+                tick: self.tick,
+                action_bits: ACTION_MOVE,
+                cursor_world,
+            },
+            now,
+        );
+    }
+
+    fn receive(&mut self, now: f64) {
+        let mut buffer = [0u8; 1200];
+        loop {
+            let length = match self.socket.recv(&mut buffer) {
+                Ok(length) => length,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => panic!("{}", e),
+            };
+
+            let received = self.connection.receive(&buffer[..length], now);
+            if let Some(reply) = received.reply {
+                self.socket.send(&reply).unwrap();
+            }
+            let Some(payload) = received.payload else {
+                continue;
+            };
+            match Message::decode(&payload, &QuantizationBounds::default()).unwrap() {
+                Message::Accepted { entity_id } => self.entity_id = Some(entity_id),
+                Message::ServerSnapshot { tick, entities, .. } => {
+                    self.latest = Some((tick, entities))
+                }
+                message => panic!("Unexpected {:?}", message),
+            }
+        }
+    }
+
+    fn get_own_state(&self) -> Option<EntityState> {
+        let (_, entities) = self.latest.as_ref()?;
+        entities
+            .iter()
+            .find(|entity| Some(entity.id) == self.entity_id)
+            .copied()
+    }
+}
+
+fn step(server: &mut Server, clients: &mut [&mut TestClient], now: f64) {
+    // Gives the datagrams time to cross the loopback
+    thread::sleep(Duration::from_millis(1));
+    server.update(now).unwrap();
+    thread::sleep(Duration::from_millis(1));
+    for client in clients {
+        client.receive(now);
+    }
+}
+
+#[test]
+fn two_clients_see_each_other_move() {
+    let mut server = Server::bind("127.0.0.1:0").unwrap();
+    let address = server.local_addr().unwrap();
+    let mut a = TestClient::connect(address, "A");
+    let mut b = TestClient::connect(address, "B");
+
+    let target_a = Vec2::new(400.0, 0.0);
+    let target_b = Vec2::new(-400.0, 300.0);
+    let mut now = 0.0;
+    for _ in 0..240 {
+        now += DT;
+        a.send_input(target_a, now);
+        b.send_input(target_b, now);
+        step(&mut server, &mut [&mut a, &mut b], now);
+    }
+
+    assert_eq!(server.get_client_count(), 2);
+    let (a_id, b_id) = (a.entity_id.unwrap(), b.entity_id.unwrap());
+    assert_ne!(a_id, b_id);
+
+    // Both walked to their targets and both clients got both entities
+    let a_state = a.get_own_state().unwrap();
+    let b_state = b.get_own_state().unwrap();
+    assert!(a_state.position.distance(target_a) < 15.0);
+    assert!(b_state.position.distance(target_b) < 15.0);
+    for client in [&a, &b] {
+        let (_, entities) = client.latest.as_ref().unwrap();
+        assert_eq!(entities.len(), 2);
+    }
+
+    // B stays, A leaves
+    a.send(&Message::Leave, now);
+    for _ in 0..10 {
+        now += DT;
+        b.send_input(target_b, now);
+        step(&mut server, &mut [&mut b], now);
+    }
+    assert_eq!(server.get_client_count(), 1);
+    let (_, entities) = b.latest.as_ref().unwrap();
+    assert_eq!(
+        entities.iter().map(|e| e.id).collect::<Vec<_>>(),
+        vec![b_id]
+    );
+}
+
+#[test]
+fn silent_clients_time_out() {
+    let mut server = Server::bind("127.0.0.1:0").unwrap();
+    let address = server.local_addr().unwrap();
+    let mut a = TestClient::connect(address, "A");
+    let mut b = TestClient::connect(address, "B");
+
+    let mut now = 0.0;
+    step(&mut server, &mut [&mut a, &mut b], now);
+    assert_eq!(server.get_client_count(), 2);
+
+    // Only B keeps sending, in big steps so the test doesn't take seconds
+    while now < CONNECTION_TIMEOUT + 1.0 {
+        now += 0.1;
+        b.send_input(Vec2::ZERO, now);
+        step(&mut server, &mut [&mut b], now);
+    }
+
+    assert_eq!(server.get_client_count(), 1);
+    let (_, entities) = b.latest.as_ref().unwrap();
+    assert_eq!(entities.len(), 1);
+    assert_eq!(Some(entities[0].id), b.entity_id);
+}
@@ -0,0 +1,160 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use server::{
+    Server,
+    websocket::{OPCODE_BINARY, OPCODE_CLOSE, decode_frame, encode_frame, get_accept_key},
+};
+use shared::{
+    math::Vec2,
+    net::{ACTION_MOVE, Connection, EntityState, Message, QuantizationBounds, SERVER_TICK_RATE},
+};
+
+const DT: f64 = 1.0 / SERVER_TICK_RATE as f64;
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+const MASK: [u8; 4] = [0x12, 0x34, 0x56, 0x78];
+
+// What a browser does, over a raw localhost TCP stream
+struct WebSocketClient {
+    stream: TcpStream,
+    received: Vec<u8>,
+    connection: Connection,
+    entity_id: Option<u32>,
+    latest: Option<Vec<EntityState>>,
+    tick: u32,
+}
+
+impl WebSocketClient {
+    fn connect(server: &mut Server, address: SocketAddr, name: &str) -> Self {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            address, KEY
+        );
+        stream.write_all(request.as_bytes()).unwrap();
+
+        // The server answers the handshake during its updates
+        let mut response = Vec::new();
+        stream.set_nonblocking(true).unwrap();
+        while !response.ends_with(b"\r\n\r\n") {
+            thread::sleep(Duration::from_millis(1));
+            server.update(0.0).unwrap();
+            let mut byte = [0u8; 1];
+            match stream.read(&mut byte) {
+                Ok(0) => panic!("The server closed the connection"),
+                Ok(_) => response.push(byte[0]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => panic!("{}", e),
+            }
+        }
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101"));
+        assert!(response.contains(&get_accept_key(KEY)));
+
+        let mut client = Self {
+            stream,
+            received: Vec::new(),
+            connection: Connection::new(0.0),
+            entity_id: None,
+            latest: None,
+            tick: 0,
+        };
+        client.send(
+            &Message::Join {
+                name: name.to_string(),
+            },
+            0.0,
+        );
+        client
+    }
+
+    fn send(&mut self, message: &Message, now: f64) {
+        let payload = message.encode(&QuantizationBounds::default());
+        let datagram = self.connection.send(&payload, message.is_reliable(), now);
+        self.send_datagram(&datagram);
+    }
+
+    fn send_datagram(&mut self, datagram: &[u8]) {
+        let frame = encode_frame(OPCODE_BINARY, datagram, Some(MASK));
+        self.stream.write_all(&frame).unwrap();
+    }
+
+    fn receive(&mut self, now: f64) {
+        let mut buffer = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(length) => self.received.extend_from_slice(&buffer[..length]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => panic!("{}", e),
+            }
+        }
+
+        while let Some((frame, length)) = decode_frame(&self.received).unwrap() {
+            self.received.drain(..length);
+            assert_eq!(frame.opcode, OPCODE_BINARY);
+
+            let received = self.connection.receive(&frame.payload, now);
+            if let Some(reply) = received.reply {
+                self.send_datagram(&reply);
+            }
+            let Some(payload) = received.payload else {
+                continue;
+            };
+            match Message::decode(&payload, &QuantizationBounds::default()).unwrap() {
+                Message::Accepted { entity_id } => self.entity_id = Some(entity_id),
+                Message::ServerSnapshot { entities, .. } => self.latest = Some(entities),
+                message => panic!("Unexpected {:?}", message),
+            }
+        }
+    }
+}
+
+fn step(server: &mut Server, client: &mut WebSocketClient, now: f64) {
+    thread::sleep(Duration::from_millis(1));
+    server.update(now).unwrap();
+    thread::sleep(Duration::from_millis(1));
+    client.receive(now);
+}
+
+#[test]
+fn browser_clients_play_over_websockets() {
+    let mut server = Server::bind("127.0.0.1:0").unwrap();
+    let address = server.listen_websocket("127.0.0.1:0").unwrap();
+    let mut client = WebSocketClient::connect(&mut server, address, "Browser");
+
+    let target = Vec2::new(300.0, -200.0);
+    let mut now = 0.0;
+    for _ in 0..240 {
+        now += DT;
+        client.tick += 1;
+        let input = Message::ClientInput {
+            tick: client.tick,
+            action_bits: ACTION_MOVE,
+            cursor_world: target,
+        };
+        client.send(&input, now);
+        step(&mut server, &mut client, now);
+    }
+
+    assert_eq!(server.get_client_count(), 1);
+    let entities = client.latest.as_ref().unwrap();
+    let own = entities
+        .iter()
+        .find(|entity| Some(entity.id) == client.entity_id)
+        .unwrap();
+    assert!(own.position.distance(target) < 15.0);
+
+    // Closing the socket is leaving
+    let close = encode_frame(OPCODE_CLOSE, &[], Some(MASK));
+    client.stream.write_all(&close).unwrap();
+    thread::sleep(Duration::from_millis(5));
+    server.update(now + DT).unwrap();
+    assert_eq!(server.get_client_count(), 0);
+}
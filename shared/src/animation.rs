@@ -0,0 +1,513 @@
+// The .dat format of animation clips, written by the tool and read by the client. The plain
+// variant stores every bone of every frame as f32s. The compressed one quantizes them:
+// rotations to the smallest three components in 48 bits, positions to 16 bits per axis over
+// the bounds of the clip, and a bone that never moves stores its position or rotation once.
+// Both decode to the same frames, sampling doesn't know which one was loaded.
+//
+// Plain: bone count (u32), frame count (u32), frames[frame][bone] as position (3 f32) and
+// rotation (w, x, y, z f32), then the time of each frame (f32).
+// Compressed: COMPRESSED_MARKER (u32), version (u8), bone count (u32), frame count (u32), the
+// bounds of the positions (6 f32), the time of each frame (f32), then per bone its flags (u8),
+// its positions (3 u16 each) and its rotations (48 bits each), one of each when constant.
+
+use std::fmt;
+
+use crate::math::{Quat, Vec3};
+
+// Where the plain variant has its bone count, which no clip comes close to
+const COMPRESSED_MARKER: u32 = u32::MAX;
+pub const COMPRESSED_VERSION: u8 = 1;
+
+const CONSTANT_POSITION: u8 = 1 << 0;
+const CONSTANT_ROTATION: u8 = 1 << 1;
+
+// The smallest three components of a unit quaternion lie within this
+const SMALLEST_THREE_RANGE: f32 = std::f32::consts::FRAC_1_SQRT_2;
+const SMALLEST_THREE_BITS: u32 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationDecodeError {
+    UnexpectedEnd,
+    UnsupportedVersion(u8),
+    TrailingBytes,
+}
+
+impl fmt::Display for AnimationDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "animation ended early"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported animation version {}", version)
+            }
+            Self::TrailingBytes => write!(f, "bytes left after the animation"),
+        }
+    }
+}
+
+impl std::error::Error for AnimationDecodeError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoneKey {
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+impl Default for BoneKey {
+    fn default() -> Self {
+        Self {
+            position: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AnimationClip {
+    pub bone_count: usize,
+    pub frames: Vec<BoneKey>, // frames[frame * bone_count + bone]
+    pub times: Vec<f32>,
+}
+
+// Largest difference between the frames of two clips of the same size
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AnimationError {
+    pub rotation_degrees: f32,
+    pub position: f32,
+}
+
+impl AnimationClip {
+    pub fn get_frame_count(&self) -> usize {
+        self.times.len()
+    }
+
+    // Min and max of every position, zero sized for an empty clip
+    pub fn get_bounds(&self) -> (Vec3, Vec3) {
+        if self.frames.is_empty() {
+            return (Vec3::ZERO, Vec3::ZERO);
+        }
+        self.frames.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), key| (min.min(key.position), max.max(key.position)),
+        )
+    }
+
+    pub fn get_error(&self, other: &AnimationClip) -> AnimationError {
+        self.frames
+            .iter()
+            .zip(&other.frames)
+            .fold(AnimationError::default(), |error, (a, b)| AnimationError {
+                rotation_degrees: error
+                    .rotation_degrees
+                    .max(a.rotation.angle_between(b.rotation).to_degrees()),
+                position: error.position.max(a.position.distance(b.position)),
+            })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.write_u32(self.bone_count as u32);
+        writer.write_u32(self.get_frame_count() as u32);
+        for key in &self.frames {
+            writer.write_f32s(&key.position.to_array());
+            writer.write_f32s(&[
+                key.rotation.w,
+                key.rotation.x,
+                key.rotation.y,
+                key.rotation.z,
+            ]);
+        }
+        writer.write_f32s(&self.times);
+        writer.bytes
+    }
+
+    pub fn encode_compressed(&self) -> Vec<u8> {
+        let frame_count = self.get_frame_count();
+        let (min, max) = self.get_bounds();
+
+        let mut writer = Writer::default();
+        writer.write_u32(COMPRESSED_MARKER);
+        writer.bytes.push(COMPRESSED_VERSION);
+        writer.write_u32(self.bone_count as u32);
+        writer.write_u32(frame_count as u32);
+        writer.write_f32s(&min.to_array());
+        writer.write_f32s(&max.to_array());
+        writer.write_f32s(&self.times);
+
+        for bone in 0..self.bone_count {
+            let keys = (0..frame_count).map(|frame| self.frames[frame * self.bone_count + bone]);
+            let positions: Vec<[u16; 3]> = keys
+                .clone()
+                .map(|key| quantize_position(key.position, min, max))
+                .collect();
+            let rotations: Vec<u64> = keys.map(|key| quantize_rotation(key.rotation)).collect();
+
+            // Compared after quantizing, so culling adds no error of its own
+            let mut flags = 0;
+            if frame_count > 0 && positions.windows(2).all(|pair| pair[0] == pair[1]) {
+                flags |= CONSTANT_POSITION;
+            }
+            if frame_count > 0 && rotations.windows(2).all(|pair| pair[0] == pair[1]) {
+                flags |= CONSTANT_ROTATION;
+            }
+            writer.bytes.push(flags);
+
+            let position_count = if flags & CONSTANT_POSITION != 0 {
+                1
+            } else {
+                frame_count
+            };
+            for position in positions.iter().take(position_count) {
+                for axis in position {
+                    writer.bytes.extend_from_slice(&axis.to_le_bytes());
+                }
+            }
+            let rotation_count = if flags & CONSTANT_ROTATION != 0 {
+                1
+            } else {
+                frame_count
+            };
+            for rotation in rotations.iter().take(rotation_count) {
+                writer.bytes.extend_from_slice(&rotation.to_le_bytes()[..6]);
+            }
+        }
+        writer.bytes
+    }
+
+    // Either variant
+    pub fn decode(bytes: &[u8]) -> Result<AnimationClip, AnimationDecodeError> {
+        let mut reader = Reader { bytes, index: 0 };
+        let first = reader.read_u32()?;
+        let clip = if first == COMPRESSED_MARKER {
+            let version = reader.read_u8()?;
+            if version != COMPRESSED_VERSION {
+                return Err(AnimationDecodeError::UnsupportedVersion(version));
+            }
+            Self::decode_compressed(&mut reader)?
+        } else {
+            Self::decode_plain(&mut reader, first as usize)?
+        };
+        if reader.index != bytes.len() {
+            return Err(AnimationDecodeError::TrailingBytes);
+        }
+        Ok(clip)
+    }
+
+    fn decode_plain(
+        reader: &mut Reader,
+        bone_count: usize,
+    ) -> Result<AnimationClip, AnimationDecodeError> {
+        let frame_count = reader.read_u32()? as usize;
+        let mut frames = Vec::with_capacity(reader.get_capacity(frame_count * bone_count, 28));
+        for _ in 0..frame_count * bone_count {
+            let position = Vec3::new(reader.read_f32()?, reader.read_f32()?, reader.read_f32()?);
+            let [w, x, y, z] = [
+                reader.read_f32()?,
+                reader.read_f32()?,
+                reader.read_f32()?,
+                reader.read_f32()?,
+            ];
+            frames.push(BoneKey {
+                position,
+                rotation: Quat::from_xyzw(x, y, z, w),
+            });
+        }
+        let times = reader.read_f32s(frame_count)?;
+        Ok(AnimationClip {
+            bone_count,
+            frames,
+            times,
+        })
+    }
+
+    fn decode_compressed(reader: &mut Reader) -> Result<AnimationClip, AnimationDecodeError> {
+        let bone_count = reader.read_u32()? as usize;
+        let frame_count = reader.read_u32()? as usize;
+        let min = Vec3::from_slice(&reader.read_f32s(3)?);
+        let max = Vec3::from_slice(&reader.read_f32s(3)?);
+        let times = reader.read_f32s(frame_count)?;
+
+        // Bones are stored one after the other, frames interleave them
+        let mut frames = vec![BoneKey::default(); frame_count * bone_count];
+        for bone in 0..bone_count {
+            let flags = reader.read_u8()?;
+
+            let position_count = if flags & CONSTANT_POSITION != 0 {
+                1
+            } else {
+                frame_count
+            };
+            let mut positions = Vec::with_capacity(reader.get_capacity(position_count, 6));
+            for _ in 0..position_count {
+                let quantized = [reader.read_u16()?, reader.read_u16()?, reader.read_u16()?];
+                positions.push(dequantize_position(quantized, min, max));
+            }
+            let rotation_count = if flags & CONSTANT_ROTATION != 0 {
+                1
+            } else {
+                frame_count
+            };
+            let mut rotations = Vec::with_capacity(reader.get_capacity(rotation_count, 6));
+            for _ in 0..rotation_count {
+                let mut quantized = [0u8; 8];
+                quantized[..6].copy_from_slice(reader.read_bytes(6)?);
+                rotations.push(dequantize_rotation(u64::from_le_bytes(quantized)));
+            }
+
+            for frame in 0..frame_count {
+                frames[frame * bone_count + bone] = BoneKey {
+                    position: positions[frame.min(positions.len() - 1)],
+                    rotation: rotations[frame.min(rotations.len() - 1)],
+                };
+            }
+        }
+        Ok(AnimationClip {
+            bone_count,
+            frames,
+            times,
+        })
+    }
+}
+
+fn quantize_position(position: Vec3, min: Vec3, max: Vec3) -> [u16; 3] {
+    let extent = (max - min).max(Vec3::splat(f32::EPSILON));
+    let normalized = ((position - min) / extent).clamp(Vec3::ZERO, Vec3::ONE);
+    (normalized * u16::MAX as f32)
+        .round()
+        .to_array()
+        .map(|axis| axis as u16)
+}
+
+fn dequantize_position(quantized: [u16; 3], min: Vec3, max: Vec3) -> Vec3 {
+    let normalized = Vec3::from_array(quantized.map(|axis| axis as f32)) / u16::MAX as f32;
+    min + (max - min) * normalized
+}
+
+// The index of the largest component in the top 2 bits of 48, then the other three in 15
+// bits each. The largest is made positive, q and -q are the same rotation.
+fn quantize_rotation(rotation: Quat) -> u64 {
+    let mut components = rotation.normalize().to_array();
+    let largest = (0..4)
+        .max_by(|a, b| components[*a].abs().total_cmp(&components[*b].abs()))
+        .unwrap_or(3);
+    if components[largest] < 0.0 {
+        components = components.map(|component| -component);
+    }
+
+    let max_value = ((1u32 << SMALLEST_THREE_BITS) - 1) as f32;
+    let mut quantized = (largest as u64) << 46;
+    let mut shift = 46;
+    for (index, component) in components.iter().enumerate() {
+        if index == largest {
+            continue;
+        }
+        shift -= SMALLEST_THREE_BITS;
+        let normalized = (component / SMALLEST_THREE_RANGE * 0.5 + 0.5).clamp(0.0, 1.0);
+        quantized |= ((normalized * max_value).round() as u64) << shift;
+    }
+    quantized
+}
+
+fn dequantize_rotation(quantized: u64) -> Quat {
+    let largest = ((quantized >> 46) & 0b11) as usize;
+    let max_value = ((1u32 << SMALLEST_THREE_BITS) - 1) as f32;
+    let mut components = [0.0; 4];
+    let mut shift = 46;
+    for (index, component) in components.iter_mut().enumerate() {
+        if index == largest {
+            continue;
+        }
+        shift -= SMALLEST_THREE_BITS;
+        let value = ((quantized >> shift) & ((1 << SMALLEST_THREE_BITS) - 1)) as f32;
+        *component = (value / max_value * 2.0 - 1.0) * SMALLEST_THREE_RANGE;
+    }
+    let sum: f32 = components
+        .iter()
+        .map(|component| component * component)
+        .sum();
+    components[largest] = (1.0 - sum).max(0.0).sqrt();
+    Quat::from_array(components).normalize()
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_f32s(&mut self, values: &[f32]) {
+        for value in values {
+            self.bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], AnimationDecodeError> {
+        let bytes = self
+            .bytes
+            .get(self.index..self.index + count)
+            .ok_or(AnimationDecodeError::UnexpectedEnd)?;
+        self.index += count;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, AnimationDecodeError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, AnimationDecodeError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, AnimationDecodeError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_f32(&mut self) -> Result<f32, AnimationDecodeError> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+    fn read_f32s(&mut self, count: usize) -> Result<Vec<f32>, AnimationDecodeError> {
+        let mut values = Vec::with_capacity(self.get_capacity(count, 4));
+        for _ in 0..count {
+            values.push(self.read_f32()?);
+        }
+        Ok(values)
+    }
+
+    // A broken header can't make us allocate more than the bytes could hold
+    fn get_capacity(&self, count: usize, size: usize) -> usize {
+        count.min((self.bytes.len() - self.index) / size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A walk cycle of 60 bones over 60 frames, the first 10 bones never move
+    fn get_clip() -> AnimationClip {
+        let bone_count = 60;
+        let frame_count = 60;
+        let mut frames = Vec::new();
+        for frame in 0..frame_count {
+            let t = frame as f32 / frame_count as f32 * std::f32::consts::TAU;
+            for bone in 0..bone_count {
+                let b = bone as f32;
+                if bone < 10 {
+                    frames.push(BoneKey {
+                        position: Vec3::new(0.0, b * 0.1, 0.0),
+                        rotation: Quat::from_rotation_y(b),
+                    });
+                    continue;
+                }
+                frames.push(BoneKey {
+                    position: Vec3::new((t + b).sin() * 0.5, b * 0.03, (t * 2.0 + b).cos()),
+                    rotation: Quat::from_euler(
+                        glam::EulerRot::YXZ,
+                        (t + b).sin() * 3.0,
+                        (t * 0.5 + b).cos() * 1.5,
+                        b,
+                    ),
+                });
+            }
+        }
+        AnimationClip {
+            bone_count,
+            frames,
+            times: (0..frame_count).map(|frame| frame as f32 / 30.0).collect(),
+        }
+    }
+
+    #[test]
+    fn plain_clips_round_trip_exactly() {
+        let clip = get_clip();
+        let bytes = clip.encode();
+        assert_eq!(bytes.len(), 8 + 60 * 60 * 28 + 60 * 4);
+        assert_eq!(AnimationClip::decode(&bytes), Ok(clip));
+    }
+
+    #[test]
+    fn compressed_clips_stay_within_the_error_bounds() {
+        let clip = get_clip();
+        let bytes = clip.encode_compressed();
+        let decoded = AnimationClip::decode(&bytes).unwrap();
+        assert_eq!(decoded.bone_count, clip.bone_count);
+        assert_eq!(decoded.times, clip.times);
+
+        let (min, max) = clip.get_bounds();
+        let error = clip.get_error(&decoded);
+        assert!(error.rotation_degrees < 0.1, "{:?}", error);
+        // In meters, half a millimeter over an extent of 2
+        assert!((max - min).max_element() <= 2.0);
+        assert!(error.position < 0.0005, "{:?}", error);
+
+        // The constant bones are stored once, the rest take 12 bytes a frame
+        let header = 4 + 1 + 8 + 24 + 60 * 4;
+        assert_eq!(bytes.len(), header + 60 + 10 * 12 + 50 * 60 * 12);
+        assert!(bytes.len() * 2 < clip.encode().len());
+    }
+
+    #[test]
+    fn rotations_keep_their_sign_and_their_largest_component() {
+        let rotations = [
+            Quat::IDENTITY,
+            Quat::from_xyzw(0.0, 0.0, 0.0, -1.0),
+            Quat::from_rotation_x(std::f32::consts::PI),
+            Quat::from_xyzw(0.5, -0.5, 0.5, -0.5),
+            Quat::from_axis_angle(Vec3::new(1.0, 2.0, -3.0).normalize(), 2.5),
+        ];
+        for rotation in rotations {
+            let decoded = dequantize_rotation(quantize_rotation(rotation));
+            assert!(
+                rotation.angle_between(decoded).to_degrees() < 0.1,
+                "{:?} came back as {:?}",
+                rotation,
+                decoded
+            );
+            assert!(decoded.is_normalized());
+        }
+    }
+
+    #[test]
+    fn broken_clips_are_rejected() {
+        let bytes = get_clip().encode_compressed();
+        assert_eq!(
+            AnimationClip::decode(&bytes[..bytes.len() - 1]),
+            Err(AnimationDecodeError::UnexpectedEnd)
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            AnimationClip::decode(&trailing),
+            Err(AnimationDecodeError::TrailingBytes)
+        );
+        let mut future = bytes;
+        future[4] = COMPRESSED_VERSION + 1;
+        assert_eq!(
+            AnimationClip::decode(&future),
+            Err(AnimationDecodeError::UnsupportedVersion(
+                COMPRESSED_VERSION + 1
+            ))
+        );
+        assert_eq!(
+            AnimationClip::decode(&[]),
+            Err(AnimationDecodeError::UnexpectedEnd)
+        );
+
+        // An empty clip is still a clip
+        let empty = AnimationClip::default();
+        assert_eq!(AnimationClip::decode(&empty.encode_compressed()), Ok(empty));
+    }
+}
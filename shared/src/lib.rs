@@ -1,4 +1,10 @@
+pub mod animation;
 pub mod math;
+pub mod movement;
+pub mod net;
 pub mod physics;
 pub mod pool;
+pub mod profiler;
+pub mod rng;
+pub mod team;
 pub mod transform;
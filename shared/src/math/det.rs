@@ -0,0 +1,133 @@
+// Math that gives the same bits on every platform, for the gameplay state the server and the
+// clients have to agree on. Adding, multiplying, dividing and square roots of floats are
+// correctly rounded everywhere, the transcendental functions are not: they come from the
+// platform and can differ in the last bits between x86_64 and wasm. The sines and cosines
+// here come from a table built at compile time instead, and states can be snapped to a
+// fixed grid so differences that do slip in don't grow step after step.
+
+use std::f64::consts::TAU;
+
+use super::Vec2;
+
+// Powers of two, so quantized values are exact in an f32
+pub const POSITION_STEP: f32 = 1.0 / 64.0;
+pub const DIRECTION_STEP: f32 = 1.0 / 65536.0;
+
+const TABLE_SIZE: usize = 4096; // Entries per turn, the last one repeats the first
+static SIN_TABLE: [f32; TABLE_SIZE + 1] = build_sin_table();
+
+// The closest multiple of the step
+pub fn quantize(value: f32, step: f32) -> f32 {
+    (value / step).round() * step
+}
+
+pub fn quantize_vec2(value: Vec2, step: f32) -> Vec2 {
+    Vec2::new(quantize(value.x, step), quantize(value.y, step))
+}
+
+// Unit length to within the direction step, zero for zero. Written out so it only uses
+// correctly rounded operations whatever glam does.
+pub fn qnormalize(value: Vec2) -> Vec2 {
+    let length_squared = value.x * value.x + value.y * value.y;
+    if length_squared == 0.0 || !length_squared.is_finite() {
+        return Vec2::ZERO;
+    }
+    let length = length_squared.sqrt();
+    quantize_vec2(
+        Vec2::new(value.x / length, value.y / length),
+        DIRECTION_STEP,
+    )
+}
+
+// Linearly interpolated from the table, within 1e-6 of sin near zero. Large angles lose
+// precision in the f32 like they do with sin.
+pub fn qsin(angle: f32) -> f32 {
+    sample_table(angle * (TABLE_SIZE as f32 / std::f32::consts::TAU))
+}
+
+pub fn qcos(angle: f32) -> f32 {
+    sample_table(angle * (TABLE_SIZE as f32 / std::f32::consts::TAU) + (TABLE_SIZE / 4) as f32)
+}
+
+// The position is in table entries
+fn sample_table(position: f32) -> f32 {
+    let floor = position.floor();
+    let fraction = position - floor;
+    let index = (floor as i64).rem_euclid(TABLE_SIZE as i64) as usize;
+    let from = SIN_TABLE[index];
+    let to = SIN_TABLE[index + 1];
+    from + (to - from) * fraction
+}
+
+// A quarter of a sine wave from a Taylor series, mirrored for the rest of the turn
+const fn build_sin_table() -> [f32; TABLE_SIZE + 1] {
+    const HALF: usize = TABLE_SIZE / 2;
+    const QUARTER: usize = TABLE_SIZE / 4;
+
+    let mut table = [0.0; TABLE_SIZE + 1];
+    let mut index = 0;
+    while index <= TABLE_SIZE {
+        let in_half = index % HALF;
+        let in_quarter = if in_half > QUARTER {
+            HALF - in_half
+        } else {
+            in_half
+        };
+        let value = get_series_sin(TAU * in_quarter as f64 / TABLE_SIZE as f64);
+        table[index] = if index % TABLE_SIZE >= HALF {
+            -value as f32
+        } else {
+            value as f32
+        };
+        index += 1;
+    }
+    table
+}
+
+// Converges to f64 precision up to a quarter turn
+const fn get_series_sin(x: f64) -> f64 {
+    let mut term = x;
+    let mut sum = x;
+    let mut n = 1;
+    while n < 12 {
+        term *= -x * x / ((2 * n) * (2 * n + 1)) as f64;
+        sum += term;
+        n += 1;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_sines_are_close_to_sin() {
+        for step in -20000..20000 {
+            let angle = step as f32 * 0.001;
+            assert!((qsin(angle) - angle.sin()).abs() < 1e-5, "sin {}", angle);
+            assert!((qcos(angle) - angle.cos()).abs() < 1e-5, "cos {}", angle);
+        }
+
+        // The quarter turns are exact
+        assert_eq!(qsin(0.0), 0.0);
+        assert_eq!(qcos(0.0), 1.0);
+        assert_eq!(SIN_TABLE[TABLE_SIZE / 4], 1.0);
+        assert_eq!(SIN_TABLE[3 * TABLE_SIZE / 4], -1.0);
+        assert_eq!(SIN_TABLE[TABLE_SIZE], 0.0);
+    }
+
+    #[test]
+    fn quantized_values_are_on_the_grid() {
+        assert_eq!(quantize(1.0 / 3.0, POSITION_STEP), 21.0 / 64.0);
+        assert_eq!(quantize(-0.005, POSITION_STEP), 0.0);
+
+        let direction = qnormalize(Vec2::new(3.0, -4.0));
+        assert_eq!(
+            direction,
+            Vec2::new(0.6, -0.8).map(|v| quantize(v, DIRECTION_STEP))
+        );
+        assert_eq!(qnormalize(Vec2::ZERO), Vec2::ZERO);
+        assert_eq!(qnormalize(Vec2::new(f32::INFINITY, 0.0)), Vec2::ZERO);
+    }
+}
@@ -0,0 +1,107 @@
+// The usual easing curves, mapping the progress of a tween from 0 to 1 onto how far the
+// value has moved. All of them start at 0 and end at 1, some go past 1 on the way.
+
+use std::f32::consts::PI;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    #[default]
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    SineIn,
+    SineOut,
+    SineInOut,
+    BackOut, // Overshoots a little and settles back
+}
+
+impl Easing {
+    // The progress is clamped to 0..1
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Self::Linear => t,
+            Self::QuadIn => t * t,
+            Self::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Self::QuadInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - 2.0 * (1.0 - t) * (1.0 - t)
+                }
+            }
+            Self::CubicIn => t * t * t,
+            Self::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Self::CubicInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - 4.0 * (1.0 - t).powi(3)
+                }
+            }
+            Self::SineIn => 1.0 - (t * PI * 0.5).cos(),
+            Self::SineOut => (t * PI * 0.5).sin(),
+            Self::SineInOut => 0.5 - 0.5 * (t * PI).cos(),
+            Self::BackOut => {
+                const OVERSHOOT: f32 = 1.70158;
+                let u = t - 1.0;
+                1.0 + u * u * ((OVERSHOOT + 1.0) * u + OVERSHOOT)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Easing; 11] = [
+        Easing::Linear,
+        Easing::QuadIn,
+        Easing::QuadOut,
+        Easing::QuadInOut,
+        Easing::CubicIn,
+        Easing::CubicOut,
+        Easing::CubicInOut,
+        Easing::SineIn,
+        Easing::SineOut,
+        Easing::SineInOut,
+        Easing::BackOut,
+    ];
+
+    #[test]
+    fn curves_start_at_zero_and_end_at_one() {
+        for easing in ALL {
+            assert!(easing.apply(0.0).abs() < 1e-6, "{:?}", easing);
+            assert!((easing.apply(1.0) - 1.0).abs() < 1e-6, "{:?}", easing);
+            assert_eq!(easing.apply(-1.0), easing.apply(0.0));
+            assert_eq!(easing.apply(2.0), easing.apply(1.0));
+        }
+    }
+
+    #[test]
+    fn curves_match_their_formulas() {
+        assert_eq!(Easing::QuadIn.apply(0.5), 0.25);
+        assert_eq!(Easing::QuadOut.apply(0.5), 0.75);
+        assert_eq!(Easing::CubicIn.apply(0.5), 0.125);
+        assert!((Easing::SineOut.apply(0.5) - 0.5f32.sqrt()).abs() < 1e-6);
+
+        // The in-out curves are symmetric around the middle
+        for easing in [Easing::QuadInOut, Easing::CubicInOut, Easing::SineInOut] {
+            assert!((easing.apply(0.5) - 0.5).abs() < 1e-6);
+            for t in [0.1, 0.25, 0.4] {
+                assert!((easing.apply(t) + easing.apply(1.0 - t) - 1.0).abs() < 1e-6);
+            }
+        }
+
+        // The overshoot peaks around 10% past the end
+        let peak = (0..=100)
+            .map(|step| Easing::BackOut.apply(step as f32 / 100.0))
+            .fold(0.0, f32::max);
+        assert!(peak > 1.09 && peak < 1.11);
+    }
+}
@@ -1,7 +1,16 @@
+pub mod det;
+pub mod easing;
+pub mod ray;
+pub mod spring;
+
 use std::ops::Neg;
 
 pub use glam::{Mat4, Quat, UVec2, Vec2, Vec2Swizzles, Vec3, Vec3Swizzles, Vec4, Vec4Swizzles};
 
+pub use easing::Easing;
+pub use ray::{Ray, RayProximity};
+pub use spring::{spring_damp, spring_damp_quat};
+
 pub type Mat4Data = [f32; 16];
 pub type Vec4Data = [f32; 4];
 pub type Vec2Data = [f32; 2];
@@ -0,0 +1,221 @@
+// Rays from the camera through the cursor and what they pass close to, for picking and
+// dragging things on screen. Handles are hit when the ray passes within a radius of them,
+// a cylinder around a segment or a torus around a circle, which is forgiving for thin lines.
+
+use super::{Mat4, Vec2, Vec3, Vec4};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3, // Unit length
+}
+
+// Where the ray passes closest to a segment or a circle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayProximity {
+    pub distance: f32, // Between the ray and the shape
+    pub t: f32,        // Along the ray, to the closest point on it
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize_or_zero(),
+        }
+    }
+
+    // Through a point on screen, 0 to 1 from the top left. From the near plane towards the
+    // far one, which works for both kinds of projection.
+    pub fn from_screen(view_projection: Mat4, screen_position: Vec2) -> Self {
+        let ndc = Vec2::new(screen_position.x * 2.0 - 1.0, 1.0 - screen_position.y * 2.0);
+        let inverse = view_projection.inverse();
+        let near = inverse * Vec4::new(ndc.x, ndc.y, 0.0, 1.0);
+        let far = inverse * Vec4::new(ndc.x, ndc.y, 1.0, 1.0);
+        let near = near.truncate() / near.w;
+        let far = far.truncate() / far.w;
+        Self::new(near, far - near)
+    }
+
+    pub fn get_point(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+
+    // None when the ray runs along the plane or points away from it
+    pub fn intersect_plane(&self, point: Vec3, normal: Vec3) -> Option<f32> {
+        let denominator = self.direction.dot(normal);
+        if denominator.abs() < 1e-6 {
+            return None;
+        }
+        let t = (point - self.origin).dot(normal) / denominator;
+        (t >= 0.0).then_some(t)
+    }
+
+    // Along the line through the point, where it is closest to the ray. None when the two are
+    // parallel and every point is as close.
+    pub fn get_closest_on_line(&self, point: Vec3, direction: Vec3) -> Option<f32> {
+        let b = self.direction.dot(direction);
+        let c = direction.length_squared();
+        let denominator = c - b * b;
+        if denominator.abs() < 1e-6 * c.max(1.0) {
+            return None;
+        }
+        let offset = point - self.origin;
+        let d = self.direction.dot(offset);
+        let e = direction.dot(offset);
+        Some((b * d - e) / denominator)
+    }
+
+    pub fn get_proximity_to_segment(&self, start: Vec3, end: Vec3) -> RayProximity {
+        let direction = end - start;
+        let s = match self.get_closest_on_line(start, direction) {
+            Some(s) => s.clamp(0.0, 1.0),
+            None => 0.0,
+        };
+        let point = start + direction * s;
+        let t = self.direction.dot(point - self.origin).max(0.0);
+        RayProximity {
+            distance: self.get_point(t).distance(point),
+            t,
+        }
+    }
+
+    // The circle as a polygon of the segment count, close enough for picking
+    pub fn get_proximity_to_circle(
+        &self,
+        center: Vec3,
+        normal: Vec3,
+        radius: f32,
+        segment_count: u32,
+    ) -> RayProximity {
+        get_circle_points(center, normal, radius, segment_count)
+            .windows(2)
+            .map(|points| self.get_proximity_to_segment(points[0], points[1]))
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+            .unwrap_or(RayProximity {
+                distance: f32::INFINITY,
+                t: 0.0,
+            })
+    }
+}
+
+// Around the normal, the first point is repeated at the end
+pub fn get_circle_points(center: Vec3, normal: Vec3, radius: f32, segment_count: u32) -> Vec<Vec3> {
+    let (tangent, bitangent) = normal.normalize_or_zero().any_orthonormal_pair();
+    let segment_count = segment_count.max(3);
+    (0..=segment_count)
+        .map(|index| {
+            let angle = index as f32 / segment_count as f32 * std::f32::consts::TAU;
+            center + (tangent * angle.cos() + bitangent * angle.sin()) * radius
+        })
+        .collect()
+}
+
+// The angle from one vector to the other around the axis, counter clockwise looking down it,
+// both are projected onto the plane of the axis first
+pub fn get_signed_angle(from: Vec3, to: Vec3, axis: Vec3) -> f32 {
+    let axis = axis.normalize_or_zero();
+    let from = from - axis * from.dot(axis);
+    let to = to - axis * to.dot(axis);
+    f32::atan2(from.cross(to).dot(axis), from.dot(to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rays_through_the_screen_start_on_the_near_plane() {
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 0.0, 10.0), Vec3::ZERO, Vec3::Y);
+        let projection = Mat4::perspective_rh(60f32.to_radians(), 1.0, 1.0, 100.0);
+        let ray = Ray::from_screen(projection * view, Vec2::splat(0.5));
+        assert!(ray.origin.abs_diff_eq(Vec3::new(0.0, 0.0, 9.0), 1e-4));
+        assert!(ray.direction.abs_diff_eq(Vec3::NEG_Z, 1e-5));
+
+        // The top of the screen is up
+        let ray = Ray::from_screen(projection * view, Vec2::new(0.5, 0.0));
+        assert!(ray.direction.y > 0.0);
+        assert!(ray.direction.is_normalized());
+    }
+
+    #[test]
+    fn planes_are_hit_in_front_of_the_ray() {
+        let ray = Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::new(1.0, -1.0, 0.0));
+        let t = ray.intersect_plane(Vec3::ZERO, Vec3::Y).unwrap();
+        assert!(
+            ray.get_point(t)
+                .abs_diff_eq(Vec3::new(10.0, 0.0, 0.0), 1e-4)
+        );
+
+        assert_eq!(ray.intersect_plane(Vec3::ZERO, Vec3::X), Some(0.0));
+        assert_eq!(
+            ray.intersect_plane(Vec3::new(0.0, 20.0, 0.0), Vec3::Y),
+            None
+        );
+        assert_eq!(ray.intersect_plane(Vec3::ZERO, Vec3::Z), None);
+    }
+
+    #[test]
+    fn segments_are_measured_from_their_closest_point() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 10.0), Vec3::NEG_Z);
+
+        // Crossing the segment 2 to the side of it
+        let proximity =
+            ray.get_proximity_to_segment(Vec3::new(-5.0, 2.0, 0.0), Vec3::new(5.0, 2.0, 0.0));
+        assert!((proximity.distance - 2.0).abs() < 1e-5);
+        assert!((proximity.t - 10.0).abs() < 1e-5);
+
+        // Past its end the end is closest
+        let proximity =
+            ray.get_proximity_to_segment(Vec3::new(3.0, 0.0, 0.0), Vec3::new(8.0, 0.0, 0.0));
+        assert!((proximity.distance - 3.0).abs() < 1e-5);
+
+        // Parallel to the ray
+        let proximity =
+            ray.get_proximity_to_segment(Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, -5.0));
+        assert!((proximity.distance - 1.0).abs() < 1e-5);
+
+        let line = ray
+            .get_closest_on_line(Vec3::new(0.0, 0.0, 4.0), Vec3::X)
+            .unwrap();
+        assert!(line.abs() < 1e-5);
+        assert_eq!(ray.get_closest_on_line(Vec3::ZERO, Vec3::Z), None);
+    }
+
+    #[test]
+    fn circles_are_hit_on_their_rim() {
+        let normal = Vec3::Y;
+        // Down onto the rim, and down through the middle where there is nothing
+        let rim = Ray::new(Vec3::new(5.0, 10.0, 0.0), Vec3::NEG_Y);
+        let proximity = rim.get_proximity_to_circle(Vec3::ZERO, normal, 5.0, 64);
+        assert!(proximity.distance < 0.01);
+        assert!((proximity.t - 10.0).abs() < 0.01);
+        let center = Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::NEG_Y);
+        let proximity = center.get_proximity_to_circle(Vec3::ZERO, normal, 5.0, 64);
+        assert!((proximity.distance - 5.0).abs() < 0.01);
+
+        // Edge on, the ray passes through the rim on both sides
+        let side = Ray::new(Vec3::new(-20.0, 0.0, 0.0), Vec3::X);
+        let proximity = side.get_proximity_to_circle(Vec3::ZERO, normal, 5.0, 64);
+        assert!(proximity.distance < 0.01);
+
+        let points = get_circle_points(Vec3::ONE, Vec3::Z, 2.0, 16);
+        assert_eq!(points.len(), 17);
+        assert!(
+            points
+                .iter()
+                .all(|point| (point.distance(Vec3::ONE) - 2.0).abs() < 1e-5)
+        );
+        assert!(points.iter().all(|point| (point.z - 1.0).abs() < 1e-5));
+    }
+
+    #[test]
+    fn angles_are_signed_around_the_axis() {
+        let quarter = std::f32::consts::FRAC_PI_2;
+        assert!((get_signed_angle(Vec3::X, Vec3::Y, Vec3::Z) - quarter).abs() < 1e-5);
+        assert!((get_signed_angle(Vec3::Y, Vec3::X, Vec3::Z) + quarter).abs() < 1e-5);
+        // Parts along the axis don't count
+        let angle = get_signed_angle(Vec3::new(1.0, 0.0, 5.0), Vec3::new(0.0, 1.0, -3.0), Vec3::Z);
+        assert!((angle - quarter).abs() < 1e-5);
+    }
+}
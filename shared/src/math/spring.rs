@@ -0,0 +1,124 @@
+// Smoothing toward a moving target for presentation, e.g. a camera zoom or a bar catching
+// up with a value. These use exp, so they are not for the state the server and the clients
+// have to agree on, see det.
+
+use std::ops::{Add, Mul, Sub};
+
+use super::{Quat, Vec2, Vec3, Vec4};
+
+pub trait Springable:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self>
+{
+}
+
+impl Springable for f32 {}
+impl Springable for Vec2 {}
+impl Springable for Vec3 {}
+impl Springable for Vec4 {}
+
+// A critically damped spring, it gets to the target as fast as it can without overshooting
+// it. Solved exactly rather than integrated, so the result doesn't depend on how the time
+// is split into steps. The smooth time is about how long it takes to get most of the way
+// there, the velocity is kept by the caller between calls.
+pub fn spring_damp<T: Springable>(
+    current: T,
+    target: T,
+    velocity: &mut T,
+    smooth_time: f32,
+    dt: f32,
+) -> T {
+    if smooth_time <= 0.0 {
+        *velocity = *velocity * 0.0;
+        return target;
+    }
+
+    let omega = 2.0 / smooth_time;
+    let offset = current - target;
+    let j = *velocity + offset * omega;
+    let decay = (-omega * dt).exp();
+    *velocity = (*velocity - j * (omega * dt)) * decay;
+    target + (offset + j * dt) * decay
+}
+
+// The components are sprung like a Vec4 and normalized, like nlerp. The target is flipped
+// into the hemisphere of the current rotation so it takes the short way around.
+pub fn spring_damp_quat(
+    current: Quat,
+    target: Quat,
+    velocity: &mut Vec4,
+    smooth_time: f32,
+    dt: f32,
+) -> Quat {
+    let current = Vec4::from(current);
+    let mut target = Vec4::from(target);
+    if current.dot(target) < 0.0 {
+        target = -target;
+    }
+    let value = spring_damp(current, target, velocity, smooth_time, dt);
+    Quat::from_vec4(value).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_analytic_solution_without_overshoot() {
+        // From rest at 1 toward 0 the position is (1 + wt) e^-wt, with w = 2 / smooth time
+        let smooth_time = 0.5;
+        let omega = 2.0 / smooth_time;
+        let mut value = 1.0;
+        let mut velocity = 0.0;
+        let mut previous = value;
+        for step in 1..=200 {
+            value = spring_damp(value, 0.0, &mut velocity, smooth_time, 0.01);
+            let t = step as f32 * 0.01;
+            let expected = (1.0 + omega * t) * (-omega * t).exp();
+            assert!((value - expected).abs() < 1e-5, "{} at {}", value, t);
+
+            // Always closer, never past the target
+            assert!(value > 0.0 && value < previous);
+            previous = value;
+        }
+    }
+
+    #[test]
+    fn the_step_size_does_not_matter() {
+        let target = Vec3::new(10.0, -4.0, 2.0);
+        let mut small = Vec3::ZERO;
+        let mut small_velocity = Vec3::new(0.0, 30.0, 0.0);
+        for _ in 0..30 {
+            small = spring_damp(small, target, &mut small_velocity, 0.2, 1.0 / 60.0);
+        }
+        let mut large_velocity = Vec3::new(0.0, 30.0, 0.0);
+        let large = spring_damp(Vec3::ZERO, target, &mut large_velocity, 0.2, 0.5);
+        assert!(small.abs_diff_eq(large, 1e-4));
+        assert!(small_velocity.abs_diff_eq(large_velocity, 1e-3));
+
+        // No smoothing snaps to the target and stops
+        let mut velocity = Vec2::X;
+        let value = spring_damp(Vec2::ZERO, Vec2::ONE, &mut velocity, 0.0, 0.1);
+        assert_eq!(value, Vec2::ONE);
+        assert_eq!(velocity, Vec2::ZERO);
+    }
+
+    #[test]
+    fn rotations_take_the_short_way_and_stay_unit() {
+        let current = Quat::from_rotation_y(0.1);
+        // The same rotation as 0.3 around y, with the other sign
+        let target = -Quat::from_rotation_y(0.3);
+        let mut velocity = Vec4::ZERO;
+        let mut rotation = current;
+        // Measured to the target on the side of the current rotation, the short way
+        let get_distance = |rotation: Quat| (Vec4::from(rotation) + Vec4::from(target)).length();
+        let mut previous_distance = get_distance(rotation);
+        for _ in 0..60 {
+            rotation = spring_damp_quat(rotation, target, &mut velocity, 0.1, 1.0 / 60.0);
+            assert!((rotation.length() - 1.0).abs() < 1e-5);
+            let distance = get_distance(rotation);
+            assert!(distance <= previous_distance + 1e-6);
+            previous_distance = distance;
+        }
+        assert!(previous_distance < 1e-3);
+    }
+}
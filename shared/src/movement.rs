@@ -0,0 +1,85 @@
+// Player movement shared by the server and the client prediction. Both have to step it
+// with the same inputs and time steps to agree on where the player is.
+
+use crate::{
+    math::{Vec2, det::qnormalize},
+    net::ACTION_MOVE,
+    physics::BodyState,
+};
+
+pub const MOVEMENT_SPEED: f32 = 300.0;
+pub const MIN_TARGET_DISTANCE: f32 = 10.0;
+const ACCELERATION: f32 = 15.0; // Fraction of the velocity change applied per second
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MoveInput {
+    pub action_bits: u32,
+    pub cursor_world: Vec2,
+}
+
+// Accelerates towards the cursor while the move bit is held and integrates the position.
+// Collisions are left to the physics world, which moves the body by the same velocity.
+pub fn simulate_body(state: BodyState, input: &MoveInput, dt: f32) -> BodyState {
+    let mut input_velocity = Vec2::ZERO;
+    if input.action_bits & ACTION_MOVE != 0 {
+        let to_target = input.cursor_world - state.position;
+        if to_target.length_squared() > MIN_TARGET_DISTANCE * MIN_TARGET_DISTANCE {
+            input_velocity = MOVEMENT_SPEED * qnormalize(to_target);
+        }
+    }
+
+    let velocity = state
+        .velocity
+        .lerp(input_velocity, (ACCELERATION * dt).clamp(0.0, 1.0));
+    BodyState {
+        position: state.position + velocity * dt,
+        velocity,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    #[test]
+    fn walks_to_the_cursor_and_stops() {
+        let input = MoveInput {
+            action_bits: ACTION_MOVE,
+            cursor_world: Vec2::new(300.0, 400.0),
+        };
+        let mut state = BodyState {
+            position: Vec2::ZERO,
+            velocity: Vec2::ZERO,
+        };
+        for _ in 0..240 {
+            state = simulate_body(state, &input, DT);
+        }
+
+        assert!(state.position.distance(input.cursor_world) <= MIN_TARGET_DISTANCE + 1.0);
+        assert!(state.velocity.length() < 1.0);
+    }
+
+    #[test]
+    fn same_inputs_give_the_same_state() {
+        let inputs = [
+            MoveInput {
+                action_bits: ACTION_MOVE,
+                cursor_world: Vec2::new(-100.0, 50.0),
+            },
+            MoveInput::default(),
+        ];
+        let start = BodyState {
+            position: Vec2::new(5.0, 5.0),
+            velocity: Vec2::new(10.0, 0.0),
+        };
+
+        let run = || {
+            (0..100).fold(start, |state, i| {
+                simulate_body(state, &inputs[(i / 30) % 2], DT)
+            })
+        };
+        assert_eq!(run(), run());
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    UnsupportedVersion(u8),
+    UnknownMessage(u8),
+    InvalidString,
+    TrailingBytes,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "message ended early"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported protocol version {}", version)
+            }
+            Self::UnknownMessage(kind) => write!(f, "unknown message type {}", kind),
+            Self::InvalidString => write!(f, "string is not valid utf-8"),
+            Self::TrailingBytes => write!(f, "bytes left after the message"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// Packs values MSB first, the last byte is padded with zeros
+#[derive(Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    bit_count: usize,
+}
+
+impl BitWriter {
+    pub fn write_bits(&mut self, value: u32, count: u32) {
+        debug_assert!(count <= 32);
+        debug_assert!(count == 32 || value >> count == 0, "value doesn't fit");
+
+        for bit in (0..count).rev() {
+            if self.bit_count.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> bit) & 1 == 1 {
+                let last = self.bytes.len() - 1;
+                self.bytes[last] |= 0x80 >> (self.bit_count % 8);
+            }
+            self.bit_count += 1;
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit_position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            bit_position: 0,
+        }
+    }
+
+    pub fn read_bits(&mut self, count: u32) -> Result<u32, DecodeError> {
+        debug_assert!(count <= 32);
+        if self.bit_position + count as usize > self.bytes.len() * 8 {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+
+        let mut value = 0u32;
+        for _ in 0..count {
+            let byte = self.bytes[self.bit_position / 8];
+            let bit = (byte >> (7 - self.bit_position % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.bit_position += 1;
+        }
+
+        Ok(value)
+    }
+
+    // Only the zero padding of the last byte may be left
+    pub fn finish(self) -> Result<(), DecodeError> {
+        let padded_end = self.bit_position.div_ceil(8);
+        if padded_end != self.bytes.len() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        Ok(())
+    }
+}
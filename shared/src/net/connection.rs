@@ -0,0 +1,218 @@
+use std::collections::VecDeque;
+
+// Framing for one peer on top of an unreliable datagram transport. Reliable payloads get a
+// sequence number and are resent until acknowledged, the receiver only delivers them in
+// order so a lost Join can't be overtaken by a Leave. Unreliable payloads go out once.
+//
+// Datagram layout: kind (1 byte), then the sequence (2 bytes) for reliable payloads and
+// acks, then the payload.
+
+const UNRELIABLE: u8 = 0;
+const RELIABLE: u8 = 1;
+const ACK: u8 = 2;
+
+pub const RESEND_INTERVAL: f64 = 0.2;
+pub const CONNECTION_TIMEOUT: f64 = 5.0;
+
+struct PendingPayload {
+    sequence: u16,
+    datagram: Vec<u8>,
+    last_sent: f64,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Received {
+    pub payload: Option<Vec<u8>>,
+    pub reply: Option<Vec<u8>>, // The ack to send back
+}
+
+pub struct Connection {
+    next_sequence: u16,
+    expected_sequence: u16,
+    pending: VecDeque<PendingPayload>,
+    last_received: f64,
+}
+
+impl Connection {
+    pub fn new(now: f64) -> Self {
+        Self {
+            next_sequence: 0,
+            expected_sequence: 0,
+            pending: VecDeque::new(),
+            last_received: now,
+        }
+    }
+
+    // Returns the datagram to send right away
+    pub fn send(&mut self, payload: &[u8], reliable: bool, now: f64) -> Vec<u8> {
+        if !reliable {
+            let mut datagram = Vec::with_capacity(payload.len() + 1);
+            datagram.push(UNRELIABLE);
+            datagram.extend_from_slice(payload);
+            return datagram;
+        }
+
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+
+        let mut datagram = Vec::with_capacity(payload.len() + 3);
+        datagram.push(RELIABLE);
+        datagram.extend_from_slice(&sequence.to_be_bytes());
+        datagram.extend_from_slice(payload);
+
+        self.pending.push_back(PendingPayload {
+            sequence,
+            datagram: datagram.clone(),
+            last_sent: now,
+        });
+        datagram
+    }
+
+    // Duplicates and reliable payloads arriving ahead of a lost one are acked or dropped
+    // without handing out the payload. Garbage is dropped without counting as a sign of life.
+    pub fn receive(&mut self, datagram: &[u8], now: f64) -> Received {
+        let mut received = Received::default();
+        let Some((&kind, rest)) = datagram.split_first() else {
+            return received;
+        };
+
+        match kind {
+            UNRELIABLE => received.payload = Some(rest.to_vec()),
+            RELIABLE | ACK => {
+                let Some((sequence, payload)) = rest.split_first_chunk::<2>() else {
+                    return received;
+                };
+                let sequence = u16::from_be_bytes(*sequence);
+
+                if kind == ACK {
+                    self.pending.retain(|pending| pending.sequence != sequence);
+                } else if sequence == self.expected_sequence {
+                    self.expected_sequence = self.expected_sequence.wrapping_add(1);
+                    received.payload = Some(payload.to_vec());
+                    received.reply = Some(Self::get_ack(sequence));
+                } else if is_before(sequence, self.expected_sequence) {
+                    // Our ack got lost, the sender is still waiting for it
+                    received.reply = Some(Self::get_ack(sequence));
+                }
+            }
+            _ => return received,
+        }
+
+        self.last_received = now;
+        received
+    }
+
+    // Reliable datagrams that have waited too long for their ack
+    pub fn get_resends(&mut self, now: f64) -> Vec<Vec<u8>> {
+        let mut resends = Vec::new();
+        for pending in self.pending.iter_mut() {
+            if now - pending.last_sent >= RESEND_INTERVAL {
+                pending.last_sent = now;
+                resends.push(pending.datagram.clone());
+            }
+        }
+        resends
+    }
+
+    pub fn is_timed_out(&self, now: f64) -> bool {
+        now - self.last_received > CONNECTION_TIMEOUT
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    fn get_ack(sequence: u16) -> Vec<u8> {
+        let mut datagram = vec![ACK];
+        datagram.extend_from_slice(&sequence.to_be_bytes());
+        datagram
+    }
+}
+
+// Sequence comparison that survives the wrap around
+fn is_before(a: u16, b: u16) -> bool {
+    a != b && b.wrapping_sub(a) < u16::MAX / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reliable_payloads_are_resent_until_acked() {
+        let mut sender = Connection::new(0.0);
+        let mut receiver = Connection::new(0.0);
+
+        let _lost = sender.send(b"join", true, 0.0);
+        assert!(sender.get_resends(0.1).is_empty());
+
+        let resends = sender.get_resends(0.2);
+        assert_eq!(resends.len(), 1);
+        let received = receiver.receive(&resends[0], 0.2);
+        assert_eq!(received.payload.as_deref(), Some(&b"join"[..]));
+
+        // The first ack gets lost too, the resend is acked again but not delivered twice
+        let resends = sender.get_resends(0.4);
+        let received = receiver.receive(&resends[0], 0.4);
+        assert_eq!(received.payload, None);
+
+        sender.receive(&received.reply.unwrap(), 0.4);
+        assert!(!sender.has_pending());
+        assert!(sender.get_resends(1.0).is_empty());
+    }
+
+    #[test]
+    fn reliable_payloads_are_delivered_in_order() {
+        let mut sender = Connection::new(0.0);
+        let mut receiver = Connection::new(0.0);
+
+        let first = sender.send(b"first", true, 0.0);
+        let second = sender.send(b"second", true, 0.0);
+
+        // Ahead of the lost first one, dropped without an ack
+        assert_eq!(receiver.receive(&second, 0.0), Received::default());
+
+        let received = receiver.receive(&first, 0.1);
+        assert_eq!(received.payload.as_deref(), Some(&b"first"[..]));
+        sender.receive(&received.reply.unwrap(), 0.1);
+
+        let resends = sender.get_resends(0.2);
+        assert_eq!(resends, vec![second]);
+        let received = receiver.receive(&resends[0], 0.2);
+        assert_eq!(received.payload.as_deref(), Some(&b"second"[..]));
+    }
+
+    #[test]
+    fn unreliable_payloads_pass_through() {
+        let mut sender = Connection::new(0.0);
+        let mut receiver = Connection::new(0.0);
+
+        let datagram = sender.send(b"input", false, 0.0);
+        let received = receiver.receive(&datagram, 0.0);
+        assert_eq!(received.payload.as_deref(), Some(&b"input"[..]));
+        assert_eq!(received.reply, None);
+        assert!(sender.get_resends(10.0).is_empty());
+    }
+
+    #[test]
+    fn silence_times_out() {
+        let mut connection = Connection::new(0.0);
+        let mut peer = Connection::new(0.0);
+
+        connection.receive(&peer.send(b"", false, 4.0), 4.0);
+        assert!(!connection.is_timed_out(8.0));
+
+        // Garbage does not keep the connection alive
+        connection.receive(&[7, 1, 2], 8.5);
+        connection.receive(&[RELIABLE, 1], 8.5);
+        assert!(connection.is_timed_out(9.5));
+    }
+
+    #[test]
+    fn sequences_wrap_around() {
+        assert!(is_before(u16::MAX, 0));
+        assert!(is_before(10, 11));
+        assert!(!is_before(11, 10));
+        assert!(!is_before(5, 5));
+    }
+}
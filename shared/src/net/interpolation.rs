@@ -0,0 +1,174 @@
+use std::f32::consts::{PI, TAU};
+
+use crate::net::message::EntityState;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub tick: u32,
+    pub entities: Vec<EntityState>,
+}
+
+// Keeps the two newest snapshots and blends between them. The caller renders a bit in the
+// past, usually one snapshot interval, so there is almost always a newer snapshot to blend
+// towards.
+pub struct SnapshotInterpolator {
+    tick_rate: f32, // Server ticks per second
+    previous: Option<Snapshot>,
+    latest: Option<Snapshot>,
+}
+
+impl SnapshotInterpolator {
+    pub fn new(tick_rate: f32) -> Self {
+        Self {
+            tick_rate,
+            previous: None,
+            latest: None,
+        }
+    }
+
+    // Snapshots arriving out of order or twice are dropped
+    pub fn push(&mut self, snapshot: Snapshot) {
+        if let Some(latest) = &self.latest
+            && snapshot.tick <= latest.tick
+        {
+            return;
+        }
+        self.previous = self.latest.replace(snapshot);
+    }
+
+    pub fn get_latest_tick(&self) -> Option<u32> {
+        self.latest.as_ref().map(|snapshot| snapshot.tick)
+    }
+
+    // Time is in seconds on the server tick clock, tick n happens at n / tick_rate. Outside
+    // the buffered range the nearest snapshot is returned as is. Entities only present in
+    // the newer snapshot pop in, entities missing from it are gone.
+    pub fn sample(&self, time: f32) -> Vec<EntityState> {
+        let (previous, latest) = match (&self.previous, &self.latest) {
+            (Some(previous), Some(latest)) => (previous, latest),
+            (None, Some(latest)) => return latest.entities.clone(),
+            _ => return Vec::new(),
+        };
+
+        let start = previous.tick as f32 / self.tick_rate;
+        let end = latest.tick as f32 / self.tick_rate;
+        let t = ((time - start) / (end - start)).clamp(0.0, 1.0);
+        if t >= 1.0 {
+            return latest.entities.clone();
+        }
+
+        latest
+            .entities
+            .iter()
+            .map(
+                |to| match previous.entities.iter().find(|from| from.id == to.id) {
+                    Some(from) => interpolate(from, to, t),
+                    None => *to,
+                },
+            )
+            .collect()
+    }
+}
+
+fn interpolate(from: &EntityState, to: &EntityState, t: f32) -> EntityState {
+    // Shortest way around so facing doesn't spin when crossing zero
+    let facing_delta = (to.facing - from.facing + PI).rem_euclid(TAU) - PI;
+    EntityState {
+        id: to.id,
+        position: from.position.lerp(to.position, t),
+        velocity: from.velocity.lerp(to.velocity, t),
+        facing: (from.facing + facing_delta * t).rem_euclid(TAU),
+        anim_state: if t < 0.5 {
+            from.anim_state
+        } else {
+            to.anim_state
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec2;
+
+    const SERVER_RATE: f32 = 10.0;
+    const RENDER_RATE: f32 = 60.0;
+
+    fn get_snapshot(tick: u32) -> Snapshot {
+        // Walks along x at 100 units per second while turning around zero
+        let time = tick as f32 / SERVER_RATE;
+        Snapshot {
+            tick,
+            entities: vec![EntityState {
+                id: 7,
+                position: Vec2::new(100.0 * time, 50.0),
+                velocity: Vec2::new(100.0, 0.0),
+                facing: (-0.2 + time).rem_euclid(TAU),
+                anim_state: tick as u8,
+            }],
+        }
+    }
+
+    #[test]
+    fn ten_hz_snapshots_play_back_smoothly_at_sixty_hz() {
+        let mut interpolator = SnapshotInterpolator::new(SERVER_RATE);
+        let delay = 1.0 / SERVER_RATE;
+
+        let mut last_x = None;
+        for frame in 0..60 {
+            let time = frame as f32 / RENDER_RATE;
+            // Snapshots arrive when the server produced them
+            let tick = (time * SERVER_RATE).floor() as u32;
+            if interpolator.get_latest_tick() != Some(tick) {
+                interpolator.push(get_snapshot(tick));
+            }
+
+            let render_time = time - delay;
+            if render_time < 0.0 {
+                continue;
+            }
+
+            let entities = interpolator.sample(render_time);
+            assert_eq!(entities.len(), 1);
+            let entity = entities[0];
+            assert!((entity.position.x - 100.0 * render_time).abs() < 1e-3);
+            assert!((entity.position.y - 50.0).abs() < 1e-3);
+
+            let expected_facing = (-0.2 + render_time).rem_euclid(TAU);
+            let facing_error = (entity.facing - expected_facing).rem_euclid(TAU);
+            assert!(facing_error.min(TAU - facing_error) < 1e-4);
+
+            // Every frame moves forward, also the ones between snapshots
+            if let Some(last_x) = last_x {
+                assert!(entity.position.x > last_x);
+            }
+            last_x = Some(entity.position.x);
+        }
+    }
+
+    #[test]
+    fn stale_snapshots_are_ignored() {
+        let mut interpolator = SnapshotInterpolator::new(SERVER_RATE);
+        interpolator.push(get_snapshot(4));
+        interpolator.push(get_snapshot(5));
+        interpolator.push(get_snapshot(3));
+        interpolator.push(get_snapshot(5));
+
+        assert_eq!(interpolator.get_latest_tick(), Some(5));
+        let entity = interpolator.sample(0.45)[0];
+        assert!((entity.position.x - 45.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn sampling_outside_the_buffer_clamps() {
+        let mut interpolator = SnapshotInterpolator::new(SERVER_RATE);
+        assert!(interpolator.sample(1.0).is_empty());
+
+        interpolator.push(get_snapshot(2));
+        assert_eq!(interpolator.sample(5.0), get_snapshot(2).entities);
+
+        interpolator.push(get_snapshot(3));
+        assert_eq!(interpolator.sample(0.0), get_snapshot(2).entities);
+        assert_eq!(interpolator.sample(5.0), get_snapshot(3).entities);
+    }
+}
@@ -0,0 +1,479 @@
+use std::f32::consts::TAU;
+
+use crate::{
+    math::Vec2,
+    net::bits::{BitReader, BitWriter, DecodeError},
+};
+
+pub const PROTOCOL_VERSION: u8 = 2;
+
+// The server steps at the client fixed rate and sends a snapshot every few ticks (20 Hz)
+pub const SERVER_TICK_RATE: f32 = 60.0;
+pub const SNAPSHOT_INTERVAL: u32 = 3;
+
+pub const ACTION_BIT_COUNT: u32 = 16;
+pub const ACTION_MOVE: u32 = 1 << 0; // Walk towards the cursor
+const MAX_NAME_LENGTH: usize = 255;
+const MAX_SNAPSHOT_ENTITIES: usize = u16::MAX as usize;
+
+// Positions are sent as 16 bits per axis over the map bounds and velocities as signed 16
+// bits over the speed limit, both sides have to agree on these
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantizationBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub max_speed: f32,
+}
+
+// Covers every level so far
+impl Default for QuantizationBounds {
+    fn default() -> Self {
+        Self {
+            min: Vec2::splat(-4096.0),
+            max: Vec2::splat(4096.0),
+            max_speed: 1000.0,
+        }
+    }
+}
+
+impl QuantizationBounds {
+    // Largest error a position can pick up on the way
+    pub fn get_position_precision(&self) -> Vec2 {
+        (self.max - self.min) / u16::MAX as f32 * 0.5
+    }
+
+    pub fn get_velocity_precision(&self) -> f32 {
+        self.max_speed / i16::MAX as f32 * 0.5
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EntityState {
+    pub id: u32,
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub facing: f32, // Radians around the up axis
+    pub anim_state: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Join {
+        name: String,
+    },
+    Leave,
+    Accepted {
+        entity_id: u32, // The entity controlled by the client
+    },
+    ClientInput {
+        tick: u32,
+        action_bits: u32, // Only the lowest ACTION_BIT_COUNT bits are sent
+        cursor_world: Vec2,
+    },
+    ServerSnapshot {
+        tick: u32,
+        last_input_tick: u32, // The newest input of the receiving client that was applied
+        entities: Vec<EntityState>,
+    },
+}
+
+impl Message {
+    const JOIN: u8 = 0;
+    const LEAVE: u8 = 1;
+    const CLIENT_INPUT: u8 = 2;
+    const SERVER_SNAPSHOT: u8 = 3;
+    const ACCEPTED: u8 = 4;
+
+    // The rest is sent every tick and may get lost
+    pub fn is_reliable(&self) -> bool {
+        matches!(
+            self,
+            Self::Join { .. } | Self::Leave | Self::Accepted { .. }
+        )
+    }
+
+    // Layout: version (8 bits), message type (8 bits), then the bitpacked fields
+    pub fn encode(&self, bounds: &QuantizationBounds) -> Vec<u8> {
+        let mut writer = BitWriter::default();
+        writer.write_bits(PROTOCOL_VERSION as u32, 8);
+
+        match self {
+            Self::Join { name } => {
+                writer.write_bits(Self::JOIN as u32, 8);
+                // Cut on a char boundary so the receiver always gets valid utf-8
+                let mut length = name.len().min(MAX_NAME_LENGTH);
+                while !name.is_char_boundary(length) {
+                    length -= 1;
+                }
+                writer.write_bits(length as u32, 8);
+                for &byte in &name.as_bytes()[..length] {
+                    writer.write_bits(byte as u32, 8);
+                }
+            }
+            Self::Leave => writer.write_bits(Self::LEAVE as u32, 8),
+            Self::Accepted { entity_id } => {
+                writer.write_bits(Self::ACCEPTED as u32, 8);
+                writer.write_bits(*entity_id, 32);
+            }
+            Self::ClientInput {
+                tick,
+                action_bits,
+                cursor_world,
+            } => {
+                writer.write_bits(Self::CLIENT_INPUT as u32, 8);
+                writer.write_bits(*tick, 32);
+                writer.write_bits(
+                    action_bits & ((1 << ACTION_BIT_COUNT) - 1),
+                    ACTION_BIT_COUNT,
+                );
+                write_position(&mut writer, *cursor_world, bounds);
+            }
+            Self::ServerSnapshot {
+                tick,
+                last_input_tick,
+                entities,
+            } => {
+                writer.write_bits(Self::SERVER_SNAPSHOT as u32, 8);
+                writer.write_bits(*tick, 32);
+                writer.write_bits(*last_input_tick, 32);
+
+                let count = entities.len().min(MAX_SNAPSHOT_ENTITIES);
+                if count < entities.len() {
+                    log::warn!(
+                        "Snapshot truncated to {} of {} entities",
+                        count,
+                        entities.len()
+                    );
+                }
+                writer.write_bits(count as u32, 16);
+                for entity in &entities[..count] {
+                    writer.write_bits(entity.id, 32);
+                    write_position(&mut writer, entity.position, bounds);
+                    write_velocity(&mut writer, entity.velocity, bounds);
+                    writer.write_bits(quantize_angle(entity.facing), 16);
+                    writer.write_bits(entity.anim_state as u32, 8);
+                }
+            }
+        }
+
+        writer.finish()
+    }
+
+    pub fn decode(bytes: &[u8], bounds: &QuantizationBounds) -> Result<Self, DecodeError> {
+        let mut reader = BitReader::new(bytes);
+        let version = reader.read_bits(8)? as u8;
+        if version != PROTOCOL_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let message = match reader.read_bits(8)? as u8 {
+            Self::JOIN => {
+                let length = reader.read_bits(8)? as usize;
+                let mut name = Vec::with_capacity(length);
+                for _ in 0..length {
+                    name.push(reader.read_bits(8)? as u8);
+                }
+                let name = String::from_utf8(name).map_err(|_| DecodeError::InvalidString)?;
+                Self::Join { name }
+            }
+            Self::LEAVE => Self::Leave,
+            Self::ACCEPTED => Self::Accepted {
+                entity_id: reader.read_bits(32)?,
+            },
+            Self::CLIENT_INPUT => Self::ClientInput {
+                tick: reader.read_bits(32)?,
+                action_bits: reader.read_bits(ACTION_BIT_COUNT)?,
+                cursor_world: read_position(&mut reader, bounds)?,
+            },
+            Self::SERVER_SNAPSHOT => {
+                let tick = reader.read_bits(32)?;
+                let last_input_tick = reader.read_bits(32)?;
+                let count = reader.read_bits(16)? as usize;
+
+                // Checked up front so a bogus count can't make us allocate a lot
+                const ENTITY_BITS: usize = 32 + 32 + 32 + 16 + 8;
+                if count * ENTITY_BITS > bytes.len() * 8 {
+                    return Err(DecodeError::UnexpectedEnd);
+                }
+
+                let mut entities = Vec::with_capacity(count);
+                for _ in 0..count {
+                    entities.push(EntityState {
+                        id: reader.read_bits(32)?,
+                        position: read_position(&mut reader, bounds)?,
+                        velocity: read_velocity(&mut reader, bounds)?,
+                        facing: dequantize_angle(reader.read_bits(16)?),
+                        anim_state: reader.read_bits(8)? as u8,
+                    });
+                }
+                Self::ServerSnapshot {
+                    tick,
+                    last_input_tick,
+                    entities,
+                }
+            }
+            kind => return Err(DecodeError::UnknownMessage(kind)),
+        };
+
+        reader.finish()?;
+        Ok(message)
+    }
+}
+
+fn quantize_unit(value: f32) -> u32 {
+    (value.clamp(0.0, 1.0) * u16::MAX as f32).round() as u32
+}
+
+fn dequantize_unit(value: u32) -> f32 {
+    value as f32 / u16::MAX as f32
+}
+
+fn write_position(writer: &mut BitWriter, position: Vec2, bounds: &QuantizationBounds) {
+    let unit = (position - bounds.min) / (bounds.max - bounds.min);
+    writer.write_bits(quantize_unit(unit.x), 16);
+    writer.write_bits(quantize_unit(unit.y), 16);
+}
+
+fn read_position(reader: &mut BitReader, bounds: &QuantizationBounds) -> Result<Vec2, DecodeError> {
+    let x = dequantize_unit(reader.read_bits(16)?);
+    let y = dequantize_unit(reader.read_bits(16)?);
+    Ok(bounds.min + Vec2::new(x, y) * (bounds.max - bounds.min))
+}
+
+fn write_velocity(writer: &mut BitWriter, velocity: Vec2, bounds: &QuantizationBounds) {
+    for component in [velocity.x, velocity.y] {
+        let scaled = (component / bounds.max_speed).clamp(-1.0, 1.0) * i16::MAX as f32;
+        writer.write_bits(scaled.round() as i16 as u16 as u32, 16);
+    }
+}
+
+fn read_velocity(reader: &mut BitReader, bounds: &QuantizationBounds) -> Result<Vec2, DecodeError> {
+    let mut read = || -> Result<f32, DecodeError> {
+        let value = reader.read_bits(16)? as u16 as i16;
+        // i16::MIN is never written, it decodes to the speed limit like i16::MIN + 1
+        Ok((value as f32 / i16::MAX as f32).max(-1.0) * bounds.max_speed)
+    };
+    Ok(Vec2::new(read()?, read()?))
+}
+
+fn quantize_angle(angle: f32) -> u32 {
+    let unit = angle.rem_euclid(TAU) / TAU;
+    ((unit * 65536.0).round() as u32) & 0xFFFF
+}
+
+fn dequantize_angle(value: u32) -> f32 {
+    value as f32 / 65536.0 * TAU
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: QuantizationBounds = QuantizationBounds {
+        min: Vec2::new(-2000.0, -2000.0),
+        max: Vec2::new(2000.0, 2000.0),
+        max_speed: 1000.0,
+    };
+
+    fn get_snapshot() -> Message {
+        Message::ServerSnapshot {
+            tick: 123_456,
+            last_input_tick: 123_400,
+            entities: vec![
+                EntityState {
+                    id: 1,
+                    position: Vec2::new(-1999.0, 1500.25),
+                    velocity: Vec2::new(300.0, -299.5),
+                    facing: 1.25,
+                    anim_state: 2,
+                },
+                EntityState {
+                    id: u32::MAX,
+                    position: Vec2::ZERO,
+                    velocity: Vec2::ZERO,
+                    facing: TAU - 0.001,
+                    anim_state: 255,
+                },
+            ],
+        }
+    }
+
+    fn assert_angle_close(a: f32, b: f32) {
+        let difference = (a - b).rem_euclid(TAU);
+        assert!(difference.min(TAU - difference) < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn exact_messages_round_trip() {
+        let messages = [
+            Message::Join {
+                name: "Brute".to_string(),
+            },
+            Message::Join {
+                name: String::new(),
+            },
+            Message::Leave,
+            Message::Accepted { entity_id: 42 },
+        ];
+
+        for message in messages {
+            let bytes = message.encode(&BOUNDS);
+            assert_eq!(Message::decode(&bytes, &BOUNDS), Ok(message));
+        }
+    }
+
+    #[test]
+    fn client_input_round_trips_within_precision() {
+        let message = Message::ClientInput {
+            tick: u32::MAX,
+            action_bits: 0b1010_0000_0000_0101,
+            cursor_world: Vec2::new(123.4, -1876.5),
+        };
+        let bytes = message.encode(&BOUNDS);
+        assert_eq!(bytes.len(), 1 + 1 + 4 + 2 + 4);
+
+        let Ok(Message::ClientInput {
+            tick,
+            action_bits,
+            cursor_world,
+        }) = Message::decode(&bytes, &BOUNDS)
+        else {
+            panic!("Decoded the wrong message");
+        };
+        assert_eq!(tick, u32::MAX);
+        assert_eq!(action_bits, 0b1010_0000_0000_0101);
+        let error = (cursor_world - Vec2::new(123.4, -1876.5)).abs();
+        assert!(error.cmple(BOUNDS.get_position_precision() * 1.01).all());
+    }
+
+    #[test]
+    fn snapshot_round_trips_within_precision() {
+        let message = get_snapshot();
+        let bytes = message.encode(&BOUNDS);
+
+        let (
+            Ok(Message::ServerSnapshot {
+                tick,
+                last_input_tick,
+                entities,
+            }),
+            Message::ServerSnapshot {
+                entities: expected, ..
+            },
+        ) = (Message::decode(&bytes, &BOUNDS), message)
+        else {
+            panic!("Decoded the wrong message");
+        };
+
+        assert_eq!(tick, 123_456);
+        assert_eq!(last_input_tick, 123_400);
+        assert_eq!(entities.len(), expected.len());
+        for (entity, expected) in entities.iter().zip(&expected) {
+            assert_eq!(entity.id, expected.id);
+            assert_eq!(entity.anim_state, expected.anim_state);
+            let position_error = (entity.position - expected.position).abs();
+            assert!(
+                position_error
+                    .cmple(BOUNDS.get_position_precision() * 1.01)
+                    .all()
+            );
+            let velocity_error = (entity.velocity - expected.velocity).abs();
+            assert!(velocity_error.max_element() <= BOUNDS.get_velocity_precision() * 1.01);
+            assert_angle_close(entity.facing, expected.facing);
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        let message = Message::ServerSnapshot {
+            tick: 0,
+            last_input_tick: 0,
+            entities: vec![EntityState {
+                id: 0,
+                position: Vec2::new(5000.0, -5000.0),
+                velocity: Vec2::new(-4000.0, 4000.0),
+                facing: -1.0,
+                anim_state: 0,
+            }],
+        };
+
+        let Ok(Message::ServerSnapshot { entities, .. }) =
+            Message::decode(&message.encode(&BOUNDS), &BOUNDS)
+        else {
+            panic!("Decoded the wrong message");
+        };
+        assert_eq!(entities[0].position, Vec2::new(2000.0, -2000.0));
+        assert_eq!(entities[0].velocity, Vec2::new(-1000.0, 1000.0));
+        assert_angle_close(entities[0].facing, TAU - 1.0);
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        let bytes = get_snapshot().encode(&BOUNDS);
+
+        // Every truncation of a valid message fails
+        for length in 0..bytes.len() {
+            assert_eq!(
+                Message::decode(&bytes[..length], &BOUNDS),
+                Err(DecodeError::UnexpectedEnd),
+                "length {}",
+                length
+            );
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            Message::decode(&trailing, &BOUNDS),
+            Err(DecodeError::TrailingBytes)
+        );
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[0] = PROTOCOL_VERSION + 1;
+        assert_eq!(
+            Message::decode(&wrong_version, &BOUNDS),
+            Err(DecodeError::UnsupportedVersion(PROTOCOL_VERSION + 1))
+        );
+
+        assert_eq!(
+            Message::decode(&[PROTOCOL_VERSION, 200], &BOUNDS),
+            Err(DecodeError::UnknownMessage(200))
+        );
+
+        // A count far larger than the payload
+        let huge_count = [
+            PROTOCOL_VERSION,
+            Message::SERVER_SNAPSHOT,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0xFF,
+            0xFF,
+        ];
+        assert_eq!(
+            Message::decode(&huge_count, &BOUNDS),
+            Err(DecodeError::UnexpectedEnd)
+        );
+
+        let invalid_name = [PROTOCOL_VERSION, Message::JOIN, 2, 0xC3, 0x28];
+        assert_eq!(
+            Message::decode(&invalid_name, &BOUNDS),
+            Err(DecodeError::InvalidString)
+        );
+    }
+
+    #[test]
+    fn long_names_are_cut_on_a_char_boundary() {
+        let name = "é".repeat(200); // 400 bytes
+        let bytes = Message::Join { name }.encode(&BOUNDS);
+        let Ok(Message::Join { name }) = Message::decode(&bytes, &BOUNDS) else {
+            panic!("Decoded the wrong message");
+        };
+        assert_eq!(name, "é".repeat(127));
+    }
+}
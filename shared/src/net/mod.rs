@@ -0,0 +1,11 @@
+mod bits;
+pub use bits::DecodeError;
+mod connection;
+pub use connection::{CONNECTION_TIMEOUT, Connection, RESEND_INTERVAL, Received};
+mod interpolation;
+pub use interpolation::{Snapshot, SnapshotInterpolator};
+mod message;
+pub use message::{
+    ACTION_BIT_COUNT, ACTION_MOVE, EntityState, Message, PROTOCOL_VERSION, QuantizationBounds,
+    SERVER_TICK_RATE, SNAPSHOT_INTERVAL,
+};
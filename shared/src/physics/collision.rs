@@ -1,16 +1,44 @@
 use crate::math::Vec2;
 
 #[repr(C)]
-#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum CollisionLayer {
     Environment,
     Player,
     PlayerProjectile,
     Enemy,
     EnemyProjectile,
+    Neutral,
+    NeutralProjectile,
 }
 
+pub const LAYER_COUNT: usize = 7;
+
+// A set of layers, one bit per layer
+pub type LayerMask = u32;
+
+pub const ALL_LAYERS: LayerMask = LayerMask::MAX;
+
 impl CollisionLayer {
+    pub const ALL: [CollisionLayer; LAYER_COUNT] = [
+        CollisionLayer::Environment,
+        CollisionLayer::Player,
+        CollisionLayer::PlayerProjectile,
+        CollisionLayer::Enemy,
+        CollisionLayer::EnemyProjectile,
+        CollisionLayer::Neutral,
+        CollisionLayer::NeutralProjectile,
+    ];
+
+    pub fn get_bit(self) -> LayerMask {
+        1 << self as u32
+    }
+
+    // Stops movement like a wall instead of being passed through
+    pub fn is_blocking(self) -> bool {
+        self == CollisionLayer::Environment
+    }
+
     pub fn collides_with(&self, other: CollisionLayer) -> bool {
         // We ehck in deterministic order so we only have to list each pair once
 
@@ -24,14 +52,55 @@ impl CollisionLayer {
             // Don't collide with own projectiles
             (CollisionLayer::Player, CollisionLayer::PlayerProjectile) => false,
             (CollisionLayer::Enemy, CollisionLayer::EnemyProjectile) => false,
+            (CollisionLayer::Neutral, CollisionLayer::NeutralProjectile) => false,
             _ => true,
         }
     }
 }
 
-#[derive(Copy, Clone)]
+// Which layers collide, starts out as CollisionLayer::collides_with and can be changed at
+// runtime, e.g. to let projectiles hit their own team
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CollisionMatrix {
+    rows: [LayerMask; LAYER_COUNT], // The layers each layer collides with
+}
+
+impl Default for CollisionMatrix {
+    fn default() -> Self {
+        let mut matrix = Self {
+            rows: [0; LAYER_COUNT],
+        };
+        for a in CollisionLayer::ALL {
+            for b in CollisionLayer::ALL {
+                matrix.set_collides(a, b, a.collides_with(b));
+            }
+        }
+        matrix
+    }
+}
+
+impl CollisionMatrix {
+    pub fn collides(&self, a: CollisionLayer, b: CollisionLayer) -> bool {
+        self.rows[a as usize] & b.get_bit() != 0
+    }
+
+    // Both ways, a pair collides or it doesn't
+    pub fn set_collides(&mut self, a: CollisionLayer, b: CollisionLayer, collides: bool) {
+        for (row, other) in [(a, b), (b, a)] {
+            if collides {
+                self.rows[row as usize] |= other.get_bit();
+            } else {
+                self.rows[row as usize] &= !other.get_bit();
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum CollisionShape {
     Circle { radius: f32 },
+    Rect { half_extents: Vec2 },               // Axis aligned
+    Obb { half_extents: Vec2, rotation: f32 }, // Radians, turning the x axis towards y
 }
 
 impl CollisionShape {
@@ -43,15 +112,122 @@ impl CollisionShape {
     pub fn get_local_abb(&self) -> (Vec2, Vec2) {
         match self {
             Self::Circle { radius } => (Vec2::new(-*radius, -*radius), Vec2::new(*radius, *radius)),
+            Self::Rect { half_extents } => (-*half_extents, *half_extents),
+            Self::Obb {
+                half_extents,
+                rotation,
+            } => {
+                let [x_axis, y_axis] = get_box_axes(*rotation);
+                let extents = x_axis.abs() * half_extents.x + y_axis.abs() * half_extents.y;
+                (-extents, extents)
+            }
+        }
+    }
+
+    // The directions of the faces of a box, none for a circle
+    fn get_axes(&self) -> Option<[Vec2; 2]> {
+        match self {
+            Self::Circle { .. } => None,
+            Self::Rect { .. } => Some([Vec2::X, Vec2::Y]),
+            Self::Obb { rotation, .. } => Some(get_box_axes(*rotation)),
+        }
+    }
+
+    // Half the width of the shape along a unit axis
+    fn get_projected_radius(&self, axis: Vec2) -> f32 {
+        match self {
+            Self::Circle { radius } => *radius,
+            Self::Rect { half_extents } => axis.abs().dot(*half_extents),
+            Self::Obb {
+                half_extents,
+                rotation,
+            } => {
+                let [x_axis, y_axis] = get_box_axes(*rotation);
+                axis.dot(x_axis).abs() * half_extents.x + axis.dot(y_axis).abs() * half_extents.y
+            }
         }
     }
 
+    // A rotated box and a circle are a rect and a circle in the frame of the box. Returns the
+    // rotation of that frame and both shapes in it.
+    fn get_box_frame(&self, other: &CollisionShape) -> Option<(f32, Self, Self)> {
+        match (self, other) {
+            (
+                Self::Obb {
+                    half_extents,
+                    rotation,
+                },
+                Self::Circle { .. },
+            ) => Some((
+                *rotation,
+                Self::Rect {
+                    half_extents: *half_extents,
+                },
+                *other,
+            )),
+            (
+                Self::Circle { .. },
+                Self::Obb {
+                    half_extents,
+                    rotation,
+                },
+            ) => Some((
+                *rotation,
+                *self,
+                Self::Rect {
+                    half_extents: *half_extents,
+                },
+            )),
+            _ => None,
+        }
+    }
+
+    fn is_rotated(&self) -> bool {
+        matches!(self, Self::Obb { .. })
+    }
+
+    // The other shape grown by this one, as a rect with rounded corners. Moving this shape's
+    // position into it is the same as the shapes starting to overlap.
+    fn get_minkowski_sum(&self, other: &CollisionShape) -> (Vec2, f32) {
+        match (self, other) {
+            (
+                Self::Circle { radius },
+                Self::Circle {
+                    radius: other_radius,
+                },
+            ) => (Vec2::ZERO, radius + other_radius),
+            (Self::Circle { radius }, Self::Rect { half_extents })
+            | (Self::Rect { half_extents }, Self::Circle { radius }) => (*half_extents, *radius),
+            (
+                Self::Rect { half_extents },
+                Self::Rect {
+                    half_extents: other_half_extents,
+                },
+            ) => (*half_extents + *other_half_extents, 0.0),
+            (Self::Obb { .. }, _) | (_, Self::Obb { .. }) => {
+                unreachable!("Rotated boxes are tested along their axes")
+            }
+        }
+    }
+
+    // The penetration depth and the normal pointing from this shape towards the other one,
+    // zero when they don't overlap
     pub fn get_overlap(
         &self,
         position: Vec2,
         other: &CollisionShape,
         other_position: Vec2,
     ) -> (f32, Vec2) {
+        if let Some((rotation, shape, other_shape)) = self.get_box_frame(other) {
+            let to_local = Vec2::from_angle(-rotation);
+            let (penetration, normal) = shape.get_overlap(
+                to_local.rotate(position),
+                &other_shape,
+                to_local.rotate(other_position),
+            );
+            return (penetration, Vec2::from_angle(rotation).rotate(normal));
+        }
+
         match (self, other) {
             (
                 CollisionShape::Circle { radius },
@@ -70,6 +246,448 @@ impl CollisionShape {
 
                 (0.0, Vec2::ZERO)
             }
+            // The circles were turned into the frame of the box above
+            (CollisionShape::Obb { .. }, _) | (_, CollisionShape::Obb { .. }) => {
+                self.get_box_overlap(position, other, other_position)
+            }
+            (CollisionShape::Rect { .. }, CollisionShape::Circle { .. }) => {
+                let (penetration, normal) = other.get_overlap(other_position, self, position);
+                (penetration, -normal)
+            }
+            _ => {
+                let (half_extents, radius) = self.get_minkowski_sum(other);
+                let offset = position - other_position;
+                let closest = offset.clamp(-half_extents, half_extents);
+                let outside = offset - closest;
+
+                if outside != Vec2::ZERO {
+                    let distance = outside.length();
+                    if distance >= radius {
+                        return (0.0, Vec2::ZERO);
+                    }
+                    return (radius - distance, -outside / distance);
+                }
+
+                // Inside the rect part, out along the axis with the least penetration
+                let depth = half_extents - offset.abs();
+                if depth.x < depth.y {
+                    (depth.x + radius, Vec2::new(-get_sign(offset.x), 0.0))
+                } else {
+                    (depth.y + radius, Vec2::new(0.0, -get_sign(offset.y)))
+                }
+            }
+        }
+    }
+
+    // Separating axis test of two boxes, one of them rotated. The axis the boxes overlap
+    // the least along is the way out, the later axis on a tie like for rects.
+    fn get_box_overlap(
+        &self,
+        position: Vec2,
+        other: &CollisionShape,
+        other_position: Vec2,
+    ) -> (f32, Vec2) {
+        let offset = position - other_position;
+        let mut overlap = (f32::INFINITY, Vec2::ZERO);
+        for axis in self
+            .get_axes()
+            .into_iter()
+            .chain(other.get_axes())
+            .flatten()
+        {
+            let distance = offset.dot(axis);
+            let depth =
+                self.get_projected_radius(axis) + other.get_projected_radius(axis) - distance.abs();
+            if depth <= 0.0 {
+                return (0.0, Vec2::ZERO);
+            }
+            if depth <= overlap.0 {
+                overlap = (depth, axis * -get_sign(distance));
+            }
+        }
+        overlap
+    }
+
+    // Moves this shape by delta and returns the fraction of it where it first touches the
+    // other shape, with the normal pointing from the other shape towards this one. Shapes
+    // that overlap at the start are hit at 0.
+    pub fn cast(
+        &self,
+        start: Vec2,
+        delta: Vec2,
+        other: &CollisionShape,
+        other_position: Vec2,
+    ) -> Option<(f32, Vec2)> {
+        if let Some((rotation, shape, other_shape)) = self.get_box_frame(other) {
+            let to_local = Vec2::from_angle(-rotation);
+            let (t, normal) = shape.cast(
+                to_local.rotate(start),
+                to_local.rotate(delta),
+                &other_shape,
+                to_local.rotate(other_position),
+            )?;
+            return Some((t, Vec2::from_angle(rotation).rotate(normal)));
+        }
+
+        let (penetration, normal) = self.get_overlap(start, other, other_position);
+        if penetration > 0.0 {
+            return Some((0.0, -normal));
+        }
+
+        let origin = start - other_position;
+        if self.is_rotated() || other.is_rotated() {
+            return self.cast_box(origin, delta, other);
+        }
+        let (half_extents, radius) = self.get_minkowski_sum(other);
+
+        // The rounded rect is the union of two crossed rects and the circles on its corners
+        let mut hit = [
+            cast_ray_aabb(origin, delta, half_extents + Vec2::new(radius, 0.0)),
+            cast_ray_aabb(origin, delta, half_extents + Vec2::new(0.0, radius)),
+        ]
+        .into_iter()
+        .flatten()
+        .min_by(|a, b| a.total_cmp(b));
+        if radius > 0.0 {
+            for corner in [
+                half_extents,
+                Vec2::new(-half_extents.x, half_extents.y),
+                -half_extents,
+                Vec2::new(half_extents.x, -half_extents.y),
+            ] {
+                if let Some(t) = cast_ray_circle(origin - corner, delta, radius) {
+                    hit = Some(hit.map_or(t, |hit| hit.min(t)));
+                }
+            }
+        }
+
+        let t = hit?;
+        let position = origin + delta * t;
+        let outside = position - position.clamp(-half_extents, half_extents);
+        let normal = if outside.length_squared() > 1e-6 {
+            outside.normalize()
+        } else if (position.x.abs() - half_extents.x).abs()
+            < (position.y.abs() - half_extents.y).abs()
+        {
+            Vec2::new(get_sign(position.x), 0.0)
+        } else {
+            Vec2::new(0.0, get_sign(position.y))
+        };
+        Some((t, normal))
+    }
+
+    // Two boxes touch once they overlap along every axis of both, so the ray enters the
+    // slab of every axis. The slab it enters last is the face that is hit.
+    fn cast_box(&self, origin: Vec2, delta: Vec2, other: &CollisionShape) -> Option<(f32, Vec2)> {
+        let mut enter = f32::NEG_INFINITY;
+        let mut exit: f32 = 1.0;
+        let mut normal = Vec2::ZERO;
+        for axis in self
+            .get_axes()
+            .into_iter()
+            .chain(other.get_axes())
+            .flatten()
+        {
+            let radius = self.get_projected_radius(axis) + other.get_projected_radius(axis);
+            let distance = origin.dot(axis);
+            let speed = delta.dot(axis);
+            if speed.abs() < 1e-9 {
+                if distance.abs() > radius {
+                    return None;
+                }
+                continue;
+            }
+
+            let a = (-radius - distance) / speed;
+            let b = (radius - distance) / speed;
+            if a.min(b) > enter {
+                enter = a.min(b);
+                normal = axis * -get_sign(speed);
+            }
+            exit = exit.min(a.max(b));
+        }
+
+        let t = enter.max(0.0);
+        (t <= exit).then_some((t, normal))
+    }
+
+    // Where a ray from origin along delta enters the shape, as a fraction of delta, with the
+    // normal of the surface. Rays starting inside are hit at 0.
+    pub fn raycast(&self, position: Vec2, origin: Vec2, delta: Vec2) -> Option<(f32, Vec2)> {
+        const POINT: CollisionShape = CollisionShape::Circle { radius: 0.0 };
+        POINT.cast(origin, delta, self, position)
+    }
+}
+
+// The x and y axes of a box turned by the rotation
+fn get_box_axes(rotation: f32) -> [Vec2; 2] {
+    let x_axis = Vec2::from_angle(rotation);
+    [x_axis, x_axis.perp()]
+}
+
+fn get_sign(value: f32) -> f32 {
+    if value < 0.0 { -1.0 } else { 1.0 }
+}
+
+// Entry fraction of a ray from origin along delta into a box around zero, within [0, 1]
+fn cast_ray_aabb(origin: Vec2, delta: Vec2, half_extents: Vec2) -> Option<f32> {
+    let mut enter: f32 = 0.0;
+    let mut exit: f32 = 1.0;
+    for axis in 0..2 {
+        if delta[axis].abs() < 1e-9 {
+            if origin[axis].abs() > half_extents[axis] {
+                return None;
+            }
+            continue;
+        }
+
+        let a = (-half_extents[axis] - origin[axis]) / delta[axis];
+        let b = (half_extents[axis] - origin[axis]) / delta[axis];
+        enter = enter.max(a.min(b));
+        exit = exit.min(a.max(b));
+    }
+    (enter <= exit).then_some(enter)
+}
+
+// Entry fraction of a ray from origin along delta into a circle around zero, within [0, 1]
+fn cast_ray_circle(origin: Vec2, delta: Vec2, radius: f32) -> Option<f32> {
+    let a = delta.length_squared();
+    let b = origin.dot(delta);
+    let c = origin.length_squared() - radius * radius;
+    if c <= 0.0 {
+        return Some(0.0);
+    }
+    if a <= 0.0 || b >= 0.0 {
+        return None; // Moving away
+    }
+
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let t = (-b - discriminant.sqrt()) / a;
+    (t <= 1.0).then_some(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_4;
+
+    use super::*;
+    use crate::rng::Rng;
+
+    // A 20 by 20 box standing on a corner, its top corner at (0, DIAMOND_TOP)
+    const DIAMOND: CollisionShape = CollisionShape::Obb {
+        half_extents: Vec2::new(10.0, 10.0),
+        rotation: FRAC_PI_4,
+    };
+
+    fn get_obb(half_extents: Vec2) -> CollisionShape {
+        CollisionShape::Obb {
+            half_extents,
+            rotation: 0.0,
+        }
+    }
+
+    #[test]
+    fn rotated_boxes_are_bounded_by_their_corners() {
+        let (min, max) = DIAMOND.get_local_abb();
+        let corner = 200.0_f32.sqrt();
+        assert!(min.abs_diff_eq(Vec2::splat(-corner), 1e-4));
+        assert!(max.abs_diff_eq(Vec2::splat(corner), 1e-4));
+
+        let (min, max) = get_obb(Vec2::new(30.0, 5.0)).get_aabb(Vec2::new(1.0, 2.0));
+        assert_eq!((min, max), (Vec2::new(-29.0, -3.0), Vec2::new(31.0, 7.0)));
+    }
+
+    #[test]
+    fn nearly_parallel_faces_push_along_the_face() {
+        let tilted = CollisionShape::Obb {
+            half_extents: Vec2::new(50.0, 10.0),
+            rotation: 0.001,
+        };
+        let floor = CollisionShape::Rect {
+            half_extents: Vec2::new(50.0, 10.0),
+        };
+
+        // The tilt lifts the far corner by 0.05
+        let (penetration, normal) = tilted.get_overlap(Vec2::ZERO, &floor, Vec2::new(0.0, 19.0));
+        assert!((penetration - 1.05).abs() < 1e-3);
+        assert!(normal.abs_diff_eq(Vec2::Y, 1e-3));
+
+        let (penetration, normal) = floor.get_overlap(Vec2::new(0.0, 19.0), &tilted, Vec2::ZERO);
+        assert!((penetration - 1.05).abs() < 1e-3);
+        assert!(normal.abs_diff_eq(Vec2::NEG_Y, 1e-3));
+
+        // Moving onto it face first
+        let (t, normal) = tilted
+            .cast(
+                Vec2::new(0.0, -10.0),
+                Vec2::new(0.0, 20.0),
+                &floor,
+                Vec2::new(0.0, 19.0),
+            )
+            .unwrap();
+        assert!((t - 0.4475).abs() < 1e-3);
+        assert!(normal.abs_diff_eq(Vec2::NEG_Y, 1e-3));
+    }
+
+    #[test]
+    fn contact_exactly_at_a_corner() {
+        let lid = CollisionShape::Rect {
+            half_extents: Vec2::new(20.0, 5.0),
+        };
+        let top = DIAMOND.get_projected_radius(Vec2::Y);
+
+        // Resting on the corner is touching but not overlapping
+        let resting = Vec2::new(0.0, top + 5.0);
+        assert_eq!(
+            DIAMOND.get_overlap(Vec2::ZERO, &lid, resting),
+            (0.0, Vec2::ZERO)
+        );
+
+        // Pressed onto it, out along the face of the lid and not the sides of the diamond
+        let pressed = resting - Vec2::new(0.0, 0.5);
+        let (penetration, normal) = DIAMOND.get_overlap(Vec2::ZERO, &lid, pressed);
+        assert!((penetration - 0.5).abs() < 1e-4);
+        assert_eq!(normal, Vec2::Y);
+
+        // A circle on the corner is pushed away from it
+        let ball = CollisionShape::Circle { radius: 5.0 };
+        let (penetration, normal) =
+            DIAMOND.get_overlap(Vec2::ZERO, &ball, Vec2::new(0.0, top + 4.0));
+        assert!((penetration - 1.0).abs() < 1e-4);
+        assert!(normal.abs_diff_eq(Vec2::Y, 1e-4));
+        let (penetration, normal) =
+            ball.get_overlap(Vec2::new(0.0, top + 4.0), &DIAMOND, Vec2::ZERO);
+        assert!((penetration - 1.0).abs() < 1e-4);
+        assert!(normal.abs_diff_eq(Vec2::NEG_Y, 1e-4));
+
+        // A ray straight down hits the corner
+        let (t, normal) = DIAMOND
+            .raycast(Vec2::ZERO, Vec2::new(0.0, 30.0), Vec2::new(0.0, -30.0))
+            .unwrap();
+        assert!((t - (30.0 - top) / 30.0).abs() < 1e-4);
+        assert!(normal.y > 0.7);
+
+        // The lid falling onto it stops on the corner
+        let (t, normal) = lid
+            .cast(
+                resting + Vec2::new(0.0, 10.0),
+                Vec2::new(0.0, -20.0),
+                &DIAMOND,
+                Vec2::ZERO,
+            )
+            .unwrap();
+        assert!((t - 0.5).abs() < 1e-4);
+        assert_eq!(normal, Vec2::Y);
+    }
+
+    #[test]
+    fn unrotated_boxes_collide_like_rects() {
+        let half_extents = Vec2::new(20.0, 10.0);
+        let rect = CollisionShape::Rect { half_extents };
+        let obb = get_obb(half_extents);
+        let ball = CollisionShape::Circle { radius: 8.0 };
+
+        for position in [
+            Vec2::new(25.0, 3.0),
+            Vec2::new(-3.0, -15.0),
+            Vec2::new(24.0, 14.0),
+            Vec2::new(41.0, 0.0),
+        ] {
+            assert_eq!(
+                obb.get_overlap(Vec2::ZERO, &ball, position),
+                rect.get_overlap(Vec2::ZERO, &ball, position)
+            );
+            assert_eq!(
+                obb.get_overlap(Vec2::ZERO, &rect, position),
+                rect.get_overlap(Vec2::ZERO, &rect, position)
+            );
+        }
+
+        let delta = Vec2::new(100.0, 10.0);
+        let start = Vec2::new(-60.0, 0.0);
+        assert_eq!(
+            obb.cast(start, delta, &rect, Vec2::ZERO),
+            rect.cast(start, delta, &rect, Vec2::ZERO)
+        );
+        assert_eq!(
+            obb.raycast(Vec2::ZERO, start, delta),
+            rect.raycast(Vec2::ZERO, start, delta)
+        );
+    }
+
+    // Unrotated boxes take the separating axis path and rects the Minkowski path, both have
+    // to agree wherever the shapes are
+    #[test]
+    fn unrotated_boxes_match_rects_at_random() {
+        let mut rng = Rng::new(2478);
+        let mut get_vec2 = |min: f32, max: f32| {
+            Vec2::new(
+                min + (max - min) * rng.gen_f32(),
+                min + (max - min) * rng.gen_f32(),
+            )
+        };
+
+        for _ in 0..2000 {
+            let half_extents = get_vec2(1.0, 50.0);
+            let other_half_extents = get_vec2(1.0, 50.0);
+            let position = get_vec2(-80.0, 80.0);
+            let other_position = get_vec2(-80.0, 80.0);
+            let delta = get_vec2(-200.0, 200.0);
+            let radius = get_vec2(1.0, 30.0).x;
+
+            let rect = CollisionShape::Rect { half_extents };
+            let other_rect = CollisionShape::Rect {
+                half_extents: other_half_extents,
+            };
+            let circle = CollisionShape::Circle { radius };
+            let pairs = [
+                ((rect, other_rect), (get_obb(half_extents), other_rect)),
+                ((rect, other_rect), (rect, get_obb(other_half_extents))),
+                (
+                    (rect, other_rect),
+                    (get_obb(half_extents), get_obb(other_half_extents)),
+                ),
+                ((circle, other_rect), (circle, get_obb(other_half_extents))),
+                ((rect, circle), (get_obb(half_extents), circle)),
+            ];
+
+            for ((expected, other_expected), (shape, other_shape)) in pairs {
+                let (penetration, normal) =
+                    expected.get_overlap(position, &other_expected, other_position);
+                let (actual_penetration, actual_normal) =
+                    shape.get_overlap(position, &other_shape, other_position);
+                assert!((penetration - actual_penetration).abs() < 1e-3);
+
+                // On a tie either way out has to separate the shapes
+                if actual_normal.distance(normal) > 1e-3 {
+                    let resolved = position - actual_normal * (actual_penetration + 1e-3);
+                    let (left, _) = expected.get_overlap(resolved, &other_expected, other_position);
+                    assert_eq!(
+                        left, 0.0,
+                        "{expected:?} at {position} by {other_expected:?}"
+                    );
+                }
+
+                let hit = expected.cast(position, delta, &other_expected, other_position);
+                let actual_hit = shape.cast(position, delta, &other_shape, other_position);
+                assert_eq!(hit.is_some(), actual_hit.is_some());
+                if let (Some((t, normal)), Some((actual_t, actual_normal))) = (hit, actual_hit) {
+                    assert!((t - actual_t).abs() < 1e-4);
+                    // Corners of rects are hit along either face
+                    assert!(actual_normal.distance(normal) < 1e-3 || radius > 0.0 || t == 0.0);
+                }
+            }
+
+            let start = position - delta * 0.5;
+            let hit = other_rect.raycast(other_position, start, delta);
+            let actual_hit = get_obb(other_half_extents).raycast(other_position, start, delta);
+            assert_eq!(hit.is_some(), actual_hit.is_some());
+            if let (Some((t, _)), Some((actual_t, _))) = (hit, actual_hit) {
+                assert!((t - actual_t).abs() < 1e-4);
+            }
         }
     }
 }
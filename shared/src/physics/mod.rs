@@ -1,4 +1,9 @@
 mod collision;
-pub use collision::{CollisionLayer, CollisionShape};
+pub use collision::{
+    ALL_LAYERS, CollisionLayer, CollisionMatrix, CollisionShape, LAYER_COUNT, LayerMask,
+};
 mod physics_world;
-pub use physics_world::{BodyId, BodySettings, BodyState, PhysicsWorld};
+pub use physics_world::{
+    BodyId, BodySettings, BodyState, DEFAULT_GRID_CELL_SIZE, DEFAULT_SIMULATION_ITERATIONS,
+    PhysicsWorld, ShapeCastResult, StepStats,
+};
@@ -1,25 +1,37 @@
 use std::collections::BTreeMap;
 
 use crate::{
-    math::Vec2,
-    physics::{CollisionLayer, collision::CollisionShape},
+    math::{
+        Vec2,
+        det::{POSITION_STEP, quantize_vec2},
+    },
+    physics::{
+        CollisionLayer,
+        collision::{CollisionMatrix, CollisionShape, LayerMask},
+    },
     pool::{Pool, PoolIndex},
+    team::Team,
 };
 
-const GRID_CELL_SIZE: f32 = 160.0;
+pub const DEFAULT_GRID_CELL_SIZE: f32 = 160.0;
+pub const DEFAULT_SIMULATION_ITERATIONS: u32 = 4;
+// Iterating stops early once no body is moved further than this in an iteration
+const CORRECTION_EPSILON: f32 = 0.01;
 type GridCellIndex = (i32, i32);
 type Grid = BTreeMap<GridCellIndex, Vec<BodyId>>;
 
-pub fn _get_grid_cell_index(position: Vec2) -> GridCellIndex {
+pub fn _get_grid_cell_index(position: Vec2, cell_size: f32) -> GridCellIndex {
     (
-        (position.x / GRID_CELL_SIZE).floor() as i32,
-        (position.y / GRID_CELL_SIZE).floor() as i32,
+        (position.x / cell_size).floor() as i32,
+        (position.y / cell_size).floor() as i32,
     )
 }
 
+// Every cell the AABB touches, no matter how many cells it spans
 pub fn for_grid_cells_in_aabb<T: FnMut((i32, i32)) -> ()>(
     aabb_min: Vec2,
     aabb_max: Vec2,
+    cell_size: f32,
     mut f: T,
 ) {
     let min_x = aabb_min.x.min(aabb_max.x);
@@ -27,10 +39,10 @@ pub fn for_grid_cells_in_aabb<T: FnMut((i32, i32)) -> ()>(
     let min_y = aabb_min.y.min(aabb_max.y);
     let max_y = aabb_min.y.max(aabb_max.y);
 
-    let min_cell_x = (min_x / GRID_CELL_SIZE).floor() as i32;
-    let max_cell_x = (max_x / GRID_CELL_SIZE).floor() as i32;
-    let min_cell_y = (min_y / GRID_CELL_SIZE).floor() as i32;
-    let max_cell_y = (max_y / GRID_CELL_SIZE).floor() as i32;
+    let min_cell_x = (min_x / cell_size).floor() as i32;
+    let max_cell_x = (max_x / cell_size).floor() as i32;
+    let min_cell_y = (min_y / cell_size).floor() as i32;
+    let max_cell_y = (max_y / cell_size).floor() as i32;
 
     for cy in min_cell_y..=max_cell_y {
         for cx in min_cell_x..=max_cell_x {
@@ -39,6 +51,25 @@ pub fn for_grid_cells_in_aabb<T: FnMut((i32, i32)) -> ()>(
     }
 }
 
+// In every cell the AABB touches, large bodies like walls are in many of them
+fn add_to_grid(grid: &mut Grid, cell_size: f32, body_id: BodyId, aabb_min: Vec2, aabb_max: Vec2) {
+    for_grid_cells_in_aabb(aabb_min, aabb_max, cell_size, |cell_index| {
+        let bodies = grid.entry(cell_index).or_default();
+
+        if bodies.len() > 32 {
+            log::warn!(
+                "The number of bodies in one cell is high({}), consider not doing linear search.",
+                bodies.len()
+            )
+        }
+
+        // This linear search will be fast for few elements
+        if !bodies.contains(&body_id) {
+            bodies.push(body_id);
+        }
+    });
+}
+
 pub type BodyId = PoolIndex;
 
 pub struct ContactEvent {
@@ -53,6 +84,8 @@ struct Body {
     layer: CollisionLayer,
     shape: CollisionShape,
     contacts: Option<Vec<ContactEvent>>, // None if not listining to contacts
+    enabled: bool,                       // Disabled bodies are kept but left out of everything
+    user_data: u64,                      // The game's, 0 until it sets some
 }
 
 impl Body {
@@ -69,27 +102,119 @@ pub struct BodySettings<'a> {
     pub listen_to_contact_events: bool,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct BodyState {
     pub position: Vec2,
     pub velocity: Vec2,
 }
 
+#[derive(Debug, Default)]
+pub struct ShapeCastResult {
+    pub blocked_at: Option<(f32, BodyId, Vec2)>, // Fraction of the path, the body and its normal
+    pub overlapped: Vec<BodyId>, // Passed through before being blocked, nearest first
+}
+
+// How much work the last step was, for the debug overlay
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct StepStats {
+    pub body_count: usize,    // The enabled ones
+    pub pair_count: usize,    // Pairs sharing a grid cell on colliding layers
+    pub contact_count: usize, // Overlapping pairs in the first iteration, like the events
+    pub iteration_count: u32, // Fewer than the maximum when the solver converged early
+    pub max_penetration_after_solve: f32,
+    pub grid_cell_count: usize,
+}
+
 pub struct PhysicsWorld {
     bodies: Pool<Body>,
     grid: Grid,
+    cell_size: f32,
+    iterations: u32,
+    correction_epsilon: f32,
+    quantized: bool, // Positions and velocities snapped to the grid of det after each step
+    collision_matrix: CollisionMatrix,
+    last_step_stats: StepStats,
 }
 
 impl PhysicsWorld {
-    const NUM_SIMULATION_ITERATIONS: u32 = 4;
-
     pub fn new() -> Self {
+        Self::with_settings(DEFAULT_GRID_CELL_SIZE, DEFAULT_SIMULATION_ITERATIONS)
+    }
+
+    pub fn with_cell_size(cell_size: f32) -> Self {
+        Self::with_settings(cell_size, DEFAULT_SIMULATION_ITERATIONS)
+    }
+
+    // Iterations are the most the solver runs per step
+    pub fn with_settings(cell_size: f32, iterations: u32) -> Self {
+        assert!(cell_size > 0.0, "The grid cell size must be positive");
         Self {
             bodies: Pool::new(),
             grid: BTreeMap::new(),
+            cell_size,
+            iterations,
+            correction_epsilon: CORRECTION_EPSILON,
+            quantized: false,
+            collision_matrix: CollisionMatrix::default(),
+            last_step_stats: StepStats::default(),
         }
     }
 
+    pub fn get_iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    pub fn set_iterations(&mut self, iterations: u32) {
+        self.iterations = iterations;
+    }
+
+    pub fn is_quantized(&self) -> bool {
+        self.quantized
+    }
+
+    // For states that have to match bit for bit on other platforms, see math::det
+    pub fn set_quantized(&mut self, quantized: bool) {
+        self.quantized = quantized;
+    }
+
+    pub fn get_collision_matrix(&self) -> CollisionMatrix {
+        self.collision_matrix
+    }
+
+    // Takes effect with the next step
+    pub fn set_collision_matrix(&mut self, collision_matrix: CollisionMatrix) {
+        self.collision_matrix = collision_matrix;
+    }
+
+    pub fn last_step_stats(&self) -> StepStats {
+        self.last_step_stats
+    }
+
+    pub fn get_cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    // Rebuilds the grid right away so queries keep working before the next step
+    pub fn set_cell_size(&mut self, cell_size: f32) {
+        assert!(cell_size > 0.0, "The grid cell size must be positive");
+        self.cell_size = cell_size;
+        self.build_grid();
+    }
+
+    // As large as the largest body, so no body spans more than four cells. Bodies of very
+    // different sizes are better off with the default and the large ones in many cells.
+    pub fn suggest_cell_size(&self) -> f32 {
+        self.bodies
+            .iter()
+            .map(|(_, body)| {
+                let (min, max) = body.shape.get_local_abb();
+                (max - min).max_element()
+            })
+            .reduce(f32::max)
+            .filter(|size| *size > 0.0)
+            .unwrap_or(DEFAULT_GRID_CELL_SIZE)
+    }
+
     pub fn create_rigid_body(&mut self, settings: &BodySettings) -> BodyId {
         self.bodies.push(Body {
             position: settings.position,
@@ -101,9 +226,52 @@ impl PhysicsWorld {
             } else {
                 None
             },
+            enabled: true,
+            user_data: 0,
         })
     }
 
+    // A disabled body keeps its slot and settings but neither moves, collides, shows up in
+    // queries nor has contacts, e.g. a pooled projectile waiting to be fired again. It leaves
+    // and rejoins the grid right away, the contacts others had with it in the last step are
+    // dropped.
+    pub fn set_enabled(&mut self, id: BodyId, enabled: bool) {
+        let Some(body) = self.bodies.get_mut(id) else {
+            return;
+        };
+        if body.enabled == enabled {
+            return;
+        }
+        body.enabled = enabled;
+        if enabled {
+            let (extent_min, extent_max) = body.shape.get_aabb(body.position);
+            add_to_grid(&mut self.grid, self.cell_size, id, extent_min, extent_max);
+            return;
+        }
+
+        if let Some(contacts) = &mut body.contacts {
+            contacts.clear();
+        }
+        for cell_bodies in self.grid.values_mut() {
+            cell_bodies.retain(|other| *other != id);
+        }
+        self.grid.retain(|_, cell_bodies| !cell_bodies.is_empty());
+        for (_, other) in self.bodies.iter_mut() {
+            if let Some(contacts) = &mut other.contacts {
+                contacts.retain(|contact| contact.other != id);
+            }
+        }
+    }
+
+    pub fn is_enabled(&self, id: BodyId) -> bool {
+        self.bodies.get(id).is_some_and(|b| b.enabled)
+    }
+
+    // query_shape keeps returning the id until the next step rebuilds the grid
+    pub fn remove_body(&mut self, id: BodyId) -> bool {
+        self.bodies.remove(id).is_some()
+    }
+
     pub fn get_state(&self, id: BodyId) -> Option<BodyState> {
         self.bodies.get(id).map(|b| BodyState {
             position: b.position,
@@ -121,10 +289,35 @@ impl PhysicsWorld {
             .and_then(|b| b.contacts.as_ref().map(|c| c.as_slice()))
     }
 
+    pub fn get_body_count(&self) -> usize {
+        self.bodies.len()
+    }
+
+    // In slot order, e.g. for saving every body
+    pub fn get_body_ids(&self) -> Vec<BodyId> {
+        self.bodies.iter().map(|(id, _)| id).collect()
+    }
+
+    pub fn is_listening_to_contact_events(&self, id: BodyId) -> bool {
+        self.bodies.get(id).is_some_and(|b| b.contacts.is_some())
+    }
+
     pub fn get_layer(&self, id: BodyId) -> Option<CollisionLayer> {
         self.bodies.get(id).map(|b| b.layer)
     }
 
+    // Whatever the game wants to find again from a contact with the body, the physics doesn't
+    // read it
+    pub fn set_user_data(&mut self, id: BodyId, user_data: u64) {
+        if let Some(body) = self.bodies.get_mut(id) {
+            body.user_data = user_data;
+        }
+    }
+
+    pub fn get_user_data(&self, id: BodyId) -> Option<u64> {
+        self.bodies.get(id).map(|b| b.user_data)
+    }
+
     pub fn set_position(&mut self, id: BodyId, position: Vec2) {
         if let Some(body) = self.bodies.get_mut(id) {
             body.position = position;
@@ -151,24 +344,15 @@ impl PhysicsWorld {
 
     fn build_grid(&mut self) {
         self.grid.clear();
-        for (body_id, body) in self.bodies.iter() {
-            // We check the four corners of the AABB. This works as long as the AABB is not larger then a cell
+        for (body_id, body) in self.bodies.iter().filter(|(_, body)| body.enabled) {
             let (extent_min, extent_max) = body.shape.get_aabb(body.position);
-            for_grid_cells_in_aabb(extent_min, extent_max, |cell_index| {
-                let bodies = self.grid.entry(cell_index).or_default();
-
-                if bodies.len() > 32 {
-                    log::warn!(
-                        "The number of bodies in one cell is high({}), consider not doing linear search.",
-                        bodies.len()
-                    )
-                }
-
-                // This linear search will be fast for few elements
-                if !bodies.contains(&body_id) {
-                    bodies.push(body_id);
-                }
-            });
+            add_to_grid(
+                &mut self.grid,
+                self.cell_size,
+                body_id,
+                extent_min,
+                extent_max,
+            );
         }
     }
 
@@ -183,34 +367,50 @@ impl PhysicsWorld {
                     let b1 = self.bodies.get(body_i).unwrap();
                     let b2 = self.bodies.get(body_j).unwrap();
 
-                    if !b1.layer.collides_with(b2.layer) {
+                    if !self.collision_matrix.collides(b1.layer, b2.layer) {
                         continue;
                     }
 
-                    // We only push pairs were this is true, if it is false,
-                    // it will be true when checking the other way around for another cell
                     if body_i.index() < body_j.index() {
                         pairs.push((body_i, body_j));
+                    } else {
+                        pairs.push((body_j, body_i));
                     }
                 }
             }
         }
 
+        // Bodies sharing several cells would be resolved once per cell
+        pairs.sort_by_key(|(a, b)| (a.index(), b.index()));
+        pairs.dedup();
         pairs
     }
 
-    pub fn step_simulation(&mut self, dt: f32) {
+    pub fn step_simulation(&mut self, dt: f32) -> StepStats {
+        crate::profile_scope!("Physics");
+        let mut body_count = 0;
         for (_, body) in self.bodies.iter_mut() {
-            body.position += body.velocity * dt;
             if let Some(contacts) = &mut body.contacts {
                 contacts.clear();
             }
+            if body.enabled {
+                body.position += body.velocity * dt;
+                body_count += 1;
+            }
         }
 
         self.build_grid();
         let collision_pairs: Vec<_> = self.get_collision_pairs();
-
-        for iter in 0..Self::NUM_SIMULATION_ITERATIONS {
+        let mut stats = StepStats {
+            body_count,
+            pair_count: collision_pairs.len(),
+            grid_cell_count: self.grid.len(),
+            ..Default::default()
+        };
+
+        for iter in 0..self.iterations {
+            stats.iteration_count += 1;
+            let mut max_correction: f32 = 0.0;
             for (body_id1, body_id2) in collision_pairs.iter() {
                 let body1 = self.bodies.get(*body_id1).unwrap();
                 let body2 = self.bodies.get(*body_id2).unwrap();
@@ -224,9 +424,11 @@ impl PhysicsWorld {
                 if penetration > 0.0 {
                     self.bodies.get_mut(*body_id1).unwrap().correct(-correction);
                     self.bodies.get_mut(*body_id2).unwrap().correct(correction);
+                    max_correction = max_correction.max(penetration * 0.5);
 
                     // Record contact events only on the first iteration
                     if iter == 0 {
+                        stats.contact_count += 1;
                         if let Some(contacts) =
                             &mut self.bodies.get_mut(*body_id1).unwrap().contacts
                         {
@@ -248,17 +450,47 @@ impl PhysicsWorld {
                     }
                 }
             }
+
+            if max_correction < self.correction_epsilon {
+                break;
+            }
+        }
+
+        if self.quantized {
+            for (_, body) in self.bodies.iter_mut() {
+                body.position = quantize_vec2(body.position, POSITION_STEP);
+                body.velocity = quantize_vec2(body.velocity, POSITION_STEP);
+            }
         }
 
+        stats.max_penetration_after_solve = collision_pairs
+            .iter()
+            .map(|(body_id1, body_id2)| {
+                let body1 = self.bodies.get(*body_id1).unwrap();
+                let body2 = self.bodies.get(*body_id2).unwrap();
+                let (penetration, _) =
+                    body1
+                        .shape
+                        .get_overlap(body1.position, &body2.shape, body2.position);
+                penetration
+            })
+            .fold(0.0, f32::max);
+        self.last_step_stats = stats;
+
         // Build for query
         self.build_grid();
+        stats
     }
 
     pub fn query_shape(&self, position: Vec2, shape: CollisionShape) -> Vec<BodyId> {
         let (extent_min, extent_max) = shape.get_aabb(position);
+        self.query_aabb(extent_min, extent_max)
+    }
+
+    fn query_aabb(&self, extent_min: Vec2, extent_max: Vec2) -> Vec<BodyId> {
         let mut result = Vec::new();
 
-        for_grid_cells_in_aabb(extent_min, extent_max, |cell_index| {
+        for_grid_cells_in_aabb(extent_min, extent_max, self.cell_size, |cell_index| {
             if let Some(cell_bodies) = self.grid.get(&cell_index) {
                 for &id in cell_bodies {
                     if !result.contains(&id) {
@@ -270,4 +502,468 @@ impl PhysicsWorld {
 
         result
     }
+
+    // Bodies actually overlapping the shape that pass the filter, unlike query_shape this
+    // tests the shapes and not only the grid cells. Uses the grid of the last step.
+    pub fn query_shape_filtered<F: Fn(BodyId, CollisionLayer) -> bool>(
+        &self,
+        position: Vec2,
+        shape: CollisionShape,
+        filter: F,
+    ) -> Vec<BodyId> {
+        let mut result = self.query_shape(position, shape);
+        result.retain(|&id| {
+            let Some(body) = self.bodies.get(id) else {
+                return false;
+            };
+
+            let (penetration, _) = shape.get_overlap(position, &body.shape, body.position);
+            penetration > 0.0 && filter(id, body.layer)
+        });
+
+        result
+    }
+
+    // Units overlapping the shape whose team passes the filter, e.g. the enemies in range
+    // with |team| team != Team::Blue. Projectiles and the environment are left out.
+    pub fn query_shape_of_teams<F: Fn(Team) -> bool>(
+        &self,
+        position: Vec2,
+        shape: CollisionShape,
+        filter: F,
+    ) -> Vec<BodyId> {
+        self.query_shape_filtered(position, shape, |_, layer| {
+            !Team::is_projectile_layer(layer) && Team::from_layer(layer).is_some_and(&filter)
+        })
+    }
+
+    // Sweeps the shape from start to end against the bodies on the masked layers, e.g. for a
+    // dash. Blocking bodies stop it, one it starts in only when moving further into it.
+    // Uses the grid of the last step.
+    pub fn shape_cast(
+        &self,
+        shape: CollisionShape,
+        start: Vec2,
+        end: Vec2,
+        layer_mask: LayerMask,
+    ) -> ShapeCastResult {
+        let delta = end - start;
+        let (start_min, start_max) = shape.get_aabb(start);
+        let (end_min, end_max) = shape.get_aabb(end);
+
+        let mut blocked_at: Option<(f32, BodyId, Vec2)> = None;
+        let mut overlaps = Vec::new();
+        for id in self.query_aabb(start_min.min(end_min), start_max.max(end_max)) {
+            let Some(body) = self.bodies.get(id) else {
+                continue;
+            };
+            if body.layer.get_bit() & layer_mask == 0 {
+                continue;
+            }
+            let Some((t, normal)) = shape.cast(start, delta, &body.shape, body.position) else {
+                continue;
+            };
+
+            if !body.layer.is_blocking() {
+                overlaps.push((t, id));
+            } else if (t > 0.0 || delta.dot(normal) < 0.0)
+                && blocked_at.is_none_or(|(blocked_t, _, _)| t < blocked_t)
+            {
+                blocked_at = Some((t, id, normal));
+            }
+        }
+
+        let limit = blocked_at.map_or(1.0, |(t, _, _)| t);
+        overlaps.retain(|(t, _)| *t <= limit);
+        overlaps.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        ShapeCastResult {
+            blocked_at,
+            overlapped: overlaps.into_iter().map(|(_, id)| id).collect(),
+        }
+    }
+
+    // The first body on the masked layers on the segment from start to end, with the
+    // fraction of the way to it and the normal of its surface. Uses the grid of the last step.
+    pub fn raycast(
+        &self,
+        start: Vec2,
+        end: Vec2,
+        layer_mask: LayerMask,
+    ) -> Option<(f32, BodyId, Vec2)> {
+        let delta = end - start;
+        self.query_aabb(start.min(end), start.max(end))
+            .into_iter()
+            .filter_map(|id| {
+                let body = self.bodies.get(id)?;
+                if body.layer.get_bit() & layer_mask == 0 {
+                    return None;
+                }
+                let (t, normal) = body.shape.raycast(body.position, start, delta)?;
+                Some((t, id, normal))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::collision::ALL_LAYERS;
+
+    #[test]
+    fn filtered_query_tests_overlap_and_layer() {
+        let mut world = PhysicsWorld::new();
+        let mut create = |x: f32, layer: CollisionLayer| {
+            world.create_rigid_body(&BodySettings {
+                position: Vec2::new(x, 0.0),
+                velocity: Vec2::ZERO,
+                layer,
+                shape: &CollisionShape::Circle { radius: 10.0 },
+                listen_to_contact_events: false,
+            })
+        };
+        let near_enemy = create(40.0, CollisionLayer::Enemy);
+        let _near_player = create(-40.0, CollisionLayer::Player);
+        let _far_enemy = create(100.0, CollisionLayer::Enemy); // Same grid cell, out of range
+        world.step_simulation(0.0);
+
+        let result = world.query_shape_filtered(
+            Vec2::ZERO,
+            CollisionShape::Circle { radius: 40.0 },
+            |_, layer| layer == CollisionLayer::Enemy,
+        );
+        assert_eq!(result, vec![near_enemy]);
+
+        let result = world.query_shape_of_teams(
+            Vec2::ZERO,
+            CollisionShape::Circle { radius: 40.0 },
+            |team| team != Team::Blue,
+        );
+        assert_eq!(result, vec![near_enemy]);
+    }
+
+    #[test]
+    fn collision_matrix_decides_which_pairs_touch() {
+        let mut world = PhysicsWorld::new();
+        let mut create = |x: f32, layer: CollisionLayer| {
+            world.create_rigid_body(&BodySettings {
+                position: Vec2::new(x, 0.0),
+                velocity: Vec2::ZERO,
+                layer,
+                shape: &CollisionShape::Circle { radius: 10.0 },
+                listen_to_contact_events: true,
+            })
+        };
+        let unit = create(0.0, Team::Blue.get_unit_layer());
+        let projectile = create(5.0, Team::Blue.get_projectile_layer());
+        world.step_simulation(0.0);
+        assert!(world.get_contacts(projectile).unwrap().is_empty());
+
+        let mut matrix = world.get_collision_matrix();
+        matrix.set_collides(
+            CollisionLayer::PlayerProjectile,
+            CollisionLayer::Player,
+            true,
+        );
+        assert!(matrix.collides(CollisionLayer::Player, CollisionLayer::PlayerProjectile));
+        world.set_collision_matrix(matrix);
+        world.step_simulation(0.0);
+        let contacts = world.get_contacts(projectile).unwrap();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].other, unit);
+
+        // The defaults are the layer rules
+        let matrix = CollisionMatrix::default();
+        for a in CollisionLayer::ALL {
+            for b in CollisionLayer::ALL {
+                assert_eq!(matrix.collides(a, b), a.collides_with(b));
+            }
+        }
+    }
+
+    fn create_body(world: &mut PhysicsWorld, position: Vec2, shape: CollisionShape) -> BodyId {
+        let layer = match shape {
+            CollisionShape::Circle { .. } => CollisionLayer::Enemy,
+            CollisionShape::Rect { .. } | CollisionShape::Obb { .. } => CollisionLayer::Environment,
+        };
+        world.create_rigid_body(&BodySettings {
+            position,
+            velocity: Vec2::ZERO,
+            layer,
+            shape: &shape,
+            listen_to_contact_events: false,
+        })
+    }
+
+    const DASHER: CollisionShape = CollisionShape::Circle { radius: 10.0 };
+    const ENEMY: CollisionShape = CollisionShape::Circle { radius: 10.0 };
+    const WALL: CollisionShape = CollisionShape::Rect {
+        half_extents: Vec2::new(20.0, 20.0),
+    };
+
+    #[test]
+    fn shape_cast_starting_inside_bodies() {
+        let mut world = PhysicsWorld::new();
+        let touching = create_body(&mut world, Vec2::new(5.0, 0.0), ENEMY);
+        let passed = create_body(&mut world, Vec2::new(100.0, 0.0), ENEMY);
+        let wall = create_body(&mut world, Vec2::new(-25.0, 0.0), WALL);
+        world.step_simulation(0.0);
+
+        // Out of the wall it starts in, through both enemies
+        let result = world.shape_cast(DASHER, Vec2::ZERO, Vec2::new(200.0, 0.0), ALL_LAYERS);
+        assert!(result.blocked_at.is_none());
+        assert_eq!(result.overlapped, vec![touching, passed]);
+
+        // Further into the wall is blocked right away
+        let result = world.shape_cast(DASHER, Vec2::ZERO, Vec2::new(-200.0, 0.0), ALL_LAYERS);
+        let (t, body, normal) = result.blocked_at.unwrap();
+        assert_eq!((t, body), (0.0, wall));
+        assert_eq!(normal, Vec2::X);
+        assert_eq!(result.overlapped, vec![touching]);
+
+        // Layers outside of the mask are ignored
+        let mask = CollisionLayer::Environment.get_bit();
+        let result = world.shape_cast(DASHER, Vec2::ZERO, Vec2::new(200.0, 0.0), mask);
+        assert!(result.overlapped.is_empty());
+    }
+
+    #[test]
+    fn shape_cast_clipping_a_wall_corner() {
+        let mut world = PhysicsWorld::new();
+        let before = create_body(&mut world, Vec2::new(40.0, 0.0), ENEMY);
+        let _behind = create_body(&mut world, Vec2::new(150.0, 0.0), ENEMY);
+        // The bottom left corner is at (80, 5), 5 units into the path of the dasher
+        let wall = create_body(&mut world, Vec2::new(100.0, 25.0), WALL);
+        world.step_simulation(0.0);
+
+        let result = world.shape_cast(DASHER, Vec2::ZERO, Vec2::new(200.0, 0.0), ALL_LAYERS);
+        let (t, body, normal) = result.blocked_at.unwrap();
+        assert_eq!(body, wall);
+
+        let x = 80.0 - 75.0_f32.sqrt();
+        assert!((t * 200.0 - x).abs() < 1e-3);
+        assert!(normal.distance(Vec2::new(x - 80.0, -5.0) / 10.0) < 1e-3);
+        assert_eq!(result.overlapped, vec![before]);
+
+        // Missing the corner by a unit
+        let result = world.shape_cast(
+            DASHER,
+            Vec2::new(0.0, -6.0),
+            Vec2::new(200.0, -6.0),
+            ALL_LAYERS,
+        );
+        assert!(result.blocked_at.is_none());
+        assert_eq!(result.overlapped.len(), 2);
+    }
+
+    #[test]
+    fn raycast_hits_the_closest_rotated_wall() {
+        let mut world = PhysicsWorld::new();
+        let near = create_body(
+            &mut world,
+            Vec2::new(100.0, 0.0),
+            CollisionShape::Obb {
+                half_extents: Vec2::new(10.0, 40.0),
+                rotation: std::f32::consts::FRAC_PI_4,
+            },
+        );
+        let _far = create_body(&mut world, Vec2::new(200.0, 0.0), WALL);
+        let enemy = create_body(&mut world, Vec2::new(50.0, 0.0), ENEMY);
+        world.step_simulation(0.0);
+
+        // The face of the wall is 10 / cos(45) in front of its center, facing back and down
+        let mask = CollisionLayer::Environment.get_bit();
+        let (t, body, normal) = world
+            .raycast(Vec2::ZERO, Vec2::new(300.0, 0.0), mask)
+            .unwrap();
+        assert_eq!(body, near);
+        assert!((t * 300.0 - (100.0 - 200.0_f32.sqrt())).abs() < 1e-3);
+        assert!(normal.abs_diff_eq(Vec2::new(-1.0, -1.0).normalize(), 1e-4));
+
+        let (_, body, _) = world
+            .raycast(Vec2::ZERO, Vec2::new(300.0, 0.0), ALL_LAYERS)
+            .unwrap();
+        assert_eq!(body, enemy);
+        assert!(
+            world
+                .raycast(Vec2::ZERO, Vec2::new(0.0, 300.0), ALL_LAYERS)
+                .is_none()
+        );
+    }
+
+    // Spans many cells at any of the tested sizes, none of its corners is near the middle
+    const LONG_WALL: CollisionShape = CollisionShape::Rect {
+        half_extents: Vec2::new(500.0, 20.0),
+    };
+
+    #[test]
+    fn long_wall_collides_in_its_center_cell() {
+        for cell_size in [40.0, DEFAULT_GRID_CELL_SIZE, 300.0, 1000.0] {
+            let mut world = PhysicsWorld::with_cell_size(cell_size);
+            let center = Vec2::new(1030.0, -470.0);
+            let wall = create_body(&mut world, center, LONG_WALL);
+            let circle = world.create_rigid_body(&BodySettings {
+                position: center + Vec2::new(0.0, 25.0),
+                velocity: Vec2::ZERO,
+                layer: CollisionLayer::Enemy,
+                shape: &ENEMY,
+                listen_to_contact_events: true,
+            });
+            world.step_simulation(0.0);
+
+            // One contact even though both bodies share several cells
+            let contacts = world.get_contacts(circle).unwrap();
+            assert_eq!(contacts.len(), 1, "cell size {}", cell_size);
+            assert_eq!(contacts[0].other, wall);
+            assert!((contacts[0].penetration - 5.0).abs() < 1e-4);
+
+            let found = world.query_shape(center, CollisionShape::Circle { radius: 1.0 });
+            assert!(found.contains(&wall), "cell size {}", cell_size);
+        }
+    }
+
+    #[test]
+    fn suggested_cell_size_fits_the_largest_shape() {
+        let mut world = PhysicsWorld::new();
+        assert_eq!(world.suggest_cell_size(), DEFAULT_GRID_CELL_SIZE);
+
+        create_body(&mut world, Vec2::ZERO, ENEMY);
+        assert_eq!(world.suggest_cell_size(), 20.0);
+        let wall = create_body(&mut world, Vec2::new(0.0, 100.0), LONG_WALL);
+        assert_eq!(world.suggest_cell_size(), 1000.0);
+
+        // Queries use the new size right away
+        world.set_cell_size(world.suggest_cell_size());
+        let found = world.query_shape(Vec2::new(400.0, 100.0), ENEMY);
+        assert!(found.contains(&wall));
+    }
+
+    // Circles in a row, each overlapping the next by 8 units, and one on top of the middle
+    fn create_pile(world: &mut PhysicsWorld) -> Vec<BodyId> {
+        let origin = Vec2::new(20.0, 20.0); // All in one grid cell
+        let mut bodies: Vec<_> = (0..6)
+            .map(|i| create_body(world, origin + Vec2::new(i as f32 * 12.0, 0.0), ENEMY))
+            .collect();
+        bodies.push(create_body(world, origin + Vec2::new(30.0, 15.0), ENEMY));
+        bodies
+    }
+
+    #[test]
+    fn early_out_keeps_the_solved_positions() {
+        let mut world = PhysicsWorld::with_settings(DEFAULT_GRID_CELL_SIZE, 200);
+        let mut reference = PhysicsWorld::with_settings(DEFAULT_GRID_CELL_SIZE, 200);
+        reference.correction_epsilon = 0.0;
+        let bodies = create_pile(&mut world);
+        create_pile(&mut reference);
+
+        let stats = world.step_simulation(0.0);
+        let reference_stats = reference.step_simulation(0.0);
+        assert!(stats.iteration_count < reference_stats.iteration_count);
+        assert_eq!(reference_stats.iteration_count, 200);
+
+        for body in bodies {
+            let position = world.get_state(body).unwrap().position;
+            let solved = reference.get_state(body).unwrap().position;
+            assert!(
+                position.distance(solved) < CORRECTION_EPSILON,
+                "{} vs {}",
+                position,
+                solved
+            );
+        }
+    }
+
+    #[test]
+    fn step_stats_describe_the_step() {
+        let mut world = PhysicsWorld::new();
+        create_pile(&mut world);
+        create_body(&mut world, Vec2::new(1000.0, 1000.0), WALL);
+
+        let stats = world.step_simulation(0.0);
+        assert_eq!(stats, world.last_step_stats());
+        assert_eq!(stats.body_count, 8);
+        // Every pair of the pile's circles is tested
+        assert_eq!(stats.pair_count, 21);
+        // Neighbours in the row, and the top circle with the two below it
+        assert_eq!(stats.contact_count, 7);
+        assert_eq!(stats.iteration_count, DEFAULT_SIMULATION_ITERATIONS);
+        assert!(stats.max_penetration_after_solve > 0.0);
+        assert!(stats.max_penetration_after_solve < 8.0);
+        assert_eq!(stats.grid_cell_count, 2);
+
+        // Once apart there is nothing left to solve
+        world.set_iterations(100);
+        let stats = world.step_simulation(0.0);
+        assert!(stats.max_penetration_after_solve < 0.1);
+        let stats = world.step_simulation(0.0);
+        assert_eq!(stats.iteration_count, 1);
+    }
+
+    #[test]
+    fn quantized_steps_end_on_the_grid() {
+        let mut world = PhysicsWorld::new();
+        world.set_quantized(true);
+        let bodies = create_pile(&mut world);
+        for &body in &bodies {
+            world.set_velocity(body, Vec2::new(1.0 / 3.0, -2.0 / 7.0));
+        }
+
+        world.step_simulation(1.0 / 60.0);
+        for body in bodies {
+            let state = world.get_state(body).unwrap();
+            assert_eq!(state.position, quantize_vec2(state.position, POSITION_STEP));
+            assert_eq!(state.velocity, quantize_vec2(state.velocity, POSITION_STEP));
+        }
+    }
+
+    #[test]
+    fn disabled_bodies_neither_collide_nor_show_up_in_queries() {
+        let mut world = PhysicsWorld::new();
+        let sensor = world.create_rigid_body(&BodySettings {
+            position: Vec2::ZERO,
+            velocity: Vec2::new(60.0, 0.0),
+            layer: CollisionLayer::PlayerProjectile,
+            shape: &CollisionShape::Circle { radius: 10.0 },
+            listen_to_contact_events: true,
+        });
+        let enemy = world.create_rigid_body(&BodySettings {
+            position: Vec2::new(5.0, 0.0),
+            velocity: Vec2::ZERO,
+            layer: CollisionLayer::Enemy,
+            shape: &CollisionShape::Circle { radius: 10.0 },
+            listen_to_contact_events: true,
+        });
+        world.step_simulation(0.0);
+        assert_eq!(world.get_contacts(sensor).unwrap().len(), 1);
+        assert_eq!(world.get_contacts(enemy).unwrap().len(), 1);
+
+        // Gone from the queries and the contacts right away, not only after the next step
+        world.set_enabled(sensor, false);
+        assert!(!world.is_enabled(sensor));
+        assert!(world.get_contacts(sensor).unwrap().is_empty());
+        assert!(world.get_contacts(enemy).unwrap().is_empty());
+        let query = CollisionShape::Circle { radius: 50.0 };
+        assert_eq!(world.query_shape(Vec2::ZERO, query), vec![enemy]);
+
+        let position = world.get_state(sensor).unwrap().position;
+        let stats = world.step_simulation(1.0);
+        assert_eq!(stats.body_count, 1);
+        assert_eq!(stats.pair_count, 0);
+        assert!(world.get_contacts(enemy).unwrap().is_empty());
+        // Nothing moved it, neither its velocity nor the enemy it is inside of
+        assert_eq!(world.get_state(sensor).unwrap().position, position);
+        assert_eq!(world.get_body_count(), 2);
+
+        // Moved far away while disabled, it is in the grid where it is now
+        world.set_position(sensor, Vec2::new(1000.0, 0.0));
+        world.set_enabled(sensor, true);
+        assert_eq!(
+            world.query_shape(Vec2::new(1000.0, 0.0), query),
+            vec![sensor]
+        );
+        assert_eq!(world.query_shape(Vec2::ZERO, query), vec![enemy]);
+        world.step_simulation(0.0);
+        assert!(world.get_contacts(sensor).unwrap().is_empty());
+    }
 }
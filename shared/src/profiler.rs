@@ -0,0 +1,375 @@
+// A scoped profiler for finding what a frame spends its time on. `profile_scope!("Name")`
+// records when the enclosing block begins and ends into a buffer of the thread, scopes
+// inside it become its children. A thread hands its events over whenever its outermost
+// scope ends, and ProfileCollector::end_frame assembles them into the tree of the frame.
+// While disabled a scope costs the one check of the flag, while enabled two reads of the
+// clock and two pushes into a vector that keeps its capacity.
+
+use std::{
+    cell::RefCell,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CLOCK: OnceLock<fn() -> f64> = OnceLock::new();
+static NEXT_THREAD: AtomicU32 = AtomicU32::new(0);
+// Of the threads whose outermost scope ended since the last frame
+static FINISHED: Mutex<Vec<ScopeEvent>> = Mutex::new(Vec::new());
+
+struct ThreadBuffer {
+    thread: u32, // In the order the threads first recorded a scope
+    depth: u32,
+    events: Vec<ScopeEvent>,
+}
+
+thread_local! {
+    static BUFFER: RefCell<ThreadBuffer> = RefCell::new(ThreadBuffer {
+        thread: NEXT_THREAD.fetch_add(1, Ordering::Relaxed),
+        depth: 0,
+        events: Vec::new(),
+    });
+}
+
+#[macro_export]
+macro_rules! profile_scope {
+    ($name:expr) => {
+        let _profile_scope = $crate::profiler::Scope::new($name);
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeEventKind {
+    Begin,
+    End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScopeEvent {
+    pub name: &'static str,
+    pub kind: ScopeEventKind,
+    pub thread: u32,
+    pub time: f64, // Seconds, of the clock
+}
+
+// Seconds from any fixed point, the client sets the one it times frames with. Without a
+// clock every scope takes no time.
+pub fn set_clock(clock: fn() -> f64) {
+    let _ = CLOCK.set(clock);
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn get_time() -> f64 {
+    CLOCK.get().map_or(0.0, |clock| clock())
+}
+
+// Ends when dropped, see profile_scope
+pub struct Scope {
+    name: Option<&'static str>, // None when the profiler was disabled as it began
+}
+
+impl Scope {
+    #[inline]
+    pub fn new(name: &'static str) -> Self {
+        if !is_enabled() {
+            return Self { name: None };
+        }
+        record(name, ScopeEventKind::Begin);
+        Self { name: Some(name) }
+    }
+}
+
+impl Drop for Scope {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(name) = self.name {
+            record(name, ScopeEventKind::End);
+        }
+    }
+}
+
+#[inline(never)]
+fn record(name: &'static str, kind: ScopeEventKind) {
+    let time = get_time();
+    BUFFER.with_borrow_mut(|buffer| {
+        buffer.events.push(ScopeEvent {
+            name,
+            kind,
+            thread: buffer.thread,
+            time,
+        });
+        buffer.depth = match kind {
+            ScopeEventKind::Begin => buffer.depth + 1,
+            ScopeEventKind::End => buffer.depth.saturating_sub(1),
+        };
+        if buffer.depth == 0
+            && let Ok(mut finished) = FINISHED.lock()
+        {
+            finished.append(&mut buffer.events);
+        }
+    });
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileNode {
+    pub name: &'static str,
+    pub thread: u32,
+    pub depth: u32,            // 0 for the outermost scopes of the thread
+    pub parent: Option<usize>, // Index of the enclosing scope's node
+    pub start: f64,
+    pub end: f64,
+}
+
+impl ProfileNode {
+    pub fn get_duration(&self) -> f64 {
+        self.end - self.start
+    }
+}
+
+// Parents come before their children, the scopes of a thread in the order they began.
+// Events of several threads can be interleaved. An end without its begin, of a scope that
+// began before the profiler was enabled, is skipped. A scope still open at the end of the
+// events ends with the frame.
+pub fn build_tree(events: &[ScopeEvent], frame_end: f64, nodes: &mut Vec<ProfileNode>) {
+    nodes.clear();
+    // The open scopes of each thread, innermost last
+    let mut stacks: Vec<(u32, Vec<usize>)> = Vec::new();
+    for event in events {
+        let stack = match stacks
+            .iter()
+            .position(|(thread, _)| *thread == event.thread)
+        {
+            Some(index) => &mut stacks[index].1,
+            None => {
+                stacks.push((event.thread, Vec::new()));
+                &mut stacks.last_mut().unwrap().1
+            }
+        };
+
+        match event.kind {
+            ScopeEventKind::Begin => {
+                stack.push(nodes.len());
+                nodes.push(ProfileNode {
+                    name: event.name,
+                    thread: event.thread,
+                    depth: stack.len() as u32 - 1,
+                    parent: stack.iter().rev().nth(1).copied(),
+                    start: event.time,
+                    end: frame_end,
+                });
+            }
+            ScopeEventKind::End => {
+                // Scopes end in reverse, anything above the match ends along with it
+                let Some(open) = stack
+                    .iter()
+                    .rposition(|&node| nodes[node].name == event.name)
+                else {
+                    continue;
+                };
+                for node in stack.drain(open..) {
+                    nodes[node].end = event.time;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameProfile {
+    pub start: f64,
+    pub end: f64,
+    pub nodes: Vec<ProfileNode>,
+}
+
+#[derive(Default)]
+pub struct ProfileCollector {
+    events: Vec<ScopeEvent>, // Swapped with the finished ones, both keep their capacity
+    frame: FrameProfile,
+    frame_start: Option<f64>,
+}
+
+impl ProfileCollector {
+    // Between two frames, outside of any scope. The frame is kept until the next one ends.
+    pub fn end_frame(&mut self) {
+        let end = get_time();
+        if let Ok(mut finished) = FINISHED.lock() {
+            std::mem::swap(&mut *finished, &mut self.events);
+        }
+
+        let first = self.events.first().map_or(end, |event| event.time);
+        self.frame.start = self.frame_start.unwrap_or(first);
+        self.frame.end = end;
+        build_tree(&self.events, end, &mut self.frame.nodes);
+        self.events.clear();
+        self.frame_start = Some(end);
+    }
+
+    // Drops what was recorded, e.g. the scopes that ended after the profiler was disabled
+    pub fn reset(&mut self) {
+        if let Ok(mut finished) = FINISHED.lock() {
+            finished.clear();
+        }
+        self.frame = FrameProfile::default();
+        self.frame_start = None;
+    }
+
+    pub fn get_last_frame(&self) -> &FrameProfile {
+        &self.frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn begin(name: &'static str, thread: u32, time: f64) -> ScopeEvent {
+        ScopeEvent {
+            name,
+            kind: ScopeEventKind::Begin,
+            thread,
+            time,
+        }
+    }
+
+    fn end(name: &'static str, thread: u32, time: f64) -> ScopeEvent {
+        ScopeEvent {
+            name,
+            kind: ScopeEventKind::End,
+            thread,
+            time,
+        }
+    }
+
+    // Name, depth, parent's name, start and end of each node
+    fn describe(nodes: &[ProfileNode]) -> Vec<(&str, u32, Option<&str>, f64, f64)> {
+        nodes
+            .iter()
+            .map(|node| {
+                let parent = node.parent.map(|parent| nodes[parent].name);
+                (node.name, node.depth, parent, node.start, node.end)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn interleaved_threads_build_their_own_trees() {
+        let events = [
+            begin("Frame", 0, 0.0),
+            begin("Update", 0, 1.0),
+            begin("Job", 1, 1.5),
+            begin("Animation", 0, 2.0),
+            begin("Sample", 1, 2.5),
+            end("Animation", 0, 3.0),
+            end("Sample", 1, 3.5),
+            begin("Input", 0, 4.0),
+            end("Job", 1, 4.5),
+            end("Input", 0, 5.0),
+            end("Update", 0, 6.0),
+            end("Frame", 0, 7.0),
+        ];
+        let mut nodes = Vec::new();
+        build_tree(&events, 10.0, &mut nodes);
+        assert_eq!(
+            describe(&nodes),
+            vec![
+                ("Frame", 0, None, 0.0, 7.0),
+                ("Update", 1, Some("Frame"), 1.0, 6.0),
+                ("Job", 0, None, 1.5, 4.5),
+                ("Animation", 2, Some("Update"), 2.0, 3.0),
+                ("Sample", 1, Some("Job"), 2.5, 3.5),
+                ("Input", 2, Some("Update"), 4.0, 5.0),
+            ]
+        );
+        assert_eq!(nodes[2].thread, 1);
+        assert_eq!(nodes[1].get_duration(), 5.0);
+    }
+
+    #[test]
+    fn unmatched_events_are_repaired() {
+        let events = [
+            // Began before the profiler was enabled
+            end("Earlier", 0, 0.5),
+            begin("Frame", 0, 1.0),
+            begin("Draw", 0, 2.0),
+            begin("Submit", 0, 3.0),
+            // Draw ends without Submit ending first, both end there
+            end("Draw", 0, 4.0),
+            begin("Present", 0, 5.0),
+        ];
+        let mut nodes = Vec::new();
+        build_tree(&events, 8.0, &mut nodes);
+        assert_eq!(
+            describe(&nodes),
+            vec![
+                ("Frame", 0, None, 1.0, 8.0),
+                ("Draw", 1, Some("Frame"), 2.0, 4.0),
+                ("Submit", 2, Some("Draw"), 3.0, 4.0),
+                ("Present", 1, Some("Frame"), 5.0, 8.0),
+            ]
+        );
+
+        // The nodes of the last build are replaced
+        build_tree(
+            &[begin("Frame", 3, 9.0), end("Frame", 3, 9.5)],
+            10.0,
+            &mut nodes,
+        );
+        assert_eq!(describe(&nodes), vec![("Frame", 0, None, 9.0, 9.5)]);
+    }
+
+    #[test]
+    fn scopes_reach_the_collector_once_they_end() {
+        fn get_step() -> f64 {
+            static STEP: AtomicU32 = AtomicU32::new(0);
+            STEP.fetch_add(1, Ordering::Relaxed) as f64
+        }
+        set_clock(get_step);
+
+        // On threads of their own, the buffers of other tests don't get in the way
+        std::thread::spawn(|| {
+            profile_scope!("Disabled");
+        })
+        .join()
+        .unwrap();
+        set_enabled(true);
+        std::thread::spawn(|| {
+            profile_scope!("Outer");
+            for _ in 0..2 {
+                profile_scope!("Inner");
+            }
+        })
+        .join()
+        .unwrap();
+        set_enabled(false);
+
+        let mut collector = ProfileCollector::default();
+        collector.end_frame();
+        let frame = collector.get_last_frame();
+        // Tests running alongside may have stepped physics meanwhile, on their own threads
+        let outer = frame
+            .nodes
+            .iter()
+            .position(|node| node.name == "Outer")
+            .unwrap();
+        let thread = frame.nodes[outer].thread;
+        let nodes: Vec<_> = frame
+            .nodes
+            .iter()
+            .filter(|node| node.thread == thread)
+            .collect();
+        let names: Vec<_> = nodes.iter().map(|node| node.name).collect();
+        assert_eq!(names, vec!["Outer", "Inner", "Inner"]);
+        assert!(nodes.iter().all(|node| node.end > node.start));
+        assert_eq!(nodes[2].parent, Some(outer));
+        assert!(frame.end > nodes[0].end);
+    }
+}
@@ -0,0 +1,59 @@
+use crate::physics::CollisionLayer;
+
+// Which side an entity fights on. Units of a team are on its unit layer and what they shoot
+// on its projectile layer, the collision matrix keeps the two apart so projectiles pass
+// through their own team.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Team {
+    Blue,
+    Red,
+    Neutral,
+}
+
+impl Team {
+    pub const ALL: [Team; 3] = [Team::Blue, Team::Red, Team::Neutral];
+
+    pub fn get_unit_layer(self) -> CollisionLayer {
+        match self {
+            Team::Blue => CollisionLayer::Player,
+            Team::Red => CollisionLayer::Enemy,
+            Team::Neutral => CollisionLayer::Neutral,
+        }
+    }
+
+    pub fn get_projectile_layer(self) -> CollisionLayer {
+        match self {
+            Team::Blue => CollisionLayer::PlayerProjectile,
+            Team::Red => CollisionLayer::EnemyProjectile,
+            Team::Neutral => CollisionLayer::NeutralProjectile,
+        }
+    }
+
+    // None for the environment, which is on no team
+    pub fn from_layer(layer: CollisionLayer) -> Option<Team> {
+        match layer {
+            CollisionLayer::Environment => None,
+            CollisionLayer::Player | CollisionLayer::PlayerProjectile => Some(Team::Blue),
+            CollisionLayer::Enemy | CollisionLayer::EnemyProjectile => Some(Team::Red),
+            CollisionLayer::Neutral | CollisionLayer::NeutralProjectile => Some(Team::Neutral),
+        }
+    }
+
+    pub fn is_projectile_layer(layer: CollisionLayer) -> bool {
+        matches!(
+            layer,
+            CollisionLayer::PlayerProjectile
+                | CollisionLayer::EnemyProjectile
+                | CollisionLayer::NeutralProjectile
+        )
+    }
+
+    // The same layer kind on another team, e.g. when a unit is mind controlled
+    pub fn get_layer_like(self, layer: CollisionLayer) -> CollisionLayer {
+        if Self::is_projectile_layer(layer) {
+            self.get_projectile_layer()
+        } else {
+            self.get_unit_layer()
+        }
+    }
+}
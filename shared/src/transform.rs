@@ -1,5 +1,6 @@
 use crate::math::{Mat4, Quat, Vec3};
 
+#[derive(Debug, Clone, Copy)]
 pub struct Transform {
     pub position: Vec3,
     pub rotation: Quat,
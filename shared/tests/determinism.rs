@@ -0,0 +1,131 @@
+// Runs a scripted fight with the quantized physics and hashes every state along the way.
+// The hash is checked in, so a platform computing anything differently fails here:
+//   cargo test -p shared --test determinism
+//   CARGO_TARGET_WASM32_WASIP1_RUNNER=wasmtime cargo test -p shared --test determinism --target wasm32-wasip1
+// Pass `-- --bless` to write a new hash after an intended change to the simulation.
+
+use std::path::Path;
+
+use shared::{
+    math::{
+        Vec2,
+        det::{qcos, qsin},
+    },
+    movement::{MoveInput, simulate_body},
+    net::ACTION_MOVE,
+    physics::{BodyId, BodySettings, CollisionLayer, CollisionShape, PhysicsWorld},
+};
+
+const STEP_COUNT: u32 = 10_000;
+const DT: f32 = 1.0 / 60.0;
+const UNIT_COUNT: usize = 16;
+
+struct Unit {
+    body: BodyId,
+    input: MoveInput,
+}
+
+fn main() {
+    let bless = std::env::args().any(|arg| arg == "--bless");
+    let hash = format!("{:016x}", run_scenario());
+    println!("determinism hash {}", hash);
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/determinism.hash");
+    if bless {
+        std::fs::write(&path, format!("{}\n", hash)).expect("Failed to write the hash");
+        println!("wrote {}", path.display());
+        return;
+    }
+
+    let expected = include_str!("determinism.hash").trim();
+    if hash != expected {
+        eprintln!("determinism ... FAILED, expected {}", expected);
+        std::process::exit(1);
+    }
+    println!("determinism ... ok");
+}
+
+// Units on a circle chasing points on another circle that turns, through a wall in the middle
+fn run_scenario() -> u64 {
+    let mut world = PhysicsWorld::new();
+    world.set_quantized(true);
+    world.create_rigid_body(&BodySettings {
+        position: Vec2::ZERO,
+        velocity: Vec2::ZERO,
+        layer: CollisionLayer::Environment,
+        shape: &CollisionShape::Rect {
+            half_extents: Vec2::new(150.0, 20.0),
+        },
+        listen_to_contact_events: false,
+    });
+
+    let mut units: Vec<Unit> = (0..UNIT_COUNT)
+        .map(|index| {
+            let angle = get_unit_angle(index);
+            let body = world.create_rigid_body(&BodySettings {
+                position: Vec2::new(qcos(angle), qsin(angle)) * 600.0,
+                velocity: Vec2::ZERO,
+                layer: CollisionLayer::Enemy,
+                shape: &CollisionShape::Circle { radius: 30.0 },
+                listen_to_contact_events: true,
+            });
+            Unit {
+                body,
+                input: MoveInput::default(),
+            }
+        })
+        .collect();
+
+    let mut hash = FNV_OFFSET;
+    for step in 0..STEP_COUNT {
+        // New orders every two seconds, everyone stands still for one in five
+        if step.is_multiple_of(120) {
+            let turn = step as f32 * 0.01;
+            for (index, unit) in units.iter_mut().enumerate() {
+                let angle = get_unit_angle(index) + std::f32::consts::PI + turn;
+                let resting = (step / 120 + index as u32).is_multiple_of(5);
+                unit.input = MoveInput {
+                    action_bits: if resting { 0 } else { ACTION_MOVE },
+                    cursor_world: Vec2::new(qcos(angle), qsin(angle)) * 500.0,
+                };
+            }
+        }
+
+        for unit in &units {
+            let state = world.get_state(unit.body).unwrap();
+            let velocity = simulate_body(state, &unit.input, DT).velocity;
+            world.set_velocity(unit.body, velocity);
+        }
+        world.step_simulation(DT);
+
+        for unit in &units {
+            let state = world.get_state(unit.body).unwrap();
+            let contact_count = world.get_contacts(unit.body).unwrap().len() as u32;
+            for value in [
+                state.position.x.to_bits(),
+                state.position.y.to_bits(),
+                state.velocity.x.to_bits(),
+                state.velocity.y.to_bits(),
+                contact_count,
+            ] {
+                hash = fnv1a(hash, &value.to_le_bytes());
+            }
+        }
+    }
+    hash
+}
+
+fn get_unit_angle(index: usize) -> f32 {
+    index as f32 * std::f32::consts::TAU / UNIT_COUNT as f32
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
@@ -1,8 +1,12 @@
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufReader, Write};
+use std::io::BufReader;
 
 use asset_importer::{Importer, postprocess::PostProcessSteps};
+use shared::{
+    animation::{AnimationClip, BoneKey},
+    math::{Quat, Vec3},
+};
 
 use crate::mesh::BoneMap;
 
@@ -10,21 +14,7 @@ pub struct AnimationLoadDesc<'a> {
     pub path: &'a str,
     pub skeleton: &'a str,
     pub output: &'a str,
-}
-
-#[derive(Clone, Copy)]
-pub struct AnimationFrame {
-    pub position: [f32; 3],
-    pub rotation: [f32; 4], // [w, x, y, z]
-}
-
-impl Default for AnimationFrame {
-    fn default() -> Self {
-        AnimationFrame {
-            position: [0.0; 3],
-            rotation: [1.0, 0.0, 0.0, 0.0],
-        }
-    }
+    pub compress: bool, // Quantized and with constant tracks culled, see shared::animation
 }
 
 pub fn load(desc: &AnimationLoadDesc) {
@@ -62,7 +52,7 @@ pub fn load(desc: &AnimationLoadDesc) {
     );
 
     // frames[frame][bone]
-    let mut frames: Vec<AnimationFrame> = vec![AnimationFrame::default(); num_frames * num_bones];
+    let mut frames: Vec<BoneKey> = vec![BoneKey::default(); num_frames * num_bones];
 
     for frame_index in 0..num_frames {
         let frame_slice = &mut frames[frame_index * num_bones..(frame_index + 1) * num_bones];
@@ -81,28 +71,23 @@ pub fn load(desc: &AnimationLoadDesc) {
                 .channel(channel_index)
                 .expect("Channel index out of range");
 
-            let mut position = [0.0, 0.0, 0.0];
+            let mut position = Vec3::ZERO;
             let pos_count = channel.num_position_keys();
             if pos_count > 0 {
                 let used = frame_index.min(pos_count - 1);
                 let key = &channel.position_keys()[used];
-                position[0] = key.value.x;
-                position[1] = key.value.y;
-                position[2] = key.value.z;
+                position = Vec3::new(key.value.x, key.value.y, key.value.z);
             }
 
-            let mut rotation = [1.0, 0.0, 0.0, 0.0];
+            let mut rotation = Quat::IDENTITY;
             let rot_count = channel.num_rotation_keys();
             if rot_count > 0 {
                 let used = frame_index.min(rot_count - 1);
                 let key = &channel.rotation_keys()[used];
-                rotation[0] = key.value.w;
-                rotation[1] = key.value.x;
-                rotation[2] = key.value.y;
-                rotation[3] = key.value.z;
+                rotation = Quat::from_xyzw(key.value.x, key.value.y, key.value.z, key.value.w);
             }
 
-            frame_slice[bone_index] = AnimationFrame { position, rotation };
+            frame_slice[bone_index] = BoneKey { position, rotation };
         }
     }
 
@@ -115,28 +100,31 @@ pub fn load(desc: &AnimationLoadDesc) {
         .map(|k| (k.time / tps) as f32)
         .collect();
 
-    let mut file = File::create(desc.output).expect("Could not open output file.");
-
-    file.write_all(&(num_bones as u32).to_le_bytes())
-        .expect("Could not write num_bones");
-    file.write_all(&(num_frames as u32).to_le_bytes())
-        .expect("Could not write num_frames");
-
-    for frame in &frames {
-        for p in &frame.position {
-            file.write_all(&p.to_le_bytes())
-                .expect("Could not write position");
-        }
-        for r in &frame.rotation {
-            file.write_all(&r.to_le_bytes())
-                .expect("Could not write rotation");
-        }
-    }
-
-    for time in &times {
-        file.write_all(&time.to_le_bytes())
-            .expect("Could not write time");
-    }
+    let clip = AnimationClip {
+        bone_count: num_bones,
+        frames,
+        times,
+    };
+    let plain = clip.encode();
+    let bytes = if desc.compress {
+        let compressed = clip.encode_compressed();
+        let decoded = AnimationClip::decode(&compressed).expect("Could not decode the animation");
+        let error = clip.get_error(&decoded);
+        let (min, max) = clip.get_bounds();
+        println!(
+            "Compressed {} to {} bytes ({:.1}%), max error {:.4} degrees and {:.6} over an extent of {:.3}.",
+            plain.len(),
+            compressed.len(),
+            compressed.len() as f32 / plain.len() as f32 * 100.0,
+            error.rotation_degrees,
+            error.position,
+            (max - min).max_element()
+        );
+        compressed
+    } else {
+        plain
+    };
+    std::fs::write(desc.output, &bytes).expect("Could not write output file.");
 
     println!("Wrote {} frames ({} bones/frame).", num_frames, num_bones);
 }
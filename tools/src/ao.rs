@@ -0,0 +1,441 @@
+// Per-vertex ambient occlusion of a mesh against its own triangles. Rays are cast over the
+// hemisphere around each vertex normal, the fraction that escapes within the distance is
+// how open the vertex is. The triangles go into a bounding volume hierarchy so meshes of
+// tens of thousands of vertices bake in seconds, and the vertices are split over threads.
+
+use std::thread;
+
+pub struct AoSettings {
+    pub ray_count: u32,
+    // Hits further away don't occlude, None for a quarter of the longest side of the mesh
+    pub max_distance: Option<f32>,
+}
+
+type Vec3 = [f32; 3];
+
+fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn add_scaled(a: Vec3, b: Vec3, scale: f32) -> Vec3 {
+    [
+        a[0] + b[0] * scale,
+        a[1] + b[1] * scale,
+        a[2] + b[2] * scale,
+    ]
+}
+
+fn dot(a: Vec3, b: Vec3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: Vec3) -> Vec3 {
+    let length = dot(a, a).sqrt();
+    if length > 0.0 {
+        a.map(|value| value / length)
+    } else {
+        [0.0, 1.0, 0.0]
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Bounds {
+    min: Vec3,
+    max: Vec3,
+}
+
+impl Bounds {
+    const EMPTY: Bounds = Bounds {
+        min: [f32::MAX; 3],
+        max: [f32::MIN; 3],
+    };
+
+    fn grow(&mut self, point: Vec3) {
+        for axis in 0..3 {
+            self.min[axis] = self.min[axis].min(point[axis]);
+            self.max[axis] = self.max[axis].max(point[axis]);
+        }
+    }
+
+    fn get_longest_axis(&self) -> usize {
+        let size = sub(self.max, self.min);
+        (0..3).fold(
+            0,
+            |best, axis| {
+                if size[axis] > size[best] { axis } else { best }
+            },
+        )
+    }
+
+    // Slab test, whether the ray enters the box before the distance
+    fn is_hit(&self, origin: Vec3, inverse_direction: Vec3, max_distance: f32) -> bool {
+        let mut near = 0.0f32;
+        let mut far = max_distance;
+        for axis in 0..3 {
+            let a = (self.min[axis] - origin[axis]) * inverse_direction[axis];
+            let b = (self.max[axis] - origin[axis]) * inverse_direction[axis];
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+        near <= far
+    }
+}
+
+// A leaf has the triangles from the start, an inner node its children at first_child and
+// the one after
+struct Node {
+    bounds: Bounds,
+    start: u32,
+    count: u32,
+    first_child: u32,
+}
+
+const LEAF_SIZE: usize = 4;
+
+struct Bvh {
+    nodes: Vec<Node>,
+    triangles: Vec<[Vec3; 3]>, // In the order of the leaves
+}
+
+impl Bvh {
+    fn new(positions: &[Vec3], triangles: &[[u32; 3]]) -> Self {
+        let mut triangles: Vec<[Vec3; 3]> = triangles
+            .iter()
+            .map(|triangle| triangle.map(|index| positions[index as usize]))
+            .collect();
+        let mut nodes = Vec::with_capacity(triangles.len() * 2 / LEAF_SIZE + 1);
+        nodes.push(Node {
+            bounds: Bounds::EMPTY,
+            start: 0,
+            count: triangles.len() as u32,
+            first_child: 0,
+        });
+        Self::split(&mut nodes, &mut triangles, 0);
+        Self { nodes, triangles }
+    }
+
+    // Splits at the median of the centroids along the longest axis of their bounds
+    fn split(nodes: &mut Vec<Node>, triangles: &mut [[Vec3; 3]], index: usize) {
+        let (start, count) = (nodes[index].start as usize, nodes[index].count as usize);
+        let node_triangles = &mut triangles[start..start + count];
+
+        let mut bounds = Bounds::EMPTY;
+        let mut centroid_bounds = Bounds::EMPTY;
+        for triangle in node_triangles.iter() {
+            triangle.iter().for_each(|corner| bounds.grow(*corner));
+            centroid_bounds.grow(get_centroid(triangle));
+        }
+        nodes[index].bounds = bounds;
+        if count <= LEAF_SIZE {
+            return;
+        }
+
+        let axis = centroid_bounds.get_longest_axis();
+        let half = count / 2;
+        node_triangles.select_nth_unstable_by(half, |a, b| {
+            get_centroid(a)[axis].total_cmp(&get_centroid(b)[axis])
+        });
+
+        let first_child = nodes.len();
+        nodes[index].first_child = first_child as u32;
+        nodes[index].count = 0;
+        for (child_start, child_count) in [(start, half), (start + half, count - half)] {
+            nodes.push(Node {
+                bounds: Bounds::EMPTY,
+                start: child_start as u32,
+                count: child_count as u32,
+                first_child: 0,
+            });
+        }
+        Self::split(nodes, triangles, first_child);
+        Self::split(nodes, triangles, first_child + 1);
+    }
+
+    // Any hit is enough, the first one found ends the search
+    fn is_occluded(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> bool {
+        let inverse_direction = direction.map(|value| 1.0 / value);
+        let mut stack = vec![0usize];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bounds.is_hit(origin, inverse_direction, max_distance) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.first_child as usize);
+                stack.push(node.first_child as usize + 1);
+                continue;
+            }
+            let start = node.start as usize;
+            let hit = self.triangles[start..start + node.count as usize]
+                .iter()
+                .any(|triangle| is_triangle_hit(triangle, origin, direction, max_distance));
+            if hit {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+fn get_centroid(triangle: &[Vec3; 3]) -> Vec3 {
+    [0, 1, 2].map(|axis| (triangle[0][axis] + triangle[1][axis] + triangle[2][axis]) / 3.0)
+}
+
+// Möller-Trumbore, both sides of the triangle count
+fn is_triangle_hit(triangle: &[Vec3; 3], origin: Vec3, direction: Vec3, max_distance: f32) -> bool {
+    let edge_1 = sub(triangle[1], triangle[0]);
+    let edge_2 = sub(triangle[2], triangle[0]);
+    let p = cross(direction, edge_2);
+    let determinant = dot(edge_1, p);
+    if determinant.abs() < 1e-12 {
+        return false;
+    }
+    let inverse_determinant = 1.0 / determinant;
+    let t_vector = sub(origin, triangle[0]);
+    let u = dot(t_vector, p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return false;
+    }
+    let q = cross(t_vector, edge_1);
+    let v = dot(direction, q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return false;
+    }
+    let distance = dot(edge_2, q) * inverse_determinant;
+    distance > 0.0 && distance < max_distance
+}
+
+// Cosine weighted directions around +z from the Hammersley set, the same for every vertex
+fn get_hemisphere_directions(count: u32) -> Vec<Vec3> {
+    (0..count)
+        .map(|index| {
+            let u = (index as f32 + 0.5) / count as f32;
+            let v = index.reverse_bits() as f32 / 2f32.powi(32);
+            let radius = u.sqrt();
+            let angle = 2.0 * std::f32::consts::PI * v;
+            [
+                radius * angle.cos(),
+                radius * angle.sin(),
+                (1.0 - u).max(0.0).sqrt(),
+            ]
+        })
+        .collect()
+}
+
+// Any two axes perpendicular to the normal and to each other
+fn get_tangent_frame(normal: Vec3) -> (Vec3, Vec3) {
+    let helper = if normal[0].abs() < 0.9 {
+        [1.0, 0.0, 0.0]
+    } else {
+        [0.0, 1.0, 0.0]
+    };
+    let tangent = normalize(cross(helper, normal));
+    (tangent, cross(normal, tangent))
+}
+
+// For every vertex, 1 when nothing is around it and 0 when every ray hits something
+pub fn bake_ambient_occlusion(
+    positions: &[Vec3],
+    normals: &[Vec3],
+    triangles: &[[u32; 3]],
+    settings: &AoSettings,
+) -> Vec<f32> {
+    if positions.is_empty() || triangles.is_empty() || settings.ray_count == 0 {
+        return vec![1.0; positions.len()];
+    }
+
+    let mut bounds = Bounds::EMPTY;
+    positions.iter().for_each(|position| bounds.grow(*position));
+    let size = sub(bounds.max, bounds.min)
+        .into_iter()
+        .fold(0.0, f32::max)
+        .max(f32::EPSILON);
+    let max_distance = settings.max_distance.unwrap_or(size * 0.25);
+    // Keeps the rays off the triangles around the vertex
+    let bias = size * 1e-4;
+
+    let bvh = Bvh::new(positions, triangles);
+    let directions = get_hemisphere_directions(settings.ray_count);
+
+    let bake_vertex = |index: usize| {
+        let normal = normalize(normals[index]);
+        let (tangent, bitangent) = get_tangent_frame(normal);
+        let origin = add_scaled(positions[index], normal, bias);
+        let hits = directions
+            .iter()
+            .filter(|local| {
+                let direction = add_scaled(
+                    add_scaled(tangent.map(|value| value * local[0]), bitangent, local[1]),
+                    normal,
+                    local[2],
+                );
+                bvh.is_occluded(origin, direction, max_distance)
+            })
+            .count();
+        1.0 - hits as f32 / directions.len() as f32
+    };
+
+    let thread_count = thread::available_parallelism().map_or(1, |count| count.get());
+    let chunk_size = positions.len().div_ceil(thread_count);
+    let mut occlusion = vec![1.0; positions.len()];
+    thread::scope(|scope| {
+        for (chunk_index, chunk) in occlusion.chunks_mut(chunk_size).enumerate() {
+            let bake_vertex = &bake_vertex;
+            scope.spawn(move || {
+                for (offset, value) in chunk.iter_mut().enumerate() {
+                    *value = bake_vertex(chunk_index * chunk_size + offset);
+                }
+            });
+        }
+    });
+    occlusion
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two triangles between the corners
+    fn push_quad(
+        positions: &mut Vec<Vec3>,
+        normals: &mut Vec<Vec3>,
+        triangles: &mut Vec<[u32; 3]>,
+        corners: [Vec3; 4],
+        normal: Vec3,
+    ) {
+        let start = positions.len() as u32;
+        positions.extend(corners);
+        normals.extend([normal; 4]);
+        triangles.push([start, start + 1, start + 2]);
+        triangles.push([start, start + 2, start + 3]);
+    }
+
+    #[test]
+    fn a_plane_is_darker_under_a_box() {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut triangles = Vec::new();
+
+        // A 20 by 20 grid of vertices on the ground, 10 units apart
+        let grid_start = positions.len();
+        for z in 0..20 {
+            for x in 0..20 {
+                positions.push([x as f32 * 10.0 - 95.0, 0.0, z as f32 * 10.0 - 95.0]);
+                normals.push([0.0, 1.0, 0.0]);
+            }
+        }
+        for z in 0..19u32 {
+            for x in 0..19u32 {
+                let corner = z * 20 + x;
+                triangles.push([corner, corner + 20, corner + 21]);
+                triangles.push([corner, corner + 21, corner + 1]);
+            }
+        }
+
+        // A box standing on the ground over the middle, 40 wide and 20 high, with a gap
+        // under its top. Only the top and the sides are there.
+        let (low, high, y) = (-20.0, 20.0, 20.0);
+        push_quad(
+            &mut positions,
+            &mut normals,
+            &mut triangles,
+            [
+                [low, y, low],
+                [low, y, high],
+                [high, y, high],
+                [high, y, low],
+            ],
+            [0.0, 1.0, 0.0],
+        );
+        for (a, b) in [
+            ([low, low], [low, high]),
+            ([high, low], [high, high]),
+            ([low, low], [high, low]),
+            ([low, high], [high, high]),
+        ] {
+            push_quad(
+                &mut positions,
+                &mut normals,
+                &mut triangles,
+                [
+                    [a[0], 2.0, a[1]],
+                    [b[0], 2.0, b[1]],
+                    [b[0], y, b[1]],
+                    [a[0], y, a[1]],
+                ],
+                [0.0, 0.0, 1.0],
+            );
+        }
+
+        let occlusion = bake_ambient_occlusion(
+            &positions,
+            &normals,
+            &triangles,
+            &AoSettings {
+                ray_count: 64,
+                max_distance: Some(100.0),
+            },
+        );
+
+        let at = |x: usize, z: usize| occlusion[grid_start + z * 20 + x];
+        // (9, 9) is at (-5, -5), under the box. (0, 0) is the far corner.
+        let under = at(9, 9);
+        let open = at(0, 0);
+        assert!(under < 0.2, "{}", under);
+        assert!(open > 0.9, "{}", open);
+        // Next to the box some of the sky is blocked
+        let beside = at(12, 9);
+        assert!(beside > under && beside < open, "{}", beside);
+
+        // Nothing around
+        let alone = bake_ambient_occlusion(
+            &positions[..400],
+            &normals[..400],
+            &triangles[..19 * 19 * 2],
+            &AoSettings {
+                ray_count: 16,
+                max_distance: None,
+            },
+        );
+        assert!(alone.iter().all(|value| *value == 1.0));
+    }
+
+    #[test]
+    fn bvh_hits_match_the_brute_force() {
+        let positions: Vec<Vec3> = (0..300)
+            .map(|index| {
+                let t = index as f32;
+                [
+                    (t * 0.37).sin() * 50.0,
+                    (t * 0.11).cos() * 50.0,
+                    (t * 0.23).sin() * 50.0,
+                ]
+            })
+            .collect();
+        let triangles: Vec<[u32; 3]> = (0..100)
+            .map(|index| [index * 3, index * 3 + 1, index * 3 + 2])
+            .collect();
+        let bvh = Bvh::new(&positions, &triangles);
+        let corners: Vec<[Vec3; 3]> = triangles
+            .iter()
+            .map(|triangle| triangle.map(|index| positions[index as usize]))
+            .collect();
+
+        for direction in get_hemisphere_directions(32) {
+            for origin in [[0.0, 0.0, 0.0], [10.0, -20.0, 5.0], [-30.0, 10.0, -10.0]] {
+                let brute_force = corners
+                    .iter()
+                    .any(|triangle| is_triangle_hit(triangle, origin, direction, 60.0));
+                assert_eq!(bvh.is_occluded(origin, direction, 60.0), brute_force);
+            }
+        }
+    }
+}
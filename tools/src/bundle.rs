@@ -0,0 +1,226 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{animation, mesh, texture};
+
+// Set in the entry flags when the entry is compressed with zstd
+const ZSTD_FLAG: u32 = 1;
+
+pub struct BundleLoadDesc<'a> {
+    pub manifest: &'a str,
+    pub output: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum EntryKind {
+    Mesh,
+    SkeletalMesh,
+    Texture,
+    Animation,
+}
+
+impl EntryKind {
+    // Matches BundleEntry::get_kind_code in the client
+    fn get_code(self) -> u32 {
+        match self {
+            Self::Mesh => 0,
+            Self::SkeletalMesh => 1,
+            Self::Texture => 2,
+            Self::Animation => 3,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestEntry {
+    name: String,
+    kind: EntryKind,
+    path: String,
+    #[serde(default)]
+    compress: Option<bool>, // Overrides the manifest's choice for this entry
+}
+
+// The paths are relative to the manifest. The skeleton is written by the skeletal mesh and
+// read by the animations, without a skeletal mesh it has to exist already.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    skeleton: Option<String>,
+    #[serde(default)]
+    compress: bool,
+    entries: Vec<ManifestEntry>,
+}
+
+// Runs the converter of each entry of the manifest and packs the results into one file:
+//
+// u32 entry count, then per entry
+//     u32 name length, the name as UTF-8
+//     u32 kind, see EntryKind::get_code
+//     u32 flags, ZSTD_FLAG when the entry is compressed
+//     u64 offset from the start of the file, u64 length as stored
+// the entry data
+pub fn load(desc: &BundleLoadDesc) -> anyhow::Result<()> {
+    let manifest: Manifest = serde_json::from_slice(
+        &std::fs::read(desc.manifest)
+            .with_context(|| format!("Failed to read {}", desc.manifest))?,
+    )
+    .with_context(|| format!("Failed to parse {}", desc.manifest))?;
+    let directory = Path::new(desc.manifest)
+        .parent()
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
+
+    for (index, entry) in manifest.entries.iter().enumerate() {
+        if manifest.entries[..index]
+            .iter()
+            .any(|other| other.name == entry.name)
+        {
+            bail!("The manifest has two entries named {}", entry.name);
+        }
+    }
+
+    // The converters write files, they are collected next to the output and removed after
+    let parts = PathBuf::from(format!("{}.parts", desc.output));
+    std::fs::create_dir_all(&parts)?;
+    let converted = convert_entries(&manifest, &directory, &parts);
+    std::fs::remove_dir_all(&parts)?;
+    let converted = converted?;
+
+    let stored: Vec<(Vec<u8>, bool)> = manifest
+        .entries
+        .iter()
+        .zip(converted)
+        .map(|(entry, bytes)| {
+            if entry.compress.unwrap_or(manifest.compress) {
+                let level = ruzstd::encoding::CompressionLevel::Fastest;
+                (ruzstd::encoding::compress_to_vec(bytes.as_slice(), level), true)
+            } else {
+                (bytes, false)
+            }
+        })
+        .collect();
+
+    let table_size: usize = manifest
+        .entries
+        .iter()
+        .map(|entry| 4 + entry.name.len() + 4 + 4 + 8 + 8)
+        .sum();
+    let mut offset = (4 + table_size) as u64;
+
+    let mut file = File::create(desc.output).expect("Could not open output file.");
+    file.write_all(&(manifest.entries.len() as u32).to_le_bytes())?;
+    for (entry, (bytes, compressed)) in manifest.entries.iter().zip(&stored) {
+        let flags = if *compressed { ZSTD_FLAG } else { 0 };
+        file.write_all(&(entry.name.len() as u32).to_le_bytes())?;
+        file.write_all(entry.name.as_bytes())?;
+        file.write_all(&entry.kind.get_code().to_le_bytes())?;
+        file.write_all(&flags.to_le_bytes())?;
+        file.write_all(&offset.to_le_bytes())?;
+        file.write_all(&(bytes.len() as u64).to_le_bytes())?;
+        offset += bytes.len() as u64;
+    }
+    for (bytes, _) in &stored {
+        file.write_all(bytes)?;
+    }
+
+    for (entry, (bytes, compressed)) in manifest.entries.iter().zip(&stored) {
+        println!(
+            "{}: {:?}, {} bytes{}",
+            entry.name,
+            entry.kind,
+            bytes.len(),
+            if *compressed { " compressed" } else { "" }
+        );
+    }
+    println!(
+        "Bundled {} entries into {}, {} bytes",
+        manifest.entries.len(),
+        desc.output,
+        offset
+    );
+
+    Ok(())
+}
+
+// The converted files in the order of the manifest. Skeletal meshes go first, since they
+// write the skeleton the animations are converted with.
+fn convert_entries(
+    manifest: &Manifest,
+    directory: &Path,
+    parts: &Path,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    let skeleton = manifest
+        .skeleton
+        .as_ref()
+        .map(|skeleton| directory.join(skeleton).to_string_lossy().into_owned());
+
+    let mut order: Vec<usize> = (0..manifest.entries.len()).collect();
+    order.sort_by_key(|index| manifest.entries[*index].kind != EntryKind::SkeletalMesh);
+
+    let mut converted = vec![Vec::new(); manifest.entries.len()];
+    for index in order {
+        let entry = &manifest.entries[index];
+        let path = directory.join(&entry.path).to_string_lossy().into_owned();
+        let output = parts
+            .join(format!("{}.dat", index))
+            .to_string_lossy()
+            .into_owned();
+        println!("Converting {} from {}", entry.name, path);
+
+        convert_entry(entry.kind, &path, &output, skeleton.as_deref())
+            .with_context(|| format!("Failed to convert the entry {}", entry.name))?;
+        converted[index] = std::fs::read(&output)?;
+    }
+
+    Ok(converted)
+}
+
+fn convert_entry(
+    kind: EntryKind,
+    path: &str,
+    output: &str,
+    skeleton: Option<&str>,
+) -> anyhow::Result<()> {
+    match kind {
+        EntryKind::Mesh | EntryKind::SkeletalMesh => {
+            let skeleton_output = match kind {
+                EntryKind::SkeletalMesh => {
+                    Some(skeleton.context("A skeletal mesh needs the skeleton of the manifest")?)
+                }
+                _ => None,
+            };
+            mesh::load(&mesh::MeshLoadDesc {
+                path,
+                output,
+                skeleton_output,
+                lods: &[],
+                lod_distances: &[],
+                decimate: 0,
+                vertex_color: mesh::VertexColorMode::Source,
+                ao: None,
+            })?;
+        }
+        EntryKind::Texture => texture::load(&texture::TextureLoadDesc {
+            path,
+            output,
+            resize_width: None,
+            resize_height: None,
+            atlas_regions: None,
+            premultiply: false,
+            alpha_coverage_threshold: None,
+            data: None,
+        })?,
+        EntryKind::Animation => animation::load(&animation::AnimationLoadDesc {
+            path,
+            skeleton: skeleton.context("An animation needs the skeleton of the manifest")?,
+            output,
+            compress: false,
+        }),
+    }
+
+    Ok(())
+}
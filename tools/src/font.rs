@@ -113,7 +113,7 @@ pub fn load(desc: &FontLoadDesc) -> anyhow::Result<()> {
         }
     }
 
-    texture::write_texture(&atlas, 1, file)?;
+    texture::write_texture(&atlas, 1, &texture::MipOptions::default(), file)?;
 
     Ok(())
 }
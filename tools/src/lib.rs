@@ -1,4 +1,5 @@
 pub mod animation;
+pub mod bundle;
 pub mod font;
 pub mod mesh;
 pub mod texture;
@@ -1,5 +1,7 @@
 use clap::{Parser, Subcommand};
 mod animation;
+mod ao;
+mod bundle;
 mod font;
 mod mesh;
 mod texture;
@@ -20,6 +22,29 @@ enum Commands {
         output: String,
         #[arg(short, long)]
         skeleton_output: Option<String>,
+        /// A lower detail export of the mesh, repeated from the most detailed one down
+        #[arg(long = "lod")]
+        lods: Vec<String>,
+        /// Where each level after the first starts, in world units from the camera
+        #[arg(long = "lod-distance")]
+        lod_distances: Vec<f32>,
+        /// Levels to generate by decimating the mesh when there is no --lod
+        #[arg(long, default_value_t = 0)]
+        decimate: u32,
+        /// Bake ambient occlusion into the vertex color alpha, the scene shader darkens the
+        /// ambient light with it
+        #[arg(long = "bake-ao")]
+        bake_ao: bool,
+        /// Rays cast from each vertex when baking
+        #[arg(long = "ao-rays", default_value_t = 64)]
+        ao_rays: u32,
+        /// Hits further away don't occlude, a quarter of the mesh size by default
+        #[arg(long = "ao-distance")]
+        ao_distance: Option<f32>,
+        /// What the rgb of the vertex colors is, gradient-y also bakes the height into the alpha
+        /// for materials swaying in the wind
+        #[arg(long = "vertex-color", value_enum, default_value_t = mesh::VertexColorMode::Source)]
+        vertex_color: mesh::VertexColorMode,
     },
     Texture {
         path: String,
@@ -29,13 +54,66 @@ enum Commands {
         resize_width: Option<u32>,
         #[arg(short = 'y', long = "resize-height")]
         resize_height: Option<u32>,
+        /// Pack a directory of images into one atlas and write its regions to this json file
+        #[arg(short = 'a', long = "atlas-regions")]
+        atlas_regions: Option<String>,
+        /// Multiply the color by the alpha before making the mips, for blended UI textures
+        #[arg(long)]
+        premultiply: bool,
+        /// Scale the alpha of each mip so as many texels pass this alpha test as in the
+        /// first, for cutouts
+        #[arg(long = "preserve-alpha-coverage")]
+        alpha_coverage_threshold: Option<f32>,
+        /// Keep a grayscale image texel for texel as 16 bit data, e.g. a heightmap, with no
+        /// mips or mips of the largest values
+        #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "none")]
+        data: Option<texture::DataMips>,
     },
+    /// Stack images of the same size into the layers of one texture, e.g. material variants
+    TextureArray {
+        #[arg(required = true)]
+        paths: Vec<String>,
+        #[arg(short, long)]
+        output: String,
+        /// Multiply the color by the alpha before making the mips, for blended UI textures
+        #[arg(long)]
+        premultiply: bool,
+    },
+    /// Pack grayscale images into the channels of one texture, "none" fills a channel
+    Pack {
+        #[arg(short, long)]
+        output: String,
+        #[arg(long)]
+        r: Option<String>,
+        #[arg(long)]
+        g: Option<String>,
+        #[arg(long)]
+        b: Option<String>,
+        #[arg(long)]
+        a: Option<String>,
+        #[arg(long = "fill-r", default_value_t = 0)]
+        fill_r: u8,
+        #[arg(long = "fill-g", default_value_t = 0)]
+        fill_g: u8,
+        #[arg(long = "fill-b", default_value_t = 0)]
+        fill_b: u8,
+        #[arg(long = "fill-a", default_value_t = 255)]
+        fill_a: u8,
+        /// Resize the images to the largest one instead of failing on different sizes
+        #[arg(long)]
+        resize: bool,
+    },
+    /// Print the header and channel ranges of a converted texture
+    Inspect { path: String },
     Animation {
         path: String,
         #[arg(short, long)]
         skeleton: String,
         #[arg(short, long)]
         output: String,
+        /// Quantize the rotations and positions and store bones that don't move once
+        #[arg(long)]
+        compress: bool,
     },
     Font {
         atlas: String,
@@ -43,6 +121,17 @@ enum Commands {
         #[arg(short, long)]
         output: String,
     },
+    /// Convert the assets listed in a json manifest and pack them into one bundle
+    Bundle {
+        manifest: String,
+        #[arg(short, long)]
+        output: String,
+    },
+}
+
+// A packed channel without a source is passed as "none"
+fn get_channel(path: &Option<String>) -> Option<&str> {
+    path.as_deref().filter(|path| *path != "none")
 }
 
 fn main() {
@@ -53,10 +142,25 @@ fn main() {
             path,
             output,
             skeleton_output,
+            lods,
+            lod_distances,
+            decimate,
+            bake_ao,
+            ao_rays,
+            ao_distance,
+            vertex_color,
         } => mesh::load(&mesh::MeshLoadDesc {
             path: &path,
             output: &output,
             skeleton_output: skeleton_output.as_deref(),
+            lods,
+            lod_distances,
+            decimate: *decimate,
+            vertex_color: *vertex_color,
+            ao: bake_ao.then_some(ao::AoSettings {
+                ray_count: *ao_rays,
+                max_distance: *ao_distance,
+            }),
         })
         .expect("Failed to load mesh."),
         Commands::Texture {
@@ -64,21 +168,65 @@ fn main() {
             output,
             resize_width,
             resize_height,
+            atlas_regions,
+            premultiply,
+            alpha_coverage_threshold,
+            data,
         } => texture::load(&texture::TextureLoadDesc {
             path: &path,
             output: &output,
             resize_width: *resize_width,
             resize_height: *resize_height,
+            atlas_regions: atlas_regions.as_deref(),
+            premultiply: *premultiply,
+            alpha_coverage_threshold: *alpha_coverage_threshold,
+            data: *data,
         })
         .expect("Failed to load texture."),
+        Commands::TextureArray {
+            paths,
+            output,
+            premultiply,
+        } => texture::load_array(&texture::TextureArrayLoadDesc {
+            paths,
+            output,
+            premultiply: *premultiply,
+        })
+        .expect("Failed to load texture array."),
+        Commands::Pack {
+            output,
+            r,
+            g,
+            b,
+            a,
+            fill_r,
+            fill_g,
+            fill_b,
+            fill_a,
+            resize,
+        } => texture::pack(&texture::PackLoadDesc {
+            channels: [
+                get_channel(r),
+                get_channel(g),
+                get_channel(b),
+                get_channel(a),
+            ],
+            fills: [*fill_r, *fill_g, *fill_b, *fill_a],
+            output: &output,
+            resize: *resize,
+        })
+        .expect("Failed to pack texture."),
+        Commands::Inspect { path } => texture::inspect(path).expect("Failed to inspect texture."),
         Commands::Animation {
             path,
             skeleton,
             output,
+            compress,
         } => animation::load(&animation::AnimationLoadDesc {
             path: &path,
             skeleton: &skeleton,
             output: &output,
+            compress: *compress,
         }),
         Commands::Font {
             atlas,
@@ -90,5 +238,10 @@ fn main() {
             output: &output,
         })
         .expect("Failed to load font"),
+        Commands::Bundle { manifest, output } => bundle::load(&bundle::BundleLoadDesc {
+            manifest: &manifest,
+            output: &output,
+        })
+        .expect("Failed to bundle"),
     }
 }
@@ -1,15 +1,37 @@
 use std::hash::Hash;
 use std::io::prelude::*;
+use std::time::Instant;
 use std::{collections::HashMap, fs::File};
 
 use asset_importer::Scene;
 use asset_importer::{Importer, mesh::Mesh, postprocess::PostProcessSteps};
 use serde::{Deserialize, Serialize};
 
+use crate::ao;
+
 pub struct MeshLoadDesc<'a> {
     pub path: &'a str,
     pub output: &'a str,
     pub skeleton_output: Option<&'a str>,
+    pub lods: &'a [String], // Lower detail exports of the same mesh, most detailed first
+    pub lod_distances: &'a [f32], // Where each level after the first starts
+    pub decimate: u32,      // Levels to generate when there are no lod exports
+    pub vertex_color: VertexColorMode,
+    pub ao: Option<ao::AoSettings>, // Bakes ambient occlusion into the vertex color alpha
+}
+
+// What goes into the rgb of the vertex colors
+#[derive(Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum VertexColorMode {
+    #[default]
+    // The colors of the file, white without them
+    Source,
+    // The baked ambient occlusion as gray, to look at the bake
+    Ao,
+    White,
+    // The colors of the file, with the height in the mesh from 0 at the bottom to 1 at the
+    // top in the alpha. Materials swaying in the wind move the vertices by it.
+    GradientY,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
@@ -22,18 +44,35 @@ pub struct BoneInfo {
 
 pub type BoneMap = HashMap<String, BoneInfo>;
 
+// Matches the client, which only keeps this many levels
+const MAX_LOD_COUNT: usize = 4;
+// Set in the mesh count when a LOD table follows it
+const LOD_TABLE_FLAG: u32 = 1 << 31;
+// Between the levels when no distances are given, in world units
+const DEFAULT_LOD_DISTANCE_STEP: f32 = 1500.0;
+// Grid cells along the longest side of the mesh for each decimated level
+const DECIMATION_CELLS: [f32; MAX_LOD_COUNT - 1] = [48.0, 24.0, 12.0];
+// When the colors show the occlusion without --bake-ao
+const DEFAULT_AO_RAY_COUNT: u32 = 32;
+
+#[derive(Clone, Copy)]
+struct Vertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+    uv: [f32; 3], // The third component is the texture layer of the mesh
+    color: [f32; 4],
+    bone_ids: [i32; 4],
+    bone_weights: [f32; 4],
+}
+
+// The indices start at 0 for each mesh
+struct MeshData {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+}
+
 pub fn load(desc: &MeshLoadDesc) -> std::io::Result<()> {
-    let importer = Importer::new();
-    let scene = importer
-        .read_file(desc.path)
-        .with_post_process(
-            PostProcessSteps::TRIANGULATE
-                | PostProcessSteps::FLIP_UVS
-                | PostProcessSteps::GEN_SMOOTH_NORMALS
-                | PostProcessSteps::POPULATE_ARMATURE_DATA,
-        )
-        .import_file(desc.path)
-        .expect("Could not import scene.");
+    let scene = import_scene(desc.path);
 
     let is_skeletal = desc.skeleton_output.is_some();
     let mut bone_map = HashMap::new();
@@ -43,19 +82,130 @@ pub fn load(desc: &MeshLoadDesc) -> std::io::Result<()> {
         skeleton_file.write_all(serde_json::to_string_pretty(&bone_map)?.as_bytes());
     }
 
+    // The lod exports use the bones of the first one, they have to share its skeleton
+    let mut lods = vec![read_meshes(&scene, &bone_map)];
+    for path in desc.lods {
+        lods.push(read_meshes(&import_scene(path), &bone_map));
+    }
+    // Before decimating, the decimated levels keep the colors of the vertices they keep
+    for meshes in &mut lods {
+        set_vertex_colors(meshes, desc);
+    }
+    if desc.lods.is_empty() {
+        for cells in DECIMATION_CELLS.iter().take(desc.decimate as usize) {
+            let decimated = lods[0].iter().map(|mesh| decimate(mesh, *cells)).collect();
+            lods.push(decimated);
+        }
+    }
+
+    if lods.len() > MAX_LOD_COUNT {
+        return Err(std::io::Error::other(format!(
+            "At most {} LODs are supported, got {}",
+            MAX_LOD_COUNT,
+            lods.len()
+        )));
+    }
+    let distances: Vec<f32> = (1..lods.len())
+        .map(|level| {
+            desc.lod_distances
+                .get(level - 1)
+                .copied()
+                .unwrap_or(level as f32 * DEFAULT_LOD_DISTANCE_STEP)
+        })
+        .collect();
+    if distances.windows(2).any(|pair| pair[1] <= pair[0]) {
+        return Err(std::io::Error::other("The LOD distances have to increase"));
+    }
+
     let mut file = File::create(desc.output).expect("Could not open output file.");
 
-    let meshes = scene.meshes();
-    file.write_all(&(meshes.len() as u32).to_le_bytes())?;
+    // Files with a single level keep the old layout
+    let mesh_count: usize = lods.iter().map(|meshes| meshes.len()).sum();
+    if lods.len() == 1 {
+        file.write_all(&(mesh_count as u32).to_le_bytes())?;
+    } else {
+        file.write_all(&(mesh_count as u32 | LOD_TABLE_FLAG).to_le_bytes())?;
+        file.write_all(&(lods.len() as u32).to_le_bytes())?;
+        for (level, meshes) in lods.iter().enumerate() {
+            let distance = if level == 0 {
+                0.0
+            } else {
+                distances[level - 1]
+            };
+            file.write_all(&(meshes.len() as u32).to_le_bytes())?;
+            file.write_all(&distance.to_le_bytes())?;
+        }
+    }
 
+    // All levels share one vertex buffer, so the indices are offset by the vertices before
     let mut total_vertex_count = 0u32;
-    for (mesh_index, mesh) in meshes.enumerate() {
+    for (level, meshes) in lods.iter().enumerate() {
+        for mesh in meshes {
+            write_mesh(&mut file, mesh, total_vertex_count, is_skeletal)?;
+            total_vertex_count += mesh.vertices.len() as u32;
+        }
+        if lods.len() > 1 {
+            let index_count: usize = meshes.iter().map(|mesh| mesh.indices.len()).sum();
+            println!(
+                "LOD {}: {} triangles, from {} units.",
+                level,
+                index_count / 3,
+                if level == 0 {
+                    0.0
+                } else {
+                    distances[level - 1]
+                }
+            );
+        }
+    }
+
+    // We also write a bone info buffer
+    if is_skeletal {
+        // Topologically sorted bone buffer
+        let mut bone_info: Vec<BoneInfo> = Vec::new();
+        bone_info.resize_with(bone_map.len(), Default::default);
+        for bone in bone_map.values() {
+            bone_info[bone.id as usize] = bone.clone();
+        }
+
+        file.write_all(&(bone_info.len() as u32).to_le_bytes())?;
+        for info in &bone_info {
+            file.write_all(&info.id.to_le_bytes())?;
+            file.write_all(&info.parent_id.to_le_bytes())?;
+            for m in info.offset_matrix {
+                file.write_all(&m.to_le_bytes())?;
+            }
+        }
+
+        println!("Wrote {} bones.", bone_info.len());
+    }
+
+    Ok(())
+}
+
+fn import_scene(path: &str) -> Scene {
+    let importer = Importer::new();
+    importer
+        .read_file(path)
+        .with_post_process(
+            PostProcessSteps::TRIANGULATE
+                | PostProcessSteps::FLIP_UVS
+                | PostProcessSteps::GEN_SMOOTH_NORMALS
+                | PostProcessSteps::POPULATE_ARMATURE_DATA,
+        )
+        .import_file(path)
+        .expect("Could not import scene.")
+}
+
+fn read_meshes(scene: &Scene, bone_map: &BoneMap) -> Vec<MeshData> {
+    let mut meshes = Vec::new();
+    for (mesh_index, mesh) in scene.meshes().enumerate() {
         let vertices = &mesh.vertices();
         let normals = &mesh.normals().expect("No normals.");
         let uvs = &mesh.texture_coords(0).expect("No UVs.");
         let colors = &mesh.vertex_colors(0);
         let (bone_weights, bone_ids) = if !bone_map.is_empty() {
-            load_bone_weights(&mesh, &bone_map)
+            load_bone_weights(&mesh, bone_map)
         } else {
             (Vec::new(), Vec::new())
         };
@@ -64,101 +214,202 @@ pub fn load(desc: &MeshLoadDesc) -> std::io::Result<()> {
         assert_eq!(vertices.len(), uvs.len());
 
         let uv_layer = mesh_index as f32;
-        let uv_layer_bytes = &uv_layer.to_le_bytes();
-
-        file.write_all(&(vertices.len() as u32).to_le_bytes())?;
-
-        for vertex_index in 0..vertices.len() {
-            let position = &vertices[vertex_index];
-            let normal = &normals[vertex_index];
-            let uv_coordinate = &uvs[vertex_index];
-
-            file.write_all(&position.x.to_le_bytes())?;
-            file.write_all(&position.y.to_le_bytes())?;
-            file.write_all(&position.z.to_le_bytes())?;
-
-            file.write_all(&normal.x.to_le_bytes())?;
-            file.write_all(&normal.y.to_le_bytes())?;
-            file.write_all(&normal.z.to_le_bytes())?;
-
-            file.write_all(&uv_coordinate.x.to_le_bytes())?;
-            file.write_all(&uv_coordinate.y.to_le_bytes())?;
-            file.write_all(uv_layer_bytes)?;
-
-            match &colors {
-                Some(color_list) => {
-                    let color_value = &color_list[vertex_index];
-                    file.write_all(&color_value.x.to_le_bytes())?;
-                    file.write_all(&color_value.y.to_le_bytes())?;
-                    file.write_all(&color_value.z.to_le_bytes())?;
-                    file.write_all(&color_value.w.to_le_bytes())?;
+        let vertices = (0..vertices.len())
+            .map(|vertex_index| {
+                let position = &vertices[vertex_index];
+                let normal = &normals[vertex_index];
+                let uv_coordinate = &uvs[vertex_index];
+                Vertex {
+                    position: [position.x, position.y, position.z],
+                    normal: [normal.x, normal.y, normal.z],
+                    uv: [uv_coordinate.x, uv_coordinate.y, uv_layer],
+                    color: match &colors {
+                        Some(color_list) => {
+                            let color_value = &color_list[vertex_index];
+                            [color_value.x, color_value.y, color_value.z, color_value.w]
+                        }
+                        None => [1.0; 4],
+                    },
+                    bone_ids: bone_ids.get(vertex_index).copied().unwrap_or([-1; 4]),
+                    bone_weights: bone_weights.get(vertex_index).copied().unwrap_or([0.0; 4]),
                 }
-                None => {
-                    let default_color_component: f32 = 1.0;
-                    let color_value_bytes = &default_color_component.to_le_bytes();
-                    file.write_all(color_value_bytes)?;
-                    file.write_all(color_value_bytes)?;
-                    file.write_all(color_value_bytes)?;
-                    file.write_all(color_value_bytes)?;
-                }
-            }
+            })
+            .collect();
 
-            if is_skeletal {
-                for bone_id in bone_ids[vertex_index] {
-                    file.write_all(&bone_id.to_le_bytes())?;
-                }
-
-                for bone_weight in bone_weights[vertex_index] {
-                    file.write_all(&bone_weight.to_le_bytes())?;
-                }
-            }
+        let mut indices = Vec::new();
+        for face in mesh.faces() {
+            assert_eq!(face.num_indices(), 3);
+            indices.extend(face.indices());
         }
-        let faces = mesh.faces();
 
-        let num_indices = (faces.len() * 3) as u32;
-        file.write_all(&(num_indices).to_le_bytes())?;
+        meshes.push(MeshData { vertices, indices });
+    }
+    meshes
+}
 
-        for face in faces {
-            assert_eq!(face.num_indices(), 3);
-            for index in face.indices() {
-                let index_value = *index + total_vertex_count;
-                file.write_all(&index_value.to_le_bytes())?;
-            }
+// The meshes of a level occlude each other, they are baked together in the bind pose
+fn set_vertex_colors(meshes: &mut [MeshData], desc: &MeshLoadDesc) {
+    let default_settings = ao::AoSettings {
+        ray_count: DEFAULT_AO_RAY_COUNT,
+        max_distance: None,
+    };
+    let settings = match (&desc.ao, desc.vertex_color) {
+        (Some(settings), _) => Some(settings),
+        (None, VertexColorMode::Ao) => Some(&default_settings),
+        (None, _) => None,
+    };
+
+    let occlusion = settings.map(|settings| {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut triangles = Vec::new();
+        for mesh in meshes.iter() {
+            let offset = positions.len() as u32;
+            positions.extend(mesh.vertices.iter().map(|vertex| vertex.position));
+            normals.extend(mesh.vertices.iter().map(|vertex| vertex.normal));
+            triangles.extend(
+                mesh.indices
+                    .chunks_exact(3)
+                    .map(|triangle| [0, 1, 2].map(|corner| triangle[corner] + offset)),
+            );
         }
 
+        let start = Instant::now();
+        let occlusion = ao::bake_ambient_occlusion(&positions, &normals, &triangles, settings);
         println!(
-            "Wrote {} vertices, {} indices.",
-            vertices.len(),
-            num_indices
+            "Baked ambient occlusion of {} vertices with {} rays in {:.2}s",
+            positions.len(),
+            settings.ray_count,
+            start.elapsed().as_secs_f32()
         );
+        occlusion
+    });
+
+    let mut vertex_index = 0;
+    for mesh in meshes.iter_mut() {
+        for vertex in mesh.vertices.iter_mut() {
+            let value = occlusion.as_ref().map(|occlusion| occlusion[vertex_index]);
+            vertex_index += 1;
+            match desc.vertex_color {
+                VertexColorMode::Source | VertexColorMode::GradientY => {}
+                VertexColorMode::Ao => vertex.color = [value.unwrap_or(1.0); 4],
+                VertexColorMode::White => vertex.color = [1.0; 4],
+            }
+            // Only with --bake-ao, without it the alpha stays out of the lighting
+            vertex.color[3] = match desc.ao {
+                Some(_) => value.unwrap_or(1.0),
+                None => 1.0,
+            };
+        }
+    }
 
-        total_vertex_count += vertices.len() as u32;
+    if desc.vertex_color == VertexColorMode::GradientY {
+        if desc.ao.is_some() {
+            println!(
+                "The alpha holds the height with --vertex-color gradient-y, not the occlusion"
+            );
+        }
+        set_height_gradient(meshes);
     }
+}
 
-    // We also write a bone info buffer
-    if is_skeletal {
-        // Topologically sorted bone buffer
-        let mut bone_info: Vec<BoneInfo> = Vec::new();
-        bone_info.resize_with(bone_map.len(), Default::default);
-        for bone in bone_map.values() {
-            bone_info[bone.id as usize] = bone.clone();
+// Over all the meshes, so the parts of a plant sway together
+fn set_height_gradient(meshes: &mut [MeshData]) {
+    let heights = meshes
+        .iter()
+        .flat_map(|mesh| mesh.vertices.iter().map(|vertex| vertex.position[1]));
+    let (min, max) = heights.fold((f32::MAX, f32::MIN), |(min, max), height| {
+        (min.min(height), max.max(height))
+    });
+    let range = (max - min).max(f32::EPSILON);
+    for vertex in meshes.iter_mut().flat_map(|mesh| mesh.vertices.iter_mut()) {
+        vertex.color[3] = (vertex.position[1] - min) / range;
+    }
+}
+
+fn write_mesh(
+    file: &mut File,
+    mesh: &MeshData,
+    index_offset: u32,
+    is_skeletal: bool,
+) -> std::io::Result<()> {
+    file.write_all(&(mesh.vertices.len() as u32).to_le_bytes())?;
+    for vertex in &mesh.vertices {
+        let floats = vertex
+            .position
+            .iter()
+            .chain(&vertex.normal)
+            .chain(&vertex.uv)
+            .chain(&vertex.color);
+        for value in floats {
+            file.write_all(&value.to_le_bytes())?;
         }
 
-        file.write_all(&(bone_info.len() as u32).to_le_bytes())?;
-        for info in &bone_info {
-            file.write_all(&info.id.to_le_bytes())?;
-            file.write_all(&info.parent_id.to_le_bytes())?;
-            for m in info.offset_matrix {
-                file.write_all(&m.to_le_bytes())?;
+        if is_skeletal {
+            for bone_id in vertex.bone_ids {
+                file.write_all(&bone_id.to_le_bytes())?;
+            }
+
+            for bone_weight in vertex.bone_weights {
+                file.write_all(&bone_weight.to_le_bytes())?;
             }
         }
+    }
 
-        println!("Wrote {} bones.", bone_info.len());
+    file.write_all(&(mesh.indices.len() as u32).to_le_bytes())?;
+    for index in &mesh.indices {
+        file.write_all(&(index + index_offset).to_le_bytes())?;
     }
 
+    println!(
+        "Wrote {} vertices, {} indices.",
+        mesh.vertices.len(),
+        mesh.indices.len()
+    );
     Ok(())
 }
 
+// Naive vertex clustering: the vertices in one cell of a grid over the mesh are merged
+// into the first of them and the triangles that collapse are dropped. Uv seams and hard
+// edges get no special care, it is meant for meshes seen from far away.
+fn decimate(mesh: &MeshData, cells: f32) -> MeshData {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for vertex in &mesh.vertices {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(vertex.position[axis]);
+            max[axis] = max[axis].max(vertex.position[axis]);
+        }
+    }
+    let size = (0..3).map(|axis| max[axis] - min[axis]).fold(0.0, f32::max);
+    let cell_size = (size / cells).max(f32::EPSILON);
+
+    let mut cell_vertices: HashMap<[i32; 3], u32> = HashMap::new();
+    let mut vertices = Vec::new();
+    let remap: Vec<u32> = mesh
+        .vertices
+        .iter()
+        .map(|vertex| {
+            let cell =
+                [0, 1, 2].map(|axis| ((vertex.position[axis] - min[axis]) / cell_size) as i32);
+            *cell_vertices.entry(cell).or_insert_with(|| {
+                vertices.push(*vertex);
+                vertices.len() as u32 - 1
+            })
+        })
+        .collect();
+
+    let mut indices = Vec::new();
+    for triangle in mesh.indices.chunks(3) {
+        let [a, b, c] = [0, 1, 2].map(|corner| remap[triangle[corner] as usize]);
+        if a != b && b != c && a != c {
+            indices.extend([a, b, c]);
+        }
+    }
+
+    MeshData { vertices, indices }
+}
+
 fn load_bones(scene: &Scene) -> BoneMap {
     let mut bone_index = 0;
     let mut bone_map: BoneMap = HashMap::new();
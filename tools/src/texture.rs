@@ -1,13 +1,59 @@
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::prelude::*;
 
-use image::{EncodableLayout, ImageReader, imageops};
+use anyhow::{Context, bail};
+use image::{EncodableLayout, GenericImage, ImageReader, Luma, imageops};
+use serde::{Deserialize, Serialize};
 
 pub struct TextureLoadDesc<'a> {
     pub path: &'a str,
     pub output: &'a str,
     pub resize_width: Option<u32>,
     pub resize_height: Option<u32>,
+    pub atlas_regions: Option<&'a str>,
+    pub premultiply: bool, // Multiply the color by the alpha before the mips are made
+    pub alpha_coverage_threshold: Option<f32>, // Keep the alpha test coverage of mip 0 in every mip
+    pub data: Option<DataMips>, // A heightmap or mask of 16 bit integers instead of colors
+}
+
+// How the mips are made from the top level, the default filters every channel the same way
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MipOptions {
+    pub premultiplied: bool, // The color is multiplied by the alpha, flagged in the header
+    pub alpha_coverage_threshold: Option<f32>,
+}
+
+// Set in the channel count when the color is multiplied by the alpha, the client blends
+// such textures with premultiplied alpha
+pub const PREMULTIPLIED_ALPHA_FLAG: u32 = 1 << 31;
+// Set in the channel count for data textures, the client reads their 16 bit channels as
+// unorm integers instead of half floats
+pub const LINEAR_DATA_FLAG: u32 = 1 << 30;
+
+// The mips of a data texture. Filtering would make up values between neighbours, which for
+// heights or mask ids are wrong, so they are either left out or keep the largest value.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DataMips {
+    #[default]
+    None,
+    // Each texel the largest of the texels it covers, e.g. the highest point of a heightmap
+    Max,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AtlasRegion {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AtlasRegions {
+    width: u32,
+    height: u32,
+    regions: BTreeMap<String, AtlasRegion>,
 }
 
 fn mip_level_count(width: u32, height: u32) -> u32 {
@@ -20,25 +66,72 @@ fn mip_level_count(width: u32, height: u32) -> u32 {
 pub fn write_texture(
     img: &image::DynamicImage,
     mip_level_count: u32,
+    options: &MipOptions,
     file: &mut File,
 ) -> anyhow::Result<()> {
-    let width = img.width();
-    let height = img.height();
-    let layer_count: u32 = 1;
-    let color = img.color();
+    write_texture_array(std::slice::from_ref(img), mip_level_count, options, file)
+}
+
+// The layers have to be of the same size and color type, each gets its own mips
+pub fn write_texture_array(
+    layers: &[image::DynamicImage],
+    mip_level_count: u32,
+    options: &MipOptions,
+    file: &mut File,
+) -> anyhow::Result<()> {
+    let Some(first) = layers.first() else {
+        bail!("A texture needs at least one layer");
+    };
+    let width = first.width();
+    let height = first.height();
+    let layer_count = layers.len() as u32;
+    let color = first.color();
     let channel_count = color.channel_count() as u32;
     let bytes_per_channel = (color.bytes_per_pixel() as u32) / channel_count;
+    for (layer_index, layer) in layers.iter().enumerate() {
+        if layer.width() != width || layer.height() != height || layer.color() != color {
+            bail!(
+                "Layer {} is {}x{} of {:?}, the first layer is {}x{} of {:?}",
+                layer_index,
+                layer.width(),
+                layer.height(),
+                layer.color(),
+                width,
+                height,
+                color
+            );
+        }
+    }
 
     println!(
         "Loaded {}x{}x{} of {:?}.",
         width, height, layer_count, color
     );
 
+    let adjusts_alpha = options.premultiplied || options.alpha_coverage_threshold.is_some();
+    if adjusts_alpha && color != image::ColorType::Rgba8 {
+        bail!("Only 8 bit RGBA images can be premultiplied or keep their alpha coverage");
+    }
+    let coverages: Vec<_> = layers
+        .iter()
+        .map(|layer| {
+            options.alpha_coverage_threshold.map(|threshold| {
+                (threshold, get_alpha_coverage(&layer.to_rgba8(), threshold, 1.0))
+            })
+        })
+        .collect();
+
+    let flags = if options.premultiplied {
+        PREMULTIPLIED_ALPHA_FLAG
+    } else {
+        0
+    };
+
     // Header
     file.write_all(&width.to_le_bytes())?;
     file.write_all(&height.to_le_bytes())?;
     file.write_all(&layer_count.to_le_bytes())?;
-    file.write_all(&channel_count.to_le_bytes())?;
+    file.write_all(&(channel_count | flags).to_le_bytes())?;
     file.write_all(&bytes_per_channel.to_le_bytes())?;
     file.write_all(&mip_level_count.to_le_bytes())?;
 
@@ -46,12 +139,23 @@ pub fn write_texture(
     for mip_index in 0..mip_level_count {
         let mip_width: u32 = width >> mip_index;
         let mip_height: u32 = height >> mip_index;
-        for layer_index in 0..layer_count {
+        for (layer_index, img) in layers.iter().enumerate() {
             println!("Layer: {}, Mip: {} {}", layer_index, mip_width, mip_height);
             if mip_width != width || mip_height != height {
                 // We need to resize
-                let mip =
+                let mut mip =
                     imageops::resize(img, mip_width, mip_height, imageops::FilterType::Lanczos3);
+                if options.premultiplied {
+                    // Lanczos overshoots, a color brighter than its alpha isn't premultiplied
+                    for pixel in mip.pixels_mut() {
+                        for channel in 0..3 {
+                            pixel[channel] = pixel[channel].min(pixel[3]);
+                        }
+                    }
+                }
+                if let Some((threshold, coverage)) = coverages[layer_index] {
+                    keep_alpha_coverage(&mut mip, threshold, coverage, options.premultiplied);
+                }
                 file.write_all(mip.as_bytes())?;
             } else {
                 // We are writing the whole image
@@ -60,10 +164,202 @@ pub fn write_texture(
         }
     }
 
+    for (layer_index, coverage) in coverages.iter().enumerate() {
+        if let Some((threshold, coverage)) = coverage {
+            println!(
+                "Kept {:.1}% of the texels above an alpha of {} in every mip of layer {}",
+                coverage * 100.0,
+                threshold,
+                layer_index
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn premultiply_alpha(img: &mut image::RgbaImage) {
+    for pixel in img.pixels_mut() {
+        let alpha = pixel[3] as u32;
+        for channel in 0..3 {
+            pixel[channel] = ((pixel[channel] as u32 * alpha + 127) / 255) as u8;
+        }
+    }
+}
+
+// The fraction of texels that pass an alpha test against the threshold once their alpha
+// is scaled
+fn get_alpha_coverage(img: &image::RgbaImage, threshold: f32, scale: f32) -> f32 {
+    let passing = img
+        .pixels()
+        .filter(|pixel| (pixel[3] as f32 / 255.0 * scale).min(1.0) > threshold)
+        .count();
+    passing as f32 / (img.width() * img.height()) as f32
+}
+
+// Filtering averages the alpha of cutouts towards the middle, so fewer texels pass the
+// alpha test the smaller the mip and cutouts thin out in the distance. The alpha of the mip
+// is scaled to let the same fraction pass as in mip 0.
+fn keep_alpha_coverage(
+    mip: &mut image::RgbaImage,
+    threshold: f32,
+    coverage: f32,
+    premultiplied: bool,
+) {
+    // The coverage only grows with the scale, so a binary search narrows it down to the
+    // step where it passes the target. Small mips can't hit it exactly, the closer side wins.
+    let (mut min_scale, mut max_scale) = (0.0f32, 4.0f32);
+    for _ in 0..16 {
+        let scale = (min_scale + max_scale) * 0.5;
+        if get_alpha_coverage(mip, threshold, scale) < coverage {
+            min_scale = scale;
+        } else {
+            max_scale = scale;
+        }
+    }
+    let get_error = |scale| (get_alpha_coverage(mip, threshold, scale) - coverage).abs();
+    let scale = if get_error(min_scale) < get_error(max_scale) {
+        min_scale
+    } else {
+        max_scale
+    };
+
+    // A premultiplied color is scaled with its alpha, otherwise it would change
+    let channels = if premultiplied { 0..4 } else { 3..4 };
+    for pixel in mip.pixels_mut() {
+        let alpha = pixel[3] as f32 * scale;
+        let clamped_scale = if alpha > 255.0 {
+            255.0 / pixel[3] as f32
+        } else {
+            scale
+        };
+        for channel in channels.clone() {
+            pixel[channel] = (pixel[channel] as f32 * clamped_scale).round().min(255.0) as u8;
+        }
+    }
+}
+
+// Packs every image in a directory into one atlas using simple shelf packing,
+// the regions are written to a json file so the client can look them up by name
+fn pack_atlas(desc: &TextureLoadDesc, regions_path: &str) -> anyhow::Result<()> {
+    const PADDING: u32 = 1;
+
+    let mut entries = std::fs::read_dir(desc.path)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    entries.sort();
+
+    let mut images = Vec::new();
+    for path in entries {
+        let img = match ImageReader::open(&path)?.with_guessed_format()?.decode() {
+            Ok(img) => img.to_rgba8(),
+            Err(_) => {
+                println!("Skipping {}, not an image.", path.display());
+                continue;
+            }
+        };
+
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .expect("Invalid file name.")
+            .to_string();
+        // The regions are looked up by name, one of the two would be lost
+        if images.iter().any(|(other, _)| *other == name) {
+            bail!("Two images in {} are named {}", desc.path, name);
+        }
+        images.push((name, img));
+    }
+
+    // Tallest first keeps the shelves tight
+    images.sort_by(|(_, a), (_, b)| b.height().cmp(&a.height()));
+
+    let total_area: u32 = images
+        .iter()
+        .map(|(_, img)| (img.width() + PADDING) * (img.height() + PADDING))
+        .sum();
+    let max_width = images.iter().map(|(_, img)| img.width()).max().unwrap_or(1);
+    let width = ((total_area as f32).sqrt().ceil() as u32)
+        .max(max_width)
+        .next_power_of_two();
+
+    let mut regions = BTreeMap::new();
+    let (mut x, mut y, mut shelf_height) = (0u32, 0u32, 0u32);
+    for (name, img) in images.iter() {
+        if x + img.width() > width {
+            x = 0;
+            y += shelf_height + PADDING;
+            shelf_height = 0;
+        }
+
+        regions.insert(
+            name.clone(),
+            AtlasRegion {
+                x,
+                y,
+                width: img.width(),
+                height: img.height(),
+            },
+        );
+
+        x += img.width() + PADDING;
+        shelf_height = shelf_height.max(img.height());
+    }
+    let height = (y + shelf_height).max(1).next_power_of_two();
+
+    let mut atlas = image::RgbaImage::new(width, height);
+    for (name, img) in images.iter() {
+        let region = &regions[name];
+        atlas.copy_from(img, region.x, region.y)?;
+    }
+
+    if desc.premultiply {
+        premultiply_alpha(&mut atlas);
+    }
+
+    // No mips, they would bleed neighbouring regions into each other
+    let mut file = File::create(desc.output).expect("Could not open output file.");
+    let options = MipOptions {
+        premultiplied: desc.premultiply,
+        alpha_coverage_threshold: None,
+    };
+    write_texture(
+        &image::DynamicImage::ImageRgba8(atlas),
+        1,
+        &options,
+        &mut file,
+    )?;
+
+    let regions = AtlasRegions {
+        width,
+        height,
+        regions,
+    };
+    let mut regions_file = File::create(regions_path)?;
+    regions_file.write_all(serde_json::to_string_pretty(&regions)?.as_bytes())?;
+
+    println!(
+        "Packed {} images into a {}x{} atlas {}, regions written to {}",
+        regions.regions.len(),
+        width,
+        height,
+        desc.output,
+        regions_path
+    );
+
     Ok(())
 }
 
 pub fn load(desc: &TextureLoadDesc) -> anyhow::Result<()> {
+    if let Some(regions_path) = desc.atlas_regions {
+        return pack_atlas(desc, regions_path);
+    }
+    if let Some(mips) = desc.data {
+        return load_data(desc, mips);
+    }
+
     let mut img = ImageReader::open(desc.path)?
         .with_guessed_format()?
         .decode()?;
@@ -84,9 +380,29 @@ pub fn load(desc: &TextureLoadDesc) -> anyhow::Result<()> {
         println!("Resized image to {}x{}", resize_width, resize_height);
     }
 
+    let options = MipOptions {
+        premultiplied: desc.premultiply,
+        alpha_coverage_threshold: desc.alpha_coverage_threshold,
+    };
+    if let Some(threshold) = options.alpha_coverage_threshold {
+        if !(0.0..1.0).contains(&threshold) {
+            bail!("The alpha coverage threshold has to be in [0, 1), got {}", threshold);
+        }
+    }
+    if options.premultiplied || options.alpha_coverage_threshold.is_some() {
+        if !img.color().has_alpha() {
+            bail!("{} has no alpha to premultiply or keep the coverage of", desc.path);
+        }
+        let mut rgba = img.to_rgba8();
+        if options.premultiplied {
+            premultiply_alpha(&mut rgba);
+        }
+        img = image::DynamicImage::ImageRgba8(rgba);
+    }
+
     let mut file = File::create(desc.output).expect("Could not open output file.");
     let mip_level_count = mip_level_count(img.width(), img.height());
-    write_texture(&img, mip_level_count, &mut file)?;
+    write_texture(&img, mip_level_count, &options, &mut file)?;
 
     let layer_count: u32 = 1;
 
@@ -97,3 +413,398 @@ pub fn load(desc: &TextureLoadDesc) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+// A grayscale image kept texel for texel as one 16 bit channel, 8 bit images are widened
+fn load_data(desc: &TextureLoadDesc, mips: DataMips) -> anyhow::Result<()> {
+    let resizes = desc.resize_width.is_some() || desc.resize_height.is_some();
+    if resizes || desc.premultiply || desc.alpha_coverage_threshold.is_some() {
+        bail!("Data textures can't be resized, premultiplied or keep an alpha coverage");
+    }
+
+    let img = ImageReader::open(desc.path)?
+        .with_guessed_format()?
+        .decode()?;
+    let data = match img {
+        image::DynamicImage::ImageLuma16(data) => data,
+        image::DynamicImage::ImageLuma8(_) => {
+            println!("{} has 8 bit channels, widened to 16 bits", desc.path);
+            img.to_luma16()
+        }
+        _ => bail!(
+            "{} is {:?}, data textures need a single gray channel",
+            desc.path,
+            img.color()
+        ),
+    };
+
+    let mut file = File::create(desc.output).expect("Could not open output file.");
+    let mip_level_count = write_data_texture(&data, mips, &mut file)?;
+
+    println!(
+        "Packed {}x{} of 16 bit data into {} with {} mips",
+        data.width(),
+        data.height(),
+        desc.output,
+        mip_level_count
+    );
+
+    Ok(())
+}
+
+// One 16 bit channel flagged as data, returns the number of mips written. The mips stop
+// at the last level both sides are at least a texel, like the client expects.
+pub fn write_data_texture(
+    img: &image::ImageBuffer<Luma<u16>, Vec<u16>>,
+    mips: DataMips,
+    writer: &mut impl Write,
+) -> anyhow::Result<u32> {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        bail!("A data texture can't be empty");
+    }
+    let mip_level_count = match mips {
+        DataMips::None => 1,
+        DataMips::Max => 32 - width.min(height).leading_zeros(),
+    };
+
+    // Header
+    writer.write_all(&width.to_le_bytes())?;
+    writer.write_all(&height.to_le_bytes())?;
+    writer.write_all(&1u32.to_le_bytes())?;
+    writer.write_all(&(1 | LINEAR_DATA_FLAG).to_le_bytes())?;
+    writer.write_all(&2u32.to_le_bytes())?;
+    writer.write_all(&mip_level_count.to_le_bytes())?;
+
+    let mut mip = img.clone();
+    for mip_index in 0..mip_level_count {
+        if mip_index > 0 {
+            mip = get_max_mip(&mip, width >> mip_index, height >> mip_index);
+        }
+        for value in mip.as_raw() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    Ok(mip_level_count)
+}
+
+// Every texel of the source falls into one texel of the mip, an odd last row or column
+// goes along with the one before it
+fn get_max_mip(
+    source: &image::ImageBuffer<Luma<u16>, Vec<u16>>,
+    width: u32,
+    height: u32,
+) -> image::ImageBuffer<Luma<u16>, Vec<u16>> {
+    let (source_width, source_height) = source.dimensions();
+    image::ImageBuffer::from_fn(width, height, |x, y| {
+        let xs = x * source_width / width..(x + 1) * source_width / width;
+        let ys = y * source_height / height..(y + 1) * source_height / height;
+        let max = ys
+            .flat_map(|y| xs.clone().map(move |x| (x, y)))
+            .map(|(x, y)| source.get_pixel(x, y)[0])
+            .max()
+            .unwrap_or_default();
+        Luma([max])
+    })
+}
+
+// Images of the same size stacked into the layers of one texture, e.g. the skins of a
+// champion that the client picks per entity as material variants
+pub struct TextureArrayLoadDesc<'a> {
+    pub paths: &'a [String], // In layer order
+    pub output: &'a str,
+    pub premultiply: bool,
+}
+
+pub fn load_array(desc: &TextureArrayLoadDesc) -> anyhow::Result<()> {
+    let mut layers = Vec::with_capacity(desc.paths.len());
+    for path in desc.paths {
+        let img = ImageReader::open(path)
+            .with_context(|| format!("Failed to open {}", path))?
+            .with_guessed_format()?
+            .decode()?;
+        let mut rgba = img.to_rgba8();
+        if desc.premultiply {
+            premultiply_alpha(&mut rgba);
+        }
+        layers.push(image::DynamicImage::ImageRgba8(rgba));
+    }
+
+    let options = MipOptions {
+        premultiplied: desc.premultiply,
+        alpha_coverage_threshold: None,
+    };
+    let Some(first) = layers.first() else {
+        bail!("A texture array needs at least one image");
+    };
+    let mip_level_count = mip_level_count(first.width(), first.height());
+    let mut file = File::create(desc.output).expect("Could not open output file.");
+    write_texture_array(&layers, mip_level_count, &options, &mut file)?;
+
+    println!(
+        "Packed images into {}. Generated {} layers, each with {} mips",
+        desc.output,
+        layers.len(),
+        mip_level_count
+    );
+
+    Ok(())
+}
+
+pub const CHANNEL_NAMES: [&str; 4] = ["r", "g", "b", "a"];
+
+// Grayscale sources packed into the channels of one texture, like roughness, metallic and
+// ambient occlusion into a mask map
+pub struct PackLoadDesc<'a> {
+    pub channels: [Option<&'a str>; 4], // None fills the channel with its constant
+    pub fills: [u8; 4],
+    pub output: &'a str,
+    pub resize: bool, // Resize the sources to the largest one instead of failing
+}
+
+pub fn pack(desc: &PackLoadDesc) -> anyhow::Result<()> {
+    let mut sources = Vec::new();
+    for (index, path) in desc.channels.iter().enumerate() {
+        if let Some(path) = path {
+            let img = ImageReader::open(path)
+                .with_context(|| format!("Failed to open {}", path))?
+                .with_guessed_format()?
+                .decode()
+                .with_context(|| format!("Failed to decode {}", path))?;
+            sources.push((index, *path, img.to_luma8()));
+        }
+    }
+
+    let width = sources
+        .iter()
+        .map(|(_, _, img)| img.width())
+        .max()
+        .unwrap_or(1);
+    let height = sources
+        .iter()
+        .map(|(_, _, img)| img.height())
+        .max()
+        .unwrap_or(1);
+    for (_, path, img) in sources.iter_mut() {
+        if img.dimensions() == (width, height) {
+            continue;
+        }
+        if !desc.resize {
+            bail!(
+                "{} is {}x{} while the largest source is {}x{}, pass --resize to resize it",
+                path,
+                img.width(),
+                img.height(),
+                width,
+                height
+            );
+        }
+        *img = imageops::resize(img, width, height, imageops::FilterType::Lanczos3);
+        println!("Resized {} to {}x{}", path, width, height);
+    }
+
+    let mut packed = image::RgbaImage::from_pixel(width, height, image::Rgba(desc.fills));
+    for (index, _, img) in sources.iter() {
+        for (pixel, source) in packed.pixels_mut().zip(img.pixels()) {
+            pixel[*index] = source[0];
+        }
+    }
+
+    // The mips are made from the packed image, so all channels are filtered the same way
+    let img = image::DynamicImage::ImageRgba8(packed);
+    let mip_level_count = mip_level_count(width, height);
+    let mut file = File::create(desc.output).expect("Could not open output file.");
+    write_texture(&img, mip_level_count, &MipOptions::default(), &mut file)?;
+
+    for (index, name) in CHANNEL_NAMES.iter().enumerate() {
+        match desc.channels[index] {
+            Some(path) => println!("{}: {}", name, path),
+            None => println!("{}: {}", name, desc.fills[index]),
+        }
+    }
+    println!(
+        "Packed {} sources into {} with {} mips",
+        sources.len(),
+        desc.output,
+        mip_level_count
+    );
+
+    Ok(())
+}
+
+// Prints the header of a converted texture and the range of each channel in the top mip
+pub fn inspect(path: &str) -> anyhow::Result<()> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+    if bytes.len() < 24 {
+        bail!("{} is too short for a texture header", path);
+    }
+    let header: Vec<u32> = bytes[..24]
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+    let [
+        width,
+        height,
+        layer_count,
+        flagged_channel_count,
+        bytes_per_channel,
+        mip_level_count,
+    ] = header[..]
+    else {
+        unreachable!()
+    };
+    let channel_count = flagged_channel_count & !(PREMULTIPLIED_ALPHA_FLAG | LINEAR_DATA_FLAG);
+
+    println!(
+        "{}x{}x{}, {} channels of {} bytes, {} mips",
+        width, height, layer_count, channel_count, bytes_per_channel, mip_level_count
+    );
+    if flagged_channel_count & PREMULTIPLIED_ALPHA_FLAG != 0 {
+        println!("Premultiplied alpha");
+    }
+    if flagged_channel_count & LINEAR_DATA_FLAG != 0 {
+        println!("Linear data");
+    }
+
+    let size = (width * height * channel_count * bytes_per_channel) as usize;
+    let Some(data) = bytes.get(24..24 + size) else {
+        bail!("{} is too short for its top mip", path);
+    };
+    if channel_count == 0 || !(1..=2).contains(&bytes_per_channel) {
+        println!("No channel ranges for {} byte channels", bytes_per_channel);
+        return Ok(());
+    }
+
+    let mut ranges = vec![(u16::MAX, 0u16); channel_count as usize];
+    let values = data
+        .chunks_exact(bytes_per_channel as usize)
+        .map(|value| match value {
+            [byte] => *byte as u16,
+            _ => u16::from_le_bytes([value[0], value[1]]),
+        });
+    for (index, value) in values.enumerate() {
+        let range = &mut ranges[index % channel_count as usize];
+        range.0 = range.0.min(value);
+        range.1 = range.1.max(value);
+    }
+    for (index, (min, max)) in ranges.iter().enumerate() {
+        let name = CHANNEL_NAMES.get(index).unwrap_or(&"?");
+        println!("{}: min {}, max {}", name, min, max);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_header(bytes: &[u8]) -> Vec<u32> {
+        bytes[..24]
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    fn get_texels(bytes: &[u8]) -> Vec<u16> {
+        bytes
+            .chunks_exact(2)
+            .map(|value| u16::from_le_bytes([value[0], value[1]]))
+            .collect()
+    }
+
+    // Odd sizes, both ends of the range and values no 8 bit texture can hold
+    fn get_heightmap() -> image::ImageBuffer<Luma<u16>, Vec<u16>> {
+        image::ImageBuffer::from_fn(5, 3, |x, y| match (x, y) {
+            (0, 0) => Luma([0]),
+            (4, 2) => Luma([u16::MAX]),
+            _ => Luma([(x * 12_001 + y * 257 + 1) as u16]),
+        })
+    }
+
+    #[test]
+    fn data_textures_keep_every_texel() {
+        let img = get_heightmap();
+        let mut bytes = Vec::new();
+        assert_eq!(
+            write_data_texture(&img, DataMips::None, &mut bytes).unwrap(),
+            1
+        );
+        assert_eq!(get_header(&bytes), [5, 3, 1, 1 | LINEAR_DATA_FLAG, 2, 1]);
+        assert_eq!(get_texels(&bytes[24..]), img.as_raw().as_slice());
+
+        // Through a 16 bit png and the command
+        let directory = std::env::temp_dir().join(format!("data_texture_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let source = directory.join("height.png");
+        let output = directory.join("height.dat");
+        img.save(&source).unwrap();
+        load(&TextureLoadDesc {
+            path: source.to_str().unwrap(),
+            output: output.to_str().unwrap(),
+            resize_width: None,
+            resize_height: None,
+            atlas_regions: None,
+            premultiply: false,
+            alpha_coverage_threshold: None,
+            data: Some(DataMips::None),
+        })
+        .unwrap();
+        let converted = std::fs::read(&output).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(converted, bytes);
+    }
+
+    #[test]
+    fn max_mips_keep_the_largest_value() {
+        let img = get_heightmap();
+        let mut bytes = Vec::new();
+        // 5x3, 2x1
+        assert_eq!(
+            write_data_texture(&img, DataMips::Max, &mut bytes).unwrap(),
+            2
+        );
+        assert_eq!(get_header(&bytes)[5], 2);
+        let texels = get_texels(&bytes[24..]);
+        assert_eq!(texels.len(), 15 + 2);
+        assert_eq!(&texels[..15], img.as_raw().as_slice());
+
+        // The left two columns and the right three, over all rows
+        let column_max = |columns: std::ops::Range<u32>| {
+            columns
+                .flat_map(|x| (0..3).map(move |y| (x, y)))
+                .map(|(x, y)| img.get_pixel(x, y)[0])
+                .max()
+                .unwrap()
+        };
+        assert_eq!(texels[15..], [column_max(0..2), column_max(2..5)]);
+        assert_eq!(texels[16], u16::MAX);
+    }
+
+    #[test]
+    fn atlas_images_need_unique_names() {
+        let directory = std::env::temp_dir().join(format!("atlas_names_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let img = image::RgbaImage::new(4, 4);
+        for file in ["icon.png", "icon.tga"] {
+            img.save_with_format(directory.join(file), image::ImageFormat::Png)
+                .unwrap();
+        }
+        let output = directory.join("atlas.dat");
+        let regions = directory.join("atlas.json");
+        let result = load(&TextureLoadDesc {
+            path: directory.to_str().unwrap(),
+            output: output.to_str().unwrap(),
+            resize_width: None,
+            resize_height: None,
+            atlas_regions: Some(regions.to_str().unwrap()),
+            premultiply: false,
+            alpha_coverage_threshold: None,
+            data: None,
+        });
+        let written = output.exists() || regions.exists();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(result.unwrap_err().to_string().contains("named icon"));
+        assert!(!written);
+    }
+}